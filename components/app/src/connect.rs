@@ -5,7 +5,7 @@ use futures::task::Spawn;
 
 use common::int_convert::usize_to_u64;
 
-use proto::consts::{MAX_FRAME_LENGTH, TICK_MS};
+use proto::consts::{CONNECT_STAGGER_TICKS, MAX_FRAME_LENGTH, TICK_MS};
 use proto::net::messages::NetAddress;
 
 use crypto::crypto_rand::{system_random, CryptoRandom};
@@ -32,13 +32,19 @@ where
 {
     let resolve_thread_pool = ThreadPool::new().map_err(|_| ConnectError)?;
 
-    // A tcp connector, Used to connect to remote servers:
-    let net_connector = NetConnector::new(MAX_FRAME_LENGTH, resolve_thread_pool, spawner.clone());
-
     // Get a timer client:
     let dur = Duration::from_millis(usize_to_u64(TICK_MS).unwrap());
     let timer_client = create_timer(dur, spawner.clone()).map_err(|_| ConnectError)?;
 
+    // A tcp connector, Used to connect to remote servers:
+    let net_connector = NetConnector::new(
+        MAX_FRAME_LENGTH,
+        timer_client.clone(),
+        CONNECT_STAGGER_TICKS,
+        resolve_thread_pool,
+        spawner.clone(),
+    );
+
     // Obtain secure cryptographic random:
     let rng = system_random();
 
@@ -25,13 +25,18 @@ pub use proto::file::node::load_node_from_file;
 pub use proto::file::relay::load_relay_from_file;
 pub use proto::file::ser_string;
 
-pub use proto::app_server::messages::{AppPermissions, NamedRelayAddress, RelayAddress};
-pub use proto::funder::messages::Receipt;
+pub use proto::app_server::messages::{
+    AppPermissions, NamedRelayAddress, RelayAddress, SelfTestStage, SelfTestStageReport,
+};
+pub use proto::funder::messages::{DustThresholds, Receipt};
 pub use proto::funder::signature_buff::verify_receipt;
 pub use proto::index_server::messages::NamedIndexServerAddress;
 pub use proto::report::signature_buff::verify_move_token_hashed_report;
 
-pub use node::connect::{AppConfig, AppReport, AppRoutes, AppSendFunds, NodeConnection};
+pub use node::connect::{
+    AddFriendError, AppConfig, AppReport, AppRoutes, AppSelfTest, AppSendFunds, ExistingFriend,
+    NodeConnection, NodeStateMirror, SendFundsError, SetFriendRelaysError, WaitForError,
+};
 
 pub use self::connect::{connect, ConnectError};
 pub use self::identity::{identity_from_file, IdentityFromFileError};
@@ -42,8 +47,8 @@ pub mod report {
         AddFriendReport, ChannelInconsistentReport, ChannelStatusReport, DirectionReport,
         FriendLivenessReport, FriendReport, FriendReportMutation, FriendStatusReport, FunderReport,
         FunderReportMutateError, FunderReportMutation, FunderReportMutations, McBalanceReport,
-        McRequestsStatusReport, MoveTokenHashedReport, RequestsStatusReport, ResetTermsReport,
-        SentLocalRelaysReport, TcReport,
+        McRequestsStatusReport, MoveTokenHashedReport, ReliabilityReport, RequestsStatusReport,
+        ResetTermsReport, SentLocalRelaysReport, TcReport, VerificationStatusReport,
     };
 
     pub use proto::app_server::messages::{NodeReport, NodeReportMutation};
@@ -52,6 +57,13 @@ pub mod report {
     };
 }
 
+pub mod invite {
+    pub use proto::invite::messages::{FriendInvite, ImportFriendInviteResult};
+    pub use proto::invite::serialize::{
+        friend_invite_to_string, string_to_friend_invite, FriendInviteStringError,
+    };
+}
+
 pub mod invoice {
     pub use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
 }
@@ -60,6 +72,10 @@ pub mod route {
     pub use proto::funder::messages::FriendsRoute;
     pub use proto::index_server::messages::RouteWithCapacity;
 
+    pub use node::connect::{
+        select_route, select_route_by_policy, CheapestFee, HighestCapacity, RandomWeighted,
+        ReliabilityWeighted, RoutePolicy, RouteSelector, Shortest,
+    };
 }
 
 pub use crypto::crypto_rand::{RandValue, RAND_VALUE_LEN};
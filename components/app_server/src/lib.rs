@@ -18,9 +18,12 @@ extern crate log;
 #[macro_use]
 extern crate common;
 
+mod scheduler;
 mod server;
+mod session;
 
 #[cfg(test)]
 mod tests;
 
 pub use self::server::{app_server_loop, AppServerError, IncomingAppConnection};
+pub use self::session::AppSession;
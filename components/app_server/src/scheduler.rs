@@ -0,0 +1,326 @@
+use std::collections::{HashMap, VecDeque};
+
+use futures::channel::{mpsc, oneshot};
+use futures::task::{Spawn, SpawnError, SpawnExt};
+use futures::{future, stream, SinkExt, StreamExt};
+
+use common::select_streams::{select_streams, BoxStream};
+
+use proto::app_server::messages::{AppServerToApp, ReportScope};
+
+/// Maximum amount of messages queued for one app.
+/// An app that does not read its messages fast enough is disconnected.
+const MAX_QUEUED_MESSAGES: usize = 0x1000;
+
+/// Queues messages of multiple scopes.
+/// Messages of the same scope are popped in order, and different scopes take turns,
+/// so that a long backlog of one scope does not delay messages of other scopes.
+pub struct ScopedQueue<T> {
+    /// Scopes that have queued messages, in the order of their next turn.
+    scopes: VecDeque<ReportScope>,
+    queues: HashMap<ReportScope, VecDeque<T>>,
+    len: usize,
+}
+
+impl<T> ScopedQueue<T> {
+    pub fn new() -> Self {
+        ScopedQueue {
+            scopes: VecDeque::new(),
+            queues: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, scope: ReportScope, item: T) {
+        if !self.queues.contains_key(&scope) {
+            self.scopes.push_back(scope.clone());
+        }
+        self.queues
+            .entry(scope)
+            .or_insert_with(VecDeque::new)
+            .push_back(item);
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        let scope = self.scopes.pop_front()?;
+        let queue = self.queues.get_mut(&scope).unwrap();
+        let item = queue.pop_front().unwrap();
+        if queue.is_empty() {
+            self.queues.remove(&scope);
+        } else {
+            // Give other scopes a turn before the next message of this scope:
+            self.scopes.push_back(scope);
+        }
+        self.len -= 1;
+        Some(item)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[derive(Debug)]
+enum SchedulerError {
+    TooManyQueuedMessages,
+}
+
+#[derive(Debug)]
+enum SchedulerEvent<B: Clone> {
+    Outgoing((ReportScope, AppServerToApp<B>)),
+    OutgoingClosed,
+    WriterRequest(oneshot::Sender<AppServerToApp<B>>),
+    WriterClosed,
+}
+
+/// Pass one message to the writer, if the writer is waiting and a message is queued.
+/// Returns false if the writer is gone.
+fn serve_writer<B>(
+    queue: &mut ScopedQueue<AppServerToApp<B>>,
+    opt_writer_request: &mut Option<oneshot::Sender<AppServerToApp<B>>>,
+) -> bool
+where
+    B: Clone,
+{
+    if queue.is_empty() {
+        return true;
+    }
+    match opt_writer_request.take() {
+        Some(writer_request) => writer_request.send(queue.pop().unwrap()).is_ok(),
+        None => true,
+    }
+}
+
+async fn app_scheduler_loop<B>(
+    outgoing: mpsc::Receiver<(ReportScope, AppServerToApp<B>)>,
+    writer_requests: mpsc::Receiver<oneshot::Sender<AppServerToApp<B>>>,
+    max_queued_messages: usize,
+) -> Result<(), SchedulerError>
+where
+    B: Clone + Send + 'static,
+{
+    let outgoing = outgoing
+        .map(SchedulerEvent::Outgoing)
+        .chain(stream::once(future::ready(SchedulerEvent::OutgoingClosed)));
+
+    let writer_requests = writer_requests
+        .map(SchedulerEvent::WriterRequest)
+        .chain(stream::once(future::ready(SchedulerEvent::WriterClosed)));
+
+    let mut events = select_streams![outgoing, writer_requests];
+
+    let mut queue = ScopedQueue::new();
+    let mut opt_writer_request = None;
+    let mut outgoing_closed = false;
+
+    while let Some(event) = await!(events.next()) {
+        match event {
+            SchedulerEvent::Outgoing((scope, message)) => {
+                queue.push(scope, message);
+                if queue.len() > max_queued_messages {
+                    return Err(SchedulerError::TooManyQueuedMessages);
+                }
+            }
+            SchedulerEvent::OutgoingClosed => outgoing_closed = true,
+            SchedulerEvent::WriterRequest(writer_request) => {
+                opt_writer_request = Some(writer_request)
+            }
+            SchedulerEvent::WriterClosed => return Ok(()),
+        }
+        if !serve_writer(&mut queue, &mut opt_writer_request) {
+            return Ok(());
+        }
+        // Deliver all the remaining messages before closing:
+        if outgoing_closed && queue.is_empty() {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Write messages to the app one by one, asking the scheduler for the next message only when
+/// the app is ready to receive it.
+async fn app_writer_loop<B>(
+    mut writer_requests_sender: mpsc::Sender<oneshot::Sender<AppServerToApp<B>>>,
+    mut sender: mpsc::Sender<AppServerToApp<B>>,
+) where
+    B: Clone + Send + 'static,
+{
+    loop {
+        let (message_sender, message_receiver) = oneshot::channel();
+        if await!(writer_requests_sender.send(message_sender)).is_err() {
+            return;
+        }
+        let message = match await!(message_receiver) {
+            Ok(message) => message,
+            Err(_) => return,
+        };
+        if await!(sender.send(message)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Schedule messages sent to an app: Messages of different scopes are interleaved, so that a slow
+/// app receives changes of every scope without waiting for the backlog of another scope.
+///
+/// Returns a sender for (scope, message) pairs. The sender is closed if the app is disconnected,
+/// or if the app falls too far behind.
+pub fn create_app_scheduler<B, S>(
+    sender: mpsc::Sender<AppServerToApp<B>>,
+    spawner: &mut S,
+) -> Result<mpsc::Sender<(ReportScope, AppServerToApp<B>)>, SpawnError>
+where
+    B: Clone + Send + 'static,
+    S: Spawn,
+{
+    let (outgoing_sender, outgoing) = mpsc::channel(0);
+    let (writer_requests_sender, writer_requests) = mpsc::channel(0);
+
+    spawner.spawn(app_writer_loop(writer_requests_sender, sender))?;
+
+    let scheduler_fut = async move {
+        if let Err(e) = await!(app_scheduler_loop(
+            outgoing,
+            writer_requests,
+            MAX_QUEUED_MESSAGES
+        )) {
+            warn!("app_scheduler_loop() error: {:?}. Disconnecting app.", e);
+        }
+    };
+    spawner.spawn(scheduler_fut)?;
+
+    Ok(outgoing_sender)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::ThreadPool;
+
+    use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+
+    use proto::app_server::messages::ReportMutations;
+
+    fn friend_scope(index: u8) -> ReportScope {
+        ReportScope::Friend(PublicKey::from(&[index; PUBLIC_KEY_LEN]))
+    }
+
+    /// A dummy message, identified by its scope and sequence number
+    fn message(scope: ReportScope, seq: u64) -> AppServerToApp<u32> {
+        AppServerToApp::ReportMutations(ReportMutations {
+            opt_app_request_id: None,
+            scope,
+            seq,
+            dependencies: Vec::new(),
+            mutations: Vec::new(),
+        })
+    }
+
+    fn scope_seq(message: AppServerToApp<u32>) -> (ReportScope, u64) {
+        match message {
+            AppServerToApp::ReportMutations(report_mutations) => {
+                (report_mutations.scope, report_mutations.seq)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_scoped_queue_round_robin() {
+        let mut queue = ScopedQueue::new();
+        for i in 0..4 {
+            queue.push(friend_scope(0), i);
+        }
+        queue.push(friend_scope(1), 100);
+        queue.push(ReportScope::Node, 200);
+        assert_eq!(queue.len(), 6);
+
+        let mut popped = Vec::new();
+        while let Some(item) = queue.pop() {
+            popped.push(item);
+        }
+        assert_eq!(popped, vec![0, 100, 200, 1, 2, 3]);
+        assert!(queue.is_empty());
+    }
+
+    async fn task_app_scheduler_flood(mut spawner: impl Spawn + Clone) {
+        let (sender, mut receiver) = mpsc::channel(0);
+        let mut outgoing_sender = create_app_scheduler(sender, &mut spawner).unwrap();
+
+        // A burst of messages for friend 0, then a single message for friend 1:
+        for seq in 0..64 {
+            await!(outgoing_sender.send((friend_scope(0), message(friend_scope(0), seq))))
+                .unwrap();
+        }
+        await!(outgoing_sender.send((friend_scope(1), message(friend_scope(1), 0)))).unwrap();
+        drop(outgoing_sender);
+
+        let mut received = Vec::new();
+        while let Some(message) = await!(receiver.next()) {
+            received.push(scope_seq(message));
+        }
+        assert_eq!(received.len(), 65);
+
+        // The message of friend 1 does not wait behind the backlog of friend 0:
+        let pos = received
+            .iter()
+            .position(|(scope, _)| scope == &friend_scope(1))
+            .unwrap();
+        assert!(pos < 8);
+
+        // Messages of friend 0 are delivered in order:
+        let seqs = received
+            .iter()
+            .filter(|(scope, _)| scope == &friend_scope(0))
+            .map(|(_, seq)| *seq)
+            .collect::<Vec<_>>();
+        assert_eq!(seqs, (0..64).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_app_scheduler_flood() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_app_scheduler_flood(thread_pool.clone()));
+    }
+
+    async fn task_app_scheduler_max_queued(mut spawner: impl Spawn + Clone) {
+        let (outgoing_sender, outgoing) = mpsc::channel(0);
+        let (writer_requests_sender, writer_requests) = mpsc::channel(0);
+        spawner
+            .spawn(
+                async move {
+                    // The writer never asks for messages, but stays open:
+                    let _writer_requests_sender = writer_requests_sender;
+                    let res = await!(app_scheduler_loop(outgoing, writer_requests, 4));
+                    assert!(res.is_err());
+                },
+            )
+            .unwrap();
+
+        // Nobody reads the messages, so the scheduler gives up after too many queued messages:
+        let mut outgoing_sender = outgoing_sender;
+        let mut num_sent = 0;
+        for seq in 0..16 {
+            if await!(outgoing_sender.send((ReportScope::Node, message(ReportScope::Node, seq))))
+                .is_err()
+            {
+                break;
+            }
+            num_sent += 1;
+        }
+        assert!(num_sent < 16);
+    }
+
+    #[test]
+    fn test_app_scheduler_max_queued() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_app_scheduler_max_queued(thread_pool.clone()));
+    }
+}
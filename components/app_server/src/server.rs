@@ -1,31 +1,61 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::marker::Unpin;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
 use futures::{future, stream, Sink, SinkExt, Stream, StreamExt};
 
-use common::conn::ConnPair;
+use serde::Serialize;
+
+use common::canonical_serialize::CanonicalSerialize;
+use common::conn::{ConnPair, FutTransform};
 use common::select_streams::{select_streams, BoxStream};
 // use common::mutable_state::MutableState;
+use crypto::identity::PublicKey;
 use crypto::uid::Uid;
 
+use identity::IdentityClient;
+
 use proto::funder::messages::{
-    FriendStatus, FunderControl, FunderIncomingControl, FunderOutgoingControl, RemoveFriend,
-    RequestsStatus, SetFriendStatus, SetRequestsStatus,
+    AddFriend, AddFriendFromInvite, CancelUserRequestResult, FailureReason, FriendStatus,
+    FunderControl, FunderIncomingControl, FunderOutgoingControl, IncomingPayment, LabeledPayment,
+    PaymentNotifier, PrewarmFailure, PrewarmResult, RemoveFriend, RequestsStatus,
+    ResponseCancelUserRequest, ResponsePrewarm, ResponseReceived, ResponseSendFundsResult,
+    SetFriendStatus, SetRequestsStatus,
 };
+use proto::invite::messages::{
+    ImportFriendInvite, ImportFriendInviteResult, ResponseImportFriendInvite,
+};
+use proto::invite::signature_buff::verify_friend_invite;
 use proto::report::convert::funder_report_mutation_to_index_mutation;
+use proto::signed_export::{append_export_footer, create_export_signature_buffer};
 
+use proto::app_server::debug_bundle::{
+    redact_node_report, serialize_debug_bundle, AppSessionEvent, AppSessionEventKind, DebugBundle,
+    DebugBundleInfo,
+};
 use proto::app_server::messages::{
-    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, NodeReport, NodeReportMutation,
-    ReportMutations,
+    split_by_scope, AppPermissions, AppRequest, AppServerToApp, AppToAppServer, NodeReport,
+    NodeReportMutation, ReportMutations, ReportScope, RequestLabeledPayments, ResponseDebugBundle,
+    ResponseLabeledPayments, ResponseSelfTest, SelfTestStageReport,
+};
+use proto::consts::{
+    MAX_APP_SESSION_EVENTS, MAX_DETACHED_APP_SESSIONS, MAX_INCOMING_PAYMENTS,
+    MAX_OPEN_APP_REQUESTS, PROTOCOL_VERSION,
 };
 use proto::index_client::messages::{
-    AppServerToIndexClient, IndexClientRequest, IndexClientToAppServer,
+    AppServerToIndexClient, ClientResponseRoutes, IndexClientRequest, IndexClientToAppServer,
+    ResponseRoutesResult,
 };
 
+use crate::scheduler::create_app_scheduler;
+use crate::session::{AppSession, DetachedSessions, OpenRequests};
+
 pub type IncomingAppConnection<B> = (
+    AppSession,
     AppPermissions,
     ConnPair<AppServerToApp<B>, AppToAppServer<B>>,
 );
@@ -37,6 +67,7 @@ pub enum AppServerError {
     IndexClientClosed,
     SendToFunderError,
     SendToIndexClientError,
+    RequestSignatureError,
     AllAppsClosed,
 }
 
@@ -49,48 +80,130 @@ pub enum AppServerEvent<B: Clone> {
     FromIndexClient(IndexClientToAppServer<B>),
     IndexClientClosed,
     FromApp((u128, Option<AppToAppServer<B>>)), // None means that app was closed
+    SelfTestDone((AppSession, ResponseSelfTest)),
+    AppRevoked(PublicKey),
 }
 
 pub struct App<B: Clone> {
+    session: AppSession,
     permissions: AppPermissions,
-    opt_sender: Option<mpsc::Sender<AppServerToApp<B>>>,
-    open_route_requests: HashSet<Uid>,
-    open_send_funds_requests: HashSet<Uid>,
+    /// Sends messages to the scheduler of this app
+    opt_sender: Option<mpsc::Sender<(ReportScope, AppServerToApp<B>)>>,
+    /// Sequence number of the next report mutations message of every scope
+    next_seqs: HashMap<ReportScope, u64>,
+    open_requests: OpenRequests,
+    /// Should incoming payments be sent to this app
+    incoming_payments_subscribed: bool,
 }
 
 impl<B> App<B>
 where
     B: Clone,
 {
-    pub fn new(permissions: AppPermissions, sender: mpsc::Sender<AppServerToApp<B>>) -> Self {
+    pub fn new(
+        session: AppSession,
+        permissions: AppPermissions,
+        sender: mpsc::Sender<(ReportScope, AppServerToApp<B>)>,
+    ) -> Self {
         App {
+            session,
             permissions,
             opt_sender: Some(sender),
-            open_route_requests: HashSet::new(),
-            open_send_funds_requests: HashSet::new(),
+            next_seqs: HashMap::new(),
+            open_requests: OpenRequests::default(),
+            incoming_payments_subscribed: false,
         }
     }
 
-    pub async fn send(&mut self, message: AppServerToApp<B>) {
+    async fn send_scoped(&mut self, scope: ReportScope, message: AppServerToApp<B>) {
         if let Some(mut sender) = self.opt_sender.take() {
-            if let Ok(()) = await!(sender.send(message)) {
+            if let Ok(()) = await!(sender.send((scope, message))) {
                 self.opt_sender = Some(sender);
             }
         }
     }
+
+    /// Send a message that is not a report mutation.
+    /// Such messages are ordered together with node scope report mutations.
+    pub async fn send(&mut self, message: AppServerToApp<B>) {
+        await!(self.send_scoped(ReportScope::Node, message))
+    }
+
+    /// Send a batch of report mutations, split into one message per scope.
+    /// The last message carries the app request id (if any), and depends on all the other
+    /// messages of the batch, so that the app considers the request done only after the whole
+    /// batch was applied.
+    pub async fn send_report_mutations(
+        &mut self,
+        opt_app_request_id: Option<Uid>,
+        mutations: Vec<NodeReportMutation<B>>,
+    ) {
+        let mut scoped_mutations = split_by_scope(mutations);
+        if scoped_mutations.is_empty() {
+            if opt_app_request_id.is_none() {
+                return;
+            }
+            // Let the app know that the request is done:
+            scoped_mutations.push((ReportScope::Node, Vec::new()));
+        }
+
+        let last_index = scoped_mutations.len() - 1;
+        let mut dependencies = Vec::new();
+        for (index, (scope, mutations)) in scoped_mutations.into_iter().enumerate() {
+            let next_seq = self.next_seqs.entry(scope.clone()).or_insert(0);
+            let seq = *next_seq;
+            *next_seq = next_seq.wrapping_add(1);
+
+            let report_mutations = if index == last_index {
+                ReportMutations {
+                    opt_app_request_id: opt_app_request_id.clone(),
+                    scope: scope.clone(),
+                    seq,
+                    dependencies: dependencies.clone(),
+                    mutations,
+                }
+            } else {
+                ReportMutations {
+                    opt_app_request_id: None,
+                    scope: scope.clone(),
+                    seq,
+                    dependencies: Vec::new(),
+                    mutations,
+                }
+            };
+            dependencies.push((scope.clone(), seq));
+            await!(self.send_scoped(scope, AppServerToApp::ReportMutations(report_mutations)));
+        }
+    }
 }
 
-pub struct AppServer<B: Clone, TF, TIC, S> {
+pub struct AppServer<B: Clone, TF, TIC, ST, S> {
     to_funder: TF,
     to_index_client: TIC,
     from_app_sender: mpsc::Sender<(u128, Option<AppToAppServer<B>>)>,
+    /// Signs debug bundles using the identity of the node
+    identity_client: IdentityClient,
+    /// Runs a self test of the node. Returns a report for every stage that was run.
+    self_tester: ST,
+    /// Results of self tests, together with the session that requested them
+    self_test_sender: mpsc::Sender<(AppSession, ResponseSelfTest)>,
     node_report: NodeReport<B>,
+    /// Consumer of notifications about incoming payments, as configured in the funder
+    opt_payment_notifier: Option<PaymentNotifier<B>>,
+    /// Incoming payments that were not yet acknowledged by an app, ordered by notification_id.
+    /// Only kept while the consumer of incoming payments is the node's apps.
+    /// Like the funder's outbox, holds at most `MAX_INCOMING_PAYMENTS` payments.
+    incoming_payments: BTreeMap<u64, IncomingPayment>,
     incoming_connections_closed: bool,
     /// A long cyclic incrementing counter,
     /// allows to give every connection a unique number.
     /// Required because an app (with one public key) might have multiple connections.
     app_counter: u128,
     apps: HashMap<u128, App<B>>,
+    /// Sessions whose connection was closed, waiting to be resumed
+    detached_sessions: DetachedSessions<B>,
+    /// Recent session events, oldest first. Included in debug bundles.
+    session_events: VecDeque<AppSessionEvent>,
     spawner: S,
 }
 
@@ -100,7 +213,13 @@ fn check_permissions<B>(app_permissions: &AppPermissions, app_request: &AppReque
         AppRequest::AddRelay(_) => app_permissions.config,
         AppRequest::RemoveRelay(_) => app_permissions.config,
         AppRequest::RequestSendFunds(_) => app_permissions.send_funds,
+        AppRequest::RequestSweepFunds(_) => app_permissions.send_funds,
+        AppRequest::CancelUserRequest(_) => app_permissions.send_funds,
+        AppRequest::ListPendingSubmissions(_) => app_permissions.send_funds,
+        AppRequest::AckSubmission(_) => app_permissions.send_funds,
+        AppRequest::RequestLabeledPayments(_) => app_permissions.send_funds,
         AppRequest::ReceiptAck(_) => app_permissions.send_funds,
+        AppRequest::PrewarmFriend(_) => app_permissions.send_funds,
         AppRequest::AddFriend(_) => app_permissions.config,
         AppRequest::SetFriendRelays(_) => app_permissions.config,
         AppRequest::SetFriendName(_) => app_permissions.config,
@@ -110,37 +229,239 @@ fn check_permissions<B>(app_permissions: &AppPermissions, app_request: &AppReque
         AppRequest::OpenFriend(_) => app_permissions.config,
         AppRequest::CloseFriend(_) => app_permissions.config,
         AppRequest::SetFriendRemoteMaxDebt(_) => app_permissions.config,
+        AppRequest::SetFriendResponseDeadline(_) => app_permissions.config,
+        AppRequest::SetFriendVerificationPhrase(_) => app_permissions.config,
+        AppRequest::SetFriendIndexPrivate(_) => app_permissions.config,
         AppRequest::ResetFriendChannel(_) => app_permissions.config,
+        AppRequest::ImportFriendInvite(_) => app_permissions.config,
+        AppRequest::SetPairFreezeLimit(_) => app_permissions.config,
+        AppRequest::RequestPairFreezeUsage(_) => app_permissions.config,
         AppRequest::RequestRoutes(_) => app_permissions.routes,
         AppRequest::AddIndexServer(_) => app_permissions.config,
         AppRequest::RemoveIndexServer(_) => app_permissions.config,
+        AppRequest::SetPaymentNotifier(_) => app_permissions.config,
+        AppRequest::ClearPaymentNotifier => app_permissions.config,
+        AppRequest::SubscribeIncomingPayments => app_permissions.config,
+        AppRequest::AckIncomingPayment(_) => app_permissions.config,
+        AppRequest::RequestDebugBundle(_) => app_permissions.config,
+        AppRequest::RequestSelfTest(_) => true,
+        AppRequest::SetDustThresholds(_) => app_permissions.config,
+        AppRequest::SetDirectory(_) => app_permissions.config,
+        AppRequest::ClearDirectory => app_permissions.config,
+        AppRequest::PinDirectoryEntry(_) => app_permissions.config,
+        AppRequest::UnpinDirectoryEntry(_) => app_permissions.config,
+        AppRequest::AnnounceShutdown(_) => app_permissions.config,
     }
 }
 
-impl<B, TF, TIC, S> AppServer<B, TF, TIC, S>
+/// Current time, in seconds since the UNIX epoch
+fn unix_time_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Does the request stay open until a response is sent to the app?
+/// The amount of such requests is limited for every app identity.
+fn is_limited_request<B>(app_request: &AppRequest<B>) -> bool {
+    match app_request {
+        AppRequest::RequestSendFunds(_)
+        | AppRequest::RequestSweepFunds(_)
+        | AppRequest::PrewarmFriend(_)
+        | AppRequest::RequestRoutes(_) => true,
+        _ => false,
+    }
+}
+
+/// Create the body of a debug bundle from the current node report and recent session events.
+/// Tokens are redacted unless `full` is set. The body should be signed before it is sent.
+fn create_debug_bundle<B>(
+    node_report: &NodeReport<B>,
+    session_events: &VecDeque<AppSessionEvent>,
+    full: bool,
+) -> Vec<u8>
 where
-    B: Clone + PartialEq + Eq + Debug + Send + Sync + 'static,
+    B: Clone + Serialize,
+{
+    let mut node_report = node_report.clone();
+    if !full {
+        redact_node_report(&mut node_report);
+    }
+    let debug_bundle = DebugBundle {
+        info: DebugBundleInfo {
+            node_version: env!("CARGO_PKG_VERSION").to_owned(),
+            protocol_version: PROTOCOL_VERSION,
+            redacted: !full,
+        },
+        node_report,
+        app_session_events: session_events.iter().cloned().collect(),
+    };
+    serialize_debug_bundle(&debug_bundle)
+}
+
+/// Sign the body of an export using the identity of the node (See `signed_export`).
+async fn sign_export(
+    identity_client: &IdentityClient,
+    body: Vec<u8>,
+) -> Result<Vec<u8>, AppServerError> {
+    let public_key = await!(identity_client.request_public_key())
+        .map_err(|_| AppServerError::RequestSignatureError)?;
+    let signature_buffer = create_export_signature_buffer(&body, &public_key);
+    let signature = await!(identity_client.request_signature(signature_buffer))
+        .map_err(|_| AppServerError::RequestSignatureError)?;
+    Ok(append_export_footer(body, &public_key, &signature))
+}
+
+/// Find a page of the labeled payments that match a request, oldest first.
+fn find_labeled_payments<'a>(
+    labeled_payments: impl Iterator<Item = &'a LabeledPayment>,
+    request_labeled_payments: &RequestLabeledPayments,
+) -> ResponseLabeledPayments {
+    let offset = usize::try_from(request_labeled_payments.offset).unwrap_or(usize::max_value());
+    let max_results =
+        usize::try_from(request_labeled_payments.max_results).unwrap_or(usize::max_value());
+
+    let mut matching = labeled_payments
+        .filter(|labeled_payment| {
+            request_labeled_payments
+                .filter
+                .is_match(&labeled_payment.label)
+        })
+        .skip(offset);
+    let page = matching.by_ref().take(max_results).cloned().collect();
+
+    ResponseLabeledPayments {
+        request_id: request_labeled_payments.request_id,
+        labeled_payments: page,
+        more: matching.next().is_some(),
+    }
+}
+
+impl<B, TF, TIC, ST, S> AppServer<B, TF, TIC, ST, S>
+where
+    B: Clone + PartialEq + Eq + Debug + Serialize + CanonicalSerialize + Send + Sync + 'static,
     TF: Sink<SinkItem = FunderIncomingControl<B>> + Unpin + Sync + Send,
     TIC: Sink<SinkItem = AppServerToIndexClient<B>> + Unpin,
+    ST: FutTransform<Input = (), Output = Vec<SelfTestStageReport>> + Clone + Send + 'static,
     S: Spawn,
 {
     pub fn new(
         to_funder: TF,
         to_index_client: TIC,
         from_app_sender: mpsc::Sender<(u128, Option<AppToAppServer<B>>)>,
+        identity_client: IdentityClient,
+        self_tester: ST,
+        self_test_sender: mpsc::Sender<(AppSession, ResponseSelfTest)>,
         node_report: NodeReport<B>,
+        opt_payment_notifier: Option<PaymentNotifier<B>>,
+        incoming_payments: Vec<IncomingPayment>,
         spawner: S,
     ) -> Self {
-        AppServer {
+        let mut app_server = AppServer {
             to_funder,
             to_index_client,
             from_app_sender,
+            identity_client,
+            self_tester,
+            self_test_sender,
             node_report,
+            opt_payment_notifier,
+            incoming_payments: BTreeMap::new(),
             incoming_connections_closed: false,
             app_counter: 0,
             apps: HashMap::new(),
+            detached_sessions: DetachedSessions::new(MAX_DETACHED_APP_SESSIONS),
+            session_events: VecDeque::new(),
             spawner,
+        };
+        if app_server.apps_consume_payments() {
+            for incoming_payment in incoming_payments {
+                app_server.insert_incoming_payment(incoming_payment);
+            }
+        }
+        app_server
+    }
+
+    /// Are incoming payments delivered to the node's apps?
+    fn apps_consume_payments(&self) -> bool {
+        match &self.opt_payment_notifier {
+            Some(payment_notifier) => payment_notifier.opt_address().is_none(),
+            None => false,
+        }
+    }
+
+    /// Keep an incoming payment until it is acknowledged.
+    /// Returns false if we already had this payment.
+    fn insert_incoming_payment(&mut self, incoming_payment: IncomingPayment) -> bool {
+        let notification_id = incoming_payment.notification_id;
+        if self
+            .incoming_payments
+            .insert(notification_id, incoming_payment)
+            .is_some()
+        {
+            return false;
+        }
+        // Drop the oldest payments, as the funder does:
+        while self.incoming_payments.len() > MAX_INCOMING_PAYMENTS {
+            let oldest_id = *self.incoming_payments.keys().next().unwrap();
+            self.incoming_payments.remove(&oldest_id);
+        }
+        true
+    }
+
+    /// Record a session event, for diagnostics
+    fn add_session_event(&mut self, session: &AppSession, kind: AppSessionEventKind) {
+        info!("App session {:?}: {:?}", session, kind);
+        self.session_events.push_back(AppSessionEvent {
+            app_public_key: session.app_public_key.clone(),
+            session_id: session.session_id,
+            kind,
+        });
+        if self.session_events.len() > MAX_APP_SESSION_EVENTS {
+            self.session_events.pop_front();
+        }
+    }
+
+    /// Keep the open requests of an app whose connection was closed, until its session is
+    /// resumed.
+    fn detach_app(&mut self, app: App<B>) {
+        self.add_session_event(&app.session, AppSessionEventKind::Detached);
+        if let Some(expired_session) = self
+            .detached_sessions
+            .insert(app.session, app.open_requests)
+        {
+            self.add_session_event(&expired_session, AppSessionEventKind::Expired);
+        }
+    }
+
+    /// Amount of open requests of an app identity, across all of its sessions
+    fn num_open_requests(&self, app_public_key: &PublicKey) -> usize {
+        let num_connected: usize = self
+            .apps
+            .values()
+            .filter(|app| &app.session.app_public_key == app_public_key)
+            .map(|app| app.open_requests.len())
+            .sum();
+        num_connected + self.detached_sessions.num_open_requests(app_public_key)
+    }
+
+    /// Send a response to the session that issued the request. `take_request` removes the
+    /// request from the open requests of a session, and returns true if the session issued the
+    /// request. If the session is disconnected, the response is kept until the session is resumed.
+    ///
+    /// Other sessions of the same app only learn about the result through report mutations.
+    async fn send_response<F>(&mut self, mut take_request: F, response: AppServerToApp<B>)
+    where
+        F: FnMut(&mut OpenRequests) -> bool,
+    {
+        for app in self.apps.values_mut() {
+            if take_request(&mut app.open_requests) {
+                await!(app.send(response));
+                return;
+            }
         }
+        self.detached_sessions.keep_response(take_request, response);
     }
 
     /// Add an application connection
@@ -148,7 +469,7 @@ where
         &mut self,
         incoming_app_connection: IncomingAppConnection<B>,
     ) -> Result<(), AppServerError> {
-        let (permissions, (sender, receiver)) = incoming_app_connection;
+        let (session, permissions, (sender, receiver)) = incoming_app_connection;
 
         let app_counter = self.app_counter;
         let mut receiver =
@@ -166,10 +487,35 @@ where
             .spawn(send_all_fut)
             .map_err(|_| AppServerError::SpawnError)?;
 
-        let mut app = App::new(permissions, sender);
+        let scheduler_sender = create_app_scheduler(sender, &mut self.spawner)
+            .map_err(|_| AppServerError::SpawnError)?;
+        let mut app = App::new(session.clone(), permissions, scheduler_sender);
         // Send the initial node report:
         await!(app.send(AppServerToApp::Report(self.node_report.clone())));
 
+        let opt_replaced_id = self
+            .apps
+            .iter()
+            .find(|(_, app)| app.session == session)
+            .map(|(app_id, _)| *app_id);
+
+        if let Some(replaced_id) = opt_replaced_id {
+            // The session already has a connection. The new connection takes over the open
+            // requests of the session. Dropping the old connection closes it:
+            let replaced_app = self.apps.remove(&replaced_id).unwrap();
+            app.open_requests = replaced_app.open_requests;
+            self.add_session_event(&session, AppSessionEventKind::Replaced);
+        } else if let Some(detached_session) = self.detached_sessions.remove(&session) {
+            // Resume the session, sending the responses that arrived while it was disconnected:
+            app.open_requests = detached_session.open_requests;
+            for response in detached_session.responses {
+                await!(app.send(response));
+            }
+            self.add_session_event(&session, AppSessionEventKind::Resumed);
+        } else {
+            self.add_session_event(&session, AppSessionEventKind::Opened);
+        }
+
         self.apps.insert(self.app_counter, app);
         self.app_counter = self.app_counter.wrapping_add(1);
 
@@ -187,10 +533,14 @@ where
     }
 
     /// Send node report mutations to all connected apps
-    pub async fn broadcast_node_report_mutations(&mut self, report_mutations: ReportMutations<B>) {
+    pub async fn broadcast_node_report_mutations(
+        &mut self,
+        opt_app_request_id: Option<Uid>,
+        mutations: Vec<NodeReportMutation<B>>,
+    ) {
         // Send node report mutations to all connected apps
         for app in &mut self.apps.values_mut() {
-            await!(app.send(AppServerToApp::ReportMutations(report_mutations.clone())));
+            await!(app.send_report_mutations(opt_app_request_id.clone(), mutations.clone()));
         }
     }
 
@@ -200,18 +550,52 @@ where
     ) -> Result<(), AppServerError> {
         match funder_message {
             FunderOutgoingControl::ResponseReceived(response_received) => {
-                // Find the app that issued the request, and forward the response to this app:
-                // TODO: Should we break the loop if found?
-                for app in self.apps.values_mut() {
-                    if app
-                        .open_send_funds_requests
-                        .remove(&response_received.request_id)
-                    {
-                        await!(
-                            app.send(AppServerToApp::ResponseReceived(response_received.clone()))
-                        );
-                    }
-                }
+                // Forward the response to the session that issued the request:
+                let request_id = response_received.request_id;
+                await!(self.send_response(
+                    move |open_requests| open_requests.send_funds.remove(&request_id),
+                    AppServerToApp::ResponseReceived(response_received)
+                ));
+            }
+            FunderOutgoingControl::ResponsePrewarm(response_prewarm) => {
+                // Forward the response to the session that issued the pre-warm request:
+                let request_id = response_prewarm.request_id;
+                await!(self.send_response(
+                    move |open_requests| open_requests.prewarm.remove(&request_id),
+                    AppServerToApp::ResponsePrewarm(response_prewarm)
+                ));
+            }
+            FunderOutgoingControl::ResponseCancelUserRequest(response_cancel) => {
+                // Forward the response to the session that asked to cancel the request:
+                let request_id = response_cancel.request_id;
+                await!(self.send_response(
+                    move |open_requests| open_requests.cancel.remove(&request_id),
+                    AppServerToApp::ResponseCancelUserRequest(response_cancel)
+                ));
+            }
+            FunderOutgoingControl::ResponseImportFriendInvite(response_import) => {
+                // Forward the response to the session that imported the invite:
+                let request_id = response_import.request_id;
+                await!(self.send_response(
+                    move |open_requests| open_requests.import_invite.remove(&request_id),
+                    AppServerToApp::ResponseImportFriendInvite(response_import)
+                ));
+            }
+            FunderOutgoingControl::ResponsePendingSubmissions(response_pending) => {
+                // Forward the response to the session that listed the submissions:
+                let request_id = response_pending.request_id;
+                await!(self.send_response(
+                    move |open_requests| open_requests.submissions.remove(&request_id),
+                    AppServerToApp::ResponsePendingSubmissions(response_pending)
+                ));
+            }
+            FunderOutgoingControl::ResponsePairFreezeUsage(response_usage) => {
+                // Forward the response to the session that asked for the usage:
+                let request_id = response_usage.request_id;
+                await!(self.send_response(
+                    move |open_requests| open_requests.pair_freeze_usage.remove(&request_id),
+                    AppServerToApp::ResponsePairFreezeUsage(response_usage)
+                ));
             }
             FunderOutgoingControl::ReportMutations(funder_report_mutations) => {
                 let mut index_mutations = Vec::new();
@@ -236,18 +620,39 @@ where
                     .map_err(|_| AppServerError::SendToIndexClientError)?;
                 }
 
-                let mut report_mutations = ReportMutations {
-                    opt_app_request_id: funder_report_mutations.opt_app_request_id,
-                    mutations: Vec::new(),
-                };
+                let mut mutations = Vec::new();
                 for funder_report_mutation in funder_report_mutations.mutations {
                     let mutation = NodeReportMutation::Funder(funder_report_mutation);
                     // Mutate our node report:
                     self.node_report.mutate(&mutation).unwrap();
-                    report_mutations.mutations.push(mutation);
+                    mutations.push(mutation);
                 }
 
-                await!(self.broadcast_node_report_mutations(report_mutations));
+                await!(self.broadcast_node_report_mutations(
+                    funder_report_mutations.opt_app_request_id,
+                    mutations
+                ));
+            }
+            FunderOutgoingControl::IncomingPayment(incoming_payment) => {
+                // If the consumer is not the node's apps, the payment is delivered by the node's
+                // payment notifier.
+                if !self.apps_consume_payments()
+                    || !self.insert_incoming_payment(incoming_payment.clone())
+                {
+                    return Ok(());
+                }
+                for app in self.apps.values_mut() {
+                    if app.incoming_payments_subscribed {
+                        await!(app.send(AppServerToApp::IncomingPayment(incoming_payment.clone())));
+                    }
+                }
+            }
+            FunderOutgoingControl::PaymentNotifierChanged(opt_payment_notifier) => {
+                self.opt_payment_notifier = opt_payment_notifier;
+                if !self.apps_consume_payments() {
+                    // The funder sends the payments again when the apps become the consumer:
+                    self.incoming_payments.clear();
+                }
             }
         }
         Ok(())
@@ -259,32 +664,26 @@ where
     ) -> Result<(), AppServerError> {
         match index_client_message {
             IndexClientToAppServer::ReportMutations(index_client_report_mutations) => {
-                let mut report_mutations = ReportMutations {
-                    opt_app_request_id: index_client_report_mutations.opt_app_request_id,
-                    mutations: Vec::new(),
-                };
+                let mut mutations = Vec::new();
                 for index_client_report_mutation in index_client_report_mutations.mutations {
                     let mutation = NodeReportMutation::IndexClient(index_client_report_mutation);
                     // Mutate our node report:
                     self.node_report.mutate(&mutation).unwrap();
-                    report_mutations.mutations.push(mutation);
+                    mutations.push(mutation);
                 }
 
-                await!(self.broadcast_node_report_mutations(report_mutations));
+                await!(self.broadcast_node_report_mutations(
+                    index_client_report_mutations.opt_app_request_id,
+                    mutations
+                ));
             }
             IndexClientToAppServer::ResponseRoutes(client_response_routes) => {
-                // We search for the app that issued the request, and send it the response.
-                // TODO: Should we break the loop if we found one originating app?
-                for app in self.apps.values_mut() {
-                    if app
-                        .open_route_requests
-                        .remove(&client_response_routes.request_id)
-                    {
-                        await!(app.send(AppServerToApp::ResponseRoutes(
-                            client_response_routes.clone()
-                        )));
-                    }
-                }
+                // Forward the response to the session that issued the request:
+                let request_id = client_response_routes.request_id;
+                await!(self.send_response(
+                    move |open_requests| open_requests.routes.remove(&request_id),
+                    AppServerToApp::ResponseRoutes(client_response_routes)
+                ));
             }
         };
         Ok(())
@@ -296,7 +695,7 @@ where
         app_message: AppToAppServer<B>,
     ) -> Result<(), AppServerError> {
         // Get the relevant application:
-        let app = match self.apps.get_mut(&app_id) {
+        let app = match self.apps.get(&app_id) {
             Some(app) => app,
             None => {
                 warn!("App {:?} does not exist!", app_id);
@@ -313,6 +712,17 @@ where
             return Ok(());
         }
 
+        // The limit on open requests applies to all the sessions of the app together:
+        let app_public_key = app.session.app_public_key.clone();
+        if is_limited_request(&app_message.app_request)
+            && self.num_open_requests(&app_public_key) >= MAX_OPEN_APP_REQUESTS
+        {
+            warn!("App {:?} has too many open requests", app_public_key);
+            await!(self.reject_request(app_id, app_message.app_request));
+            return Ok(());
+        }
+
+        let app = self.apps.get_mut(&app_id).unwrap();
         let app_request_id = app_message.app_request_id;
 
         match app_message.app_request {
@@ -328,19 +738,78 @@ where
             ))
             .map_err(|_| AppServerError::SendToFunderError),
             AppRequest::RequestSendFunds(user_request_send_funds) => {
-                // Keep track of which application issued this request:
-                app.open_send_funds_requests
+                // Keep track of which session issued this request:
+                app.open_requests
+                    .send_funds
                     .insert(user_request_send_funds.request_id);
-                await!(self.to_funder.send(FunderIncomingControl::new(
+                // The funder records the submission, so that the app could find it later:
+                await!(self.to_funder.send(FunderIncomingControl::new_from_app(
+                    app_public_key,
                     app_request_id,
                     FunderControl::RequestSendFunds(user_request_send_funds)
                 )))
                 .map_err(|_| AppServerError::SendToFunderError)
             }
+            AppRequest::RequestSweepFunds(user_request_sweep_funds) => {
+                // Keep track of which session issued this request:
+                app.open_requests
+                    .send_funds
+                    .insert(user_request_sweep_funds.request_id);
+                await!(self.to_funder.send(FunderIncomingControl::new_from_app(
+                    app_public_key,
+                    app_request_id,
+                    FunderControl::RequestSweepFunds(user_request_sweep_funds)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::CancelUserRequest(request_id) => {
+                // An app may only cancel requests it has issued:
+                if !app.open_requests.send_funds.contains(&request_id) {
+                    let response_cancel = ResponseCancelUserRequest {
+                        request_id,
+                        result: CancelUserRequestResult::NotFound,
+                    };
+                    await!(app.send(AppServerToApp::ResponseCancelUserRequest(response_cancel)));
+                    return Ok(());
+                }
+                app.open_requests.cancel.insert(request_id);
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::CancelUserRequest(request_id)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::ListPendingSubmissions(request_id) => {
+                // Keep track of which session issued this request:
+                app.open_requests.submissions.insert(request_id);
+                await!(self.to_funder.send(FunderIncomingControl::new_from_app(
+                    app_public_key,
+                    app_request_id,
+                    FunderControl::ListPendingSubmissions(request_id)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::AckSubmission(request_id) => {
+                await!(self.to_funder.send(FunderIncomingControl::new_from_app(
+                    app_public_key,
+                    app_request_id,
+                    FunderControl::AckSubmission(request_id)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
             AppRequest::ReceiptAck(receipt_ack) => await!(self.to_funder.send(
                 FunderIncomingControl::new(app_request_id, FunderControl::ReceiptAck(receipt_ack))
             ))
             .map_err(|_| AppServerError::SendToFunderError),
+            AppRequest::PrewarmFriend(prewarm_friend) => {
+                // Keep track of which session issued this request:
+                app.open_requests.prewarm.insert(prewarm_friend.request_id);
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::PrewarmFriend(prewarm_friend)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
             AppRequest::AddFriend(add_friend) => await!(self.to_funder.send(
                 FunderIncomingControl::new(app_request_id, FunderControl::AddFriend(add_friend))
             ))
@@ -418,6 +887,27 @@ where
                 )))
                 .map_err(|_| AppServerError::SendToFunderError)
             }
+            AppRequest::SetFriendResponseDeadline(set_friend_response_deadline) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::SetFriendResponseDeadline(set_friend_response_deadline)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::SetFriendVerificationPhrase(set_friend_verification_phrase) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::SetFriendVerificationPhrase(set_friend_verification_phrase)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::SetFriendIndexPrivate(set_friend_index_private) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::SetFriendIndexPrivate(set_friend_index_private)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
             AppRequest::ResetFriendChannel(reset_friend_channel) => {
                 await!(self.to_funder.send(FunderIncomingControl::new(
                     app_request_id,
@@ -425,9 +915,71 @@ where
                 )))
                 .map_err(|_| AppServerError::SendToFunderError)
             }
+            AppRequest::SetPairFreezeLimit(set_pair_freeze_limit) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::SetPairFreezeLimit(set_pair_freeze_limit)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::RequestPairFreezeUsage(request_usage) => {
+                // Keep track of which session issued this request:
+                app.open_requests
+                    .pair_freeze_usage
+                    .insert(request_usage.request_id);
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::RequestPairFreezeUsage(request_usage)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::ImportFriendInvite(import_friend_invite) => {
+                let ImportFriendInvite {
+                    request_id,
+                    invite,
+                    name,
+                    accept_suggested_max_debt,
+                } = import_friend_invite;
+
+                // The invite is checked here, as the funder has no notion of wall clock time:
+                let opt_failure = if !verify_friend_invite(&invite) {
+                    Some(ImportFriendInviteResult::InvalidSignature)
+                } else if invite.expiry_time <= unix_time_now() {
+                    Some(ImportFriendInviteResult::Expired)
+                } else {
+                    None
+                };
+                if let Some(result) = opt_failure {
+                    let response_import = ResponseImportFriendInvite { request_id, result };
+                    await!(app.send(AppServerToApp::ResponseImportFriendInvite(response_import)));
+                    return Ok(());
+                }
+
+                let opt_remote_max_debt = if accept_suggested_max_debt {
+                    invite.opt_suggested_max_debt
+                } else {
+                    None
+                };
+                let add_friend_from_invite = AddFriendFromInvite {
+                    request_id,
+                    add_friend: AddFriend {
+                        friend_public_key: invite.public_key,
+                        relays: invite.relays,
+                        name,
+                        balance: 0,
+                    },
+                    opt_remote_max_debt,
+                };
+                app.open_requests.import_invite.insert(request_id);
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::AddFriendFromInvite(add_friend_from_invite)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
             AppRequest::RequestRoutes(request_routes) => {
-                // Keep track of which application issued this request:
-                app.open_route_requests.insert(request_routes.request_id);
+                // Keep track of which session issued this request:
+                app.open_requests.routes.insert(request_routes.request_id);
                 await!(self
                     .to_index_client
                     .send(AppServerToIndexClient::AppRequest((
@@ -450,9 +1002,197 @@ where
                     IndexClientRequest::RemoveIndexServer(index_server_address)
                 ))))
             .map_err(|_| AppServerError::SendToIndexClientError),
+            AppRequest::SetPaymentNotifier(payment_notifier) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::SetPaymentNotifier(payment_notifier)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::ClearPaymentNotifier => await!(self.to_funder.send(
+                FunderIncomingControl::new(app_request_id, FunderControl::ClearPaymentNotifier)
+            ))
+            .map_err(|_| AppServerError::SendToFunderError),
+            AppRequest::SubscribeIncomingPayments => {
+                // Send all the payments that were not yet acknowledged.
+                // They might have been sent to this app (or to another app) before:
+                app.incoming_payments_subscribed = true;
+                for incoming_payment in self.incoming_payments.values() {
+                    await!(app.send(AppServerToApp::IncomingPayment(incoming_payment.clone())));
+                }
+                Ok(())
+            }
+            AppRequest::AckIncomingPayment(notification_id) => {
+                let _ = self.incoming_payments.remove(&notification_id);
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::AckIncomingPayment(notification_id)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::RequestDebugBundle(request_debug_bundle) => {
+                // The node report is only changed between handled events, therefore all of its
+                // parts describe the same point in time:
+                let body = create_debug_bundle(
+                    &self.node_report,
+                    &self.session_events,
+                    request_debug_bundle.full,
+                );
+                let bundle = await!(sign_export(&self.identity_client, body))?;
+                await!(app.send(AppServerToApp::ResponseDebugBundle(ResponseDebugBundle {
+                    request_id: request_debug_bundle.request_id,
+                    bundle,
+                })));
+                Ok(())
+            }
+            AppRequest::RequestSelfTest(request_id) => {
+                // A self test may take a while. It runs in the background, and the response is
+                // sent to the app when it is done:
+                let mut c_self_tester = self.self_tester.clone();
+                let mut c_self_test_sender = self.self_test_sender.clone();
+                let session = app.session.clone();
+                let self_test_fut = async move {
+                    let stages = await!(c_self_tester.transform(()));
+                    let response_self_test = ResponseSelfTest { request_id, stages };
+                    let _ = await!(c_self_test_sender.send((session, response_self_test)));
+                };
+                self.spawner
+                    .spawn(self_test_fut)
+                    .map_err(|_| AppServerError::SpawnError)
+            }
+            AppRequest::SetDustThresholds(dust_thresholds) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::SetDustThresholds(dust_thresholds)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::SetDirectory(subscription) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::SetDirectory(subscription)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::ClearDirectory => await!(self.to_funder.send(
+                FunderIncomingControl::new(app_request_id, FunderControl::ClearDirectory)
+            ))
+            .map_err(|_| AppServerError::SendToFunderError),
+            AppRequest::PinDirectoryEntry(public_key) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::PinDirectoryEntry(public_key)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::UnpinDirectoryEntry(public_key) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::UnpinDirectoryEntry(public_key)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::AnnounceShutdown(goodbye) => {
+                await!(self.to_funder.send(FunderIncomingControl::new(
+                    app_request_id,
+                    FunderControl::AnnounceShutdown(goodbye)
+                )))
+                .map_err(|_| AppServerError::SendToFunderError)
+            }
+            AppRequest::RequestLabeledPayments(request_labeled_payments) => {
+                // The funder reports every labeled payment, so we can answer from our copy of
+                // the node report:
+                let response_labeled_payments = find_labeled_payments(
+                    self.node_report.funder_report.labeled_payments.iter(),
+                    &request_labeled_payments,
+                );
+                await!(app.send(AppServerToApp::ResponseLabeledPayments(
+                    response_labeled_payments
+                )));
+                Ok(())
+            }
         }
     }
 
+    /// Fail a request immediately, because the app has too many open requests
+    async fn reject_request(&mut self, app_id: u128, app_request: AppRequest<B>) {
+        let local_public_key = self.node_report.funder_report.local_public_key.clone();
+        let send_funds_failure = |request_id, opt_label| {
+            AppServerToApp::ResponseReceived(ResponseReceived {
+                request_id,
+                result: ResponseSendFundsResult::Failure((
+                    local_public_key,
+                    FailureReason::RateLimited,
+                )),
+                opt_timing: None,
+                opt_label,
+            })
+        };
+
+        let response = match app_request {
+            AppRequest::RequestSendFunds(user_request_send_funds) => send_funds_failure(
+                user_request_send_funds.request_id,
+                user_request_send_funds.opt_label,
+            ),
+            AppRequest::RequestSweepFunds(user_request_sweep_funds) => {
+                send_funds_failure(user_request_sweep_funds.request_id, None)
+            }
+            AppRequest::PrewarmFriend(prewarm_friend) => {
+                AppServerToApp::ResponsePrewarm(ResponsePrewarm {
+                    request_id: prewarm_friend.request_id,
+                    result: PrewarmResult::Failure(PrewarmFailure::RateLimited),
+                })
+            }
+            AppRequest::RequestRoutes(request_routes) => {
+                AppServerToApp::ResponseRoutes(ClientResponseRoutes {
+                    request_id: request_routes.request_id,
+                    result: ResponseRoutesResult::Failure,
+                })
+            }
+            _ => return,
+        };
+
+        if let Some(app) = self.apps.get_mut(&app_id) {
+            await!(app.send(response));
+        }
+    }
+
+    /// A self test is done. Send the results to the session that requested it, if it is still
+    /// connected.
+    pub async fn handle_self_test_done(
+        &mut self,
+        session: AppSession,
+        response_self_test: ResponseSelfTest,
+    ) -> Result<(), AppServerError> {
+        if let Some(app) = self.apps.values_mut().find(|app| app.session == session) {
+            await!(app.send(AppServerToApp::ResponseSelfTest(response_self_test)));
+        }
+        Ok(())
+    }
+
+    /// An app is no longer trusted. Close all of its sessions.
+    pub async fn handle_app_revoked(
+        &mut self,
+        app_public_key: PublicKey,
+    ) -> Result<(), AppServerError> {
+        let revoked_ids = self
+            .apps
+            .iter()
+            .filter(|(_, app)| app.session.app_public_key == app_public_key)
+            .map(|(app_id, _)| *app_id)
+            .collect::<Vec<_>>();
+
+        for app_id in revoked_ids {
+            // Dropping the app closes its connection:
+            let app = self.apps.remove(&app_id).unwrap();
+            self.add_session_event(&app.session, AppSessionEventKind::Revoked);
+        }
+        for session in self.detached_sessions.remove_app(&app_public_key) {
+            self.add_session_event(&session, AppSessionEventKind::Revoked);
+        }
+        Ok(())
+    }
+
     pub async fn handle_from_app(
         &mut self,
         app_id: u128,
@@ -460,9 +1200,11 @@ where
     ) -> Result<(), AppServerError> {
         match opt_app_message {
             None => {
-                // Remove the application. We assert that this application exists
-                // in our apps map:
-                self.apps.remove(&app_id).unwrap();
+                // The application might have been removed already, if its session was taken
+                // over by another connection, or if it was revoked:
+                if let Some(app) = self.apps.remove(&app_id) {
+                    self.detach_app(app);
+                }
                 if self.apps.is_empty() && self.incoming_connections_closed {
                     return Err(AppServerError::AllAppsClosed);
                 }
@@ -474,30 +1216,43 @@ where
 }
 
 #[allow(unused)]
-pub async fn app_server_loop<B, FF, TF, FIC, TIC, IC, S>(
+pub async fn app_server_loop<B, FF, TF, FIC, TIC, IC, AR, ST, S>(
     from_funder: FF,
     to_funder: TF,
     from_index_client: FIC,
     to_index_client: TIC,
     incoming_connections: IC,
+    app_revocations: AR,
     initial_node_report: NodeReport<B>,
+    opt_payment_notifier: Option<PaymentNotifier<B>>,
+    incoming_payments: Vec<IncomingPayment>,
+    identity_client: IdentityClient,
+    self_tester: ST,
     mut spawner: S,
 ) -> Result<(), AppServerError>
 where
-    B: Clone + PartialEq + Eq + Debug + Send + Sync + 'static,
+    B: Clone + PartialEq + Eq + Debug + Serialize + CanonicalSerialize + Send + Sync + 'static,
     FF: Stream<Item = FunderOutgoingControl<B>> + Unpin + Send,
     TF: Sink<SinkItem = FunderIncomingControl<B>> + Unpin + Sync + Send,
     FIC: Stream<Item = IndexClientToAppServer<B>> + Unpin + Send,
     TIC: Sink<SinkItem = AppServerToIndexClient<B>> + Unpin,
     IC: Stream<Item = IncomingAppConnection<B>> + Unpin + Send,
+    AR: Stream<Item = PublicKey> + Unpin + Send,
+    ST: FutTransform<Input = (), Output = Vec<SelfTestStageReport>> + Clone + Send + 'static,
     S: Spawn,
 {
     let (from_app_sender, from_app_receiver) = mpsc::channel(0);
+    let (self_test_sender, self_test_receiver) = mpsc::channel(0);
     let mut app_server = AppServer::new(
         to_funder,
         to_index_client,
         from_app_sender,
+        identity_client,
+        self_tester,
+        self_test_sender,
         initial_node_report,
+        opt_payment_notifier,
+        incoming_payments,
         spawner,
     );
 
@@ -513,17 +1268,23 @@ where
 
     let from_app_receiver = from_app_receiver.map(AppServerEvent::FromApp);
 
+    let self_test_receiver = self_test_receiver.map(AppServerEvent::SelfTestDone);
+
     let incoming_connections = incoming_connections
         .map(AppServerEvent::IncomingConnection)
         .chain(stream::once(future::ready(
             AppServerEvent::IncomingConnectionsClosed,
         )));
 
+    let app_revocations = app_revocations.map(AppServerEvent::AppRevoked);
+
     let mut events = select_streams![
         from_funder,
         from_index_client,
         from_app_receiver,
-        incoming_connections
+        self_test_receiver,
+        incoming_connections,
+        app_revocations
     ];
 
     while let Some(event) = await!(events.next()) {
@@ -545,6 +1306,12 @@ where
             AppServerEvent::FromApp((app_id, opt_app_message)) => {
                 await!(app_server.handle_from_app(app_id, opt_app_message))?
             }
+            AppServerEvent::SelfTestDone((session, response_self_test)) => {
+                await!(app_server.handle_self_test_done(session, response_self_test))?
+            }
+            AppServerEvent::AppRevoked(app_public_key) => {
+                await!(app_server.handle_app_revoked(app_public_key))?
+            }
         }
     }
     Ok(())
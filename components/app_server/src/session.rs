@@ -0,0 +1,212 @@
+use std::collections::{HashSet, VecDeque};
+
+use crypto::identity::PublicKey;
+use crypto::uid::Uid;
+
+use proto::app_server::messages::AppServerToApp;
+
+/// Identifies a session of an app. An app (identified by its public key) may have multiple
+/// sessions at the same time, for example if it runs on multiple devices.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AppSession {
+    pub app_public_key: PublicKey,
+    /// Chosen by the app
+    pub session_id: Uid,
+}
+
+/// Requests of a session that were not answered yet.
+/// A response is only sent to the session that issued the request.
+#[derive(Debug, Default)]
+pub struct OpenRequests {
+    pub routes: HashSet<Uid>,
+    pub send_funds: HashSet<Uid>,
+    pub prewarm: HashSet<Uid>,
+    /// Requests to cancel a request to send funds
+    pub cancel: HashSet<Uid>,
+    /// Requests to import a friend invite
+    pub import_invite: HashSet<Uid>,
+    /// Requests to list the payment submissions of the app
+    pub submissions: HashSet<Uid>,
+    /// Requests for the frozen credits of a pair of friends
+    pub pair_freeze_usage: HashSet<Uid>,
+}
+
+impl OpenRequests {
+    pub fn len(&self) -> usize {
+        self.routes.len()
+            + self.send_funds.len()
+            + self.prewarm.len()
+            + self.cancel.len()
+            + self.import_invite.len()
+            + self.submissions.len()
+            + self.pair_freeze_usage.len()
+    }
+
+    /// Move all the open requests of `other` into this set
+    pub fn extend(&mut self, other: OpenRequests) {
+        self.routes.extend(other.routes);
+        self.send_funds.extend(other.send_funds);
+        self.prewarm.extend(other.prewarm);
+        self.cancel.extend(other.cancel);
+        self.import_invite.extend(other.import_invite);
+        self.submissions.extend(other.submissions);
+        self.pair_freeze_usage.extend(other.pair_freeze_usage);
+    }
+}
+
+/// A session whose connection was closed. Responses to its open requests are kept until the
+/// session is resumed.
+pub struct DetachedSession<B: Clone> {
+    pub open_requests: OpenRequests,
+    /// Responses received while the session was disconnected, oldest first.
+    /// Every response answers an open request, therefore the amount of kept responses is bounded
+    /// by the limit on open requests.
+    pub responses: Vec<AppServerToApp<B>>,
+}
+
+/// Disconnected sessions, waiting to be resumed
+pub struct DetachedSessions<B: Clone> {
+    /// Ordered by the time of detaching, oldest first
+    sessions: VecDeque<(AppSession, DetachedSession<B>)>,
+    max_sessions: usize,
+}
+
+impl<B> DetachedSessions<B>
+where
+    B: Clone,
+{
+    pub fn new(max_sessions: usize) -> Self {
+        DetachedSessions {
+            sessions: VecDeque::new(),
+            max_sessions,
+        }
+    }
+
+    /// Detach a session whose connection was closed.
+    /// Returns a session that was dropped to make room, if any.
+    pub fn insert(
+        &mut self,
+        session: AppSession,
+        open_requests: OpenRequests,
+    ) -> Option<AppSession> {
+        let detached_session = DetachedSession {
+            open_requests,
+            responses: Vec::new(),
+        };
+        self.sessions.push_back((session, detached_session));
+        if self.sessions.len() > self.max_sessions {
+            self.sessions.pop_front().map(|(session, _)| session)
+        } else {
+            None
+        }
+    }
+
+    pub fn remove(&mut self, session: &AppSession) -> Option<DetachedSession<B>> {
+        let index = self
+            .sessions
+            .iter()
+            .position(|(detached, _)| detached == session)?;
+        self.sessions
+            .remove(index)
+            .map(|(_, detached_session)| detached_session)
+    }
+
+    /// Remove all the sessions of an app. Returns the removed sessions.
+    pub fn remove_app(&mut self, app_public_key: &PublicKey) -> Vec<AppSession> {
+        let mut removed = Vec::new();
+        let mut kept = VecDeque::new();
+        for (session, detached_session) in self.sessions.drain(..) {
+            if &session.app_public_key == app_public_key {
+                removed.push(session);
+            } else {
+                kept.push_back((session, detached_session));
+            }
+        }
+        self.sessions = kept;
+        removed
+    }
+
+    /// Keep a response for the session that issued the request, if it is one of the detached
+    /// sessions. `take_request` removes the request from the open requests of a session, and
+    /// returns true if the session issued the request.
+    pub fn keep_response<F>(&mut self, mut take_request: F, response: AppServerToApp<B>)
+    where
+        F: FnMut(&mut OpenRequests) -> bool,
+    {
+        for (_, detached_session) in self.sessions.iter_mut() {
+            if take_request(&mut detached_session.open_requests) {
+                detached_session.responses.push(response);
+                return;
+            }
+        }
+    }
+
+    /// Amount of open requests of the detached sessions of an app
+    pub fn num_open_requests(&self, app_public_key: &PublicKey) -> usize {
+        self.sessions
+            .iter()
+            .filter(|(session, _)| &session.app_public_key == app_public_key)
+            .map(|(_, detached_session)| detached_session.open_requests.len())
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::uid::UID_LEN;
+
+    fn session(app_index: u8, session_index: u8) -> AppSession {
+        AppSession {
+            app_public_key: PublicKey::from(&[app_index; PUBLIC_KEY_LEN]),
+            session_id: Uid::from(&[session_index; UID_LEN]),
+        }
+    }
+
+    fn open_send_funds(request_index: u8) -> OpenRequests {
+        let mut open_requests = OpenRequests::default();
+        open_requests
+            .send_funds
+            .insert(Uid::from(&[request_index; UID_LEN]));
+        open_requests
+    }
+
+    #[test]
+    fn test_detached_sessions_bounded() {
+        let app0 = session(0, 0).app_public_key;
+        let mut detached_sessions = DetachedSessions::<u32>::new(2);
+        assert!(detached_sessions
+            .insert(session(0, 0), open_send_funds(0))
+            .is_none());
+        assert!(detached_sessions
+            .insert(session(0, 1), open_send_funds(1))
+            .is_none());
+        assert_eq!(detached_sessions.num_open_requests(&app0), 2);
+
+        // The oldest session is dropped:
+        assert_eq!(
+            detached_sessions.insert(session(1, 0), open_send_funds(2)),
+            Some(session(0, 0))
+        );
+        assert!(detached_sessions.remove(&session(0, 0)).is_none());
+        assert_eq!(detached_sessions.num_open_requests(&app0), 1);
+
+        let detached_session = detached_sessions.remove(&session(0, 1)).unwrap();
+        assert_eq!(detached_session.open_requests.len(), 1);
+        assert!(detached_session.responses.is_empty());
+    }
+
+    #[test]
+    fn test_detached_sessions_remove_app() {
+        let mut detached_sessions = DetachedSessions::<u32>::new(4);
+        detached_sessions.insert(session(0, 0), OpenRequests::default());
+        detached_sessions.insert(session(1, 0), OpenRequests::default());
+        detached_sessions.insert(session(0, 1), OpenRequests::default());
+
+        let removed = detached_sessions.remove_app(&session(0, 0).app_public_key);
+        assert_eq!(removed, vec![session(0, 0), session(0, 1)]);
+        assert!(detached_sessions.remove(&session(1, 0)).is_some());
+    }
+}
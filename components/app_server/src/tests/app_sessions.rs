@@ -0,0 +1,338 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::hash::{HashResult, HASH_RESULT_LEN};
+use crypto::identity::{PublicKey, Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, NodeReport, ReportMutations,
+};
+use proto::consts::MAX_OPEN_APP_REQUESTS;
+use proto::funder::messages::{
+    FailureReason, FriendsRoute, FunderControl, FunderIncomingControl, FunderOutgoingControl,
+    Receipt, ResponseReceived, ResponseSendFundsResult, UserRequestSendFunds,
+};
+use proto::index_client::messages::{
+    IndexClientReportMutation, IndexClientReportMutations, IndexClientToAppServer,
+};
+use proto::index_server::messages::NamedIndexServerAddress;
+use proto::report::messages::{FunderReportMutation, FunderReportMutations};
+
+use super::utils::spawn_dummy_app_server;
+
+use crate::server::IncomingAppConnection;
+use crate::session::AppSession;
+
+/// A session of the app with the given index
+fn app_session(app_index: u8, session_index: u8) -> AppSession {
+    AppSession {
+        app_public_key: PublicKey::from(&[app_index; PUBLIC_KEY_LEN]),
+        session_id: Uid::from(&[session_index; UID_LEN]),
+    }
+}
+
+/// Connect a session to the app server. Returns the app side of the connection.
+async fn connect_session<'a>(
+    connections_sender: &'a mut mpsc::Sender<IncomingAppConnection<u32>>,
+    session: AppSession,
+) -> (
+    mpsc::Sender<AppToAppServer<u32>>,
+    mpsc::Receiver<AppServerToApp<u32>>,
+) {
+    let (app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, app_receiver) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: true,
+        send_funds: true,
+        config: true,
+    };
+    await!(connections_sender.send((session, app_permissions, app_server_conn_pair))).unwrap();
+    (app_sender, app_receiver)
+}
+
+async fn recv_report<'a>(
+    app_receiver: &'a mut mpsc::Receiver<AppServerToApp<u32>>,
+) -> NodeReport<u32> {
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::Report(node_report) => node_report,
+        _ => unreachable!(),
+    }
+}
+
+async fn recv_report_mutations<'a>(
+    app_receiver: &'a mut mpsc::Receiver<AppServerToApp<u32>>,
+) -> ReportMutations<u32> {
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::ReportMutations(report_mutations) => report_mutations,
+        _ => unreachable!(),
+    }
+}
+
+async fn recv_response_received<'a>(
+    app_receiver: &'a mut mpsc::Receiver<AppServerToApp<u32>>,
+) -> ResponseReceived {
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::ResponseReceived(response_received) => response_received,
+        _ => unreachable!(),
+    }
+}
+
+fn request_id(index: usize) -> Uid {
+    let mut request_id = Uid::from(&[0; UID_LEN]);
+    request_id[0] = (index & 0xff) as u8;
+    request_id[1] = (index >> 8) as u8;
+    request_id
+}
+
+fn send_funds_request(index: usize) -> AppToAppServer<u32> {
+    let user_request_send_funds = UserRequestSendFunds {
+        request_id: request_id(index),
+        route: FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[0xff; PUBLIC_KEY_LEN]),
+            ],
+        },
+        invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
+        dest_payment: 20,
+        opt_label: None,
+    };
+    AppToAppServer::new(
+        Uid::from(&[22; UID_LEN]),
+        AppRequest::RequestSendFunds(user_request_send_funds),
+    )
+}
+
+fn success_response(index: usize) -> ResponseReceived {
+    ResponseReceived {
+        request_id: request_id(index),
+        result: ResponseSendFundsResult::Success(Receipt {
+            response_hash: HashResult::from(&[2; HASH_RESULT_LEN]),
+            invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
+            dest_payment: 20,
+            signature: Signature::from(&[3; SIGNATURE_LEN]),
+        }),
+        opt_timing: None,
+        opt_label: None,
+    }
+}
+
+/// Make sure that the funder received a send funds request
+fn assert_send_funds(funder_incoming_control: FunderIncomingControl<u32>, index: usize) {
+    match funder_incoming_control.funder_control {
+        FunderControl::RequestSendFunds(user_request_send_funds) => {
+            assert_eq!(user_request_send_funds.request_id, request_id(index))
+        }
+        _ => unreachable!(),
+    };
+}
+
+async fn task_app_server_loop_app_sessions<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        mut index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        mut app_revocations_sender,
+        initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    // The same app connects twice, for example from two devices:
+    let session_a = app_session(0x11, 0);
+    let session_b = app_session(0x11, 1);
+    let (mut app_sender_a, mut app_receiver_a) =
+        await!(connect_session(&mut connections_sender, session_a.clone()));
+    let (_app_sender_b, mut app_receiver_b) =
+        await!(connect_session(&mut connections_sender, session_b.clone()));
+
+    // Both sessions receive the current node report:
+    let mut node_report_a = await!(recv_report(&mut app_receiver_a));
+    let mut node_report_b = await!(recv_report(&mut app_receiver_b));
+    assert_eq!(node_report_a, initial_node_report);
+    assert_eq!(node_report_b, initial_node_report);
+
+    // Both sessions receive the same report mutations:
+    let index_client_report_mutations = IndexClientReportMutations {
+        opt_app_request_id: None,
+        mutations: vec![IndexClientReportMutation::AddIndexServer(
+            NamedIndexServerAddress {
+                public_key: PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]),
+                address: 300u32,
+                name: "IndexServer300".to_string(),
+            },
+        )],
+    };
+    await!(
+        index_client_sender.send(IndexClientToAppServer::ReportMutations(
+            index_client_report_mutations
+        ))
+    )
+    .unwrap();
+
+    let report_mutations_a = await!(recv_report_mutations(&mut app_receiver_a));
+    let report_mutations_b = await!(recv_report_mutations(&mut app_receiver_b));
+    assert_eq!(report_mutations_a, report_mutations_b);
+    for mutation in &report_mutations_a.mutations {
+        node_report_a.mutate(mutation).unwrap();
+        node_report_b.mutate(mutation).unwrap();
+    }
+    assert_eq!(node_report_a, node_report_b);
+    assert_ne!(node_report_a, initial_node_report);
+
+    // Session A sends a payment:
+    await!(app_sender_a.send(send_funds_request(0))).unwrap();
+    assert_send_funds(await!(funder_receiver.next()).unwrap(), 0);
+
+    // Only session A receives the outcome of the payment:
+    await!(funder_sender.send(FunderOutgoingControl::ResponseReceived(
+        success_response(0)
+    )))
+    .unwrap();
+    assert_eq!(
+        await!(recv_response_received(&mut app_receiver_a)),
+        success_response(0)
+    );
+
+    // Session B learns about the payment only through the report mutations:
+    let funder_report_mutations = FunderReportMutations {
+        opt_app_request_id: None,
+        mutations: vec![FunderReportMutation::SetNumReadyReceipts(1)],
+    };
+    await!(funder_sender.send(FunderOutgoingControl::ReportMutations(
+        funder_report_mutations
+    )))
+    .unwrap();
+
+    let report_mutations_b = await!(recv_report_mutations(&mut app_receiver_b));
+    let report_mutations_a = await!(recv_report_mutations(&mut app_receiver_a));
+    assert_eq!(report_mutations_a, report_mutations_b);
+    for mutation in &report_mutations_a.mutations {
+        node_report_a.mutate(mutation).unwrap();
+        node_report_b.mutate(mutation).unwrap();
+    }
+    assert_eq!(node_report_a, node_report_b);
+    assert!(app_receiver_b.try_next().is_err());
+
+    // Session A disconnects while it has an open request:
+    await!(app_sender_a.send(send_funds_request(1))).unwrap();
+    assert_send_funds(await!(funder_receiver.next()).unwrap(), 1);
+    drop(app_sender_a);
+    assert!(await!(app_receiver_a.next()).is_none());
+
+    // The outcome arrives while session A is disconnected:
+    await!(funder_sender.send(FunderOutgoingControl::ResponseReceived(
+        success_response(1)
+    )))
+    .unwrap();
+
+    // Session A is resumed. The outcome is delivered after the node report:
+    let (_app_sender_a, mut app_receiver_a) =
+        await!(connect_session(&mut connections_sender, session_a.clone()));
+    assert_eq!(await!(recv_report(&mut app_receiver_a)), node_report_a);
+    assert_eq!(
+        await!(recv_response_received(&mut app_receiver_a)),
+        success_response(1)
+    );
+    assert!(app_receiver_b.try_next().is_err());
+
+    // The app is revoked. Both of its sessions are closed:
+    await!(app_revocations_sender.send(session_a.app_public_key.clone())).unwrap();
+    assert!(await!(app_receiver_a.next()).is_none());
+    assert!(await!(app_receiver_b.next()).is_none());
+}
+
+#[test]
+fn test_app_server_loop_app_sessions() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_app_sessions(thread_pool.clone()));
+}
+
+async fn task_app_server_loop_app_sessions_rate_limit<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _app_revocations_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    // Two sessions of one app, and a session of another app:
+    let (mut app_sender_a, mut app_receiver_a) =
+        await!(connect_session(&mut connections_sender, app_session(0x11, 0)));
+    let (mut app_sender_b, mut app_receiver_b) =
+        await!(connect_session(&mut connections_sender, app_session(0x11, 1)));
+    let (mut app_sender_c, mut app_receiver_c) =
+        await!(connect_session(&mut connections_sender, app_session(0x22, 2)));
+
+    let _ = await!(recv_report(&mut app_receiver_a));
+    let _ = await!(recv_report(&mut app_receiver_b));
+    let _ = await!(recv_report(&mut app_receiver_c));
+
+    // Both sessions of the first app open requests, until the limit of the app is reached:
+    for index in 0..MAX_OPEN_APP_REQUESTS {
+        let app_sender = if index % 2 == 0 {
+            &mut app_sender_a
+        } else {
+            &mut app_sender_b
+        };
+        await!(app_sender.send(send_funds_request(index))).unwrap();
+        assert_send_funds(await!(funder_receiver.next()).unwrap(), index);
+    }
+
+    // A further request of the app fails immediately:
+    await!(app_sender_b.send(send_funds_request(MAX_OPEN_APP_REQUESTS))).unwrap();
+    let response_received = await!(recv_response_received(&mut app_receiver_b));
+    assert_eq!(
+        response_received.request_id,
+        request_id(MAX_OPEN_APP_REQUESTS)
+    );
+    match response_received.result {
+        ResponseSendFundsResult::Failure((_, FailureReason::RateLimited)) => {}
+        _ => unreachable!(),
+    };
+
+    // The other app is not limited:
+    await!(app_sender_c.send(send_funds_request(MAX_OPEN_APP_REQUESTS + 1))).unwrap();
+    assert_send_funds(
+        await!(funder_receiver.next()).unwrap(),
+        MAX_OPEN_APP_REQUESTS + 1,
+    );
+
+    // One of the requests is done. The first app may open a new request:
+    await!(funder_sender.send(FunderOutgoingControl::ResponseReceived(
+        success_response(0)
+    )))
+    .unwrap();
+    assert_eq!(
+        await!(recv_response_received(&mut app_receiver_a)),
+        success_response(0)
+    );
+
+    await!(app_sender_b.send(send_funds_request(MAX_OPEN_APP_REQUESTS + 2))).unwrap();
+    assert_send_funds(
+        await!(funder_receiver.next()).unwrap(),
+        MAX_OPEN_APP_REQUESTS + 2,
+    );
+}
+
+#[test]
+fn test_app_server_loop_app_sessions_rate_limit() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_app_sessions_rate_limit(
+        thread_pool.clone(),
+    ));
+}
@@ -0,0 +1,159 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::funder::messages::{
+    CancelUserRequestResult, FailureReason, FriendsRoute, FunderControl, FunderOutgoingControl,
+    ResponseCancelUserRequest, ResponseReceived, ResponseSendFundsResult, UserRequestSendFunds,
+};
+
+use super::utils::{dummy_app_session, spawn_dummy_app_server};
+
+async fn task_app_server_loop_cancel_user_request<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _app_revocations_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    // Connect two apps:
+    let (mut app_sender0, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver0) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: true,
+        send_funds: true,
+        config: true,
+    };
+    await!(connections_sender.send((
+        dummy_app_session(0),
+        app_permissions.clone(),
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    let (mut app_sender1, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver1) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    await!(connections_sender.send((
+        dummy_app_session(1),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The apps should receive the current node report as the first message:
+    let _to_app_message = await!(app_receiver0.next()).unwrap();
+    let _to_app_message = await!(app_receiver1.next()).unwrap();
+
+    let pk_e = PublicKey::from(&[0xee; PUBLIC_KEY_LEN]);
+    let pk_f = PublicKey::from(&[0xff; PUBLIC_KEY_LEN]);
+
+    // app0 requests to send funds:
+    let request_id = Uid::from(&[3; UID_LEN]);
+    let user_request_send_funds = UserRequestSendFunds {
+        request_id,
+        route: FriendsRoute {
+            public_keys: vec![pk_e.clone(), pk_f],
+        },
+        invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
+        dest_payment: 20,
+        opt_label: None,
+    };
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[22; UID_LEN]),
+        AppRequest::RequestSendFunds(user_request_send_funds),
+    );
+    await!(app_sender0.send(to_app_server)).unwrap();
+    let _funder_incoming_control = await!(funder_receiver.next()).unwrap();
+
+    // app1 can not cancel a request it did not issue:
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[23; UID_LEN]),
+        AppRequest::CancelUserRequest(request_id),
+    );
+    await!(app_sender1.send(to_app_server)).unwrap();
+    match await!(app_receiver1.next()).unwrap() {
+        AppServerToApp::ResponseCancelUserRequest(response_cancel) => {
+            assert_eq!(response_cancel.request_id, request_id);
+            assert_eq!(response_cancel.result, CancelUserRequestResult::NotFound);
+        }
+        _ => unreachable!(),
+    };
+    assert!(funder_receiver.try_next().is_err());
+
+    // app0 cancels its request. The request is forwarded to the funder:
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[24; UID_LEN]),
+        AppRequest::CancelUserRequest(request_id),
+    );
+    await!(app_sender0.send(to_app_server)).unwrap();
+    let funder_incoming_control = await!(funder_receiver.next()).unwrap();
+    assert_eq!(
+        funder_incoming_control.app_request_id,
+        Uid::from(&[24; UID_LEN])
+    );
+    match funder_incoming_control.funder_control {
+        FunderControl::CancelUserRequest(received_request_id) => {
+            assert_eq!(received_request_id, request_id)
+        }
+        _ => unreachable!(),
+    };
+
+    // The funder fails the request, and then answers the cancel request:
+    let response_received = ResponseReceived {
+        request_id,
+        result: ResponseSendFundsResult::Failure((pk_e, FailureReason::Cancelled)),
+        opt_timing: None,
+        opt_label: None,
+    };
+    await!(funder_sender.send(FunderOutgoingControl::ResponseReceived(
+        response_received.clone()
+    )))
+    .unwrap();
+    let response_cancel = ResponseCancelUserRequest {
+        request_id,
+        result: CancelUserRequestResult::Cancelled,
+    };
+    await!(funder_sender.send(FunderOutgoingControl::ResponseCancelUserRequest(
+        response_cancel.clone()
+    )))
+    .unwrap();
+
+    match await!(app_receiver0.next()).unwrap() {
+        AppServerToApp::ResponseReceived(obtained_response_received) => {
+            assert_eq!(obtained_response_received, response_received);
+        }
+        _ => unreachable!(),
+    };
+    match await!(app_receiver0.next()).unwrap() {
+        AppServerToApp::ResponseCancelUserRequest(obtained_response_cancel) => {
+            assert_eq!(obtained_response_cancel, response_cancel);
+        }
+        _ => unreachable!(),
+    };
+
+    // We shouldn't get an incoming message at app1:
+    assert!(app_receiver1.try_next().is_err());
+}
+
+#[test]
+fn test_app_server_loop_cancel_user_request() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_cancel_user_request(
+        thread_pool.clone(),
+    ));
+}
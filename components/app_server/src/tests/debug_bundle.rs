@@ -0,0 +1,222 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::crypto_rand::{RandValue, RAND_VALUE_LEN};
+use crypto::hash::{HashResult, HASH_RESULT_LEN};
+use crypto::identity::{Identity, PublicKey, Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+use crypto::test_utils::fixture_software_identity;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::debug_bundle::{
+    deserialize_debug_bundle, redact_node_report, verify_debug_bundle, AppSessionEventKind,
+    DebugBundle,
+};
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, NodeReport, RequestDebugBundle,
+};
+use proto::consts::PROTOCOL_VERSION;
+use proto::funder::messages::FunderOutgoingControl;
+use proto::index_client::messages::AppServerToIndexClient;
+use proto::report::messages::{
+    AddFriendReport, ChannelInconsistentReport, ChannelStatusReport, FunderReportMutation,
+    FunderReportMutations, MoveTokenHashedReport, ResetTermsReport,
+};
+
+use super::utils::{dummy_app_session, spawn_dummy_app_server, NODE_IDENTITY_SEED};
+
+/// Request a debug bundle through an app, and return the deserialized bundle.
+async fn request_debug_bundle<'a>(
+    app_sender: &'a mut mpsc::Sender<AppToAppServer<u32>>,
+    app_receiver: &'a mut mpsc::Receiver<AppServerToApp<u32>>,
+    full: bool,
+) -> DebugBundle<u32> {
+    let request_debug_bundle = RequestDebugBundle {
+        request_id: Uid::from(&[5; UID_LEN]),
+        full,
+    };
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[6; UID_LEN]),
+        AppRequest::RequestDebugBundle(request_debug_bundle),
+    );
+    await!(app_sender.send(to_app_server)).unwrap();
+
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::ResponseDebugBundle(response_debug_bundle) => {
+            assert_eq!(response_debug_bundle.request_id, Uid::from(&[5; UID_LEN]));
+            // The bundle is signed by the identity of the node:
+            let node_public_key = fixture_software_identity(NODE_IDENTITY_SEED).get_public_key();
+            assert_eq!(
+                verify_debug_bundle(&response_debug_bundle.bundle).unwrap(),
+                node_public_key
+            );
+            deserialize_debug_bundle(&response_debug_bundle.bundle).unwrap()
+        }
+        _ => unreachable!(),
+    }
+}
+
+async fn task_app_server_loop_debug_bundle<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        _funder_receiver,
+        _index_client_sender,
+        mut index_client_receiver,
+        mut connections_sender,
+        _app_revocations_sender,
+        initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    // Connect an app with config permissions:
+    let (mut app_sender0, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver0) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: true,
+        send_funds: true,
+        config: true,
+    };
+    await!(connections_sender.send((
+        dummy_app_session(0),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // Connect an app without config permissions:
+    let (mut app_sender1, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver1) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: true,
+        send_funds: true,
+        config: false,
+    };
+    await!(connections_sender.send((
+        dummy_app_session(1),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The apps should receive the current node report as the first message:
+    let mut node_report: NodeReport<u32> = match await!(app_receiver0.next()).unwrap() {
+        AppServerToApp::Report(node_report) => node_report,
+        _ => unreachable!(),
+    };
+    assert_eq!(node_report, initial_node_report);
+    let _to_app_message = await!(app_receiver1.next()).unwrap();
+
+    // The funder adds a friend with an inconsistent channel:
+    let friend_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let add_friend_report = AddFriendReport {
+        friend_public_key: friend_public_key.clone(),
+        name: "friend".to_owned(),
+        relays: Vec::new(),
+        balance: -5,
+        opt_last_incoming_move_token: Some(MoveTokenHashedReport {
+            prefix_hash: HashResult::from(&[1; HASH_RESULT_LEN]),
+            local_public_key: node_report.funder_report.local_public_key.clone(),
+            remote_public_key: friend_public_key.clone(),
+            inconsistency_counter: 1,
+            move_token_counter: 2,
+            balance: -5,
+            local_pending_debt: 0,
+            remote_pending_debt: 0,
+            rand_nonce: RandValue::from(&[2; RAND_VALUE_LEN]),
+            new_token: Signature::from(&[3; SIGNATURE_LEN]),
+        }),
+        channel_status: ChannelStatusReport::Inconsistent(ChannelInconsistentReport {
+            local_reset_terms_balance: -5,
+            opt_remote_reset_terms: Some(ResetTermsReport {
+                reset_token: Signature::from(&[4; SIGNATURE_LEN]),
+                balance_for_reset: 5,
+            }),
+        }),
+    };
+    let funder_report_mutations = FunderReportMutations {
+        opt_app_request_id: None,
+        mutations: vec![FunderReportMutation::AddFriend(add_friend_report)],
+    };
+    await!(funder_sender.send(FunderOutgoingControl::ReportMutations(
+        funder_report_mutations
+    )))
+    .unwrap();
+
+    // The new friend is reported to the index client:
+    match await!(index_client_receiver.next()).unwrap() {
+        AppServerToIndexClient::ApplyMutations(_) => {}
+        _ => unreachable!(),
+    };
+
+    // Keep the node report of app0 up to date:
+    match await!(app_receiver0.next()).unwrap() {
+        AppServerToApp::ReportMutations(report_mutations) => {
+            for mutation in &report_mutations.mutations {
+                node_report.mutate(mutation).unwrap();
+            }
+        }
+        _ => unreachable!(),
+    };
+    let _to_app_message = await!(app_receiver1.next()).unwrap();
+
+    // A redacted debug bundle:
+    let debug_bundle = await!(request_debug_bundle(&mut app_sender0, &mut app_receiver0, false));
+    assert_eq!(debug_bundle.info.protocol_version, PROTOCOL_VERSION);
+    assert!(debug_bundle.info.redacted);
+
+    // The bundle matches the node report known to the app, except for the tokens:
+    let mut redacted_node_report = node_report.clone();
+    redact_node_report(&mut redacted_node_report);
+    assert_eq!(debug_bundle.node_report, redacted_node_report);
+
+    // The sessions of both apps were opened:
+    let session_events = &debug_bundle.app_session_events;
+    assert_eq!(session_events.len(), 2);
+    assert!(session_events
+        .iter()
+        .all(|event| event.kind == AppSessionEventKind::Opened));
+
+    let friend_report = debug_bundle
+        .node_report
+        .funder_report
+        .friends
+        .get(&friend_public_key)
+        .unwrap();
+    let move_token_hashed = friend_report.opt_last_incoming_move_token.as_ref().unwrap();
+    assert_eq!(move_token_hashed.new_token, Signature::zero());
+    match &friend_report.channel_status {
+        ChannelStatusReport::Inconsistent(channel_inconsistent) => {
+            let reset_terms = channel_inconsistent.opt_remote_reset_terms.as_ref().unwrap();
+            assert_eq!(reset_terms.reset_token, Signature::zero());
+            assert_eq!(reset_terms.balance_for_reset, 5);
+        }
+        ChannelStatusReport::Consistent(_) => unreachable!(),
+    };
+
+    // A full debug bundle contains the tokens:
+    let debug_bundle = await!(request_debug_bundle(&mut app_sender0, &mut app_receiver0, true));
+    assert!(!debug_bundle.info.redacted);
+    assert_eq!(debug_bundle.node_report, node_report);
+
+    // An app without config permissions can not request a debug bundle:
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[7; UID_LEN]),
+        AppRequest::RequestDebugBundle(RequestDebugBundle {
+            request_id: Uid::from(&[8; UID_LEN]),
+            full: true,
+        }),
+    );
+    await!(app_sender1.send(to_app_server)).unwrap();
+    assert!(app_receiver1.try_next().is_err());
+}
+
+#[test]
+fn test_app_server_loop_debug_bundle() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_debug_bundle(thread_pool.clone()));
+}
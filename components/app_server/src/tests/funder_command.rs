@@ -11,7 +11,7 @@ use proto::app_server::messages::{
 use proto::funder::messages::{FunderControl, FunderOutgoingControl};
 use proto::report::messages::{FunderReportMutation, FunderReportMutations};
 
-use super::utils::{dummy_named_relay_address, spawn_dummy_app_server};
+use super::utils::{dummy_app_session, dummy_named_relay_address, spawn_dummy_app_server};
 
 async fn task_app_server_loop_funder_command<S>(spawner: S)
 where
@@ -23,6 +23,7 @@ where
         _index_client_sender,
         _index_client_receiver,
         mut connections_sender,
+        _app_revocations_sender,
         initial_node_report,
     ) = spawn_dummy_app_server(spawner.clone());
 
@@ -36,7 +37,12 @@ where
         config: true,
     };
 
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_session(0),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     // The app should receive the current node report as the first message:
     let to_app_message = await!(app_receiver.next()).unwrap();
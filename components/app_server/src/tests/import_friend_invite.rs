@@ -0,0 +1,174 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{
+    generate_pkcs8_key_pair, Identity, PublicKey, Signature, SoftwareEd25519Identity,
+    PUBLIC_KEY_LEN, SIGNATURE_LEN,
+};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, RelayAddress,
+};
+use proto::funder::messages::{FunderControl, FunderOutgoingControl};
+use proto::invite::messages::{
+    FriendInvite, ImportFriendInvite, ImportFriendInviteResult, ResponseImportFriendInvite,
+};
+use proto::invite::signature_buff::create_friend_invite_signature_buffer;
+
+use super::utils::{dummy_app_session, spawn_dummy_app_server};
+
+/// Create an invite signed by `identity`
+fn create_invite(identity: &SoftwareEd25519Identity, expiry_time: u64) -> FriendInvite<u32> {
+    let mut invite = FriendInvite {
+        public_key: identity.get_public_key(),
+        relays: vec![RelayAddress {
+            public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            address: 7u32,
+        }],
+        opt_suggested_max_debt: Some(100),
+        expiry_time,
+        signature: Signature::from(&[0; SIGNATURE_LEN]),
+    };
+    invite.signature = identity.sign(&create_friend_invite_signature_buffer(&invite));
+    invite
+}
+
+fn import_request(
+    uid_index: u8,
+    invite: FriendInvite<u32>,
+    accept_suggested_max_debt: bool,
+) -> AppToAppServer<u32> {
+    AppToAppServer::new(
+        Uid::from(&[uid_index; UID_LEN]),
+        AppRequest::ImportFriendInvite(ImportFriendInvite {
+            request_id: Uid::from(&[uid_index; UID_LEN]),
+            invite,
+            name: "friend".to_owned(),
+            accept_suggested_max_debt,
+        }),
+    )
+}
+
+async fn task_app_server_loop_import_friend_invite<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _app_revocations_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: false,
+        send_funds: false,
+        config: true,
+    };
+    await!(connections_sender.send((
+        dummy_app_session(0),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The app should receive the current node report as the first message:
+    let _to_app_message = await!(app_receiver.next()).unwrap();
+
+    let rng = DummyRandom::new(&[1u8]);
+    let pkcs8 = generate_pkcs8_key_pair(&rng);
+    let identity = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
+
+    // A tampered invite is rejected, and never reaches the funder:
+    let mut invite = create_invite(&identity, u64::max_value());
+    invite.opt_suggested_max_debt = Some(1_000_000);
+    await!(app_sender.send(import_request(30, invite, true))).unwrap();
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::ResponseImportFriendInvite(response_import) => {
+            assert_eq!(response_import.request_id, Uid::from(&[30; UID_LEN]));
+            assert_eq!(
+                response_import.result,
+                ImportFriendInviteResult::InvalidSignature
+            );
+        }
+        _ => unreachable!(),
+    };
+    assert!(funder_receiver.try_next().is_err());
+
+    // An expired invite is rejected:
+    let invite = create_invite(&identity, 1);
+    await!(app_sender.send(import_request(31, invite, true))).unwrap();
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::ResponseImportFriendInvite(response_import) => {
+            assert_eq!(response_import.request_id, Uid::from(&[31; UID_LEN]));
+            assert_eq!(response_import.result, ImportFriendInviteResult::Expired);
+        }
+        _ => unreachable!(),
+    };
+    assert!(funder_receiver.try_next().is_err());
+
+    // A valid invite is forwarded to the funder, together with the suggested max debt:
+    let invite = create_invite(&identity, u64::max_value());
+    await!(app_sender.send(import_request(32, invite.clone(), true))).unwrap();
+    let funder_incoming_control = await!(funder_receiver.next()).unwrap();
+    assert_eq!(
+        funder_incoming_control.app_request_id,
+        Uid::from(&[32; UID_LEN])
+    );
+    match funder_incoming_control.funder_control {
+        FunderControl::AddFriendFromInvite(add_friend_from_invite) => {
+            assert_eq!(add_friend_from_invite.request_id, Uid::from(&[32; UID_LEN]));
+            let add_friend = add_friend_from_invite.add_friend;
+            assert_eq!(add_friend.friend_public_key, invite.public_key);
+            assert_eq!(add_friend.relays, invite.relays);
+            assert_eq!(add_friend.name, "friend");
+            assert_eq!(add_friend.balance, 0);
+            assert_eq!(add_friend_from_invite.opt_remote_max_debt, Some(100));
+        }
+        _ => unreachable!(),
+    };
+
+    // The response of the funder is forwarded to the app:
+    let response_import = ResponseImportFriendInvite {
+        request_id: Uid::from(&[32; UID_LEN]),
+        result: ImportFriendInviteResult::Success,
+    };
+    await!(funder_sender.send(FunderOutgoingControl::ResponseImportFriendInvite(
+        response_import.clone()
+    )))
+    .unwrap();
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::ResponseImportFriendInvite(obtained_response_import) => {
+            assert_eq!(obtained_response_import, response_import);
+        }
+        _ => unreachable!(),
+    };
+
+    // The suggested max debt is only used if the app accepted it:
+    await!(app_sender.send(import_request(33, invite, false))).unwrap();
+    let funder_incoming_control = await!(funder_receiver.next()).unwrap();
+    match funder_incoming_control.funder_control {
+        FunderControl::AddFriendFromInvite(add_friend_from_invite) => {
+            assert_eq!(add_friend_from_invite.opt_remote_max_debt, None);
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_app_server_loop_import_friend_invite() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_import_friend_invite(
+        thread_pool.clone(),
+    ));
+}
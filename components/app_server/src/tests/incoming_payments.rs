@@ -0,0 +1,167 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::hash::{HashResult, HASH_RESULT_LEN};
+use crypto::identity::{Signature, SIGNATURE_LEN};
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::funder::messages::{
+    FunderControl, FunderOutgoingControl, IncomingPayment, PaymentConsumer, PaymentNotifier,
+    PaymentNotifyFilter, Receipt,
+};
+
+use super::utils::{dummy_app_session, spawn_dummy_app_server};
+
+fn dummy_incoming_payment(notification_id: u64) -> IncomingPayment {
+    IncomingPayment {
+        notification_id,
+        route_len: 3,
+        receipt: Receipt {
+            response_hash: HashResult::from(&[0x11; HASH_RESULT_LEN]),
+            invoice_id: InvoiceId::from(&[0x22; INVOICE_ID_LEN]),
+            dest_payment: 10,
+            signature: Signature::from(&[0x33; SIGNATURE_LEN]),
+        },
+    }
+}
+
+async fn recv_incoming_payment(app_receiver: &mut mpsc::Receiver<AppServerToApp<u32>>) -> u64 {
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::IncomingPayment(incoming_payment) => {
+            assert_eq!(incoming_payment, dummy_incoming_payment(incoming_payment.notification_id));
+            incoming_payment.notification_id
+        }
+        _ => unreachable!(),
+    }
+}
+
+async fn task_app_server_loop_incoming_payments<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _app_revocations_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let app_permissions = AppPermissions {
+        routes: false,
+        send_funds: false,
+        config: true,
+    };
+
+    // The node's apps become the consumer of incoming payments:
+    let payment_notifier = PaymentNotifier {
+        consumer: PaymentConsumer::Apps,
+        filter: PaymentNotifyFilter::All,
+    };
+    await!(funder_sender.send(FunderOutgoingControl::PaymentNotifierChanged(Some(
+        payment_notifier
+    ))))
+    .unwrap();
+
+    // Payments arrive before any app is connected:
+    for notification_id in 0..2 {
+        let incoming_payment = dummy_incoming_payment(notification_id);
+        await!(funder_sender.send(FunderOutgoingControl::IncomingPayment(incoming_payment)))
+            .unwrap();
+    }
+
+    // Connect app1 and subscribe:
+    let (mut app_sender1, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver1) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    await!(connections_sender.send((
+        dummy_app_session(0),
+        app_permissions.clone(),
+        app_server_conn_pair
+    )))
+    .unwrap();
+    match await!(app_receiver1.next()).unwrap() {
+        AppServerToApp::Report(_) => {}
+        _ => unreachable!(),
+    };
+    let app_request = AppRequest::SubscribeIncomingPayments;
+    await!(app_sender1.send(AppToAppServer::new(Uid::from(&[1; UID_LEN]), app_request))).unwrap();
+
+    assert_eq!(await!(recv_incoming_payment(&mut app_receiver1)), 0);
+    assert_eq!(await!(recv_incoming_payment(&mut app_receiver1)), 1);
+
+    // The funder sends payment 1 again (For example, after the payment notifier was set again).
+    // It is not sent again to the app:
+    for notification_id in 1..3 {
+        let incoming_payment = dummy_incoming_payment(notification_id);
+        await!(funder_sender.send(FunderOutgoingControl::IncomingPayment(incoming_payment)))
+            .unwrap();
+    }
+    assert_eq!(await!(recv_incoming_payment(&mut app_receiver1)), 2);
+
+    // App1 acknowledges payment 0, and disconnects:
+    let app_request = AppRequest::AckIncomingPayment(0);
+    await!(app_sender1.send(AppToAppServer::new(Uid::from(&[2; UID_LEN]), app_request))).unwrap();
+    let to_funder_message = await!(funder_receiver.next()).unwrap();
+    assert_eq!(to_funder_message.app_request_id, Uid::from(&[2; UID_LEN]));
+    match to_funder_message.funder_control {
+        FunderControl::AckIncomingPayment(notification_id) => assert_eq!(notification_id, 0),
+        _ => unreachable!(),
+    };
+    drop(app_sender1);
+    drop(app_receiver1);
+
+    // Connect app2 and subscribe. Payments that were not acknowledged are sent again:
+    let (mut app_sender2, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver2) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    await!(connections_sender.send((
+        dummy_app_session(1),
+        app_permissions.clone(),
+        app_server_conn_pair
+    )))
+    .unwrap();
+    match await!(app_receiver2.next()).unwrap() {
+        AppServerToApp::Report(_) => {}
+        _ => unreachable!(),
+    };
+    let app_request = AppRequest::SubscribeIncomingPayments;
+    await!(app_sender2.send(AppToAppServer::new(Uid::from(&[3; UID_LEN]), app_request))).unwrap();
+    assert_eq!(await!(recv_incoming_payment(&mut app_receiver2)), 1);
+    assert_eq!(await!(recv_incoming_payment(&mut app_receiver2)), 2);
+
+    // Connect app3 and subscribe. App3 never reads its messages:
+    let (mut app_sender3, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, _app_receiver3) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    await!(connections_sender.send((
+        dummy_app_session(2),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
+    let app_request = AppRequest::SubscribeIncomingPayments;
+    await!(app_sender3.send(AppToAppServer::new(Uid::from(&[4; UID_LEN]), app_request))).unwrap();
+
+    // The funder is not held back by the slow app:
+    for notification_id in 3..0x40 {
+        let incoming_payment = dummy_incoming_payment(notification_id);
+        await!(funder_sender.send(FunderOutgoingControl::IncomingPayment(incoming_payment)))
+            .unwrap();
+    }
+    for notification_id in 3..0x40 {
+        assert_eq!(await!(recv_incoming_payment(&mut app_receiver2)), notification_id);
+    }
+}
+
+#[test]
+fn test_app_server_loop_incoming_payments() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_incoming_payments(thread_pool.clone()));
+}
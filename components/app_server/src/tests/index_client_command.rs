@@ -14,7 +14,7 @@ use proto::index_client::messages::{
 };
 use proto::index_server::messages::NamedIndexServerAddress;
 
-use super::utils::spawn_dummy_app_server;
+use super::utils::{dummy_app_session, spawn_dummy_app_server};
 
 async fn task_app_server_loop_index_client_command<S>(spawner: S)
 where
@@ -26,6 +26,7 @@ where
         mut index_client_sender,
         mut index_client_receiver,
         mut connections_sender,
+        _app_revocations_sender,
         initial_node_report,
     ) = spawn_dummy_app_server(spawner.clone());
 
@@ -39,7 +40,12 @@ where
         config: true,
     };
 
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_session(0),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     // The app should receive the current node report as the first message:
     let to_app_message = await!(app_receiver.next()).unwrap();
@@ -0,0 +1,226 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::debug_bundle::{deserialize_debug_bundle, DebugBundle};
+use proto::app_server::messages::{
+    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, LabelFilter, RequestDebugBundle,
+    RequestLabeledPayments, ResponseLabeledPayments,
+};
+use proto::funder::messages::{
+    FailureReason, FunderOutgoingControl, LabeledPayment, ResponseSendFundsResult,
+};
+use proto::report::messages::{FunderReportMutation, FunderReportMutations};
+
+use super::utils::{dummy_app_session, spawn_dummy_app_server};
+
+async fn request_labeled_payments<'a>(
+    app_sender: &'a mut mpsc::Sender<AppToAppServer<u32>>,
+    app_receiver: &'a mut mpsc::Receiver<AppServerToApp<u32>>,
+    filter: LabelFilter,
+    offset: u64,
+    max_results: u64,
+) -> ResponseLabeledPayments {
+    let request_labeled_payments = RequestLabeledPayments {
+        request_id: Uid::from(&[3; UID_LEN]),
+        filter,
+        offset,
+        max_results,
+    };
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[4; UID_LEN]),
+        AppRequest::RequestLabeledPayments(request_labeled_payments),
+    );
+    await!(app_sender.send(to_app_server)).unwrap();
+
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::ResponseLabeledPayments(response_labeled_payments) => {
+            assert_eq!(
+                response_labeled_payments.request_id,
+                Uid::from(&[3; UID_LEN])
+            );
+            response_labeled_payments
+        }
+        _ => unreachable!(),
+    }
+}
+
+async fn request_debug_bundle<'a>(
+    app_sender: &'a mut mpsc::Sender<AppToAppServer<u32>>,
+    app_receiver: &'a mut mpsc::Receiver<AppServerToApp<u32>>,
+    full: bool,
+) -> DebugBundle<u32> {
+    let request_debug_bundle = RequestDebugBundle {
+        request_id: Uid::from(&[5; UID_LEN]),
+        full,
+    };
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[6; UID_LEN]),
+        AppRequest::RequestDebugBundle(request_debug_bundle),
+    );
+    await!(app_sender.send(to_app_server)).unwrap();
+
+    match await!(app_receiver.next()).unwrap() {
+        AppServerToApp::ResponseDebugBundle(response_debug_bundle) => {
+            deserialize_debug_bundle(&response_debug_bundle.bundle).unwrap()
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn labels(labeled_payments: &[LabeledPayment]) -> Vec<&[u8]> {
+    labeled_payments
+        .iter()
+        .map(|labeled_payment| &labeled_payment.label[..])
+        .collect()
+}
+
+async fn task_app_server_loop_labeled_payments<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        _funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _app_revocations_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    let (mut app_sender, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: false,
+        send_funds: true,
+        config: true,
+    };
+    await!(connections_sender.send((
+        dummy_app_session(0),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The app should receive the current node report as the first message:
+    let _to_app_message = await!(app_receiver.next()).unwrap();
+
+    // The funder reports payments that were sent with labels. The first payment has failed:
+    let all_labels = vec![
+        &b"order-1"[..],
+        &b"order-2"[..],
+        &b"order-3"[..],
+        &b"order-4"[..],
+        &b"refund-1"[..],
+    ];
+    let mut mutations = Vec::new();
+    for (index, label) in all_labels.iter().enumerate() {
+        mutations.push(FunderReportMutation::AddLabeledPayment(LabeledPayment {
+            request_id: Uid::from(&[index as u8; UID_LEN]),
+            label: label.to_vec(),
+            opt_result: None,
+        }));
+    }
+    let failure = ResponseSendFundsResult::Failure((
+        PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+        FailureReason::Unspecified,
+    ));
+    mutations.push(FunderReportMutation::SetLabeledPaymentResult((
+        Uid::from(&[0; UID_LEN]),
+        failure.clone(),
+    )));
+    let funder_report_mutations = FunderReportMutations {
+        opt_app_request_id: None,
+        mutations,
+    };
+    await!(funder_sender.send(FunderOutgoingControl::ReportMutations(
+        funder_report_mutations
+    )))
+    .unwrap();
+    let _to_app_message = await!(app_receiver.next()).unwrap();
+
+    // Find a payment by its exact label:
+    let response = await!(request_labeled_payments(
+        &mut app_sender,
+        &mut app_receiver,
+        LabelFilter::Exact(b"order-1".to_vec()),
+        0,
+        10
+    ));
+    assert_eq!(response.labeled_payments.len(), 1);
+    assert_eq!(response.labeled_payments[0].opt_result, Some(failure));
+    assert!(!response.more);
+
+    // Page through the payments with a label prefix:
+    let response = await!(request_labeled_payments(
+        &mut app_sender,
+        &mut app_receiver,
+        LabelFilter::Prefix(b"order-".to_vec()),
+        0,
+        3
+    ));
+    assert_eq!(
+        labels(&response.labeled_payments),
+        vec![&b"order-1"[..], &b"order-2"[..], &b"order-3"[..]]
+    );
+    assert!(response.more);
+
+    let response = await!(request_labeled_payments(
+        &mut app_sender,
+        &mut app_receiver,
+        LabelFilter::Prefix(b"order-".to_vec()),
+        3,
+        3
+    ));
+    assert_eq!(labels(&response.labeled_payments), vec![&b"order-4"[..]]);
+    assert!(!response.more);
+
+    // An empty prefix matches all the labels:
+    let response = await!(request_labeled_payments(
+        &mut app_sender,
+        &mut app_receiver,
+        LabelFilter::Prefix(Vec::new()),
+        0,
+        10
+    ));
+    assert_eq!(labels(&response.labeled_payments), all_labels);
+
+    // Labels are removed from a redacted debug bundle, but the payments are kept:
+    let debug_bundle = await!(request_debug_bundle(
+        &mut app_sender,
+        &mut app_receiver,
+        false
+    ));
+    let labeled_payments = &debug_bundle.node_report.funder_report.labeled_payments;
+    assert_eq!(labeled_payments.len(), all_labels.len());
+    assert!(labeled_payments
+        .iter()
+        .all(|labeled_payment| labeled_payment.label.is_empty()));
+
+    // A full debug bundle contains the labels:
+    let debug_bundle = await!(request_debug_bundle(
+        &mut app_sender,
+        &mut app_receiver,
+        true
+    ));
+    let labeled_payments = debug_bundle
+        .node_report
+        .funder_report
+        .labeled_payments
+        .iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(labels(&labeled_payments), all_labels);
+}
+
+#[test]
+fn test_app_server_loop_labeled_payments() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_labeled_payments(thread_pool.clone()));
+}
@@ -1,7 +1,16 @@
 mod all_apps_closed;
+mod app_sessions;
+mod cancel_user_request;
+mod debug_bundle;
 mod funder_command;
+mod import_friend_invite;
+mod incoming_payments;
 mod index_client_command;
+mod labeled_payments;
+mod pair_freeze_limits;
+mod pending_submissions;
 mod request_routes;
 mod request_send_funds;
+mod self_test;
 mod two_apps;
 mod utils;
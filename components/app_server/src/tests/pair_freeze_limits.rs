@@ -0,0 +1,129 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::funder::messages::{
+    FriendPair, FunderControl, FunderOutgoingControl, PairFreezeLimit, RequestPairFreezeUsage,
+    ResponsePairFreezeUsage, SetPairFreezeLimit,
+};
+
+use super::utils::{dummy_app_session, spawn_dummy_app_server};
+
+async fn task_app_server_loop_pair_freeze_limits<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _app_revocations_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    // Connect two apps:
+    let mut app_senders = Vec::new();
+    let mut app_receivers = Vec::new();
+    for index in 0..2u8 {
+        let (app_sender, app_server_receiver) = mpsc::channel(0);
+        let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+        let app_server_conn_pair = (app_server_sender, app_server_receiver);
+        let app_permissions = AppPermissions {
+            routes: false,
+            send_funds: false,
+            config: true,
+        };
+        await!(connections_sender.send((
+            dummy_app_session(index),
+            app_permissions,
+            app_server_conn_pair
+        )))
+        .unwrap();
+
+        // The app should receive the current node report as the first message:
+        let _to_app_message = await!(app_receiver.next()).unwrap();
+        app_senders.push(app_sender);
+        app_receivers.push(app_receiver);
+    }
+
+    let pair = FriendPair {
+        opt_from: None,
+        opt_to: Some(PublicKey::from(&[0xbb; PUBLIC_KEY_LEN])),
+    };
+
+    // Setting a limit is forwarded to the funder:
+    let set_pair_freeze_limit = SetPairFreezeLimit {
+        pair: pair.clone(),
+        opt_limit: Some(100),
+    };
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[22; UID_LEN]),
+        AppRequest::SetPairFreezeLimit(set_pair_freeze_limit.clone()),
+    );
+    await!(app_senders[0].send(to_app_server)).unwrap();
+    let funder_incoming_control = await!(funder_receiver.next()).unwrap();
+    match funder_incoming_control.funder_control {
+        FunderControl::SetPairFreezeLimit(obtained_set_pair_freeze_limit) => {
+            assert_eq!(obtained_set_pair_freeze_limit, set_pair_freeze_limit);
+        }
+        _ => unreachable!(),
+    };
+
+    // So is a request for the usage of a pair:
+    let request_usage = RequestPairFreezeUsage {
+        request_id: Uid::from(&[4; UID_LEN]),
+        from_public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+        to_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+    };
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[23; UID_LEN]),
+        AppRequest::RequestPairFreezeUsage(request_usage.clone()),
+    );
+    await!(app_senders[1].send(to_app_server)).unwrap();
+    let funder_incoming_control = await!(funder_receiver.next()).unwrap();
+    match funder_incoming_control.funder_control {
+        FunderControl::RequestPairFreezeUsage(obtained_request_usage) => {
+            assert_eq!(obtained_request_usage, request_usage);
+        }
+        _ => unreachable!(),
+    };
+
+    // The response of the funder is forwarded only to the app that asked for the usage:
+    let response_usage = ResponsePairFreezeUsage {
+        request_id: request_usage.request_id,
+        opt_limit: Some(PairFreezeLimit { pair, limit: 100 }),
+        frozen_credits: 30,
+    };
+    await!(funder_sender.send(FunderOutgoingControl::ResponsePairFreezeUsage(
+        response_usage.clone()
+    )))
+    .unwrap();
+    match await!(app_receivers[1].next()).unwrap() {
+        AppServerToApp::ResponsePairFreezeUsage(obtained_response_usage) => {
+            assert_eq!(obtained_response_usage, response_usage);
+        }
+        _ => unreachable!(),
+    };
+    assert!(app_receivers[0].try_next().is_err());
+
+    // A repeated response has no open request, and is discarded:
+    await!(funder_sender.send(FunderOutgoingControl::ResponsePairFreezeUsage(
+        response_usage
+    )))
+    .unwrap();
+    assert!(app_receivers[0].try_next().is_err());
+    assert!(app_receivers[1].try_next().is_err());
+}
+
+#[test]
+fn test_app_server_loop_pair_freeze_limits() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_pair_freeze_limits(thread_pool.clone()));
+}
@@ -0,0 +1,158 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+use proto::funder::messages::{
+    FriendsRoute, FunderControl, FunderOutgoingControl, PaymentSubmission,
+    ResponsePendingSubmissions, UserRequestSendFunds,
+};
+
+use super::utils::{dummy_app_session, spawn_dummy_app_server};
+
+async fn task_app_server_loop_pending_submissions<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        mut funder_sender,
+        mut funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _app_revocations_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    // Connect two apps:
+    let mut app_senders = Vec::new();
+    let mut app_receivers = Vec::new();
+    for index in 0..2u8 {
+        let (app_sender, app_server_receiver) = mpsc::channel(0);
+        let (app_server_sender, mut app_receiver) = mpsc::channel(0);
+        let app_server_conn_pair = (app_server_sender, app_server_receiver);
+        let app_permissions = AppPermissions {
+            routes: false,
+            send_funds: true,
+            config: false,
+        };
+        await!(connections_sender.send((
+            dummy_app_session(index),
+            app_permissions,
+            app_server_conn_pair
+        )))
+        .unwrap();
+
+        // The app should receive the current node report as the first message:
+        let _to_app_message = await!(app_receiver.next()).unwrap();
+        app_senders.push(app_sender);
+        app_receivers.push(app_receiver);
+    }
+
+    let app_public_key = PublicKey::from(&[0; PUBLIC_KEY_LEN]);
+    let user_request_send_funds = UserRequestSendFunds {
+        request_id: Uid::from(&[3; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[0xff; PUBLIC_KEY_LEN]),
+            ],
+        },
+        invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
+        dest_payment: 20,
+        opt_label: None,
+    };
+
+    // The funder is told which app submitted the payment:
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[22; UID_LEN]),
+        AppRequest::RequestSendFunds(user_request_send_funds.clone()),
+    );
+    await!(app_senders[0].send(to_app_server)).unwrap();
+    let funder_incoming_control = await!(funder_receiver.next()).unwrap();
+    assert_eq!(
+        funder_incoming_control.opt_app_public_key,
+        Some(app_public_key.clone())
+    );
+
+    // So is the case for listing the submissions:
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[23; UID_LEN]),
+        AppRequest::ListPendingSubmissions(Uid::from(&[4; UID_LEN])),
+    );
+    await!(app_senders[0].send(to_app_server)).unwrap();
+    let funder_incoming_control = await!(funder_receiver.next()).unwrap();
+    assert_eq!(
+        funder_incoming_control.opt_app_public_key,
+        Some(app_public_key.clone())
+    );
+    match funder_incoming_control.funder_control {
+        FunderControl::ListPendingSubmissions(request_id) => {
+            assert_eq!(request_id, Uid::from(&[4; UID_LEN]));
+        }
+        _ => unreachable!(),
+    };
+
+    // The response of the funder is forwarded only to the app that listed the submissions:
+    let response_pending = ResponsePendingSubmissions {
+        request_id: Uid::from(&[4; UID_LEN]),
+        submissions: vec![PaymentSubmission {
+            request_id: user_request_send_funds.request_id,
+            app_public_key: app_public_key.clone(),
+            route: user_request_send_funds.route.clone(),
+            invoice_id: user_request_send_funds.invoice_id.clone(),
+            dest_payment: user_request_send_funds.dest_payment,
+            opt_result: None,
+        }],
+    };
+    await!(funder_sender.send(FunderOutgoingControl::ResponsePendingSubmissions(
+        response_pending.clone()
+    )))
+    .unwrap();
+    match await!(app_receivers[0].next()).unwrap() {
+        AppServerToApp::ResponsePendingSubmissions(obtained_response_pending) => {
+            assert_eq!(obtained_response_pending, response_pending);
+        }
+        _ => unreachable!(),
+    };
+    assert!(app_receivers[1].try_next().is_err());
+
+    // A repeated response has no open request, and is discarded:
+    await!(funder_sender.send(FunderOutgoingControl::ResponsePendingSubmissions(
+        response_pending
+    )))
+    .unwrap();
+    assert!(app_receivers[0].try_next().is_err());
+    assert!(app_receivers[1].try_next().is_err());
+
+    // Acknowledgements are forwarded together with the key of the acknowledging app:
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[24; UID_LEN]),
+        AppRequest::AckSubmission(Uid::from(&[3; UID_LEN])),
+    );
+    await!(app_senders[1].send(to_app_server)).unwrap();
+    let funder_incoming_control = await!(funder_receiver.next()).unwrap();
+    assert_eq!(
+        funder_incoming_control.opt_app_public_key,
+        Some(PublicKey::from(&[1; PUBLIC_KEY_LEN]))
+    );
+    match funder_incoming_control.funder_control {
+        FunderControl::AckSubmission(request_id) => {
+            assert_eq!(request_id, Uid::from(&[3; UID_LEN]));
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_app_server_loop_pending_submissions() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_pending_submissions(
+        thread_pool.clone(),
+    ));
+}
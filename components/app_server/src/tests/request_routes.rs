@@ -10,10 +10,10 @@ use crypto::uid::UID_LEN;
 use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
 use proto::index_client::messages::{
     AppServerToIndexClient, ClientResponseRoutes, IndexClientRequest, IndexClientToAppServer,
-    RequestRoutes, ResponseRoutesResult,
+    RequestRoutes, ResponseRoutesResult, RouteDisjointness,
 };
 
-use super::utils::spawn_dummy_app_server;
+use super::utils::{dummy_app_session, spawn_dummy_app_server};
 
 async fn task_app_server_loop_request_routes<S>(spawner: S)
 where
@@ -25,6 +25,7 @@ where
         mut index_client_sender,
         mut index_client_receiver,
         mut connections_sender,
+        _app_revocations_sender,
         _initial_node_report,
     ) = spawn_dummy_app_server(spawner.clone());
 
@@ -37,7 +38,12 @@ where
         send_funds: true,
         config: true,
     };
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_session(0),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     let (_app_sender1, app_server_receiver) = mpsc::channel(0);
     let (app_server_sender, mut app_receiver1) = mpsc::channel(0);
@@ -47,7 +53,12 @@ where
         send_funds: true,
         config: true,
     };
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_session(1),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     // The apps should receive the current node report as the first message:
     let _to_app_message = await!(app_receiver0.next()).unwrap();
@@ -60,6 +71,8 @@ where
         source: PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
         destination: PublicKey::from(&[0xff; PUBLIC_KEY_LEN]),
         opt_exclude: None,
+        disjointness: RouteDisjointness::None,
+        allow_partial: false,
     };
 
     let to_app_server = AppToAppServer::new(
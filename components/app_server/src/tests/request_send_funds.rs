@@ -9,11 +9,11 @@ use crypto::uid::{Uid, UID_LEN};
 
 use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
 use proto::funder::messages::{
-    FriendsRoute, FunderControl, FunderOutgoingControl, ResponseReceived, ResponseSendFundsResult,
-    UserRequestSendFunds,
+    FailureReason, FriendsRoute, FunderControl, FunderOutgoingControl, ResponseReceived,
+    ResponseSendFundsResult, UserRequestSendFunds,
 };
 
-use super::utils::spawn_dummy_app_server;
+use super::utils::{dummy_app_session, spawn_dummy_app_server};
 
 async fn task_app_server_loop_request_send_funds<S>(spawner: S)
 where
@@ -25,6 +25,7 @@ where
         _index_client_sender,
         _index_client_receiver,
         mut connections_sender,
+        _app_revocations_sender,
         _initial_node_report,
     ) = spawn_dummy_app_server(spawner.clone());
 
@@ -37,7 +38,12 @@ where
         send_funds: true,
         config: true,
     };
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_session(0),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     let (_app_sender1, app_server_receiver) = mpsc::channel(0);
     let (app_server_sender, mut app_receiver1) = mpsc::channel(0);
@@ -47,7 +53,12 @@ where
         send_funds: true,
         config: true,
     };
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_session(1),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     // The apps should receive the current node report as the first message:
     let _to_app_message = await!(app_receiver0.next()).unwrap();
@@ -63,6 +74,7 @@ where
         },
         invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
         dest_payment: 20,
+        opt_label: None,
     };
 
     let to_app_server = AppToAppServer::new(
@@ -87,7 +99,9 @@ where
     // Funder returns a response that is not related to any open request.
     let response_received = ResponseReceived {
         request_id: Uid::from(&[2; UID_LEN]),
-        result: ResponseSendFundsResult::Failure(pk_e.clone()),
+        result: ResponseSendFundsResult::Failure((pk_e.clone(), FailureReason::Unspecified)),
+        opt_timing: None,
+        opt_label: None,
     };
     await!(funder_sender.send(FunderOutgoingControl::ResponseReceived(response_received))).unwrap();
 
@@ -98,7 +112,9 @@ where
     // Funder returns a response that corresponds to the open request:
     let response_received = ResponseReceived {
         request_id: Uid::from(&[3; UID_LEN]),
-        result: ResponseSendFundsResult::Failure(pk_e.clone()),
+        result: ResponseSendFundsResult::Failure((pk_e.clone(), FailureReason::Unspecified)),
+        opt_timing: None,
+        opt_label: None,
     };
     await!(funder_sender.send(FunderOutgoingControl::ResponseReceived(
         response_received.clone()
@@ -120,7 +136,9 @@ where
     // has a matching id:
     let response_received = ResponseReceived {
         request_id: Uid::from(&[3; UID_LEN]),
-        result: ResponseSendFundsResult::Failure(pk_e),
+        result: ResponseSendFundsResult::Failure((pk_e, FailureReason::Unspecified)),
+        opt_timing: None,
+        opt_label: None,
     };
     await!(funder_sender.send(FunderOutgoingControl::ResponseReceived(response_received))).unwrap();
 
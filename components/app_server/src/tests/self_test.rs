@@ -0,0 +1,79 @@
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::Spawn;
+use futures::{SinkExt, StreamExt};
+
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{AppPermissions, AppRequest, AppServerToApp, AppToAppServer};
+
+use super::utils::{dummy_app_session, dummy_self_test_stages, spawn_dummy_app_server};
+
+async fn task_app_server_loop_self_test<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let (
+        _funder_sender,
+        _funder_receiver,
+        _index_client_sender,
+        _index_client_receiver,
+        mut connections_sender,
+        _app_revocations_sender,
+        _initial_node_report,
+    ) = spawn_dummy_app_server(spawner.clone());
+
+    // Connect two apps without any permissions:
+    let (mut app_sender0, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver0) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    let app_permissions = AppPermissions {
+        routes: false,
+        send_funds: false,
+        config: false,
+    };
+    await!(connections_sender.send((
+        dummy_app_session(0),
+        app_permissions.clone(),
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    let (_app_sender1, app_server_receiver) = mpsc::channel(0);
+    let (app_server_sender, mut app_receiver1) = mpsc::channel(0);
+    let app_server_conn_pair = (app_server_sender, app_server_receiver);
+    await!(connections_sender.send((
+        dummy_app_session(1),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
+
+    // The apps should receive the current node report as the first message:
+    let _to_app_message = await!(app_receiver0.next()).unwrap();
+    let _to_app_message = await!(app_receiver1.next()).unwrap();
+
+    // A self test is allowed without any permissions:
+    let to_app_server = AppToAppServer::new(
+        Uid::from(&[6; UID_LEN]),
+        AppRequest::RequestSelfTest(Uid::from(&[5; UID_LEN])),
+    );
+    await!(app_sender0.send(to_app_server)).unwrap();
+
+    match await!(app_receiver0.next()).unwrap() {
+        AppServerToApp::ResponseSelfTest(response_self_test) => {
+            assert_eq!(response_self_test.request_id, Uid::from(&[5; UID_LEN]));
+            assert_eq!(response_self_test.stages, dummy_self_test_stages());
+        }
+        _ => unreachable!(),
+    };
+
+    // Only the requesting app receives the results:
+    assert!(app_receiver1.try_next().is_err());
+}
+
+#[test]
+fn test_app_server_loop_self_test() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_app_server_loop_self_test(thread_pool.clone()));
+}
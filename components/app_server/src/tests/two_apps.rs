@@ -11,7 +11,7 @@ use proto::index_client::messages::{
 };
 use proto::index_server::messages::NamedIndexServerAddress;
 
-use super::utils::spawn_dummy_app_server;
+use super::utils::{dummy_app_session, spawn_dummy_app_server};
 
 async fn task_app_server_loop_two_apps<S>(spawner: S)
 where
@@ -23,6 +23,7 @@ where
         mut index_client_sender,
         _index_client_receiver,
         mut connections_sender,
+        _app_revocations_sender,
         initial_node_report,
     ) = spawn_dummy_app_server(spawner.clone());
 
@@ -34,7 +35,12 @@ where
         send_funds: true,
         config: true,
     };
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_session(0),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     let (_app_sender1, app_server_receiver) = mpsc::channel(0);
     let (app_server_sender, mut app_receiver1) = mpsc::channel(0);
@@ -44,7 +50,12 @@ where
         send_funds: true,
         config: true,
     };
-    await!(connections_sender.send((app_permissions, app_server_conn_pair))).unwrap();
+    await!(connections_sender.send((
+        dummy_app_session(1),
+        app_permissions,
+        app_server_conn_pair
+    )))
+    .unwrap();
 
     // The apps should receive the current node report as the first message:
     // Send a report
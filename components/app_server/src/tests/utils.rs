@@ -1,12 +1,19 @@
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
-use futures::{FutureExt, TryFutureExt};
+use futures::{future, FutureExt, TryFutureExt};
 
 use im::hashmap::HashMap as ImHashMap;
 
+use common::conn::FuncFutTransform;
+
 use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use identity::test_utils::spawn_fixture_identity;
 
-use proto::app_server::messages::{NamedRelayAddress, NodeReport};
+use proto::app_server::messages::{
+    NamedRelayAddress, NodeReport, SelfTestStage, SelfTestStageReport,
+};
 use proto::funder::messages::{FunderIncomingControl, FunderOutgoingControl};
 use proto::index_client::messages::{
     AppServerToIndexClient, IndexClientReport, IndexClientToAppServer,
@@ -15,6 +22,10 @@ use proto::index_server::messages::NamedIndexServerAddress;
 use proto::report::messages::FunderReport;
 
 use crate::server::{app_server_loop, IncomingAppConnection};
+use crate::session::AppSession;
+
+/// Seed of the identity used by the node of the dummy app server.
+pub const NODE_IDENTITY_SEED: u8 = 0;
 
 /// A helper function to quickly create a dummy NamedRelayAddress.
 pub fn dummy_named_relay_address(index: u8) -> NamedRelayAddress<u32> {
@@ -25,6 +36,31 @@ pub fn dummy_named_relay_address(index: u8) -> NamedRelayAddress<u32> {
     }
 }
 
+/// A helper function to quickly create a dummy AppSession.
+/// Every index represents a different app.
+pub fn dummy_app_session(index: u8) -> AppSession {
+    AppSession {
+        app_public_key: PublicKey::from(&[index; PUBLIC_KEY_LEN]),
+        session_id: Uid::from(&[index; UID_LEN]),
+    }
+}
+
+/// The stage reports returned by every self test of the dummy app server.
+pub fn dummy_self_test_stages() -> Vec<SelfTestStageReport> {
+    vec![
+        SelfTestStageReport {
+            stage: SelfTestStage::Identity,
+            success: true,
+            ticks: 0,
+        },
+        SelfTestStageReport {
+            stage: SelfTestStage::Channel,
+            success: false,
+            ticks: 7,
+        },
+    ]
+}
+
 /*
 /// A helper function to quickly create a dummy RelayAddress.
 pub fn dummy_relay_address(index: u8) -> RelayAddress<u32> {
@@ -43,6 +79,7 @@ pub fn spawn_dummy_app_server<S>(
     mpsc::Sender<IndexClientToAppServer<u32>>,
     mpsc::Receiver<AppServerToIndexClient<u32>>,
     mpsc::Sender<IncomingAppConnection<u32>>,
+    mpsc::Sender<PublicKey>,
     NodeReport<u32>,
 )
 where
@@ -55,6 +92,9 @@ where
     let (to_index_client, index_client_receiver) = mpsc::channel(0);
 
     let (connections_sender, incoming_connections) = mpsc::channel(0);
+    let (app_revocations_sender, app_revocations) = mpsc::channel(0);
+
+    let (identity_client, _) = spawn_fixture_identity(NODE_IDENTITY_SEED, &mut spawner);
 
     // Create a dummy initial_node_report:
     let funder_report = FunderReport {
@@ -64,6 +104,12 @@ where
             .collect(),
         friends: ImHashMap::new(),
         num_ready_receipts: 0,
+        quarantined_friends: Default::default(),
+        dust_thresholds: Default::default(),
+        directory: Default::default(),
+        reliability: Default::default(),
+        read_only: false,
+        labeled_payments: Default::default(),
     };
 
     let server100 = NamedIndexServerAddress {
@@ -81,6 +127,7 @@ where
     let index_client_report = IndexClientReport {
         index_servers: vec![server100, server101],
         opt_connected_server: Some(PublicKey::from(&[0xaa; PUBLIC_KEY_LEN])),
+        num_corrections: 0,
     };
 
     let initial_node_report = NodeReport {
@@ -88,13 +135,20 @@ where
         index_client_report,
     };
 
+    let self_tester = FuncFutTransform::new(|()| Box::pin(future::ready(dummy_self_test_stages())));
+
     let fut_loop = app_server_loop(
         from_funder,
         to_funder,
         from_index_client,
         to_index_client,
         incoming_connections,
+        app_revocations,
         initial_node_report.clone(),
+        None,
+        Vec::new(),
+        identity_client,
+        self_tester,
         spawner.clone(),
     )
     .map_err(|e| error!("app_server_loop() error: {:?}", e))
@@ -108,6 +162,7 @@ where
         index_client_sender,
         index_client_receiver,
         connections_sender,
+        app_revocations_sender,
         initial_node_report,
     )
 }
@@ -12,7 +12,10 @@
     clippy::new_without_default
 )]
 
+pub mod passphrase;
+#[cfg(feature = "index-server")]
 pub mod stindexlib;
 pub mod stmgrlib;
 pub mod stnodelib;
+#[cfg(feature = "relay-server")]
 pub mod strelaylib;
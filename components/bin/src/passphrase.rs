@@ -0,0 +1,65 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use zeroize::Zeroize;
+
+use crypto::identity::Identity;
+use proto::file::identity::{load_identity_from_file_with_passphrase, IdentityFileError};
+
+#[derive(Debug)]
+pub enum PassphraseError {
+    ReadError(io::Error),
+    /// The passphrase typed the second time is different
+    Mismatch,
+    Empty,
+}
+
+/// Read a passphrase from the first line of `opt_passphrase_file`, or from the terminal if no
+/// file is given. A passphrase may be passed through a file descriptor by giving its path (For
+/// example: `/dev/fd/3`).
+pub fn read_passphrase(
+    opt_passphrase_file: Option<&Path>,
+    prompt: &str,
+) -> Result<String, PassphraseError> {
+    match opt_passphrase_file {
+        Some(passphrase_file) => {
+            let mut data =
+                fs::read_to_string(passphrase_file).map_err(PassphraseError::ReadError)?;
+            let passphrase = data.lines().next().unwrap_or("").to_owned();
+            data.zeroize();
+            Ok(passphrase)
+        }
+        None => rpassword::prompt_password_stderr(prompt).map_err(PassphraseError::ReadError),
+    }
+}
+
+/// Read a passphrase for a new encrypted file.
+/// A passphrase typed in the terminal is asked for twice.
+pub fn read_new_passphrase(opt_passphrase_file: Option<&Path>) -> Result<String, PassphraseError> {
+    let mut passphrase = read_passphrase(opt_passphrase_file, "New passphrase: ")?;
+    if opt_passphrase_file.is_none() {
+        let mut passphrase2 = read_passphrase(None, "Repeat passphrase: ")?;
+        let is_match = passphrase == passphrase2;
+        passphrase2.zeroize();
+        if !is_match {
+            passphrase.zeroize();
+            return Err(PassphraseError::Mismatch);
+        }
+    }
+    if passphrase.is_empty() {
+        return Err(PassphraseError::Empty);
+    }
+    Ok(passphrase)
+}
+
+/// Load an identity file that may be encrypted.
+/// A passphrase is read (See `read_passphrase()`) only if the file is encrypted.
+pub fn load_identity_with_passphrase(
+    idfile: &Path,
+    opt_passphrase_file: Option<&Path>,
+) -> Result<impl Identity, IdentityFileError> {
+    load_identity_from_file_with_passphrase(idfile, || {
+        read_passphrase(opt_passphrase_file, "Identity file passphrase: ").ok()
+    })
+}
@@ -17,13 +17,16 @@ use crypto::crypto_rand::system_random;
 use identity::{create_identity, IdentityClient};
 
 use index_server::{net_index_server, NetIndexServerError};
-use proto::consts::{MAX_FRAME_LENGTH, TICK_MS};
+use proto::consts::{CONNECT_STAGGER_TICKS, MAX_FRAME_LENGTH, TICK_MS};
 use timer::create_timer;
 
 use net::{NetConnector, TcpListener};
 
-use proto::file::identity::load_identity_from_file;
+use proto::file::identity::IdentityFileError;
 use proto::file::index_server::{load_trusted_servers, IndexServerDirectoryError};
+use proto::file::relay::{load_relay_from_file, RelayFileError};
+
+use crate::passphrase::load_identity_with_passphrase;
 
 // TODO; Maybe take as a command line argument in the future?
 /// Maximum amount of concurrent encrypted channel set-ups.
@@ -31,6 +34,9 @@ use proto::file::index_server::{load_trusted_servers, IndexServerDirectoryError}
 pub const MAX_CONCURRENT_ENCRYPT: usize = 0x200;
 /// Amount of ticks we wait before attempting to reconnect to a remote index server.
 pub const BACKOFF_TICKS: usize = 0x8;
+/// The amount of ticks we are willing to wait until a connection from a remote index server is
+/// established through a relay.
+pub const CONN_TIMEOUT_TICKS: usize = 0x8;
 
 /// stindex: Offst Index Server
 /// A server used to index the Offst network. Collects topology information from nodes, and serves
@@ -50,6 +56,14 @@ pub struct StIndexCmd {
     /// Directory path of trusted index servers
     #[structopt(parse(from_os_str), short = "t", long = "trusted")]
     pub trusted: PathBuf,
+    /// Relay file path. Trusted index servers may also connect through this relay.
+    /// May be specified multiple times.
+    #[structopt(parse(from_os_str), short = "r", long = "relay")]
+    pub relays: Vec<PathBuf>,
+    /// Passphrase file path, used if the identity file is encrypted (Example: /dev/fd/3).
+    /// If not specified, the passphrase is asked for in the terminal.
+    #[structopt(parse(from_os_str), long = "passphrase-file")]
+    pub opt_passphrase_file: Option<PathBuf>,
 }
 
 #[allow(clippy::enum_variant_names)]
@@ -58,9 +72,10 @@ pub enum IndexServerBinError {
     CreateThreadPoolError,
     CreateTimerError,
     NetIndexServerError(NetIndexServerError),
-    LoadIdentityError,
+    LoadIdentityError(IdentityFileError),
     CreateIdentityError,
     LoadTrustedServersError(IndexServerDirectoryError),
+    LoadRelayError(RelayFileError),
 }
 
 pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
@@ -69,10 +84,13 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
         lclient,
         lserver,
         trusted,
+        relays,
+        opt_passphrase_file,
     } = st_index_cmd;
 
-    let identity = load_identity_from_file(Path::new(&idfile))
-        .map_err(|_| IndexServerBinError::LoadIdentityError)?;
+    let identity =
+        load_identity_with_passphrase(&idfile, opt_passphrase_file.as_ref().map(PathBuf::as_path))
+            .map_err(IndexServerBinError::LoadIdentityError)?;
 
     let trusted_servers = load_trusted_servers(Path::new(&trusted))
         .map_err(IndexServerBinError::LoadTrustedServersError)?
@@ -85,6 +103,12 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
         })
         .collect::<HashMap<_, _>>();
 
+    let listen_relays = relays
+        .iter()
+        .map(|relay_path| load_relay_from_file(relay_path))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(IndexServerBinError::LoadRelayError)?;
+
     // Create a ThreadPool:
     let mut thread_pool =
         ThreadPool::new().map_err(|_| IndexServerBinError::CreateThreadPoolError)?;
@@ -118,8 +142,13 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
     let (_config_sender, incoming_server_raw_conns) = server_tcp_listener.listen(lserver);
 
     // A tcp connector, Used to connect to remote servers:
-    let raw_server_net_connector =
-        NetConnector::new(MAX_FRAME_LENGTH, resolve_thread_pool, thread_pool.clone());
+    let raw_server_net_connector = NetConnector::new(
+        MAX_FRAME_LENGTH,
+        timer_client.clone(),
+        CONNECT_STAGGER_TICKS,
+        resolve_thread_pool,
+        thread_pool.clone(),
+    );
 
     let rng = system_random();
 
@@ -131,8 +160,10 @@ pub fn stindex(st_index_cmd: StIndexCmd) -> Result<(), IndexServerBinError> {
         timer_client,
         rng,
         trusted_servers,
+        listen_relays,
         MAX_CONCURRENT_ENCRYPT,
         BACKOFF_TICKS,
+        CONN_TIMEOUT_TICKS,
         graph_service_thread_pool,
         thread_pool.clone(),
     );
@@ -1,24 +1,40 @@
 use std::convert::TryInto;
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use structopt::StructOpt;
+use zeroize::Zeroize;
 
 use crypto::crypto_rand::system_random;
-use crypto::identity::{generate_pkcs8_key_pair, Identity};
+use crypto::identity::{generate_pkcs8_key_pair, Identity, Signature, SIGNATURE_LEN};
 
+use proto::app_server::debug_bundle::DEBUG_BUNDLE_MAGIC;
 use proto::app_server::messages::{AppPermissions, RelayAddress};
 use proto::index_server::messages::IndexServerAddress;
+use proto::invite::messages::FriendInvite;
+use proto::invite::serialize::friend_invite_to_string;
+use proto::invite::signature_buff::create_friend_invite_signature_buffer;
 use proto::net::messages::{NetAddress, NetAddressError};
 use proto::node::types::NodeAddress;
+use proto::signed_export::{sign_export, verify_export, SignedExportError};
 
 use database::file_db::FileDb;
+use database::AtomicDb;
+use funder::debug_json::{create_state_export, funder_state_to_json, STATE_EXPORT_MAGIC};
 use node::NodeState;
 
 use proto::file::app::{store_trusted_app_to_file, TrustedApp};
-use proto::file::identity::{load_identity_from_file, store_raw_identity_to_file};
+use proto::file::identity::{
+    encrypt_identity_file, store_raw_identity_to_encrypted_file, store_raw_identity_to_file,
+    IdentityFileError, IDENTITY_KDF_ITERATIONS,
+};
 use proto::file::index_server::store_index_server_to_file;
 use proto::file::node::store_node_to_file;
-use proto::file::relay::store_relay_to_file;
+use proto::file::relay::{load_relay_from_file, store_relay_to_file};
+use proto::file::ser_string::{public_key_to_string, string_to_public_key};
+
+use crate::passphrase::{load_identity_with_passphrase, read_new_passphrase, PassphraseError};
 
 #[derive(Debug)]
 pub enum InitNodeDbError {
@@ -42,6 +58,27 @@ pub struct GenIdentCmd {
     /// Identity file output file path
     #[structopt(parse(from_os_str), short = "o", long = "output")]
     pub output: PathBuf,
+    /// Encrypt the identity file using a passphrase
+    #[structopt(long = "encrypt")]
+    pub encrypt: bool,
+    /// Passphrase file path, used with --encrypt (Example: /dev/fd/3).
+    /// If not specified, the passphrase is asked for in the terminal.
+    #[structopt(parse(from_os_str), long = "passphrase-file")]
+    pub opt_passphrase_file: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct EncryptIdentCmd {
+    /// Plain identity file path
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub idfile: PathBuf,
+    /// Encrypted identity file output file path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output: PathBuf,
+    /// Passphrase file path (Example: /dev/fd/3).
+    /// If not specified, the passphrase is asked for in the terminal.
+    #[structopt(parse(from_os_str), long = "passphrase-file")]
+    pub opt_passphrase_file: Option<PathBuf>,
 }
 
 #[derive(Debug, StructOpt)]
@@ -102,6 +139,74 @@ pub struct NodeTicketCmd {
     pub address: String,
 }
 
+#[derive(Debug, StructOpt)]
+pub struct ExportQuarantinedCmd {
+    /// Database file path
+    #[structopt(parse(from_os_str), short = "d", long = "database")]
+    pub database: PathBuf,
+    /// Public key of the quarantined friend
+    #[structopt(short = "f", long = "friend")]
+    pub friend_public_key: String,
+    /// Output file path for the original stored state of the friend
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct DumpStateCmd {
+    /// Database file path
+    #[structopt(parse(from_os_str), short = "d", long = "database")]
+    pub database: PathBuf,
+    /// Include tokens (Signatures that prove a balance, allow to reset a channel or prove a
+    /// payment). Tokens are redacted by default.
+    #[structopt(long = "full")]
+    pub full: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ExportStateCmd {
+    /// Node identity file path. Used to sign the export.
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub idfile: PathBuf,
+    /// Database file path
+    #[structopt(parse(from_os_str), short = "d", long = "database")]
+    pub database: PathBuf,
+    /// Include tokens (Signatures that prove a balance, allow to reset a channel or prove a
+    /// payment). Tokens are redacted by default.
+    #[structopt(long = "full")]
+    pub full: bool,
+    /// Output file path for the signed export
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct VerifyExportCmd {
+    /// A state export or a debug bundle
+    #[structopt(parse(from_os_str), short = "i", long = "input")]
+    pub input: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct FriendInviteCmd {
+    /// Node identity file path
+    #[structopt(parse(from_os_str), short = "i", long = "idfile")]
+    pub idfile: PathBuf,
+    /// Relay ticket file path of a relay the node can be reached through.
+    /// May be specified multiple times.
+    #[structopt(parse(from_os_str), short = "r", long = "relay")]
+    pub relays: Vec<PathBuf>,
+    /// Suggested max debt: The amount of credits the invited side may let us owe
+    #[structopt(short = "m", long = "max-debt")]
+    pub opt_suggested_max_debt: Option<u128>,
+    /// Amount of seconds until the invite expires
+    #[structopt(short = "l", long = "lifetime", default_value = "604800")]
+    pub lifetime: u64,
+    /// Friend invite output file path
+    #[structopt(parse(from_os_str), short = "o", long = "output")]
+    pub output: PathBuf,
+}
+
 /// stmgr: offST ManaGeR
 /// A util for managing Offst entities and files
 #[derive(Debug, StructOpt)]
@@ -113,6 +218,9 @@ pub enum StMgrCmd {
     /// Randomly generate a new identity file
     #[structopt(name = "gen-ident")]
     GenIdent(GenIdentCmd),
+    /// Encrypt an existing identity file using a passphrase. The identity is not changed.
+    #[structopt(name = "encrypt-ident")]
+    EncryptIdent(EncryptIdentCmd),
     /// Create an application ticket
     #[structopt(name = "app-ticket")]
     AppTicket(AppTicketCmd),
@@ -125,6 +233,22 @@ pub enum StMgrCmd {
     /// Create a node server ticket
     #[structopt(name = "node-ticket")]
     NodeTicket(NodeTicketCmd),
+    /// Export the original stored state of a quarantined friend
+    #[structopt(name = "export-quarantined")]
+    ExportQuarantined(ExportQuarantinedCmd),
+    /// Print the funder state of a node database as JSON, for debugging
+    #[structopt(name = "dump-state")]
+    DumpState(DumpStateCmd),
+    /// Export the funder state of a node database as JSON, signed by the identity of the node
+    #[structopt(name = "export-state")]
+    ExportState(ExportStateCmd),
+    /// Verify that a state export or a debug bundle was not modified, and print the public key
+    /// of the node that signed it
+    #[structopt(name = "verify-export")]
+    VerifyExport(VerifyExportCmd),
+    /// Create a signed friend invite, that can be imported by another node in one step
+    #[structopt(name = "friend-invite")]
+    FriendInvite(FriendInviteCmd),
 }
 
 fn init_node_db(InitNodeDbCmd { idfile, output }: InitNodeDbCmd) -> Result<(), InitNodeDbError> {
@@ -137,8 +261,8 @@ fn init_node_db(InitNodeDbCmd { idfile, output }: InitNodeDbCmd) -> Result<(), I
     }
 
     // Parse identity file:
-    let identity =
-        load_identity_from_file(&idfile).map_err(|_| InitNodeDbError::LoadIdentityError)?;
+    let identity = load_identity_with_passphrase(&idfile, None)
+        .map_err(|_| InitNodeDbError::LoadIdentityError)?;
     let local_public_key = identity.get_public_key();
 
     // Create a new database file:
@@ -151,20 +275,78 @@ fn init_node_db(InitNodeDbCmd { idfile, output }: InitNodeDbCmd) -> Result<(), I
 #[derive(Debug)]
 pub enum GenIdentityError {
     OutputAlreadyExists,
+    PassphraseError(PassphraseError),
     StoreToFileError,
 }
 
 /// Randomly generate an identity file (private-public key pair)
-fn gen_identity(GenIdentCmd { output }: GenIdentCmd) -> Result<(), GenIdentityError> {
+fn gen_identity(
+    GenIdentCmd {
+        output,
+        encrypt,
+        opt_passphrase_file,
+    }: GenIdentCmd,
+) -> Result<(), GenIdentityError> {
+    if output.exists() {
+        return Err(GenIdentityError::OutputAlreadyExists);
+    }
+
+    let opt_passphrase = if encrypt {
+        Some(
+            read_new_passphrase(opt_passphrase_file.as_ref().map(PathBuf::as_path))
+                .map_err(GenIdentityError::PassphraseError)?,
+        )
+    } else {
+        None
+    };
+
     // Generate a new random keypair:
     let rng = system_random();
-    let pkcs8 = generate_pkcs8_key_pair(&rng);
+    let mut pkcs8 = generate_pkcs8_key_pair(&rng);
+
+    let res = match opt_passphrase {
+        Some(mut passphrase) => {
+            let res = store_raw_identity_to_encrypted_file(
+                &pkcs8,
+                &passphrase,
+                IDENTITY_KDF_ITERATIONS,
+                &rng,
+                &output,
+            );
+            passphrase.zeroize();
+            res
+        }
+        None => store_raw_identity_to_file(&pkcs8, &output),
+    };
+    pkcs8[..].zeroize();
+    res.map_err(|_| GenIdentityError::StoreToFileError)
+}
 
+#[derive(Debug)]
+pub enum EncryptIdentityError {
+    OutputAlreadyExists,
+    PassphraseError(PassphraseError),
+    EncryptError(IdentityFileError),
+}
+
+/// Encrypt an existing identity file using a passphrase
+fn encrypt_identity(
+    EncryptIdentCmd {
+        idfile,
+        output,
+        opt_passphrase_file,
+    }: EncryptIdentCmd,
+) -> Result<(), EncryptIdentityError> {
     if output.exists() {
-        return Err(GenIdentityError::OutputAlreadyExists);
+        return Err(EncryptIdentityError::OutputAlreadyExists);
     }
 
-    store_raw_identity_to_file(&pkcs8, &output).map_err(|_| GenIdentityError::StoreToFileError)
+    let mut passphrase = read_new_passphrase(opt_passphrase_file.as_ref().map(PathBuf::as_path))
+        .map_err(EncryptIdentityError::PassphraseError)?;
+    let rng = system_random();
+    let res = encrypt_identity_file(&idfile, &output, &passphrase, IDENTITY_KDF_ITERATIONS, &rng);
+    passphrase.zeroize();
+    res.map_err(EncryptIdentityError::EncryptError)
 }
 
 #[derive(Debug)]
@@ -191,7 +373,7 @@ fn app_ticket(
     }: AppTicketCmd,
 ) -> Result<(), AppTicketError> {
     // Obtain app's public key:
-    let identity = load_identity_from_file(Path::new(&idfile))
+    let identity = load_identity_with_passphrase(Path::new(&idfile), None)
         .map_err(|_| AppTicketError::LoadIdentityError)?;
     let public_key = identity.get_public_key();
 
@@ -244,8 +426,8 @@ fn relay_ticket(
     }
 
     // Parse identity file:
-    let identity =
-        load_identity_from_file(&idfile).map_err(|_| RelayTicketError::LoadIdentityError)?;
+    let identity = load_identity_with_passphrase(&idfile, None)
+        .map_err(|_| RelayTicketError::LoadIdentityError)?;
     let public_key = identity.get_public_key();
 
     let relay_address = RelayAddress {
@@ -285,8 +467,8 @@ fn index_ticket(
     }
 
     // Parse identity file:
-    let identity =
-        load_identity_from_file(&idfile).map_err(|_| IndexTicketError::LoadIdentityError)?;
+    let identity = load_identity_with_passphrase(&idfile, None)
+        .map_err(|_| IndexTicketError::LoadIdentityError)?;
     let public_key = identity.get_public_key();
 
     let index_address = IndexServerAddress {
@@ -327,8 +509,8 @@ fn node_ticket(
     }
 
     // Parse identity file:
-    let identity =
-        load_identity_from_file(&idfile).map_err(|_| NodeTicketError::LoadIdentityError)?;
+    let identity = load_identity_with_passphrase(&idfile, None)
+        .map_err(|_| NodeTicketError::LoadIdentityError)?;
     let public_key = identity.get_public_key();
 
     let node_address = NodeAddress {
@@ -339,15 +521,203 @@ fn node_ticket(
     store_node_to_file(&node_address, &output).map_err(|_| NodeTicketError::StoreNodeFileError)
 }
 
+#[derive(Debug)]
+pub enum ExportQuarantinedError {
+    OutputAlreadyExists,
+    LoadDbError,
+    InvalidPublicKey,
+    FriendNotQuarantined,
+    WriteOutputError,
+}
+
+/// Export the original stored bytes of a quarantined friend, for manual recovery
+fn export_quarantined(
+    ExportQuarantinedCmd {
+        database,
+        friend_public_key,
+        output,
+    }: ExportQuarantinedCmd,
+) -> Result<(), ExportQuarantinedError> {
+    // Make sure that output does not exist.
+    if output.exists() {
+        return Err(ExportQuarantinedError::OutputAlreadyExists);
+    }
+
+    let friend_public_key = string_to_public_key(&friend_public_key)
+        .map_err(|_| ExportQuarantinedError::InvalidPublicKey)?;
+
+    let atomic_db = FileDb::<NodeState<NetAddress>>::load(database)
+        .map_err(|_| ExportQuarantinedError::LoadDbError)?;
+
+    let quarantined_friend = atomic_db
+        .get_state()
+        .funder_state
+        .quarantined_friends
+        .get(&friend_public_key)
+        .ok_or(ExportQuarantinedError::FriendNotQuarantined)?;
+
+    fs::write(&output, &quarantined_friend.data)
+        .map_err(|_| ExportQuarantinedError::WriteOutputError)
+}
+
+#[derive(Debug)]
+pub enum DumpStateError {
+    LoadDbError,
+    RenderError,
+}
+
+/// Print a human readable rendering of the funder state stored in a node database.
+/// The rendering can not be loaded back into a database.
+fn dump_state(DumpStateCmd { database, full }: DumpStateCmd) -> Result<(), DumpStateError> {
+    let atomic_db =
+        FileDb::<NodeState<NetAddress>>::load(database).map_err(|_| DumpStateError::LoadDbError)?;
+
+    let json = funder_state_to_json(&atomic_db.get_state().funder_state, full)
+        .map_err(|_| DumpStateError::RenderError)?;
+    println!("{}", json);
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum ExportStateError {
+    OutputAlreadyExists,
+    LoadIdentityError,
+    LoadDbError,
+    /// The identity is not the identity of the node that owns the database
+    IdentityMismatch,
+    RenderError,
+    WriteOutputError,
+}
+
+/// Export the funder state stored in a node database, signed by the identity of the node.
+/// The export can be verified using `verify-export`.
+fn export_state(
+    ExportStateCmd {
+        idfile,
+        database,
+        full,
+        output,
+    }: ExportStateCmd,
+) -> Result<(), ExportStateError> {
+    // Make sure that output does not exist.
+    if output.exists() {
+        return Err(ExportStateError::OutputAlreadyExists);
+    }
+
+    let identity = load_identity_with_passphrase(&idfile, None)
+        .map_err(|_| ExportStateError::LoadIdentityError)?;
+
+    let atomic_db = FileDb::<NodeState<NetAddress>>::load(database)
+        .map_err(|_| ExportStateError::LoadDbError)?;
+    let funder_state = &atomic_db.get_state().funder_state;
+    if funder_state.local_public_key != identity.get_public_key() {
+        return Err(ExportStateError::IdentityMismatch);
+    }
+
+    let body =
+        create_state_export(funder_state, full).map_err(|_| ExportStateError::RenderError)?;
+    fs::write(&output, sign_export(body, &identity)).map_err(|_| ExportStateError::WriteOutputError)
+}
+
+#[derive(Debug)]
+pub enum VerifyExportError {
+    ReadInputError,
+    InvalidExport(SignedExportError),
+}
+
+/// Verify the signature of a state export or a debug bundle.
+fn verify_export_file(VerifyExportCmd { input }: VerifyExportCmd) -> Result<(), VerifyExportError> {
+    let data = fs::read(&input).map_err(|_| VerifyExportError::ReadInputError)?;
+    let verified_export = verify_export(&data).map_err(VerifyExportError::InvalidExport)?;
+
+    let kind = if verified_export.magic == *STATE_EXPORT_MAGIC {
+        "state export"
+    } else if verified_export.magic == *DEBUG_BUNDLE_MAGIC {
+        "debug bundle"
+    } else {
+        "unknown"
+    };
+    println!("Kind: {} (version {})", kind, verified_export.version);
+    println!(
+        "Signed by: {}",
+        public_key_to_string(&verified_export.public_key)
+    );
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum FriendInviteError {
+    OutputAlreadyExists,
+    LoadIdentityError,
+    LoadRelayError,
+    /// An invite with no relays can not be used to reach the node
+    NoRelays,
+    SystemTimeError,
+    WriteOutputError,
+}
+
+/// Create a friend invite: The public key and relays of the node, an optional suggested max
+/// debt and an expiry time, signed by the identity of the node.
+fn friend_invite(
+    FriendInviteCmd {
+        idfile,
+        relays,
+        opt_suggested_max_debt,
+        lifetime,
+        output,
+    }: FriendInviteCmd,
+) -> Result<(), FriendInviteError> {
+    // Make sure that output does not exist.
+    if output.exists() {
+        return Err(FriendInviteError::OutputAlreadyExists);
+    }
+
+    if relays.is_empty() {
+        return Err(FriendInviteError::NoRelays);
+    }
+
+    let identity = load_identity_with_passphrase(&idfile, None)
+        .map_err(|_| FriendInviteError::LoadIdentityError)?;
+
+    let mut relay_addresses = Vec::new();
+    for relay_file in &relays {
+        relay_addresses
+            .push(load_relay_from_file(relay_file).map_err(|_| FriendInviteError::LoadRelayError)?);
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| FriendInviteError::SystemTimeError)?
+        .as_secs();
+
+    let mut invite = FriendInvite {
+        public_key: identity.get_public_key(),
+        relays: relay_addresses,
+        opt_suggested_max_debt,
+        expiry_time: now.saturating_add(lifetime),
+        signature: Signature::from(&[0; SIGNATURE_LEN]),
+    };
+    invite.signature = identity.sign(&create_friend_invite_signature_buffer(&invite));
+
+    fs::write(&output, friend_invite_to_string(&invite))
+        .map_err(|_| FriendInviteError::WriteOutputError)
+}
+
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
 pub enum StmError {
     InitNodeDbError(InitNodeDbError),
     GenIdentityError(GenIdentityError),
+    EncryptIdentityError(EncryptIdentityError),
     AppTicketError(AppTicketError),
     RelayTicketError(RelayTicketError),
     IndexTicketError(IndexTicketError),
     NodeTicketError(NodeTicketError),
+    ExportQuarantinedError(ExportQuarantinedError),
+    DumpStateError(DumpStateError),
+    ExportStateError(ExportStateError),
+    VerifyExportError(VerifyExportError),
+    FriendInviteError(FriendInviteError),
 }
 
 impl From<InitNodeDbError> for StmError {
@@ -362,6 +732,12 @@ impl From<GenIdentityError> for StmError {
     }
 }
 
+impl From<EncryptIdentityError> for StmError {
+    fn from(e: EncryptIdentityError) -> Self {
+        StmError::EncryptIdentityError(e)
+    }
+}
+
 impl From<AppTicketError> for StmError {
     fn from(e: AppTicketError) -> Self {
         StmError::AppTicketError(e)
@@ -386,14 +762,50 @@ impl From<NodeTicketError> for StmError {
     }
 }
 
+impl From<ExportQuarantinedError> for StmError {
+    fn from(e: ExportQuarantinedError) -> Self {
+        StmError::ExportQuarantinedError(e)
+    }
+}
+
+impl From<DumpStateError> for StmError {
+    fn from(e: DumpStateError) -> Self {
+        StmError::DumpStateError(e)
+    }
+}
+
+impl From<ExportStateError> for StmError {
+    fn from(e: ExportStateError) -> Self {
+        StmError::ExportStateError(e)
+    }
+}
+
+impl From<VerifyExportError> for StmError {
+    fn from(e: VerifyExportError) -> Self {
+        StmError::VerifyExportError(e)
+    }
+}
+
+impl From<FriendInviteError> for StmError {
+    fn from(e: FriendInviteError) -> Self {
+        StmError::FriendInviteError(e)
+    }
+}
+
 pub fn stmgr(st_mgr_cmd: StMgrCmd) -> Result<(), StmError> {
     match st_mgr_cmd {
         StMgrCmd::InitNodeDb(i) => init_node_db(i)?,
         StMgrCmd::GenIdent(i) => gen_identity(i)?,
+        StMgrCmd::EncryptIdent(i) => encrypt_identity(i)?,
         StMgrCmd::AppTicket(i) => app_ticket(i)?,
         StMgrCmd::RelayTicket(i) => relay_ticket(i)?,
         StMgrCmd::IndexTicket(i) => index_ticket(i)?,
         StMgrCmd::NodeTicket(i) => node_ticket(i)?,
+        StMgrCmd::ExportQuarantined(i) => export_quarantined(i)?,
+        StMgrCmd::DumpState(i) => dump_state(i)?,
+        StMgrCmd::ExportState(i) => export_state(i)?,
+        StMgrCmd::VerifyExport(i) => verify_export_file(i)?,
+        StMgrCmd::FriendInvite(i) => friend_invite(i)?,
     }
 
     Ok(())
@@ -5,26 +5,39 @@ use std::path::PathBuf;
 use std::time::Duration;
 
 use futures::executor::ThreadPool;
+use futures::stream;
 use futures::task::SpawnExt;
 
 use structopt::StructOpt;
 
-use common::conn::Listener;
+use common::bounded_cache::CacheLimits;
+use common::conn::{ConnPairVec, Listener};
 use common::int_convert::usize_to_u64;
+use common::select_streams::BoxStream;
 
 use crypto::crypto_rand::system_random;
 
 use identity::{create_identity, IdentityClient};
 use timer::create_timer;
 
+use funder::SlowEventLimits;
+
 use node::{net_node, NetNodeError, NodeConfig, NodeState};
 
 use database::file_db::FileDb;
+use database::AtomicDb;
 
 use net::{NetConnector, TcpListener};
 use proto::consts::{
-    KEEPALIVE_TICKS, MAX_FRAME_LENGTH, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, TICKS_TO_REKEY,
-    TICK_MS,
+    COMPLETED_CACHE_MAX_AGE_TICKS, COMPLETED_CACHE_MAX_ENTRIES, CONNECT_STAGGER_TICKS,
+    DATABASE_COMPACT_TICKS, DEADLINES_GRANULARITY_TICKS, FRIEND_PREWARM_TICKS,
+    FRIEND_RELAYS_DAMPING_TICKS, INDEX_RECONCILE_TICKS, INDEX_ROUTE_CACHE_MAX_AGE_TICKS,
+    INDEX_ROUTE_CACHE_MAX_ENTRIES, KEEPALIVE_TICKS, MAX_FRAME_LENGTH, MAX_MESSAGES_BEFORE_REKEY,
+    MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, MIN_OPERATIONS_IN_BATCH,
+    PAYMENT_TIMINGS_CACHE_MAX_AGE_TICKS, PAYMENT_TIMINGS_CACHE_MAX_ENTRIES,
+    RELIABILITY_DECAY_TICKS, SELF_TEST_STAGE_TICKS, SESSION_RESUME_TICKS,
+    SLOW_FUNDER_EVENT_LOG_INTERVAL_MS, SLOW_FUNDER_EVENT_MS, TICKS_TO_REKEY, TICK_MS,
+    TRUSTED_APPS_RELOAD_TICKS,
 };
 use proto::net::messages::NetAddress;
 
@@ -48,6 +61,10 @@ const CONN_TIMEOUT_TICKS: usize = 0x8;
 /// Maximum amount of concurrent applications
 /// going through the incoming connection transform at the same time
 const MAX_CONCURRENT_INCOMING_APPS: usize = 0x8;
+/// Amount of incoming messages from a friend that may wait for processing
+const FRIEND_INCOMING_QUEUE_LEN: usize = 0x4;
+/// Amount of ticks between two fetches of the directory document
+const DIRECTORY_FETCH_TICKS: usize = 0x1000;
 
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
@@ -56,6 +73,7 @@ pub enum NodeBinError {
     CreateThreadPoolError,
     CreateTimerError,
     LoadDbError,
+    StoreDbError,
     SpawnError,
     NetNodeError(NetNodeError),
 }
@@ -74,20 +92,35 @@ pub struct StNodeCmd {
     /// Listening address (Used for communication with apps)
     #[structopt(short = "l", long = "laddr")]
     pub laddr: SocketAddr,
+    /// Listening address for direct connections from friends (Optional).
+    /// To be reachable without a relay, add this address as a relay with the node's own public key
+    #[structopt(long = "direct-laddr")]
+    pub opt_direct_laddr: Option<SocketAddr>,
     /// Database file path
     #[structopt(parse(from_os_str), short = "d", long = "database")]
     pub database: PathBuf,
     /// Directory path of trusted applications
     #[structopt(parse(from_os_str), short = "t", long = "trusted")]
     pub trusted: PathBuf,
+    /// Move the state of friends that are found corrupted in the database into quarantine,
+    /// instead of refusing to start
+    #[structopt(long = "quarantine-corrupt")]
+    pub quarantine_corrupt: bool,
+    /// Connect to friends through the same relay over a single connection to the relay.
+    /// All the relays of the node's friends must support multiplexed connections
+    #[structopt(long = "relay-mux")]
+    pub relay_mux: bool,
 }
 
 pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
     let StNodeCmd {
         idfile,
         laddr,
+        opt_direct_laddr,
         database,
         trusted,
+        quarantine_corrupt,
+        relay_mux,
     } = st_node_cmd;
 
     // Parse identity file:
@@ -125,12 +158,21 @@ pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
         keepalive_ticks: KEEPALIVE_TICKS,
         /// Amount of ticks to wait until the next rekeying (Channel encryption)
         ticks_to_rekey: TICKS_TO_REKEY,
+        /// Amount of messages sent over a relay or friend secure channel before rekeying
+        max_messages_before_rekey: Some(MAX_MESSAGES_BEFORE_REKEY),
+        /// Amount of ticks a closed secure channel with a friend may be resumed, without a full
+        /// exchange
+        session_resume_ticks: SESSION_RESUME_TICKS,
         /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
         /// time.
         max_concurrent_encrypt: MAX_CONCURRENT_ENCRYPT,
         /// The amount of ticks we are willing to wait until a connection is established (Through
         /// the relay)
         conn_timeout_ticks: CONN_TIMEOUT_TICKS,
+        /// Connect to friends through the same relay over a single connection to the relay
+        relay_mux,
+        /// Minimum amount of operations in one move token message
+        min_operations_in_batch: MIN_OPERATIONS_IN_BATCH,
         /// Maximum amount of operations in one move token message
         max_operations_in_batch: MAX_OPERATIONS_IN_BATCH,
         /// The size we allocate for the user send funds requests queue.
@@ -139,25 +181,97 @@ pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
         max_open_index_client_requests: MAX_OPEN_INDEX_CLIENT_REQUESTS,
         /// Maximum amount of relays a node may use.
         max_node_relays: MAX_NODE_RELAYS,
+        /// Minimum amount of ticks between two applied changes of a friend's relays
+        friend_relays_damping_ticks: FRIEND_RELAYS_DAMPING_TICKS,
+        /// Minimum amount of ticks between two token requests of pre-warms
+        friend_prewarm_ticks: FRIEND_PREWARM_TICKS,
         /// Maximum amount of incoming app connections we set up at the same time
         max_concurrent_incoming_apps: MAX_CONCURRENT_INCOMING_APPS,
+        /// Amount of ticks between two periodic compactions of the database
+        database_compact_ticks: DATABASE_COMPACT_TICKS,
+        /// Maximum amount of ticks a single stage of a self test may take
+        self_test_stage_ticks: SELF_TEST_STAGE_TICKS,
+        /// Amount of incoming messages from a friend that may wait for processing
+        friend_incoming_queue_len: FRIEND_INCOMING_QUEUE_LEN,
+        /// Amount of ticks between two fetches of the directory document
+        directory_fetch_ticks: DIRECTORY_FETCH_TICKS,
+        /// Amount of ticks after which recorded payment outcomes of remote nodes lose half of
+        /// their weight
+        reliability_decay_ticks: RELIABILITY_DECAY_TICKS,
+        /// Reported deadlines of friends are only updated when they change by at least this
+        /// amount of ticks
+        deadlines_granularity_ticks: DEADLINES_GRANULARITY_TICKS,
+        /// Limits for the recently completed requests remembered for every friend
+        completed_cache_limits: CacheLimits {
+            max_entries: COMPLETED_CACHE_MAX_ENTRIES,
+            max_age_ticks: COMPLETED_CACHE_MAX_AGE_TICKS,
+        },
+        /// Limits for the start ticks of pending payments we originate
+        payment_timings_cache_limits: CacheLimits {
+            max_entries: PAYMENT_TIMINGS_CACHE_MAX_ENTRIES,
+            max_age_ticks: PAYMENT_TIMINGS_CACHE_MAX_AGE_TICKS,
+        },
+        /// Amount of ticks between two reloads of the trusted apps
+        trusted_apps_reload_ticks: TRUSTED_APPS_RELOAD_TICKS,
+        /// Amount of ticks between two comparisons of the capacities sent to the index server
+        /// against the current capacities of our friends
+        index_reconcile_ticks: INDEX_RECONCILE_TICKS,
+        /// Limits for the recent route responses of index servers
+        index_route_cache_limits: CacheLimits {
+            max_entries: INDEX_ROUTE_CACHE_MAX_ENTRIES,
+            max_age_ticks: INDEX_ROUTE_CACHE_MAX_AGE_TICKS,
+        },
+        /// Incoming events the funder takes a long time to handle are logged
+        slow_funder_event_limits: SlowEventLimits {
+            threshold: Duration::from_millis(usize_to_u64(SLOW_FUNDER_EVENT_MS).unwrap()),
+            log_interval: Duration::from_millis(
+                usize_to_u64(SLOW_FUNDER_EVENT_LOG_INTERVAL_MS).unwrap(),
+            ),
+        },
     };
 
     // A tcp connector, Used to connect to remote servers:
-    let net_connector =
-        NetConnector::new(MAX_FRAME_LENGTH, resolve_thread_pool, thread_pool.clone());
+    let net_connector = NetConnector::new(
+        MAX_FRAME_LENGTH,
+        timer_client.clone(),
+        CONNECT_STAGGER_TICKS,
+        resolve_thread_pool,
+        thread_pool.clone(),
+    );
 
     // Obtain secure cryptographic random:
     let rng = system_random();
 
     // Load database:
-    let atomic_db =
-        FileDb::<NodeState<NetAddress>>::load(database).map_err(|_| NodeBinError::LoadDbError)?;
+    let atomic_db = if quarantine_corrupt {
+        let mut atomic_db =
+            FileDb::load_with(database, NodeState::<NetAddress>::deserialize_quarantine)
+                .map_err(|_| NodeBinError::LoadDbError)?;
+        // Store the quarantine right away, together with the original bytes of the
+        // quarantined friends:
+        atomic_db
+            .mutate_db(&[])
+            .map_err(|_| NodeBinError::StoreDbError)?;
+        atomic_db
+    } else {
+        FileDb::<NodeState<NetAddress>>::load(database).map_err(|_| NodeBinError::LoadDbError)?
+    };
 
     // Start listening to apps:
     let app_tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
     let (_config_sender, incoming_app_raw_conns) = app_tcp_listener.listen(laddr);
 
+    // Optionally listen to direct connections from friends:
+    let incoming_direct_raw_conns: BoxStream<'static, ConnPairVec> = match opt_direct_laddr {
+        Some(direct_laddr) => {
+            let direct_tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
+            let (_config_sender, incoming_direct_raw_conns) =
+                direct_tcp_listener.listen(direct_laddr);
+            Box::pin(incoming_direct_raw_conns)
+        }
+        None => Box::pin(stream::empty()),
+    };
+
     // Create a closure for loading trusted apps map:
     let get_trusted_apps = move || -> Option<_> {
         Some(
@@ -171,6 +285,7 @@ pub fn stnode(st_node_cmd: StNodeCmd) -> Result<(), NodeBinError> {
 
     let node_fut = net_node(
         incoming_app_raw_conns,
+        incoming_direct_raw_conns,
         net_connector,
         timer_client,
         identity_client,
@@ -20,7 +20,9 @@ use net::TcpListener;
 use relay::{net_relay_server, NetRelayServerError};
 use timer::create_timer;
 
-use proto::file::identity::load_identity_from_file;
+use proto::file::identity::IdentityFileError;
+
+use crate::passphrase::load_identity_with_passphrase;
 
 // TODO; Maybe take as a command line argument in the future?
 /// Maximum amount of concurrent encrypted channel set-ups.
@@ -31,7 +33,7 @@ pub const MAX_CONCURRENT_ENCRYPT: usize = 0x200;
 #[derive(Debug)]
 pub enum RelayServerBinError {
     CreateThreadPoolError,
-    LoadIdentityError,
+    LoadIdentityError(IdentityFileError),
     CreateIdentityError,
     CreateTimerError,
     NetRelayServerError(NetRelayServerError),
@@ -48,14 +50,23 @@ pub struct StRelayCmd {
     /// Listening address (Example: 0.0.0.0:1337)
     #[structopt(short = "l", long = "laddr")]
     pub laddr: SocketAddr,
+    /// Passphrase file path, used if the identity file is encrypted (Example: /dev/fd/3).
+    /// If not specified, the passphrase is asked for in the terminal.
+    #[structopt(parse(from_os_str), long = "passphrase-file")]
+    pub opt_passphrase_file: Option<PathBuf>,
 }
 
 pub fn strelay(st_relay_cmd: StRelayCmd) -> Result<(), RelayServerBinError> {
-    let StRelayCmd { idfile, laddr } = st_relay_cmd;
+    let StRelayCmd {
+        idfile,
+        laddr,
+        opt_passphrase_file,
+    } = st_relay_cmd;
 
     // Parse identity file:
     let identity =
-        load_identity_from_file(&idfile).map_err(|_| RelayServerBinError::LoadIdentityError)?;
+        load_identity_with_passphrase(&idfile, opt_passphrase_file.as_ref().map(PathBuf::as_path))
+            .map_err(RelayServerBinError::LoadIdentityError)?;
 
     // Create a ThreadPool:
     let mut thread_pool =
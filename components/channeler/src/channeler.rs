@@ -96,6 +96,8 @@ struct OutFriend<RA> {
     config_client: CpConfigClient<RA>,
     connect_client: CpConnectClient,
     status: OutFriendStatus,
+    /// Do not reconnect after the current connection is closed.
+    suspended: bool,
 }
 
 struct Friends<RA> {
@@ -236,6 +238,7 @@ where
                 config_client,
                 connect_client,
                 status: OutFriendStatus::Connecting,
+                suspended: false,
             };
             self.friends
                 .out_friends
@@ -293,6 +296,7 @@ where
                 } else if let Some(out_friend) =
                     self.friends.out_friends.get_mut(&friend_public_key)
                 {
+                    out_friend.suspended = false;
                     await!(out_friend.config_client.config(friend_relays))
                         .map_err(|_| ChannelerError::ConnectorConfigError)?;
                 }
@@ -309,6 +313,25 @@ where
 
                 self.friends.out_friends.remove(&friend_public_key);
 
+                Ok(())
+            }
+            FunderToChanneler::SuspendFriend(friend_public_key) => {
+                // We never connect to friends that should connect to us, so there is nothing to
+                // suspend for those.
+                let out_friend = match self.friends.out_friends.get_mut(&friend_public_key) {
+                    Some(out_friend) => out_friend,
+                    None => return Ok(()),
+                };
+
+                match out_friend.status {
+                    OutFriendStatus::Connecting => {
+                        // Stop the connection attempts. The friend is added again on the next
+                        // UpdateFriend:
+                        self.friends.out_friends.remove(&friend_public_key);
+                    }
+                    OutFriendStatus::Connected(_) => out_friend.suspended = true,
+                }
+
                 Ok(())
             }
         }
@@ -437,9 +460,15 @@ where
                 } else if let Some(out_friend) =
                     self.friends.out_friends.get_mut(&friend_public_key)
                 {
-                    // Request a new connection
-                    out_friend.status = OutFriendStatus::Connecting;
-                    self.connect_out_friend(&friend_public_key)?;
+                    if out_friend.suspended {
+                        // The friend went offline intentionally.
+                        // We will connect again on the next UpdateFriend:
+                        self.friends.out_friends.remove(&friend_public_key);
+                    } else {
+                        // Request a new connection
+                        out_friend.status = OutFriendStatus::Connecting;
+                        self.connect_out_friend(&friend_public_key)?;
+                    }
                 }
             }
         }
@@ -447,12 +476,15 @@ where
     }
 }
 
-pub async fn channeler_loop<FF, TF, RA, C, L, S>(
+/// `incoming_direct_conns` are encrypted connections that were received directly, without
+/// going through a relay. They are handled just like connections received through the listener.
+pub async fn channeler_loop<FF, TF, RA, C, L, IDC, S>(
     local_public_key: PublicKey,
     from_funder: FF,
     to_funder: TF,
     connector: C,
     listener: L,
+    incoming_direct_conns: IDC,
     spawner: S,
 ) -> Result<(), ChannelerError>
 where
@@ -465,6 +497,7 @@ where
         + Sync
         + 'static,
     L: Listener<Connection = (PublicKey, RawConn), Config = LpConfig<RA>, Arg = ()> + Clone + Send,
+    IDC: Stream<Item = (PublicKey, RawConn)> + Send + Unpin + 'static,
     S: Spawn + Clone + Send + Sync + 'static,
 {
     let (event_sender, event_receiver) = mpsc::channel(0);
@@ -493,6 +526,18 @@ where
         .spawn(send_listen_conns_fut)
         .map_err(|_| ChannelerError::SpawnError)?;
 
+    // Forward incoming direct connections.
+    // Direct connections are optional, so we keep going if they are closed:
+    let mut c_event_sender = channeler.event_sender.clone();
+    let mut incoming_direct_conns = incoming_direct_conns.map(ChannelerEvent::Connection);
+    let send_direct_conns_fut = async move {
+        let _ = await!(c_event_sender.send_all(&mut incoming_direct_conns));
+    };
+    channeler
+        .spawner
+        .spawn(send_direct_conns_fut)
+        .map_err(|_| ChannelerError::SpawnError)?;
+
     let from_funder = from_funder
         .map(ChannelerEvent::FromFunder)
         .chain(stream::once(future::ready(ChannelerEvent::FunderClosed)));
@@ -558,6 +603,7 @@ mod tests {
                     to_funder,
                     connector,
                     listener,
+                    stream::empty(),
                     spawner.clone(),
                 )
                 .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
@@ -748,6 +794,7 @@ mod tests {
                     to_funder,
                     connector,
                     listener,
+                    stream::empty(),
                     spawner.clone(),
                 )
                 .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
@@ -872,6 +919,7 @@ mod tests {
                     to_funder,
                     connector,
                     listener,
+                    stream::empty(),
                     spawner.clone(),
                 )
                 .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
@@ -973,6 +1021,7 @@ mod tests {
                     to_funder,
                     connector,
                     listener,
+                    stream::empty(),
                     spawner.clone(),
                 )
                 .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
@@ -1040,6 +1089,133 @@ mod tests {
         ));
     }
 
+    /// Test the case of a friend the channeler initiates connection to, that goes offline
+    /// intentionally.
+    async fn task_channeler_loop_suspend_friend<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + Sync + 'static,
+    {
+        let (mut funder_sender, from_funder) = mpsc::channel(0);
+        let (to_funder, mut funder_receiver) = mpsc::channel(0);
+
+        // We sort the public keys ahead of time, so that we know how to break ties.
+        // Our local public key will be pks[1]. pks[0] < pks[1] < pks[2]
+        //
+        // pks[1] >= pks[0], so pks[0] be an active send friend (We initiate connection)
+        let mut pks = (0..3)
+            .map(|i| PublicKey::from(&[i; PUBLIC_KEY_LEN]))
+            .collect::<Vec<PublicKey>>();
+        pks.sort_by(compare_public_key);
+
+        let (conn_request_sender, mut conn_request_receiver) = mpsc::channel(0);
+        let connector = DummyConnector::new(conn_request_sender);
+
+        let (listener_req_sender, mut listener_req_receiver) = mpsc::channel(0);
+        let listener = DummyListener::new(listener_req_sender, spawner.clone());
+
+        spawner
+            .spawn(
+                channeler_loop(
+                    pks[1].clone(),
+                    from_funder,
+                    to_funder,
+                    connector,
+                    listener,
+                    stream::empty(),
+                    spawner.clone(),
+                )
+                .map_err(|e| error!("Error in channeler_loop(): {:?}", e))
+                .map(|_| ()),
+            )
+            .unwrap();
+
+        let _listener_request = await!(listener_req_receiver.next()).unwrap();
+
+        // Add a friend:
+        let channeler_update_friend = ChannelerUpdateFriend {
+            friend_public_key: pks[0].clone(),
+            friend_relays: vec![0x0u32],
+            local_relays: vec![0x2u32, 0x3u32],
+        };
+
+        await!(funder_sender.send(FunderToChanneler::UpdateFriend(
+            channeler_update_friend.clone()
+        )))
+        .unwrap();
+        let conn_request = await!(conn_request_receiver.next()).unwrap();
+        assert_eq!(conn_request.address, pks[0]);
+        let (connect_sender0, mut connect_receiver0) = mpsc::channel(0);
+        let (config_sender0, mut config_receiver0) = mpsc::channel(0);
+
+        let config_client0 = CpConfigClient::new(config_sender0);
+        let connect_client0 = CpConnectClient::new(connect_sender0);
+        conn_request.reply((config_client0, connect_client0));
+
+        let config0 = await!(config_receiver0.next()).unwrap();
+        assert_eq!(config0, vec![0x0u32]);
+
+        let connect_req0 = await!(connect_receiver0.next()).unwrap();
+
+        // Send back a connection:
+        let (pk0_sender, local_receiver) = mpsc::channel(0);
+        let (local_sender, mut pk0_receiver) = mpsc::channel(0);
+        connect_req0
+            .response_sender
+            .send((local_sender, local_receiver))
+            .unwrap();
+
+        // Friend should be reported as online:
+        let channeler_to_funder = await!(funder_receiver.next()).unwrap();
+        match channeler_to_funder {
+            ChannelerToFunder::Online(public_key) => assert_eq!(public_key, pks[0]),
+            _ => unreachable!(),
+        };
+
+        // pks[0] is going offline intentionally:
+        await!(funder_sender.send(FunderToChanneler::SuspendFriend(pks[0].clone()))).unwrap();
+
+        // Messages from the Funder are handled in order. Once this message arrives, we know that
+        // the suspension was handled:
+        await!(funder_sender.send(FunderToChanneler::Message((pks[0].clone(), vec![1, 2, 3]))))
+            .unwrap();
+        assert_eq!(await!(pk0_receiver.next()).unwrap(), vec![1, 2, 3]);
+
+        // Drop pks[0] connection:
+        drop(pk0_sender);
+        drop(pk0_receiver);
+
+        // pks[0] should be reported as offline:
+        let channeler_to_funder = await!(funder_receiver.next()).unwrap();
+        match channeler_to_funder {
+            ChannelerToFunder::Offline(public_key) => assert_eq!(public_key, pks[0]),
+            _ => unreachable!(),
+        };
+
+        // No connection is attempted. The connection requests receiver should be closed:
+        assert!(await!(connect_receiver0.next()).is_none());
+
+        // Connection attempts resume on the next UpdateFriend:
+        await!(funder_sender.send(FunderToChanneler::UpdateFriend(channeler_update_friend)))
+            .unwrap();
+
+        let conn_request = await!(conn_request_receiver.next()).unwrap();
+        assert_eq!(conn_request.address, pks[0]);
+
+        // Reply to the conn request, to avoid panic on exit:
+        let (connect_sender0, _connect_receiver0) = mpsc::channel(0);
+        let (config_sender0, _config_receiver0) = mpsc::channel(0);
+
+        let config_client0 = CpConfigClient::new(config_sender0);
+        let connect_client0 = CpConnectClient::new(connect_sender0);
+        conn_request.reply((config_client0, connect_client0));
+    }
+
+    #[test]
+    fn test_channeler_loop_suspend_friend() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_channeler_loop_suspend_friend(thread_pool.clone()));
+    }
+
     // TODO: Add tests to make sure access control works properly?
     // If a friend with a strange public key tries to connect, he should not be able to succeed?
 }
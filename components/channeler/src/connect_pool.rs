@@ -1,4 +1,5 @@
 use std::collections::{HashSet, VecDeque};
+use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::marker::{PhantomData, Unpin};
@@ -10,7 +11,7 @@ use futures::{future, select, stream, FutureExt, SinkExt, Stream, StreamExt, Try
 
 use common::conn::{BoxFuture, FutTransform};
 use common::select_streams::{select_streams, BoxStream};
-use timer::TimerClient;
+use timer::{TimerClient, TimerTick};
 
 use crate::types::RawConn;
 use crypto::identity::PublicKey;
@@ -70,7 +71,11 @@ enum CpEvent<RA> {
     ConfigRequest(Vec<RA>),
     ConfigRequestClosed,
     ConnectAttemptDone(Option<RawConn>),
-    TimerTick,
+    /// A migration attempt of the live connection to the given address is done.
+    /// Contains `true` if the migration succeeded.
+    MigrateAttemptDone((RA, bool)),
+    /// Amount of time ticks that have elapsed
+    TimerTick(usize),
     TimerClosed,
 }
 
@@ -80,14 +85,27 @@ enum CpStatus<RA> {
     Connecting((RA, oneshot::Sender<()>, oneshot::Sender<RawConn>)),
 }
 
-struct ConnectPool<RA, C, ET, S> {
+/// A connection that was handed to the user, and was not yet replaced by a new one.
+struct LiveConn<RA> {
+    /// Address the connection currently goes through.
+    address: RA,
+    /// Closing the sender closes the connection, making the user request a new connection.
+    sender: mpsc::Sender<Vec<u8>>,
+    /// A migration attempt in progress: (address, canceler)
+    opt_migrating: Option<(RA, oneshot::Sender<()>)>,
+}
+
+struct ConnectPool<RA, C, ET, MT, S> {
     friend_public_key: PublicKey,
     addresses: VecDeque<RA>,
     status: CpStatus<RA>,
+    opt_live_conn: Option<LiveConn<RA>>,
     conn_done_sender: mpsc::Sender<Option<RawConn>>,
+    migrate_done_sender: mpsc::Sender<(RA, bool)>,
     backoff_ticks: usize,
     client_connector: C,
     encrypt_transform: ET,
+    migrate_transform: MT,
     spawner: S,
 }
 
@@ -119,7 +137,38 @@ where
     }
 }
 
-impl<RA, C, ET, S> ConnectPool<RA, C, ET, S>
+/// Move the live connection with the friend to a new connection through `address`.
+/// Returns `true` if the migration succeeded.
+async fn migrate_attempt<RA, C, MT>(
+    friend_public_key: PublicKey,
+    address: RA,
+    mut client_connector: C,
+    mut migrate_transform: MT,
+    canceler: oneshot::Receiver<()>,
+) -> bool
+where
+    RA: Eq,
+    C: FutTransform<Input = (RA, PublicKey), Output = Option<RawConn>> + Clone,
+    MT: FutTransform<Input = (PublicKey, RawConn), Output = bool> + Clone,
+{
+    let migrate_fut = Box::pin(
+        async move {
+            let raw_conn =
+                match await!(client_connector.transform((address, friend_public_key.clone()))) {
+                    Some(raw_conn) => raw_conn,
+                    None => return false,
+                };
+            await!(migrate_transform.transform((friend_public_key, raw_conn)))
+        },
+    );
+
+    select! {
+        migrated = migrate_fut.fuse() => migrated,
+        _ = canceler.fuse() => false,
+    }
+}
+
+impl<RA, C, ET, MT, S> ConnectPool<RA, C, ET, MT, S>
 where
     RA: Hash + Clone + Eq + Send + Debug + 'static,
     S: Spawn,
@@ -127,24 +176,30 @@ where
         + Clone
         + Send
         + 'static,
+    MT: FutTransform<Input = (PublicKey, RawConn), Output = bool> + Clone + Send + 'static,
     C: FutTransform<Input = (RA, PublicKey), Output = Option<RawConn>> + Clone + Send + 'static,
 {
     pub fn new(
         friend_public_key: PublicKey,
         conn_done_sender: mpsc::Sender<Option<RawConn>>,
+        migrate_done_sender: mpsc::Sender<(RA, bool)>,
         backoff_ticks: usize,
         client_connector: C,
         encrypt_transform: ET,
+        migrate_transform: MT,
         spawner: S,
     ) -> Self {
         ConnectPool {
             friend_public_key,
             addresses: VecDeque::new(),
             status: CpStatus::NoRequest,
+            opt_live_conn: None,
             conn_done_sender,
+            migrate_done_sender,
             backoff_ticks,
             client_connector,
             encrypt_transform,
+            migrate_transform,
             spawner,
         }
     }
@@ -179,6 +234,96 @@ where
         Ok(cancel_sender)
     }
 
+    /// Start an attempt to migrate the live connection to a new connection through a relay with a
+    /// given address.
+    /// Returns a canceler.
+    fn create_migrate_attempt(
+        &mut self,
+        address: RA,
+    ) -> Result<oneshot::Sender<()>, ConnectPoolError> {
+        let (cancel_sender, cancel_receiver) = oneshot::channel();
+        let c_friend_public_key = self.friend_public_key.clone();
+        let c_client_connector = self.client_connector.clone();
+        let c_migrate_transform = self.migrate_transform.clone();
+
+        let mut c_migrate_done_sender = self.migrate_done_sender.clone();
+        let migrate_fut = async move {
+            let migrated = await!(migrate_attempt(
+                c_friend_public_key,
+                address.clone(),
+                c_client_connector,
+                c_migrate_transform,
+                cancel_receiver
+            ));
+            let _ = await!(c_migrate_done_sender.send((address, migrated)));
+        };
+
+        self.spawner
+            .spawn(migrate_fut)
+            .map_err(|_| ConnectPoolError::SpawnError)?;
+
+        Ok(cancel_sender)
+    }
+
+    /// If the live connection goes through an address that was removed, start migrating it to
+    /// one of the remaining addresses.
+    fn try_migrate(&mut self) -> Result<(), ConnectPoolError> {
+        let needs_migration = match &self.opt_live_conn {
+            Some(live_conn) => {
+                live_conn.opt_migrating.is_none() && !self.addresses.contains(&live_conn.address)
+            }
+            None => false,
+        };
+        if !needs_migration {
+            return Ok(());
+        }
+        let address = match self.addresses.front() {
+            Some(address) => address.clone(),
+            // There is nowhere to migrate to. We keep using the current connection:
+            None => return Ok(()),
+        };
+
+        let canceler = self.create_migrate_attempt(address.clone())?;
+        if let Some(live_conn) = &mut self.opt_live_conn {
+            live_conn.opt_migrating = Some((address, canceler));
+        }
+        Ok(())
+    }
+
+    pub fn handle_migrate_attempt_done(
+        &mut self,
+        address: RA,
+        migrated: bool,
+    ) -> Result<(), ConnectPoolError> {
+        let live_conn = match &mut self.opt_live_conn {
+            Some(live_conn) => live_conn,
+            // The live connection was already replaced:
+            None => return Ok(()),
+        };
+        match &live_conn.opt_migrating {
+            Some((migrating_address, _canceler)) if migrating_address == &address => {}
+            _ => return Ok(()),
+        }
+        live_conn.opt_migrating = None;
+
+        if migrated {
+            live_conn.address = address;
+            // The new address might have been removed while migrating:
+            return self.try_migrate();
+        }
+
+        // Fall back to a full reconnect. Closing the live connection makes the user request a
+        // new connection:
+        warn!(
+            "handle_migrate_attempt_done(): Migration to {:?} failed. Reconnecting.",
+            address
+        );
+        if let Some(mut live_conn) = self.opt_live_conn.take() {
+            live_conn.sender.close_channel();
+        }
+        Ok(())
+    }
+
     pub fn handle_connect_request(
         &mut self,
         connect_request: CpConnectRequest,
@@ -188,6 +333,10 @@ where
             return Err(ConnectPoolError::MultipleConnectRequests);
         }
 
+        // The user asks for a new connection, so the previous connection is no longer used.
+        // Dropping it also cancels its migration, if any:
+        self.opt_live_conn = None;
+
         let address = match self.addresses.pop_front() {
             None => {
                 // We can't connect yet, because we don't know of any address.
@@ -259,10 +408,13 @@ where
         for added_address in new_addresses.difference(&old_addresses) {
             self.add_address(added_address.clone())?;
         }
-        Ok(())
+
+        self.try_migrate()
     }
 
-    pub fn handle_timer_tick(&mut self) -> Result<(), ConnectPoolError> {
+    /// Advance the backoff by `ticks_elapsed`.
+    /// If many ticks have elapsed at once, only a single connection attempt is made.
+    pub fn handle_timer_tick(&mut self, ticks_elapsed: usize) -> Result<(), ConnectPoolError> {
         let waiting = match mem::replace(&mut self.status, CpStatus::NoRequest) {
             CpStatus::Waiting(waiting) => waiting,
             other_status => {
@@ -272,7 +424,7 @@ where
         };
 
         let (mut backoff_ticks, response_sender) = waiting;
-        backoff_ticks = backoff_ticks.saturating_sub(1);
+        backoff_ticks = backoff_ticks.saturating_sub(ticks_elapsed);
         if backoff_ticks == 0 {
             if let Some(address) = self.addresses.pop_front() {
                 let canceler = self.create_conn_attempt(address.clone())?;
@@ -293,14 +445,21 @@ where
         };
 
         let (address, _canceler, response_sender) = connecting;
-        self.addresses.push_back(address);
+        self.addresses.push_back(address.clone());
 
         if let Some(conn) = opt_conn {
+            let sender = conn.0.clone();
             if let Err(e) = response_sender.send(conn) {
                 warn!(
                     "handle_connect_attempt_done(): Failed to send connection response: {:?}",
                     e
                 );
+            } else {
+                self.opt_live_conn = Some(LiveConn {
+                    address,
+                    sender,
+                    opt_migrating: None,
+                });
             }
             self.status = CpStatus::NoRequest;
         } else {
@@ -309,11 +468,12 @@ where
     }
 }
 
-async fn connect_pool_loop<RA, ET, TS, C, S>(
+async fn connect_pool_loop<RA, ET, MT, TS, C, S>(
     incoming_requests: mpsc::Receiver<CpConnectRequest>,
     incoming_config: mpsc::Receiver<Vec<RA>>,
     timer_stream: TS,
     encrypt_transform: ET,
+    migrate_transform: MT,
     friend_public_key: PublicKey,
     backoff_ticks: usize,
     client_connector: C,
@@ -323,24 +483,29 @@ async fn connect_pool_loop<RA, ET, TS, C, S>(
 where
     RA: Hash + Clone + Eq + Send + Debug + 'static,
     C: FutTransform<Input = (RA, PublicKey), Output = Option<RawConn>> + Clone + Send + 'static,
-    TS: Stream + Unpin + Send,
+    TS: Stream<Item = TimerTick> + Unpin + Send,
     ET: FutTransform<Input = (PublicKey, RawConn), Output = Option<RawConn>>
         + Clone
         + Send
         + 'static,
+    MT: FutTransform<Input = (PublicKey, RawConn), Output = bool> + Clone + Send + 'static,
     S: Spawn + Clone,
 {
     let (conn_done_sender, incoming_conn_done) = mpsc::channel(0);
+    let (migrate_done_sender, incoming_migrate_done) = mpsc::channel(0);
     let mut connect_pool = ConnectPool::new(
         friend_public_key,
         conn_done_sender,
+        migrate_done_sender,
         backoff_ticks,
         client_connector,
         encrypt_transform,
+        migrate_transform,
         spawner.clone(),
     );
 
     let incoming_conn_done = incoming_conn_done.map(CpEvent::<RA>::ConnectAttemptDone);
+    let incoming_migrate_done = incoming_migrate_done.map(CpEvent::MigrateAttemptDone);
 
     let incoming_requests = incoming_requests
         .map(CpEvent::<RA>::ConnectRequest)
@@ -351,11 +516,16 @@ where
         .chain(stream::once(future::ready(CpEvent::ConfigRequestClosed)));
 
     let incoming_ticks = timer_stream
-        .map(|_| CpEvent::TimerTick)
+        .map(|timer_tick| {
+            let ticks_elapsed =
+                usize::try_from(timer_tick.ticks_elapsed).unwrap_or(usize::max_value());
+            CpEvent::TimerTick(ticks_elapsed)
+        })
         .chain(stream::once(future::ready(CpEvent::TimerClosed)));
 
     let mut incoming_events = select_streams![
         incoming_conn_done,
+        incoming_migrate_done,
         incoming_requests,
         incoming_config,
         incoming_ticks
@@ -375,7 +545,7 @@ where
                 info!("connect_pool_loop(): config request closed");
                 break;
             }
-            CpEvent::TimerTick => connect_pool.handle_timer_tick()?,
+            CpEvent::TimerTick(ticks_elapsed) => connect_pool.handle_timer_tick(ticks_elapsed)?,
             CpEvent::TimerClosed => {
                 info!("connect_pool_loop(): timer closed");
                 break;
@@ -383,6 +553,9 @@ where
             CpEvent::ConnectAttemptDone(opt_conn) => {
                 connect_pool.handle_connect_attempt_done(opt_conn)
             }
+            CpEvent::MigrateAttemptDone((address, migrated)) => {
+                connect_pool.handle_migrate_attempt_done(address, migrated)?
+            }
         }
         if let Some(ref mut event_sender) = opt_event_sender {
             let _ = await!(event_sender.send(()));
@@ -394,9 +567,15 @@ where
 
 pub type ConnectPoolControl<RA> = (CpConfigClient<RA>, CpConnectClient);
 
-pub fn create_connect_pool<RA, ET, TS, C, S>(
+/// Create a pool that connects to a friend through one of its addresses.
+///
+/// If the address of a live connection is removed, the connection is migrated to another address
+/// using `migrate_transform`. If the migration fails, the live connection is closed, so that the
+/// user requests a new one.
+pub fn create_connect_pool<RA, ET, MT, TS, C, S>(
     timer_stream: TS,
     encrypt_transform: ET,
+    migrate_transform: MT,
     friend_public_key: PublicKey,
     backoff_ticks: usize,
     client_connector: C,
@@ -405,11 +584,12 @@ pub fn create_connect_pool<RA, ET, TS, C, S>(
 where
     RA: Hash + Clone + Eq + Send + Debug + 'static,
     C: FutTransform<Input = (RA, PublicKey), Output = Option<RawConn>> + Clone + Send + 'static,
-    TS: Stream + Unpin + Send + 'static,
+    TS: Stream<Item = TimerTick> + Unpin + Send + 'static,
     ET: FutTransform<Input = (PublicKey, RawConn), Output = Option<RawConn>>
         + Clone
         + Send
         + 'static,
+    MT: FutTransform<Input = (PublicKey, RawConn), Output = bool> + Clone + Send + 'static,
     S: Spawn + Clone + Send + 'static,
 {
     let (connect_request_sender, incoming_requests) = mpsc::channel(0);
@@ -420,6 +600,7 @@ where
         incoming_config,
         timer_stream,
         encrypt_transform,
+        migrate_transform,
         friend_public_key,
         backoff_ticks,
         client_connector,
@@ -440,16 +621,17 @@ where
 }
 
 #[derive(Clone)]
-pub struct PoolConnector<RA, C, ET, S> {
+pub struct PoolConnector<RA, C, ET, MT, S> {
     timer_client: TimerClient,
     client_connector: C,
     encrypt_transform: ET,
+    migrate_transform: MT,
     backoff_ticks: usize,
     spawner: S,
     phantom_b: PhantomData<RA>,
 }
 
-impl<RA, C, ET, S> PoolConnector<RA, C, ET, S>
+impl<RA, C, ET, MT, S> PoolConnector<RA, C, ET, MT, S>
 where
     RA: Hash + Clone + Eq + Send + 'static,
     C: FutTransform<Input = (RA, PublicKey), Output = Option<RawConn>> + Clone + Send + 'static,
@@ -457,12 +639,14 @@ where
         + Clone
         + Send
         + 'static,
+    MT: FutTransform<Input = (PublicKey, RawConn), Output = bool> + Clone + Send + 'static,
     S: Spawn + Clone + Send + 'static,
 {
     pub fn new(
         timer_client: TimerClient,
         client_connector: C,
         encrypt_transform: ET,
+        migrate_transform: MT,
         backoff_ticks: usize,
         spawner: S,
     ) -> Self {
@@ -470,6 +654,7 @@ where
             timer_client,
             client_connector,
             encrypt_transform,
+            migrate_transform,
             backoff_ticks,
             spawner,
             phantom_b: PhantomData,
@@ -477,7 +662,7 @@ where
     }
 }
 
-impl<RA, C, ET, S> FutTransform for PoolConnector<RA, C, ET, S>
+impl<RA, C, ET, MT, S> FutTransform for PoolConnector<RA, C, ET, MT, S>
 where
     RA: Hash + Clone + Eq + Send + Debug + 'static,
     C: FutTransform<Input = (RA, PublicKey), Output = Option<RawConn>> + Clone + Send + 'static,
@@ -485,6 +670,7 @@ where
         + Clone
         + Send
         + 'static,
+    MT: FutTransform<Input = (PublicKey, RawConn), Output = bool> + Clone + Send + 'static,
     S: Spawn + Clone + Send + 'static,
 {
     type Input = PublicKey;
@@ -498,6 +684,7 @@ where
                 create_connect_pool(
                     timer_stream,
                     self.encrypt_transform.clone(),
+                    self.migrate_transform.clone(),
                     friend_public_key,
                     self.backoff_ticks,
                     self.client_connector.clone(),
@@ -537,10 +724,15 @@ mod tests {
             Box::pin(future::ready(Some(conn_pair)))
         });
 
-        let mut pool_connector = PoolConnector::<u32, _, _, _>::new(
+        // Migration is not used in this test:
+        let migrate_transform =
+            FuncFutTransform::new(|(_public_key, _conn_pair)| Box::pin(future::ready(false)));
+
+        let mut pool_connector = PoolConnector::<u32, _, _, _, _>::new(
             timer_client,
             client_connector,
             encrypt_transform,
+            migrate_transform,
             backoff_ticks,
             spawner,
         );
@@ -668,6 +860,10 @@ mod tests {
             Box::pin(future::ready(Some(conn_pair)))
         });
 
+        // Migration is not used in this test:
+        let migrate_transform =
+            FuncFutTransform::new(|(_public_key, _conn_pair)| Box::pin(future::ready(false)));
+
         let timer_stream = await!(timer_client.request_timer_stream()).unwrap();
         let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
 
@@ -690,6 +886,7 @@ mod tests {
             incoming_config,
             timer_stream,
             encrypt_transform,
+            migrate_transform,
             pk_b.clone(), // friend_public_key
             backoff_ticks,
             client_connector,
@@ -728,7 +925,7 @@ mod tests {
 
                 // Wait backoff_ticks:
                 for _ in 0..backoff_ticks {
-                    await!(tick_sender.send(TimerTick)).unwrap();
+                    await!(tick_sender.send(TimerTick { ticks_elapsed: 1 })).unwrap();
                     await!(event_receiver.next()).unwrap(); // timer tick event
                 }
             }
@@ -762,4 +959,196 @@ mod tests {
         let mut thread_pool = ThreadPool::new().unwrap();
         thread_pool.run(task_pool_connector_backoff_ticks(thread_pool.clone()));
     }
+
+    async fn task_pool_connector_ticks_jump<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        // Create a mock time service:
+        let (mut tick_sender_receiver, mut timer_client) =
+            dummy_timer_multi_sender(spawner.clone());
+
+        let backoff_ticks = 2;
+
+        let (conn_request_sender, mut conn_request_receiver) = mpsc::channel(0);
+        let client_connector = DummyConnector::new(conn_request_sender);
+
+        // We don't need encryption for this test:
+        let encrypt_transform = FuncFutTransform::new(|(_public_key, conn_pair)| {
+            Box::pin(future::ready(Some(conn_pair)))
+        });
+
+        // Migration is not used in this test:
+        let migrate_transform =
+            FuncFutTransform::new(|(_public_key, _conn_pair)| Box::pin(future::ready(false)));
+
+        let timer_stream = await!(timer_client.request_timer_stream()).unwrap();
+        let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
+
+        // Used for debugging the loop:
+        let (event_sender, mut event_receiver) = mpsc::channel(0);
+
+        let (request_sender, incoming_requests) = mpsc::channel(0);
+        let (config_sender, incoming_config) = mpsc::channel(0);
+
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        let loop_fut = connect_pool_loop(
+            incoming_requests,
+            incoming_config,
+            timer_stream,
+            encrypt_transform,
+            migrate_transform,
+            pk_b.clone(), // friend_public_key
+            backoff_ticks,
+            client_connector,
+            spawner.clone(),
+            Some(event_sender),
+        )
+        .map_err(|e| error!("connect_pool_loop() error: {:?}", e))
+        .map(|_| ());
+
+        spawner.spawn(loop_fut).unwrap();
+
+        let mut connect_client = CpConnectClient::new(request_sender);
+        let mut config_client = CpConfigClient::new(config_sender);
+
+        await!(config_client.config(vec![0x0u32])).unwrap();
+        await!(event_receiver.next()).unwrap();
+
+        let connect_fut = connect_client.connect();
+        let handle_connect_fut = async {
+            await!(event_receiver.next()).unwrap(); // Connection request event
+            let conn_request = await!(conn_request_receiver.next()).unwrap();
+
+            // Connection attempt failed:
+            conn_request.reply(None);
+            await!(event_receiver.next()).unwrap(); // connection attempt done event
+
+            // Many ticks elapse at once (For example, the host was suspended):
+            await!(tick_sender.send(TimerTick {
+                ticks_elapsed: 10_000
+            }))
+            .unwrap();
+            await!(event_receiver.next()).unwrap(); // timer tick event
+
+            // Exactly one new connection attempt is made:
+            let conn_request = await!(conn_request_receiver.next()).unwrap();
+            assert!(conn_request_receiver.try_next().is_err());
+
+            let (local_sender, remote_receiver) = mpsc::channel(0);
+            let (remote_sender, local_receiver) = mpsc::channel(0);
+            conn_request.reply(Some((local_sender, local_receiver)));
+            await!(event_receiver.next()).unwrap(); // connection attempt done event
+            (conn_request_receiver, (remote_sender, remote_receiver))
+        };
+        let (_local_conn, (_conn_request_receiver, _remote_conn)) =
+            await!(connect_fut.join(handle_connect_fut));
+    }
+
+    #[test]
+    fn test_pool_connector_ticks_jump() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_pool_connector_ticks_jump(thread_pool.clone()));
+    }
+
+    async fn task_pool_connector_migrate<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        // Create a mock time service:
+        let (mut tick_sender_receiver, timer_client) = dummy_timer_multi_sender(spawner.clone());
+
+        let backoff_ticks = 2;
+
+        let (conn_request_sender, mut conn_request_receiver) = mpsc::channel(0);
+        let client_connector = DummyConnector::new(conn_request_sender);
+
+        // We don't need encryption for this test:
+        let encrypt_transform = FuncFutTransform::new(|(_public_key, conn_pair)| {
+            Box::pin(future::ready(Some(conn_pair)))
+        });
+
+        // Every migration attempt is reported, and the test decides whether it succeeds:
+        let (migrate_request_sender, mut migrate_request_receiver) = mpsc::channel(0);
+        let migrate_transform = FuncFutTransform::new(move |(public_key, _conn_pair)| {
+            let mut c_migrate_request_sender = migrate_request_sender.clone();
+            Box::pin(
+                async move {
+                    let (response_sender, response_receiver) = oneshot::channel::<bool>();
+                    await!(c_migrate_request_sender.send((public_key, response_sender))).unwrap();
+                    await!(response_receiver).unwrap_or(false)
+                },
+            ) as BoxFuture<'static, bool>
+        });
+
+        let mut pool_connector = PoolConnector::<u32, _, _, _, _>::new(
+            timer_client,
+            client_connector,
+            encrypt_transform,
+            migrate_transform,
+            backoff_ticks,
+            spawner,
+        );
+
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let (mut config_client, mut connect_client) =
+            await!(pool_connector.transform(pk_b.clone()));
+        let _tick_sender = await!(tick_sender_receiver.next()).unwrap();
+
+        let mut addresses = vec![0x0u32, 0x1u32, 0x2u32];
+        await!(config_client.config(addresses.clone())).unwrap();
+
+        let connect_fut = connect_client.connect();
+        let handle_connect_fut = async {
+            let conn_request = await!(conn_request_receiver.next()).unwrap();
+            let (local_sender, remote_receiver) = mpsc::channel(0);
+            let (remote_sender, local_receiver) = mpsc::channel(0);
+
+            let address = conn_request.address.0;
+            conn_request.reply(Some((local_sender, local_receiver)));
+            (address, (remote_sender, remote_receiver))
+        };
+        let (_local_conn, (address, (_remote_sender, mut remote_receiver))) =
+            await!(connect_fut.join(handle_connect_fut));
+
+        // The address of the live connection is removed. The connection is migrated to one of
+        // the remaining addresses:
+        addresses.retain(|cur_address| cur_address != &address);
+        await!(config_client.config(addresses.clone())).unwrap();
+
+        let conn_request = await!(conn_request_receiver.next()).unwrap();
+        let (address, pk) = conn_request.address.clone();
+        assert_eq!(pk, pk_b);
+        assert!(addresses.contains(&address));
+        let (new_sender, _new_remote_receiver) = mpsc::channel(0);
+        let (_new_remote_sender, new_receiver) = mpsc::channel(0);
+        conn_request.reply(Some((new_sender, new_receiver)));
+
+        let (pk, response_sender) = await!(migrate_request_receiver.next()).unwrap();
+        assert_eq!(pk, pk_b);
+        response_sender.send(true).unwrap();
+
+        // The new address is removed too. This time the migration fails:
+        addresses.retain(|cur_address| cur_address != &address);
+        await!(config_client.config(addresses.clone())).unwrap();
+
+        let conn_request = await!(conn_request_receiver.next()).unwrap();
+        assert_eq!(conn_request.address, (addresses[0], pk_b.clone()));
+        let (new_sender, _new_remote_receiver) = mpsc::channel(0);
+        let (_new_remote_sender, new_receiver) = mpsc::channel(0);
+        conn_request.reply(Some((new_sender, new_receiver)));
+
+        let (_pk, response_sender) = await!(migrate_request_receiver.next()).unwrap();
+        response_sender.send(false).unwrap();
+
+        // We fall back to a full reconnect: The live connection is closed.
+        assert!(await!(remote_receiver.next()).is_none());
+    }
+
+    #[test]
+    fn test_pool_connector_migrate() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_pool_connector_migrate(thread_pool.clone()));
+    }
 }
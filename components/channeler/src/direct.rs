@@ -0,0 +1,240 @@
+use common::conn::{BoxFuture, ConnPairVec, FutTransform};
+
+use crypto::identity::PublicKey;
+
+use proto::app_server::messages::RelayAddress;
+use proto::funder::messages::{ChannelerUpdateFriend, FunderToChanneler};
+
+use crate::types::RawConn;
+
+/// Is `relay_address` a direct address of the node with the given `public_key`?
+///
+/// A node that can be reached without going through a relay (For example, on a LAN) advertises
+/// its direct address as a relay address that carries its own public key.
+pub fn is_direct_address<B>(relay_address: &RelayAddress<B>, public_key: &PublicKey) -> bool {
+    &relay_address.public_key == public_key
+}
+
+/// Remove our own direct addresses from the local relays sent by the funder.
+/// We never listen through a relay on our direct address. Incoming direct connections are
+/// received separately.
+pub fn remove_local_direct<B>(
+    funder_to_channeler: FunderToChanneler<RelayAddress<B>>,
+    local_public_key: &PublicKey,
+) -> FunderToChanneler<RelayAddress<B>> {
+    let is_relay =
+        |relay_address: &RelayAddress<B>| !is_direct_address(relay_address, local_public_key);
+
+    match funder_to_channeler {
+        FunderToChanneler::SetRelays(relays) => {
+            FunderToChanneler::SetRelays(relays.into_iter().filter(is_relay).collect())
+        }
+        FunderToChanneler::UpdateFriend(channeler_update_friend) => {
+            let ChannelerUpdateFriend {
+                friend_public_key,
+                friend_relays,
+                local_relays,
+            } = channeler_update_friend;
+            FunderToChanneler::UpdateFriend(ChannelerUpdateFriend {
+                friend_public_key,
+                friend_relays,
+                local_relays: local_relays.into_iter().filter(is_relay).collect(),
+            })
+        }
+        funder_to_channeler => funder_to_channeler,
+    }
+}
+
+/// Connect to a friend, either through a relay, or directly if the address is a direct address
+/// of the friend.
+///
+/// Direct connections are wrapped with keepalive, like connections to relays.
+/// Encryption against the friend is applied on top of the returned connection in both cases.
+#[derive(Clone)]
+pub struct DirectConnector<CC, DC, KT> {
+    client_connector: CC,
+    direct_connector: DC,
+    keepalive_transform: KT,
+}
+
+impl<CC, DC, KT> DirectConnector<CC, DC, KT> {
+    pub fn new(client_connector: CC, direct_connector: DC, keepalive_transform: KT) -> Self {
+        DirectConnector {
+            client_connector,
+            direct_connector,
+            keepalive_transform,
+        }
+    }
+}
+
+impl<B, CC, DC, KT> FutTransform for DirectConnector<CC, DC, KT>
+where
+    B: Send + 'static,
+    CC: FutTransform<Input = (RelayAddress<B>, PublicKey), Output = Option<RawConn>> + Send,
+    DC: FutTransform<Input = B, Output = Option<ConnPairVec>> + Send,
+    KT: FutTransform<Input = ConnPairVec, Output = ConnPairVec> + Send,
+{
+    type Input = (RelayAddress<B>, PublicKey);
+    type Output = Option<RawConn>;
+
+    fn transform(&mut self, input: Self::Input) -> BoxFuture<'_, Self::Output> {
+        let (relay_address, friend_public_key) = input;
+
+        Box::pin(
+            async move {
+                if !is_direct_address(&relay_address, &friend_public_key) {
+                    return await!(self
+                        .client_connector
+                        .transform((relay_address, friend_public_key)));
+                }
+                let conn_pair = await!(self.direct_connector.transform(relay_address.address))?;
+                Some(await!(self.keepalive_transform.transform(conn_pair)))
+            },
+        )
+    }
+}
+
+/// Set up an incoming direct connection: Wrap it with keepalive and encrypt it.
+/// Returns the public key of the remote side, because we can not predict it.
+/// The channeler only accepts the connection if the remote side is a friend.
+#[derive(Clone)]
+pub struct DirectListenTransform<ET, KT> {
+    encrypt_transform: ET,
+    keepalive_transform: KT,
+}
+
+impl<ET, KT> DirectListenTransform<ET, KT> {
+    pub fn new(encrypt_transform: ET, keepalive_transform: KT) -> Self {
+        DirectListenTransform {
+            encrypt_transform,
+            keepalive_transform,
+        }
+    }
+}
+
+impl<ET, KT> FutTransform for DirectListenTransform<ET, KT>
+where
+    ET: FutTransform<
+            Input = (Option<PublicKey>, ConnPairVec),
+            Output = Option<(PublicKey, ConnPairVec)>,
+        > + Send,
+    KT: FutTransform<Input = ConnPairVec, Output = ConnPairVec> + Send,
+{
+    type Input = ConnPairVec;
+    type Output = Option<(PublicKey, RawConn)>;
+
+    fn transform(&mut self, conn_pair: Self::Input) -> BoxFuture<'_, Self::Output> {
+        Box::pin(
+            async move {
+                let conn_pair = await!(self.keepalive_transform.transform(conn_pair));
+                await!(self.encrypt_transform.transform((None, conn_pair)))
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::channel::mpsc;
+    use futures::executor::ThreadPool;
+    use futures::{future, FutureExt, StreamExt};
+
+    use common::conn::FuncFutTransform;
+    use common::dummy_connector::DummyConnector;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    fn relay_address(public_key: &PublicKey, address: u32) -> RelayAddress<u32> {
+        RelayAddress {
+            public_key: public_key.clone(),
+            address,
+        }
+    }
+
+    #[test]
+    fn test_remove_local_direct() {
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let pk_relay = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+
+        let set_relays = FunderToChanneler::SetRelays(vec![
+            relay_address(&pk_a, 0),
+            relay_address(&pk_relay, 1),
+        ]);
+        match remove_local_direct(set_relays, &pk_a) {
+            FunderToChanneler::SetRelays(relays) => {
+                assert_eq!(relays, vec![relay_address(&pk_relay, 1)]);
+            }
+            _ => unreachable!(),
+        };
+
+        // Direct addresses of the friend are kept:
+        let update_friend = FunderToChanneler::UpdateFriend(ChannelerUpdateFriend {
+            friend_public_key: pk_b.clone(),
+            friend_relays: vec![relay_address(&pk_b, 2), relay_address(&pk_relay, 1)],
+            local_relays: vec![relay_address(&pk_a, 0), relay_address(&pk_relay, 1)],
+        });
+        match remove_local_direct(update_friend, &pk_a) {
+            FunderToChanneler::UpdateFriend(channeler_update_friend) => {
+                assert_eq!(channeler_update_friend.friend_public_key, pk_b);
+                assert_eq!(
+                    channeler_update_friend.friend_relays,
+                    vec![relay_address(&pk_b, 2), relay_address(&pk_relay, 1)]
+                );
+                assert_eq!(
+                    channeler_update_friend.local_relays,
+                    vec![relay_address(&pk_relay, 1)]
+                );
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    async fn task_direct_connector() {
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let pk_relay = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+
+        let (client_req_sender, mut client_req_receiver) = mpsc::channel(0);
+        let client_connector = DummyConnector::new(client_req_sender);
+
+        let (direct_req_sender, mut direct_req_receiver) = mpsc::channel(0);
+        let direct_connector = DummyConnector::new(direct_req_sender);
+
+        // We don't need keepalive for this test:
+        let keepalive_transform =
+            FuncFutTransform::new(|conn_pair| Box::pin(future::ready(conn_pair)));
+
+        let mut direct_connector =
+            DirectConnector::new(client_connector, direct_connector, keepalive_transform);
+
+        // A relay address is connected through the relay client:
+        let connect_fut = direct_connector.transform((relay_address(&pk_relay, 1), pk_b.clone()));
+        let handle_connect_fut = async {
+            let conn_request = await!(client_req_receiver.next()).unwrap();
+            assert_eq!(conn_request.address, (relay_address(&pk_relay, 1), pk_b.clone()));
+            conn_request.reply(None);
+        };
+        let (opt_conn, ()) = await!(connect_fut.join(handle_connect_fut));
+        assert!(opt_conn.is_none());
+
+        // A direct address of the friend is connected directly:
+        let connect_fut = direct_connector.transform((relay_address(&pk_b, 2), pk_b.clone()));
+        let handle_connect_fut = async {
+            let conn_request = await!(direct_req_receiver.next()).unwrap();
+            assert_eq!(conn_request.address, 2);
+            let (local_sender, _remote_receiver) = mpsc::channel(0);
+            let (_remote_sender, local_receiver) = mpsc::channel(0);
+            conn_request.reply(Some((local_sender, local_receiver)));
+        };
+        let (opt_conn, ()) = await!(connect_fut.join(handle_connect_fut));
+        assert!(opt_conn.is_some());
+    }
+
+    #[test]
+    fn test_direct_connector() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_direct_connector());
+    }
+}
@@ -21,6 +21,7 @@ extern crate common;
 mod channeler;
 mod connect_pool;
 mod connector_utils;
+mod direct;
 mod listen_pool;
 mod listen_pool_state;
 mod overwrite_channel;
@@ -28,4 +29,5 @@ mod spawn;
 mod types;
 
 pub use self::channeler::ChannelerError;
+pub use self::direct::is_direct_address;
 pub use self::spawn::{spawn_channeler, SpawnChannelerError};
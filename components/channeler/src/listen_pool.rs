@@ -1,4 +1,5 @@
 use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::marker::PhantomData;
@@ -12,7 +13,7 @@ use common::conn::{FutTransform, Listener};
 use common::select_streams::{select_streams, BoxStream};
 use common::transform_pool::transform_pool_loop;
 
-use timer::TimerClient;
+use timer::{TimerClient, TimerTick};
 
 use crate::listen_pool_state::{ListenPoolState, Relay};
 use crate::types::{AccessControlOpPk, AccessControlPk, RawConn};
@@ -61,7 +62,8 @@ enum LpEvent<RA> {
     Config(LpConfig<RA>),
     ConfigClosed,
     RelayClosed(RA),
-    TimerTick,
+    /// Amount of time ticks that have elapsed
+    TimerTick(usize),
     TimerClosed,
 }
 
@@ -219,12 +221,14 @@ where
         Ok(())
     }
 
-    pub fn handle_timer_tick(&mut self) -> Result<(), ListenPoolError> {
+    /// Advance the backoff of relays we are not listening to by `ticks_elapsed`.
+    /// If many ticks have elapsed at once, we attempt to listen again only once.
+    pub fn handle_timer_tick(&mut self, ticks_elapsed: usize) -> Result<(), ListenPoolError> {
         let mut spawn_addresses = Vec::new();
         for (address, relay) in &mut self.state.relays {
             match &mut relay.status {
                 RelayStatus::Waiting(ref mut remaining_ticks) => {
-                    *remaining_ticks = (*remaining_ticks).saturating_sub(1);
+                    *remaining_ticks = (*remaining_ticks).saturating_sub(ticks_elapsed);
                     if *remaining_ticks > 0 {
                         continue;
                     }
@@ -263,7 +267,7 @@ where
             Arg = (RA, AccessControlPk),
         > + Clone
         + 'static,
-    TS: Stream + Unpin + Send,
+    TS: Stream<Item = TimerTick> + Unpin + Send,
     S: Spawn + Clone + Send + 'static,
 {
     let (relay_closed_sender, relay_closed_receiver) = mpsc::channel(0);
@@ -283,7 +287,11 @@ where
         .chain(stream::once(future::ready(LpEvent::ConfigClosed)));
 
     let timer_stream = timer_stream
-        .map(|_| LpEvent::<RA>::TimerTick)
+        .map(|timer_tick| {
+            let ticks_elapsed =
+                usize::try_from(timer_tick.ticks_elapsed).unwrap_or(usize::max_value());
+            LpEvent::<RA>::TimerTick(ticks_elapsed)
+        })
         .chain(stream::once(future::ready(LpEvent::TimerClosed)));
 
     let mut incoming_events = select_streams![incoming_relay_closed, incoming_config, timer_stream];
@@ -293,7 +301,7 @@ where
             LpEvent::Config(config) => await!(listen_pool.handle_config(config))?,
             LpEvent::ConfigClosed => break,
             LpEvent::RelayClosed(address) => listen_pool.handle_relay_closed(address)?,
-            LpEvent::TimerTick => listen_pool.handle_timer_tick()?,
+            LpEvent::TimerTick(ticks_elapsed) => listen_pool.handle_timer_tick(ticks_elapsed)?,
             LpEvent::TimerClosed => break,
         };
 
@@ -567,7 +575,7 @@ mod tests {
 
             // Wait until backoff_ticks time passes:
             for _ in 0..backoff_ticks {
-                await!(tick_sender.send(TimerTick)).unwrap();
+                await!(tick_sender.send(TimerTick { ticks_elapsed: 1 })).unwrap();
                 await!(event_receiver.next()).unwrap();
             }
         }
@@ -2,18 +2,22 @@ use std::fmt::Debug;
 use std::hash::Hash;
 
 use futures::channel::mpsc;
-use futures::task::Spawn;
+use futures::task::{Spawn, SpawnExt};
+use futures::{FutureExt, Stream, StreamExt, TryFutureExt};
 
 use common::conn::{BoxFuture, ConnPairVec, FutTransform};
+use common::transform_pool::transform_pool_loop;
 use timer::TimerClient;
 
 use crypto::identity::PublicKey;
 
-use relay::{ClientConnector, ClientListener};
+use relay::{ClientConnector, ClientListener, MuxClientConnector};
 
 use crate::channeler::{channeler_loop, ChannelerError};
 use crate::connect_pool::PoolConnector;
+use crate::direct::{remove_local_direct, DirectConnector, DirectListenTransform};
 use crate::listen_pool::PoolListener;
+use proto::app_server::messages::RelayAddress;
 use proto::funder::messages::{ChannelerToFunder, FunderToChanneler};
 
 /// A connection style encrypt transform.
@@ -89,6 +93,31 @@ where
     }
 }
 
+/// Connects to friends through relays.
+/// Either every connection uses a separate connection to the relay, or all the connections
+/// through the same relay share a single multiplexed connection to the relay.
+#[derive(Clone)]
+enum RelayConnector<CC, MC> {
+    Plain(CC),
+    Mux(MC),
+}
+
+impl<I, CC, MC> FutTransform for RelayConnector<CC, MC>
+where
+    CC: FutTransform<Input = I, Output = Option<ConnPairVec>>,
+    MC: FutTransform<Input = I, Output = Option<ConnPairVec>>,
+{
+    type Input = I;
+    type Output = Option<ConnPairVec>;
+
+    fn transform(&mut self, input: Self::Input) -> BoxFuture<'_, Self::Output> {
+        match self {
+            RelayConnector::Plain(client_connector) => client_connector.transform(input),
+            RelayConnector::Mux(mux_client_connector) => mux_client_connector.transform(input),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum SpawnChannelerError {
     SpawnError,
@@ -96,22 +125,44 @@ pub enum SpawnChannelerError {
 
 // TODO: Possibly rename this function and module, as the channeler future
 // is not spawned here.
-pub async fn spawn_channeler<RA, C, ET, KT, S>(
+/// Run the channeler.
+///
+/// `direct_connector` is used to connect directly to friends that advertise a direct address (See
+/// `is_direct_address`). `incoming_direct_raw_conns` are connections received directly from
+/// remote nodes. Every such connection is encrypted, and kept only if the remote side is a friend.
+///
+/// If `relay_mux` is set, all the connections to friends through the same relay share a single
+/// connection to the relay. This requires relays that support multiplexed connections.
+///
+/// `migrate_transform` moves a live encrypted channel with a friend to a new plain channel. It is
+/// used when the relay we are connected to a friend through is removed from the friend's relays.
+/// If the migration fails, we reconnect to the friend.
+pub async fn spawn_channeler<B, C, DC, IDC, ET, MT, KT, S>(
     local_public_key: PublicKey,
     timer_client: TimerClient,
     backoff_ticks: usize,
     conn_timeout_ticks: usize,
     max_concurrent_encrypt: usize,
+    relay_mux: bool,
     enc_relay_connector: C,
+    direct_connector: DC,
+    incoming_direct_raw_conns: IDC,
     encrypt_transform: ET,
+    migrate_transform: MT,
     keepalive_transform: KT,
-    from_funder: mpsc::Receiver<FunderToChanneler<RA>>,
+    from_funder: mpsc::Receiver<FunderToChanneler<RelayAddress<B>>>,
     to_funder: mpsc::Sender<ChannelerToFunder>,
-    spawner: S,
+    mut spawner: S,
 ) -> Result<(), ChannelerError>
 where
-    RA: Eq + Hash + Clone + Send + Sync + Debug + 'static,
-    C: FutTransform<Input = RA, Output = Option<ConnPairVec>> + Clone + Send + Sync + 'static,
+    B: Eq + Hash + Clone + Send + Sync + Debug + 'static,
+    C: FutTransform<Input = RelayAddress<B>, Output = Option<ConnPairVec>>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    DC: FutTransform<Input = B, Output = Option<ConnPairVec>> + Clone + Send + Sync + 'static,
+    IDC: Stream<Item = ConnPairVec> + Send + Unpin + 'static,
     ET: FutTransform<
             Input = (Option<PublicKey>, ConnPairVec),
             Output = Option<(PublicKey, ConnPairVec)>,
@@ -119,18 +170,40 @@ where
         + Send
         + Sync
         + 'static,
+    MT: FutTransform<Input = (PublicKey, ConnPairVec), Output = bool>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
     KT: FutTransform<Input = ConnPairVec, Output = ConnPairVec> + Clone + Send + Sync + 'static,
     S: Spawn + Clone + Send + Sync + 'static,
 {
-    let client_connector =
-        ClientConnector::new(enc_relay_connector.clone(), keepalive_transform.clone());
+    let client_connector = if relay_mux {
+        RelayConnector::Mux(MuxClientConnector::new(
+            enc_relay_connector.clone(),
+            keepalive_transform.clone(),
+            spawner.clone(),
+        ))
+    } else {
+        RelayConnector::Plain(ClientConnector::new(
+            enc_relay_connector.clone(),
+            keepalive_transform.clone(),
+        ))
+    };
+
+    let direct_connector = DirectConnector::new(
+        client_connector,
+        direct_connector,
+        keepalive_transform.clone(),
+    );
 
     let connect_encrypt_transform = ConnectEncryptTransform::new(encrypt_transform.clone());
 
     let pool_connector = PoolConnector::new(
         timer_client.clone(),
-        client_connector.clone(),
+        direct_connector,
         connect_encrypt_transform,
+        migrate_transform,
         backoff_ticks,
         spawner.clone(),
     );
@@ -145,7 +218,7 @@ where
 
     let listen_encrypt_transform = ListenEncryptTransform::new(encrypt_transform.clone());
 
-    let pool_listener = PoolListener::<RA, _, _, _>::new(
+    let pool_listener = PoolListener::<RelayAddress<B>, _, _, _>::new(
         client_listener,
         listen_encrypt_transform,
         max_concurrent_encrypt,
@@ -154,6 +227,29 @@ where
         spawner.clone(),
     );
 
+    // Set up incoming direct connections:
+    let direct_listen_transform =
+        DirectListenTransform::new(encrypt_transform.clone(), keepalive_transform.clone());
+    let (direct_conns_sender, incoming_direct_conns) = mpsc::channel(0);
+    let direct_pool_fut = transform_pool_loop(
+        incoming_direct_raw_conns,
+        direct_conns_sender,
+        direct_listen_transform,
+        max_concurrent_encrypt,
+        spawner.clone(),
+    )
+    .map_err(|e| error!("direct transform_pool_loop() error: {:?}", e))
+    .map(|_| ());
+    spawner
+        .spawn(direct_pool_fut)
+        .map_err(|_| ChannelerError::SpawnError)?;
+
+    // We never listen through relays on our own direct addresses:
+    let c_local_public_key = local_public_key.clone();
+    let from_funder = from_funder.map(move |funder_to_channeler| {
+        remove_local_direct(funder_to_channeler, &c_local_public_key)
+    });
+
     // TODO: Maybe use await! instead of spawn_with_handle() here?
     await!(channeler_loop(
         local_public_key,
@@ -161,6 +257,7 @@ where
         to_funder,
         pool_connector,
         pool_listener,
+        incoming_direct_conns,
         spawner.clone()
     ))
 }
@@ -0,0 +1,318 @@
+use std::hash::Hash;
+
+use im::hashmap::HashMap as ImHashMap;
+use im::ordmap::OrdMap as ImOrdMap;
+
+use crate::int_convert::usize_to_u64;
+
+/// Limits of a bounded cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheLimits {
+    /// Maximum amount of entries. When exceeded, the least recently used entries are evicted.
+    pub max_entries: usize,
+    /// Entries that were not used for this amount of ticks are evicted.
+    pub max_age_ticks: usize,
+}
+
+/// Size metrics of a bounded cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheMetrics {
+    /// Current amount of entries
+    pub len: usize,
+    /// Amount of entries evicted because the cache was full
+    pub evicted_by_count: u64,
+    /// Amount of entries evicted because they were not used for too long
+    pub evicted_by_age: u64,
+}
+
+impl CacheMetrics {
+    /// Combine the metrics of two caches.
+    pub fn combine(&self, other: &CacheMetrics) -> CacheMetrics {
+        CacheMetrics {
+            len: self.len.saturating_add(other.len),
+            evicted_by_count: self.evicted_by_count.saturating_add(other.evicted_by_count),
+            evicted_by_age: self.evicted_by_age.saturating_add(other.evicted_by_age),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry<V> {
+    value: V,
+    /// Position of the entry in the order of use
+    seq: u64,
+    /// The tick in which the entry was last used
+    last_used: u64,
+}
+
+/// A least recently used cache, bounded both by the amount of entries and by the age of the
+/// entries.
+///
+/// Time is measured in ticks: The owner of the cache should call `tick()` on every timer tick,
+/// with the amount of ticks that have elapsed.
+/// The cache is made of persistent data structures, so cloning it is cheap.
+#[derive(Debug, Clone)]
+pub struct BoundedCache<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    limits: CacheLimits,
+    /// Amount of ticks that have passed since the cache was created
+    ticks: u64,
+    /// Position in the order of use of the next used entry
+    next_seq: u64,
+    entries: ImHashMap<K, CacheEntry<V>>,
+    /// Keys, ordered by their last use. The least recently used key comes first.
+    order: ImOrdMap<u64, K>,
+    evicted_by_count: u64,
+    evicted_by_age: u64,
+}
+
+impl<K, V> BoundedCache<K, V>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+{
+    pub fn new(limits: CacheLimits) -> Self {
+        BoundedCache {
+            limits,
+            ticks: 0,
+            next_seq: 0,
+            entries: ImHashMap::new(),
+            order: ImOrdMap::new(),
+            evicted_by_count: 0,
+            evicted_by_age: 0,
+        }
+    }
+
+    pub fn limits(&self) -> &CacheLimits {
+        &self.limits
+    }
+
+    /// Insert an entry, marking it as the most recently used entry.
+    /// Returns the previous value of the entry, if it existed.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let opt_old_value = self.remove(&key);
+
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.order.insert(seq, key.clone());
+        let cache_entry = CacheEntry {
+            value,
+            seq,
+            last_used: self.ticks,
+        };
+        self.entries.insert(key, cache_entry);
+
+        while self.entries.len() > self.limits.max_entries {
+            if self.evict_oldest().is_none() {
+                break;
+            }
+            self.evicted_by_count = self.evicted_by_count.saturating_add(1);
+        }
+        opt_old_value
+    }
+
+    /// Mark an entry as the most recently used entry.
+    /// Returns false if the entry does not exist.
+    pub fn touch(&mut self, key: &K) -> bool {
+        match self.remove(key) {
+            Some(value) => {
+                self.insert(key.clone(), value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Get the value of an entry. This does not count as a use of the entry.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|cache_entry| &cache_entry.value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let cache_entry = self.entries.remove(key)?;
+        let _ = self.order.remove(&cache_entry.seq);
+        Some(cache_entry.value)
+    }
+
+    /// `ticks_elapsed` time ticks have passed. Evicts the entries that were not used for too
+    /// long.
+    pub fn tick(&mut self, ticks_elapsed: u64) {
+        self.ticks = self.ticks.wrapping_add(ticks_elapsed);
+        let max_age_ticks = usize_to_u64(self.limits.max_age_ticks).unwrap();
+
+        while let Some((_seq, key)) = self.order.get_min() {
+            // Entries are ordered by their last use, so the first entry is also the oldest:
+            let last_used = self.entries.get(key).unwrap().last_used;
+            if self.ticks.wrapping_sub(last_used) < max_age_ticks {
+                break;
+            }
+            let _ = self.evict_oldest();
+            self.evicted_by_age = self.evicted_by_age.saturating_add(1);
+        }
+    }
+
+    fn evict_oldest(&mut self) -> Option<K> {
+        let (seq, key) = self.order.get_min()?.clone();
+        let _ = self.order.remove(&seq);
+        let _ = self.entries.remove(&key);
+        Some(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Keys of all the entries, in no particular order.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.entries.keys()
+    }
+
+    pub fn metrics(&self) -> CacheMetrics {
+        CacheMetrics {
+            len: self.entries.len(),
+            evicted_by_count: self.evicted_by_count,
+            evicted_by_age: self.evicted_by_age,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    fn limits(max_entries: usize, max_age_ticks: usize) -> CacheLimits {
+        CacheLimits {
+            max_entries,
+            max_age_ticks,
+        }
+    }
+
+    #[test]
+    fn test_bounded_cache_count_eviction() {
+        let mut cache = BoundedCache::new(limits(3, 100));
+        for i in 0..3u32 {
+            assert_eq!(cache.insert(i, i * 10), None);
+        }
+        assert_eq!(cache.len(), 3);
+
+        // 0 is the least recently used entry. Using it makes 1 the least recently used entry:
+        assert!(cache.touch(&0));
+        assert!(!cache.touch(&7));
+        cache.insert(3, 30);
+        assert_eq!(cache.len(), 3);
+        assert!(!cache.contains_key(&1));
+        assert_eq!(cache.get(&0), Some(&0));
+        assert_eq!(cache.get(&3), Some(&30));
+
+        // Inserting an existing entry replaces its value, without evicting anything:
+        assert_eq!(cache.insert(2, 21), Some(20));
+        assert_eq!(cache.len(), 3);
+
+        assert_eq!(
+            cache.metrics(),
+            CacheMetrics {
+                len: 3,
+                evicted_by_count: 1,
+                evicted_by_age: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_bounded_cache_age_eviction() {
+        let mut cache = BoundedCache::new(limits(100, 3));
+        cache.insert(0u32, ());
+        cache.tick(1);
+        cache.insert(1u32, ());
+        cache.tick(1);
+        cache.insert(2u32, ());
+        cache.tick(1);
+        // 0 was not used for 3 ticks:
+        assert!(!cache.contains_key(&0));
+        assert_eq!(cache.len(), 2);
+
+        // Using an entry keeps it in the cache:
+        assert!(cache.touch(&1));
+        cache.tick(1);
+        cache.tick(1);
+        assert!(!cache.contains_key(&2));
+        assert!(cache.contains_key(&1));
+        cache.tick(1);
+        assert!(cache.is_empty());
+
+        assert_eq!(cache.metrics().evicted_by_age, 3);
+        assert_eq!(cache.metrics().evicted_by_count, 0);
+
+        // Removing entries explicitly is not counted as an eviction:
+        cache.insert(3u32, ());
+        assert_eq!(cache.remove(&3), Some(()));
+        cache.tick(1);
+        assert_eq!(cache.metrics().evicted_by_age, 3);
+
+        // Many ticks elapsed at once:
+        cache.insert(4u32, ());
+        cache.insert(5u32, ());
+        cache.tick(10_000);
+        assert!(cache.is_empty());
+        assert_eq!(cache.metrics().evicted_by_age, 5);
+    }
+
+    #[test]
+    fn test_bounded_cache_zero_entries() {
+        let mut cache = BoundedCache::new(limits(0, 100));
+        cache.insert(0u32, ());
+        assert!(cache.is_empty());
+        assert_eq!(cache.metrics().evicted_by_count, 1);
+    }
+
+    #[test]
+    fn test_bounded_cache_concurrent_access() {
+        const NUM_THREADS: u32 = 8;
+        const INSERTS_PER_THREAD: u32 = 0x200;
+        const MAX_ENTRIES: usize = 0x40;
+
+        let cache = Arc::new(Mutex::new(BoundedCache::new(limits(MAX_ENTRIES, 0x10))));
+
+        let handles = (0..NUM_THREADS)
+            .map(|thread_index| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for i in 0..INSERTS_PER_THREAD {
+                        let mut cache = cache.lock().unwrap();
+                        cache.insert((thread_index, i), i);
+                        if i % 0x10 == 0 {
+                            cache.tick(1);
+                        }
+                        assert!(cache.len() <= MAX_ENTRIES);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // Every inserted entry is either still in the cache, or was evicted:
+        let metrics = cache.lock().unwrap().metrics();
+        assert!(metrics.len <= MAX_ENTRIES);
+        assert_eq!(
+            metrics.len as u64 + metrics.evicted_by_count + metrics.evicted_by_age,
+            u64::from(NUM_THREADS * INSERTS_PER_THREAD)
+        );
+    }
+}
@@ -1,9 +1,27 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use im::hashmap::HashMap as ImHashMap;
+
 use crate::int_convert::usize_to_u64;
 use byteorder::{BigEndian, WriteBytesExt};
 
 /// Canonically serialize an object
 /// This serialization is used for security related applications (For example, signatures and
 /// hashing), therefore the serialization result must be the same on any system.
+///
+/// Layout of the implementations in this module:
+/// - Integers: Big endian, using the full width of the type (`u32`: 4 bytes, `u64`: 8 bytes,
+///   `u128` and `i128`: 16 bytes). `i128` is written in two's complement.
+/// - `bool`: One byte, `0` for `false` and `1` for `true`.
+/// - `String`: The UTF-8 bytes, without a length prefix.
+/// - `Option<T>`: One byte, `0` for `None`. For `Some(t)`, `1` followed by `t`.
+/// - `Vec<T>`: The amount of items as a big endian `u64`, followed by all the items.
+/// - `(A, B)`: `A` followed by `B`.
+/// - `HashMap<K, V>` and `ImHashMap<K, V>`: The amount of entries as a big endian `u64`,
+///   followed by all the entries (Every entry is the key followed by the value). Entries are
+///   sorted by the serialization of their keys, so the result does not depend on the iteration
+///   order of the map.
 pub trait CanonicalSerialize {
     fn canonical_serialize(&self) -> Vec<u8>;
 }
@@ -45,12 +63,20 @@ where
     }
 }
 
+// Note that there is no length prefix. A String should only be serialized in places where its
+// length is known (For example, as the last field).
 impl CanonicalSerialize for String {
     fn canonical_serialize(&self) -> Vec<u8> {
         self.as_bytes().to_vec()
     }
 }
 
+impl CanonicalSerialize for bool {
+    fn canonical_serialize(&self) -> Vec<u8> {
+        vec![u8::from(*self)]
+    }
+}
+
 // Used mostly for testing:
 impl CanonicalSerialize for u32 {
     fn canonical_serialize(&self) -> Vec<u8> {
@@ -60,6 +86,30 @@ impl CanonicalSerialize for u32 {
     }
 }
 
+impl CanonicalSerialize for u64 {
+    fn canonical_serialize(&self) -> Vec<u8> {
+        let mut res_data = Vec::new();
+        res_data.write_u64::<BigEndian>(*self).unwrap();
+        res_data
+    }
+}
+
+impl CanonicalSerialize for u128 {
+    fn canonical_serialize(&self) -> Vec<u8> {
+        let mut res_data = Vec::new();
+        res_data.write_u128::<BigEndian>(*self).unwrap();
+        res_data
+    }
+}
+
+impl CanonicalSerialize for i128 {
+    fn canonical_serialize(&self) -> Vec<u8> {
+        let mut res_data = Vec::new();
+        res_data.write_i128::<BigEndian>(*self).unwrap();
+        res_data
+    }
+}
+
 impl<T, W> CanonicalSerialize for (T, W)
 where
     T: CanonicalSerialize,
@@ -73,3 +123,156 @@ where
         res_data
     }
 }
+
+/// Serialize the entries of a map, sorted by the serialization of their keys.
+fn canonical_serialize_map<'a, K, V>(entries: impl Iterator<Item = (&'a K, &'a V)>) -> Vec<u8>
+where
+    K: CanonicalSerialize + 'a,
+    V: CanonicalSerialize + 'a,
+{
+    let mut ser_entries = entries
+        .map(|(key, value)| (key.canonical_serialize(), value.canonical_serialize()))
+        .collect::<Vec<_>>();
+    // Keys of a map are unique, therefore there are no ties:
+    ser_entries.sort_by(|(key_a, _), (key_b, _)| key_a.cmp(key_b));
+
+    let mut res_data = Vec::new();
+    res_data
+        .write_u64::<BigEndian>(usize_to_u64(ser_entries.len()).unwrap())
+        .unwrap();
+    for (ser_key, ser_value) in ser_entries {
+        res_data.extend_from_slice(&ser_key);
+        res_data.extend_from_slice(&ser_value);
+    }
+    res_data
+}
+
+impl<K, V> CanonicalSerialize for HashMap<K, V>
+where
+    K: CanonicalSerialize + Hash + Eq,
+    V: CanonicalSerialize,
+{
+    fn canonical_serialize(&self) -> Vec<u8> {
+        canonical_serialize_map(self.iter())
+    }
+}
+
+impl<K, V> CanonicalSerialize for ImHashMap<K, V>
+where
+    K: CanonicalSerialize + Hash + Eq + Clone,
+    V: CanonicalSerialize + Clone,
+{
+    fn canonical_serialize(&self) -> Vec<u8> {
+        canonical_serialize_map(self.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canonical_serialize_layout() {
+        assert_eq!(0x0102_0304u32.canonical_serialize(), vec![1, 2, 3, 4]);
+        assert_eq!(
+            0x0102_0304_0506_0708u64.canonical_serialize(),
+            vec![1, 2, 3, 4, 5, 6, 7, 8]
+        );
+        let mut expected = vec![0; 15];
+        expected.push(5);
+        assert_eq!(5u128.canonical_serialize(), expected);
+        assert_eq!(5i128.canonical_serialize(), expected);
+        assert_eq!((-1i128).canonical_serialize(), vec![0xff; 16]);
+        assert_eq!(false.canonical_serialize(), vec![0]);
+        assert_eq!(true.canonical_serialize(), vec![1]);
+        assert_eq!("ab".to_owned().canonical_serialize(), b"ab".to_vec());
+        assert_eq!((true, 7u32).canonical_serialize(), vec![1, 0, 0, 0, 7]);
+
+        let mut map = HashMap::new();
+        map.insert(2u32, true);
+        map.insert(1u32, false);
+        assert_eq!(
+            map.canonical_serialize(),
+            vec![0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 0, 2, 1]
+        );
+    }
+
+    #[test]
+    fn test_canonical_serialize_map_order() {
+        let keys = (0..64u32)
+            .map(|i| i.wrapping_mul(0x9e37_79b9))
+            .collect::<Vec<_>>();
+
+        // Insert the same entries in opposite orders:
+        let mut map_a = HashMap::new();
+        let mut im_map_a = ImHashMap::new();
+        for key in &keys {
+            map_a.insert(*key, u128::from(*key));
+            im_map_a.insert(*key, u128::from(*key));
+        }
+        let mut map_b = HashMap::new();
+        let mut im_map_b = ImHashMap::new();
+        for key in keys.iter().rev() {
+            map_b.insert(*key, u128::from(*key));
+            im_map_b.insert(*key, u128::from(*key));
+        }
+
+        let ser_map = map_a.canonical_serialize();
+        assert_eq!(ser_map, map_b.canonical_serialize());
+        assert_eq!(ser_map, im_map_a.canonical_serialize());
+        assert_eq!(ser_map, im_map_b.canonical_serialize());
+
+        // A different value gives a different serialization:
+        map_b.insert(keys[0], 0x1234);
+        assert_ne!(ser_map, map_b.canonical_serialize());
+    }
+
+    /// Check that all the serializations are different.
+    fn assert_distinct(ser_values: &[Vec<u8>]) {
+        for (i, ser_value) in ser_values.iter().enumerate() {
+            assert!(ser_values.iter().take(i).all(|prev| prev != ser_value));
+        }
+    }
+
+    #[test]
+    fn test_canonical_serialize_distinct() {
+        let values = vec![
+            0i128,
+            1,
+            -1,
+            255,
+            256,
+            i128::max_value(),
+            i128::min_value(),
+            i128::from(u64::max_value()),
+        ];
+        let ser_values = values
+            .iter()
+            .map(CanonicalSerialize::canonical_serialize)
+            .collect::<Vec<_>>();
+        // Serialization is stable:
+        for (value, ser_value) in values.iter().zip(ser_values.iter()) {
+            assert_eq!(&value.canonical_serialize(), ser_value);
+        }
+        assert_distinct(&ser_values);
+
+        let u128_values = vec![
+            0u128,
+            1,
+            u128::max_value(),
+            u128::from(u64::max_value()) + 1,
+        ];
+        let ser_u128_values = u128_values
+            .iter()
+            .map(CanonicalSerialize::canonical_serialize)
+            .collect::<Vec<_>>();
+        assert_distinct(&ser_u128_values);
+
+        let opt_values = vec![None, Some(false), Some(true)];
+        let ser_opt_values = opt_values
+            .iter()
+            .map(CanonicalSerialize::canonical_serialize)
+            .collect::<Vec<_>>();
+        assert_distinct(&ser_opt_values);
+    }
+}
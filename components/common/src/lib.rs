@@ -24,6 +24,7 @@ pub mod async_adapter;
 // pub mod frame_codec;
 pub mod access_control;
 pub mod async_test_utils;
+pub mod bounded_cache;
 pub mod caller_info;
 pub mod canonical_serialize;
 pub mod conn;
@@ -33,6 +34,7 @@ pub mod futures_compat;
 pub mod multi_consumer;
 pub mod mutable_state;
 pub mod select_streams;
+pub mod ser_hex;
 pub mod state_service;
 pub mod transform_pool;
 // pub mod wait_spawner;
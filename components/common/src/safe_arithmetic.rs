@@ -181,4 +181,103 @@ mod tests {
         assert_eq!((254_u8).saturating_sub_signed(-1_i8), 255_u8);
         assert_eq!((254_u8).saturating_sub_signed(-3_i8), 255_u8);
     }
+
+    fn to_i8(x: i32) -> Option<i8> {
+        if x >= i32::from(i8::min_value()) && x <= i32::from(i8::max_value()) {
+            Some(x as i8)
+        } else {
+            None
+        }
+    }
+
+    fn to_u8(x: i32) -> Option<u8> {
+        if x >= 0 && x <= i32::from(u8::max_value()) {
+            Some(x as u8)
+        } else {
+            None
+        }
+    }
+
+    /// All the integer sizes share the same implementation. For 8 bit integers we can compare
+    /// against wider arithmetic for every possible input.
+    #[test]
+    fn test_safe_arithmetic_8bit_exhaustive() {
+        for s in i8::min_value()..=i8::max_value() {
+            for u in u8::min_value()..=u8::max_value() {
+                let (s_wide, u_wide) = (i32::from(s), i32::from(u));
+
+                assert_eq!(i32::from(s.safe_abs()), s_wide.abs());
+                assert_eq!(s.checked_add_unsigned(u), to_i8(s_wide + u_wide));
+                assert_eq!(s.checked_sub_unsigned(u), to_i8(s_wide - u_wide));
+                assert_eq!(
+                    s.saturating_add_unsigned(u),
+                    to_i8(s_wide + u_wide).unwrap_or(i8::max_value())
+                );
+                assert_eq!(
+                    s.saturating_sub_unsigned(u),
+                    to_i8(s_wide - u_wide).unwrap_or(i8::min_value())
+                );
+
+                assert_eq!(u.checked_add_signed(s), to_u8(u_wide + s_wide));
+                assert_eq!(u.checked_sub_signed(s), to_u8(u_wide - s_wide));
+                let saturate = |x: i32| to_u8(x).unwrap_or(if x < 0 { 0 } else { u8::max_value() });
+                assert_eq!(u.saturating_add_signed(s), saturate(u_wide + s_wide));
+                assert_eq!(u.saturating_sub_signed(s), saturate(u_wide - s_wide));
+            }
+        }
+    }
+
+    /// The balances and debts of the funder: Debts are bounded by MAX_FUNDER_DEBT, balances may
+    /// be as low as i128::min_value().
+    #[test]
+    fn test_safe_arithmetic_128bit_corner_cases() {
+        // Same as MAX_FUNDER_DEBT in the funder:
+        let max_debt: u128 = (1 << 127) - 1;
+        let i128_min = i128::min_value();
+        let i128_max = i128::max_value();
+        let u128_max = u128::max_value();
+
+        assert_eq!(i128_min.safe_abs(), max_debt + 1);
+        assert_eq!(i128_max.safe_abs(), max_debt);
+        assert_eq!((-1_i128).safe_abs(), 1_u128);
+
+        assert_eq!(0_i128.checked_add_unsigned(max_debt), Some(i128_max));
+        assert_eq!(1_i128.checked_add_unsigned(max_debt), None);
+        assert_eq!(i128_min.checked_add_unsigned(max_debt), Some(-1_i128));
+        assert_eq!(i128_min.checked_add_unsigned(u128_max), Some(i128_max));
+        assert_eq!((i128_min + 1).checked_add_unsigned(u128_max), None);
+        assert_eq!((-1_i128).checked_add_unsigned(u128_max), None);
+
+        assert_eq!(0_i128.checked_sub_unsigned(max_debt + 1), Some(i128_min));
+        assert_eq!((-1_i128).checked_sub_unsigned(max_debt + 1), None);
+        assert_eq!(i128_max.checked_sub_unsigned(u128_max), Some(i128_min));
+        assert_eq!((i128_max - 1).checked_sub_unsigned(u128_max), None);
+        assert_eq!(i128_min.checked_sub_unsigned(1), None);
+        assert_eq!(i128_min.checked_sub_unsigned(0), Some(i128_min));
+
+        assert_eq!(i128_min.saturating_add_unsigned(u128_max), i128_max);
+        assert_eq!(0_i128.saturating_add_unsigned(u128_max), i128_max);
+        assert_eq!(i128_min.saturating_add_unsigned(max_debt), -1_i128);
+        assert_eq!(i128_min.saturating_sub_unsigned(1), i128_min);
+        assert_eq!(i128_max.saturating_sub_unsigned(u128_max), i128_min);
+        assert_eq!(0_i128.saturating_sub_unsigned(max_debt), -i128_max);
+
+        assert_eq!(0_u128.checked_sub_signed(i128_min), Some(max_debt + 1));
+        assert_eq!(max_debt.checked_sub_signed(i128_min), Some(u128_max));
+        assert_eq!((max_debt + 1).checked_sub_signed(i128_min), None);
+        assert_eq!(max_debt.checked_sub_signed(i128_max), Some(0));
+        assert_eq!(0_u128.checked_sub_signed(1), None);
+
+        assert_eq!(u128_max.checked_add_signed(i128_min), Some(max_debt));
+        assert_eq!(max_debt.checked_add_signed(i128_max), Some(u128_max - 1));
+        assert_eq!(max_debt.checked_add_signed(i128_min), None);
+        assert_eq!(u128_max.checked_add_signed(1), None);
+
+        assert_eq!(0_u128.saturating_add_signed(i128_min), 0);
+        assert_eq!(max_debt.saturating_add_signed(i128_min), 0);
+        assert_eq!(u128_max.saturating_add_signed(i128_max), u128_max);
+        assert_eq!(u128_max.saturating_sub_signed(i128_min), u128_max);
+        assert_eq!(max_debt.saturating_sub_signed(i128_max), 0);
+        assert_eq!(max_debt.saturating_sub_signed(-1), max_debt + 1);
+    }
 }
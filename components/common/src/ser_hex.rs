@@ -0,0 +1,74 @@
+//! Serde adapters for byte arrays. When the serialization format is human readable (For
+//! example JSON), byte arrays are serialized as hex strings. Binary formats (For example
+//! bincode) are not affected: They serialize the arrays exactly as if no adapter was used.
+//!
+//! Only serialization is adapted. Human readable serializations are meant for debugging, and
+//! are never deserialized. `from_hex` is provided for data files that store bytes as hex strings
+//! explicitly.
+
+use serde::ser::{Serialize, Serializer};
+
+use crate::big_array::BigArray;
+
+/// Encode bytes as a lowercase hex string
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decode a hex string (Lowercase or uppercase) into bytes.
+/// Returns None if the string is not a valid hex encoding.
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Serialize an array of bytes (Of size up to 32)
+pub fn serialize_array<A, S>(array: &A, serializer: S) -> Result<S::Ok, S::Error>
+where
+    A: AsRef<[u8]> + Serialize,
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&to_hex(array.as_ref()))
+    } else {
+        array.serialize(serializer)
+    }
+}
+
+/// Serialize an array of 64 bytes. Used for arrays that are serialized using `BigArray`.
+pub fn serialize_big_array<S>(array: &[u8; 64], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    if serializer.is_human_readable() {
+        serializer.serialize_str(&to_hex(&array[..]))
+    } else {
+        BigArray::serialize(array, serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_hex() {
+        assert_eq!(to_hex(&[]), "");
+        assert_eq!(to_hex(&[0x00, 0x0f, 0xa5, 0xff]), "000fa5ff");
+    }
+
+    #[test]
+    fn test_from_hex() {
+        assert_eq!(from_hex(""), Some(Vec::new()));
+        assert_eq!(from_hex("000fa5ff"), Some(vec![0x00, 0x0f, 0xa5, 0xff]));
+        assert_eq!(from_hex("000FA5FF"), Some(vec![0x00, 0x0f, 0xa5, 0xff]));
+        assert_eq!(from_hex("0"), None);
+        assert_eq!(from_hex("0g"), None);
+        assert_eq!(from_hex("+f"), None);
+    }
+}
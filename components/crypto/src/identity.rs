@@ -13,7 +13,13 @@ pub const SIGNATURE_LEN: usize = 64;
 define_fixed_bytes!(PublicKey, PUBLIC_KEY_LEN);
 
 #[derive(Clone, Serialize, Deserialize, From)]
-pub struct Signature(#[serde(with = "BigArray")] [u8; SIGNATURE_LEN]);
+pub struct Signature(
+    #[serde(
+        serialize_with = "common::ser_hex::serialize_big_array",
+        deserialize_with = "BigArray::deserialize"
+    )]
+    [u8; SIGNATURE_LEN],
+);
 
 /// Check if one public key is "lower" than another.
 /// This is used to decide which side begins the token channel.
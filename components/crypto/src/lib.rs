@@ -18,6 +18,7 @@ pub mod hash;
 pub mod identity;
 pub mod invoice_id;
 pub mod nonce_window;
+pub mod passphrase;
 pub mod sym_encrypt;
 pub mod test_utils;
 pub mod uid;
@@ -0,0 +1,66 @@
+use ring::digest::SHA512;
+use ring::pbkdf2;
+use ring::rand::SecureRandom;
+
+use super::sym_encrypt::SymmetricKey;
+use super::CryptoError;
+
+pub const PASSPHRASE_SALT_LEN: usize = 32;
+
+define_fixed_bytes!(PassphraseSalt, PASSPHRASE_SALT_LEN);
+
+impl PassphraseSalt {
+    pub fn new<R: SecureRandom>(crypt_rng: &R) -> Result<PassphraseSalt, CryptoError> {
+        let mut salt = PassphraseSalt::default();
+
+        if crypt_rng.fill(&mut salt).is_ok() {
+            Ok(salt)
+        } else {
+            Err(CryptoError)
+        }
+    }
+}
+
+/// Derive a symmetric key from a passphrase, using PBKDF2-HMAC-SHA512.
+/// More iterations make guessing the passphrase slower. `iterations` must not be zero.
+pub fn derive_passphrase_key(
+    passphrase: &[u8],
+    salt: &PassphraseSalt,
+    iterations: u32,
+) -> Result<SymmetricKey, CryptoError> {
+    if iterations == 0 {
+        return Err(CryptoError);
+    }
+    let mut symmetric_key = SymmetricKey::default();
+    pbkdf2::derive(&SHA512, iterations, salt, passphrase, &mut symmetric_key);
+    Ok(symmetric_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::DummyRandom;
+
+    #[test]
+    fn test_derive_passphrase_key() {
+        let rng = DummyRandom::new(&[1u8]);
+        let salt = PassphraseSalt::new(&rng).unwrap();
+
+        let key1 = derive_passphrase_key(b"passphrase", &salt, 16).unwrap();
+        let key2 = derive_passphrase_key(b"passphrase", &salt, 16).unwrap();
+        assert_eq!(key1, key2);
+
+        // A different passphrase, salt or amount of iterations gives a different key:
+        let salt2 = PassphraseSalt::new(&rng).unwrap();
+        for &(passphrase, salt, iterations) in &[
+            (&b"passphrasf"[..], &salt, 16),
+            (&b"passphrase"[..], &salt2, 16),
+            (&b"passphrase"[..], &salt, 17),
+        ] {
+            let key = derive_passphrase_key(passphrase, salt, iterations).unwrap();
+            assert_ne!(key1, key);
+        }
+
+        assert!(derive_passphrase_key(b"passphrase", &salt, 0).is_err());
+    }
+}
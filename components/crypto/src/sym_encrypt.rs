@@ -103,6 +103,9 @@ impl Decryptor {
 
     /// Decrypt and authenticate a message.
     pub fn decrypt(&mut self, cipher_msg: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        if cipher_msg.len() < ENC_NONCE_LEN {
+            return Err(CryptoError);
+        }
         let enc_nonce = &cipher_msg[..ENC_NONCE_LEN];
         if enc_nonce != self.nonce_counter.as_ref() {
             // Nonce doesn't match!
@@ -159,5 +162,8 @@ mod tests {
         let decrypted_msg = decryptor.decrypt(&cipher_msg).unwrap();
 
         assert_eq!(plain_msg, &decrypted_msg[..]);
+
+        // A message too short to contain a nonce:
+        assert!(decryptor.decrypt(&[0u8; 4]).is_err());
     }
 }
@@ -3,6 +3,9 @@ use std::clone::Clone;
 use std::sync::Mutex;
 
 use crate::crypto_rand::CryptoRandom;
+use crate::identity::{
+    compare_public_key, generate_pkcs8_key_pair, Identity, SoftwareEd25519Identity,
+};
 use rand::{self, RngCore, StdRng};
 use ring::{error::Unspecified, rand::SecureRandom};
 
@@ -44,3 +47,70 @@ impl SecureRandom for DummyRandom {
 }
 
 impl CryptoRandom for DummyRandom {}
+
+/// A deterministic identity, generated from a seed.
+/// The same seed results in the same identity on every platform.
+pub fn fixture_software_identity(seed: u8) -> SoftwareEd25519Identity {
+    let rng = DummyRandom::new(&[seed]);
+    let pkcs8 = generate_pkcs8_key_pair(&rng);
+    SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap()
+}
+
+/// `n` deterministic identities, generated from the seeds `1..=n`.
+///
+/// The identities are sorted by `compare_public_key`. A token channel between two of the
+/// identities is always initially outgoing for the one that appears first.
+pub fn fixture_keypairs(n: u8) -> Vec<SoftwareEd25519Identity> {
+    let mut identities = (1..=n).map(fixture_software_identity).collect::<Vec<_>>();
+    identities.sort_by(|identity1, identity2| {
+        compare_public_key(&identity1.get_public_key(), &identity2.get_public_key())
+    });
+    identities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::identity::{PublicKey, PUBLIC_KEY_LEN};
+
+    const FIXTURE_PUBLIC_KEY1: [u8; PUBLIC_KEY_LEN] = [
+        0x49, 0x4c, 0xb9, 0x0a, 0x4e, 0x21, 0x6f, 0x3e, 0x24, 0x00, 0x7b, 0xce, 0x02, 0xe7, 0xf2,
+        0x54, 0xc1, 0x26, 0x53, 0x30, 0x47, 0x49, 0x04, 0xda, 0xe0, 0x4c, 0x8c, 0x9f, 0x01, 0x5c,
+        0xee, 0xa5,
+    ];
+    const FIXTURE_PUBLIC_KEY2: [u8; PUBLIC_KEY_LEN] = [
+        0xeb, 0xf5, 0xe9, 0x7b, 0x42, 0x16, 0xb1, 0x87, 0xd8, 0xf7, 0x7c, 0x82, 0x73, 0xda, 0xf7,
+        0xa4, 0x72, 0x9c, 0x23, 0x81, 0xac, 0x79, 0xae, 0x2f, 0x37, 0x69, 0xd9, 0x7c, 0x5e, 0xf0,
+        0x9d, 0x40,
+    ];
+    const FIXTURE_PUBLIC_KEY3: [u8; PUBLIC_KEY_LEN] = [
+        0x8c, 0x42, 0x3e, 0x97, 0x19, 0x92, 0xaf, 0x69, 0xbe, 0x4f, 0xae, 0x0a, 0xc6, 0xfc, 0x39,
+        0x73, 0xb3, 0x05, 0x52, 0xe1, 0x0f, 0x0a, 0x60, 0x8d, 0xe0, 0x45, 0xd7, 0xec, 0xc7, 0x44,
+        0x35, 0xdb,
+    ];
+
+    #[test]
+    fn test_fixture_identities_deterministic() {
+        assert_eq!(
+            fixture_software_identity(1).get_public_key(),
+            PublicKey::from(&FIXTURE_PUBLIC_KEY1)
+        );
+        assert_eq!(
+            fixture_software_identity(2).get_public_key(),
+            PublicKey::from(&FIXTURE_PUBLIC_KEY2)
+        );
+
+        let public_keys = fixture_keypairs(3)
+            .iter()
+            .map(|identity| identity.get_public_key())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            public_keys,
+            vec![
+                PublicKey::from(&FIXTURE_PUBLIC_KEY3),
+                PublicKey::from(&FIXTURE_PUBLIC_KEY2),
+                PublicKey::from(&FIXTURE_PUBLIC_KEY1),
+            ]
+        );
+    }
+}
@@ -1,3 +1,15 @@
+/// Findings of a database compaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactReport {
+    /// Size of the stored database before compaction, in bytes.
+    pub size_before: u64,
+    /// Size of the stored database after compaction, in bytes.
+    pub size_after: u64,
+    /// The stored database did not match the state held in memory.
+    /// This means that the stored database was corrupted (And was fixed by the compaction).
+    pub found_corruption: bool,
+}
+
 /// An atomic database. Allows to batch a list of mutations, and guarantees to apply them to the
 /// database in an atomic manner.
 pub trait AtomicDb {
@@ -7,4 +19,15 @@ pub trait AtomicDb {
 
     fn get_state(&self) -> &Self::State;
     fn mutate_db(&mut self, mutations: &[Self::Mutation]) -> Result<(), Self::Error>;
+
+    /// Rewrite the stored database from the current state, dropping any superseded data, and
+    /// verify the integrity of the rewritten copy before it replaces the stored database.
+    /// If verification fails, the stored database is left untouched.
+    fn compact_db(&mut self) -> Result<CompactReport, Self::Error>;
+
+    /// Was the error caused by storage that is temporarily unavailable for writing (For example:
+    /// a full disk)? In this case no mutation was applied, and a later attempt may succeed.
+    fn is_storage_unavailable(_error: &Self::Error) -> bool {
+        false
+    }
 }
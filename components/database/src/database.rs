@@ -1,9 +1,11 @@
 use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, SinkExt, StreamExt};
+use futures::{future, stream, SinkExt, Stream, StreamExt};
 use std::fmt::Debug;
 
-use crate::atomic_db::AtomicDb;
+use common::select_streams::{select_streams, BoxStream};
+
+use crate::atomic_db::{AtomicDb, CompactReport};
 
 #[derive(Debug)]
 pub enum DatabaseError<ADE> {
@@ -11,11 +13,21 @@ pub enum DatabaseError<ADE> {
     SpawnError,
 }
 
-// A request to apply mutations to the database
+/// A request to apply mutations to the database.
+/// The response is None if the storage is unavailable for writing. None of the mutations were
+/// applied in this case.
 #[derive(Debug)]
 pub struct DatabaseRequest<M> {
     pub mutations: Vec<M>,
-    pub response_sender: oneshot::Sender<()>,
+    pub response_sender: oneshot::Sender<Option<()>>,
+}
+
+/// A request to compact the database and verify its integrity.
+/// The response is None if compaction failed (The stored database is left untouched in this
+/// case).
+#[derive(Debug)]
+pub struct CompactRequest {
+    pub response_sender: oneshot::Sender<Option<CompactReport>>,
 }
 
 #[derive(Clone)]
@@ -27,6 +39,10 @@ pub struct DatabaseClient<M> {
 pub enum DatabaseClientError {
     SendError,
     ResponseCanceled,
+    CompactError,
+    /// The storage is unavailable for writing (For example: the disk is full).
+    /// No mutation was applied.
+    StorageUnavailable,
 }
 
 impl<M> DatabaseClient<M>
@@ -48,21 +64,82 @@ where
             .map_err(|_| DatabaseClientError::SendError)?;
 
         // Wait for ack from the service:
-        await!(request_done).map_err(|_| DatabaseClientError::ResponseCanceled)?;
+        await!(request_done)
+            .map_err(|_| DatabaseClientError::ResponseCanceled)?
+            .ok_or(DatabaseClientError::StorageUnavailable)
+    }
+}
+
+#[derive(Clone)]
+pub struct CompactClient {
+    request_sender: mpsc::Sender<CompactRequest>,
+}
+
+impl CompactClient {
+    pub fn new(request_sender: mpsc::Sender<CompactRequest>) -> Self {
+        CompactClient { request_sender }
+    }
+
+    /// Compact the database and verify its integrity.
+    pub async fn compact(&mut self) -> Result<CompactReport, DatabaseClientError> {
+        let (response_sender, request_done) = oneshot::channel();
+        let compact_request = CompactRequest { response_sender };
+        // Send the request:
+        await!(self.request_sender.send(compact_request))
+            .map_err(|_| DatabaseClientError::SendError)?;
+
+        // Wait for the compaction to complete:
+        await!(request_done)
+            .map_err(|_| DatabaseClientError::ResponseCanceled)?
+            .ok_or(DatabaseClientError::CompactError)
+    }
+}
+
+/// Periodically compact the database, every `compact_ticks` ticks of `timer_stream`.
+pub async fn compact_loop<TS>(
+    mut compact_client: CompactClient,
+    mut timer_stream: TS,
+    compact_ticks: usize,
+) -> Result<(), DatabaseClientError>
+where
+    TS: Stream + Unpin,
+{
+    let mut ticks_to_compact = compact_ticks;
+    while let Some(_) = await!(timer_stream.next()) {
+        ticks_to_compact = ticks_to_compact.saturating_sub(1);
+        if ticks_to_compact > 0 {
+            continue;
+        }
+        ticks_to_compact = compact_ticks;
 
-        Ok(())
+        match await!(compact_client.compact()) {
+            Ok(compact_report) => info!("compact_loop(): Compaction done: {:?}", compact_report),
+            Err(DatabaseClientError::CompactError) => {
+                // Compaction failure is already reported by the database loop.
+                // The stored database was left untouched, so we try again later.
+            }
+            Err(e) => return Err(e),
+        }
     }
+    Ok(())
+}
+
+enum DatabaseEvent<M> {
+    Request(DatabaseRequest<M>),
+    RequestsClosed,
+    CompactRequest(CompactRequest),
 }
 
 pub async fn database_loop<AD, S>(
     mut atomic_db: AD,
-    mut incoming_requests: mpsc::Receiver<DatabaseRequest<AD::Mutation>>,
+    incoming_requests: mpsc::Receiver<DatabaseRequest<AD::Mutation>>,
+    incoming_compact_requests: mpsc::Receiver<CompactRequest>,
     mut database_spawner: S,
 ) -> Result<AD, DatabaseError<AD::Error>>
 where
     AD: AtomicDb + Send + 'static,
     AD::Mutation: Debug + Send + 'static,
-    AD::Error: Send + 'static,
+    AD::Error: Debug + Send + 'static,
     S: Spawn,
 {
     // We use an independent spawner (`database_spawner`) to make sure our synchronous interaction
@@ -70,25 +147,82 @@ where
     // TODO: Maybe there will be a better way to do this in the future (Possibly a future version
     // of Tokio that has this feature)
 
-    while let Some(database_request) = await!(incoming_requests.next()) {
-        let DatabaseRequest {
-            mutations,
-            response_sender,
-        } = database_request;
-        let mutate_fut = future::lazy(move |_| {
-            atomic_db
-                .mutate_db(&mutations[..])
-                .map_err(DatabaseError::AtomicDbError)?;
-            Ok(atomic_db)
-        });
-        let handle = database_spawner
-            .spawn_with_handle(mutate_fut)
-            .map_err(|_| DatabaseError::SpawnError)?;
-
-        atomic_db = await!(handle)?;
-
-        // Notify client that the database mutation request was processed:
-        let _ = response_sender.send(());
+    let incoming_requests = incoming_requests
+        .map(DatabaseEvent::Request)
+        .chain(stream::once(future::ready(DatabaseEvent::RequestsClosed)));
+
+    let incoming_compact_requests = incoming_compact_requests.map(DatabaseEvent::CompactRequest);
+
+    let mut incoming_events = select_streams![incoming_requests, incoming_compact_requests];
+
+    // Note that requests are handled one by one. Therefore a compaction can never run
+    // concurrently with a mutation, and no mutation can be lost during compaction.
+    while let Some(event) = await!(incoming_events.next()) {
+        match event {
+            DatabaseEvent::Request(database_request) => {
+                let DatabaseRequest {
+                    mutations,
+                    response_sender,
+                } = database_request;
+                let mutate_fut = future::lazy(move |_| {
+                    let res = atomic_db.mutate_db(&mutations[..]);
+                    (atomic_db, res)
+                });
+                let handle = database_spawner
+                    .spawn_with_handle(mutate_fut)
+                    .map_err(|_| DatabaseError::SpawnError)?;
+
+                let (new_atomic_db, res) = await!(handle);
+                atomic_db = new_atomic_db;
+
+                let opt_done = match res {
+                    Ok(()) => Some(()),
+                    Err(e) => {
+                        if !AD::is_storage_unavailable(&e) {
+                            return Err(DatabaseError::AtomicDbError(e));
+                        }
+                        // Nothing was applied. The client may try again later:
+                        warn!("database_loop(): Storage is unavailable: {:?}", e);
+                        None
+                    }
+                };
+
+                // Notify client that the database mutation request was processed:
+                let _ = response_sender.send(opt_done);
+            }
+            DatabaseEvent::RequestsClosed => break,
+            DatabaseEvent::CompactRequest(compact_request) => {
+                let compact_fut = future::lazy(move |_| {
+                    let res = atomic_db.compact_db();
+                    (atomic_db, res)
+                });
+                let handle = database_spawner
+                    .spawn_with_handle(compact_fut)
+                    .map_err(|_| DatabaseError::SpawnError)?;
+
+                let (new_atomic_db, res) = await!(handle);
+                atomic_db = new_atomic_db;
+
+                let opt_compact_report = match res {
+                    Ok(compact_report) => {
+                        if compact_report.found_corruption {
+                            error!(
+                                "database_loop(): Stored database was corrupted: {:?}",
+                                compact_report
+                            );
+                        }
+                        Some(compact_report)
+                    }
+                    Err(e) => {
+                        // A failed compaction leaves the stored database untouched,
+                        // so we can keep going:
+                        error!("database_loop(): Compaction failed: {:?}", e);
+                        None
+                    }
+                };
+                let _ = compact_request.response_sender.send(opt_compact_report);
+            }
+        }
     }
     // Return the current state
     Ok(atomic_db)
@@ -99,6 +233,8 @@ mod tests {
     use super::*;
     use futures::executor::ThreadPool;
     use futures::task::{Spawn, SpawnExt};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
 
     /// A dummy state (used for testing)
     #[derive(Debug)]
@@ -119,16 +255,24 @@ mod tests {
         Dec,
     }
 
+    #[derive(Debug)]
+    enum DummyAtomicDbError {
+        StorageUnavailable,
+    }
+
     /// A dummy AtomicDb (used for testing)
     #[derive(Debug)]
     struct DummyAtomicDb {
         pub dummy_state: DummyState,
+        /// Writes fail while set, as if the disk was full
+        pub storage_full: Arc<AtomicBool>,
     }
 
     impl DummyAtomicDb {
         pub fn new() -> Self {
             DummyAtomicDb {
                 dummy_state: DummyState::new(),
+                storage_full: Arc::new(AtomicBool::new(false)),
             }
         }
     }
@@ -136,13 +280,16 @@ mod tests {
     impl AtomicDb for DummyAtomicDb {
         type State = DummyState;
         type Mutation = DummyMutation;
-        type Error = ();
+        type Error = DummyAtomicDbError;
 
         fn get_state(&self) -> &Self::State {
             &self.dummy_state
         }
 
         fn mutate_db(&mut self, mutations: &[Self::Mutation]) -> Result<(), Self::Error> {
+            if self.storage_full.load(Ordering::SeqCst) {
+                return Err(DummyAtomicDbError::StorageUnavailable);
+            }
             for mutation in mutations {
                 match mutation {
                     DummyMutation::Inc => {
@@ -155,6 +302,20 @@ mod tests {
             }
             Ok(())
         }
+
+        fn compact_db(&mut self) -> Result<CompactReport, Self::Error> {
+            Ok(CompactReport {
+                size_before: 0,
+                size_after: 0,
+                found_corruption: false,
+            })
+        }
+
+        fn is_storage_unavailable(error: &Self::Error) -> bool {
+            match error {
+                DummyAtomicDbError::StorageUnavailable => true,
+            }
+        }
     }
 
     async fn task_database_loop_basic<S>(mut spawner: S)
@@ -163,7 +324,13 @@ mod tests {
     {
         let atomic_db = DummyAtomicDb::new();
         let (request_sender, incoming_requests) = mpsc::channel(0);
-        let loop_fut = database_loop(atomic_db, incoming_requests, spawner.clone());
+        let (compact_sender, incoming_compact_requests) = mpsc::channel(0);
+        let loop_fut = database_loop(
+            atomic_db,
+            incoming_requests,
+            incoming_compact_requests,
+            spawner.clone(),
+        );
         let loop_res_fut = spawner.spawn_with_handle(loop_fut).unwrap();
 
         let mut db_client = DatabaseClient::new(request_sender);
@@ -181,6 +348,9 @@ mod tests {
         ]))
         .unwrap();
 
+        let mut compact_client = CompactClient::new(compact_sender);
+        await!(compact_client.compact()).unwrap();
+
         // Dropping the only client should close the loop:
         drop(db_client);
 
@@ -193,4 +363,49 @@ mod tests {
         let mut thread_pool = ThreadPool::new().unwrap();
         thread_pool.run(task_database_loop_basic(thread_pool.clone()));
     }
+
+    async fn task_database_loop_storage_unavailable<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let atomic_db = DummyAtomicDb::new();
+        let storage_full = atomic_db.storage_full.clone();
+        let (request_sender, incoming_requests) = mpsc::channel(0);
+        let (_compact_sender, incoming_compact_requests) = mpsc::channel(0);
+        let loop_fut = database_loop(
+            atomic_db,
+            incoming_requests,
+            incoming_compact_requests,
+            spawner.clone(),
+        );
+        let loop_res_fut = spawner.spawn_with_handle(loop_fut).unwrap();
+
+        let mut db_client = DatabaseClient::new(request_sender);
+        await!(db_client.mutate(vec![DummyMutation::Inc])).unwrap();
+
+        // The disk is full. Mutations are rejected, but the database keeps running:
+        storage_full.store(true, Ordering::SeqCst);
+        for _ in 0..3 {
+            match await!(db_client.mutate(vec![DummyMutation::Inc])) {
+                Err(DatabaseClientError::StorageUnavailable) => {}
+                _ => unreachable!(),
+            }
+        }
+
+        // Space was freed:
+        storage_full.store(false, Ordering::SeqCst);
+        await!(db_client.mutate(vec![DummyMutation::Inc])).unwrap();
+
+        drop(db_client);
+
+        // Rejected mutations were not applied:
+        let atomic_db = await!(loop_res_fut).unwrap();
+        assert_eq!(atomic_db.dummy_state.x, 2);
+    }
+
+    #[test]
+    fn test_database_loop_storage_unavailable() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_database_loop_storage_unavailable(thread_pool.clone()));
+    }
 }
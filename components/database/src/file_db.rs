@@ -1,9 +1,9 @@
 use std::io;
 use std::io::prelude::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use std::fmt::Debug;
-use std::fs::File;
+use std::fs::{self, File};
 
 use serde::de::DeserializeOwned;
 use serde::Serialize;
@@ -12,7 +12,8 @@ use serde::Serialize;
 use atomicwrites;
 use bincode;
 
-use crate::atomic_db::AtomicDb;
+use crate::atomic_db::{AtomicDb, CompactReport};
+use common::int_convert::usize_to_u64;
 use common::mutable_state::MutableState;
 
 #[derive(Debug)]
@@ -24,6 +25,46 @@ pub enum FileDbError<ME> {
     SerializeError(bincode::Error),
     MutateError(ME),
     FileAlreadyExists,
+    /// The compacted copy of the database did not pass verification.
+    /// The stored database was left untouched.
+    VerifyError,
+    RenameError(io::Error),
+}
+
+/// Raw OS error codes of write failures caused by the storage, and not by the database:
+/// No space left on device (ENOSPC) and Read-only file system (EROFS).
+#[cfg(unix)]
+const STORAGE_UNAVAILABLE_OS_ERRORS: &[i32] = &[28, 30];
+
+/// Raw OS error codes of write failures caused by the storage, and not by the database:
+/// ERROR_WRITE_PROTECT, ERROR_HANDLE_DISK_FULL and ERROR_DISK_FULL.
+#[cfg(windows)]
+const STORAGE_UNAVAILABLE_OS_ERRORS: &[i32] = &[19, 39, 112];
+
+#[cfg(not(any(unix, windows)))]
+const STORAGE_UNAVAILABLE_OS_ERRORS: &[i32] = &[];
+
+impl<ME> FileDbError<ME> {
+    /// Did writing fail because the storage is full or read only?
+    pub fn is_storage_unavailable(&self) -> bool {
+        let io_error = match self {
+            FileDbError::WriteError(atomicwrites::Error::Internal(io_error))
+            | FileDbError::WriteError(atomicwrites::Error::User(io_error)) => io_error,
+            _ => return false,
+        };
+        io_error
+            .raw_os_error()
+            .map(|code| STORAGE_UNAVAILABLE_OS_ERRORS.contains(&code))
+            .unwrap_or(false)
+    }
+}
+
+/// Read a whole file into memory
+fn read_file(path: &Path) -> Result<Vec<u8>, io::Error> {
+    let mut f = File::open(path)?;
+    let mut buff = Vec::new();
+    f.read_to_end(&mut buff)?;
+    Ok(buff)
 }
 
 pub struct FileDb<S> {
@@ -67,17 +108,95 @@ where
     /// Load an existing database from file
     /// Returns an error if database file does not exist
     pub fn load(path_buf: PathBuf) -> Result<Self, FileDbError<S::MutateError>> {
+        Self::load_with(path_buf, |serialized| bincode::deserialize(serialized))
+    }
+
+    /// Load an existing database from file, using a custom deserialization function.
+    /// Returns an error if database file does not exist
+    pub fn load_with<F>(
+        path_buf: PathBuf,
+        deserialize: F,
+    ) -> Result<Self, FileDbError<S::MutateError>>
+    where
+        F: FnOnce(&[u8]) -> Result<S, bincode::Error>,
+    {
         let mut f = File::open(&path_buf).map_err(FileDbError::OpenError)?;
         // read the whole file
         let mut serialized_buff = Vec::new();
         f.read_to_end(&mut serialized_buff)
             .map_err(FileDbError::ReadError)?;
 
-        let state: S =
-            bincode::deserialize(&serialized_buff).map_err(FileDbError::DeserializeError)?;
+        let state = deserialize(&serialized_buff).map_err(FileDbError::DeserializeError)?;
 
         Ok(FileDb { path_buf, state })
     }
+
+    /// Path of the compacted copy of the database, before it replaces the stored database.
+    fn candidate_path(&self) -> PathBuf {
+        let mut file_name = self
+            .path_buf
+            .file_name()
+            .map(|file_name| file_name.to_os_string())
+            .unwrap_or_default();
+        file_name.push(".compact");
+        self.path_buf.with_file_name(file_name)
+    }
+
+    /// Compact the database. `before_verify` is invoked with the path of the compacted copy
+    /// after it was written, and before it is verified.
+    fn inner_compact_db<F>(
+        &mut self,
+        before_verify: F,
+    ) -> Result<CompactReport, FileDbError<S::MutateError>>
+    where
+        F: FnOnce(&Path),
+    {
+        let serialized_buff =
+            bincode::serialize(&self.state).map_err(FileDbError::SerializeError)?;
+
+        // Check the currently stored database against the state we hold in memory.
+        // An unreadable stored database is also considered corrupted:
+        let opt_stored_buff = read_file(&self.path_buf).ok();
+        let found_corruption = opt_stored_buff.as_ref() != Some(&serialized_buff);
+
+        // Write a compacted copy of the database:
+        let candidate_path = self.candidate_path();
+        let af = atomicwrites::AtomicFile::new(&candidate_path, atomicwrites::AllowOverwrite);
+        af.write(|fw| fw.write_all(&serialized_buff))
+            .map_err(FileDbError::WriteError)?;
+
+        before_verify(&candidate_path);
+
+        // Verify the compacted copy: It should be loadable, and identical to our current state:
+        let is_valid = match read_file(&candidate_path) {
+            Ok(candidate_buff) => {
+                candidate_buff == serialized_buff
+                    && bincode::deserialize::<S>(&candidate_buff)
+                        .ok()
+                        .and_then(|candidate_state| bincode::serialize(&candidate_state).ok())
+                        .map(|reserialized_buff| reserialized_buff == serialized_buff)
+                        .unwrap_or(false)
+            }
+            Err(_) => false,
+        };
+
+        if !is_valid {
+            // Leave the stored database untouched:
+            let _ = fs::remove_file(&candidate_path);
+            return Err(FileDbError::VerifyError);
+        }
+
+        // Replace the stored database with the compacted copy (Atomically):
+        fs::rename(&candidate_path, &self.path_buf).map_err(FileDbError::RenameError)?;
+
+        Ok(CompactReport {
+            size_before: opt_stored_buff
+                .map(|stored_buff| usize_to_u64(stored_buff.len()).unwrap())
+                .unwrap_or(0),
+            size_after: usize_to_u64(serialized_buff.len()).unwrap(),
+            found_corruption,
+        })
+    }
 }
 
 impl<S> AtomicDb for FileDb<S>
@@ -96,32 +215,48 @@ where
     }
 
     /// Apply a set of mutations atomically the database, and save it.
+    /// The state is only updated if it was saved successfully.
     fn mutate_db(&mut self, mutations: &[Self::Mutation]) -> Result<(), Self::Error> {
-        // Apply all mutations to state:
+        // Apply all mutations to a copy of the state:
+        let mut new_state = self.state.clone();
         for mutation in mutations.iter() {
-            self.state
+            new_state
                 .mutate(mutation)
                 .map_err(FileDbError::MutateError)?;
         }
 
         // Serialize the state:
         let serialized_buff =
-            bincode::serialize(&self.state).map_err(FileDbError::SerializeError)?;
+            bincode::serialize(&new_state).map_err(FileDbError::SerializeError)?;
 
         // Save the new state to file, atomically:
         let af = atomicwrites::AtomicFile::new(&self.path_buf, atomicwrites::AllowOverwrite);
         af.write(|fw| fw.write_all(&serialized_buff))
             .map_err(FileDbError::WriteError)?;
 
+        self.state = new_state;
         Ok(())
     }
+
+    fn compact_db(&mut self) -> Result<CompactReport, Self::Error> {
+        self.inner_compact_db(|_| {})
+    }
+
+    fn is_storage_unavailable(error: &Self::Error) -> bool {
+        error.is_storage_unavailable()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use futures::channel::mpsc;
+    use futures::executor::ThreadPool;
+    use futures::task::{Spawn, SpawnExt};
     use tempfile::tempdir;
 
+    use crate::database::{database_loop, CompactClient, DatabaseClient};
+
     /// A dummy state (used for testing)
     #[derive(Debug, Serialize, Deserialize, Clone)]
     struct DummyState {
@@ -203,4 +338,170 @@ mod tests {
         // Remove temporary directory:
         dir.close().unwrap();
     }
+
+    #[test]
+    fn test_file_db_compact() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("database_file");
+
+        let initial_state = DummyState::new(0);
+        let mut file_db = FileDb::<DummyState>::create(file_path.clone(), initial_state).unwrap();
+        file_db
+            .mutate_db(&[DummyMutation::Inc, DummyMutation::Inc])
+            .unwrap();
+
+        let compact_report = file_db.compact_db().unwrap();
+        assert!(!compact_report.found_corruption);
+        assert_eq!(compact_report.size_before, compact_report.size_after);
+        assert!(!file_db.candidate_path().exists());
+
+        // Corrupt the stored database:
+        fs::write(&file_path, b"corrupted").unwrap();
+        let compact_report = file_db.compact_db().unwrap();
+        assert!(compact_report.found_corruption);
+
+        // Compaction should have fixed the stored database:
+        let file_db = FileDb::<DummyState>::load(file_path.clone()).unwrap();
+        assert_eq!(file_db.get_state().x, 2);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_file_db_compact_corrupted_candidate() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("database_file");
+
+        let initial_state = DummyState::new(0);
+        let mut file_db = FileDb::<DummyState>::create(file_path.clone(), initial_state).unwrap();
+        file_db
+            .mutate_db(&[DummyMutation::Inc, DummyMutation::Inc])
+            .unwrap();
+        let stored_buff = fs::read(&file_path).unwrap();
+
+        // The compacted copy is corrupted before it is verified:
+        let res = file_db.inner_compact_db(|candidate_path| {
+            fs::write(candidate_path, b"corrupted").unwrap();
+        });
+        match res {
+            Err(FileDbError::VerifyError) => {}
+            _ => unreachable!(),
+        }
+
+        // The stored database should be left untouched:
+        assert_eq!(fs::read(&file_path).unwrap(), stored_buff);
+        assert!(!file_db.candidate_path().exists());
+
+        // We can still use the database:
+        file_db.mutate_db(&[DummyMutation::Inc]).unwrap();
+        let file_db = FileDb::<DummyState>::load(file_path.clone()).unwrap();
+        assert_eq!(file_db.get_state().x, 3);
+
+        dir.close().unwrap();
+    }
+
+    #[test]
+    fn test_file_db_failed_write() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("database_file");
+
+        let initial_state = DummyState::new(0);
+        let mut file_db = FileDb::<DummyState>::create(file_path.clone(), initial_state).unwrap();
+        file_db.mutate_db(&[DummyMutation::Inc]).unwrap();
+
+        // Writing fails, because the database directory is gone:
+        dir.close().unwrap();
+        match file_db.mutate_db(&[DummyMutation::Inc]) {
+            Err(FileDbError::WriteError(_)) => {}
+            _ => unreachable!(),
+        }
+        // The state is only updated after it was saved:
+        assert_eq!(file_db.get_state().x, 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_db_error_storage_unavailable() {
+        let io_error = |code| atomicwrites::Error::Internal(io::Error::from_raw_os_error(code));
+
+        // No space left on device, Read-only file system:
+        for &code in &[28, 30] {
+            let error = FileDbError::<DummyMutateError>::WriteError(io_error(code));
+            assert!(error.is_storage_unavailable());
+        }
+        // Permission denied:
+        let error = FileDbError::<DummyMutateError>::WriteError(io_error(13));
+        assert!(!error.is_storage_unavailable());
+        let error = FileDbError::<DummyMutateError>::VerifyError;
+        assert!(!error.is_storage_unavailable());
+    }
+
+    async fn task_file_db_compact_concurrent_mutations<S>(mut spawner: S, file_path: PathBuf)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let initial_state = DummyState::new(0);
+        let file_db = FileDb::<DummyState>::create(file_path.clone(), initial_state).unwrap();
+
+        let (request_sender, incoming_requests) = mpsc::channel(0);
+        let (compact_sender, incoming_compact_requests) = mpsc::channel(0);
+        let loop_fut = database_loop(
+            file_db,
+            incoming_requests,
+            incoming_compact_requests,
+            spawner.clone(),
+        );
+        let loop_handle = spawner.spawn_with_handle(loop_fut).unwrap();
+
+        // A few clients mutating the database concurrently:
+        let db_client = DatabaseClient::new(request_sender);
+        let mut handles = Vec::new();
+        for _ in 0..4 {
+            let mut c_db_client = db_client.clone();
+            let handle = spawner
+                .spawn_with_handle(
+                    async move {
+                        for _ in 0..16 {
+                            await!(c_db_client.mutate(vec![DummyMutation::Inc])).unwrap();
+                        }
+                    },
+                )
+                .unwrap();
+            handles.push(handle);
+        }
+
+        // Compact while mutations are being applied:
+        let mut compact_client = CompactClient::new(compact_sender);
+        for _ in 0..8 {
+            let compact_report = await!(compact_client.compact()).unwrap();
+            assert!(!compact_report.found_corruption);
+        }
+
+        for handle in handles {
+            await!(handle);
+        }
+        drop(db_client);
+
+        // All database clients were dropped, so the loop should close:
+        let file_db = await!(loop_handle).unwrap();
+        assert_eq!(file_db.get_state().x, 4 * 16);
+
+        // No mutation was lost:
+        let file_db = FileDb::<DummyState>::load(file_path).unwrap();
+        assert_eq!(file_db.get_state().x, 4 * 16);
+    }
+
+    #[test]
+    fn test_file_db_compact_concurrent_mutations() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("database_file");
+
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_file_db_compact_concurrent_mutations(
+            thread_pool.clone(),
+            file_path,
+        ));
+
+        dir.close().unwrap();
+    }
 }
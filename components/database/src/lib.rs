@@ -16,9 +16,18 @@
 #[macro_use]
 extern crate serde_derive;
 
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+extern crate common;
+
 mod atomic_db;
 mod database;
 pub mod file_db;
 
-pub use self::atomic_db::AtomicDb;
-pub use self::database::{database_loop, DatabaseClient, DatabaseClientError, DatabaseRequest};
+pub use self::atomic_db::{AtomicDb, CompactReport};
+pub use self::database::{
+    compact_loop, database_loop, CompactClient, CompactRequest, DatabaseClient, DatabaseClientError,
+    DatabaseRequest,
+};
@@ -0,0 +1,196 @@
+#ifndef OFFST_H
+#define OFFST_H
+
+/* Generated by cbindgen from components/ffi. Do not edit by hand. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * The result of every function of the C interface.
+ *
+ * The numeric value of a result is part of the stable interface:
+ * Existing values never change, and a value is never reused for a different meaning.
+ * New results may only be added with new values.
+ */
+typedef enum {
+  /**
+   * The operation completed successfully.
+   */
+  OFFST_RESULT_OK = 0,
+  /**
+   * A pointer argument was null.
+   */
+  OFFST_RESULT_NULL_POINTER = 1,
+  /**
+   * A handle argument is not a live handle of the expected type.
+   * (For example: A handle that was already freed)
+   */
+  OFFST_RESULT_INVALID_HANDLE = 2,
+  /**
+   * An argument could not be parsed.
+   */
+  OFFST_RESULT_INVALID_ARGUMENT = 3,
+  /**
+   * The given identity could not be loaded.
+   */
+  OFFST_RESULT_INVALID_IDENTITY = 4,
+  /**
+   * The internal runtime could not be started.
+   */
+  OFFST_RESULT_RUNTIME_ERROR = 5,
+  /**
+   * Connecting to the node failed.
+   */
+  OFFST_RESULT_CONNECT_ERROR = 6,
+  /**
+   * The client is not connected to a node.
+   */
+  OFFST_RESULT_NOT_CONNECTED = 7,
+  /**
+   * The client is already connected to a node.
+   */
+  OFFST_RESULT_ALREADY_CONNECTED = 8,
+  /**
+   * The node does not permit this operation for this app.
+   */
+  OFFST_RESULT_PERMISSION_DENIED = 9,
+  /**
+   * The connection to the node was lost.
+   */
+  OFFST_RESULT_DISCONNECTED = 10,
+  /**
+   * No event is waiting to be polled.
+   */
+  OFFST_RESULT_NO_EVENT = 11,
+  /**
+   * The operation is still in progress. Poll again later.
+   */
+  OFFST_RESULT_PENDING = 12,
+  /**
+   * No suitable route to the destination was found.
+   */
+  OFFST_RESULT_NO_ROUTE = 13,
+  /**
+   * The payment failed. No credits were transferred.
+   */
+  OFFST_RESULT_PAYMENT_FAILED = 14,
+  /**
+   * The payment was sent, but no response was received.
+   */
+  OFFST_RESULT_PAYMENT_NO_RESPONSE = 15,
+} OffstResult;
+
+/**
+ * A buffer of bytes owned by the library.
+ * Every buffer handed out by the library must be freed using `offst_buffer_free`.
+ */
+typedef struct OffstBuffer OffstBuffer;
+
+/**
+ * A client context, used by an app to communicate with a node.
+ */
+typedef struct OffstClient OffstClient;
+
+/**
+ * A payment in progress.
+ */
+typedef struct OffstPayment OffstPayment;
+
+/**
+ * Free a buffer handed out by the library.
+ */
+OffstResult offst_buffer_free(OffstBuffer *buffer);
+
+/**
+ * Obtain the contents of a buffer.
+ * The returned pointer is valid until the buffer is freed.
+ */
+OffstResult offst_buffer_get(OffstBuffer *buffer, const uint8_t **out_data, size_t *out_len);
+
+/**
+ * Obtain the balances against all the friends of the node.
+ * The returned buffer contains one entry for every friend with a consistent channel:
+ * friend public key (32 bytes) || balance (16 bytes, big endian, two's complement)
+ * The returned buffer must be freed using `offst_buffer_free`.
+ */
+OffstResult offst_client_balances(OffstClient *client, OffstBuffer **out_balances);
+
+/**
+ * Connect to the node. Blocks until the connection is established or fails.
+ * Report events are queued from the moment of connection. See `offst_client_poll_event`.
+ */
+OffstResult offst_client_connect(OffstClient *client);
+
+/**
+ * Free a client context. Closes the connection to the node, if any.
+ * Payments that are still in progress keep their handles, and must be freed separately.
+ */
+OffstResult offst_client_free(OffstClient *client);
+
+/**
+ * List the friends of the node.
+ * The returned buffer contains the 32 bytes public keys of all the friends, one after the other.
+ * The returned buffer must be freed using `offst_buffer_free`.
+ */
+OffstResult offst_client_list_friends(OffstClient *client, OffstBuffer **out_friends);
+
+/**
+ * Obtain the public key of the app (32 bytes).
+ * The returned buffer must be freed using `offst_buffer_free`.
+ */
+OffstResult offst_client_local_public_key(OffstClient *client, OffstBuffer **out_public_key);
+
+/**
+ * Create a new client context.
+ *
+ * `identity_data` is the pkcs8 encoded private key of the app.
+ * `node_public_key` points to the 32 bytes public key of the node.
+ * `node_address` is a nul terminated address string, for example: "node.example.com:1337".
+ *
+ * No connection is made until `offst_client_connect` is called.
+ * The client must be freed using `offst_client_free`.
+ */
+OffstResult offst_client_new(const uint8_t *identity_data,
+                             size_t identity_len,
+                             const uint8_t *node_public_key,
+                             const char *node_address,
+                             OffstClient **out_client);
+
+/**
+ * Poll the next report event, without blocking.
+ * An event is a capnp (packed) serialized `NodeReportMutation`.
+ * Returns `OFFST_RESULT_NO_EVENT` if no event is waiting.
+ * The returned buffer must be freed using `offst_buffer_free`.
+ */
+OffstResult offst_client_poll_event(OffstClient *client, OffstBuffer **out_event);
+
+/**
+ * Start a payment to `destination` (32 bytes public key) for the invoice `invoice_id`
+ * (32 bytes). `dest_payment` points to the amount to pay (16 bytes, big endian).
+ *
+ * Returns immediately. The completion of the payment is obtained using `offst_payment_poll`.
+ * The payment must be freed using `offst_payment_free`.
+ */
+OffstResult offst_client_send_payment(OffstClient *client,
+                                      const uint8_t *destination,
+                                      const uint8_t *invoice_id,
+                                      const uint8_t *dest_payment,
+                                      OffstPayment **out_payment);
+
+/**
+ * Free a payment. Freeing a payment that is still in progress does not cancel it.
+ */
+OffstResult offst_payment_free(OffstPayment *payment);
+
+/**
+ * Check whether a payment has completed, without blocking.
+ * Returns `OFFST_RESULT_PENDING` while the payment is in progress.
+ * On success, `out_receipt` is set to the receipt of the payment:
+ * response_hash (32 bytes) || invoice_id (32 bytes) || dest_payment (16 bytes, big endian)
+ * || signature (64 bytes)
+ * The returned buffer must be freed using `offst_buffer_free`.
+ */
+OffstResult offst_payment_poll(OffstPayment *payment, OffstBuffer **out_receipt);
+
+#endif /* OFFST_H */
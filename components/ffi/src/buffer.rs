@@ -0,0 +1,43 @@
+use crate::handle::{get_mut, register, release};
+use crate::result::OffstResult;
+
+/// A buffer of bytes owned by the library.
+/// Every buffer handed out by the library must be freed using `offst_buffer_free`.
+pub struct OffstBuffer {
+    data: Vec<u8>,
+}
+
+/// Hand out `data` as a new buffer through `out_buffer`.
+pub unsafe fn write_buffer(data: Vec<u8>, out_buffer: *mut *mut OffstBuffer) -> OffstResult {
+    if out_buffer.is_null() {
+        return OffstResult::NullPointer;
+    }
+    *out_buffer = register(OffstBuffer { data });
+    OffstResult::Ok
+}
+
+/// Obtain the contents of a buffer.
+/// The returned pointer is valid until the buffer is freed.
+#[no_mangle]
+pub unsafe extern "C" fn offst_buffer_get(
+    buffer: *mut OffstBuffer,
+    out_data: *mut *const u8,
+    out_len: *mut usize,
+) -> OffstResult {
+    if out_data.is_null() || out_len.is_null() {
+        return OffstResult::NullPointer;
+    }
+    let buffer = try_ffi!(get_mut(buffer));
+    *out_data = buffer.data.as_ptr();
+    *out_len = buffer.data.len();
+    OffstResult::Ok
+}
+
+/// Free a buffer handed out by the library.
+#[no_mangle]
+pub unsafe extern "C" fn offst_buffer_free(buffer: *mut OffstBuffer) -> OffstResult {
+    match release(buffer) {
+        Ok(_) => OffstResult::Ok,
+        Err(res) => res,
+    }
+}
@@ -0,0 +1,235 @@
+use std::os::raw::c_char;
+
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::SpawnExt;
+use futures::{SinkExt, StreamExt};
+
+use app::report::NodeReportMutation;
+use app::{connect, NodeConnection, NodeStateMirror, PublicKey};
+use crypto::identity::{Identity, SoftwareEd25519Identity};
+use identity::{create_identity, IdentityClient};
+use proto::net::messages::NetAddress;
+use proto::report::serialize::serialize_node_report_mutation;
+
+use crate::buffer::{write_buffer, OffstBuffer};
+use crate::convert::{read_net_address, read_public_key, read_slice};
+use crate::handle::{get_mut, register, release};
+use crate::result::OffstResult;
+
+/// Maximum amount of report events that may wait to be polled.
+/// Further reports are not read from the node until events are polled.
+const EVENTS_QUEUE_LEN: usize = 0x100;
+
+/// A client context, used by an app to communicate with a node.
+pub struct OffstClient {
+    pub(crate) thread_pool: ThreadPool,
+    identity_client: IdentityClient,
+    pub(crate) local_public_key: PublicKey,
+    node_public_key: PublicKey,
+    node_address: NetAddress,
+    pub(crate) opt_conn: Option<NodeConnection>,
+    /// Serialized report mutations, waiting to be polled
+    opt_events: Option<mpsc::Receiver<Vec<u8>>>,
+}
+
+impl OffstClient {
+    /// Obtain a mirror of the current state of the node.
+    fn mirror(&mut self) -> Result<NodeStateMirror, OffstResult> {
+        let thread_pool = &mut self.thread_pool;
+        let conn = self.opt_conn.as_mut().ok_or(OffstResult::NotConnected)?;
+        thread_pool
+            .run(conn.report().mirror())
+            .map_err(|_| OffstResult::Disconnected)
+    }
+}
+
+/// Serialize incoming report mutations and queue them as events.
+async fn forward_events(
+    mut incoming_mutations: mpsc::Receiver<Vec<NodeReportMutation>>,
+    mut events_sender: mpsc::Sender<Vec<u8>>,
+) {
+    while let Some(mutations) = await!(incoming_mutations.next()) {
+        for mutation in &mutations {
+            let event = serialize_node_report_mutation(mutation);
+            if await!(events_sender.send(event)).is_err() {
+                // The client was freed:
+                return;
+            }
+        }
+    }
+}
+
+/// Create a new client context.
+///
+/// `identity_data` is the pkcs8 encoded private key of the app.
+/// `node_public_key` points to the 32 bytes public key of the node.
+/// `node_address` is a nul terminated address string, for example: "node.example.com:1337".
+///
+/// No connection is made until `offst_client_connect` is called.
+/// The client must be freed using `offst_client_free`.
+#[no_mangle]
+pub unsafe extern "C" fn offst_client_new(
+    identity_data: *const u8,
+    identity_len: usize,
+    node_public_key: *const u8,
+    node_address: *const c_char,
+    out_client: *mut *mut OffstClient,
+) -> OffstResult {
+    if out_client.is_null() {
+        return OffstResult::NullPointer;
+    }
+    let identity_data = try_ffi!(read_slice(identity_data, identity_len));
+    let node_public_key = try_ffi!(read_public_key(node_public_key));
+    let node_address = try_ffi!(read_net_address(node_address));
+
+    let identity = try_ffi!(SoftwareEd25519Identity::from_pkcs8(identity_data)
+        .map_err(|_| OffstResult::InvalidIdentity));
+    let local_public_key = identity.get_public_key();
+
+    // The internal runtime. All the work of the client is done on its threads:
+    let mut thread_pool = try_ffi!(ThreadPool::new().map_err(|_| OffstResult::RuntimeError));
+
+    // Spawn identity service:
+    let (requests_sender, identity_loop) = create_identity(identity);
+    try_ffi!(thread_pool
+        .spawn(identity_loop)
+        .map_err(|_| OffstResult::RuntimeError));
+
+    *out_client = register(OffstClient {
+        thread_pool,
+        identity_client: IdentityClient::new(requests_sender),
+        local_public_key,
+        node_public_key,
+        node_address,
+        opt_conn: None,
+        opt_events: None,
+    });
+    OffstResult::Ok
+}
+
+/// Free a client context. Closes the connection to the node, if any.
+/// Payments that are still in progress keep their handles, and must be freed separately.
+#[no_mangle]
+pub unsafe extern "C" fn offst_client_free(client: *mut OffstClient) -> OffstResult {
+    match release(client) {
+        Ok(_) => OffstResult::Ok,
+        Err(res) => res,
+    }
+}
+
+/// Connect to the node. Blocks until the connection is established or fails.
+/// Report events are queued from the moment of connection. See `offst_client_poll_event`.
+#[no_mangle]
+pub unsafe extern "C" fn offst_client_connect(client: *mut OffstClient) -> OffstResult {
+    let client = try_ffi!(get_mut(client));
+    if client.opt_conn.is_some() {
+        return OffstResult::AlreadyConnected;
+    }
+
+    let connect_fut = connect(
+        client.node_public_key.clone(),
+        client.node_address.clone(),
+        client.identity_client.clone(),
+        client.thread_pool.clone(),
+    );
+    let mut conn = try_ffi!(client
+        .thread_pool
+        .run(connect_fut)
+        .map_err(|_| OffstResult::ConnectError));
+
+    let (_node_report, incoming_mutations) = try_ffi!(client
+        .thread_pool
+        .run(conn.report().incoming_reports())
+        .map_err(|_| OffstResult::Disconnected));
+    let (events_sender, events_receiver) = mpsc::channel(EVENTS_QUEUE_LEN);
+    try_ffi!(client
+        .thread_pool
+        .spawn(forward_events(incoming_mutations, events_sender))
+        .map_err(|_| OffstResult::RuntimeError));
+
+    client.opt_conn = Some(conn);
+    client.opt_events = Some(events_receiver);
+    OffstResult::Ok
+}
+
+/// Obtain the public key of the app (32 bytes).
+/// The returned buffer must be freed using `offst_buffer_free`.
+#[no_mangle]
+pub unsafe extern "C" fn offst_client_local_public_key(
+    client: *mut OffstClient,
+    out_public_key: *mut *mut OffstBuffer,
+) -> OffstResult {
+    let client = try_ffi!(get_mut(client));
+    write_buffer(client.local_public_key.to_vec(), out_public_key)
+}
+
+/// List the friends of the node.
+/// The returned buffer contains the 32 bytes public keys of all the friends, one after the other.
+/// The returned buffer must be freed using `offst_buffer_free`.
+#[no_mangle]
+pub unsafe extern "C" fn offst_client_list_friends(
+    client: *mut OffstClient,
+    out_friends: *mut *mut OffstBuffer,
+) -> OffstResult {
+    if out_friends.is_null() {
+        return OffstResult::NullPointer;
+    }
+    let client = try_ffi!(get_mut(client));
+    let mirror = try_ffi!(client.mirror());
+
+    let mut data = Vec::new();
+    for friend_public_key in mirror.node_report().funder_report.friends.keys() {
+        data.extend_from_slice(friend_public_key);
+    }
+    write_buffer(data, out_friends)
+}
+
+/// Obtain the balances against all the friends of the node.
+/// The returned buffer contains one entry for every friend with a consistent channel:
+/// friend public key (32 bytes) || balance (16 bytes, big endian, two's complement)
+/// The returned buffer must be freed using `offst_buffer_free`.
+#[no_mangle]
+pub unsafe extern "C" fn offst_client_balances(
+    client: *mut OffstClient,
+    out_balances: *mut *mut OffstBuffer,
+) -> OffstResult {
+    if out_balances.is_null() {
+        return OffstResult::NullPointer;
+    }
+    let client = try_ffi!(get_mut(client));
+    let mirror = try_ffi!(client.mirror());
+
+    let mut data = Vec::new();
+    for friend_public_key in mirror.node_report().funder_report.friends.keys() {
+        if let Some(balance) = mirror.balance(friend_public_key) {
+            data.extend_from_slice(friend_public_key);
+            data.extend_from_slice(&balance.to_be_bytes());
+        }
+    }
+    write_buffer(data, out_balances)
+}
+
+/// Poll the next report event, without blocking.
+/// An event is a capnp (packed) serialized `NodeReportMutation`.
+/// Returns `OFFST_RESULT_NO_EVENT` if no event is waiting.
+/// The returned buffer must be freed using `offst_buffer_free`.
+#[no_mangle]
+pub unsafe extern "C" fn offst_client_poll_event(
+    client: *mut OffstClient,
+    out_event: *mut *mut OffstBuffer,
+) -> OffstResult {
+    if out_event.is_null() {
+        return OffstResult::NullPointer;
+    }
+    let client = try_ffi!(get_mut(client));
+    let events = match &mut client.opt_events {
+        Some(events) => events,
+        None => return OffstResult::NotConnected,
+    };
+    match events.try_next() {
+        Ok(Some(event)) => write_buffer(event, out_event),
+        Ok(None) => OffstResult::Disconnected,
+        Err(_) => OffstResult::NoEvent,
+    }
+}
@@ -0,0 +1,60 @@
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::slice;
+
+use app::invoice::{InvoiceId, INVOICE_ID_LEN};
+use app::{PublicKey, Receipt, PUBLIC_KEY_LEN};
+use proto::net::messages::NetAddress;
+
+use crate::result::OffstResult;
+
+/// Length of a 128 bit integer, encoded as big endian bytes.
+pub const INT128_LEN: usize = 16;
+
+/// Borrow `len` bytes starting at `data`.
+pub unsafe fn read_slice<'a>(data: *const u8, len: usize) -> Result<&'a [u8], OffstResult> {
+    if data.is_null() {
+        return Err(OffstResult::NullPointer);
+    }
+    Ok(slice::from_raw_parts(data, len))
+}
+
+pub unsafe fn read_public_key(data: *const u8) -> Result<PublicKey, OffstResult> {
+    let public_key_bytes = read_slice(data, PUBLIC_KEY_LEN)?;
+    PublicKey::try_from(public_key_bytes).map_err(|_| OffstResult::InvalidArgument)
+}
+
+pub unsafe fn read_invoice_id(data: *const u8) -> Result<InvoiceId, OffstResult> {
+    let invoice_id_bytes = read_slice(data, INVOICE_ID_LEN)?;
+    InvoiceId::try_from(invoice_id_bytes).map_err(|_| OffstResult::InvalidArgument)
+}
+
+/// Read a big endian unsigned 128 bit integer.
+pub unsafe fn read_u128(data: *const u8) -> Result<u128, OffstResult> {
+    let mut int_bytes = [0u8; INT128_LEN];
+    int_bytes.copy_from_slice(read_slice(data, INT128_LEN)?);
+    Ok(u128::from_be_bytes(int_bytes))
+}
+
+/// Read a nul terminated address string, for example: "node.example.com:1337"
+pub unsafe fn read_net_address(data: *const c_char) -> Result<NetAddress, OffstResult> {
+    if data.is_null() {
+        return Err(OffstResult::NullPointer);
+    }
+    let address = CStr::from_ptr(data)
+        .to_str()
+        .map_err(|_| OffstResult::InvalidArgument)?;
+    NetAddress::try_from(address.to_owned()).map_err(|_| OffstResult::InvalidArgument)
+}
+
+/// Encode a receipt as:
+/// response_hash || invoice_id || dest_payment (16 bytes, big endian) || signature
+pub fn receipt_to_bytes(receipt: &Receipt) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&receipt.response_hash);
+    data.extend_from_slice(&receipt.invoice_id);
+    data.extend_from_slice(&receipt.dest_payment.to_be_bytes());
+    data.extend_from_slice(&receipt.signature);
+    data
+}
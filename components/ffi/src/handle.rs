@@ -0,0 +1,50 @@
+use std::any::TypeId;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use crate::result::OffstResult;
+
+lazy_static! {
+    /// The addresses of all the live handles, together with their types.
+    /// Used to reject handles that were already freed, or handles of the wrong type.
+    static ref LIVE_HANDLES: Mutex<HashMap<usize, TypeId>> = Mutex::new(HashMap::new());
+}
+
+/// Move `value` to the heap and register it as a live handle.
+/// The returned handle must be released using `release`.
+pub fn register<T: 'static>(value: T) -> *mut T {
+    let handle = Box::into_raw(Box::new(value));
+    let mut live_handles = LIVE_HANDLES.lock().unwrap();
+    live_handles.insert(handle as usize, TypeId::of::<T>());
+    handle
+}
+
+fn check_live<T: 'static>(
+    live_handles: &HashMap<usize, TypeId>,
+    handle: *const T,
+) -> Result<(), OffstResult> {
+    if handle.is_null() {
+        return Err(OffstResult::NullPointer);
+    }
+    match live_handles.get(&(handle as usize)) {
+        Some(type_id) if *type_id == TypeId::of::<T>() => Ok(()),
+        _ => Err(OffstResult::InvalidHandle),
+    }
+}
+
+/// Obtain a mutable reference to the value behind a live handle.
+///
+/// The caller must make sure that the handle is not used concurrently.
+pub unsafe fn get_mut<'a, T: 'static>(handle: *mut T) -> Result<&'a mut T, OffstResult> {
+    check_live(&LIVE_HANDLES.lock().unwrap(), handle)?;
+    Ok(&mut *handle)
+}
+
+/// Unregister a live handle and take back ownership over its value.
+/// Releasing the same handle twice results in `InvalidHandle`.
+pub unsafe fn release<T: 'static>(handle: *mut T) -> Result<T, OffstResult> {
+    let mut live_handles = LIVE_HANDLES.lock().unwrap();
+    check_live(&live_handles, handle)?;
+    live_handles.remove(&(handle as usize));
+    Ok(*Box::from_raw(handle))
+}
@@ -0,0 +1,55 @@
+#![feature(futures_api, async_await, await_macro, arbitrary_self_types)]
+#![feature(nll)]
+#![feature(generators)]
+#![feature(never_type)]
+#![deny(trivial_numeric_casts, warnings)]
+#![allow(intra_doc_link_resolution_failure)]
+#![allow(
+    clippy::too_many_arguments,
+    clippy::implicit_hasher,
+    clippy::module_inception,
+    clippy::new_without_default
+)]
+
+//! A C interface for embedding an offst app client, for example in mobile apps.
+//!
+//! Every function returns an `OffstResult`. Results are passed back through out pointers.
+//! Every handle and buffer handed out by the library has a matching free function.
+//! Freeing a handle twice, or passing a handle of the wrong type, results in
+//! `OFFST_RESULT_INVALID_HANDLE`.
+//! A handle must not be used from more than one thread at the same time.
+//!
+//! The header `include/offst.h` is generated using cbindgen. See `cbindgen.toml`.
+
+#[macro_use]
+extern crate lazy_static;
+
+/// Return early from a C interface function if `$e` is an error.
+macro_rules! try_ffi {
+    ($e:expr) => {
+        match $e {
+            Ok(value) => value,
+            Err(res) => return res,
+        }
+    };
+}
+
+mod buffer;
+mod client;
+mod convert;
+mod handle;
+mod payment;
+mod result;
+
+#[cfg(test)]
+mod tests;
+
+pub use self::buffer::{offst_buffer_free, offst_buffer_get, OffstBuffer};
+pub use self::client::{
+    offst_client_balances, offst_client_connect, offst_client_free, offst_client_list_friends,
+    offst_client_local_public_key, offst_client_new, offst_client_poll_event, OffstClient,
+};
+pub use self::payment::{
+    offst_client_send_payment, offst_payment_free, offst_payment_poll, OffstPayment,
+};
+pub use self::result::OffstResult;
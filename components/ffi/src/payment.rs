@@ -0,0 +1,141 @@
+use futures::channel::oneshot;
+use futures::task::SpawnExt;
+
+use app::gen::gen_uid;
+use app::invoice::InvoiceId;
+use app::{AppRoutes, AppSendFunds, PublicKey, Receipt, SendFundsError};
+
+use crate::buffer::{write_buffer, OffstBuffer};
+use crate::client::OffstClient;
+use crate::convert::{read_invoice_id, read_public_key, read_u128, receipt_to_bytes};
+use crate::handle::{get_mut, register, release};
+use crate::result::OffstResult;
+
+/// A payment in progress.
+pub struct OffstPayment {
+    receiver: oneshot::Receiver<Result<Receipt, OffstResult>>,
+    opt_result: Option<Result<Receipt, OffstResult>>,
+}
+
+async fn send_payment(
+    mut app_routes: AppRoutes,
+    mut app_send_funds: AppSendFunds,
+    local_public_key: PublicKey,
+    destination: PublicKey,
+    invoice_id: InvoiceId,
+    dest_payment: u128,
+) -> Result<Receipt, OffstResult> {
+    let routes_with_capacity = await!(app_routes.request_routes(
+        dest_payment,
+        local_public_key, // source
+        destination,
+        None
+    )) // No exclusion of edges
+    .map_err(|_| OffstResult::NoRoute)?;
+
+    let route = app_routes
+        .select_route(routes_with_capacity, dest_payment, None)
+        .ok_or(OffstResult::NoRoute)?;
+
+    let request_id = gen_uid();
+    let receipt =
+        await!(app_send_funds.request_send_funds(request_id, route, invoice_id, dest_payment))
+            .map_err(|e| match e {
+                SendFundsError::NoResponse => OffstResult::PaymentNoResponse,
+                _ => OffstResult::PaymentFailed,
+            })?;
+
+    // We only send the ack if we managed to get the receipt:
+    await!(app_send_funds.receipt_ack(request_id, receipt.clone()))
+        .map_err(|_| OffstResult::Disconnected)?;
+    Ok(receipt)
+}
+
+/// Start a payment to `destination` (32 bytes public key) for the invoice `invoice_id`
+/// (32 bytes). `dest_payment` points to the amount to pay (16 bytes, big endian).
+///
+/// Returns immediately. The completion of the payment is obtained using `offst_payment_poll`.
+/// The payment must be freed using `offst_payment_free`.
+#[no_mangle]
+pub unsafe extern "C" fn offst_client_send_payment(
+    client: *mut OffstClient,
+    destination: *const u8,
+    invoice_id: *const u8,
+    dest_payment: *const u8,
+    out_payment: *mut *mut OffstPayment,
+) -> OffstResult {
+    if out_payment.is_null() {
+        return OffstResult::NullPointer;
+    }
+    let client = try_ffi!(get_mut(client));
+    let destination = try_ffi!(read_public_key(destination));
+    let invoice_id = try_ffi!(read_invoice_id(invoice_id));
+    let dest_payment = try_ffi!(read_u128(dest_payment));
+
+    let conn = match &mut client.opt_conn {
+        Some(conn) => conn,
+        None => return OffstResult::NotConnected,
+    };
+    let app_routes = try_ffi!(conn.routes().ok_or(OffstResult::PermissionDenied)).clone();
+    let app_send_funds = try_ffi!(conn.send_funds().ok_or(OffstResult::PermissionDenied)).clone();
+
+    let (sender, receiver) = oneshot::channel();
+    let payment_fut = send_payment(
+        app_routes,
+        app_send_funds,
+        client.local_public_key.clone(),
+        destination,
+        invoice_id,
+        dest_payment,
+    );
+    try_ffi!(client
+        .thread_pool
+        .spawn(async move {
+            let _ = sender.send(await!(payment_fut));
+        })
+        .map_err(|_| OffstResult::RuntimeError));
+
+    *out_payment = register(OffstPayment {
+        receiver,
+        opt_result: None,
+    });
+    OffstResult::Ok
+}
+
+/// Check whether a payment has completed, without blocking.
+/// Returns `OFFST_RESULT_PENDING` while the payment is in progress.
+/// On success, `out_receipt` is set to the receipt of the payment:
+/// response_hash (32 bytes) || invoice_id (32 bytes) || dest_payment (16 bytes, big endian)
+/// || signature (64 bytes)
+/// The returned buffer must be freed using `offst_buffer_free`.
+#[no_mangle]
+pub unsafe extern "C" fn offst_payment_poll(
+    payment: *mut OffstPayment,
+    out_receipt: *mut *mut OffstBuffer,
+) -> OffstResult {
+    if out_receipt.is_null() {
+        return OffstResult::NullPointer;
+    }
+    let payment = try_ffi!(get_mut(payment));
+    if payment.opt_result.is_none() {
+        payment.opt_result = match payment.receiver.try_recv() {
+            Ok(Some(result)) => Some(result),
+            Ok(None) => return OffstResult::Pending,
+            // The runtime dropped the payment:
+            Err(_) => Some(Err(OffstResult::Disconnected)),
+        };
+    }
+    match payment.opt_result.as_ref().unwrap() {
+        Ok(receipt) => write_buffer(receipt_to_bytes(receipt), out_receipt),
+        Err(res) => *res,
+    }
+}
+
+/// Free a payment. Freeing a payment that is still in progress does not cancel it.
+#[no_mangle]
+pub unsafe extern "C" fn offst_payment_free(payment: *mut OffstPayment) -> OffstResult {
+    match release(payment) {
+        Ok(_) => OffstResult::Ok,
+        Err(res) => res,
+    }
+}
@@ -0,0 +1,42 @@
+/// The result of every function of the C interface.
+///
+/// The numeric value of a result is part of the stable interface:
+/// Existing values never change, and a value is never reused for a different meaning.
+/// New results may only be added with new values.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffstResult {
+    /// The operation completed successfully.
+    Ok = 0,
+    /// A pointer argument was null.
+    NullPointer = 1,
+    /// A handle argument is not a live handle of the expected type.
+    /// (For example: A handle that was already freed)
+    InvalidHandle = 2,
+    /// An argument could not be parsed.
+    InvalidArgument = 3,
+    /// The given identity could not be loaded.
+    InvalidIdentity = 4,
+    /// The internal runtime could not be started.
+    RuntimeError = 5,
+    /// Connecting to the node failed.
+    ConnectError = 6,
+    /// The client is not connected to a node.
+    NotConnected = 7,
+    /// The client is already connected to a node.
+    AlreadyConnected = 8,
+    /// The node does not permit this operation for this app.
+    PermissionDenied = 9,
+    /// The connection to the node was lost.
+    Disconnected = 10,
+    /// No event is waiting to be polled.
+    NoEvent = 11,
+    /// The operation is still in progress. Poll again later.
+    Pending = 12,
+    /// No suitable route to the destination was found.
+    NoRoute = 13,
+    /// The payment failed. No credits were transferred.
+    PaymentFailed = 14,
+    /// The payment was sent, but no response was received.
+    PaymentNoResponse = 15,
+}
@@ -0,0 +1,236 @@
+use std::ffi::CString;
+use std::ptr;
+use std::slice;
+
+use crypto::identity::{generate_pkcs8_key_pair, Identity, SoftwareEd25519Identity};
+use crypto::test_utils::DummyRandom;
+
+use super::*;
+
+const NODE_PUBLIC_KEY: [u8; 32] = [0xaa; 32];
+
+/// Create a client, using the C interface
+unsafe fn create_client(seed: u8) -> *mut OffstClient {
+    let rng = DummyRandom::new(&[seed]);
+    let pkcs8 = generate_pkcs8_key_pair(&rng);
+    let node_address = CString::new("127.0.0.1:1337").unwrap();
+
+    let mut client = ptr::null_mut();
+    let res = offst_client_new(
+        pkcs8.as_ptr(),
+        pkcs8.len(),
+        NODE_PUBLIC_KEY.as_ptr(),
+        node_address.as_ptr(),
+        &mut client,
+    );
+    assert_eq!(res, OffstResult::Ok);
+    assert!(!client.is_null());
+    client
+}
+
+#[test]
+fn test_client_new_rejects_null_pointers() {
+    let rng = DummyRandom::new(&[1u8]);
+    let pkcs8 = generate_pkcs8_key_pair(&rng);
+    let node_address = CString::new("127.0.0.1:1337").unwrap();
+    let mut client = ptr::null_mut();
+
+    unsafe {
+        let res = offst_client_new(
+            ptr::null(),
+            pkcs8.len(),
+            NODE_PUBLIC_KEY.as_ptr(),
+            node_address.as_ptr(),
+            &mut client,
+        );
+        assert_eq!(res, OffstResult::NullPointer);
+
+        let res = offst_client_new(
+            pkcs8.as_ptr(),
+            pkcs8.len(),
+            ptr::null(),
+            node_address.as_ptr(),
+            &mut client,
+        );
+        assert_eq!(res, OffstResult::NullPointer);
+
+        let res = offst_client_new(
+            pkcs8.as_ptr(),
+            pkcs8.len(),
+            NODE_PUBLIC_KEY.as_ptr(),
+            ptr::null(),
+            &mut client,
+        );
+        assert_eq!(res, OffstResult::NullPointer);
+
+        let res = offst_client_new(
+            pkcs8.as_ptr(),
+            pkcs8.len(),
+            NODE_PUBLIC_KEY.as_ptr(),
+            node_address.as_ptr(),
+            ptr::null_mut(),
+        );
+        assert_eq!(res, OffstResult::NullPointer);
+    }
+    assert!(client.is_null());
+}
+
+#[test]
+fn test_client_new_invalid_arguments() {
+    let rng = DummyRandom::new(&[1u8]);
+    let pkcs8 = generate_pkcs8_key_pair(&rng);
+    let node_address = CString::new("127.0.0.1:1337").unwrap();
+    let mut client = ptr::null_mut();
+
+    unsafe {
+        // A truncated identity:
+        let res = offst_client_new(
+            pkcs8.as_ptr(),
+            pkcs8.len() - 1,
+            NODE_PUBLIC_KEY.as_ptr(),
+            node_address.as_ptr(),
+            &mut client,
+        );
+        assert_eq!(res, OffstResult::InvalidIdentity);
+
+        // An address that is not valid UTF-8:
+        let bad_address = CString::new(vec![0xff, 0xfe]).unwrap();
+        let res = offst_client_new(
+            pkcs8.as_ptr(),
+            pkcs8.len(),
+            NODE_PUBLIC_KEY.as_ptr(),
+            bad_address.as_ptr(),
+            &mut client,
+        );
+        assert_eq!(res, OffstResult::InvalidArgument);
+    }
+    assert!(client.is_null());
+}
+
+#[test]
+fn test_client_double_free() {
+    unsafe {
+        let client = create_client(2);
+        assert_eq!(offst_client_free(client), OffstResult::Ok);
+        assert_eq!(offst_client_free(client), OffstResult::InvalidHandle);
+        assert_eq!(offst_client_free(ptr::null_mut()), OffstResult::NullPointer);
+
+        // A freed client can not be used:
+        let mut buffer = ptr::null_mut();
+        let res = offst_client_local_public_key(client, &mut buffer);
+        assert_eq!(res, OffstResult::InvalidHandle);
+        assert!(buffer.is_null());
+    }
+}
+
+#[test]
+fn test_buffer_ownership() {
+    let rng = DummyRandom::new(&[3u8]);
+    let pkcs8 = generate_pkcs8_key_pair(&rng);
+    let local_public_key = SoftwareEd25519Identity::from_pkcs8(&pkcs8)
+        .unwrap()
+        .get_public_key();
+
+    unsafe {
+        let client = create_client(3);
+        let mut buffer = ptr::null_mut();
+        let res = offst_client_local_public_key(client, &mut buffer);
+        assert_eq!(res, OffstResult::Ok);
+
+        let mut data = ptr::null();
+        let mut len = 0;
+        assert_eq!(
+            offst_buffer_get(buffer, &mut data, &mut len),
+            OffstResult::Ok
+        );
+        assert_eq!(slice::from_raw_parts(data, len), &local_public_key[..]);
+        assert_eq!(
+            offst_buffer_get(buffer, ptr::null_mut(), &mut len),
+            OffstResult::NullPointer
+        );
+
+        // The buffer outlives the client:
+        assert_eq!(offst_client_free(client), OffstResult::Ok);
+        assert_eq!(
+            offst_buffer_get(buffer, &mut data, &mut len),
+            OffstResult::Ok
+        );
+        assert_eq!(len, local_public_key.len());
+
+        assert_eq!(offst_buffer_free(buffer), OffstResult::Ok);
+        assert_eq!(offst_buffer_free(buffer), OffstResult::InvalidHandle);
+        assert_eq!(
+            offst_buffer_get(buffer, &mut data, &mut len),
+            OffstResult::InvalidHandle
+        );
+    }
+}
+
+#[test]
+fn test_handle_type_mismatch() {
+    unsafe {
+        let client = create_client(4);
+        // A client handle is not a buffer or a payment:
+        assert_eq!(
+            offst_buffer_free(client as *mut OffstBuffer),
+            OffstResult::InvalidHandle
+        );
+        assert_eq!(
+            offst_payment_free(client as *mut OffstPayment),
+            OffstResult::InvalidHandle
+        );
+        assert_eq!(offst_client_free(client), OffstResult::Ok);
+    }
+}
+
+#[test]
+fn test_not_connected() {
+    let destination = [0xbb; 32];
+    let invoice_id = [0xcc; 32];
+    let dest_payment = 10u128.to_be_bytes();
+
+    unsafe {
+        let client = create_client(5);
+        let mut buffer = ptr::null_mut();
+        assert_eq!(
+            offst_client_list_friends(client, &mut buffer),
+            OffstResult::NotConnected
+        );
+        assert_eq!(
+            offst_client_balances(client, &mut buffer),
+            OffstResult::NotConnected
+        );
+        assert_eq!(
+            offst_client_poll_event(client, &mut buffer),
+            OffstResult::NotConnected
+        );
+        assert!(buffer.is_null());
+
+        let mut payment = ptr::null_mut();
+        let res = offst_client_send_payment(
+            client,
+            destination.as_ptr(),
+            invoice_id.as_ptr(),
+            dest_payment.as_ptr(),
+            &mut payment,
+        );
+        assert_eq!(res, OffstResult::NotConnected);
+        assert!(payment.is_null());
+
+        // Null arguments are rejected before the connection is checked:
+        let res = offst_client_send_payment(
+            client,
+            destination.as_ptr(),
+            ptr::null(),
+            dest_payment.as_ptr(),
+            &mut payment,
+        );
+        assert_eq!(res, OffstResult::NullPointer);
+        assert_eq!(
+            offst_payment_poll(ptr::null_mut(), &mut buffer),
+            OffstResult::NullPointer
+        );
+
+        assert_eq!(offst_client_free(client), OffstResult::Ok);
+    }
+}
@@ -0,0 +1,266 @@
+use crypto::identity::PublicKey;
+use im::hashmap::HashMap as ImHashMap;
+
+/// Round trip time estimates are kept in units of 1/RTT_SCALE ticks.
+const RTT_SCALE: usize = 0x10;
+
+/// Weight of a new round trip time sample in the moving average is 1/RTT_EWMA_DIV.
+const RTT_EWMA_DIV: usize = 4;
+
+/// The batch size is changed only if the new batch size differs from the current one
+/// by at least 1/HYSTERESIS_DIV of the current one (Or if a bound is reached).
+/// This prevents the batch size from oscillating on every exchange of the token.
+const HYSTERESIS_DIV: usize = 4;
+
+/// Round trip time measurements of a single friend
+#[derive(Clone, Debug, Default)]
+pub struct FriendRtt {
+    /// Ticks passed since we sent a move token asking for the token back.
+    /// None if we are not waiting for the token.
+    pub opt_pending_ticks: Option<usize>,
+    /// Exponentially weighted moving average of the token round trip time,
+    /// in units of 1/RTT_SCALE ticks.
+    pub opt_rtt_ewma: Option<usize>,
+    /// The adapted amount of operations in one move token sent to this friend.
+    /// None if not yet adapted.
+    pub opt_batch_size: Option<usize>,
+}
+
+/// Adapts the amount of operations in one move token to the token round trip time of every
+/// friend. Slow friends get larger batches (To amortize the round trip),
+/// fast friends get smaller batches (To reduce latency).
+#[derive(Clone, Default)]
+pub struct AdaptiveBatch {
+    pub friends: ImHashMap<PublicKey, FriendRtt>,
+}
+
+#[derive(Debug)]
+pub enum AdaptiveBatchMutation {
+    /// A move token asking for the token back was sent to a friend.
+    TokenSent(PublicKey),
+    /// A valid incoming move token was received from a friend.
+    TokenReceived(PublicKey),
+    /// Stop waiting for the token of a friend (For example, if the friend went offline).
+    /// The current estimate is kept.
+    CancelPending(PublicKey),
+    /// Set the adapted batch size of a friend.
+    SetBatchSize((PublicKey, usize)),
+    /// The given amount of time ticks has passed.
+    /// A long pause of the node (For example, a suspend of the host) inflates the pending
+    /// measurements. Its effect on the estimate fades away with the next measurements.
+    Tick(usize),
+}
+
+/// Calculate the batch size fitting a round trip time estimate.
+/// The batch size grows linearly with the round trip time (Measured in whole ticks),
+/// and is kept between `min_batch_size` and `max_batch_size`.
+fn target_batch_size(rtt_ewma: usize, min_batch_size: usize, max_batch_size: usize) -> usize {
+    let rtt_ticks = rtt_ewma / RTT_SCALE;
+    min_batch_size
+        .saturating_mul(rtt_ticks.saturating_add(1))
+        .min(max_batch_size)
+        .max(min_batch_size)
+}
+
+impl AdaptiveBatch {
+    pub fn new() -> AdaptiveBatch {
+        AdaptiveBatch {
+            friends: ImHashMap::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &AdaptiveBatchMutation) {
+        match mutation {
+            AdaptiveBatchMutation::TokenSent(public_key) => {
+                let mut friend_rtt = self.friends.get(public_key).cloned().unwrap_or_default();
+                // A retransmission does not restart the measurement:
+                if friend_rtt.opt_pending_ticks.is_none() {
+                    friend_rtt.opt_pending_ticks = Some(0);
+                }
+                self.friends.insert(public_key.clone(), friend_rtt);
+            }
+            AdaptiveBatchMutation::TokenReceived(public_key) => {
+                let friend_rtt = match self.friends.get_mut(public_key) {
+                    Some(friend_rtt) => friend_rtt,
+                    None => return,
+                };
+                let sample_ticks = match friend_rtt.opt_pending_ticks.take() {
+                    Some(sample_ticks) => sample_ticks,
+                    None => return,
+                };
+                let sample = sample_ticks.saturating_mul(RTT_SCALE);
+                friend_rtt.opt_rtt_ewma = Some(match friend_rtt.opt_rtt_ewma {
+                    None => sample,
+                    Some(rtt_ewma) => rtt_ewma
+                        .saturating_mul(RTT_EWMA_DIV - 1)
+                        .saturating_add(sample)
+                        / RTT_EWMA_DIV,
+                });
+            }
+            AdaptiveBatchMutation::CancelPending(public_key) => {
+                if let Some(friend_rtt) = self.friends.get_mut(public_key) {
+                    friend_rtt.opt_pending_ticks = None;
+                }
+            }
+            AdaptiveBatchMutation::SetBatchSize((public_key, batch_size)) => {
+                let mut friend_rtt = self.friends.get(public_key).cloned().unwrap_or_default();
+                friend_rtt.opt_batch_size = Some(*batch_size);
+                self.friends.insert(public_key.clone(), friend_rtt);
+            }
+            AdaptiveBatchMutation::Tick(ticks_elapsed) => {
+                let mut friends = ImHashMap::new();
+                for (public_key, friend_rtt) in &self.friends {
+                    let mut friend_rtt = friend_rtt.clone();
+                    friend_rtt.opt_pending_ticks = friend_rtt
+                        .opt_pending_ticks
+                        .map(|pending_ticks| pending_ticks.saturating_add(*ticks_elapsed));
+                    friends.insert(public_key.clone(), friend_rtt);
+                }
+                self.friends = friends;
+            }
+        }
+    }
+
+    /// Are we waiting for the token of any friend?
+    pub fn is_pending(&self) -> bool {
+        self.friends
+            .values()
+            .any(|friend_rtt| friend_rtt.opt_pending_ticks.is_some())
+    }
+
+    /// Are we waiting for the token of a friend?
+    pub fn is_friend_pending(&self, friend_public_key: &PublicKey) -> bool {
+        self.friends
+            .get(friend_public_key)
+            .map(|friend_rtt| friend_rtt.opt_pending_ticks.is_some())
+            .unwrap_or(false)
+    }
+
+    /// The amount of operations to put in one move token sent to a friend.
+    /// `max_batch_size` is used until the batch size of the friend is adapted.
+    pub fn batch_size(&self, friend_public_key: &PublicKey, max_batch_size: usize) -> usize {
+        self.friends
+            .get(friend_public_key)
+            .and_then(|friend_rtt| friend_rtt.opt_batch_size)
+            .unwrap_or(max_batch_size)
+    }
+
+    /// Calculate new batch sizes for friends whose round trip time estimate moved far enough
+    /// from their current batch size.
+    pub fn adapt(&self, min_batch_size: usize, max_batch_size: usize) -> Vec<(PublicKey, usize)> {
+        let mut new_batch_sizes = Vec::new();
+        for (public_key, friend_rtt) in &self.friends {
+            let rtt_ewma = match friend_rtt.opt_rtt_ewma {
+                Some(rtt_ewma) => rtt_ewma,
+                None => continue,
+            };
+            let current = friend_rtt.opt_batch_size.unwrap_or(max_batch_size);
+            let target = target_batch_size(rtt_ewma, min_batch_size, max_batch_size);
+
+            let diff = if target > current {
+                target - current
+            } else {
+                current - target
+            };
+            let reached_bound = target == min_batch_size || target == max_batch_size;
+            if diff > 0 && (reached_bound || diff.saturating_mul(HYSTERESIS_DIV) >= current) {
+                new_batch_sizes.push((public_key.clone(), target));
+            }
+        }
+        new_batch_sizes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    /// Simulate one token round trip of `rtt_ticks` ticks with a friend,
+    /// and apply the resulting batch size changes.
+    fn round_trip(adaptive_batch: &mut AdaptiveBatch, public_key: &PublicKey, rtt_ticks: usize) {
+        adaptive_batch.mutate(&AdaptiveBatchMutation::TokenSent(public_key.clone()));
+        for _ in 0..rtt_ticks {
+            adaptive_batch.mutate(&AdaptiveBatchMutation::Tick(1));
+        }
+        adaptive_batch.mutate(&AdaptiveBatchMutation::TokenReceived(public_key.clone()));
+        for new_batch_size in adaptive_batch.adapt(4, 64) {
+            adaptive_batch.mutate(&AdaptiveBatchMutation::SetBatchSize(new_batch_size));
+        }
+    }
+
+    #[test]
+    fn test_adaptive_batch_diverge() {
+        let mut adaptive_batch = AdaptiveBatch::new();
+        let pk_fast = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_slow = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        // Before any measurement, the maximum batch size is used:
+        assert_eq!(adaptive_batch.batch_size(&pk_fast, 64), 64);
+        assert_eq!(adaptive_batch.batch_size(&pk_slow, 64), 64);
+
+        for _ in 0..16 {
+            round_trip(&mut adaptive_batch, &pk_fast, 0);
+            round_trip(&mut adaptive_batch, &pk_slow, 3);
+            for pk in &[&pk_fast, &pk_slow] {
+                let batch_size = adaptive_batch.batch_size(pk, 64);
+                assert!(batch_size >= 4 && batch_size <= 64);
+            }
+        }
+
+        assert_eq!(adaptive_batch.batch_size(&pk_fast, 64), 4);
+        assert_eq!(adaptive_batch.batch_size(&pk_slow, 64), 16);
+
+        // A very slow friend reaches the ceiling:
+        for _ in 0..16 {
+            round_trip(&mut adaptive_batch, &pk_slow, 100);
+        }
+        assert_eq!(adaptive_batch.batch_size(&pk_slow, 64), 64);
+    }
+
+    #[test]
+    fn test_adaptive_batch_hysteresis() {
+        let mut adaptive_batch = AdaptiveBatch::new();
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+
+        for _ in 0..16 {
+            round_trip(&mut adaptive_batch, &pk_a, 7);
+        }
+        assert_eq!(adaptive_batch.batch_size(&pk_a, 64), 32);
+
+        // Small changes in the round trip time do not change the batch size:
+        round_trip(&mut adaptive_batch, &pk_a, 6);
+        round_trip(&mut adaptive_batch, &pk_a, 8);
+        round_trip(&mut adaptive_batch, &pk_a, 6);
+        assert_eq!(adaptive_batch.batch_size(&pk_a, 64), 32);
+    }
+
+    #[test]
+    fn test_adaptive_batch_pending() {
+        let mut adaptive_batch = AdaptiveBatch::new();
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+
+        adaptive_batch.mutate(&AdaptiveBatchMutation::TokenSent(pk_a.clone()));
+        adaptive_batch.mutate(&AdaptiveBatchMutation::Tick(1));
+        // A retransmission does not restart the measurement:
+        adaptive_batch.mutate(&AdaptiveBatchMutation::TokenSent(pk_a.clone()));
+        adaptive_batch.mutate(&AdaptiveBatchMutation::Tick(1));
+        assert!(adaptive_batch.is_friend_pending(&pk_a));
+        adaptive_batch.mutate(&AdaptiveBatchMutation::TokenReceived(pk_a.clone()));
+        assert!(!adaptive_batch.is_pending());
+        assert_eq!(
+            adaptive_batch.friends.get(&pk_a).unwrap().opt_rtt_ewma,
+            Some(2 * RTT_SCALE)
+        );
+
+        // A canceled measurement is not counted:
+        adaptive_batch.mutate(&AdaptiveBatchMutation::TokenSent(pk_a.clone()));
+        adaptive_batch.mutate(&AdaptiveBatchMutation::Tick(1));
+        adaptive_batch.mutate(&AdaptiveBatchMutation::CancelPending(pk_a.clone()));
+        adaptive_batch.mutate(&AdaptiveBatchMutation::TokenReceived(pk_a.clone()));
+        assert_eq!(
+            adaptive_batch.friends.get(&pk_a).unwrap().opt_rtt_ewma,
+            Some(2 * RTT_SCALE)
+        );
+    }
+}
@@ -0,0 +1,37 @@
+#![deny(trivial_numeric_casts, warnings)]
+
+//! Generate the protocol conformance vectors of the current protocol version.
+//! See conformance/README.md
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process;
+
+use offst_funder::conformance::{generate_vector_files, version_dir_name};
+
+/// Write all the vector files into a directory named after the protocol version, inside
+/// `conformance_dir`.
+fn run(conformance_dir: &Path) -> Result<(), io::Error> {
+    let version_dir = conformance_dir.join(version_dir_name());
+    fs::create_dir_all(&version_dir)?;
+    for (file_name, contents) in generate_vector_files() {
+        let file_path = version_dir.join(file_name);
+        fs::write(&file_path, contents)?;
+        println!("{}", file_path.display());
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = env::args().collect::<Vec<_>>();
+    if args.len() != 2 {
+        eprintln!("Usage: gen-conformance <conformance_dir>");
+        process::exit(1);
+    }
+    if let Err(e) = run(Path::new(&args[1])) {
+        eprintln!("error: {:?}", e);
+        process::exit(1);
+    }
+}
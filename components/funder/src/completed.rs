@@ -0,0 +1,158 @@
+use common::bounded_cache::{BoundedCache, CacheLimits, CacheMetrics};
+use crypto::identity::PublicKey;
+use crypto::uid::Uid;
+use im::hashmap::HashMap as ImHashMap;
+use im::hashset::HashSet as ImHashSet;
+
+/// Remembers the requests we sent to every friend that were recently completed:
+/// A response or a failure was received for them.
+///
+/// After a channel reset, a friend might send again a response or a failure for a request that
+/// was completed before the reset. Such messages can then be ignored, instead of being
+/// considered an inconsistency.
+///
+/// The completed requests of every friend are kept in a bounded cache. If a completed request
+/// is evicted early, a retransmitted response or failure for it is considered an inconsistency,
+/// and the channel with the friend has to be reset again.
+#[derive(Clone)]
+pub struct Completed {
+    /// Limits for the completed requests remembered for every friend
+    limits: CacheLimits,
+    pub friends: ImHashMap<PublicKey, BoundedCache<Uid, ()>>,
+}
+
+#[derive(Debug)]
+pub enum CompletedMutation {
+    /// A response or a failure was received from a friend for one of our requests.
+    Add((PublicKey, Uid)),
+    /// The given amount of time ticks has passed.
+    Tick(u64),
+}
+
+impl Completed {
+    pub fn new(limits: CacheLimits) -> Completed {
+        Completed {
+            limits,
+            friends: ImHashMap::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &CompletedMutation) {
+        match mutation {
+            CompletedMutation::Add((friend_public_key, request_id)) => {
+                let mut friend_completed = self
+                    .friends
+                    .get(friend_public_key)
+                    .cloned()
+                    .unwrap_or_else(|| BoundedCache::new(self.limits));
+                friend_completed.insert(*request_id, ());
+                self.friends.insert(friend_public_key.clone(), friend_completed);
+            }
+            CompletedMutation::Tick(ticks_elapsed) => {
+                // Friends whose completed requests were all forgotten are removed.
+                // This also takes care of friends that were removed:
+                self.friends = self
+                    .friends
+                    .iter()
+                    .filter_map(|(friend_public_key, friend_completed)| {
+                        let mut friend_completed = friend_completed.clone();
+                        friend_completed.tick(*ticks_elapsed);
+                        if friend_completed.is_empty() {
+                            None
+                        } else {
+                            Some((friend_public_key.clone(), friend_completed))
+                        }
+                    })
+                    .collect();
+            }
+        }
+    }
+
+    /// Ids of the requests sent to a friend that were recently completed.
+    pub fn request_ids(&self, friend_public_key: &PublicKey) -> ImHashSet<Uid> {
+        self.friends
+            .get(friend_public_key)
+            .map(|friend_completed| friend_completed.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Size metrics of the completed requests remembered for all the friends.
+    pub fn metrics(&self) -> CacheMetrics {
+        self.friends
+            .values()
+            .fold(CacheMetrics::default(), |metrics, friend_completed| {
+                metrics.combine(&friend_completed.metrics())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::uid::UID_LEN;
+
+    const TEST_LIMITS: CacheLimits = CacheLimits {
+        max_entries: 0x10,
+        max_age_ticks: 4,
+    };
+
+    #[test]
+    fn test_completed_basic() {
+        let mut completed = Completed::new(TEST_LIMITS);
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let request_id = Uid::from(&[0; UID_LEN]);
+
+        completed.mutate(&CompletedMutation::Add((pk_a.clone(), request_id)));
+        completed.mutate(&CompletedMutation::Add((pk_a.clone(), request_id)));
+        assert!(completed.request_ids(&pk_a).contains(&request_id));
+        assert!(!completed.request_ids(&pk_b).contains(&request_id));
+        assert_eq!(completed.request_ids(&pk_a).len(), 1);
+        assert!(completed.request_ids(&pk_b).is_empty());
+    }
+
+    #[test]
+    fn test_completed_forget_oldest() {
+        let mut completed = Completed::new(TEST_LIMITS);
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+
+        let request_ids = (0..=TEST_LIMITS.max_entries)
+            .map(|i| Uid::from(&[i as u8; UID_LEN]))
+            .collect::<Vec<_>>();
+
+        for request_id in &request_ids {
+            completed.mutate(&CompletedMutation::Add((pk_a.clone(), *request_id)));
+        }
+
+        assert_eq!(completed.request_ids(&pk_a).len(), TEST_LIMITS.max_entries);
+        assert!(!completed.request_ids(&pk_a).contains(&request_ids[0]));
+        assert!(completed.request_ids(&pk_a).contains(&request_ids[1]));
+        assert!(completed.request_ids(&pk_a).contains(request_ids.last().unwrap()));
+        assert_eq!(completed.metrics().evicted_by_count, 1);
+    }
+
+    #[test]
+    fn test_completed_forget_old() {
+        let mut completed = Completed::new(TEST_LIMITS);
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let request_id_a = Uid::from(&[0xaa; UID_LEN]);
+        let request_id_b = Uid::from(&[0xbb; UID_LEN]);
+
+        completed.mutate(&CompletedMutation::Add((pk_a.clone(), request_id_a)));
+        completed.mutate(&CompletedMutation::Tick(1));
+        completed.mutate(&CompletedMutation::Add((pk_b.clone(), request_id_b)));
+        for _ in 0..TEST_LIMITS.max_age_ticks - 1 {
+            completed.mutate(&CompletedMutation::Tick(1));
+        }
+
+        // Friends without remembered requests are removed:
+        assert!(!completed.friends.contains_key(&pk_a));
+        assert!(completed.request_ids(&pk_b).contains(&request_id_b));
+        assert_eq!(completed.metrics().len, 1);
+
+        completed.mutate(&CompletedMutation::Tick(1));
+        assert!(completed.friends.is_empty());
+    }
+}
@@ -0,0 +1,857 @@
+//! Protocol conformance vectors.
+//!
+//! The vectors pin the exact bytes of the parts of the protocol that are signed or hashed:
+//! Canonical serializations of operations and relays, move token signature buffers (Including
+//! reset move tokens), response and failure signature buffers, route ids and the results of the
+//! credit calculator. They are stored as JSON files in the `conformance/` directory of this crate,
+//! one directory for every protocol version, so that other implementations can test against
+//! them. See `conformance/README.md` for the layout and for how to add vectors.
+//!
+//! The vectors are created by the `gen-conformance` binary from the fixture identities
+//! (`fixture_software_identity()`) and from `DummyRandom` generators. The seeds are recorded in
+//! the vectors, therefore generating the vectors is deterministic.
+//!
+//! Inside the vectors, bytes are stored as lowercase hex strings, and u128 or i128 values are
+//! stored as decimal strings.
+
+use std::convert::TryFrom;
+
+use serde::Serialize;
+
+use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::usize_to_u32;
+use common::ser_hex::to_hex;
+
+use crypto::crypto_rand::{CryptoRandom, RandValue};
+use crypto::identity::{Identity, PublicKey, Signature, SIGNATURE_LEN};
+use crypto::invoice_id::InvoiceId;
+use crypto::test_utils::{fixture_software_identity, DummyRandom};
+use crypto::uid::Uid;
+
+use proto::app_server::messages::RelayAddress;
+use proto::consts::{MAX_ROUTE_LEN, PROTOCOL_VERSION};
+use proto::funder::messages::{
+    FailureReason, FailureSendFunds, FriendTcOp, FriendsRoute, PendingRequest, RequestSendFunds,
+    ResponseSendFunds,
+};
+use proto::funder::signature_buff::{
+    create_failure_signature_buffer, create_response_signature_buffer, move_token_signature_buff,
+    prefix_hash, prepare_receipt,
+};
+use proto::net::messages::NetAddress;
+
+use crate::credit_calc::CreditCalculator;
+use crate::types::{create_unsigned_move_token, UnsignedMoveToken};
+
+pub const MOVE_TOKEN_FILE: &str = "move_token.json";
+pub const SEND_FUNDS_FILE: &str = "send_funds.json";
+pub const ROUTE_ID_FILE: &str = "route_id.json";
+pub const CREDIT_CALC_FILE: &str = "credit_calc.json";
+
+/// A file of conformance vectors of one kind
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorFile<V> {
+    pub protocol_version: u32,
+    pub vectors: Vec<V>,
+}
+
+/// A friend operation, in the form it is stored in the vectors
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum OpVector {
+    EnableRequests,
+    DisableRequests,
+    SetRemoteMaxDebt {
+        remote_max_debt: String,
+    },
+    RequestSendFunds {
+        request_id: String,
+        route: Vec<String>,
+        dest_payment: String,
+        invoice_id: String,
+    },
+    ResponseSendFunds {
+        request_id: String,
+        rand_nonce: String,
+        signature: String,
+    },
+    FailureSendFunds {
+        request_id: String,
+        reporting_public_key: String,
+        reason: u16,
+        rand_nonce: String,
+        signature: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayVector {
+    pub public_key: String,
+    pub address: String,
+}
+
+/// A move token, and the bytes derived from it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoveTokenVector {
+    pub name: String,
+    /// Seed of the fixture identity of the local side, which signs the move token
+    pub signer_seed: u8,
+    /// Seed of the `DummyRandom` the random fields were drawn from
+    pub rand_seed: u8,
+    pub operations: Vec<OpVector>,
+    pub opt_local_relays: Option<Vec<RelayVector>>,
+    pub old_token: String,
+    pub local_public_key: String,
+    pub remote_public_key: String,
+    pub inconsistency_counter: u64,
+    pub move_token_counter: String,
+    pub balance: String,
+    pub local_pending_debt: String,
+    pub remote_pending_debt: String,
+    pub rand_nonce: String,
+    /// Canonical serialization of every operation
+    pub operations_serialized: Vec<String>,
+    /// Canonical serialization of `opt_local_relays`
+    pub local_relays_serialized: String,
+    pub prefix_hash: String,
+    /// The buffer signed to create `new_token`
+    pub signature_buff: String,
+    pub new_token: String,
+}
+
+/// A pending request, together with a response and a failure for it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SendFundsVector {
+    pub name: String,
+    /// Seeds of the fixture identities along the route. The last identity signs the response.
+    pub route_seeds: Vec<u8>,
+    /// Seed of the fixture identity that signs the failure
+    pub reporting_seed: u8,
+    /// Seed of the `DummyRandom` the random fields were drawn from
+    pub rand_seed: u8,
+    pub request_id: String,
+    pub route: Vec<String>,
+    pub dest_payment: String,
+    pub invoice_id: String,
+    pub rand_nonce: String,
+    pub reporting_public_key: String,
+    pub failure_reason: u16,
+    pub failure_rand_nonce: String,
+    pub response_signature_buff: String,
+    pub response_signature: String,
+    pub receipt_response_hash: String,
+    pub failure_signature_buff: String,
+    pub failure_signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteIdVector {
+    pub name: String,
+    /// Seeds of the fixture identities along the route
+    pub route_seeds: Vec<u8>,
+    pub route: Vec<String>,
+    pub route_serialized: String,
+    pub route_id: String,
+}
+
+/// Credits computed for a node along a route.
+/// None means that the computation has no result (For example, because of an overflow).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeCreditsVector {
+    pub node_index: u32,
+    pub credits_to_freeze: Option<String>,
+    pub credits_on_success: Option<String>,
+    pub max_credits_on_failure: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreditCalcVector {
+    pub name: String,
+    pub route_len: usize,
+    pub dest_payment: String,
+    /// None if a credit calculator can not be created for the route
+    pub opt_nodes: Option<Vec<NodeCreditsVector>>,
+}
+
+/// Name of the directory that contains the vectors of the current protocol version
+pub fn version_dir_name() -> String {
+    format!("v{}", PROTOCOL_VERSION)
+}
+
+/// Generate all the vector files of the current protocol version.
+/// Returns pairs of (file name, file contents).
+pub fn generate_vector_files() -> Vec<(&'static str, String)> {
+    vec![
+        (MOVE_TOKEN_FILE, file_contents(gen_move_token_vectors())),
+        (SEND_FUNDS_FILE, file_contents(gen_send_funds_vectors())),
+        (ROUTE_ID_FILE, file_contents(gen_route_id_vectors())),
+        (CREDIT_CALC_FILE, file_contents(gen_credit_calc_vectors())),
+    ]
+}
+
+fn file_contents<V: Serialize>(vectors: Vec<V>) -> String {
+    let vector_file = VectorFile {
+        protocol_version: PROTOCOL_VERSION,
+        vectors,
+    };
+    let mut contents = serde_json::to_string_pretty(&vector_file).unwrap();
+    contents.push('\n');
+    contents
+}
+
+fn fixture_public_key(seed: u8) -> PublicKey {
+    fixture_software_identity(seed).get_public_key()
+}
+
+fn fixture_route(seeds: &[u8]) -> FriendsRoute {
+    FriendsRoute {
+        public_keys: seeds.iter().cloned().map(fixture_public_key).collect(),
+    }
+}
+
+/// Random bytes in the shape of a signature.
+/// Used for tokens, and for signatures that are not verified at the level of the vectors.
+fn rand_signature<R: CryptoRandom>(rng: &R) -> Signature {
+    let mut buff = [0; SIGNATURE_LEN];
+    rng.fill(&mut buff).unwrap();
+    Signature::from(buff)
+}
+
+fn route_vector(route: &FriendsRoute) -> Vec<String> {
+    route
+        .public_keys
+        .iter()
+        .map(|public_key| to_hex(public_key.as_ref()))
+        .collect()
+}
+
+fn op_vector(op: &FriendTcOp) -> OpVector {
+    match op {
+        FriendTcOp::EnableRequests => OpVector::EnableRequests,
+        FriendTcOp::DisableRequests => OpVector::DisableRequests,
+        FriendTcOp::SetRemoteMaxDebt(remote_max_debt) => OpVector::SetRemoteMaxDebt {
+            remote_max_debt: remote_max_debt.to_string(),
+        },
+        FriendTcOp::RequestSendFunds(request_send_funds) => OpVector::RequestSendFunds {
+            request_id: to_hex(&request_send_funds.request_id),
+            route: route_vector(&request_send_funds.route),
+            dest_payment: request_send_funds.dest_payment.to_string(),
+            invoice_id: to_hex(&request_send_funds.invoice_id),
+        },
+        FriendTcOp::ResponseSendFunds(response_send_funds) => OpVector::ResponseSendFunds {
+            request_id: to_hex(&response_send_funds.request_id),
+            rand_nonce: to_hex(&response_send_funds.rand_nonce),
+            signature: to_hex(&response_send_funds.signature),
+        },
+        FriendTcOp::FailureSendFunds(failure_send_funds) => OpVector::FailureSendFunds {
+            request_id: to_hex(&failure_send_funds.request_id),
+            reporting_public_key: to_hex(&failure_send_funds.reporting_public_key),
+            reason: failure_send_funds.reason.to_u16(),
+            rand_nonce: to_hex(&failure_send_funds.rand_nonce),
+            signature: to_hex(&failure_send_funds.signature),
+        },
+    }
+}
+
+fn relay_vector(relay_address: &RelayAddress) -> RelayVector {
+    RelayVector {
+        public_key: to_hex(&relay_address.public_key),
+        address: relay_address.address.to_string(),
+    }
+}
+
+fn move_token_vector(
+    name: &str,
+    signer_seed: u8,
+    rand_seed: u8,
+    u_move_token: &UnsignedMoveToken<NetAddress>,
+) -> MoveTokenVector {
+    let signature_buff = move_token_signature_buff(u_move_token);
+    let new_token = fixture_software_identity(signer_seed).sign(&signature_buff);
+
+    MoveTokenVector {
+        name: name.to_owned(),
+        signer_seed,
+        rand_seed,
+        operations: u_move_token.operations.iter().map(op_vector).collect(),
+        opt_local_relays: u_move_token
+            .opt_local_relays
+            .as_ref()
+            .map(|local_relays| local_relays.iter().map(relay_vector).collect()),
+        old_token: to_hex(&u_move_token.old_token),
+        local_public_key: to_hex(&u_move_token.local_public_key),
+        remote_public_key: to_hex(&u_move_token.remote_public_key),
+        inconsistency_counter: u_move_token.inconsistency_counter,
+        move_token_counter: u_move_token.move_token_counter.to_string(),
+        balance: u_move_token.balance.to_string(),
+        local_pending_debt: u_move_token.local_pending_debt.to_string(),
+        remote_pending_debt: u_move_token.remote_pending_debt.to_string(),
+        rand_nonce: to_hex(&u_move_token.rand_nonce),
+        operations_serialized: u_move_token
+            .operations
+            .iter()
+            .map(|op| to_hex(&op.canonical_serialize()))
+            .collect(),
+        local_relays_serialized: to_hex(&u_move_token.opt_local_relays.canonical_serialize()),
+        prefix_hash: to_hex(&prefix_hash(u_move_token)),
+        signature_buff: to_hex(&signature_buff),
+        new_token: to_hex(&new_token),
+    }
+}
+
+fn gen_move_token_vectors() -> Vec<MoveTokenVector> {
+    let mut vectors = Vec::new();
+
+    // A move token without operations:
+    let rand_seed = 1;
+    let rng = DummyRandom::new(&[rand_seed]);
+    let old_token = rand_signature(&rng);
+    let rand_nonce = RandValue::new(&rng);
+    let u_move_token = create_unsigned_move_token(
+        Vec::new(),
+        None,
+        old_token,
+        fixture_public_key(1),
+        fixture_public_key(2),
+        0,
+        1,
+        0,
+        0,
+        0,
+        rand_nonce,
+    );
+    vectors.push(move_token_vector(
+        "empty_operations",
+        1,
+        rand_seed,
+        &u_move_token,
+    ));
+
+    // A move token with every kind of operation, and with local relays:
+    let rand_seed = 2;
+    let rng = DummyRandom::new(&[rand_seed]);
+    let request_send_funds = RequestSendFunds {
+        request_id: Uid::new(&rng),
+        route: fixture_route(&[2, 3, 4]),
+        dest_payment: 50,
+        invoice_id: InvoiceId::new(&rng),
+    };
+    let response_send_funds = ResponseSendFunds {
+        request_id: Uid::new(&rng),
+        rand_nonce: RandValue::new(&rng),
+        signature: rand_signature(&rng),
+    };
+    let failure_send_funds = FailureSendFunds {
+        request_id: Uid::new(&rng),
+        reporting_public_key: fixture_public_key(3),
+        reason: FailureReason::PricingRejected,
+        rand_nonce: RandValue::new(&rng),
+        signature: rand_signature(&rng),
+    };
+    let operations = vec![
+        FriendTcOp::EnableRequests,
+        FriendTcOp::SetRemoteMaxDebt(u128::max_value()),
+        FriendTcOp::RequestSendFunds(request_send_funds),
+        FriendTcOp::ResponseSendFunds(response_send_funds),
+        FriendTcOp::FailureSendFunds(failure_send_funds),
+        FriendTcOp::DisableRequests,
+    ];
+    let local_relays = vec![RelayAddress {
+        public_key: fixture_public_key(5),
+        address: NetAddress::try_from("relay.example.org:7000".to_owned()).unwrap(),
+    }];
+    let old_token = rand_signature(&rng);
+    let rand_nonce = RandValue::new(&rng);
+    let u_move_token = create_unsigned_move_token(
+        operations,
+        Some(local_relays),
+        old_token,
+        fixture_public_key(2),
+        fixture_public_key(3),
+        3,
+        (1u128 << 64) + 5,
+        -1234,
+        50,
+        7,
+        rand_nonce,
+    );
+    vectors.push(move_token_vector(
+        "all_operations",
+        2,
+        rand_seed,
+        &u_move_token,
+    ));
+
+    // A reset move token. The old token is the reset token of the remote side
+    // (See `gen_reset_terms()`), and the balance is the negated remote balance for reset:
+    let rand_seed = 3;
+    let rng = DummyRandom::new(&[rand_seed]);
+    let reset_token = rand_signature(&rng);
+    let rand_nonce = RandValue::new(&rng);
+    let u_move_token = create_unsigned_move_token(
+        Vec::new(),
+        None,
+        reset_token,
+        fixture_public_key(1),
+        fixture_public_key(2),
+        1,
+        0,
+        -20,
+        0,
+        0,
+        rand_nonce,
+    );
+    vectors.push(move_token_vector("reset", 1, rand_seed, &u_move_token));
+
+    vectors
+}
+
+fn send_funds_vector(
+    name: &str,
+    route_seeds: &[u8],
+    reporting_seed: u8,
+    rand_seed: u8,
+    dest_payment: u128,
+    failure_reason: FailureReason,
+) -> SendFundsVector {
+    let rng = DummyRandom::new(&[rand_seed]);
+    let pending_request = PendingRequest {
+        request_id: Uid::new(&rng),
+        route: fixture_route(route_seeds),
+        dest_payment,
+        invoice_id: InvoiceId::new(&rng),
+    };
+    let u_response_send_funds = ResponseSendFunds {
+        request_id: pending_request.request_id.clone(),
+        rand_nonce: RandValue::new(&rng),
+        signature: (),
+    };
+    let u_failure_send_funds = FailureSendFunds {
+        request_id: pending_request.request_id.clone(),
+        reporting_public_key: fixture_public_key(reporting_seed),
+        reason: failure_reason,
+        rand_nonce: RandValue::new(&rng),
+        signature: (),
+    };
+
+    // The destination signs the response:
+    let dest_seed = *route_seeds.last().unwrap();
+    let response_signature_buff =
+        create_response_signature_buffer(&u_response_send_funds, &pending_request);
+    let response_send_funds = ResponseSendFunds {
+        request_id: u_response_send_funds.request_id,
+        rand_nonce: u_response_send_funds.rand_nonce,
+        signature: fixture_software_identity(dest_seed).sign(&response_signature_buff),
+    };
+    let receipt = prepare_receipt(&response_send_funds, &pending_request);
+
+    let failure_signature_buff =
+        create_failure_signature_buffer(&u_failure_send_funds, &pending_request);
+    let failure_signature = fixture_software_identity(reporting_seed).sign(&failure_signature_buff);
+
+    SendFundsVector {
+        name: name.to_owned(),
+        route_seeds: route_seeds.to_vec(),
+        reporting_seed,
+        rand_seed,
+        request_id: to_hex(&pending_request.request_id),
+        route: route_vector(&pending_request.route),
+        dest_payment: dest_payment.to_string(),
+        invoice_id: to_hex(&pending_request.invoice_id),
+        rand_nonce: to_hex(&response_send_funds.rand_nonce),
+        reporting_public_key: to_hex(&u_failure_send_funds.reporting_public_key),
+        failure_reason: failure_reason.to_u16(),
+        failure_rand_nonce: to_hex(&u_failure_send_funds.rand_nonce),
+        response_signature_buff: to_hex(&response_signature_buff),
+        response_signature: to_hex(&response_send_funds.signature),
+        receipt_response_hash: to_hex(&receipt.response_hash),
+        failure_signature_buff: to_hex(&failure_signature_buff),
+        failure_signature: to_hex(&failure_signature),
+    }
+}
+
+fn gen_send_funds_vectors() -> Vec<SendFundsVector> {
+    vec![
+        send_funds_vector("two_nodes", &[1, 2], 2, 4, 10, FailureReason::Unspecified),
+        send_funds_vector(
+            "four_nodes",
+            &[1, 2, 3, 4],
+            3,
+            5,
+            u128::max_value() - 1,
+            FailureReason::PricingRejected,
+        ),
+    ]
+}
+
+fn route_id_vector(name: &str, route_seeds: &[u8]) -> RouteIdVector {
+    let route = fixture_route(route_seeds);
+    RouteIdVector {
+        name: name.to_owned(),
+        route_seeds: route_seeds.to_vec(),
+        route: route_vector(&route),
+        route_serialized: to_hex(&route.canonical_serialize()),
+        route_id: to_hex(route.id().as_hash_result()),
+    }
+}
+
+fn gen_route_id_vectors() -> Vec<RouteIdVector> {
+    vec![
+        route_id_vector("empty", &[]),
+        route_id_vector("two_nodes", &[1, 2]),
+        route_id_vector("three_nodes", &[1, 2, 3]),
+        route_id_vector("cycle", &[1, 2, 3, 1]),
+    ]
+}
+
+fn node_credits_vector(credit_calc: &CreditCalculator, node_index: u32) -> NodeCreditsVector {
+    NodeCreditsVector {
+        node_index,
+        credits_to_freeze: credit_calc
+            .credits_to_freeze(node_index)
+            .map(|credits| credits.to_string()),
+        credits_on_success: credit_calc
+            .credits_on_success(node_index)
+            .map(|credits| credits.to_string()),
+        max_credits_on_failure: credit_calc
+            .max_credits_on_failure(node_index)
+            .map(|credits| credits.to_string()),
+    }
+}
+
+/// Credits for all the nodes along a route of length `route_len`
+fn route_credits(route_len: usize, dest_payment: u128) -> Option<Vec<NodeCreditsVector>> {
+    let credit_calc = CreditCalculator::new(route_len, dest_payment).ok()?;
+    Some(
+        (0..usize_to_u32(route_len)?)
+            .map(|node_index| node_credits_vector(&credit_calc, node_index))
+            .collect(),
+    )
+}
+
+fn credit_calc_vector(name: &str, route_len: usize, dest_payment: u128) -> CreditCalcVector {
+    CreditCalcVector {
+        name: name.to_owned(),
+        route_len,
+        dest_payment: dest_payment.to_string(),
+        opt_nodes: route_credits(route_len, dest_payment),
+    }
+}
+
+fn gen_credit_calc_vectors() -> Vec<CreditCalcVector> {
+    vec![
+        credit_calc_vector("two_nodes", 2, 10),
+        credit_calc_vector("five_nodes", 5, 100),
+        credit_calc_vector("max_route_len", MAX_ROUTE_LEN, 1),
+        credit_calc_vector("route_too_long", MAX_ROUTE_LEN + 1, 1),
+        credit_calc_vector("payment_overflow", 3, u128::max_value()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::de::DeserializeOwned;
+
+    use common::ser_hex::from_hex;
+
+    use crypto::crypto_rand::RAND_VALUE_LEN;
+    use crypto::hash::sha_512_256;
+    use crypto::identity::{verify_signature, PUBLIC_KEY_LEN};
+    use crypto::invoice_id::INVOICE_ID_LEN;
+    use crypto::uid::UID_LEN;
+
+    use proto::funder::signature_buff::{verify_failure_signature, verify_response_signature};
+
+    const VECTOR_FILES: &[(&str, &str)] = &[
+        (
+            MOVE_TOKEN_FILE,
+            include_str!("../conformance/v0/move_token.json"),
+        ),
+        (
+            SEND_FUNDS_FILE,
+            include_str!("../conformance/v0/send_funds.json"),
+        ),
+        (
+            ROUTE_ID_FILE,
+            include_str!("../conformance/v0/route_id.json"),
+        ),
+        (
+            CREDIT_CALC_FILE,
+            include_str!("../conformance/v0/credit_calc.json"),
+        ),
+    ];
+
+    /// Load a vector file, and make sure it belongs to the current protocol version
+    fn load_vectors<V>(file_name: &str) -> Vec<V>
+    where
+        V: DeserializeOwned,
+    {
+        let (_, contents) = VECTOR_FILES
+            .iter()
+            .find(|(name, _)| *name == file_name)
+            .unwrap();
+        let vector_file: VectorFile<V> = serde_json::from_str(contents).unwrap();
+        assert_eq!(vector_file.protocol_version, PROTOCOL_VERSION);
+        vector_file.vectors
+    }
+
+    fn parse_fixed<T>(hex: &str, len: usize) -> T
+    where
+        T: for<'a> TryFrom<&'a [u8]>,
+    {
+        let bytes = from_hex(hex).unwrap();
+        assert_eq!(bytes.len(), len);
+        T::try_from(&bytes[..]).ok().unwrap()
+    }
+
+    fn parse_route(route: &[String]) -> FriendsRoute {
+        FriendsRoute {
+            public_keys: route
+                .iter()
+                .map(|public_key| parse_fixed(public_key, PUBLIC_KEY_LEN))
+                .collect(),
+        }
+    }
+
+    fn parse_op(op_vector: &OpVector) -> FriendTcOp {
+        match op_vector {
+            OpVector::EnableRequests => FriendTcOp::EnableRequests,
+            OpVector::DisableRequests => FriendTcOp::DisableRequests,
+            OpVector::SetRemoteMaxDebt { remote_max_debt } => {
+                FriendTcOp::SetRemoteMaxDebt(remote_max_debt.parse().unwrap())
+            }
+            OpVector::RequestSendFunds {
+                request_id,
+                route,
+                dest_payment,
+                invoice_id,
+            } => FriendTcOp::RequestSendFunds(RequestSendFunds {
+                request_id: parse_fixed(request_id, UID_LEN),
+                route: parse_route(route),
+                dest_payment: dest_payment.parse().unwrap(),
+                invoice_id: parse_fixed(invoice_id, INVOICE_ID_LEN),
+            }),
+            OpVector::ResponseSendFunds {
+                request_id,
+                rand_nonce,
+                signature,
+            } => FriendTcOp::ResponseSendFunds(ResponseSendFunds {
+                request_id: parse_fixed(request_id, UID_LEN),
+                rand_nonce: parse_fixed(rand_nonce, RAND_VALUE_LEN),
+                signature: parse_fixed(signature, SIGNATURE_LEN),
+            }),
+            OpVector::FailureSendFunds {
+                request_id,
+                reporting_public_key,
+                reason,
+                rand_nonce,
+                signature,
+            } => FriendTcOp::FailureSendFunds(FailureSendFunds {
+                request_id: parse_fixed(request_id, UID_LEN),
+                reporting_public_key: parse_fixed(reporting_public_key, PUBLIC_KEY_LEN),
+                reason: FailureReason::from_u16(*reason),
+                rand_nonce: parse_fixed(rand_nonce, RAND_VALUE_LEN),
+                signature: parse_fixed(signature, SIGNATURE_LEN),
+            }),
+        }
+    }
+
+    fn parse_relay(relay_vector: &RelayVector) -> RelayAddress {
+        RelayAddress {
+            public_key: parse_fixed(&relay_vector.public_key, PUBLIC_KEY_LEN),
+            address: NetAddress::try_from(relay_vector.address.clone()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_conformance_move_token() {
+        let vectors = load_vectors::<MoveTokenVector>(MOVE_TOKEN_FILE);
+        assert!(!vectors.is_empty());
+        for vector in &vectors {
+            let u_move_token = create_unsigned_move_token(
+                vector.operations.iter().map(parse_op).collect(),
+                vector
+                    .opt_local_relays
+                    .as_ref()
+                    .map(|local_relays| local_relays.iter().map(parse_relay).collect()),
+                parse_fixed(&vector.old_token, SIGNATURE_LEN),
+                parse_fixed(&vector.local_public_key, PUBLIC_KEY_LEN),
+                parse_fixed(&vector.remote_public_key, PUBLIC_KEY_LEN),
+                vector.inconsistency_counter,
+                vector.move_token_counter.parse().unwrap(),
+                vector.balance.parse().unwrap(),
+                vector.local_pending_debt.parse().unwrap(),
+                vector.remote_pending_debt.parse().unwrap(),
+                parse_fixed(&vector.rand_nonce, RAND_VALUE_LEN),
+            );
+
+            let operations_serialized = u_move_token
+                .operations
+                .iter()
+                .map(|op| to_hex(&op.canonical_serialize()))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                operations_serialized, vector.operations_serialized,
+                "{}",
+                vector.name
+            );
+            assert_eq!(
+                to_hex(&u_move_token.opt_local_relays.canonical_serialize()),
+                vector.local_relays_serialized,
+                "{}",
+                vector.name
+            );
+            assert_eq!(
+                to_hex(&prefix_hash(&u_move_token)),
+                vector.prefix_hash,
+                "{}",
+                vector.name
+            );
+
+            let signature_buff = move_token_signature_buff(&u_move_token);
+            assert_eq!(
+                to_hex(&signature_buff),
+                vector.signature_buff,
+                "{}",
+                vector.name
+            );
+
+            // new_token is the signature of the local side over the signature buffer:
+            let new_token: Signature = parse_fixed(&vector.new_token, SIGNATURE_LEN);
+            assert!(verify_signature(
+                &signature_buff,
+                &u_move_token.local_public_key,
+                &new_token
+            ));
+            let identity = fixture_software_identity(vector.signer_seed);
+            assert_eq!(identity.get_public_key(), u_move_token.local_public_key);
+            assert_eq!(identity.sign(&signature_buff), new_token);
+        }
+    }
+
+    #[test]
+    fn test_conformance_send_funds() {
+        let vectors = load_vectors::<SendFundsVector>(SEND_FUNDS_FILE);
+        assert!(!vectors.is_empty());
+        for vector in &vectors {
+            let pending_request = PendingRequest {
+                request_id: parse_fixed(&vector.request_id, UID_LEN),
+                route: parse_route(&vector.route),
+                dest_payment: vector.dest_payment.parse().unwrap(),
+                invoice_id: parse_fixed(&vector.invoice_id, INVOICE_ID_LEN),
+            };
+            assert_eq!(pending_request.route, fixture_route(&vector.route_seeds));
+
+            let response_send_funds = ResponseSendFunds {
+                request_id: pending_request.request_id.clone(),
+                rand_nonce: parse_fixed(&vector.rand_nonce, RAND_VALUE_LEN),
+                signature: parse_fixed(&vector.response_signature, SIGNATURE_LEN),
+            };
+            assert_eq!(
+                to_hex(&create_response_signature_buffer(
+                    &response_send_funds,
+                    &pending_request
+                )),
+                vector.response_signature_buff,
+                "{}",
+                vector.name
+            );
+            assert!(verify_response_signature(
+                &response_send_funds,
+                &pending_request
+            ));
+            let receipt = prepare_receipt(&response_send_funds, &pending_request);
+            assert_eq!(
+                to_hex(&receipt.response_hash),
+                vector.receipt_response_hash,
+                "{}",
+                vector.name
+            );
+
+            let failure_send_funds = FailureSendFunds {
+                request_id: pending_request.request_id.clone(),
+                reporting_public_key: parse_fixed(&vector.reporting_public_key, PUBLIC_KEY_LEN),
+                reason: FailureReason::from_u16(vector.failure_reason),
+                rand_nonce: parse_fixed(&vector.failure_rand_nonce, RAND_VALUE_LEN),
+                signature: parse_fixed(&vector.failure_signature, SIGNATURE_LEN),
+            };
+            assert_eq!(
+                failure_send_funds.reporting_public_key,
+                fixture_public_key(vector.reporting_seed)
+            );
+            assert_eq!(
+                to_hex(&create_failure_signature_buffer(
+                    &failure_send_funds,
+                    &pending_request
+                )),
+                vector.failure_signature_buff,
+                "{}",
+                vector.name
+            );
+            assert!(verify_failure_signature(&failure_send_funds, &pending_request).is_some());
+        }
+    }
+
+    #[test]
+    fn test_conformance_route_id() {
+        let vectors = load_vectors::<RouteIdVector>(ROUTE_ID_FILE);
+        assert!(!vectors.is_empty());
+        for vector in &vectors {
+            let route = parse_route(&vector.route);
+            assert_eq!(route, fixture_route(&vector.route_seeds));
+
+            let route_serialized = route.canonical_serialize();
+            assert_eq!(
+                to_hex(&route_serialized),
+                vector.route_serialized,
+                "{}",
+                vector.name
+            );
+
+            // The route id is the hash of the canonical serialization:
+            let route_id = route.id();
+            assert_eq!(
+                to_hex(route_id.as_hash_result()),
+                vector.route_id,
+                "{}",
+                vector.name
+            );
+            assert_eq!(route_id.as_hash_result(), &sha_512_256(&route_serialized));
+        }
+    }
+
+    #[test]
+    fn test_conformance_credit_calc() {
+        let vectors = load_vectors::<CreditCalcVector>(CREDIT_CALC_FILE);
+        assert!(!vectors.is_empty());
+        for vector in &vectors {
+            let dest_payment = vector.dest_payment.parse().unwrap();
+            assert_eq!(
+                route_credits(vector.route_len, dest_payment),
+                vector.opt_nodes,
+                "{}",
+                vector.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_conformance_files_up_to_date() {
+        // The files on disk are exactly what the generator creates.
+        // If this test fails, see conformance/README.md
+        assert_eq!(version_dir_name(), "v0");
+        let generated_files = generate_vector_files();
+        assert_eq!(generated_files.len(), VECTOR_FILES.len());
+        for (file_name, generated_contents) in generated_files {
+            let (_, contents) = VECTOR_FILES
+                .iter()
+                .find(|(name, _)| *name == file_name)
+                .unwrap();
+            let generated: serde_json::Value = serde_json::from_str(&generated_contents).unwrap();
+            let expected: serde_json::Value = serde_json::from_str(contents).unwrap();
+            assert_eq!(generated, expected, "{}", file_name);
+        }
+    }
+}
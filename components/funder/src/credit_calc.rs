@@ -1,7 +1,10 @@
 #![warn(unused)]
 
 use std::convert::TryFrom;
-// use utils::int_convert::usize_to_u32;
+
+use common::int_convert::usize_to_u32;
+
+use proto::consts::MAX_ROUTE_LEN;
 
 // TODO: Why do we take node_index and route_len as u32?
 // Possibly change this in the future?
@@ -26,8 +29,30 @@ pub fn credits_on_success(node_index: u32, route_len: u32, dest_payment: u128) -
 
 /// The amount of credits paid to a node in case of failure.
 ///
-pub fn credits_on_failure() -> Option<u128> {
-    Some(0)
+/// ```text
+///                           req      req      req
+///                           fail     fail
+///                    B  --   C   --  (D)   --   E   --   F
+///
+/// node_index:        0       1        2         3        4
+/// ```
+///
+/// In the example above, if D reports a failure, C is paid using node_index = 1 and
+/// reporting_node_index = 2.
+///
+/// The reporting node must be on the route, and must be the node itself or a node after it.
+/// Note that the destination may also report a failure (For example, if the payment is too
+/// small).
+///
+pub fn credits_on_failure(
+    node_index: u32,
+    reporting_node_index: u32,
+    route_len: u32,
+) -> Result<u128, CreditCalcError> {
+    if reporting_node_index < node_index || reporting_node_index >= route_len {
+        return Err(CreditCalcError::InvalidReportingNode);
+    }
+    Ok(0)
 }
 
 /// Compute the amount of credits we need to freeze when sending a request to a node which is
@@ -48,18 +73,62 @@ pub fn credits_to_freeze(node_index: u32, route_len: u32, dest_payment: u128) ->
     credits_on_success(node_index, route_len, dest_payment)
 }
 
+/// The maximum dest_payment for which the source node freezes at most `max_freeze_credits`
+/// credits when sending the request to the first node on the route (node_index = 1).
+/// Returns None if not even a zero payment fits.
+///
+/// The amount of credits to freeze is linear in dest_payment (with a slope of 1), so we solve
+/// for dest_payment directly, instead of searching for it.
+///
+pub fn max_dest_payment(route_len: u32, max_freeze_credits: u128) -> Option<u128> {
+    let fees = credits_to_freeze(1, route_len, 0)?;
+    let dest_payment = max_freeze_credits.checked_sub(fees)?;
+    debug_assert_eq!(
+        credits_to_freeze(1, route_len, dest_payment),
+        Some(max_freeze_credits)
+    );
+    Some(dest_payment)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CreditCalcError {
+    /// The route has less than 2 nodes
+    RouteTooShort,
+    /// The route is longer than MAX_ROUTE_LEN
+    RouteTooLong,
+    /// The amount of credits to freeze for the payment does not fit in a u128
+    CreditsOverflow,
+    /// The reporting node of a failure is not on the route, or is before the paid node
+    InvalidReportingNode,
+}
+
 /// A credit calculator object that is wired to work with a specific request.
+#[derive(Debug)]
 pub struct CreditCalculator {
     route_len: u32,
     dest_payment: u128,
 }
 
 impl CreditCalculator {
-    pub fn new(route_len: u32, dest_payment: u128) -> Self {
-        CreditCalculator {
-            route_len,
-            dest_payment,
+    /// Create a credit calculator for a route of length `route_len`.
+    /// Routes shorter than 2 nodes or longer than MAX_ROUTE_LEN are rejected, and so are
+    /// payments for which the amount of credits to freeze overflows.
+    pub fn new(route_len: usize, dest_payment: u128) -> Result<Self, CreditCalcError> {
+        if route_len < 2 {
+            return Err(CreditCalcError::RouteTooShort);
         }
+        if route_len > MAX_ROUTE_LEN {
+            return Err(CreditCalcError::RouteTooLong);
+        }
+        let credit_calc = CreditCalculator {
+            route_len: usize_to_u32(route_len).ok_or(CreditCalcError::RouteTooLong)?,
+            dest_payment,
+        };
+        // The first node on the route freezes the most credits:
+        credit_calc
+            .credits_to_freeze(1)
+            .ok_or(CreditCalcError::CreditsOverflow)?;
+        Ok(credit_calc)
     }
 
     /// Amount of credits node <index-1> should freeze when sending
@@ -79,8 +148,26 @@ impl CreditCalculator {
     /// Amount of credits to be paid to node <index> when it sends a failure message to node
     /// <index-1>
     /// Source node has index 0. Destination node has index route_len - 1.
-    pub fn credits_on_failure(&self, _node_index: u32, _reporting_node_index: u32) -> Option<u128> {
-        credits_on_failure()
+    pub fn credits_on_failure(
+        &self,
+        node_index: u32,
+        reporting_node_index: u32,
+    ) -> Result<u128, CreditCalcError> {
+        credits_on_failure(node_index, reporting_node_index, self.route_len)
+    }
+
+    /// The maximum amount of credits to be paid to node <index> when any node from <index> onwards
+    /// sends a failure message.
+    /// Source node has index 0. Destination node has index route_len - 1.
+    pub fn max_credits_on_failure(&self, node_index: u32) -> Option<u128> {
+        let mut max_credits = 0;
+        for reporting_node_index in node_index..self.route_len {
+            let credits = self
+                .credits_on_failure(node_index, reporting_node_index)
+                .ok()?;
+            max_credits = max_credits.max(credits);
+        }
+        Some(max_credits)
     }
 }
 
@@ -90,7 +177,51 @@ mod tests {
     // use num_traits::PrimInt;
     // use std::cmp;
 
-    // TODO: Add tests for CreditCalculator.
+    #[test]
+    fn test_credit_calculator_max_route_len() {
+        let credit_calc = CreditCalculator::new(MAX_ROUTE_LEN, 100).unwrap();
+        let max_route_len = usize_to_u32(MAX_ROUTE_LEN).unwrap();
+        assert_eq!(
+            credit_calc.credits_to_freeze(1),
+            credits_to_freeze(1, max_route_len, 100)
+        );
+        assert_eq!(
+            credit_calc.credits_on_success(max_route_len - 1),
+            Some(100)
+        );
+
+        assert_eq!(
+            CreditCalculator::new(MAX_ROUTE_LEN + 1, 100).unwrap_err(),
+            CreditCalcError::RouteTooLong
+        );
+    }
+
+    #[test]
+    fn test_credit_calculator_new_errors() {
+        assert_eq!(
+            CreditCalculator::new(0, 100).unwrap_err(),
+            CreditCalcError::RouteTooShort
+        );
+        assert_eq!(
+            CreditCalculator::new(1, 100).unwrap_err(),
+            CreditCalcError::RouteTooShort
+        );
+
+        // A direct payment to a friend has no fees, so any payment fits:
+        assert!(CreditCalculator::new(2, u128::max_value()).is_ok());
+        // Every mediator adds to the amount of credits to freeze:
+        assert_eq!(
+            CreditCalculator::new(3, u128::max_value()).unwrap_err(),
+            CreditCalcError::CreditsOverflow
+        );
+        let max_route_len = usize_to_u32(MAX_ROUTE_LEN).unwrap();
+        let max_dest_payment = max_dest_payment(max_route_len, u128::max_value()).unwrap();
+        assert!(CreditCalculator::new(MAX_ROUTE_LEN, max_dest_payment).is_ok());
+        assert_eq!(
+            CreditCalculator::new(MAX_ROUTE_LEN, max_dest_payment + 1).unwrap_err(),
+            CreditCalcError::CreditsOverflow
+        );
+    }
 
     /*
     fn is_linear<F,N,M>(f: F, begin: N, end: N) -> bool
@@ -134,6 +265,97 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_max_dest_payment() {
+        // (route_len, max_freeze_credits, expected max dest_payment):
+        let cases = [
+            // A direct payment to a friend has no fees:
+            (2, 100, Some(100)),
+            (2, 0, Some(0)),
+            // Every mediator on the route takes one credit:
+            (3, 100, Some(99)),
+            (5, 100, Some(97)),
+            (5, 3, Some(0)),
+            (5, 2, None),
+            (40, 1000, Some(962)),
+            (2, u128::max_value(), Some(u128::max_value())),
+        ];
+        for &(route_len, max_freeze_credits, expected) in &cases {
+            let opt_dest_payment = max_dest_payment(route_len, max_freeze_credits);
+            assert_eq!(opt_dest_payment, expected);
+            if let Some(dest_payment) = opt_dest_payment {
+                // The payment freezes exactly the maximum:
+                assert_eq!(
+                    credits_to_freeze(1, route_len, dest_payment),
+                    Some(max_freeze_credits)
+                );
+                // One more credit would not fit:
+                let opt_freeze_credits = dest_payment
+                    .checked_add(1)
+                    .and_then(|dest_payment| credits_to_freeze(1, route_len, dest_payment));
+                if let Some(freeze_credits) = opt_freeze_credits {
+                    assert!(freeze_credits > max_freeze_credits);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_credits_on_failure_invalid_reporting_node() {
+        let credit_calc = CreditCalculator::new(5, 100).unwrap();
+
+        // The node itself, the node right before the destination and the destination may report a
+        // failure:
+        assert_eq!(credit_calc.credits_on_failure(1, 1), Ok(0));
+        assert_eq!(credit_calc.credits_on_failure(1, 3), Ok(0));
+        assert_eq!(credit_calc.credits_on_failure(1, 4), Ok(0));
+        assert_eq!(credit_calc.credits_on_failure(4, 4), Ok(0));
+
+        // Reporting nodes outside of the route:
+        for &reporting_node_index in &[5, 6, u32::max_value()] {
+            assert_eq!(
+                credit_calc.credits_on_failure(1, reporting_node_index),
+                Err(CreditCalcError::InvalidReportingNode)
+            );
+        }
+
+        // A reporting node before the paid node:
+        assert_eq!(
+            credit_calc.credits_on_failure(2, 1),
+            Err(CreditCalcError::InvalidReportingNode)
+        );
+
+        // An empty route has no reporting nodes:
+        assert_eq!(
+            credits_on_failure(0, 0, 0),
+            Err(CreditCalcError::InvalidReportingNode)
+        );
+    }
+
+    #[test]
+    fn test_max_credits_on_failure() {
+        for route_len in 2..=MAX_ROUTE_LEN {
+            let credit_calc = CreditCalculator::new(route_len, 100).unwrap();
+            let route_len = usize_to_u32(route_len).unwrap();
+            for node_index in 1..route_len {
+                // Brute force over all the nodes that may report a failure:
+                let mut expected = Some(0);
+                for reporting_node_index in node_index..route_len {
+                    let credits = credit_calc.credits_on_failure(node_index, reporting_node_index);
+                    expected = match (expected, credits) {
+                        (Some(expected), Ok(credits)) => Some(expected.max(credits)),
+                        _ => None,
+                    };
+                }
+                let max_credits = credit_calc.max_credits_on_failure(node_index);
+                assert_eq!(max_credits, expected);
+                assert!(max_credits.unwrap() <= credit_calc.credits_to_freeze(node_index).unwrap());
+            }
+            // No credits are paid for a failure reported by the destination:
+            assert_eq!(credit_calc.max_credits_on_failure(route_len - 1), Some(0));
+        }
+    }
+
     #[test]
     fn test_credits_to_freeze_bigger_than_success() {
         let route_len = 40;
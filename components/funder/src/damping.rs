@@ -0,0 +1,91 @@
+use crypto::identity::PublicKey;
+use im::hashmap::HashMap as ImHashMap;
+
+/// Keeps track of recent changes to the relays of friends.
+/// After a change of relays was applied for a friend, further changes of relays for this friend
+/// are delayed until a few ticks have passed.
+#[derive(Clone, Default)]
+pub struct RelaysDamping {
+    /// Amount of ticks left until the next change of relays can be applied, for every friend.
+    /// A friend that does not appear here may have his relays changed immediately.
+    pub ticks_left: ImHashMap<PublicKey, usize>,
+}
+
+#[derive(Debug)]
+pub enum RelaysDampingMutation {
+    /// A change of relays was applied for a friend.
+    /// The next change will be possible only after the given amount of ticks.
+    SetChanged((PublicKey, usize)),
+    /// The given amount of time ticks has passed.
+    Tick(usize),
+}
+
+impl RelaysDamping {
+    pub fn new() -> RelaysDamping {
+        RelaysDamping {
+            ticks_left: ImHashMap::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &RelaysDampingMutation) {
+        match mutation {
+            RelaysDampingMutation::SetChanged((public_key, ticks)) => {
+                if *ticks > 0 {
+                    self.ticks_left.insert(public_key.clone(), *ticks);
+                } else {
+                    let _ = self.ticks_left.remove(public_key);
+                }
+            }
+            RelaysDampingMutation::Tick(ticks_elapsed) => {
+                let mut ticks_left = ImHashMap::new();
+                for (public_key, ticks) in &self.ticks_left {
+                    if *ticks > *ticks_elapsed {
+                        ticks_left.insert(public_key.clone(), *ticks - *ticks_elapsed);
+                    }
+                }
+                self.ticks_left = ticks_left;
+            }
+        }
+    }
+
+    /// Is a change of relays for this friend currently delayed?
+    pub fn is_damped(&self, friend_public_key: &PublicKey) -> bool {
+        self.ticks_left.contains_key(friend_public_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    #[test]
+    fn test_relays_damping_basic() {
+        let mut relays_damping = RelaysDamping::new();
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        assert!(!relays_damping.is_damped(&pk_a));
+        assert!(!relays_damping.is_damped(&pk_b));
+
+        relays_damping.mutate(&RelaysDampingMutation::SetChanged((pk_a.clone(), 2)));
+        relays_damping.mutate(&RelaysDampingMutation::SetChanged((pk_b.clone(), 0)));
+        assert!(relays_damping.is_damped(&pk_a));
+        assert!(!relays_damping.is_damped(&pk_b));
+
+        relays_damping.mutate(&RelaysDampingMutation::Tick(1));
+        assert!(relays_damping.is_damped(&pk_a));
+
+        relays_damping.mutate(&RelaysDampingMutation::SetChanged((pk_b.clone(), 1)));
+        assert!(relays_damping.is_damped(&pk_b));
+
+        relays_damping.mutate(&RelaysDampingMutation::Tick(1));
+        assert!(!relays_damping.is_damped(&pk_a));
+        assert!(!relays_damping.is_damped(&pk_b));
+
+        // Many ticks elapsed at once:
+        relays_damping.mutate(&RelaysDampingMutation::SetChanged((pk_a.clone(), 3)));
+        relays_damping.mutate(&RelaysDampingMutation::Tick(10_000));
+        assert!(!relays_damping.is_damped(&pk_a));
+    }
+}
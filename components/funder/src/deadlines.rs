@@ -0,0 +1,180 @@
+use std::fmt::Debug;
+
+use common::canonical_serialize::CanonicalSerialize;
+use crypto::identity::PublicKey;
+use crypto::uid::Uid;
+use im::hashmap::HashMap as ImHashMap;
+use im::hashset::HashSet as ImHashSet;
+
+use proto::report::messages::FriendDeadlinesReport;
+
+use crate::ephemeral::Ephemeral;
+use crate::friend::{ChannelStatus, FriendState};
+
+/// Keeps track of the deadlines of friends, as they were last reported.
+/// A deadline is reported again only when it changes by at least the configured granularity,
+/// to avoid a report mutation every tick.
+#[derive(Clone, Default)]
+pub struct ReportedDeadlines {
+    pub friends: ImHashMap<PublicKey, FriendDeadlinesReport>,
+}
+
+#[derive(Debug)]
+pub enum ReportedDeadlinesMutation {
+    /// The deadlines of a friend were reported.
+    Set((PublicKey, FriendDeadlinesReport)),
+    /// The friend was removed.
+    Forget(PublicKey),
+}
+
+impl ReportedDeadlines {
+    pub fn new() -> ReportedDeadlines {
+        ReportedDeadlines {
+            friends: ImHashMap::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &ReportedDeadlinesMutation) {
+        match mutation {
+            ReportedDeadlinesMutation::Set((public_key, deadlines)) => {
+                self.friends.insert(public_key.clone(), deadlines.clone());
+            }
+            ReportedDeadlinesMutation::Forget(public_key) => {
+                let _ = self.friends.remove(public_key);
+            }
+        }
+    }
+
+    /// Get the last reported deadlines of a friend.
+    /// A friend whose deadlines were never reported has no deadlines.
+    pub fn get(&self, friend_public_key: &PublicKey) -> FriendDeadlinesReport {
+        self.friends
+            .get(friend_public_key)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Calculate the amount of ticks until the soonest response deadline of a request we have
+/// forwarded to a friend.
+fn calc_response_timeout_ticks<B>(
+    friend: &FriendState<B>,
+    ephemeral: &Ephemeral,
+    expired_requests: &ImHashSet<Uid>,
+) -> Option<u64>
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let deadline_ticks = friend.opt_response_deadline_ticks?;
+    let token_channel = match &friend.channel_status {
+        ChannelStatus::Consistent(token_channel) => token_channel,
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => return None,
+    };
+
+    token_channel
+        .get_mutual_credit()
+        .state()
+        .pending_requests
+        .pending_local_requests
+        .values()
+        // Only requests we have forwarded have a response deadline:
+        .filter(|pending_request| {
+            pending_request.route.index_to_pk(0) != Some(&friend.local_public_key)
+                && !expired_requests.contains(&pending_request.request_id)
+        })
+        .map(|pending_request| {
+            // A request that was forwarded after the last tick is not tracked yet:
+            ephemeral
+                .response_deadlines
+                .ticks_left
+                .get(&pending_request.request_id)
+                .cloned()
+                .unwrap_or(deadline_ticks)
+        })
+        .min()
+}
+
+/// Calculate the current deadlines of a friend, relative to the current tick.
+pub fn calc_friend_deadlines<B>(
+    friend: &FriendState<B>,
+    ephemeral: &Ephemeral,
+    expired_requests: &ImHashSet<Uid>,
+    friend_public_key: &PublicKey,
+) -> FriendDeadlinesReport
+where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    FriendDeadlinesReport {
+        opt_reconnect_ticks: ephemeral
+            .goodbyes
+            .ticks_left
+            .get(friend_public_key)
+            .cloned(),
+        opt_response_timeout_ticks: calc_response_timeout_ticks(friend, ephemeral, expired_requests),
+        opt_remote_max_debt_expiry_ticks: friend
+            .opt_remote_max_debt_expiry
+            .as_ref()
+            .map(|remote_max_debt_expiry| remote_max_debt_expiry.expires_after_ticks),
+    }
+}
+
+fn is_ticks_changed(old: Option<u64>, new: Option<u64>, granularity_ticks: u64) -> bool {
+    match (old, new) {
+        (Some(old), Some(new)) => old != new && (old.max(new) - old.min(new)) >= granularity_ticks,
+        (None, None) => false,
+        (Some(_), None) | (None, Some(_)) => true,
+    }
+}
+
+/// Should the new deadlines of a friend be reported, given the last reported deadlines?
+/// A deadline that was set or cleared is always reported.
+pub fn is_deadlines_changed(
+    reported: &FriendDeadlinesReport,
+    current: &FriendDeadlinesReport,
+    granularity_ticks: u64,
+) -> bool {
+    is_ticks_changed(
+        reported.opt_reconnect_ticks,
+        current.opt_reconnect_ticks,
+        granularity_ticks,
+    ) || is_ticks_changed(
+        reported.opt_response_timeout_ticks,
+        current.opt_response_timeout_ticks,
+        granularity_ticks,
+    ) || is_ticks_changed(
+        reported.opt_remote_max_debt_expiry_ticks,
+        current.opt_remote_max_debt_expiry_ticks,
+        granularity_ticks,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_deadlines_changed() {
+        let reported = FriendDeadlinesReport {
+            opt_reconnect_ticks: Some(10),
+            opt_response_timeout_ticks: None,
+            opt_remote_max_debt_expiry_ticks: None,
+        };
+
+        // Small changes are not reported:
+        let mut current = reported.clone();
+        current.opt_reconnect_ticks = Some(8);
+        assert!(!is_deadlines_changed(&reported, &current, 3));
+        current.opt_reconnect_ticks = Some(7);
+        assert!(is_deadlines_changed(&reported, &current, 3));
+
+        // A deadline that was cleared or set is always reported:
+        current.opt_reconnect_ticks = None;
+        assert!(is_deadlines_changed(&reported, &current, 3));
+        let mut current = reported.clone();
+        current.opt_response_timeout_ticks = Some(100);
+        assert!(is_deadlines_changed(&reported, &current, 3));
+
+        // Equal deadlines are never reported:
+        assert!(!is_deadlines_changed(&reported, &reported, 0));
+    }
+}
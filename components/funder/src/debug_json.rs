@@ -0,0 +1,658 @@
+//! A human readable (JSON) rendering of the funder state, meant for inspecting the state of a
+//! node while debugging.
+//!
+//! The rendering is one way: A funder state can not be loaded from its JSON rendering. This
+//! avoids hand-edited states. Differences from a plain serde_json serialization:
+//!
+//! - Byte arrays (Public keys, signatures, hashes etc.) are rendered as hex strings.
+//! - i128 and u128 values are rendered as strings. JSON numbers can not represent them
+//!   precisely in many implementations.
+//! - Maps are rendered as arrays of `{"key": ..., "value": ...}` entries, sorted by key.
+//!   This keeps the rendering deterministic, and allows keys that are not strings.
+//! - Tokens are redacted, unless a full rendering is requested.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use serde::ser::{self, Serialize};
+use serde_json::{Map, Value};
+
+use common::ser_hex::to_hex;
+
+use proto::signed_export::create_export_body;
+
+use crate::state::FunderState;
+
+/// Fields that contain tokens: Signatures that prove a balance, allow to reset a channel or
+/// prove that a payment was made (Receipts).
+const TOKEN_FIELDS: &[&str] = &["old_token", "new_token", "reset_token", "signature"];
+
+/// Rendered instead of a redacted token
+const REDACTED: &str = "<redacted>";
+
+/// Every state export begins with these bytes.
+pub const STATE_EXPORT_MAGIC: &[u8; 8] = b"OFSTSTA\0";
+
+/// Version of the state export format.
+/// Should be incremented whenever the rendering of the funder state changes.
+pub const STATE_EXPORT_VERSION: u32 = 0;
+
+#[derive(Debug)]
+pub struct DebugJsonError(String);
+
+impl fmt::Display for DebugJsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DebugJsonError: {}", self.0)
+    }
+}
+
+impl std::error::Error for DebugJsonError {}
+
+impl ser::Error for DebugJsonError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        DebugJsonError(msg.to_string())
+    }
+}
+
+/// Render a funder state as pretty JSON.
+/// Tokens are redacted unless `full` is true.
+pub fn funder_state_to_json<B>(
+    funder_state: &FunderState<B>,
+    full: bool,
+) -> Result<String, DebugJsonError>
+where
+    B: Clone + Serialize,
+{
+    let value = funder_state.serialize(ValueSerializer { full })?;
+    serde_json::to_string_pretty(&value).map_err(|e| DebugJsonError(e.to_string()))
+}
+
+/// Create the body of a state export (See `proto::signed_export`), containing the JSON rendering
+/// of a funder state. The body should be signed by the node before it is handed to others.
+pub fn create_state_export<B>(
+    funder_state: &FunderState<B>,
+    full: bool,
+) -> Result<Vec<u8>, DebugJsonError>
+where
+    B: Clone + Serialize,
+{
+    let json = funder_state_to_json(funder_state, full)?;
+    Ok(create_export_body(
+        STATE_EXPORT_MAGIC,
+        STATE_EXPORT_VERSION,
+        json.as_bytes(),
+    ))
+}
+
+/// Serializes into a `serde_json::Value`, applying the rendering rules of this module.
+#[derive(Clone, Copy)]
+struct ValueSerializer {
+    full: bool,
+}
+
+impl ValueSerializer {
+    fn field_value<T>(self, key: &'static str, value: &T) -> Result<Value, DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        if !self.full && TOKEN_FIELDS.contains(&key) {
+            Ok(Value::String(REDACTED.to_owned()))
+        } else {
+            value.serialize(self)
+        }
+    }
+}
+
+/// A JSON object with a single field, used for enum variants that have contents
+fn variant_value(variant: &'static str, value: Value) -> Value {
+    let mut map = Map::new();
+    map.insert(variant.to_owned(), value);
+    Value::Object(map)
+}
+
+/// Order map keys. Unsigned integers are ordered by value, anything else by its JSON text.
+fn compare_keys(a: &Value, b: &Value) -> Ordering {
+    match (a.as_u64(), b.as_u64()) {
+        (Some(a), Some(b)) => a.cmp(&b),
+        _ => a.to_string().cmp(&b.to_string()),
+    }
+}
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = DebugJsonError;
+
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = TupleVariantSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = StructSerializer;
+    type SerializeStructVariant = StructVariantSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, DebugJsonError> {
+        Ok(Value::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, DebugJsonError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, DebugJsonError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, DebugJsonError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, DebugJsonError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Value, DebugJsonError> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, DebugJsonError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, DebugJsonError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, DebugJsonError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, DebugJsonError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Value, DebugJsonError> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, DebugJsonError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, DebugJsonError> {
+        Ok(Value::from(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, DebugJsonError> {
+        Ok(Value::String(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, DebugJsonError> {
+        Ok(Value::String(v.to_owned()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, DebugJsonError> {
+        Ok(Value::String(to_hex(v)))
+    }
+
+    fn serialize_none(self) -> Result<Value, DebugJsonError> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Value, DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, DebugJsonError> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, DebugJsonError> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value, DebugJsonError> {
+        Ok(Value::String(variant.to_owned()))
+    }
+
+    fn serialize_newtype_struct<T>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        Ok(variant_value(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, DebugJsonError> {
+        Ok(SeqSerializer {
+            serializer: self,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, DebugJsonError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, DebugJsonError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<TupleVariantSerializer, DebugJsonError> {
+        Ok(TupleVariantSerializer {
+            variant,
+            seq: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapSerializer, DebugJsonError> {
+        Ok(MapSerializer {
+            serializer: self,
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            opt_key: None,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<StructSerializer, DebugJsonError> {
+        Ok(StructSerializer {
+            serializer: self,
+            fields: Map::new(),
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<StructVariantSerializer, DebugJsonError> {
+        Ok(StructVariantSerializer {
+            variant,
+            fields: self.serialize_struct(name, len)?,
+        })
+    }
+}
+
+struct SeqSerializer {
+    serializer: ValueSerializer,
+    items: Vec<Value>,
+}
+
+impl SeqSerializer {
+    fn push<T>(&mut self, value: &T) -> Result<(), DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.items.push(value.serialize(self.serializer)?);
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Value;
+    type Error = DebugJsonError;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, DebugJsonError> {
+        Ok(Value::Array(self.items))
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Value;
+    type Error = DebugJsonError;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, DebugJsonError> {
+        Ok(Value::Array(self.items))
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Value;
+    type Error = DebugJsonError;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, DebugJsonError> {
+        Ok(Value::Array(self.items))
+    }
+}
+
+struct TupleVariantSerializer {
+    variant: &'static str,
+    seq: SeqSerializer,
+}
+
+impl ser::SerializeTupleVariant for TupleVariantSerializer {
+    type Ok = Value;
+    type Error = DebugJsonError;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.seq.push(value)
+    }
+
+    fn end(self) -> Result<Value, DebugJsonError> {
+        Ok(variant_value(self.variant, Value::Array(self.seq.items)))
+    }
+}
+
+struct MapSerializer {
+    serializer: ValueSerializer,
+    entries: Vec<(Value, Value)>,
+    /// A key waiting for its value
+    opt_key: Option<Value>,
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Value;
+    type Error = DebugJsonError;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.opt_key = Some(key.serialize(self.serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        let key = self
+            .opt_key
+            .take()
+            .ok_or_else(|| DebugJsonError("Map value without a key".to_owned()))?;
+        self.entries.push((key, value.serialize(self.serializer)?));
+        Ok(())
+    }
+
+    fn end(mut self) -> Result<Value, DebugJsonError> {
+        self.entries.sort_by(|(a, _), (b, _)| compare_keys(a, b));
+        let entries = self
+            .entries
+            .into_iter()
+            .map(|(key, value)| {
+                let mut entry = Map::new();
+                entry.insert("key".to_owned(), key);
+                entry.insert("value".to_owned(), value);
+                Value::Object(entry)
+            })
+            .collect();
+        Ok(Value::Array(entries))
+    }
+}
+
+struct StructSerializer {
+    serializer: ValueSerializer,
+    fields: Map<String, Value>,
+}
+
+impl ser::SerializeStruct for StructSerializer {
+    type Ok = Value;
+    type Error = DebugJsonError;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        let value = self.serializer.field_value(key, value)?;
+        self.fields.insert(key.to_owned(), value);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, DebugJsonError> {
+        Ok(Value::Object(self.fields))
+    }
+}
+
+struct StructVariantSerializer {
+    variant: &'static str,
+    fields: StructSerializer,
+}
+
+impl ser::SerializeStructVariant for StructVariantSerializer {
+    type Ok = Value;
+    type Error = DebugJsonError;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), DebugJsonError>
+    where
+        T: ?Sized + Serialize,
+    {
+        ser::SerializeStruct::serialize_field(&mut self.fields, key, value)
+    }
+
+    fn end(self) -> Result<Value, DebugJsonError> {
+        Ok(variant_value(
+            self.variant,
+            Value::Object(self.fields.fields),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use common::big_array::BigArray;
+    use crypto::hash::{HashResult, HASH_RESULT_LEN};
+    use crypto::identity::{Identity, PublicKey, Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+    use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+    use crypto::test_utils::fixture_software_identity;
+
+    use proto::funder::messages::{AddFriend, Receipt};
+    use proto::signed_export::{sign_export, verify_export, SignedExportError, EXPORT_HEADER_LEN};
+
+    use crate::state::FunderMutation;
+
+    /// Serialized like `PublicKey` before the human readable adapters were added
+    #[derive(Serialize)]
+    struct LegacyPublicKey([u8; PUBLIC_KEY_LEN]);
+
+    /// Serialized like `Signature` before the human readable adapters were added
+    #[derive(Serialize)]
+    struct LegacySignature(#[serde(with = "BigArray")] [u8; SIGNATURE_LEN]);
+
+    fn fixture_state() -> FunderState<u32> {
+        let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::new(local_public_key, Vec::new());
+        for (friend_byte, balance) in &[(0xcc, 3), (0xbb, -5)] {
+            state.mutate(&FunderMutation::AddFriend(AddFriend {
+                friend_public_key: PublicKey::from(&[*friend_byte; PUBLIC_KEY_LEN]),
+                relays: Vec::new(),
+                name: "friend".to_owned(),
+                balance: *balance,
+            }));
+        }
+        for dest_payment in &[10, 9] {
+            let receipt = Receipt {
+                response_hash: HashResult::from(&[0x11; HASH_RESULT_LEN]),
+                invoice_id: InvoiceId::from(&[0x22; INVOICE_ID_LEN]),
+                dest_payment: *dest_payment,
+                signature: Signature::from(&[0x33; SIGNATURE_LEN]),
+            };
+            state.mutate(&FunderMutation::AddIncomingPayment((receipt, 2)));
+        }
+        state
+    }
+
+    /// Find the first value of a field with the given name, searching depth first
+    fn find_field<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+        match value {
+            Value::Object(map) => map
+                .get(key)
+                .or_else(|| map.values().find_map(|value| find_field(value, key))),
+            Value::Array(items) => items.iter().find_map(|item| find_field(item, key)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_funder_state_to_json() {
+        let state = fixture_state();
+        let json = funder_state_to_json(&state, false).unwrap();
+        let value: Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(
+            value["local_public_key"],
+            Value::String("aa".repeat(PUBLIC_KEY_LEN))
+        );
+
+        // Maps are sorted arrays:
+        let friends = value["friends"].as_array().unwrap();
+        assert_eq!(friends.len(), 2);
+        assert_eq!(
+            friends[0]["key"],
+            Value::String("bb".repeat(PUBLIC_KEY_LEN))
+        );
+        assert_eq!(
+            friends[1]["key"],
+            Value::String("cc".repeat(PUBLIC_KEY_LEN))
+        );
+        let friend_b = &friends[0]["value"];
+        assert_eq!(friend_b["name"], Value::String("friend".to_owned()));
+        assert_eq!(
+            friend_b["remote_public_key"],
+            Value::String("bb".repeat(PUBLIC_KEY_LEN))
+        );
+
+        // i128 and u128 are strings:
+        assert_eq!(
+            friend_b["wanted_remote_max_debt"],
+            Value::String("0".to_owned())
+        );
+        let mc_balance = find_field(friend_b, "mutual_credit")
+            .and_then(|mutual_credit| find_field(mutual_credit, "balance"))
+            .unwrap();
+        assert_eq!(mc_balance["balance"], Value::String("-5".to_owned()));
+
+        let incoming_payments = value["incoming_payments"].as_array().unwrap();
+        assert_eq!(incoming_payments.len(), 2);
+        assert_eq!(incoming_payments[0]["key"], Value::from(0u64));
+        let receipt = &incoming_payments[0]["value"]["receipt"];
+        assert_eq!(receipt["dest_payment"], Value::String("10".to_owned()));
+        assert_eq!(
+            receipt["response_hash"],
+            Value::String("11".repeat(HASH_RESULT_LEN))
+        );
+
+        // Tokens are redacted:
+        assert_eq!(receipt["signature"], Value::String(REDACTED.to_owned()));
+
+        // A full rendering contains the tokens:
+        let json = funder_state_to_json(&state, true).unwrap();
+        let value: Value = serde_json::from_str(&json).unwrap();
+        let receipt = &value["incoming_payments"][0]["value"]["receipt"];
+        assert_eq!(
+            receipt["signature"],
+            Value::String("33".repeat(SIGNATURE_LEN))
+        );
+    }
+
+    #[test]
+    fn test_binary_serialization_unchanged() {
+        let public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        assert_eq!(
+            bincode::serialize(&public_key).unwrap(),
+            bincode::serialize(&LegacyPublicKey([0xaa; PUBLIC_KEY_LEN])).unwrap()
+        );
+
+        let signature = Signature::from(&[0x33; SIGNATURE_LEN]);
+        assert_eq!(
+            bincode::serialize(&signature).unwrap(),
+            bincode::serialize(&LegacySignature([0x33; SIGNATURE_LEN])).unwrap()
+        );
+
+        // Round trip of a whole funder state:
+        let state = fixture_state();
+        let serialized = bincode::serialize(&state).unwrap();
+        let loaded_state: FunderState<u32> = bincode::deserialize(&serialized).unwrap();
+        assert_eq!(bincode::serialize(&loaded_state).unwrap(), serialized);
+    }
+
+    #[test]
+    fn test_state_export() {
+        let identity = fixture_software_identity(1);
+        let state = fixture_state();
+
+        // Both redacted and full exports are signed:
+        for &full in &[false, true] {
+            let data = sign_export(create_state_export(&state, full).unwrap(), &identity);
+            let verified_export = verify_export(&data).unwrap();
+            assert_eq!(&verified_export.magic, STATE_EXPORT_MAGIC);
+            assert_eq!(verified_export.version, STATE_EXPORT_VERSION);
+            assert_eq!(verified_export.public_key, identity.get_public_key());
+            assert_eq!(
+                verified_export.contents,
+                funder_state_to_json(&state, full).unwrap().as_bytes()
+            );
+
+            // A modified export:
+            let mut data2 = data.clone();
+            data2[EXPORT_HEADER_LEN] ^= 0x01;
+            assert_eq!(
+                verify_export(&data2),
+                Err(SignedExportError::InvalidSignature)
+            );
+        }
+    }
+}
@@ -1,19 +1,108 @@
+use common::bounded_cache::{CacheLimits, CacheMetrics};
+
+use proto::consts::{
+    COMPLETED_CACHE_MAX_AGE_TICKS, COMPLETED_CACHE_MAX_ENTRIES, PAYMENT_TIMINGS_CACHE_MAX_AGE_TICKS,
+    PAYMENT_TIMINGS_CACHE_MAX_ENTRIES,
+};
+
+use super::adaptive_batch::{AdaptiveBatch, AdaptiveBatchMutation};
+use super::completed::{Completed, CompletedMutation};
+use super::damping::{RelaysDamping, RelaysDampingMutation};
+use super::deadlines::{ReportedDeadlines, ReportedDeadlinesMutation};
+use super::goodbye::{GoodbyeMutation, Goodbyes};
 use super::liveness::{Liveness, LivenessMutation};
+use super::payment_timing::{LatencyPercentiles, PaymentTimingMutation, PaymentTimings};
+use super::prewarm::{Prewarm, PrewarmMutation};
+use super::response_deadline::{ResponseDeadlineMutation, ResponseDeadlines};
+
+/// Limits of the bounded caches kept in the ephemeral state.
+/// See the documentation of every cache for the cost of an early eviction.
+#[derive(Debug, Clone)]
+pub struct EphemeralLimits {
+    /// Requests we sent that were recently completed, for every friend
+    pub completed: CacheLimits,
+    /// Measurement points of payments we originate
+    pub payment_timings: CacheLimits,
+}
+
+impl Default for EphemeralLimits {
+    fn default() -> Self {
+        EphemeralLimits {
+            completed: CacheLimits {
+                max_entries: COMPLETED_CACHE_MAX_ENTRIES,
+                max_age_ticks: COMPLETED_CACHE_MAX_AGE_TICKS,
+            },
+            payment_timings: CacheLimits {
+                max_entries: PAYMENT_TIMINGS_CACHE_MAX_ENTRIES,
+                max_age_ticks: PAYMENT_TIMINGS_CACHE_MAX_AGE_TICKS,
+            },
+        }
+    }
+}
+
+/// Size metrics of the bounded caches kept in the ephemeral state,
+/// and the latencies of recent payments we originated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EphemeralMetrics {
+    pub completed: CacheMetrics,
+    pub payment_timings: CacheMetrics,
+    /// None if no payment has succeeded yet
+    pub opt_payment_latency: Option<LatencyPercentiles>,
+}
+
+impl EphemeralMetrics {
+    /// Total amount of entries kept in all the caches
+    pub fn total_len(&self) -> usize {
+        self.completed.len + self.payment_timings.len
+    }
+}
 
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct Ephemeral {
     pub liveness: Liveness,
+    pub relays_damping: RelaysDamping,
+    pub adaptive_batch: AdaptiveBatch,
+    pub prewarm: Prewarm,
+    pub completed: Completed,
+    pub response_deadlines: ResponseDeadlines,
+    pub goodbyes: Goodbyes,
+    pub payment_timings: PaymentTimings,
+    pub reported_deadlines: ReportedDeadlines,
+    /// The database can not be written. No payments are handled until writing is possible again.
+    pub read_only: bool,
 }
 
 #[derive(Debug)]
 pub enum EphemeralMutation {
     LivenessMutation(LivenessMutation),
+    RelaysDampingMutation(RelaysDampingMutation),
+    AdaptiveBatchMutation(AdaptiveBatchMutation),
+    PrewarmMutation(PrewarmMutation),
+    CompletedMutation(CompletedMutation),
+    ResponseDeadlineMutation(ResponseDeadlineMutation),
+    GoodbyeMutation(GoodbyeMutation),
+    PaymentTimingMutation(PaymentTimingMutation),
+    ReportedDeadlinesMutation(ReportedDeadlinesMutation),
+    SetReadOnly(bool),
 }
 
 impl Ephemeral {
     pub fn new() -> Ephemeral {
+        Ephemeral::with_limits(&EphemeralLimits::default())
+    }
+
+    pub fn with_limits(ephemeral_limits: &EphemeralLimits) -> Ephemeral {
         Ephemeral {
             liveness: Liveness::new(),
+            relays_damping: RelaysDamping::new(),
+            adaptive_batch: AdaptiveBatch::new(),
+            prewarm: Prewarm::new(),
+            completed: Completed::new(ephemeral_limits.completed),
+            response_deadlines: ResponseDeadlines::new(),
+            goodbyes: Goodbyes::new(),
+            payment_timings: PaymentTimings::new(ephemeral_limits.payment_timings),
+            reported_deadlines: ReportedDeadlines::new(),
+            read_only: false,
         }
     }
 
@@ -22,6 +111,39 @@ impl Ephemeral {
             EphemeralMutation::LivenessMutation(liveness_mutation) => {
                 self.liveness.mutate(liveness_mutation)
             }
+            EphemeralMutation::RelaysDampingMutation(relays_damping_mutation) => {
+                self.relays_damping.mutate(relays_damping_mutation)
+            }
+            EphemeralMutation::AdaptiveBatchMutation(adaptive_batch_mutation) => {
+                self.adaptive_batch.mutate(adaptive_batch_mutation)
+            }
+            EphemeralMutation::PrewarmMutation(prewarm_mutation) => {
+                self.prewarm.mutate(prewarm_mutation)
+            }
+            EphemeralMutation::CompletedMutation(completed_mutation) => {
+                self.completed.mutate(completed_mutation)
+            }
+            EphemeralMutation::ResponseDeadlineMutation(response_deadline_mutation) => {
+                self.response_deadlines.mutate(response_deadline_mutation)
+            }
+            EphemeralMutation::GoodbyeMutation(goodbye_mutation) => {
+                self.goodbyes.mutate(goodbye_mutation)
+            }
+            EphemeralMutation::PaymentTimingMutation(payment_timing_mutation) => {
+                self.payment_timings.mutate(payment_timing_mutation)
+            }
+            EphemeralMutation::ReportedDeadlinesMutation(reported_deadlines_mutation) => {
+                self.reported_deadlines.mutate(reported_deadlines_mutation)
+            }
+            EphemeralMutation::SetReadOnly(read_only) => self.read_only = *read_only,
+        }
+    }
+
+    pub fn metrics(&self) -> EphemeralMetrics {
+        EphemeralMetrics {
+            completed: self.completed.metrics(),
+            payment_timings: self.payment_timings.started.metrics(),
+            opt_payment_latency: self.payment_timings.latency_percentiles(),
         }
     }
 }
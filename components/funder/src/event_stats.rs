@@ -0,0 +1,303 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use common::int_convert::usize_to_u64;
+
+use proto::consts::{SLOW_FUNDER_EVENT_LOG_INTERVAL_MS, SLOW_FUNDER_EVENT_MS};
+
+/// Upper bounds (In microseconds) of the buckets of the latency histograms.
+/// The last bucket holds all the longer durations.
+pub const LATENCY_BUCKETS_US: [u64; NUM_BUCKETS] =
+    [100, 1_000, 10_000, 100_000, 1_000_000, u64::max_value()];
+
+const NUM_BUCKETS: usize = 6;
+
+/// A phase of handling a single incoming event by the funder loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventPhase {
+    /// Validating the event, and creating the mutations and the outgoing messages it causes.
+    /// This includes signing move tokens.
+    Handle,
+    /// Applying the mutations to a copy of the funder state (And checking invariants)
+    Mutate,
+    /// Persisting the mutations to the database
+    Persist,
+    /// Sending the outgoing messages
+    Emit,
+}
+
+pub const EVENT_PHASES: [EventPhase; 4] = [
+    EventPhase::Handle,
+    EventPhase::Mutate,
+    EventPhase::Persist,
+    EventPhase::Emit,
+];
+
+impl EventPhase {
+    fn index(self) -> usize {
+        match self {
+            EventPhase::Handle => 0,
+            EventPhase::Mutate => 1,
+            EventPhase::Persist => 2,
+            EventPhase::Emit => 3,
+        }
+    }
+}
+
+/// The durations of the phases of handling a single incoming event.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PhaseDurations {
+    durations: [Duration; 4],
+}
+
+impl PhaseDurations {
+    pub fn set(&mut self, phase: EventPhase, duration: Duration) {
+        self.durations[phase.index()] = duration;
+    }
+
+    pub fn get(&self, phase: EventPhase) -> Duration {
+        self.durations[phase.index()]
+    }
+
+    pub fn total(&self) -> Duration {
+        self.durations.iter().sum()
+    }
+
+    /// The phase that took the longest
+    pub fn dominant(&self) -> EventPhase {
+        let mut dominant = EventPhase::Handle;
+        for &phase in &EVENT_PHASES {
+            if self.get(phase) > self.get(dominant) {
+                dominant = phase;
+            }
+        }
+        dominant
+    }
+}
+
+fn duration_to_us(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .saturating_mul(1_000_000)
+        .saturating_add(u64::from(duration.subsec_micros()))
+}
+
+fn millis_to_duration(millis: usize) -> Duration {
+    Duration::from_millis(usize_to_u64(millis).unwrap())
+}
+
+/// A histogram of durations, using the buckets of `LATENCY_BUCKETS_US`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// Amount of durations in every bucket
+    pub counts: [u64; NUM_BUCKETS],
+    /// Sum of all the durations, in microseconds
+    pub total_us: u64,
+}
+
+impl LatencyHistogram {
+    fn add(&mut self, duration: Duration) {
+        let us = duration_to_us(duration);
+        let bucket = LATENCY_BUCKETS_US
+            .iter()
+            .position(|&bound| us <= bound)
+            .unwrap();
+        self.counts[bucket] = self.counts[bucket].saturating_add(1);
+        self.total_us = self.total_us.saturating_add(us);
+    }
+
+    /// Amount of durations in the histogram
+    pub fn num_samples(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+#[derive(Debug, Default)]
+struct StatsInner {
+    phases: [LatencyHistogram; 4],
+    total: LatencyHistogram,
+    slow_events: u64,
+    slow_event_warnings: u64,
+}
+
+/// Latency histograms of the events handled by the funder loop.
+/// Clones share the same histograms.
+#[derive(Debug, Clone, Default)]
+pub struct FunderEventStats {
+    inner: Arc<Mutex<StatsInner>>,
+}
+
+impl FunderEventStats {
+    pub(crate) fn add_event(&self, phase_durations: &PhaseDurations) {
+        let mut inner = self.inner.lock().unwrap();
+        for &phase in &EVENT_PHASES {
+            inner.phases[phase.index()].add(phase_durations.get(phase));
+        }
+        inner.total.add(phase_durations.total());
+    }
+
+    pub(crate) fn add_slow_event(&self, logged: bool) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.slow_events = inner.slow_events.saturating_add(1);
+        if logged {
+            inner.slow_event_warnings = inner.slow_event_warnings.saturating_add(1);
+        }
+    }
+
+    /// Durations of a single phase of all the handled events.
+    pub fn phase_histogram(&self, phase: EventPhase) -> LatencyHistogram {
+        self.inner.lock().unwrap().phases[phase.index()].clone()
+    }
+
+    /// Total durations of all the handled events.
+    pub fn total_histogram(&self) -> LatencyHistogram {
+        self.inner.lock().unwrap().total.clone()
+    }
+
+    /// Amount of events that took longer than the slow event threshold.
+    pub fn slow_events(&self) -> u64 {
+        self.inner.lock().unwrap().slow_events
+    }
+
+    /// Amount of warnings logged about slow events.
+    pub fn slow_event_warnings(&self) -> u64 {
+        self.inner.lock().unwrap().slow_event_warnings
+    }
+}
+
+/// Configuration for the warnings about slow events.
+#[derive(Debug, Clone)]
+pub struct SlowEventLimits {
+    /// An event that takes longer than this to handle is slow.
+    pub threshold: Duration,
+    /// At most one warning is logged during this interval. Further slow events are only counted.
+    pub log_interval: Duration,
+}
+
+impl Default for SlowEventLimits {
+    fn default() -> Self {
+        SlowEventLimits {
+            threshold: millis_to_duration(SLOW_FUNDER_EVENT_MS),
+            log_interval: millis_to_duration(SLOW_FUNDER_EVENT_LOG_INTERVAL_MS),
+        }
+    }
+}
+
+/// Decides which slow events are logged.
+pub(crate) struct SlowEventLog {
+    limits: SlowEventLimits,
+    opt_last_warning: Option<Instant>,
+    /// Slow events that were not logged since the last warning
+    suppressed: u64,
+}
+
+impl SlowEventLog {
+    pub fn new(limits: SlowEventLimits) -> Self {
+        SlowEventLog {
+            limits,
+            opt_last_warning: None,
+            suppressed: 0,
+        }
+    }
+
+    pub fn is_slow(&self, phase_durations: &PhaseDurations) -> bool {
+        phase_durations.total() > self.limits.threshold
+    }
+
+    /// Check the durations of a handled event.
+    /// Returns Some(suppressed) if a warning should be logged about the event, where
+    /// `suppressed` is the amount of slow events that were not logged since the last warning.
+    pub fn check(&mut self, phase_durations: &PhaseDurations, now: Instant) -> Option<u64> {
+        if !self.is_slow(phase_durations) {
+            return None;
+        }
+        if let Some(last_warning) = self.opt_last_warning {
+            if now.duration_since(last_warning) < self.limits.log_interval {
+                self.suppressed = self.suppressed.saturating_add(1);
+                return None;
+            }
+        }
+        self.opt_last_warning = Some(now);
+        Some(std::mem::replace(&mut self.suppressed, 0))
+    }
+}
+
+/// Format the durations of the phases of an event, for logging.
+pub(crate) fn format_phases(phase_durations: &PhaseDurations) -> String {
+    let mut parts = vec![format!(
+        "total={}us",
+        duration_to_us(phase_durations.total())
+    )];
+    for &phase in &EVENT_PHASES {
+        parts.push(format!(
+            "{:?}={}us",
+            phase,
+            duration_to_us(phase_durations.get(phase))
+        ));
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn phase_durations(handle_ms: u64, persist_ms: u64) -> PhaseDurations {
+        let mut phase_durations = PhaseDurations::default();
+        phase_durations.set(EventPhase::Handle, Duration::from_millis(handle_ms));
+        phase_durations.set(EventPhase::Persist, Duration::from_millis(persist_ms));
+        phase_durations
+    }
+
+    #[test]
+    fn test_latency_histogram_buckets() {
+        let mut histogram = LatencyHistogram::default();
+        histogram.add(Duration::from_micros(0));
+        histogram.add(Duration::from_micros(100));
+        histogram.add(Duration::from_micros(101));
+        histogram.add(Duration::from_millis(50));
+        histogram.add(Duration::from_secs(3600));
+        assert_eq!(histogram.counts, [2, 1, 0, 1, 0, 1]);
+        assert_eq!(histogram.num_samples(), 5);
+        assert_eq!(histogram.total_us, 100 + 101 + 50_000 + 3_600_000_000);
+    }
+
+    #[test]
+    fn test_phase_durations_dominant() {
+        let phase_durations = phase_durations(2, 30);
+        assert_eq!(phase_durations.total(), Duration::from_millis(32));
+        assert_eq!(phase_durations.dominant(), EventPhase::Persist);
+        assert_eq!(
+            format_phases(&phase_durations),
+            "total=32000us Handle=2000us Mutate=0us Persist=30000us Emit=0us"
+        );
+    }
+
+    #[test]
+    fn test_slow_event_log_rate_limit() {
+        let mut slow_event_log = SlowEventLog::new(SlowEventLimits {
+            threshold: Duration::from_millis(10),
+            log_interval: Duration::from_secs(60),
+        });
+        let start = Instant::now();
+
+        // Fast events are never logged:
+        assert_eq!(slow_event_log.check(&phase_durations(1, 9), start), None);
+
+        // Only the first slow event of a burst is logged:
+        assert_eq!(
+            slow_event_log.check(&phase_durations(1, 20), start),
+            Some(0)
+        );
+        for i in 1..10 {
+            let now = start + Duration::from_secs(i);
+            assert_eq!(slow_event_log.check(&phase_durations(1, 20), now), None);
+        }
+
+        // After the interval, the next slow event is logged, together with the amount of
+        // suppressed slow events:
+        let now = start + Duration::from_secs(60);
+        assert_eq!(slow_event_log.check(&phase_durations(1, 20), now), Some(9));
+        assert_eq!(slow_event_log.check(&phase_durations(1, 20), now), None);
+    }
+}
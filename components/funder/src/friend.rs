@@ -2,16 +2,21 @@ use im::vector::Vector as ImVec;
 use std::fmt::Debug;
 
 use crypto::identity::PublicKey;
+use crypto::uid::Uid;
 
 use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::usize_to_u32;
 use common::safe_arithmetic::SafeUnsignedArithmetic;
 
 use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
 use proto::funder::messages::{
-    FailureSendFunds, FriendStatus, PendingRequest, RequestSendFunds, RequestsStatus, ResetTerms,
-    ResponseSendFunds,
+    FailureReason, FailureSendFunds, FriendStatus, FriendsRoute, MoveToken, PendingRequest,
+    ProtocolViolationReport, RemoteMaxDebtExpiry, RequestSendFunds, RequestsStatus, ResetTerms,
+    ResponseSendFunds, VerificationProof, VerificationStatus,
 };
+use proto::funder::signature_buff::verify_verification_proof;
 
+use crate::credit_calc::credits_to_freeze;
 use crate::token_channel::{TcMutation, TokenChannel};
 use crate::types::MoveTokenHashed;
 
@@ -20,7 +25,7 @@ pub enum ResponseOp {
     Response(ResponseSendFunds),
     UnsignedResponse(PendingRequest),
     Failure(FailureSendFunds),
-    UnsignedFailure(PendingRequest),
+    UnsignedFailure((PendingRequest, FailureReason)),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -69,7 +74,11 @@ pub enum FriendMutation<B: Clone> {
     TcMutation(TcMutation<B>),
     SetInconsistent(ChannelInconsistent),
     SetConsistent(TokenChannel<B>),
+    SetPendingReset(ChannelPendingReset<B>),
     SetWantedRemoteMaxDebt(u128),
+    SetRemoteMaxDebtExpiry(Option<RemoteMaxDebtExpiry>),
+    /// The remote max debt has expired: Set the wanted remote max debt and clear the expiry.
+    ExpireRemoteMaxDebt(u128),
     SetWantedLocalRequestsStatus(RequestsStatus),
     PushBackPendingRequest(RequestSendFunds),
     PopFrontPendingRequest,
@@ -77,10 +86,18 @@ pub enum FriendMutation<B: Clone> {
     PopFrontPendingResponse,
     PushBackPendingUserRequest(RequestSendFunds),
     PopFrontPendingUserRequest,
+    /// Remove a pending user request (By its request id)
+    RemovePendingUserRequest(Uid),
     SetStatus(FriendStatus),
     SetRemoteRelays(Vec<RelayAddress<B>>),
+    SetPendingRemoteRelays(Option<Vec<RelayAddress<B>>>),
     SetName(String),
+    SetResponseDeadline(Option<u64>),
     SetSentLocalRelays(SentLocalRelays<B>),
+    SetProtocolViolation(ProtocolViolationReport),
+    SetVerificationPhrase(String),
+    SetRemoteVerificationProof(VerificationProof),
+    SetIndexPrivate(bool),
 }
 
 #[derive(PartialEq, Eq, Clone, Serialize, Deserialize, Debug)]
@@ -90,10 +107,35 @@ pub struct ChannelInconsistent {
     pub opt_remote_reset_terms: Option<ResetTerms>,
 }
 
+/// We have accepted the remote reset terms, but the remote side has not yet acknowledged our
+/// reset move token. The reset move token is retransmitted until the first valid incoming move
+/// token on top of it is received, at which point the channel becomes consistent.
+#[derive(Clone, Serialize, Deserialize, Debug)]
+pub struct ChannelPendingReset<B> {
+    pub opt_last_incoming_move_token: Option<MoveTokenHashed>,
+    pub local_reset_terms: ResetTerms,
+    /// The remote reset terms we have accepted
+    pub remote_reset_terms: ResetTerms,
+    /// Our reset move token, created according to the accepted remote reset terms
+    pub reset_move_token: MoveToken<B>,
+}
+
+impl<B> ChannelPendingReset<B> {
+    /// The inconsistent state of the channel before the remote reset terms were accepted
+    pub fn to_inconsistent(&self) -> ChannelInconsistent {
+        ChannelInconsistent {
+            opt_last_incoming_move_token: self.opt_last_incoming_move_token.clone(),
+            local_reset_terms: self.local_reset_terms.clone(),
+            opt_remote_reset_terms: Some(self.remote_reset_terms.clone()),
+        }
+    }
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum ChannelStatus<B> {
     Inconsistent(ChannelInconsistent),
+    PendingReset(ChannelPendingReset<B>),
     Consistent(TokenChannel<B>),
 }
 
@@ -106,6 +148,9 @@ where
             ChannelStatus::Inconsistent(channel_inconsistent) => {
                 channel_inconsistent.opt_last_incoming_move_token.clone()
             }
+            ChannelStatus::PendingReset(channel_pending_reset) => {
+                channel_pending_reset.opt_last_incoming_move_token.clone()
+            }
             ChannelStatus::Consistent(token_channel) => {
                 token_channel.get_last_incoming_move_token_hashed().cloned()
             }
@@ -118,10 +163,29 @@ pub struct FriendState<B: Clone> {
     pub local_public_key: PublicKey,
     pub remote_public_key: PublicKey,
     pub remote_relays: Vec<RelayAddress<B>>,
+    /// Relays received from the remote side that were not applied yet,
+    /// because the remote side has changed his relays too recently.
+    pub opt_pending_remote_relays: Option<Vec<RelayAddress<B>>>,
     pub sent_local_relays: SentLocalRelays<B>,
     pub name: String,
+    /// Amount of ticks we wait for this friend to answer a request we have forwarded to him.
+    /// `None` means that we wait for as long as it takes.
+    pub opt_response_deadline_ticks: Option<u64>,
     pub channel_status: ChannelStatus<B>,
+    /// The last report the remote side has sent us about a move token it rejected.
+    /// Used only for diagnostics.
+    pub opt_protocol_violation: Option<ProtocolViolationReport>,
+    /// A phrase the user has shared with this friend out of band, used for verification.
+    pub opt_verification_phrase: Option<String>,
+    /// The last verification proof the remote side has sent us.
+    /// Kept so that it could be checked if our verification phrase is set later.
+    pub opt_remote_verification_proof: Option<VerificationProof>,
+    pub verification_status: VerificationStatus,
+    /// The capacities with this friend are not advertised to index servers.
+    pub index_private: bool,
     pub wanted_remote_max_debt: u128,
+    /// An expiry for wanted_remote_max_debt. `expires_after_ticks` is counted down on every tick.
+    pub opt_remote_max_debt_expiry: Option<RemoteMaxDebtExpiry>,
     pub wanted_local_requests_status: RequestsStatus,
     pub pending_requests: ImVec<RequestSendFunds>,
     pub pending_responses: ImVec<ResponseOp>,
@@ -149,13 +213,21 @@ where
             local_public_key: local_public_key.clone(),
             remote_public_key: remote_public_key.clone(),
             remote_relays,
+            opt_pending_remote_relays: None,
             sent_local_relays: SentLocalRelays::NeverSent,
             name,
+            opt_response_deadline_ticks: None,
             channel_status: ChannelStatus::Consistent(token_channel),
+            opt_protocol_violation: None,
+            opt_verification_phrase: None,
+            opt_remote_verification_proof: None,
+            verification_status: VerificationStatus::Unverified,
+            index_private: false,
 
             // The remote_max_debt we want to have. When possible, this will be sent to the remote
             // side.
             wanted_remote_max_debt: 0,
+            opt_remote_max_debt_expiry: None,
             wanted_local_requests_status: RequestsStatus::Closed,
             // The local_send_price we want to have (Or possibly close requests, by having an empty
             // send price). When possible, this will be updated with the TokenChannel.
@@ -186,20 +258,96 @@ where
             ChannelStatus::Consistent(token_channel) => {
                 &token_channel.get_mutual_credit().state().balance
             }
-            ChannelStatus::Inconsistent(_channel_inconsistent) => return 0,
+            ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => return 0,
         };
         balance
             .local_max_debt
             .saturating_add_signed(balance.balance)
     }
 
+    /// The debt the remote side currently owes us, including debt frozen by pending requests.
+    /// Returns 0 if the channel is not consistent.
+    pub fn get_remote_used_debt(&self) -> u128 {
+        let balance = match &self.channel_status {
+            ChannelStatus::Consistent(token_channel) => {
+                &token_channel.get_mutual_credit().state().balance
+            }
+            ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => return 0,
+        };
+        balance
+            .remote_pending_debt
+            .saturating_add_signed(balance.balance)
+    }
+
+    /// Credits frozen against the remote side, or queued to be frozen, for requests that arrived
+    /// from the friend `from_public_key` and were forwarded to the remote side.
+    pub fn get_frozen_credits_from(&self, from_public_key: &PublicKey) -> u128 {
+        let credits_from = |route: &FriendsRoute, dest_payment: u128| -> Option<u128> {
+            // The route must go through: from --> local --> remote
+            let from_index = route.find_pk_pair(from_public_key, &self.local_public_key)?;
+            let remote_index = from_index.checked_add(2)?;
+            if route.index_to_pk(remote_index)? != &self.remote_public_key {
+                return None;
+            }
+            credits_to_freeze(
+                usize_to_u32(remote_index)?,
+                usize_to_u32(route.len())?,
+                dest_payment,
+            )
+        };
+
+        let queued_credits = self
+            .pending_requests
+            .iter()
+            .filter_map(|request| credits_from(&request.route, request.dest_payment))
+            .fold(0, u128::saturating_add);
+
+        let frozen_credits = match &self.channel_status {
+            ChannelStatus::Consistent(token_channel) => token_channel
+                .get_mutual_credit()
+                .state()
+                .pending_requests
+                .pending_local_requests
+                .values()
+                .filter_map(|pending_request| {
+                    credits_from(&pending_request.route, pending_request.dest_payment)
+                })
+                .fold(0, u128::saturating_add),
+            ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => 0,
+        };
+
+        queued_credits.saturating_add(frozen_credits)
+    }
+
+    /// Check the verification proof of the remote side against our verification phrase.
+    fn check_verification(&self) -> VerificationStatus {
+        let (phrase, verification_proof) = match (
+            &self.opt_verification_phrase,
+            &self.opt_remote_verification_proof,
+        ) {
+            (Some(phrase), Some(verification_proof)) => (phrase, verification_proof),
+            _ => return VerificationStatus::Unverified,
+        };
+
+        if verify_verification_proof(
+            verification_proof,
+            &self.remote_public_key,
+            &self.local_public_key,
+            phrase,
+        ) {
+            VerificationStatus::PhraseVerified
+        } else {
+            VerificationStatus::Failed
+        }
+    }
+
     pub fn mutate(&mut self, friend_mutation: &FriendMutation<B>) {
         match friend_mutation {
             FriendMutation::TcMutation(tc_mutation) => match &mut self.channel_status {
                 ChannelStatus::Consistent(ref mut token_channel) => {
                     token_channel.mutate(tc_mutation)
                 }
-                ChannelStatus::Inconsistent(_) => unreachable!(),
+                ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => unreachable!(),
             },
             FriendMutation::SetInconsistent(channel_inconsistent) => {
                 self.channel_status = ChannelStatus::Inconsistent(channel_inconsistent.clone());
@@ -207,9 +355,19 @@ where
             FriendMutation::SetConsistent(token_channel) => {
                 self.channel_status = ChannelStatus::Consistent(token_channel.clone());
             }
+            FriendMutation::SetPendingReset(channel_pending_reset) => {
+                self.channel_status = ChannelStatus::PendingReset(channel_pending_reset.clone());
+            }
             FriendMutation::SetWantedRemoteMaxDebt(wanted_remote_max_debt) => {
                 self.wanted_remote_max_debt = *wanted_remote_max_debt;
             }
+            FriendMutation::SetRemoteMaxDebtExpiry(opt_remote_max_debt_expiry) => {
+                self.opt_remote_max_debt_expiry = opt_remote_max_debt_expiry.clone();
+            }
+            FriendMutation::ExpireRemoteMaxDebt(wanted_remote_max_debt) => {
+                self.wanted_remote_max_debt = *wanted_remote_max_debt;
+                self.opt_remote_max_debt_expiry = None;
+            }
             FriendMutation::SetWantedLocalRequestsStatus(wanted_local_requests_status) => {
                 self.wanted_local_requests_status = wanted_local_requests_status.clone();
             }
@@ -232,18 +390,47 @@ where
             FriendMutation::PopFrontPendingUserRequest => {
                 let _ = self.pending_user_requests.pop_front();
             }
+            FriendMutation::RemovePendingUserRequest(request_id) => {
+                if let Some(index) = self
+                    .pending_user_requests
+                    .iter()
+                    .position(|request| &request.request_id == request_id)
+                {
+                    let _ = self.pending_user_requests.remove(index);
+                }
+            }
             FriendMutation::SetStatus(friend_status) => {
                 self.status = friend_status.clone();
             }
             FriendMutation::SetRemoteRelays(remote_relays) => {
                 self.remote_relays = remote_relays.clone();
             }
+            FriendMutation::SetPendingRemoteRelays(opt_pending_remote_relays) => {
+                self.opt_pending_remote_relays = opt_pending_remote_relays.clone();
+            }
             FriendMutation::SetName(friend_name) => {
                 self.name = friend_name.clone();
             }
+            FriendMutation::SetResponseDeadline(opt_response_deadline_ticks) => {
+                self.opt_response_deadline_ticks = *opt_response_deadline_ticks;
+            }
             FriendMutation::SetSentLocalRelays(sent_local_relays) => {
                 self.sent_local_relays = sent_local_relays.clone();
             }
+            FriendMutation::SetProtocolViolation(protocol_violation_report) => {
+                self.opt_protocol_violation = Some(protocol_violation_report.clone());
+            }
+            FriendMutation::SetVerificationPhrase(phrase) => {
+                self.opt_verification_phrase = Some(phrase.clone());
+                self.verification_status = self.check_verification();
+            }
+            FriendMutation::SetRemoteVerificationProof(verification_proof) => {
+                self.opt_remote_verification_proof = Some(verification_proof.clone());
+                self.verification_status = self.check_verification();
+            }
+            FriendMutation::SetIndexPrivate(index_private) => {
+                self.index_private = *index_private;
+            }
         }
     }
 }
@@ -1,31 +1,47 @@
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
+use std::time::Instant;
 
 use futures::channel::mpsc;
-use futures::{future, stream, SinkExt, StreamExt};
+use futures::{future, stream, SinkExt, Stream, StreamExt};
 
 use common::canonical_serialize::CanonicalSerialize;
 
 use crypto::crypto_rand::CryptoRandom;
-use identity::IdentityClient;
+use crypto::identity::PublicKey;
+use identity::{IdentityClient, IdentityClientError};
+use timer::{TimerClient, TimerTick};
 
 // use crate::database::{AtomicDb, DbRunner, DbRunnerError};
-use database::DatabaseClient;
+use database::{DatabaseClient, DatabaseClientError};
 
 use proto::funder::messages::{FunderIncomingControl, FunderOutgoingControl};
+use proto::report::messages::FunderReportMutations;
 
-use crate::ephemeral::Ephemeral;
-use crate::handler::funder_handle_message;
+use crate::ephemeral::{Ephemeral, EphemeralLimits};
+use crate::event_stats::{
+    format_phases, EventPhase, FunderEventStats, PhaseDurations, SlowEventLimits, SlowEventLog,
+};
+use crate::handler::{funder_handle_message, FunderHandlerError};
+#[cfg(any(test, feature = "invariants"))]
+use crate::invariants::check_invariants;
 use crate::state::{FunderMutation, FunderState};
-use crate::types::{FunderIncoming, FunderIncomingComm, FunderOutgoingComm};
+use crate::types::{
+    FunderIncoming, FunderIncomingComm, FunderOutgoingComm, IncomingLivenessMessage,
+};
 
 #[derive(Debug)]
 pub enum FunderError {
     IncomingControlClosed,
     IncomingCommClosed,
+    TimerClosed,
+    RequestTimerStreamError,
     IncomingMessagesError,
     DbError,
     SendControlError,
     SendCommError,
+    /// The identity service could not sign. Continuing without signing capability is unsafe.
+    IdentityError(IdentityClientError),
 }
 
 #[derive(Debug, Clone)]
@@ -33,32 +49,113 @@ pub enum FunderEvent<B> {
     FunderIncoming(FunderIncoming<B>),
     IncomingControlClosed,
     IncomingCommClosed,
+    TimerClosed,
 }
 
-pub async fn inner_funder_loop<B, R>(
+fn liveness_public_key(liveness_message: &IncomingLivenessMessage) -> &PublicKey {
+    match liveness_message {
+        IncomingLivenessMessage::Online(public_key)
+        | IncomingLivenessMessage::Offline(public_key) => public_key,
+    }
+}
+
+/// A short name of the kind of an incoming event, for logging.
+fn event_kind<B>(funder_incoming: &FunderIncoming<B>) -> &'static str {
+    match funder_incoming {
+        FunderIncoming::Init => "init",
+        FunderIncoming::Control(_) => "control",
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(_)) => "liveness",
+        FunderIncoming::Comm(FunderIncomingComm::Friend(_)) => "friend",
+        FunderIncoming::TimerTick(_) => "timer_tick",
+        FunderIncoming::SetReadOnly(_) => "set_read_only",
+    }
+}
+
+/// Record the durations of the phases of a handled event.
+/// A warning is logged about a slow event, unless too many warnings were logged recently.
+fn record_event<B>(
+    event_stats: &FunderEventStats,
+    slow_event_log: &mut SlowEventLog,
+    funder_incoming: &FunderIncoming<B>,
+    phase_durations: &PhaseDurations,
+) {
+    event_stats.add_event(phase_durations);
+    if !slow_event_log.is_slow(phase_durations) {
+        return;
+    }
+    let opt_suppressed = slow_event_log.check(phase_durations, Instant::now());
+    event_stats.add_slow_event(opt_suppressed.is_some());
+    if let Some(suppressed) = opt_suppressed {
+        warn!(
+            "Funder: Slow event: kind={} dominant={:?} {} (suppressed: {})",
+            event_kind(funder_incoming),
+            phase_durations.dominant(),
+            format_phases(phase_durations),
+            suppressed
+        );
+    }
+}
+
+/// Persist a batch of mutations.
+/// Returns false if the database can not be written. Nothing is persisted in this case.
+async fn persist<'a, B>(
+    db_client: &'a mut DatabaseClient<FunderMutation<B>>,
+    mutations: Vec<FunderMutation<B>>,
+) -> Result<bool, FunderError>
+where
+    B: Clone + Debug + 'a,
+{
+    match await!(db_client.mutate(mutations)) {
+        Ok(()) => Ok(true),
+        Err(DatabaseClientError::StorageUnavailable) => Ok(false),
+        Err(_) => Err(FunderError::DbError),
+    }
+}
+
+/// The main loop of the funder.
+///
+/// Incoming control messages, friend messages and timer ticks are handled one at a time, in the
+/// order they arrive. The mutations caused by a message are persisted before anything caused by
+/// the message is sent. In particular, the acknowledgement of a control message (A
+/// `ReportMutations` with the `app_request_id` of the message) is only sent after its mutations
+/// were committed to the database. A configuration change that was acknowledged to the app
+/// therefore applies to every payment the app submits after the acknowledgement, and to no payment
+/// submitted before it.
+pub async fn inner_funder_loop<B, R, TS>(
     mut identity_client: IdentityClient,
     rng: R,
+    timer_stream: TS,
     incoming_control: mpsc::Receiver<FunderIncomingControl<B>>,
     incoming_comm: mpsc::Receiver<FunderIncomingComm<B>>,
     control_sender: mpsc::Sender<FunderOutgoingControl<B>>,
     comm_sender: mpsc::Sender<FunderOutgoingComm<B>>,
     mut funder_state: FunderState<B>,
     mut db_client: DatabaseClient<FunderMutation<B>>,
+    min_operations_in_batch: usize,
     max_operations_in_batch: usize,
     max_node_relays: usize,
     max_pending_user_requests: usize,
+    relays_damping_ticks: usize,
+    prewarm_ticks: usize,
+    reliability_decay_ticks: usize,
+    deadlines_granularity_ticks: usize,
+    ephemeral_limits: EphemeralLimits,
+    slow_event_limits: SlowEventLimits,
+    event_stats: FunderEventStats,
     mut opt_event_sender: Option<mpsc::Sender<FunderEvent<B>>>,
 ) -> Result<(), FunderError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
     R: CryptoRandom + 'static,
+    TS: Stream<Item = TimerTick> + Unpin,
 {
     // Transform error type:
     let mut comm_sender = comm_sender.sink_map_err(|_| ());
     let mut control_sender = control_sender.sink_map_err(|_| ());
 
     // let mut db_runner = DbRunner::new(atomic_db);
-    let mut ephemeral = Ephemeral::new();
+    let mut ephemeral = Ephemeral::with_limits(&ephemeral_limits);
+    let mut slow_event_log = SlowEventLog::new(slow_event_limits);
 
     // Select over all possible events:
     let incoming_control = incoming_control
@@ -73,34 +170,90 @@ where
             FunderEvent::FunderIncoming(FunderIncoming::Comm(incoming_comm_msg))
         })
         .chain(stream::once(future::ready(FunderEvent::IncomingCommClosed)));
+    let timer_stream = timer_stream
+        .map(|timer_tick| {
+            FunderEvent::FunderIncoming(FunderIncoming::TimerTick(timer_tick.ticks_elapsed))
+        })
+        .chain(stream::once(future::ready(FunderEvent::TimerClosed)));
     // Chain the Init message first:
     let mut incoming_messages = stream::once(future::ready(FunderEvent::FunderIncoming(
         FunderIncoming::Init,
     )))
-    .chain(incoming_control.select(incoming_comm));
+    .chain(incoming_control.select(incoming_comm).select(timer_stream));
+
+    // Messages the funder loop sends to itself. They are handled before any other event:
+    let mut internal_incoming = VecDeque::new();
+    // Liveness messages we could not handle because the database was read only.
+    // Only the last message of every friend is kept. They are handled again once we recover.
+    let mut deferred_liveness = HashMap::new();
+    let mut read_only = false;
+
+    loop {
+        let funder_event = match internal_incoming.pop_front() {
+            Some(funder_incoming) => FunderEvent::FunderIncoming(funder_incoming),
+            None => match await!(incoming_messages.next()) {
+                Some(funder_event) => funder_event,
+                None => break,
+            },
+        };
 
-    while let Some(funder_event) = await!(incoming_messages.next()) {
         // For testing:
         // Read one message from incoming messages:
         let funder_incoming = match funder_event.clone() {
             FunderEvent::IncomingControlClosed => return Err(FunderError::IncomingControlClosed),
             FunderEvent::IncomingCommClosed => return Err(FunderError::IncomingCommClosed),
+            FunderEvent::TimerClosed => return Err(FunderError::TimerClosed),
             FunderEvent::FunderIncoming(funder_incoming) => funder_incoming,
         };
 
+        // Liveness messages of a friend must be handled in order:
+        if let FunderIncoming::Comm(FunderIncomingComm::Liveness(liveness_message)) =
+            &funder_incoming
+        {
+            let friend_public_key = liveness_public_key(liveness_message);
+            if deferred_liveness.contains_key(friend_public_key) {
+                deferred_liveness.insert(friend_public_key.clone(), liveness_message.clone());
+                continue;
+            }
+        }
+
+        // Time every phase of handling the message:
+        let mut phase_durations = PhaseDurations::default();
+        let handle_start = Instant::now();
         let res = await!(funder_handle_message(
             &mut identity_client,
             &rng,
             funder_state.clone(),
             ephemeral.clone(),
             max_node_relays,
+            min_operations_in_batch,
             max_operations_in_batch,
             max_pending_user_requests,
-            funder_incoming
+            relays_damping_ticks,
+            prewarm_ticks,
+            reliability_decay_ticks,
+            deadlines_granularity_ticks,
+            funder_incoming.clone()
         ));
+        phase_durations.set(EventPhase::Handle, handle_start.elapsed());
 
         let handler_output = match res {
             Ok(handler_output) => handler_output,
+            Err(FunderHandlerError::IdentityError(e)) => {
+                // None of the mutations of this message were applied or persisted.
+                // We stop here, to let the node shut down:
+                error!("Funder: Identity service failure, shutting down: {:?}", e);
+                return Err(FunderError::IdentityError(e));
+            }
+            Err(FunderHandlerError::InvariantError(e)) => {
+                // None of the mutations of this message were applied. The funder state is left
+                // as it was before the message:
+                error!(
+                    "Funder: Dropping a message that violates an invariant: {:?}\nincoming: {:?}",
+                    e, funder_event
+                );
+                continue;
+            }
             Err(handler_error) => {
                 // Reporting a recoverable error:
                 error!("Funder handler error: {:?}", handler_error);
@@ -108,17 +261,103 @@ where
             }
         };
 
-        if !handler_output.funder_mutations.is_empty() {
-            // Mutate our funder_state in memory:
+        // The new funder_state. It replaces the current funder_state only after it was persisted:
+        let mutate_start = Instant::now();
+        let opt_new_funder_state = if handler_output.funder_mutations.is_empty() {
+            None
+        } else {
+            let mut new_funder_state = funder_state.clone();
             for mutation in &handler_output.funder_mutations {
-                funder_state.mutate(mutation);
+                new_funder_state.mutate(mutation);
+            }
+            #[cfg(any(test, feature = "invariants"))]
+            {
+                if let Err(e) = check_invariants(&new_funder_state) {
+                    panic!(
+                        "Funder invariant violated: {:?}\nincoming: {:?}\nmutations: {:?}",
+                        e, funder_event, handler_output.funder_mutations
+                    );
+                }
+            }
+            Some(new_funder_state)
+        };
+        phase_durations.set(EventPhase::Mutate, mutate_start.elapsed());
+
+        // While read only, every timer tick probes the database with an empty write:
+        let is_probe = read_only
+            && opt_new_funder_state.is_none()
+            && match funder_incoming {
+                FunderIncoming::TimerTick(_) => true,
+                _ => false,
+            };
+
+        if opt_new_funder_state.is_some() || is_probe {
+            // Persist the mutations before anything is sent:
+            let persist_start = Instant::now();
+            let persisted = await!(persist(&mut db_client, handler_output.funder_mutations))?;
+            phase_durations.set(EventPhase::Persist, persist_start.elapsed());
+            if !persisted {
+                record_event(
+                    &event_stats,
+                    &mut slow_event_log,
+                    &funder_incoming,
+                    &phase_durations,
+                );
+                // Nothing caused by this message is applied or sent. In particular, no move
+                // token we could not persist ever leaves the funder.
+                warn!(
+                    "Funder: The database is read only. Dropping: {:?}",
+                    funder_incoming
+                );
+                match funder_incoming {
+                    FunderIncoming::Control(incoming_control) => {
+                        if read_only {
+                            // Acknowledge the request, to let the app know it was handled:
+                            let funder_report_mutations = FunderReportMutations {
+                                opt_app_request_id: Some(incoming_control.app_request_id),
+                                mutations: Vec::new(),
+                            };
+                            let outgoing_control =
+                                FunderOutgoingControl::ReportMutations(funder_report_mutations);
+                            await!(control_sender.send(outgoing_control))
+                                .map_err(|_| FunderError::SendControlError)?;
+                        } else {
+                            // Handle the request again in read only mode.
+                            // (Payments are answered with a StorageUnavailable failure):
+                            internal_incoming.push_back(FunderIncoming::Control(incoming_control));
+                        }
+                    }
+                    FunderIncoming::Comm(FunderIncomingComm::Liveness(liveness_message)) => {
+                        let friend_public_key = liveness_public_key(&liveness_message).clone();
+                        deferred_liveness.insert(friend_public_key, liveness_message);
+                    }
+                    _ => {}
+                }
+                if !read_only {
+                    read_only = true;
+                    internal_incoming.push_front(FunderIncoming::SetReadOnly(true));
+                }
+                continue;
             }
-            // If there are any mutations, send them to the database:
-            await!(db_client.mutate(handler_output.funder_mutations))
-                .map_err(|_| FunderError::DbError)?;
+
+            if read_only {
+                // The database can be written again:
+                read_only = false;
+                internal_incoming.push_back(FunderIncoming::SetReadOnly(false));
+                for (_, liveness_message) in deferred_liveness.drain() {
+                    internal_incoming.push_back(FunderIncoming::Comm(
+                        FunderIncomingComm::Liveness(liveness_message),
+                    ));
+                }
+            }
+        }
+
+        if let Some(new_funder_state) = opt_new_funder_state {
+            funder_state = new_funder_state;
         }
 
         // Apply ephemeral mutations to our ephemeral:
+        let emit_start = Instant::now();
         for mutation in &handler_output.ephemeral_mutations {
             ephemeral.mutate(mutation);
         }
@@ -131,6 +370,13 @@ where
         let mut control_stream = stream::iter::<_>(handler_output.outgoing_control);
         await!(control_sender.send_all(&mut control_stream))
             .map_err(|_| FunderError::SendControlError)?;
+        phase_durations.set(EventPhase::Emit, emit_start.elapsed());
+        record_event(
+            &event_stats,
+            &mut slow_event_log,
+            &funder_incoming,
+            &phase_durations,
+        );
 
         if let Some(ref mut event_sender) = opt_event_sender {
             await!(event_sender.send(funder_event)).unwrap();
@@ -143,13 +389,22 @@ where
 pub async fn funder_loop<B, R>(
     identity_client: IdentityClient,
     rng: R,
+    mut timer_client: TimerClient,
     incoming_control: mpsc::Receiver<FunderIncomingControl<B>>,
     incoming_comm: mpsc::Receiver<FunderIncomingComm<B>>,
     control_sender: mpsc::Sender<FunderOutgoingControl<B>>,
     comm_sender: mpsc::Sender<FunderOutgoingComm<B>>,
+    min_operations_in_batch: usize,
     max_operations_in_batch: usize,
     max_node_relays: usize,
     max_pending_user_requests: usize,
+    relays_damping_ticks: usize,
+    prewarm_ticks: usize,
+    reliability_decay_ticks: usize,
+    deadlines_granularity_ticks: usize,
+    ephemeral_limits: EphemeralLimits,
+    slow_event_limits: SlowEventLimits,
+    event_stats: FunderEventStats,
     funder_state: FunderState<B>,
     db_client: DatabaseClient<FunderMutation<B>>,
 ) -> Result<(), FunderError>
@@ -157,18 +412,30 @@ where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
     R: CryptoRandom + 'static,
 {
+    let timer_stream = await!(timer_client.request_timer_stream())
+        .map_err(|_| FunderError::RequestTimerStreamError)?;
+
     await!(inner_funder_loop(
         identity_client,
         rng,
+        timer_stream,
         incoming_control,
         incoming_comm,
         control_sender,
         comm_sender,
         funder_state,
         db_client,
+        min_operations_in_batch,
         max_operations_in_batch,
         max_node_relays,
         max_pending_user_requests,
+        relays_damping_ticks,
+        prewarm_ticks,
+        reliability_decay_ticks,
+        deadlines_granularity_ticks,
+        ephemeral_limits,
+        slow_event_limits,
+        event_stats,
         None
     ))
 }
@@ -0,0 +1,104 @@
+use crypto::identity::PublicKey;
+use im::hashmap::HashMap as ImHashMap;
+
+use proto::funder::messages::Goodbye;
+
+/// Keeps track of friends that told us they were going offline on purpose.
+#[derive(Clone, Default)]
+pub struct Goodbyes {
+    /// The last goodbye received from a friend, until the friend is online again.
+    pub friends: ImHashMap<PublicKey, Goodbye>,
+    /// Amount of ticks left until we try to reconnect to a friend that said goodbye.
+    /// We do not try to reconnect to the friend while it is offline on purpose.
+    pub ticks_left: ImHashMap<PublicKey, u64>,
+}
+
+#[derive(Debug)]
+pub enum GoodbyeMutation {
+    /// A goodbye was received from a friend.
+    Received((PublicKey, Goodbye)),
+    /// Set the amount of ticks left until we try to reconnect to a friend.
+    SetTicksLeft((PublicKey, u64)),
+    /// Try to reconnect to a friend. The goodbye is remembered until the friend is online.
+    Resume(PublicKey),
+    /// Forget the goodbye of a friend.
+    Forget(PublicKey),
+}
+
+impl Goodbyes {
+    pub fn new() -> Goodbyes {
+        Goodbyes {
+            friends: ImHashMap::new(),
+            ticks_left: ImHashMap::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &GoodbyeMutation) {
+        match mutation {
+            GoodbyeMutation::Received((public_key, goodbye)) => {
+                self.friends.insert(public_key.clone(), goodbye.clone());
+                match goodbye.opt_downtime_ticks {
+                    Some(downtime_ticks) => {
+                        self.ticks_left.insert(public_key.clone(), downtime_ticks);
+                    }
+                    None => {
+                        let _ = self.ticks_left.remove(public_key);
+                    }
+                }
+            }
+            GoodbyeMutation::SetTicksLeft((public_key, ticks_left)) => {
+                self.ticks_left.insert(public_key.clone(), *ticks_left);
+            }
+            GoodbyeMutation::Resume(public_key) => {
+                let _ = self.ticks_left.remove(public_key);
+            }
+            GoodbyeMutation::Forget(public_key) => {
+                let _ = self.friends.remove(public_key);
+                let _ = self.ticks_left.remove(public_key);
+            }
+        }
+    }
+
+    /// Get the goodbye received from a friend, if any.
+    pub fn get(&self, friend_public_key: &PublicKey) -> Option<&Goodbye> {
+        self.friends.get(friend_public_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    #[test]
+    fn test_goodbyes_basic() {
+        let mut goodbyes = Goodbyes::new();
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        let goodbye_a = Goodbye {
+            opt_downtime_ticks: Some(5),
+        };
+        let goodbye_b = Goodbye {
+            opt_downtime_ticks: None,
+        };
+        goodbyes.mutate(&GoodbyeMutation::Received((pk_a.clone(), goodbye_a.clone())));
+        goodbyes.mutate(&GoodbyeMutation::Received((pk_b.clone(), goodbye_b.clone())));
+        assert_eq!(goodbyes.get(&pk_a), Some(&goodbye_a));
+        assert_eq!(goodbyes.get(&pk_b), Some(&goodbye_b));
+        assert_eq!(goodbyes.ticks_left.get(&pk_a), Some(&5));
+        assert!(!goodbyes.ticks_left.contains_key(&pk_b));
+
+        goodbyes.mutate(&GoodbyeMutation::SetTicksLeft((pk_a.clone(), 4)));
+        assert_eq!(goodbyes.ticks_left.get(&pk_a), Some(&4));
+
+        goodbyes.mutate(&GoodbyeMutation::Resume(pk_a.clone()));
+        assert!(!goodbyes.ticks_left.contains_key(&pk_a));
+        assert_eq!(goodbyes.get(&pk_a), Some(&goodbye_a));
+
+        goodbyes.mutate(&GoodbyeMutation::Forget(pk_a.clone()));
+        goodbyes.mutate(&GoodbyeMutation::Forget(pk_b.clone()));
+        assert!(goodbyes.get(&pk_a).is_none());
+        assert!(goodbyes.get(&pk_b).is_none());
+    }
+}
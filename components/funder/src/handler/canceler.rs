@@ -3,7 +3,8 @@ use crypto::identity::PublicKey;
 use std::fmt::Debug;
 
 use proto::funder::messages::{
-    FunderOutgoingControl, RequestSendFunds, ResponseReceived, ResponseSendFundsResult,
+    FailureReason, FunderOutgoingControl, RequestSendFunds, ResponseReceived,
+    ResponseSendFundsResult,
 };
 
 use crate::handler::handler::{find_request_origin, MutableFunderState};
@@ -44,11 +45,12 @@ pub fn reply_with_failure<B>(
     send_commands: &mut SendCommands,
     remote_public_key: &PublicKey,
     request_send_funds: &RequestSendFunds,
+    reason: FailureReason,
 ) where
     B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
 {
     let pending_request = create_pending_request(request_send_funds);
-    let u_failure_op = ResponseOp::UnsignedFailure(pending_request);
+    let u_failure_op = ResponseOp::UnsignedFailure((pending_request, reason));
     let friend_mutation = FriendMutation::PushBackPendingResponse(u_failure_op);
     let funder_mutation =
         FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
@@ -69,46 +71,59 @@ pub fn cancel_local_pending_requests<B>(
     let friend = m_state.state().friends.get(friend_public_key).unwrap();
 
     let token_channel = match &friend.channel_status {
-        ChannelStatus::Inconsistent(_) => unreachable!(),
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => unreachable!(),
         ChannelStatus::Consistent(token_channel) => token_channel,
     };
 
     // Mark all pending requests to this friend as errors.
     // As the token channel is being reset, we can be sure we will never obtain a response
     // for those requests.
-    let pending_local_requests = token_channel
-        .get_mutual_credit()
-        .state()
-        .pending_requests
-        .pending_local_requests
-        .clone();
+    let pending_local_requests = token_channel.get_reset_cancelled_requests();
 
     // Prepare a list of all remote requests that we need to cancel:
-    for (local_request_id, pending_local_request) in pending_local_requests {
+    for pending_local_request in pending_local_requests {
+        let local_request_id = pending_local_request.request_id;
+        if m_state.state().expired_requests.contains(&local_request_id) {
+            // The response deadline of this request has passed, and the origin
+            // was already sent a failure:
+            continue;
+        }
         let opt_origin_public_key =
             find_request_origin(m_state.state(), &local_request_id).cloned();
         match opt_origin_public_key {
             Some(origin_public_key) => {
                 // We have found the friend that is the origin of this request.
                 // We send him a failure message.
-                let u_failure_op = ResponseOp::UnsignedFailure(pending_local_request);
+                let u_failure_op = ResponseOp::UnsignedFailure((
+                    pending_local_request,
+                    FailureReason::Unspecified,
+                ));
                 let friend_mutation = FriendMutation::PushBackPendingResponse(u_failure_op);
                 let funder_mutation =
                     FunderMutation::FriendMutation((origin_public_key.clone(), friend_mutation));
                 m_state.mutate(funder_mutation);
                 send_commands.set_try_send(&origin_public_key);
             }
-            None => {
+            None if pending_local_request.route.index_to_pk(0)
+                == Some(&m_state.state().local_public_key) =>
+            {
                 // We are the origin of this request.
                 // We send a failure response through the control:
+                let local_public_key = m_state.state().local_public_key.clone();
                 let response_received = ResponseReceived {
                     request_id: pending_local_request.request_id,
-                    result: ResponseSendFundsResult::Failure(
-                        m_state.state().local_public_key.clone(),
-                    ),
+                    result: ResponseSendFundsResult::Failure((
+                        local_public_key,
+                        FailureReason::Unspecified,
+                    )),
+                    opt_timing: None,
+                    opt_label: None,
                 };
                 outgoing_control.push(FunderOutgoingControl::ResponseReceived(response_received));
             }
+            None => {
+                // We have forwarded this request, but its origin no longer waits for it.
+            }
         };
     }
 }
@@ -135,7 +150,10 @@ pub fn cancel_pending_requests<B>(
         match opt_origin_public_key {
             Some(origin_public_key) => {
                 let local_pending_request = create_pending_request(&pending_request);
-                let u_failure_op = ResponseOp::UnsignedFailure(local_pending_request);
+                let u_failure_op = ResponseOp::UnsignedFailure((
+                    local_pending_request,
+                    FailureReason::Unspecified,
+                ));
                 let friend_mutation = FriendMutation::PushBackPendingResponse(u_failure_op);
                 let funder_mutation =
                     FunderMutation::FriendMutation((origin_public_key.clone(), friend_mutation));
@@ -144,11 +162,15 @@ pub fn cancel_pending_requests<B>(
             }
             None => {
                 // We are the origin of this request:
+                let local_public_key = m_state.state().local_public_key.clone();
                 let response_received = ResponseReceived {
                     request_id: pending_request.request_id,
-                    result: ResponseSendFundsResult::Failure(
-                        m_state.state().local_public_key.clone(),
-                    ),
+                    result: ResponseSendFundsResult::Failure((
+                        local_public_key,
+                        FailureReason::Unspecified,
+                    )),
+                    opt_timing: None,
+                    opt_label: None,
                 };
                 outgoing_control.push(FunderOutgoingControl::ResponseReceived(response_received));
             }
@@ -173,9 +195,15 @@ pub fn cancel_pending_user_requests<B>(
         m_state.mutate(funder_mutation);
 
         // We are the origin of this request:
+        let local_public_key = m_state.state().local_public_key.clone();
         let response_received = ResponseReceived {
             request_id: pending_user_request.request_id,
-            result: ResponseSendFundsResult::Failure(m_state.state().local_public_key.clone()),
+            result: ResponseSendFundsResult::Failure((
+                local_public_key,
+                FailureReason::Unspecified,
+            )),
+            opt_timing: None,
+            opt_label: None,
         };
         outgoing_control.push(FunderOutgoingControl::ResponseReceived(response_received));
     }
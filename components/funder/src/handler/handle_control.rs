@@ -1,32 +1,58 @@
 use std::fmt::Debug;
 
 use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::usize_to_u32;
+use common::safe_arithmetic::SafeUnsignedArithmetic;
 
 use crypto::identity::PublicKey;
+use crypto::uid::Uid;
 
-use crate::friend::{ChannelStatus, FriendMutation};
-use crate::state::FunderMutation;
+use crate::credit_calc::{credits_to_freeze, max_dest_payment};
+use crate::friend::{ChannelStatus, FriendMutation, FriendState};
+use crate::state::{FunderMutation, FunderState};
 
 use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
+use proto::consts::MAX_PAYMENT_LABEL_LEN;
+use proto::directory::messages::{DirectoryListing, DirectoryState, DirectorySubscription};
 use proto::funder::messages::{
-    AddFriend, ChannelerUpdateFriend, FriendStatus, FunderControl, FunderOutgoingControl,
-    ReceiptAck, RemoveFriend, ResetFriendChannel, ResponseReceived, ResponseSendFundsResult,
-    SetFriendName, SetFriendRelays, SetFriendRemoteMaxDebt, SetFriendStatus, SetRequestsStatus,
-    UserRequestSendFunds,
+    AddFriend, AddFriendFromInvite, CancelUserRequestResult, ChannelerUpdateFriend, DustThresholds,
+    FailureReason, FriendStatus, FunderControl, FunderOutgoingControl, Goodbye, LabeledPayment,
+    PaymentNotifier, PaymentSubmission, PrewarmFailure, PrewarmFriend, PrewarmResult, ReceiptAck,
+    RemoveFriend, RequestPairFreezeUsage, ResetFriendChannel, ResponseCancelUserRequest,
+    ResponsePairFreezeUsage, ResponsePendingSubmissions, ResponsePrewarm, ResponseReceived,
+    ResponseSendFundsResult, SetFriendIndexPrivate, SetFriendName, SetFriendRelays,
+    SetFriendRemoteMaxDebt, SetFriendResponseDeadline, SetFriendStatus,
+    SetFriendVerificationPhrase, SetPairFreezeLimit, SetRequestsStatus, UserRequestSendFunds,
+    UserRequestSweepFunds,
 };
+use proto::invite::messages::{ImportFriendInviteResult, ResponseImportFriendInvite};
 
-use crate::ephemeral::Ephemeral;
+use crate::ephemeral::{Ephemeral, EphemeralMutation};
 use crate::handler::canceler::{
     cancel_local_pending_requests, cancel_pending_requests, cancel_pending_user_requests,
 };
-use crate::handler::handler::{is_friend_ready, MutableEphemeral, MutableFunderState};
+use crate::handler::handle_friend::find_pair_freeze_limit;
+use crate::handler::handler::{
+    forget_goodbye, is_friend_ready, mutate_payment_timings, MutableEphemeral, MutableFunderState,
+};
 use crate::handler::sender::SendCommands;
+use crate::payment_timing::PaymentTimingMutation;
+use crate::prewarm::PrewarmMutation;
+use crate::token_channel::TcDirection;
 
 use crate::types::ChannelerConfig;
 
 #[derive(Debug)]
 pub enum HandleControlError {
     FriendDoesNotExist,
+    /// A friend with the same public key already exists.
+    /// The address of an existing friend is changed using SetFriendRelays.
+    FriendAlreadyExists,
+    /// The friend's stored state was found corrupted, and was moved into quarantine.
+    FriendQuarantined,
+    /// The initial balance with a new friend can not be negated, so the friend can not hold the
+    /// opposite balance.
+    BalanceOutOfRange,
     NotInvitedToReset,
     ResetTokenMismatch,
     NotFirstInRoute,
@@ -35,9 +61,25 @@ pub enum HandleControlError {
     PendingUserRequestsFull,
     ReceiptDoesNotExist,
     ReceiptSignatureMismatch,
+    IncomingPaymentDoesNotExist,
+    /// The app has no submission with this request id.
+    SubmissionDoesNotExist,
+    /// The outcome of the submission is not known yet, so it can not be acknowledged.
+    SubmissionPending,
     UserRequestInvalid,
+    /// The payment is below our minimum payment for sending.
+    BelowMinPayment,
+    /// The maximum amount we can send is below our minimum payment for sending.
+    NothingToSend,
+    /// We can not freeze enough credits against the first hop friend.
+    /// Contains the largest payment we could send along the same route.
+    InsufficientLocalCapacity(u128),
+    /// The database can not be written.
+    StorageUnavailable,
     FriendNotReady,
     MaxNodeRelaysReached,
+    /// The directory listing is not newer than the current listing.
+    StaleDirectoryListing,
 }
 
 fn control_set_friend_remote_max_debt<B>(
@@ -54,8 +96,20 @@ where
         .friends
         .get(&set_friend_remote_max_debt.friend_public_key)
         .ok_or(HandleControlError::FriendDoesNotExist)?;
+    let wanted_remote_max_debt = friend.wanted_remote_max_debt;
+
+    // Setting a new remote max debt always replaces a previous expiry:
+    if friend.opt_remote_max_debt_expiry != set_friend_remote_max_debt.opt_expiry {
+        let friend_mutation =
+            FriendMutation::SetRemoteMaxDebtExpiry(set_friend_remote_max_debt.opt_expiry.clone());
+        let m_mutation = FunderMutation::FriendMutation((
+            set_friend_remote_max_debt.friend_public_key.clone(),
+            friend_mutation,
+        ));
+        m_state.mutate(m_mutation);
+    }
 
-    if friend.wanted_remote_max_debt == set_friend_remote_max_debt.remote_max_debt {
+    if wanted_remote_max_debt == set_friend_remote_max_debt.remote_max_debt {
         // Wanted remote max debt is already set to this value. Nothing to do here.
         return Ok(());
     }
@@ -103,6 +157,17 @@ where
                 }
             }
         }
+        // We have already accepted these reset terms. Accepting them again will only resend our
+        // reset move token:
+        ChannelStatus::PendingReset(channel_pending_reset) => {
+            if channel_pending_reset.remote_reset_terms.reset_token
+                != reset_friend_channel.reset_token
+            {
+                Err(HandleControlError::ResetTokenMismatch)
+            } else {
+                Ok(())
+            }
+        }
     }?;
 
     // We don't have the ability to sign here, therefore we defer the creation
@@ -112,8 +177,11 @@ where
     Ok(())
 }
 
+/// Enabling a friend that is already enabled makes the Channeler try to reconnect to the friend
+/// immediately, even if the friend told us it would be offline for a while.
 fn enable_friend<B>(
     m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
     outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
     friend_public_key: &PublicKey,
     friend_relays: &[RelayAddress<B>],
@@ -130,10 +198,13 @@ fn enable_friend<B>(
     };
     let channeler_config = ChannelerConfig::UpdateFriend(channeler_add_friend);
     outgoing_channeler_config.push(channeler_config);
+
+    forget_goodbye(m_ephemeral, friend_public_key);
 }
 
 fn disable_friend<B>(
     m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
     send_commands: &mut SendCommands,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
@@ -146,6 +217,14 @@ fn disable_friend<B>(
 
     cancel_pending_user_requests(m_state, outgoing_control, friend_public_key);
 
+    // Let the friend know that we are disconnecting on purpose.
+    // We do not know when (and if) we are going to reconnect:
+    let goodbye = Goodbye {
+        opt_downtime_ticks: None,
+    };
+    send_commands.set_goodbye(friend_public_key, goodbye);
+    forget_goodbye(m_ephemeral, friend_public_key);
+
     // Notify Channeler:
     let channeler_config = ChannelerConfig::RemoveFriend(friend_public_key.clone());
     outgoing_channeler_config.push(channeler_config);
@@ -221,18 +300,95 @@ fn control_remove_relay<B>(
     }
 }
 
-fn control_add_friend<B>(m_state: &mut MutableFunderState<B>, add_friend: AddFriend<B>)
+fn control_add_friend<B>(
+    m_state: &mut MutableFunderState<B>,
+    add_friend: AddFriend<B>,
+) -> Result<(), HandleControlError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
+    // A quarantined friend keeps its stored state until it is recovered manually:
+    if m_state
+        .state()
+        .quarantined_friends
+        .contains_key(&add_friend.friend_public_key)
+    {
+        return Err(HandleControlError::FriendQuarantined);
+    }
+
+    // Adding an existing friend again never overrides its state:
+    if m_state
+        .state()
+        .friends
+        .contains_key(&add_friend.friend_public_key)
+    {
+        return Err(HandleControlError::FriendAlreadyExists);
+    }
+
+    if add_friend.balance.checked_neg().is_none() {
+        return Err(HandleControlError::BalanceOutOfRange);
+    }
+
     let funder_mutation = FunderMutation::AddFriend(add_friend.clone());
     m_state.mutate(funder_mutation);
+    Ok(())
+}
+
+/// Add a friend from an invite, and set the max debt it may owe us.
+/// Both changes are applied together, or not at all: If the friend can not be added, nothing is
+/// changed.
+fn control_add_friend_from_invite<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    add_friend_from_invite: AddFriendFromInvite<B>,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let AddFriendFromInvite {
+        request_id,
+        add_friend,
+        opt_remote_max_debt,
+    } = add_friend_from_invite;
+    let friend_public_key = add_friend.friend_public_key.clone();
+
+    let result = match control_add_friend(m_state, add_friend) {
+        Ok(()) => {
+            if let Some(remote_max_debt) = opt_remote_max_debt {
+                let set_friend_remote_max_debt = SetFriendRemoteMaxDebt {
+                    friend_public_key,
+                    remote_max_debt,
+                    opt_expiry: None,
+                };
+                // The friend was just added, so this can not fail:
+                control_set_friend_remote_max_debt(
+                    m_state,
+                    send_commands,
+                    set_friend_remote_max_debt,
+                )?;
+            }
+            ImportFriendInviteResult::Success
+        }
+        Err(HandleControlError::FriendAlreadyExists)
+        | Err(HandleControlError::FriendQuarantined) => {
+            ImportFriendInviteResult::FriendAlreadyExists
+        }
+        Err(e) => return Err(e),
+    };
+
+    let response_import = ResponseImportFriendInvite { request_id, result };
+    outgoing_control.push(FunderOutgoingControl::ResponseImportFriendInvite(
+        response_import,
+    ));
+    Ok(())
 }
 
 /// This is a violent operation, as it removes all the known state with the remote friend.
 /// An inconsistency will occur if the friend is added again.
 fn control_remove_friend<B>(
     m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
     send_commands: &mut SendCommands,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
@@ -250,6 +406,7 @@ where
 
     disable_friend(
         m_state,
+        m_ephemeral,
         send_commands,
         outgoing_control,
         outgoing_channeler_config,
@@ -271,6 +428,7 @@ where
 
 fn control_set_friend_status<B>(
     m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
     send_commands: &mut SendCommands,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
@@ -305,12 +463,14 @@ where
     match set_friend_status.status {
         FriendStatus::Enabled => enable_friend(
             m_state,
+            m_ephemeral,
             outgoing_channeler_config,
             friend_public_key,
             &friend_address,
         ),
         FriendStatus::Disabled => disable_friend(
             m_state,
+            m_ephemeral,
             send_commands,
             outgoing_control,
             outgoing_channeler_config,
@@ -421,10 +581,104 @@ where
     Ok(())
 }
 
+fn control_set_friend_response_deadline<B>(
+    m_state: &mut MutableFunderState<B>,
+    set_friend_response_deadline: SetFriendResponseDeadline,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    // Make sure that friend exists:
+    let friend = m_state
+        .state()
+        .friends
+        .get(&set_friend_response_deadline.friend_public_key)
+        .ok_or(HandleControlError::FriendDoesNotExist)?;
+
+    if friend.opt_response_deadline_ticks == set_friend_response_deadline.opt_deadline_ticks {
+        return Ok(());
+    }
+
+    let friend_mutation =
+        FriendMutation::SetResponseDeadline(set_friend_response_deadline.opt_deadline_ticks);
+    let funder_mutation = FunderMutation::FriendMutation((
+        set_friend_response_deadline.friend_public_key.clone(),
+        friend_mutation,
+    ));
+    m_state.mutate(funder_mutation);
+
+    Ok(())
+}
+
+/// Set the verification phrase of a friend, and prove to the friend that we know it.
+/// A proof the friend has already sent us is checked against the new phrase.
+fn control_set_friend_verification_phrase<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    set_friend_verification_phrase: SetFriendVerificationPhrase,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    // Make sure that friend exists:
+    let _ = m_state
+        .state()
+        .friends
+        .get(&set_friend_verification_phrase.friend_public_key)
+        .ok_or(HandleControlError::FriendDoesNotExist)?;
+
+    let friend_mutation =
+        FriendMutation::SetVerificationPhrase(set_friend_verification_phrase.phrase);
+    let funder_mutation = FunderMutation::FriendMutation((
+        set_friend_verification_phrase.friend_public_key.clone(),
+        friend_mutation,
+    ));
+    m_state.mutate(funder_mutation);
+
+    send_commands.set_send_verification_proof(&set_friend_verification_phrase.friend_public_key);
+
+    Ok(())
+}
+
+/// Hide (Or stop hiding) the capacities with a friend from index servers.
+/// The report mutation of the friend is translated to index mutations outside of the funder.
+fn control_set_friend_index_private<B>(
+    m_state: &mut MutableFunderState<B>,
+    set_friend_index_private: SetFriendIndexPrivate,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    // Make sure that friend exists:
+    let friend = m_state
+        .state()
+        .friends
+        .get(&set_friend_index_private.friend_public_key)
+        .ok_or(HandleControlError::FriendDoesNotExist)?;
+
+    if friend.index_private == set_friend_index_private.index_private {
+        return Ok(());
+    }
+
+    let friend_mutation = FriendMutation::SetIndexPrivate(set_friend_index_private.index_private);
+    let funder_mutation = FunderMutation::FriendMutation((
+        set_friend_index_private.friend_public_key.clone(),
+        friend_mutation,
+    ));
+    m_state.mutate(funder_mutation);
+
+    Ok(())
+}
+
 fn check_user_request_valid(user_request_send_funds: &UserRequestSendFunds) -> Option<()> {
     if !user_request_send_funds.route.is_valid() {
         return None;
     }
+    if let Some(label) = &user_request_send_funds.opt_label {
+        if label.len() > MAX_PAYMENT_LABEL_LEN {
+            return None;
+        }
+    }
     Some(())
 }
 
@@ -453,11 +707,22 @@ where
         let response_received = ResponseReceived {
             request_id: user_request_send_funds.request_id,
             result: ResponseSendFundsResult::Success(receipt.clone()),
+            opt_timing: None,
+            opt_label: user_request_send_funds.opt_label.clone(),
         };
         outgoing_control.push(FunderOutgoingControl::ResponseReceived(response_received));
         return Ok(());
     }
 
+    // We could not persist the request:
+    if ephemeral.read_only {
+        return Err(HandleControlError::StorageUnavailable);
+    }
+
+    if user_request_send_funds.dest_payment < m_state.state().dust_thresholds.min_send_payment {
+        return Err(HandleControlError::BelowMinPayment);
+    }
+
     let route = &user_request_send_funds.route;
 
     // We have to be the first on the route:
@@ -473,6 +738,14 @@ where
     }
     let friend_public_key = route.public_keys[1].clone();
 
+    if m_state
+        .state()
+        .quarantined_friends
+        .contains_key(&friend_public_key)
+    {
+        return Err(HandleControlError::FriendQuarantined);
+    }
+
     let friend = match m_state.state().friends.get(&friend_public_key) {
         Some(friend) => Ok(friend),
         None => Err(HandleControlError::FriendDoesNotExist),
@@ -491,7 +764,7 @@ where
     }
 
     let token_channel = match &friend.channel_status {
-        ChannelStatus::Inconsistent(_) => unreachable!(),
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => unreachable!(),
         ChannelStatus::Consistent(token_channel) => token_channel,
     };
 
@@ -511,6 +784,29 @@ where
         return Err(HandleControlError::PendingUserRequestsFull);
     }
 
+    // Make sure the request can be frozen against the friend when it is sent, taking into account
+    // the requests that are already queued. Otherwise the request would fail later, in a way
+    // indistinguishable from a remote failure:
+    let route_len = usize_to_u32(route.len()).ok_or(HandleControlError::InvalidRoute)?;
+    let freeze_credits = credits_to_freeze(1, route_len, user_request_send_funds.dest_payment)
+        .ok_or(HandleControlError::InvalidRoute)?;
+    let send_capacity = remaining_send_capacity(friend, &friend_public_key);
+    if freeze_credits > send_capacity {
+        let max_payment = max_dest_payment(route_len, send_capacity).unwrap_or(0);
+        return Err(HandleControlError::InsufficientLocalCapacity(max_payment));
+    }
+
+    // Remember the label, so that the payment could be found by its label later.
+    // The label itself is never sent to the friend:
+    if let Some(label) = &user_request_send_funds.opt_label {
+        let labeled_payment = LabeledPayment {
+            request_id: user_request_send_funds.request_id,
+            label: label.clone(),
+            opt_result: None,
+        };
+        m_state.mutate(FunderMutation::AddLabeledPayment(labeled_payment));
+    }
+
     let request_send_funds = user_request_send_funds.into_request();
     let friend_mutation = FriendMutation::PushBackPendingUserRequest(request_send_funds);
     let funder_mutation =
@@ -521,34 +817,120 @@ where
     Ok(())
 }
 
-fn control_request_send_funds<B>(
+/// Remember a payment submitted by an app, together with its result if the result is already
+/// known. A request id that was submitted before keeps its original submission.
+///
+/// The submission is persisted together with the admission of the payment, so an app that lost
+/// its connection while submitting the payment can always find out what happened to it.
+fn record_submission<B>(
     m_state: &mut MutableFunderState<B>,
     ephemeral: &Ephemeral,
+    outgoing_control: &[FunderOutgoingControl<B>],
+    app_public_key: &PublicKey,
+    user_request_send_funds: &UserRequestSendFunds,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    // The submission could not be persisted:
+    if ephemeral.read_only {
+        return;
+    }
+
+    let request_id = user_request_send_funds.request_id;
+    if m_state
+        .state()
+        .submissions
+        .iter()
+        .any(|submission| submission.request_id == request_id)
+    {
+        return;
+    }
+
+    let opt_result = outgoing_control.iter().find_map(|control| match control {
+        FunderOutgoingControl::ResponseReceived(response_received)
+            if response_received.request_id == request_id =>
+        {
+            Some(response_received.result.clone())
+        }
+        _ => None,
+    });
+
+    let submission = PaymentSubmission {
+        request_id,
+        app_public_key: app_public_key.clone(),
+        route: user_request_send_funds.route.clone(),
+        invoice_id: user_request_send_funds.invoice_id.clone(),
+        dest_payment: user_request_send_funds.dest_payment,
+        opt_result,
+    };
+    m_state.mutate(FunderMutation::AddPaymentSubmission(submission));
+}
+
+fn control_request_send_funds<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     send_commands: &mut SendCommands,
     max_pending_user_requests: usize,
+    opt_app_public_key: Option<&PublicKey>,
     user_request_send_funds: UserRequestSendFunds,
 ) -> Result<(), HandleControlError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
-    // If we managed to push the message, we return an Ok(()).
-    // Otherwise, we return the internal error and return a response failure message.
-    if let Err(e) = control_request_send_funds_inner(
+    let res = control_request_send_funds_inner(
         m_state,
-        ephemeral,
+        m_ephemeral.ephemeral(),
         outgoing_control,
         send_commands,
         max_pending_user_requests,
         user_request_send_funds.clone(),
-    ) {
+    );
+
+    // A request that is already in progress keeps its own submission, if it has one:
+    let is_in_progress = match res {
+        Err(HandleControlError::RequestAlreadyInProgress) => true,
+        _ => false,
+    };
+
+    // If we managed to push the message, we return an Ok(()).
+    // Otherwise, we return the internal error and return a response failure message.
+    if let Err(e) = res {
         error!("control_request_send_funds_inner() failed: {:?}", e);
+        let reason = match e {
+            HandleControlError::BelowMinPayment => FailureReason::PricingRejected,
+            HandleControlError::InsufficientLocalCapacity(max_payment) => {
+                FailureReason::InsufficientLocalCapacity(max_payment)
+            }
+            HandleControlError::StorageUnavailable => FailureReason::StorageUnavailable,
+            _ => FailureReason::Unspecified,
+        };
+        let local_public_key = m_state.state().local_public_key.clone();
         let response_received = ResponseReceived {
             request_id: user_request_send_funds.request_id,
-            result: ResponseSendFundsResult::Failure(m_state.state().local_public_key.clone()),
+            result: ResponseSendFundsResult::Failure((local_public_key, reason)),
+            opt_timing: None,
+            opt_label: user_request_send_funds.opt_label.clone(),
         };
 
         outgoing_control.push(FunderOutgoingControl::ResponseReceived(response_received));
+    } else {
+        // Measure the amount of ticks until the payment is answered:
+        let payment_timing_mutation =
+            PaymentTimingMutation::Start(user_request_send_funds.request_id);
+        mutate_payment_timings(m_ephemeral, payment_timing_mutation);
+    }
+
+    if let Some(app_public_key) = opt_app_public_key {
+        if !is_in_progress {
+            record_submission(
+                m_state,
+                m_ephemeral.ephemeral(),
+                outgoing_control,
+                app_public_key,
+                &user_request_send_funds,
+            );
+        }
     }
 
     // Every RequestSendFunds must have a matching response. Therefore we don't return an error
@@ -556,6 +938,326 @@ where
     Ok(())
 }
 
+/// Credits we will freeze when the requests queued for a friend are sent to the friend.
+fn queued_freeze_credits<B>(friend: &FriendState<B>, friend_public_key: &PublicKey) -> u128
+where
+    B: Clone,
+{
+    friend
+        .pending_user_requests
+        .iter()
+        .chain(friend.pending_requests.iter())
+        .filter_map(|request_send_funds| {
+            let route = &request_send_funds.route;
+            let node_index = usize_to_u32(route.pk_to_index(friend_public_key)?)?;
+            let route_len = usize_to_u32(route.len())?;
+            credits_to_freeze(node_index, route_len, request_send_funds.dest_payment)
+        })
+        .fold(0, u128::saturating_add)
+}
+
+/// Credits we can still freeze against a friend, after the requests queued for the friend are
+/// sent.
+fn remaining_send_capacity<B>(friend: &FriendState<B>, friend_public_key: &PublicKey) -> u128
+where
+    B: Clone,
+{
+    let balance = match &friend.channel_status {
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => unreachable!(),
+        ChannelStatus::Consistent(token_channel) => {
+            &token_channel.get_mutual_credit().state().balance
+        }
+    };
+    // We may freeze credits as long as local_pending_debt - balance <= local_max_debt:
+    balance
+        .local_max_debt
+        .saturating_add_signed(balance.balance)
+        .saturating_sub(balance.local_pending_debt)
+        .saturating_sub(queued_freeze_credits(friend, friend_public_key))
+}
+
+/// Calculate the maximum dest_payment we can send along the route of a sweep request.
+/// The total amount we freeze (dest_payment and fees) must fit both in our capacity to send to
+/// the first hop friend and in the capacity of the route.
+fn sweep_dest_payment<B>(
+    state: &FunderState<B>,
+    ephemeral: &Ephemeral,
+    user_request_sweep_funds: &UserRequestSweepFunds,
+) -> Result<u128, HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    // A retransmitted sweep that already succeeded gets its receipt back:
+    if let Some(receipt) = state
+        .ready_receipts
+        .get(&user_request_sweep_funds.request_id)
+    {
+        return Ok(receipt.dest_payment);
+    }
+
+    if ephemeral.read_only {
+        return Err(HandleControlError::StorageUnavailable);
+    }
+
+    let route = &user_request_sweep_funds.route;
+    if !route.is_valid() {
+        return Err(HandleControlError::InvalidRoute);
+    }
+    if route.public_keys[0] != state.local_public_key {
+        return Err(HandleControlError::NotFirstInRoute);
+    }
+    let friend_public_key = &route.public_keys[1];
+    let friend = state
+        .friends
+        .get(friend_public_key)
+        .ok_or(HandleControlError::FriendDoesNotExist)?;
+    if !is_friend_ready(state, ephemeral, friend_public_key) {
+        return Err(HandleControlError::FriendNotReady);
+    }
+
+    let send_capacity = remaining_send_capacity(friend, friend_public_key);
+    let max_freeze_credits = match user_request_sweep_funds.opt_route_capacity {
+        Some(route_capacity) => send_capacity.min(route_capacity),
+        None => send_capacity,
+    };
+
+    let route_len = usize_to_u32(route.len()).ok_or(HandleControlError::InvalidRoute)?;
+    let dest_payment = max_dest_payment(route_len, max_freeze_credits)
+        .ok_or(HandleControlError::NothingToSend)?;
+    if dest_payment == 0 || dest_payment < state.dust_thresholds.min_send_payment {
+        return Err(HandleControlError::NothingToSend);
+    }
+    Ok(dest_payment)
+}
+
+/// Send the maximum possible amount along a route.
+/// Like RequestSendFunds, every sweep request gets a matching response.
+fn control_request_sweep_funds<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    send_commands: &mut SendCommands,
+    max_pending_user_requests: usize,
+    opt_app_public_key: Option<&PublicKey>,
+    user_request_sweep_funds: UserRequestSweepFunds,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    match sweep_dest_payment(
+        m_state.state(),
+        m_ephemeral.ephemeral(),
+        &user_request_sweep_funds,
+    ) {
+        Ok(dest_payment) => control_request_send_funds(
+            m_state,
+            m_ephemeral,
+            outgoing_control,
+            send_commands,
+            max_pending_user_requests,
+            opt_app_public_key,
+            user_request_sweep_funds.into_user_request_send_funds(dest_payment),
+        ),
+        Err(e) => {
+            error!("sweep_dest_payment() failed: {:?}", e);
+            let reason = match e {
+                HandleControlError::NothingToSend => FailureReason::NothingToSend,
+                HandleControlError::StorageUnavailable => FailureReason::StorageUnavailable,
+                _ => FailureReason::Unspecified,
+            };
+            let local_public_key = m_state.state().local_public_key.clone();
+            let response_received = ResponseReceived {
+                request_id: user_request_sweep_funds.request_id,
+                result: ResponseSendFundsResult::Failure((local_public_key, reason)),
+                opt_timing: None,
+                opt_label: None,
+            };
+            outgoing_control.push(FunderOutgoingControl::ResponseReceived(response_received));
+
+            // Nothing was sent:
+            if let Some(app_public_key) = opt_app_public_key {
+                record_submission(
+                    m_state,
+                    m_ephemeral.ephemeral(),
+                    outgoing_control,
+                    app_public_key,
+                    &user_request_sweep_funds.into_user_request_send_funds(0),
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+/// List the submissions of an app whose outcome the app has not acknowledged yet.
+fn control_list_pending_submissions<B>(
+    m_state: &MutableFunderState<B>,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    opt_app_public_key: Option<&PublicKey>,
+    request_id: Uid,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let submissions = m_state
+        .state()
+        .submissions
+        .iter()
+        .filter(|submission| Some(&submission.app_public_key) == opt_app_public_key)
+        .cloned()
+        .collect();
+
+    let response_pending_submissions = ResponsePendingSubmissions {
+        request_id,
+        submissions,
+    };
+    outgoing_control.push(FunderOutgoingControl::ResponsePendingSubmissions(
+        response_pending_submissions,
+    ));
+}
+
+/// Forget a submission whose outcome was learned by the app that submitted it.
+fn control_ack_submission<B>(
+    m_state: &mut MutableFunderState<B>,
+    opt_app_public_key: Option<&PublicKey>,
+    request_id: Uid,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let submission = m_state
+        .state()
+        .submissions
+        .iter()
+        .find(|submission| {
+            submission.request_id == request_id
+                && Some(&submission.app_public_key) == opt_app_public_key
+        })
+        .ok_or(HandleControlError::SubmissionDoesNotExist)?;
+
+    // The outcome of a pending submission is not known yet:
+    if submission.opt_result.is_none() {
+        return Err(HandleControlError::SubmissionPending);
+    }
+
+    m_state.mutate(FunderMutation::RemovePaymentSubmission(request_id));
+    Ok(())
+}
+
+fn control_set_pair_freeze_limit<B>(
+    m_state: &mut MutableFunderState<B>,
+    set_pair_freeze_limit: SetPairFreezeLimit,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let opt_cur_limit = m_state
+        .state()
+        .pair_freeze_limits
+        .iter()
+        .find(|pair_freeze_limit| pair_freeze_limit.pair == set_pair_freeze_limit.pair)
+        .map(|pair_freeze_limit| pair_freeze_limit.limit);
+    if opt_cur_limit == set_pair_freeze_limit.opt_limit {
+        // Nothing to do here:
+        return;
+    }
+    m_state.mutate(FunderMutation::SetPairFreezeLimit(set_pair_freeze_limit));
+}
+
+/// Report the rule that applies to a pair of friends, and the credits currently frozen for
+/// the pair.
+fn control_request_pair_freeze_usage<B>(
+    m_state: &MutableFunderState<B>,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    request_usage: RequestPairFreezeUsage,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let opt_limit = find_pair_freeze_limit(
+        &m_state.state().pair_freeze_limits,
+        &request_usage.from_public_key,
+        &request_usage.to_public_key,
+    )
+    .cloned();
+
+    let frozen_credits = m_state
+        .state()
+        .friends
+        .get(&request_usage.to_public_key)
+        .map_or(0, |friend| {
+            friend.get_frozen_credits_from(&request_usage.from_public_key)
+        });
+
+    let response_usage = ResponsePairFreezeUsage {
+        request_id: request_usage.request_id,
+        opt_limit,
+        frozen_credits,
+    };
+    outgoing_control.push(FunderOutgoingControl::ResponsePairFreezeUsage(
+        response_usage,
+    ));
+}
+
+/// Withdraw a user request that is still waiting to be sent to the first hop friend.
+///
+/// A request is sent atomically with the move token it was queued into, therefore a request is
+/// either still queued (And can be cancelled), or already pending at the token channel with the
+/// first hop friend.
+fn control_cancel_user_request<B>(
+    m_state: &mut MutableFunderState<B>,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    request_id: Uid,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let opt_queued_friend = m_state
+        .state()
+        .friends
+        .iter()
+        .find(|(_, friend)| {
+            friend
+                .pending_user_requests
+                .iter()
+                .any(|request| request.request_id == request_id)
+        })
+        .map(|(friend_public_key, _)| friend_public_key.clone());
+
+    let result = if let Some(friend_public_key) = opt_queued_friend {
+        let friend_mutation = FriendMutation::RemovePendingUserRequest(request_id);
+        let funder_mutation = FunderMutation::FriendMutation((friend_public_key, friend_mutation));
+        m_state.mutate(funder_mutation);
+
+        // We are the origin of this request:
+        let local_public_key = m_state.state().local_public_key.clone();
+        let response_received = ResponseReceived {
+            request_id,
+            result: ResponseSendFundsResult::Failure((local_public_key, FailureReason::Cancelled)),
+            opt_timing: None,
+            opt_label: None,
+        };
+        outgoing_control.push(FunderOutgoingControl::ResponseReceived(response_received));
+        CancelUserRequestResult::Cancelled
+    } else if m_state.state().friends.values().any(|friend| {
+        if let ChannelStatus::Consistent(token_channel) = &friend.channel_status {
+            token_channel
+                .get_mutual_credit()
+                .state()
+                .pending_requests
+                .pending_local_requests
+                .contains_key(&request_id)
+        } else {
+            false
+        }
+    }) {
+        CancelUserRequestResult::InFlight
+    } else {
+        CancelUserRequestResult::NotFound
+    };
+
+    let response_cancel = ResponseCancelUserRequest { request_id, result };
+    outgoing_control.push(FunderOutgoingControl::ResponseCancelUserRequest(
+        response_cancel,
+    ));
+}
+
 /// Handle an incoming receipt ack message
 fn control_receipt_ack<B>(
     m_state: &mut MutableFunderState<B>,
@@ -583,6 +1285,242 @@ where
     Ok(())
 }
 
+fn control_set_dust_thresholds<B>(
+    m_state: &mut MutableFunderState<B>,
+    dust_thresholds: DustThresholds,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    if m_state.state().dust_thresholds == dust_thresholds {
+        // Nothing to do here:
+        return;
+    }
+    let funder_mutation = FunderMutation::SetDustThresholds(dust_thresholds);
+    m_state.mutate(funder_mutation);
+}
+
+fn set_directory_state<B>(m_state: &mut MutableFunderState<B>, directory: DirectoryState<B>)
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    if m_state.state().directory == directory {
+        // Nothing to do here:
+        return;
+    }
+    let funder_mutation = FunderMutation::SetDirectory(directory);
+    m_state.mutate(funder_mutation);
+}
+
+/// Set (or clear) the directory we are subscribed to.
+/// Relays and index servers added by a previous directory are kept, so that the new directory
+/// may remove them. The version is reset, as versions of different directories are unrelated.
+fn control_set_directory<B>(
+    m_state: &mut MutableFunderState<B>,
+    opt_subscription: Option<DirectorySubscription<B>>,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let mut directory = m_state.state().directory.clone();
+    if directory.opt_subscription == opt_subscription {
+        // Nothing to do here:
+        return;
+    }
+    directory.opt_subscription = opt_subscription;
+    directory.listing.version = 0;
+    set_directory_state(m_state, directory);
+}
+
+/// Record the listing of a newly applied directory document.
+/// Listings older than the current listing are ignored.
+fn control_set_directory_listing<B>(
+    m_state: &mut MutableFunderState<B>,
+    listing: DirectoryListing,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let mut directory = m_state.state().directory.clone();
+    if listing.version <= directory.listing.version {
+        return Err(HandleControlError::StaleDirectoryListing);
+    }
+    directory.listing = listing;
+    set_directory_state(m_state, directory);
+    Ok(())
+}
+
+fn control_pin_directory_entry<B>(
+    m_state: &mut MutableFunderState<B>,
+    public_key: PublicKey,
+    pin: bool,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let mut directory = m_state.state().directory.clone();
+    directory
+        .pinned
+        .retain(|pinned_public_key| pinned_public_key != &public_key);
+    if pin {
+        directory.pinned.push(public_key);
+    }
+    set_directory_state(m_state, directory);
+}
+
+/// Set (or clear) the consumer of notifications about incoming payments.
+/// Notifications that were not yet acknowledged are sent again, for the new consumer.
+fn control_set_payment_notifier<B>(
+    m_state: &mut MutableFunderState<B>,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    opt_payment_notifier: Option<PaymentNotifier<B>>,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let funder_mutation = FunderMutation::SetPaymentNotifier(opt_payment_notifier.clone());
+    m_state.mutate(funder_mutation);
+
+    let has_consumer = opt_payment_notifier.is_some();
+    outgoing_control.push(FunderOutgoingControl::PaymentNotifierChanged(
+        opt_payment_notifier,
+    ));
+
+    if has_consumer {
+        for incoming_payment in m_state.state().incoming_payments.values() {
+            outgoing_control.push(FunderOutgoingControl::IncomingPayment(
+                incoming_payment.clone(),
+            ));
+        }
+    }
+}
+
+/// Handle an acknowledgement of a delivered incoming payment notification
+fn control_ack_incoming_payment<B>(
+    m_state: &mut MutableFunderState<B>,
+    notification_id: u64,
+) -> Result<(), HandleControlError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    if !m_state
+        .state()
+        .incoming_payments
+        .contains_key(&notification_id)
+    {
+        return Err(HandleControlError::IncomingPaymentDoesNotExist);
+    }
+
+    let funder_mutation = FunderMutation::RemoveIncomingPayment(notification_id);
+    m_state.mutate(funder_mutation);
+
+    Ok(())
+}
+
+/// Get the token of a friend in advance, so that a following payment through this friend will
+/// not have to wait for the token. No credit operations are sent.
+/// Every PrewarmFriend request gets a matching response.
+fn control_prewarm_friend<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    send_commands: &mut SendCommands,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    prewarm_ticks: usize,
+    prewarm_friend: PrewarmFriend,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let PrewarmFriend {
+        request_id,
+        friend_public_key,
+    } = prewarm_friend;
+
+    let check_res = check_prewarm_friend(m_state, m_ephemeral.ephemeral(), &friend_public_key);
+    let result = match check_res {
+        Ok(true) => PrewarmResult::Success,
+        Ok(false) => {
+            // The token is at the remote side:
+            let prewarm = &m_ephemeral.ephemeral().prewarm;
+            let is_pending = prewarm.is_pending(&friend_public_key);
+            let is_limited = prewarm.is_limited(&friend_public_key);
+
+            if !is_pending && is_limited {
+                PrewarmResult::Failure(PrewarmFailure::RateLimited)
+            } else {
+                // If the token was already requested for another pre-warm, we just wait for it
+                // together with the other pre-warm:
+                if !is_pending {
+                    let prewarm_mutation =
+                        PrewarmMutation::SetRequested((friend_public_key.clone(), prewarm_ticks));
+                    m_ephemeral.mutate(EphemeralMutation::PrewarmMutation(prewarm_mutation));
+                    send_commands.set_want_token(&friend_public_key);
+                }
+                let prewarm_mutation = PrewarmMutation::AddPending((friend_public_key, request_id));
+                m_ephemeral.mutate(EphemeralMutation::PrewarmMutation(prewarm_mutation));
+                // A response will be sent when the token arrives:
+                return;
+            }
+        }
+        Err(prewarm_failure) => PrewarmResult::Failure(prewarm_failure),
+    };
+
+    let response_prewarm = ResponsePrewarm { request_id, result };
+    outgoing_control.push(FunderOutgoingControl::ResponsePrewarm(response_prewarm));
+}
+
+/// Send a goodbye to all the enabled friends that are online.
+fn control_announce_shutdown<B>(
+    m_state: &MutableFunderState<B>,
+    ephemeral: &Ephemeral,
+    send_commands: &mut SendCommands,
+    goodbye: Goodbye,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    for (friend_public_key, friend) in &m_state.state().friends {
+        if let FriendStatus::Disabled = friend.status {
+            continue;
+        }
+        if ephemeral.liveness.is_online(friend_public_key) {
+            send_commands.set_goodbye(friend_public_key, goodbye.clone());
+        }
+    }
+}
+
+/// Check if the channel with a friend can be used for payments: The friend is enabled and online,
+/// and the channel is consistent.
+/// On success, returns whether we hold the token of the channel.
+pub fn check_prewarm_friend<B>(
+    m_state: &MutableFunderState<B>,
+    ephemeral: &Ephemeral,
+    friend_public_key: &PublicKey,
+) -> Result<bool, PrewarmFailure>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let friend = m_state
+        .state()
+        .friends
+        .get(friend_public_key)
+        .ok_or(PrewarmFailure::FriendDoesNotExist)?;
+
+    if let FriendStatus::Disabled = friend.status {
+        return Err(PrewarmFailure::FriendDisabled);
+    }
+
+    if !ephemeral.liveness.is_online(friend_public_key) {
+        return Err(PrewarmFailure::FriendOffline);
+    }
+
+    let token_channel = match &friend.channel_status {
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => {
+            return Err(PrewarmFailure::ChannelInconsistent)
+        }
+        ChannelStatus::Consistent(token_channel) => token_channel,
+    };
+
+    Ok(match token_channel.get_direction() {
+        TcDirection::Incoming(_) => true,
+        TcDirection::Outgoing(_) => false,
+    })
+}
+
 pub fn handle_control_message<B>(
     m_state: &mut MutableFunderState<B>,
     m_ephemeral: &mut MutableEphemeral,
@@ -591,6 +1529,8 @@ pub fn handle_control_message<B>(
     outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
     max_node_relays: usize,
     max_pending_user_requests: usize,
+    prewarm_ticks: usize,
+    opt_app_public_key: Option<PublicKey>,
     incoming_control: FunderControl<B>,
 ) -> Result<(), HandleControlError>
 where
@@ -623,13 +1563,20 @@ where
             Ok(())
         }
 
-        FunderControl::AddFriend(add_friend) => {
-            control_add_friend(m_state, add_friend);
-            Ok(())
+        FunderControl::AddFriend(add_friend) => control_add_friend(m_state, add_friend),
+
+        FunderControl::AddFriendFromInvite(add_friend_from_invite) => {
+            control_add_friend_from_invite(
+                m_state,
+                send_commands,
+                outgoing_control,
+                add_friend_from_invite,
+            )
         }
 
         FunderControl::RemoveFriend(remove_friend) => control_remove_friend(
             m_state,
+            m_ephemeral,
             send_commands,
             outgoing_control,
             outgoing_channeler_config,
@@ -638,6 +1585,7 @@ where
 
         FunderControl::SetFriendStatus(set_friend_status) => control_set_friend_status(
             m_state,
+            m_ephemeral,
             send_commands,
             outgoing_control,
             outgoing_channeler_config,
@@ -656,15 +1604,131 @@ where
             control_set_friend_name(m_state, set_friend_name)
         }
 
+        FunderControl::SetFriendResponseDeadline(set_friend_response_deadline) => {
+            control_set_friend_response_deadline(m_state, set_friend_response_deadline)
+        }
+
+        FunderControl::SetFriendVerificationPhrase(set_friend_verification_phrase) => {
+            control_set_friend_verification_phrase(
+                m_state,
+                send_commands,
+                set_friend_verification_phrase,
+            )
+        }
+
+        FunderControl::SetFriendIndexPrivate(set_friend_index_private) => {
+            control_set_friend_index_private(m_state, set_friend_index_private)
+        }
+
         FunderControl::RequestSendFunds(user_request_send_funds) => control_request_send_funds(
             m_state,
-            m_ephemeral.ephemeral(),
+            m_ephemeral,
             outgoing_control,
             send_commands,
             max_pending_user_requests,
+            opt_app_public_key.as_ref(),
             user_request_send_funds,
         ),
 
+        FunderControl::RequestSweepFunds(user_request_sweep_funds) => control_request_sweep_funds(
+            m_state,
+            m_ephemeral,
+            outgoing_control,
+            send_commands,
+            max_pending_user_requests,
+            opt_app_public_key.as_ref(),
+            user_request_sweep_funds,
+        ),
+
+        FunderControl::CancelUserRequest(request_id) => {
+            control_cancel_user_request(m_state, outgoing_control, request_id);
+            Ok(())
+        }
+
         FunderControl::ReceiptAck(receipt_ack) => control_receipt_ack(m_state, receipt_ack),
+
+        FunderControl::SetPaymentNotifier(payment_notifier) => {
+            control_set_payment_notifier(m_state, outgoing_control, Some(payment_notifier));
+            Ok(())
+        }
+
+        FunderControl::ClearPaymentNotifier => {
+            control_set_payment_notifier(m_state, outgoing_control, None);
+            Ok(())
+        }
+
+        FunderControl::AckIncomingPayment(notification_id) => {
+            control_ack_incoming_payment(m_state, notification_id)
+        }
+
+        FunderControl::PrewarmFriend(prewarm_friend) => {
+            control_prewarm_friend(
+                m_state,
+                m_ephemeral,
+                send_commands,
+                outgoing_control,
+                prewarm_ticks,
+                prewarm_friend,
+            );
+            Ok(())
+        }
+
+        FunderControl::SetDustThresholds(dust_thresholds) => {
+            control_set_dust_thresholds(m_state, dust_thresholds);
+            Ok(())
+        }
+
+        FunderControl::SetDirectory(subscription) => {
+            control_set_directory(m_state, Some(subscription));
+            Ok(())
+        }
+
+        FunderControl::ClearDirectory => {
+            control_set_directory(m_state, None);
+            Ok(())
+        }
+
+        FunderControl::SetDirectoryListing(listing) => {
+            control_set_directory_listing(m_state, listing)
+        }
+
+        FunderControl::PinDirectoryEntry(public_key) => {
+            control_pin_directory_entry(m_state, public_key, true);
+            Ok(())
+        }
+
+        FunderControl::UnpinDirectoryEntry(public_key) => {
+            control_pin_directory_entry(m_state, public_key, false);
+            Ok(())
+        }
+
+        FunderControl::AnnounceShutdown(goodbye) => {
+            control_announce_shutdown(m_state, m_ephemeral.ephemeral(), send_commands, goodbye);
+            Ok(())
+        }
+
+        FunderControl::ListPendingSubmissions(request_id) => {
+            control_list_pending_submissions(
+                m_state,
+                outgoing_control,
+                opt_app_public_key.as_ref(),
+                request_id,
+            );
+            Ok(())
+        }
+
+        FunderControl::AckSubmission(request_id) => {
+            control_ack_submission(m_state, opt_app_public_key.as_ref(), request_id)
+        }
+
+        FunderControl::SetPairFreezeLimit(set_pair_freeze_limit) => {
+            control_set_pair_freeze_limit(m_state, set_pair_freeze_limit);
+            Ok(())
+        }
+
+        FunderControl::RequestPairFreezeUsage(request_usage) => {
+            control_request_pair_freeze_usage(m_state, outgoing_control, request_usage);
+            Ok(())
+        }
     }
 }
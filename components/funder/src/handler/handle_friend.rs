@@ -1,30 +1,49 @@
 use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::{usize_to_u32, usize_to_u64};
+use common::safe_arithmetic::SafeUnsignedArithmetic;
 use std::fmt::Debug;
 
+use im::hashset::HashSet as ImHashSet;
+use im::vector::Vector as ImVec;
+
 use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::{PublicKey, Signature, SIGNATURE_LEN};
+use crypto::uid::Uid;
 
-use proto::app_server::messages::RelayAddress;
+use proto::app_server::messages::{is_valid_relays, RelayAddress};
 use proto::funder::messages::{
-    ChannelerUpdateFriend, FailureSendFunds, FriendMessage, FunderOutgoingControl,
-    MoveTokenRequest, PendingRequest, RequestSendFunds, ResetTerms, ResponseReceived,
-    ResponseSendFunds, ResponseSendFundsResult,
+    ChannelerUpdateFriend, FailureReason, FailureSendFunds, FriendMessage, FriendPair,
+    FriendStatus, FriendTcOp, FunderOutgoingControl, Goodbye, MoveToken, MoveTokenRequest,
+    OperationErrorCode, PairFreezeLimit, PaymentTiming, PendingRequest, ProtocolViolationReport,
+    RequestSendFunds, ResetTerms, ResponseReceived, ResponseSendFunds, ResponseSendFundsResult,
+    SetPairFreezeLimit, VerificationProof,
 };
 use proto::funder::signature_buff::{prepare_receipt, verify_move_token};
 
+use crate::credit_calc::CreditCalculator;
 use crate::mutual_credit::incoming::{
     IncomingFailureSendFunds, IncomingMessage, IncomingResponseSendFunds,
 };
-use crate::token_channel::{MoveTokenReceived, ReceiveMoveTokenOutput, TokenChannel};
+use crate::token_channel::{
+    MoveTokenReceived, ReceiveMoveTokenError, ReceiveMoveTokenOutput,
+    ReceiveMoveTokenRequestOutput, TokenChannel,
+};
 
 use crate::types::{create_pending_request, ChannelerConfig};
 
 use crate::friend::{
-    ChannelInconsistent, ChannelStatus, FriendMutation, ResponseOp, SentLocalRelays,
+    ChannelInconsistent, ChannelPendingReset, ChannelStatus, FriendMutation, ResponseOp,
+    SentLocalRelays,
 };
-use crate::state::FunderMutation;
+use crate::state::{FunderMutation, FunderState};
 
-use crate::ephemeral::Ephemeral;
+use crate::adaptive_batch::AdaptiveBatchMutation;
+use crate::completed::CompletedMutation;
+use crate::damping::RelaysDampingMutation;
+use crate::ephemeral::{Ephemeral, EphemeralMutation};
+use crate::goodbye::GoodbyeMutation;
+use crate::payment_timing::PaymentTimingMutation;
+use crate::reliability::{PaymentOutcome, ReliabilityMutation};
 
 use crate::handler::canceler::{
     cancel_local_pending_requests, cancel_pending_requests, cancel_pending_user_requests,
@@ -39,6 +58,10 @@ use crate::handler::sender::SendCommands;
 pub enum HandleFriendError {
     FriendDoesNotExist,
     InconsistencyWhenTokenOwned,
+    /// Our balance for reset can not be represented.
+    BalanceForResetOverflow,
+    /// The remote reset terms contain a balance we can not negate.
+    InvalidRemoteResetTerms,
 }
 
 /// Generate a random token to be used for resetting the channel.
@@ -51,7 +74,10 @@ where
     Signature::from(buff)
 }
 
-pub fn gen_reset_terms<B, R>(token_channel: &TokenChannel<B>, rng: &R) -> ResetTerms
+pub fn gen_reset_terms<B, R>(
+    token_channel: &TokenChannel<B>,
+    rng: &R,
+) -> Result<ResetTerms, HandleFriendError>
 where
     R: CryptoRandom,
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
@@ -60,13 +86,18 @@ where
     // the remote side has already used the next counter.
     let reset_token = gen_channel_reset_token(rng);
 
-    ResetTerms {
+    let balance_for_reset = token_channel
+        .get_mutual_credit()
+        .balance_for_reset()
+        .map_err(|_| HandleFriendError::BalanceForResetOverflow)?;
+
+    Ok(ResetTerms {
         reset_token,
         // TODO: Should we do something other than wrapping_add(1)?
         // 2**64 inconsistencies are required for an overflow.
         inconsistency_counter: token_channel.get_inconsistency_counter().wrapping_add(1),
-        balance_for_reset: token_channel.get_mutual_credit().balance_for_reset(),
-    }
+        balance_for_reset,
+    })
 }
 
 /// Check if channel reset is required (Remove side used the RESET token)
@@ -88,7 +119,7 @@ pub fn try_reset_channel<B>(
         || move_token.opt_local_relays.is_some()
         || move_token.inconsistency_counter != local_reset_terms.inconsistency_counter
         || move_token.move_token_counter != 0
-        || move_token.balance != local_reset_terms.balance_for_reset.checked_neg().unwrap()
+        || Some(move_token.balance) != local_reset_terms.balance_for_reset.checked_neg()
         || move_token.local_pending_debt != 0
         || move_token.remote_pending_debt != 0
         || !verify_move_token(move_token, friend_public_key)
@@ -116,6 +147,140 @@ pub fn try_reset_channel<B>(
     }
 }
 
+/// Check if the remote side has acknowledged our reset move token, by sending a valid move token
+/// on top of it. If so, the channel becomes consistent, and true is returned.
+/// The incoming move token itself is not processed here.
+fn try_complete_local_reset<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    friend_public_key: &PublicKey,
+    channel_pending_reset: &ChannelPendingReset<B>,
+    move_token_request: &MoveTokenRequest<B>,
+    completed_request_ids: &ImHashSet<Uid>,
+) -> bool
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let remote_reset_terms = &channel_pending_reset.remote_reset_terms;
+    let token_channel = TokenChannel::new_from_local_reset(
+        &m_state.state().local_public_key,
+        friend_public_key,
+        &channel_pending_reset.reset_move_token,
+        // Remote reset terms are verified to be negatable when received:
+        remote_reset_terms.balance_for_reset.checked_neg().unwrap(),
+        channel_pending_reset.opt_last_incoming_move_token.clone(),
+    );
+
+    let friend_move_token = move_token_request.friend_move_token.clone();
+    match token_channel.simulate_receive_move_token(friend_move_token, completed_request_ids) {
+        Ok(ReceiveMoveTokenOutput::Received(_)) => {}
+        Ok(ReceiveMoveTokenOutput::Duplicate)
+        | Ok(ReceiveMoveTokenOutput::RetransmitOutgoing(_))
+        | Err(_) => {
+            // The remote side did not receive our reset move token yet:
+            send_commands.set_resend_outgoing(friend_public_key);
+            return false;
+        }
+    }
+
+    let friend_mutation = FriendMutation::SetConsistent(token_channel);
+    let funder_mutation =
+        FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+    true
+}
+
+/// Pairs with a higher specificity take precedence. See `FriendPair`.
+fn pair_specificity(pair: &FriendPair) -> u8 {
+    match (&pair.opt_from, &pair.opt_to) {
+        (Some(_), Some(_)) => 3,
+        (None, Some(_)) => 2,
+        (Some(_), None) => 1,
+        (None, None) => 0,
+    }
+}
+
+/// Set the rule of a pair of friends, replacing any previous rule of the same pair.
+pub fn set_pair_freeze_limit(
+    pair_freeze_limits: &mut ImVec<PairFreezeLimit>,
+    set_pair_freeze_limit: &SetPairFreezeLimit,
+) {
+    pair_freeze_limits.retain(|cur| cur.pair != set_pair_freeze_limit.pair);
+    if let Some(limit) = set_pair_freeze_limit.opt_limit {
+        pair_freeze_limits.push_back(PairFreezeLimit {
+            pair: set_pair_freeze_limit.pair.clone(),
+            limit,
+        });
+    }
+}
+
+/// Find the most specific rule that applies to requests from `from_public_key` forwarded to
+/// `to_public_key`.
+pub fn find_pair_freeze_limit<'a>(
+    pair_freeze_limits: &'a ImVec<PairFreezeLimit>,
+    from_public_key: &PublicKey,
+    to_public_key: &PublicKey,
+) -> Option<&'a PairFreezeLimit> {
+    pair_freeze_limits
+        .iter()
+        .filter(|pair_freeze_limit| {
+            pair_freeze_limit
+                .pair
+                .matches(from_public_key, to_public_key)
+        })
+        .max_by_key(|pair_freeze_limit| pair_specificity(&pair_freeze_limit.pair))
+}
+
+/// Check if forwarding a request that arrived from `remote_public_key` to `next_public_key`
+/// would freeze more credits for this pair of friends than the rule of the pair allows.
+/// If no rule applies to the pair, only the channel with the next friend limits the request.
+fn exceeds_pair_freeze_limit<B>(
+    state: &FunderState<B>,
+    remote_public_key: &PublicKey,
+    next_public_key: &PublicKey,
+    request_send_funds: &RequestSendFunds,
+) -> bool
+where
+    B: Clone,
+{
+    let pair_freeze_limit = match find_pair_freeze_limit(
+        &state.pair_freeze_limits,
+        remote_public_key,
+        next_public_key,
+    ) {
+        Some(pair_freeze_limit) => pair_freeze_limit,
+        None => return false,
+    };
+
+    let next_index = match request_send_funds
+        .route
+        .pk_to_index(next_public_key)
+        .and_then(usize_to_u32)
+    {
+        Some(next_index) => next_index,
+        None => return true,
+    };
+    let opt_freeze_credits = CreditCalculator::new(
+        request_send_funds.route.len(),
+        request_send_funds.dest_payment,
+    )
+    .ok()
+    .and_then(|credit_calc| credit_calc.credits_to_freeze(next_index));
+    let freeze_credits = match opt_freeze_credits {
+        Some(freeze_credits) => freeze_credits,
+        None => return true,
+    };
+
+    let frozen_credits = match state.friends.get(next_public_key) {
+        Some(friend) => friend.get_frozen_credits_from(remote_public_key),
+        None => 0,
+    };
+    match frozen_credits.checked_add(freeze_credits) {
+        Some(total_credits) => total_credits > pair_freeze_limit.limit,
+        None => true,
+    }
+}
+
 /// Forward a request message to the relevant friend and token channel.
 fn forward_request<B>(
     m_state: &mut MutableFunderState<B>,
@@ -156,8 +321,20 @@ fn handle_request_send_funds<B>(
 
     let local_index = remote_index.checked_add(1).unwrap();
     let next_index = local_index.checked_add(1).unwrap();
+    let dust_thresholds = &m_state.state().dust_thresholds;
     if next_index >= request_send_funds.route.len() {
-        // We are the destination of this request. We return a response:
+        // We are the destination of this request.
+        if request_send_funds.dest_payment < dust_thresholds.min_receive_payment {
+            reply_with_failure(
+                m_state,
+                send_commands,
+                remote_public_key,
+                &request_send_funds,
+                FailureReason::PricingRejected,
+            );
+            return;
+        }
+        // We return a response:
         let pending_request = create_pending_request(&request_send_funds);
         let u_response_op = ResponseOp::UnsignedResponse(pending_request);
         let friend_mutation = FriendMutation::PushBackPendingResponse(u_response_op);
@@ -168,6 +345,18 @@ fn handle_request_send_funds<B>(
         return;
     }
 
+    // We are an intermediary node. The threshold applies to dest_payment, without fees:
+    if request_send_funds.dest_payment < dust_thresholds.min_forward_payment {
+        reply_with_failure(
+            m_state,
+            send_commands,
+            remote_public_key,
+            &request_send_funds,
+            FailureReason::PricingRejected,
+        );
+        return;
+    }
+
     // The node on the route has to be one of our friends:
     let next_public_key = request_send_funds.route.index_to_pk(next_index).unwrap();
     let friend_exists = m_state.state().friends.contains_key(next_public_key);
@@ -182,11 +371,38 @@ fn handle_request_send_funds<B>(
     };
 
     if !friend_ready {
+        if m_state
+            .state()
+            .quarantined_friends
+            .contains_key(next_public_key)
+        {
+            warn!(
+                "Failing a request routed through quarantined friend {:?}",
+                next_public_key
+            );
+        }
+        reply_with_failure(
+            m_state,
+            send_commands,
+            remote_public_key,
+            &request_send_funds,
+            FailureReason::Unspecified,
+        );
+        return;
+    }
+
+    if exceeds_pair_freeze_limit(
+        m_state.state(),
+        remote_public_key,
+        next_public_key,
+        &request_send_funds,
+    ) {
         reply_with_failure(
             m_state,
             send_commands,
             remote_public_key,
             &request_send_funds,
+            FailureReason::Unspecified,
         );
         return;
     }
@@ -195,8 +411,76 @@ fn handle_request_send_funds<B>(
     forward_request(m_state, send_commands, request_send_funds);
 }
 
+/// Stop measuring a payment we have originated, because it was answered.
+/// Returns the timing of the payment, or None if it was not measured.
+fn finish_payment_timing(
+    m_ephemeral: &mut MutableEphemeral,
+    pending_request: &PendingRequest,
+    is_success: bool,
+) -> Option<PaymentTiming> {
+    let request_id = pending_request.request_id;
+    let opt_payment_timing = m_ephemeral
+        .ephemeral()
+        .payment_timings
+        .timing(&request_id, &pending_request.route);
+    let payment_timing_mutation = if is_success {
+        PaymentTimingMutation::Complete(request_id)
+    } else {
+        PaymentTimingMutation::Forget(request_id)
+    };
+    m_ephemeral.mutate(EphemeralMutation::PaymentTimingMutation(
+        payment_timing_mutation,
+    ));
+    opt_payment_timing
+}
+
+/// Record the outcome of a payment we have originated, for the reliability scores of the nodes
+/// along its route. `opt_reporting_public_key` is the node that reported a failure,
+/// or None if the payment has succeeded, in `opt_latency_ticks` ticks.
+fn record_payment_outcome<B>(
+    m_state: &mut MutableFunderState<B>,
+    pending_request: &PendingRequest,
+    opt_reporting_public_key: Option<&PublicKey>,
+    opt_latency_ticks: Option<u64>,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    // The first public key on the route is ours:
+    let route_public_keys = &pending_request.route.public_keys[1..];
+    let first_hop_public_key = &route_public_keys[0];
+
+    let mut payment_outcomes = Vec::new();
+    match opt_reporting_public_key {
+        None => {
+            // All the nodes along the route have done their part.
+            // Latency is attributed to the first hop:
+            payment_outcomes.push((
+                first_hop_public_key.clone(),
+                PaymentOutcome::Success(opt_latency_ticks),
+            ));
+            for public_key in &route_public_keys[1..] {
+                payment_outcomes.push((public_key.clone(), PaymentOutcome::Success(None)));
+            }
+        }
+        Some(reporting_public_key) => {
+            payment_outcomes.push((first_hop_public_key.clone(), PaymentOutcome::Failure));
+            if reporting_public_key != first_hop_public_key
+                && route_public_keys.contains(reporting_public_key)
+            {
+                payment_outcomes.push((reporting_public_key.clone(), PaymentOutcome::Failure));
+            }
+        }
+    }
+
+    for payment_outcome in payment_outcomes {
+        let reliability_mutation = ReliabilityMutation::Record(payment_outcome);
+        m_state.mutate(FunderMutation::ReliabilityMutation(reliability_mutation));
+    }
+}
+
 fn handle_response_send_funds<B>(
     m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
     send_commands: &mut SendCommands,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     response_send_funds: ResponseSendFunds,
@@ -205,8 +489,14 @@ fn handle_response_send_funds<B>(
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
     match find_request_origin(m_state.state(), &response_send_funds.request_id).cloned() {
-        None => {
+        None if pending_request.route.index_to_pk(0) == Some(&m_state.state().local_public_key) => {
             // We are the origin of this request, and we got a response.
+            let opt_timing = finish_payment_timing(m_ephemeral, &pending_request, true);
+            let opt_latency_ticks = opt_timing
+                .as_ref()
+                .map(|payment_timing| payment_timing.ticks_to_response);
+            record_payment_outcome(m_state, &pending_request, None, opt_latency_ticks);
+
             // We provide a receipt to the user:
             let receipt = prepare_receipt(&response_send_funds, &pending_request);
 
@@ -214,12 +504,23 @@ fn handle_response_send_funds<B>(
             outgoing_control.push(FunderOutgoingControl::ResponseReceived(ResponseReceived {
                 request_id: pending_request.request_id,
                 result: response_send_funds_result,
+                opt_timing,
+                opt_label: None,
             }));
             // We make our own copy of the receipt, in case the user abruptly crashes.
             // In that case the user will be able to obtain the receipt again later.
             let funder_mutation = FunderMutation::AddReceipt((pending_request.request_id, receipt));
             m_state.mutate(funder_mutation);
         }
+        None => {
+            // We have forwarded this request, but its origin no longer waits for it (For
+            // example, the token channel with the origin was reset). We have already paid the
+            // next hop, and nobody will pay us:
+            warn!(
+                "Response for a forwarded request without an origin: {:?}",
+                pending_request.request_id
+            );
+        }
         Some(friend_public_key) => {
             // Queue this response message to another token channel:
             let response_op = ResponseOp::Response(response_send_funds);
@@ -235,6 +536,7 @@ fn handle_response_send_funds<B>(
 
 fn handle_failure_send_funds<B>(
     m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
     send_commands: &mut SendCommands,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     failure_send_funds: FailureSendFunds,
@@ -243,17 +545,36 @@ fn handle_failure_send_funds<B>(
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
     match find_request_origin(m_state.state(), &failure_send_funds.request_id).cloned() {
-        None => {
+        None if pending_request.route.index_to_pk(0) == Some(&m_state.state().local_public_key) => {
             // We are the origin of this request, and we got a failure
             // We should pass it back to encryptor.
+            let opt_timing = finish_payment_timing(m_ephemeral, &pending_request, false);
 
-            let response_send_funds_result =
-                ResponseSendFundsResult::Failure(failure_send_funds.reporting_public_key);
+            // A node that rejects small payments is not unreliable:
+            if failure_send_funds.reason != FailureReason::PricingRejected {
+                record_payment_outcome(
+                    m_state,
+                    &pending_request,
+                    Some(&failure_send_funds.reporting_public_key),
+                    None,
+                );
+            }
+
+            let response_send_funds_result = ResponseSendFundsResult::Failure((
+                failure_send_funds.reporting_public_key,
+                failure_send_funds.reason,
+            ));
             outgoing_control.push(FunderOutgoingControl::ResponseReceived(ResponseReceived {
                 request_id: pending_request.request_id,
                 result: response_send_funds_result,
+                opt_timing,
+                opt_label: None,
             }));
         }
+        None => {
+            // We have forwarded this request, but its origin no longer waits for it.
+            // There is nobody to pass the failure to.
+        }
         Some(friend_public_key) => {
             // Queue this failure message to another token channel:
             let failure_op = ResponseOp::Failure(failure_send_funds);
@@ -267,7 +588,108 @@ fn handle_failure_send_funds<B>(
     };
 }
 
+/// Check if the response deadline of a request we have forwarded has passed.
+/// If so, the origin of the request was already sent a failure, and the request is forgotten.
+fn forget_expired_request<B>(m_state: &mut MutableFunderState<B>, request_id: &Uid) -> bool
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    if !m_state.state().expired_requests.contains(request_id) {
+        return false;
+    }
+    m_state.mutate(FunderMutation::RemoveExpiredRequest(*request_id));
+    true
+}
+
+/// Find a response for a request whose response deadline has passed inside an incoming move
+/// token. We have already sent a failure to the origin of such a request, so the response must
+/// never move credits. Returns the index of the response operation and the request id.
+fn find_late_response<B>(state: &FunderState<B>, move_token: &MoveToken<B>) -> Option<(usize, Uid)>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let expired_requests = &state.expired_requests;
+    move_token
+        .operations
+        .iter()
+        .enumerate()
+        .find_map(|(index, operation)| match operation {
+            FriendTcOp::ResponseSendFunds(response_send_funds)
+                if expired_requests.contains(&response_send_funds.request_id) =>
+            {
+                Some((index, response_send_funds.request_id))
+            }
+            _ => None,
+        })
+}
+
+/// Amount of credits the remote side froze when it sent us an incoming request.
+fn incoming_freeze_credits(
+    local_public_key: &PublicKey,
+    remote_public_key: &PublicKey,
+    request_send_funds: &RequestSendFunds,
+) -> Option<u128> {
+    let remote_index = request_send_funds
+        .route
+        .find_pk_pair(remote_public_key, local_public_key)?;
+    let local_index = usize_to_u32(remote_index.checked_add(1)?)?;
+    CreditCalculator::new(
+        request_send_funds.route.len(),
+        request_send_funds.dest_payment,
+    )
+    .ok()?
+    .credits_to_freeze(local_index)
+}
+
+/// Amount of credits the remote side may still freeze for the requests in `incoming_messages`,
+/// according to the wanted remote max debt.
+///
+/// A lowered remote max debt reaches the token channel only when we send the SetRemoteMaxDebt
+/// operation, so incoming requests the remote side sent before that were checked against the
+/// previous limit. Returns None if the wanted remote max debt is not lower than the limit of the
+/// token channel.
+fn wanted_debt_room<B>(
+    m_state: &MutableFunderState<B>,
+    remote_public_key: &PublicKey,
+    incoming_messages: &[IncomingMessage],
+) -> Option<u128>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let local_public_key = &m_state.state().local_public_key;
+    let friend = m_state.state().friends.get(remote_public_key).unwrap();
+    let token_channel = match &friend.channel_status {
+        ChannelStatus::Consistent(token_channel) => token_channel,
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => unreachable!(),
+    };
+    let balance = &token_channel.get_mutual_credit().state().balance;
+    if friend.wanted_remote_max_debt >= balance.remote_max_debt {
+        return None;
+    }
+
+    // Credits that were frozen before the incoming requests arrived:
+    let mut remote_pending_debt = balance.remote_pending_debt;
+    for incoming_message in incoming_messages {
+        if let IncomingMessage::Request(request_send_funds) = incoming_message {
+            let freeze_credits =
+                incoming_freeze_credits(local_public_key, remote_public_key, request_send_funds)
+                    .unwrap_or(0);
+            remote_pending_debt = remote_pending_debt.saturating_sub(freeze_credits);
+        }
+    }
+
+    // If the balance is above the wanted remote max debt, nothing can be frozen:
+    let max_remote_pending_debt = friend
+        .wanted_remote_max_debt
+        .saturating_sub_signed(balance.balance);
+    Some(max_remote_pending_debt.saturating_sub(remote_pending_debt))
+}
+
 /// Process valid incoming operations from remote side.
+///
+/// A remote max debt we have acknowledged to the user applies to every request we receive after
+/// the acknowledgement, even if the remote side has not yet received the new limit: Requests
+/// beyond the wanted remote max debt are answered with a failure, in the order they arrive.
 fn handle_move_token_output<B>(
     m_state: &mut MutableFunderState<B>,
     m_ephemeral: &mut MutableEphemeral,
@@ -278,9 +700,34 @@ fn handle_move_token_output<B>(
 ) where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
+    let local_public_key = m_state.state().local_public_key.clone();
+    let mut opt_debt_room = wanted_debt_room(m_state, remote_public_key, &incoming_messages);
+
     for incoming_message in incoming_messages {
         match incoming_message {
             IncomingMessage::Request(request_send_funds) => {
+                if let Some(debt_room) = opt_debt_room.as_mut() {
+                    let opt_freeze_credits = incoming_freeze_credits(
+                        &local_public_key,
+                        remote_public_key,
+                        &request_send_funds,
+                    );
+                    match opt_freeze_credits {
+                        Some(freeze_credits) if freeze_credits <= *debt_room => {
+                            *debt_room -= freeze_credits;
+                        }
+                        _ => {
+                            reply_with_failure(
+                                m_state,
+                                send_commands,
+                                remote_public_key,
+                                &request_send_funds,
+                                FailureReason::Unspecified,
+                            );
+                            continue;
+                        }
+                    }
+                }
                 handle_request_send_funds(
                     m_state,
                     m_ephemeral.ephemeral(),
@@ -293,8 +740,16 @@ fn handle_move_token_output<B>(
                 pending_request,
                 incoming_response,
             }) => {
+                let completed_mutation = CompletedMutation::Add((
+                    remote_public_key.clone(),
+                    pending_request.request_id,
+                ));
+                m_ephemeral.mutate(EphemeralMutation::CompletedMutation(completed_mutation));
+                // Responses for requests whose response deadline has passed are rejected before
+                // the move token is applied. See `find_late_response()`.
                 handle_response_send_funds(
                     m_state,
+                    m_ephemeral,
                     send_commands,
                     outgoing_control,
                     incoming_response,
@@ -305,8 +760,17 @@ fn handle_move_token_output<B>(
                 pending_request,
                 incoming_failure,
             }) => {
+                let completed_mutation = CompletedMutation::Add((
+                    remote_public_key.clone(),
+                    pending_request.request_id,
+                ));
+                m_ephemeral.mutate(EphemeralMutation::CompletedMutation(completed_mutation));
+                if forget_expired_request(m_state, &pending_request.request_id) {
+                    continue;
+                }
                 handle_failure_send_funds(
                     m_state,
+                    m_ephemeral,
                     send_commands,
                     outgoing_control,
                     incoming_failure,
@@ -318,24 +782,45 @@ fn handle_move_token_output<B>(
 }
 
 /// Handle an error with incoming move token.
+/// Describe to the remote side why we rejected its move token.
+/// Only invalid operations are described. Other errors are already visible to the remote side
+/// by the InconsistencyError message.
+fn create_protocol_violation_report(
+    receive_move_token_error: &ReceiveMoveTokenError,
+    new_token: Signature,
+) -> Option<ProtocolViolationReport> {
+    match receive_move_token_error {
+        ReceiveMoveTokenError::InvalidTransaction(process_trans_list_error) => {
+            Some(ProtocolViolationReport {
+                operation_index: usize_to_u64(process_trans_list_error.index()).unwrap(),
+                error_code: process_trans_list_error.error().error_code(),
+                new_token,
+            })
+        }
+        _ => None,
+    }
+}
+
 fn handle_move_token_error<B, R>(
     m_state: &mut MutableFunderState<B>,
     send_commands: &mut SendCommands,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     rng: &R,
     remote_public_key: &PublicKey,
-) where
+    opt_protocol_violation: Option<ProtocolViolationReport>,
+) -> Result<(), HandleFriendError>
+where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
     R: CryptoRandom,
 {
     let friend = m_state.state().friends.get(remote_public_key).unwrap();
     let token_channel = match &friend.channel_status {
         ChannelStatus::Consistent(token_channel) => token_channel,
-        ChannelStatus::Inconsistent(_) => unreachable!(),
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => unreachable!(),
     };
     let opt_last_incoming_move_token = token_channel.get_last_incoming_move_token_hashed().cloned();
     // Send an InconsistencyError message to remote side:
-    let local_reset_terms = gen_reset_terms(&token_channel, rng);
+    let local_reset_terms = gen_reset_terms(&token_channel, rng)?;
 
     // Cancel all internal pending requests inside token channel:
     cancel_local_pending_requests(m_state, send_commands, outgoing_control, remote_public_key);
@@ -354,6 +839,110 @@ fn handle_move_token_error<B, R>(
         FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
     m_state.mutate(funder_mutation);
     send_commands.set_try_send(remote_public_key);
+    if let Some(protocol_violation_report) = opt_protocol_violation {
+        send_commands.set_protocol_violation(remote_public_key, protocol_violation_report);
+    }
+    Ok(())
+}
+
+/// Apply new relays for a friend.
+/// Clears any pending relays of the friend. If the relays have actually changed, a damping
+/// period starts for this friend, and the Channeler is notified if the friend is enabled.
+pub fn apply_remote_relays<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
+    relays_damping_ticks: usize,
+    remote_public_key: &PublicKey,
+    new_remote_relays: Vec<RelayAddress<B>>,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let friend = m_state.state().friends.get(remote_public_key).unwrap();
+    if friend.opt_pending_remote_relays.is_some() {
+        let friend_mutation = FriendMutation::SetPendingRemoteRelays(None);
+        let funder_mutation =
+            FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
+        m_state.mutate(funder_mutation);
+    }
+
+    // Make sure that the new relays are different than the ones we already have:
+    let friend = m_state.state().friends.get(remote_public_key).unwrap();
+    if friend.remote_relays == new_remote_relays {
+        return;
+    }
+
+    // Update remote relays:
+    let friend_mutation = FriendMutation::SetRemoteRelays(new_remote_relays.clone());
+    let funder_mutation =
+        FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+
+    // Further changes of relays will be delayed for a while:
+    let relays_damping_mutation =
+        RelaysDampingMutation::SetChanged((remote_public_key.clone(), relays_damping_ticks));
+    m_ephemeral.mutate(EphemeralMutation::RelaysDampingMutation(
+        relays_damping_mutation,
+    ));
+
+    let friend = m_state.state().friends.get(remote_public_key).unwrap();
+    if let FriendStatus::Enabled = friend.status {
+        // Notify Channeler to change the friend's address:
+        let update_friend = ChannelerUpdateFriend {
+            friend_public_key: remote_public_key.clone(),
+            friend_relays: new_remote_relays,
+            local_relays: friend.sent_local_relays.to_vec(),
+        };
+        let channeler_config = ChannelerConfig::UpdateFriend(update_friend);
+        outgoing_channeler_config.push(channeler_config);
+    }
+}
+
+/// Handle relays sent by the remote side inside a move token.
+/// Invalid relays are ignored. If the remote side has changed his relays too recently, the new
+/// relays are kept pending until the damping period is over.
+fn handle_incoming_remote_relays<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
+    max_node_relays: usize,
+    relays_damping_ticks: usize,
+    remote_public_key: &PublicKey,
+    new_remote_relays: Vec<RelayAddress<B>>,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    if !is_valid_relays(&new_remote_relays, max_node_relays) {
+        warn!("Received invalid relays from friend {:?}", remote_public_key);
+        return;
+    }
+
+    let friend = m_state.state().friends.get(remote_public_key).unwrap();
+    if friend.remote_relays == new_remote_relays
+        || !m_ephemeral
+            .ephemeral()
+            .relays_damping
+            .is_damped(remote_public_key)
+    {
+        apply_remote_relays(
+            m_state,
+            m_ephemeral,
+            outgoing_channeler_config,
+            relays_damping_ticks,
+            remote_public_key,
+            new_remote_relays,
+        );
+        return;
+    }
+
+    // The remote side has changed his relays too recently.
+    // We keep the new relays pending, replacing any older pending relays:
+    if friend.opt_pending_remote_relays.as_ref() != Some(&new_remote_relays) {
+        let friend_mutation = FriendMutation::SetPendingRemoteRelays(Some(new_remote_relays));
+        let funder_mutation =
+            FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
+        m_state.mutate(funder_mutation);
+    }
 }
 
 /// Handle success with incoming move token.
@@ -363,13 +952,24 @@ fn handle_move_token_success<B>(
     send_commands: &mut SendCommands,
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
+    max_node_relays: usize,
+    relays_damping_ticks: usize,
     remote_public_key: &PublicKey,
-    receive_move_token_output: ReceiveMoveTokenOutput<B>,
-    token_wanted: bool,
+    receive_output: ReceiveMoveTokenRequestOutput<B>,
 ) where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
-    match receive_move_token_output {
+    let ReceiveMoveTokenRequestOutput {
+        output,
+        remote_wants_token,
+    } = receive_output;
+
+    // The remote side might want the token even if it has resent an old move token:
+    if remote_wants_token {
+        send_commands.set_remote_wants_token(&remote_public_key);
+    }
+
+    match output {
         ReceiveMoveTokenOutput::Duplicate => {}
         ReceiveMoveTokenOutput::RetransmitOutgoing(_outgoing_move_token) => {
             // Retransmit last sent token channel message:
@@ -380,6 +980,19 @@ fn handle_move_token_success<B>(
         ReceiveMoveTokenOutput::Received(move_token_received) => {
             send_commands.set_try_send(remote_public_key);
 
+            // Complete a measurement of the token round trip time:
+            if m_ephemeral
+                .ephemeral()
+                .adaptive_batch
+                .is_friend_pending(remote_public_key)
+            {
+                let adaptive_batch_mutation =
+                    AdaptiveBatchMutation::TokenReceived(remote_public_key.clone());
+                m_ephemeral.mutate(EphemeralMutation::AdaptiveBatchMutation(
+                    adaptive_batch_mutation,
+                ));
+            }
+
             let MoveTokenReceived {
                 incoming_messages,
                 mutations,
@@ -389,19 +1002,15 @@ fn handle_move_token_success<B>(
 
             // Update address for remote side if necessary:
             if let Some(new_remote_relays) = opt_local_relays {
-                let friend = m_state.state().friends.get(remote_public_key).unwrap();
-                // Make sure that the newly sent remote address is different than the one we
-                // already have:
-                if friend.remote_relays != new_remote_relays {
-                    // Update remote address:
-                    let friend_mutation =
-                        FriendMutation::SetRemoteRelays(new_remote_relays.clone());
-                    let funder_mutation = FunderMutation::FriendMutation((
-                        remote_public_key.clone(),
-                        friend_mutation,
-                    ));
-                    m_state.mutate(funder_mutation);
-                }
+                handle_incoming_remote_relays(
+                    m_state,
+                    m_ephemeral,
+                    outgoing_channeler_config,
+                    max_node_relays,
+                    relays_damping_ticks,
+                    remote_public_key,
+                    new_remote_relays,
+                );
             }
 
             // Apply all mutations:
@@ -470,9 +1079,6 @@ fn handle_move_token_success<B>(
             );
         }
     }
-    if token_wanted {
-        send_commands.set_remote_wants_token(&remote_public_key);
-    }
 }
 
 fn handle_move_token_request<B, R>(
@@ -482,6 +1088,8 @@ fn handle_move_token_request<B, R>(
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
     rng: &R,
+    max_node_relays: usize,
+    relays_damping_ticks: usize,
     remote_public_key: &PublicKey,
     friend_move_token_request: MoveTokenRequest<B>,
 ) -> Result<(), HandleFriendError>
@@ -507,34 +1115,109 @@ where
             );
             return Ok(());
         }
+        ChannelStatus::PendingReset(channel_pending_reset) => {
+            let c_channel_pending_reset = channel_pending_reset.clone();
+            let completed_request_ids = m_ephemeral
+                .ephemeral()
+                .completed
+                .request_ids(remote_public_key);
+            if try_complete_local_reset(
+                m_state,
+                send_commands,
+                remote_public_key,
+                &c_channel_pending_reset,
+                &friend_move_token_request,
+                &completed_request_ids,
+            ) {
+                // The channel is now consistent. Process the incoming move token as usual:
+                return handle_move_token_request(
+                    m_state,
+                    m_ephemeral,
+                    send_commands,
+                    outgoing_control,
+                    outgoing_channeler_config,
+                    rng,
+                    max_node_relays,
+                    relays_damping_ticks,
+                    remote_public_key,
+                    friend_move_token_request,
+                );
+            }
+            // The remote side might have accepted our reset terms in the meanwhile:
+            try_reset_channel(
+                m_state,
+                send_commands,
+                remote_public_key,
+                &c_channel_pending_reset.local_reset_terms,
+                &friend_move_token_request,
+            );
+            return Ok(());
+        }
     };
 
     // We will only consider move token messages if we are in a consistent state:
-    let receive_move_token_res =
-        token_channel.simulate_receive_move_token(friend_move_token_request.friend_move_token);
-    let token_wanted = friend_move_token_request.token_wanted;
+    let completed_request_ids = m_ephemeral
+        .ephemeral()
+        .completed
+        .request_ids(remote_public_key);
+    let new_token = friend_move_token_request.friend_move_token.new_token.clone();
+    let opt_late_response =
+        find_late_response(m_state.state(), &friend_move_token_request.friend_move_token);
+    let receive_move_token_res = token_channel
+        .simulate_receive_move_token_request(friend_move_token_request, &completed_request_ids);
 
     match receive_move_token_res {
-        Ok(receive_move_token_output) => {
+        Ok(ReceiveMoveTokenRequestOutput {
+            output: ReceiveMoveTokenOutput::Received(_),
+            ..
+        }) if opt_late_response.is_some() => {
+            // A valid move token with a response for a request we have already failed towards
+            // its origin. We reject the move token, so that no credits move for the request.
+            // Our reset terms do not include the credits we have frozen for the request:
+            let (operation_index, request_id) = opt_late_response.unwrap();
+            warn!(
+                "Late response for a request whose deadline has passed: {:?}",
+                request_id
+            );
+            m_state.mutate(FunderMutation::RemoveExpiredRequest(request_id));
+            let protocol_violation_report = ProtocolViolationReport {
+                operation_index: usize_to_u64(operation_index).unwrap(),
+                error_code: OperationErrorCode::LateResponse,
+                new_token,
+            };
+            handle_move_token_error(
+                m_state,
+                send_commands,
+                outgoing_control,
+                rng,
+                remote_public_key,
+                Some(protocol_violation_report),
+            )?;
+        }
+        Ok(receive_output) => {
             handle_move_token_success(
                 m_state,
                 m_ephemeral,
                 send_commands,
                 outgoing_control,
                 outgoing_channeler_config,
+                max_node_relays,
+                relays_damping_ticks,
                 remote_public_key,
-                receive_move_token_output,
-                token_wanted,
+                receive_output,
             );
         }
-        Err(_receive_move_token_error) => {
+        Err(receive_move_token_error) => {
+            let opt_protocol_violation =
+                create_protocol_violation_report(&receive_move_token_error, new_token);
             handle_move_token_error(
                 m_state,
                 send_commands,
                 outgoing_control,
                 rng,
                 remote_public_key,
-            );
+                opt_protocol_violation,
+            )?;
         }
     };
     Ok(())
@@ -558,6 +1241,11 @@ where
         None => Err(HandleFriendError::FriendDoesNotExist),
     }?;
 
+    // We will have to use the negation of the remote balance for reset as our balance:
+    if remote_reset_terms.balance_for_reset.checked_neg().is_none() {
+        return Err(HandleFriendError::InvalidRemoteResetTerms);
+    }
+
     // Cancel all pending requests to this friend:
     cancel_pending_requests(m_state, send_commands, outgoing_control, remote_public_key);
     cancel_pending_user_requests(m_state, outgoing_control, remote_public_key);
@@ -565,6 +1253,16 @@ where
     // Save remote incoming inconsistency details:
     let new_remote_reset_terms = remote_reset_terms;
 
+    // If we already accepted these exact terms, the remote side did not receive our reset move
+    // token yet:
+    let friend = m_state.state().friends.get(remote_public_key).unwrap();
+    if let ChannelStatus::PendingReset(channel_pending_reset) = &friend.channel_status {
+        if channel_pending_reset.remote_reset_terms == new_remote_reset_terms {
+            send_commands.set_resend_outgoing(remote_public_key);
+            return Ok(());
+        }
+    }
+
     // Obtain information about our reset terms:
     let friend = m_state.state().friends.get(remote_public_key).unwrap();
     let (should_send_outgoing, new_local_reset_terms, opt_last_incoming_move_token) =
@@ -575,7 +1273,7 @@ where
                 }
                 (
                     true,
-                    gen_reset_terms(&token_channel, rng),
+                    gen_reset_terms(&token_channel, rng)?,
                     token_channel.get_last_incoming_move_token_hashed().cloned(),
                 )
             }
@@ -584,8 +1282,21 @@ where
                 channel_inconsistent.local_reset_terms.clone(),
                 channel_inconsistent.opt_last_incoming_move_token.clone(),
             ),
+            // The remote side has changed its reset terms. The terms we have accepted are no
+            // longer relevant:
+            ChannelStatus::PendingReset(channel_pending_reset) => (
+                false,
+                channel_pending_reset.local_reset_terms.clone(),
+                channel_pending_reset.opt_last_incoming_move_token.clone(),
+            ),
         };
 
+    // The token channel is about to be reset. We will never obtain a response for the local
+    // pending requests inside the token channel:
+    if should_send_outgoing {
+        cancel_local_pending_requests(m_state, send_commands, outgoing_control, remote_public_key);
+    }
+
     // Keep outgoing InconsistencyError message details in memory:
     let channel_inconsistent = ChannelInconsistent {
         opt_last_incoming_move_token,
@@ -604,6 +1315,65 @@ where
     Ok(())
 }
 
+/// The remote side has rejected one of our move tokens, and told us why.
+/// The report is not authenticated, so we only keep it for diagnostics.
+fn handle_protocol_violation<B>(
+    m_state: &mut MutableFunderState<B>,
+    remote_public_key: &PublicKey,
+    protocol_violation_report: ProtocolViolationReport,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    warn!(
+        "Friend {:?} rejected our move token: {:?}",
+        remote_public_key, protocol_violation_report
+    );
+    let friend_mutation = FriendMutation::SetProtocolViolation(protocol_violation_report);
+    let funder_mutation =
+        FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+}
+
+/// The remote side proves that it knows the verification phrase.
+/// The proof is kept even if we have no verification phrase yet, and is checked when we do.
+/// A failed verification is only reported to the user, and does not affect the channel.
+fn handle_verification_proof<B>(
+    m_state: &mut MutableFunderState<B>,
+    remote_public_key: &PublicKey,
+    verification_proof: VerificationProof,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    let friend = m_state.state().friends.get(remote_public_key).unwrap();
+    // The remote side sends the same proof every time it reconnects:
+    if friend.opt_remote_verification_proof.as_ref() == Some(&verification_proof) {
+        return;
+    }
+
+    let friend_mutation = FriendMutation::SetRemoteVerificationProof(verification_proof);
+    let funder_mutation =
+        FunderMutation::FriendMutation((remote_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+}
+
+/// The remote friend is about to disconnect on purpose.
+fn handle_goodbye<B>(
+    m_ephemeral: &mut MutableEphemeral,
+    outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
+    remote_public_key: &PublicKey,
+    goodbye: Goodbye,
+) {
+    // If we know when the friend will be back, there is no point in trying to reconnect
+    // before that time:
+    if goodbye.opt_downtime_ticks.is_some() {
+        let channeler_config = ChannelerConfig::SuspendFriend(remote_public_key.clone());
+        outgoing_channeler_config.push(channeler_config);
+    }
+
+    let goodbye_mutation = GoodbyeMutation::Received((remote_public_key.clone(), goodbye));
+    m_ephemeral.mutate(EphemeralMutation::GoodbyeMutation(goodbye_mutation));
+}
+
 pub fn handle_friend_message<B, R>(
     m_state: &mut MutableFunderState<B>,
     m_ephemeral: &mut MutableEphemeral,
@@ -611,6 +1381,8 @@ pub fn handle_friend_message<B, R>(
     outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
     outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
     rng: &R,
+    max_node_relays: usize,
+    relays_damping_ticks: usize,
     remote_public_key: &PublicKey,
     friend_message: FriendMessage<B>,
 ) -> Result<(), HandleFriendError>
@@ -632,6 +1404,8 @@ where
             outgoing_control,
             outgoing_channeler_config,
             rng,
+            max_node_relays,
+            relays_damping_ticks,
             remote_public_key,
             friend_move_token_request,
         ),
@@ -644,5 +1418,99 @@ where
             remote_public_key,
             remote_reset_terms,
         ),
+
+        FriendMessage::ProtocolViolation(protocol_violation_report) => {
+            handle_protocol_violation(m_state, remote_public_key, protocol_violation_report);
+            Ok(())
+        }
+
+        FriendMessage::VerificationProof(verification_proof) => {
+            handle_verification_proof(m_state, remote_public_key, verification_proof);
+            Ok(())
+        }
+
+        FriendMessage::Goodbye(goodbye) => {
+            handle_goodbye(
+                m_ephemeral,
+                outgoing_channeler_config,
+                remote_public_key,
+                goodbye,
+            );
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    fn pair(opt_from: Option<u8>, opt_to: Option<u8>) -> FriendPair {
+        FriendPair {
+            opt_from: opt_from.map(|i| PublicKey::from(&[i; PUBLIC_KEY_LEN])),
+            opt_to: opt_to.map(|i| PublicKey::from(&[i; PUBLIC_KEY_LEN])),
+        }
+    }
+
+    /// The limit of the rule that applies to requests from friend `from` to friend `to`
+    fn find_limit(limits: &ImVec<PairFreezeLimit>, from: u8, to: u8) -> Option<u128> {
+        let from_public_key = PublicKey::from(&[from; PUBLIC_KEY_LEN]);
+        let to_public_key = PublicKey::from(&[to; PUBLIC_KEY_LEN]);
+        find_pair_freeze_limit(limits, &from_public_key, &to_public_key)
+            .map(|pair_freeze_limit| pair_freeze_limit.limit)
+    }
+
+    #[test]
+    fn test_pair_freeze_limit_precedence() {
+        let mut limits = ImVec::new();
+        assert_eq!(find_limit(&limits, 1, 2), None);
+
+        // The least specific rules are set first, to make sure the order does not matter:
+        let rules = vec![
+            (pair(None, None), 1_000),
+            (pair(Some(1), None), 100),
+            (pair(None, Some(2)), 10),
+            (pair(Some(1), Some(2)), 1),
+        ];
+        for (pair, limit) in rules {
+            set_pair_freeze_limit(
+                &mut limits,
+                &SetPairFreezeLimit {
+                    pair,
+                    opt_limit: Some(limit),
+                },
+            );
+        }
+
+        assert_eq!(find_limit(&limits, 1, 2), Some(1));
+        // A rule for the destination friend wins over a rule for the source friend:
+        assert_eq!(find_limit(&limits, 3, 2), Some(10));
+        assert_eq!(find_limit(&limits, 1, 3), Some(100));
+        assert_eq!(find_limit(&limits, 3, 4), Some(1_000));
+        // The direction matters:
+        assert_eq!(find_limit(&limits, 2, 1), Some(1_000));
+
+        // Setting a rule again replaces it:
+        let set_pair_limit = SetPairFreezeLimit {
+            pair: pair(Some(1), Some(2)),
+            opt_limit: Some(5),
+        };
+        set_pair_freeze_limit(&mut limits, &set_pair_limit);
+        assert_eq!(limits.len(), 4);
+        assert_eq!(find_limit(&limits, 1, 2), Some(5));
+
+        // Removing the most specific rule exposes the next one:
+        for pair in vec![pair(Some(1), Some(2)), pair(None, Some(2))] {
+            set_pair_freeze_limit(
+                &mut limits,
+                &SetPairFreezeLimit {
+                    pair,
+                    opt_limit: None,
+                },
+            );
+        }
+        assert_eq!(find_limit(&limits, 1, 2), Some(100));
+        assert_eq!(limits.len(), 2);
     }
 }
@@ -5,11 +5,12 @@ use proto::funder::messages::{FriendStatus, FunderOutgoingControl};
 
 use crate::types::IncomingLivenessMessage;
 
+use crate::adaptive_batch::AdaptiveBatchMutation;
 use crate::ephemeral::EphemeralMutation;
 use crate::liveness::LivenessMutation;
 
 use crate::handler::canceler::{cancel_pending_requests, cancel_pending_user_requests};
-use crate::handler::handler::{MutableEphemeral, MutableFunderState};
+use crate::handler::handler::{forget_goodbye, MutableEphemeral, MutableFunderState};
 use crate::handler::sender::SendCommands;
 
 #[derive(Debug)]
@@ -53,10 +54,17 @@ where
             }
 
             send_commands.set_resend_outgoing(&friend_public_key);
+            // The remote side might have missed our verification proof while it was offline:
+            if friend.opt_verification_phrase.is_some() {
+                send_commands.set_send_verification_proof(&friend_public_key);
+            }
 
             let liveness_mutation = LivenessMutation::SetOnline(friend_public_key.clone());
             let ephemeral_mutation = EphemeralMutation::LivenessMutation(liveness_mutation);
             m_ephemeral.mutate(ephemeral_mutation);
+
+            // The friend is back:
+            forget_goodbye(m_ephemeral, &friend_public_key);
         }
         IncomingLivenessMessage::Offline(friend_public_key) => {
             // It is possible that the friend is disabled and we get an offline notification.
@@ -66,6 +74,19 @@ where
             let ephemeral_mutation = EphemeralMutation::LivenessMutation(liveness_mutation);
             m_ephemeral.mutate(ephemeral_mutation);
 
+            // We will not get the token back through this connection:
+            if m_ephemeral
+                .ephemeral()
+                .adaptive_batch
+                .is_friend_pending(&friend_public_key)
+            {
+                let adaptive_batch_mutation =
+                    AdaptiveBatchMutation::CancelPending(friend_public_key.clone());
+                m_ephemeral.mutate(EphemeralMutation::AdaptiveBatchMutation(
+                    adaptive_batch_mutation,
+                ));
+            }
+
             // If the friend does not exist, we have nothing more to do here:
             if m_state.state().friends.get(&friend_public_key).is_none() {
                 return Ok(());
@@ -83,12 +104,8 @@ where
 mod tests {
     use super::*;
 
-    use std::cmp::Ordering;
-
-    use crypto::identity::{
-        compare_public_key, generate_pkcs8_key_pair, Identity, SoftwareEd25519Identity,
-    };
-    use crypto::test_utils::DummyRandom;
+    use crypto::identity::Identity;
+    use crypto::test_utils::fixture_keypairs;
     use proto::funder::messages::{AddFriend, FriendStatus};
 
     use crate::ephemeral::Ephemeral;
@@ -101,23 +118,13 @@ mod tests {
 
     #[test]
     fn test_handle_liveness_basic() {
-        let rng1 = DummyRandom::new(&[1u8]);
-        let pkcs8 = generate_pkcs8_key_pair(&rng1);
-        let identity1 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-
-        let rng2 = DummyRandom::new(&[2u8]);
-        let pkcs8 = generate_pkcs8_key_pair(&rng2);
-        let identity2 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-
-        let pk1 = identity1.get_public_key();
-        let pk2 = identity2.get_public_key();
-
-        let (_local_identity, local_pk, _remote_identity, remote_pk) =
-            if compare_public_key(&pk1, &pk2) == Ordering::Less {
-                (identity1, pk1, identity2, pk2)
-            } else {
-                (identity2, pk2, identity1, pk1)
-            };
+        // The local side begins the token channel:
+        let public_keys = fixture_keypairs(2)
+            .iter()
+            .map(|identity| identity.get_public_key())
+            .collect::<Vec<_>>();
+        let local_pk = public_keys[0].clone();
+        let remote_pk = public_keys[1].clone();
 
         let relays = vec![dummy_named_relay_address(0)];
         let mut state = FunderState::<u32>::new(local_pk, relays);
@@ -0,0 +1,391 @@
+use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::usize_to_u64;
+use std::cmp;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::fmt::Debug;
+
+use crypto::identity::PublicKey;
+use crypto::uid::Uid;
+
+use proto::app_server::messages::RelayAddress;
+use proto::funder::messages::{
+    ChannelerUpdateFriend, FailureReason, FriendStatus, PendingRequest, RemoteMaxDebtExpiry,
+};
+
+use crate::adaptive_batch::AdaptiveBatchMutation;
+use crate::completed::CompletedMutation;
+use crate::damping::RelaysDampingMutation;
+use crate::ephemeral::EphemeralMutation;
+use crate::friend::{ChannelStatus, FriendMutation, ResponseOp};
+use crate::goodbye::GoodbyeMutation;
+use crate::payment_timing::PaymentTimingMutation;
+use crate::prewarm::PrewarmMutation;
+use crate::reliability::ReliabilityMutation;
+use crate::response_deadline::ResponseDeadlineMutation;
+use crate::state::FunderMutation;
+use crate::types::ChannelerConfig;
+
+use crate::handler::handle_friend::apply_remote_relays;
+use crate::handler::handler::{
+    find_request_origin, mutate_payment_timings, MutableEphemeral, MutableFunderState,
+};
+use crate::handler::sender::SendCommands;
+
+/// Advance the response deadlines of requests we have forwarded to friends by `ticks_elapsed`.
+/// A forwarded request whose deadline has passed is failed towards its origin.
+///
+/// Note that the request itself remains pending inside the token channel with the friend we
+/// have forwarded it to: It can only be removed by a failure from that friend, or by a reset of
+/// the token channel. A late response from the friend is rejected, and the channel becomes
+/// inconsistent. Our reset terms release the credits frozen for the request, so no credits
+/// move for a request we have already failed towards its origin.
+fn tick_response_deadlines<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    send_commands: &mut SendCommands,
+    ticks_elapsed: u64,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let response_deadlines = &m_ephemeral.ephemeral().response_deadlines;
+    let expired_requests = &m_state.state().expired_requests;
+
+    // Forwarded requests that are still pending and should be remembered:
+    let mut live_request_ids: HashSet<Uid> = HashSet::new();
+    let mut tracked: Vec<(Uid, u64)> = Vec::new();
+    let mut expired: Vec<(PublicKey, PendingRequest)> = Vec::new();
+
+    for friend in m_state.state().friends.values() {
+        let token_channel = match &friend.channel_status {
+            ChannelStatus::Consistent(token_channel) => token_channel,
+            ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => continue,
+        };
+        let pending_local_requests = &token_channel
+            .get_mutual_credit()
+            .state()
+            .pending_requests
+            .pending_local_requests;
+
+        for (request_id, pending_request) in pending_local_requests {
+            if expired_requests.contains(request_id) {
+                live_request_ids.insert(*request_id);
+                continue;
+            }
+            let deadline_ticks = match friend.opt_response_deadline_ticks {
+                Some(deadline_ticks) => deadline_ticks,
+                None => continue,
+            };
+            // Only requests we have forwarded have a response deadline:
+            if pending_request.route.index_to_pk(0) == Some(&friend.local_public_key) {
+                continue;
+            }
+            let origin_public_key = match find_request_origin(m_state.state(), request_id) {
+                Some(origin_public_key) => origin_public_key.clone(),
+                // The origin no longer waits for this request:
+                None => continue,
+            };
+            live_request_ids.insert(*request_id);
+
+            let ticks_left = response_deadlines
+                .ticks_left
+                .get(request_id)
+                .cloned()
+                .unwrap_or(deadline_ticks)
+                .saturating_sub(ticks_elapsed);
+            if ticks_left == 0 {
+                expired.push((origin_public_key, pending_request.clone()));
+            } else {
+                tracked.push((*request_id, ticks_left));
+            }
+        }
+    }
+
+    let forgotten = response_deadlines
+        .ticks_left
+        .keys()
+        .filter(|request_id| !live_request_ids.contains(*request_id))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    let forgotten_expired = expired_requests
+        .iter()
+        .filter(|request_id| !live_request_ids.contains(*request_id))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for (request_id, ticks_left) in tracked {
+        let response_deadline_mutation = ResponseDeadlineMutation::Track((request_id, ticks_left));
+        m_ephemeral.mutate(EphemeralMutation::ResponseDeadlineMutation(
+            response_deadline_mutation,
+        ));
+    }
+
+    for (origin_public_key, pending_request) in expired {
+        let request_id = pending_request.request_id;
+        // We are the reporting node of this failure:
+        let u_failure_op =
+            ResponseOp::UnsignedFailure((pending_request, FailureReason::Unspecified));
+        let friend_mutation = FriendMutation::PushBackPendingResponse(u_failure_op);
+        let funder_mutation =
+            FunderMutation::FriendMutation((origin_public_key.clone(), friend_mutation));
+        m_state.mutate(funder_mutation);
+        send_commands.set_try_send(&origin_public_key);
+
+        m_state.mutate(FunderMutation::AddExpiredRequest(request_id));
+        let response_deadline_mutation = ResponseDeadlineMutation::Forget(request_id);
+        m_ephemeral.mutate(EphemeralMutation::ResponseDeadlineMutation(
+            response_deadline_mutation,
+        ));
+    }
+
+    for request_id in forgotten {
+        let response_deadline_mutation = ResponseDeadlineMutation::Forget(request_id);
+        m_ephemeral.mutate(EphemeralMutation::ResponseDeadlineMutation(
+            response_deadline_mutation,
+        ));
+    }
+
+    for request_id in forgotten_expired {
+        m_state.mutate(FunderMutation::RemoveExpiredRequest(request_id));
+    }
+}
+
+/// Advance the expiries of remote max debts by `ticks_elapsed`.
+/// When an expiry passes, the wanted remote max debt is reduced to the post expiry value, but
+/// never below the debt the friend already owes us.
+fn tick_remote_max_debt_expiries<B>(
+    m_state: &mut MutableFunderState<B>,
+    send_commands: &mut SendCommands,
+    ticks_elapsed: u64,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let remote_max_debt_expiries = m_state
+        .state()
+        .friends
+        .iter()
+        .filter_map(|(friend_public_key, friend)| {
+            friend
+                .opt_remote_max_debt_expiry
+                .clone()
+                .map(|remote_max_debt_expiry| (friend_public_key.clone(), remote_max_debt_expiry))
+        })
+        .collect::<Vec<_>>();
+
+    for (friend_public_key, remote_max_debt_expiry) in remote_max_debt_expiries {
+        let friend_mutation = if remote_max_debt_expiry.expires_after_ticks > ticks_elapsed {
+            FriendMutation::SetRemoteMaxDebtExpiry(Some(RemoteMaxDebtExpiry {
+                expires_after_ticks: remote_max_debt_expiry.expires_after_ticks - ticks_elapsed,
+                post_expiry_max_debt: remote_max_debt_expiry.post_expiry_max_debt,
+            }))
+        } else {
+            let friend = m_state.state().friends.get(&friend_public_key).unwrap();
+            let wanted_remote_max_debt = cmp::max(
+                remote_max_debt_expiry.post_expiry_max_debt,
+                friend.get_remote_used_debt(),
+            );
+            // The new remote max debt will be sent to the friend:
+            send_commands.set_try_send(&friend_public_key);
+            FriendMutation::ExpireRemoteMaxDebt(wanted_remote_max_debt)
+        };
+        let funder_mutation = FunderMutation::FriendMutation((friend_public_key, friend_mutation));
+        m_state.mutate(funder_mutation);
+    }
+}
+
+/// Advance the expected downtimes of friends that went offline on purpose by `ticks_elapsed`.
+/// When the expected downtime of a friend passes, we try to reconnect to the friend.
+fn tick_goodbyes<B>(
+    m_state: &MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
+    ticks_elapsed: u64,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let goodbyes_ticks_left = m_ephemeral
+        .ephemeral()
+        .goodbyes
+        .ticks_left
+        .iter()
+        .map(|(friend_public_key, ticks_left)| (friend_public_key.clone(), *ticks_left))
+        .collect::<Vec<_>>();
+
+    for (friend_public_key, ticks_left) in goodbyes_ticks_left {
+        if ticks_left > ticks_elapsed {
+            let goodbye_mutation =
+                GoodbyeMutation::SetTicksLeft((friend_public_key, ticks_left - ticks_elapsed));
+            m_ephemeral.mutate(EphemeralMutation::GoodbyeMutation(goodbye_mutation));
+            continue;
+        }
+
+        let goodbye_mutation = GoodbyeMutation::Resume(friend_public_key.clone());
+        m_ephemeral.mutate(EphemeralMutation::GoodbyeMutation(goodbye_mutation));
+
+        let friend = match m_state.state().friends.get(&friend_public_key) {
+            Some(friend) => friend,
+            None => continue,
+        };
+        if let FriendStatus::Enabled = friend.status {
+            // Notify Channeler to reconnect to the friend:
+            let update_friend = ChannelerUpdateFriend {
+                friend_public_key: friend_public_key.clone(),
+                friend_relays: friend.remote_relays.clone(),
+                local_relays: friend.sent_local_relays.to_vec(),
+            };
+            outgoing_channeler_config.push(ChannelerConfig::UpdateFriend(update_friend));
+        }
+    }
+}
+
+/// Advance the measurement of payment timings, and forget the timings of payments that are no
+/// longer pending. Every `reliability_decay_ticks` ticks, the weight of the recorded outcomes of
+/// payments is halved, so that the reliability scores of remote nodes recover over time.
+/// When many ticks have elapsed at once, the weight is halved once for every
+/// `reliability_decay_ticks` ticks that have passed.
+fn tick_reliability<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    reliability_decay_ticks: usize,
+    ticks_elapsed: u64,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let prev_ticks = m_ephemeral.ephemeral().payment_timings.ticks;
+    mutate_payment_timings(m_ephemeral, PaymentTimingMutation::Tick(ticks_elapsed));
+
+    let payment_timings = &m_ephemeral.ephemeral().payment_timings;
+    if !payment_timings.started.is_empty() {
+        // Requests that are still pending, either queued or inside a token channel:
+        let mut pending_request_ids: HashSet<Uid> = HashSet::new();
+        for friend in m_state.state().friends.values() {
+            for request_send_funds in &friend.pending_user_requests {
+                pending_request_ids.insert(request_send_funds.request_id);
+            }
+            if let ChannelStatus::Consistent(token_channel) = &friend.channel_status {
+                let pending_local_requests = &token_channel
+                    .get_mutual_credit()
+                    .state()
+                    .pending_requests
+                    .pending_local_requests;
+                pending_request_ids.extend(pending_local_requests.keys().cloned());
+            }
+        }
+
+        let forgotten = payment_timings
+            .started
+            .keys()
+            .filter(|request_id| !pending_request_ids.contains(*request_id))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for request_id in forgotten {
+            let payment_timing_mutation = PaymentTimingMutation::Forget(request_id);
+            m_ephemeral.mutate(EphemeralMutation::PaymentTimingMutation(
+                payment_timing_mutation,
+            ));
+        }
+    }
+
+    let ticks = m_ephemeral.ephemeral().payment_timings.ticks;
+    // Amount of multiples of `reliability_decay_ticks` in (prev_ticks, ticks]:
+    let num_decays = usize_to_u64(reliability_decay_ticks)
+        .filter(|decay_ticks| *decay_ticks > 0)
+        .map(|decay_ticks| (ticks / decay_ticks).wrapping_sub(prev_ticks / decay_ticks))
+        .unwrap_or(0);
+    // Nodes are forgotten once their weight is small enough, so only a few decays can apply:
+    for _ in 0..num_decays {
+        if m_state.state().reliability.nodes.is_empty() {
+            break;
+        }
+        m_state.mutate(FunderMutation::ReliabilityMutation(
+            ReliabilityMutation::Decay,
+        ));
+    }
+}
+
+/// Handle `ticks_elapsed` time ticks. More than one tick may elapse at once, for example after
+/// the host was suspended. Every timeout that has passed fires once, no matter how long ago.
+/// Advances the damping of relays changes, and applies pending relays of friends
+/// that are no longer damped. Also advances pending measurements of token round trip times,
+/// the rate limiting of pre-warms, the ages of remembered completed requests, the response
+/// deadlines of forwarded requests, the expiries of remote max debts, the expected downtimes of
+/// friends that went offline on purpose and the decay of reliability scores.
+pub fn handle_timer_tick<B>(
+    m_state: &mut MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    send_commands: &mut SendCommands,
+    outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
+    relays_damping_ticks: usize,
+    reliability_decay_ticks: usize,
+    ticks_elapsed: u64,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let usize_ticks_elapsed = usize::try_from(ticks_elapsed).unwrap_or(usize::max_value());
+
+    if !m_ephemeral.ephemeral().relays_damping.ticks_left.is_empty() {
+        m_ephemeral.mutate(EphemeralMutation::RelaysDampingMutation(
+            RelaysDampingMutation::Tick(usize_ticks_elapsed),
+        ));
+    }
+
+    if m_ephemeral.ephemeral().adaptive_batch.is_pending() {
+        m_ephemeral.mutate(EphemeralMutation::AdaptiveBatchMutation(
+            AdaptiveBatchMutation::Tick(usize_ticks_elapsed),
+        ));
+    }
+
+    if !m_ephemeral.ephemeral().prewarm.ticks_left.is_empty() {
+        m_ephemeral.mutate(EphemeralMutation::PrewarmMutation(PrewarmMutation::Tick(
+            usize_ticks_elapsed,
+        )));
+    }
+
+    if !m_ephemeral.ephemeral().completed.friends.is_empty() {
+        m_ephemeral.mutate(EphemeralMutation::CompletedMutation(
+            CompletedMutation::Tick(ticks_elapsed),
+        ));
+    }
+
+    tick_response_deadlines(m_state, m_ephemeral, send_commands, ticks_elapsed);
+    tick_remote_max_debt_expiries(m_state, send_commands, ticks_elapsed);
+    tick_goodbyes(
+        m_state,
+        m_ephemeral,
+        outgoing_channeler_config,
+        ticks_elapsed,
+    );
+    tick_reliability(m_state, m_ephemeral, reliability_decay_ticks, ticks_elapsed);
+
+    // Collect pending relays that are ready to be applied:
+    let ready_relays = m_state
+        .state()
+        .friends
+        .iter()
+        .filter(|(friend_public_key, _friend)| {
+            !m_ephemeral
+                .ephemeral()
+                .relays_damping
+                .is_damped(friend_public_key)
+        })
+        .filter_map(|(friend_public_key, friend)| {
+            friend
+                .opt_pending_remote_relays
+                .clone()
+                .map(|pending_remote_relays| (friend_public_key.clone(), pending_remote_relays))
+        })
+        .collect::<Vec<_>>();
+
+    for (friend_public_key, pending_remote_relays) in ready_relays {
+        apply_remote_relays(
+            m_state,
+            m_ephemeral,
+            outgoing_channeler_config,
+            relays_damping_ticks,
+            &friend_public_key,
+            pending_remote_relays,
+        );
+    }
+}
@@ -1,27 +1,39 @@
 use std::fmt::Debug;
 
 use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::usize_to_u64;
 
 use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::PublicKey;
 use crypto::uid::Uid;
 
 use proto::app_server::messages::RelayAddress;
-use proto::funder::messages::FunderOutgoingControl;
+use proto::funder::messages::{
+    FriendMessage, FriendTcOp, FunderControl, FunderOutgoingControl, PrewarmResult, ResponsePrewarm,
+};
 use proto::report::messages::{FunderReportMutation, FunderReportMutations};
 
-use identity::IdentityClient;
+use identity::{IdentityClient, IdentityClientError};
 
 use crate::state::{FunderMutation, FunderState};
 
-use crate::handler::handle_control::handle_control_message;
+use crate::handler::handle_control::{check_prewarm_friend, handle_control_message};
 use crate::handler::handle_friend::{handle_friend_message, HandleFriendError};
 use crate::handler::handle_init::handle_init;
 use crate::handler::handle_liveness::{handle_liveness_message, HandleLivenessError};
-use crate::handler::sender::{create_friend_messages, SendCommands};
+use crate::handler::handle_timer::handle_timer_tick;
+use crate::handler::sender::{
+    create_friend_messages, create_goodbye_messages, SendCommands, SendError,
+};
 
+use crate::adaptive_batch::AdaptiveBatchMutation;
+use crate::deadlines::{calc_friend_deadlines, is_deadlines_changed, ReportedDeadlinesMutation};
 use crate::ephemeral::{Ephemeral, EphemeralMutation};
 use crate::friend::ChannelStatus;
+use crate::goodbye::GoodbyeMutation;
+use crate::invariants::InvariantError;
+use crate::payment_timing::PaymentTimingMutation;
+use crate::prewarm::PrewarmMutation;
 use crate::report::{ephemeral_mutation_to_report_mutations, funder_mutation_to_report_mutations};
 use crate::types::{ChannelerConfig, FunderIncoming, FunderIncomingComm, FunderOutgoingComm};
 
@@ -88,6 +100,11 @@ pub enum FunderHandlerError {
     // HandleControlError(HandleControlError),
     HandleFriendError(HandleFriendError),
     HandleLivenessError(HandleLivenessError),
+    /// Failed to sign using the identity service.
+    IdentityError(IdentityClientError),
+    /// Outgoing messages would have left the funder state inconsistent.
+    /// None of the mutations of this message are applied.
+    InvariantError(InvariantError),
 }
 
 pub struct FunderHandlerOutput<B>
@@ -115,7 +132,7 @@ where
 {
     for (friend_public_key, friend) in &state.friends {
         match &friend.channel_status {
-            ChannelStatus::Inconsistent(_) => continue,
+            ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => continue,
             ChannelStatus::Consistent(token_channel) => {
                 if token_channel
                     .get_mutual_credit()
@@ -147,7 +164,7 @@ where
 
     // Make sure that the channel is consistent:
     let token_channel = match &friend.channel_status {
-        ChannelStatus::Inconsistent(_) => return false,
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => return false,
         ChannelStatus::Consistent(token_channel) => token_channel,
     };
 
@@ -160,6 +177,81 @@ where
         .is_open()
 }
 
+/// Forget the goodbye received from a friend, if any.
+pub fn forget_goodbye(m_ephemeral: &mut MutableEphemeral, friend_public_key: &PublicKey) {
+    if m_ephemeral
+        .ephemeral()
+        .goodbyes
+        .get(friend_public_key)
+        .is_none()
+    {
+        return;
+    }
+    let goodbye_mutation = GoodbyeMutation::Forget(friend_public_key.clone());
+    m_ephemeral.mutate(EphemeralMutation::GoodbyeMutation(goodbye_mutation));
+}
+
+/// Apply a mutation that may evict measurement points from the payment timings cache.
+/// Evicted payments are forgotten explicitly, so that the report stops showing their ticks.
+pub fn mutate_payment_timings(
+    m_ephemeral: &mut MutableEphemeral,
+    payment_timing_mutation: PaymentTimingMutation,
+) {
+    let tracked_before = m_ephemeral
+        .ephemeral()
+        .payment_timings
+        .started
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    m_ephemeral.mutate(EphemeralMutation::PaymentTimingMutation(
+        payment_timing_mutation,
+    ));
+
+    let payment_timings = &m_ephemeral.ephemeral().payment_timings;
+    let evicted = tracked_before
+        .into_iter()
+        .filter(|request_id| !payment_timings.started.contains_key(request_id))
+        .collect::<Vec<_>>();
+    for request_id in evicted {
+        let payment_timing_mutation = PaymentTimingMutation::Forget(request_id);
+        m_ephemeral.mutate(EphemeralMutation::PaymentTimingMutation(
+            payment_timing_mutation,
+        ));
+    }
+}
+
+/// Answer pending pre-warm requests of friends whose token arrived, or that can not be
+/// pre-warmed anymore.
+fn resolve_prewarms<B>(
+    m_state: &MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let pending = m_ephemeral.ephemeral().prewarm.pending.clone();
+    for (friend_public_key, request_ids) in pending {
+        let check_res = check_prewarm_friend(m_state, m_ephemeral.ephemeral(), &friend_public_key);
+        let result = match check_res {
+            Ok(true) => PrewarmResult::Success,
+            // Still waiting for the token:
+            Ok(false) => continue,
+            Err(prewarm_failure) => PrewarmResult::Failure(prewarm_failure),
+        };
+
+        for request_id in request_ids {
+            let response_prewarm = ResponsePrewarm {
+                request_id,
+                result: result.clone(),
+            };
+            outgoing_control.push(FunderOutgoingControl::ResponsePrewarm(response_prewarm));
+        }
+        let prewarm_mutation = PrewarmMutation::ClearPending(friend_public_key);
+        m_ephemeral.mutate(EphemeralMutation::PrewarmMutation(prewarm_mutation));
+    }
+}
+
 type FunderHandleIncomingOutput<B> = (
     SendCommands,
     Vec<FunderOutgoingControl<B>>,
@@ -172,6 +264,9 @@ pub fn funder_handle_incoming<B, R>(
     rng: &R,
     max_node_relays: usize,
     max_pending_user_requests: usize,
+    relays_damping_ticks: usize,
+    prewarm_ticks: usize,
+    reliability_decay_ticks: usize,
     funder_incoming: FunderIncoming<B>,
 ) -> Result<FunderHandleIncomingOutput<B>, FunderHandlerError>
 where
@@ -181,6 +276,8 @@ where
     let mut send_commands = SendCommands::new();
     let mut outgoing_control = Vec::new();
     let mut outgoing_channeler_config = Vec::new();
+    // The request submitted by the handled control, if any:
+    let mut opt_submitted_request_id = None;
 
     let opt_app_request_id = match funder_incoming {
         FunderIncoming::Init => {
@@ -189,6 +286,12 @@ where
         }
 
         FunderIncoming::Control(funder_incoming_control) => {
+            opt_submitted_request_id = match &funder_incoming_control.funder_control {
+                FunderControl::RequestSendFunds(user_request) => Some(user_request.request_id),
+                FunderControl::RequestSweepFunds(user_request) => Some(user_request.request_id),
+                _ => None,
+            };
+
             // Even if an error occurs, we must return an indication to the
             // user that the control request was received.
             if let Err(e) = handle_control_message(
@@ -199,6 +302,8 @@ where
                 &mut outgoing_channeler_config,
                 max_node_relays,
                 max_pending_user_requests,
+                prewarm_ticks,
+                funder_incoming_control.opt_app_public_key,
                 funder_incoming_control.funder_control,
             ) {
                 error!("handle_control_error(): {:?}", e);
@@ -218,23 +323,57 @@ where
                 .map_err(FunderHandlerError::HandleLivenessError)?,
 
                 FunderIncomingComm::Friend((origin_public_key, friend_message)) => {
-                    handle_friend_message(
-                        &mut m_state,
-                        &mut m_ephemeral,
-                        &mut send_commands,
-                        &mut outgoing_control,
-                        &mut outgoing_channeler_config,
-                        rng,
-                        &origin_public_key,
-                        friend_message,
-                    )
-                    .map_err(FunderHandlerError::HandleFriendError)?
+                    if m_ephemeral.ephemeral().read_only {
+                        // We could not persist the changes caused by the message. The friend
+                        // will send it again after we leave the read only mode:
+                        warn!(
+                            "Dropping a message from {:?}: The database is read only",
+                            origin_public_key
+                        );
+                    } else {
+                        handle_friend_message(
+                            &mut m_state,
+                            &mut m_ephemeral,
+                            &mut send_commands,
+                            &mut outgoing_control,
+                            &mut outgoing_channeler_config,
+                            rng,
+                            max_node_relays,
+                            relays_damping_ticks,
+                            &origin_public_key,
+                            friend_message,
+                        )
+                        .map_err(FunderHandlerError::HandleFriendError)?;
+                    }
                 }
             };
             None
         }
+
+        FunderIncoming::TimerTick(ticks_elapsed) => {
+            handle_timer_tick(
+                &mut m_state,
+                &mut m_ephemeral,
+                &mut send_commands,
+                &mut outgoing_channeler_config,
+                relays_damping_ticks,
+                reliability_decay_ticks,
+                ticks_elapsed,
+            );
+            None
+        }
+
+        FunderIncoming::SetReadOnly(read_only) => {
+            handle_set_read_only(&m_state, &mut m_ephemeral, &mut send_commands, read_only);
+            None
+        }
     };
 
+    // The result of the submitted request itself was recorded together with its submission:
+    record_submission_results(&mut m_state, &outgoing_control, opt_submitted_request_id);
+
+    resolve_prewarms(&m_state, &mut m_ephemeral, &mut outgoing_control);
+
     Ok((
         send_commands,
         outgoing_control,
@@ -243,10 +382,42 @@ where
     ))
 }
 
+/// Enter or leave the read only mode, used while the database can not be written.
+fn handle_set_read_only<B>(
+    m_state: &MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    send_commands: &mut SendCommands,
+    read_only: bool,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    if m_ephemeral.ephemeral().read_only == read_only {
+        return;
+    }
+    m_ephemeral.mutate(EphemeralMutation::SetReadOnly(read_only));
+    if read_only {
+        return;
+    }
+
+    // Messages from friends were dropped while we were read only. Resending our outgoing move
+    // token (Or sending a new one, if the token is ours) makes the friend send its outgoing
+    // move token again:
+    for friend_public_key in m_state.state().friends.keys() {
+        if m_ephemeral
+            .ephemeral()
+            .liveness
+            .is_online(friend_public_key)
+        {
+            send_commands.set_resend_outgoing(friend_public_key);
+        }
+    }
+}
+
 fn create_report_mutations<B>(
     initial_state: FunderState<B>,
     funder_mutations: &[FunderMutation<B>],
     ephemeral_mutations: &[EphemeralMutation],
+    ephemeral: &Ephemeral,
 ) -> Vec<FunderReportMutation<B>>
 where
     B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
@@ -257,6 +428,7 @@ where
         report_mutations.extend(funder_mutation_to_report_mutations(
             funder_mutation,
             &running_state,
+            &ephemeral.payment_timings,
         ));
         running_state.mutate(funder_mutation);
     }
@@ -268,20 +440,155 @@ where
         report_mutations.extend(ephemeral_mutation_to_report_mutations::<B>(
             ephemeral_mutation,
             &funder_state,
+            ephemeral,
         ));
     }
 
     report_mutations
 }
 
+/// Adapt the batch sizes of friends to their measured token round trip times.
+fn adapt_batch_sizes(
+    m_ephemeral: &mut MutableEphemeral,
+    min_operations_in_batch: usize,
+    max_operations_in_batch: usize,
+) {
+    let new_batch_sizes = m_ephemeral
+        .ephemeral()
+        .adaptive_batch
+        .adapt(min_operations_in_batch, max_operations_in_batch);
+
+    for (friend_public_key, batch_size) in new_batch_sizes {
+        info!(
+            "Batch size for friend {:?} adapted to {}",
+            friend_public_key, batch_size
+        );
+        let adaptive_batch_mutation =
+            AdaptiveBatchMutation::SetBatchSize((friend_public_key, batch_size));
+        m_ephemeral.mutate(EphemeralMutation::AdaptiveBatchMutation(
+            adaptive_batch_mutation,
+        ));
+    }
+}
+
+/// Attach labels to the responses of labeled payments, and remember the results of the
+/// payments.
+fn label_responses<B>(
+    m_state: &mut MutableFunderState<B>,
+    outgoing_control: &mut [FunderOutgoingControl<B>],
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    for control in outgoing_control.iter_mut() {
+        let response_received = match control {
+            FunderOutgoingControl::ResponseReceived(response_received) => response_received,
+            _ => continue,
+        };
+        let labeled_payment = match m_state
+            .state()
+            .labeled_payments
+            .iter()
+            .find(|labeled_payment| labeled_payment.request_id == response_received.request_id)
+        {
+            Some(labeled_payment) => labeled_payment.clone(),
+            None => continue,
+        };
+
+        if labeled_payment.opt_result.as_ref() != Some(&response_received.result) {
+            m_state.mutate(FunderMutation::SetLabeledPaymentResult((
+                response_received.request_id,
+                response_received.result.clone(),
+            )));
+        }
+        response_received.opt_label = Some(labeled_payment.label);
+    }
+}
+
+/// Remember the results of pending payments that were submitted by apps.
+/// Responses to `opt_skip_request_id` are ignored.
+fn record_submission_results<B>(
+    m_state: &mut MutableFunderState<B>,
+    outgoing_control: &[FunderOutgoingControl<B>],
+    opt_skip_request_id: Option<Uid>,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    for control in outgoing_control {
+        let response_received = match control {
+            FunderOutgoingControl::ResponseReceived(response_received) => response_received,
+            _ => continue,
+        };
+        if Some(response_received.request_id) == opt_skip_request_id {
+            continue;
+        }
+        let is_pending = m_state.state().submissions.iter().any(|submission| {
+            submission.request_id == response_received.request_id && submission.opt_result.is_none()
+        });
+        if is_pending {
+            m_state.mutate(FunderMutation::SetPaymentSubmissionResult((
+                response_received.request_id,
+                response_received.result.clone(),
+            )));
+        }
+    }
+}
+
+/// Update the reported deadlines of friends that have changed by at least `granularity_ticks`.
+fn update_reported_deadlines<B>(
+    m_state: &MutableFunderState<B>,
+    m_ephemeral: &mut MutableEphemeral,
+    granularity_ticks: usize,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let granularity_ticks = usize_to_u64(granularity_ticks).unwrap();
+    let mut reported_deadlines_mutations = Vec::new();
+
+    for (friend_public_key, friend) in &m_state.state().friends {
+        let ephemeral = m_ephemeral.ephemeral();
+        let reported = ephemeral.reported_deadlines.get(friend_public_key);
+        let current = calc_friend_deadlines(
+            friend,
+            ephemeral,
+            &m_state.state().expired_requests,
+            friend_public_key,
+        );
+        if is_deadlines_changed(&reported, &current, granularity_ticks) {
+            reported_deadlines_mutations.push(ReportedDeadlinesMutation::Set((
+                friend_public_key.clone(),
+                current,
+            )));
+        }
+    }
+
+    // Forget the deadlines of removed friends:
+    for friend_public_key in m_ephemeral.ephemeral().reported_deadlines.friends.keys() {
+        if !m_state.state().friends.contains_key(friend_public_key) {
+            reported_deadlines_mutations
+                .push(ReportedDeadlinesMutation::Forget(friend_public_key.clone()));
+        }
+    }
+
+    for reported_deadlines_mutation in reported_deadlines_mutations {
+        m_ephemeral.mutate(EphemeralMutation::ReportedDeadlinesMutation(
+            reported_deadlines_mutation,
+        ));
+    }
+}
+
 pub async fn funder_handle_message<'a, B, R>(
     identity_client: &'a mut IdentityClient,
     rng: &'a R,
     funder_state: FunderState<B>,
     funder_ephemeral: Ephemeral,
     max_node_relays: usize,
+    min_operations_in_batch: usize,
     max_operations_in_batch: usize,
     max_pending_user_requests: usize,
+    relays_damping_ticks: usize,
+    prewarm_ticks: usize,
+    reliability_decay_ticks: usize,
+    deadlines_granularity_ticks: usize,
     funder_incoming: FunderIncoming<B>,
 ) -> Result<FunderHandlerOutput<B>, FunderHandlerError>
 where
@@ -299,13 +606,28 @@ where
             rng,
             max_node_relays,
             max_pending_user_requests,
+            relays_damping_ticks,
+            prewarm_ticks,
+            reliability_decay_ticks,
             funder_incoming,
         )?;
 
+    // Goodbye messages are sent before the Channeler is configured,
+    // as the configuration might close the connection to the friend:
+    for friend_message in create_goodbye_messages(m_ephemeral.ephemeral(), &send_commands) {
+        outgoing_comms.push(FunderOutgoingComm::FriendMessage(friend_message));
+    }
+
     for channeler_config in outgoing_channeler_config {
         outgoing_comms.push(FunderOutgoingComm::ChannelerConfig(channeler_config));
     }
 
+    adapt_batch_sizes(
+        &mut m_ephemeral,
+        min_operations_in_batch,
+        max_operations_in_batch,
+    );
+
     // Send all possible messages according to SendCommands
     // TODO: Maybe we should output outgoing_comms instead of friend_messages and
     // outgoing_channeler_config. When we merge the two, we might be out of order!
@@ -317,23 +639,65 @@ where
             max_operations_in_batch,
             identity_client,
             rng
-        ));
+        ))
+        .map_err(|e| match e {
+            SendError::IdentityError(e) => FunderHandlerError::IdentityError(e),
+            SendError::InvariantError(e) => FunderHandlerError::InvariantError(e),
+        })?;
 
     for channeler_config in outgoing_channeler_config {
         outgoing_comms.push(FunderOutgoingComm::ChannelerConfig(channeler_config));
     }
 
     for friend_message in friend_messages {
+        // Start measuring the token round trip time:
+        if let (friend_public_key, FriendMessage::MoveTokenRequest(move_token_request)) =
+            &friend_message
+        {
+            if move_token_request.token_wanted {
+                let adaptive_batch_mutation =
+                    AdaptiveBatchMutation::TokenSent(friend_public_key.clone());
+                m_ephemeral.mutate(EphemeralMutation::AdaptiveBatchMutation(
+                    adaptive_batch_mutation,
+                ));
+            }
+            // Mark the time our own payments were sent to the first hop:
+            for op in &move_token_request.friend_move_token.operations {
+                if let FriendTcOp::RequestSendFunds(request_send_funds) = op {
+                    let request_id = request_send_funds.request_id;
+                    if m_ephemeral
+                        .ephemeral()
+                        .payment_timings
+                        .started
+                        .contains_key(&request_id)
+                    {
+                        let payment_timing_mutation = PaymentTimingMutation::Sent(request_id);
+                        m_ephemeral.mutate(EphemeralMutation::PaymentTimingMutation(
+                            payment_timing_mutation,
+                        ));
+                    }
+                }
+            }
+        }
         outgoing_comms.push(FunderOutgoingComm::FriendMessage(friend_message));
     }
 
+    record_submission_results(&mut m_state, &sender_outgoing_control, None);
+
+    let mut user_outgoing_control = handle_outgoing_control;
+    user_outgoing_control.extend(sender_outgoing_control);
+    label_responses(&mut m_state, &mut user_outgoing_control);
+
+    update_reported_deadlines(&m_state, &mut m_ephemeral, deadlines_granularity_ticks);
+
     // Add reports:
     let (initial_state, funder_mutations, _state) = m_state.done();
-    let (ephemeral_mutations, _ephemeral) = m_ephemeral.done();
+    let (ephemeral_mutations, ephemeral) = m_ephemeral.done();
     let report_mutations = create_report_mutations(
         initial_state,
         &funder_mutations[..],
         &ephemeral_mutations[..],
+        &ephemeral,
     );
 
     let funder_report_mutations = FunderReportMutations {
@@ -351,8 +715,7 @@ where
     }
 
     // We always send the report mutations first through the outgoing control:
-    outgoing_control.extend(handle_outgoing_control);
-    outgoing_control.extend(sender_outgoing_control);
+    outgoing_control.extend(user_outgoing_control);
 
     Ok(FunderHandlerOutput {
         funder_mutations,
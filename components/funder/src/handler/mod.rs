@@ -3,10 +3,12 @@ mod handle_control;
 mod handle_friend;
 mod handle_init;
 mod handle_liveness;
+mod handle_timer;
 mod handler;
 mod sender;
 
 #[cfg(test)]
 mod tests;
 
+pub use self::handle_friend::set_pair_freeze_limit;
 pub use self::handler::{funder_handle_message, FunderHandlerError};
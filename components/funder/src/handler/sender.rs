@@ -1,19 +1,26 @@
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
 
+use im::vector::Vector as ImVec;
+
 use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::usize_to_u32;
 
 use crypto::crypto_rand::{CryptoRandom, RandValue};
 use crypto::identity::PublicKey;
 
-use proto::app_server::messages::RelayAddress;
+use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
+use proto::consts::MAX_INCOMING_PAYMENTS;
 use proto::funder::messages::{
-    ChannelerUpdateFriend, FriendMessage, FriendTcOp, FunderOutgoingControl, MoveTokenRequest,
-    RequestsStatus, ResponseReceived, ResponseSendFundsResult,
+    ChannelerUpdateFriend, FailureReason, FriendMessage, FriendTcOp, FunderOutgoingControl, Goodbye,
+    MoveTokenRequest, ProtocolViolationReport, Receipt, RequestsStatus, ResponseReceived,
+    ResponseSendFundsResult, VerificationProof,
 };
+use proto::funder::signature_buff::{create_verification_proof_buffer, prepare_receipt};
 
-use identity::IdentityClient;
+use identity::{IdentityClient, IdentityClientError};
 
+use crate::mutual_credit::incoming::validate_operations_list;
 use crate::mutual_credit::outgoing::{OutgoingMc, QueueOperationError};
 use crate::types::{
     create_failure_send_funds, create_pending_request, create_response_send_funds,
@@ -21,12 +28,14 @@ use crate::types::{
 };
 
 use crate::friend::{
-    ChannelInconsistent, ChannelStatus, FriendMutation, ResponseOp, SentLocalRelays,
+    ChannelInconsistent, ChannelPendingReset, ChannelStatus, FriendMutation, ResponseOp,
+    SentLocalRelays,
 };
-use crate::token_channel::{SetDirection, TcDirection, TcMutation, TokenChannel};
+use crate::token_channel::{SetDirection, TcDirection, TcMutation};
 
 use crate::ephemeral::Ephemeral;
 use crate::handler::handler::{find_request_origin, MutableFunderState};
+use crate::invariants::InvariantError;
 use crate::state::{FunderMutation, FunderState};
 
 #[derive(Debug, Clone)]
@@ -39,6 +48,14 @@ pub struct FriendSendCommands {
     pub remote_wants_token: bool,
     /// We want to perform a local reset
     pub local_reset: bool,
+    /// We want the token, even if we have nothing to send (Pre-warm)
+    pub want_token: bool,
+    /// Tell the remote side why we rejected its last move token
+    pub opt_protocol_violation: Option<ProtocolViolationReport>,
+    /// Prove to the remote side that we know the verification phrase
+    pub send_verification_proof: bool,
+    /// Tell the remote side that we are about to disconnect on purpose
+    pub opt_goodbye: Option<Goodbye>,
 }
 
 impl FriendSendCommands {
@@ -48,6 +65,10 @@ impl FriendSendCommands {
             resend_outgoing: false,
             remote_wants_token: false,
             local_reset: false,
+            want_token: false,
+            opt_protocol_violation: None,
+            send_verification_proof: false,
+            opt_goodbye: None,
         }
     }
 }
@@ -97,6 +118,42 @@ impl SendCommands {
             .or_insert_with(FriendSendCommands::new);
         friend_send_commands.local_reset = true;
     }
+
+    pub fn set_want_token(&mut self, friend_public_key: &PublicKey) {
+        let friend_send_commands = self
+            .send_commands
+            .entry(friend_public_key.clone())
+            .or_insert_with(FriendSendCommands::new);
+        friend_send_commands.want_token = true;
+    }
+
+    pub fn set_protocol_violation(
+        &mut self,
+        friend_public_key: &PublicKey,
+        protocol_violation_report: ProtocolViolationReport,
+    ) {
+        let friend_send_commands = self
+            .send_commands
+            .entry(friend_public_key.clone())
+            .or_insert_with(FriendSendCommands::new);
+        friend_send_commands.opt_protocol_violation = Some(protocol_violation_report);
+    }
+
+    pub fn set_send_verification_proof(&mut self, friend_public_key: &PublicKey) {
+        let friend_send_commands = self
+            .send_commands
+            .entry(friend_public_key.clone())
+            .or_insert_with(FriendSendCommands::new);
+        friend_send_commands.send_verification_proof = true;
+    }
+
+    pub fn set_goodbye(&mut self, friend_public_key: &PublicKey, goodbye: Goodbye) {
+        let friend_send_commands = self
+            .send_commands
+            .entry(friend_public_key.clone())
+            .or_insert_with(FriendSendCommands::new);
+        friend_send_commands.opt_goodbye = Some(goodbye);
+    }
 }
 
 #[derive(Debug)]
@@ -108,6 +165,16 @@ enum PendingQueueError {
 #[derive(Debug)]
 enum CollectOutgoingError {
     MaxOperationsReached,
+    IdentityError(IdentityClientError),
+}
+
+#[derive(Debug)]
+pub enum SendError {
+    /// Failed to sign using the identity service.
+    IdentityError(IdentityClientError),
+    /// The state of a friend does not match a move token that was built for it earlier in the
+    /// same pass.
+    InvariantError(InvariantError),
 }
 
 struct PendingMoveToken<B> {
@@ -118,7 +185,9 @@ struct PendingMoveToken<B> {
     token_wanted: bool,
     max_operations_in_batch: usize,
     /// Can we send this move token with empty operations list
-    /// and empty opt_local_address?
+    /// and empty opt_local_address? Set only when we have to hand the token over
+    /// (The remote side wants it, or we resend our outgoing move token).
+    /// An empty move token is dropped otherwise.
     may_send_empty: bool,
 }
 
@@ -145,6 +214,7 @@ where
 
     /// Attempt to queue one operation into a certain `pending_move_token`.
     /// If successful, mutations are applied and the operation is queued.
+    /// A response or failure that does not fit our frozen credits or balance is dropped.
     /// Otherwise, an error is returned.
     fn queue_operation(
         &mut self,
@@ -164,6 +234,16 @@ where
             Err(QueueOperationError::InsufficientTrust) => {
                 Err(PendingQueueError::InsufficientTrust)
             }
+            Err(QueueOperationError::InsufficientFrozenCredits)
+            | Err(QueueOperationError::BalanceOverflow) => {
+                // The remote side would reject a move token containing this operation.
+                // Nothing was mutated, so we drop the operation instead of sending it.
+                warn!(
+                    "Dropping operation with inconsistent credits: {:?}",
+                    operation
+                );
+                return Ok(());
+            }
             Err(_) => unreachable!(),
         }?;
 
@@ -189,7 +269,7 @@ where
 }
 
 fn transmit_outgoing<B>(
-    m_state: &MutableFunderState<B>,
+    m_state: &mut MutableFunderState<B>,
     friend_public_key: &PublicKey,
     token_wanted: bool,
     outgoing_messages: &mut Vec<OutgoingMessage<B>>,
@@ -199,13 +279,23 @@ fn transmit_outgoing<B>(
     let friend = m_state.state().friends.get(friend_public_key).unwrap();
     let token_channel = match &friend.channel_status {
         ChannelStatus::Consistent(token_channel) => token_channel,
-        ChannelStatus::Inconsistent(_) => unreachable!(),
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => unreachable!(),
     };
 
-    let move_token = match &token_channel.get_direction() {
-        TcDirection::Outgoing(tc_outgoing) => tc_outgoing.create_outgoing_move_token(),
+    let tc_outgoing = match &token_channel.get_direction() {
+        TcDirection::Outgoing(tc_outgoing) => tc_outgoing,
         TcDirection::Incoming(_) => unreachable!(),
     };
+    let move_token = tc_outgoing.create_outgoing_move_token();
+
+    // Keep track of whether we asked for the token, so that it can be reported:
+    if tc_outgoing.token_wanted != token_wanted {
+        let tc_mutation = TcMutation::SetTokenWanted(token_wanted);
+        let friend_mutation = FriendMutation::TcMutation(tc_mutation);
+        let funder_mutation =
+            FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+        m_state.mutate(funder_mutation);
+    }
 
     let move_token_request = MoveTokenRequest {
         friend_move_token: move_token,
@@ -224,7 +314,8 @@ pub async fn apply_local_reset<'a, B, R>(
     channel_inconsistent: &'a ChannelInconsistent,
     identity_client: &'a mut IdentityClient,
     rng: &'a R,
-) where
+) -> Result<(), IdentityClientError>
+where
     B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
     R: CryptoRandom,
 {
@@ -246,26 +337,29 @@ pub async fn apply_local_reset<'a, B, R>(
         friend_public_key.clone(),
         remote_reset_terms.inconsistency_counter,
         move_token_counter,
+        // Remote reset terms are verified to be negatable when received:
         remote_reset_terms.balance_for_reset.checked_neg().unwrap(),
         local_pending_debt,
         remote_pending_debt,
         rand_nonce,
     );
 
-    let reset_move_token = await!(sign_move_token(u_reset_move_token, identity_client));
+    let reset_move_token = await!(sign_move_token(u_reset_move_token, identity_client))?;
 
-    let token_channel = TokenChannel::new_from_local_reset(
-        &m_state.state().local_public_key,
-        friend_public_key,
-        &reset_move_token,
-        remote_reset_terms.balance_for_reset.checked_neg().unwrap(),
-        channel_inconsistent.opt_last_incoming_move_token.clone(),
-    );
+    // The channel becomes consistent only after the remote side acknowledges our reset move
+    // token. See `handle_move_token_request()`.
+    let channel_pending_reset = ChannelPendingReset {
+        opt_last_incoming_move_token: channel_inconsistent.opt_last_incoming_move_token.clone(),
+        local_reset_terms: channel_inconsistent.local_reset_terms.clone(),
+        remote_reset_terms,
+        reset_move_token,
+    };
 
-    let friend_mutation = FriendMutation::SetConsistent(token_channel);
+    let friend_mutation = FriendMutation::SetPendingReset(channel_pending_reset);
     let funder_mutation =
         FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
     m_state.mutate(funder_mutation);
+    Ok(())
 }
 
 async fn send_friend_iter1<'a, B, R>(
@@ -273,6 +367,7 @@ async fn send_friend_iter1<'a, B, R>(
     friend_public_key: &'a PublicKey,
     friend_send_commands: &'a FriendSendCommands,
     pending_move_tokens: &'a mut HashMap<PublicKey, PendingMoveToken<B>>,
+    local_named_relays: &'a ImVec<NamedRelayAddress<B>>,
     identity_client: &'a mut IdentityClient,
     rng: &'a R,
     max_operations_in_batch: usize,
@@ -280,16 +375,45 @@ async fn send_friend_iter1<'a, B, R>(
     mut outgoing_messages: &'a mut Vec<OutgoingMessage<B>>,
     outgoing_control: &'a mut Vec<FunderOutgoingControl<B>>,
     outgoing_channeler_config: &'a mut Vec<ChannelerConfig<RelayAddress<B>>>,
-) where
+) -> Result<(), IdentityClientError>
+where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
     R: CryptoRandom,
 {
+    // The verification proof is not related to the token channel, and is sent regardless of its
+    // state:
+    if friend_send_commands.send_verification_proof {
+        let friend = m_state.state().friends.get(friend_public_key).unwrap();
+        if let Some(phrase) = &friend.opt_verification_phrase {
+            let proof_buffer = create_verification_proof_buffer(
+                &friend.local_public_key,
+                friend_public_key,
+                phrase,
+            );
+            let signature = await!(identity_client.request_signature(proof_buffer))?;
+            outgoing_messages.push((
+                friend_public_key.clone(),
+                FriendMessage::VerificationProof(VerificationProof { signature }),
+            ));
+        }
+    }
+
     if !friend_send_commands.try_send
         && !friend_send_commands.resend_outgoing
         && !friend_send_commands.remote_wants_token
         && !friend_send_commands.local_reset
+        && !friend_send_commands.want_token
+        && friend_send_commands.opt_protocol_violation.is_none()
     {
-        return;
+        return Ok(());
+    }
+
+    // The diagnostic report goes out before the InconsistencyError message:
+    if let Some(protocol_violation_report) = &friend_send_commands.opt_protocol_violation {
+        outgoing_messages.push((
+            friend_public_key.clone(),
+            FriendMessage::ProtocolViolation(protocol_violation_report.clone()),
+        ));
     }
 
     let friend = m_state.state().friends.get(friend_public_key).unwrap();
@@ -304,7 +428,7 @@ async fn send_friend_iter1<'a, B, R>(
                 &c_channel_inconsistent,
                 identity_client,
                 rng
-            ));
+            ))?;
         }
     }
 
@@ -321,13 +445,33 @@ async fn send_friend_iter1<'a, B, R>(
                     ),
                 ));
             }
-            return;
+            return Ok(());
+        }
+        ChannelStatus::PendingReset(channel_pending_reset) => {
+            // Retransmit our reset move token until the remote side acknowledges it.
+            // We always want the token back, to find out that the reset was received.
+            if friend_send_commands.local_reset
+                || friend_send_commands.resend_outgoing
+                || friend_send_commands.try_send
+            {
+                let move_token_request = MoveTokenRequest {
+                    friend_move_token: channel_pending_reset.reset_move_token.clone(),
+                    token_wanted: true,
+                };
+                outgoing_messages.push((
+                    friend_public_key.clone(),
+                    FriendMessage::MoveTokenRequest(move_token_request),
+                ));
+            }
+            return Ok(());
         }
     };
 
     let tc_incoming = match &token_channel.get_direction() {
         TcDirection::Outgoing(tc_outgoing) => {
-            if estimate_should_send(m_state.state(), friend_public_key) {
+            if estimate_should_send(m_state.state(), friend_public_key)
+                || friend_send_commands.want_token
+            {
                 let is_token_wanted = true;
                 transmit_outgoing(
                     m_state,
@@ -345,7 +489,7 @@ async fn send_friend_iter1<'a, B, R>(
                 );
             }
 
-            return;
+            return Ok(());
         }
         TcDirection::Incoming(tc_incoming) => tc_incoming,
     };
@@ -368,27 +512,39 @@ async fn send_friend_iter1<'a, B, R>(
     );
     pending_move_tokens.insert(friend_public_key.clone(), pending_move_token);
     let pending_move_token = pending_move_tokens.get_mut(friend_public_key).unwrap();
-    let _ = await!(collect_outgoing_move_token(
+    match await!(collect_outgoing_move_token(
         m_state,
         outgoing_channeler_config,
         outgoing_control,
         failure_public_keys,
         friend_public_key,
         pending_move_token,
+        local_named_relays,
         identity_client,
         rng
-    ));
+    )) {
+        Ok(()) | Err(CollectOutgoingError::MaxOperationsReached) => Ok(()),
+        Err(CollectOutgoingError::IdentityError(e)) => Err(e),
+    }
 }
 
 /// Do we need to send anything to the remote side?
 /// Note that this is only an estimation. It is possible that when the token from remote side
 /// arrives, the state will be different.
+///
+/// Nothing can be sent through a channel that is not consistent, hence we return false for such
+/// channels.
 fn estimate_should_send<'a, B>(state: &'a FunderState<B>, friend_public_key: &'a PublicKey) -> bool
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
 {
-    // Check if notification about local address change is required:
     let friend = state.friends.get(friend_public_key).unwrap();
+    let token_channel = match &friend.channel_status {
+        ChannelStatus::Consistent(token_channel) => token_channel,
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => return false,
+    };
+
+    // Check if notification about local address change is required:
     match &friend.sent_local_relays {
         SentLocalRelays::NeverSent => return true,
         SentLocalRelays::Transition((relays, _)) | SentLocalRelays::LastSent(relays) => {
@@ -399,25 +555,20 @@ where
     };
 
     // Check if update to remote_max_debt is required:
-    match &friend.channel_status {
-        ChannelStatus::Consistent(token_channel) => {
-            if friend.wanted_remote_max_debt != token_channel.get_remote_max_debt() {
-                return true;
-            }
+    if friend.wanted_remote_max_debt != token_channel.get_remote_max_debt() {
+        return true;
+    }
 
-            // Open or close requests is needed:
-            let local_requests_status = &token_channel
-                .get_mutual_credit()
-                .state()
-                .requests_status
-                .local;
+    // Open or close requests is needed:
+    let local_requests_status = &token_channel
+        .get_mutual_credit()
+        .state()
+        .requests_status
+        .local;
 
-            if friend.wanted_local_requests_status != *local_requests_status {
-                return true;
-            }
-        }
-        ChannelStatus::Inconsistent(_) => {}
-    };
+    if friend.wanted_local_requests_status != *local_requests_status {
+        return true;
+    }
 
     if !friend.pending_responses.is_empty() {
         return true;
@@ -471,7 +622,8 @@ where
             // The friend with public key `origin_public_key` is the origin of this request.
             // We send him back a failure message:
             let pending_request = create_pending_request(request_send_funds);
-            let u_failure_op = ResponseOp::UnsignedFailure(pending_request);
+            let u_failure_op =
+                ResponseOp::UnsignedFailure((pending_request, FailureReason::Unspecified));
             let friend_mutation = FriendMutation::PushBackPendingResponse(u_failure_op);
             let funder_mutation =
                 FunderMutation::FriendMutation((origin_public_key.clone(), friend_mutation));
@@ -481,9 +633,15 @@ where
         }
         None => {
             // We are the origin of this request
+            let local_public_key = m_state.state().local_public_key.clone();
             let response_received = ResponseReceived {
                 request_id: request_send_funds.request_id,
-                result: ResponseSendFundsResult::Failure(m_state.state().local_public_key.clone()),
+                result: ResponseSendFundsResult::Failure((
+                    local_public_key,
+                    FailureReason::Unspecified,
+                )),
+                opt_timing: None,
+                opt_label: None,
             };
             outgoing_control.push(FunderOutgoingControl::ResponseReceived(response_received));
         }
@@ -527,17 +685,52 @@ where
 }
 */
 
+/// Add a notification about a completed incoming payment to the outbox,
+/// if the payment should be reported to the configured payment notifier.
+fn add_incoming_payment<B>(
+    m_state: &mut MutableFunderState<B>,
+    outgoing_control: &mut Vec<FunderOutgoingControl<B>>,
+    receipt: Receipt,
+    route_len: u32,
+) where
+    B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
+{
+    let should_notify = match &m_state.state().opt_payment_notifier {
+        Some(payment_notifier) => payment_notifier.filter.matches(&receipt.invoice_id),
+        None => false,
+    };
+    if !should_notify {
+        return;
+    }
+
+    if m_state.state().incoming_payments.len() >= MAX_INCOMING_PAYMENTS {
+        warn!("Incoming payments outbox is full. Dropping the oldest notification.");
+    }
+
+    let notification_id = m_state.state().next_notification_id;
+    let funder_mutation = FunderMutation::AddIncomingPayment((receipt, route_len));
+    m_state.mutate(funder_mutation);
+
+    let incoming_payment = m_state
+        .state()
+        .incoming_payments
+        .get(&notification_id)
+        .unwrap()
+        .clone();
+    outgoing_control.push(FunderOutgoingControl::IncomingPayment(incoming_payment));
+}
+
 async fn response_op_to_friend_tc_op<'a, B, R>(
     m_state: &'a mut MutableFunderState<B>,
     response_op: ResponseOp,
     mut identity_client: &'a mut IdentityClient,
     rng: &'a R,
-) -> FriendTcOp
+) -> Result<FriendTcOp, IdentityClientError>
 where
     B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
     R: CryptoRandom,
 {
-    match response_op {
+    Ok(match response_op {
         ResponseOp::Response(response) => FriendTcOp::ResponseSendFunds(response),
         ResponseOp::UnsignedResponse(pending_request) => {
             let rand_nonce = RandValue::new(rng);
@@ -545,19 +738,80 @@ where
                 &pending_request,
                 rand_nonce,
                 identity_client
-            )))
+            ))?)
         }
         ResponseOp::Failure(failure) => FriendTcOp::FailureSendFunds(failure),
-        ResponseOp::UnsignedFailure(pending_request) => {
+        ResponseOp::UnsignedFailure((pending_request, reason)) => {
             let rand_nonce = RandValue::new(rng);
             FriendTcOp::FailureSendFunds(await!(create_failure_send_funds(
                 &pending_request,
                 &(m_state.state().local_public_key),
+                reason,
                 rand_nonce,
                 &mut identity_client
-            )))
+            ))?)
         }
+    })
+}
+
+/// Add our local relays to a pending move token if the friend does not know them yet, and update
+/// `friend.sent_local_relays` accordingly.
+///
+/// `local_named_relays` is captured once for every pass, before any PendingMoveToken is built, so
+/// that all the move tokens of a pass carry the same address. An address change during the pass
+/// is sent with the next move token.
+fn set_pending_local_relays<B>(
+    m_state: &mut MutableFunderState<B>,
+    outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
+    friend_public_key: &PublicKey,
+    pending_move_token: &mut PendingMoveToken<B>,
+    local_named_relays: &ImVec<NamedRelayAddress<B>>,
+) where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
+{
+    // The address of this move token was already decided:
+    if pending_move_token.opt_local_relays.is_some() {
+        return;
     }
+
+    let friend = m_state.state().friends.get(friend_public_key).unwrap();
+    let new_sent_local_relays = match &friend.sent_local_relays {
+        SentLocalRelays::NeverSent => SentLocalRelays::LastSent(local_named_relays.clone()),
+        SentLocalRelays::Transition((last_sent_local_relays, _))
+        | SentLocalRelays::LastSent(last_sent_local_relays) => {
+            if local_named_relays == last_sent_local_relays {
+                return;
+            }
+            SentLocalRelays::Transition((
+                local_named_relays.clone(),
+                last_sent_local_relays.clone(),
+            ))
+        }
+    };
+
+    let local_relays = local_named_relays
+        .iter()
+        .cloned()
+        .map(RelayAddress::from)
+        .collect();
+    pending_move_token.set_local_relays(local_relays);
+
+    // Update friend.sent_local_relays accordingly:
+    let friend_mutation = FriendMutation::SetSentLocalRelays(new_sent_local_relays);
+    let funder_mutation =
+        FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+    m_state.mutate(funder_mutation);
+
+    let friend = m_state.state().friends.get(friend_public_key).unwrap();
+
+    // Notify Channeler to change the friend's address:
+    let update_friend = ChannelerUpdateFriend {
+        friend_public_key: friend_public_key.clone(),
+        friend_relays: friend.remote_relays.clone(),
+        local_relays: friend.sent_local_relays.to_vec(),
+    };
+    let channeler_config = ChannelerConfig::UpdateFriend(update_friend);
+    outgoing_channeler_config.push(channeler_config);
 }
 
 /// Given a friend with an incoming move token state, create the largest possible move token to
@@ -570,6 +824,7 @@ async fn collect_outgoing_move_token<'a, B, R>(
     failure_public_keys: &'a mut HashSet<PublicKey>,
     friend_public_key: &'a PublicKey,
     pending_move_token: &'a mut PendingMoveToken<B>,
+    local_named_relays: &'a ImVec<NamedRelayAddress<B>>,
     identity_client: &'a mut IdentityClient,
     rng: &'a R,
 ) -> Result<(), CollectOutgoingError>
@@ -591,59 +846,20 @@ where
     */
 
     // Send update about local address if needed:
-    let friend = m_state.state().friends.get(friend_public_key).unwrap();
-    let local_named_relays = m_state.state().relays.clone();
-
-    let local_relays = local_named_relays
-        .iter()
-        .cloned()
-        .map(RelayAddress::from)
-        .collect();
-
-    let opt_new_sent_local_relays = match &friend.sent_local_relays {
-        SentLocalRelays::NeverSent => {
-            pending_move_token.set_local_relays(local_relays);
-            Some(SentLocalRelays::LastSent(local_named_relays.clone()))
-        }
-        SentLocalRelays::Transition((last_sent_local_relays, _))
-        | SentLocalRelays::LastSent(last_sent_local_relays) => {
-            if &local_named_relays != last_sent_local_relays {
-                pending_move_token.set_local_relays(local_relays.clone());
-                Some(SentLocalRelays::Transition((
-                    local_named_relays.clone(),
-                    last_sent_local_relays.clone(),
-                )))
-            } else {
-                None
-            }
-        }
-    };
-
-    // Update friend.sent_local_relays accordingly:
-    if let Some(new_sent_local_relays) = opt_new_sent_local_relays {
-        let friend_mutation = FriendMutation::SetSentLocalRelays(new_sent_local_relays);
-        let funder_mutation =
-            FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
-        m_state.mutate(funder_mutation);
-
-        let friend = m_state.state().friends.get(friend_public_key).unwrap();
-
-        // Notify Channeler to change the friend's address:
-        let update_friend = ChannelerUpdateFriend {
-            friend_public_key: friend_public_key.clone(),
-            friend_relays: friend.remote_relays.clone(),
-            local_relays: friend.sent_local_relays.to_vec(),
-        };
-        let channeler_config = ChannelerConfig::UpdateFriend(update_friend);
-        outgoing_channeler_config.push(channeler_config);
-    }
+    set_pending_local_relays(
+        m_state,
+        outgoing_channeler_config,
+        friend_public_key,
+        pending_move_token,
+        local_named_relays,
+    );
 
     let friend = m_state.state().friends.get(friend_public_key).unwrap();
 
     // Set remote_max_debt if needed:
     let remote_max_debt = match &friend.channel_status {
         ChannelStatus::Consistent(token_channel) => token_channel,
-        ChannelStatus::Inconsistent(_) => unreachable!(),
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => unreachable!(),
     }
     .get_remote_max_debt();
 
@@ -661,7 +877,7 @@ where
     let friend = m_state.state().friends.get(friend_public_key).unwrap();
     let token_channel = match &friend.channel_status {
         ChannelStatus::Consistent(token_channel) => token_channel,
-        ChannelStatus::Inconsistent(_) => unreachable!(),
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => unreachable!(),
     };
 
     // Open or close requests is needed:
@@ -691,12 +907,19 @@ where
     // TODO: Possibly replace this clone with something more efficient later:
     let mut pending_responses = friend.pending_responses.clone();
     while let Some(pending_response) = pending_responses.pop_front() {
+        // If we are the destination of the request, we keep the original request, to be able to
+        // create a receipt for the incoming payment:
+        let opt_incoming_request = match &pending_response {
+            ResponseOp::UnsignedResponse(pending_request) => Some(pending_request.clone()),
+            _ => None,
+        };
         let pending_op = await!(response_op_to_friend_tc_op(
             m_state,
             pending_response,
             identity_client,
             rng
-        ));
+        ))
+        .map_err(CollectOutgoingError::IdentityError)?;
         await!(queue_operation_or_failure(
             m_state,
             pending_move_token,
@@ -705,6 +928,14 @@ where
             &pending_op
         ))?;
 
+        if let (Some(pending_request), FriendTcOp::ResponseSendFunds(response_send_funds)) =
+            (opt_incoming_request, &pending_op)
+        {
+            let receipt = prepare_receipt(response_send_funds, &pending_request);
+            let route_len = usize_to_u32(pending_request.route.public_keys.len()).unwrap();
+            add_incoming_payment(m_state, outgoing_control, receipt, route_len);
+        }
+
         let friend_mutation = FriendMutation::PopFrontPendingResponse;
         let funder_mutation =
             FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
@@ -774,7 +1005,8 @@ where
             pending_response,
             identity_client,
             rng
-        ));
+        ))
+        .map_err(CollectOutgoingError::IdentityError)?;
         // TODO: Find a more elegant way to do this:
         let mut dummy_failure_public_keys = HashSet::new();
         let mut dummy_outgoing_control = Vec::new();
@@ -801,7 +1033,8 @@ async fn send_move_token<'a, B, R>(
     identity_client: &'a mut IdentityClient,
     rng: &'a R,
     outgoing_messages: &'a mut Vec<OutgoingMessage<B>>,
-) where
+) -> Result<(), SendError>
+where
     B: Clone + CanonicalSerialize + PartialEq + Eq + Debug,
     R: CryptoRandom,
 {
@@ -814,7 +1047,7 @@ async fn send_move_token<'a, B, R>(
     } = pending_move_token;
 
     if operations.is_empty() && opt_local_relays.is_none() && !may_send_empty {
-        return;
+        return Ok(());
     }
 
     // We want the token back if we just set a new address, to be sure
@@ -823,21 +1056,55 @@ async fn send_move_token<'a, B, R>(
 
     let friend = m_state.state().friends.get(&friend_public_key).unwrap();
 
-    let rand_nonce = RandValue::new(rng);
-    let token_channel = match &friend.channel_status {
-        ChannelStatus::Consistent(token_channel) => token_channel,
-        ChannelStatus::Inconsistent(_) => unreachable!(),
+    // The pending move token was built for an incoming token channel:
+    let opt_tc_incoming = match &friend.channel_status {
+        ChannelStatus::Consistent(token_channel) => match token_channel.get_direction() {
+            TcDirection::Incoming(tc_incoming) => Some(tc_incoming),
+            TcDirection::Outgoing(_) => None,
+        },
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => None,
     };
+    let tc_incoming = opt_tc_incoming.ok_or_else(|| {
+        SendError::InvariantError(InvariantError::MoveTokenNotIncoming(
+            friend_public_key.clone(),
+        ))
+    })?;
+
+    // Local relays sent in a move token must be the ones recorded as last sent:
+    if let Some(local_relays) = &opt_local_relays {
+        let is_recorded = match &friend.sent_local_relays {
+            SentLocalRelays::NeverSent => false,
+            SentLocalRelays::Transition((last_sent_local_relays, _))
+            | SentLocalRelays::LastSent(last_sent_local_relays) => {
+                last_sent_local_relays
+                    .iter()
+                    .cloned()
+                    .map(RelayAddress::from)
+                    .collect::<Vec<_>>()
+                    == *local_relays
+            }
+        };
+        if !is_recorded {
+            return Err(SendError::InvariantError(
+                InvariantError::SentLocalRelaysMismatch(friend_public_key.clone()),
+            ));
+        }
+    }
 
-    let tc_incoming = match token_channel.get_direction() {
-        TcDirection::Outgoing(_) => unreachable!(),
-        TcDirection::Incoming(tc_incoming) => tc_incoming,
-    };
+    // The operations were queued one by one. We make sure that none of them was queued twice
+    // before we sign the move token:
+    if validate_operations_list(&operations).is_err() {
+        return Err(SendError::InvariantError(
+            InvariantError::ContradictingOperations(friend_public_key.clone()),
+        ));
+    }
 
+    let rand_nonce = RandValue::new(rng);
     let u_move_token =
         tc_incoming.create_unsigned_move_token(operations, opt_local_relays, rand_nonce);
 
-    let move_token = await!(sign_move_token(u_move_token, identity_client));
+    let move_token =
+        await!(sign_move_token(u_move_token, identity_client)).map_err(SendError::IdentityError)?;
 
     let tc_mutation = TcMutation::SetDirection(SetDirection::Outgoing(move_token));
     let friend_mutation = FriendMutation::TcMutation(tc_mutation);
@@ -845,10 +1112,18 @@ async fn send_move_token<'a, B, R>(
         FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
     m_state.mutate(funder_mutation);
 
+    if token_wanted {
+        let tc_mutation = TcMutation::SetTokenWanted(token_wanted);
+        let friend_mutation = FriendMutation::TcMutation(tc_mutation);
+        let funder_mutation =
+            FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation));
+        m_state.mutate(funder_mutation);
+    }
+
     let friend = m_state.state().friends.get(&friend_public_key).unwrap();
     let token_channel = match &friend.channel_status {
         ChannelStatus::Consistent(token_channel) => token_channel,
-        ChannelStatus::Inconsistent(_) => unreachable!(),
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => unreachable!(),
     };
 
     let tc_outgoing = match token_channel.get_direction() {
@@ -866,6 +1141,7 @@ async fn send_move_token<'a, B, R>(
         friend_public_key.clone(),
         FriendMessage::MoveTokenRequest(move_token_request),
     ));
+    Ok(())
 }
 
 fn init_failure_pending_move_token<B>(
@@ -873,7 +1149,9 @@ fn init_failure_pending_move_token<B>(
     ephemeral: &Ephemeral,
     max_operations_in_batch: usize,
     failure_public_keys: &HashSet<PublicKey>,
+    local_named_relays: &ImVec<NamedRelayAddress<B>>,
     pending_move_tokens: &mut HashMap<PublicKey, PendingMoveToken<B>>,
+    outgoing_channeler_config: &mut Vec<ChannelerConfig<RelayAddress<B>>>,
 ) where
     B: Clone + Eq + CanonicalSerialize + Debug,
 {
@@ -895,7 +1173,7 @@ fn init_failure_pending_move_token<B>(
         // this friend.
         let token_channel = match &friend.channel_status {
             ChannelStatus::Consistent(token_channel) => token_channel,
-            ChannelStatus::Inconsistent(_) => unreachable!(),
+            ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => unreachable!(),
         };
         let tc_incoming = match &token_channel.get_direction() {
             TcDirection::Outgoing(_) => continue,
@@ -904,17 +1182,47 @@ fn init_failure_pending_move_token<B>(
         let outgoing_mc = tc_incoming.begin_outgoing_move_token();
 
         let may_send_empty = false;
-        let pending_move_token = PendingMoveToken::new(
+        let batch_size = ephemeral
+            .adaptive_batch
+            .batch_size(friend_public_key, max_operations_in_batch);
+        let mut pending_move_token = PendingMoveToken::new(
             friend_public_key.clone(),
             outgoing_mc,
-            max_operations_in_batch,
+            batch_size,
             may_send_empty,
         );
+        // Move tokens of the same pass carry the same address:
+        set_pending_local_relays(
+            m_state,
+            outgoing_channeler_config,
+            friend_public_key,
+            &mut pending_move_token,
+            local_named_relays,
+        );
         pending_move_tokens.insert(friend_public_key.clone(), pending_move_token);
     }
 }
 
 /// Send all possible messages according to SendCommands
+/// Create goodbye messages for online friends we are about to disconnect from.
+/// Goodbye messages are not related to the token channel, and are sent regardless of its state.
+pub fn create_goodbye_messages<B>(
+    ephemeral: &Ephemeral,
+    send_commands: &SendCommands,
+) -> Vec<OutgoingMessage<B>> {
+    send_commands
+        .send_commands
+        .iter()
+        .filter(|(friend_public_key, _)| ephemeral.liveness.is_online(friend_public_key))
+        .filter_map(|(friend_public_key, friend_send_commands)| {
+            friend_send_commands
+                .opt_goodbye
+                .clone()
+                .map(|goodbye| (friend_public_key.clone(), FriendMessage::Goodbye(goodbye)))
+        })
+        .collect()
+}
+
 pub async fn create_friend_messages<'a, B, R>(
     m_state: &'a mut MutableFunderState<B>,
     ephemeral: &'a Ephemeral,
@@ -922,11 +1230,14 @@ pub async fn create_friend_messages<'a, B, R>(
     max_operations_in_batch: usize,
     identity_client: &'a mut IdentityClient,
     rng: &'a R,
-) -> (
-    Vec<FunderOutgoingControl<B>>,
-    Vec<OutgoingMessage<B>>,
-    Vec<ChannelerConfig<RelayAddress<B>>>,
-)
+) -> Result<
+    (
+        Vec<FunderOutgoingControl<B>>,
+        Vec<OutgoingMessage<B>>,
+        Vec<ChannelerConfig<RelayAddress<B>>>,
+    ),
+    SendError,
+>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug,
     R: CryptoRandom,
@@ -936,25 +1247,33 @@ where
     let mut outgoing_channeler_config = Vec::new();
     let mut pending_move_tokens: HashMap<PublicKey, PendingMoveToken<B>> = HashMap::new();
 
+    // The local address is decided once for the whole pass:
+    let local_named_relays = m_state.state().relays.clone();
+
     // First iteration:
     let mut failure_public_keys = HashSet::new();
     for (friend_public_key, friend_send_commands) in &send_commands.send_commands {
         if !ephemeral.liveness.is_online(friend_public_key) {
             continue;
         }
+        let batch_size = ephemeral
+            .adaptive_batch
+            .batch_size(friend_public_key, max_operations_in_batch);
         await!(send_friend_iter1(
             m_state,
             friend_public_key,
             friend_send_commands,
             &mut pending_move_tokens,
+            &local_named_relays,
             identity_client,
             rng,
-            max_operations_in_batch,
+            batch_size,
             &mut failure_public_keys,
             &mut outgoing_messages,
             &mut outgoing_control,
             &mut outgoing_channeler_config
-        ));
+        ))
+        .map_err(SendError::IdentityError)?;
     }
 
     // Create PendingMoveToken-s for all the friends that were queued
@@ -964,19 +1283,24 @@ where
         ephemeral,
         max_operations_in_batch,
         &failure_public_keys,
+        &local_named_relays,
         &mut pending_move_tokens,
+        &mut outgoing_channeler_config,
     );
 
     // Second iteration (Attempt to queue failures created in the first iteration):
     for (friend_public_key, pending_move_token) in &mut pending_move_tokens {
         assert!(ephemeral.liveness.is_online(&friend_public_key));
-        let _ = await!(append_failures_to_move_token(
+        match await!(append_failures_to_move_token(
             m_state,
             friend_public_key,
             pending_move_token,
             identity_client,
             rng
-        ));
+        )) {
+            Ok(()) | Err(CollectOutgoingError::MaxOperationsReached) => {}
+            Err(CollectOutgoingError::IdentityError(e)) => return Err(SendError::IdentityError(e)),
+        }
     }
 
     // Send all pending move tokens:
@@ -989,12 +1313,538 @@ where
             identity_client,
             rng,
             &mut outgoing_messages
-        ));
+        ))?;
     }
 
-    (
+    Ok((
         outgoing_control,
         outgoing_messages,
         outgoing_channeler_config,
-    )
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::ThreadPool;
+    use futures::task::Spawn;
+
+    use crypto::identity::{Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+    use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+    use crypto::test_utils::DummyRandom;
+    use crypto::uid::{Uid, UID_LEN};
+    use identity::test_utils::spawn_fixture_identity;
+    use proto::funder::messages::{
+        AddFriend, FriendStatus, FriendsRoute, RequestSendFunds, ResetTerms,
+    };
+
+    use crate::mutual_credit::types::McMutation;
+    use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+
+    const MAX_OPERATIONS_IN_BATCH: usize = 16;
+
+    /// Create a state with one enabled friend, where there is nothing to send to the friend.
+    fn create_state(local_pk: &PublicKey, remote_pk: &PublicKey) -> FunderState<u32> {
+        let relays = vec![dummy_named_relay_address(0)];
+        let mut state = FunderState::<u32>::new(local_pk.clone(), relays);
+
+        let add_friend = AddFriend {
+            friend_public_key: remote_pk.clone(),
+            relays: vec![dummy_relay_address(1)],
+            name: "remote_pk".into(),
+            balance: 0i128,
+        };
+        state.mutate(&FunderMutation::AddFriend(add_friend));
+
+        let friend_mutation = FriendMutation::SetStatus(FriendStatus::Enabled);
+        mutate_friend(&mut state, remote_pk, friend_mutation);
+
+        // The friend already knows our relays:
+        let sent_local_relays = SentLocalRelays::LastSent(state.relays.clone());
+        let friend_mutation = FriendMutation::SetSentLocalRelays(sent_local_relays);
+        mutate_friend(&mut state, remote_pk, friend_mutation);
+        state
+    }
+
+    fn mutate_friend(
+        state: &mut FunderState<u32>,
+        remote_pk: &PublicKey,
+        friend_mutation: FriendMutation<u32>,
+    ) {
+        state.mutate(&FunderMutation::FriendMutation((
+            remote_pk.clone(),
+            friend_mutation,
+        )));
+    }
+
+    fn mutate_mutual_credit(
+        state: &mut FunderState<u32>,
+        remote_pk: &PublicKey,
+        mc_mutation: McMutation,
+    ) {
+        let tc_mutation = TcMutation::McMutation(mc_mutation);
+        mutate_friend(state, remote_pk, FriendMutation::TcMutation(tc_mutation));
+    }
+
+    fn create_request(index: u8, public_keys: Vec<PublicKey>) -> RequestSendFunds {
+        RequestSendFunds {
+            request_id: Uid::from(&[index; UID_LEN]),
+            route: FriendsRoute { public_keys },
+            dest_payment: 10,
+            invoice_id: InvoiceId::from(&[index; INVOICE_ID_LEN]),
+        }
+    }
+
+    /// Pick a friend for which our side of the token channel is incoming, so that we can collect
+    /// move tokens to send to this friend.
+    fn incoming_remote_pk(local_pk: &PublicKey) -> PublicKey {
+        (0..=255u8)
+            .map(|i| PublicKey::from(&[i; PUBLIC_KEY_LEN]))
+            .find(|remote_pk| {
+                let state = create_state(local_pk, remote_pk);
+                match &state.friends.get(remote_pk).unwrap().channel_status {
+                    ChannelStatus::Consistent(token_channel) => !token_channel.is_outgoing(),
+                    _ => unreachable!(),
+                }
+            })
+            .unwrap()
+    }
+
+    /// Create a state in which the friend can handle requests from us.
+    fn create_open_state(local_pk: &PublicKey, remote_pk: &PublicKey) -> FunderState<u32> {
+        let mut state = create_state(local_pk, remote_pk);
+        mutate_mutual_credit(
+            &mut state,
+            remote_pk,
+            McMutation::SetRemoteRequestsStatus(RequestsStatus::Open),
+        );
+        mutate_mutual_credit(&mut state, remote_pk, McMutation::SetLocalMaxDebt(1000));
+        state
+    }
+
+    /// Collect an outgoing move token for the friend (Whose token channel must be incoming).
+    /// Returns the state after collecting, the collected move token and the result of collecting.
+    async fn collect_move_token<'a>(
+        state: &'a FunderState<u32>,
+        remote_pk: &'a PublicKey,
+        identity_client: &'a mut IdentityClient,
+        max_operations_in_batch: usize,
+    ) -> (
+        FunderState<u32>,
+        PendingMoveToken<u32>,
+        Result<(), CollectOutgoingError>,
+    ) {
+        let friend = state.friends.get(remote_pk).unwrap();
+        let outgoing_mc = match &friend.channel_status {
+            ChannelStatus::Consistent(token_channel) => match token_channel.get_direction() {
+                TcDirection::Incoming(tc_incoming) => tc_incoming.begin_outgoing_move_token(),
+                TcDirection::Outgoing(_) => unreachable!(),
+            },
+            ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => unreachable!(),
+        };
+        let mut pending_move_token = PendingMoveToken::new(
+            remote_pk.clone(),
+            outgoing_mc,
+            max_operations_in_batch,
+            false,
+        );
+
+        let mut m_state = MutableFunderState::new(state.clone());
+        let rng = DummyRandom::new(&[1u8]);
+        let res = await!(collect_outgoing_move_token(
+            &mut m_state,
+            &mut Vec::new(),
+            &mut Vec::new(),
+            &mut HashSet::new(),
+            remote_pk,
+            &mut pending_move_token,
+            &state.relays,
+            identity_client,
+            &rng
+        ));
+        let (_initial_state, _mutations, final_state) = m_state.done();
+        (final_state, pending_move_token, res)
+    }
+
+    /// Collect an outgoing move token for the friend, and check if it has anything in it.
+    async fn collect_is_nonempty<'a>(
+        state: &'a FunderState<u32>,
+        remote_pk: &'a PublicKey,
+        identity_client: &'a mut IdentityClient,
+    ) -> bool {
+        let (_state, pending_move_token, res) = await!(collect_move_token(
+            state,
+            remote_pk,
+            identity_client,
+            MAX_OPERATIONS_IN_BATCH
+        ));
+        res.unwrap();
+        !pending_move_token.operations.is_empty() || pending_move_token.opt_local_relays.is_some()
+    }
+
+    async fn task_estimate_should_send<S>(mut spawner: S)
+    where
+        S: Spawn,
+    {
+        let (mut identity_client, local_pk) = spawn_fixture_identity(0, &mut spawner);
+
+        // We compare the estimation against a move token we actually collect:
+        let remote_pk = incoming_remote_pk(&local_pk);
+        let base_state = create_state(&local_pk, &remote_pk);
+
+        // (state, should_send):
+        let mut cases = vec![(base_state.clone(), false)];
+
+        // We have never sent our relays to the friend:
+        let mut state = base_state.clone();
+        mutate_friend(
+            &mut state,
+            &remote_pk,
+            FriendMutation::SetSentLocalRelays(SentLocalRelays::NeverSent),
+        );
+        cases.push((state, true));
+
+        // Our relays have changed since we last sent them:
+        let mut state = base_state.clone();
+        state.mutate(&FunderMutation::AddRelay(dummy_named_relay_address(2)));
+        cases.push((state, true));
+
+        // We want a different remote max debt:
+        let mut state = base_state.clone();
+        mutate_friend(
+            &mut state,
+            &remote_pk,
+            FriendMutation::SetWantedRemoteMaxDebt(100),
+        );
+        cases.push((state, true));
+
+        // We want to open requests:
+        let mut state = base_state.clone();
+        mutate_friend(
+            &mut state,
+            &remote_pk,
+            FriendMutation::SetWantedLocalRequestsStatus(RequestsStatus::Open),
+        );
+        cases.push((state, true));
+
+        // The friend can handle requests from us, but we have no requests:
+        let open_state = create_open_state(&local_pk, &remote_pk);
+        cases.push((open_state.clone(), false));
+
+        // We have a response to a request the friend sent us:
+        let mut state = base_state.clone();
+        let request = create_request(0, vec![remote_pk.clone(), local_pk.clone()]);
+        let pending_request = create_pending_request(&request);
+        mutate_mutual_credit(
+            &mut state,
+            &remote_pk,
+            McMutation::InsertRemotePendingRequest(pending_request.clone()),
+        );
+        mutate_mutual_credit(
+            &mut state,
+            &remote_pk,
+            McMutation::SetRemotePendingDebt(1000),
+        );
+        mutate_friend(
+            &mut state,
+            &remote_pk,
+            FriendMutation::PushBackPendingResponse(ResponseOp::UnsignedResponse(pending_request)),
+        );
+        cases.push((state, true));
+
+        // We have a request to forward to the friend:
+        let mut state = open_state.clone();
+        let third_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let request = create_request(1, vec![third_pk, local_pk.clone(), remote_pk.clone()]);
+        mutate_friend(
+            &mut state,
+            &remote_pk,
+            FriendMutation::PushBackPendingRequest(request),
+        );
+        cases.push((state, true));
+
+        // We have a request of our own to send to the friend:
+        let mut state = open_state.clone();
+        let request = create_request(2, vec![local_pk.clone(), remote_pk.clone()]);
+        mutate_friend(
+            &mut state,
+            &remote_pk,
+            FriendMutation::PushBackPendingUserRequest(request),
+        );
+        cases.push((state.clone(), true));
+
+        for (state, should_send) in cases {
+            assert_eq!(estimate_should_send(&state, &remote_pk), should_send);
+            assert_eq!(
+                await!(collect_is_nonempty(
+                    &state,
+                    &remote_pk,
+                    &mut identity_client
+                )),
+                should_send
+            );
+        }
+
+        // Nothing is sent through an inconsistent channel, even if there are pending requests:
+        let channel_inconsistent = ChannelInconsistent {
+            opt_last_incoming_move_token: None,
+            local_reset_terms: ResetTerms {
+                reset_token: Signature::from(&[0; SIGNATURE_LEN]),
+                inconsistency_counter: 1,
+                balance_for_reset: 0,
+            },
+            opt_remote_reset_terms: None,
+        };
+        mutate_friend(
+            &mut state,
+            &remote_pk,
+            FriendMutation::SetInconsistent(channel_inconsistent),
+        );
+        assert!(!estimate_should_send(&state, &remote_pk));
+    }
+
+    #[test]
+    fn test_estimate_should_send() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_estimate_should_send(thread_pool.clone()));
+    }
+
+    async fn task_collect_outgoing_move_token_batch_limit<S>(mut spawner: S)
+    where
+        S: Spawn,
+    {
+        let (mut identity_client, local_pk) = spawn_fixture_identity(0, &mut spawner);
+        let remote_pk = incoming_remote_pk(&local_pk);
+        let mut state = create_open_state(&local_pk, &remote_pk);
+
+        // One forwarded request and three requests of our own:
+        let third_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let request = create_request(0, vec![third_pk, local_pk.clone(), remote_pk.clone()]);
+        mutate_friend(
+            &mut state,
+            &remote_pk,
+            FriendMutation::PushBackPendingRequest(request),
+        );
+        for index in 1..4 {
+            let request = create_request(index, vec![local_pk.clone(), remote_pk.clone()]);
+            mutate_friend(
+                &mut state,
+                &remote_pk,
+                FriendMutation::PushBackPendingUserRequest(request),
+            );
+        }
+
+        // The batch limit is hit in the middle of the user requests queue:
+        let (state, pending_move_token, res) = await!(collect_move_token(
+            &state,
+            &remote_pk,
+            &mut identity_client,
+            3
+        ));
+        match res {
+            Err(CollectOutgoingError::MaxOperationsReached) => {}
+            _ => unreachable!(),
+        };
+        assert_eq!(pending_move_token.operations.len(), 3);
+        // We want the token back, to send the rest of the requests:
+        assert!(pending_move_token.token_wanted);
+
+        // The remaining request stays queued:
+        let friend = state.friends.get(&remote_pk).unwrap();
+        assert!(friend.pending_requests.is_empty());
+        assert_eq!(friend.pending_user_requests.len(), 1);
+        assert_eq!(
+            friend.pending_user_requests[0].request_id,
+            Uid::from(&[3; UID_LEN])
+        );
+        assert!(estimate_should_send(&state, &remote_pk));
+
+        // The remaining request is sent with the next move token:
+        let (state, pending_move_token, res) = await!(collect_move_token(
+            &state,
+            &remote_pk,
+            &mut identity_client,
+            3
+        ));
+        res.unwrap();
+        assert_eq!(pending_move_token.operations.len(), 1);
+        let friend = state.friends.get(&remote_pk).unwrap();
+        assert!(friend.pending_user_requests.is_empty());
+    }
+
+    #[test]
+    fn test_collect_outgoing_move_token_batch_limit() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_collect_outgoing_move_token_batch_limit(
+            thread_pool.clone(),
+        ));
+    }
+
+    /// Queue a response to a request the friend sent us.
+    fn push_pending_response(
+        state: &mut FunderState<u32>,
+        local_pk: &PublicKey,
+        remote_pk: &PublicKey,
+        index: u8,
+    ) {
+        let request = create_request(index, vec![remote_pk.clone(), local_pk.clone()]);
+        let pending_request = create_pending_request(&request);
+        mutate_mutual_credit(
+            state,
+            remote_pk,
+            McMutation::InsertRemotePendingRequest(pending_request.clone()),
+        );
+        let response_op = ResponseOp::UnsignedResponse(pending_request);
+        mutate_friend(
+            state,
+            remote_pk,
+            FriendMutation::PushBackPendingResponse(response_op),
+        );
+    }
+
+    async fn task_collect_outgoing_move_token_no_duplicates<S>(mut spawner: S)
+    where
+        S: Spawn,
+    {
+        let (mut identity_client, local_pk) = spawn_fixture_identity(0, &mut spawner);
+        let remote_pk = incoming_remote_pk(&local_pk);
+        let mut state = create_open_state(&local_pk, &remote_pk);
+        mutate_mutual_credit(
+            &mut state,
+            &remote_pk,
+            McMutation::SetRemotePendingDebt(1000),
+        );
+
+        // Three responses and four requests of our own:
+        for index in 0..3 {
+            push_pending_response(&mut state, &local_pk, &remote_pk, index);
+        }
+        for index in 3..7 {
+            let request = create_request(index, vec![local_pk.clone(), remote_pk.clone()]);
+            mutate_friend(
+                &mut state,
+                &remote_pk,
+                FriendMutation::PushBackPendingUserRequest(request),
+            );
+        }
+
+        // Queueing fails in the middle of every batch, after some of the queued operations were
+        // already popped:
+        let mut all_operations = Vec::new();
+        for _ in 0..3 {
+            let (new_state, pending_move_token, res) = await!(collect_move_token(
+                &state,
+                &remote_pk,
+                &mut identity_client,
+                2
+            ));
+            match res {
+                Err(CollectOutgoingError::MaxOperationsReached) => {}
+                _ => unreachable!(),
+            };
+            assert_eq!(pending_move_token.operations.len(), 2);
+            assert!(validate_operations_list(&pending_move_token.operations).is_ok());
+
+            // The collected move token can be signed:
+            let mut m_state = MutableFunderState::new(new_state.clone());
+            let rng = DummyRandom::new(&[2u8]);
+            all_operations.extend(pending_move_token.operations.iter().cloned());
+            await!(send_move_token(
+                &mut m_state,
+                remote_pk.clone(),
+                pending_move_token,
+                &mut identity_client,
+                &rng,
+                &mut Vec::new()
+            ))
+            .unwrap();
+            state = new_state;
+        }
+
+        // The last operation:
+        let (state, pending_move_token, res) = await!(collect_move_token(
+            &state,
+            &remote_pk,
+            &mut identity_client,
+            2
+        ));
+        res.unwrap();
+        assert_eq!(pending_move_token.operations.len(), 1);
+        all_operations.extend(pending_move_token.operations);
+
+        // Every queued operation was collected exactly once:
+        assert_eq!(all_operations.len(), 7);
+        assert!(validate_operations_list(&all_operations).is_ok());
+        let friend = state.friends.get(&remote_pk).unwrap();
+        assert!(friend.pending_responses.is_empty());
+        assert!(friend.pending_user_requests.is_empty());
+    }
+
+    #[test]
+    fn test_collect_outgoing_move_token_no_duplicates() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_collect_outgoing_move_token_no_duplicates(
+            thread_pool.clone(),
+        ));
+    }
+
+    async fn task_send_move_token_duplicate_operation<S>(mut spawner: S)
+    where
+        S: Spawn,
+    {
+        let (mut identity_client, local_pk) = spawn_fixture_identity(0, &mut spawner);
+        let remote_pk = incoming_remote_pk(&local_pk);
+        let mut state = create_open_state(&local_pk, &remote_pk);
+        mutate_mutual_credit(
+            &mut state,
+            &remote_pk,
+            McMutation::SetRemotePendingDebt(1000),
+        );
+        push_pending_response(&mut state, &local_pk, &remote_pk, 0);
+
+        let (state, mut pending_move_token, res) = await!(collect_move_token(
+            &state,
+            &remote_pk,
+            &mut identity_client,
+            MAX_OPERATIONS_IN_BATCH
+        ));
+        res.unwrap();
+        assert_eq!(pending_move_token.operations.len(), 1);
+
+        // The same response was queued twice:
+        let operation = pending_move_token.operations[0].clone();
+        pending_move_token.operations.push(operation);
+
+        let mut m_state = MutableFunderState::new(state);
+        let rng = DummyRandom::new(&[2u8]);
+        let mut outgoing_messages = Vec::new();
+        let res = await!(send_move_token(
+            &mut m_state,
+            remote_pk.clone(),
+            pending_move_token,
+            &mut identity_client,
+            &rng,
+            &mut outgoing_messages
+        ));
+        match res {
+            Err(SendError::InvariantError(InvariantError::ContradictingOperations(public_key))) => {
+                assert_eq!(public_key, remote_pk)
+            }
+            _ => unreachable!(),
+        };
+
+        // Nothing was signed or sent:
+        assert!(outgoing_messages.is_empty());
+        let (_initial_state, mutations, _final_state) = m_state.done();
+        assert!(mutations.is_empty());
+    }
+
+    #[test]
+    fn test_send_move_token_duplicate_operation() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_send_move_token_duplicate_operation(
+            thread_pool.clone(),
+        ));
+    }
 }
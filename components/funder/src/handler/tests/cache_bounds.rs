@@ -0,0 +1,156 @@
+use super::utils::{apply_control_and_deliver, create_net, deliver_all, TestNet};
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use common::bounded_cache::CacheLimits;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    FriendsRoute, FunderControl, FunderOutgoingControl, ReceiptAck, ResponseSendFundsResult,
+    UserRequestSendFunds,
+};
+
+use crate::ephemeral::EphemeralLimits;
+use crate::types::FunderIncoming;
+
+/// Amount of payments sent in the test. Every node originates half of them.
+const NUM_PAYMENTS: usize = 0x100;
+
+/// A timer tick is sent to the nodes once every this amount of payments
+const PAYMENTS_PER_TICK: usize = 4;
+
+const TEST_EPHEMERAL_LIMITS: EphemeralLimits = EphemeralLimits {
+    completed: CacheLimits {
+        max_entries: 8,
+        max_age_ticks: 0x20,
+    },
+    payment_timings: CacheLimits {
+        max_entries: 4,
+        max_age_ticks: 0x20,
+    },
+};
+
+/// Send a timer tick to all the nodes.
+async fn tick_nodes<'a>(net: &'a mut TestNet, rng: &'a mut RngContainer<DummyRandom>) {
+    let incoming = (0..net.nodes.len())
+        .map(|index| (index, FunderIncoming::TimerTick(1)))
+        .collect();
+    await!(deliver_all(net, rng, incoming));
+}
+
+/// Total amount of entries the caches of a node may hold. Every node has a single friend.
+fn max_cache_len() -> usize {
+    TEST_EPHEMERAL_LIMITS.completed.max_entries + TEST_EPHEMERAL_LIMITS.payment_timings.max_entries
+}
+
+async fn task_handler_cache_bounds(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    // Two friends that trust each other:
+    let mut net = await!(create_net(
+        identity_clients,
+        &TEST_EPHEMERAL_LIMITS,
+        &[(0, 1), (1, 0)],
+        100,
+        &mut rng
+    ));
+
+    // The nodes keep paying each other:
+    for i in 0..NUM_PAYMENTS {
+        let (payer, payee) = if i % 2 == 0 { (0, 1) } else { (1, 0) };
+
+        let mut request_id = Uid::from(&[0; UID_LEN]);
+        request_id[0] = (i & 0xff) as u8;
+        request_id[1] = (i >> 8) as u8;
+        let user_request_send_funds = UserRequestSendFunds {
+            request_id,
+            route: FriendsRoute {
+                public_keys: vec![
+                    net.nodes[payer].public_key.clone(),
+                    net.nodes[payee].public_key.clone(),
+                ],
+            },
+            invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+            dest_payment: 1,
+            opt_label: None,
+        };
+        let controls = await!(apply_control_and_deliver(
+            &mut net,
+            &mut rng,
+            payer,
+            10,
+            FunderControl::RequestSendFunds(user_request_send_funds)
+        ));
+
+        let receipt = controls
+            .into_iter()
+            .filter_map(|(index, control)| match control {
+                FunderOutgoingControl::ResponseReceived(response_received) if index == payer => {
+                    match response_received.result {
+                        ResponseSendFundsResult::Success(receipt) => Some(receipt),
+                        ResponseSendFundsResult::Failure(_) => unreachable!(),
+                    }
+                }
+                _ => None,
+            })
+            .next()
+            .unwrap();
+
+        let receipt_ack = ReceiptAck {
+            request_id,
+            receipt_signature: receipt.signature,
+        };
+        await!(apply_control_and_deliver(
+            &mut net,
+            &mut rng,
+            payer,
+            10,
+            FunderControl::ReceiptAck(receipt_ack)
+        ));
+
+        if i % PAYMENTS_PER_TICK == 0 {
+            await!(tick_nodes(&mut net, &mut rng));
+        }
+
+        for node in &net.nodes {
+            assert!(node.ephemeral.metrics().total_len() <= max_cache_len());
+        }
+    }
+
+    // Old completed requests were evicted to make room for new ones:
+    for node in &net.nodes {
+        let metrics = node.ephemeral.metrics();
+        assert_eq!(
+            metrics.completed.len,
+            TEST_EPHEMERAL_LIMITS.completed.max_entries
+        );
+        assert!(metrics.completed.evicted_by_count > 0);
+        // All the payments were answered:
+        assert_eq!(metrics.payment_timings.len, 0);
+    }
+
+    // When the nodes are idle, all the cached entries are eventually evicted:
+    for _ in 0..TEST_EPHEMERAL_LIMITS.completed.max_age_ticks {
+        await!(tick_nodes(&mut net, &mut rng));
+    }
+    for node in &net.nodes {
+        assert_eq!(node.ephemeral.metrics().total_len(), 0);
+    }
+}
+
+#[test]
+fn test_handler_cache_bounds() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=2u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_cache_bounds(identity_clients));
+}
@@ -0,0 +1,184 @@
+use super::utils::{
+    apply_control_and_deliver, control_message, create_chain_net, deliver_all, is_success,
+    request_send_funds, responses_received, unmute_and_deliver, TestNet,
+};
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    CancelUserRequestResult, FailureReason, FunderControl, FunderOutgoingControl,
+    ResponseSendFundsResult, SetFriendRemoteMaxDebt,
+};
+
+use crate::friend::ChannelStatus;
+use crate::token_channel::TcDirection;
+use crate::types::FunderIncoming;
+
+/// Amount of nodes in the test network. Node i is a friend of node i + 1.
+const NUM_NODES: usize = 2;
+
+/// The maximum debt node1 allows node0.
+const MAX_DEBT: u128 = 100;
+
+/// Find the result of a request to cancel a user request
+fn cancel_result(
+    controls: &[(usize, FunderOutgoingControl<u32>)],
+    request_id: &Uid,
+) -> Option<CancelUserRequestResult> {
+    controls.iter().find_map(|(_index, control)| match control {
+        FunderOutgoingControl::ResponseCancelUserRequest(response_cancel)
+            if &response_cancel.request_id == request_id =>
+        {
+            Some(response_cancel.result)
+        }
+        _ => None,
+    })
+}
+
+/// Does node0 hold the token of the channel with node1?
+fn node0_holds_token(net: &TestNet) -> bool {
+    let friend = net.nodes[0]
+        .state
+        .friends
+        .get(&net.nodes[1].public_key)
+        .unwrap();
+    match &friend.channel_status {
+        ChannelStatus::Consistent(token_channel) => match token_channel.get_direction() {
+            TcDirection::Incoming(_) => true,
+            TcDirection::Outgoing(_) => false,
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// Is a request still waiting at node0 to be sent to node1?
+fn is_queued(net: &TestNet, request_id: &Uid) -> bool {
+    net.nodes[0]
+        .state
+        .friends
+        .get(&net.nodes[1].public_key)
+        .unwrap()
+        .pending_user_requests
+        .iter()
+        .any(|request| &request.request_id == request_id)
+}
+
+fn cancel_user_request(uid_index: u8) -> FunderIncoming<u32> {
+    control_message(
+        uid_index,
+        FunderControl::CancelUserRequest(Uid::from(&[uid_index; UID_LEN])),
+    )
+}
+
+async fn task_handler_cancel_user_request(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut net = await!(create_chain_net(identity_clients, MAX_DEBT, &mut rng));
+
+    // A request that does not exist:
+    let incoming = vec![(0, cancel_user_request(29))];
+    let controls = await!(deliver_all(&mut net, &mut rng, incoming));
+    assert_eq!(
+        cancel_result(&controls, &Uid::from(&[29; UID_LEN])),
+        Some(CancelUserRequestResult::NotFound)
+    );
+    assert!(responses_received(&controls, 0).is_empty());
+
+    // node1 sends a configuration change to node0. At the end node0 holds the token:
+    let set_friend_remote_max_debt = SetFriendRemoteMaxDebt {
+        friend_public_key: net.nodes[0].public_key.clone(),
+        remote_max_debt: MAX_DEBT + 1,
+        opt_expiry: None,
+    };
+    await!(apply_control_and_deliver(
+        &mut net,
+        &mut rng,
+        1,
+        22,
+        FunderControl::SetFriendRemoteMaxDebt(set_friend_remote_max_debt)
+    ));
+    assert!(node0_holds_token(&net));
+
+    // node0 holds the token, so a request is sent to node1 as soon as it is submitted.
+    // Cancelling right after submitting is too late:
+    net.opt_muted = Some(1);
+    let incoming = vec![
+        (0, request_send_funds(&net, &[0, 1], 30, 10)),
+        (0, cancel_user_request(30)),
+    ];
+    let controls = await!(deliver_all(&mut net, &mut rng, incoming));
+    assert_eq!(
+        cancel_result(&controls, &Uid::from(&[30; UID_LEN])),
+        Some(CancelUserRequestResult::InFlight)
+    );
+    assert!(responses_received(&controls, 0).is_empty());
+    assert!(!net.held.is_empty());
+
+    // The request in flight is answered as usual:
+    let controls = await!(unmute_and_deliver(&mut net, &mut rng));
+    let responses = responses_received(&controls, 0);
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].request_id, Uid::from(&[30; UID_LEN]));
+    assert!(is_success(&responses[0]));
+
+    // The answered request can not be cancelled anymore:
+    let incoming = vec![(0, cancel_user_request(30))];
+    let controls = await!(deliver_all(&mut net, &mut rng, incoming));
+    assert_eq!(
+        cancel_result(&controls, &Uid::from(&[30; UID_LEN])),
+        Some(CancelUserRequestResult::NotFound)
+    );
+
+    // The second request waits at node0 until node0 gets the token back:
+    net.opt_muted = Some(1);
+    let incoming = vec![
+        (0, request_send_funds(&net, &[0, 1], 31, 10)),
+        (0, request_send_funds(&net, &[0, 1], 32, 10)),
+    ];
+    let controls = await!(deliver_all(&mut net, &mut rng, incoming));
+    assert!(responses_received(&controls, 0).is_empty());
+    assert!(is_queued(&net, &Uid::from(&[32; UID_LEN])));
+
+    // The queued request is withdrawn, and answered with a failure:
+    let incoming = vec![(0, cancel_user_request(32))];
+    let controls = await!(deliver_all(&mut net, &mut rng, incoming));
+    assert_eq!(
+        cancel_result(&controls, &Uid::from(&[32; UID_LEN])),
+        Some(CancelUserRequestResult::Cancelled)
+    );
+    let responses = responses_received(&controls, 0);
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].request_id, Uid::from(&[32; UID_LEN]));
+    assert_eq!(
+        responses[0].result,
+        ResponseSendFundsResult::Failure((
+            net.nodes[0].public_key.clone(),
+            FailureReason::Cancelled,
+        ))
+    );
+    assert!(!is_queued(&net, &Uid::from(&[32; UID_LEN])));
+
+    // Only the first request reaches node1:
+    let controls = await!(unmute_and_deliver(&mut net, &mut rng));
+    let responses = responses_received(&controls, 0);
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].request_id, Uid::from(&[31; UID_LEN]));
+    assert!(is_success(&responses[0]));
+}
+
+#[test]
+fn test_handler_cancel_user_request() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=NUM_NODES as u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_cancel_user_request(identity_clients));
+}
@@ -3,21 +3,22 @@ use super::utils::apply_funder_incoming;
 use std::cmp::Ordering;
 
 use futures::executor::ThreadPool;
-use futures::task::SpawnExt;
-use futures::{future, FutureExt};
 
-use identity::{create_identity, IdentityClient};
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
 
 use crypto::crypto_rand::RngContainer;
-use crypto::identity::{compare_public_key, generate_pkcs8_key_pair, SoftwareEd25519Identity};
+use crypto::identity::{compare_public_key, PublicKey};
 use crypto::test_utils::DummyRandom;
 use crypto::uid::{Uid, UID_LEN};
 
+use proto::app_server::messages::RelayAddress;
 use proto::funder::messages::{
     AddFriend, FriendMessage, FriendStatus, FunderControl, FunderIncomingControl, SetFriendStatus,
 };
 
 use crate::ephemeral::Ephemeral;
+use crate::friend::SentLocalRelays;
 use crate::state::FunderState;
 use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
 use crate::types::{
@@ -25,6 +26,20 @@ use crate::types::{
     IncomingLivenessMessage,
 };
 
+/// The local relays last sent to a friend, while waiting for the friend to acknowledge them.
+fn last_sent_relays(
+    state: &FunderState<u32>,
+    friend_public_key: &PublicKey,
+) -> Vec<RelayAddress<u32>> {
+    let friend = state.friends.get(friend_public_key).unwrap();
+    match &friend.sent_local_relays {
+        SentLocalRelays::Transition((last_sent, _)) => {
+            last_sent.iter().cloned().map(RelayAddress::from).collect()
+        }
+        SentLocalRelays::NeverSent | SentLocalRelays::LastSent(_) => unreachable!(),
+    }
+}
+
 async fn task_handler_change_address(
     identity_client1: IdentityClient,
     identity_client2: IdentityClient,
@@ -392,8 +407,21 @@ async fn task_handler_change_address(
     )))
     .unwrap();
 
-    assert_eq!(outgoing_comms.len(), 1);
-    let friend_message = match &outgoing_comms[0] {
+    // Node2 applies the new address of Node1, and notifies the Channeler:
+    assert_eq!(outgoing_comms.len(), 2);
+    match &outgoing_comms[0] {
+        FunderOutgoingComm::ChannelerConfig(ChannelerConfig::UpdateFriend(update_friend)) => {
+            assert_eq!(update_friend.friend_public_key, pk1);
+            assert_eq!(
+                update_friend.friend_relays,
+                vec![dummy_relay_address(1), dummy_relay_address(11)]
+            );
+            assert_eq!(update_friend.local_relays, vec![dummy_relay_address(2)]);
+        }
+        _ => unreachable!(),
+    };
+
+    let friend_message = match &outgoing_comms[1] {
         FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
             if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
                 assert_eq!(pk, &pk1);
@@ -436,29 +464,159 @@ async fn task_handler_change_address(
         }
         _ => unreachable!(),
     };
+
+    // Node1 changes his address again:
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[16; UID_LEN]),
+        FunderControl::AddRelay(dummy_named_relay_address(12)),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        &mut identity_client1
+    )))
+    .unwrap();
+
+    let relays_1_11_12 = vec![
+        dummy_relay_address(1),
+        dummy_relay_address(11),
+        dummy_relay_address(12),
+    ];
+    assert_eq!(outgoing_comms.len(), 3);
+    let friend_message = match &outgoing_comms[2] {
+        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+            if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
+                assert_eq!(pk, &pk2);
+                let friend_move_token = &move_token_request.friend_move_token;
+                assert_eq!(friend_move_token.move_token_counter, 6);
+                assert_eq!(
+                    friend_move_token.opt_local_relays,
+                    Some(relays_1_11_12.clone())
+                );
+            } else {
+                unreachable!();
+            }
+            friend_message.clone()
+        }
+        _ => unreachable!(),
+    };
+
+    // Node1 changes his address while the move token with the previous change is still
+    // pending, before Node2 has answered:
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[17; UID_LEN]),
+        FunderControl::RemoveRelay(dummy_named_relay_address(11).public_key),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        &mut identity_client1
+    )))
+    .unwrap();
+
+    // The pending move token is resent as is, carrying the address from before the change:
+    assert_eq!(outgoing_comms.len(), 2);
+    match &outgoing_comms[0] {
+        FunderOutgoingComm::ChannelerConfig(ChannelerConfig::SetRelays(relays)) => {
+            assert_eq!(
+                relays,
+                &vec![dummy_relay_address(1), dummy_relay_address(12)]
+            );
+        }
+        _ => unreachable!(),
+    };
+    match &outgoing_comms[1] {
+        FunderOutgoingComm::FriendMessage((pk, resent_friend_message)) => {
+            assert_eq!(pk, &pk2);
+            assert_eq!(resent_friend_message, &friend_message);
+        }
+        _ => unreachable!(),
+    };
+    assert_eq!(last_sent_relays(&state1, &pk2), relays_1_11_12);
+
+    // Node2: Receive friend_message from Node1:
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Friend((pk1.clone(), friend_message)));
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        &mut identity_client2
+    )))
+    .unwrap();
+
+    let friend_message = match outgoing_comms.last().unwrap() {
+        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+            assert_eq!(pk, &pk1);
+            friend_message.clone()
+        }
+        _ => unreachable!(),
+    };
+
+    // Node1: Receive friend_message from Node2.
+    // Node2 knows about the address from before the change. The new address is sent in the next
+    // move token:
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Friend((pk2.clone(), friend_message)));
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        &mut identity_client1
+    )))
+    .unwrap();
+
+    assert_eq!(outgoing_comms.len(), 3);
+    match &outgoing_comms[0] {
+        FunderOutgoingComm::ChannelerConfig(ChannelerConfig::UpdateFriend(update_friend)) => {
+            assert_eq!(update_friend.local_relays, relays_1_11_12);
+        }
+        _ => unreachable!(),
+    };
+    match &outgoing_comms[1] {
+        FunderOutgoingComm::ChannelerConfig(ChannelerConfig::UpdateFriend(update_friend)) => {
+            // Node2 may still use the previous address until he receives the new one:
+            assert_eq!(update_friend.local_relays, relays_1_11_12);
+        }
+        _ => unreachable!(),
+    };
+    match &outgoing_comms[2] {
+        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+            if let FriendMessage::MoveTokenRequest(move_token_request) = friend_message {
+                assert_eq!(pk, &pk2);
+                assert_eq!(move_token_request.token_wanted, true);
+                let friend_move_token = &move_token_request.friend_move_token;
+                assert_eq!(friend_move_token.move_token_counter, 8);
+                assert_eq!(
+                    friend_move_token.opt_local_relays,
+                    Some(vec![dummy_relay_address(1), dummy_relay_address(12)])
+                );
+            } else {
+                unreachable!();
+            }
+        }
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        last_sent_relays(&state1, &pk2),
+        vec![dummy_relay_address(1), dummy_relay_address(12)]
+    );
 }
 
 #[test]
 fn test_handler_change_address() {
     let mut thread_pool = ThreadPool::new().unwrap();
 
-    let rng1 = DummyRandom::new(&[1u8]);
-    let pkcs8 = generate_pkcs8_key_pair(&rng1);
-    let identity1 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-    let (requests_sender1, identity_server1) = create_identity(identity1);
-    let identity_client1 = IdentityClient::new(requests_sender1);
-    thread_pool
-        .spawn(identity_server1.then(|_| future::ready(())))
-        .unwrap();
-
-    let rng2 = DummyRandom::new(&[2u8]);
-    let pkcs8 = generate_pkcs8_key_pair(&rng2);
-    let identity2 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-    let (requests_sender2, identity_server2) = create_identity(identity2);
-    let identity_client2 = IdentityClient::new(requests_sender2);
-    thread_pool
-        .spawn(identity_server2.then(|_| future::ready(())))
-        .unwrap();
+    let (identity_client1, _) = spawn_fixture_identity(1, &mut thread_pool);
+    let (identity_client2, _) = spawn_fixture_identity(2, &mut thread_pool);
 
     thread_pool.run(task_handler_change_address(
         identity_client1,
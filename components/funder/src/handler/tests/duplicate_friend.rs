@@ -0,0 +1,234 @@
+use super::utils::apply_funder_incoming;
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    AddFriend, FriendStatus, FunderControl, FunderIncomingControl, FunderOutgoingControl,
+    SetFriendRelays, SetFriendStatus,
+};
+use proto::report::messages::{FriendReportMutation, FunderReportMutation};
+
+use crate::ephemeral::Ephemeral;
+use crate::friend::ChannelStatus;
+use crate::state::FunderState;
+use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+use crate::types::{ChannelerConfig, FunderIncoming, FunderOutgoingComm};
+
+/// Collect the report mutations acknowledging the app request `app_request_id`.
+/// Returns None if the request was not acknowledged.
+fn acked_report_mutations(
+    outgoing_control: &[FunderOutgoingControl<u32>],
+    app_request_id: &Uid,
+) -> Option<Vec<FunderReportMutation<u32>>> {
+    outgoing_control.iter().find_map(|control| match control {
+        FunderOutgoingControl::ReportMutations(report_mutations)
+            if report_mutations.opt_app_request_id.as_ref() == Some(app_request_id) =>
+        {
+            Some(report_mutations.mutations.clone())
+        }
+        _ => None,
+    })
+}
+
+fn friend_balance(state: &FunderState<u32>, friend_public_key: &PublicKey) -> i128 {
+    let friend = state.friends.get(friend_public_key).unwrap();
+    match &friend.channel_status {
+        ChannelStatus::Consistent(token_channel) => {
+            token_channel.get_mutual_credit().state().balance.balance
+        }
+        _ => unreachable!(),
+    }
+}
+
+async fn task_handler_duplicate_friend<'a>(identity_client: &'a mut IdentityClient) {
+    let local_pk = await!(identity_client.request_public_key()).unwrap();
+    let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+    let relays = vec![dummy_named_relay_address(0)];
+    let mut state = FunderState::<u32>::new(local_pk, relays);
+    let mut ephemeral = Ephemeral::new();
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+
+    let funder_incoming = FunderIncoming::Init;
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    )))
+    .unwrap();
+
+    // Add pk_a as a friend, and enable it:
+    let add_friend = AddFriend {
+        friend_public_key: pk_a.clone(),
+        relays: vec![dummy_relay_address(1)],
+        name: "friend_a".to_owned(),
+        balance: 20,
+    };
+    let set_friend_status = SetFriendStatus {
+        friend_public_key: pk_a.clone(),
+        status: FriendStatus::Enabled,
+    };
+    let funder_controls = vec![
+        FunderControl::AddFriend(add_friend),
+        FunderControl::SetFriendStatus(set_friend_status),
+    ];
+    for (index, funder_control) in funder_controls.into_iter().enumerate() {
+        let incoming_control_message =
+            FunderIncomingControl::new(Uid::from(&[index as u8; UID_LEN]), funder_control);
+        let funder_incoming = FunderIncoming::Control(incoming_control_message);
+        await!(Box::pin(apply_funder_incoming(
+            funder_incoming,
+            &mut state,
+            &mut ephemeral,
+            &mut rng,
+            identity_client
+        )))
+        .unwrap();
+    }
+
+    // pk_a is added again, from a different invite:
+    let add_friend = AddFriend {
+        friend_public_key: pk_a.clone(),
+        relays: vec![dummy_relay_address(2)],
+        name: "friend_a_again".to_owned(),
+        balance: -5,
+    };
+    let app_request_id = Uid::from(&[0x10; UID_LEN]);
+    let incoming_control_message =
+        FunderIncomingControl::new(app_request_id.clone(), FunderControl::AddFriend(add_friend));
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    )))
+    .unwrap();
+
+    // The request is acknowledged, but nothing changes:
+    assert!(outgoing_comms.is_empty());
+    let report_mutations = acked_report_mutations(&outgoing_control, &app_request_id).unwrap();
+    assert!(report_mutations.is_empty());
+    assert_eq!(state.friends.len(), 1);
+    let friend_a = state.friends.get(&pk_a).unwrap();
+    assert_eq!(friend_a.name, "friend_a");
+    assert_eq!(friend_a.remote_relays, vec![dummy_relay_address(1)]);
+    assert_eq!(friend_balance(&state, &pk_a), 20);
+
+    // Update the address of pk_a:
+    let set_friend_relays = SetFriendRelays {
+        friend_public_key: pk_a.clone(),
+        relays: vec![dummy_relay_address(2)],
+    };
+    let app_request_id = Uid::from(&[0x11; UID_LEN]);
+    let incoming_control_message = FunderIncomingControl::new(
+        app_request_id.clone(),
+        FunderControl::SetFriendRelays(set_friend_relays),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    )))
+    .unwrap();
+
+    // The Channeler reconnects to pk_a using the new address:
+    let update_friend = outgoing_comms
+        .iter()
+        .find_map(|comm| match comm {
+            FunderOutgoingComm::ChannelerConfig(ChannelerConfig::UpdateFriend(update_friend)) => {
+                Some(update_friend)
+            }
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(update_friend.friend_public_key, pk_a);
+    assert_eq!(update_friend.friend_relays, vec![dummy_relay_address(2)]);
+
+    // The new address is reported. The channel with pk_a is kept:
+    let report_mutations = acked_report_mutations(&outgoing_control, &app_request_id).unwrap();
+    assert_eq!(
+        report_mutations,
+        vec![FunderReportMutation::FriendReportMutation((
+            pk_a.clone(),
+            FriendReportMutation::SetRemoteRelays(vec![dummy_relay_address(2)]),
+        ))]
+    );
+    assert_eq!(friend_balance(&state, &pk_a), 20);
+
+    // Updating the address of a nonexistent friend changes nothing:
+    let set_friend_relays = SetFriendRelays {
+        friend_public_key: pk_b.clone(),
+        relays: vec![dummy_relay_address(3)],
+    };
+    let app_request_id = Uid::from(&[0x12; UID_LEN]);
+    let incoming_control_message = FunderIncomingControl::new(
+        app_request_id.clone(),
+        FunderControl::SetFriendRelays(set_friend_relays),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    )))
+    .unwrap();
+
+    assert!(outgoing_comms.is_empty());
+    let report_mutations = acked_report_mutations(&outgoing_control, &app_request_id).unwrap();
+    assert!(report_mutations.is_empty());
+    assert_eq!(state.friends.len(), 1);
+    assert!(!state.friends.contains_key(&pk_b));
+
+    // A friend can not be added with a balance that can not be negated:
+    let add_friend = AddFriend {
+        friend_public_key: pk_b.clone(),
+        relays: vec![dummy_relay_address(3)],
+        name: "friend_b".to_owned(),
+        balance: i128::min_value(),
+    };
+    let app_request_id = Uid::from(&[0x13; UID_LEN]);
+    let incoming_control_message =
+        FunderIncomingControl::new(app_request_id.clone(), FunderControl::AddFriend(add_friend));
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    )))
+    .unwrap();
+
+    assert!(outgoing_comms.is_empty());
+    let report_mutations = acked_report_mutations(&outgoing_control, &app_request_id).unwrap();
+    assert!(report_mutations.is_empty());
+    assert_eq!(state.friends.len(), 1);
+    assert!(!state.friends.contains_key(&pk_b));
+}
+
+#[test]
+fn test_handler_duplicate_friend() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let (mut identity_client, _) = spawn_fixture_identity(1, &mut thread_pool);
+
+    thread_pool.run(task_handler_duplicate_friend(&mut identity_client));
+}
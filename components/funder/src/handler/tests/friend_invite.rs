@@ -0,0 +1,169 @@
+use super::utils::apply_funder_incoming;
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    AddFriend, AddFriendFromInvite, FunderControl, FunderIncomingControl, FunderOutgoingControl,
+};
+use proto::invite::messages::ImportFriendInviteResult;
+
+use crate::ephemeral::Ephemeral;
+use crate::state::FunderState;
+use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+use crate::types::FunderIncoming;
+
+/// Find the result of a request to import a friend invite
+fn import_result(
+    outgoing_control: &[FunderOutgoingControl<u32>],
+    request_id: &Uid,
+) -> Option<ImportFriendInviteResult> {
+    outgoing_control.iter().find_map(|control| match control {
+        FunderOutgoingControl::ResponseImportFriendInvite(response_import)
+            if &response_import.request_id == request_id =>
+        {
+            Some(response_import.result)
+        }
+        _ => None,
+    })
+}
+
+/// Did the control with the given app request id change the report?
+fn has_report_mutations(
+    outgoing_control: &[FunderOutgoingControl<u32>],
+    app_request_id: &Uid,
+) -> bool {
+    outgoing_control.iter().any(|control| match control {
+        FunderOutgoingControl::ReportMutations(report_mutations) => {
+            report_mutations.opt_app_request_id.as_ref() == Some(app_request_id)
+                && !report_mutations.mutations.is_empty()
+        }
+        _ => false,
+    })
+}
+
+async fn apply_add_friend_from_invite<'a>(
+    state: &'a mut FunderState<u32>,
+    ephemeral: &'a mut Ephemeral,
+    rng: &'a mut RngContainer<DummyRandom>,
+    identity_client: &'a mut IdentityClient,
+    uid_index: u8,
+    add_friend_from_invite: AddFriendFromInvite<u32>,
+) -> Vec<FunderOutgoingControl<u32>> {
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[uid_index; UID_LEN]),
+        FunderControl::AddFriendFromInvite(add_friend_from_invite),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (_outgoing_comms, outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        state,
+        ephemeral,
+        rng,
+        identity_client
+    )))
+    .unwrap();
+    outgoing_control
+}
+
+async fn task_handler_friend_invite<'a>(identity_client: &'a mut IdentityClient) {
+    let local_pk = await!(identity_client.request_public_key()).unwrap();
+    let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+
+    let relays = vec![dummy_named_relay_address(0)];
+    let mut state = FunderState::<u32>::new(local_pk, relays);
+    let mut ephemeral = Ephemeral::new();
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+
+    await!(Box::pin(apply_funder_incoming(
+        FunderIncoming::Init,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    )))
+    .unwrap();
+
+    // pk_a is added from an invite, together with the suggested max debt:
+    let request_id = Uid::from(&[0x20; UID_LEN]);
+    let add_friend_from_invite = AddFriendFromInvite {
+        request_id,
+        add_friend: AddFriend {
+            friend_public_key: pk_a.clone(),
+            relays: vec![dummy_relay_address(1)],
+            name: "friend_a".to_owned(),
+            balance: 0,
+        },
+        opt_remote_max_debt: Some(100),
+    };
+    let outgoing_control = await!(apply_add_friend_from_invite(
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client,
+        0x10,
+        add_friend_from_invite
+    ));
+    assert_eq!(
+        import_result(&outgoing_control, &request_id),
+        Some(ImportFriendInviteResult::Success)
+    );
+    assert!(has_report_mutations(
+        &outgoing_control,
+        &Uid::from(&[0x10; UID_LEN])
+    ));
+    let friend_a = state.friends.get(&pk_a).unwrap();
+    assert_eq!(friend_a.name, "friend_a");
+    assert_eq!(friend_a.remote_relays, vec![dummy_relay_address(1)]);
+    assert_eq!(friend_a.wanted_remote_max_debt, 100);
+
+    // The same invite is imported again. Nothing changes:
+    let request_id = Uid::from(&[0x21; UID_LEN]);
+    let add_friend_from_invite = AddFriendFromInvite {
+        request_id,
+        add_friend: AddFriend {
+            friend_public_key: pk_a.clone(),
+            relays: vec![dummy_relay_address(2)],
+            name: "friend_a_again".to_owned(),
+            balance: 0,
+        },
+        opt_remote_max_debt: Some(200),
+    };
+    let outgoing_control = await!(apply_add_friend_from_invite(
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client,
+        0x11,
+        add_friend_from_invite
+    ));
+    assert_eq!(
+        import_result(&outgoing_control, &request_id),
+        Some(ImportFriendInviteResult::FriendAlreadyExists)
+    );
+    assert!(!has_report_mutations(
+        &outgoing_control,
+        &Uid::from(&[0x11; UID_LEN])
+    ));
+    assert_eq!(state.friends.len(), 1);
+    let friend_a = state.friends.get(&pk_a).unwrap();
+    assert_eq!(friend_a.name, "friend_a");
+    assert_eq!(friend_a.remote_relays, vec![dummy_relay_address(1)]);
+    assert_eq!(friend_a.wanted_remote_max_debt, 100);
+}
+
+#[test]
+fn test_handler_friend_invite() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let (mut identity_client, _) = spawn_fixture_identity(1, &mut thread_pool);
+
+    thread_pool.run(task_handler_friend_invite(&mut identity_client));
+}
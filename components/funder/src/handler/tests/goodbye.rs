@@ -0,0 +1,284 @@
+use super::utils::apply_funder_incoming;
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    AddFriend, FriendMessage, FriendStatus, FunderControl, FunderIncomingControl,
+    FunderOutgoingControl, Goodbye, SetFriendStatus,
+};
+use proto::report::messages::{FriendLivenessReport, FriendReportMutation, FunderReportMutation};
+
+use crate::ephemeral::Ephemeral;
+use crate::report::create_report;
+use crate::state::FunderState;
+use crate::types::{
+    ChannelerConfig, FunderIncoming, FunderIncomingComm, FunderOutgoingComm,
+    IncomingLivenessMessage,
+};
+
+use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+
+/// Find the liveness of a friend set by the report mutations, if any.
+fn reported_liveness(
+    outgoing_control: &[FunderOutgoingControl<u32>],
+    friend_public_key: &PublicKey,
+) -> Option<FriendLivenessReport> {
+    outgoing_control
+        .iter()
+        .flat_map(|control| match control {
+            FunderOutgoingControl::ReportMutations(report_mutations) => {
+                report_mutations.mutations.clone()
+            }
+            _ => Vec::new(),
+        })
+        .filter_map(|report_mutation| match &report_mutation {
+            FunderReportMutation::FriendReportMutation((
+                pk,
+                FriendReportMutation::SetLiveness(friend_liveness),
+            )) if pk == friend_public_key => Some(friend_liveness.clone()),
+            _ => None,
+        })
+        .last()
+}
+
+fn friend_liveness(
+    state: &FunderState<u32>,
+    ephemeral: &Ephemeral,
+    friend_public_key: &PublicKey,
+) -> FriendLivenessReport {
+    let report = create_report(state, ephemeral);
+    report
+        .friends
+        .get(friend_public_key)
+        .unwrap()
+        .liveness
+        .clone()
+}
+
+/// Find the index of the goodbye message sent to a friend inside the outgoing comms, if any.
+fn find_goodbye(
+    outgoing_comms: &[FunderOutgoingComm<u32>],
+    friend_public_key: &PublicKey,
+) -> Option<(usize, Goodbye)> {
+    outgoing_comms
+        .iter()
+        .enumerate()
+        .find_map(|(index, outgoing_comm)| match outgoing_comm {
+            FunderOutgoingComm::FriendMessage((pk, FriendMessage::Goodbye(goodbye)))
+                if pk == friend_public_key =>
+            {
+                Some((index, goodbye.clone()))
+            }
+            _ => None,
+        })
+}
+
+async fn task_handler_goodbye<'a>(identity_client: &'a mut IdentityClient) {
+    let local_pk = await!(identity_client.request_public_key()).unwrap();
+    let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+
+    let relays = vec![dummy_named_relay_address(0)];
+    let mut state = FunderState::<u32>::new(local_pk, relays);
+    let mut ephemeral = Ephemeral::new();
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+
+    // Add pk_a as a friend, enable it and bring it online:
+    let add_friend = AddFriend {
+        friend_public_key: pk_a.clone(),
+        relays: vec![dummy_relay_address(1)],
+        name: "friend_a".to_owned(),
+        balance: 0,
+    };
+    let set_friend_status = SetFriendStatus {
+        friend_public_key: pk_a.clone(),
+        status: FriendStatus::Enabled,
+    };
+    let funder_incomings = vec![
+        FunderIncoming::Init,
+        FunderIncoming::Control(FunderIncomingControl::new(
+            Uid::from(&[0; UID_LEN]),
+            FunderControl::AddFriend(add_friend),
+        )),
+        FunderIncoming::Control(FunderIncomingControl::new(
+            Uid::from(&[1; UID_LEN]),
+            FunderControl::SetFriendStatus(set_friend_status),
+        )),
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(IncomingLivenessMessage::Online(
+            pk_a.clone(),
+        ))),
+    ];
+    for funder_incoming in funder_incomings {
+        await!(Box::pin(apply_funder_incoming(
+            funder_incoming,
+            &mut state,
+            &mut ephemeral,
+            &mut rng,
+            identity_client
+        )))
+        .unwrap();
+    }
+    assert_eq!(
+        friend_liveness(&state, &ephemeral, &pk_a),
+        FriendLivenessReport::Online
+    );
+
+    // pk_a says goodbye, and expects to be back in 2 ticks:
+    let goodbye = Goodbye {
+        opt_downtime_ticks: Some(2),
+    };
+    let friend_message = FriendMessage::Goodbye(goodbye.clone());
+    let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Friend((
+        pk_a.clone(),
+        friend_message,
+    )));
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    )))
+    .unwrap();
+
+    // We stop reconnecting to pk_a. pk_a is still online until it disconnects:
+    match &outgoing_comms[..] {
+        [FunderOutgoingComm::ChannelerConfig(ChannelerConfig::SuspendFriend(pk))] => {
+            assert_eq!(pk, &pk_a)
+        }
+        _ => unreachable!(),
+    };
+    assert_eq!(
+        friend_liveness(&state, &ephemeral, &pk_a),
+        FriendLivenessReport::Online
+    );
+
+    // pk_a disconnects:
+    let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Liveness(
+        IncomingLivenessMessage::Offline(pk_a.clone()),
+    ));
+    let (_outgoing_comms, outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    )))
+    .unwrap();
+    assert_eq!(
+        reported_liveness(&outgoing_control, &pk_a),
+        Some(FriendLivenessReport::IntentionallyOffline(goodbye.clone()))
+    );
+
+    // We try to reconnect to pk_a only after the expected downtime has passed:
+    for tick in 0..2 {
+        let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+            FunderIncoming::TimerTick(1),
+            &mut state,
+            &mut ephemeral,
+            &mut rng,
+            identity_client
+        )))
+        .unwrap();
+
+        let opt_update_friend = outgoing_comms.iter().find_map(|comm| match comm {
+            FunderOutgoingComm::ChannelerConfig(ChannelerConfig::UpdateFriend(update_friend)) => {
+                Some(update_friend)
+            }
+            _ => None,
+        });
+        if tick == 0 {
+            assert!(opt_update_friend.is_none());
+        } else {
+            assert_eq!(opt_update_friend.unwrap().friend_public_key, pk_a);
+        }
+    }
+
+    // The goodbye is kept until pk_a is back online:
+    assert_eq!(
+        friend_liveness(&state, &ephemeral, &pk_a),
+        FriendLivenessReport::IntentionallyOffline(goodbye.clone())
+    );
+
+    let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Liveness(
+        IncomingLivenessMessage::Online(pk_a.clone()),
+    ));
+    let (_outgoing_comms, outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    )))
+    .unwrap();
+    assert_eq!(
+        reported_liveness(&outgoing_control, &pk_a),
+        Some(FriendLivenessReport::Online)
+    );
+    assert!(ephemeral.goodbyes.get(&pk_a).is_none());
+
+    // We are about to shut down:
+    let shutdown_goodbye = Goodbye {
+        opt_downtime_ticks: Some(10),
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[2; UID_LEN]),
+        FunderControl::AnnounceShutdown(shutdown_goodbye.clone()),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    )))
+    .unwrap();
+    let (_, goodbye) = find_goodbye(&outgoing_comms, &pk_a).unwrap();
+    assert_eq!(goodbye, shutdown_goodbye);
+
+    // Disabling pk_a sends a goodbye before the Channeler disconnects from pk_a:
+    let set_friend_status = SetFriendStatus {
+        friend_public_key: pk_a.clone(),
+        status: FriendStatus::Disabled,
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[3; UID_LEN]),
+        FunderControl::SetFriendStatus(set_friend_status),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    )))
+    .unwrap();
+    let (goodbye_index, goodbye) = find_goodbye(&outgoing_comms, &pk_a).unwrap();
+    assert_eq!(goodbye.opt_downtime_ticks, None);
+    let remove_friend_index = outgoing_comms
+        .iter()
+        .position(|comm| match comm {
+            FunderOutgoingComm::ChannelerConfig(ChannelerConfig::RemoveFriend(pk)) => pk == &pk_a,
+            _ => false,
+        })
+        .unwrap();
+    assert!(goodbye_index < remove_friend_index);
+}
+
+#[test]
+fn test_handler_goodbye() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let (mut identity_client, _) = spawn_fixture_identity(1, &mut thread_pool);
+
+    thread_pool.run(task_handler_goodbye(&mut identity_client));
+}
@@ -0,0 +1,141 @@
+use super::utils::{apply_control_and_deliver, create_chain_net, responses_received, TestNet};
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::consts::MAX_PAYMENT_LABEL_LEN;
+use proto::funder::messages::{
+    FriendsRoute, FunderControl, ResponseReceived, ResponseSendFundsResult, UserRequestSendFunds,
+};
+
+use crate::ephemeral::Ephemeral;
+use crate::quarantine::StoredFunderState;
+use crate::report::create_report;
+
+/// Amount of nodes in the test network. Node i is a friend of node i + 1.
+const NUM_NODES: usize = 3;
+
+/// node0 pays node2 through node1. Returns the response node0 received.
+async fn pay_node2<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    uid_index: u8,
+    opt_label: Option<Vec<u8>>,
+) -> ResponseReceived {
+    let route = FriendsRoute {
+        public_keys: net
+            .nodes
+            .iter()
+            .map(|node| node.public_key.clone())
+            .collect(),
+    };
+    let user_request_send_funds = UserRequestSendFunds {
+        request_id: Uid::from(&[uid_index; UID_LEN]),
+        route,
+        invoice_id: InvoiceId::from(&[uid_index; INVOICE_ID_LEN]),
+        dest_payment: 10,
+        opt_label,
+    };
+    let controls = await!(apply_control_and_deliver(
+        net,
+        rng,
+        0,
+        uid_index,
+        FunderControl::RequestSendFunds(user_request_send_funds)
+    ));
+    let mut responses = responses_received(&controls, 0);
+    assert_eq!(responses.len(), 1);
+    responses.pop().unwrap()
+}
+
+/// The Debug rendering of the bytes of a label, as it would appear inside the Debug rendering of
+/// any message that contains the label.
+fn label_debug(label: &[u8]) -> String {
+    let rendered = format!("{:?}", label);
+    rendered[1..rendered.len() - 1].to_owned()
+}
+
+async fn task_handler_labeled_payments(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut net = await!(create_chain_net(identity_clients, 100, &mut rng));
+
+    let labels = vec![b"ledger:order-0001".to_vec(), b"ledger:order-0002".to_vec()];
+    let labeled_uids = [30u8, 31u8];
+
+    // Two labeled payments and one payment without a label.
+    // Every response carries the label of its payment:
+    for (label, &uid_index) in labels.iter().zip(labeled_uids.iter()) {
+        let response_received = await!(pay_node2(
+            &mut net,
+            &mut rng,
+            uid_index,
+            Some(label.clone())
+        ));
+        assert_eq!(response_received.opt_label.as_ref(), Some(label));
+        match &response_received.result {
+            ResponseSendFundsResult::Success(_) => {}
+            ResponseSendFundsResult::Failure(_) => unreachable!(),
+        };
+    }
+    let response_received = await!(pay_node2(&mut net, &mut rng, 32, None));
+    assert_eq!(response_received.opt_label, None);
+
+    // A label that is too long is rejected, and is not remembered:
+    let long_label = vec![0x77; MAX_PAYMENT_LABEL_LEN + 1];
+    let response_received = await!(pay_node2(&mut net, &mut rng, 33, Some(long_label.clone())));
+    assert_eq!(response_received.opt_label, Some(long_label));
+    match &response_received.result {
+        ResponseSendFundsResult::Failure(_) => {}
+        ResponseSendFundsResult::Success(_) => unreachable!(),
+    };
+
+    // The labels were never sent to other nodes:
+    assert!(!net.friend_messages.is_empty());
+    for friend_message in &net.friend_messages {
+        let rendered = format!("{:?}", friend_message);
+        for label in &labels {
+            assert!(!rendered.contains(&label_debug(label)));
+        }
+    }
+
+    // node0 remembers the labeled payments together with their results, oldest first:
+    let labeled_payments = net.nodes[0].state.labeled_payments.clone();
+    assert_eq!(labeled_payments.len(), 2);
+    for (labeled_payment, (label, &uid_index)) in labeled_payments
+        .iter()
+        .zip(labels.iter().zip(labeled_uids.iter()))
+    {
+        assert_eq!(labeled_payment.request_id, Uid::from(&[uid_index; UID_LEN]));
+        assert_eq!(&labeled_payment.label, label);
+        match &labeled_payment.opt_result {
+            Some(ResponseSendFundsResult::Success(_)) => {}
+            _ => unreachable!(),
+        };
+    }
+
+    // The labeled payments survive a restart of node0, and are reported:
+    let serialized = bincode::serialize(&net.nodes[0].state).unwrap();
+    let stored_state: StoredFunderState<u32> = bincode::deserialize(&serialized).unwrap();
+    let loaded_state = stored_state.quarantine_corrupt();
+    assert_eq!(loaded_state.labeled_payments, labeled_payments);
+    let funder_report = create_report(&loaded_state, &Ephemeral::new());
+    assert_eq!(funder_report.labeled_payments, labeled_payments);
+}
+
+#[test]
+fn test_handler_labeled_payments() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=NUM_NODES as u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_labeled_payments(identity_clients));
+}
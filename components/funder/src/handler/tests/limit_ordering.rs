@@ -0,0 +1,182 @@
+use super::utils::{apply_only, control_message, create_chain_net, request_send_funds, TestNet};
+
+use std::collections::{HashMap, VecDeque};
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    FriendMessage, FriendTcOp, FunderControl, FunderOutgoingControl, ResponseSendFundsResult,
+    SetFriendRemoteMaxDebt,
+};
+
+use crate::types::{FunderIncoming, FunderIncomingComm};
+
+/// Maximum amount of messages we expect to be delivered during a single interleaving.
+/// Protects the test from looping forever.
+const MAX_INTERLEAVING_DELIVERIES: usize = 512;
+
+/// The maximum debt node1 allows node0 at the beginning.
+const MAX_DEBT: u128 = 100;
+
+/// The maximum debt node1 allows node0 after the limit decrease.
+const NEW_MAX_DEBT: u128 = 35;
+
+/// Amount of payments node0 submits during an interleaving. All of them fit within MAX_DEBT.
+const NUM_PAYMENTS: u8 = 8;
+
+/// Amount of credits paid (and frozen) by every payment.
+const PAYMENT: u128 = 10;
+
+/// Amount of scheduler seeds to sweep.
+const NUM_SEEDS: u8 = 48;
+
+/// The app request id of the limit decrease.
+const LIMIT_UID_INDEX: u8 = 0xee;
+
+/// node1 lowers the maximum debt of node0 to NEW_MAX_DEBT.
+fn decrease_limit(net: &TestNet) -> FunderIncoming<u32> {
+    let set_friend_remote_max_debt = SetFriendRemoteMaxDebt {
+        friend_public_key: net.nodes[0].public_key.clone(),
+        remote_max_debt: NEW_MAX_DEBT,
+        opt_expiry: None,
+    };
+    control_message(
+        LIMIT_UID_INDEX,
+        FunderControl::SetFriendRemoteMaxDebt(set_friend_remote_max_debt),
+    )
+}
+
+/// Was the limit decrease acknowledged?
+fn is_limit_ack(control: &FunderOutgoingControl<u32>) -> bool {
+    match control {
+        FunderOutgoingControl::ReportMutations(report_mutations) => {
+            report_mutations.opt_app_request_id == Some(Uid::from(&[LIMIT_UID_INDEX; UID_LEN]))
+        }
+        _ => false,
+    }
+}
+
+/// Ids of the requests contained in a move token sent to a node
+fn received_request_ids(funder_incoming: &FunderIncoming<u32>) -> Vec<Uid> {
+    let move_token_request = match funder_incoming {
+        FunderIncoming::Comm(FunderIncomingComm::Friend((
+            _,
+            FriendMessage::MoveTokenRequest(move_token_request),
+        ))) => move_token_request,
+        _ => return Vec::new(),
+    };
+    move_token_request
+        .friend_move_token
+        .operations
+        .iter()
+        .filter_map(|operation| match operation {
+            FriendTcOp::RequestSendFunds(request_send_funds) => Some(request_send_funds.request_id),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Run one interleaving of a limit decrease at node1 with a stream of payments submitted at
+/// node0. The interleaving is chosen by a scheduler using the given seed.
+///
+/// Checks that the new limit applies to every request node1 receives after the limit decrease
+/// was acknowledged, and that the old limit applies to every request received before.
+async fn run_interleaving(identity_clients: Vec<IdentityClient>, seed: u8) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[seed]));
+    let scheduler = DummyRandom::new(&[0x66, seed]);
+    let mut net = await!(create_chain_net(identity_clients, MAX_DEBT, &mut rng));
+
+    // Submissions in the order of submission. The limit decrease is submitted somewhere
+    // between the payments:
+    let limit_position = usize::from(Uid::new(&scheduler).as_ref()[0] % (NUM_PAYMENTS + 1));
+    let mut submissions = (0..NUM_PAYMENTS)
+        .map(|uid_index| (0, request_send_funds(&net, &[0, 1], uid_index, PAYMENT)))
+        .collect::<VecDeque<_>>();
+    submissions.insert(limit_position, (1, decrease_limit(&net)));
+
+    let mut is_acked = false;
+    // The result node1 should give to every request it received:
+    let mut expected_success: HashMap<Uid, bool> = HashMap::new();
+    // Credits node0 owes node1, or are frozen for node0's requests:
+    let mut used_credits = 0u128;
+    let mut responses = Vec::new();
+    // Friend messages that were sent and were not delivered yet: (destination index, message)
+    let mut in_flight = VecDeque::new();
+    let mut deliveries = 0;
+
+    loop {
+        // The scheduler chooses between submitting the next message and delivering the next
+        // friend message in flight:
+        let submit = Uid::new(&scheduler).as_ref()[0] % 2 == 0;
+        let (index, funder_incoming) =
+            if !submissions.is_empty() && (submit || in_flight.is_empty()) {
+                submissions.pop_front().unwrap()
+            } else if let Some(delivery) = in_flight.pop_front() {
+                deliveries += 1;
+                assert!(deliveries <= MAX_INTERLEAVING_DELIVERIES);
+                delivery
+            } else {
+                break;
+            };
+
+        if index == 1 {
+            for request_id in received_request_ids(&funder_incoming) {
+                if expected_success.contains_key(&request_id) {
+                    // A retransmission of a move token:
+                    continue;
+                }
+                let max_debt = if is_acked { NEW_MAX_DEBT } else { MAX_DEBT };
+                let success = used_credits + PAYMENT <= max_debt;
+                if success {
+                    used_credits += PAYMENT;
+                }
+                expected_success.insert(request_id, success);
+            }
+        }
+
+        let (undelivered, controls) =
+            await!(apply_only(&mut net, &mut rng, index, funder_incoming));
+        in_flight.extend(undelivered);
+        for control in controls {
+            if index == 1 && is_limit_ack(&control) {
+                is_acked = true;
+            }
+            if let FunderOutgoingControl::ResponseReceived(response_received) = control {
+                assert_eq!(index, 0);
+                responses.push(response_received);
+            }
+        }
+    }
+    assert!(is_acked);
+
+    // Every payment was answered exactly once:
+    assert_eq!(responses.len(), usize::from(NUM_PAYMENTS));
+    for response_received in &responses {
+        let success = match response_received.result {
+            ResponseSendFundsResult::Success(_) => true,
+            ResponseSendFundsResult::Failure(_) => false,
+        };
+        // A payment that never reached node1 was rejected by node0:
+        let expected = expected_success.get(&response_received.request_id) == Some(&true);
+        assert_eq!(success, expected, "seed: {}", seed);
+    }
+}
+
+#[test]
+fn test_handler_limit_ordering() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    for seed in 0..NUM_SEEDS {
+        let identity_clients = (1..=2u8)
+            .map(|identity_seed| spawn_fixture_identity(identity_seed, &mut thread_pool).0)
+            .collect::<Vec<_>>();
+        thread_pool.run(run_interleaving(identity_clients, seed));
+    }
+}
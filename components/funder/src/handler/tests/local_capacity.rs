@@ -0,0 +1,168 @@
+use super::utils::{
+    create_chain_net, deliver_all, is_success, request_send_funds, responses_received,
+};
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::identity::PublicKey;
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{FailureReason, ResponseSendFundsResult};
+
+use crate::ephemeral::Ephemeral;
+use crate::friend::ChannelStatus;
+use crate::quarantine::StoredFunderState;
+use crate::state::FunderState;
+use crate::types::{FunderIncoming, FunderIncomingComm, IncomingLivenessMessage};
+
+/// Amount of nodes in the test network. Node i is a friend of node i + 1.
+const NUM_NODES: usize = 2;
+
+/// The maximum debt node1 allows node0.
+const MAX_DEBT: u128 = 100;
+
+async fn task_handler_local_capacity(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut net = await!(create_chain_net(identity_clients, MAX_DEBT, &mut rng));
+
+    // Two payments are submitted before any of them is sent to node1.
+    // The second payment exceeds the capacity that remains after the first payment:
+    let incoming = vec![
+        (0, request_send_funds(&net, &[0, 1], 30, 60)),
+        (0, request_send_funds(&net, &[0, 1], 31, 50)),
+    ];
+    let controls = await!(deliver_all(&mut net, &mut rng, incoming));
+    let responses = responses_received(&controls, 0);
+    assert_eq!(responses.len(), 2);
+
+    // The second payment is rejected immediately, with the remaining capacity:
+    assert_eq!(responses[0].request_id, Uid::from(&[31; UID_LEN]));
+    assert_eq!(
+        responses[0].result,
+        ResponseSendFundsResult::Failure((
+            net.nodes[0].public_key.clone(),
+            FailureReason::InsufficientLocalCapacity(MAX_DEBT - 60),
+        ))
+    );
+    assert_eq!(responses[1].request_id, Uid::from(&[30; UID_LEN]));
+    assert!(is_success(&responses[1]));
+
+    // Sending the remaining capacity succeeds:
+    let incoming = vec![(0, request_send_funds(&net, &[0, 1], 32, MAX_DEBT - 60))];
+    let controls = await!(deliver_all(&mut net, &mut rng, incoming));
+    let responses = responses_received(&controls, 0);
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].request_id, Uid::from(&[32; UID_LEN]));
+    assert!(is_success(&responses[0]));
+
+    // No capacity remains:
+    let incoming = vec![(0, request_send_funds(&net, &[0, 1], 33, 1))];
+    let controls = await!(deliver_all(&mut net, &mut rng, incoming));
+    assert_eq!(
+        responses_received(&controls, 0)[0].result,
+        ResponseSendFundsResult::Failure((
+            net.nodes[0].public_key.clone(),
+            FailureReason::InsufficientLocalCapacity(0),
+        ))
+    );
+}
+
+#[test]
+fn test_handler_local_capacity() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=NUM_NODES as u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_local_capacity(identity_clients));
+}
+
+/// Check that a request to `friend_public_key` is pending, and that the pending requests to
+/// `friend_public_key` freeze `frozen` credits.
+fn assert_pending(
+    state: &FunderState<u32>,
+    friend_public_key: &PublicKey,
+    request_id: &Uid,
+    frozen: u128,
+) {
+    let token_channel = match &state.friends.get(friend_public_key).unwrap().channel_status {
+        ChannelStatus::Consistent(token_channel) => token_channel,
+        _ => unreachable!(),
+    };
+    let mc_state = token_channel.get_mutual_credit().state();
+    assert_eq!(mc_state.balance.local_pending_debt, frozen);
+    assert!(mc_state
+        .pending_requests
+        .pending_local_requests
+        .contains_key(request_id));
+}
+
+async fn task_handler_local_capacity_restart(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut net = await!(create_chain_net(identity_clients, MAX_DEBT, &mut rng));
+
+    // node1 does not receive the request, so it remains pending at node0:
+    net.opt_muted = Some(1);
+    let incoming = vec![(0, request_send_funds(&net, &[0, 1], 30, 60))];
+    let controls = await!(deliver_all(&mut net, &mut rng, incoming));
+    assert!(responses_received(&controls, 0).is_empty());
+
+    // The credits frozen for the pending request are part of the persistent state:
+    let node1_public_key = net.nodes[1].public_key.clone();
+    let request_id = Uid::from(&[30; UID_LEN]);
+    assert_pending(&net.nodes[0].state, &node1_public_key, &request_id, 60);
+
+    // node0 restarts. The state is loaded from the database, and the ephemeral state is empty:
+    let serialized = bincode::serialize(&net.nodes[0].state).unwrap();
+    let stored_state: StoredFunderState<u32> = bincode::deserialize(&serialized).unwrap();
+    net.nodes[0].state = stored_state.quarantine_corrupt();
+    net.nodes[0].ephemeral = Ephemeral::new();
+    assert_pending(&net.nodes[0].state, &node1_public_key, &request_id, 60);
+
+    let incoming = vec![
+        (0, FunderIncoming::Init),
+        (
+            0,
+            FunderIncoming::Comm(FunderIncomingComm::Liveness(
+                IncomingLivenessMessage::Online(node1_public_key.clone()),
+            )),
+        ),
+    ];
+    await!(deliver_all(&mut net, &mut rng, incoming));
+
+    // A payment that exceeds the capacity left by the pending request is still rejected:
+    let incoming = vec![(0, request_send_funds(&net, &[0, 1], 31, 50))];
+    let controls = await!(deliver_all(&mut net, &mut rng, incoming));
+    let responses = responses_received(&controls, 0);
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].request_id, Uid::from(&[31; UID_LEN]));
+    assert_eq!(
+        responses[0].result,
+        ResponseSendFundsResult::Failure((
+            net.nodes[0].public_key.clone(),
+            FailureReason::InsufficientLocalCapacity(MAX_DEBT - 60),
+        ))
+    );
+
+    // The remaining capacity is accepted:
+    let incoming = vec![(0, request_send_funds(&net, &[0, 1], 32, MAX_DEBT - 60))];
+    let controls = await!(deliver_all(&mut net, &mut rng, incoming));
+    assert!(responses_received(&controls, 0).is_empty());
+}
+
+#[test]
+fn test_handler_local_capacity_restart() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=NUM_NODES as u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_local_capacity_restart(identity_clients));
+}
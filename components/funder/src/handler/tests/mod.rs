@@ -1,4 +1,23 @@
+mod cache_bounds;
+mod cancel_user_request;
 mod change_address;
+mod duplicate_friend;
+mod friend_invite;
+mod goodbye;
+mod labeled_payments;
+mod limit_ordering;
+mod local_capacity;
 mod pair_basic;
+mod pair_freeze_limits;
 mod pair_inconsistency;
+mod payment_submissions;
+mod payment_timing;
+mod prewarm;
+mod protocol_violation;
+mod remote_max_debt_expiry;
+mod remote_relays;
+mod reset_crash_recovery;
+mod report_direction;
+mod response_deadline;
 mod utils;
+mod verification;
@@ -3,13 +3,12 @@ use super::utils::apply_funder_incoming;
 use std::cmp::Ordering;
 
 use futures::executor::ThreadPool;
-use futures::task::SpawnExt;
-use futures::{future, FutureExt};
 
-use identity::{create_identity, IdentityClient};
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
 
 use crypto::crypto_rand::RngContainer;
-use crypto::identity::{compare_public_key, generate_pkcs8_key_pair, SoftwareEd25519Identity};
+use crypto::identity::compare_public_key;
 use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
 use crypto::test_utils::DummyRandom;
 use crypto::uid::{Uid, UID_LEN};
@@ -345,6 +344,7 @@ async fn task_handler_pair_basic<'a>(
     let set_friend_remote_max_debt = SetFriendRemoteMaxDebt {
         friend_public_key: pk2.clone(),
         remote_max_debt: 100,
+        opt_expiry: None,
     };
     let incoming_control_message = FunderIncomingControl::new(
         Uid::from(&[15; UID_LEN]),
@@ -423,6 +423,7 @@ async fn task_handler_pair_basic<'a>(
         },
         invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
         dest_payment: 20,
+        opt_label: None,
     };
     let incoming_control_message = FunderIncomingControl::new(
         Uid::from(&[16; UID_LEN]),
@@ -565,6 +566,7 @@ async fn task_handler_pair_basic<'a>(
         },
         invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
         dest_payment: 20,
+        opt_label: None,
     };
     let incoming_control_message = FunderIncomingControl::new(
         Uid::from(&[18; UID_LEN]),
@@ -650,29 +652,19 @@ async fn task_handler_pair_basic<'a>(
     assert_eq!(mutual_credit_state.balance.balance, -20);
     assert_eq!(mutual_credit_state.balance.remote_pending_debt, 0);
     assert_eq!(mutual_credit_state.balance.local_pending_debt, 0);
+
+    // Node2 remembers that the request was completed:
+    let completed_request_ids = ephemeral2.completed.request_ids(&pk1);
+    assert!(completed_request_ids.contains(&Uid::from(&[3; UID_LEN])));
+    assert!(ephemeral1.completed.friends.is_empty());
 }
 
 #[test]
 fn test_handler_pair_basic() {
     let mut thread_pool = ThreadPool::new().unwrap();
 
-    let rng1 = DummyRandom::new(&[1u8]);
-    let pkcs8 = generate_pkcs8_key_pair(&rng1);
-    let identity1 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-    let (requests_sender1, identity_server1) = create_identity(identity1);
-    let mut identity_client1 = IdentityClient::new(requests_sender1);
-    thread_pool
-        .spawn(identity_server1.then(|_| future::ready(())))
-        .unwrap();
-
-    let rng2 = DummyRandom::new(&[2u8]);
-    let pkcs8 = generate_pkcs8_key_pair(&rng2);
-    let identity2 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-    let (requests_sender2, identity_server2) = create_identity(identity2);
-    let mut identity_client2 = IdentityClient::new(requests_sender2);
-    thread_pool
-        .spawn(identity_server2.then(|_| future::ready(())))
-        .unwrap();
+    let (mut identity_client1, _) = spawn_fixture_identity(1, &mut thread_pool);
+    let (mut identity_client2, _) = spawn_fixture_identity(2, &mut thread_pool);
 
     thread_pool.run(task_handler_pair_basic(
         &mut identity_client1,
@@ -0,0 +1,258 @@
+use super::utils::{
+    apply_control_and_deliver, create_net, deliver_all, request_send_funds, responses_received,
+    unmute_and_deliver, TestNet,
+};
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    FailureReason, FriendPair, FunderControl, FunderOutgoingControl, PairFreezeLimit,
+    RequestPairFreezeUsage, ResponsePairFreezeUsage, ResponseReceived, ResponseSendFundsResult,
+    SetPairFreezeLimit,
+};
+
+use crate::credit_calc::credits_to_freeze;
+use crate::ephemeral::EphemeralLimits;
+
+/// Nodes of the test network: Node 1 is a hub, forwarding requests from nodes 0 and 3 to node 2.
+/// ```text
+/// 0 -- 1 -- 2
+///      |
+///      3
+/// ```
+const NUM_NODES: usize = 4;
+const HUB: usize = 1;
+const DEST: usize = 2;
+
+/// Friendships of the test network: (sender, receiver).
+/// The receiver trusts the sender, and allows it to send requests.
+const EDGES: [(usize, usize); 3] = [(0, 1), (1, 2), (3, 1)];
+
+const MAX_DEBT: u128 = 1_000;
+
+/// Amount of credits every payment pays to the destination.
+const DEST_PAYMENT: u128 = 20;
+
+/// Send a payment from node `src` through the hub. Returns the responses received by `src`.
+async fn send_funds<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    src: usize,
+    uid_index: u8,
+) -> Vec<ResponseReceived> {
+    let incoming = vec![(
+        src,
+        request_send_funds(net, &[src, HUB, DEST], uid_index, DEST_PAYMENT),
+    )];
+    let controls = await!(deliver_all(net, rng, incoming));
+    responses_received(&controls, src)
+}
+
+/// Check that the payment with the given uid index was rejected by the hub.
+fn assert_rejected_by_hub(net: &TestNet, responses: &[ResponseReceived], uid_index: u8) {
+    assert_eq!(responses.len(), 1);
+    assert_eq!(responses[0].request_id, Uid::from(&[uid_index; UID_LEN]));
+    assert_eq!(
+        responses[0].result,
+        ResponseSendFundsResult::Failure((
+            net.nodes[HUB].public_key.clone(),
+            FailureReason::Unspecified,
+        ))
+    );
+}
+
+/// Set a rule at the hub. A wildcard is represented by None.
+async fn set_pair_freeze_limit<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    opt_from: Option<usize>,
+    opt_to: Option<usize>,
+    opt_limit: Option<u128>,
+) {
+    let pair = FriendPair {
+        opt_from: opt_from.map(|index| net.nodes[index].public_key.clone()),
+        opt_to: opt_to.map(|index| net.nodes[index].public_key.clone()),
+    };
+    let set_pair_freeze_limit = SetPairFreezeLimit { pair, opt_limit };
+    await!(apply_control_and_deliver(
+        net,
+        rng,
+        HUB,
+        30,
+        FunderControl::SetPairFreezeLimit(set_pair_freeze_limit)
+    ));
+}
+
+/// Ask the hub for the rule and the frozen credits of requests forwarded from `from` to `to`.
+async fn pair_freeze_usage<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    from: usize,
+    to: usize,
+) -> ResponsePairFreezeUsage {
+    let request_usage = RequestPairFreezeUsage {
+        request_id: Uid::from(&[31; UID_LEN]),
+        from_public_key: net.nodes[from].public_key.clone(),
+        to_public_key: net.nodes[to].public_key.clone(),
+    };
+    let controls = await!(apply_control_and_deliver(
+        net,
+        rng,
+        HUB,
+        32,
+        FunderControl::RequestPairFreezeUsage(request_usage)
+    ));
+    let mut responses = controls
+        .into_iter()
+        .filter_map(|(_, control)| match control {
+            FunderOutgoingControl::ResponsePairFreezeUsage(response_usage) => Some(response_usage),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(responses.len(), 1);
+    responses.pop().unwrap()
+}
+
+async fn task_handler_pair_freeze_limits(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut net = await!(create_net(
+        identity_clients,
+        &EphemeralLimits::default(),
+        &EDGES,
+        MAX_DEBT,
+        &mut rng
+    ));
+
+    // Credits the hub freezes against the destination for every payment:
+    let freeze_credits = credits_to_freeze(2, 3, DEST_PAYMENT).unwrap();
+
+    // No rule applies by default:
+    let response_usage = await!(pair_freeze_usage(&mut net, &mut rng, 0, DEST));
+    assert_eq!(response_usage.opt_limit, None);
+    assert_eq!(response_usage.frozen_credits, 0);
+
+    // Allow at most two payments from node0 to be in flight to the destination:
+    await!(set_pair_freeze_limit(
+        &mut net,
+        &mut rng,
+        Some(0),
+        Some(DEST),
+        Some(2 * freeze_credits)
+    ));
+
+    // The destination does not receive any messages, so the payments remain in flight:
+    net.opt_muted = Some(DEST);
+    assert!(await!(send_funds(&mut net, &mut rng, 0, 40)).is_empty());
+    assert!(await!(send_funds(&mut net, &mut rng, 0, 41)).is_empty());
+
+    let response_usage = await!(pair_freeze_usage(&mut net, &mut rng, 0, DEST));
+    assert_eq!(response_usage.frozen_credits, 2 * freeze_credits);
+    assert_eq!(
+        response_usage
+            .opt_limit
+            .map(|pair_freeze_limit| pair_freeze_limit.limit),
+        Some(2 * freeze_credits)
+    );
+
+    // The third payment exceeds the limit of the pair:
+    let responses = await!(send_funds(&mut net, &mut rng, 0, 42));
+    assert_rejected_by_hub(&net, &responses, 42);
+
+    // Other pairs are not affected:
+    assert!(await!(send_funds(&mut net, &mut rng, 3, 43)).is_empty());
+    let response_usage = await!(pair_freeze_usage(&mut net, &mut rng, 3, DEST));
+    assert_eq!(response_usage.opt_limit, None);
+    assert_eq!(response_usage.frozen_credits, freeze_credits);
+
+    // A rule for all the requests forwarded to the destination does not override the exact rule
+    // of the pair (0, DEST):
+    await!(set_pair_freeze_limit(
+        &mut net,
+        &mut rng,
+        None,
+        Some(DEST),
+        Some(0)
+    ));
+    await!(set_pair_freeze_limit(
+        &mut net,
+        &mut rng,
+        Some(0),
+        Some(DEST),
+        Some(3 * freeze_credits)
+    ));
+    assert!(await!(send_funds(&mut net, &mut rng, 0, 44)).is_empty());
+    let responses = await!(send_funds(&mut net, &mut rng, 3, 45));
+    assert_rejected_by_hub(&net, &responses, 45);
+
+    // Without the exact rule, the rule for requests forwarded to the destination takes
+    // precedence over a rule for requests that arrive from node0:
+    await!(set_pair_freeze_limit(
+        &mut net,
+        &mut rng,
+        Some(0),
+        Some(DEST),
+        None
+    ));
+    await!(set_pair_freeze_limit(
+        &mut net,
+        &mut rng,
+        Some(0),
+        None,
+        Some(MAX_DEBT)
+    ));
+    let response_usage = await!(pair_freeze_usage(&mut net, &mut rng, 0, DEST));
+    assert_eq!(
+        response_usage.opt_limit,
+        Some(PairFreezeLimit {
+            pair: FriendPair {
+                opt_from: None,
+                opt_to: Some(net.nodes[DEST].public_key.clone()),
+            },
+            limit: 0,
+        })
+    );
+    assert_eq!(response_usage.frozen_credits, 3 * freeze_credits);
+    let responses = await!(send_funds(&mut net, &mut rng, 0, 46));
+    assert_rejected_by_hub(&net, &responses, 46);
+
+    // Once the destination receives the requests, all the payments in flight are completed:
+    let controls = await!(unmute_and_deliver(&mut net, &mut rng));
+    let mut request_ids = responses_received(&controls, 0)
+        .into_iter()
+        .chain(responses_received(&controls, 3))
+        .map(|response_received| {
+            assert!(match response_received.result {
+                ResponseSendFundsResult::Success(_) => true,
+                ResponseSendFundsResult::Failure(_) => false,
+            });
+            response_received.request_id
+        })
+        .collect::<Vec<_>>();
+    request_ids.sort();
+    let expected_request_ids = [40u8, 41, 43, 44]
+        .iter()
+        .map(|&uid_index| Uid::from(&[uid_index; UID_LEN]))
+        .collect::<Vec<_>>();
+    assert_eq!(request_ids, expected_request_ids);
+
+    let response_usage = await!(pair_freeze_usage(&mut net, &mut rng, 0, DEST));
+    assert_eq!(response_usage.frozen_credits, 0);
+}
+
+#[test]
+fn test_handler_pair_freeze_limits() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=NUM_NODES as u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_pair_freeze_limits(identity_clients));
+}
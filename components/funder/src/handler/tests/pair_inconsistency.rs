@@ -3,13 +3,12 @@ use super::utils::apply_funder_incoming;
 use std::cmp::Ordering;
 
 use futures::executor::ThreadPool;
-use futures::task::SpawnExt;
-use futures::{future, FutureExt};
 
-use identity::{create_identity, IdentityClient};
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
 
 use crypto::crypto_rand::RngContainer;
-use crypto::identity::{compare_public_key, generate_pkcs8_key_pair, SoftwareEd25519Identity};
+use crypto::identity::compare_public_key;
 use crypto::test_utils::DummyRandom;
 use crypto::uid::{Uid, UID_LEN};
 
@@ -340,13 +339,15 @@ async fn task_handler_pair_inconsistency<'a>(
     )))
     .unwrap();
 
+    // The channel is not consistent until Node2 acknowledges the reset:
     let friend2 = state1.friends.get(&pk2).unwrap();
     match &friend2.channel_status {
-        ChannelStatus::Consistent(token_channel) => {
+        ChannelStatus::PendingReset(channel_pending_reset) => {
             assert_eq!(
-                token_channel.get_mutual_credit().state().balance.balance,
-                10i128
+                channel_pending_reset.remote_reset_terms.reset_token,
+                reset_token2
             );
+            assert_eq!(channel_pending_reset.reset_move_token.balance, 10i128);
         }
         _ => unreachable!(),
     };
@@ -374,6 +375,68 @@ async fn task_handler_pair_inconsistency<'a>(
         _ => unreachable!(),
     };
 
+    // Node1 crashes before the reset move token is sent.
+    // After restart, Node1 should send the same reset move token again:
+    let mut ephemeral1 = Ephemeral::new();
+    let funder_incoming = FunderIncoming::Init;
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+
+    let incoming_liveness_message = IncomingLivenessMessage::Online(pk2.clone());
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(incoming_liveness_message));
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+
+    assert_eq!(outgoing_comms.len(), 1);
+    match &outgoing_comms[0] {
+        FunderOutgoingComm::FriendMessage((pk, resent_friend_message)) => {
+            assert_eq!(pk, &pk2);
+            assert_eq!(resent_friend_message, &friend_message);
+        }
+        _ => unreachable!(),
+    };
+
+    // Node1: Accepting the same reset terms again only resends the reset move token:
+    let reset_friend_channel = ResetFriendChannel {
+        friend_public_key: pk2.clone(),
+        reset_token: reset_token2.clone(),
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[16; UID_LEN]),
+        FunderControl::ResetFriendChannel(reset_friend_channel),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+
+    assert_eq!(outgoing_comms.len(), 1);
+    match &outgoing_comms[0] {
+        FunderOutgoingComm::FriendMessage((pk, resent_friend_message)) => {
+            assert_eq!(pk, &pk2);
+            assert_eq!(resent_friend_message, &friend_message);
+        }
+        _ => unreachable!(),
+    };
+
     // Node2: Receive MoveToken (that resolves inconsistency) from Node1:
     let funder_incoming =
         FunderIncoming::Comm(FunderIncomingComm::Friend((pk1.clone(), friend_message)));
@@ -422,6 +485,18 @@ async fn task_handler_pair_inconsistency<'a>(
     .unwrap();
 
     // Inconsistency is resolved.
+    // The balance is set according to the reset terms, and is not applied twice:
+    let friend2 = state1.friends.get(&pk2).unwrap();
+    match &friend2.channel_status {
+        ChannelStatus::Consistent(token_channel) => {
+            assert_eq!(
+                token_channel.get_mutual_credit().state().balance.balance,
+                10i128
+            );
+        }
+        _ => unreachable!(),
+    };
+
     // Node1 sends his address:
     assert_eq!(outgoing_comms.len(), 2);
     let friend_message = match &outgoing_comms[1] {
@@ -498,23 +573,8 @@ async fn task_handler_pair_inconsistency<'a>(
 fn test_handler_pair_inconsistency() {
     let mut thread_pool = ThreadPool::new().unwrap();
 
-    let rng1 = DummyRandom::new(&[1u8]);
-    let pkcs8 = generate_pkcs8_key_pair(&rng1);
-    let identity1 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-    let (requests_sender1, identity_server1) = create_identity(identity1);
-    let mut identity_client1 = IdentityClient::new(requests_sender1);
-    thread_pool
-        .spawn(identity_server1.then(|_| future::ready(())))
-        .unwrap();
-
-    let rng2 = DummyRandom::new(&[2u8]);
-    let pkcs8 = generate_pkcs8_key_pair(&rng2);
-    let identity2 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-    let (requests_sender2, identity_server2) = create_identity(identity2);
-    let mut identity_client2 = IdentityClient::new(requests_sender2);
-    thread_pool
-        .spawn(identity_server2.then(|_| future::ready(())))
-        .unwrap();
+    let (mut identity_client1, _) = spawn_fixture_identity(1, &mut thread_pool);
+    let (mut identity_client2, _) = spawn_fixture_identity(2, &mut thread_pool);
 
     thread_pool.run(task_handler_pair_inconsistency(
         &mut identity_client1,
@@ -0,0 +1,232 @@
+use super::utils::{apply_control_and_deliver, apply_only, create_chain_net, deliver_all, TestNet};
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    FriendsRoute, FunderControl, FunderIncomingControl, FunderOutgoingControl, PaymentSubmission,
+    ResponseReceived, ResponseSendFundsResult, UserRequestSendFunds,
+};
+
+use crate::quarantine::StoredFunderState;
+use crate::types::FunderIncoming;
+
+/// Amount of nodes in the test network. Node i is a friend of node i + 1.
+const NUM_NODES: usize = 3;
+
+fn app_public_key(app_index: u8) -> PublicKey {
+    PublicKey::from(&[0xa0 + app_index; PUBLIC_KEY_LEN])
+}
+
+/// A request of node0 to pay node2 through node1.
+fn request_pay_node2(net: &TestNet, uid_index: u8, dest_payment: u128) -> FunderControl<u32> {
+    let route = FriendsRoute {
+        public_keys: net
+            .nodes
+            .iter()
+            .map(|node| node.public_key.clone())
+            .collect(),
+    };
+    FunderControl::RequestSendFunds(UserRequestSendFunds {
+        request_id: Uid::from(&[uid_index; UID_LEN]),
+        route,
+        invoice_id: InvoiceId::from(&[uid_index; INVOICE_ID_LEN]),
+        dest_payment,
+        opt_label: None,
+    })
+}
+
+/// Apply a control sent by an app to node0. The friend messages sent by node0 are not delivered.
+async fn apply_app_control<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    app_index: u8,
+    funder_control: FunderControl<u32>,
+) -> (
+    Vec<(usize, FunderIncoming<u32>)>,
+    Vec<FunderOutgoingControl<u32>>,
+) {
+    let incoming_control = FunderIncomingControl::new_from_app(
+        app_public_key(app_index),
+        Uid::from(&[0x50 + app_index; UID_LEN]),
+        funder_control,
+    );
+    await!(apply_only(
+        net,
+        rng,
+        0,
+        FunderIncoming::Control(incoming_control)
+    ))
+}
+
+/// List the submissions of an app at node0
+async fn list_submissions<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    app_index: u8,
+) -> Vec<PaymentSubmission> {
+    let request_id = Uid::from(&[0x60 + app_index; UID_LEN]);
+    let (undelivered, outgoing_control) = await!(apply_app_control(
+        net,
+        rng,
+        app_index,
+        FunderControl::ListPendingSubmissions(request_id)
+    ));
+    assert!(undelivered.is_empty());
+    let mut responses = outgoing_control
+        .into_iter()
+        .filter_map(|control| match control {
+            FunderOutgoingControl::ResponsePendingSubmissions(response) => Some(response),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(responses.len(), 1);
+    let response = responses.pop().unwrap();
+    assert_eq!(response.request_id, request_id);
+    response.submissions
+}
+
+fn responses_received(outgoing_control: &[FunderOutgoingControl<u32>]) -> Vec<ResponseReceived> {
+    outgoing_control
+        .iter()
+        .filter_map(|control| match control {
+            FunderOutgoingControl::ResponseReceived(response_received) => {
+                Some(response_received.clone())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Did the payment of a submission succeed?
+fn submission_succeeded(opt_result: &Option<ResponseSendFundsResult>) -> bool {
+    match opt_result {
+        Some(ResponseSendFundsResult::Success(_)) => true,
+        _ => false,
+    }
+}
+
+fn is_failure(opt_result: &Option<ResponseSendFundsResult>) -> bool {
+    match opt_result {
+        Some(ResponseSendFundsResult::Failure(_)) => true,
+        _ => false,
+    }
+}
+
+async fn task_handler_payment_submissions(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut net = await!(create_chain_net(identity_clients, 100, &mut rng));
+
+    // Nothing was submitted yet:
+    assert!(await!(list_submissions(&mut net, &mut rng, 0)).is_empty());
+
+    // The app disconnects after its payment was admitted, before the payment is sent to node1.
+    // The payment is discoverable, and is still pending:
+    let request_pay = request_pay_node2(&net, 40, 10);
+    let (undelivered, _) = await!(apply_app_control(&mut net, &mut rng, 0, request_pay));
+    let submissions = await!(list_submissions(&mut net, &mut rng, 0));
+    assert_eq!(submissions.len(), 1);
+    assert_eq!(submissions[0].request_id, Uid::from(&[40; UID_LEN]));
+    assert_eq!(submissions[0].app_public_key, app_public_key(0));
+    assert_eq!(submissions[0].dest_payment, 10);
+    assert_eq!(submissions[0].opt_result, None);
+
+    // The app submits the same payment again after reconnecting.
+    // The payment is not sent twice, and its submission is not changed:
+    let request_pay = request_pay_node2(&net, 40, 10);
+    let (_, outgoing_control) = await!(apply_app_control(&mut net, &mut rng, 0, request_pay));
+    let responses = responses_received(&outgoing_control);
+    assert_eq!(responses.len(), 1);
+    match &responses[0].result {
+        ResponseSendFundsResult::Failure(_) => {}
+        ResponseSendFundsResult::Success(_) => unreachable!(),
+    };
+    assert_eq!(await!(list_submissions(&mut net, &mut rng, 0)), submissions);
+
+    // A pending submission can not be acknowledged:
+    let ack = FunderControl::AckSubmission(Uid::from(&[40; UID_LEN]));
+    await!(apply_app_control(&mut net, &mut rng, 0, ack));
+    assert_eq!(await!(list_submissions(&mut net, &mut rng, 0)), submissions);
+
+    // Another app can not see the submission:
+    assert!(await!(list_submissions(&mut net, &mut rng, 1)).is_empty());
+
+    // The submission survives a restart of node0:
+    let serialized = bincode::serialize(&net.nodes[0].state).unwrap();
+    let stored_state: StoredFunderState<u32> = bincode::deserialize(&serialized).unwrap();
+    net.nodes[0].state = stored_state.quarantine_corrupt();
+    assert_eq!(await!(list_submissions(&mut net, &mut rng, 0)), submissions);
+
+    // The payment completes while the app is disconnected:
+    await!(deliver_all(&mut net, &mut rng, undelivered));
+    let submissions = await!(list_submissions(&mut net, &mut rng, 0));
+    assert_eq!(submissions.len(), 1);
+    assert!(submission_succeeded(&submissions[0].opt_result));
+
+    // Another app can not acknowledge the submission:
+    let ack = FunderControl::AckSubmission(Uid::from(&[40; UID_LEN]));
+    await!(apply_app_control(&mut net, &mut rng, 1, ack));
+    assert_eq!(await!(list_submissions(&mut net, &mut rng, 0)), submissions);
+
+    // After the app acknowledges the submission it is forgotten:
+    let ack = FunderControl::AckSubmission(Uid::from(&[40; UID_LEN]));
+    await!(apply_app_control(&mut net, &mut rng, 0, ack));
+    assert!(await!(list_submissions(&mut net, &mut rng, 0)).is_empty());
+
+    // The app disconnects after a payment is rejected at submission.
+    // The rejection is discoverable:
+    let request_pay = request_pay_node2(&net, 41, 1_000);
+    let (undelivered, _) = await!(apply_app_control(&mut net, &mut rng, 0, request_pay));
+    assert!(undelivered.is_empty());
+    let submissions = await!(list_submissions(&mut net, &mut rng, 0));
+    assert_eq!(submissions.len(), 1);
+    assert_eq!(submissions[0].request_id, Uid::from(&[41; UID_LEN]));
+    assert!(is_failure(&submissions[0].opt_result));
+    let ack = FunderControl::AckSubmission(Uid::from(&[41; UID_LEN]));
+    await!(apply_app_control(&mut net, &mut rng, 0, ack));
+
+    // Payments that were not sent by an app are not recorded:
+    let request_pay = request_pay_node2(&net, 42, 10);
+    await!(apply_control_and_deliver(
+        &mut net,
+        &mut rng,
+        0,
+        42,
+        request_pay
+    ));
+    assert!(net.nodes[0].state.submissions.is_empty());
+
+    // Nothing is recorded while the database is read only.
+    // The app will find no trace of the payment, and may safely submit it again:
+    await!(apply_only(
+        &mut net,
+        &mut rng,
+        0,
+        FunderIncoming::SetReadOnly(true)
+    ));
+    let request_pay = request_pay_node2(&net, 43, 10);
+    let (undelivered, outgoing_control) =
+        await!(apply_app_control(&mut net, &mut rng, 0, request_pay));
+    assert!(undelivered.is_empty());
+    assert_eq!(responses_received(&outgoing_control).len(), 1);
+    assert!(net.nodes[0].state.submissions.is_empty());
+}
+
+#[test]
+fn test_handler_payment_submissions() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=NUM_NODES as u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_payment_submissions(identity_clients));
+}
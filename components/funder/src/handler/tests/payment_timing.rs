@@ -0,0 +1,116 @@
+use super::utils::{
+    apply_control_and_deliver, create_chain_net, deliver_all, responses_received,
+    unmute_and_deliver,
+};
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    FriendsRoute, FunderControl, PaymentTiming, ResponseSendFundsResult, UserRequestSendFunds,
+};
+
+use crate::payment_timing::LatencyPercentiles;
+use crate::types::FunderIncoming;
+
+/// Amount of nodes in the test network. Node i is a friend of node i + 1.
+const NUM_NODES: usize = 3;
+
+/// Amount of ticks node0 waits for its payment to be answered.
+const LATENCY_TICKS: u64 = 5;
+
+async fn task_handler_payment_timing(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut net = await!(create_chain_net(identity_clients, 100, &mut rng));
+    assert!(net.nodes[0]
+        .ephemeral
+        .metrics()
+        .opt_payment_latency
+        .is_none());
+
+    // node2 is slow to answer. node0 pays node2 through node1:
+    net.opt_muted = Some(2);
+    let request_id = Uid::from(&[30; UID_LEN]);
+    let route = FriendsRoute {
+        public_keys: net
+            .nodes
+            .iter()
+            .map(|node| node.public_key.clone())
+            .collect(),
+    };
+    let user_request_send_funds = UserRequestSendFunds {
+        request_id,
+        route: route.clone(),
+        invoice_id: InvoiceId::from(&[30; INVOICE_ID_LEN]),
+        dest_payment: 10,
+        opt_label: None,
+    };
+    let controls = await!(apply_control_and_deliver(
+        &mut net,
+        &mut rng,
+        0,
+        30,
+        FunderControl::RequestSendFunds(user_request_send_funds)
+    ));
+    assert!(responses_received(&controls, 0).is_empty());
+    assert!(!net.held.is_empty());
+
+    // Time passes at node0 while the request waits at node2:
+    for _ in 0..LATENCY_TICKS {
+        let controls = await!(deliver_all(
+            &mut net,
+            &mut rng,
+            vec![(0, FunderIncoming::TimerTick(1))]
+        ));
+        assert!(responses_received(&controls, 0).is_empty());
+    }
+
+    // node2 answers. The response carries the timing of the payment:
+    let controls = await!(unmute_and_deliver(&mut net, &mut rng));
+    let responses = responses_received(&controls, 0);
+    assert_eq!(responses.len(), 1);
+    let response_received = &responses[0];
+    assert_eq!(response_received.request_id, request_id);
+    match &response_received.result {
+        ResponseSendFundsResult::Success(_) => {}
+        ResponseSendFundsResult::Failure(_) => unreachable!(),
+    };
+    assert_eq!(
+        response_received.opt_timing,
+        Some(PaymentTiming {
+            opt_ticks_to_sent: Some(0),
+            ticks_to_response: LATENCY_TICKS,
+            route,
+        })
+    );
+
+    // The latency is kept for the metrics, and the payment is no longer measured:
+    let metrics = net.nodes[0].ephemeral.metrics();
+    assert_eq!(metrics.payment_timings.len, 0);
+    assert_eq!(
+        metrics.opt_payment_latency,
+        Some(LatencyPercentiles {
+            p50: LATENCY_TICKS,
+            p90: LATENCY_TICKS,
+            p99: LATENCY_TICKS,
+        })
+    );
+}
+
+#[test]
+fn test_handler_payment_timing() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=NUM_NODES as u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_payment_timing(identity_clients));
+}
@@ -0,0 +1,221 @@
+use super::utils::{
+    apply_control_and_deliver, control_message, create_net, deliver_all, deliver_all_hops,
+    request_send_funds, HopControl, TestNet, TEST_PREWARM_TICKS,
+};
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    FunderControl, FunderOutgoingControl, PrewarmFailure, PrewarmFriend, PrewarmResult,
+    ResponseSendFundsResult, SetFriendRemoteMaxDebt,
+};
+
+use crate::ephemeral::EphemeralLimits;
+use crate::friend::ChannelStatus;
+use crate::token_channel::TcDirection;
+use crate::types::{FunderIncoming, FunderIncomingComm, IncomingLivenessMessage};
+
+/// Does the node hold the token of the channel with the other node?
+fn holds_token(net: &TestNet, index: usize) -> bool {
+    let friend = net.nodes[index]
+        .state
+        .friends
+        .get(&net.nodes[1 - index].public_key)
+        .unwrap();
+    match &friend.channel_status {
+        ChannelStatus::Consistent(token_channel) => match token_channel.get_direction() {
+            TcDirection::Incoming(_) => true,
+            TcDirection::Outgoing(_) => false,
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// Find the hop in which a node received a response for a certain request to send funds.
+fn response_received_hop(hop_controls: &[HopControl], request_id: &Uid) -> usize {
+    hop_controls
+        .iter()
+        .find_map(|(_index, hop, control)| match control {
+            FunderOutgoingControl::ResponseReceived(response_received)
+                if &response_received.request_id == request_id =>
+            {
+                match response_received.result {
+                    ResponseSendFundsResult::Success(_) => Some(*hop),
+                    ResponseSendFundsResult::Failure(_) => unreachable!(),
+                }
+            }
+            _ => None,
+        })
+        .unwrap()
+}
+
+/// Find the result of a pre-warm request
+fn prewarm_result(hop_controls: &[HopControl], request_id: &Uid) -> Option<PrewarmResult> {
+    hop_controls
+        .iter()
+        .find_map(|(_index, _hop, control)| match control {
+            FunderOutgoingControl::ResponsePrewarm(response_prewarm)
+                if &response_prewarm.request_id == request_id =>
+            {
+                Some(response_prewarm.result.clone())
+            }
+            _ => None,
+        })
+}
+
+/// node1 sends a configuration change to node0. At the end node0 holds the token.
+async fn pass_token_to_node0<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    uid_index: u8,
+    remote_max_debt: u128,
+) {
+    let set_friend_remote_max_debt = SetFriendRemoteMaxDebt {
+        friend_public_key: net.nodes[0].public_key.clone(),
+        remote_max_debt,
+        opt_expiry: None,
+    };
+    await!(apply_control_and_deliver(
+        net,
+        rng,
+        1,
+        uid_index,
+        FunderControl::SetFriendRemoteMaxDebt(set_friend_remote_max_debt)
+    ));
+    assert!(holds_token(net, 0));
+}
+
+/// node1 pays node0. Returns the amount of hops it took node1 to receive a response.
+async fn pay_node0<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    uid_index: u8,
+) -> usize {
+    let request_id = Uid::from(&[uid_index; UID_LEN]);
+    let funder_incoming = request_send_funds(net, &[1, 0], uid_index, 10);
+    let hop_controls = await!(deliver_all_hops(net, rng, vec![(1, funder_incoming)]));
+    response_received_hop(&hop_controls, &request_id)
+}
+
+async fn task_handler_prewarm(identity_client1: IdentityClient, identity_client2: IdentityClient) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    // node0 trusts node1, and allows node1 to send requests:
+    let mut net = await!(create_net(
+        vec![identity_client1, identity_client2],
+        &EphemeralLimits::default(),
+        &[(1, 0)],
+        100,
+        &mut rng
+    ));
+
+    // A cold payment: node1 first has to get the token from node0.
+    await!(pass_token_to_node0(&mut net, &mut rng, 30, 100));
+    let cold_hops = await!(pay_node0(&mut net, &mut rng, 31));
+    assert_eq!(cold_hops, 4);
+
+    // A pre-warmed payment: node1 gets the token in advance.
+    await!(pass_token_to_node0(&mut net, &mut rng, 32, 200));
+    let prewarm_friend = PrewarmFriend {
+        request_id: Uid::from(&[33; UID_LEN]),
+        friend_public_key: net.nodes[0].public_key.clone(),
+    };
+    let funder_incoming = control_message(33, FunderControl::PrewarmFriend(prewarm_friend.clone()));
+    let hop_controls = await!(deliver_all_hops(
+        &mut net,
+        &mut rng,
+        vec![(1, funder_incoming)]
+    ));
+    assert_eq!(
+        prewarm_result(&hop_controls, &prewarm_friend.request_id),
+        Some(PrewarmResult::Success)
+    );
+    assert!(holds_token(&net, 1));
+    // No credits were moved by the pre-warm:
+    assert!(!hop_controls
+        .iter()
+        .any(|(_index, _hop, control)| match control {
+            FunderOutgoingControl::ResponseReceived(_) => true,
+            _ => false,
+        }));
+
+    let warm_hops = await!(pay_node0(&mut net, &mut rng, 34));
+    assert_eq!(warm_hops, 2);
+
+    // Another pre-warm right away is rate limited:
+    await!(pass_token_to_node0(&mut net, &mut rng, 35, 300));
+    let prewarm_friend = PrewarmFriend {
+        request_id: Uid::from(&[36; UID_LEN]),
+        friend_public_key: net.nodes[0].public_key.clone(),
+    };
+    let funder_incoming = control_message(36, FunderControl::PrewarmFriend(prewarm_friend.clone()));
+    let hop_controls = await!(deliver_all_hops(
+        &mut net,
+        &mut rng,
+        vec![(1, funder_incoming)]
+    ));
+    assert_eq!(
+        prewarm_result(&hop_controls, &prewarm_friend.request_id),
+        Some(PrewarmResult::Failure(PrewarmFailure::RateLimited))
+    );
+    assert!(holds_token(&net, 0));
+
+    // After enough time has passed, the friend can be pre-warmed again:
+    for _ in 0..TEST_PREWARM_TICKS {
+        await!(deliver_all(
+            &mut net,
+            &mut rng,
+            vec![(1, FunderIncoming::TimerTick(1))]
+        ));
+    }
+    let prewarm_friend = PrewarmFriend {
+        request_id: Uid::from(&[37; UID_LEN]),
+        friend_public_key: net.nodes[0].public_key.clone(),
+    };
+    let funder_incoming = control_message(37, FunderControl::PrewarmFriend(prewarm_friend.clone()));
+    let hop_controls = await!(deliver_all_hops(
+        &mut net,
+        &mut rng,
+        vec![(1, funder_incoming)]
+    ));
+    assert_eq!(
+        prewarm_result(&hop_controls, &prewarm_friend.request_id),
+        Some(PrewarmResult::Success)
+    );
+
+    // A friend that is offline can not be pre-warmed:
+    let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Liveness(
+        IncomingLivenessMessage::Offline(net.nodes[0].public_key.clone()),
+    ));
+    await!(deliver_all(&mut net, &mut rng, vec![(1, funder_incoming)]));
+    let prewarm_friend = PrewarmFriend {
+        request_id: Uid::from(&[38; UID_LEN]),
+        friend_public_key: net.nodes[0].public_key.clone(),
+    };
+    let funder_incoming = control_message(38, FunderControl::PrewarmFriend(prewarm_friend.clone()));
+    let hop_controls = await!(deliver_all_hops(
+        &mut net,
+        &mut rng,
+        vec![(1, funder_incoming)]
+    ));
+    assert_eq!(
+        prewarm_result(&hop_controls, &prewarm_friend.request_id),
+        Some(PrewarmResult::Failure(PrewarmFailure::FriendOffline))
+    );
+}
+
+#[test]
+fn test_handler_prewarm() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let (identity_client1, _) = spawn_fixture_identity(1, &mut thread_pool);
+    let (identity_client2, _) = spawn_fixture_identity(2, &mut thread_pool);
+
+    thread_pool.run(task_handler_prewarm(identity_client1, identity_client2));
+}
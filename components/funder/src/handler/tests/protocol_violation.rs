@@ -0,0 +1,413 @@
+use super::utils::apply_funder_incoming;
+
+use std::cmp::Ordering;
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::{RandValue, RngContainer, RAND_VALUE_LEN};
+use crypto::identity::{compare_public_key, PublicKey, Signature, SIGNATURE_LEN};
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    AddFriend, FailureReason, FailureSendFunds, FriendMessage, FriendStatus, FriendTcOp,
+    FriendsRoute, FunderControl, FunderIncomingControl, MoveTokenRequest, OperationErrorCode,
+    RequestSendFunds, SetFriendStatus,
+};
+
+use crate::ephemeral::Ephemeral;
+use crate::report::create_report;
+use crate::state::FunderState;
+use crate::types::{
+    create_unsigned_move_token, sign_move_token, FunderIncoming, FunderIncomingComm,
+    FunderOutgoingComm, IncomingLivenessMessage,
+};
+
+use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+
+/// Creates the operations of an invalid move token, sent from `sender` to `receiver`.
+type CreateOperations = fn(sender: &PublicKey, receiver: &PublicKey) -> Vec<FriendTcOp>;
+
+/// A request from `sender` to `receiver`.
+fn request_op(sender: &PublicKey, receiver: &PublicKey, request_id: u8) -> FriendTcOp {
+    FriendTcOp::RequestSendFunds(RequestSendFunds {
+        request_id: Uid::from(&[request_id; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![sender.clone(), receiver.clone()],
+        },
+        dest_payment: 1,
+        invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+    })
+}
+
+/// A failure for a request of `receiver`, reported by `sender`.
+fn failure_op(sender: &PublicKey, request_id: u8) -> FriendTcOp {
+    FriendTcOp::FailureSendFunds(FailureSendFunds {
+        request_id: Uid::from(&[request_id; UID_LEN]),
+        reporting_public_key: sender.clone(),
+        reason: FailureReason::Unspecified,
+        rand_nonce: RandValue::from(&[0; RAND_VALUE_LEN]),
+        signature: Signature::from(&[0; SIGNATURE_LEN]),
+    })
+}
+
+/// Node2 sends a move token with invalid operations to Node1.
+/// `expected_violation` is the index and the error code Node1 is expected to report.
+async fn task_handler_protocol_violation<'a>(
+    identity_client1: &'a mut IdentityClient,
+    identity_client2: &'a mut IdentityClient,
+    create_operations: CreateOperations,
+    expected_violation: (usize, OperationErrorCode),
+) {
+    let (expected_index, expected_error_code) = expected_violation;
+
+    // Sort the identities. identity_client1 will be the first sender:
+    let pk1 = await!(identity_client1.request_public_key()).unwrap();
+    let pk2 = await!(identity_client2.request_public_key()).unwrap();
+    let (identity_client1, pk1, identity_client2, pk2) =
+        if compare_public_key(&pk1, &pk2) == Ordering::Less {
+            (identity_client1, pk1, identity_client2, pk2)
+        } else {
+            (identity_client2, pk2, identity_client1, pk1)
+        };
+
+    let relays1 = vec![dummy_named_relay_address(1)];
+    let mut state1 = FunderState::<u32>::new(pk1.clone(), relays1);
+    let mut ephemeral1 = Ephemeral::new();
+    let relays2 = vec![dummy_named_relay_address(2)];
+    let mut state2 = FunderState::<u32>::new(pk2.clone(), relays2);
+    let mut ephemeral2 = Ephemeral::new();
+
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+
+    // Initialize 1:
+    let funder_incoming = FunderIncoming::Init;
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+
+    // Initialize 2:
+    let funder_incoming = FunderIncoming::Init;
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+
+    // Node1: Add friend 2:
+    let add_friend = AddFriend {
+        friend_public_key: pk2.clone(),
+        relays: vec![dummy_relay_address(2)],
+        name: String::from("pk2"),
+        balance: 20i128,
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[11; UID_LEN]),
+        FunderControl::AddFriend(add_friend),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+
+    // Node1: Enable friend 2:
+    let set_friend_status = SetFriendStatus {
+        friend_public_key: pk2.clone(),
+        status: FriendStatus::Enabled,
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[12; UID_LEN]),
+        FunderControl::SetFriendStatus(set_friend_status),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+
+    // Node2: Add friend 1:
+    let add_friend = AddFriend {
+        friend_public_key: pk1.clone(),
+        relays: vec![dummy_relay_address(1)],
+        name: String::from("pk1"),
+        balance: -20i128,
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[13; UID_LEN]),
+        FunderControl::AddFriend(add_friend),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+
+    // Node2: enable friend 1:
+    let set_friend_status = SetFriendStatus {
+        friend_public_key: pk1.clone(),
+        status: FriendStatus::Enabled,
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[14; UID_LEN]),
+        FunderControl::SetFriendStatus(set_friend_status),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+
+    // Node1: Notify that Node2 is alive
+    let incoming_liveness_message = IncomingLivenessMessage::Online(pk2.clone());
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(incoming_liveness_message));
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+
+    assert_eq!(outgoing_comms.len(), 1);
+    let friend_message = match &outgoing_comms[0] {
+        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+            assert_eq!(pk, &pk2);
+            friend_message.clone()
+        }
+        _ => unreachable!(),
+    };
+
+    // Node2: Notify that Node1 is alive
+    let incoming_liveness_message = IncomingLivenessMessage::Online(pk1.clone());
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(incoming_liveness_message));
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+
+    // Node2: Receive MoveToken from Node1:
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Friend((pk1.clone(), friend_message)));
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+
+    assert_eq!(outgoing_comms.len(), 1);
+    let move_token_request = match &outgoing_comms[0] {
+        FunderOutgoingComm::FriendMessage((pk, FriendMessage::MoveTokenRequest(request))) => {
+            assert_eq!(pk, &pk1);
+            request.clone()
+        }
+        _ => unreachable!(),
+    };
+
+    // Node2 misbehaves: Its next move token contains invalid operations.
+    let move_token = move_token_request.friend_move_token;
+    let operations = create_operations(&pk2, &pk1);
+    let unsigned_move_token = create_unsigned_move_token(
+        operations,
+        move_token.opt_local_relays,
+        move_token.old_token,
+        move_token.local_public_key,
+        move_token.remote_public_key,
+        move_token.inconsistency_counter,
+        move_token.move_token_counter,
+        move_token.balance,
+        move_token.local_pending_debt,
+        move_token.remote_pending_debt,
+        move_token.rand_nonce,
+    );
+    let bad_move_token = await!(sign_move_token(unsigned_move_token, identity_client2)).unwrap();
+    let bad_new_token = bad_move_token.new_token.clone();
+    let friend_message = FriendMessage::MoveTokenRequest(MoveTokenRequest {
+        friend_move_token: bad_move_token,
+        token_wanted: move_token_request.token_wanted,
+    });
+
+    // Node1: Receive the invalid MoveToken from Node2:
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Friend((pk2.clone(), friend_message)));
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+
+    // Node1 explains the rejection, and then sends an inconsistency error:
+    assert_eq!(outgoing_comms.len(), 2);
+    let protocol_violation_message = match &outgoing_comms[0] {
+        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+            assert_eq!(pk, &pk2);
+            if let FriendMessage::ProtocolViolation(protocol_violation_report) = friend_message {
+                assert_eq!(protocol_violation_report.operation_index, expected_index);
+                assert_eq!(protocol_violation_report.error_code, expected_error_code);
+                assert_eq!(protocol_violation_report.new_token, bad_new_token);
+            } else {
+                unreachable!();
+            }
+            friend_message.clone()
+        }
+        _ => unreachable!(),
+    };
+    let inconsistency_message = match &outgoing_comms[1] {
+        FunderOutgoingComm::FriendMessage((pk, friend_message)) => {
+            assert_eq!(pk, &pk2);
+            if let FriendMessage::InconsistencyError(reset_terms) = friend_message {
+                assert_eq!(reset_terms.inconsistency_counter, 1);
+                assert_eq!(reset_terms.balance_for_reset, 20i128);
+            } else {
+                unreachable!();
+            }
+            friend_message.clone()
+        }
+        _ => unreachable!(),
+    };
+
+    // A peer that does not understand the ProtocolViolation message drops it, and only
+    // receives the InconsistencyError. It should behave as usual:
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Friend((pk1.clone(), inconsistency_message)));
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+
+    assert_eq!(outgoing_comms.len(), 1);
+    match &outgoing_comms[0] {
+        FunderOutgoingComm::FriendMessage((pk, FriendMessage::InconsistencyError(reset_terms))) => {
+            assert_eq!(pk, &pk1);
+            assert_eq!(reset_terms.inconsistency_counter, 1);
+            assert_eq!(reset_terms.balance_for_reset, -20i128);
+        }
+        _ => unreachable!(),
+    };
+    assert!(state2
+        .friends
+        .get(&pk1)
+        .unwrap()
+        .opt_protocol_violation
+        .is_none());
+
+    // Node2: Receive the ProtocolViolation message.
+    // It is only kept for diagnostics:
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Friend((pk1.clone(), protocol_violation_message)));
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+    assert!(outgoing_comms.is_empty());
+
+    let report2 = create_report(&state2, &ephemeral2);
+    let friend_report = report2.friends.get(&pk1).unwrap();
+    let protocol_violation_report = friend_report.opt_protocol_violation.as_ref().unwrap();
+    assert_eq!(protocol_violation_report.operation_index, expected_index);
+    assert_eq!(protocol_violation_report.error_code, expected_error_code);
+    assert_eq!(protocol_violation_report.new_token, bad_new_token);
+}
+
+fn run_handler_protocol_violation(
+    create_operations: CreateOperations,
+    expected_violation: (usize, OperationErrorCode),
+) {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let (mut identity_client1, _) = spawn_fixture_identity(1, &mut thread_pool);
+    let (mut identity_client2, _) = spawn_fixture_identity(2, &mut thread_pool);
+
+    thread_pool.run(task_handler_protocol_violation(
+        &mut identity_client1,
+        &mut identity_client2,
+        create_operations,
+        expected_violation,
+    ));
+}
+
+#[test]
+fn test_handler_protocol_violation() {
+    // The third operation is invalid, because requests are already disabled at that point:
+    run_handler_protocol_violation(
+        |_sender, _receiver| {
+            vec![
+                FriendTcOp::EnableRequests,
+                FriendTcOp::DisableRequests,
+                FriendTcOp::DisableRequests,
+            ]
+        },
+        (2, OperationErrorCode::RequestsAlreadyDisabled),
+    );
+}
+
+#[test]
+fn test_handler_protocol_violation_duplicate_request_id() {
+    run_handler_protocol_violation(
+        |sender, receiver| {
+            vec![
+                FriendTcOp::EnableRequests,
+                request_op(sender, receiver, 1),
+                request_op(sender, receiver, 1),
+            ]
+        },
+        (2, OperationErrorCode::DuplicateRequestId),
+    );
+}
+
+#[test]
+fn test_handler_protocol_violation_resolves_request_of_same_batch() {
+    run_handler_protocol_violation(
+        |sender, receiver| vec![request_op(sender, receiver, 1), failure_op(sender, 1)],
+        (1, OperationErrorCode::ResolvesRequestOfSameBatch),
+    );
+}
@@ -0,0 +1,197 @@
+use super::utils::apply_funder_incoming;
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    AddFriend, FunderControl, FunderIncomingControl, FunderOutgoingControl, RemoteMaxDebtExpiry,
+    SetFriendRemoteMaxDebt,
+};
+use proto::report::messages::{FriendReportMutation, FunderReportMutation};
+
+use crate::ephemeral::Ephemeral;
+use crate::state::FunderState;
+use crate::types::FunderIncoming;
+
+use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+
+/// Count the reported expiries of the remote max debt of a friend.
+fn count_expired_reports(
+    outgoing_control: &[FunderOutgoingControl<u32>],
+    friend_public_key: &PublicKey,
+) -> usize {
+    outgoing_control
+        .iter()
+        .filter_map(|control| match control {
+            FunderOutgoingControl::ReportMutations(report_mutations) => {
+                Some(report_mutations.mutations.iter())
+            }
+            _ => None,
+        })
+        .flatten()
+        .filter(|mutation| match mutation {
+            FunderReportMutation::FriendReportMutation((
+                public_key,
+                FriendReportMutation::RemoteMaxDebtExpired(_),
+            )) => public_key == friend_public_key,
+            _ => false,
+        })
+        .count()
+}
+
+/// Apply a few time ticks, and collect the outgoing control messages.
+async fn apply_ticks<'a>(
+    num_ticks: usize,
+    state: &'a mut FunderState<u32>,
+    ephemeral: &'a mut Ephemeral,
+    rng: &'a mut RngContainer<DummyRandom>,
+    identity_client: &'a mut IdentityClient,
+) -> Vec<FunderOutgoingControl<u32>> {
+    let mut outgoing_control = Vec::new();
+    for _ in 0..num_ticks {
+        let (_outgoing_comms, tick_outgoing_control) = await!(Box::pin(apply_funder_incoming(
+            FunderIncoming::TimerTick(1),
+            state,
+            ephemeral,
+            rng,
+            identity_client
+        )))
+        .unwrap();
+        outgoing_control.extend(tick_outgoing_control);
+    }
+    outgoing_control
+}
+
+async fn task_handler_remote_max_debt_expiry<'a>(identity_client: &'a mut IdentityClient) {
+    let local_pk = await!(identity_client.request_public_key()).unwrap();
+    // pk_a owes us 20 credits. pk_b owes us nothing:
+    let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+    let relays = vec![dummy_named_relay_address(0)];
+    let mut state = FunderState::<u32>::new(local_pk, relays);
+    let mut ephemeral = Ephemeral::new();
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+
+    let funder_incoming = FunderIncoming::Init;
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    )))
+    .unwrap();
+
+    // (public key, balance, expires after ticks, uid index):
+    let friends = vec![(pk_a.clone(), 20, 3, 11), (pk_b.clone(), -20, 5, 12)];
+    for (friend_public_key, balance, expires_after_ticks, uid_index) in friends {
+        let add_friend = AddFriend {
+            friend_public_key: friend_public_key.clone(),
+            relays: vec![dummy_relay_address(1)],
+            name: format!("friend{}", uid_index),
+            balance,
+        };
+        let incoming_control_message = FunderIncomingControl::new(
+            Uid::from(&[uid_index; UID_LEN]),
+            FunderControl::AddFriend(add_friend),
+        );
+        let funder_incoming = FunderIncoming::Control(incoming_control_message);
+        await!(Box::pin(apply_funder_incoming(
+            funder_incoming,
+            &mut state,
+            &mut ephemeral,
+            &mut rng,
+            identity_client
+        )))
+        .unwrap();
+
+        // Lend the friend 100 credits for a limited amount of ticks:
+        let set_friend_remote_max_debt = SetFriendRemoteMaxDebt {
+            friend_public_key,
+            remote_max_debt: 100,
+            opt_expiry: Some(RemoteMaxDebtExpiry {
+                expires_after_ticks,
+                post_expiry_max_debt: 0,
+            }),
+        };
+        let incoming_control_message = FunderIncomingControl::new(
+            Uid::from(&[uid_index + 10; UID_LEN]),
+            FunderControl::SetFriendRemoteMaxDebt(set_friend_remote_max_debt),
+        );
+        let funder_incoming = FunderIncoming::Control(incoming_control_message);
+        await!(Box::pin(apply_funder_incoming(
+            funder_incoming,
+            &mut state,
+            &mut ephemeral,
+            &mut rng,
+            identity_client
+        )))
+        .unwrap();
+    }
+
+    // Right before the expiry of pk_a, nothing changes:
+    let outgoing_control = await!(apply_ticks(
+        2,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    ));
+    assert_eq!(count_expired_reports(&outgoing_control, &pk_a), 0);
+    let friend_a = state.friends.get(&pk_a).unwrap();
+    assert_eq!(friend_a.wanted_remote_max_debt, 100);
+    assert_eq!(
+        friend_a.opt_remote_max_debt_expiry,
+        Some(RemoteMaxDebtExpiry {
+            expires_after_ticks: 1,
+            post_expiry_max_debt: 0,
+        })
+    );
+
+    // pk_a expires. The remote max debt is clamped to the debt pk_a already owes us:
+    let outgoing_control = await!(apply_ticks(
+        1,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    ));
+    assert_eq!(count_expired_reports(&outgoing_control, &pk_a), 1);
+    assert_eq!(count_expired_reports(&outgoing_control, &pk_b), 0);
+    let friend_a = state.friends.get(&pk_a).unwrap();
+    assert_eq!(friend_a.wanted_remote_max_debt, 20);
+    assert!(friend_a.opt_remote_max_debt_expiry.is_none());
+
+    // pk_b expires. pk_b owes us nothing, so the remote max debt drops to zero.
+    // The expiry of pk_a is not reported again:
+    let outgoing_control = await!(apply_ticks(
+        8,
+        &mut state,
+        &mut ephemeral,
+        &mut rng,
+        identity_client
+    ));
+    assert_eq!(count_expired_reports(&outgoing_control, &pk_a), 0);
+    assert_eq!(count_expired_reports(&outgoing_control, &pk_b), 1);
+    let friend_b = state.friends.get(&pk_b).unwrap();
+    assert_eq!(friend_b.wanted_remote_max_debt, 0);
+    assert!(friend_b.opt_remote_max_debt_expiry.is_none());
+    assert_eq!(state.friends.get(&pk_a).unwrap().wanted_remote_max_debt, 20);
+}
+
+#[test]
+fn test_handler_remote_max_debt_expiry() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let (mut identity_client, _) = spawn_fixture_identity(1, &mut thread_pool);
+
+    thread_pool.run(task_handler_remote_max_debt_expiry(&mut identity_client));
+}
@@ -0,0 +1,340 @@
+use super::utils::{node_apply, TestNode, TEST_MAX_NODE_RELAYS, TEST_RELAYS_DAMPING_TICKS};
+
+use std::cmp::Ordering;
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::identity::{compare_public_key, PublicKey};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
+use proto::funder::messages::{
+    AddFriend, FriendMessage, FriendStatus, FunderControl, FunderIncomingControl,
+    FunderOutgoingControl, SetFriendStatus,
+};
+use proto::report::messages::{FriendReportMutation, FunderReportMutation};
+
+use crate::ephemeral::Ephemeral;
+use crate::state::FunderState;
+use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+use crate::types::{
+    ChannelerConfig, FunderIncoming, FunderIncomingComm, FunderOutgoingComm,
+    IncomingLivenessMessage,
+};
+
+type TestOutput = (Vec<FunderOutgoingComm<u32>>, Vec<FunderOutgoingControl<u32>>);
+
+/// Extract the only friend message out of outgoing comms.
+fn single_friend_message(outgoing_comms: &[FunderOutgoingComm<u32>]) -> FriendMessage<u32> {
+    let mut friend_messages = outgoing_comms
+        .iter()
+        .filter_map(|outgoing_comm| match outgoing_comm {
+            FunderOutgoingComm::FriendMessage((_pk, friend_message)) => {
+                Some(friend_message.clone())
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(friend_messages.len(), 1);
+    friend_messages.pop().unwrap()
+}
+
+/// Collect the friend relays of all UpdateFriend Channeler configurations.
+fn update_friend_relays(outgoing_comms: &[FunderOutgoingComm<u32>]) -> Vec<Vec<RelayAddress<u32>>> {
+    outgoing_comms
+        .iter()
+        .filter_map(|outgoing_comm| match outgoing_comm {
+            FunderOutgoingComm::ChannelerConfig(ChannelerConfig::UpdateFriend(update_friend)) => {
+                Some(update_friend.friend_relays.clone())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Check if the reports contain a change of the remote relays of a friend.
+fn reports_remote_relays(
+    outgoing_control: &[FunderOutgoingControl<u32>],
+    friend_public_key: &PublicKey,
+    remote_relays: &[RelayAddress<u32>],
+) -> bool {
+    let expected_mutation = FunderReportMutation::FriendReportMutation((
+        friend_public_key.clone(),
+        FriendReportMutation::SetRemoteRelays(remote_relays.to_vec()),
+    ));
+    outgoing_control.iter().any(|control| match control {
+        FunderOutgoingControl::ReportMutations(report_mutations) => {
+            report_mutations.mutations.contains(&expected_mutation)
+        }
+        _ => false,
+    })
+}
+
+/// Create two friends that have exchanged move tokens. node1 holds the token.
+async fn create_node_pair<'a>(
+    identity_client1: IdentityClient,
+    identity_client2: IdentityClient,
+    relays1: Vec<NamedRelayAddress<u32>>,
+    rng: &'a mut RngContainer<DummyRandom>,
+) -> (TestNode, TestNode) {
+    // Sort the identities. identity_client1 will be the first sender:
+    let pk1 = await!(identity_client1.request_public_key()).unwrap();
+    let pk2 = await!(identity_client2.request_public_key()).unwrap();
+    let (identity_client1, pk1, identity_client2, pk2) =
+        if compare_public_key(&pk1, &pk2) == Ordering::Less {
+            (identity_client1, pk1, identity_client2, pk2)
+        } else {
+            (identity_client2, pk2, identity_client1, pk1)
+        };
+
+    let mut node1 = TestNode {
+        public_key: pk1.clone(),
+        identity_client: identity_client1,
+        state: FunderState::<u32>::new(pk1.clone(), relays1),
+        ephemeral: Ephemeral::new(),
+    };
+    let mut node2 = TestNode {
+        public_key: pk2.clone(),
+        identity_client: identity_client2,
+        state: FunderState::<u32>::new(pk2.clone(), vec![dummy_named_relay_address(2)]),
+        ephemeral: Ephemeral::new(),
+    };
+
+    await!(node_apply(&mut node1, rng, FunderIncoming::Init));
+    await!(node_apply(&mut node2, rng, FunderIncoming::Init));
+
+    // Add and enable friends:
+    for (node, friend_public_key, friend_relay, uid_index) in vec![
+        (&mut node1, pk2.clone(), dummy_relay_address(2), 11u8),
+        (&mut node2, pk1.clone(), dummy_relay_address(1), 13u8),
+    ] {
+        let add_friend = AddFriend {
+            friend_public_key: friend_public_key.clone(),
+            relays: vec![friend_relay],
+            name: String::from("friend"),
+            balance: 0i128,
+        };
+        let incoming_control_message = FunderIncomingControl::new(
+            Uid::from(&[uid_index; UID_LEN]),
+            FunderControl::AddFriend(add_friend),
+        );
+        let funder_incoming = FunderIncoming::Control(incoming_control_message);
+        await!(node_apply(node, rng, funder_incoming));
+
+        let set_friend_status = SetFriendStatus {
+            friend_public_key: friend_public_key.clone(),
+            status: FriendStatus::Enabled,
+        };
+        let incoming_control_message = FunderIncomingControl::new(
+            Uid::from(&[uid_index + 1; UID_LEN]),
+            FunderControl::SetFriendStatus(set_friend_status),
+        );
+        let funder_incoming = FunderIncoming::Control(incoming_control_message);
+        await!(node_apply(node, rng, funder_incoming));
+    }
+
+    // Node1: Notify that Node2 is alive:
+    let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Liveness(
+        IncomingLivenessMessage::Online(pk2.clone()),
+    ));
+    let (outgoing_comms, _) = await!(node_apply(&mut node1, rng, funder_incoming));
+    let mut friend_message = single_friend_message(&outgoing_comms);
+
+    // Node2: Notify that Node1 is alive:
+    let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Liveness(
+        IncomingLivenessMessage::Online(pk1.clone()),
+    ));
+    await!(node_apply(&mut node2, rng, funder_incoming));
+
+    // Pass move tokens back and forth, until node1 holds the token:
+    for _ in 0..2 {
+        let funder_incoming =
+            FunderIncoming::Comm(FunderIncomingComm::Friend((pk1.clone(), friend_message)));
+        let (outgoing_comms, _) = await!(node_apply(&mut node2, rng, funder_incoming));
+        let node2_message = single_friend_message(&outgoing_comms);
+
+        let funder_incoming =
+            FunderIncoming::Comm(FunderIncomingComm::Friend((pk2.clone(), node2_message)));
+        let (outgoing_comms, _) = await!(node_apply(&mut node1, rng, funder_incoming));
+        let has_friend_message = outgoing_comms.iter().any(|outgoing_comm| match outgoing_comm {
+            FunderOutgoingComm::FriendMessage(_) => true,
+            _ => false,
+        });
+        if !has_friend_message {
+            break;
+        }
+        friend_message = single_friend_message(&outgoing_comms);
+    }
+
+    (node1, node2)
+}
+
+/// node1 changes his relays (Adding or removing relay 11) and sends them to node2.
+/// Returns the output of node2 after receiving the move token.
+/// At the end, node1 holds the token again.
+async fn change_relays<'a>(
+    node1: &'a mut TestNode,
+    node2: &'a mut TestNode,
+    rng: &'a mut RngContainer<DummyRandom>,
+    uid_index: u8,
+) -> TestOutput {
+    let funder_control = if node1
+        .state
+        .relays
+        .iter()
+        .any(|relay| relay.public_key == dummy_relay_address(11).public_key)
+    {
+        FunderControl::RemoveRelay(dummy_relay_address(11).public_key)
+    } else {
+        FunderControl::AddRelay(dummy_named_relay_address(11))
+    };
+    let incoming_control_message =
+        FunderIncomingControl::new(Uid::from(&[uid_index; UID_LEN]), funder_control);
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, _) = await!(node_apply(node1, rng, funder_incoming));
+    let friend_message = single_friend_message(&outgoing_comms);
+
+    let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Friend((
+        node1.public_key.clone(),
+        friend_message,
+    )));
+    let (node2_comms, node2_control) = await!(node_apply(node2, rng, funder_incoming));
+    let friend_message = single_friend_message(&node2_comms);
+
+    // node1 receives the token back:
+    let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Friend((
+        node2.public_key.clone(),
+        friend_message,
+    )));
+    await!(node_apply(node1, rng, funder_incoming));
+
+    (node2_comms, node2_control)
+}
+
+async fn task_handler_remote_relays_damping(
+    identity_client1: IdentityClient,
+    identity_client2: IdentityClient,
+) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let (mut node1, mut node2) = await!(create_node_pair(
+        identity_client1,
+        identity_client2,
+        vec![dummy_named_relay_address(1)],
+        &mut rng
+    ));
+    let pk1 = node1.public_key.clone();
+
+    let relays_a = vec![dummy_relay_address(1)];
+    let relays_b = vec![dummy_relay_address(1), dummy_relay_address(11)];
+
+    // A single change of relays is applied immediately and reported:
+    let (outgoing_comms, outgoing_control) =
+        await!(change_relays(&mut node1, &mut node2, &mut rng, 20));
+    assert_eq!(update_friend_relays(&outgoing_comms), vec![relays_b.clone()]);
+    assert!(reports_remote_relays(&outgoing_control, &pk1, &relays_b));
+    let friend = node2.state.friends.get(&pk1).unwrap();
+    assert_eq!(friend.remote_relays, relays_b);
+    assert!(friend.opt_pending_remote_relays.is_none());
+
+    // Flapping relays during the damping period are kept pending:
+    for (i, expected_pending) in vec![
+        Some(relays_a.clone()),
+        None,
+        Some(relays_a.clone()),
+        None,
+        Some(relays_a.clone()),
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        let (outgoing_comms, outgoing_control) =
+            await!(change_relays(&mut node1, &mut node2, &mut rng, 21 + i as u8));
+        assert!(update_friend_relays(&outgoing_comms).is_empty());
+        assert!(!reports_remote_relays(&outgoing_control, &pk1, &relays_a));
+        let friend = node2.state.friends.get(&pk1).unwrap();
+        assert_eq!(friend.remote_relays, relays_b);
+        assert_eq!(friend.opt_pending_remote_relays, expected_pending);
+    }
+
+    // Pending relays are applied only after the damping period:
+    let mut update_friends = Vec::new();
+    for _ in 0..TEST_RELAYS_DAMPING_TICKS {
+        let friend = node2.state.friends.get(&pk1).unwrap();
+        assert_eq!(friend.remote_relays, relays_b);
+
+        let (outgoing_comms, outgoing_control) =
+            await!(node_apply(&mut node2, &mut rng, FunderIncoming::TimerTick(1)));
+        update_friends.extend(update_friend_relays(&outgoing_comms));
+        if !update_friends.is_empty() {
+            assert!(reports_remote_relays(&outgoing_control, &pk1, &relays_a));
+        }
+    }
+    // At most one reconnect during the damping window:
+    assert_eq!(update_friends, vec![relays_a.clone()]);
+    let friend = node2.state.friends.get(&pk1).unwrap();
+    assert_eq!(friend.remote_relays, relays_a);
+    assert!(friend.opt_pending_remote_relays.is_none());
+
+    // Another change is damped again, as relays were just changed:
+    let (outgoing_comms, _) = await!(change_relays(&mut node1, &mut node2, &mut rng, 30));
+    assert!(update_friend_relays(&outgoing_comms).is_empty());
+    let friend = node2.state.friends.get(&pk1).unwrap();
+    assert_eq!(friend.remote_relays, relays_a);
+    assert_eq!(friend.opt_pending_remote_relays, Some(relays_b.clone()));
+}
+
+#[test]
+fn test_handler_remote_relays_damping() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    let (identity_client1, identity_client2) = create_identity_clients(&mut thread_pool);
+    thread_pool.run(task_handler_remote_relays_damping(
+        identity_client1,
+        identity_client2,
+    ));
+}
+
+async fn task_handler_remote_relays_invalid(
+    identity_client1: IdentityClient,
+    identity_client2: IdentityClient,
+) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    // node1 uses more relays than node2 is willing to accept:
+    let num_relays = TEST_MAX_NODE_RELAYS as u8 + 1;
+    let relays1 = (1..=num_relays)
+        .map(dummy_named_relay_address)
+        .collect::<Vec<_>>();
+    let (node1, node2) = await!(create_node_pair(
+        identity_client1,
+        identity_client2,
+        relays1,
+        &mut rng
+    ));
+
+    // The oversized list of relays was rejected:
+    let friend = node2.state.friends.get(&node1.public_key).unwrap();
+    assert_eq!(friend.remote_relays, vec![dummy_relay_address(1)]);
+    assert!(friend.opt_pending_remote_relays.is_none());
+    assert!(!node2.ephemeral.relays_damping.is_damped(&node1.public_key));
+}
+
+#[test]
+fn test_handler_remote_relays_invalid() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    let (identity_client1, identity_client2) = create_identity_clients(&mut thread_pool);
+    thread_pool.run(task_handler_remote_relays_invalid(
+        identity_client1,
+        identity_client2,
+    ));
+}
+
+fn create_identity_clients(thread_pool: &mut ThreadPool) -> (IdentityClient, IdentityClient) {
+    let (identity_client1, _) = spawn_fixture_identity(1, thread_pool);
+    let (identity_client2, _) = spawn_fixture_identity(2, thread_pool);
+
+    (identity_client1, identity_client2)
+}
@@ -0,0 +1,237 @@
+use super::utils::{apply_only, control_message, create_nodes, TestNet};
+
+use std::collections::VecDeque;
+
+use futures::executor::ThreadPool;
+
+use common::mutable_state::MutableState;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::test_utils::DummyRandom;
+
+use proto::funder::messages::{
+    AddFriend, FriendStatus, FunderControl, FunderOutgoingControl, SetFriendRemoteMaxDebt,
+    SetFriendStatus,
+};
+use proto::report::messages::{ChannelStatusReport, DirectionReport, FunderReport, TcReport};
+
+use crate::ephemeral::EphemeralLimits;
+use crate::report::create_report;
+use crate::tests::utils::dummy_relay_address;
+use crate::types::{FunderIncoming, FunderIncomingComm, IncomingLivenessMessage};
+
+/// Maximum amount of messages we expect to be delivered in response to a single
+/// incoming message. Protects the test from looping forever.
+const MAX_DELIVERIES: usize = 64;
+
+/// A test network, together with the reports seen by the apps of the nodes.
+struct ReportNet {
+    test_net: TestNet,
+    /// The reports as seen by the apps, built only from the report mutations sent by the nodes.
+    reports: Vec<FunderReport<u32>>,
+    /// Friend messages that were sent and were not delivered yet: (destination index, message)
+    in_flight: VecDeque<(usize, FunderIncoming<u32>)>,
+}
+
+impl ReportNet {
+    /// The token channel report of a node, as seen by an app.
+    fn tc_report(&self, index: usize) -> &TcReport {
+        let friend_public_key = &self.test_net.nodes[1 - index].public_key;
+        let friend_report = self.reports[index].friends.get(friend_public_key).unwrap();
+        match &friend_report.channel_status {
+            ChannelStatusReport::Consistent(tc_report) => tc_report,
+            ChannelStatusReport::Inconsistent(_) => unreachable!(),
+        }
+    }
+
+    /// The report seen by the app of a node must match the state of the node.
+    fn check_report(&self, index: usize) {
+        let friend_public_key = &self.test_net.nodes[1 - index].public_key;
+        let friend = self.test_net.nodes[index]
+            .state
+            .friends
+            .get(friend_public_key)
+            .unwrap();
+        assert_eq!(
+            ChannelStatusReport::Consistent(self.tc_report(index).clone()),
+            ChannelStatusReport::from(&friend.channel_status)
+        );
+    }
+}
+
+/// Apply an incoming message to a node. Friend messages sent by the node are kept in flight, and
+/// report mutations are applied to the report of the node.
+async fn apply_incoming<'a>(
+    net: &'a mut ReportNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    index: usize,
+    funder_incoming: FunderIncoming<u32>,
+) {
+    let (undelivered, outgoing_control) =
+        await!(apply_only(&mut net.test_net, rng, index, funder_incoming));
+
+    for control in outgoing_control {
+        if let FunderOutgoingControl::ReportMutations(report_mutations) = control {
+            for mutation in &report_mutations.mutations {
+                net.reports[index].mutate(mutation).unwrap();
+            }
+        }
+    }
+    net.in_flight.extend(undelivered);
+}
+
+/// Deliver friend messages between the nodes until no more messages are sent.
+/// Returns the directions of node0, as seen by its app, after every delivery.
+async fn deliver_all<'a>(
+    net: &'a mut ReportNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+) -> Vec<DirectionReport> {
+    let mut directions = Vec::new();
+    let mut deliveries = 0;
+    while let Some((index, funder_incoming)) = net.in_flight.pop_front() {
+        deliveries += 1;
+        assert!(deliveries <= MAX_DELIVERIES);
+        await!(apply_incoming(net, rng, index, funder_incoming));
+        directions.push(net.tc_report(0).direction.clone());
+    }
+    directions
+}
+
+/// Create a pair of friends: node0 -- node1, that have exchanged move tokens.
+async fn create_net<'a>(
+    identity_clients: Vec<IdentityClient>,
+    rng: &'a mut RngContainer<DummyRandom>,
+) -> ReportNet {
+    let test_net = await!(create_nodes(identity_clients, &EphemeralLimits::default(), rng));
+    // The apps start from the reports of the initialized nodes:
+    let reports = test_net
+        .nodes
+        .iter()
+        .map(|node| create_report(&node.state, &node.ephemeral))
+        .collect();
+    let mut net = ReportNet {
+        test_net,
+        reports,
+        in_flight: VecDeque::new(),
+    };
+
+    // Add and enable friends:
+    for index in 0..2 {
+        let friend_public_key = net.test_net.nodes[1 - index].public_key.clone();
+        let add_friend = AddFriend {
+            friend_public_key: friend_public_key.clone(),
+            relays: vec![dummy_relay_address((1 - index) as u8)],
+            name: String::from("friend"),
+            balance: 0i128,
+        };
+        await!(apply_incoming(
+            &mut net,
+            rng,
+            index,
+            control_message(10, FunderControl::AddFriend(add_friend))
+        ));
+        let set_friend_status = SetFriendStatus {
+            friend_public_key,
+            status: FriendStatus::Enabled,
+        };
+        await!(apply_incoming(
+            &mut net,
+            rng,
+            index,
+            control_message(11, FunderControl::SetFriendStatus(set_friend_status))
+        ));
+    }
+
+    // Notify both friends that the other side is alive, and exchange move tokens:
+    for index in 0..2 {
+        let liveness_message =
+            IncomingLivenessMessage::Online(net.test_net.nodes[1 - index].public_key.clone());
+        let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Liveness(liveness_message));
+        await!(apply_incoming(&mut net, rng, index, funder_incoming));
+    }
+    await!(deliver_all(&mut net, rng));
+
+    net
+}
+
+async fn task_handler_report_direction(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut net = await!(create_net(identity_clients, &mut rng));
+    net.check_report(0);
+    net.check_report(1);
+
+    // Every configuration change is sent to the friend inside a move token. A node that does not
+    // hold the token has to ask for it first:
+    let mut directions = Vec::new();
+    let mut last_move_token_counter = net.tc_report(0).move_token_counter;
+    for (step, &index) in [0usize, 1, 1, 0, 0, 1].iter().enumerate() {
+        let was_outgoing = net.tc_report(index).direction.is_outgoing();
+
+        let set_friend_remote_max_debt = SetFriendRemoteMaxDebt {
+            friend_public_key: net.test_net.nodes[1 - index].public_key.clone(),
+            remote_max_debt: 10 * (step as u128 + 1),
+            opt_expiry: None,
+        };
+        let funder_control = FunderControl::SetFriendRemoteMaxDebt(set_friend_remote_max_debt);
+        await!(apply_incoming(
+            &mut net,
+            &mut rng,
+            index,
+            control_message(20 + step as u8, funder_control)
+        ));
+
+        if was_outgoing {
+            // The node sent its outgoing move token again, asking for the token back:
+            assert_eq!(
+                net.tc_report(index).direction,
+                DirectionReport::Outgoing { token_wanted: true }
+            );
+        }
+        net.check_report(index);
+
+        directions.extend(await!(deliver_all(&mut net, &mut rng)));
+
+        // The node sent the change, and does not want the token back:
+        assert_eq!(
+            net.tc_report(index).direction,
+            DirectionReport::Outgoing {
+                token_wanted: false
+            }
+        );
+        assert_eq!(
+            net.tc_report(1 - index).direction,
+            DirectionReport::Incoming
+        );
+        net.check_report(0);
+        net.check_report(1);
+
+        // Both sides agree about the last move token:
+        let move_token_counter = net.tc_report(0).move_token_counter;
+        assert_eq!(move_token_counter, net.tc_report(1).move_token_counter);
+        assert!(move_token_counter > last_move_token_counter);
+        last_move_token_counter = move_token_counter;
+        assert_eq!(net.tc_report(0).inconsistency_counter, 0);
+        assert_eq!(net.tc_report(1).inconsistency_counter, 0);
+    }
+
+    // The direction seen by the app of node0 flipped back and forth:
+    let num_flips = directions
+        .windows(2)
+        .filter(|pair| pair[0].is_outgoing() != pair[1].is_outgoing())
+        .count();
+    assert!(num_flips >= 5);
+}
+
+#[test]
+fn test_handler_report_direction() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=2u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_report_direction(identity_clients));
+}
@@ -0,0 +1,443 @@
+use super::utils::{handle_funder_incoming, MAX_DELIVERIES};
+
+use std::collections::VecDeque;
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::identity::{compare_public_key, PublicKey};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use database::{AtomicDb, CompactReport};
+
+use proto::funder::messages::{
+    AddFriend, FriendMessage, FriendStatus, FunderControl, FunderIncomingControl,
+    ResetFriendChannel, SetFriendStatus,
+};
+
+use crate::ephemeral::Ephemeral;
+use crate::friend::ChannelStatus;
+use crate::state::{FunderMutation, FunderState};
+use crate::types::{
+    FunderIncoming, FunderIncomingComm, FunderOutgoingComm, IncomingLivenessMessage,
+};
+
+use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+
+/// The point of the reset acceptance flow in which Node1 crashes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CrashPoint {
+    /// Before the acceptance of the reset terms was persisted
+    BeforePersist,
+    /// After the acceptance was persisted, but before the reset move token was sent
+    BeforeSend,
+    /// After the reset move token was sent, but before the acknowledgement of the remote side
+    /// was received
+    BeforeAck,
+}
+
+#[derive(Debug)]
+enum MemAtomicDbError {}
+
+/// An in memory AtomicDb, holding the persisted funder state of a node (used for testing).
+struct MemAtomicDb {
+    state: FunderState<u32>,
+}
+
+impl AtomicDb for MemAtomicDb {
+    type State = FunderState<u32>;
+    type Mutation = FunderMutation<u32>;
+    type Error = MemAtomicDbError;
+
+    fn get_state(&self) -> &Self::State {
+        &self.state
+    }
+
+    fn mutate_db(&mut self, mutations: &[Self::Mutation]) -> Result<(), Self::Error> {
+        // A batch is applied as a whole, or not at all:
+        let mut state = self.state.clone();
+        for mutation in mutations {
+            state.mutate(mutation);
+        }
+        self.state = state;
+        Ok(())
+    }
+
+    fn compact_db(&mut self) -> Result<CompactReport, Self::Error> {
+        Ok(CompactReport {
+            size_before: 0,
+            size_after: 0,
+            found_corruption: false,
+        })
+    }
+}
+
+/// A node with a single friend. Its funder state is only kept in its database, and its
+/// ephemeral state is lost when it crashes.
+struct CrashNode {
+    public_key: PublicKey,
+    friend_public_key: PublicKey,
+    identity_client: IdentityClient,
+    db: MemAtomicDb,
+    ephemeral: Ephemeral,
+}
+
+/// Handle an incoming message the way the funder loop does: All the mutations are persisted as
+/// one batch, and only then the outgoing friend messages are sent.
+/// If `opt_crash` is given, the node crashes before persisting or before sending.
+async fn handle_message<'a>(
+    node: &'a mut CrashNode,
+    rng: &'a RngContainer<DummyRandom>,
+    funder_incoming: FunderIncoming<u32>,
+    opt_crash: Option<CrashPoint>,
+) -> Vec<FriendMessage<u32>> {
+    // NOTE: We use Box::pin() in order to make sure we don't get a too large Future which will
+    // cause a stack overflow.
+    // See:  https://github.com/rust-lang-nursery/futures-rs/issues/1330
+    let funder_handler_output = await!(Box::pin(handle_funder_incoming(
+        funder_incoming,
+        node.db.get_state(),
+        &node.ephemeral,
+        rng,
+        &mut node.identity_client
+    )))
+    .unwrap();
+
+    if opt_crash == Some(CrashPoint::BeforePersist) {
+        return Vec::new();
+    }
+    node.db
+        .mutate_db(&funder_handler_output.funder_mutations)
+        .unwrap();
+    if opt_crash == Some(CrashPoint::BeforeSend) {
+        return Vec::new();
+    }
+
+    for mutation in &funder_handler_output.ephemeral_mutations {
+        node.ephemeral.mutate(mutation);
+    }
+
+    let mut friend_messages = Vec::new();
+    for outgoing_comm in funder_handler_output.outgoing_comms {
+        if let FunderOutgoingComm::FriendMessage((public_key, friend_message)) = outgoing_comm {
+            assert_eq!(public_key, node.friend_public_key);
+            friend_messages.push(friend_message);
+        }
+    }
+    friend_messages
+}
+
+/// Handle an incoming control message. See `handle_message`.
+async fn handle_control<'a>(
+    node: &'a mut CrashNode,
+    rng: &'a RngContainer<DummyRandom>,
+    uid_index: u8,
+    funder_control: FunderControl<u32>,
+    opt_crash: Option<CrashPoint>,
+) -> Vec<FriendMessage<u32>> {
+    let funder_incoming = FunderIncoming::Control(FunderIncomingControl::new(
+        Uid::from(&[uid_index; UID_LEN]),
+        funder_control,
+    ));
+    await!(handle_message(node, rng, funder_incoming, opt_crash))
+}
+
+/// Restart a crashed node: The ephemeral state is lost, and the funder state is loaded from the
+/// database. Returns the messages the node sends once it sees its friend online again.
+async fn restart<'a>(
+    node: &'a mut CrashNode,
+    rng: &'a RngContainer<DummyRandom>,
+) -> Vec<FriendMessage<u32>> {
+    node.ephemeral = Ephemeral::new();
+    await!(handle_message(node, rng, FunderIncoming::Init, None));
+
+    let incoming_liveness_message = IncomingLivenessMessage::Online(node.friend_public_key.clone());
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(incoming_liveness_message));
+    await!(handle_message(node, rng, funder_incoming, None))
+}
+
+/// Deliver friend messages between the two nodes, until no more messages are sent.
+/// `pending` contains (destination index, message) pairs.
+async fn exchange<'a>(
+    nodes: &'a mut [CrashNode],
+    rng: &'a RngContainer<DummyRandom>,
+    pending: Vec<(usize, FriendMessage<u32>)>,
+) {
+    let mut pending = pending.into_iter().collect::<VecDeque<_>>();
+    let mut deliveries = 0;
+
+    while let Some((index, friend_message)) = pending.pop_front() {
+        deliveries += 1;
+        assert!(deliveries <= MAX_DELIVERIES);
+
+        let origin_public_key = nodes[1 - index].public_key.clone();
+        let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Friend((
+            origin_public_key,
+            friend_message,
+        )));
+        let friend_messages = await!(handle_message(
+            &mut nodes[index],
+            rng,
+            funder_incoming,
+            None
+        ));
+        for friend_message in friend_messages {
+            pending.push_back((1 - index, friend_message));
+        }
+    }
+}
+
+/// The persisted balance of a node with its friend, if the channel is consistent.
+fn consistent_balance(node: &CrashNode) -> Option<i128> {
+    let friend = node
+        .db
+        .get_state()
+        .friends
+        .get(&node.friend_public_key)
+        .unwrap();
+    match &friend.channel_status {
+        ChannelStatus::Consistent(token_channel) => {
+            Some(token_channel.get_mutual_credit().state().balance.balance)
+        }
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => None,
+    }
+}
+
+/// Create two inconsistent friends. Node1 thinks its balance is 20, while Node2 thinks its
+/// balance is -10. Both nodes have exchanged their reset terms.
+async fn create_inconsistent_pair<'a>(
+    identity_client1: IdentityClient,
+    identity_client2: IdentityClient,
+    rng: &'a RngContainer<DummyRandom>,
+) -> Vec<CrashNode> {
+    let mut nodes = Vec::new();
+    for (index, identity_client) in vec![identity_client1, identity_client2]
+        .into_iter()
+        .enumerate()
+    {
+        let public_key = await!(identity_client.request_public_key()).unwrap();
+        let relays = vec![dummy_named_relay_address(index as u8 + 1)];
+        nodes.push(CrashNode {
+            public_key: public_key.clone(),
+            friend_public_key: public_key.clone(),
+            identity_client,
+            db: MemAtomicDb {
+                state: FunderState::<u32>::new(public_key, relays),
+            },
+            ephemeral: Ephemeral::new(),
+        });
+    }
+
+    // Sort the nodes. nodes[0] (Node1) will be the first sender:
+    nodes.sort_by(|node_a, node_b| compare_public_key(&node_a.public_key, &node_b.public_key));
+    nodes[0].friend_public_key = nodes[1].public_key.clone();
+    nodes[1].friend_public_key = nodes[0].public_key.clone();
+
+    for node in nodes.iter_mut() {
+        await!(handle_message(node, rng, FunderIncoming::Init, None));
+    }
+
+    // Note that Node2's initial balance should have been -20i128, but we assign -10i128 to cause
+    // an inconsistency:
+    for &(index, balance) in &[(0usize, 20i128), (1usize, -10i128)] {
+        let friend_public_key = nodes[index].friend_public_key.clone();
+        let add_friend = AddFriend {
+            friend_public_key: friend_public_key.clone(),
+            relays: vec![dummy_relay_address(2 - index as u8)],
+            name: String::from("friend"),
+            balance,
+        };
+        await!(handle_control(
+            &mut nodes[index],
+            rng,
+            11,
+            FunderControl::AddFriend(add_friend),
+            None
+        ));
+
+        let set_friend_status = SetFriendStatus {
+            friend_public_key,
+            status: FriendStatus::Enabled,
+        };
+        await!(handle_control(
+            &mut nodes[index],
+            rng,
+            12,
+            FunderControl::SetFriendStatus(set_friend_status),
+            None
+        ));
+    }
+
+    // Node1: Notify that Node2 is alive:
+    let incoming_liveness_message = IncomingLivenessMessage::Online(nodes[1].public_key.clone());
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(incoming_liveness_message));
+    let friend_messages = await!(handle_message(&mut nodes[0], rng, funder_incoming, None));
+
+    // Node2: Notify that Node1 is alive.
+    // The messages Node2 sends here are not delivered:
+    let incoming_liveness_message = IncomingLivenessMessage::Online(nodes[0].public_key.clone());
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(incoming_liveness_message));
+    await!(handle_message(&mut nodes[1], rng, funder_incoming, None));
+
+    // Node2 receives the move token of Node1, and the inconsistency is detected:
+    let pending = friend_messages
+        .into_iter()
+        .map(|friend_message| (1, friend_message))
+        .collect();
+    await!(exchange(&mut nodes, rng, pending));
+
+    assert!(consistent_balance(&nodes[0]).is_none());
+    assert!(consistent_balance(&nodes[1]).is_none());
+    nodes
+}
+
+async fn task_handler_reset_crash_recovery(
+    identity_client1: IdentityClient,
+    identity_client2: IdentityClient,
+    crash_point: CrashPoint,
+) {
+    let rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut nodes = await!(create_inconsistent_pair(
+        identity_client1,
+        identity_client2,
+        &rng
+    ));
+
+    // Node1 accepts the reset terms of Node2:
+    let pk2 = nodes[1].public_key.clone();
+    let reset_token2 = match &nodes[0]
+        .db
+        .get_state()
+        .friends
+        .get(&pk2)
+        .unwrap()
+        .channel_status
+    {
+        ChannelStatus::Inconsistent(channel_inconsistent) => channel_inconsistent
+            .opt_remote_reset_terms
+            .as_ref()
+            .unwrap()
+            .reset_token
+            .clone(),
+        _ => unreachable!(),
+    };
+    let reset_friend_channel = ResetFriendChannel {
+        friend_public_key: pk2.clone(),
+        reset_token: reset_token2.clone(),
+    };
+    let opt_crash = match crash_point {
+        CrashPoint::BeforePersist | CrashPoint::BeforeSend => Some(crash_point),
+        CrashPoint::BeforeAck => None,
+    };
+    let friend_messages = await!(handle_control(
+        &mut nodes[0],
+        &rng,
+        15,
+        FunderControl::ResetFriendChannel(reset_friend_channel.clone()),
+        opt_crash
+    ));
+
+    let friend_messages = match crash_point {
+        CrashPoint::BeforePersist => {
+            // Nothing was persisted. After restart Node1 is still inconsistent, and the user
+            // accepts the reset terms again:
+            assert!(friend_messages.is_empty());
+            let mut friend_messages = await!(restart(&mut nodes[0], &rng));
+            let friend2 = nodes[0].db.get_state().friends.get(&pk2).unwrap();
+            match &friend2.channel_status {
+                ChannelStatus::Inconsistent(_) => {}
+                _ => unreachable!(),
+            };
+
+            friend_messages.extend(await!(handle_control(
+                &mut nodes[0],
+                &rng,
+                16,
+                FunderControl::ResetFriendChannel(reset_friend_channel),
+                None
+            )));
+            friend_messages
+        }
+        CrashPoint::BeforeSend => {
+            // The acceptance was persisted. After restart Node1 sends the reset move token:
+            assert!(friend_messages.is_empty());
+            let friend2 = nodes[0].db.get_state().friends.get(&pk2).unwrap();
+            match &friend2.channel_status {
+                ChannelStatus::PendingReset(channel_pending_reset) => {
+                    assert_eq!(
+                        channel_pending_reset.remote_reset_terms.reset_token,
+                        reset_token2
+                    );
+                    assert_eq!(channel_pending_reset.reset_move_token.balance, 10i128);
+                }
+                _ => unreachable!(),
+            };
+            await!(restart(&mut nodes[0], &rng))
+        }
+        CrashPoint::BeforeAck => {
+            // Node2 receives the reset move token and acknowledges it, but Node1 crashes before
+            // receiving the acknowledgement:
+            assert_eq!(friend_messages.len(), 1);
+            let reset_message = friend_messages[0].clone();
+            let origin_public_key = nodes[0].public_key.clone();
+            let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Friend((
+                origin_public_key,
+                reset_message.clone(),
+            )));
+            let ack_messages = await!(handle_message(&mut nodes[1], &rng, funder_incoming, None));
+            assert!(!ack_messages.is_empty());
+            assert_eq!(consistent_balance(&nodes[1]), Some(-10i128));
+
+            // After restart Node1 sends the same reset move token again:
+            let friend_messages = await!(restart(&mut nodes[0], &rng));
+            assert!(friend_messages.contains(&reset_message));
+            friend_messages
+        }
+    };
+
+    // The nodes exchange messages until the channel is consistent again:
+    let pending = friend_messages
+        .into_iter()
+        .map(|friend_message| (1, friend_message))
+        .collect();
+    await!(exchange(&mut nodes, &rng, pending));
+
+    // The balance for reset is applied exactly once on both sides:
+    assert_eq!(consistent_balance(&nodes[0]), Some(10i128));
+    assert_eq!(consistent_balance(&nodes[1]), Some(-10i128));
+}
+
+fn run_handler_reset_crash_recovery(crash_point: CrashPoint) {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let (identity_client1, _) = spawn_fixture_identity(1, &mut thread_pool);
+    let (identity_client2, _) = spawn_fixture_identity(2, &mut thread_pool);
+
+    thread_pool.run(task_handler_reset_crash_recovery(
+        identity_client1,
+        identity_client2,
+        crash_point,
+    ));
+}
+
+#[test]
+fn test_handler_reset_crash_before_persist() {
+    run_handler_reset_crash_recovery(CrashPoint::BeforePersist);
+}
+
+#[test]
+fn test_handler_reset_crash_before_send() {
+    run_handler_reset_crash_recovery(CrashPoint::BeforeSend);
+}
+
+#[test]
+fn test_handler_reset_crash_before_ack() {
+    run_handler_reset_crash_recovery(CrashPoint::BeforeAck);
+}
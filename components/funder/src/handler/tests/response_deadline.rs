@@ -0,0 +1,424 @@
+use super::utils::{
+    apply_control_and_deliver, create_chain_net, deliver_all, node_apply, request_send_funds,
+    unmute_and_deliver, TestNet,
+};
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::identity::PublicKey;
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    FailureReason, FunderControl, FunderOutgoingControl, ResetFriendChannel,
+    ResponseSendFundsResult, SetFriendResponseDeadline,
+};
+use proto::report::messages::{FriendDeadlinesReport, FriendReportMutation, FunderReportMutation};
+
+use crate::ephemeral::Ephemeral;
+use crate::friend::ChannelStatus;
+use crate::mutual_credit::types::McBalance;
+use crate::quarantine::StoredFunderState;
+use crate::report::create_report;
+use crate::token_channel::TcDirection;
+use crate::types::{FunderIncoming, FunderIncomingComm, IncomingLivenessMessage};
+
+/// Amount of nodes in the test network. Node i is a friend of node i + 1.
+const NUM_NODES: usize = 4;
+
+/// Amount of ticks node1 waits for node2 to answer a forwarded request.
+const DEADLINE_TICKS: u64 = 4;
+
+fn token_channel_balance(net: &TestNet, index: usize, friend_index: usize) -> McBalance {
+    let friend = net.nodes[index]
+        .state
+        .friends
+        .get(&net.nodes[friend_index].public_key)
+        .unwrap();
+    match &friend.channel_status {
+        ChannelStatus::Consistent(token_channel) => {
+            token_channel.get_mutual_credit().state().balance.clone()
+        }
+        _ => unreachable!(),
+    }
+}
+
+/// Does the node hold the token of the channel with the friend?
+fn holds_token(net: &TestNet, index: usize, friend_index: usize) -> bool {
+    let friend = net.nodes[index]
+        .state
+        .friends
+        .get(&net.nodes[friend_index].public_key)
+        .unwrap();
+    match &friend.channel_status {
+        ChannelStatus::Consistent(token_channel) => match token_channel.get_direction() {
+            TcDirection::Incoming(_) => true,
+            TcDirection::Outgoing(_) => false,
+        },
+        _ => unreachable!(),
+    }
+}
+
+/// Collect the results of requests to send funds received by a node
+fn responses_received(
+    controls: &[(usize, FunderOutgoingControl<u32>)],
+    index: usize,
+) -> Vec<(Uid, ResponseSendFundsResult)> {
+    controls
+        .iter()
+        .filter_map(|(control_index, control)| match control {
+            FunderOutgoingControl::ResponseReceived(response_received)
+                if *control_index == index =>
+            {
+                Some((
+                    response_received.request_id,
+                    response_received.result.clone(),
+                ))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Collect the deadlines of a friend reported by a node
+fn deadlines_reported(
+    controls: &[(usize, FunderOutgoingControl<u32>)],
+    index: usize,
+    friend_public_key: &PublicKey,
+) -> Vec<FriendDeadlinesReport> {
+    controls
+        .iter()
+        .filter(|(control_index, _)| *control_index == index)
+        .flat_map(|(_, control)| match control {
+            FunderOutgoingControl::ReportMutations(report_mutations) => {
+                report_mutations.mutations.clone()
+            }
+            _ => Vec::new(),
+        })
+        .filter_map(|report_mutation| match report_mutation {
+            FunderReportMutation::FriendReportMutation((
+                public_key,
+                FriendReportMutation::SetDeadlines(friend_deadlines),
+            )) => {
+                if &public_key == friend_public_key {
+                    Some(friend_deadlines)
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The deadlines of a friend, as they appear in the full report of a node
+fn report_deadlines(net: &TestNet, index: usize, friend_index: usize) -> FriendDeadlinesReport {
+    let node = &net.nodes[index];
+    let report = create_report(&node.state, &node.ephemeral);
+    report
+        .friends
+        .get(&net.nodes[friend_index].public_key)
+        .unwrap()
+        .deadlines
+        .clone()
+}
+
+/// Restart a node: Its state is loaded from storage, and its ephemeral state is lost.
+/// The node is notified again that its friends are online.
+async fn restart_node<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    index: usize,
+) -> Vec<(usize, FunderOutgoingControl<u32>)> {
+    let serialized = bincode::serialize(&net.nodes[index].state).unwrap();
+    let stored_state: StoredFunderState<u32> = bincode::deserialize(&serialized).unwrap();
+    net.nodes[index].state = stored_state.quarantine_corrupt();
+    net.nodes[index].ephemeral = Ephemeral::new();
+    await!(node_apply(&mut net.nodes[index], rng, FunderIncoming::Init));
+
+    let friend_indices = [index.checked_sub(1), Some(index + 1)];
+    let incoming = friend_indices
+        .iter()
+        .filter_map(|opt_friend_index| *opt_friend_index)
+        .filter(|friend_index| *friend_index < NUM_NODES)
+        .map(|friend_index| {
+            (
+                index,
+                FunderIncoming::Comm(FunderIncomingComm::Liveness(
+                    IncomingLivenessMessage::Online(net.nodes[friend_index].public_key.clone()),
+                )),
+            )
+        })
+        .collect::<Vec<_>>();
+    await!(deliver_all(net, rng, incoming))
+}
+
+/// The node at `friend_index` accepts the reset terms of the node at `index`, after the node at
+/// `index` has found their token channel inconsistent.
+async fn reset_with_local_terms<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    index: usize,
+    friend_index: usize,
+) -> Vec<(usize, FunderOutgoingControl<u32>)> {
+    let friend = net.nodes[index]
+        .state
+        .friends
+        .get(&net.nodes[friend_index].public_key)
+        .unwrap();
+    let reset_token = match &friend.channel_status {
+        ChannelStatus::Inconsistent(channel_inconsistent) => {
+            channel_inconsistent.local_reset_terms.reset_token.clone()
+        }
+        _ => unreachable!(),
+    };
+    let reset_friend_channel = ResetFriendChannel {
+        friend_public_key: net.nodes[index].public_key.clone(),
+        reset_token,
+    };
+    await!(apply_control_and_deliver(
+        net,
+        rng,
+        friend_index,
+        40,
+        FunderControl::ResetFriendChannel(reset_friend_channel)
+    ))
+}
+
+/// Set a response deadline at node1 for requests forwarded to node2, mute node2 and send a
+/// payment from node0 to node3. Returns the request id of the payment.
+async fn send_stuck_request<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+) -> Uid {
+    // node1 waits a limited amount of ticks for node2 to answer forwarded requests:
+    let set_friend_response_deadline = SetFriendResponseDeadline {
+        friend_public_key: net.nodes[2].public_key.clone(),
+        opt_deadline_ticks: Some(DEADLINE_TICKS),
+    };
+    await!(apply_control_and_deliver(
+        net,
+        rng,
+        1,
+        30,
+        FunderControl::SetFriendResponseDeadline(set_friend_response_deadline)
+    ));
+    assert!(holds_token(net, 1, 2));
+
+    // node2 stops answering. node0 pays node3 through node1 and node2:
+    net.opt_muted = Some(2);
+    let request_id = Uid::from(&[31; UID_LEN]);
+    let incoming = vec![(0, request_send_funds(net, &[0, 1, 2, 3], 31, 10))];
+    let controls = await!(deliver_all(net, rng, incoming));
+    assert!(responses_received(&controls, 0).is_empty());
+    assert!(!net.held.is_empty());
+    assert!(token_channel_balance(net, 0, 1).local_pending_debt > 0);
+    request_id
+}
+
+async fn task_handler_response_deadline(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut net = await!(create_chain_net(identity_clients, 100, &mut rng));
+    let balance12 = token_channel_balance(&net, 1, 2).balance;
+    let balance21 = token_channel_balance(&net, 2, 1).balance;
+    let request_id = await!(send_stuck_request(&mut net, &mut rng));
+
+    // No failure is sent before the deadline passes:
+    for _ in 0..DEADLINE_TICKS - 1 {
+        let controls = await!(deliver_all(
+            &mut net,
+            &mut rng,
+            vec![(1, FunderIncoming::TimerTick(1))]
+        ));
+        assert!(responses_received(&controls, 0).is_empty());
+    }
+
+    // The deadline passes. node1 reports a failure to node0:
+    let controls = await!(deliver_all(
+        &mut net,
+        &mut rng,
+        vec![(1, FunderIncoming::TimerTick(1))]
+    ));
+    assert_eq!(
+        responses_received(&controls, 0),
+        vec![(
+            request_id,
+            ResponseSendFundsResult::Failure((
+                net.nodes[1].public_key.clone(),
+                FailureReason::Unspecified,
+            ))
+        )]
+    );
+    let balance01 = token_channel_balance(&net, 0, 1);
+    assert_eq!(balance01.balance, 0);
+    assert_eq!(balance01.local_pending_debt, 0);
+
+    // node2 comes back and answers late. node1 rejects the response, and does not forward it:
+    let controls = await!(unmute_and_deliver(&mut net, &mut rng));
+    assert!(responses_received(&controls, 0).is_empty());
+    assert!(net.nodes[1].state.ready_receipts.is_empty());
+    assert!(!net.nodes[1].state.expired_requests.contains(&request_id));
+
+    // The channel is reset using the terms of node1. No credits move between node1 and node2:
+    let controls = await!(reset_with_local_terms(&mut net, &mut rng, 1, 2));
+    assert!(responses_received(&controls, 0).is_empty());
+    assert_eq!(token_channel_balance(&net, 1, 2).balance, balance12);
+    assert_eq!(token_channel_balance(&net, 2, 1).balance, balance21);
+    assert_eq!(token_channel_balance(&net, 0, 1).balance, 0);
+}
+
+#[test]
+fn test_handler_response_deadline() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=NUM_NODES as u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_response_deadline(identity_clients));
+}
+
+async fn task_handler_response_deadline_ticks_jump(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut net = await!(create_chain_net(identity_clients, 100, &mut rng));
+    let request_id = await!(send_stuck_request(&mut net, &mut rng));
+
+    // Many ticks elapse at once (For example, the host was suspended).
+    // The deadline has passed, and node1 reports a single failure to node0:
+    let controls = await!(deliver_all(
+        &mut net,
+        &mut rng,
+        vec![(1, FunderIncoming::TimerTick(10_000))]
+    ));
+    assert_eq!(
+        responses_received(&controls, 0),
+        vec![(
+            request_id,
+            ResponseSendFundsResult::Failure((
+                net.nodes[1].public_key.clone(),
+                FailureReason::Unspecified,
+            ))
+        )]
+    );
+    assert_eq!(token_channel_balance(&net, 0, 1).local_pending_debt, 0);
+
+    // The failure is not reported again:
+    let controls = await!(deliver_all(
+        &mut net,
+        &mut rng,
+        vec![(1, FunderIncoming::TimerTick(10_000))]
+    ));
+    assert!(responses_received(&controls, 0).is_empty());
+}
+
+#[test]
+fn test_handler_response_deadline_ticks_jump() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=NUM_NODES as u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_response_deadline_ticks_jump(identity_clients));
+}
+
+async fn task_handler_response_deadline_restart(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut net = await!(create_chain_net(identity_clients, 100, &mut rng));
+    let balance12 = token_channel_balance(&net, 1, 2).balance;
+    let balance21 = token_channel_balance(&net, 2, 1).balance;
+    let request_id = await!(send_stuck_request(&mut net, &mut rng));
+
+    // The deadline passes. node1 reports a failure to node0:
+    let controls = await!(deliver_all(
+        &mut net,
+        &mut rng,
+        vec![(1, FunderIncoming::TimerTick(DEADLINE_TICKS))]
+    ));
+    assert_eq!(responses_received(&controls, 0).len(), 1);
+    assert!(net.nodes[1].state.expired_requests.contains(&request_id));
+
+    // node1 restarts, and still remembers that the deadline of the request has passed:
+    let mut controls = await!(restart_node(&mut net, &mut rng, 1));
+    assert!(net.nodes[1].state.expired_requests.contains(&request_id));
+
+    // node2 comes back and answers late. The response is rejected. It is neither forwarded nor
+    // taken as a response to a payment of node1:
+    controls.extend(await!(unmute_and_deliver(&mut net, &mut rng)));
+    controls.extend(await!(reset_with_local_terms(&mut net, &mut rng, 1, 2)));
+    assert!(responses_received(&controls, 0).is_empty());
+    assert!(responses_received(&controls, 1).is_empty());
+    assert_eq!(token_channel_balance(&net, 1, 2).balance, balance12);
+    assert_eq!(token_channel_balance(&net, 2, 1).balance, balance21);
+    assert_eq!(token_channel_balance(&net, 0, 1).balance, 0);
+    assert!(net.nodes[1].state.ready_receipts.is_empty());
+    assert!(net.nodes[1].state.expired_requests.is_empty());
+}
+
+#[test]
+fn test_handler_response_deadline_restart() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=NUM_NODES as u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_response_deadline_restart(identity_clients));
+}
+
+async fn task_handler_response_deadline_report(identity_clients: Vec<IdentityClient>) {
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+    let mut net = await!(create_chain_net(identity_clients, 100, &mut rng));
+    assert_eq!(
+        report_deadlines(&net, 1, 2),
+        FriendDeadlinesReport::default()
+    );
+
+    await!(send_stuck_request(&mut net, &mut rng));
+    assert_eq!(
+        report_deadlines(&net, 1, 2).opt_response_timeout_ticks,
+        Some(DEADLINE_TICKS)
+    );
+
+    // The reported deadline decreases in steps of TEST_DEADLINES_GRANULARITY_TICKS (2 ticks).
+    // When the deadline passes, the reported deadline is cleared:
+    let pk2 = net.nodes[2].public_key.clone();
+    let expected = vec![
+        // (Report mutations, deadline in the full report)
+        (vec![], Some(4)),
+        (vec![Some(2)], Some(2)),
+        (vec![], Some(2)),
+        (vec![None], None),
+    ];
+    for (expected_mutations, expected_report) in expected {
+        let controls = await!(deliver_all(
+            &mut net,
+            &mut rng,
+            vec![(1, FunderIncoming::TimerTick(1))]
+        ));
+        let reported = deadlines_reported(&controls, 1, &pk2)
+            .into_iter()
+            .map(|friend_deadlines| friend_deadlines.opt_response_timeout_ticks)
+            .collect::<Vec<_>>();
+        assert_eq!(reported, expected_mutations);
+        assert_eq!(
+            report_deadlines(&net, 1, 2).opt_response_timeout_ticks,
+            expected_report
+        );
+    }
+}
+
+#[test]
+fn test_handler_response_deadline_report() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let identity_clients = (1..=NUM_NODES as u8)
+        .map(|seed| spawn_fixture_identity(seed, &mut thread_pool).0)
+        .collect::<Vec<_>>();
+
+    thread_pool.run(task_handler_response_deadline_report(identity_clients));
+}
@@ -1,42 +1,90 @@
 use identity::IdentityClient;
+use std::collections::VecDeque;
 use std::fmt::Debug;
 
 use common::canonical_serialize::CanonicalSerialize;
-use crypto::crypto_rand::CryptoRandom;
+use crypto::crypto_rand::{CryptoRandom, RngContainer};
+use crypto::identity::PublicKey;
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
 
-use proto::funder::messages::FunderOutgoingControl;
+use proto::funder::messages::{
+    AddFriend, FriendMessage, FriendStatus, FriendsRoute, FunderControl, FunderIncomingControl,
+    FunderOutgoingControl, RequestsStatus, ResponseReceived, ResponseSendFundsResult,
+    SetFriendRemoteMaxDebt, SetFriendStatus, SetRequestsStatus, UserRequestSendFunds,
+};
 
-use crate::ephemeral::Ephemeral;
+use crate::ephemeral::{Ephemeral, EphemeralLimits};
 use crate::handler::handler::{funder_handle_message, FunderHandlerError, FunderHandlerOutput};
 use crate::state::FunderState;
-use crate::types::{FunderIncoming, FunderOutgoingComm};
+use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+use crate::types::{
+    FunderIncoming, FunderIncomingComm, FunderOutgoingComm, IncomingLivenessMessage,
+};
 
-const TEST_MAX_NODE_RELAYS: usize = 16;
+pub const TEST_MAX_NODE_RELAYS: usize = 16;
+const TEST_MIN_OPERATIONS_IN_BATCH: usize = 4;
 const TEST_MAX_OPERATIONS_IN_BATCH: usize = 16;
 const TEST_MAX_PENDING_USER_REQUESTS: usize = 16;
+pub const TEST_RELAYS_DAMPING_TICKS: usize = 4;
+pub const TEST_PREWARM_TICKS: usize = 4;
+pub const TEST_RELIABILITY_DECAY_TICKS: usize = 8;
+pub const TEST_DEADLINES_GRANULARITY_TICKS: usize = 2;
 
-/// A helper function. Applies an incoming funder message, updating state and ephemeral
-/// accordingly:
-pub async fn apply_funder_incoming<'a, B, R>(
+/// Maximum amount of messages we expect to be delivered in response to a single
+/// incoming message. Protects the test from looping forever.
+pub const MAX_DELIVERIES: usize = 256;
+
+/// A helper function. Handles an incoming funder message, without applying the resulting
+/// mutations:
+pub async fn handle_funder_incoming<'a, B, R>(
     funder_incoming: FunderIncoming<B>,
-    state: &'a mut FunderState<B>,
-    ephemeral: &'a mut Ephemeral,
-    rng: &'a mut R,
+    state: &'a FunderState<B>,
+    ephemeral: &'a Ephemeral,
+    rng: &'a R,
     identity_client: &'a mut IdentityClient,
-) -> Result<(Vec<FunderOutgoingComm<B>>, Vec<FunderOutgoingControl<B>>), FunderHandlerError>
+) -> Result<FunderHandlerOutput<B>, FunderHandlerError>
 where
     B: Clone + PartialEq + Eq + CanonicalSerialize + Debug + 'a,
     R: CryptoRandom + 'a,
 {
-    let funder_handler_output = await!(funder_handle_message(
+    await!(funder_handle_message(
         identity_client,
         rng,
         state.clone(),
         ephemeral.clone(),
         TEST_MAX_NODE_RELAYS,
+        TEST_MIN_OPERATIONS_IN_BATCH,
         TEST_MAX_OPERATIONS_IN_BATCH,
         TEST_MAX_PENDING_USER_REQUESTS,
+        TEST_RELAYS_DAMPING_TICKS,
+        TEST_PREWARM_TICKS,
+        TEST_RELIABILITY_DECAY_TICKS,
+        TEST_DEADLINES_GRANULARITY_TICKS,
         funder_incoming
+    ))
+}
+
+/// A helper function. Applies an incoming funder message, updating state and ephemeral
+/// accordingly:
+pub async fn apply_funder_incoming<'a, B, R>(
+    funder_incoming: FunderIncoming<B>,
+    state: &'a mut FunderState<B>,
+    ephemeral: &'a mut Ephemeral,
+    rng: &'a mut R,
+    identity_client: &'a mut IdentityClient,
+) -> Result<(Vec<FunderOutgoingComm<B>>, Vec<FunderOutgoingControl<B>>), FunderHandlerError>
+where
+    B: Clone + PartialEq + Eq + CanonicalSerialize + Debug + 'a,
+    R: CryptoRandom + 'a,
+{
+    let funder_handler_output = await!(handle_funder_incoming(
+        funder_incoming,
+        state,
+        ephemeral,
+        rng,
+        identity_client
     ))?;
 
     let FunderHandlerOutput {
@@ -58,3 +106,361 @@ where
 
     Ok((outgoing_comms, outgoing_control))
 }
+
+/// A funder in a test network.
+pub struct TestNode {
+    pub public_key: PublicKey,
+    pub identity_client: IdentityClient,
+    pub state: FunderState<u32>,
+    pub ephemeral: Ephemeral,
+}
+
+/// Nodes that send friend messages directly to each other.
+pub struct TestNet {
+    pub nodes: Vec<TestNode>,
+    /// Messages sent to this node are held until the node is unmuted.
+    pub opt_muted: Option<usize>,
+    /// Held messages: (destination index, incoming message)
+    pub held: Vec<(usize, FunderIncoming<u32>)>,
+    /// All the friend messages that were sent between the nodes
+    pub friend_messages: Vec<FriendMessage<u32>>,
+}
+
+impl TestNet {
+    pub fn new(nodes: Vec<TestNode>) -> TestNet {
+        TestNet {
+            nodes,
+            opt_muted: None,
+            held: Vec::new(),
+            friend_messages: Vec::new(),
+        }
+    }
+}
+
+/// An outgoing control message, together with the index of the node that sent it, and the
+/// amount of friend messages (hops) that were delivered before it was sent.
+pub type HopControl = (usize, usize, FunderOutgoingControl<u32>);
+
+pub async fn node_apply<'a>(
+    node: &'a mut TestNode,
+    rng: &'a mut RngContainer<DummyRandom>,
+    funder_incoming: FunderIncoming<u32>,
+) -> (Vec<FunderOutgoingComm<u32>>, Vec<FunderOutgoingControl<u32>>) {
+    // NOTE: We use Box::pin() in order to make sure we don't get a too large Future which will
+    // cause a stack overflow.
+    // See:  https://github.com/rust-lang-nursery/futures-rs/issues/1330
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut node.state,
+        &mut node.ephemeral,
+        rng,
+        &mut node.identity_client
+    )))
+    .unwrap()
+}
+
+pub fn node_index(net: &TestNet, public_key: &PublicKey) -> usize {
+    net.nodes
+        .iter()
+        .position(|node| &node.public_key == public_key)
+        .unwrap()
+}
+
+/// Apply an incoming message to a node, without delivering the friend messages it sends.
+/// Returns the friend messages (As incoming messages of the destination nodes), and the outgoing
+/// control messages of the node.
+pub async fn apply_only<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    index: usize,
+    funder_incoming: FunderIncoming<u32>,
+) -> (
+    Vec<(usize, FunderIncoming<u32>)>,
+    Vec<FunderOutgoingControl<u32>>,
+) {
+    let origin_public_key = net.nodes[index].public_key.clone();
+    let (outgoing_comms, outgoing_control) =
+        await!(node_apply(&mut net.nodes[index], rng, funder_incoming));
+
+    let mut undelivered = Vec::new();
+    for outgoing_comm in outgoing_comms {
+        if let FunderOutgoingComm::FriendMessage((public_key, friend_message)) = outgoing_comm {
+            net.friend_messages.push(friend_message.clone());
+            let dest_index = node_index(net, &public_key);
+            let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Friend((
+                origin_public_key.clone(),
+                friend_message,
+            )));
+            undelivered.push((dest_index, funder_incoming));
+        }
+    }
+    (undelivered, outgoing_control)
+}
+
+/// Apply incoming messages to the nodes, and then deliver friend messages between the nodes
+/// until no more messages are sent. Messages sent to a muted node are held.
+/// Returns the outgoing control messages of all the nodes, together with their hops.
+pub async fn deliver_all_hops<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    incoming: Vec<(usize, FunderIncoming<u32>)>,
+) -> Vec<HopControl> {
+    let mut hop_controls = Vec::new();
+    // (destination index, hop, incoming message):
+    let mut pending = incoming
+        .into_iter()
+        .map(|(index, funder_incoming)| (index, 0, funder_incoming))
+        .collect::<VecDeque<_>>();
+    let mut deliveries = 0;
+
+    while let Some((index, hop, funder_incoming)) = pending.pop_front() {
+        deliveries += 1;
+        assert!(deliveries <= MAX_DELIVERIES);
+        let (undelivered, outgoing_control) = await!(apply_only(net, rng, index, funder_incoming));
+
+        for (dest_index, funder_incoming) in undelivered {
+            if net.opt_muted == Some(dest_index) {
+                net.held.push((dest_index, funder_incoming));
+            } else {
+                pending.push_back((dest_index, hop + 1, funder_incoming));
+            }
+        }
+        for control in outgoing_control {
+            hop_controls.push((index, hop, control));
+        }
+    }
+    hop_controls
+}
+
+/// Apply incoming messages to the nodes, and then deliver friend messages between the nodes
+/// until no more messages are sent. Messages sent to a muted node are held.
+/// Returns the outgoing control messages of all the nodes.
+pub async fn deliver_all<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    incoming: Vec<(usize, FunderIncoming<u32>)>,
+) -> Vec<(usize, FunderOutgoingControl<u32>)> {
+    await!(deliver_all_hops(net, rng, incoming))
+        .into_iter()
+        .map(|(index, _hop, control)| (index, control))
+        .collect()
+}
+
+/// Unmute the muted node, and deliver all the messages that were held for it.
+pub async fn unmute_and_deliver<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+) -> Vec<(usize, FunderOutgoingControl<u32>)> {
+    net.opt_muted = None;
+    let held = net.held.drain(..).collect::<Vec<_>>();
+    await!(deliver_all(net, rng, held))
+}
+
+pub fn control_message(uid_index: u8, funder_control: FunderControl<u32>) -> FunderIncoming<u32> {
+    FunderIncoming::Control(FunderIncomingControl::new(
+        Uid::from(&[uid_index; UID_LEN]),
+        funder_control,
+    ))
+}
+
+pub async fn apply_control_and_deliver<'a>(
+    net: &'a mut TestNet,
+    rng: &'a mut RngContainer<DummyRandom>,
+    index: usize,
+    uid_index: u8,
+    funder_control: FunderControl<u32>,
+) -> Vec<(usize, FunderOutgoingControl<u32>)> {
+    let funder_incoming = control_message(uid_index, funder_control);
+    await!(deliver_all(net, rng, vec![(index, funder_incoming)]))
+}
+
+/// Collect the responses to requests to send funds received by a node
+pub fn responses_received(
+    controls: &[(usize, FunderOutgoingControl<u32>)],
+    index: usize,
+) -> Vec<ResponseReceived> {
+    controls
+        .iter()
+        .filter_map(|(control_index, control)| match control {
+            FunderOutgoingControl::ResponseReceived(response_received)
+                if *control_index == index =>
+            {
+                Some(response_received.clone())
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Create the nodes of a test network, and initialize them. The nodes have no friends.
+pub async fn create_nodes<'a>(
+    identity_clients: Vec<IdentityClient>,
+    ephemeral_limits: &'a EphemeralLimits,
+    rng: &'a mut RngContainer<DummyRandom>,
+) -> TestNet {
+    let mut nodes = Vec::new();
+    for (index, identity_client) in identity_clients.into_iter().enumerate() {
+        let public_key = await!(identity_client.request_public_key()).unwrap();
+        let relays = vec![dummy_named_relay_address(index as u8)];
+        nodes.push(TestNode {
+            public_key: public_key.clone(),
+            identity_client,
+            state: FunderState::<u32>::new(public_key, relays),
+            ephemeral: Ephemeral::with_limits(ephemeral_limits),
+        });
+    }
+    let mut net = TestNet::new(nodes);
+
+    for node in net.nodes.iter_mut() {
+        await!(node_apply(node, rng, FunderIncoming::Init));
+    }
+    net
+}
+
+/// Create a network of friends. For every (sender, receiver) pair in `edges` the two nodes are
+/// friends, and the receiver trusts the sender with `remote_max_debt` credits and allows it to
+/// send requests.
+pub async fn create_net<'a>(
+    identity_clients: Vec<IdentityClient>,
+    ephemeral_limits: &'a EphemeralLimits,
+    edges: &'a [(usize, usize)],
+    remote_max_debt: u128,
+    rng: &'a mut RngContainer<DummyRandom>,
+) -> TestNet {
+    let mut net = await!(create_nodes(identity_clients, ephemeral_limits, rng));
+
+    for &(sender, receiver) in edges {
+        let are_friends = net.nodes[sender]
+            .state
+            .friends
+            .contains_key(&net.nodes[receiver].public_key);
+        if !are_friends {
+            // Add and enable friends:
+            for &(local, remote) in &[(sender, receiver), (receiver, sender)] {
+                let friend_public_key = net.nodes[remote].public_key.clone();
+                let add_friend = AddFriend {
+                    friend_public_key: friend_public_key.clone(),
+                    relays: vec![dummy_relay_address(remote as u8)],
+                    name: String::from("friend"),
+                    balance: 0i128,
+                };
+                await!(apply_control_and_deliver(
+                    &mut net,
+                    rng,
+                    local,
+                    10,
+                    FunderControl::AddFriend(add_friend)
+                ));
+
+                let set_friend_status = SetFriendStatus {
+                    friend_public_key,
+                    status: FriendStatus::Enabled,
+                };
+                await!(apply_control_and_deliver(
+                    &mut net,
+                    rng,
+                    local,
+                    11,
+                    FunderControl::SetFriendStatus(set_friend_status)
+                ));
+            }
+
+            // Notify both friends that the other side is alive, and exchange move tokens:
+            let incoming = vec![
+                (
+                    sender,
+                    FunderIncoming::Comm(FunderIncomingComm::Liveness(
+                        IncomingLivenessMessage::Online(net.nodes[receiver].public_key.clone()),
+                    )),
+                ),
+                (
+                    receiver,
+                    FunderIncoming::Comm(FunderIncomingComm::Liveness(
+                        IncomingLivenessMessage::Online(net.nodes[sender].public_key.clone()),
+                    )),
+                ),
+            ];
+            await!(deliver_all(&mut net, rng, incoming));
+        }
+
+        // The receiver trusts the sender, and allows it to send requests:
+        let set_friend_remote_max_debt = SetFriendRemoteMaxDebt {
+            friend_public_key: net.nodes[sender].public_key.clone(),
+            remote_max_debt,
+            opt_expiry: None,
+        };
+        await!(apply_control_and_deliver(
+            &mut net,
+            rng,
+            receiver,
+            20,
+            FunderControl::SetFriendRemoteMaxDebt(set_friend_remote_max_debt)
+        ));
+
+        let set_requests_status = SetRequestsStatus {
+            friend_public_key: net.nodes[sender].public_key.clone(),
+            status: RequestsStatus::Open,
+        };
+        await!(apply_control_and_deliver(
+            &mut net,
+            rng,
+            receiver,
+            21,
+            FunderControl::SetRequestsStatus(set_requests_status)
+        ));
+    }
+
+    net
+}
+
+/// Create a chain of friends: node0 -- node1 -- node2 -- ...
+/// Every node trusts the previous node with `remote_max_debt` credits, and allows it to send
+/// requests.
+pub async fn create_chain_net<'a>(
+    identity_clients: Vec<IdentityClient>,
+    remote_max_debt: u128,
+    rng: &'a mut RngContainer<DummyRandom>,
+) -> TestNet {
+    let edges = (1..identity_clients.len())
+        .map(|index| (index - 1, index))
+        .collect::<Vec<_>>();
+    await!(create_net(
+        identity_clients,
+        &EphemeralLimits::default(),
+        &edges,
+        remote_max_debt,
+        rng
+    ))
+}
+
+/// A request to send `dest_payment` credits along a route of nodes, given by their indices.
+pub fn request_send_funds(
+    net: &TestNet,
+    route: &[usize],
+    uid_index: u8,
+    dest_payment: u128,
+) -> FunderIncoming<u32> {
+    let user_request_send_funds = UserRequestSendFunds {
+        request_id: Uid::from(&[uid_index; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: route
+                .iter()
+                .map(|&index| net.nodes[index].public_key.clone())
+                .collect(),
+        },
+        invoice_id: InvoiceId::from(&[uid_index; INVOICE_ID_LEN]),
+        dest_payment,
+        opt_label: None,
+    };
+    control_message(
+        uid_index,
+        FunderControl::RequestSendFunds(user_request_send_funds),
+    )
+}
+
+pub fn is_success(response_received: &ResponseReceived) -> bool {
+    match response_received.result {
+        ResponseSendFundsResult::Success(_) => true,
+        ResponseSendFundsResult::Failure(_) => false,
+    }
+}
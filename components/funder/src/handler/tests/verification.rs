@@ -0,0 +1,396 @@
+use super::utils::apply_funder_incoming;
+
+use futures::executor::ThreadPool;
+
+use identity::test_utils::spawn_fixture_identity;
+use identity::IdentityClient;
+
+use crypto::crypto_rand::RngContainer;
+use crypto::identity::PublicKey;
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    AddFriend, FriendMessage, FriendStatus, FunderControl, FunderIncomingControl, SetFriendStatus,
+    SetFriendVerificationPhrase,
+};
+use proto::report::messages::VerificationStatusReport;
+
+use crate::ephemeral::Ephemeral;
+use crate::friend::ChannelStatus;
+use crate::report::create_report;
+use crate::state::FunderState;
+use crate::types::{FunderIncoming, FunderIncomingComm, FunderOutgoingComm, IncomingLivenessMessage};
+
+use crate::tests::utils::{dummy_named_relay_address, dummy_relay_address};
+
+/// Find the verification proof message sent to a friend, if any
+fn find_verification_proof(
+    outgoing_comms: &[FunderOutgoingComm<u32>],
+    friend_public_key: &PublicKey,
+) -> Option<FriendMessage<u32>> {
+    outgoing_comms.iter().find_map(|outgoing_comm| match outgoing_comm {
+        FunderOutgoingComm::FriendMessage((pk, FriendMessage::VerificationProof(proof)))
+            if pk == friend_public_key =>
+        {
+            Some(FriendMessage::VerificationProof(proof.clone()))
+        }
+        _ => None,
+    })
+}
+
+fn verification_status(
+    state: &FunderState<u32>,
+    ephemeral: &Ephemeral,
+    friend_public_key: &PublicKey,
+) -> VerificationStatusReport {
+    let report = create_report(state, ephemeral);
+    report
+        .friends
+        .get(friend_public_key)
+        .unwrap()
+        .verification_status
+        .clone()
+}
+
+async fn task_handler_verification<'a>(
+    identity_client1: &'a mut IdentityClient,
+    identity_client2: &'a mut IdentityClient,
+) {
+    let pk1 = await!(identity_client1.request_public_key()).unwrap();
+    let pk2 = await!(identity_client2.request_public_key()).unwrap();
+
+    let relays1 = vec![dummy_named_relay_address(1)];
+    let mut state1 = FunderState::<u32>::new(pk1.clone(), relays1);
+    let mut ephemeral1 = Ephemeral::new();
+    let relays2 = vec![dummy_named_relay_address(2)];
+    let mut state2 = FunderState::<u32>::new(pk2.clone(), relays2);
+    let mut ephemeral2 = Ephemeral::new();
+
+    let mut rng = RngContainer::new(DummyRandom::new(&[3u8]));
+
+    // Initialize 1:
+    let funder_incoming = FunderIncoming::Init;
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+
+    // Initialize 2:
+    let funder_incoming = FunderIncoming::Init;
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+
+    // Node1: Add friend 2:
+    let add_friend = AddFriend {
+        friend_public_key: pk2.clone(),
+        relays: vec![dummy_relay_address(2)],
+        name: String::from("pk2"),
+        balance: 20i128,
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[11; UID_LEN]),
+        FunderControl::AddFriend(add_friend),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+
+    // Node1: Enable friend 2:
+    let set_friend_status = SetFriendStatus {
+        friend_public_key: pk2.clone(),
+        status: FriendStatus::Enabled,
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[12; UID_LEN]),
+        FunderControl::SetFriendStatus(set_friend_status),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+
+    // Node2: Add friend 1:
+    let add_friend = AddFriend {
+        friend_public_key: pk1.clone(),
+        relays: vec![dummy_relay_address(1)],
+        name: String::from("pk1"),
+        balance: -20i128,
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[13; UID_LEN]),
+        FunderControl::AddFriend(add_friend),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+
+    // Node2: enable friend 1:
+    let set_friend_status = SetFriendStatus {
+        friend_public_key: pk1.clone(),
+        status: FriendStatus::Enabled,
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[14; UID_LEN]),
+        FunderControl::SetFriendStatus(set_friend_status),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+
+    // Node1: Notify that Node2 is alive
+    let incoming_liveness_message = IncomingLivenessMessage::Online(pk2.clone());
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(incoming_liveness_message));
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+    // No verification phrase was set, so no proof is sent:
+    assert!(find_verification_proof(&outgoing_comms, &pk2).is_none());
+
+    // Node2: Notify that Node1 is alive
+    let incoming_liveness_message = IncomingLivenessMessage::Online(pk1.clone());
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(incoming_liveness_message));
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+
+    assert_eq!(
+        verification_status(&state1, &ephemeral1, &pk2),
+        VerificationStatusReport::Unverified
+    );
+    assert_eq!(
+        verification_status(&state2, &ephemeral2, &pk1),
+        VerificationStatusReport::Unverified
+    );
+
+    // Node1: Set a verification phrase for friend 2:
+    let set_friend_verification_phrase = SetFriendVerificationPhrase {
+        friend_public_key: pk2.clone(),
+        phrase: String::from("purple elephant"),
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[15; UID_LEN]),
+        FunderControl::SetFriendVerificationPhrase(set_friend_verification_phrase),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+    let verification_proof1 = find_verification_proof(&outgoing_comms, &pk2).unwrap();
+
+    // As long as we have no proof from the remote side, the friend remains unverified.
+    // This is also the case if the remote side does not support verification:
+    assert_eq!(
+        verification_status(&state1, &ephemeral1, &pk2),
+        VerificationStatusReport::Unverified
+    );
+    match &state1.friends.get(&pk2).unwrap().channel_status {
+        ChannelStatus::Consistent(_) => {}
+        _ => unreachable!(),
+    };
+
+    // Node2: Receive the proof before a verification phrase was set:
+    let funder_incoming = FunderIncoming::Comm(FunderIncomingComm::Friend((
+        pk1.clone(),
+        verification_proof1.clone(),
+    )));
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+    assert_eq!(
+        verification_status(&state2, &ephemeral2, &pk1),
+        VerificationStatusReport::Unverified
+    );
+
+    // Node2: Set the same verification phrase. The kept proof is checked:
+    let set_friend_verification_phrase = SetFriendVerificationPhrase {
+        friend_public_key: pk1.clone(),
+        phrase: String::from("purple elephant"),
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[16; UID_LEN]),
+        FunderControl::SetFriendVerificationPhrase(set_friend_verification_phrase),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+    let verification_proof2 = find_verification_proof(&outgoing_comms, &pk1).unwrap();
+    assert_eq!(
+        verification_status(&state2, &ephemeral2, &pk1),
+        VerificationStatusReport::PhraseVerified
+    );
+
+    // Node1: Receive the proof from Node2:
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Friend((pk2.clone(), verification_proof2)));
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+    assert_eq!(
+        verification_status(&state1, &ephemeral1, &pk2),
+        VerificationStatusReport::PhraseVerified
+    );
+
+    // A proof can not be reflected back to its signer:
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Friend((pk2.clone(), verification_proof1)));
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+    assert_eq!(
+        verification_status(&state1, &ephemeral1, &pk2),
+        VerificationStatusReport::Failed
+    );
+
+    // Node1: Set a phrase that does not match the phrase of Node2:
+    let set_friend_verification_phrase = SetFriendVerificationPhrase {
+        friend_public_key: pk2.clone(),
+        phrase: String::from("purple elefant"),
+    };
+    let incoming_control_message = FunderIncomingControl::new(
+        Uid::from(&[17; UID_LEN]),
+        FunderControl::SetFriendVerificationPhrase(set_friend_verification_phrase),
+    );
+    let funder_incoming = FunderIncoming::Control(incoming_control_message);
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+    let verification_proof1 = find_verification_proof(&outgoing_comms, &pk2).unwrap();
+
+    // Node2: Receive the mismatched proof:
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Friend((pk1.clone(), verification_proof1)));
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state2,
+        &mut ephemeral2,
+        &mut rng,
+        identity_client2
+    )))
+    .unwrap();
+    assert_eq!(
+        verification_status(&state2, &ephemeral2, &pk1),
+        VerificationStatusReport::Failed
+    );
+
+    // A failed verification does not affect the channel:
+    match &state2.friends.get(&pk1).unwrap().channel_status {
+        ChannelStatus::Consistent(_) => {}
+        _ => unreachable!(),
+    };
+
+    // Node1: Node2 goes offline and comes back. The proof is sent again:
+    let incoming_liveness_message = IncomingLivenessMessage::Offline(pk2.clone());
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(incoming_liveness_message));
+    await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+
+    let incoming_liveness_message = IncomingLivenessMessage::Online(pk2.clone());
+    let funder_incoming =
+        FunderIncoming::Comm(FunderIncomingComm::Liveness(incoming_liveness_message));
+    let (outgoing_comms, _outgoing_control) = await!(Box::pin(apply_funder_incoming(
+        funder_incoming,
+        &mut state1,
+        &mut ephemeral1,
+        &mut rng,
+        identity_client1
+    )))
+    .unwrap();
+    assert!(find_verification_proof(&outgoing_comms, &pk2).is_some());
+}
+
+#[test]
+fn test_handler_verification() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+
+    let (mut identity_client1, _) = spawn_fixture_identity(1, &mut thread_pool);
+    let (mut identity_client2, _) = spawn_fixture_identity(2, &mut thread_pool);
+
+    thread_pool.run(task_handler_verification(
+        &mut identity_client1,
+        &mut identity_client2,
+    ));
+}
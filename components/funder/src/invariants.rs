@@ -0,0 +1,266 @@
+use im::hashmap::HashMap as ImHashMap;
+
+use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::usize_to_u32;
+
+use crypto::identity::PublicKey;
+use crypto::uid::Uid;
+
+use proto::consts::MAX_INCOMING_PAYMENTS;
+use proto::funder::messages::PendingRequest;
+
+use crate::credit_calc::CreditCalculator;
+use crate::friend::{ChannelStatus, FriendState};
+use crate::mutual_credit::types::MutualCredit;
+use crate::state::FunderState;
+
+/// A violation of an invariant of the funder state.
+/// Contains enough information to find the offending friend or request.
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvariantError {
+    /// A friend is kept under the public key of another friend.
+    FriendKeyMismatch(PublicKey),
+    /// The identities of a friend (or its mutual credit) do not match the funder state.
+    IdentsMismatch(PublicKey),
+    /// A pending request is kept under the request id of another request.
+    /// (friend_public_key, request_id)
+    RequestIdMismatch((PublicKey, Uid)),
+    /// A pending request does not contain the pair of (sender, receiver) on its route.
+    /// (friend_public_key, request_id)
+    RequestNotOnRoute((PublicKey, Uid)),
+    /// Could not calculate the amount of credits frozen for a pending request.
+    /// (friend_public_key, request_id)
+    FrozenCreditsCalc((PublicKey, Uid)),
+    /// Sum of frozen credits of pending local requests does not equal local_pending_debt.
+    /// (friend_public_key, sum of frozen credits, local_pending_debt)
+    LocalPendingDebt((PublicKey, u128, u128)),
+    /// Sum of frozen credits of pending remote requests does not equal remote_pending_debt.
+    /// (friend_public_key, sum of frozen credits, remote_pending_debt)
+    RemotePendingDebt((PublicKey, u128, u128)),
+    /// Max debts or pending debts are larger than MAX_FUNDER_DEBT.
+    DebtOutOfRange(PublicKey),
+    /// The balance can not be represented on both sides, or resolving the pending requests would
+    /// overflow it.
+    BalanceOutOfRange(PublicKey),
+    /// An incoming payment is kept under the notification id of another payment.
+    NotificationIdMismatch(u64),
+    /// An incoming payment has a notification id that was not yet allocated.
+    NotificationIdNotAllocated(u64),
+    /// The outbox of incoming payments holds more than MAX_INCOMING_PAYMENTS notifications.
+    TooManyIncomingPayments,
+    /// A move token was about to be sent to a friend whose token channel is not incoming.
+    MoveTokenNotIncoming(PublicKey),
+    /// A move token was about to carry local relays other than the relays recorded as last sent
+    /// to the friend.
+    SentLocalRelaysMismatch(PublicKey),
+    /// A move token was about to carry operations that contradict each other (For example, the
+    /// same response twice). The remote side would reject such a move token.
+    ContradictingOperations(PublicKey),
+}
+
+/// Sum the credits frozen for a set of pending requests.
+/// `sender` and `receiver` are the pair of nodes the requests were sent between.
+fn sum_frozen_credits(
+    pending_requests: &ImHashMap<Uid, PendingRequest>,
+    sender: &PublicKey,
+    receiver: &PublicKey,
+    friend_public_key: &PublicKey,
+) -> Result<u128, InvariantError> {
+    let mut sum: u128 = 0;
+    for (request_id, pending_request) in pending_requests {
+        let error_ident = (friend_public_key.clone(), request_id.clone());
+        if &pending_request.request_id != request_id {
+            return Err(InvariantError::RequestIdMismatch(error_ident));
+        }
+        let sender_index = pending_request
+            .route
+            .find_pk_pair(sender, receiver)
+            .ok_or_else(|| InvariantError::RequestNotOnRoute(error_ident.clone()))?;
+
+        let frozen_credits = CreditCalculator::new(
+            pending_request.route.len(),
+            pending_request.dest_payment,
+        )
+        .ok()
+        .and_then(|credit_calc| {
+            let receiver_index = usize_to_u32(sender_index.checked_add(1)?)?;
+            credit_calc.credits_to_freeze(receiver_index)
+        })
+        .ok_or_else(|| InvariantError::FrozenCreditsCalc(error_ident.clone()))?;
+
+        sum = sum
+            .checked_add(frozen_credits)
+            .ok_or_else(|| InvariantError::FrozenCreditsCalc(error_ident.clone()))?;
+    }
+    Ok(sum)
+}
+
+fn check_mutual_credit(
+    mutual_credit: &MutualCredit,
+    friend_public_key: &PublicKey,
+) -> Result<(), InvariantError> {
+    let mc_state = mutual_credit.state();
+    let local_public_key = &mc_state.idents.local_public_key;
+    let remote_public_key = &mc_state.idents.remote_public_key;
+    if remote_public_key != friend_public_key {
+        return Err(InvariantError::IdentsMismatch(friend_public_key.clone()));
+    }
+
+    let balance = &mc_state.balance;
+
+    let local_frozen = sum_frozen_credits(
+        &mc_state.pending_requests.pending_local_requests,
+        local_public_key,
+        remote_public_key,
+        friend_public_key,
+    )?;
+    if local_frozen != balance.local_pending_debt {
+        return Err(InvariantError::LocalPendingDebt((
+            friend_public_key.clone(),
+            local_frozen,
+            balance.local_pending_debt,
+        )));
+    }
+
+    let remote_frozen = sum_frozen_credits(
+        &mc_state.pending_requests.pending_remote_requests,
+        remote_public_key,
+        local_public_key,
+        friend_public_key,
+    )?;
+    if remote_frozen != balance.remote_pending_debt {
+        return Err(InvariantError::RemotePendingDebt((
+            friend_public_key.clone(),
+            remote_frozen,
+            balance.remote_pending_debt,
+        )));
+    }
+
+    mutual_credit.validate_invariants()
+}
+
+/// Verify the invariants of the state of a single friend.
+pub fn check_friend_invariants<B>(
+    friend: &FriendState<B>,
+    local_public_key: &PublicKey,
+    friend_public_key: &PublicKey,
+) -> Result<(), InvariantError>
+where
+    B: Clone + CanonicalSerialize,
+{
+    if &friend.remote_public_key != friend_public_key {
+        return Err(InvariantError::FriendKeyMismatch(friend_public_key.clone()));
+    }
+    if &friend.local_public_key != local_public_key {
+        return Err(InvariantError::IdentsMismatch(friend_public_key.clone()));
+    }
+
+    match &friend.channel_status {
+        ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => Ok(()),
+        ChannelStatus::Consistent(token_channel) => {
+            let mutual_credit = token_channel.get_mutual_credit();
+            if &mutual_credit.state().idents.local_public_key != local_public_key {
+                return Err(InvariantError::IdentsMismatch(friend_public_key.clone()));
+            }
+            check_mutual_credit(mutual_credit, friend_public_key)
+        }
+    }
+}
+
+/// Verify cheap invariants of the funder state.
+/// Should hold after every batch of mutations applied by the funder.
+/// (But not necessarily between single mutations of a batch).
+pub fn check_invariants<B>(funder_state: &FunderState<B>) -> Result<(), InvariantError>
+where
+    B: Clone + CanonicalSerialize,
+{
+    for (friend_public_key, friend) in &funder_state.friends {
+        check_friend_invariants(friend, &funder_state.local_public_key, friend_public_key)?;
+    }
+
+    if funder_state.incoming_payments.len() > MAX_INCOMING_PAYMENTS {
+        return Err(InvariantError::TooManyIncomingPayments);
+    }
+
+    for (notification_id, incoming_payment) in &funder_state.incoming_payments {
+        if &incoming_payment.notification_id != notification_id {
+            return Err(InvariantError::NotificationIdMismatch(*notification_id));
+        }
+        if *notification_id >= funder_state.next_notification_id {
+            return Err(InvariantError::NotificationIdNotAllocated(*notification_id));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+    use crypto::uid::UID_LEN;
+
+    use proto::funder::messages::{AddFriend, FriendsRoute};
+
+    use crate::friend::FriendMutation;
+    use crate::mutual_credit::types::McMutation;
+    use crate::state::FunderMutation;
+    use crate::token_channel::{TcMutation, TokenChannel};
+
+    fn mc_mutation(friend_public_key: &PublicKey, mutation: McMutation) -> FunderMutation<u32> {
+        let friend_mutation = FriendMutation::TcMutation(TcMutation::McMutation(mutation));
+        FunderMutation::FriendMutation((friend_public_key.clone(), friend_mutation))
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let friend_pk = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let dest_pk = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+
+        let mut state = FunderState::<u32>::new(local_pk.clone(), Vec::new());
+        state.mutate(&FunderMutation::AddFriend(AddFriend {
+            friend_public_key: friend_pk.clone(),
+            relays: Vec::new(),
+            name: "friend".to_owned(),
+            balance: 0,
+        }));
+        assert_eq!(check_invariants(&state), Ok(()));
+
+        // local -> friend -> dest. We should freeze 11 credits for the friend:
+        let pending_request = PendingRequest {
+            request_id: Uid::from(&[1; UID_LEN]),
+            route: FriendsRoute {
+                public_keys: vec![local_pk.clone(), friend_pk.clone(), dest_pk.clone()],
+            },
+            dest_payment: 10,
+            invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+        };
+        state.mutate(&mc_mutation(
+            &friend_pk,
+            McMutation::InsertLocalPendingRequest(pending_request),
+        ));
+        assert_eq!(
+            check_invariants(&state),
+            Err(InvariantError::LocalPendingDebt((friend_pk.clone(), 11, 0)))
+        );
+
+        state.mutate(&mc_mutation(&friend_pk, McMutation::SetLocalPendingDebt(11)));
+        assert_eq!(check_invariants(&state), Ok(()));
+
+        // Token channels do not accept mutations that take the balance out of range, hence we
+        // replace the whole token channel:
+        let token_channel = TokenChannel::new(&local_pk, &friend_pk, i128::min_value());
+        let friend_mutation = FriendMutation::SetConsistent(token_channel);
+        state.mutate(&FunderMutation::FriendMutation((
+            friend_pk.clone(),
+            friend_mutation,
+        )));
+        assert_eq!(
+            check_invariants(&state),
+            Err(InvariantError::BalanceOutOfRange(friend_pk.clone()))
+        );
+    }
+}
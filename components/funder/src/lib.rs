@@ -18,19 +18,36 @@ extern crate log;
 #[macro_use]
 extern crate serde_derive;
 
+mod adaptive_batch;
+mod completed;
+pub mod conformance;
 mod credit_calc;
+mod damping;
+mod deadlines;
+pub mod debug_json;
 mod ephemeral;
+pub mod event_stats;
 mod friend;
 mod funder;
+mod goodbye;
 mod handler;
+pub mod invariants;
 mod liveness;
 mod mutual_credit;
+mod payment_timing;
+mod prewarm;
+pub mod quarantine;
+mod reliability;
 pub mod report;
+mod response_deadline;
 mod state;
 #[cfg(test)]
 mod tests;
 mod token_channel;
 pub mod types;
 
+pub use self::credit_calc::{CreditCalcError, CreditCalculator};
+pub use self::ephemeral::EphemeralLimits;
+pub use self::event_stats::{FunderEventStats, SlowEventLimits};
 pub use self::funder::{funder_loop, FunderError};
 pub use self::state::{FunderMutation, FunderState};
@@ -1,17 +1,21 @@
-use crypto::identity::verify_signature;
+use std::collections::HashSet;
+
+use im::hashset::HashSet as ImHashSet;
+
+use crypto::uid::Uid;
 
 use common::int_convert::usize_to_u32;
-use common::safe_arithmetic::SafeSignedArithmetic;
+use common::safe_arithmetic::{SafeSignedArithmetic, SafeUnsignedArithmetic};
 
 use proto::funder::messages::{
-    FailureSendFunds, FriendTcOp, PendingRequest, RequestSendFunds, RequestsStatus,
-    ResponseSendFunds,
+    FailureSendFunds, FriendTcOp, OperationErrorCode, PendingRequest, RequestSendFunds,
+    RequestsStatus, ResponseSendFunds,
 };
-use proto::funder::signature_buff::{create_response_signature_buffer, verify_failure_signature};
+use proto::funder::signature_buff::{verify_failure_signature, verify_response_signature};
 
 use crate::types::create_pending_request;
 
-use crate::credit_calc::CreditCalculator;
+use crate::credit_calc::{CreditCalcError, CreditCalculator};
 
 use super::types::{McMutation, MutualCredit, MAX_FUNDER_DEBT};
 
@@ -56,8 +60,6 @@ pub enum ProcessOperationError {
     RequestsAlreadyDisabled,
     RouteTooLong,
     InsufficientTrust,
-    CreditsCalcOverflow,
-    CreditCalculatorFailure,
     RequestAlreadyExists,
     RequestDoesNotExist,
     InvalidResponseSignature,
@@ -65,6 +67,13 @@ pub enum ProcessOperationError {
     InvalidReportingNode,
     InvalidFailureSignature,
     LocalRequestsClosed,
+    /// Less credits are frozen than required to complete a request.
+    InsufficientFrozenCredits,
+    BalanceOverflow,
+    /// Another operation of the same list already refers to this request id.
+    DuplicateRequestId,
+    /// A response or a failure for a request that was sent in the same list.
+    ResolvesRequestOfSameBatch,
 }
 
 #[derive(Debug)]
@@ -73,18 +82,178 @@ pub struct ProcessTransListError {
     process_trans_error: ProcessOperationError,
 }
 
-pub fn process_operations_list(
+impl ProcessOperationError {
+    /// The code used to describe this error to the remote side
+    pub fn error_code(&self) -> OperationErrorCode {
+        match self {
+            ProcessOperationError::RemoteMaxDebtTooLarge(_) => {
+                OperationErrorCode::RemoteMaxDebtTooLarge
+            }
+            ProcessOperationError::PkPairNotInRoute => OperationErrorCode::PkPairNotInRoute,
+            ProcessOperationError::InvalidRoute => OperationErrorCode::InvalidRoute,
+            ProcessOperationError::RequestsAlreadyDisabled => {
+                OperationErrorCode::RequestsAlreadyDisabled
+            }
+            ProcessOperationError::RouteTooLong => OperationErrorCode::RouteTooLong,
+            ProcessOperationError::InsufficientTrust => OperationErrorCode::InsufficientTrust,
+            ProcessOperationError::RequestAlreadyExists => OperationErrorCode::RequestAlreadyExists,
+            ProcessOperationError::RequestDoesNotExist => OperationErrorCode::RequestDoesNotExist,
+            ProcessOperationError::InvalidResponseSignature => {
+                OperationErrorCode::InvalidResponseSignature
+            }
+            ProcessOperationError::ReportingNodeNonexistent => {
+                OperationErrorCode::ReportingNodeNonexistent
+            }
+            ProcessOperationError::InvalidReportingNode => OperationErrorCode::InvalidReportingNode,
+            ProcessOperationError::InvalidFailureSignature => {
+                OperationErrorCode::InvalidFailureSignature
+            }
+            ProcessOperationError::LocalRequestsClosed => OperationErrorCode::LocalRequestsClosed,
+            ProcessOperationError::InsufficientFrozenCredits => {
+                OperationErrorCode::InsufficientFrozenCredits
+            }
+            ProcessOperationError::BalanceOverflow => OperationErrorCode::BalanceOverflow,
+            ProcessOperationError::DuplicateRequestId => OperationErrorCode::DuplicateRequestId,
+            ProcessOperationError::ResolvesRequestOfSameBatch => {
+                OperationErrorCode::ResolvesRequestOfSameBatch
+            }
+        }
+    }
+}
+
+impl ProcessTransListError {
+    /// Index of the invalid operation
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn error(&self) -> &ProcessOperationError {
+        &self.process_trans_error
+    }
+}
+
+/// Is this operation a response or a failure for one of our requests that was already completed?
+/// This might happen if the remote side retransmits a response or a failure after a channel
+/// reset. Incoming requests are never considered to be retransmissions.
+fn is_completed_retransmission(
+    mutual_credit: &MutualCredit,
+    completed_request_ids: &ImHashSet<Uid>,
+    friend_tc_op: &FriendTcOp,
+) -> bool {
+    let request_id = match friend_tc_op {
+        FriendTcOp::ResponseSendFunds(response_send_funds) => &response_send_funds.request_id,
+        FriendTcOp::FailureSendFunds(failure_send_funds) => &failure_send_funds.request_id,
+        _ => return false,
+    };
+
+    let pending_local_requests = &mutual_credit
+        .state()
+        .pending_requests
+        .pending_local_requests;
+    !pending_local_requests.contains_key(request_id) && completed_request_ids.contains(request_id)
+}
+
+/// Check a response or a failure against the request ids seen earlier in the same list.
+fn check_batch_resolve(
+    request_ids: &HashSet<Uid>,
+    resolved_ids: &mut HashSet<Uid>,
+    request_id: &Uid,
+) -> Result<(), ProcessOperationError> {
+    if request_ids.contains(request_id) {
+        return Err(ProcessOperationError::ResolvesRequestOfSameBatch);
+    }
+    if !resolved_ids.insert(*request_id) {
+        return Err(ProcessOperationError::DuplicateRequestId);
+    }
+    Ok(())
+}
+
+/// Check that the operations of a list (The operations of one move token) do not contradict
+/// each other.
+///
+/// Operations are applied in order, and most orderings are allowed. For example, a list may
+/// enable requests, send requests and then disable requests again. The following combinations
+/// are rejected:
+/// - Two requests with the same request id.
+/// - Two responses or failures with the same request id, or a request that uses the request id
+///   of an earlier response or failure.
+/// - A response or a failure for a request sent earlier in the same list. The remote side sends
+///   responses and failures only for our requests, so an honest remote side never does this.
+///
+/// A `SetRemoteMaxDebt` can not contradict the requests of the same list: It limits the debt of
+/// the receiving side, while the requests of the list freeze credits of the sending side.
+///
+/// These checks do not depend on the state of the mutual credit, so they are done before any of
+/// the operations is processed.
+pub(crate) fn validate_operations_list(
+    operations: &[FriendTcOp],
+) -> Result<(), ProcessTransListError> {
+    let mut request_ids = HashSet::new();
+    let mut resolved_ids = HashSet::new();
+
+    for (index, operation) in operations.iter().enumerate() {
+        let res = match operation {
+            FriendTcOp::RequestSendFunds(request_send_funds) => {
+                let request_id = &request_send_funds.request_id;
+                if resolved_ids.contains(request_id) || !request_ids.insert(*request_id) {
+                    Err(ProcessOperationError::DuplicateRequestId)
+                } else {
+                    Ok(())
+                }
+            }
+            FriendTcOp::ResponseSendFunds(response_send_funds) => check_batch_resolve(
+                &request_ids,
+                &mut resolved_ids,
+                &response_send_funds.request_id,
+            ),
+            FriendTcOp::FailureSendFunds(failure_send_funds) => check_batch_resolve(
+                &request_ids,
+                &mut resolved_ids,
+                &failure_send_funds.request_id,
+            ),
+            FriendTcOp::EnableRequests
+            | FriendTcOp::DisableRequests
+            | FriendTcOp::SetRemoteMaxDebt(_) => Ok(()),
+        };
+        res.map_err(|process_trans_error| ProcessTransListError {
+            index,
+            process_trans_error,
+        })?;
+    }
+    Ok(())
+}
+
+/// Process a list of operations sent by the remote side.
+/// `completed_request_ids` contains the ids of our requests that were recently completed.
+/// Responses and failures for those requests are silently ignored.
+/// Process a list of incoming operations.
+/// The output of every operation is handed to `handle_output` as soon as the operation is
+/// processed, so that the outputs of a long list are never kept together in an intermediate list.
+/// Note that a later invalid operation still fails the whole list. The caller should not act on
+/// any of the outputs before `Ok` is returned.
+/// Before processing, the list is checked as a whole (See `validate_operations_list`).
+pub fn process_operations_list<F>(
     mutual_credit: &mut MutualCredit,
     operations: Vec<FriendTcOp>,
-) -> Result<Vec<ProcessOperationOutput>, ProcessTransListError> {
-    let mut outputs = Vec::new();
-
+    completed_request_ids: &ImHashSet<Uid>,
+    mut handle_output: F,
+) -> Result<(), ProcessTransListError>
+where
+    F: FnMut(ProcessOperationOutput),
+{
     // We do not change the original MutualCredit.
     // Instead, we are operating over a clone:
     // This operation is not very expensive, because we are using immutable data structures
     // (specifically, HashMaps).
 
+    validate_operations_list(&operations)?;
+
     for (index, funds) in operations.into_iter().enumerate() {
+        if is_completed_retransmission(mutual_credit, completed_request_ids, &funds) {
+            // A retransmission of a response or a failure we have already handled.
+            // Note that we can not report this anywhere, as there are no metrics in the funder.
+            continue;
+        }
         match process_operation(mutual_credit, funds) {
             Err(e) => {
                 return Err(ProcessTransListError {
@@ -92,10 +261,10 @@ pub fn process_operations_list(
                     process_trans_error: e,
                 })
             }
-            Ok(trans_output) => outputs.push(trans_output),
+            Ok(trans_output) => handle_output(trans_output),
         }
     }
-    Ok(outputs)
+    Ok(())
 }
 
 pub fn process_operation(
@@ -197,19 +366,31 @@ fn process_request_send_funds(
         return Err(ProcessOperationError::LocalRequestsClosed);
     }
 
-    let route_len =
-        usize_to_u32(request_send_funds.route.len()).ok_or(ProcessOperationError::RouteTooLong)?;
-    let credit_calc = CreditCalculator::new(route_len, request_send_funds.dest_payment);
+    let route_len = request_send_funds.route.len();
+    let credit_calc = match CreditCalculator::new(route_len, request_send_funds.dest_payment) {
+        Ok(credit_calc) => credit_calc,
+        // If the amount of credits to freeze does not even fit in a u128, we can never freeze it:
+        Err(CreditCalcError::CreditsOverflow) => {
+            return Err(ProcessOperationError::InsufficientTrust)
+        }
+        Err(CreditCalcError::RouteTooShort) | Err(CreditCalcError::RouteTooLong) => {
+            return Err(ProcessOperationError::RouteTooLong)
+        }
+        Err(CreditCalcError::InvalidReportingNode) => {
+            return Err(ProcessOperationError::InvalidReportingNode)
+        }
+    };
 
     let local_index = remote_index
         .checked_add(1)
         .ok_or(ProcessOperationError::RouteTooLong)?;
     let local_index = usize_to_u32(local_index).ok_or(ProcessOperationError::RouteTooLong)?;
 
-    // Calculate amount of credits to freeze
+    // Calculate amount of credits to freeze.
+    // If the amount does not even fit in a u128, we can never freeze it:
     let own_freeze_credits = credit_calc
         .credits_to_freeze(local_index)
-        .ok_or(ProcessOperationError::CreditCalculatorFailure)?;
+        .ok_or(ProcessOperationError::InsufficientTrust)?;
 
     // Make sure we can freeze the credits
     let balance = &mutual_credit.state().balance;
@@ -217,19 +398,17 @@ fn process_request_send_funds(
     let new_remote_pending_debt = balance
         .remote_pending_debt
         .checked_add(own_freeze_credits)
-        .ok_or(ProcessOperationError::CreditsCalcOverflow)?;
+        .ok_or(ProcessOperationError::InsufficientTrust)?;
 
-    // Check that local_pending_debt - balance <= local_max_debt:
-    let add = balance
-        .balance
-        .checked_add_unsigned(new_remote_pending_debt)
-        .ok_or(ProcessOperationError::CreditsCalcOverflow)?;
-
-    if add
-        .checked_sub_unsigned(balance.remote_max_debt)
-        .ok_or(ProcessOperationError::CreditsCalcOverflow)?
-        > 0
-    {
+    // Check that balance + remote_pending_debt <= remote_max_debt.
+    // We compare against remote_max_debt - balance, which can not overflow (remote_max_debt is
+    // at most MAX_FUNDER_DEBT). If it is negative, the remote side can not freeze any credits.
+    let max_remote_pending_debt = balance
+        .remote_max_debt
+        .checked_sub_signed(balance.balance)
+        .ok_or(ProcessOperationError::InsufficientTrust)?;
+
+    if new_remote_pending_debt > max_remote_pending_debt {
         return Err(ProcessOperationError::InsufficientTrust);
     }
 
@@ -284,24 +463,17 @@ fn process_response_send_funds(
         .ok_or(ProcessOperationError::RequestDoesNotExist)?
         .clone();
 
-    let dest_public_key = pending_request.route.public_keys.last().unwrap();
-
-    let response_signature_buffer =
-        create_response_signature_buffer(&response_send_funds, &pending_request);
-
-    // Verify response funds signature:
-    if !verify_signature(
-        &response_signature_buffer,
-        dest_public_key,
-        &response_send_funds.signature,
-    ) {
+    // Verify response funds signature.
+    // We verify the signature before changing any credits, so that a hop along the route can
+    // not move balances using a forged response:
+    if !verify_response_signature(&response_send_funds, &pending_request) {
         return Err(ProcessOperationError::InvalidResponseSignature);
     }
 
-    // It should never happen that usize_to_u32 fails here, because we
-    // checked this when we created the pending_request.
-    let route_len = usize_to_u32(pending_request.route.len()).unwrap();
-    let credit_calc = CreditCalculator::new(route_len, pending_request.dest_payment);
+    // It should never happen that CreditCalculator::new() fails here, because we
+    // checked the route length when we created the pending_request.
+    let credit_calc =
+        CreditCalculator::new(pending_request.route.len(), pending_request.dest_payment).unwrap();
 
     // Find ourselves on the route. If we are not there, abort.
     let local_index = pending_request
@@ -312,35 +484,36 @@ fn process_response_send_funds(
         )
         .unwrap();
 
-    let mut mc_mutations = Vec::new();
-
-    // Remove entry from local_pending hashmap:
-    let tc_mutation = McMutation::RemoveLocalPendingRequest(response_send_funds.request_id);
-    mutual_credit.mutate(&tc_mutation);
-    mc_mutations.push(tc_mutation);
-
     let remote_index = usize_to_u32(local_index.checked_add(1).unwrap()).unwrap();
     let success_credits = credit_calc.credits_on_success(remote_index).unwrap();
     let freeze_credits = credit_calc.credits_to_freeze(remote_index).unwrap();
 
-    // Decrease frozen credits and decrease balance:
+    // Check frozen credits and balance before changing anything:
     let new_local_pending_debt = mutual_credit
         .state()
         .balance
         .local_pending_debt
         .checked_sub(freeze_credits)
-        .unwrap();
-
-    let tc_mutation = McMutation::SetLocalPendingDebt(new_local_pending_debt);
-    mutual_credit.mutate(&tc_mutation);
-    mc_mutations.push(tc_mutation);
+        .ok_or(ProcessOperationError::InsufficientFrozenCredits)?;
 
     let new_balance = mutual_credit
         .state()
         .balance
         .balance
         .checked_sub_unsigned(success_credits)
-        .unwrap();
+        .ok_or(ProcessOperationError::BalanceOverflow)?;
+
+    let mut mc_mutations = Vec::new();
+
+    // Remove entry from local_pending hashmap:
+    let tc_mutation = McMutation::RemoveLocalPendingRequest(response_send_funds.request_id);
+    mutual_credit.mutate(&tc_mutation);
+    mc_mutations.push(tc_mutation);
+
+    // Decrease frozen credits and decrease balance:
+    let tc_mutation = McMutation::SetLocalPendingDebt(new_local_pending_debt);
+    mutual_credit.mutate(&tc_mutation);
+    mc_mutations.push(tc_mutation);
 
     let tc_mutation = McMutation::SetBalance(new_balance);
     mutual_credit.mutate(&tc_mutation);
@@ -387,7 +560,6 @@ fn process_failure_send_funds(
     // Make sure that reporting node public key is:
     //  - inside the route
     //  - After us on the route.
-    //  - Not the destination node
 
     let reporting_index = pending_request
         .route
@@ -398,45 +570,57 @@ fn process_failure_send_funds(
         return Err(ProcessOperationError::InvalidReportingNode);
     }
 
+    // Verify the signature of the reporting node before changing any credits:
     verify_failure_signature(&failure_send_funds, &pending_request)
         .ok_or(ProcessOperationError::InvalidFailureSignature)?;
 
     // At this point we believe the failure funds is valid.
-    let route_len = usize_to_u32(pending_request.route.len()).unwrap();
-    let credit_calc = CreditCalculator::new(route_len, pending_request.dest_payment);
-
-    let mut mc_mutations = Vec::new();
-
-    // Remove entry from local_pending hashmap:
-    let tc_mutation = McMutation::RemoveLocalPendingRequest(failure_send_funds.request_id);
-    mutual_credit.mutate(&tc_mutation);
-    mc_mutations.push(tc_mutation);
+    let credit_calc =
+        CreditCalculator::new(pending_request.route.len(), pending_request.dest_payment).unwrap();
 
     let remote_index = usize_to_u32(local_index.checked_add(1).unwrap()).unwrap();
-    let reporting_index = usize_to_u32(reporting_index).unwrap();
-    let failure_credits = credit_calc
-        .credits_on_failure(remote_index, reporting_index)
-        .unwrap();
+    let reporting_index =
+        usize_to_u32(reporting_index).ok_or(ProcessOperationError::InvalidReportingNode)?;
+    let failure_credits = match credit_calc.credits_on_failure(remote_index, reporting_index) {
+        Ok(failure_credits) => failure_credits,
+        Err(CreditCalcError::InvalidReportingNode) => {
+            return Err(ProcessOperationError::InvalidReportingNode)
+        }
+        Err(CreditCalcError::CreditsOverflow) => {
+            return Err(ProcessOperationError::BalanceOverflow)
+        }
+        Err(CreditCalcError::RouteTooShort) | Err(CreditCalcError::RouteTooLong) => {
+            return Err(ProcessOperationError::RouteTooLong)
+        }
+    };
     let freeze_credits = credit_calc.credits_to_freeze(remote_index).unwrap();
 
-    // Decrease frozen credits and decrease balance:
+    // Check frozen credits and balance before changing anything:
     let new_local_pending_debt = mutual_credit
         .state()
         .balance
         .local_pending_debt
         .checked_sub(freeze_credits)
-        .unwrap();
-
-    let tc_mutation = McMutation::SetLocalPendingDebt(new_local_pending_debt);
-    mutual_credit.mutate(&tc_mutation);
-    mc_mutations.push(tc_mutation);
+        .ok_or(ProcessOperationError::InsufficientFrozenCredits)?;
 
     let new_balance = mutual_credit
         .state()
         .balance
         .balance
         .checked_sub_unsigned(failure_credits)
-        .unwrap();
+        .ok_or(ProcessOperationError::BalanceOverflow)?;
+
+    let mut mc_mutations = Vec::new();
+
+    // Remove entry from local_pending hashmap:
+    let tc_mutation = McMutation::RemoveLocalPendingRequest(failure_send_funds.request_id);
+    mutual_credit.mutate(&tc_mutation);
+    mc_mutations.push(tc_mutation);
+
+    // Decrease frozen credits and decrease balance:
+    let tc_mutation = McMutation::SetLocalPendingDebt(new_local_pending_debt);
+    mutual_credit.mutate(&tc_mutation);
+    mc_mutations.push(tc_mutation);
 
     let tc_mutation = McMutation::SetBalance(new_balance);
     mutual_credit.mutate(&tc_mutation);
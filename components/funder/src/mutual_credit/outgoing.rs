@@ -1,15 +1,13 @@
-use crypto::identity::verify_signature;
-
 use common::int_convert::usize_to_u32;
-use common::safe_arithmetic::SafeSignedArithmetic;
+use common::safe_arithmetic::{SafeSignedArithmetic, SafeUnsignedArithmetic};
 
 use proto::funder::messages::{
     FailureSendFunds, FriendTcOp, RequestSendFunds, RequestsStatus, ResponseSendFunds,
 };
-use proto::funder::signature_buff::{create_response_signature_buffer, verify_failure_signature};
+use proto::funder::signature_buff::{verify_failure_signature, verify_response_signature};
 
 use super::types::{McMutation, MutualCredit, MAX_FUNDER_DEBT};
-use crate::credit_calc::CreditCalculator;
+use crate::credit_calc::{CreditCalcError, CreditCalculator};
 use crate::types::create_pending_request;
 
 /// Processes outgoing funds for a token channel.
@@ -24,8 +22,6 @@ pub enum QueueOperationError {
     InvalidRoute,
     PkPairNotInRoute,
     RouteTooLong,
-    CreditCalculatorFailure,
-    CreditsCalcOverflow,
     InsufficientTrust,
     RequestAlreadyExists,
     RequestDoesNotExist,
@@ -33,8 +29,10 @@ pub enum QueueOperationError {
     ReportingNodeNonexistent,
     InvalidReportingNode,
     InvalidFailureSignature,
-    FailureSentFromDest,
     RemoteRequestsClosed,
+    /// Less credits are frozen than required to complete a request.
+    InsufficientFrozenCredits,
+    BalanceOverflow,
 }
 
 /// A wrapper over a token channel, accumulating funds to be sent as one transaction.
@@ -125,9 +123,21 @@ impl OutgoingMc {
         }
 
         // Calculate amount of credits to freeze.
-        let route_len = usize_to_u32(request_send_funds.route.len())
-            .ok_or(QueueOperationError::RouteTooLong)?;
-        let credit_calc = CreditCalculator::new(route_len, request_send_funds.dest_payment);
+        let route_len = request_send_funds.route.len();
+        let credit_calc = match CreditCalculator::new(route_len, request_send_funds.dest_payment) {
+            Ok(credit_calc) => credit_calc,
+            // If the amount of credits to freeze does not even fit in a u128, we can never
+            // freeze it:
+            Err(CreditCalcError::CreditsOverflow) => {
+                return Err(QueueOperationError::InsufficientTrust)
+            }
+            Err(CreditCalcError::RouteTooShort) | Err(CreditCalcError::RouteTooLong) => {
+                return Err(QueueOperationError::RouteTooLong)
+            }
+            Err(CreditCalcError::InvalidReportingNode) => {
+                return Err(QueueOperationError::InvalidReportingNode)
+            }
+        };
 
         // Get index of remote friend on the route:
         let remote_index = local_index
@@ -135,10 +145,11 @@ impl OutgoingMc {
             .ok_or(QueueOperationError::RouteTooLong)?;
         let remote_index = usize_to_u32(remote_index).ok_or(QueueOperationError::RouteTooLong)?;
 
-        // Calculate amount of credits to freeze
+        // Calculate amount of credits to freeze.
+        // If the amount does not even fit in a u128, we can never freeze it:
         let own_freeze_credits = credit_calc
             .credits_to_freeze(remote_index)
-            .ok_or(QueueOperationError::CreditCalculatorFailure)?;
+            .ok_or(QueueOperationError::InsufficientTrust)?;
 
         let balance = &self.mutual_credit.state().balance;
 
@@ -146,19 +157,17 @@ impl OutgoingMc {
         let new_local_pending_debt = balance
             .local_pending_debt
             .checked_add(own_freeze_credits)
-            .ok_or(QueueOperationError::CreditsCalcOverflow)?;
+            .ok_or(QueueOperationError::InsufficientTrust)?;
 
-        // Check that local_pending_debt - balance <= local_max_debt:
-        let sub = balance
-            .balance
-            .checked_sub_unsigned(new_local_pending_debt)
-            .ok_or(QueueOperationError::CreditsCalcOverflow)?;
-
-        if sub
-            .checked_add_unsigned(balance.local_max_debt)
-            .ok_or(QueueOperationError::CreditsCalcOverflow)?
-            < 0
-        {
+        // Check that local_pending_debt - balance <= local_max_debt.
+        // We compare against local_max_debt + balance, which can not overflow (local_max_debt is at
+        // most MAX_FUNDER_DEBT). If it is negative, we can not freeze any credits.
+        let max_local_pending_debt = balance
+            .local_max_debt
+            .checked_add_signed(balance.balance)
+            .ok_or(QueueOperationError::InsufficientTrust)?;
+
+        if new_local_pending_debt > max_local_pending_debt {
             return Err(QueueOperationError::InsufficientTrust);
         }
 
@@ -207,25 +216,15 @@ impl OutgoingMc {
             .clone();
         // TODO: Possibly get rid of clone() here for optimization later
 
-        // verify signature:
-        let response_signature_buffer =
-            create_response_signature_buffer(&response_send_funds, &pending_request);
-        // The response was signed by the destination node:
-        let dest_public_key = pending_request.route.public_keys.last().unwrap();
-
-        // Verify response funds signature:
-        if !verify_signature(
-            &response_signature_buffer,
-            dest_public_key,
-            &response_send_funds.signature,
-        ) {
+        // Verify response funds signature (Signed by the destination node):
+        if !verify_response_signature(&response_send_funds, &pending_request) {
             return Err(QueueOperationError::InvalidResponseSignature);
         }
 
         // Calculate amount of credits to freeze.
-        let route_len =
-            usize_to_u32(pending_request.route.len()).ok_or(QueueOperationError::RouteTooLong)?;
-        let credit_calc = CreditCalculator::new(route_len, pending_request.dest_payment);
+        let credit_calc =
+            CreditCalculator::new(pending_request.route.len(), pending_request.dest_payment)
+                .map_err(|_| QueueOperationError::RouteTooLong)?;
 
         // Find ourselves on the route. If we are not there, abort.
         let remote_index = pending_request
@@ -238,27 +237,18 @@ impl OutgoingMc {
 
         let local_index = usize_to_u32(remote_index.checked_add(1).unwrap()).unwrap();
 
-        // Remove entry from remote_pending hashmap:
-        let mut tc_mutations = Vec::new();
-        let tc_mutation = McMutation::RemoveRemotePendingRequest(response_send_funds.request_id);
-        self.mutual_credit.mutate(&tc_mutation);
-        tc_mutations.push(tc_mutation);
-
         let success_credits = credit_calc.credits_on_success(local_index).unwrap();
         let freeze_credits = credit_calc.credits_to_freeze(local_index).unwrap();
 
-        // Decrease frozen credits and increase balance:
+        // Calculate the new frozen credits and balance before changing anything, so that a
+        // failed check leaves the mutual credit untouched:
         let new_remote_pending_debt = self
             .mutual_credit
             .state()
             .balance
             .remote_pending_debt
             .checked_sub(freeze_credits)
-            .expect("Insufficient frozen credit!");
-
-        let tc_mutation = McMutation::SetRemotePendingDebt(new_remote_pending_debt);
-        self.mutual_credit.mutate(&tc_mutation);
-        tc_mutations.push(tc_mutation);
+            .ok_or(QueueOperationError::InsufficientFrozenCredits)?;
 
         let new_balance = self
             .mutual_credit
@@ -266,7 +256,18 @@ impl OutgoingMc {
             .balance
             .balance
             .checked_add_unsigned(success_credits)
-            .expect("balance overflow");
+            .ok_or(QueueOperationError::BalanceOverflow)?;
+
+        // Remove entry from remote_pending hashmap:
+        let mut tc_mutations = Vec::new();
+        let tc_mutation = McMutation::RemoveRemotePendingRequest(response_send_funds.request_id);
+        self.mutual_credit.mutate(&tc_mutation);
+        tc_mutations.push(tc_mutation);
+
+        // Decrease frozen credits and increase balance:
+        let tc_mutation = McMutation::SetRemotePendingDebt(new_remote_pending_debt);
+        self.mutual_credit.mutate(&tc_mutation);
+        tc_mutations.push(tc_mutation);
 
         let tc_mutation = McMutation::SetBalance(new_balance);
         self.mutual_credit.mutate(&tc_mutation);
@@ -301,17 +302,12 @@ impl OutgoingMc {
             )
             .unwrap();
 
+        // Note that we may be the destination. The destination fails payments that are too small.
         let local_index = remote_index.checked_add(1).unwrap();
-        if local_index.checked_add(1).unwrap() == pending_request.route.len() {
-            // Note that we can not be the destination. The destination can not be the sender of a
-            // failure funds.
-            return Err(QueueOperationError::FailureSentFromDest);
-        }
 
         // Make sure that reporting node public key is:
         //  - inside the route
         //  - After us on the route, or us.
-        //  - Not the destination node
 
         let reporting_index = pending_request
             .route
@@ -326,47 +322,58 @@ impl OutgoingMc {
             .ok_or(QueueOperationError::InvalidFailureSignature)?;
 
         // At this point we believe the failure funds is valid.
-        let route_len =
-            usize_to_u32(pending_request.route.len()).ok_or(QueueOperationError::RouteTooLong)?;
-        let credit_calc = CreditCalculator::new(route_len, pending_request.dest_payment);
-
-        // Remove entry from remote hashmap:
-        let mut tc_mutations = Vec::new();
-
-        let tc_mutation = McMutation::RemoveRemotePendingRequest(failure_send_funds.request_id);
-        self.mutual_credit.mutate(&tc_mutation);
-        tc_mutations.push(tc_mutation);
+        let credit_calc =
+            CreditCalculator::new(pending_request.route.len(), pending_request.dest_payment)
+                .map_err(|_| QueueOperationError::RouteTooLong)?;
 
         let local_index = usize_to_u32(local_index).unwrap();
-        let reporting_index = usize_to_u32(reporting_index).unwrap();
+        let reporting_index =
+            usize_to_u32(reporting_index).ok_or(QueueOperationError::InvalidReportingNode)?;
 
-        let failure_credits = credit_calc
-            .credits_on_failure(local_index, reporting_index)
-            .unwrap();
+        let failure_credits = match credit_calc.credits_on_failure(local_index, reporting_index) {
+            Ok(failure_credits) => failure_credits,
+            Err(CreditCalcError::InvalidReportingNode) => {
+                return Err(QueueOperationError::InvalidReportingNode)
+            }
+            Err(CreditCalcError::CreditsOverflow) => {
+                return Err(QueueOperationError::BalanceOverflow)
+            }
+            Err(CreditCalcError::RouteTooShort) | Err(CreditCalcError::RouteTooLong) => {
+                return Err(QueueOperationError::RouteTooLong)
+            }
+        };
         let freeze_credits = credit_calc.credits_to_freeze(local_index).unwrap();
 
-        // Decrease frozen credits:
+        // Check frozen credits and balance before changing anything:
         let new_remote_pending_debt = self
             .mutual_credit
             .state()
             .balance
             .remote_pending_debt
             .checked_sub(freeze_credits)
-            .unwrap();
-
-        let tc_mutation = McMutation::SetRemotePendingDebt(new_remote_pending_debt);
-        self.mutual_credit.mutate(&tc_mutation);
-        tc_mutations.push(tc_mutation);
+            .ok_or(QueueOperationError::InsufficientFrozenCredits)?;
 
-        // Add to balance:
         let new_balance = self
             .mutual_credit
             .state()
             .balance
             .balance
             .checked_add_unsigned(failure_credits)
-            .unwrap();
+            .ok_or(QueueOperationError::BalanceOverflow)?;
+
+        // Remove entry from remote hashmap:
+        let mut tc_mutations = Vec::new();
 
+        let tc_mutation = McMutation::RemoveRemotePendingRequest(failure_send_funds.request_id);
+        self.mutual_credit.mutate(&tc_mutation);
+        tc_mutations.push(tc_mutation);
+
+        // Decrease frozen credits:
+        let tc_mutation = McMutation::SetRemotePendingDebt(new_remote_pending_debt);
+        self.mutual_credit.mutate(&tc_mutation);
+        tc_mutations.push(tc_mutation);
+
+        // Add to balance:
         let tc_mutation = McMutation::SetBalance(new_balance);
         self.mutual_credit.mutate(&tc_mutation);
         tc_mutations.push(tc_mutation);
@@ -1,25 +1,32 @@
-use crypto::identity::{
-    generate_pkcs8_key_pair, Identity, Signature, SoftwareEd25519Identity, SIGNATURE_LEN,
-};
+use im::hashset::HashSet as ImHashSet;
+
+use crypto::identity::{Identity, Signature, SIGNATURE_LEN};
 use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
-use crypto::test_utils::DummyRandom;
+use crypto::test_utils::{fixture_software_identity, DummyRandom};
 use crypto::uid::{Uid, UID_LEN};
 
-use crypto::crypto_rand::{RandValue, RAND_VALUE_LEN};
+use crypto::crypto_rand::{CryptoRandom, RandValue, RAND_VALUE_LEN};
 use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
 
+use proto::consts::MAX_ROUTE_LEN;
 use proto::funder::messages::{
-    FailureSendFunds, FriendTcOp, FriendsRoute, RequestSendFunds, RequestsStatus, ResponseSendFunds,
+    FailureReason, FailureSendFunds, FriendTcOp, FriendsRoute, OperationErrorCode, PendingRequest,
+    RequestSendFunds, RequestsStatus, ResponseSendFunds,
 };
 use proto::funder::signature_buff::{
     create_failure_signature_buffer, create_response_signature_buffer,
 };
 
-use crate::mutual_credit::types::MutualCredit;
+use crate::credit_calc::CreditCalculator;
+use crate::invariants::InvariantError;
+use crate::mutual_credit::types::{
+    BalanceForResetError, McMutation, MutualCredit, MAX_FUNDER_DEBT,
+};
 use crate::types::create_pending_request;
 
 use crate::mutual_credit::incoming::{
-    process_operation, ProcessOperationError, ProcessOperationOutput,
+    process_operation, process_operations_list, IncomingMessage, ProcessOperationError,
+    ProcessOperationOutput,
 };
 use crate::mutual_credit::outgoing::{OutgoingMc, QueueOperationError};
 
@@ -107,9 +114,7 @@ fn test_request_response_send_funds() {
     // Remote side should open his requests status:
     apply_incoming(&mut mutual_credit, FriendTcOp::EnableRequests).unwrap();
 
-    let rng = DummyRandom::new(&[1u8]);
-    let pkcs8 = generate_pkcs8_key_pair(&rng);
-    let identity = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
+    let identity = fixture_software_identity(1);
     let public_key_c = identity.get_public_key();
 
     let request_id = Uid::from(&[3; UID_LEN]);
@@ -171,9 +176,7 @@ fn test_request_response_send_funds() {
 
 #[test]
 fn test_request_failure_send_funds() {
-    let rng = DummyRandom::new(&[1u8]);
-    let pkcs8 = generate_pkcs8_key_pair(&rng);
-    let identity = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
+    let identity = fixture_software_identity(1);
     let public_key_b = identity.get_public_key();
 
     let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
@@ -223,6 +226,7 @@ fn test_request_failure_send_funds() {
     let mut failure_send_funds = FailureSendFunds {
         request_id,
         reporting_public_key: public_key_b.clone(),
+        reason: FailureReason::Unspecified,
         rand_nonce,
         signature: Signature::from(&[0; SIGNATURE_LEN]),
     };
@@ -242,3 +246,835 @@ fn test_request_failure_send_funds() {
     assert_eq!(mutual_credit.state().balance.local_pending_debt, 0);
     assert_eq!(mutual_credit.state().balance.remote_pending_debt, 0);
 }
+
+/// A route of 4 nodes: A -- B -- C -- D
+/// We are B, and the remote side is C. The request B sent to C is pending.
+/// Returns the pending request and the mutual credit of B with C.
+fn create_pending_hop_request(
+    public_key_c: &PublicKey,
+    public_key_d: &PublicKey,
+) -> (PendingRequest, MutualCredit) {
+    let public_key_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let mut mutual_credit = MutualCredit::new(&public_key_b, public_key_c, 0);
+
+    apply_incoming(&mut mutual_credit, FriendTcOp::SetRemoteMaxDebt(100)).unwrap();
+    apply_incoming(&mut mutual_credit, FriendTcOp::EnableRequests).unwrap();
+
+    let request_send_funds = RequestSendFunds {
+        request_id: Uid::from(&[3; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                public_key_b.clone(),
+                public_key_c.clone(),
+                public_key_d.clone(),
+            ],
+        },
+        dest_payment: 10,
+        invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+    };
+
+    let pending_request = create_pending_request(&request_send_funds);
+    apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::RequestSendFunds(request_send_funds),
+    )
+    .unwrap();
+
+    (pending_request, mutual_credit)
+}
+
+#[test]
+fn test_hop_rejects_corrupted_failure_signature() {
+    let identity_c = fixture_software_identity(1);
+    let public_key_c = identity_c.get_public_key();
+    let public_key_d = PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]);
+    let (pending_request, mut mutual_credit) =
+        create_pending_hop_request(&public_key_c, &public_key_d);
+
+    let local_pending_debt = mutual_credit.state().balance.local_pending_debt;
+    assert!(local_pending_debt > 0);
+
+    // C reports a failure, but the signature is corrupted on the way:
+    let mut failure_send_funds = FailureSendFunds {
+        request_id: pending_request.request_id,
+        reporting_public_key: public_key_c.clone(),
+        reason: FailureReason::Unspecified,
+        rand_nonce: RandValue::from(&[5; RAND_VALUE_LEN]),
+        signature: Signature::from(&[0; SIGNATURE_LEN]),
+    };
+    let sign_buffer = create_failure_signature_buffer(&failure_send_funds, &pending_request);
+    failure_send_funds.signature = identity_c.sign(&sign_buffer);
+    failure_send_funds.signature[0] ^= 0x01;
+
+    match apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::FailureSendFunds(failure_send_funds),
+    ) {
+        Err(ProcessOperationError::InvalidFailureSignature) => {}
+        _ => unreachable!(),
+    };
+
+    // Credits were not changed, and the request is still pending:
+    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(
+        mutual_credit.state().balance.local_pending_debt,
+        local_pending_debt
+    );
+    assert!(mutual_credit
+        .state()
+        .pending_requests
+        .pending_local_requests
+        .contains_key(&pending_request.request_id));
+}
+
+#[test]
+fn test_hop_rejects_forged_response_signature() {
+    let identity_c = fixture_software_identity(1);
+    let public_key_c = identity_c.get_public_key();
+    let identity_d = fixture_software_identity(2);
+    let public_key_d = identity_d.get_public_key();
+    let (pending_request, mut mutual_credit) =
+        create_pending_hop_request(&public_key_c, &public_key_d);
+
+    let local_pending_debt = mutual_credit.state().balance.local_pending_debt;
+
+    // C signs a response on its own, pretending to be the destination D:
+    let mut response_send_funds = ResponseSendFunds {
+        request_id: pending_request.request_id,
+        rand_nonce: RandValue::from(&[5; RAND_VALUE_LEN]),
+        signature: Signature::from(&[0; SIGNATURE_LEN]),
+    };
+    let sign_buffer = create_response_signature_buffer(&response_send_funds, &pending_request);
+    response_send_funds.signature = identity_c.sign(&sign_buffer);
+
+    match apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::ResponseSendFunds(response_send_funds.clone()),
+    ) {
+        Err(ProcessOperationError::InvalidResponseSignature) => {}
+        _ => unreachable!(),
+    };
+    assert_eq!(mutual_credit.state().balance.balance, 0);
+    assert_eq!(
+        mutual_credit.state().balance.local_pending_debt,
+        local_pending_debt
+    );
+
+    // A response signed by D is accepted:
+    response_send_funds.signature = identity_d.sign(&sign_buffer);
+    apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::ResponseSendFunds(response_send_funds),
+    )
+    .unwrap();
+    assert_eq!(
+        mutual_credit.state().balance.balance,
+        -(local_pending_debt as i128)
+    );
+    assert_eq!(mutual_credit.state().balance.local_pending_debt, 0);
+}
+
+/// Create a request to send funds along a route of `route_len` nodes,
+/// where the first two nodes on the route are `first` and `second`.
+fn create_request_send_funds(
+    request_id: Uid,
+    first: &PublicKey,
+    second: &PublicKey,
+    route_len: usize,
+) -> RequestSendFunds {
+    let mut public_keys = vec![first.clone(), second.clone()];
+    for i in 0..route_len - 2 {
+        public_keys.push(PublicKey::from(&[i as u8; PUBLIC_KEY_LEN]));
+    }
+    RequestSendFunds {
+        request_id,
+        route: FriendsRoute { public_keys },
+        dest_payment: 10,
+        invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+    }
+}
+
+#[test]
+fn test_request_send_funds_max_route_len() {
+    let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let remote_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let balance = 0;
+    let mut mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, balance);
+
+    // Allow requests in both directions:
+    apply_incoming(&mut mutual_credit, FriendTcOp::SetRemoteMaxDebt(1000)).unwrap();
+    apply_incoming(&mut mutual_credit, FriendTcOp::EnableRequests).unwrap();
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::SetRemoteMaxDebt(1000)).unwrap();
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::EnableRequests).unwrap();
+
+    // Outgoing requests:
+    let request_send_funds = create_request_send_funds(
+        Uid::from(&[0; UID_LEN]),
+        &local_public_key,
+        &remote_public_key,
+        MAX_ROUTE_LEN,
+    );
+    apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::RequestSendFunds(request_send_funds),
+    )
+    .unwrap();
+
+    let request_send_funds = create_request_send_funds(
+        Uid::from(&[1; UID_LEN]),
+        &local_public_key,
+        &remote_public_key,
+        MAX_ROUTE_LEN + 1,
+    );
+    match apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::RequestSendFunds(request_send_funds),
+    ) {
+        Err(QueueOperationError::InvalidRoute) => {}
+        _ => unreachable!(),
+    };
+
+    // Incoming requests:
+    let request_send_funds = create_request_send_funds(
+        Uid::from(&[2; UID_LEN]),
+        &remote_public_key,
+        &local_public_key,
+        MAX_ROUTE_LEN,
+    );
+    apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::RequestSendFunds(request_send_funds),
+    )
+    .unwrap();
+
+    let request_send_funds = create_request_send_funds(
+        Uid::from(&[3; UID_LEN]),
+        &remote_public_key,
+        &local_public_key,
+        MAX_ROUTE_LEN + 1,
+    );
+    match apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::RequestSendFunds(request_send_funds),
+    ) {
+        Err(ProcessOperationError::InvalidRoute) => {}
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_incoming_request_exhausts_max_funder_debt() {
+    let identity = fixture_software_identity(1);
+    let local_public_key = identity.get_public_key();
+    let remote_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let balance = 0;
+    let mut mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, balance);
+
+    // Trust the remote side with the maximum possible debt, and allow incoming requests:
+    apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::SetRemoteMaxDebt(MAX_FUNDER_DEBT),
+    )
+    .unwrap();
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::EnableRequests).unwrap();
+
+    // The remote side pays us directly, freezing exactly all of its trust:
+    let request_send_funds = RequestSendFunds {
+        request_id: Uid::from(&[0; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![remote_public_key.clone(), local_public_key.clone()],
+        },
+        dest_payment: MAX_FUNDER_DEBT,
+        invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+    };
+    let pending_request = create_pending_request(&request_send_funds);
+    apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::RequestSendFunds(request_send_funds),
+    )
+    .unwrap();
+    assert_eq!(
+        mutual_credit.state().balance.remote_pending_debt,
+        MAX_FUNDER_DEBT
+    );
+
+    // One more credit is beyond the trust we gave:
+    let request_send_funds = RequestSendFunds {
+        request_id: Uid::from(&[1; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![remote_public_key.clone(), local_public_key.clone()],
+        },
+        dest_payment: 1,
+        invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
+    };
+    match apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::RequestSendFunds(request_send_funds),
+    ) {
+        Err(ProcessOperationError::InsufficientTrust) => {}
+        _ => unreachable!(),
+    };
+
+    // While the credits are frozen, the balance for reset includes them:
+    assert_eq!(
+        mutual_credit.balance_for_reset(),
+        Ok(MAX_FUNDER_DEBT as i128)
+    );
+
+    // Complete the payment:
+    let mut response_send_funds = ResponseSendFunds {
+        request_id: Uid::from(&[0; UID_LEN]),
+        rand_nonce: RandValue::from(&[5; RAND_VALUE_LEN]),
+        signature: Signature::from(&[0; SIGNATURE_LEN]),
+    };
+    let sign_buffer = create_response_signature_buffer(&response_send_funds, &pending_request);
+    response_send_funds.signature = identity.sign(&sign_buffer);
+    apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::ResponseSendFunds(response_send_funds),
+    )
+    .unwrap();
+
+    assert_eq!(
+        mutual_credit.state().balance.balance,
+        MAX_FUNDER_DEBT as i128
+    );
+    assert_eq!(mutual_credit.state().balance.remote_pending_debt, 0);
+    assert_eq!(
+        mutual_credit.balance_for_reset(),
+        Ok(MAX_FUNDER_DEBT as i128)
+    );
+}
+
+#[test]
+fn test_outgoing_request_exhausts_max_funder_debt() {
+    let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let remote_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let balance = 0;
+    let mut mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, balance);
+
+    // The remote side trusts us with the maximum possible debt:
+    apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::SetRemoteMaxDebt(MAX_FUNDER_DEBT),
+    )
+    .unwrap();
+    apply_incoming(&mut mutual_credit, FriendTcOp::EnableRequests).unwrap();
+
+    let public_key_c = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+    let create_request = |index: u8, dest_payment: u128| RequestSendFunds {
+        request_id: Uid::from(&[index; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![
+                local_public_key.clone(),
+                remote_public_key.clone(),
+                public_key_c.clone(),
+            ],
+        },
+        dest_payment,
+        invoice_id: InvoiceId::from(&[index; INVOICE_ID_LEN]),
+    };
+
+    // The fee for the remote side is one credit, so the request freezes dest_payment + 1 credits.
+    // Freezing one or two credits beyond our trust, or an amount that does not fit in a u128,
+    // fails gracefully:
+    for &(index, dest_payment) in &[
+        (0, MAX_FUNDER_DEBT),
+        (1, MAX_FUNDER_DEBT + 1),
+        (2, u128::max_value()),
+    ] {
+        match apply_outgoing(
+            &mut mutual_credit,
+            &FriendTcOp::RequestSendFunds(create_request(index, dest_payment)),
+        ) {
+            Err(QueueOperationError::InsufficientTrust) => {}
+            _ => unreachable!(),
+        };
+    }
+    assert_eq!(mutual_credit.state().balance.local_pending_debt, 0);
+
+    // Freezing exactly all of our trust succeeds:
+    apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::RequestSendFunds(create_request(3, MAX_FUNDER_DEBT - 1)),
+    )
+    .unwrap();
+    assert_eq!(
+        mutual_credit.state().balance.local_pending_debt,
+        MAX_FUNDER_DEBT
+    );
+
+    match apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::RequestSendFunds(create_request(4, 1)),
+    ) {
+        Err(QueueOperationError::InsufficientTrust) => {}
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_balance_for_reset_overflow() {
+    let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let remote_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+    let mutual_credit = MutualCredit::new(
+        &local_public_key,
+        &remote_public_key,
+        -(MAX_FUNDER_DEBT as i128),
+    );
+    assert_eq!(
+        mutual_credit.balance_for_reset(),
+        Ok(-(MAX_FUNDER_DEBT as i128))
+    );
+
+    // The remote side would not be able to use the negation of this balance:
+    let mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, i128::min_value());
+    assert_eq!(
+        mutual_credit.balance_for_reset(),
+        Err(BalanceForResetError::Overflow)
+    );
+}
+
+#[test]
+fn test_validate_invariants() {
+    let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let remote_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, 0);
+    assert_eq!(mutual_credit.validate_invariants(), Ok(()));
+
+    // The remote side may lower our max debt below our pending debt:
+    let mut mc = mutual_credit.clone();
+    mc.mutate(&McMutation::SetLocalMaxDebt(100));
+    mc.mutate(&McMutation::SetLocalPendingDebt(100));
+    mc.mutate(&McMutation::SetLocalMaxDebt(10));
+    assert_eq!(mc.validate_invariants(), Ok(()));
+
+    // Debts beyond MAX_FUNDER_DEBT:
+    let mut mc = mutual_credit.clone();
+    mc.mutate(&McMutation::SetLocalPendingDebt(MAX_FUNDER_DEBT + 1));
+    assert_eq!(
+        mc.validate_invariants(),
+        Err(InvariantError::DebtOutOfRange(remote_public_key.clone()))
+    );
+
+    let mut mc = mutual_credit.clone();
+    mc.mutate(&McMutation::SetRemoteMaxDebt(MAX_FUNDER_DEBT + 1));
+    assert_eq!(
+        mc.validate_invariants(),
+        Err(InvariantError::DebtOutOfRange(remote_public_key.clone()))
+    );
+
+    // Resolving the local pending requests would overflow the balance:
+    let mut mc = mutual_credit.clone();
+    mc.mutate(&McMutation::SetLocalPendingDebt(11));
+    mc.mutate(&McMutation::SetBalance(i128::min_value() + 5));
+    assert_eq!(
+        mc.validate_invariants(),
+        Err(InvariantError::BalanceOutOfRange(remote_public_key.clone()))
+    );
+
+    // The balance for reset can not be computed:
+    let mut mc = mutual_credit.clone();
+    mc.mutate(&McMutation::SetBalance(i128::max_value()));
+    mc.mutate(&McMutation::SetRemotePendingDebt(1));
+    assert!(mc.balance_for_reset().is_err());
+    assert_eq!(
+        mc.validate_invariants(),
+        Err(InvariantError::BalanceOutOfRange(remote_public_key.clone()))
+    );
+
+    // The balance can not be negated by the remote side:
+    let mut mc = mutual_credit.clone();
+    mc.mutate(&McMutation::SetBalance(i128::min_value()));
+    assert_eq!(
+        mc.validate_invariants(),
+        Err(InvariantError::BalanceOutOfRange(remote_public_key.clone()))
+    );
+}
+
+/// Draw a random byte.
+fn rand_byte<R: CryptoRandom>(rng: &R) -> u8 {
+    RandValue::new(rng)[0]
+}
+
+/// Recompute from scratch the amount of credits frozen by the pending local requests.
+/// We are always the first node of the route.
+fn naive_local_pending_debt(mutual_credit: &MutualCredit) -> u128 {
+    mutual_credit
+        .state()
+        .pending_requests
+        .pending_local_requests
+        .values()
+        .map(|pending_request| {
+            CreditCalculator::new(pending_request.route.len(), pending_request.dest_payment)
+                .unwrap()
+                .credits_to_freeze(1)
+                .unwrap()
+        })
+        .sum()
+}
+
+#[test]
+fn test_local_pending_debt_random_operations() {
+    // A -- B -- C -- D
+    // We are A, and the remote side is B.
+    let identity_b = fixture_software_identity(1);
+    let identity_c = fixture_software_identity(2);
+    let identity_d = fixture_software_identity(3);
+
+    let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let remote_public_key = identity_b.get_public_key();
+    let mut mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, 0);
+
+    apply_incoming(&mut mutual_credit, FriendTcOp::SetRemoteMaxDebt(1_000_000)).unwrap();
+    apply_incoming(&mut mutual_credit, FriendTcOp::EnableRequests).unwrap();
+
+    let rng = DummyRandom::new(&[1u8]);
+    let mut pending_requests = Vec::new();
+
+    for iter in 0..200u8 {
+        let action = rand_byte(&rng) % 3;
+        if action == 0 || pending_requests.is_empty() {
+            // Add a request, frozen credits go up:
+            let mut public_keys = vec![
+                local_public_key.clone(),
+                remote_public_key.clone(),
+                identity_c.get_public_key(),
+            ];
+            if rand_byte(&rng) % 2 == 0 {
+                public_keys.push(identity_d.get_public_key());
+            }
+            let request_send_funds = RequestSendFunds {
+                request_id: Uid::from(&[iter; UID_LEN]),
+                route: FriendsRoute { public_keys },
+                dest_payment: u128::from(rand_byte(&rng)) + 1,
+                invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+            };
+            pending_requests.push(create_pending_request(&request_send_funds));
+            apply_outgoing(
+                &mut mutual_credit,
+                &FriendTcOp::RequestSendFunds(request_send_funds),
+            )
+            .unwrap();
+        } else {
+            // Resolve a pending request, frozen credits go down:
+            let index = usize::from(rand_byte(&rng)) % pending_requests.len();
+            let pending_request = pending_requests.remove(index);
+            let rand_nonce = RandValue::new(&rng);
+            let friend_tc_op = if action == 1 {
+                let mut response_send_funds = ResponseSendFunds {
+                    request_id: pending_request.request_id,
+                    rand_nonce,
+                    signature: Signature::from(&[0; SIGNATURE_LEN]),
+                };
+                let sign_buffer =
+                    create_response_signature_buffer(&response_send_funds, &pending_request);
+                let identity_dest = if pending_request.route.len() == 3 {
+                    &identity_c
+                } else {
+                    &identity_d
+                };
+                response_send_funds.signature = identity_dest.sign(&sign_buffer);
+                FriendTcOp::ResponseSendFunds(response_send_funds)
+            } else {
+                let mut failure_send_funds = FailureSendFunds {
+                    request_id: pending_request.request_id,
+                    reporting_public_key: remote_public_key.clone(),
+                    reason: FailureReason::Unspecified,
+                    rand_nonce,
+                    signature: Signature::from(&[0; SIGNATURE_LEN]),
+                };
+                let sign_buffer =
+                    create_failure_signature_buffer(&failure_send_funds, &pending_request);
+                failure_send_funds.signature = identity_b.sign(&sign_buffer);
+                FriendTcOp::FailureSendFunds(failure_send_funds)
+            };
+            apply_incoming(&mut mutual_credit, friend_tc_op).unwrap();
+        }
+
+        assert_eq!(
+            mutual_credit.state().balance.local_pending_debt,
+            naive_local_pending_debt(&mutual_credit)
+        );
+        assert_eq!(
+            mutual_credit
+                .state()
+                .pending_requests
+                .pending_local_requests
+                .len(),
+            pending_requests.len()
+        );
+        assert_eq!(mutual_credit.validate_invariants(), Ok(()));
+    }
+}
+
+/// Process a list of incoming operations.
+/// Returns the outputs, or the index and the error code of the invalid operation.
+fn apply_incoming_list(
+    mutual_credit: &mut MutualCredit,
+    operations: Vec<FriendTcOp>,
+) -> Result<Vec<ProcessOperationOutput>, (usize, OperationErrorCode)> {
+    let mut outputs = Vec::new();
+    process_operations_list(mutual_credit, operations, &ImHashSet::new(), |output| {
+        outputs.push(output)
+    })
+    .map_err(|e| (e.index(), e.error().error_code()))?;
+    Ok(outputs)
+}
+
+#[test]
+fn test_operations_list_contradictions() {
+    let identity_b = fixture_software_identity(1);
+    let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let remote_public_key = identity_b.get_public_key();
+    let mut mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, 0);
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::EnableRequests).unwrap();
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::SetRemoteMaxDebt(100)).unwrap();
+
+    let request_op = |request_id: u8| {
+        FriendTcOp::RequestSendFunds(RequestSendFunds {
+            request_id: Uid::from(&[request_id; UID_LEN]),
+            route: FriendsRoute {
+                public_keys: vec![remote_public_key.clone(), local_public_key.clone()],
+            },
+            dest_payment: 10,
+            invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+        })
+    };
+    let response_op = |request_id: u8| {
+        FriendTcOp::ResponseSendFunds(ResponseSendFunds {
+            request_id: Uid::from(&[request_id; UID_LEN]),
+            rand_nonce: RandValue::from(&[5; RAND_VALUE_LEN]),
+            signature: Signature::from(&[0; SIGNATURE_LEN]),
+        })
+    };
+    let failure_op = |request_id: u8| {
+        FriendTcOp::FailureSendFunds(FailureSendFunds {
+            request_id: Uid::from(&[request_id; UID_LEN]),
+            reporting_public_key: remote_public_key.clone(),
+            reason: FailureReason::Unspecified,
+            rand_nonce: RandValue::from(&[5; RAND_VALUE_LEN]),
+            signature: Signature::from(&[0; SIGNATURE_LEN]),
+        })
+    };
+
+    let bad_lists = vec![
+        (
+            vec![request_op(1), request_op(1)],
+            (1, OperationErrorCode::DuplicateRequestId),
+        ),
+        (
+            vec![response_op(2), failure_op(2)],
+            (1, OperationErrorCode::DuplicateRequestId),
+        ),
+        (
+            vec![failure_op(2), request_op(2)],
+            (1, OperationErrorCode::DuplicateRequestId),
+        ),
+        (
+            vec![request_op(1), response_op(1)],
+            (1, OperationErrorCode::ResolvesRequestOfSameBatch),
+        ),
+        (
+            vec![
+                FriendTcOp::EnableRequests,
+                request_op(1),
+                request_op(3),
+                failure_op(3),
+            ],
+            (3, OperationErrorCode::ResolvesRequestOfSameBatch),
+        ),
+    ];
+
+    for (operations, expected_error) in bad_lists {
+        assert_eq!(
+            apply_incoming_list(&mut mutual_credit, operations).err(),
+            Some(expected_error)
+        );
+        // The list was rejected before any of the operations was processed:
+        assert_eq!(
+            mutual_credit.state().requests_status.remote,
+            RequestsStatus::Closed
+        );
+        assert!(mutual_credit
+            .state()
+            .pending_requests
+            .pending_remote_requests
+            .is_empty());
+    }
+}
+
+#[test]
+fn test_operations_list_complex() {
+    // A -- B -- C
+    // We are A, and the remote side is B.
+    let identity_b = fixture_software_identity(1);
+    let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let remote_public_key = identity_b.get_public_key();
+    let mut mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, 0);
+
+    apply_incoming(&mut mutual_credit, FriendTcOp::SetRemoteMaxDebt(100)).unwrap();
+    apply_incoming(&mut mutual_credit, FriendTcOp::EnableRequests).unwrap();
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::SetRemoteMaxDebt(100)).unwrap();
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::EnableRequests).unwrap();
+
+    // A request from A to C:
+    let local_request = RequestSendFunds {
+        request_id: Uid::from(&[1; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![
+                local_public_key.clone(),
+                remote_public_key.clone(),
+                PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]),
+            ],
+        },
+        dest_payment: 10,
+        invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+    };
+    let pending_request = create_pending_request(&local_request);
+    apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::RequestSendFunds(local_request),
+    )
+    .unwrap();
+
+    let mut failure_send_funds = FailureSendFunds {
+        request_id: pending_request.request_id,
+        reporting_public_key: remote_public_key.clone(),
+        reason: FailureReason::Unspecified,
+        rand_nonce: RandValue::from(&[5; RAND_VALUE_LEN]),
+        signature: Signature::from(&[0; SIGNATURE_LEN]),
+    };
+    let sign_buffer = create_failure_signature_buffer(&failure_send_funds, &pending_request);
+    failure_send_funds.signature = identity_b.sign(&sign_buffer);
+
+    // A request from B to A:
+    let remote_request = RequestSendFunds {
+        request_id: Uid::from(&[2; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![remote_public_key.clone(), local_public_key.clone()],
+        },
+        dest_payment: 10,
+        invoice_id: InvoiceId::from(&[0; INVOICE_ID_LEN]),
+    };
+
+    // B lowers our max debt, sends a request, fails our request, and toggles its requests
+    // status, all in one list:
+    let operations = vec![
+        FriendTcOp::SetRemoteMaxDebt(50),
+        FriendTcOp::RequestSendFunds(remote_request),
+        FriendTcOp::FailureSendFunds(failure_send_funds),
+        FriendTcOp::DisableRequests,
+        FriendTcOp::EnableRequests,
+    ];
+    let outputs = apply_incoming_list(&mut mutual_credit, operations).unwrap();
+    assert_eq!(outputs.len(), 5);
+    match &outputs[1].incoming_message {
+        Some(IncomingMessage::Request(request_send_funds)) => {
+            assert_eq!(request_send_funds.request_id, Uid::from(&[2; UID_LEN]))
+        }
+        _ => unreachable!(),
+    };
+    match &outputs[2].incoming_message {
+        Some(IncomingMessage::Failure(incoming_failure)) => assert_eq!(
+            incoming_failure.pending_request.request_id,
+            Uid::from(&[1; UID_LEN])
+        ),
+        _ => unreachable!(),
+    };
+
+    let mc_state = mutual_credit.state();
+    assert_eq!(mc_state.balance.local_max_debt, 50);
+    assert_eq!(mc_state.balance.local_pending_debt, 0);
+    assert!(mc_state.balance.remote_pending_debt > 0);
+    assert!(mc_state.pending_requests.pending_local_requests.is_empty());
+    assert_eq!(mc_state.pending_requests.pending_remote_requests.len(), 1);
+    assert_eq!(mc_state.requests_status.remote, RequestsStatus::Open);
+}
+
+#[test]
+fn test_response_insufficient_frozen_credits_no_mutation() {
+    let identity_a = fixture_software_identity(1);
+    let identity_b = fixture_software_identity(2);
+    let local_public_key = identity_a.get_public_key();
+    let remote_public_key = identity_b.get_public_key();
+    let balance = 0;
+    let mut mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, balance);
+
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::SetRemoteMaxDebt(100)).unwrap();
+    apply_outgoing(&mut mutual_credit, &FriendTcOp::EnableRequests).unwrap();
+    apply_incoming(&mut mutual_credit, FriendTcOp::SetRemoteMaxDebt(100)).unwrap();
+    apply_incoming(&mut mutual_credit, FriendTcOp::EnableRequests).unwrap();
+
+    let create_request = |index: u8, public_keys: Vec<PublicKey>| RequestSendFunds {
+        request_id: Uid::from(&[index; UID_LEN]),
+        route: FriendsRoute { public_keys },
+        dest_payment: 10,
+        invoice_id: InvoiceId::from(&[index; INVOICE_ID_LEN]),
+    };
+    let create_response = |pending_request: &PendingRequest, identity: &dyn Identity| {
+        let mut response_send_funds = ResponseSendFunds {
+            request_id: pending_request.request_id,
+            rand_nonce: RandValue::from(&[5; RAND_VALUE_LEN]),
+            signature: Signature::from(&[0; SIGNATURE_LEN]),
+        };
+        let sign_buffer = create_response_signature_buffer(&response_send_funds, pending_request);
+        response_send_funds.signature = identity.sign(&sign_buffer);
+        response_send_funds
+    };
+
+    // A request from us to B, and a request from B to us:
+    let local_request =
+        create_request(0, vec![local_public_key.clone(), remote_public_key.clone()]);
+    let local_pending_request = create_pending_request(&local_request);
+    apply_outgoing(
+        &mut mutual_credit,
+        &FriendTcOp::RequestSendFunds(local_request),
+    )
+    .unwrap();
+
+    let remote_request =
+        create_request(1, vec![remote_public_key.clone(), local_public_key.clone()]);
+    let remote_pending_request = create_pending_request(&remote_request);
+    apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::RequestSendFunds(remote_request),
+    )
+    .unwrap();
+
+    // Lose track of the frozen credits on both sides:
+    mutual_credit.mutate(&McMutation::SetLocalPendingDebt(0));
+    mutual_credit.mutate(&McMutation::SetRemotePendingDebt(0));
+    let balance_before = mutual_credit.state().balance.clone();
+
+    // An incoming response can not unfreeze credits, and leaves the mutual credit untouched:
+    let incoming_response = create_response(&local_pending_request, &identity_b);
+    match apply_incoming(
+        &mut mutual_credit,
+        FriendTcOp::ResponseSendFunds(incoming_response),
+    ) {
+        Err(ProcessOperationError::InsufficientFrozenCredits) => {}
+        _ => unreachable!(),
+    };
+    let mc_state = mutual_credit.state();
+    assert_eq!(mc_state.balance.balance, balance_before.balance);
+    assert_eq!(mc_state.balance.local_pending_debt, 0);
+    assert_eq!(mc_state.balance.remote_pending_debt, 0);
+    assert!(mc_state
+        .pending_requests
+        .pending_local_requests
+        .contains_key(&local_pending_request.request_id));
+
+    // Queueing an outgoing response fails the same way every time, because the pending request
+    // is not removed by a failed attempt:
+    let outgoing_response = create_response(&remote_pending_request, &identity_a);
+    let mut outgoing = OutgoingMc::new(&mutual_credit);
+    for _ in 0..2 {
+        match outgoing.queue_operation(&FriendTcOp::ResponseSendFunds(outgoing_response.clone())) {
+            Err(QueueOperationError::InsufficientFrozenCredits) => {}
+            _ => unreachable!(),
+        };
+    }
+}
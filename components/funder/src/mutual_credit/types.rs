@@ -6,6 +6,8 @@ use crypto::uid::Uid;
 
 use proto::funder::messages::{PendingRequest, RequestsStatus};
 
+use crate::invariants::InvariantError;
+
 /// The maximum possible funder debt.
 /// We don't use the full u128 because i128 can not go beyond this value.
 pub const MAX_FUNDER_DEBT: u128 = (1 << 127) - 1;
@@ -99,6 +101,12 @@ pub struct MutualCredit {
     state: MutualCreditState,
 }
 
+#[derive(Debug, PartialEq, Eq)]
+pub enum BalanceForResetError {
+    /// The balance for reset (Or its negation) can not be represented as an i128.
+    Overflow,
+}
+
 #[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub enum McMutation {
     SetLocalRequestsStatus(RequestsStatus),
@@ -135,12 +143,16 @@ impl MutualCredit {
 
     /// Calculate required balance for reset.
     /// This would be current balance plus additional future profits.
-    pub fn balance_for_reset(&self) -> i128 {
+    ///
+    /// The remote side will use the negation of this value as its balance, hence the result
+    /// must be negatable (It can not be i128::min_value()).
+    pub fn balance_for_reset(&self) -> Result<i128, BalanceForResetError> {
         self.state
             .balance
             .balance
             .checked_add_unsigned(self.state.balance.remote_pending_debt)
-            .expect("Overflow when calculating balance_for_reset")
+            .filter(|balance_for_reset| balance_for_reset.checked_neg().is_some())
+            .ok_or(BalanceForResetError::Overflow)
         // TODO: Is this the correct formula?
         // Other options:
         // *    balance
@@ -151,6 +163,42 @@ impl MutualCredit {
         &self.state
     }
 
+    /// Verify that the debts and the balance are in range.
+    ///
+    /// Note that the pending debts are not compared against the current max debts: The max debts
+    /// are only enforced when a request is made, and the remote side may lower our max debt at
+    /// any time. Hence debts are only bounded by MAX_FUNDER_DEBT.
+    pub fn validate_invariants(&self) -> Result<(), InvariantError> {
+        let balance = &self.state.balance;
+        let remote_public_key = &self.state.idents.remote_public_key;
+
+        let debts_in_range = balance.local_max_debt <= MAX_FUNDER_DEBT
+            && balance.remote_max_debt <= MAX_FUNDER_DEBT
+            && balance.local_pending_debt <= MAX_FUNDER_DEBT
+            && balance.remote_pending_debt <= MAX_FUNDER_DEBT;
+        if !debts_in_range {
+            return Err(InvariantError::DebtOutOfRange(remote_public_key.clone()));
+        }
+
+        // The balance must be representable on both sides, and resolving all pending requests must
+        // not overflow it:
+        let balance_in_range = balance.balance != i128::min_value()
+            && balance
+                .balance
+                .checked_sub_unsigned(balance.local_pending_debt)
+                .is_some()
+            && balance
+                .balance
+                .checked_add_unsigned(balance.remote_pending_debt)
+                .is_some()
+            && self.balance_for_reset().is_ok();
+        if !balance_in_range {
+            return Err(InvariantError::BalanceOutOfRange(remote_public_key.clone()));
+        }
+
+        Ok(())
+    }
+
     pub fn mutate(&mut self, tc_mutation: &McMutation) {
         match tc_mutation {
             McMutation::SetLocalRequestsStatus(requests_status) => {
@@ -0,0 +1,265 @@
+use std::collections::VecDeque;
+
+use common::bounded_cache::{BoundedCache, CacheLimits};
+use crypto::uid::Uid;
+
+use proto::consts::PAYMENT_LATENCY_SAMPLES;
+use proto::funder::messages::{FriendsRoute, PaymentTiming};
+
+/// The ticks in which a payment we originate has passed the measurement points.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PaymentMarks {
+    /// The payment was requested by the user
+    pub start_ticks: u64,
+    /// The move token carrying the request was first sent to the first hop
+    pub opt_sent_ticks: Option<u64>,
+}
+
+/// Percentiles of the amounts of ticks it took recent payments to succeed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyPercentiles {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+}
+
+/// Measures the amount of ticks it takes until payments we originate are answered.
+///
+/// Measurement points are kept in a bounded cache. If the marks of a payment are evicted early,
+/// the payment is still handled, but its latency is not measured.
+#[derive(Clone)]
+pub struct PaymentTimings {
+    /// Amount of ticks that have passed since the funder has started.
+    pub ticks: u64,
+    /// The measurement points of each of our pending payments.
+    pub started: BoundedCache<Uid, PaymentMarks>,
+    /// Latencies of recent successful payments, oldest first.
+    pub recent_latencies: VecDeque<u64>,
+}
+
+#[derive(Debug)]
+pub enum PaymentTimingMutation {
+    /// The given amount of time ticks has passed.
+    Tick(u64),
+    /// A payment was requested by the user.
+    Start(Uid),
+    /// A move token carrying the request of a payment was sent.
+    Sent(Uid),
+    /// A payment has succeeded. Its latency is kept for the metrics.
+    Complete(Uid),
+    /// A payment is no longer pending.
+    Forget(Uid),
+}
+
+impl PaymentTimings {
+    pub fn new(limits: CacheLimits) -> PaymentTimings {
+        PaymentTimings {
+            ticks: 0,
+            started: BoundedCache::new(limits),
+            recent_latencies: VecDeque::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &PaymentTimingMutation) {
+        match mutation {
+            PaymentTimingMutation::Tick(ticks_elapsed) => {
+                self.ticks = self.ticks.wrapping_add(*ticks_elapsed);
+                self.started.tick(*ticks_elapsed);
+            }
+            PaymentTimingMutation::Start(request_id) => {
+                let payment_marks = PaymentMarks {
+                    start_ticks: self.ticks,
+                    opt_sent_ticks: None,
+                };
+                self.started.insert(*request_id, payment_marks);
+            }
+            PaymentTimingMutation::Sent(request_id) => {
+                // Only the first time the request was sent is measured:
+                if let Some(payment_marks) = self.started.get(request_id).cloned() {
+                    if payment_marks.opt_sent_ticks.is_none() {
+                        let payment_marks = PaymentMarks {
+                            opt_sent_ticks: Some(self.ticks),
+                            ..payment_marks
+                        };
+                        self.started.insert(*request_id, payment_marks);
+                    }
+                }
+            }
+            PaymentTimingMutation::Complete(request_id) => {
+                if let Some(latency_ticks) = self.elapsed(request_id) {
+                    if self.recent_latencies.len() >= PAYMENT_LATENCY_SAMPLES {
+                        let _ = self.recent_latencies.pop_front();
+                    }
+                    self.recent_latencies.push_back(latency_ticks);
+                }
+                let _ = self.started.remove(request_id);
+            }
+            PaymentTimingMutation::Forget(request_id) => {
+                let _ = self.started.remove(request_id);
+            }
+        }
+    }
+
+    /// Amount of ticks that have passed since a payment was requested.
+    /// Returns None if the payment is not tracked.
+    pub fn elapsed(&self, request_id: &Uid) -> Option<u64> {
+        self.started
+            .get(request_id)
+            .map(|payment_marks| self.ticks.wrapping_sub(payment_marks.start_ticks))
+    }
+
+    /// The tick in which a payment was requested. Returns None if the payment is not tracked.
+    pub fn opt_start_ticks(&self, request_id: &Uid) -> Option<u64> {
+        self.started
+            .get(request_id)
+            .map(|payment_marks| payment_marks.start_ticks)
+    }
+
+    /// The tick in which the request of a payment was first sent.
+    /// Returns None if the payment is not tracked, or was not sent yet.
+    pub fn opt_sent_ticks(&self, request_id: &Uid) -> Option<u64> {
+        self.started
+            .get(request_id)
+            .and_then(|payment_marks| payment_marks.opt_sent_ticks)
+    }
+
+    /// The timing of a payment that is answered at the current tick.
+    /// Returns None if the payment is not tracked.
+    pub fn timing(&self, request_id: &Uid, route: &FriendsRoute) -> Option<PaymentTiming> {
+        let payment_marks = self.started.get(request_id)?;
+        Some(PaymentTiming {
+            opt_ticks_to_sent: payment_marks
+                .opt_sent_ticks
+                .map(|sent_ticks| sent_ticks.wrapping_sub(payment_marks.start_ticks)),
+            ticks_to_response: self.ticks.wrapping_sub(payment_marks.start_ticks),
+            route: route.clone(),
+        })
+    }
+
+    /// Percentiles of the latencies of recent successful payments (Nearest rank).
+    /// Returns None if no payment has succeeded yet.
+    pub fn latency_percentiles(&self) -> Option<LatencyPercentiles> {
+        if self.recent_latencies.is_empty() {
+            return None;
+        }
+        let mut latencies = self.recent_latencies.iter().cloned().collect::<Vec<_>>();
+        latencies.sort_unstable();
+        let percentile = |percent: usize| {
+            let rank = (latencies.len() * percent + 99) / 100;
+            latencies[rank.saturating_sub(1)]
+        };
+        Some(LatencyPercentiles {
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+    use crypto::uid::UID_LEN;
+
+    #[test]
+    fn test_payment_timings_basic() {
+        let mut payment_timings = PaymentTimings::new(CacheLimits {
+            max_entries: 0x10,
+            max_age_ticks: 0x10,
+        });
+        let request_id_a = Uid::from(&[0xaa; UID_LEN]);
+        let request_id_b = Uid::from(&[0xbb; UID_LEN]);
+
+        payment_timings.mutate(&PaymentTimingMutation::Tick(1));
+        payment_timings.mutate(&PaymentTimingMutation::Start(request_id_a));
+        payment_timings.mutate(&PaymentTimingMutation::Tick(1));
+        payment_timings.mutate(&PaymentTimingMutation::Start(request_id_b));
+        payment_timings.mutate(&PaymentTimingMutation::Tick(1));
+        payment_timings.mutate(&PaymentTimingMutation::Tick(1));
+
+        assert_eq!(payment_timings.elapsed(&request_id_a), Some(3));
+        assert_eq!(payment_timings.elapsed(&request_id_b), Some(2));
+
+        payment_timings.mutate(&PaymentTimingMutation::Forget(request_id_a));
+        assert_eq!(payment_timings.elapsed(&request_id_a), None);
+        assert_eq!(payment_timings.started.len(), 1);
+    }
+
+    #[test]
+    fn test_payment_timings_marks() {
+        let mut payment_timings = PaymentTimings::new(CacheLimits {
+            max_entries: 0x10,
+            max_age_ticks: 0x10,
+        });
+        let request_id = Uid::from(&[0xaa; UID_LEN]);
+        let route = FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            ],
+        };
+
+        payment_timings.mutate(&PaymentTimingMutation::Start(request_id));
+        payment_timings.mutate(&PaymentTimingMutation::Tick(2));
+        payment_timings.mutate(&PaymentTimingMutation::Sent(request_id));
+        payment_timings.mutate(&PaymentTimingMutation::Tick(3));
+        // Only the first send is measured:
+        payment_timings.mutate(&PaymentTimingMutation::Sent(request_id));
+        payment_timings.mutate(&PaymentTimingMutation::Tick(1));
+
+        assert_eq!(
+            payment_timings.timing(&request_id, &route),
+            Some(PaymentTiming {
+                opt_ticks_to_sent: Some(2),
+                ticks_to_response: 6,
+                route: route.clone(),
+            })
+        );
+        assert!(payment_timings.latency_percentiles().is_none());
+
+        payment_timings.mutate(&PaymentTimingMutation::Complete(request_id));
+        assert!(payment_timings.timing(&request_id, &route).is_none());
+        assert_eq!(
+            payment_timings.latency_percentiles(),
+            Some(LatencyPercentiles {
+                p50: 6,
+                p90: 6,
+                p99: 6,
+            })
+        );
+    }
+
+    #[test]
+    fn test_payment_timings_percentiles() {
+        let mut payment_timings = PaymentTimings::new(CacheLimits {
+            max_entries: 0x10,
+            max_age_ticks: 0x100,
+        });
+        // Latencies 1..=100 ticks:
+        for latency_ticks in 1..=100u8 {
+            let request_id = Uid::from(&[latency_ticks; UID_LEN]);
+            payment_timings.mutate(&PaymentTimingMutation::Start(request_id));
+            payment_timings.mutate(&PaymentTimingMutation::Tick(u64::from(latency_ticks)));
+            payment_timings.mutate(&PaymentTimingMutation::Complete(request_id));
+        }
+        assert_eq!(
+            payment_timings.latency_percentiles(),
+            Some(LatencyPercentiles {
+                p50: 50,
+                p90: 90,
+                p99: 99,
+            })
+        );
+
+        // Only the most recent latencies are kept:
+        for index in 0..PAYMENT_LATENCY_SAMPLES {
+            let request_id = Uid::from(&[(index % 0x100) as u8; UID_LEN]);
+            payment_timings.mutate(&PaymentTimingMutation::Start(request_id));
+            payment_timings.mutate(&PaymentTimingMutation::Tick(1));
+            payment_timings.mutate(&PaymentTimingMutation::Complete(request_id));
+        }
+        assert_eq!(payment_timings.recent_latencies.len(), PAYMENT_LATENCY_SAMPLES);
+        assert_eq!(payment_timings.latency_percentiles().unwrap().p99, 1);
+    }
+}
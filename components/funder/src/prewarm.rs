@@ -0,0 +1,124 @@
+use crypto::identity::PublicKey;
+use crypto::uid::Uid;
+use im::hashmap::HashMap as ImHashMap;
+
+/// Keeps track of pre-warm requests: Requests to get the token of a friend in advance,
+/// so that an upcoming payment through this friend does not wait for the token.
+#[derive(Clone, Default)]
+pub struct Prewarm {
+    /// Pre-warm requests waiting for the token of a friend.
+    pub pending: ImHashMap<PublicKey, Vec<Uid>>,
+    /// Amount of ticks left until the token of a friend may be requested again because of a
+    /// pre-warm. A friend that does not appear here may be pre-warmed immediately.
+    pub ticks_left: ImHashMap<PublicKey, usize>,
+}
+
+#[derive(Debug)]
+pub enum PrewarmMutation {
+    /// A pre-warm request is waiting for the token of a friend.
+    AddPending((PublicKey, Uid)),
+    /// All the pre-warm requests of a friend were answered.
+    ClearPending(PublicKey),
+    /// The token of a friend was requested because of a pre-warm.
+    /// The next request will be possible only after the given amount of ticks.
+    SetRequested((PublicKey, usize)),
+    /// The given amount of time ticks has passed.
+    Tick(usize),
+}
+
+impl Prewarm {
+    pub fn new() -> Prewarm {
+        Prewarm {
+            pending: ImHashMap::new(),
+            ticks_left: ImHashMap::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &PrewarmMutation) {
+        match mutation {
+            PrewarmMutation::AddPending((public_key, request_id)) => {
+                let mut request_ids = self.pending.get(public_key).cloned().unwrap_or_default();
+                request_ids.push(request_id.clone());
+                self.pending.insert(public_key.clone(), request_ids);
+            }
+            PrewarmMutation::ClearPending(public_key) => {
+                let _ = self.pending.remove(public_key);
+            }
+            PrewarmMutation::SetRequested((public_key, ticks)) => {
+                if *ticks > 0 {
+                    self.ticks_left.insert(public_key.clone(), *ticks);
+                } else {
+                    let _ = self.ticks_left.remove(public_key);
+                }
+            }
+            PrewarmMutation::Tick(ticks_elapsed) => {
+                let mut ticks_left = ImHashMap::new();
+                for (public_key, ticks) in &self.ticks_left {
+                    if *ticks > *ticks_elapsed {
+                        ticks_left.insert(public_key.clone(), *ticks - *ticks_elapsed);
+                    }
+                }
+                self.ticks_left = ticks_left;
+            }
+        }
+    }
+
+    /// Is there a pre-warm request waiting for the token of this friend?
+    pub fn is_pending(&self, friend_public_key: &PublicKey) -> bool {
+        self.pending.contains_key(friend_public_key)
+    }
+
+    /// Was the token of this friend requested too recently because of a pre-warm?
+    pub fn is_limited(&self, friend_public_key: &PublicKey) -> bool {
+        self.ticks_left.contains_key(friend_public_key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::uid::UID_LEN;
+
+    #[test]
+    fn test_prewarm_basic() {
+        let mut prewarm = Prewarm::new();
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        prewarm.mutate(&PrewarmMutation::AddPending((pk_a.clone(), Uid::from(&[0; UID_LEN]))));
+        prewarm.mutate(&PrewarmMutation::AddPending((pk_a.clone(), Uid::from(&[1; UID_LEN]))));
+        prewarm.mutate(&PrewarmMutation::SetRequested((pk_a.clone(), 2)));
+        assert!(prewarm.is_pending(&pk_a));
+        assert!(!prewarm.is_pending(&pk_b));
+        assert_eq!(prewarm.pending.get(&pk_a).unwrap().len(), 2);
+        assert!(prewarm.is_limited(&pk_a));
+        assert!(!prewarm.is_limited(&pk_b));
+
+        prewarm.mutate(&PrewarmMutation::ClearPending(pk_a.clone()));
+        assert!(!prewarm.is_pending(&pk_a));
+
+        prewarm.mutate(&PrewarmMutation::Tick(1));
+        assert!(prewarm.is_limited(&pk_a));
+        prewarm.mutate(&PrewarmMutation::Tick(1));
+        assert!(!prewarm.is_limited(&pk_a));
+    }
+
+    #[test]
+    fn test_prewarm_ticks_jump() {
+        let mut prewarm = Prewarm::new();
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+
+        prewarm.mutate(&PrewarmMutation::SetRequested((pk_a.clone(), 4)));
+        prewarm.mutate(&PrewarmMutation::Tick(10_000));
+        assert!(!prewarm.is_limited(&pk_a));
+
+        // Elapsed ticks are not saved up: The limit applies again after a single request.
+        prewarm.mutate(&PrewarmMutation::SetRequested((pk_a.clone(), 4)));
+        assert!(prewarm.is_limited(&pk_a));
+        prewarm.mutate(&PrewarmMutation::Tick(3));
+        assert!(prewarm.is_limited(&pk_a));
+        prewarm.mutate(&PrewarmMutation::Tick(1));
+        assert!(!prewarm.is_limited(&pk_a));
+    }
+}
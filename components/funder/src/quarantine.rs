@@ -0,0 +1,272 @@
+use im::hashmap::HashMap as ImHashMap;
+use im::hashset::HashSet as ImHashSet;
+use im::ordmap::OrdMap as ImOrdMap;
+use im::vector::Vector as ImVec;
+
+use serde::de::DeserializeOwned;
+
+use common::canonical_serialize::CanonicalSerialize;
+use crypto::identity::PublicKey;
+use crypto::uid::Uid;
+
+use proto::app_server::messages::NamedRelayAddress;
+use proto::directory::messages::DirectoryState;
+use proto::funder::messages::{
+    DustThresholds, IncomingPayment, LabeledPayment, PairFreezeLimit, PaymentNotifier,
+    PaymentSubmission, Receipt,
+};
+
+use crate::friend::FriendState;
+use crate::invariants::check_friend_invariants;
+use crate::reliability::Reliability;
+use crate::state::FunderState;
+
+/// The reason for moving the state of a friend into quarantine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuarantineReason {
+    /// The stored state of the friend could not be deserialized.
+    DeserializeError,
+    /// The stored state of the friend violates an invariant of the funder state.
+    InvariantError,
+}
+
+/// The state of a friend that was found corrupted when the funder state was loaded.
+/// A quarantined friend has no channel activity. Payments through it fail.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuarantinedFriend {
+    pub reason: QuarantineReason,
+    /// The stored state of the friend, exactly as it was found.
+    /// Kept for manual recovery.
+    pub data: Vec<u8>,
+}
+
+/// Serialize the state of every friend separately, so that a single corrupted friend can be
+/// isolated when the funder state is loaded. See `StoredFunderState`.
+pub(crate) mod framed_friends {
+    use im::hashmap::HashMap as ImHashMap;
+
+    use serde::de::{DeserializeOwned, Error as DeError};
+    use serde::ser::Error as SerError;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crypto::identity::PublicKey;
+
+    use crate::friend::FriendState;
+
+    pub fn serialize<B, S>(
+        friends: &ImHashMap<PublicKey, FriendState<B>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        B: Clone + Serialize,
+        S: Serializer,
+    {
+        // Human readable serializations are only used for debugging. Friends are shown in full
+        // there:
+        if serializer.is_human_readable() {
+            return friends.serialize(serializer);
+        }
+
+        let mut framed_friends = Vec::new();
+        for (friend_public_key, friend) in friends {
+            let data = bincode::serialize(friend).map_err(S::Error::custom)?;
+            framed_friends.push((friend_public_key.clone(), data));
+        }
+        framed_friends.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, B, D>(
+        deserializer: D,
+    ) -> Result<ImHashMap<PublicKey, FriendState<B>>, D::Error>
+    where
+        B: Clone + DeserializeOwned,
+        D: Deserializer<'de>,
+    {
+        let framed_friends = Vec::<(PublicKey, Vec<u8>)>::deserialize(deserializer)?;
+        let mut friends = ImHashMap::new();
+        for (friend_public_key, data) in framed_friends {
+            let friend = bincode::deserialize(&data).map_err(D::Error::custom)?;
+            friends.insert(friend_public_key, friend);
+        }
+        Ok(friends)
+    }
+}
+
+/// The stored representation of a `FunderState`, where the state of every friend is kept as
+/// separate bytes. Must have the same fields (In the same order) as `FunderState`.
+#[derive(Deserialize)]
+pub struct StoredFunderState<B: Clone> {
+    local_public_key: PublicKey,
+    relays: ImVec<NamedRelayAddress<B>>,
+    friends: Vec<(PublicKey, Vec<u8>)>,
+    quarantined_friends: ImHashMap<PublicKey, QuarantinedFriend>,
+    ready_receipts: ImHashMap<Uid, Receipt>,
+    opt_payment_notifier: Option<PaymentNotifier<B>>,
+    incoming_payments: ImOrdMap<u64, IncomingPayment>,
+    next_notification_id: u64,
+    dust_thresholds: DustThresholds,
+    directory: DirectoryState<B>,
+    reliability: Reliability,
+    labeled_payments: ImVec<LabeledPayment>,
+    submissions: ImVec<PaymentSubmission>,
+    pair_freeze_limits: ImVec<PairFreezeLimit>,
+    expired_requests: ImHashSet<Uid>,
+}
+
+impl<B> StoredFunderState<B>
+where
+    B: Clone + CanonicalSerialize + DeserializeOwned,
+{
+    /// Convert into a `FunderState`. The state of every friend that can not be deserialized,
+    /// or that violates an invariant, is moved into quarantine together with its stored bytes.
+    /// The rest of the state is loaded as usual.
+    pub fn quarantine_corrupt(self) -> FunderState<B> {
+        let StoredFunderState {
+            local_public_key,
+            relays,
+            friends: framed_friends,
+            mut quarantined_friends,
+            ready_receipts,
+            opt_payment_notifier,
+            incoming_payments,
+            next_notification_id,
+            dust_thresholds,
+            directory,
+            reliability,
+            labeled_payments,
+            submissions,
+            pair_freeze_limits,
+            expired_requests,
+        } = self;
+
+        let mut friends = ImHashMap::new();
+        for (friend_public_key, data) in framed_friends {
+            let res = bincode::deserialize::<FriendState<B>>(&data)
+                .map_err(|_| QuarantineReason::DeserializeError)
+                .and_then(|friend| {
+                    check_friend_invariants(&friend, &local_public_key, &friend_public_key)
+                        .map_err(|_| QuarantineReason::InvariantError)?;
+                    Ok(friend)
+                });
+
+            match res {
+                Ok(friend) => {
+                    friends.insert(friend_public_key, friend);
+                }
+                Err(reason) => {
+                    warn!(
+                        "Moving corrupted friend {:?} into quarantine: {:?}",
+                        friend_public_key, reason
+                    );
+                    let quarantined_friend = QuarantinedFriend { reason, data };
+                    quarantined_friends.insert(friend_public_key, quarantined_friend);
+                }
+            }
+        }
+
+        FunderState {
+            local_public_key,
+            relays,
+            friends,
+            quarantined_friends,
+            ready_receipts,
+            opt_payment_notifier,
+            incoming_payments,
+            next_notification_id,
+            dust_thresholds,
+            directory,
+            reliability,
+            labeled_payments,
+            submissions,
+            pair_freeze_limits,
+            expired_requests,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    use proto::funder::messages::AddFriend;
+
+    use crate::friend::FriendMutation;
+    use crate::state::FunderMutation;
+
+    /// Replace the stored bytes of a friend inside a serialized funder state.
+    fn corrupt_friend(serialized: &mut [u8], friend: &FriendState<u32>) {
+        let data = bincode::serialize(friend).unwrap();
+        let pos = serialized
+            .windows(data.len())
+            .position(|window| window == &data[..])
+            .unwrap();
+        for byte in &mut serialized[pos..pos + data.len()] {
+            *byte = 0xff;
+        }
+    }
+
+    #[test]
+    fn test_quarantine_corrupt() {
+        let local_pk = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let pk_c = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+        let pk_d = PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]);
+
+        let mut state = FunderState::<u32>::new(local_pk.clone(), Vec::new());
+        for (friend_public_key, balance) in &[(&pk_b, 5), (&pk_c, -5), (&pk_d, 0)] {
+            state.mutate(&FunderMutation::AddFriend(AddFriend {
+                friend_public_key: (*friend_public_key).clone(),
+                relays: Vec::new(),
+                name: "friend".to_owned(),
+                balance: *balance,
+            }));
+        }
+        // pk_d violates an invariant: It believes it is a friend of someone else:
+        let mut friend_d = state.friends.get(&pk_d).unwrap().clone();
+        friend_d.local_public_key = pk_b.clone();
+        state.friends.insert(pk_d.clone(), friend_d);
+        state.mutate(&FunderMutation::FriendMutation((
+            pk_b.clone(),
+            FriendMutation::SetName("friend_b".to_owned()),
+        )));
+
+        // The invariants of friends are only checked when loading with quarantine:
+        let mut serialized = bincode::serialize(&state).unwrap();
+        let loaded_state: FunderState<u32> = bincode::deserialize(&serialized).unwrap();
+        assert_eq!(loaded_state.friends.len(), 3);
+        let stored_state: StoredFunderState<u32> = bincode::deserialize(&serialized).unwrap();
+        let loaded_state = stored_state.quarantine_corrupt();
+        assert_eq!(loaded_state.friends.len(), 2);
+        assert_eq!(loaded_state.quarantined_friends.len(), 1);
+        let quarantined_d = loaded_state.quarantined_friends.get(&pk_d).unwrap();
+        assert_eq!(quarantined_d.reason, QuarantineReason::InvariantError);
+
+        // Corrupt the stored bytes of pk_c:
+        let friend_c = state.friends.get(&pk_c).unwrap().clone();
+        let original_data = bincode::serialize(&friend_c).unwrap();
+        corrupt_friend(&mut serialized, &friend_c);
+
+        // Loading the whole state fails:
+        assert!(bincode::deserialize::<FunderState<u32>>(&serialized).is_err());
+
+        // With quarantine, only pk_c and pk_d are isolated:
+        let stored_state: StoredFunderState<u32> = bincode::deserialize(&serialized).unwrap();
+        let loaded_state = stored_state.quarantine_corrupt();
+        assert_eq!(loaded_state.friends.len(), 1);
+        assert_eq!(loaded_state.friends.get(&pk_b).unwrap().name, "friend_b");
+        let quarantined_c = loaded_state.quarantined_friends.get(&pk_c).unwrap();
+        assert_eq!(quarantined_c.reason, QuarantineReason::DeserializeError);
+        assert_eq!(quarantined_c.data, vec![0xff; original_data.len()]);
+
+        // The quarantine is kept when the state is stored again:
+        let serialized = bincode::serialize(&loaded_state).unwrap();
+        let reloaded_state: FunderState<u32> = bincode::deserialize(&serialized).unwrap();
+        assert_eq!(
+            reloaded_state.quarantined_friends,
+            loaded_state.quarantined_friends
+        );
+        assert!(reloaded_state.friends.contains_key(&pk_b));
+    }
+}
@@ -0,0 +1,219 @@
+use im::hashmap::HashMap as ImHashMap;
+
+use crypto::identity::PublicKey;
+
+use proto::consts::NEUTRAL_SUCCESS_PPM;
+use proto::report::messages::ReliabilityReport;
+
+/// Weight of a single payment outcome.
+/// Weights are kept as integers, so that they can be decayed gradually.
+pub const SAMPLE_WEIGHT: u64 = 0x400;
+
+/// Weight of the neutral score every node starts with.
+/// A few outcomes can not move the score of a node far away from neutral.
+const PRIOR_WEIGHT: u64 = 2 * SAMPLE_WEIGHT;
+
+/// A node whose outcomes have decayed below this weight is forgotten.
+const MIN_WEIGHT: u64 = SAMPLE_WEIGHT / 8;
+
+/// Decaying summary of the outcomes of payments we have sent through a remote node.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeReliability {
+    /// Weight of payments through the node that succeeded
+    pub successes: u64,
+    /// Weight of payments through the node that failed
+    pub failures: u64,
+    /// Average amount of ticks until a response arrives, for payments whose first hop is this
+    /// node.
+    pub opt_latency_ticks: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaymentOutcome {
+    /// The payment succeeded. Contains the amount of ticks it took, if the node was the first
+    /// hop of the payment.
+    Success(Option<u64>),
+    Failure,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ReliabilityMutation {
+    Record((PublicKey, PaymentOutcome)),
+    /// Halve the weight of all recorded outcomes.
+    Decay,
+}
+
+/// Empirical reliability of remote nodes, measured from the outcomes of payments we have sent.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Reliability {
+    pub nodes: ImHashMap<PublicKey, NodeReliability>,
+}
+
+impl NodeReliability {
+    fn record(&mut self, payment_outcome: &PaymentOutcome) {
+        match payment_outcome {
+            PaymentOutcome::Success(opt_latency_ticks) => {
+                self.successes = self.successes.saturating_add(SAMPLE_WEIGHT);
+                if let Some(latency_ticks) = opt_latency_ticks {
+                    // Every new measurement gets a weight of 1/4:
+                    self.opt_latency_ticks = Some(match self.opt_latency_ticks {
+                        Some(old_latency_ticks) => {
+                            (old_latency_ticks.saturating_mul(3) / 4)
+                                .saturating_add(latency_ticks / 4)
+                        }
+                        None => *latency_ticks,
+                    });
+                }
+            }
+            PaymentOutcome::Failure => {
+                self.failures = self.failures.saturating_add(SAMPLE_WEIGHT);
+            }
+        }
+    }
+
+    fn decay(&mut self) {
+        self.successes /= 2;
+        self.failures /= 2;
+    }
+
+    fn weight(&self) -> u64 {
+        self.successes.saturating_add(self.failures)
+    }
+
+    /// Estimated probability (In parts per million) that a payment through the node succeeds.
+    /// Outcomes are combined with a neutral prior, so that nodes with few recorded outcomes
+    /// stay close to neutral.
+    pub fn success_ppm(&self) -> u32 {
+        let successes = u128::from(self.successes);
+        let total = u128::from(self.weight()) + u128::from(PRIOR_WEIGHT);
+        let prior = u128::from(PRIOR_WEIGHT) * u128::from(NEUTRAL_SUCCESS_PPM);
+        // The result is never larger than max(1_000_000, NEUTRAL_SUCCESS_PPM):
+        ((successes * 1_000_000 + prior) / total) as u32
+    }
+
+    pub fn to_report(&self) -> ReliabilityReport {
+        ReliabilityReport {
+            success_ppm: self.success_ppm(),
+            opt_latency_ticks: self.opt_latency_ticks,
+            samples: self.weight() / SAMPLE_WEIGHT,
+        }
+    }
+}
+
+impl Reliability {
+    pub fn new() -> Reliability {
+        Reliability {
+            nodes: ImHashMap::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &ReliabilityMutation) {
+        match mutation {
+            ReliabilityMutation::Record((public_key, payment_outcome)) => {
+                let mut node_reliability = self.nodes.get(public_key).cloned().unwrap_or_default();
+                node_reliability.record(payment_outcome);
+                self.nodes.insert(public_key.clone(), node_reliability);
+            }
+            ReliabilityMutation::Decay => {
+                self.nodes = self
+                    .nodes
+                    .iter()
+                    .filter_map(|(public_key, node_reliability)| {
+                        let mut node_reliability = node_reliability.clone();
+                        node_reliability.decay();
+                        if node_reliability.weight() < MIN_WEIGHT {
+                            None
+                        } else {
+                            Some((public_key.clone(), node_reliability))
+                        }
+                    })
+                    .collect();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    fn record(reliability: &mut Reliability, public_key: &PublicKey, outcome: PaymentOutcome) {
+        reliability.mutate(&ReliabilityMutation::Record((public_key.clone(), outcome)));
+    }
+
+    #[test]
+    fn test_reliability_scores() {
+        let mut reliability = Reliability::new();
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        // A node with no outcomes is neutral:
+        assert_eq!(NodeReliability::default().success_ppm(), NEUTRAL_SUCCESS_PPM);
+
+        // A single failure does not dominate the score:
+        record(&mut reliability, &pk_a, PaymentOutcome::Failure);
+        let success_ppm_a = reliability.nodes.get(&pk_a).unwrap().success_ppm();
+        assert!(success_ppm_a < NEUTRAL_SUCCESS_PPM);
+        assert!(success_ppm_a > NEUTRAL_SUCCESS_PPM / 2);
+
+        // Many failures do:
+        for _ in 0..20 {
+            record(&mut reliability, &pk_a, PaymentOutcome::Failure);
+        }
+        let report_a = reliability.nodes.get(&pk_a).unwrap().to_report();
+        assert!(report_a.success_ppm < NEUTRAL_SUCCESS_PPM / 8);
+        assert_eq!(report_a.samples, 21);
+        assert_eq!(report_a.opt_latency_ticks, None);
+
+        // Half of the payments through pk_b fail:
+        for i in 0..20 {
+            let outcome = if i % 2 == 0 {
+                PaymentOutcome::Success(Some(8))
+            } else {
+                PaymentOutcome::Failure
+            };
+            record(&mut reliability, &pk_b, outcome);
+        }
+        let report_b = reliability.nodes.get(&pk_b).unwrap().to_report();
+        assert!(report_b.success_ppm > report_a.success_ppm);
+        assert!(report_b.success_ppm < NEUTRAL_SUCCESS_PPM * 3 / 5);
+        assert_eq!(report_b.opt_latency_ticks, Some(8));
+        assert_eq!(report_b.samples, 20);
+    }
+
+    #[test]
+    fn test_reliability_latency() {
+        let mut node_reliability = NodeReliability::default();
+        node_reliability.record(&PaymentOutcome::Success(Some(16)));
+        assert_eq!(node_reliability.opt_latency_ticks, Some(16));
+
+        // Success without a measurement does not change the latency:
+        node_reliability.record(&PaymentOutcome::Success(None));
+        assert_eq!(node_reliability.opt_latency_ticks, Some(16));
+
+        node_reliability.record(&PaymentOutcome::Success(Some(32)));
+        assert_eq!(node_reliability.opt_latency_ticks, Some(20));
+    }
+
+    #[test]
+    fn test_reliability_decay() {
+        let mut reliability = Reliability::new();
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+
+        for _ in 0..8 {
+            record(&mut reliability, &pk_a, PaymentOutcome::Failure);
+        }
+        let mut last_success_ppm = reliability.nodes.get(&pk_a).unwrap().success_ppm();
+
+        // The score recovers towards neutral, until the node is forgotten:
+        let mut num_decays = 0;
+        while let Some(node_reliability) = reliability.nodes.get(&pk_a) {
+            assert!(node_reliability.success_ppm() >= last_success_ppm);
+            last_success_ppm = node_reliability.success_ppm();
+            reliability.mutate(&ReliabilityMutation::Decay);
+            num_decays += 1;
+        }
+        assert_eq!(num_decays, 7);
+    }
+}
@@ -3,19 +3,31 @@ use im::hashmap::HashMap as ImHashMap;
 use common::canonical_serialize::CanonicalSerialize;
 use common::int_convert::usize_to_u64;
 
+use crypto::identity::PublicKey;
+use crypto::uid::Uid;
+
 use proto::report::messages::{
     AddFriendReport, ChannelInconsistentReport, ChannelStatusReport, DirectionReport,
-    FriendLivenessReport, FriendReport, FriendReportMutation, FriendStatusReport, FunderReport,
-    FunderReportMutation, McBalanceReport, McRequestsStatusReport, MoveTokenHashedReport,
+    FriendDeadlinesReport, FriendLivenessReport, FriendReport, FriendReportMutation,
+    FriendStatusReport, FunderReport, FunderReportMutation, McBalanceReport,
+    McRequestsStatusReport, MoveTokenHashedReport, PendingPaymentReport, PendingPaymentStageReport,
     RequestsStatusReport, ResetTermsReport, SentLocalRelaysReport, TcReport,
+    VerificationStatusReport,
 };
 
+use crate::credit_calc::CreditCalculator;
 use crate::types::MoveTokenHashed;
 
+use crate::deadlines::ReportedDeadlinesMutation;
 use crate::ephemeral::{Ephemeral, EphemeralMutation};
-use crate::friend::{ChannelStatus, FriendMutation, FriendState, SentLocalRelays};
+use crate::friend::{
+    ChannelInconsistent, ChannelStatus, FriendMutation, FriendState, SentLocalRelays,
+};
+use crate::goodbye::GoodbyeMutation;
 use crate::liveness::LivenessMutation;
 use crate::mutual_credit::types::{McBalance, McRequestsStatus};
+use crate::payment_timing::{PaymentTimingMutation, PaymentTimings};
+use crate::reliability::ReliabilityMutation;
 use crate::state::{FunderMutation, FunderState};
 use crate::token_channel::{TcDirection, TcMutation, TokenChannel};
 
@@ -77,11 +89,15 @@ where
     fn from(token_channel: &TokenChannel<B>) -> TcReport {
         let direction = match token_channel.get_direction() {
             TcDirection::Incoming(_) => DirectionReport::Incoming,
-            TcDirection::Outgoing(_) => DirectionReport::Outgoing,
+            TcDirection::Outgoing(tc_outgoing) => DirectionReport::Outgoing {
+                token_wanted: tc_outgoing.token_wanted,
+            },
         };
         let mutual_credit_state = token_channel.get_mutual_credit().state();
         TcReport {
             direction,
+            move_token_counter: token_channel.get_move_token_counter(),
+            inconsistency_counter: token_channel.get_inconsistency_counter(),
             balance: McBalanceReport::from(&mutual_credit_state.balance),
             requests_status: McRequestsStatusReport::from(&mutual_credit_state.requests_status),
             num_local_pending_requests: usize_to_u64(
@@ -102,6 +118,22 @@ where
     }
 }
 
+impl From<&ChannelInconsistent> for ChannelInconsistentReport {
+    fn from(channel_inconsistent: &ChannelInconsistent) -> ChannelInconsistentReport {
+        let opt_remote_reset_terms = channel_inconsistent
+            .opt_remote_reset_terms
+            .clone()
+            .map(|remote_reset_terms| ResetTermsReport {
+                reset_token: remote_reset_terms.reset_token.clone(),
+                balance_for_reset: remote_reset_terms.balance_for_reset,
+            });
+        ChannelInconsistentReport {
+            local_reset_terms_balance: channel_inconsistent.local_reset_terms.balance_for_reset,
+            opt_remote_reset_terms,
+        }
+    }
+}
+
 impl<B> From<&ChannelStatus<B>> for ChannelStatusReport
 where
     B: Clone + CanonicalSerialize,
@@ -109,20 +141,16 @@ where
     fn from(channel_status: &ChannelStatus<B>) -> ChannelStatusReport {
         match channel_status {
             ChannelStatus::Inconsistent(channel_inconsistent) => {
-                let opt_remote_reset_terms = channel_inconsistent
-                    .opt_remote_reset_terms
-                    .clone()
-                    .map(|remote_reset_terms| ResetTermsReport {
-                        reset_token: remote_reset_terms.reset_token.clone(),
-                        balance_for_reset: remote_reset_terms.balance_for_reset,
-                    });
-                let channel_inconsistent_report = ChannelInconsistentReport {
-                    local_reset_terms_balance: channel_inconsistent
-                        .local_reset_terms
-                        .balance_for_reset,
-                    opt_remote_reset_terms,
-                };
-                ChannelStatusReport::Inconsistent(channel_inconsistent_report)
+                ChannelStatusReport::Inconsistent(ChannelInconsistentReport::from(
+                    channel_inconsistent,
+                ))
+            }
+            // The channel is reported as inconsistent until the remote side acknowledges our
+            // reset move token:
+            ChannelStatus::PendingReset(channel_pending_reset) => {
+                ChannelStatusReport::Inconsistent(ChannelInconsistentReport::from(
+                    &channel_pending_reset.to_inconsistent(),
+                ))
             }
             ChannelStatus::Consistent(token_channel) => {
                 ChannelStatusReport::Consistent(TcReport::from(token_channel))
@@ -131,9 +159,106 @@ where
     }
 }
 
+/// Amount of credits frozen against the first hop of a route, when sending a request.
+fn first_hop_frozen_credits(route_len: usize, dest_payment: u128) -> u128 {
+    CreditCalculator::new(route_len, dest_payment)
+        .ok()
+        .and_then(|credit_calc| credit_calc.credits_to_freeze(1))
+        .unwrap_or(0)
+}
+
+/// Create a report of all the payments originated locally through this friend,
+/// that were not yet completed.
+/// The ticks in which a payment was queued and signed are taken from `payment_timings`.
+fn create_pending_payments_report<B>(
+    friend_state: &FriendState<B>,
+    payment_timings: &PaymentTimings,
+) -> Vec<PendingPaymentReport>
+where
+    B: Clone + CanonicalSerialize,
+{
+    // Requests queued by the user, waiting to be sent:
+    let mut pending_payments = friend_state
+        .pending_user_requests
+        .iter()
+        .map(|request_send_funds| PendingPaymentReport {
+            request_id: request_send_funds.request_id.clone(),
+            route: request_send_funds.route.clone(),
+            dest_payment: request_send_funds.dest_payment,
+            frozen_credits: first_hop_frozen_credits(
+                request_send_funds.route.len(),
+                request_send_funds.dest_payment,
+            ),
+            stage: PendingPaymentStageReport::Queued,
+            opt_queued_tick: payment_timings.opt_start_ticks(&request_send_funds.request_id),
+            opt_signed_tick: None,
+        })
+        .collect::<Vec<_>>();
+
+    // Requests that were already sent inside a MoveToken.
+    // We only include requests that were originated by us (And not requests we forward):
+    if let ChannelStatus::Consistent(token_channel) = &friend_state.channel_status {
+        let mut sent_payments = token_channel
+            .get_mutual_credit()
+            .state()
+            .pending_requests
+            .pending_local_requests
+            .values()
+            .filter(|pending_request| {
+                pending_request.route.index_to_pk(0) == Some(&friend_state.local_public_key)
+            })
+            .map(|pending_request| PendingPaymentReport {
+                request_id: pending_request.request_id.clone(),
+                route: pending_request.route.clone(),
+                dest_payment: pending_request.dest_payment,
+                frozen_credits: first_hop_frozen_credits(
+                    pending_request.route.len(),
+                    pending_request.dest_payment,
+                ),
+                stage: PendingPaymentStageReport::Sent,
+                opt_queued_tick: payment_timings.opt_start_ticks(&pending_request.request_id),
+                opt_signed_tick: payment_timings.opt_sent_ticks(&pending_request.request_id),
+            })
+            .collect::<Vec<_>>();
+        // Make the order deterministic:
+        sent_payments.sort_by(|a, b| a.request_id.cmp(&b.request_id));
+        pending_payments.extend(sent_payments);
+    }
+
+    pending_payments
+}
+
+/// Find the friend through which a payment we have originated is still pending.
+fn find_pending_payment_friend<'a, B>(
+    funder_state: &'a FunderState<B>,
+    request_id: &Uid,
+) -> Option<(&'a PublicKey, &'a FriendState<B>)>
+where
+    B: Clone,
+{
+    funder_state.friends.iter().find(|(_, friend)| {
+        let is_queued = friend
+            .pending_user_requests
+            .iter()
+            .any(|request_send_funds| &request_send_funds.request_id == request_id);
+        let is_sent = match &friend.channel_status {
+            ChannelStatus::Consistent(token_channel) => token_channel
+                .get_mutual_credit()
+                .state()
+                .pending_requests
+                .pending_local_requests
+                .contains_key(request_id),
+            ChannelStatus::Inconsistent(_) | ChannelStatus::PendingReset(_) => false,
+        };
+        is_queued || is_sent
+    })
+}
+
 fn create_friend_report<B>(
     friend_state: &FriendState<B>,
     friend_liveness: &FriendLivenessReport,
+    friend_deadlines: FriendDeadlinesReport,
+    payment_timings: &PaymentTimings,
 ) -> FriendReport<B>
 where
     B: Clone + CanonicalSerialize,
@@ -158,6 +283,26 @@ where
         num_pending_responses: usize_to_u64(friend_state.pending_responses.len()).unwrap(),
         status: FriendStatusReport::from(&friend_state.status),
         num_pending_user_requests: usize_to_u64(friend_state.pending_user_requests.len()).unwrap(),
+        pending_payments: create_pending_payments_report(friend_state, payment_timings),
+        opt_protocol_violation: friend_state.opt_protocol_violation.clone(),
+        verification_status: VerificationStatusReport::from(&friend_state.verification_status),
+        deadlines: friend_deadlines,
+        index_private: friend_state.index_private,
+    }
+}
+
+/// A friend that is not online is reported as intentionally offline if it told us it was going
+/// offline on purpose.
+fn create_friend_liveness_report(
+    ephemeral: &Ephemeral,
+    friend_public_key: &PublicKey,
+) -> FriendLivenessReport {
+    if ephemeral.liveness.is_online(friend_public_key) {
+        FriendLivenessReport::Online
+    } else if let Some(goodbye) = ephemeral.goodbyes.get(friend_public_key) {
+        FriendLivenessReport::IntentionallyOffline(goodbye.clone())
+    } else {
+        FriendLivenessReport::Offline
     }
 }
 
@@ -167,20 +312,33 @@ where
 {
     let mut friends = ImHashMap::new();
     for (friend_public_key, friend_state) in &funder_state.friends {
-        let friend_liveness = if ephemeral.liveness.is_online(friend_public_key) {
-            FriendLivenessReport::Online
-        } else {
-            FriendLivenessReport::Offline
-        };
-        let friend_report = create_friend_report(&friend_state, &friend_liveness);
+        let friend_liveness = create_friend_liveness_report(ephemeral, friend_public_key);
+        let friend_deadlines = ephemeral.reported_deadlines.get(friend_public_key);
+        let friend_report = create_friend_report(
+            &friend_state,
+            &friend_liveness,
+            friend_deadlines,
+            &ephemeral.payment_timings,
+        );
         friends.insert(friend_public_key.clone(), friend_report);
     }
 
+    let mut reliability = ImHashMap::new();
+    for (public_key, node_reliability) in &funder_state.reliability.nodes {
+        reliability.insert(public_key.clone(), node_reliability.to_report());
+    }
+
     FunderReport {
         local_public_key: funder_state.local_public_key.clone(),
         relays: funder_state.relays.clone(),
         friends,
         num_ready_receipts: usize_to_u64(funder_state.ready_receipts.len()).unwrap(),
+        quarantined_friends: funder_state.quarantined_friends.keys().cloned().collect(),
+        dust_thresholds: funder_state.dust_thresholds.clone(),
+        directory: funder_state.directory.clone(),
+        reliability,
+        read_only: ephemeral.read_only,
+        labeled_payments: funder_state.labeled_payments.clone(),
     }
 }
 
@@ -191,18 +349,22 @@ where
     create_report(funder_state, &Ephemeral::new())
 }
 
+/// `payment_timings` are the payment timings after all the mutations were applied.
 pub fn friend_mutation_to_report_mutations<B>(
     friend_mutation: &FriendMutation<B>,
     friend: &FriendState<B>,
+    payment_timings: &PaymentTimings,
 ) -> Vec<FriendReportMutation<B>>
 where
     B: Clone + CanonicalSerialize,
 {
     let mut friend_after = friend.clone();
     friend_after.mutate(friend_mutation);
-    match friend_mutation {
+    let mut report_mutations = match friend_mutation {
         FriendMutation::TcMutation(tc_mutation) => match tc_mutation {
-            TcMutation::McMutation(_) | TcMutation::SetDirection(_) => {
+            TcMutation::McMutation(_)
+            | TcMutation::SetDirection(_)
+            | TcMutation::SetTokenWanted(_) => {
                 let channel_status_report = ChannelStatusReport::from(&friend_after.channel_status);
                 let set_channel_status =
                     FriendReportMutation::SetChannelStatus(channel_status_report);
@@ -253,7 +415,8 @@ where
                 usize_to_u64(friend_after.pending_user_requests.len()).unwrap(),
             )]
         }
-        FriendMutation::PopFrontPendingUserRequest => {
+        FriendMutation::PopFrontPendingUserRequest
+        | FriendMutation::RemovePendingUserRequest(_) => {
             vec![FriendReportMutation::SetNumPendingUserRequests(
                 usize_to_u64(friend_after.pending_user_requests.len()).unwrap(),
             )]
@@ -264,13 +427,39 @@ where
         FriendMutation::SetRemoteRelays(remote_relays) => {
             vec![FriendReportMutation::SetRemoteRelays(remote_relays.clone())]
         }
+        // Pending relays are only reported once they are applied:
+        FriendMutation::SetPendingRemoteRelays(_) => Vec::new(),
         FriendMutation::SetName(name) => vec![FriendReportMutation::SetName(name.clone())],
+        FriendMutation::SetResponseDeadline(_) => Vec::new(),
+        // The countdown of an expiry is not reported, only the expiry itself:
+        FriendMutation::SetRemoteMaxDebtExpiry(_) => Vec::new(),
+        FriendMutation::ExpireRemoteMaxDebt(wanted_remote_max_debt) => {
+            vec![FriendReportMutation::RemoteMaxDebtExpired(
+                *wanted_remote_max_debt,
+            )]
+        }
+        FriendMutation::SetProtocolViolation(protocol_violation_report) => {
+            vec![FriendReportMutation::SetOptProtocolViolation(Some(
+                protocol_violation_report.clone(),
+            ))]
+        }
+        FriendMutation::SetVerificationPhrase(_)
+        | FriendMutation::SetRemoteVerificationProof(_) => {
+            vec![FriendReportMutation::SetVerificationStatus(
+                VerificationStatusReport::from(&friend_after.verification_status),
+            )]
+        }
+        FriendMutation::SetIndexPrivate(index_private) => {
+            vec![FriendReportMutation::SetIndexPrivate(*index_private)]
+        }
         FriendMutation::SetSentLocalRelays(sent_local_relays) => {
             vec![FriendReportMutation::SetSentLocalRelays(
                 sent_local_relays.into(),
             )]
         }
-        FriendMutation::SetInconsistent(_) | FriendMutation::SetConsistent(_) => {
+        FriendMutation::SetInconsistent(_)
+        | FriendMutation::SetPendingReset(_)
+        | FriendMutation::SetConsistent(_) => {
             let channel_status_report = ChannelStatusReport::from(&friend_after.channel_status);
             let set_channel_status = FriendReportMutation::SetChannelStatus(channel_status_report);
             let opt_move_token_hashed_report = friend_after
@@ -281,7 +470,17 @@ where
                 FriendReportMutation::SetOptLastIncomingMoveToken(opt_move_token_hashed_report);
             vec![set_channel_status, set_last_incoming_move_token]
         }
+    };
+
+    // Pending payments may change due to many different mutations
+    // (User requests, token channel mutations, channel resets):
+    let pending_payments_after = create_pending_payments_report(&friend_after, payment_timings);
+    if create_pending_payments_report(friend, payment_timings) != pending_payments_after {
+        report_mutations.push(FriendReportMutation::SetPendingPayments(
+            pending_payments_after,
+        ));
     }
+    report_mutations
 }
 
 /// Convert a FunderMutation to FunderReportMutation
@@ -291,9 +490,12 @@ where
 ///
 /// In the future if we simplify Funder's mutations, we might be able discard the `funder_state`
 /// argument here.
+///
+/// `payment_timings` are the payment timings after all the mutations were applied.
 pub fn funder_mutation_to_report_mutations<B>(
     funder_mutation: &FunderMutation<B>,
     funder_state: &FunderState<B>,
+    payment_timings: &PaymentTimings,
 ) -> Vec<FunderReportMutation<B>>
 where
     B: Clone + CanonicalSerialize,
@@ -303,7 +505,7 @@ where
     match funder_mutation {
         FunderMutation::FriendMutation((public_key, friend_mutation)) => {
             let friend = funder_state.friends.get(public_key).unwrap();
-            friend_mutation_to_report_mutations(&friend_mutation, &friend)
+            friend_mutation_to_report_mutations(&friend_mutation, &friend, payment_timings)
                 .into_iter()
                 .map(|friend_report_mutation| {
                     FunderReportMutation::FriendReportMutation((
@@ -360,15 +562,70 @@ where
                 Vec::new()
             }
         }
+        FunderMutation::SetDustThresholds(dust_thresholds) => {
+            vec![FunderReportMutation::SetDustThresholds(
+                dust_thresholds.clone(),
+            )]
+        }
+        FunderMutation::SetDirectory(directory) => {
+            vec![FunderReportMutation::SetDirectory(directory.clone())]
+        }
+        FunderMutation::ReliabilityMutation(reliability_mutation) => {
+            let nodes_after = &funder_state_after.reliability.nodes;
+            match reliability_mutation {
+                ReliabilityMutation::Record((public_key, _)) => {
+                    let node_reliability = nodes_after.get(public_key).unwrap();
+                    vec![FunderReportMutation::SetReliability((
+                        public_key.clone(),
+                        node_reliability.to_report(),
+                    ))]
+                }
+                // Nodes whose outcomes have decayed away are removed:
+                ReliabilityMutation::Decay => funder_state
+                    .reliability
+                    .nodes
+                    .keys()
+                    .map(|public_key| match nodes_after.get(public_key) {
+                        Some(node_reliability) => FunderReportMutation::SetReliability((
+                            public_key.clone(),
+                            node_reliability.to_report(),
+                        )),
+                        None => FunderReportMutation::RemoveReliability(public_key.clone()),
+                    })
+                    .collect(),
+            }
+        }
+        FunderMutation::AddLabeledPayment(labeled_payment) => {
+            vec![FunderReportMutation::AddLabeledPayment(
+                labeled_payment.clone(),
+            )]
+        }
+        FunderMutation::SetLabeledPaymentResult(request_result) => {
+            vec![FunderReportMutation::SetLabeledPaymentResult(
+                request_result.clone(),
+            )]
+        }
+        FunderMutation::SetPaymentNotifier(_)
+        | FunderMutation::AddIncomingPayment(_)
+        | FunderMutation::RemoveIncomingPayment(_)
+        | FunderMutation::AddPaymentSubmission(_)
+        | FunderMutation::SetPaymentSubmissionResult(_)
+        | FunderMutation::RemovePaymentSubmission(_)
+        | FunderMutation::SetPairFreezeLimit(_)
+        | FunderMutation::AddExpiredRequest(_)
+        | FunderMutation::RemoveExpiredRequest(_) => Vec::new(),
     }
 }
 
+/// Convert an ephemeral mutation to report mutations.
+/// `ephemeral` is the ephemeral state after all the mutations were applied.
 pub fn ephemeral_mutation_to_report_mutations<B>(
     ephemeral_mutation: &EphemeralMutation,
     funder_state: &FunderState<B>,
+    ephemeral: &Ephemeral,
 ) -> Vec<FunderReportMutation<B>>
 where
-    B: Clone,
+    B: Clone + CanonicalSerialize,
 {
     match ephemeral_mutation {
         EphemeralMutation::LivenessMutation(liveness_mutation) => match liveness_mutation {
@@ -400,13 +657,84 @@ where
                     // We ignore the liveness mutation if friend does not exist.
                     return Vec::new();
                 }
-                let friend_report_mutation =
-                    FriendReportMutation::SetLiveness(FriendLivenessReport::Offline);
+                let friend_liveness = create_friend_liveness_report(ephemeral, public_key);
+                let friend_report_mutation = FriendReportMutation::SetLiveness(friend_liveness);
+                vec![FunderReportMutation::FriendReportMutation((
+                    public_key.clone(),
+                    friend_report_mutation,
+                ))]
+            }
+        },
+        EphemeralMutation::GoodbyeMutation(goodbye_mutation) => match goodbye_mutation {
+            GoodbyeMutation::Received((public_key, _)) | GoodbyeMutation::Forget(public_key) => {
+                // The liveness of an online friend is not affected by goodbyes:
+                if !funder_state.friends.contains_key(public_key)
+                    || ephemeral.liveness.is_online(public_key)
+                {
+                    return Vec::new();
+                }
+                let friend_liveness = create_friend_liveness_report(ephemeral, public_key);
+                let friend_report_mutation = FriendReportMutation::SetLiveness(friend_liveness);
                 vec![FunderReportMutation::FriendReportMutation((
                     public_key.clone(),
                     friend_report_mutation,
                 ))]
             }
+            GoodbyeMutation::SetTicksLeft(_) | GoodbyeMutation::Resume(_) => Vec::new(),
         },
+        // Damping of relays changes is not reported. The applied change of relays is reported
+        // through the funder state mutations.
+        EphemeralMutation::RelaysDampingMutation(_) => Vec::new(),
+        EphemeralMutation::AdaptiveBatchMutation(_) => Vec::new(),
+        EphemeralMutation::PrewarmMutation(_) => Vec::new(),
+        EphemeralMutation::CompletedMutation(_) => Vec::new(),
+        EphemeralMutation::ResponseDeadlineMutation(_) => Vec::new(),
+        EphemeralMutation::PaymentTimingMutation(payment_timing_mutation) => {
+            match payment_timing_mutation {
+                // The measurement points of a pending payment were evicted. See
+                // `mutate_payment_timings()`:
+                PaymentTimingMutation::Forget(request_id) => {
+                    let (public_key, friend) =
+                        match find_pending_payment_friend(funder_state, request_id) {
+                            Some(public_key_friend) => public_key_friend,
+                            None => return Vec::new(),
+                        };
+                    let pending_payments =
+                        create_pending_payments_report(friend, &ephemeral.payment_timings);
+                    let friend_report_mutation =
+                        FriendReportMutation::SetPendingPayments(pending_payments);
+                    vec![FunderReportMutation::FriendReportMutation((
+                        public_key.clone(),
+                        friend_report_mutation,
+                    ))]
+                }
+                // Other changes of the payment timings come together with a change of the
+                // pending payments, and are reported through the funder state mutations:
+                PaymentTimingMutation::Tick(_)
+                | PaymentTimingMutation::Start(_)
+                | PaymentTimingMutation::Sent(_)
+                | PaymentTimingMutation::Complete(_) => Vec::new(),
+            }
+        }
+        EphemeralMutation::ReportedDeadlinesMutation(reported_deadlines_mutation) => {
+            match reported_deadlines_mutation {
+                ReportedDeadlinesMutation::Set((public_key, friend_deadlines)) => {
+                    if !funder_state.friends.contains_key(public_key) {
+                        return Vec::new();
+                    }
+                    let friend_report_mutation =
+                        FriendReportMutation::SetDeadlines(friend_deadlines.clone());
+                    vec![FunderReportMutation::FriendReportMutation((
+                        public_key.clone(),
+                        friend_report_mutation,
+                    ))]
+                }
+                // The friend was removed from the report together with its deadlines:
+                ReportedDeadlinesMutation::Forget(_) => Vec::new(),
+            }
+        }
+        EphemeralMutation::SetReadOnly(read_only) => {
+            vec![FunderReportMutation::SetReadOnly(*read_only)]
+        }
     }
 }
@@ -0,0 +1,69 @@
+use crypto::uid::Uid;
+use im::hashmap::HashMap as ImHashMap;
+
+/// Keeps track of requests we have forwarded to friends with a response deadline.
+/// When a deadline passes, we fail the request towards its origin, without waiting for the
+/// friend to answer. The expired requests are kept in `FunderState::expired_requests`.
+///
+/// The amount of ticks left is not persisted: After a restart, the deadlines of pending
+/// forwarded requests start counting again from the full deadline of the friend.
+#[derive(Clone, Default)]
+pub struct ResponseDeadlines {
+    /// Amount of ticks left until the deadline of a forwarded request passes.
+    pub ticks_left: ImHashMap<Uid, u64>,
+}
+
+#[derive(Debug)]
+pub enum ResponseDeadlineMutation {
+    /// Set the amount of ticks left until the deadline of a forwarded request passes.
+    Track((Uid, u64)),
+    /// Stop tracking the deadline of a forwarded request.
+    /// Either the deadline has passed, or the request is no longer pending.
+    Forget(Uid),
+}
+
+impl ResponseDeadlines {
+    pub fn new() -> ResponseDeadlines {
+        ResponseDeadlines {
+            ticks_left: ImHashMap::new(),
+        }
+    }
+
+    pub fn mutate(&mut self, mutation: &ResponseDeadlineMutation) {
+        match mutation {
+            ResponseDeadlineMutation::Track((request_id, ticks)) => {
+                self.ticks_left.insert(*request_id, *ticks);
+            }
+            ResponseDeadlineMutation::Forget(request_id) => {
+                let _ = self.ticks_left.remove(request_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::uid::UID_LEN;
+
+    #[test]
+    fn test_response_deadlines_basic() {
+        let mut response_deadlines = ResponseDeadlines::new();
+        let request_id_a = Uid::from(&[0xaa; UID_LEN]);
+        let request_id_b = Uid::from(&[0xbb; UID_LEN]);
+
+        response_deadlines.mutate(&ResponseDeadlineMutation::Track((request_id_a, 2)));
+        response_deadlines.mutate(&ResponseDeadlineMutation::Track((request_id_b, 3)));
+        assert_eq!(response_deadlines.ticks_left.get(&request_id_a), Some(&2));
+
+        response_deadlines.mutate(&ResponseDeadlineMutation::Track((request_id_a, 1)));
+        assert_eq!(response_deadlines.ticks_left.get(&request_id_a), Some(&1));
+
+        response_deadlines.mutate(&ResponseDeadlineMutation::Forget(request_id_a));
+        assert!(!response_deadlines.ticks_left.contains_key(&request_id_a));
+        assert_eq!(response_deadlines.ticks_left.get(&request_id_b), Some(&3));
+
+        response_deadlines.mutate(&ResponseDeadlineMutation::Forget(request_id_b));
+        assert!(response_deadlines.ticks_left.is_empty());
+    }
+}
@@ -1,4 +1,6 @@
 use im::hashmap::HashMap as ImHashMap;
+use im::hashset::HashSet as ImHashSet;
+use im::ordmap::OrdMap as ImOrdMap;
 use im::vector::Vector as ImVec;
 
 use common::canonical_serialize::CanonicalSerialize;
@@ -6,18 +8,60 @@ use crypto::identity::PublicKey;
 use crypto::uid::Uid;
 
 use proto::app_server::messages::NamedRelayAddress;
-use proto::funder::messages::{AddFriend, Receipt};
+use proto::consts::{MAX_INCOMING_PAYMENTS, MAX_PAYMENT_SUBMISSIONS};
+use proto::directory::messages::DirectoryState;
+use proto::funder::messages::{
+    add_labeled_payment, set_labeled_payment_result, AddFriend, DustThresholds, IncomingPayment,
+    LabeledPayment, PairFreezeLimit, PaymentNotifier, PaymentSubmission, Receipt,
+    ResponseSendFundsResult, SetPairFreezeLimit,
+};
 
 use crate::friend::{FriendMutation, FriendState};
+use crate::handler::set_pair_freeze_limit;
+use crate::quarantine::{framed_friends, QuarantinedFriend};
+use crate::reliability::{Reliability, ReliabilityMutation};
 
+/// Note: When adding or reordering fields, `StoredFunderState` must be updated as well.
 #[derive(Clone, Serialize, Deserialize, Debug)]
+#[serde(bound(deserialize = "B: Clone + serde::de::DeserializeOwned"))]
 pub struct FunderState<B: Clone> {
     pub local_public_key: PublicKey,
     /// Address of relay we are going to connect to.
     /// None means that no address was configured.
     pub relays: ImVec<NamedRelayAddress<B>>,
+    #[serde(with = "framed_friends")]
     pub friends: ImHashMap<PublicKey, FriendState<B>>,
+    /// Friends whose stored state was found corrupted at load time.
+    /// Quarantined friends have no channel activity, and can not be added again.
+    pub quarantined_friends: ImHashMap<PublicKey, QuarantinedFriend>,
     pub ready_receipts: ImHashMap<Uid, Receipt>,
+    /// Consumer of notifications about incoming payments.
+    /// None means that no payment notifier was configured.
+    pub opt_payment_notifier: Option<PaymentNotifier<B>>,
+    /// Notifications about incoming payments that were not yet acknowledged by the consumer.
+    /// Holds at most `MAX_INCOMING_PAYMENTS` notifications. The oldest are dropped first.
+    pub incoming_payments: ImOrdMap<u64, IncomingPayment>,
+    /// Identifier for the next incoming payment notification
+    pub next_notification_id: u64,
+    /// Minimum payments we are willing to send, forward or receive.
+    pub dust_thresholds: DustThresholds,
+    /// Directory of relays and index servers we are subscribed to.
+    pub directory: DirectoryState<B>,
+    /// Reliability of remote nodes we have sent payments through.
+    pub reliability: Reliability,
+    /// Payments we have sent with a label, oldest first.
+    /// Holds at most `MAX_LABELED_PAYMENTS` payments. The oldest are dropped first.
+    pub labeled_payments: ImVec<LabeledPayment>,
+    /// Payments submitted by apps whose outcome was not yet acknowledged, oldest first.
+    /// Holds at most `MAX_PAYMENT_SUBMISSIONS` submissions. The oldest are dropped first.
+    pub submissions: ImVec<PaymentSubmission>,
+    /// Limits on the credits we freeze for requests forwarded between pairs of friends.
+    /// At most one rule is kept for every pair.
+    pub pair_freeze_limits: ImVec<PairFreezeLimit>,
+    /// Requests we have forwarded whose response deadline has passed. The origin of those
+    /// requests was already sent a failure, so a late response or failure must not be forwarded.
+    /// Kept until the request is no longer pending inside the token channel.
+    pub expired_requests: ImHashSet<Uid>,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -30,6 +74,20 @@ pub enum FunderMutation<B: Clone> {
     RemoveFriend(PublicKey),
     AddReceipt((Uid, Receipt)), //(request_id, receipt)
     RemoveReceipt(Uid),
+    SetPaymentNotifier(Option<PaymentNotifier<B>>),
+    AddIncomingPayment((Receipt, u32)), // (receipt, route_len)
+    RemoveIncomingPayment(u64), // notification_id
+    SetDustThresholds(DustThresholds),
+    SetDirectory(DirectoryState<B>),
+    ReliabilityMutation(ReliabilityMutation),
+    AddLabeledPayment(LabeledPayment),
+    SetLabeledPaymentResult((Uid, ResponseSendFundsResult)), // (request_id, result)
+    AddPaymentSubmission(PaymentSubmission),
+    SetPaymentSubmissionResult((Uid, ResponseSendFundsResult)), // (request_id, result)
+    RemovePaymentSubmission(Uid),
+    SetPairFreezeLimit(SetPairFreezeLimit),
+    AddExpiredRequest(Uid),
+    RemoveExpiredRequest(Uid),
 }
 
 impl<B> FunderState<B>
@@ -44,7 +102,18 @@ where
             local_public_key,
             relays,
             friends: ImHashMap::new(),
+            quarantined_friends: ImHashMap::new(),
             ready_receipts: ImHashMap::new(),
+            opt_payment_notifier: None,
+            incoming_payments: ImOrdMap::new(),
+            next_notification_id: 0,
+            dust_thresholds: DustThresholds::default(),
+            directory: DirectoryState::default(),
+            reliability: Reliability::new(),
+            labeled_payments: ImVec::new(),
+            submissions: ImVec::new(),
+            pair_freeze_limits: ImVec::new(),
+            expired_requests: ImHashSet::new(),
         }
     }
     // TODO: Add code for initialization from database?
@@ -94,6 +163,171 @@ where
             FunderMutation::RemoveReceipt(uid) => {
                 let _ = self.ready_receipts.remove(uid);
             }
+            FunderMutation::SetPaymentNotifier(opt_payment_notifier) => {
+                self.opt_payment_notifier = opt_payment_notifier.clone();
+            }
+            FunderMutation::SetDustThresholds(dust_thresholds) => {
+                self.dust_thresholds = dust_thresholds.clone();
+            }
+            FunderMutation::SetDirectory(directory) => {
+                self.directory = directory.clone();
+            }
+            FunderMutation::ReliabilityMutation(reliability_mutation) => {
+                self.reliability.mutate(reliability_mutation);
+            }
+            FunderMutation::AddIncomingPayment((receipt, route_len)) => {
+                // The outbox is bounded. Drop the oldest notification to make room:
+                if self.incoming_payments.len() >= MAX_INCOMING_PAYMENTS {
+                    if let Some(oldest_id) = self.incoming_payments.keys().next().cloned() {
+                        let _ = self.incoming_payments.remove(&oldest_id);
+                    }
+                }
+                let incoming_payment = IncomingPayment {
+                    notification_id: self.next_notification_id,
+                    route_len: *route_len,
+                    receipt: receipt.clone(),
+                };
+                self.incoming_payments
+                    .insert(self.next_notification_id, incoming_payment);
+                self.next_notification_id += 1;
+            }
+            FunderMutation::RemoveIncomingPayment(notification_id) => {
+                let _ = self.incoming_payments.remove(notification_id);
+            }
+            FunderMutation::AddLabeledPayment(labeled_payment) => {
+                add_labeled_payment(&mut self.labeled_payments, labeled_payment);
+            }
+            FunderMutation::SetLabeledPaymentResult((request_id, result)) => {
+                set_labeled_payment_result(&mut self.labeled_payments, request_id, result);
+            }
+            FunderMutation::AddPaymentSubmission(submission) => {
+                // A submission that is already remembered is not added again:
+                if !self
+                    .submissions
+                    .iter()
+                    .any(|cur| cur.request_id == submission.request_id)
+                {
+                    // Drop the oldest submission to make room:
+                    if self.submissions.len() >= MAX_PAYMENT_SUBMISSIONS {
+                        let _ = self.submissions.pop_front();
+                    }
+                    self.submissions.push_back(submission.clone());
+                }
+            }
+            FunderMutation::SetPaymentSubmissionResult((request_id, result)) => {
+                // The first result of a submission is final:
+                for submission in self.submissions.iter_mut() {
+                    if &submission.request_id == request_id && submission.opt_result.is_none() {
+                        submission.opt_result = Some(result.clone());
+                    }
+                }
+            }
+            FunderMutation::RemovePaymentSubmission(request_id) => {
+                self.submissions
+                    .retain(|submission| &submission.request_id != request_id);
+            }
+            FunderMutation::SetPairFreezeLimit(set_limit) => {
+                set_pair_freeze_limit(&mut self.pair_freeze_limits, set_limit);
+            }
+            FunderMutation::AddExpiredRequest(request_id) => {
+                self.expired_requests.insert(*request_id);
+            }
+            FunderMutation::RemoveExpiredRequest(request_id) => {
+                let _ = self.expired_requests.remove(request_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::hash::{HashResult, HASH_RESULT_LEN};
+    use crypto::identity::{Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+    use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+    use crypto::uid::UID_LEN;
+    use proto::funder::messages::{FailureReason, FriendsRoute};
+
+    #[test]
+    fn test_incoming_payments_bounded() {
+        let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(local_public_key, Vec::new());
+
+        let num_payments = MAX_INCOMING_PAYMENTS + 3;
+        for i in 0..num_payments {
+            let receipt = Receipt {
+                response_hash: HashResult::from(&[0x11; HASH_RESULT_LEN]),
+                invoice_id: InvoiceId::from(&[0x22; INVOICE_ID_LEN]),
+                dest_payment: i as u128,
+                signature: Signature::from(&[0x33; SIGNATURE_LEN]),
+            };
+            state.mutate(&FunderMutation::AddIncomingPayment((receipt, 2)));
+        }
+
+        // Only the newest notifications are kept:
+        assert_eq!(state.incoming_payments.len(), MAX_INCOMING_PAYMENTS);
+        assert_eq!(state.next_notification_id, num_payments as u64);
+        let oldest_id = *state.incoming_payments.keys().next().unwrap();
+        assert_eq!(oldest_id, 3);
+        let incoming_payment = state.incoming_payments.get(&oldest_id).unwrap();
+        assert_eq!(incoming_payment.receipt.dest_payment, 3);
+        assert_eq!(incoming_payment.route_len, 2);
+    }
+
+    fn dummy_submission(i: usize) -> PaymentSubmission {
+        let mut request_id = [0u8; UID_LEN];
+        request_id[0] = (i % 0x100) as u8;
+        request_id[1] = (i / 0x100) as u8;
+        PaymentSubmission {
+            request_id: Uid::from(&request_id),
+            app_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            route: FriendsRoute {
+                public_keys: vec![PublicKey::from(&[0xaa; PUBLIC_KEY_LEN])],
+            },
+            invoice_id: InvoiceId::from(&[0x22; INVOICE_ID_LEN]),
+            dest_payment: i as u128,
+            opt_result: None,
+        }
+    }
+
+    #[test]
+    fn test_payment_submissions() {
+        let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut state = FunderState::<u32>::new(local_public_key, Vec::new());
+
+        // Submitting the same request again does not add it twice:
+        state.mutate(&FunderMutation::AddPaymentSubmission(dummy_submission(0)));
+        state.mutate(&FunderMutation::AddPaymentSubmission(dummy_submission(0)));
+        assert_eq!(state.submissions.len(), 1);
+
+        // Only the first result is recorded:
+        let request_id = dummy_submission(0).request_id;
+        let failure = ResponseSendFundsResult::Failure((
+            PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]),
+            FailureReason::Unspecified,
+        ));
+        let other_failure = ResponseSendFundsResult::Failure((
+            PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]),
+            FailureReason::Cancelled,
+        ));
+        state.mutate(&FunderMutation::SetPaymentSubmissionResult((
+            request_id,
+            failure.clone(),
+        )));
+        state.mutate(&FunderMutation::SetPaymentSubmissionResult((
+            request_id,
+            other_failure,
+        )));
+        assert_eq!(state.submissions[0].opt_result, Some(failure));
+
+        state.mutate(&FunderMutation::RemovePaymentSubmission(request_id));
+        assert!(state.submissions.is_empty());
+
+        // Only the newest submissions are kept:
+        for i in 0..MAX_PAYMENT_SUBMISSIONS + 3 {
+            state.mutate(&FunderMutation::AddPaymentSubmission(dummy_submission(i)));
         }
+        assert_eq!(state.submissions.len(), MAX_PAYMENT_SUBMISSIONS);
+        assert_eq!(state.submissions[0].dest_payment, 3);
     }
 }
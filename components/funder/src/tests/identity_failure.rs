@@ -0,0 +1,175 @@
+use std::cmp::Ordering;
+
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::{Spawn, SpawnExt};
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{compare_public_key, PublicKey, PUBLIC_KEY_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    AddFriend, FriendStatus, FunderControl, FunderIncomingControl, FunderOutgoingControl,
+    SetFriendStatus,
+};
+
+use database::DatabaseClient;
+
+use identity::test_utils::fixture_identity;
+use timer::TimerTick;
+
+use crate::ephemeral::EphemeralLimits;
+use crate::event_stats::{FunderEventStats, SlowEventLimits};
+use crate::funder::{inner_funder_loop, FunderError};
+use crate::state::{FunderMutation, FunderState};
+use crate::types::{FunderIncomingComm, FunderOutgoingComm, IncomingLivenessMessage};
+
+use super::utils::{
+    dummy_named_relay_address, dummy_relay_address, CHANNEL_SIZE, TEST_DEADLINES_GRANULARITY_TICKS,
+    TEST_MAX_NODE_RELAYS, TEST_MAX_OPERATIONS_IN_BATCH, TEST_MAX_PENDING_USER_REQUESTS,
+    TEST_MIN_OPERATIONS_IN_BATCH, TEST_PREWARM_TICKS, TEST_RELAYS_DAMPING_TICKS,
+    TEST_RELIABILITY_DECAY_TICKS,
+};
+
+/// Send a control message to the funder, and wait until it is acknowledged.
+async fn apply_control<'a>(
+    send_control: &'a mut mpsc::Sender<FunderIncomingControl<u32>>,
+    recv_control: &'a mut mpsc::Receiver<FunderOutgoingControl<u32>>,
+    app_request_id: Uid,
+    funder_control: FunderControl<u32>,
+) {
+    let incoming_control = FunderIncomingControl::new(app_request_id, funder_control);
+    await!(send_control.send(incoming_control)).unwrap();
+    while let Some(outgoing_control) = await!(recv_control.next()) {
+        if let FunderOutgoingControl::ReportMutations(report_mutations) = outgoing_control {
+            if report_mutations.opt_app_request_id == Some(app_request_id) {
+                return;
+            }
+        }
+    }
+    unreachable!();
+}
+
+async fn task_funder_identity_failure<S>(mut spawner: S)
+where
+    S: Spawn,
+{
+    let (identity_client, local_public_key, identity_server) = fixture_identity(0);
+    let identity_handle = spawner.spawn_with_handle(identity_server).unwrap();
+
+    // A database that records all the persisted batches of mutations:
+    let (db_request_sender, mut incoming_db_requests) = mpsc::channel(0);
+    let db_client = DatabaseClient::new(db_request_sender);
+    let (batch_sender, mut batch_receiver) = mpsc::unbounded::<Vec<FunderMutation<u32>>>();
+    let fut_record_db_requests = async move {
+        while let Some(request) = await!(incoming_db_requests.next()) {
+            batch_sender.unbounded_send(request.mutations).unwrap();
+            let _ = request.response_sender.send(Some(()));
+        }
+    };
+    spawner.spawn(fut_record_db_requests).unwrap();
+
+    let (mut send_control, incoming_control) = mpsc::channel(CHANNEL_SIZE);
+    let (control_sender, mut recv_control) = mpsc::channel(CHANNEL_SIZE);
+
+    let (mut send_comm, incoming_comm) = mpsc::channel(CHANNEL_SIZE);
+    let (comm_sender, recv_comm) = mpsc::channel(CHANNEL_SIZE);
+
+    let (_tick_sender, timer_stream) = mpsc::channel::<TimerTick>(0);
+
+    let relays = vec![dummy_named_relay_address(0)];
+    let funder_state = FunderState::new(local_public_key.clone(), relays);
+    let funder_fut = inner_funder_loop(
+        identity_client,
+        DummyRandom::new(&[0u8]),
+        timer_stream,
+        incoming_control,
+        incoming_comm,
+        control_sender,
+        comm_sender,
+        funder_state,
+        db_client,
+        TEST_MIN_OPERATIONS_IN_BATCH,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        TEST_MAX_NODE_RELAYS,
+        TEST_MAX_PENDING_USER_REQUESTS,
+        TEST_RELAYS_DAMPING_TICKS,
+        TEST_PREWARM_TICKS,
+        TEST_RELIABILITY_DECAY_TICKS,
+        TEST_DEADLINES_GRANULARITY_TICKS,
+        EphemeralLimits::default(),
+        SlowEventLimits::default(),
+        FunderEventStats::default(),
+        None,
+    );
+    let funder_handle = spawner.spawn_with_handle(funder_fut).unwrap();
+
+    // Pick a friend for which we are the second sender, so that we have to sign a move token
+    // when the friend becomes online:
+    let friend_public_key = (0..=255u8)
+        .map(|i| PublicKey::from(&[i; PUBLIC_KEY_LEN]))
+        .find(|public_key| compare_public_key(&local_public_key, public_key) == Ordering::Greater)
+        .unwrap();
+
+    let add_friend = AddFriend {
+        friend_public_key: friend_public_key.clone(),
+        relays: vec![dummy_relay_address(1)],
+        name: "friend".to_owned(),
+        balance: 0,
+    };
+    await!(apply_control(
+        &mut send_control,
+        &mut recv_control,
+        Uid::from(&[0; UID_LEN]),
+        FunderControl::AddFriend(add_friend)
+    ));
+
+    let set_friend_status = SetFriendStatus {
+        friend_public_key: friend_public_key.clone(),
+        status: FriendStatus::Enabled,
+    };
+    await!(apply_control(
+        &mut send_control,
+        &mut recv_control,
+        Uid::from(&[1; UID_LEN]),
+        FunderControl::SetFriendStatus(set_friend_status)
+    ));
+
+    // Batches are persisted before the request is acknowledged:
+    let mut persisted = Vec::new();
+    while let Ok(Some(batch)) = batch_receiver.try_next() {
+        persisted.extend(batch);
+    }
+    assert!(persisted.iter().any(|funder_mutation| match funder_mutation {
+        FunderMutation::AddFriend(_) => true,
+        _ => false,
+    }));
+
+    // The identity service dies:
+    drop(identity_handle);
+
+    // The friend becomes online. We can not sign the move token:
+    let liveness_message = IncomingLivenessMessage::Online(friend_public_key.clone());
+    await!(send_comm.send(FunderIncomingComm::Liveness(liveness_message))).unwrap();
+
+    // The funder shuts down:
+    match await!(funder_handle) {
+        Err(FunderError::IdentityError(_)) => {}
+        _ => unreachable!(),
+    };
+
+    // Nothing from the failed message was persisted or sent:
+    assert!(await!(batch_receiver.collect::<Vec<_>>()).is_empty());
+    let outgoing_comms = await!(recv_comm.collect::<Vec<_>>());
+    assert!(!outgoing_comms.iter().any(|outgoing_comm| match outgoing_comm {
+        FunderOutgoingComm::FriendMessage(_) => true,
+        _ => false,
+    }));
+}
+
+#[test]
+fn test_funder_identity_failure() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_funder_identity_failure(thread_pool.clone()));
+}
@@ -1,2 +1,5 @@
+mod identity_failure;
+mod read_only;
+mod slow_events;
 mod tests;
 pub mod utils;
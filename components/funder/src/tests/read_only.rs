@@ -0,0 +1,282 @@
+use std::cmp::Ordering;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::{Spawn, SpawnExt};
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{compare_public_key, PublicKey, PUBLIC_KEY_LEN};
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    AddFriend, FailureReason, FriendMessage, FriendStatus, FriendsRoute, FunderControl,
+    FunderIncomingControl, FunderOutgoingControl, ResponseReceived, ResponseSendFundsResult,
+    SetFriendStatus, UserRequestSendFunds,
+};
+use proto::report::messages::FunderReportMutation;
+
+use database::DatabaseClient;
+
+use identity::test_utils::spawn_fixture_identity;
+use timer::TimerTick;
+
+use crate::ephemeral::EphemeralLimits;
+use crate::event_stats::{FunderEventStats, SlowEventLimits};
+use crate::funder::inner_funder_loop;
+use crate::state::{FunderMutation, FunderState};
+use crate::types::{FunderIncomingComm, FunderOutgoingComm, IncomingLivenessMessage};
+
+use super::utils::{
+    dummy_named_relay_address, dummy_relay_address, CHANNEL_SIZE, TEST_DEADLINES_GRANULARITY_TICKS,
+    TEST_MAX_NODE_RELAYS, TEST_MAX_OPERATIONS_IN_BATCH, TEST_MAX_PENDING_USER_REQUESTS,
+    TEST_MIN_OPERATIONS_IN_BATCH, TEST_PREWARM_TICKS, TEST_RELAYS_DAMPING_TICKS,
+    TEST_RELIABILITY_DECAY_TICKS,
+};
+
+/// Send a control message to the funder, and wait until it is acknowledged.
+async fn apply_control<'a>(
+    send_control: &'a mut mpsc::Sender<FunderIncomingControl<u32>>,
+    recv_control: &'a mut mpsc::Receiver<FunderOutgoingControl<u32>>,
+    app_request_id: Uid,
+    funder_control: FunderControl<u32>,
+) {
+    let incoming_control = FunderIncomingControl::new(app_request_id, funder_control);
+    await!(send_control.send(incoming_control)).unwrap();
+    while let Some(outgoing_control) = await!(recv_control.next()) {
+        if let FunderOutgoingControl::ReportMutations(report_mutations) = outgoing_control {
+            if report_mutations.opt_app_request_id == Some(app_request_id) {
+                return;
+            }
+        }
+    }
+    unreachable!();
+}
+
+/// Wait until the funder reports entering (true) or leaving (false) the read only mode.
+async fn wait_read_only(
+    recv_control: &mut mpsc::Receiver<FunderOutgoingControl<u32>>,
+    read_only: bool,
+) {
+    while let Some(outgoing_control) = await!(recv_control.next()) {
+        if let FunderOutgoingControl::ReportMutations(report_mutations) = outgoing_control {
+            if report_mutations
+                .mutations
+                .contains(&FunderReportMutation::SetReadOnly(read_only))
+            {
+                return;
+            }
+        }
+    }
+    unreachable!();
+}
+
+/// Send a payment request to the funder, and wait for the response.
+async fn request_send_funds<'a>(
+    send_control: &'a mut mpsc::Sender<FunderIncomingControl<u32>>,
+    recv_control: &'a mut mpsc::Receiver<FunderOutgoingControl<u32>>,
+    user_request_send_funds: UserRequestSendFunds,
+) -> ResponseReceived {
+    let incoming_control = FunderIncomingControl::new(
+        Uid::from(&[0x10; UID_LEN]),
+        FunderControl::RequestSendFunds(user_request_send_funds),
+    );
+    await!(send_control.send(incoming_control)).unwrap();
+    while let Some(outgoing_control) = await!(recv_control.next()) {
+        if let FunderOutgoingControl::ResponseReceived(response_received) = outgoing_control {
+            return response_received;
+        }
+    }
+    unreachable!();
+}
+
+fn is_move_token(outgoing_comm: &FunderOutgoingComm<u32>) -> bool {
+    match outgoing_comm {
+        FunderOutgoingComm::FriendMessage((_, FriendMessage::MoveTokenRequest(_))) => true,
+        _ => false,
+    }
+}
+
+async fn task_funder_read_only<S>(mut spawner: S)
+where
+    S: Spawn,
+{
+    let (identity_client, local_public_key) = spawn_fixture_identity(0, &mut spawner);
+
+    // A database that records all the batches of mutations it was asked to persist.
+    // Writes fail while the disk is full:
+    let disk_full = Arc::new(AtomicBool::new(false));
+    let c_disk_full = disk_full.clone();
+    let (db_request_sender, mut incoming_db_requests) = mpsc::channel(0);
+    let db_client = DatabaseClient::new(db_request_sender);
+    let (batch_sender, mut batch_receiver) = mpsc::unbounded::<(Vec<FunderMutation<u32>>, bool)>();
+    let fut_record_db_requests = async move {
+        while let Some(request) = await!(incoming_db_requests.next()) {
+            let persisted = !c_disk_full.load(atomic::Ordering::SeqCst);
+            batch_sender
+                .unbounded_send((request.mutations, persisted))
+                .unwrap();
+            let opt_done = if persisted { Some(()) } else { None };
+            let _ = request.response_sender.send(opt_done);
+        }
+    };
+    spawner.spawn(fut_record_db_requests).unwrap();
+
+    let (mut send_control, incoming_control) = mpsc::channel(CHANNEL_SIZE);
+    let (control_sender, mut recv_control) = mpsc::channel(CHANNEL_SIZE);
+
+    let (mut send_comm, incoming_comm) = mpsc::channel(CHANNEL_SIZE);
+    let (comm_sender, mut recv_comm) = mpsc::channel(CHANNEL_SIZE);
+
+    let (mut tick_sender, timer_stream) = mpsc::channel::<TimerTick>(0);
+
+    let relays = vec![dummy_named_relay_address(0)];
+    let funder_state = FunderState::new(local_public_key.clone(), relays);
+    let funder_fut = inner_funder_loop(
+        identity_client,
+        DummyRandom::new(&[0u8]),
+        timer_stream,
+        incoming_control,
+        incoming_comm,
+        control_sender,
+        comm_sender,
+        funder_state,
+        db_client,
+        TEST_MIN_OPERATIONS_IN_BATCH,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        TEST_MAX_NODE_RELAYS,
+        TEST_MAX_PENDING_USER_REQUESTS,
+        TEST_RELAYS_DAMPING_TICKS,
+        TEST_PREWARM_TICKS,
+        TEST_RELIABILITY_DECAY_TICKS,
+        TEST_DEADLINES_GRANULARITY_TICKS,
+        EphemeralLimits::default(),
+        SlowEventLimits::default(),
+        FunderEventStats::default(),
+        None,
+    );
+    // The funder keeps running for as long as we hold the handle:
+    let _funder_handle = spawner.spawn_with_handle(funder_fut).unwrap();
+
+    // Pick a friend for which we are the second sender, so that we have to send a new move token
+    // when the friend becomes online:
+    let friend_public_key = (0..=255u8)
+        .map(|i| PublicKey::from(&[i; PUBLIC_KEY_LEN]))
+        .find(|public_key| compare_public_key(&local_public_key, public_key) == Ordering::Greater)
+        .unwrap();
+
+    let add_friend = AddFriend {
+        friend_public_key: friend_public_key.clone(),
+        relays: vec![dummy_relay_address(1)],
+        name: "friend".to_owned(),
+        balance: 0,
+    };
+    await!(apply_control(
+        &mut send_control,
+        &mut recv_control,
+        Uid::from(&[0; UID_LEN]),
+        FunderControl::AddFriend(add_friend)
+    ));
+
+    let set_friend_status = SetFriendStatus {
+        friend_public_key: friend_public_key.clone(),
+        status: FriendStatus::Enabled,
+    };
+    await!(apply_control(
+        &mut send_control,
+        &mut recv_control,
+        Uid::from(&[1; UID_LEN]),
+        FunderControl::SetFriendStatus(set_friend_status)
+    ));
+
+    // Batches are persisted before the request is acknowledged:
+    while let Ok(Some((_mutations, persisted))) = batch_receiver.try_next() {
+        assert!(persisted);
+    }
+
+    // The disk is full:
+    disk_full.store(true, atomic::Ordering::SeqCst);
+
+    // The friend becomes online. We can not persist the move token we have to send:
+    let liveness_message = IncomingLivenessMessage::Online(friend_public_key.clone());
+    await!(send_comm.send(FunderIncomingComm::Liveness(liveness_message))).unwrap();
+    await!(wait_read_only(&mut recv_control, true));
+
+    // Payments are rejected:
+    let user_request_send_funds = UserRequestSendFunds {
+        request_id: Uid::from(&[2; UID_LEN]),
+        route: FriendsRoute {
+            public_keys: vec![local_public_key.clone(), friend_public_key.clone()],
+        },
+        invoice_id: InvoiceId::from(&[2; INVOICE_ID_LEN]),
+        dest_payment: 10,
+        opt_label: None,
+    };
+    let response_received = await!(request_send_funds(
+        &mut send_control,
+        &mut recv_control,
+        user_request_send_funds.clone()
+    ));
+    assert_eq!(
+        response_received.result,
+        ResponseSendFundsResult::Failure((
+            local_public_key.clone(),
+            FailureReason::StorageUnavailable
+        ))
+    );
+
+    // Every timer tick probes the database:
+    await!(tick_sender.send(TimerTick { ticks_elapsed: 1 })).unwrap();
+    loop {
+        let (mutations, persisted) = await!(batch_receiver.next()).unwrap();
+        assert!(!persisted);
+        if mutations.is_empty() {
+            break;
+        }
+    }
+
+    // No move token was sent while the database was read only:
+    while let Ok(Some(outgoing_comm)) = recv_comm.try_next() {
+        assert!(!is_move_token(&outgoing_comm));
+    }
+
+    // Space was freed on the disk. The next probe succeeds:
+    disk_full.store(false, atomic::Ordering::SeqCst);
+    await!(tick_sender.send(TimerTick { ticks_elapsed: 1 })).unwrap();
+    await!(wait_read_only(&mut recv_control, false));
+
+    // The move token we could not send before is sent now, after it was persisted:
+    loop {
+        let outgoing_comm = await!(recv_comm.next()).unwrap();
+        if is_move_token(&outgoing_comm) {
+            break;
+        }
+    }
+    let mut persisted_batches = Vec::new();
+    while let Ok(Some((mutations, persisted))) = batch_receiver.try_next() {
+        if persisted && !mutations.is_empty() {
+            persisted_batches.push(mutations);
+        }
+    }
+    assert!(!persisted_batches.is_empty());
+
+    // Payments are no longer rejected because of the database:
+    let response_received = await!(request_send_funds(
+        &mut send_control,
+        &mut recv_control,
+        user_request_send_funds
+    ));
+    assert!(match response_received.result {
+        ResponseSendFundsResult::Failure((_, FailureReason::StorageUnavailable)) => false,
+        _ => true,
+    });
+}
+
+#[test]
+fn test_funder_read_only() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_funder_read_only(thread_pool.clone()));
+}
@@ -0,0 +1,159 @@
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use futures::executor::ThreadPool;
+use futures::task::{Spawn, SpawnExt};
+use futures::{SinkExt, StreamExt};
+
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+use crypto::test_utils::DummyRandom;
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::funder::messages::{
+    AddFriend, FunderControl, FunderIncomingControl, FunderOutgoingControl,
+};
+
+use database::DatabaseClient;
+
+use identity::test_utils::spawn_fixture_identity;
+use timer::TimerTick;
+
+use crate::ephemeral::EphemeralLimits;
+use crate::event_stats::{EventPhase, FunderEventStats, SlowEventLimits, LATENCY_BUCKETS_US};
+use crate::funder::inner_funder_loop;
+use crate::state::FunderState;
+
+use super::utils::{
+    dummy_named_relay_address, dummy_relay_address, CHANNEL_SIZE, TEST_DEADLINES_GRANULARITY_TICKS,
+    TEST_MAX_NODE_RELAYS, TEST_MAX_OPERATIONS_IN_BATCH, TEST_MAX_PENDING_USER_REQUESTS,
+    TEST_MIN_OPERATIONS_IN_BATCH, TEST_PREWARM_TICKS, TEST_RELAYS_DAMPING_TICKS,
+    TEST_RELIABILITY_DECAY_TICKS,
+};
+
+/// Every write to the database takes this long:
+const PERSIST_MS: u64 = 30;
+
+/// Amount of slow events in the burst
+const NUM_SLOW_EVENTS: u8 = 5;
+
+async fn task_funder_slow_events<S>(mut spawner: S)
+where
+    S: Spawn,
+{
+    let (identity_client, local_public_key) = spawn_fixture_identity(0, &mut spawner);
+
+    // A slow database:
+    let (db_request_sender, mut incoming_db_requests) = mpsc::channel(0);
+    let db_client = DatabaseClient::new(db_request_sender);
+    let fut_slow_db = async move {
+        while let Some(request) = await!(incoming_db_requests.next()) {
+            thread::sleep(Duration::from_millis(PERSIST_MS));
+            let _ = request.response_sender.send(Some(()));
+        }
+    };
+    spawner.spawn(fut_slow_db).unwrap();
+
+    let (mut send_control, incoming_control) = mpsc::channel(CHANNEL_SIZE);
+    let (control_sender, mut recv_control) = mpsc::channel(CHANNEL_SIZE);
+
+    let (_send_comm, incoming_comm) = mpsc::channel(CHANNEL_SIZE);
+    let (comm_sender, _recv_comm) = mpsc::channel(CHANNEL_SIZE);
+
+    let (_tick_sender, timer_stream) = mpsc::channel::<TimerTick>(0);
+
+    // Events are slow long before they are as slow as the database.
+    // Only one warning may be logged during the test:
+    let slow_event_limits = SlowEventLimits {
+        threshold: Duration::from_millis(PERSIST_MS / 3),
+        log_interval: Duration::from_secs(60 * 60),
+    };
+    let event_stats = FunderEventStats::default();
+
+    let relays = vec![dummy_named_relay_address(0)];
+    let funder_state = FunderState::new(local_public_key.clone(), relays);
+    let funder_fut = inner_funder_loop(
+        identity_client,
+        DummyRandom::new(&[0u8]),
+        timer_stream,
+        incoming_control,
+        incoming_comm,
+        control_sender,
+        comm_sender,
+        funder_state,
+        db_client,
+        TEST_MIN_OPERATIONS_IN_BATCH,
+        TEST_MAX_OPERATIONS_IN_BATCH,
+        TEST_MAX_NODE_RELAYS,
+        TEST_MAX_PENDING_USER_REQUESTS,
+        TEST_RELAYS_DAMPING_TICKS,
+        TEST_PREWARM_TICKS,
+        TEST_RELIABILITY_DECAY_TICKS,
+        TEST_DEADLINES_GRANULARITY_TICKS,
+        EphemeralLimits::default(),
+        slow_event_limits,
+        event_stats.clone(),
+        None,
+    );
+    // The funder keeps running for as long as we hold the handle:
+    let _funder_handle = spawner.spawn_with_handle(funder_fut).unwrap();
+
+    // A burst of control messages, every one of them has to be persisted:
+    for i in 0..NUM_SLOW_EVENTS {
+        let add_friend = AddFriend {
+            friend_public_key: PublicKey::from(&[i; PUBLIC_KEY_LEN]),
+            relays: vec![dummy_relay_address(1)],
+            name: format!("friend{}", i),
+            balance: 0,
+        };
+        let app_request_id = Uid::from(&[i; UID_LEN]);
+        let incoming_control =
+            FunderIncomingControl::new(app_request_id, FunderControl::AddFriend(add_friend));
+        await!(send_control.send(incoming_control)).unwrap();
+
+        // Wait for the acknowledgement:
+        while let Some(outgoing_control) = await!(recv_control.next()) {
+            if let FunderOutgoingControl::ReportMutations(report_mutations) = outgoing_control {
+                if report_mutations.opt_app_request_id == Some(app_request_id) {
+                    break;
+                }
+            }
+        }
+    }
+
+    // The statistics of an event are recorded right after its acknowledgement is sent.
+    // Wait until the last event of the burst is recorded:
+    while event_stats.slow_events() < u64::from(NUM_SLOW_EVENTS) {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    // Only the first slow event of the burst was logged:
+    assert_eq!(event_stats.slow_events(), u64::from(NUM_SLOW_EVENTS));
+    assert_eq!(event_stats.slow_event_warnings(), 1);
+
+    // Persisting dominates the time spent handling the events:
+    let persist_histogram = event_stats.phase_histogram(EventPhase::Persist);
+    for &phase in &[EventPhase::Handle, EventPhase::Mutate, EventPhase::Emit] {
+        assert!(persist_histogram.total_us > event_stats.phase_histogram(phase).total_us);
+    }
+    assert!(persist_histogram.total_us >= u64::from(NUM_SLOW_EVENTS) * PERSIST_MS * 1000);
+
+    // Every slow write was recorded in the buckets above 10ms:
+    let slow_bucket = LATENCY_BUCKETS_US
+        .iter()
+        .position(|&bound| bound == 10_000)
+        .unwrap();
+    let num_slow_persists: u64 = persist_histogram.counts[slow_bucket + 1..].iter().sum();
+    assert_eq!(num_slow_persists, u64::from(NUM_SLOW_EVENTS));
+
+    // Every handled event was recorded in all the histograms:
+    let num_events = event_stats.total_histogram().num_samples();
+    assert!(num_events >= u64::from(NUM_SLOW_EVENTS));
+    assert_eq!(persist_histogram.num_samples(), num_events);
+}
+
+#[test]
+fn test_funder_slow_events() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_funder_slow_events(thread_pool.clone()));
+}
@@ -6,10 +6,13 @@ use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
 use crypto::uid::{Uid, UID_LEN};
 
 use proto::funder::messages::{
-    FriendStatus, FriendsRoute, FunderControl, FunderIncomingControl, ReceiptAck, RequestsStatus,
-    ResetFriendChannel, ResponseSendFundsResult, UserRequestSendFunds,
+    DustThresholds, FailureReason, FriendStatus, FriendsRoute, FunderControl,
+    FunderIncomingControl, PaymentConsumer, PaymentNotifier, PaymentNotifyFilter, ReceiptAck,
+    RequestsStatus, ResetFriendChannel, ResponseSendFundsResult, UserRequestSendFunds,
 };
-use proto::report::messages::{ChannelStatusReport, FunderReport};
+use proto::report::messages::{ChannelStatusReport, FunderReport, PendingPaymentStageReport};
+
+use crate::credit_calc::credits_to_freeze;
 
 use super::utils::{create_node_controls, dummy_named_relay_address, dummy_relay_address};
 
@@ -55,6 +58,7 @@ async fn task_funder_basic(spawner: impl Spawn + Clone + Send + 'static) {
         },
         invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
         dest_payment: 5,
+        opt_label: None,
     };
     let incoming_control_message = FunderIncomingControl::new(
         Uid::from(&[40; UID_LEN]),
@@ -167,14 +171,40 @@ async fn task_funder_forward_payment(spawner: impl Spawn + Clone + Send + 'stati
         },
         invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
         dest_payment: 20,
+        opt_label: None,
     };
     let incoming_control_message = FunderIncomingControl::new(
         Uid::from(&[42; UID_LEN]),
         FunderControl::RequestSendFunds(user_request_send_funds),
     );
     await!(node_controls[0].send(incoming_control_message)).unwrap();
+
+    // While the payment is in flight, the frozen credits are visible in the report:
+    let pred = |report: &FunderReport<_>| {
+        let friend = report.friends.get(&public_keys[1]).unwrap();
+        match friend.pending_payments.as_slice() {
+            [pending_payment] => {
+                assert_eq!(pending_payment.request_id, Uid::from(&[3; UID_LEN]));
+                assert_eq!(
+                    pending_payment.frozen_credits,
+                    credits_to_freeze(1, 3, 20).unwrap()
+                );
+                pending_payment.stage == PendingPaymentStageReport::Sent
+            }
+            _ => false,
+        }
+    };
+    await!(node_controls[0].recv_until(pred));
+
     let response_received = await!(node_controls[0].recv_until_response()).unwrap();
     assert_eq!(response_received.request_id, Uid::from(&[3; UID_LEN]));
+
+    // The payment is not pending anymore:
+    let pred = |report: &FunderReport<_>| {
+        let friend = report.friends.get(&public_keys[1]).unwrap();
+        friend.pending_payments.is_empty()
+    };
+    await!(node_controls[0].recv_until(pred));
     let receipt = match response_received.result {
         ResponseSendFundsResult::Failure(_) => unreachable!(),
         ResponseSendFundsResult::Success(send_funds_receipt) => send_funds_receipt,
@@ -275,6 +305,7 @@ async fn task_funder_payment_failure(spawner: impl Spawn + Clone + Send + 'stati
         },
         invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
         dest_payment: 20,
+        opt_label: None,
     };
     let incoming_control_message = FunderIncomingControl::new(
         Uid::from(&[44; UID_LEN]),
@@ -284,7 +315,7 @@ async fn task_funder_payment_failure(spawner: impl Spawn + Clone + Send + 'stati
     let response_received = await!(node_controls[0].recv_until_response()).unwrap();
     assert_eq!(response_received.request_id, Uid::from(&[3; UID_LEN]));
     let reporting_public_key = match response_received.result {
-        ResponseSendFundsResult::Failure(reporting_public_key) => reporting_public_key,
+        ResponseSendFundsResult::Failure((reporting_public_key, _reason)) => reporting_public_key,
         ResponseSendFundsResult::Success(_) => unreachable!(),
     };
 
@@ -433,3 +464,189 @@ fn test_funder_add_relay() {
     let mut thread_pool = ThreadPool::new().unwrap();
     thread_pool.run(task_funder_add_relay(thread_pool.clone()));
 }
+
+async fn task_funder_incoming_payment_notifications<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let num_nodes = 2;
+    let mut node_controls = await!(create_node_controls(num_nodes, spawner));
+
+    let public_keys = node_controls
+        .iter()
+        .map(|nc| nc.public_key.clone())
+        .collect::<Vec<PublicKey>>();
+
+    let relays0 = vec![dummy_relay_address(0)];
+    let relays1 = vec![dummy_relay_address(1)];
+    await!(node_controls[0].add_friend(&public_keys[1], relays1, "node1", 8));
+    await!(node_controls[1].add_friend(&public_keys[0], relays0, "node0", -8));
+
+    await!(node_controls[0].set_friend_status(&public_keys[1], FriendStatus::Enabled));
+    await!(node_controls[1].set_friend_status(&public_keys[0], FriendStatus::Enabled));
+
+    await!(node_controls[0].set_remote_max_debt(&public_keys[1], 200));
+    await!(node_controls[1].set_remote_max_debt(&public_keys[0], 100));
+
+    await!(node_controls[0].set_requests_status(&public_keys[1], RequestsStatus::Open));
+    await!(node_controls[1].set_requests_status(&public_keys[0], RequestsStatus::Open));
+
+    await!(node_controls[0].wait_until_ready(&public_keys[1]));
+    await!(node_controls[1].wait_until_ready(&public_keys[0]));
+
+    // Node1 wants to be notified only about payments for invoice 1:
+    await!(node_controls[1].set_payment_notifier(PaymentNotifier {
+        consumer: PaymentConsumer::Address(0x1234),
+        filter: PaymentNotifyFilter::Invoices(vec![InvoiceId::from(&[1; INVOICE_ID_LEN])]),
+    }));
+
+    // Send credits 0 --> 1, first for invoice 2 and then for invoice 1:
+    let mut receipts = Vec::new();
+    for (i, invoice_byte) in [2u8, 1u8].iter().enumerate() {
+        let request_id = Uid::from(&[3 + i as u8; UID_LEN]);
+        let user_request_send_funds = UserRequestSendFunds {
+            request_id,
+            route: FriendsRoute {
+                public_keys: vec![public_keys[0].clone(), public_keys[1].clone()],
+            },
+            invoice_id: InvoiceId::from(&[*invoice_byte; INVOICE_ID_LEN]),
+            dest_payment: 5,
+            opt_label: None,
+        };
+        let incoming_control_message = FunderIncomingControl::new(
+            Uid::from(&[40 + i as u8; UID_LEN]),
+            FunderControl::RequestSendFunds(user_request_send_funds),
+        );
+        await!(node_controls[0].send(incoming_control_message)).unwrap();
+        let response_received = await!(node_controls[0].recv_until_response()).unwrap();
+        match response_received.result {
+            ResponseSendFundsResult::Failure(_) => unreachable!(),
+            ResponseSendFundsResult::Success(receipt) => receipts.push(receipt),
+        };
+    }
+
+    // Only the payment for invoice 1 is reported, with the same receipt the payer got:
+    let incoming_payment = await!(node_controls[1].recv_until_incoming_payment()).unwrap();
+    assert_eq!(incoming_payment.notification_id, 0);
+    assert_eq!(incoming_payment.route_len, 2);
+    assert_eq!(incoming_payment.receipt, receipts[1]);
+}
+
+#[test]
+fn test_funder_incoming_payment_notifications() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_funder_incoming_payment_notifications(
+        thread_pool.clone(),
+    ));
+}
+
+async fn task_funder_dust_thresholds(spawner: impl Spawn + Clone + Send + 'static) {
+    /*
+     * 0 -- 1 -- 2
+     * node0 does not send payments below 10.
+     * node1 does not forward payments below 20.
+     * node2 does not receive payments below 20.
+     */
+    let num_nodes = 3;
+    let mut node_controls = await!(create_node_controls(num_nodes, spawner));
+
+    let public_keys = node_controls
+        .iter()
+        .map(|nc| nc.public_key.clone())
+        .collect::<Vec<PublicKey>>();
+
+    // Add friends:
+    let relays0 = vec![dummy_relay_address(0)];
+    let relays1 = vec![dummy_relay_address(1)];
+    let relays2 = vec![dummy_relay_address(2)];
+    await!(node_controls[0].add_friend(&public_keys[1], relays1.clone(), "node1", 8));
+    await!(node_controls[1].add_friend(&public_keys[0], relays0, "node0", -8));
+    await!(node_controls[1].add_friend(&public_keys[2], relays2, "node2", 6));
+    await!(node_controls[2].add_friend(&public_keys[1], relays1, "node1", -6));
+
+    // Enable friends:
+    await!(node_controls[0].set_friend_status(&public_keys[1], FriendStatus::Enabled));
+    await!(node_controls[1].set_friend_status(&public_keys[0], FriendStatus::Enabled));
+    await!(node_controls[1].set_friend_status(&public_keys[2], FriendStatus::Enabled));
+    await!(node_controls[2].set_friend_status(&public_keys[1], FriendStatus::Enabled));
+
+    // Set remote max debt:
+    await!(node_controls[0].set_remote_max_debt(&public_keys[1], 200));
+    await!(node_controls[1].set_remote_max_debt(&public_keys[0], 100));
+    await!(node_controls[1].set_remote_max_debt(&public_keys[2], 300));
+    await!(node_controls[2].set_remote_max_debt(&public_keys[1], 400));
+
+    // Open requests, allowing this route: 0 --> 1 --> 2
+    await!(node_controls[1].set_requests_status(&public_keys[0], RequestsStatus::Open));
+    await!(node_controls[2].set_requests_status(&public_keys[1], RequestsStatus::Open));
+
+    await!(node_controls[0].wait_until_ready(&public_keys[1]));
+    await!(node_controls[1].wait_until_ready(&public_keys[2]));
+
+    // Set dust thresholds:
+    await!(node_controls[0].set_dust_thresholds(DustThresholds {
+        min_send_payment: 10,
+        ..DustThresholds::default()
+    }));
+    await!(node_controls[1].set_dust_thresholds(DustThresholds {
+        min_forward_payment: 20,
+        ..DustThresholds::default()
+    }));
+    await!(node_controls[2].set_dust_thresholds(DustThresholds {
+        min_receive_payment: 20,
+        ..DustThresholds::default()
+    }));
+
+    // (sender index, route, dest_payment, expected failure)
+    let payments = vec![
+        // Rejected by the sender:
+        (0, vec![0, 1], 9, Some((0, FailureReason::PricingRejected))),
+        // Exactly the minimum for sending:
+        (0, vec![0, 1], 10, None),
+        // Rejected by the first intermediary:
+        (0, vec![0, 1, 2], 19, Some((1, FailureReason::PricingRejected))),
+        // Rejected by the destination:
+        (1, vec![1, 2], 19, Some((2, FailureReason::PricingRejected))),
+        // Exactly the minimum for forwarding and receiving:
+        (0, vec![0, 1, 2], 20, None),
+    ];
+
+    for (i, (sender, route, dest_payment, opt_failure)) in payments.into_iter().enumerate() {
+        let request_id = Uid::from(&[0x50 + i as u8; UID_LEN]);
+        let user_request_send_funds = UserRequestSendFunds {
+            request_id,
+            route: FriendsRoute {
+                public_keys: route
+                    .into_iter()
+                    .map(|index| public_keys[index].clone())
+                    .collect(),
+            },
+            invoice_id: InvoiceId::from(&[1; INVOICE_ID_LEN]),
+            dest_payment,
+            opt_label: None,
+        };
+        let incoming_control_message = FunderIncomingControl::new(
+            Uid::from(&[0x60 + i as u8; UID_LEN]),
+            FunderControl::RequestSendFunds(user_request_send_funds),
+        );
+        await!(node_controls[sender].send(incoming_control_message)).unwrap();
+        let response_received =
+            await!(node_controls[sender].recv_until_response()).unwrap();
+        assert_eq!(response_received.request_id, request_id);
+        match (response_received.result, opt_failure) {
+            (ResponseSendFundsResult::Success(receipt), None) => {
+                assert_eq!(receipt.dest_payment, dest_payment);
+            }
+            (ResponseSendFundsResult::Failure(failure), Some((index, reason))) => {
+                assert_eq!(failure, (public_keys[index].clone(), reason));
+            }
+            _ => unreachable!(),
+        };
+    }
+}
+
+#[test]
+fn test_funder_dust_thresholds() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_funder_dust_thresholds(thread_pool.clone()));
+}
@@ -7,9 +7,7 @@ use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
 use futures::{future, FutureExt, SinkExt, StreamExt};
 
-use crypto::identity::{
-    generate_pkcs8_key_pair, PublicKey, SoftwareEd25519Identity, PUBLIC_KEY_LEN,
-};
+use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
 use crypto::test_utils::DummyRandom;
 use crypto::uid::{Uid, UID_LEN};
 
@@ -20,15 +18,20 @@ use proto::report::messages::{
 
 use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
 use proto::funder::messages::{
-    AddFriend, FriendStatus, FunderControl, FunderIncomingControl, FunderOutgoingControl,
-    RequestsStatus, ResponseReceived, SetFriendRemoteMaxDebt, SetFriendStatus, SetRequestsStatus,
+    AddFriend, DustThresholds, FriendStatus, FunderControl, FunderIncomingControl,
+    FunderOutgoingControl, IncomingPayment, PaymentNotifier, RequestsStatus,
+    ResponseCancelUserRequest, ResponsePairFreezeUsage, ResponsePendingSubmissions,
+    ResponsePrewarm, ResponseReceived, SetFriendRemoteMaxDebt, SetFriendStatus, SetRequestsStatus,
 };
+use proto::invite::messages::ResponseImportFriendInvite;
 
 use database::DatabaseClient;
 
-use identity::{create_identity, IdentityClient};
+use identity::test_utils::spawn_fixture_identity;
+use timer::TimerTick;
 
-use crate::ephemeral::Ephemeral;
+use crate::ephemeral::{Ephemeral, EphemeralLimits};
+use crate::event_stats::{FunderEventStats, SlowEventLimits};
 use crate::funder::inner_funder_loop;
 use crate::report::create_report;
 use crate::state::FunderState;
@@ -37,16 +40,21 @@ use crate::types::{
     ChannelerConfig, FunderIncomingComm, FunderOutgoingComm, IncomingLivenessMessage,
 };
 
-const TEST_MAX_NODE_RELAYS: usize = 16;
-const TEST_MAX_OPERATIONS_IN_BATCH: usize = 16;
-const TEST_MAX_PENDING_USER_REQUESTS: usize = 16;
+pub const TEST_MAX_NODE_RELAYS: usize = 16;
+pub const TEST_MIN_OPERATIONS_IN_BATCH: usize = 4;
+pub const TEST_MAX_OPERATIONS_IN_BATCH: usize = 16;
+pub const TEST_MAX_PENDING_USER_REQUESTS: usize = 16;
+pub const TEST_RELAYS_DAMPING_TICKS: usize = 4;
+pub const TEST_PREWARM_TICKS: usize = 4;
+pub const TEST_RELIABILITY_DECAY_TICKS: usize = 8;
+pub const TEST_DEADLINES_GRANULARITY_TICKS: usize = 2;
 
 // This is required to make sure the tests are not stuck.
 //
 // We could instead have CHANNEL_SIZE = 0 with some kind of (event_sender, event_receiver) pair, to make
 // sure an asynchronous event was fully processed before continuing with the next one, but this
 // approach makes tests difficult to write.
-const CHANNEL_SIZE: usize = 64;
+pub const CHANNEL_SIZE: usize = 64;
 
 /// A helper function to quickly create a dummy NamedRelayAddress.
 pub fn dummy_named_relay_address(index: u8) -> NamedRelayAddress<u32> {
@@ -135,6 +143,9 @@ async fn router_handle_outgoing_comm<'a, B: 'a>(
                         await!(comm_out.send(incoming_comm_message)).unwrap();
                     }
                 }
+                ChannelerConfig::SuspendFriend(_) => {
+                    // Do nothing here. The mock router does not reconnect to friends.
+                }
                 ChannelerConfig::SetRelays(_) => {
                     // Do nothing here. We use a mock router instead of a set of relays,
                     // so changing the address has no meaning.
@@ -205,6 +216,13 @@ pub struct NodeControl<B: Clone> {
 pub enum NodeRecv<B: Clone> {
     ReportMutations(FunderReportMutations<B>),
     ResponseReceived(ResponseReceived),
+    ResponsePrewarm(ResponsePrewarm),
+    ResponseCancelUserRequest(ResponseCancelUserRequest),
+    ResponseImportFriendInvite(ResponseImportFriendInvite),
+    ResponsePendingSubmissions(ResponsePendingSubmissions),
+    ResponsePairFreezeUsage(ResponsePairFreezeUsage),
+    IncomingPayment(IncomingPayment),
+    PaymentNotifierChanged(Option<PaymentNotifier<B>>),
 }
 
 impl<B> NodeControl<B>
@@ -227,6 +245,27 @@ where
             FunderOutgoingControl::ResponseReceived(response_received) => {
                 Some(NodeRecv::ResponseReceived(response_received))
             }
+            FunderOutgoingControl::ResponsePrewarm(response_prewarm) => {
+                Some(NodeRecv::ResponsePrewarm(response_prewarm))
+            }
+            FunderOutgoingControl::ResponseCancelUserRequest(response_cancel) => {
+                Some(NodeRecv::ResponseCancelUserRequest(response_cancel))
+            }
+            FunderOutgoingControl::ResponseImportFriendInvite(response_import) => {
+                Some(NodeRecv::ResponseImportFriendInvite(response_import))
+            }
+            FunderOutgoingControl::ResponsePendingSubmissions(response_pending) => {
+                Some(NodeRecv::ResponsePendingSubmissions(response_pending))
+            }
+            FunderOutgoingControl::ResponsePairFreezeUsage(response_usage) => {
+                Some(NodeRecv::ResponsePairFreezeUsage(response_usage))
+            }
+            FunderOutgoingControl::IncomingPayment(incoming_payment) => {
+                Some(NodeRecv::IncomingPayment(incoming_payment))
+            }
+            FunderOutgoingControl::PaymentNotifierChanged(opt_payment_notifier) => {
+                Some(NodeRecv::PaymentNotifierChanged(opt_payment_notifier))
+            }
         }
     }
 
@@ -236,8 +275,14 @@ where
     {
         while !predicate(&self.report) {
             match await!(self.recv()).unwrap() {
-                NodeRecv::ReportMutations(_) => {}
-                NodeRecv::ResponseReceived(_) => unreachable!(),
+                NodeRecv::ReportMutations(_) | NodeRecv::PaymentNotifierChanged(_) => {}
+                NodeRecv::ResponseReceived(_)
+                | NodeRecv::ResponsePrewarm(_)
+                | NodeRecv::ResponseCancelUserRequest(_)
+                | NodeRecv::ResponseImportFriendInvite(_)
+                | NodeRecv::ResponsePendingSubmissions(_)
+                | NodeRecv::ResponsePairFreezeUsage(_)
+                | NodeRecv::IncomingPayment(_) => unreachable!(),
             };
         }
     }
@@ -245,12 +290,59 @@ where
     pub async fn recv_until_response(&mut self) -> Option<ResponseReceived> {
         loop {
             match await!(self.recv())? {
-                NodeRecv::ReportMutations(_) => {}
+                NodeRecv::ReportMutations(_) | NodeRecv::PaymentNotifierChanged(_) => {}
                 NodeRecv::ResponseReceived(response_received) => return Some(response_received),
+                NodeRecv::ResponsePrewarm(_)
+                | NodeRecv::ResponseCancelUserRequest(_)
+                | NodeRecv::ResponseImportFriendInvite(_)
+                | NodeRecv::ResponsePendingSubmissions(_)
+                | NodeRecv::ResponsePairFreezeUsage(_)
+                | NodeRecv::IncomingPayment(_) => unreachable!(),
             };
         }
     }
 
+    pub async fn recv_until_incoming_payment(&mut self) -> Option<IncomingPayment> {
+        loop {
+            match await!(self.recv())? {
+                NodeRecv::ReportMutations(_) | NodeRecv::PaymentNotifierChanged(_) => {}
+                NodeRecv::ResponseReceived(_)
+                | NodeRecv::ResponsePrewarm(_)
+                | NodeRecv::ResponseCancelUserRequest(_)
+                | NodeRecv::ResponseImportFriendInvite(_)
+                | NodeRecv::ResponsePendingSubmissions(_)
+                | NodeRecv::ResponsePairFreezeUsage(_) => unreachable!(),
+                NodeRecv::IncomingPayment(incoming_payment) => return Some(incoming_payment),
+            };
+        }
+    }
+
+    pub async fn set_payment_notifier<'a>(&'a mut self, payment_notifier: PaymentNotifier<B>) {
+        let incoming_control_message = FunderIncomingControl::new(
+            Uid::from(&[38; UID_LEN]),
+            FunderControl::SetPaymentNotifier(payment_notifier.clone()),
+        );
+        await!(self.send(incoming_control_message)).unwrap();
+        loop {
+            if let NodeRecv::PaymentNotifierChanged(opt_payment_notifier) =
+                await!(self.recv()).unwrap()
+            {
+                assert_eq!(opt_payment_notifier, Some(payment_notifier));
+                return;
+            }
+        }
+    }
+
+    pub async fn set_dust_thresholds<'a>(&'a mut self, dust_thresholds: DustThresholds) {
+        let incoming_control_message = FunderIncomingControl::new(
+            Uid::from(&[39; UID_LEN]),
+            FunderControl::SetDustThresholds(dust_thresholds.clone()),
+        );
+        await!(self.send(incoming_control_message)).unwrap();
+        let pred = |report: &FunderReport<_>| report.dust_thresholds == dust_thresholds;
+        await!(self.recv_until(pred));
+    }
+
     pub async fn add_relay<'a>(&'a mut self, named_relay_address: NamedRelayAddress<B>) {
         let incoming_control_message = FunderIncomingControl::new(
             Uid::from(&[33; UID_LEN]),
@@ -329,6 +421,7 @@ where
         let set_remote_max_debt = SetFriendRemoteMaxDebt {
             friend_public_key: friend_public_key.clone(),
             remote_max_debt: remote_max_debt,
+            opt_expiry: None,
         };
         let incoming_control_message = FunderIncomingControl::new(
             Uid::from(&[36; UID_LEN]),
@@ -415,16 +508,7 @@ where
     let mut node_controls = Vec::new();
 
     for i in 0..num_nodes {
-        let rng = DummyRandom::new(&[i as u8]);
-        let pkcs8 = generate_pkcs8_key_pair(&rng);
-        let identity1 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-        let (requests_sender, identity_server) = create_identity(identity1);
-        let identity_client = IdentityClient::new(requests_sender);
-        spawner
-            .spawn(identity_server.then(|_| future::ready(())))
-            .unwrap();
-
-        let public_key = await!(identity_client.request_public_key()).unwrap();
+        let (identity_client, public_key) = spawn_fixture_identity(i as u8, &mut spawner);
         let relays = vec![dummy_named_relay_address(i as u8)];
         let funder_state = FunderState::new(public_key.clone(), relays);
         let ephemeral = Ephemeral::new();
@@ -439,7 +523,7 @@ where
         let fut_dispose_db_requests = async move {
             // Read all incoming db requests:
             while let Some(request) = await!(incoming_db_requests.next()) {
-                let _ = request.response_sender.send(());
+                let _ = request.response_sender.send(Some(()));
             }
         };
         spawner.spawn(fut_dispose_db_requests).unwrap();
@@ -450,23 +534,39 @@ where
         let (send_comm, incoming_comm) = mpsc::channel(CHANNEL_SIZE);
         let (comm_sender, recv_comm) = mpsc::channel(CHANNEL_SIZE);
 
+        // The tests here do not send timer ticks. We keep the sender alive for as long as the
+        // funder runs, to make sure the timer stream is not closed.
+        let (tick_sender, timer_stream) = mpsc::channel::<TimerTick>(0);
+
         let funder_fut = inner_funder_loop(
             identity_client.clone(),
             DummyRandom::new(&[i as u8]),
+            timer_stream,
             incoming_control,
             incoming_comm,
             control_sender,
             comm_sender,
             funder_state,
             db_client,
-            TEST_MAX_NODE_RELAYS,
+            TEST_MIN_OPERATIONS_IN_BATCH,
             TEST_MAX_OPERATIONS_IN_BATCH,
+            TEST_MAX_NODE_RELAYS,
             TEST_MAX_PENDING_USER_REQUESTS,
+            TEST_RELAYS_DAMPING_TICKS,
+            TEST_PREWARM_TICKS,
+            TEST_RELIABILITY_DECAY_TICKS,
+            TEST_DEADLINES_GRANULARITY_TICKS,
+            EphemeralLimits::default(),
+            SlowEventLimits::default(),
+            FunderEventStats::default(),
             None,
         );
 
         spawner
-            .spawn(funder_fut.then(|_| future::ready(())))
+            .spawn(funder_fut.then(move |_| {
+                drop(tick_sender);
+                future::ready(())
+            }))
             .unwrap();
 
         /*
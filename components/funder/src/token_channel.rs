@@ -3,14 +3,18 @@ use std::convert::TryFrom;
 
 use common::canonical_serialize::CanonicalSerialize;
 
+use im::hashset::HashSet as ImHashSet;
+
 use crypto::crypto_rand::{RandValue, RAND_VALUE_LEN};
 use crypto::hash::sha_512_256;
 use crypto::identity::{compare_public_key, PublicKey, Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+use crypto::uid::Uid;
 
 use proto::app_server::messages::RelayAddress;
-use proto::funder::messages::{FriendTcOp, MoveToken};
+use proto::funder::messages::{FriendTcOp, MoveToken, MoveTokenRequest, PendingRequest};
 use proto::funder::signature_buff::verify_move_token;
 
+use crate::invariants::InvariantError;
 use crate::mutual_credit::incoming::{
     process_operations_list, IncomingMessage, ProcessOperationOutput, ProcessTransListError,
 };
@@ -30,6 +34,9 @@ pub enum SetDirection<B> {
 pub enum TcMutation<B> {
     McMutation(McMutation),
     SetDirection(SetDirection<B>),
+    /// Record whether we asked for the token back in our last transmission of the outgoing move
+    /// token. Only applies to an outgoing token channel.
+    SetTokenWanted(bool),
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -37,6 +44,9 @@ pub struct TcOutgoing<B> {
     pub mutual_credit: MutualCredit,
     pub move_token_out: MoveToken<B>,
     pub opt_prev_move_token_in: Option<MoveTokenHashed>,
+    /// Did we ask the remote side to send the token back, when we last transmitted
+    /// move_token_out?
+    pub token_wanted: bool,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -67,6 +77,8 @@ pub enum ReceiveMoveTokenError {
     MoveTokenCounterOverflow,
     InvalidMoveTokenCounter,
     TooManyOperations,
+    /// Applying the move token would break the invariants of the mutual credit.
+    InvariantViolation(InvariantError),
 }
 
 #[derive(Debug)]
@@ -86,6 +98,15 @@ pub enum ReceiveMoveTokenOutput<B> {
     // In case of a reset, all the local pending requests will be canceled.
 }
 
+/// The result of receiving a whole move token request.
+#[derive(Debug)]
+pub struct ReceiveMoveTokenRequestOutput<B> {
+    pub output: ReceiveMoveTokenOutput<B>,
+    /// Does the remote side want the token back? This is set even if the incoming move token was
+    /// a duplicate, or if we should retransmit our outgoing move token.
+    pub remote_wants_token: bool,
+}
+
 /// Create a token from a public key
 /// Currently this function puts the public key in the beginning of the signature buffer,
 /// as the public key is shorter than a signature.
@@ -142,7 +163,30 @@ where
     B: Clone + CanonicalSerialize,
 {
     pub fn new(local_public_key: &PublicKey, remote_public_key: &PublicKey, balance: i128) -> Self {
-        let mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, balance);
+        TokenChannel::new_with_terms(local_public_key, remote_public_key, balance, 0, 0)
+    }
+
+    /// Create a token channel using terms both sides agreed upon in advance (For example, when
+    /// restoring a relationship from an external agreement), so that no SetRemoteMaxDebt
+    /// exchange is required before requests can be sent.
+    ///
+    /// The balance is stated in the genesis move token. If the remote side was created with a
+    /// different balance, the first move token it sends is rejected with `InvalidStatedBalance`.
+    /// Move tokens do not state max debts, so disagreeing max debts are only noticed when a
+    /// request exceeds them.
+    ///
+    /// The remote side uses the negation of the balance, hence the balance must be negatable (It
+    /// can not be i128::min_value()). Balances given by the user are checked in advance.
+    pub fn new_with_terms(
+        local_public_key: &PublicKey,
+        remote_public_key: &PublicKey,
+        balance: i128,
+        local_max_debt: u128,
+        remote_max_debt: u128,
+    ) -> Self {
+        let mut mutual_credit = MutualCredit::new(&local_public_key, &remote_public_key, balance);
+        mutual_credit.mutate(&McMutation::SetLocalMaxDebt(local_max_debt));
+        mutual_credit.mutate(&McMutation::SetRemoteMaxDebt(remote_max_debt));
 
         if compare_public_key(&local_public_key, &remote_public_key) == Ordering::Less {
             // We are the first sender
@@ -150,6 +194,7 @@ where
                 mutual_credit,
                 move_token_out: initial_move_token(local_public_key, remote_public_key, balance),
                 opt_prev_move_token_in: None,
+                token_wanted: false,
             };
             TokenChannel {
                 direction: TcDirection::Outgoing(tc_outgoing),
@@ -161,6 +206,7 @@ where
                 move_token_in: create_hashed::<B>(&initial_move_token(
                     remote_public_key,
                     local_public_key,
+                    // The balance is negatable, see the documentation above:
                     balance.checked_neg().unwrap(),
                 )),
             };
@@ -199,6 +245,7 @@ where
             mutual_credit: MutualCredit::new(local_public_key, remote_public_key, balance),
             move_token_out: reset_move_token.clone(),
             opt_prev_move_token_in: opt_last_incoming_move_token,
+            token_wanted: false,
         };
         TokenChannel {
             direction: TcDirection::Outgoing(tc_outgoing),
@@ -213,10 +260,32 @@ where
         }
     }
 
+    /// Verify the invariants of the mutual credit of this token channel.
+    pub fn validate_invariants(&self) -> Result<(), InvariantError> {
+        self.get_mutual_credit().validate_invariants()
+    }
+
     pub fn get_remote_max_debt(&self) -> u128 {
         self.get_mutual_credit().state().balance.remote_max_debt
     }
 
+    /// Get the local pending requests that are cancelled if this token channel is reset.
+    /// A response will never be received for those requests, so the caller should report them as
+    /// failures. Remote pending requests are simply dropped: it is up to the remote side to cancel
+    /// them.
+    pub fn get_reset_cancelled_requests(&self) -> Vec<PendingRequest> {
+        let mut cancelled_requests: Vec<_> = self
+            .get_mutual_credit()
+            .state()
+            .pending_requests
+            .pending_local_requests
+            .values()
+            .cloned()
+            .collect();
+        cancelled_requests.sort_by(|a, b| a.request_id.cmp(&b.request_id));
+        cancelled_requests
+    }
+
     pub fn get_direction(&self) -> &TcDirection<B> {
         &self.direction
     }
@@ -242,6 +311,7 @@ where
                     TcDirection::Outgoing(tc_outgoing) => &mut tc_outgoing.mutual_credit,
                 };
                 mutual_credit.mutate(mc_mutation);
+                debug_assert_eq!(self.validate_invariants(), Ok(()));
             }
             TcMutation::SetDirection(ref set_direction) => {
                 self.direction = match set_direction {
@@ -259,11 +329,18 @@ where
                             opt_prev_move_token_in: self
                                 .get_last_incoming_move_token_hashed()
                                 .cloned(),
+                            token_wanted: false,
                         };
                         TcDirection::Outgoing(tc_outgoing)
                     }
                 };
             }
+            TcMutation::SetTokenWanted(token_wanted) => {
+                // An incoming token channel has no outgoing move token to transmit:
+                if let TcDirection::Outgoing(tc_outgoing) = &mut self.direction {
+                    tc_outgoing.token_wanted = *token_wanted;
+                }
+            }
         }
     }
 
@@ -288,15 +365,38 @@ where
         }
     }
 
+    /// `completed_request_ids` contains the ids of our requests that were recently completed.
+    /// Responses and failures for those requests are ignored.
     pub fn simulate_receive_move_token(
         &self,
         new_move_token: MoveToken<B>,
+        completed_request_ids: &ImHashSet<Uid>,
     ) -> Result<ReceiveMoveTokenOutput<B>, ReceiveMoveTokenError> {
         match &self.direction {
             TcDirection::Incoming(tc_incoming) => tc_incoming.handle_incoming(new_move_token),
-            TcDirection::Outgoing(tc_outgoing) => tc_outgoing.handle_incoming(new_move_token),
+            TcDirection::Outgoing(tc_outgoing) => {
+                tc_outgoing.handle_incoming(new_move_token, completed_request_ids)
+            }
         }
     }
+
+    /// Simulate receiving a move token request. Unlike `simulate_receive_move_token`, the output
+    /// also tells whether the remote side wants the token back.
+    pub fn simulate_receive_move_token_request(
+        &self,
+        move_token_request: MoveTokenRequest<B>,
+        completed_request_ids: &ImHashSet<Uid>,
+    ) -> Result<ReceiveMoveTokenRequestOutput<B>, ReceiveMoveTokenError> {
+        let MoveTokenRequest {
+            friend_move_token,
+            token_wanted,
+        } = move_token_request;
+        let output = self.simulate_receive_move_token(friend_move_token, completed_request_ids)?;
+        Ok(ReceiveMoveTokenRequestOutput {
+            output,
+            remote_wants_token: token_wanted,
+        })
+    }
 }
 
 impl TcIncoming {
@@ -354,6 +454,7 @@ where
     fn handle_incoming(
         &self,
         new_move_token: MoveToken<B>,
+        completed_request_ids: &ImHashSet<Uid>,
     ) -> Result<ReceiveMoveTokenOutput<B>, ReceiveMoveTokenError> {
         // Make sure that the stated remote public key and local public key match:
         if !((self.mutual_credit.state().idents.local_public_key
@@ -365,7 +466,7 @@ where
         }
 
         if new_move_token.old_token == self.move_token_out.new_token {
-            self.handle_incoming_token_match(new_move_token)
+            self.handle_incoming_token_match(new_move_token, completed_request_ids)
         // self.outgoing_to_incoming(friend_move_token, new_move_token)
         } else if self.move_token_out.old_token == new_move_token.new_token {
             // We should retransmit our move token message to the remote side.
@@ -380,6 +481,7 @@ where
     fn handle_incoming_token_match(
         &self,
         new_move_token: MoveToken<B>,
+        completed_request_ids: &ImHashSet<Uid>,
     ) -> Result<ReceiveMoveTokenOutput<B>, ReceiveMoveTokenError> {
         // Verify signature:
         // Note that we only verify the signature here, and not at the Incoming part.
@@ -405,64 +507,82 @@ where
             return Err(ReceiveMoveTokenError::InvalidMoveTokenCounter);
         }
 
-        let mut mutual_credit = self.mutual_credit.clone();
-        let res = process_operations_list(&mut mutual_credit, new_move_token.operations.clone());
+        // We hash the move token before taking its operations, so that the operations (possibly
+        // containing long routes) are never cloned:
+        let move_token_hashed = create_hashed(&new_move_token);
+        let MoveToken {
+            operations,
+            opt_local_relays,
+            balance,
+            local_pending_debt,
+            remote_pending_debt,
+            ..
+        } = new_move_token;
 
-        match res {
-            Ok(outputs) => {
-                let initial_remote_requests =
-                    self.mutual_credit.state().requests_status.remote.is_open();
+        let initial_remote_requests = self.mutual_credit.state().requests_status.remote.is_open();
 
-                let mut incoming_messages = Vec::new();
-                let mut mutations = Vec::new();
+        let mut incoming_messages = Vec::new();
+        let mut mutations = Vec::new();
 
-                // We apply mutations on this token channel, to verify stated balance values
-                let mut check_mutual_credit = self.mutual_credit.clone();
+        // We apply mutations on this token channel, to verify stated balance values
+        let mut check_mutual_credit = self.mutual_credit.clone();
 
-                let mut final_remote_requests: bool = initial_remote_requests;
-                for output in outputs {
-                    let ProcessOperationOutput {
-                        incoming_message,
-                        mc_mutations,
-                    } = output;
+        let mut final_remote_requests: bool = initial_remote_requests;
 
-                    if let Some(funds) = incoming_message {
-                        incoming_messages.push(funds);
-                    }
-                    for mc_mutation in mc_mutations {
-                        check_mutual_credit.mutate(&mc_mutation);
-                        if let McMutation::SetRemoteRequestsStatus(requests_status) = &mc_mutation {
-                            final_remote_requests = requests_status.is_open();
-                        }
-                        mutations.push(TcMutation::McMutation(mc_mutation));
+        // The outputs are consumed while the operations are processed. Nothing is applied to this
+        // token channel before all the operations were found valid:
+        let mut mutual_credit = self.mutual_credit.clone();
+        process_operations_list(
+            &mut mutual_credit,
+            operations,
+            completed_request_ids,
+            |output| {
+                let ProcessOperationOutput {
+                    incoming_message,
+                    mc_mutations,
+                } = output;
+
+                if let Some(funds) = incoming_message {
+                    incoming_messages.push(funds);
+                }
+                for mc_mutation in mc_mutations {
+                    check_mutual_credit.mutate(&mc_mutation);
+                    if let McMutation::SetRemoteRequestsStatus(requests_status) = &mc_mutation {
+                        final_remote_requests = requests_status.is_open();
                     }
+                    mutations.push(TcMutation::McMutation(mc_mutation));
                 }
+            },
+        )
+        .map_err(ReceiveMoveTokenError::InvalidTransaction)?;
+
+        // Verify stated balances. The stated balance is chosen by the remote side, and might not
+        // be negatable:
+        let check_balance = &check_mutual_credit.state().balance;
+        if Some(check_balance.balance) != balance.checked_neg()
+            || check_balance.local_pending_debt != remote_pending_debt
+            || check_balance.remote_pending_debt != local_pending_debt
+        {
+            return Err(ReceiveMoveTokenError::InvalidStatedBalance);
+        }
 
-                // Verify stated balances:
-                let check_balance = &check_mutual_credit.state().balance;
-                if check_balance.balance != -new_move_token.balance
-                    || check_balance.local_pending_debt != new_move_token.remote_pending_debt
-                    || check_balance.remote_pending_debt != new_move_token.local_pending_debt
-                {
-                    return Err(ReceiveMoveTokenError::InvalidStatedBalance);
-                }
+        check_mutual_credit
+            .validate_invariants()
+            .map_err(ReceiveMoveTokenError::InvariantViolation)?;
 
-                mutations.push(TcMutation::SetDirection(SetDirection::Incoming(
-                    create_hashed(&new_move_token),
-                )));
+        mutations.push(TcMutation::SetDirection(SetDirection::Incoming(
+            move_token_hashed,
+        )));
 
-                let move_token_received = MoveTokenReceived {
-                    incoming_messages,
-                    mutations,
-                    // Were the remote requests initially open and now it is closed?
-                    remote_requests_closed: final_remote_requests && !initial_remote_requests,
-                    opt_local_relays: new_move_token.opt_local_relays.clone(),
-                };
+        let move_token_received = MoveTokenReceived {
+            incoming_messages,
+            mutations,
+            // Were the remote requests initially open and now it is closed?
+            remote_requests_closed: final_remote_requests && !initial_remote_requests,
+            opt_local_relays,
+        };
 
-                Ok(ReceiveMoveTokenOutput::Received(move_token_received))
-            }
-            Err(e) => Err(ReceiveMoveTokenError::InvalidTransaction(e)),
-        }
+        Ok(ReceiveMoveTokenOutput::Received(move_token_received))
     }
 
     /// Get the current outgoing move token
@@ -476,10 +596,24 @@ mod tests {
     use super::*;
 
     use crypto::identity::Identity;
-    use crypto::identity::{generate_pkcs8_key_pair, SoftwareEd25519Identity};
-    use crypto::test_utils::DummyRandom;
+    use crypto::test_utils::fixture_keypairs;
+
+    use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+    use crypto::uid::UID_LEN;
+
+    use crate::mutual_credit::types::MAX_FUNDER_DEBT;
 
-    use proto::funder::signature_buff::move_token_signature_buff;
+    use proto::consts::{MAX_OPERATIONS_IN_BATCH, MAX_ROUTE_LEN};
+    use proto::funder::messages::{
+        FriendsRoute, RequestSendFunds, RequestsStatus, ResponseSendFunds,
+    };
+    use proto::funder::signature_buff::{
+        create_response_signature_buffer, move_token_signature_buff,
+    };
+
+    use crate::types::create_pending_request;
+
+    use proptest::prelude::*;
 
     /// A helper function to sign an UnsignedMoveToken using an identity:
     fn dummy_sign_move_token<B, I>(
@@ -502,7 +636,7 @@ mod tests {
             move_token_counter: unsigned_move_token.move_token_counter,
             balance: unsigned_move_token.balance,
             local_pending_debt: unsigned_move_token.local_pending_debt,
-            remote_pending_debt: unsigned_move_token.local_pending_debt,
+            remote_pending_debt: unsigned_move_token.remote_pending_debt,
             rand_nonce: unsigned_move_token.rand_nonce,
             new_token: identity.sign(&signature_buff),
         }
@@ -547,23 +681,6 @@ mod tests {
         assert!(tc_outgoing.opt_prev_move_token_in.is_none());
     }
 
-    /// Sort the two identity client.
-    /// The result will be a pair where the first is initially configured to have outgoing message,
-    /// and the second is initially configured to have incoming message.
-    fn sort_sides<I>(identity1: I, identity2: I) -> (I, I)
-    where
-        I: Identity,
-    {
-        let pk1 = identity1.get_public_key();
-        let pk2 = identity2.get_public_key();
-        let token_channel12 = TokenChannel::<u32>::new(&pk1, &pk2, 0i128); // (local, remote)
-        if token_channel12.is_outgoing() {
-            (identity1, identity2)
-        } else {
-            (identity2, identity1)
-        }
-    }
-
     /// Before: tc1: outgoing, tc2: incoming
     /// Send SetRemoteMaxDebt: tc2 -> tc1
     /// After: tc1: incoming, tc2: outgoing
@@ -604,7 +721,7 @@ mod tests {
         assert!(tc2.is_outgoing());
 
         let receive_move_token_output = tc1
-            .simulate_receive_move_token(friend_move_token.clone())
+            .simulate_receive_move_token(friend_move_token.clone(), &ImHashSet::new())
             .unwrap();
 
         let move_token_received = match receive_move_token_output {
@@ -634,6 +751,7 @@ mod tests {
                         _ => unreachable!(),
                     }
                 }
+                TcMutation::SetTokenWanted(_) => unreachable!(),
             }
         }
         assert!(seen_mc_mutation && seen_set_direction);
@@ -656,15 +774,11 @@ mod tests {
     /// This tests sends a SetRemoteMaxDebt(100) in both ways.
     #[test]
     fn test_simulate_receive_move_token_basic() {
-        let rng1 = DummyRandom::new(&[1u8]);
-        let pkcs8 = generate_pkcs8_key_pair(&rng1);
-        let identity1 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-
-        let rng2 = DummyRandom::new(&[2u8]);
-        let pkcs8 = generate_pkcs8_key_pair(&rng2);
-        let identity2 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-
-        let (identity1, identity2) = sort_sides(identity1, identity2);
+        // identity1 is initially configured to have outgoing message,
+        // identity2 is initially configured to have incoming message:
+        let mut identities = fixture_keypairs(2);
+        let identity2 = identities.pop().unwrap();
+        let identity1 = identities.pop().unwrap();
 
         let pk1 = identity1.get_public_key();
         let pk2 = identity2.get_public_key();
@@ -682,6 +796,1057 @@ mod tests {
         set_remote_max_debt21(&identity2, &identity1, &mut tc2, &mut tc1);
     }
 
+    /// Create both sides of a token channel using the given terms (balance1 is the balance of
+    /// tc1, balance2 is the balance of tc2). tc2 then sends its first move token, containing a
+    /// request that is only allowed by the agreed max debts.
+    fn receive_first_move_token_with_terms(
+        balance1: i128,
+        balance2: i128,
+    ) -> Result<ReceiveMoveTokenOutput<u32>, ReceiveMoveTokenError> {
+        let mut identities = fixture_keypairs(2);
+        let identity2 = identities.pop().unwrap();
+        let identity1 = identities.pop().unwrap();
+
+        let pk1 = identity1.get_public_key();
+        let pk2 = identity2.get_public_key();
+        // tc2 may owe tc1 up to 100 credits, tc1 may owe tc2 up to 50 credits:
+        let mut tc1 = TokenChannel::<u32>::new_with_terms(&pk1, &pk2, balance1, 50, 100);
+        let mut tc2 = TokenChannel::<u32>::new_with_terms(&pk2, &pk1, balance2, 100, 50);
+        assert!(tc1.is_outgoing());
+        assert_eq!(tc1.get_mutual_credit().state().balance.remote_max_debt, 100);
+        assert_eq!(tc2.get_mutual_credit().state().balance.local_max_debt, 100);
+
+        let mc_mutation = McMutation::SetLocalRequestsStatus(RequestsStatus::Open);
+        tc1.mutate(&TcMutation::McMutation(mc_mutation));
+        let mc_mutation = McMutation::SetRemoteRequestsStatus(RequestsStatus::Open);
+        tc2.mutate(&TcMutation::McMutation(mc_mutation));
+
+        let request_send_funds = RequestSendFunds {
+            request_id: Uid::from(&[3; UID_LEN]),
+            route: FriendsRoute {
+                public_keys: vec![pk2.clone(), pk1.clone()],
+            },
+            dest_payment: 10,
+            invoice_id: InvoiceId::from(&[4; INVOICE_ID_LEN]),
+        };
+        let operation = FriendTcOp::RequestSendFunds(request_send_funds);
+        let mc_mutations = match tc2.get_direction() {
+            TcDirection::Incoming(tc2_incoming) => {
+                let mut outgoing_mc = tc2_incoming.begin_outgoing_move_token();
+                outgoing_mc.queue_operation(&operation).unwrap()
+            }
+            TcDirection::Outgoing(_) => unreachable!(),
+        };
+        for mc_mutation in mc_mutations {
+            tc2.mutate(&TcMutation::McMutation(mc_mutation));
+        }
+
+        let tc2_incoming = match tc2.get_direction() {
+            TcDirection::Incoming(tc2_incoming) => tc2_incoming,
+            TcDirection::Outgoing(_) => unreachable!(),
+        };
+        let rand_nonce = RandValue::from(&[5; RAND_VALUE_LEN]);
+        let unsigned_move_token =
+            tc2_incoming.create_unsigned_move_token(vec![operation], None, rand_nonce);
+        let friend_move_token = dummy_sign_move_token(unsigned_move_token, &identity2);
+
+        tc1.simulate_receive_move_token(friend_move_token, &ImHashSet::new())
+    }
+
+    #[test]
+    fn test_new_with_terms_matching() {
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let tc_a_b = TokenChannel::<u32>::new_with_terms(&pk_a, &pk_b, 20, 50, 100);
+        let tc_b_a = TokenChannel::<u32>::new_with_terms(&pk_b, &pk_a, -20, 100, 50);
+
+        // Both sides agree on the genesis move token, which states the agreed balance:
+        let move_token_out = match tc_a_b.get_direction() {
+            TcDirection::Outgoing(tc_outgoing) => &tc_outgoing.move_token_out,
+            TcDirection::Incoming(_) => unreachable!(),
+        };
+        assert_eq!(move_token_out.balance, 20);
+        match tc_b_a.get_direction() {
+            TcDirection::Incoming(tc_incoming) => {
+                assert_eq!(create_hashed(move_token_out), tc_incoming.move_token_in)
+            }
+            TcDirection::Outgoing(_) => unreachable!(),
+        };
+
+        let receive_move_token_output = receive_first_move_token_with_terms(20, -20).unwrap();
+        let move_token_received = match receive_move_token_output {
+            ReceiveMoveTokenOutput::Received(move_token_received) => move_token_received,
+            _ => unreachable!(),
+        };
+        assert_eq!(move_token_received.incoming_messages.len(), 1);
+    }
+
+    #[test]
+    fn test_new_with_terms_mismatching() {
+        match receive_first_move_token_with_terms(20, -30) {
+            Err(ReceiveMoveTokenError::InvalidStatedBalance) => {}
+            _ => unreachable!(),
+        };
+    }
+
+    /// A response to a request that was already completed (For example, before a reset) is
+    /// ignored only if the request id is known to be completed.
+    #[test]
+    fn test_simulate_receive_move_token_completed_response() {
+        let mut identities = fixture_keypairs(2);
+        let identity2 = identities.pop().unwrap();
+        let identity1 = identities.pop().unwrap();
+
+        let pk1 = identity1.get_public_key();
+        let pk2 = identity2.get_public_key();
+        let tc1 = TokenChannel::<u32>::new(&pk1, &pk2, 0i128); // (local, remote)
+        let tc2 = TokenChannel::<u32>::new(&pk2, &pk1, 0i128); // (local, remote)
+        assert!(tc1.is_outgoing());
+
+        let tc2_incoming = match tc2.get_direction() {
+            TcDirection::Incoming(tc2_incoming) => tc2_incoming,
+            TcDirection::Outgoing(_) => unreachable!(),
+        };
+
+        // tc2 sends again a response to an old request of tc1:
+        let request_id = Uid::from(&[3; UID_LEN]);
+        let response_send_funds = ResponseSendFunds {
+            request_id,
+            rand_nonce: RandValue::from(&[6; RAND_VALUE_LEN]),
+            signature: Signature::from(&[7; SIGNATURE_LEN]),
+        };
+        let operations = vec![FriendTcOp::ResponseSendFunds(response_send_funds)];
+        let rand_nonce = RandValue::from(&[5; RAND_VALUE_LEN]);
+        let unsigned_move_token =
+            tc2_incoming.create_unsigned_move_token(operations, None, rand_nonce);
+        let friend_move_token = dummy_sign_move_token(unsigned_move_token, &identity2);
+
+        // The request is known to be completed. The response is ignored:
+        let mut completed_request_ids = ImHashSet::new();
+        completed_request_ids.insert(request_id);
+        let receive_move_token_output = tc1
+            .simulate_receive_move_token(friend_move_token.clone(), &completed_request_ids)
+            .unwrap();
+        let move_token_received = match receive_move_token_output {
+            ReceiveMoveTokenOutput::Received(move_token_received) => move_token_received,
+            _ => unreachable!(),
+        };
+        assert!(move_token_received.incoming_messages.is_empty());
+        assert_eq!(move_token_received.mutations.len(), 1);
+        match &move_token_received.mutations[0] {
+            TcMutation::SetDirection(SetDirection::Incoming(_)) => {}
+            _ => unreachable!(),
+        };
+
+        // The request is unknown. This is an inconsistency:
+        let mut completed_request_ids = ImHashSet::new();
+        completed_request_ids.insert(Uid::from(&[4; UID_LEN]));
+        match tc1.simulate_receive_move_token(friend_move_token, &completed_request_ids) {
+            Err(ReceiveMoveTokenError::InvalidTransaction(_)) => {}
+            _ => unreachable!(),
+        };
+    }
+
+    /// Queue operations on the incoming side `tc_in`, as done before sending a move token.
+    fn queue_operations(tc_in: &mut TokenChannel<u32>, operations: &[FriendTcOp]) {
+        let mc_mutations = match tc_in.get_direction() {
+            TcDirection::Incoming(tc_incoming) => {
+                let mut outgoing_mc = tc_incoming.begin_outgoing_move_token();
+                let mut mc_mutations = Vec::new();
+                for operation in operations {
+                    mc_mutations.extend(outgoing_mc.queue_operation(operation).unwrap());
+                }
+                mc_mutations
+            }
+            TcDirection::Outgoing(_) => unreachable!(),
+        };
+        for mc_mutation in mc_mutations {
+            tc_in.mutate(&TcMutation::McMutation(mc_mutation));
+        }
+    }
+
+    /// The incoming side `tc_in` sends a move token with the given operations, and the outgoing
+    /// side `tc_out` receives it. The operations are not applied to `tc_in` here, see
+    /// `queue_operations()`.
+    fn send_move_token<I: Identity>(
+        identity_in: &I,
+        tc_in: &mut TokenChannel<u32>,
+        tc_out: &mut TokenChannel<u32>,
+        operations: Vec<FriendTcOp>,
+        completed_request_ids: &ImHashSet<Uid>,
+    ) -> Result<MoveTokenReceived<u32>, ReceiveMoveTokenError> {
+        let unsigned_move_token = match tc_in.get_direction() {
+            TcDirection::Incoming(tc_incoming) => tc_incoming.create_unsigned_move_token(
+                operations,
+                None,
+                RandValue::from(&[5; RAND_VALUE_LEN]),
+            ),
+            TcDirection::Outgoing(_) => unreachable!(),
+        };
+        let move_token = dummy_sign_move_token(unsigned_move_token, identity_in);
+
+        let output =
+            tc_out.simulate_receive_move_token(move_token.clone(), completed_request_ids)?;
+        let move_token_received = match output {
+            ReceiveMoveTokenOutput::Received(move_token_received) => move_token_received,
+            _ => unreachable!(),
+        };
+        tc_in.mutate(&TcMutation::SetDirection(SetDirection::Outgoing(
+            move_token,
+        )));
+        for tc_mutation in &move_token_received.mutations {
+            tc_out.mutate(tc_mutation);
+        }
+        Ok(move_token_received)
+    }
+
+    /// Settle a payment, reset the channel and then let the remote side send again the response
+    /// it has already applied before the reset. The response is ignored, the balances do not
+    /// change and the channel stays consistent.
+    #[test]
+    fn test_completed_response_replay_after_reset() {
+        let mut identities = fixture_keypairs(2);
+        let identity2 = identities.pop().unwrap();
+        let identity1 = identities.pop().unwrap();
+
+        let pk1 = identity1.get_public_key();
+        let pk2 = identity2.get_public_key();
+        // tc1 may owe tc2 up to 100 credits:
+        let mut tc1 = TokenChannel::<u32>::new_with_terms(&pk1, &pk2, 0, 100, 0);
+        let mut tc2 = TokenChannel::<u32>::new_with_terms(&pk2, &pk1, 0, 0, 100);
+        assert!(tc1.is_outgoing());
+
+        let mc_mutation = McMutation::SetRemoteRequestsStatus(RequestsStatus::Open);
+        tc1.mutate(&TcMutation::McMutation(mc_mutation));
+        let mc_mutation = McMutation::SetLocalRequestsStatus(RequestsStatus::Open);
+        tc2.mutate(&TcMutation::McMutation(mc_mutation));
+
+        let no_completed = ImHashSet::new();
+
+        // Pass the token to tc1:
+        send_move_token(&identity2, &mut tc2, &mut tc1, Vec::new(), &no_completed).unwrap();
+
+        // tc1 sends a request to tc2:
+        let request_send_funds = RequestSendFunds {
+            request_id: Uid::from(&[3; UID_LEN]),
+            route: FriendsRoute {
+                public_keys: vec![pk1.clone(), pk2.clone()],
+            },
+            dest_payment: 10,
+            invoice_id: InvoiceId::from(&[4; INVOICE_ID_LEN]),
+        };
+        let pending_request = create_pending_request(&request_send_funds);
+        let operations = vec![FriendTcOp::RequestSendFunds(request_send_funds)];
+        queue_operations(&mut tc1, &operations);
+        let move_token_received =
+            send_move_token(&identity1, &mut tc1, &mut tc2, operations, &no_completed).unwrap();
+        assert_eq!(move_token_received.incoming_messages.len(), 1);
+
+        // tc2 (The destination) responds, and tc1 settles the payment:
+        let mut response_send_funds = ResponseSendFunds {
+            request_id: pending_request.request_id,
+            rand_nonce: RandValue::from(&[6; RAND_VALUE_LEN]),
+            signature: Signature::from(&[0; SIGNATURE_LEN]),
+        };
+        let sign_buffer = create_response_signature_buffer(&response_send_funds, &pending_request);
+        response_send_funds.signature = identity2.sign(&sign_buffer);
+        let response_op = FriendTcOp::ResponseSendFunds(response_send_funds);
+        queue_operations(&mut tc2, &[response_op.clone()]);
+        let move_token_received = send_move_token(
+            &identity2,
+            &mut tc2,
+            &mut tc1,
+            vec![response_op.clone()],
+            &no_completed,
+        )
+        .unwrap();
+        match &move_token_received.incoming_messages[..] {
+            [IncomingMessage::Response(_)] => {}
+            _ => unreachable!(),
+        };
+        assert_eq!(tc1.get_mutual_credit().state().balance.balance, -10);
+        assert_eq!(tc2.get_mutual_credit().state().balance.balance, 10);
+
+        // tc1 remembers the completed request:
+        let mut completed_request_ids = ImHashSet::new();
+        completed_request_ids.insert(pending_request.request_id);
+
+        // tc1 resets the channel, using the reset terms of tc2:
+        let balance_for_reset = tc2.get_mutual_credit().balance_for_reset().unwrap();
+        let u_reset_move_token = create_unsigned_move_token(
+            Vec::new(),
+            None,
+            Signature::from(&[8; SIGNATURE_LEN]),
+            pk1.clone(),
+            pk2.clone(),
+            tc2.get_inconsistency_counter().wrapping_add(1),
+            0,
+            balance_for_reset.checked_neg().unwrap(),
+            0,
+            0,
+            RandValue::from(&[9; RAND_VALUE_LEN]),
+        );
+        let reset_move_token = dummy_sign_move_token(u_reset_move_token, &identity1);
+        let opt_last_incoming = tc1.get_last_incoming_move_token_hashed().cloned();
+        let mut tc1 = TokenChannel::new_from_local_reset(
+            &pk1,
+            &pk2,
+            &reset_move_token,
+            balance_for_reset.checked_neg().unwrap(),
+            opt_last_incoming,
+        );
+        let mut tc2 =
+            TokenChannel::new_from_remote_reset(&pk2, &pk1, &reset_move_token, balance_for_reset);
+
+        // A response for a request tc1 has never sent is still rejected:
+        let mut fabricated_response = match &response_op {
+            FriendTcOp::ResponseSendFunds(response_send_funds) => response_send_funds.clone(),
+            _ => unreachable!(),
+        };
+        fabricated_response.request_id = Uid::from(&[4; UID_LEN]);
+        let operations = vec![FriendTcOp::ResponseSendFunds(fabricated_response)];
+        match send_move_token(
+            &identity2,
+            &mut tc2.clone(),
+            &mut tc1.clone(),
+            operations,
+            &completed_request_ids,
+        ) {
+            Err(ReceiveMoveTokenError::InvalidTransaction(_)) => {}
+            _ => unreachable!(),
+        };
+
+        // tc2 sends again the response it has already applied before the reset. Its stated
+        // balance already contains the payment, and tc1 ignores the response:
+        let move_token_received = send_move_token(
+            &identity2,
+            &mut tc2,
+            &mut tc1,
+            vec![response_op],
+            &completed_request_ids,
+        )
+        .unwrap();
+        assert!(move_token_received.incoming_messages.is_empty());
+        for tc in &[&tc1, &tc2] {
+            let balance = &tc.get_mutual_credit().state().balance;
+            assert_eq!(balance.local_pending_debt, 0);
+            assert_eq!(balance.remote_pending_debt, 0);
+        }
+        assert_eq!(tc1.get_mutual_credit().state().balance.balance, -10);
+        assert_eq!(tc2.get_mutual_credit().state().balance.balance, 10);
+
+        // The channel is still consistent. tc1 can send a move token:
+        send_move_token(&identity1, &mut tc1, &mut tc2, Vec::new(), &no_completed).unwrap();
+        assert!(tc1.is_outgoing());
+        assert!(!tc2.is_outgoing());
+    }
+
+    /// A stated balance that can not be negated is rejected (And does not cause an overflow).
+    #[test]
+    fn test_simulate_receive_move_token_min_balance() {
+        let mut identities = fixture_keypairs(2);
+        let identity2 = identities.pop().unwrap();
+        let identity1 = identities.pop().unwrap();
+
+        let pk1 = identity1.get_public_key();
+        let pk2 = identity2.get_public_key();
+        let tc1 = TokenChannel::<u32>::new(&pk1, &pk2, 0i128); // (local, remote)
+        let tc2 = TokenChannel::<u32>::new(&pk2, &pk1, 0i128); // (local, remote)
+        assert!(tc1.is_outgoing());
+
+        let tc2_incoming = match tc2.get_direction() {
+            TcDirection::Incoming(tc2_incoming) => tc2_incoming,
+            TcDirection::Outgoing(_) => unreachable!(),
+        };
+        let rand_nonce = RandValue::from(&[5; RAND_VALUE_LEN]);
+        let mut unsigned_move_token =
+            tc2_incoming.create_unsigned_move_token(Vec::new(), None, rand_nonce);
+        unsigned_move_token.balance = i128::min_value();
+        let friend_move_token = dummy_sign_move_token(unsigned_move_token, &identity2);
+
+        match tc1.simulate_receive_move_token(friend_move_token, &ImHashSet::new()) {
+            Err(ReceiveMoveTokenError::InvalidStatedBalance) => {}
+            _ => unreachable!(),
+        };
+    }
+
+    fn dummy_pending_request(i: u8) -> PendingRequest {
+        PendingRequest {
+            request_id: Uid::from(&[i; UID_LEN]),
+            route: FriendsRoute {
+                public_keys: vec![
+                    PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                    PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                ],
+            },
+            dest_payment: 10,
+            invoice_id: InvoiceId::from(&[i; INVOICE_ID_LEN]),
+        }
+    }
+
+    #[test]
+    fn test_get_reset_cancelled_requests() {
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let mut token_channel = TokenChannel::<u32>::new(&pk_a, &pk_b, 0i128);
+        assert!(token_channel.get_reset_cancelled_requests().is_empty());
+
+        for i in &[3u8, 1, 2] {
+            let mc_mutation = McMutation::InsertLocalPendingRequest(dummy_pending_request(*i));
+            token_channel.mutate(&TcMutation::McMutation(mc_mutation));
+        }
+        for i in &[4u8, 5] {
+            let mc_mutation = McMutation::InsertRemotePendingRequest(dummy_pending_request(*i));
+            token_channel.mutate(&TcMutation::McMutation(mc_mutation));
+        }
+
+        // Only the local pending requests are cancelled:
+        assert_eq!(
+            token_channel.get_reset_cancelled_requests(),
+            vec![
+                dummy_pending_request(1),
+                dummy_pending_request(2),
+                dummy_pending_request(3)
+            ]
+        );
+
+        // The reset channel has no pending requests:
+        let reset_move_token = match token_channel.get_direction() {
+            TcDirection::Outgoing(tc_outgoing) => tc_outgoing.move_token_out.clone(),
+            TcDirection::Incoming(_) => unreachable!(),
+        };
+        let new_token_channel =
+            TokenChannel::new_from_local_reset(&pk_a, &pk_b, &reset_move_token, 0i128, None);
+        assert!(new_token_channel.get_reset_cancelled_requests().is_empty());
+    }
+
+    #[test]
+    fn test_simulate_receive_move_token_request_token_wanted() {
+        let mut identities = fixture_keypairs(2);
+        let identity2 = identities.pop().unwrap();
+        let identity1 = identities.pop().unwrap();
+
+        let pk1 = identity1.get_public_key();
+        let pk2 = identity2.get_public_key();
+        let mut tc1 = TokenChannel::<u32>::new(&pk1, &pk2, 0i128); // (local, remote)
+        let tc2 = TokenChannel::<u32>::new(&pk2, &pk1, 0i128); // (local, remote)
+        assert!(tc1.is_outgoing());
+
+        let tc2_incoming = match tc2.get_direction() {
+            TcDirection::Incoming(tc2_incoming) => tc2_incoming,
+            TcDirection::Outgoing(_) => unreachable!(),
+        };
+        let rand_nonce = RandValue::from(&[5; RAND_VALUE_LEN]);
+        let unsigned_move_token =
+            tc2_incoming.create_unsigned_move_token(Vec::new(), None, rand_nonce);
+        let friend_move_token = dummy_sign_move_token(unsigned_move_token, &identity2);
+        let move_token_request = MoveTokenRequest {
+            friend_move_token,
+            token_wanted: true,
+        };
+
+        // A new move token, where the remote side wants the token back:
+        let receive_output = tc1
+            .simulate_receive_move_token_request(move_token_request.clone(), &ImHashSet::new())
+            .unwrap();
+        assert!(receive_output.remote_wants_token);
+        let move_token_received = match receive_output.output {
+            ReceiveMoveTokenOutput::Received(move_token_received) => move_token_received,
+            _ => unreachable!(),
+        };
+        for tc_mutation in &move_token_received.mutations {
+            tc1.mutate(tc_mutation);
+        }
+        assert!(!tc1.is_outgoing());
+
+        // The remote side sends the same move token again, and still wants the token back:
+        let receive_output = tc1
+            .simulate_receive_move_token_request(move_token_request.clone(), &ImHashSet::new())
+            .unwrap();
+        assert!(receive_output.remote_wants_token);
+        match receive_output.output {
+            ReceiveMoveTokenOutput::Duplicate => {}
+            _ => unreachable!(),
+        };
+
+        // The flag is not set if the remote side does not want the token:
+        let move_token_request = MoveTokenRequest {
+            token_wanted: false,
+            ..move_token_request
+        };
+        let receive_output = tc1
+            .simulate_receive_move_token_request(move_token_request, &ImHashSet::new())
+            .unwrap();
+        assert!(!receive_output.remote_wants_token);
+        match receive_output.output {
+            ReceiveMoveTokenOutput::Duplicate => {}
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    #[should_panic]
+    #[cfg(debug_assertions)]
+    fn test_mutate_invariant_violation() {
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let mut token_channel = TokenChannel::<u32>::new(&pk_a, &pk_b, 0i128);
+
+        let mc_mutation = McMutation::SetLocalPendingDebt(MAX_FUNDER_DEBT + 1);
+        token_channel.mutate(&TcMutation::McMutation(mc_mutation));
+    }
+
+    #[test]
+    fn test_simulate_receive_move_token_invariant_violation() {
+        let mut identities = fixture_keypairs(2);
+        let identity2 = identities.pop().unwrap();
+        let identity1 = identities.pop().unwrap();
+
+        let pk1 = identity1.get_public_key();
+        let pk2 = identity2.get_public_key();
+        let mut tc1 = TokenChannel::<u32>::new(&pk1, &pk2, 0i128);
+        let tc2 = TokenChannel::<u32>::new(&pk2, &pk1, 0i128);
+
+        // Put tc1 into a state that token channel mutations do not allow:
+        let pending_debt = MAX_FUNDER_DEBT + 1;
+        match &mut tc1.direction {
+            TcDirection::Outgoing(tc_outgoing) => {
+                tc_outgoing
+                    .mutual_credit
+                    .mutate(&McMutation::SetLocalPendingDebt(pending_debt));
+                tc_outgoing
+                    .mutual_credit
+                    .mutate(&McMutation::SetRemotePendingDebt(pending_debt));
+            }
+            TcDirection::Incoming(_) => unreachable!(),
+        };
+
+        // The remote side sends an empty move token that agrees with the pending debts of tc1:
+        let tc2_incoming = match tc2.get_direction() {
+            TcDirection::Incoming(tc2_incoming) => tc2_incoming,
+            TcDirection::Outgoing(_) => unreachable!(),
+        };
+        let rand_nonce = RandValue::from(&[5; RAND_VALUE_LEN]);
+        let mut unsigned_move_token =
+            tc2_incoming.create_unsigned_move_token(Vec::new(), None, rand_nonce);
+        unsigned_move_token.local_pending_debt = pending_debt;
+        unsigned_move_token.remote_pending_debt = pending_debt;
+        let friend_move_token = dummy_sign_move_token(unsigned_move_token, &identity2);
+
+        match tc1.simulate_receive_move_token(friend_move_token, &ImHashSet::new()) {
+            Err(ReceiveMoveTokenError::InvariantViolation(InvariantError::DebtOutOfRange(
+                public_key,
+            ))) => assert_eq!(public_key, pk2),
+            _ => unreachable!(),
+        };
+    }
+
+    /// A move token with the maximum amount of operations, where every operation carries a route
+    /// of maximum length. Either all the operations are received, or none of them.
+    #[test]
+    fn test_simulate_receive_move_token_max_batch() {
+        let mut identities = fixture_keypairs(2);
+        let identity2 = identities.pop().unwrap();
+        let identity1 = identities.pop().unwrap();
+
+        let pk1 = identity1.get_public_key();
+        let pk2 = identity2.get_public_key();
+        let mut tc1 = TokenChannel::<u32>::new(&pk1, &pk2, 0i128);
+        let mut tc2 = TokenChannel::<u32>::new(&pk2, &pk1, 0i128);
+        assert!(tc1.is_outgoing());
+
+        // tc1 accepts requests from tc2:
+        let mc_mutation = McMutation::SetLocalRequestsStatus(RequestsStatus::Open);
+        tc1.mutate(&TcMutation::McMutation(mc_mutation));
+        let mc_mutation = McMutation::SetRemoteMaxDebt(MAX_FUNDER_DEBT);
+        tc1.mutate(&TcMutation::McMutation(mc_mutation));
+
+        let mc_mutation = McMutation::SetRemoteRequestsStatus(RequestsStatus::Open);
+        tc2.mutate(&TcMutation::McMutation(mc_mutation));
+        let mc_mutation = McMutation::SetLocalMaxDebt(MAX_FUNDER_DEBT);
+        tc2.mutate(&TcMutation::McMutation(mc_mutation));
+
+        let mut public_keys = vec![pk2.clone(), pk1.clone()];
+        for i in 0..MAX_ROUTE_LEN - 2 {
+            public_keys.push(PublicKey::from(&[i as u8; PUBLIC_KEY_LEN]));
+        }
+        let operations = (0..MAX_OPERATIONS_IN_BATCH)
+            .map(|i| {
+                FriendTcOp::RequestSendFunds(RequestSendFunds {
+                    request_id: Uid::from(&[i as u8; UID_LEN]),
+                    route: FriendsRoute {
+                        public_keys: public_keys.clone(),
+                    },
+                    dest_payment: 10,
+                    invoice_id: InvoiceId::from(&[i as u8; INVOICE_ID_LEN]),
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // tc2 sends all the requests:
+        let mc_mutations = match tc2.get_direction() {
+            TcDirection::Incoming(tc2_incoming) => {
+                let mut outgoing_mc = tc2_incoming.begin_outgoing_move_token();
+                let mut mc_mutations = Vec::new();
+                for operation in &operations {
+                    mc_mutations.extend(outgoing_mc.queue_operation(operation).unwrap());
+                }
+                mc_mutations
+            }
+            TcDirection::Outgoing(_) => unreachable!(),
+        };
+        for mc_mutation in mc_mutations {
+            tc2.mutate(&TcMutation::McMutation(mc_mutation));
+        }
+        let tc2_incoming = match tc2.get_direction() {
+            TcDirection::Incoming(tc2_incoming) => tc2_incoming,
+            TcDirection::Outgoing(_) => unreachable!(),
+        };
+        let create_move_token = |operations| {
+            let rand_nonce = RandValue::from(&[5; RAND_VALUE_LEN]);
+            let unsigned_move_token =
+                tc2_incoming.create_unsigned_move_token(operations, None, rand_nonce);
+            dummy_sign_move_token(unsigned_move_token, &identity2)
+        };
+
+        // An invalid last operation (A duplicate request) rejects the whole move token:
+        let mut invalid_operations = operations.clone();
+        invalid_operations[MAX_OPERATIONS_IN_BATCH - 1] = operations[0].clone();
+        match tc1
+            .simulate_receive_move_token(create_move_token(invalid_operations), &ImHashSet::new())
+        {
+            Err(ReceiveMoveTokenError::InvalidTransaction(_)) => {}
+            _ => unreachable!(),
+        };
+
+        let receive_move_token_output = tc1
+            .simulate_receive_move_token(create_move_token(operations), &ImHashSet::new())
+            .unwrap();
+        let move_token_received = match receive_move_token_output {
+            ReceiveMoveTokenOutput::Received(move_token_received) => move_token_received,
+            _ => unreachable!(),
+        };
+
+        // The requests are received in order:
+        assert_eq!(
+            move_token_received.incoming_messages.len(),
+            MAX_OPERATIONS_IN_BATCH
+        );
+        for (i, incoming_message) in move_token_received.incoming_messages.iter().enumerate() {
+            match incoming_message {
+                IncomingMessage::Request(request_send_funds) => {
+                    assert_eq!(
+                        request_send_funds.request_id,
+                        Uid::from(&[i as u8; UID_LEN])
+                    );
+                    assert_eq!(request_send_funds.route.public_keys, public_keys);
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        // Every request inserts a pending request and sets the pending debt:
+        assert_eq!(
+            move_token_received.mutations.len(),
+            2 * MAX_OPERATIONS_IN_BATCH + 1
+        );
+        for tc_mutation in &move_token_received.mutations {
+            tc1.mutate(tc_mutation);
+        }
+        assert!(!tc1.is_outgoing());
+        let tc1_state = tc1.get_mutual_credit().state();
+        assert_eq!(
+            tc1_state.pending_requests.pending_remote_requests.len(),
+            MAX_OPERATIONS_IN_BATCH
+        );
+    }
+
+    /// The state one side of a `TcPair` is expected to have, derived only from the token channel
+    /// mutations applied to that side.
+    #[derive(Clone)]
+    struct TcSideModel {
+        /// Follows the last `SetDirection` mutation
+        direction: ExpectedDirection,
+        last_incoming: Option<MoveTokenHashed>,
+        /// The last move token received from the remote side. Cleared by a reset.
+        opt_last_received: Option<MoveToken<u32>>,
+        /// (inconsistency_counter, move_token_counter) seen at the last check
+        counters: (u64, u128),
+    }
+
+    #[derive(Clone)]
+    enum ExpectedDirection {
+        Incoming(MoveTokenHashed),
+        Outgoing((MoveToken<u32>, bool)),
+    }
+
+    /// Both sides of a token channel, driven through valid sequences of actions.
+    #[derive(Clone)]
+    struct TcPair {
+        tcs: Vec<TokenChannel<u32>>,
+        models: Vec<TcSideModel>,
+        /// Used to create a distinct reset token for every reset
+        num_resets: u8,
+    }
+
+    #[derive(Debug, Clone)]
+    enum TcPairAction {
+        /// The incoming side sends a move token with the given operations and rand nonce.
+        PassToken((Vec<FriendTcOp>, u8)),
+        /// A side records whether it asked for the token back. Ignored by an incoming side.
+        /// (side, token_wanted)
+        SetTokenWanted((usize, bool)),
+        /// The incoming side receives the last move token again.
+        ReceiveDuplicate,
+        /// The outgoing side receives the last move token it received again.
+        ReceiveRetransmit,
+        /// A side resets the channel, using the reset terms of the other side.
+        Reset(usize),
+        /// Replace both token channels with a serialization round trip of themselves.
+        RoundTrip,
+    }
+
+    impl TcPair {
+        fn new(public_keys: &[PublicKey], balance: i128) -> Self {
+            let tcs = vec![
+                TokenChannel::new(&public_keys[0], &public_keys[1], balance),
+                TokenChannel::new(&public_keys[1], &public_keys[0], -balance),
+            ];
+            let models = tcs
+                .iter()
+                .map(|tc| {
+                    let direction = match tc.get_direction() {
+                        TcDirection::Incoming(tc_incoming) => {
+                            ExpectedDirection::Incoming(tc_incoming.move_token_in.clone())
+                        }
+                        TcDirection::Outgoing(tc_outgoing) => {
+                            ExpectedDirection::Outgoing((tc_outgoing.move_token_out.clone(), false))
+                        }
+                    };
+                    TcSideModel {
+                        direction,
+                        last_incoming: tc.get_last_incoming_move_token_hashed().cloned(),
+                        opt_last_received: None,
+                        counters: (0, 0),
+                    }
+                })
+                .collect();
+            TcPair {
+                tcs,
+                models,
+                num_resets: 0,
+            }
+        }
+
+        fn outgoing_side(&self) -> usize {
+            assert!(self.tcs[0].is_outgoing() ^ self.tcs[1].is_outgoing());
+            if self.tcs[0].is_outgoing() {
+                0
+            } else {
+                1
+            }
+        }
+
+        fn outgoing_move_token(&self) -> MoveToken<u32> {
+            match &self.models[self.outgoing_side()].direction {
+                ExpectedDirection::Outgoing((move_token_out, _)) => move_token_out.clone(),
+                ExpectedDirection::Incoming(_) => unreachable!(),
+            }
+        }
+
+        fn mutate(&mut self, side: usize, tc_mutation: &TcMutation<u32>) {
+            self.tcs[side].mutate(tc_mutation);
+            let model = &mut self.models[side];
+            match tc_mutation {
+                TcMutation::McMutation(_) => {}
+                TcMutation::SetDirection(SetDirection::Incoming(move_token_in)) => {
+                    model.direction = ExpectedDirection::Incoming(move_token_in.clone());
+                    model.last_incoming = Some(move_token_in.clone());
+                }
+                TcMutation::SetDirection(SetDirection::Outgoing(move_token_out)) => {
+                    model.direction = ExpectedDirection::Outgoing((move_token_out.clone(), false));
+                }
+                TcMutation::SetTokenWanted(token_wanted) => {
+                    if let ExpectedDirection::Outgoing((_, model_token_wanted)) =
+                        &mut model.direction
+                    {
+                        *model_token_wanted = *token_wanted;
+                    }
+                }
+            }
+        }
+
+        fn pass_token<I: Identity>(
+            &mut self,
+            identities: &[I],
+            operations: &[FriendTcOp],
+            nonce: u8,
+        ) {
+            let receiver = self.outgoing_side();
+            let sender = 1 - receiver;
+
+            let mc_mutations = match self.tcs[sender].get_direction() {
+                TcDirection::Incoming(tc_incoming) => {
+                    let mut outgoing_mc = tc_incoming.begin_outgoing_move_token();
+                    let mut mc_mutations = Vec::new();
+                    for operation in operations {
+                        mc_mutations.extend(outgoing_mc.queue_operation(operation).unwrap());
+                    }
+                    mc_mutations
+                }
+                TcDirection::Outgoing(_) => unreachable!(),
+            };
+            for mc_mutation in mc_mutations {
+                self.mutate(sender, &TcMutation::McMutation(mc_mutation));
+            }
+            let unsigned_move_token = match self.tcs[sender].get_direction() {
+                TcDirection::Incoming(tc_incoming) => tc_incoming.create_unsigned_move_token(
+                    operations.to_vec(),
+                    None,
+                    RandValue::from(&[nonce; RAND_VALUE_LEN]),
+                ),
+                TcDirection::Outgoing(_) => unreachable!(),
+            };
+            let move_token = dummy_sign_move_token(unsigned_move_token, &identities[sender]);
+            let set_direction = SetDirection::Outgoing(move_token.clone());
+            self.mutate(sender, &TcMutation::SetDirection(set_direction));
+
+            let output = self.tcs[receiver]
+                .simulate_receive_move_token(move_token.clone(), &ImHashSet::new())
+                .unwrap();
+            let move_token_received = match output {
+                ReceiveMoveTokenOutput::Received(move_token_received) => move_token_received,
+                _ => unreachable!(),
+            };
+            assert!(move_token_received.incoming_messages.is_empty());
+            match move_token_received.mutations.last() {
+                Some(TcMutation::SetDirection(SetDirection::Incoming(move_token_in))) => {
+                    assert_eq!(move_token_in, &create_hashed(&move_token))
+                }
+                _ => unreachable!(),
+            };
+            for tc_mutation in &move_token_received.mutations {
+                self.mutate(receiver, tc_mutation);
+            }
+            self.models[receiver].opt_last_received = Some(move_token);
+        }
+
+        fn receive_duplicate(&self) {
+            let receiver = 1 - self.outgoing_side();
+            let output = self.tcs[receiver]
+                .simulate_receive_move_token(self.outgoing_move_token(), &ImHashSet::new())
+                .unwrap();
+            match output {
+                ReceiveMoveTokenOutput::Duplicate => {}
+                _ => unreachable!(),
+            };
+        }
+
+        fn receive_retransmit(&self) {
+            let receiver = self.outgoing_side();
+            let last_received = match &self.models[receiver].opt_last_received {
+                Some(last_received) => last_received.clone(),
+                None => return,
+            };
+            let output = self.tcs[receiver]
+                .simulate_receive_move_token(last_received, &ImHashSet::new())
+                .unwrap();
+            match output {
+                ReceiveMoveTokenOutput::RetransmitOutgoing(move_token) => {
+                    assert_eq!(move_token, self.outgoing_move_token())
+                }
+                _ => unreachable!(),
+            };
+        }
+
+        /// `side` sends a reset move token, using the reset terms of the remote side (As done in
+        /// `apply_local_reset()`). The remote side receives it.
+        fn reset<I: Identity>(&mut self, identities: &[I], side: usize) {
+            let remote = 1 - side;
+            self.num_resets = self.num_resets.wrapping_add(1);
+
+            let remote_tc = &self.tcs[remote];
+            let reset_token = Signature::from([self.num_resets; SIGNATURE_LEN]);
+            let inconsistency_counter = remote_tc.get_inconsistency_counter().wrapping_add(1);
+            let balance_for_reset = remote_tc.get_mutual_credit().balance_for_reset().unwrap();
+
+            let u_reset_move_token = create_unsigned_move_token(
+                Vec::new(),
+                None,
+                reset_token,
+                identities[side].get_public_key(),
+                identities[remote].get_public_key(),
+                inconsistency_counter,
+                0,
+                balance_for_reset.checked_neg().unwrap(),
+                0,
+                0,
+                RandValue::from(&[self.num_resets; RAND_VALUE_LEN]),
+            );
+            let reset_move_token = dummy_sign_move_token(u_reset_move_token, &identities[side]);
+
+            let opt_last_incoming = self.tcs[side]
+                .get_last_incoming_move_token_hashed()
+                .cloned();
+            self.tcs[side] = TokenChannel::new_from_local_reset(
+                &identities[side].get_public_key(),
+                &identities[remote].get_public_key(),
+                &reset_move_token,
+                balance_for_reset.checked_neg().unwrap(),
+                opt_last_incoming,
+            );
+            self.tcs[remote] = TokenChannel::new_from_remote_reset(
+                &identities[remote].get_public_key(),
+                &identities[side].get_public_key(),
+                &reset_move_token,
+                balance_for_reset,
+            );
+
+            let reset_move_token_hashed = create_hashed(&reset_move_token);
+            let side_model = &mut self.models[side];
+            side_model.direction = ExpectedDirection::Outgoing((reset_move_token, false));
+            side_model.opt_last_received = None;
+            let remote_model = &mut self.models[remote];
+            remote_model.direction = ExpectedDirection::Incoming(reset_move_token_hashed.clone());
+            remote_model.last_incoming = Some(reset_move_token_hashed);
+            remote_model.opt_last_received = None;
+        }
+
+        fn apply<I: Identity>(&mut self, identities: &[I], action: &TcPairAction) {
+            match action {
+                TcPairAction::PassToken((operations, nonce)) => {
+                    self.pass_token(identities, operations, *nonce)
+                }
+                TcPairAction::SetTokenWanted((side, token_wanted)) => {
+                    self.mutate(*side, &TcMutation::SetTokenWanted(*token_wanted))
+                }
+                TcPairAction::ReceiveDuplicate => self.receive_duplicate(),
+                TcPairAction::ReceiveRetransmit => self.receive_retransmit(),
+                TcPairAction::Reset(side) => self.reset(identities, *side),
+                TcPairAction::RoundTrip => unreachable!(),
+            }
+        }
+
+        /// Verify that both sides match their models, and that the sides agree with each other.
+        fn check(&mut self) {
+            for (tc, model) in self.tcs.iter().zip(self.models.iter_mut()) {
+                match (tc.get_direction(), &model.direction) {
+                    (
+                        TcDirection::Incoming(tc_incoming),
+                        ExpectedDirection::Incoming(move_token_in),
+                    ) => {
+                        assert_eq!(&tc_incoming.move_token_in, move_token_in);
+                    }
+                    (
+                        TcDirection::Outgoing(tc_outgoing),
+                        ExpectedDirection::Outgoing((move_token_out, token_wanted)),
+                    ) => {
+                        assert_eq!(&tc_outgoing.move_token_out, move_token_out);
+                        assert_eq!(tc_outgoing.token_wanted, *token_wanted);
+                    }
+                    _ => panic!("Direction does not match the last SetDirection"),
+                };
+                assert_eq!(
+                    tc.get_last_incoming_move_token_hashed(),
+                    model.last_incoming.as_ref()
+                );
+
+                // The move token counter only goes back when the inconsistency counter advances:
+                let counters = (tc.get_inconsistency_counter(), tc.get_move_token_counter());
+                assert!(counters >= model.counters);
+                model.counters = counters;
+
+                assert_eq!(tc.validate_invariants(), Ok(()));
+            }
+
+            let incoming_side = 1 - self.outgoing_side();
+            assert_eq!(
+                self.tcs[incoming_side].get_last_incoming_move_token_hashed(),
+                Some(&create_hashed(&self.outgoing_move_token()))
+            );
+        }
+
+        fn round_trip(&self) -> TcPair {
+            let tcs = self
+                .tcs
+                .iter()
+                .map(|tc| bincode::deserialize(&bincode::serialize(tc).unwrap()).unwrap())
+                .collect();
+            TcPair {
+                tcs,
+                ..self.clone()
+            }
+        }
+
+        fn serialize(&self) -> Vec<Vec<u8>> {
+            self.tcs
+                .iter()
+                .map(|tc| bincode::serialize(tc).unwrap())
+                .collect()
+        }
+    }
+
+    fn friend_tc_op_strategy() -> impl Strategy<Value = FriendTcOp> {
+        prop_oneof![
+            Just(FriendTcOp::EnableRequests),
+            Just(FriendTcOp::DisableRequests),
+            (0..=MAX_FUNDER_DEBT).prop_map(FriendTcOp::SetRemoteMaxDebt),
+        ]
+    }
+
+    fn tc_pair_action_strategy() -> impl Strategy<Value = TcPairAction> {
+        prop_oneof![
+            4 => (prop::collection::vec(friend_tc_op_strategy(), 0..4), any::<u8>())
+                .prop_map(TcPairAction::PassToken),
+            2 => (0..2usize, any::<bool>()).prop_map(TcPairAction::SetTokenWanted),
+            1 => Just(TcPairAction::ReceiveDuplicate),
+            1 => Just(TcPairAction::ReceiveRetransmit),
+            1 => (0..2usize).prop_map(TcPairAction::Reset),
+            2 => Just(TcPairAction::RoundTrip),
+        ]
+    }
+
+    proptest! {
+        /// Drive both sides of a token channel through random sequences of valid actions.
+        /// After every action, the direction of every side matches the last `SetDirection` applied
+        /// to it, and the counters never go back. A token channel that went through a
+        /// serialization round trip behaves like the original in the following action.
+        #[test]
+        fn test_tc_pair_actions(
+            balance in (i128::min_value() + 1)..=i128::max_value(),
+            actions in prop::collection::vec(tc_pair_action_strategy(), 0..32),
+        ) {
+            let identities = fixture_keypairs(2);
+            let public_keys = identities
+                .iter()
+                .map(|identity| identity.get_public_key())
+                .collect::<Vec<_>>();
+            let mut pair = TcPair::new(&public_keys, balance);
+            pair.check();
+
+            let mut opt_round_tripped: Option<TcPair> = None;
+            for action in &actions {
+                if let TcPairAction::RoundTrip = action {
+                    let round_tripped = opt_round_tripped.as_ref().unwrap_or(&pair).round_trip();
+                    prop_assert_eq!(round_tripped.serialize(), pair.serialize());
+                    opt_round_tripped = Some(round_tripped);
+                    continue;
+                }
+
+                pair.apply(&identities, action);
+                pair.check();
+
+                if let Some(mut round_tripped) = opt_round_tripped.take() {
+                    round_tripped.apply(&identities, action);
+                    round_tripped.check();
+                    prop_assert_eq!(round_tripped.serialize(), pair.serialize());
+                    // Continue with the deserialized token channels:
+                    pair = round_tripped;
+                }
+            }
+        }
+    }
+
     // TODO: Add more tests.
     // - Test behaviour of Duplicate, ChainInconsistency
 }
@@ -6,8 +6,9 @@ use crypto::identity::{PublicKey, Signature};
 
 use proto::app_server::messages::RelayAddress;
 use proto::funder::messages::{
-    ChannelerUpdateFriend, FailureSendFunds, FriendMessage, FriendTcOp, FunderIncomingControl,
-    FunderOutgoingControl, MoveToken, PendingRequest, RequestSendFunds, ResponseSendFunds,
+    ChannelerUpdateFriend, FailureReason, FailureSendFunds, FriendMessage, FriendTcOp,
+    FunderIncomingControl, FunderOutgoingControl, MoveToken, PendingRequest, RequestSendFunds,
+    ResponseSendFunds,
 };
 
 use proto::funder::signature_buff::{
@@ -15,7 +16,7 @@ use proto::funder::signature_buff::{
     prefix_hash,
 };
 
-use identity::IdentityClient;
+use identity::{IdentityClient, IdentityClientError};
 
 pub type UnsignedFailureSendFunds = FailureSendFunds<()>;
 pub type UnsignedResponseSendFunds = ResponseSendFunds<()>;
@@ -24,14 +25,14 @@ pub type UnsignedMoveToken<B> = MoveToken<B, ()>;
 pub async fn sign_move_token<'a, B>(
     unsigned_move_token: UnsignedMoveToken<B>,
     identity_client: &'a mut IdentityClient,
-) -> MoveToken<B>
+) -> Result<MoveToken<B>, IdentityClientError>
 where
     B: CanonicalSerialize + 'a,
 {
     let signature_buff = move_token_signature_buff(&unsigned_move_token);
-    let new_token = await!(identity_client.request_signature(signature_buff)).unwrap();
+    let new_token = await!(identity_client.request_signature(signature_buff))?;
 
-    MoveToken {
+    Ok(MoveToken {
         operations: unsigned_move_token.operations,
         opt_local_relays: unsigned_move_token.opt_local_relays,
         old_token: unsigned_move_token.old_token,
@@ -44,14 +45,14 @@ where
         remote_pending_debt: unsigned_move_token.remote_pending_debt,
         rand_nonce: unsigned_move_token.rand_nonce,
         new_token,
-    }
+    })
 }
 
 pub async fn create_response_send_funds<'a>(
     pending_request: &'a PendingRequest,
     rand_nonce: RandValue,
     identity_client: &'a mut IdentityClient,
-) -> ResponseSendFunds {
+) -> Result<ResponseSendFunds, IdentityClientError> {
     let u_response_send_funds = ResponseSendFunds {
         request_id: pending_request.request_id,
         rand_nonce,
@@ -59,37 +60,40 @@ pub async fn create_response_send_funds<'a>(
     };
 
     let signature_buff = create_response_signature_buffer(&u_response_send_funds, pending_request);
-    let signature = await!(identity_client.request_signature(signature_buff)).unwrap();
+    let signature = await!(identity_client.request_signature(signature_buff))?;
 
-    ResponseSendFunds {
+    Ok(ResponseSendFunds {
         request_id: u_response_send_funds.request_id,
         rand_nonce: u_response_send_funds.rand_nonce,
         signature,
-    }
+    })
 }
 
 pub async fn create_failure_send_funds<'a>(
     pending_request: &'a PendingRequest,
     local_public_key: &'a PublicKey,
+    reason: FailureReason,
     rand_nonce: RandValue,
     identity_client: &'a mut IdentityClient,
-) -> FailureSendFunds {
+) -> Result<FailureSendFunds, IdentityClientError> {
     let u_failure_send_funds = FailureSendFunds {
         request_id: pending_request.request_id,
         reporting_public_key: local_public_key.clone(),
+        reason,
         rand_nonce,
         signature: (),
     };
 
     let signature_buff = create_failure_signature_buffer(&u_failure_send_funds, pending_request);
-    let signature = await!(identity_client.request_signature(signature_buff)).unwrap();
+    let signature = await!(identity_client.request_signature(signature_buff))?;
 
     FailureSendFunds {
         request_id: u_failure_send_funds.request_id,
         reporting_public_key: u_failure_send_funds.reporting_public_key,
+        reason: u_failure_send_funds.reason,
         rand_nonce: u_failure_send_funds.rand_nonce,
         signature,
-    }
+    })
 }
 
 #[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
@@ -234,6 +238,9 @@ pub enum ChannelerConfig<RA> {
     SetRelays(Vec<RA>),
     UpdateFriend(ChannelerUpdateFriend<RA>),
     RemoveFriend(PublicKey),
+    /// Stop reconnecting to a friend that went offline on purpose,
+    /// until the next UpdateFriend for this friend.
+    SuspendFriend(PublicKey),
 }
 
 #[derive(Debug, Clone)]
@@ -250,6 +257,11 @@ pub enum FunderIncoming<B> {
     Init,
     Control(FunderIncomingControl<B>),
     Comm(FunderIncomingComm<B>),
+    /// Contains the amount of time ticks that have elapsed. At least 1.
+    TimerTick(u64),
+    /// The database became unavailable for writing (true), or available again (false).
+    /// Sent by the funder loop itself.
+    SetReadOnly(bool),
 }
 
 #[allow(clippy::large_enum_variant)]
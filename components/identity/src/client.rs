@@ -1,3 +1,6 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+
 use futures::channel::{mpsc, oneshot};
 use futures::{Future, TryFutureExt};
 
@@ -6,20 +9,51 @@ use crypto::identity::{PublicKey, Signature};
 
 use super::messages::{ResponsePublicKey, ResponseSignature, ToIdentity};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum IdentityClientError {
     RequestSendFailed,
     OneshotReceiverCanceled,
 }
 
+type PublicKeyResult = Result<PublicKey, IdentityClientError>;
+
+enum PublicKeyCache {
+    Empty,
+    /// A request for the public key is in flight.
+    /// The callers waiting for it are notified when it resolves.
+    Pending(Vec<oneshot::Sender<PublicKeyResult>>),
+    Ready(PublicKey),
+}
+
+/// Makes sure that waiting callers are not left hanging if the caller that requested the
+/// public key was dropped before the request resolved.
+struct PendingGuard<'a> {
+    public_key_cache: &'a Mutex<PublicKeyCache>,
+}
+
+impl<'a> Drop for PendingGuard<'a> {
+    fn drop(&mut self) {
+        let mut public_key_cache = self.public_key_cache.lock().unwrap();
+        if let PublicKeyCache::Pending(_) = &*public_key_cache {
+            // Dropping the senders cancels the waiting callers:
+            *public_key_cache = PublicKeyCache::Empty;
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct IdentityClient {
     requests_sender: mpsc::Sender<ToIdentity>,
+    /// Shared between all the clones of this client
+    public_key_cache: Arc<Mutex<PublicKeyCache>>,
 }
 
 impl IdentityClient {
     pub fn new(requests_sender: mpsc::Sender<ToIdentity>) -> Self {
-        IdentityClient { requests_sender }
+        IdentityClient {
+            requests_sender,
+            public_key_cache: Arc::new(Mutex::new(PublicKeyCache::Empty)),
+        }
     }
 
     /// Send a request to the Identity. Returns a Future that waits for the response.
@@ -62,6 +96,56 @@ impl IdentityClient {
         self.request_response(request, rx)
             .map_ok(|response_public_key: ResponsePublicKey| response_public_key.public_key)
     }
+
+    /// Get the public key of the used Identity.
+    /// Only the first call sends a request to the Identity. Concurrent first calls share the
+    /// same request. Later calls resolve immediately.
+    /// A failed request is not cached: The next call will send a new request.
+    pub async fn cached_public_key(&self) -> PublicKeyResult {
+        let opt_receiver = {
+            let mut public_key_cache = self.public_key_cache.lock().unwrap();
+            match &mut *public_key_cache {
+                PublicKeyCache::Ready(public_key) => return Ok(public_key.clone()),
+                PublicKeyCache::Pending(waiters) => {
+                    let (sender, receiver) = oneshot::channel();
+                    waiters.push(sender);
+                    Some(receiver)
+                }
+                PublicKeyCache::Empty => {
+                    *public_key_cache = PublicKeyCache::Pending(Vec::new());
+                    None
+                }
+            }
+        };
+
+        if let Some(receiver) = opt_receiver {
+            // Another caller is already requesting the public key:
+            return match await!(receiver) {
+                Ok(res) => res,
+                Err(oneshot::Canceled) => Err(IdentityClientError::OneshotReceiverCanceled),
+            };
+        }
+
+        let _pending_guard = PendingGuard {
+            public_key_cache: &self.public_key_cache,
+        };
+        let res = await!(self.request_public_key());
+
+        let new_public_key_cache = match &res {
+            Ok(public_key) => PublicKeyCache::Ready(public_key.clone()),
+            Err(_) => PublicKeyCache::Empty,
+        };
+        let mut public_key_cache = self.public_key_cache.lock().unwrap();
+        if let PublicKeyCache::Pending(waiters) =
+            mem::replace(&mut *public_key_cache, new_public_key_cache)
+        {
+            for waiter in waiters {
+                let _ = waiter.send(res.clone());
+            }
+        }
+        drop(public_key_cache);
+        res
+    }
 }
 
 #[cfg(test)]
@@ -71,10 +155,12 @@ mod tests {
     use futures::executor::LocalPool;
     use futures::future;
     use futures::task::SpawnExt;
-    use futures::FutureExt;
+    use futures::{FutureExt, StreamExt};
 
     use crate::identity::create_identity;
-    use crypto::identity::{generate_pkcs8_key_pair, verify_signature, SoftwareEd25519Identity};
+    use crypto::identity::{
+        generate_pkcs8_key_pair, verify_signature, SoftwareEd25519Identity, PUBLIC_KEY_LEN,
+    };
     use crypto::test_utils::DummyRandom;
 
     #[test]
@@ -124,5 +210,73 @@ mod tests {
         assert!(verify_signature(&my_message[..], &public_key, &signature));
     }
 
+    #[test]
+    fn test_identity_cached_public_key() {
+        // We play the role of the Identity, counting the requests we get:
+        let (requests_sender, mut requests_receiver) = mpsc::channel(0);
+        let smc = IdentityClient::new(requests_sender);
+        let public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+
+        let mut local_pool = LocalPool::new();
+        let mut spawner = local_pool.spawner();
+
+        // Concurrent first calls:
+        let c_smc = smc.clone();
+        let handle1 = spawner
+            .spawn_with_handle(
+                async move { await!(c_smc.cached_public_key()) },
+            )
+            .unwrap();
+        let c_smc = smc.clone();
+        let handle2 = spawner
+            .spawn_with_handle(
+                async move { await!(c_smc.cached_public_key()) },
+            )
+            .unwrap();
+
+        // A failed request is shared by the concurrent callers, but is not cached:
+        match local_pool.run_until(requests_receiver.next()).unwrap() {
+            ToIdentity::RequestPublicKey { response_sender } => drop(response_sender),
+            _ => unreachable!(),
+        };
+        assert!(local_pool.run_until(handle1).is_err());
+        assert!(local_pool.run_until(handle2).is_err());
+        assert!(requests_receiver.try_next().is_err());
+
+        let c_smc = smc.clone();
+        let handle1 = spawner
+            .spawn_with_handle(
+                async move { await!(c_smc.cached_public_key()) },
+            )
+            .unwrap();
+        let c_smc = smc.clone();
+        let handle2 = spawner
+            .spawn_with_handle(
+                async move { await!(c_smc.cached_public_key()) },
+            )
+            .unwrap();
+
+        match local_pool.run_until(requests_receiver.next()).unwrap() {
+            ToIdentity::RequestPublicKey { response_sender } => {
+                let response_public_key = ResponsePublicKey {
+                    public_key: public_key.clone(),
+                };
+                response_sender.send(response_public_key).ok().unwrap();
+            }
+            _ => unreachable!(),
+        };
+        assert_eq!(local_pool.run_until(handle1).unwrap(), public_key);
+        assert_eq!(local_pool.run_until(handle2).unwrap(), public_key);
+        // Only one request was sent:
+        assert!(requests_receiver.try_next().is_err());
+
+        // Later calls resolve without contacting the Identity, even if it is gone:
+        drop(requests_receiver);
+        assert_eq!(local_pool.run_until(smc.cached_public_key()).unwrap(), public_key);
+        // The cache is shared between clones:
+        let c_smc = smc.clone();
+        assert_eq!(local_pool.run_until(c_smc.cached_public_key()).unwrap(), public_key);
+    }
+
     // TODO: Add tests that check "concurrency": Multiple clients that send requests.
 }
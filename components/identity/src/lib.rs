@@ -17,6 +17,7 @@ extern crate futures;
 mod client;
 mod identity;
 mod messages;
+pub mod test_utils;
 
-pub use crate::client::IdentityClient;
+pub use crate::client::{IdentityClient, IdentityClientError};
 pub use crate::identity::create_identity;
@@ -0,0 +1,28 @@
+use futures::task::{Spawn, SpawnExt};
+use futures::Future;
+
+use crypto::identity::{Identity, PublicKey};
+use crypto::test_utils::fixture_software_identity;
+
+use crate::client::IdentityClient;
+use crate::identity::create_identity;
+
+/// Create an identity service for a deterministic identity, generated from a seed.
+/// Returns a client, the public key of the identity and the service future.
+/// The client can be used only after the service future was spawned.
+pub fn fixture_identity(seed: u8) -> (IdentityClient, PublicKey, impl Future<Output = ()>) {
+    let identity = fixture_software_identity(seed);
+    let public_key = identity.get_public_key();
+    let (requests_sender, identity_server) = create_identity(identity);
+    (IdentityClient::new(requests_sender), public_key, identity_server)
+}
+
+/// Create an identity service for a deterministic identity, and spawn it.
+pub fn spawn_fixture_identity<S>(seed: u8, spawner: &mut S) -> (IdentityClient, PublicKey)
+where
+    S: Spawn,
+{
+    let (identity_client, public_key, identity_server) = fixture_identity(seed);
+    spawner.spawn(identity_server).unwrap();
+    (identity_client, public_key)
+}
@@ -15,7 +15,9 @@ use crate::single_client::{
 
 pub type ControlSender = mpsc::Sender<SingleClientControl>;
 pub type CloseReceiver = oneshot::Receiver<Result<(), SingleClientError>>;
-pub type SessionHandle = (ControlSender, CloseReceiver);
+/// Notifies about requests of the server for our full state
+pub type FullStateRequestReceiver = mpsc::Receiver<()>;
+pub type SessionHandle = (ControlSender, CloseReceiver, FullStateRequestReceiver);
 
 #[derive(Clone)]
 pub struct IndexClientSession<C, R, S> {
@@ -57,10 +59,12 @@ where
         let (control_sender, incoming_control) = mpsc::channel(0);
 
         let (close_sender, close_receiver) = oneshot::channel();
+        let (full_state_request_sender, full_state_request_receiver) = mpsc::channel(0);
 
         let single_client_fut = single_client_loop(
             (to_server, from_server),
             incoming_control,
+            full_state_request_sender,
             self.local_public_key.clone(),
             self.identity_client.clone(),
             self.rng.clone(),
@@ -73,7 +77,7 @@ where
         });
 
         self.spawner.spawn(single_client_fut).ok()?;
-        Some((control_sender, close_receiver))
+        Some((control_sender, close_receiver, full_state_request_receiver))
     }
 }
 
@@ -86,7 +90,8 @@ where
 {
     /// Address of an index server
     type Input = ISA;
-    /// A pair: control sender, and a receiver that notifies about disconnection.
+    /// A control sender, a receiver that notifies about disconnection, and a receiver that
+    /// notifies about requests of the server for our full state.
     type Output = Option<SessionHandle>;
 
     fn transform(&mut self, index_server_address: Self::Input) -> BoxFuture<'_, Self::Output> {
@@ -151,7 +156,8 @@ mod tests {
         let session_handle_fut = index_client_session.transform(0x1337u32);
 
         let (opt_session_handle, ()) = await!(session_handle_fut.join(handle_conn_request_fut));
-        let (_control_sender, close_receiver) = opt_session_handle.unwrap();
+        let (_control_sender, close_receiver, _full_state_request_receiver) =
+            opt_session_handle.unwrap();
 
         drop(server_sender);
         let single_client_loop_res = await!(close_receiver).unwrap();
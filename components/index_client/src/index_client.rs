@@ -1,12 +1,15 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::Debug;
 use std::marker::Unpin;
+use std::sync::{Arc, Mutex};
 
 use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnExt};
 use futures::{future, select, stream, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt};
 
+use common::bounded_cache::CacheLimits;
 use common::conn::FutTransform;
+use common::int_convert::usize_to_u64;
 use common::mutable_state::MutableState;
 use common::select_streams::{select_streams, BoxStream};
 
@@ -18,11 +21,12 @@ use database::DatabaseClient;
 use proto::index_client::messages::{
     AppServerToIndexClient, ClientResponseRoutes, IndexClientReportMutation,
     IndexClientReportMutations, IndexClientRequest, IndexClientToAppServer, IndexMutation,
-    RequestRoutes, ResponseRoutesResult,
+    RequestRoutes, ResponseRoutesResult, UpdateFriend,
 };
 use proto::index_server::messages::{IndexServerAddress, NamedIndexServerAddress};
 
 use crate::client_session::{ControlSender, SessionHandle};
+use crate::route_cache::{RouteCache, RouteCacheTicket};
 use crate::seq_friends::SeqFriendsClient;
 use crate::single_client::SingleClientControl;
 
@@ -71,10 +75,15 @@ where
     }
 }
 
+/// Capacities of friends, as last sent to an index server.
+/// Shared with the send_full_state() task.
+type SentFriends = Arc<Mutex<HashMap<PublicKey, (u128, u128)>>>;
+
 #[derive(Debug)]
 struct ServerConnecting<ISA> {
     index_server: IndexServerAddress<ISA>,
     opt_cancel_sender: Option<oneshot::Sender<()>>,
+    sent_friends: SentFriends,
 }
 
 #[derive(Debug)]
@@ -88,6 +97,11 @@ struct ServerConnected<ISA> {
     /// Decrementing counter. When reaches 0 we send a SendMutations
     /// to the server and reset this value to keepalive_ticks:
     ticks_to_send_keepalive: usize,
+    /// Capacities of friends sent to the server during this connection
+    sent_friends: SentFriends,
+    /// Decrementing counter. When reaches 0 we compare sent_friends against the current
+    /// capacities of our friends, and reset this value to reconcile_ticks:
+    ticks_to_reconcile: usize,
 }
 
 #[derive(Debug)]
@@ -113,7 +127,9 @@ enum IndexClientEvent<ISA> {
     AppServerClosed,
     IndexServerConnected(ControlSender),
     IndexServerClosed,
-    ResponseRoutes((Uid, ResponseRoutesResult)),
+    /// The connected index server requested our full state
+    FullStateRequested,
+    ResponseRoutes((Uid, RouteCacheTicket, ResponseRoutesResult)),
     TimerTick,
 }
 
@@ -131,16 +147,62 @@ struct IndexClient<ISA, TAS, ICS, S> {
     num_open_requests: usize,
     keepalive_ticks: usize,
     backoff_ticks: usize,
+    reconcile_ticks: usize,
+    /// Amount of friend capacities corrected by reconciliation
+    num_corrections: u64,
+    /// Recent responses to route requests
+    route_cache: RouteCache,
     conn_status: ConnStatus<ISA>,
     db_client: DatabaseClient<IndexClientConfigMutation<ISA>>,
     spawner: S,
 }
 
+/// Remember the capacities of friends sent to an index server in `mutations`.
+fn record_sent_mutations(sent_friends: &SentFriends, mutations: &[IndexMutation]) {
+    let mut sent_friends = sent_friends.lock().unwrap();
+    for mutation in mutations {
+        match mutation {
+            IndexMutation::UpdateFriend(update_friend) => {
+                let capacities = (update_friend.send_capacity, update_friend.recv_capacity);
+                sent_friends.insert(update_friend.public_key.clone(), capacities);
+            }
+            IndexMutation::RemoveFriend(public_key) => {
+                sent_friends.remove(public_key);
+            }
+        }
+    }
+}
+
+/// Create mutations that bring the capacities sent to an index server (`sent_friends`) in line
+/// with the current capacities of our friends (`friends`).
+fn correction_mutations(
+    sent_friends: &HashMap<PublicKey, (u128, u128)>,
+    friends: &HashMap<PublicKey, (u128, u128)>,
+) -> Vec<IndexMutation> {
+    let mut mutations = Vec::new();
+    for (public_key, &(send_capacity, recv_capacity)) in friends {
+        if sent_friends.get(public_key) != Some(&(send_capacity, recv_capacity)) {
+            mutations.push(IndexMutation::UpdateFriend(UpdateFriend {
+                public_key: public_key.clone(),
+                send_capacity,
+                recv_capacity,
+            }));
+        }
+    }
+    for public_key in sent_friends.keys() {
+        if !friends.contains_key(public_key) {
+            mutations.push(IndexMutation::RemoveFriend(public_key.clone()));
+        }
+    }
+    mutations
+}
+
 /// Send our full friends state as mutations to the server.
 /// We do this in a separate task so that we don't block user requests or incoming funder reports.
 async fn send_full_state(
     mut seq_friends_client: SeqFriendsClient,
     mut control_sender: ControlSender,
+    sent_friends: SentFriends,
 ) -> Result<(), IndexClientError> {
     await!(seq_friends_client.reset_countdown()).map_err(|_| IndexClientError::SeqFriendsError)?;
 
@@ -156,6 +218,7 @@ async fn send_full_state(
         // TODO: Maybe send mutations in batches in the future:
         // However, we need to be careful to not send too many mutations in one batch.
         let mutations = vec![IndexMutation::UpdateFriend(update_friend)];
+        record_sent_mutations(&sent_friends, &mutations);
         if await!(control_sender.send(SingleClientControl::SendMutations(mutations))).is_err() {
             break;
         }
@@ -188,6 +251,8 @@ where
         max_open_requests: usize,
         keepalive_ticks: usize,
         backoff_ticks: usize,
+        reconcile_ticks: usize,
+        route_cache_limits: CacheLimits,
         db_client: DatabaseClient<IndexClientConfigMutation<ISA>>,
         spawner: S,
     ) -> Self {
@@ -210,6 +275,9 @@ where
             num_open_requests: 0,
             keepalive_ticks,
             backoff_ticks,
+            reconcile_ticks,
+            num_corrections: 0,
+            route_cache: RouteCache::new(route_cache_limits),
             conn_status: ConnStatus::Empty(backoff_ticks),
             db_client,
             spawner,
@@ -239,10 +307,14 @@ where
         let mut c_index_client_session = self.index_client_session.clone();
         let mut c_event_sender = self.event_sender.clone();
 
+        // A new connection starts without any sent capacities:
+        let sent_friends: SentFriends = Arc::new(Mutex::new(HashMap::new()));
+
         let (cancel_sender, cancel_receiver) = oneshot::channel();
         let server_connecting = ServerConnecting {
             index_server: index_server.clone(),
             opt_cancel_sender: Some(cancel_sender),
+            sent_friends: sent_friends.clone(),
         };
         self.conn_status = ConnStatus::Connecting(server_connecting);
 
@@ -257,12 +329,25 @@ where
         let connect_fut = Box::pin(
             async move {
                 let res = await!(c_index_client_session.transform(index_server))?;
-                let (control_sender, close_receiver) = res;
+                let (control_sender, close_receiver, full_state_request_receiver) = res;
+
+                // Forward full state requests from the server:
+                let mut c_event_sender_requests = c_event_sender.clone();
+                let mut full_state_requests =
+                    full_state_request_receiver.map(|()| IndexClientEvent::FullStateRequested);
+                c_spawner
+                    .spawn(
+                        async move {
+                            let _ =
+                                await!(c_event_sender_requests.send_all(&mut full_state_requests));
+                        },
+                    )
+                    .ok()?;
 
                 let c_control_sender = control_sender.clone();
                 let send_full_state_cancellable_fut = async move {
                     let send_full_state_fut = Box::pin(
-                        send_full_state(c_seq_friends_client, c_control_sender)
+                        send_full_state(c_seq_friends_client, c_control_sender, sent_friends)
                             .map_err(|e| warn!("Error in send_full_state(): {:?}", e))
                             .map(|_| {
                                 let _ = sfs_done_sender.send(());
@@ -437,6 +522,20 @@ where
             )))
         .map_err(|_| IndexClientError::SendToAppServerFailed)?;
 
+        // Answer from the cache, if possible:
+        if let Some(routes) = self.route_cache.get(&request_routes) {
+            let client_response_routes = ClientResponseRoutes {
+                request_id: request_routes.request_id,
+                result: ResponseRoutesResult::Success(routes),
+            };
+            return await!(self
+                .to_app_server
+                .send(IndexClientToAppServer::ResponseRoutes(
+                    client_response_routes
+                )))
+            .map_err(|_| IndexClientError::SendToAppServerFailed);
+        }
+
         if self.num_open_requests >= self.max_open_requests {
             return await!(self.return_response_routes_failure(request_routes.request_id));
         }
@@ -455,6 +554,8 @@ where
         };
 
         let c_request_id = request_routes.request_id;
+        let allow_partial = request_routes.allow_partial;
+        let route_cache_ticket = self.route_cache.ticket(&request_routes);
         let (response_sender, response_receiver) = oneshot::channel();
         let single_client_control =
             SingleClientControl::RequestRoutes((request_routes, response_sender));
@@ -467,12 +568,19 @@ where
         let mut c_event_sender = self.event_sender.clone();
         let request_fut = async move {
             let response_routes_result = match await!(response_receiver) {
-                Ok(routes) => ResponseRoutesResult::Success(routes),
+                Ok(mut routes) => {
+                    // Partial routes are only passed on if they were requested:
+                    if !allow_partial {
+                        routes.retain(|route| route.meets_request);
+                    }
+                    ResponseRoutesResult::Success(routes)
+                }
                 Err(_) => ResponseRoutesResult::Failure,
             };
             // TODO: Should report error here if failure occurs?
             let _ = await!(c_event_sender.send(IndexClientEvent::ResponseRoutes((
                 c_request_id,
+                route_cache_ticket,
                 response_routes_result
             ))));
         };
@@ -489,6 +597,7 @@ where
     ) -> Result<(), IndexClientError> {
         // Update state:
         for mutation in &mutations {
+            self.route_cache.invalidate(mutation);
             await!(self.seq_friends_client.mutate(mutation.clone()))
                 .map_err(|_| IndexClientError::SeqFriendsError)?;
         }
@@ -515,6 +624,7 @@ where
             mutations.push(IndexMutation::UpdateFriend(update_friend));
         }
 
+        record_sent_mutations(&server_connected.sent_friends, &mutations);
         if let Ok(()) = await!(control_sender.send(SingleClientControl::SendMutations(mutations))) {
             server_connected.opt_control_sender = Some(control_sender);
         }
@@ -553,7 +663,7 @@ where
         &mut self,
         control_sender: ControlSender,
     ) -> Result<(), IndexClientError> {
-        let (index_server, opt_cancel_sender) = match &mut self.conn_status {
+        let (index_server, opt_cancel_sender, sent_friends) = match &mut self.conn_status {
             ConnStatus::Empty(_) => {
                 error!("Did not attempt to connect!");
                 return Ok(());
@@ -565,6 +675,7 @@ where
             ConnStatus::Connecting(server_connecting) => (
                 server_connecting.index_server.clone(),
                 server_connecting.opt_cancel_sender.take(),
+                server_connecting.sent_friends.clone(),
             ),
         };
 
@@ -573,6 +684,8 @@ where
             opt_control_sender: Some(control_sender.clone()),
             opt_cancel_sender,
             ticks_to_send_keepalive: self.keepalive_ticks,
+            sent_friends,
+            ticks_to_reconcile: self.reconcile_ticks,
         });
 
         // Send report:
@@ -611,13 +724,60 @@ where
         Ok(())
     }
 
+    /// The index server detected that some of our mutations were lost on their way to one of the
+    /// index servers. We send the full state of our friends in a single batch of mutations.
+    /// Friends removed during this connection are removed again, in case the removal was lost.
+    pub async fn handle_full_state_requested(&mut self) -> Result<(), IndexClientError> {
+        let friends = await!(self.seq_friends_client.friends())
+            .map_err(|_| IndexClientError::SeqFriendsError)?;
+
+        let server_connected = match &mut self.conn_status {
+            ConnStatus::Empty(_) | ConnStatus::Connecting(_) => return Ok(()), // Not connected
+            ConnStatus::Connected(server_connected) => server_connected,
+        };
+
+        let mut control_sender = match server_connected.opt_control_sender.take() {
+            Some(control_sender) => control_sender,
+            None => return Ok(()),
+        };
+
+        let mut mutations = friends
+            .iter()
+            .map(|(public_key, &(send_capacity, recv_capacity))| {
+                IndexMutation::UpdateFriend(UpdateFriend {
+                    public_key: public_key.clone(),
+                    send_capacity,
+                    recv_capacity,
+                })
+            })
+            .collect::<Vec<_>>();
+        for public_key in server_connected.sent_friends.lock().unwrap().keys() {
+            if !friends.contains_key(public_key) {
+                mutations.push(IndexMutation::RemoveFriend(public_key.clone()));
+            }
+        }
+
+        record_sent_mutations(&server_connected.sent_friends, &mutations);
+        if let Ok(()) = await!(control_sender.send(SingleClientControl::SendMutations(mutations))) {
+            server_connected.opt_control_sender = Some(control_sender);
+        }
+        // Reset ticks_to_send_keepalive:
+        server_connected.ticks_to_send_keepalive = self.keepalive_ticks;
+        Ok(())
+    }
+
     pub async fn handle_response_routes(
         &mut self,
         request_id: Uid,
+        route_cache_ticket: RouteCacheTicket,
         response_routes_result: ResponseRoutesResult,
     ) -> Result<(), IndexClientError> {
         self.num_open_requests = self.num_open_requests.checked_sub(1).unwrap();
 
+        if let ResponseRoutesResult::Success(routes) = &response_routes_result {
+            self.route_cache.insert(route_cache_ticket, routes.clone());
+        }
+
         let client_response_routes = ClientResponseRoutes {
             request_id,
             result: response_routes_result,
@@ -631,7 +791,80 @@ where
         .map_err(|_| IndexClientError::SendToAppServerFailed)
     }
 
+    /// Compare the capacities sent to the connected index server against the current capacities
+    /// of our friends, and correct any divergence.
+    /// A divergence means that an update was lost on its way to the server.
+    async fn reconcile_friends(&mut self) -> Result<(), IndexClientError> {
+        let friends = await!(self.seq_friends_client.friends())
+            .map_err(|_| IndexClientError::SeqFriendsError)?;
+
+        let server_connected = match &mut self.conn_status {
+            ConnStatus::Empty(_) | ConnStatus::Connecting(_) => return Ok(()), // Server is not ready
+            ConnStatus::Connected(server_connected) => server_connected,
+        };
+
+        let mutations =
+            correction_mutations(&server_connected.sent_friends.lock().unwrap(), &friends);
+        if mutations.is_empty() {
+            return Ok(());
+        }
+
+        let mut control_sender = match server_connected.opt_control_sender.take() {
+            Some(control_sender) => control_sender,
+            None => return Ok(()),
+        };
+
+        warn!(
+            "Correcting {} outdated friends on index server {:?}",
+            mutations.len(),
+            server_connected.index_server.public_key
+        );
+        self.num_corrections = self
+            .num_corrections
+            .saturating_add(usize_to_u64(mutations.len()).unwrap());
+
+        record_sent_mutations(&server_connected.sent_friends, &mutations);
+        if let Ok(()) = await!(control_sender.send(SingleClientControl::SendMutations(mutations))) {
+            server_connected.opt_control_sender = Some(control_sender);
+        }
+        // Reset ticks_to_send_keepalive:
+        server_connected.ticks_to_send_keepalive = self.keepalive_ticks;
+
+        // Send report:
+        let index_client_report_mutation =
+            IndexClientReportMutation::SetNumCorrections(self.num_corrections);
+        let index_client_report_mutations = IndexClientReportMutations {
+            opt_app_request_id: None,
+            mutations: vec![index_client_report_mutation],
+        };
+        await!(self
+            .to_app_server
+            .send(IndexClientToAppServer::ReportMutations(
+                index_client_report_mutations
+            )))
+        .map_err(|_| IndexClientError::SendToAppServerFailed)
+    }
+
     pub async fn handle_timer_tick(&mut self) -> Result<(), IndexClientError> {
+        self.route_cache.tick();
+
+        let should_reconcile = match &mut self.conn_status {
+            ConnStatus::Empty(_) | ConnStatus::Connecting(_) => false,
+            ConnStatus::Connected(server_connected) => {
+                server_connected.ticks_to_reconcile =
+                    server_connected.ticks_to_reconcile.saturating_sub(1);
+                if server_connected.ticks_to_reconcile == 0 {
+                    server_connected.ticks_to_reconcile = self.reconcile_ticks;
+                    true
+                } else {
+                    false
+                }
+            }
+        };
+        if should_reconcile {
+            await!(self.reconcile_friends())?;
+        }
+
         // Make sure that we are connected to any server:
         let server_connected: &mut ServerConnected<ISA> = match self.conn_status {
             ConnStatus::Empty(ref mut ticks_to_reconnect) => {
@@ -677,6 +910,7 @@ where
             mutations.push(IndexMutation::UpdateFriend(update_friend));
         }
 
+        record_sent_mutations(&server_connected.sent_friends, &mutations);
         if let Ok(()) = await!(control_sender.send(SingleClientControl::SendMutations(mutations))) {
             server_connected.opt_control_sender = Some(control_sender);
         }
@@ -695,6 +929,8 @@ pub async fn index_client_loop<ISA, FAS, TAS, ICS, TS, S>(
     max_open_requests: usize,
     keepalive_ticks: usize,
     backoff_ticks: usize,
+    reconcile_ticks: usize,
+    route_cache_limits: CacheLimits,
     db_client: DatabaseClient<IndexClientConfigMutation<ISA>>,
     timer_stream: TS,
     spawner: S,
@@ -720,6 +956,8 @@ where
         max_open_requests,
         keepalive_ticks,
         backoff_ticks,
+        reconcile_ticks,
+        route_cache_limits,
         db_client,
         spawner,
     );
@@ -750,9 +988,18 @@ where
             IndexClientEvent::IndexServerClosed => {
                 await!(index_client.handle_index_server_closed())?
             }
-            IndexClientEvent::ResponseRoutes((request_id, response_routes_result)) => {
-                await!(index_client.handle_response_routes(request_id, response_routes_result))?
+            IndexClientEvent::FullStateRequested => {
+                await!(index_client.handle_full_state_requested())?
             }
+            IndexClientEvent::ResponseRoutes((
+                request_id,
+                route_cache_ticket,
+                response_routes_result,
+            )) => await!(index_client.handle_response_routes(
+                request_id,
+                route_cache_ticket,
+                response_routes_result
+            ))?,
             IndexClientEvent::TimerTick => await!(index_client.handle_timer_tick())?,
         };
     }
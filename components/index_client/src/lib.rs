@@ -23,6 +23,7 @@ extern crate common;
 
 mod client_session;
 mod index_client;
+mod route_cache;
 mod seq_friends;
 mod seq_map;
 mod single_client;
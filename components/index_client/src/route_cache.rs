@@ -0,0 +1,302 @@
+use common::bounded_cache::{BoundedCache, CacheLimits};
+
+use crypto::identity::PublicKey;
+
+use proto::index_client::messages::{IndexMutation, RequestRoutes, RouteDisjointness};
+use proto::index_server::messages::RouteWithCapacity;
+
+/// Requests with capacities in the same bucket share a cache entry.
+/// The bucket of a capacity is the position of its highest set bit.
+fn capacity_bucket(capacity: u128) -> u32 {
+    128 - capacity.leading_zeros()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RouteCacheKey {
+    source: PublicKey,
+    destination: PublicKey,
+    capacity_bucket: u32,
+    opt_exclude: Option<(PublicKey, PublicKey)>,
+    disjointness: RouteDisjointness,
+    allow_partial: bool,
+}
+
+impl RouteCacheKey {
+    fn new(request_routes: &RequestRoutes) -> Self {
+        RouteCacheKey {
+            source: request_routes.source.clone(),
+            destination: request_routes.destination.clone(),
+            capacity_bucket: capacity_bucket(request_routes.capacity),
+            opt_exclude: request_routes.opt_exclude.clone(),
+            disjointness: request_routes.disjointness.clone(),
+            allow_partial: request_routes.allow_partial,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct RouteCacheEntry {
+    /// The capacity the routes were requested with
+    capacity: u128,
+    routes: Vec<RouteWithCapacity>,
+}
+
+/// Allows to cache the response to a request that was sent to an index server.
+/// Obtained using `RouteCache::ticket()` before sending the request.
+#[derive(Debug)]
+pub struct RouteCacheTicket {
+    key: RouteCacheKey,
+    capacity: u128,
+    /// The cache generation when the request was sent
+    generation: u64,
+}
+
+/// Recent responses of index servers to route requests.
+///
+/// An entry is kept for `max_age_ticks` ticks, and is dropped earlier if the capacity of one of
+/// our friends changes in a way that might affect its routes.
+pub struct RouteCache {
+    entries: BoundedCache<RouteCacheKey, RouteCacheEntry>,
+    /// Incremented whenever entries are invalidated.
+    /// Responses to requests that were sent before an invalidation are not cached.
+    generation: u64,
+}
+
+impl RouteCache {
+    /// A `max_entries` or `max_age_ticks` of 0 disables the cache.
+    pub fn new(limits: CacheLimits) -> Self {
+        RouteCache {
+            entries: BoundedCache::new(limits),
+            generation: 0,
+        }
+    }
+
+    /// Get cached routes for a request.
+    /// Only routes with at least the requested capacity are returned. Partial routes are never
+    /// returned from the cache.
+    pub fn get(&self, request_routes: &RequestRoutes) -> Option<Vec<RouteWithCapacity>> {
+        let entry = self.entries.get(&RouteCacheKey::new(request_routes))?;
+        let routes = entry
+            .routes
+            .iter()
+            .filter(|route| route.capacity >= request_routes.capacity)
+            .map(|route| RouteWithCapacity {
+                // A partial route of the cached request might meet this request:
+                meets_request: true,
+                ..route.clone()
+            })
+            .collect::<Vec<_>>();
+
+        // We know that there are no routes only if the index server found no routes for the same
+        // capacity or a smaller one:
+        if routes.is_empty()
+            && !(entry.routes.is_empty() && entry.capacity <= request_routes.capacity)
+        {
+            return None;
+        }
+        Some(routes)
+    }
+
+    pub fn ticket(&self, request_routes: &RequestRoutes) -> RouteCacheTicket {
+        RouteCacheTicket {
+            key: RouteCacheKey::new(request_routes),
+            capacity: request_routes.capacity,
+            generation: self.generation,
+        }
+    }
+
+    /// Cache the routes returned by an index server.
+    pub fn insert(&mut self, ticket: RouteCacheTicket, routes: Vec<RouteWithCapacity>) {
+        if self.entries.limits().max_age_ticks == 0 || ticket.generation != self.generation {
+            return;
+        }
+        let entry = RouteCacheEntry {
+            capacity: ticket.capacity,
+            routes,
+        };
+        let _ = self.entries.insert(ticket.key, entry);
+    }
+
+    /// Drop the entries that might be affected by a change to the capacities of one of our
+    /// friends: Entries with a route going through the friend, and entries without any routes
+    /// (The new capacity might create a route).
+    pub fn invalidate(&mut self, mutation: &IndexMutation) {
+        let public_key = match mutation {
+            IndexMutation::UpdateFriend(update_friend) => &update_friend.public_key,
+            IndexMutation::RemoveFriend(public_key) => public_key,
+        };
+        self.generation = self.generation.wrapping_add(1);
+
+        let entries = &self.entries;
+        let affected_keys = entries
+            .keys()
+            .filter(|key| {
+                let routes = &entries.get(key).unwrap().routes;
+                routes.is_empty()
+                    || routes
+                        .iter()
+                        .any(|route| route.route.public_keys.contains(public_key))
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        for key in affected_keys {
+            let _ = self.entries.remove(&key);
+        }
+    }
+
+    pub fn tick(&mut self) {
+        self.entries.tick(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::uid::{Uid, UID_LEN};
+
+    use proto::funder::messages::FriendsRoute;
+    use proto::index_client::messages::UpdateFriend;
+
+    fn pk(i: u8) -> PublicKey {
+        PublicKey::from(&[i; PUBLIC_KEY_LEN])
+    }
+
+    fn request_routes(request_id: u8, capacity: u128) -> RequestRoutes {
+        RequestRoutes {
+            request_id: Uid::from(&[request_id; UID_LEN]),
+            capacity,
+            source: pk(0),
+            destination: pk(3),
+            opt_exclude: None,
+            disjointness: RouteDisjointness::None,
+            allow_partial: false,
+        }
+    }
+
+    fn route_with_capacity(public_keys: Vec<PublicKey>, capacity: u128) -> RouteWithCapacity {
+        RouteWithCapacity {
+            route: FriendsRoute { public_keys },
+            capacity,
+            meets_request: true,
+        }
+    }
+
+    fn create_route_cache(max_age_ticks: usize) -> RouteCache {
+        RouteCache::new(CacheLimits {
+            max_entries: 0x10,
+            max_age_ticks,
+        })
+    }
+
+    #[test]
+    fn test_capacity_bucket() {
+        assert_eq!(capacity_bucket(0), 0);
+        assert_eq!(capacity_bucket(1), 1);
+        assert_eq!(capacity_bucket(2), 2);
+        assert_eq!(capacity_bucket(3), 2);
+        assert_eq!(capacity_bucket(4), 3);
+        assert_eq!(capacity_bucket(u128::max_value()), 128);
+    }
+
+    #[test]
+    fn test_route_cache_hit() {
+        let mut route_cache = create_route_cache(8);
+        let routes = vec![
+            route_with_capacity(vec![pk(0), pk(1), pk(3)], 100),
+            route_with_capacity(vec![pk(0), pk(2), pk(3)], 70),
+        ];
+
+        let request = request_routes(1, 65);
+        assert_eq!(route_cache.get(&request), None);
+        let ticket = route_cache.ticket(&request);
+        route_cache.insert(ticket, routes.clone());
+
+        // A new request, with a capacity in the same bucket:
+        assert_eq!(
+            route_cache.get(&request_routes(2, 65)),
+            Some(routes.clone())
+        );
+        assert_eq!(
+            route_cache.get(&request_routes(3, 90)),
+            Some(vec![routes[0].clone()])
+        );
+        // The index server might know about other routes with a capacity of 127:
+        assert_eq!(route_cache.get(&request_routes(4, 127)), None);
+        // 64 is in the same bucket, but 128 is not:
+        assert_eq!(
+            route_cache.get(&request_routes(5, 64)),
+            Some(routes.clone())
+        );
+        assert_eq!(route_cache.get(&request_routes(6, 128)), None);
+
+        // A different destination:
+        let mut other_request = request_routes(7, 65);
+        other_request.destination = pk(2);
+        assert_eq!(route_cache.get(&other_request), None);
+    }
+
+    #[test]
+    fn test_route_cache_ttl() {
+        let mut route_cache = create_route_cache(3);
+        let routes = vec![route_with_capacity(vec![pk(0), pk(1), pk(3)], 100)];
+        let ticket = route_cache.ticket(&request_routes(1, 100));
+        route_cache.insert(ticket, routes.clone());
+
+        route_cache.tick();
+        route_cache.tick();
+        assert_eq!(route_cache.get(&request_routes(2, 100)), Some(routes));
+        route_cache.tick();
+        assert_eq!(route_cache.get(&request_routes(3, 100)), None);
+
+        // A TTL of 0 disables the cache:
+        let mut route_cache = create_route_cache(0);
+        let ticket = route_cache.ticket(&request_routes(1, 100));
+        route_cache.insert(ticket, Vec::new());
+        assert_eq!(route_cache.get(&request_routes(2, 100)), None);
+    }
+
+    #[test]
+    fn test_route_cache_invalidate() {
+        let mut route_cache = create_route_cache(8);
+        let routes = vec![route_with_capacity(vec![pk(0), pk(1), pk(3)], 100)];
+        let ticket = route_cache.ticket(&request_routes(1, 100));
+        route_cache.insert(ticket, routes.clone());
+
+        // The capacity of a friend that is not on the route changes:
+        let update_friend = UpdateFriend {
+            public_key: pk(2),
+            send_capacity: 50,
+            recv_capacity: 50,
+        };
+        route_cache.invalidate(&IndexMutation::UpdateFriend(update_friend));
+        assert_eq!(
+            route_cache.get(&request_routes(2, 100)),
+            Some(routes.clone())
+        );
+
+        // The capacity of a friend on the route changes:
+        let update_friend = UpdateFriend {
+            public_key: pk(1),
+            send_capacity: 50,
+            recv_capacity: 50,
+        };
+        route_cache.invalidate(&IndexMutation::UpdateFriend(update_friend));
+        assert_eq!(route_cache.get(&request_routes(3, 100)), None);
+
+        // A response to a request sent before an invalidation is not cached:
+        let ticket = route_cache.ticket(&request_routes(4, 100));
+        route_cache.invalidate(&IndexMutation::RemoveFriend(pk(2)));
+        route_cache.insert(ticket, routes.clone());
+        assert_eq!(route_cache.get(&request_routes(5, 100)), None);
+
+        // An empty response is dropped on any change:
+        let ticket = route_cache.ticket(&request_routes(6, 100));
+        route_cache.insert(ticket, Vec::new());
+        assert_eq!(route_cache.get(&request_routes(7, 100)), Some(Vec::new()));
+        route_cache.invalidate(&IndexMutation::RemoveFriend(pk(2)));
+        assert_eq!(route_cache.get(&request_routes(8, 100)), None);
+    }
+}
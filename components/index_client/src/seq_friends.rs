@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnError, SpawnExt};
 use futures::{SinkExt, StreamExt};
@@ -13,6 +15,7 @@ pub enum SeqFriendsRequest {
     Mutate(IndexMutation, oneshot::Sender<()>),
     ResetCountdown(oneshot::Sender<()>),
     NextUpdate(oneshot::Sender<Option<(usize, UpdateFriend)>>),
+    Friends(oneshot::Sender<HashMap<PublicKey, (u128, u128)>>),
 }
 
 #[derive(Debug)]
@@ -67,6 +70,9 @@ async fn seq_friends_loop(
                         });
                 let _ = response_sender.send(update_friend);
             }
+            SeqFriendsRequest::Friends(response_sender) => {
+                let _ = response_sender.send(seq_friends.map().clone());
+            }
         }
     }
 }
@@ -104,6 +110,17 @@ impl SeqFriendsClient {
             .map_err(|_| SeqFriendsClientError::SendRequestError)?;
         Ok(await!(receiver).map_err(|_| SeqFriendsClientError::RecvResponseError)?)
     }
+
+    /// Get the current capacities of all friends.
+    pub async fn friends(
+        &mut self,
+    ) -> Result<HashMap<PublicKey, (u128, u128)>, SeqFriendsClientError> {
+        let (sender, receiver) = oneshot::channel();
+        let request = SeqFriendsRequest::Friends(sender);
+        await!(self.requests_sender.send(request))
+            .map_err(|_| SeqFriendsClientError::SendRequestError)?;
+        Ok(await!(receiver).map_err(|_| SeqFriendsClientError::RecvResponseError)?)
+    }
 }
 
 pub fn create_seq_friends_service<S>(
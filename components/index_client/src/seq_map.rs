@@ -45,6 +45,11 @@ where
         self.map.remove(key)
     }
 
+    /// All the pairs currently in the map.
+    pub fn map(&self) -> &HashMap<K, V> {
+        &self.map
+    }
+
     pub fn reset_countdown(&mut self) {
         self.cycle_countdown = self.queue.len();
     }
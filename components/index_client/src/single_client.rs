@@ -55,6 +55,8 @@ struct SingleClient<TS, R> {
     server_time_hash: HashResult,
     /// Unanswered requests, waiting for a response from the server
     open_requests: HashMap<Uid, oneshot::Sender<Vec<RouteWithCapacity>>>,
+    /// Notifies the IndexClient code that the server requested our full state
+    full_state_request_sender: mpsc::Sender<()>,
 }
 
 impl<TS, R> SingleClient<TS, R>
@@ -69,6 +71,7 @@ where
         to_server: TS,
         session_id: Uid,
         server_time_hash: HashResult,
+        full_state_request_sender: mpsc::Sender<()>,
     ) -> Self {
         SingleClient {
             local_public_key,
@@ -79,6 +82,7 @@ where
             counter: 0,
             server_time_hash,
             open_requests: HashMap::new(),
+            full_state_request_sender,
         }
     }
 
@@ -108,6 +112,15 @@ where
                     );
                 }
             }
+            IndexServerToClient::RequestFullState => {
+                // If a previous request is still pending, the full state was not sent yet, and
+                // this request can be dropped:
+                if let Err(e) = self.full_state_request_sender.try_send(()) {
+                    if e.is_disconnected() {
+                        warn!("Failed to notify about a full state request");
+                    }
+                }
+            }
         }
         Ok(())
     }
@@ -182,6 +195,7 @@ pub async fn first_server_time_hash(
 pub async fn single_client_loop<IC, R>(
     server_conn: ServerConn,
     incoming_control: IC,
+    full_state_request_sender: mpsc::Sender<()>,
     local_public_key: PublicKey,
     identity_client: IdentityClient,
     rng: R,
@@ -201,6 +215,7 @@ where
         to_server,
         session_id,
         first_server_time_hash,
+        full_state_request_sender,
     );
 
     let from_server = from_server
@@ -245,6 +260,7 @@ mod tests {
     use crypto::uid::UID_LEN;
 
     use identity::create_identity;
+    use proto::index_server::messages::RouteDisjointness;
 
     async fn task_first_server_time_hash() {
         let (mut to_server, mut from_server) = mpsc::channel(0);
@@ -287,6 +303,7 @@ mod tests {
         let (mut server_sender, client_receiver) = mpsc::channel(0);
         let (client_sender, mut server_receiver) = mpsc::channel(0);
         let (mut control_sender, incoming_control) = mpsc::channel(0);
+        let (full_state_request_sender, mut full_state_request_receiver) = mpsc::channel(0);
 
         // Create identity_client:
         let rng = DummyRandom::new(&[1u8]);
@@ -304,6 +321,7 @@ mod tests {
         let loop_fut = single_client_loop(
             server_conn,
             incoming_control,
+            full_state_request_sender,
             local_public_key.clone(),
             identity_client,
             rng,
@@ -327,6 +345,8 @@ mod tests {
             source: PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]),
             destination: PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]),
             opt_exclude: None,
+            disjointness: RouteDisjointness::None,
+            allow_partial: false,
         };
 
         let (response_sender, response_receiver) = oneshot::channel();
@@ -380,6 +400,10 @@ mod tests {
                 _ => unreachable!(),
             };
         }
+
+        // Server requests our full state:
+        await!(server_sender.send(IndexServerToClient::RequestFullState)).unwrap();
+        await!(full_state_request_receiver.next()).unwrap();
     }
 
     #[test]
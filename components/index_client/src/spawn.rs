@@ -4,6 +4,7 @@ use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
 use futures::{Future, SinkExt, StreamExt};
 
+use common::bounded_cache::CacheLimits;
 use common::conn::{BoxFuture, ConnPairVec, FutTransform};
 use database::DatabaseClient;
 use identity::IdentityClient;
@@ -122,6 +123,8 @@ pub async fn spawn_index_client<ISA, C, R, S>(
     max_open_index_client_requests: usize,
     keepalive_ticks: usize,
     backoff_ticks: usize,
+    reconcile_ticks: usize,
+    route_cache_limits: CacheLimits,
     net_connector: C,
     rng: R,
     mut spawner: S,
@@ -162,6 +165,8 @@ where
         max_open_index_client_requests,
         keepalive_ticks,
         backoff_ticks,
+        reconcile_ticks,
+        route_cache_limits,
         database_client,
         timer_stream,
         spawner.clone(),
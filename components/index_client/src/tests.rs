@@ -1,17 +1,23 @@
+use std::collections::HashMap;
+
 use futures::channel::{mpsc, oneshot};
 use futures::executor::ThreadPool;
 use futures::task::{Spawn, SpawnExt};
 use futures::{FutureExt, SinkExt, StreamExt, TryFutureExt};
 
+use common::bounded_cache::CacheLimits;
 use common::dummy_connector::{ConnRequest, DummyConnector};
 
 use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
 use crypto::uid::{Uid, UID_LEN};
+use proto::funder::messages::FriendsRoute;
 use proto::index_client::messages::{
     AppServerToIndexClient, IndexClientReportMutation, IndexClientRequest, IndexClientToAppServer,
-    IndexMutation, RequestRoutes, ResponseRoutesResult, UpdateFriend,
+    IndexMutation, RequestRoutes, ResponseRoutesResult, RouteDisjointness, UpdateFriend,
+};
+use proto::index_server::messages::{
+    IndexServerAddress, NamedIndexServerAddress, RouteWithCapacity,
 };
-use proto::index_server::messages::{IndexServerAddress, NamedIndexServerAddress};
 
 use database::{DatabaseClient, DatabaseRequest};
 
@@ -34,6 +40,7 @@ struct IndexClientControl<ISA> {
     #[allow(unused)]
     keepalive_ticks: usize,
     backoff_ticks: usize,
+    reconcile_ticks: usize,
 }
 
 /// Create a basic IndexClientControl, used for testing
@@ -65,6 +72,11 @@ where
     let max_open_requests = 2;
     let keepalive_ticks = 8;
     let backoff_ticks = 4;
+    let reconcile_ticks = 4;
+    let route_cache_limits = CacheLimits {
+        max_entries: 0x10,
+        max_age_ticks: 0x10,
+    };
 
     let (tick_sender, timer_stream) = mpsc::channel::<()>(0);
 
@@ -77,6 +89,8 @@ where
         max_open_requests,
         keepalive_ticks,
         backoff_ticks,
+        reconcile_ticks,
+        route_cache_limits,
         db_client,
         timer_stream,
         spawner.clone(),
@@ -96,6 +110,7 @@ where
         max_open_requests,
         keepalive_ticks,
         backoff_ticks,
+        reconcile_ticks,
     }
 }
 
@@ -127,6 +142,21 @@ where
     ) -> (
         mpsc::Receiver<SingleClientControl>,
         oneshot::Sender<Result<(), SingleClientError>>,
+    ) {
+        let (control_receiver, close_sender, _full_state_request_sender) =
+            await!(self.expect_server_connection_with_requests(index_server));
+        (control_receiver, close_sender)
+    }
+
+    /// Expect a connection to index server of a certain public key.
+    /// Also returns a sender for simulating full state requests from the server.
+    async fn expect_server_connection_with_requests(
+        &mut self,
+        index_server: IndexServerAddress<ISA>,
+    ) -> (
+        mpsc::Receiver<SingleClientControl>,
+        oneshot::Sender<Result<(), SingleClientError>>,
+        mpsc::Sender<()>,
     ) {
         // Wait for a connection request:
         let session_conn_request = await!(self.session_receiver.next()).unwrap();
@@ -135,7 +165,12 @@ where
         // Send a SessionHandle back to the index client:
         let (control_sender, mut control_receiver) = mpsc::channel(0);
         let (close_sender, close_receiver) = oneshot::channel();
-        session_conn_request.reply(Some((control_sender, close_receiver)));
+        let (full_state_request_sender, full_state_request_receiver) = mpsc::channel(0);
+        session_conn_request.reply(Some((
+            control_sender,
+            close_receiver,
+            full_state_request_receiver,
+        )));
 
         // We should be notified that a connection to a server was established:
         await!(self.expect_set_connected_server(Some(index_server.public_key)));
@@ -166,7 +201,7 @@ where
             _ => unreachable!(),
         };
 
-        (control_receiver, close_sender)
+        (control_receiver, close_sender, full_state_request_sender)
     }
 
     /// Add an index server to the IndexClient (From AppServer)
@@ -184,7 +219,7 @@ where
                 named_index_server_address.clone()
             )]
         );
-        db_request.response_sender.send(()).unwrap();
+        db_request.response_sender.send(Some(())).unwrap();
 
         match await!(self.app_server_receiver.next()).unwrap() {
             IndexClientToAppServer::ReportMutations(mut ic_report_mutations) => {
@@ -219,7 +254,7 @@ where
                 public_key.clone()
             )]
         );
-        db_request.response_sender.send(()).unwrap();
+        db_request.response_sender.send(Some(())).unwrap();
 
         match await!(self.app_server_receiver.next()).unwrap() {
             IndexClientToAppServer::ReportMutations(mut ic_report_mutations) => {
@@ -291,7 +326,12 @@ where
     // Send a SessionHandle back to the index client:
     let (control_sender, _control_receiver) = mpsc::channel(0);
     let (_close_sender, close_receiver) = oneshot::channel();
-    session_conn_request.reply(Some((control_sender, close_receiver)));
+    let (_full_state_request_sender, full_state_request_receiver) = mpsc::channel(0);
+    session_conn_request.reply(Some((
+        control_sender,
+        close_receiver,
+        full_state_request_receiver,
+    )));
 
     // A new connection should be made to 0x1339:
     await!(icc.expect_set_connected_server(Some(PublicKey::from(&[0x39; PUBLIC_KEY_LEN]))));
@@ -387,6 +427,8 @@ where
         source: PublicKey::from(PublicKey::from(&[0xee; PUBLIC_KEY_LEN])),
         destination: PublicKey::from(PublicKey::from(&[0xff; PUBLIC_KEY_LEN])),
         opt_exclude: None,
+        disjointness: RouteDisjointness::None,
+        allow_partial: false,
     };
 
     // Request routes from IndexClient (From AppServer):
@@ -440,6 +482,227 @@ fn test_index_client_loop_request_routes_basic() {
     ));
 }
 
+impl<ISA> IndexClientControl<ISA>
+where
+    ISA: std::cmp::Eq + std::fmt::Debug + Clone,
+{
+    /// Send a routes request (From AppServer), and wait for the empty report mutations
+    async fn send_request_routes(&mut self, app_request_id: Uid, request_routes: RequestRoutes) {
+        let app_server_to_index_client = AppServerToIndexClient::AppRequest((
+            app_request_id,
+            IndexClientRequest::RequestRoutes(request_routes),
+        ));
+        await!(self.app_server_sender.send(app_server_to_index_client)).unwrap();
+
+        match await!(self.app_server_receiver.next()).unwrap() {
+            IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+                assert_eq!(ic_report_mutations.opt_app_request_id, Some(app_request_id));
+                assert!(ic_report_mutations.mutations.is_empty());
+            }
+            _ => unreachable!(),
+        };
+    }
+
+    async fn expect_response_routes(&mut self, request_id: Uid) -> Vec<RouteWithCapacity> {
+        match await!(self.app_server_receiver.next()).unwrap() {
+            IndexClientToAppServer::ResponseRoutes(client_response_routes) => {
+                assert_eq!(client_response_routes.request_id, request_id);
+                match client_response_routes.result {
+                    ResponseRoutesResult::Success(routes) => routes,
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+async fn task_index_client_loop_request_routes_cache<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let mut icc = basic_index_client(spawner.clone());
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PUBLIC_KEY_LEN]),
+        address: 0x1337,
+    };
+    let (mut control_receiver, _close_sender) = await!(icc.expect_server_connection(index_server));
+
+    let create_request_routes = |i: u8| RequestRoutes {
+        request_id: Uid::from(&[i; UID_LEN]),
+        capacity: 250,
+        source: PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
+        destination: PublicKey::from(&[0xff; PUBLIC_KEY_LEN]),
+        opt_exclude: None,
+        disjointness: RouteDisjointness::None,
+        allow_partial: false,
+    };
+    let routes = vec![RouteWithCapacity {
+        route: FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[0xff; PUBLIC_KEY_LEN]),
+            ],
+        },
+        capacity: 300,
+        meets_request: true,
+    }];
+
+    // The first request is forwarded to the server:
+    await!(icc.send_request_routes(Uid::from(&[50; UID_LEN]), create_request_routes(1)));
+    match await!(control_receiver.next()).unwrap() {
+        SingleClientControl::RequestRoutes((request_routes0, response_sender)) => {
+            assert_eq!(request_routes0, create_request_routes(1));
+            response_sender.send(routes.clone()).unwrap();
+        }
+        _ => unreachable!(),
+    };
+    let routes0 = await!(icc.expect_response_routes(Uid::from(&[1; UID_LEN])));
+    assert_eq!(routes0, routes);
+
+    // An identical request is answered from the cache:
+    await!(icc.send_request_routes(Uid::from(&[51; UID_LEN]), create_request_routes(2)));
+    let routes0 = await!(icc.expect_response_routes(Uid::from(&[2; UID_LEN])));
+    assert_eq!(routes0, routes);
+
+    // The capacity of a friend on the route changes:
+    let index_mutation = IndexMutation::UpdateFriend(UpdateFriend {
+        public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+        send_capacity: 200,
+        recv_capacity: 100,
+    });
+    await!(icc
+        .app_server_sender
+        .send(AppServerToIndexClient::ApplyMutations(vec![
+            index_mutation.clone()
+        ])))
+    .unwrap();
+
+    match await!(icc.seq_friends_receiver.next()).unwrap() {
+        SeqFriendsRequest::Mutate(index_mutation0, response_sender) => {
+            assert_eq!(index_mutation0, index_mutation);
+            response_sender.send(()).unwrap();
+        }
+        _ => unreachable!(),
+    };
+    match await!(icc.seq_friends_receiver.next()).unwrap() {
+        SeqFriendsRequest::NextUpdate(response_sender) => {
+            response_sender.send(None).unwrap();
+        }
+        _ => unreachable!(),
+    };
+    match await!(control_receiver.next()).unwrap() {
+        SingleClientControl::SendMutations(mutations0) => {
+            assert_eq!(mutations0, vec![index_mutation]);
+        }
+        _ => unreachable!(),
+    };
+
+    // The cached routes were dropped, so the next request is forwarded to the server again.
+    // (The second request never reached the server):
+    await!(icc.send_request_routes(Uid::from(&[52; UID_LEN]), create_request_routes(3)));
+    match await!(control_receiver.next()).unwrap() {
+        SingleClientControl::RequestRoutes((request_routes0, response_sender)) => {
+            assert_eq!(request_routes0, create_request_routes(3));
+            response_sender.send(vec![]).unwrap();
+        }
+        _ => unreachable!(),
+    };
+    let routes0 = await!(icc.expect_response_routes(Uid::from(&[3; UID_LEN])));
+    assert!(routes0.is_empty());
+}
+
+#[test]
+fn test_index_client_loop_request_routes_cache() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_index_client_loop_request_routes_cache(
+        thread_pool.clone(),
+    ));
+}
+
+async fn task_index_client_loop_request_routes_partial<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let mut icc = basic_index_client(spawner.clone());
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PUBLIC_KEY_LEN]),
+        address: 0x1337,
+    };
+    let (mut control_receiver, _close_sender) = await!(icc.expect_server_connection(index_server));
+
+    let create_request_routes = |i: u8, allow_partial: bool| RequestRoutes {
+        request_id: Uid::from(&[i; UID_LEN]),
+        capacity: 100,
+        source: PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
+        destination: PublicKey::from(&[0xff; PUBLIC_KEY_LEN]),
+        opt_exclude: None,
+        disjointness: RouteDisjointness::NodeDisjoint(2),
+        allow_partial,
+    };
+    // Two disjoint routes that have the requested capacity only together:
+    let partial_routes = vec![0xaa, 0xbb]
+        .into_iter()
+        .zip(vec![60, 50])
+        .map(|(i, capacity)| RouteWithCapacity {
+            route: FriendsRoute {
+                public_keys: vec![
+                    PublicKey::from(&[0xee; PUBLIC_KEY_LEN]),
+                    PublicKey::from(&[i; PUBLIC_KEY_LEN]),
+                    PublicKey::from(&[0xff; PUBLIC_KEY_LEN]),
+                ],
+            },
+            capacity,
+            meets_request: false,
+        })
+        .collect::<Vec<_>>();
+
+    // Partial routes are requested:
+    await!(icc.send_request_routes(Uid::from(&[50; UID_LEN]), create_request_routes(1, true)));
+    match await!(control_receiver.next()).unwrap() {
+        SingleClientControl::RequestRoutes((request_routes0, response_sender)) => {
+            assert_eq!(request_routes0, create_request_routes(1, true));
+            response_sender.send(partial_routes.clone()).unwrap();
+        }
+        _ => unreachable!(),
+    };
+    let routes0 = await!(icc.expect_response_routes(Uid::from(&[1; UID_LEN])));
+    assert_eq!(routes0, partial_routes);
+
+    // Partial routes are not requested. Partial routes sent by the server are not passed on:
+    await!(icc.send_request_routes(Uid::from(&[51; UID_LEN]), create_request_routes(2, false)));
+    match await!(control_receiver.next()).unwrap() {
+        SingleClientControl::RequestRoutes((request_routes0, response_sender)) => {
+            assert_eq!(request_routes0, create_request_routes(2, false));
+            response_sender.send(partial_routes.clone()).unwrap();
+        }
+        _ => unreachable!(),
+    };
+    let routes0 = await!(icc.expect_response_routes(Uid::from(&[2; UID_LEN])));
+    assert!(routes0.is_empty());
+
+    // Partial routes are not served from the cache:
+    await!(icc.send_request_routes(Uid::from(&[52; UID_LEN]), create_request_routes(3, true)));
+    match await!(control_receiver.next()).unwrap() {
+        SingleClientControl::RequestRoutes((request_routes0, response_sender)) => {
+            assert_eq!(request_routes0, create_request_routes(3, true));
+            response_sender.send(partial_routes.clone()).unwrap();
+        }
+        _ => unreachable!(),
+    };
+    let routes0 = await!(icc.expect_response_routes(Uid::from(&[3; UID_LEN])));
+    assert_eq!(routes0, partial_routes);
+}
+
+#[test]
+fn test_index_client_loop_request_routes_partial() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_index_client_loop_request_routes_partial(
+        thread_pool.clone(),
+    ));
+}
+
 async fn task_index_client_loop_connecting_state<S>(spawner: S)
 where
     S: Spawn + Clone + Send + 'static,
@@ -491,6 +754,8 @@ where
         source: PublicKey::from(PublicKey::from(&[0xee; PUBLIC_KEY_LEN])),
         destination: PublicKey::from(PublicKey::from(&[0xff; PUBLIC_KEY_LEN])),
         opt_exclude: None,
+        disjointness: RouteDisjointness::None,
+        allow_partial: false,
     };
 
     // Request routes from IndexClient (From AppServer):
@@ -540,4 +805,188 @@ fn test_index_client_loop_connecting_state() {
     thread_pool.run(task_index_client_loop_connecting_state(thread_pool.clone()));
 }
 
+async fn task_index_client_loop_reconcile_friends<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let mut icc = basic_index_client(spawner.clone());
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PUBLIC_KEY_LEN]),
+        address: 0x1337,
+    };
+    // The full state sent to the server contains the 0xaa friend:
+    let (mut control_receiver, _close_sender) = await!(icc.expect_server_connection(index_server));
+
+    // The update of the 0xbb friend was lost before it was sent to the server:
+    let public_key_aa = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let public_key_bb = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let mut friends = HashMap::new();
+    friends.insert(public_key_aa.clone(), (100, 50));
+    friends.insert(public_key_bb.clone(), (200, 100));
+
+    for _ in 0..icc.reconcile_ticks {
+        await!(icc.tick_sender.send(())).unwrap();
+    }
+
+    match await!(icc.seq_friends_receiver.next()).unwrap() {
+        SeqFriendsRequest::Friends(response_sender) => {
+            response_sender.send(friends.clone()).unwrap();
+        }
+        _ => unreachable!(),
+    };
+
+    // Only the outdated friend is sent again:
+    match await!(control_receiver.next()).unwrap() {
+        SingleClientControl::SendMutations(mutations) => {
+            let update_friend = UpdateFriend {
+                public_key: public_key_bb.clone(),
+                send_capacity: 200,
+                recv_capacity: 100,
+            };
+            assert_eq!(mutations, vec![IndexMutation::UpdateFriend(update_friend)]);
+        }
+        _ => unreachable!(),
+    };
+
+    match await!(icc.app_server_receiver.next()).unwrap() {
+        IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+            assert_eq!(ic_report_mutations.opt_app_request_id, None);
+            assert_eq!(
+                ic_report_mutations.mutations,
+                vec![IndexClientReportMutation::SetNumCorrections(1)]
+            );
+        }
+        _ => unreachable!(),
+    };
+
+    // The removal of the 0xaa friend was lost too:
+    friends.remove(&public_key_aa);
+
+    for _ in 0..icc.reconcile_ticks {
+        await!(icc.tick_sender.send(())).unwrap();
+    }
+
+    match await!(icc.seq_friends_receiver.next()).unwrap() {
+        SeqFriendsRequest::Friends(response_sender) => {
+            response_sender.send(friends).unwrap();
+        }
+        _ => unreachable!(),
+    };
+
+    // The 0xbb friend is already up to date on the server:
+    match await!(control_receiver.next()).unwrap() {
+        SingleClientControl::SendMutations(mutations) => {
+            assert_eq!(mutations, vec![IndexMutation::RemoveFriend(public_key_aa)]);
+        }
+        _ => unreachable!(),
+    };
+
+    match await!(icc.app_server_receiver.next()).unwrap() {
+        IndexClientToAppServer::ReportMutations(ic_report_mutations) => {
+            assert_eq!(
+                ic_report_mutations.mutations,
+                vec![IndexClientReportMutation::SetNumCorrections(2)]
+            );
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_index_client_loop_reconcile_friends() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_index_client_loop_reconcile_friends(
+        thread_pool.clone(),
+    ));
+}
+
+async fn task_index_client_loop_reconnect_full_state<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let mut icc = basic_index_client(spawner.clone());
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PUBLIC_KEY_LEN]),
+        address: 0x1337,
+    };
+    let (_control_receiver, close_sender) =
+        await!(icc.expect_server_connection(index_server.clone()));
+
+    // The server goes offline:
+    let _ = close_sender.send(Err(SingleClientError::ServerClosed));
+    await!(icc.expect_set_connected_server(None));
+
+    for _ in 0..icc.backoff_ticks {
+        await!(icc.tick_sender.send(())).unwrap();
+    }
+
+    // After reconnecting, the full state is sent to the server again:
+    let (_control_receiver, _close_sender) = await!(icc.expect_server_connection(index_server));
+}
+
+#[test]
+fn test_index_client_loop_reconnect_full_state() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_index_client_loop_reconnect_full_state(
+        thread_pool.clone(),
+    ));
+}
+
+async fn task_index_client_loop_full_state_requested<S>(spawner: S)
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    let mut icc = basic_index_client(spawner.clone());
+    let index_server = IndexServerAddress {
+        public_key: PublicKey::from(&[0x37; PUBLIC_KEY_LEN]),
+        address: 0x1337,
+    };
+    // The full state sent to the server contains the 0xaa friend:
+    let (mut control_receiver, _close_sender, mut full_state_request_sender) =
+        await!(icc.expect_server_connection_with_requests(index_server));
+
+    // The 0xaa friend was removed, and the 0xbb friend was added:
+    let public_key_aa = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+    let public_key_bb = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+    let mut friends = HashMap::new();
+    friends.insert(public_key_bb.clone(), (200, 100));
+
+    // Some of our mutations were lost on their way to one of the servers:
+    await!(full_state_request_sender.send(())).unwrap();
+
+    match await!(icc.seq_friends_receiver.next()).unwrap() {
+        SeqFriendsRequest::Friends(response_sender) => {
+            response_sender.send(friends).unwrap();
+        }
+        _ => unreachable!(),
+    };
+
+    // All of our friends are sent in one batch, together with the removal of the 0xaa friend:
+    match await!(control_receiver.next()).unwrap() {
+        SingleClientControl::SendMutations(mutations) => {
+            let update_friend = UpdateFriend {
+                public_key: public_key_bb,
+                send_capacity: 200,
+                recv_capacity: 100,
+            };
+            assert_eq!(
+                mutations,
+                vec![
+                    IndexMutation::UpdateFriend(update_friend),
+                    IndexMutation::RemoveFriend(public_key_aa)
+                ]
+            );
+        }
+        _ => unreachable!(),
+    };
+}
+
+#[test]
+fn test_index_client_loop_full_state_requested() {
+    let mut thread_pool = ThreadPool::new().unwrap();
+    thread_pool.run(task_index_client_loop_full_state_requested(
+        thread_pool.clone(),
+    ));
+}
+
 // TODO: Add more tests.
@@ -110,7 +110,7 @@ mod tests {
                     req.reply(None); // Connection failed
                     let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
                     for _ in 0..8usize {
-                        await!(tick_sender.send(TimerTick)).unwrap();
+                        await!(tick_sender.send(TimerTick { ticks_elapsed: 1 })).unwrap();
                     }
                 }
                 let req = await!(req_receiver.next()).unwrap();
@@ -0,0 +1,213 @@
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+
+use common::access_control::{AccessControl, AccessControlOp};
+use common::conn::{BoxFuture, ConnPairVec, FutTransform, Listener};
+
+use crypto::identity::PublicKey;
+
+use proto::app_server::messages::RelayAddress;
+use proto::index_server::messages::FederationAddress;
+
+use timer::utils::sleep_ticks;
+use timer::TimerClient;
+
+/// Open an encrypted connection to a relay.
+#[derive(Clone)]
+pub struct EncRelayConnector<C, VT, ET> {
+    net_connector: C,
+    version_transform: VT,
+    encrypt_transform: ET,
+}
+
+impl<C, VT, ET> EncRelayConnector<C, VT, ET> {
+    pub fn new(net_connector: C, version_transform: VT, encrypt_transform: ET) -> Self {
+        EncRelayConnector {
+            net_connector,
+            version_transform,
+            encrypt_transform,
+        }
+    }
+}
+
+impl<A, C, VT, ET> FutTransform for EncRelayConnector<C, VT, ET>
+where
+    A: Send + 'static,
+    C: FutTransform<Input = A, Output = Option<ConnPairVec>> + Send,
+    VT: FutTransform<Input = ConnPairVec, Output = ConnPairVec> + Send,
+    ET: FutTransform<
+            Input = (Option<PublicKey>, ConnPairVec),
+            Output = Option<(PublicKey, ConnPairVec)>,
+        > + Send,
+{
+    type Input = RelayAddress<A>;
+    type Output = Option<ConnPairVec>;
+
+    fn transform(&mut self, relay_address: Self::Input) -> BoxFuture<'_, Self::Output> {
+        Box::pin(
+            async move {
+                let conn_pair = await!(self.net_connector.transform(relay_address.address))?;
+                let conn_pair = await!(self.version_transform.transform(conn_pair));
+                let (_public_key, conn_pair) = await!(self
+                    .encrypt_transform
+                    .transform((Some(relay_address.public_key), conn_pair)))?;
+                Some(conn_pair)
+            },
+        )
+    }
+}
+
+/// Open a raw connection to a trusted index server, either directly or through a relay.
+/// The layers above (Version prefix, encryption, keepalives) are the same in both cases.
+#[derive(Clone)]
+pub struct FederationConnector<C, RC> {
+    net_connector: C,
+    relay_connector: RC,
+}
+
+impl<C, RC> FederationConnector<C, RC> {
+    pub fn new(net_connector: C, relay_connector: RC) -> Self {
+        FederationConnector {
+            net_connector,
+            relay_connector,
+        }
+    }
+}
+
+impl<A, C, RC> FutTransform for FederationConnector<C, RC>
+where
+    C: FutTransform<Input = A, Output = Option<ConnPairVec>> + Send,
+    RC: FutTransform<Input = (RelayAddress<A>, PublicKey), Output = Option<ConnPairVec>> + Send,
+{
+    type Input = (PublicKey, FederationAddress<A>);
+    type Output = Option<ConnPairVec>;
+
+    fn transform(&mut self, input: Self::Input) -> BoxFuture<'_, Self::Output> {
+        let (public_key, address) = input;
+        match address {
+            FederationAddress::Direct(address) => self.net_connector.transform(address),
+            FederationAddress::Relay(relay_address) => {
+                self.relay_connector.transform((relay_address, public_key))
+            }
+        }
+    }
+}
+
+/// Listen through a relay for connections from trusted index servers.
+/// Whenever the connection to the relay is lost, we wait `backoff_ticks` and listen again.
+pub async fn relay_listen_loop<A, L>(
+    listener: L,
+    relay_address: RelayAddress<A>,
+    trusted_public_keys: Vec<PublicKey>,
+    mut conns_sender: mpsc::Sender<ConnPairVec>,
+    timer_client: TimerClient,
+    backoff_ticks: usize,
+) where
+    A: Clone,
+    L: Listener<
+            Connection = (PublicKey, ConnPairVec),
+            Config = AccessControlOp<PublicKey>,
+            Arg = (RelayAddress<A>, AccessControl<PublicKey>),
+        > + Clone,
+{
+    let mut access_control = AccessControl::new();
+    for public_key in trusted_public_keys {
+        access_control.apply_op(AccessControlOp::Add(public_key));
+    }
+
+    loop {
+        // The listener stops if _access_control_sender is dropped:
+        let (_access_control_sender, mut connections) = listener
+            .clone()
+            .listen((relay_address.clone(), access_control.clone()));
+
+        // The public key of the remote server is verified later, when the connection is
+        // encrypted:
+        while let Some((_public_key, conn_pair)) = await!(connections.next()) {
+            if await!(conns_sender.send(conn_pair)).is_err() {
+                return;
+            }
+        }
+        warn!("relay_listen_loop(): Lost connection to relay");
+
+        // Wait before we attempt to listen again:
+        if await!(sleep_ticks(backoff_ticks, timer_client.clone())).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::ThreadPool;
+    use futures::task::{Spawn, SpawnExt};
+
+    use common::dummy_connector::DummyConnector;
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    async fn task_federation_connector_basic<S>(mut spawner: S)
+    where
+        S: Spawn,
+    {
+        let (net_req_sender, mut net_req_receiver) = mpsc::channel(0);
+        let net_connector = DummyConnector::<u32, Option<ConnPairVec>>::new(net_req_sender);
+
+        let (relay_req_sender, mut relay_req_receiver) = mpsc::channel(0);
+        let relay_connector =
+            DummyConnector::<(RelayAddress<u32>, PublicKey), Option<ConnPairVec>>::new(
+                relay_req_sender,
+            );
+
+        let federation_connector = FederationConnector::new(net_connector, relay_connector);
+
+        let server_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let relay_address = RelayAddress {
+            public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            address: 11u32,
+        };
+
+        // A direct address is connected using the net connector:
+        let mut c_federation_connector = federation_connector.clone();
+        let c_server_public_key = server_public_key.clone();
+        let fut_conn = spawner
+            .spawn_with_handle(
+                async move {
+                    await!(c_federation_connector
+                        .transform((c_server_public_key, FederationAddress::Direct(10u32))))
+                },
+            )
+            .unwrap();
+        let req = await!(net_req_receiver.next()).unwrap();
+        assert_eq!(req.address, 10u32);
+        req.reply(None);
+        assert!(await!(fut_conn).is_none());
+
+        // A relay address is connected through the relay:
+        let mut c_federation_connector = federation_connector.clone();
+        let c_server_public_key = server_public_key.clone();
+        let c_relay_address = relay_address.clone();
+        let fut_conn = spawner
+            .spawn_with_handle(
+                async move {
+                    await!(c_federation_connector.transform((
+                        c_server_public_key,
+                        FederationAddress::Relay(c_relay_address)
+                    )))
+                },
+            )
+            .unwrap();
+        let req = await!(relay_req_receiver.next()).unwrap();
+        assert_eq!(req.address, (relay_address, server_public_key));
+        let (sender, receiver) = mpsc::channel(0);
+        req.reply(Some((sender, receiver)));
+        assert!(await!(fut_conn).is_some());
+    }
+
+    #[test]
+    fn test_federation_connector_basic() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_federation_connector_basic(thread_pool.clone()));
+    }
+}
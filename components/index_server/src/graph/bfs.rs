@@ -1,7 +1,12 @@
 use std::collections::{HashMap, HashSet, VecDeque};
 use std::{cmp, hash};
 
-fn bfs_loop<'c, I, N, F>(src: &'c N, dst: &'c N, get_neighbors: F) -> Option<HashMap<N, Option<N>>>
+fn bfs_loop<'c, I, N, F>(
+    src: &'c N,
+    dst: &'c N,
+    max_route_len: usize,
+    get_neighbors: F,
+) -> Option<HashMap<N, Option<N>>>
 where
     I: Iterator<Item = &'c N>,
     F: Fn(&N) -> I,
@@ -9,13 +14,18 @@ where
 {
     let mut backtrack: HashMap<N, Option<N>> = HashMap::new();
     let mut visited: HashSet<N> = HashSet::new();
-    let mut queue: VecDeque<N> = VecDeque::new();
+    // Nodes to visit, together with the length of the route from src to the node:
+    let mut queue: VecDeque<(N, usize)> = VecDeque::new();
 
     backtrack.insert(src.clone(), None);
-    queue.push_back(src.clone());
+    queue.push_back((src.clone(), 1));
     visited.insert(src.clone());
 
-    while let Some(node) = queue.pop_front() {
+    while let Some((node, route_len)) = queue.pop_front() {
+        if route_len >= max_route_len {
+            // Continuing from this node will create a route that is too long.
+            continue;
+        }
         for neighbor in get_neighbors(&node) {
             if visited.contains(&neighbor) {
                 continue;
@@ -24,7 +34,7 @@ where
             if neighbor == dst {
                 return Some(backtrack);
             }
-            queue.push_back(neighbor.clone());
+            queue.push_back((neighbor.clone(), route_len + 1));
             visited.insert(neighbor.clone());
         }
     }
@@ -49,13 +59,20 @@ where
     Some(route)
 }
 
-pub fn bfs<'c, I, N, F>(src: &'c N, dst: &'c N, get_neighbors: F) -> Option<Vec<N>>
+/// Find a shortest route from `src` to `dst`.
+/// Routes with more than `max_route_len` nodes are never constructed.
+pub fn bfs<'c, I, N, F>(
+    src: &'c N,
+    dst: &'c N,
+    max_route_len: usize,
+    get_neighbors: F,
+) -> Option<Vec<N>>
 where
     I: Iterator<Item = &'c N>,
     F: Fn(&N) -> I,
     N: Clone + cmp::Eq + hash::Hash,
 {
-    let backtrack = bfs_loop(src, dst, get_neighbors)?;
+    let backtrack = bfs_loop(src, dst, max_route_len, get_neighbors)?;
     bfs_backtrack(dst, &backtrack)
 }
 
@@ -63,6 +80,8 @@ where
 mod tests {
     use super::*;
 
+    const MAX_ROUTE_LEN: usize = 16;
+
     #[test]
     fn test_bfs_backtrack_basic() {
         let mut backtrack: HashMap<u32, Option<u32>> = HashMap::new();
@@ -128,20 +147,38 @@ mod tests {
         graph.insert(9, vec![]);
 
         let get_neighbors = |node: &u32| graph.get(&node).unwrap().iter();
-        assert_eq!(bfs(&0, &1, get_neighbors), Some(vec![0, 1]));
-        assert_eq!(bfs(&1, &0, get_neighbors), Some(vec![1, 2, 3, 0]));
+        assert_eq!(bfs(&0, &1, MAX_ROUTE_LEN, get_neighbors), Some(vec![0, 1]));
+        assert_eq!(bfs(&1, &0, MAX_ROUTE_LEN, get_neighbors), Some(vec![1, 2, 3, 0]));
 
         assert_eq!(
-            bfs(&0, &9, get_neighbors),
+            bfs(&0, &9, MAX_ROUTE_LEN, get_neighbors),
             Some(vec![0, 1, 2, 3, 4, 6, 8, 9])
         );
 
-        assert_eq!(bfs(&8, &6, get_neighbors), None);
-        assert_eq!(bfs(&9, &8, get_neighbors), None);
-        assert_eq!(bfs(&5, &4, get_neighbors), None);
-        assert_eq!(bfs(&4, &3, get_neighbors), None);
+        assert_eq!(bfs(&8, &6, MAX_ROUTE_LEN, get_neighbors), None);
+        assert_eq!(bfs(&9, &8, MAX_ROUTE_LEN, get_neighbors), None);
+        assert_eq!(bfs(&5, &4, MAX_ROUTE_LEN, get_neighbors), None);
+        assert_eq!(bfs(&4, &3, MAX_ROUTE_LEN, get_neighbors), None);
+
+        assert_eq!(bfs(&6, &7, MAX_ROUTE_LEN, get_neighbors), Some(vec![6, 7]));
+        assert_eq!(bfs(&7, &6, MAX_ROUTE_LEN, get_neighbors), Some(vec![7, 6]));
+    }
+
+    #[test]
+    fn test_bfs_max_route_len() {
+        // A chain graph: 0 --> 1 --> 2 --> ... --> 9
+        let mut graph = HashMap::new();
+        for i in 0u32..9 {
+            graph.insert(i, vec![i + 1]);
+        }
+        graph.insert(9, vec![]);
 
-        assert_eq!(bfs(&6, &7, get_neighbors), Some(vec![6, 7]));
-        assert_eq!(bfs(&7, &6, get_neighbors), Some(vec![7, 6]));
+        let get_neighbors = |node: &u32| graph.get(&node).unwrap().iter();
+        let route: Vec<u32> = (0..10).collect();
+        assert_eq!(bfs(&0, &9, 10, get_neighbors), Some(route.clone()));
+        assert_eq!(bfs(&0, &9, 11, get_neighbors), Some(route));
+        assert_eq!(bfs(&0, &9, 9, get_neighbors), None);
+        assert_eq!(bfs(&0, &1, 2, get_neighbors), Some(vec![0, 1]));
+        assert_eq!(bfs(&0, &1, 1, get_neighbors), None);
     }
 }
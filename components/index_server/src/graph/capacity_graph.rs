@@ -1,3 +1,5 @@
+use proto::index_server::messages::RouteDisjointness;
+
 pub type CapacityEdge<C> = (C, C);
 pub type CapacityRoute<N, C> = (Vec<N>, C);
 
@@ -30,12 +32,19 @@ pub trait CapacityGraph {
     ///
     /// opt_exclude is an optional edge to exclude (The returned route must not go through this
     /// edge). This can be useful for finding non trivial loops.
+    ///
+    /// disjointness determines whether multiple disjoint routes should be returned.
+    ///
+    /// If allow_partial is true and there is no route with capacity at least `capacity`, the
+    /// routes with the largest capacities are returned instead (A bounded amount).
     fn get_routes(
         &self,
         a: &Self::Node,
         b: &Self::Node,
         capacity: Self::Capacity,
         opt_exclude: Option<(&Self::Node, &Self::Node)>,
+        disjointness: &RouteDisjointness,
+        allow_partial: bool,
     ) -> Vec<CapacityRoute<Self::Node, Self::Capacity>>;
 
     /// Simulate advancement of time. Used to remove old edges.
@@ -2,6 +2,8 @@ use futures::channel::{mpsc, oneshot};
 use futures::task::{Spawn, SpawnError, SpawnExt};
 use futures::{FutureExt, SinkExt, StreamExt, TryFutureExt};
 
+use proto::index_server::messages::RouteDisjointness;
+
 use super::capacity_graph::{CapacityEdge, CapacityGraph, CapacityRoute};
 
 pub enum GraphRequest<N, C> {
@@ -24,8 +26,10 @@ pub enum GraphRequest<N, C> {
         N,
         C,
         Option<(N, N)>,
+        RouteDisjointness,
+        bool,
         oneshot::Sender<Vec<CapacityRoute<N, C>>>,
-    ), // (from, to, capacity, opt_exclude)
+    ), // (from, to, capacity, opt_exclude, disjointness, allow_partial)
     /// Expire old outgoing edges for the specified node
     Tick(N, oneshot::Sender<()>),
 }
@@ -52,10 +56,27 @@ where
         GraphRequest::RemoveNode(a, sender) => {
             let _ = sender.send(capacity_graph.remove_node(&a));
         }
-        GraphRequest::GetRoutes(a, b, capacity, opt_exclude, sender) => {
+        GraphRequest::GetRoutes(
+            a,
+            b,
+            capacity,
+            opt_exclude,
+            disjointness,
+            allow_partial,
+            sender,
+        ) => {
             let routes = match opt_exclude {
-                Some((c, d)) => capacity_graph.get_routes(&a, &b, capacity, Some((&c, &d))),
-                None => capacity_graph.get_routes(&a, &b, capacity, None),
+                Some((c, d)) => capacity_graph.get_routes(
+                    &a,
+                    &b,
+                    capacity,
+                    Some((&c, &d)),
+                    &disjointness,
+                    allow_partial,
+                ),
+                None => {
+                    capacity_graph.get_routes(&a, &b, capacity, None, &disjointness, allow_partial)
+                }
             };
             let _ = sender.send(routes);
         }
@@ -168,12 +189,19 @@ impl<N, C> GraphClient<N, C> {
     ///
     /// opt_exclude is an optional edge to exclude (The returned route must not go through this
     /// edge). This can be useful for finding non trivial loops.
+    ///
+    /// disjointness determines whether multiple disjoint routes should be returned.
+    ///
+    /// allow_partial allows to return the routes with the largest capacities if no route has
+    /// capacity at least `capacity`.
     pub async fn get_routes(
         &mut self,
         a: N,
         b: N,
         capacity: C,
         opt_exclude: Option<(N, N)>,
+        disjointness: RouteDisjointness,
+        allow_partial: bool,
     ) -> Result<Vec<CapacityRoute<N, C>>, GraphClientError> {
         let (sender, receiver) = oneshot::channel();
         await!(self.requests_sender.send(GraphRequest::GetRoutes(
@@ -181,6 +209,8 @@ impl<N, C> GraphClient<N, C> {
             b,
             capacity,
             opt_exclude,
+            disjointness,
+            allow_partial,
             sender
         )))?;
         Ok(await!(receiver)?)
@@ -238,15 +268,18 @@ mod tests {
         await!(graph_client.update_edge(5, 2, (5, 30))).unwrap();
 
         assert_eq!(
-            await!(graph_client.get_routes(2, 5, 29, None)).unwrap(),
+            await!(graph_client.get_routes(2, 5, 29, None, RouteDisjointness::None, false))
+                .unwrap(),
             vec![(vec![2, 5], 30)]
         );
         assert_eq!(
-            await!(graph_client.get_routes(2, 5, 30, None)).unwrap(),
+            await!(graph_client.get_routes(2, 5, 30, None, RouteDisjointness::None, false))
+                .unwrap(),
             vec![(vec![2, 5], 30)]
         );
         assert_eq!(
-            await!(graph_client.get_routes(2, 5, 31, None)).unwrap(),
+            await!(graph_client.get_routes(2, 5, 31, None, RouteDisjointness::None, false))
+                .unwrap(),
             vec![]
         );
 
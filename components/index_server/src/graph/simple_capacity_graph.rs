@@ -1,6 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::{cmp, hash};
 
+use proto::consts::{MAX_PARTIAL_ROUTES, MAX_ROUTE_LEN};
+use proto::index_server::messages::RouteDisjointness;
+
 use super::bfs::bfs;
 use super::capacity_graph::{CapacityEdge, CapacityGraph};
 use super::utils::{option_to_vec, OptionIterator};
@@ -142,21 +145,153 @@ where
         capacity: u128,
         opt_exclude: Option<(&N, &N)>,
     ) -> Option<(Vec<N>, u128)> {
-        let (opt_e_start, opt_e_end) = match opt_exclude {
-            Some((e_start, e_end)) => (Some(e_start), Some(e_end)),
-            None => (None, None),
-        };
+        let mut excluded_edges = HashSet::new();
+        if let Some((e_start, e_end)) = opt_exclude {
+            excluded_edges.insert((e_start.clone(), e_end.clone()));
+        }
+        self.get_route_excluding(a, b, capacity, &excluded_edges, &HashSet::new())
+    }
+
+    /// Get a route with capacity at least `capacity`, that does not go through any of the
+    /// directed edges in `excluded_edges`, and does not visit any of the nodes in `excluded_nodes`.
+    fn get_route_excluding(
+        &self,
+        a: &N,
+        b: &N,
+        capacity: u128,
+        excluded_edges: &HashSet<(N, N)>,
+        excluded_nodes: &HashSet<N>,
+    ) -> Option<(Vec<N>, u128)> {
         let get_neighbors = |cur_node: &N| {
-            let cur_node_is_e_start = Some(cur_node) == opt_e_start;
+            let cur_node = cur_node.clone();
             self.neighbors_with_send_capacity(cur_node.clone(), capacity)
-                .filter(move |&next_node| !cur_node_is_e_start || Some(next_node) != opt_e_end)
+                .filter(move |&next_node| {
+                    !excluded_nodes.contains(next_node)
+                        && !excluded_edges.contains(&(cur_node.clone(), next_node.clone()))
+                })
         };
-        let route = bfs(a, b, get_neighbors)?;
+        let route = bfs(a, b, MAX_ROUTE_LEN, get_neighbors)?;
         // We assert that we will always have valid capacity here:
         let capacity = self.get_route_capacity(&route).unwrap();
 
         Some((route, capacity))
     }
+
+    /// Capacities of all the directed edges in the graph, from the largest to the smallest,
+    /// without repetitions. Edges without capacity are ignored.
+    fn edge_capacities(&self) -> Vec<u128> {
+        let mut capacities = self
+            .nodes
+            .iter()
+            .flat_map(|(a, a_edges)| {
+                a_edges
+                    .edges
+                    .keys()
+                    .map(move |b| self.get_send_capacity(a, b))
+            })
+            .filter(|&capacity| capacity > 0)
+            .collect::<Vec<_>>();
+        capacities.sort_by(|x, y| y.cmp(x));
+        capacities.dedup();
+        capacities
+    }
+
+    /// Get the route with the largest capacity, that does not go through any of the directed
+    /// edges in `excluded_edges`, and does not visit any of the nodes in `excluded_nodes`.
+    /// `capacities` are the edge capacities of the graph, from the largest to the smallest.
+    fn get_widest_route_excluding(
+        &self,
+        a: &N,
+        b: &N,
+        capacities: &[u128],
+        excluded_edges: &HashSet<(N, N)>,
+        excluded_nodes: &HashSet<N>,
+    ) -> Option<(Vec<N>, u128)> {
+        // The capacity of a route is one of the edge capacities. If there is a route with
+        // capacity at least capacities[i], there is also one for every capacity after it.
+        // We search for the first capacity that has a route:
+        let has_route = |i: usize| {
+            self.get_route_excluding(a, b, capacities[i], excluded_edges, excluded_nodes)
+                .is_some()
+        };
+        let (mut low, mut high) = (0, capacities.len());
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if has_route(mid) {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        let capacity = capacities.get(low)?;
+        self.get_route_excluding(a, b, *capacity, excluded_edges, excluded_nodes)
+    }
+
+    /// Get up to `max_routes` routes that are disjoint according to `node_disjoint`.
+    /// Every route is obtained using `find_route`, given the directed edges and the nodes the
+    /// route must not go through.
+    ///
+    /// This is a greedy search: We repeatedly find the best route, and then remove its
+    /// intermediate nodes (or edges) from the graph before searching for the next route.
+    /// Hence the amount of returned routes is not always the maximal possible.
+    fn get_disjoint_routes<F>(
+        &self,
+        opt_exclude: Option<(&N, &N)>,
+        max_routes: usize,
+        node_disjoint: bool,
+        find_route: F,
+    ) -> Vec<(Vec<N>, u128)>
+    where
+        F: Fn(&HashSet<(N, N)>, &HashSet<N>) -> Option<(Vec<N>, u128)>,
+    {
+        let mut excluded_edges = HashSet::new();
+        if let Some((e_start, e_end)) = opt_exclude {
+            excluded_edges.insert((e_start.clone(), e_end.clone()));
+        }
+        let mut excluded_nodes = HashSet::new();
+
+        let mut routes = Vec::new();
+        while routes.len() < max_routes {
+            let (route, route_capacity) = match find_route(&excluded_edges, &excluded_nodes) {
+                Some(route_with_capacity) => route_with_capacity,
+                None => break,
+            };
+
+            if node_disjoint && route.len() > 2 {
+                for node in &route[1..route.len() - 1] {
+                    excluded_nodes.insert(node.clone());
+                }
+            } else {
+                // Edge disjointness, or a direct route (with no intermediate nodes):
+                for edge in route.windows(2) {
+                    excluded_edges.insert((edge[0].clone(), edge[1].clone()));
+                }
+            }
+            routes.push((route, route_capacity));
+        }
+        routes
+    }
+
+    /// Get up to `max_routes` routes with the largest capacities, that are disjoint according to
+    /// `node_disjoint`.
+    fn get_partial_routes(
+        &self,
+        a: &N,
+        b: &N,
+        opt_exclude: Option<(&N, &N)>,
+        max_routes: usize,
+        node_disjoint: bool,
+    ) -> Vec<(Vec<N>, u128)> {
+        let capacities = self.edge_capacities();
+        self.get_disjoint_routes(
+            opt_exclude,
+            max_routes,
+            node_disjoint,
+            |excluded_edges, excluded_nodes| {
+                self.get_widest_route_excluding(a, b, &capacities, excluded_edges, excluded_nodes)
+            },
+        )
+    }
 }
 
 impl<N> CapacityGraph for SimpleCapacityGraph<N>
@@ -206,8 +341,39 @@ where
         b: &N,
         capacity: u128,
         opt_exclude: Option<(&N, &N)>,
+        disjointness: &RouteDisjointness,
+        allow_partial: bool,
     ) -> Vec<(Vec<N>, u128)> {
-        option_to_vec(self.get_route(a, b, capacity, opt_exclude))
+        let find_route = |excluded_edges: &HashSet<(N, N)>, excluded_nodes: &HashSet<N>| {
+            self.get_route_excluding(a, b, capacity, excluded_edges, excluded_nodes)
+        };
+        let routes = match disjointness {
+            RouteDisjointness::None => option_to_vec(self.get_route(a, b, capacity, opt_exclude)),
+            RouteDisjointness::NodeDisjoint(max_routes) => {
+                self.get_disjoint_routes(opt_exclude, usize::from(*max_routes), true, find_route)
+            }
+            RouteDisjointness::EdgeDisjoint(max_routes) => {
+                self.get_disjoint_routes(opt_exclude, usize::from(*max_routes), false, find_route)
+            }
+        };
+        if !routes.is_empty() || !allow_partial {
+            return routes;
+        }
+
+        // No route has the wanted capacity. Return the routes with the largest capacities
+        // instead, so that the payment could be split between them:
+        let (max_routes, node_disjoint) = match disjointness {
+            RouteDisjointness::None => (1, false),
+            RouteDisjointness::NodeDisjoint(max_routes) => (usize::from(*max_routes), true),
+            RouteDisjointness::EdgeDisjoint(max_routes) => (usize::from(*max_routes), false),
+        };
+        self.get_partial_routes(
+            a,
+            b,
+            opt_exclude,
+            cmp::min(max_routes, MAX_PARTIAL_ROUTES),
+            node_disjoint,
+        )
     }
 
     fn tick(&mut self, a: &N) {
@@ -334,6 +500,187 @@ mod tests {
         assert_eq!(cg.get_route(&2, &1, 7, Some((&2, &1))), None);
     }
 
+    /// Add a bidirectional friendship between `a` and `b`, allowing to send `capacity` both ways.
+    fn add_friends(cg: &mut SimpleCapacityGraph<u32>, a: u32, b: u32, capacity: u128) {
+        cg.update_edge(a, b, (capacity, capacity));
+        cg.update_edge(b, a, (capacity, capacity));
+    }
+
+    #[test]
+    fn test_get_route_max_route_len() {
+        // A chain: 0 -- 1 -- 2 -- ... -- MAX_ROUTE_LEN
+        let mut cg = SimpleCapacityGraph::<u32>::new();
+        let last = MAX_ROUTE_LEN as u32;
+        for i in 0..last {
+            add_friends(&mut cg, i, i + 1, 10);
+        }
+
+        // A route of exactly MAX_ROUTE_LEN nodes:
+        let route: Vec<u32> = (0..last).collect();
+        assert_eq!(cg.get_route(&0, &(last - 1), 5, None), Some((route, 10)));
+
+        // A route of MAX_ROUTE_LEN + 1 nodes is never returned:
+        assert_eq!(cg.get_route(&0, &last, 5, None), None);
+        assert!(cg
+            .get_routes(
+                &0,
+                &last,
+                5,
+                None,
+                &RouteDisjointness::EdgeDisjoint(2),
+                false
+            )
+            .is_empty());
+    }
+
+    #[test]
+    fn test_get_routes_diamond() {
+        /*
+         * Diamond graph:
+         *
+         *      1
+         *    /   \
+         *   0     3
+         *    \   /
+         *      2
+         *
+         */
+
+        let mut cg = SimpleCapacityGraph::<u32>::new();
+        add_friends(&mut cg, 0, 1, 10);
+        add_friends(&mut cg, 1, 3, 10);
+        add_friends(&mut cg, 0, 2, 20);
+        add_friends(&mut cg, 2, 3, 20);
+
+        // Without disjointness, only one route is returned:
+        let routes = cg.get_routes(&0, &3, 5, None, &RouteDisjointness::None, false);
+        assert_eq!(routes.len(), 1);
+
+        let mut routes = cg.get_routes(&0, &3, 5, None, &RouteDisjointness::NodeDisjoint(4), false);
+        routes.sort();
+        assert_eq!(routes, vec![(vec![0, 1, 3], 10), (vec![0, 2, 3], 20)]);
+
+        let mut routes = cg.get_routes(&0, &3, 5, None, &RouteDisjointness::EdgeDisjoint(4), false);
+        routes.sort();
+        assert_eq!(routes, vec![(vec![0, 1, 3], 10), (vec![0, 2, 3], 20)]);
+
+        // Amount of routes is limited:
+        let routes = cg.get_routes(&0, &3, 5, None, &RouteDisjointness::NodeDisjoint(1), false);
+        assert_eq!(routes.len(), 1);
+
+        // Only one route has enough capacity:
+        let routes = cg.get_routes(&0, &3, 15, None, &RouteDisjointness::NodeDisjoint(4), false);
+        assert_eq!(routes, vec![(vec![0, 2, 3], 20)]);
+    }
+
+    #[test]
+    fn test_get_routes_shared_node() {
+        /*
+         * All routes from 0 to 3 go through node 1:
+         *
+         *     0 -- 1 -- 3
+         *      \  / \  /
+         *       2    4
+         *
+         */
+
+        let mut cg = SimpleCapacityGraph::<u32>::new();
+        add_friends(&mut cg, 0, 1, 10);
+        add_friends(&mut cg, 0, 2, 10);
+        add_friends(&mut cg, 2, 1, 10);
+        add_friends(&mut cg, 1, 3, 10);
+        add_friends(&mut cg, 1, 4, 10);
+        add_friends(&mut cg, 4, 3, 10);
+
+        // Node disjoint routes are impossible, we get less routes than requested:
+        let routes = cg.get_routes(&0, &3, 5, None, &RouteDisjointness::NodeDisjoint(2), false);
+        assert_eq!(routes, vec![(vec![0, 1, 3], 10)]);
+
+        // Edge disjoint routes are possible:
+        let mut routes = cg.get_routes(&0, &3, 5, None, &RouteDisjointness::EdgeDisjoint(2), false);
+        routes.sort();
+        assert_eq!(
+            routes,
+            vec![(vec![0, 1, 3], 10), (vec![0, 2, 1, 4, 3], 10)]
+        );
+    }
+
+    #[test]
+    fn test_get_routes_direct() {
+        let mut cg = SimpleCapacityGraph::<u32>::new();
+        add_friends(&mut cg, 0, 1, 10);
+
+        // A direct route should only be returned once:
+        let routes = cg.get_routes(&0, &1, 5, None, &RouteDisjointness::NodeDisjoint(2), false);
+        assert_eq!(routes, vec![(vec![0, 1], 10)]);
+    }
+
+    #[test]
+    fn test_get_routes_partial() {
+        /*
+         * No route has capacity 100, but routes 0-1-3 and 0-2-3 have 110 together:
+         *
+         *      1
+         *    /   \
+         *   0 --- 3
+         *    \   /
+         *      2
+         *
+         */
+
+        let mut cg = SimpleCapacityGraph::<u32>::new();
+        add_friends(&mut cg, 0, 1, 60);
+        add_friends(&mut cg, 1, 3, 60);
+        add_friends(&mut cg, 0, 2, 50);
+        add_friends(&mut cg, 2, 3, 80);
+        add_friends(&mut cg, 0, 3, 10);
+
+        // Partial routes were not requested:
+        let routes = cg.get_routes(
+            &0,
+            &3,
+            100,
+            None,
+            &RouteDisjointness::NodeDisjoint(4),
+            false,
+        );
+        assert!(routes.is_empty());
+
+        // The routes with the largest capacities are returned, and not the shortest ones:
+        let routes = cg.get_routes(&0, &3, 100, None, &RouteDisjointness::NodeDisjoint(4), true);
+        assert_eq!(
+            routes,
+            vec![(vec![0, 1, 3], 60), (vec![0, 2, 3], 50), (vec![0, 3], 10)]
+        );
+
+        // Without disjointness, only the widest route is returned:
+        let routes = cg.get_routes(&0, &3, 100, None, &RouteDisjointness::None, true);
+        assert_eq!(routes, vec![(vec![0, 1, 3], 60)]);
+
+        // Amount of routes is limited:
+        let routes = cg.get_routes(&0, &3, 100, None, &RouteDisjointness::EdgeDisjoint(2), true);
+        assert_eq!(routes, vec![(vec![0, 1, 3], 60), (vec![0, 2, 3], 50)]);
+
+        // The excluded edge is never used:
+        let routes = cg.get_routes(
+            &0,
+            &3,
+            100,
+            Some((&1, &3)),
+            &RouteDisjointness::NodeDisjoint(4),
+            true,
+        );
+        assert_eq!(routes, vec![(vec![0, 2, 3], 50), (vec![0, 3], 10)]);
+
+        // If a route has the wanted capacity, partial routes are not returned:
+        let routes = cg.get_routes(&0, &3, 55, None, &RouteDisjointness::NodeDisjoint(4), true);
+        assert_eq!(routes, vec![(vec![0, 1, 3], 60)]);
+
+        // No routes at all:
+        let routes = cg.get_routes(&0, &4, 100, None, &RouteDisjointness::NodeDisjoint(4), true);
+        assert!(routes.is_empty());
+    }
+
     #[test]
     fn test_simple_capacity_graph_tick() {
         let mut cg = SimpleCapacityGraph::<u32>::new();
@@ -20,6 +20,7 @@ extern crate log;
 extern crate common;
 
 mod backoff_connector;
+mod federation;
 mod graph;
 mod net_server;
 mod server;
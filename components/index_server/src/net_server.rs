@@ -7,11 +7,13 @@ use futures::task::{Spawn, SpawnExt};
 use futures::{FutureExt, SinkExt, Stream, StreamExt, TryFutureExt};
 
 use common::conn::{BoxFuture, ConnPair, ConnPairVec, FuncFutTransform, FutTransform};
+use common::select_streams::{select_streams, BoxStream};
 use common::transform_pool::transform_pool_loop;
 
+use proto::app_server::messages::RelayAddress;
 use proto::consts::{INDEX_NODE_TIMEOUT_TICKS, KEEPALIVE_TICKS, PROTOCOL_VERSION, TICKS_TO_REKEY};
 use proto::index_server::messages::{
-    IndexClientToServer, IndexServerToClient, IndexServerToServer,
+    FederationAddress, IndexClientToServer, IndexServerToClient, IndexServerToServer,
 };
 use proto::index_server::serialize::{
     deserialize_index_client_to_server, deserialize_index_server_to_server,
@@ -25,6 +27,7 @@ use crypto::identity::{compare_public_key, PublicKey};
 
 use identity::IdentityClient;
 use keepalive::KeepAliveChannel;
+use relay::{ClientConnector, ClientListener};
 use secure_channel::SecureChannel;
 use version::VersionPrefix;
 
@@ -32,6 +35,7 @@ use crate::server::{server_loop, ServerLoopError};
 pub use crate::server::{ClientConn, ServerConn};
 
 use crate::backoff_connector::BackoffConnector;
+use crate::federation::{relay_listen_loop, EncRelayConnector, FederationConnector};
 use crate::graph::graph_service::create_graph_service;
 use crate::graph::simple_capacity_graph::SimpleCapacityGraph;
 use crate::verifier::simple_verifier::SimpleVerifier;
@@ -316,6 +320,11 @@ pub enum NetIndexServerError {
     SpawnError,
 }
 
+/// Run an index server over the network.
+///
+/// Trusted servers are connected either directly or through a relay (See `FederationAddress`).
+/// Connections from trusted servers are received at `incoming_server_raw_conns`, and also through
+/// every relay in `listen_relays`.
 pub async fn net_index_server<A, ICC, ISC, SC, R, GS, S>(
     incoming_client_raw_conns: ICC,
     incoming_server_raw_conns: ISC,
@@ -323,15 +332,17 @@ pub async fn net_index_server<A, ICC, ISC, SC, R, GS, S>(
     identity_client: IdentityClient,
     timer_client: TimerClient,
     rng: R,
-    trusted_servers: HashMap<PublicKey, A>,
+    trusted_servers: HashMap<PublicKey, FederationAddress<A>>,
+    listen_relays: Vec<RelayAddress<A>>,
     max_concurrent_encrypt: usize,
     backoff_ticks: usize,
+    conn_timeout_ticks: usize,
     graph_service_spawner: GS,
     mut spawner: S,
 ) -> Result<(), NetIndexServerError>
 where
-    A: Clone + Send + Debug + 'static,
-    SC: FutTransform<Input = A, Output = Option<ConnPairVec>> + Clone + Send + 'static,
+    A: Clone + Send + Sync + Debug + 'static,
+    SC: FutTransform<Input = A, Output = Option<ConnPairVec>> + Clone + Send + Sync + 'static,
     ICC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
     ISC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
     R: CryptoRandom + Clone + 'static,
@@ -347,6 +358,9 @@ where
         rng.clone(),
         timer_client.clone(),
         TICKS_TO_REKEY,
+        None, // max_messages_before_rekey
+        0,    // incoming_queue_len
+        None, // opt_resume_ticks
         spawner.clone(),
     );
 
@@ -354,9 +368,9 @@ where
         KeepAliveChannel::new(timer_client.clone(), KEEPALIVE_TICKS, spawner.clone());
 
     let conn_transformer = ConnTransformer::new(
-        version_transform,
-        encrypt_transform,
-        keepalive_transform,
+        version_transform.clone(),
+        encrypt_transform.clone(),
+        keepalive_transform.clone(),
         spawner.clone(),
     );
 
@@ -382,6 +396,42 @@ where
         .spawn(pool_fut)
         .map_err(|_| NetIndexServerError::SpawnError)?;
 
+    // Connections to relays:
+    let enc_relay_connector = EncRelayConnector::new(
+        raw_server_net_connector.clone(),
+        version_transform.clone(),
+        encrypt_transform.clone(),
+    );
+
+    // Listen for connections from trusted servers through relays:
+    let (relay_conns_sender, incoming_relay_raw_conns) = mpsc::channel(0);
+    let client_listener = ClientListener::new(
+        enc_relay_connector.clone(),
+        keepalive_transform.clone(),
+        conn_timeout_ticks,
+        timer_client.clone(),
+        spawner.clone(),
+    );
+    let trusted_public_keys = trusted_servers.keys().cloned().collect::<Vec<_>>();
+    for relay_address in listen_relays {
+        let listen_fut = relay_listen_loop(
+            client_listener.clone(),
+            relay_address,
+            trusted_public_keys.clone(),
+            relay_conns_sender.clone(),
+            timer_client.clone(),
+            backoff_ticks,
+        );
+        spawner
+            .spawn(listen_fut)
+            .map_err(|_| NetIndexServerError::SpawnError)?;
+    }
+    // Incoming server connections end only after all the relay listeners are closed:
+    drop(relay_conns_sender);
+
+    let incoming_server_raw_conns =
+        select_streams![incoming_server_raw_conns, incoming_relay_raw_conns];
+
     // Transform incoming server connections:
     let c_conn_transformer = conn_transformer.clone();
     let incoming_server_transform = FuncFutTransform::new(move |raw_conn| {
@@ -404,18 +454,26 @@ where
         .spawn(pool_fut)
         .map_err(|_| NetIndexServerError::SpawnError)?;
 
+    // Connect to trusted servers directly, or through relays:
+    let relay_connector = ClientConnector::new(enc_relay_connector, keepalive_transform);
+    let federation_connector = FederationConnector::new(raw_server_net_connector, relay_connector);
+
     // Apply transform to create server connector:
     let c_conn_transformer = conn_transformer.clone();
-    let server_connector = FuncFutTransform::new(move |(public_key, net_address)| {
-        let mut c_raw_server_net_connector = raw_server_net_connector.clone();
-        let c_conn_transformer = c_conn_transformer.clone();
-        Box::pin(
-            async move {
-                let raw_conn = await!(c_raw_server_net_connector.transform(net_address))?;
-                await!(c_conn_transformer.outgoing_index_server_conn_transform(public_key, raw_conn))
-            },
-        )
-    });
+    let server_connector = FuncFutTransform::new(
+        move |(public_key, address): (PublicKey, FederationAddress<A>)| {
+            let mut c_federation_connector = federation_connector.clone();
+            let c_conn_transformer = c_conn_transformer.clone();
+            Box::pin(
+                async move {
+                    let raw_conn =
+                        await!(c_federation_connector.transform((public_key.clone(), address)))?;
+                    await!(c_conn_transformer
+                        .outgoing_index_server_conn_transform(public_key, raw_conn))
+                },
+            )
+        },
+    );
 
     await!(index_server(
         local_public_key,
@@ -1,5 +1,5 @@
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::marker::Unpin;
 
 use futures::channel::{mpsc, oneshot};
@@ -86,6 +86,16 @@ struct RemoteServer<A> {
     state: RemoteServerState,
 }
 
+/// The last mutations update accepted from a node
+#[derive(Debug)]
+struct NodeCounter {
+    session_id: Uid,
+    counter: u64,
+    /// The server that forwarded the mutations update to us.
+    /// None if the node is connected to us directly.
+    opt_server_public_key: Option<PublicKey>,
+}
+
 struct IndexServer<A, S, SC, V, CMP> {
     local_public_key: PublicKey,
     server_connector: SC,
@@ -94,6 +104,10 @@ struct IndexServer<A, S, SC, V, CMP> {
     compare_public_key: CMP,
     remote_servers: HashMap<PublicKey, RemoteServer<A>>,
     clients: HashMap<PublicKey, Connected<IndexServerToClient>>,
+    /// Last mutations update accepted from every node, used to detect lost mutations updates
+    node_counters: HashMap<PublicKey, NodeCounter>,
+    /// Nodes we asked for a full state during the current tick
+    full_state_requests: HashSet<PublicKey>,
     event_sender: mpsc::Sender<IndexServerEvent>,
     spawner: S,
 }
@@ -131,6 +145,20 @@ fn is_listen_server(local_public_key: &PublicKey, friend_public_key: &PublicKey)
 }
 */
 
+/// Check if some mutations updates of a node were lost before a newly accepted mutations update
+/// (With `session_id` and `counter`) arrived.
+/// `opt_node_counter` is the last mutations update we accepted from the node.
+fn is_counter_gap(opt_node_counter: Option<&NodeCounter>, session_id: &Uid, counter: u64) -> bool {
+    match opt_node_counter {
+        Some(node_counter) if node_counter.session_id == *session_id => {
+            counter > node_counter.counter.saturating_add(1)
+        }
+        // A new session begins with the counter 0.
+        // If we have never heard about the node, we also miss the earlier mutations of the node:
+        _ => counter != 0,
+    }
+}
+
 impl<A, S, SC, V, CMP> IndexServer<A, S, SC, V, CMP>
 where
     A: Clone + Send + std::fmt::Debug + 'static,
@@ -157,6 +185,8 @@ where
             compare_public_key,
             remote_servers: HashMap::new(),
             clients: HashMap::new(),
+            node_counters: HashMap::new(),
+            full_state_requests: HashSet::new(),
             event_sender,
             spawner,
         };
@@ -274,6 +304,21 @@ where
 
         // The message is valid and fresh.
 
+        let node_public_key = mutations_update.node_public_key.clone();
+        let is_gap = is_counter_gap(
+            self.node_counters.get(&node_public_key),
+            &mutations_update.session_id,
+            mutations_update.counter,
+        );
+        self.node_counters.insert(
+            node_public_key.clone(),
+            NodeCounter {
+                session_id: mutations_update.session_id,
+                counter: mutations_update.counter,
+                opt_server_public_key: opt_server_public_key.clone(),
+            },
+        );
+
         // Expire old edges for `node_public_key`:
         // Note: This tick happens every time a message is received from this `node_public_key`,
         // and not every constant amount of time.
@@ -325,9 +370,51 @@ where
                 forward_mutations_update.clone(),
             ));
         }
+
+        // The mutations were applied incrementally. If some of the node's mutations were lost
+        // on their way to us, we ask the node to send its full state:
+        if is_gap {
+            warn!(
+                "{}: handle_forward_mutations_update: Lost mutations of node {:?}",
+                self.local_public_key[0], node_public_key
+            );
+            self.request_full_state(node_public_key);
+        }
         Ok(())
     }
 
+    /// Ask a node to send the full state of its friends.
+    /// The request is sent back along the path the last mutations update of the node arrived
+    /// from. A request for the same node is sent at most once every tick.
+    fn request_full_state(&mut self, node_public_key: PublicKey) {
+        if !self.full_state_requests.insert(node_public_key.clone()) {
+            // We have already asked for the state of this node during this tick
+            return;
+        }
+
+        let opt_server_public_key = match self.node_counters.get(&node_public_key) {
+            Some(node_counter) => node_counter.opt_server_public_key.clone(),
+            None => return, // We don't know where the mutations of this node come from
+        };
+
+        match opt_server_public_key {
+            None => {
+                if let Some(connected_client) = self.clients.get_mut(&node_public_key) {
+                    let _ = connected_client.try_send(IndexServerToClient::RequestFullState);
+                }
+            }
+            Some(server_public_key) => {
+                if let Some(remote_server) = self.remote_servers.get_mut(&server_public_key) {
+                    if let RemoteServerState::Connected(connected_server) = &mut remote_server.state
+                    {
+                        let _ = connected_server
+                            .try_send(IndexServerToServer::RequestFullState(node_public_key));
+                    }
+                }
+            }
+        }
+    }
+
     pub async fn handle_from_server(
         &mut self,
         public_key: PublicKey,
@@ -341,12 +428,16 @@ where
                 await!(self
                     .handle_forward_mutations_update(Some(public_key), forward_mutations_update))?;
             }
+            IndexServerToServer::RequestFullState(node_public_key) => {
+                self.request_full_state(node_public_key);
+            }
         };
         Ok(())
     }
 
     pub async fn handle_timer_tick(&mut self) -> Result<(), ServerLoopError> {
         let (time_hash, removed_nodes) = self.verifier.tick();
+        self.full_state_requests.clear();
 
         // Try to send the time tick to all servers. Sending to some of them might fail:
         for (_server_public_key, connected_server) in self.iter_connected_servers() {
@@ -360,6 +451,7 @@ where
 
         // Update the graph service about removed nodes:
         for node_public_key in removed_nodes {
+            self.node_counters.remove(&node_public_key);
             await!(self.graph_client.remove_node(node_public_key))?;
         }
 
@@ -387,13 +479,16 @@ async fn client_handler(
                     request_routes.source.clone(),
                     request_routes.destination.clone(),
                     request_routes.capacity,
-                    request_routes.opt_exclude.clone()
+                    request_routes.opt_exclude.clone(),
+                    request_routes.disjointness.clone(),
+                    request_routes.allow_partial
                 ))?;
                 let routes = route_tuples
                     .into_iter()
                     .map(|(route, capacity)| RouteWithCapacity {
                         route: FriendsRoute { public_keys: route },
                         capacity,
+                        meets_request: capacity >= request_routes.capacity,
                     })
                     .collect::<Vec<_>>();
 
@@ -628,6 +723,7 @@ mod tests {
     use futures::task::Spawn;
 
     use crypto::crypto_rand::{RandValue, RAND_VALUE_LEN};
+    use crypto::hash::HashResult;
     use crypto::identity::{
         generate_pkcs8_key_pair, PublicKey, Signature, SoftwareEd25519Identity, PUBLIC_KEY_LEN,
         SIGNATURE_LEN,
@@ -637,7 +733,7 @@ mod tests {
 
     use common::dummy_connector::{ConnRequest, DummyConnector};
     use identity::{create_identity, IdentityClient};
-    use proto::index_server::messages::RequestRoutes;
+    use proto::index_server::messages::{RequestRoutes, RouteDisjointness};
 
     use crate::graph::graph_service::GraphRequest;
     use crate::verifier::simple_verifier::SimpleVerifier;
@@ -724,16 +820,28 @@ mod tests {
             source: PublicKey::from(&[8; PUBLIC_KEY_LEN]),
             destination: PublicKey::from(&[9; PUBLIC_KEY_LEN]),
             opt_exclude: None,
+            disjointness: RouteDisjointness::None,
+            allow_partial: false,
         };
         await!(client_sender.send(IndexClientToServer::RequestRoutes(request_routes))).unwrap();
 
         // Handle the graph request:
         match await!(graph_requests_receiver.next()).unwrap() {
-            GraphRequest::GetRoutes(src, dest, capacity, opt_exclude, response_sender) => {
+            GraphRequest::GetRoutes(
+                src,
+                dest,
+                capacity,
+                opt_exclude,
+                disjointness,
+                allow_partial,
+                response_sender,
+            ) => {
                 assert_eq!(src, PublicKey::from(&[8; PUBLIC_KEY_LEN]));
                 assert_eq!(dest, PublicKey::from(&[9; PUBLIC_KEY_LEN]));
                 assert_eq!(capacity, 100);
                 assert_eq!(opt_exclude, None);
+                assert_eq!(disjointness, RouteDisjointness::None);
+                assert!(!allow_partial);
                 response_sender.send(Vec::new()).unwrap();
             }
             _ => unreachable!(),
@@ -747,6 +855,63 @@ mod tests {
             _ => unreachable!(),
         };
 
+        // Client requests routes, allowing partial routes:
+        let request_id = Uid::from(&[1; UID_LEN]);
+        let request_routes = RequestRoutes {
+            request_id: request_id.clone(),
+            capacity: 100,
+            source: PublicKey::from(&[8; PUBLIC_KEY_LEN]),
+            destination: PublicKey::from(&[9; PUBLIC_KEY_LEN]),
+            opt_exclude: None,
+            disjointness: RouteDisjointness::NodeDisjoint(2),
+            allow_partial: true,
+        };
+        await!(client_sender.send(IndexClientToServer::RequestRoutes(request_routes))).unwrap();
+
+        let route = |i: u8| {
+            vec![
+                PublicKey::from(&[8; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[i; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[9; PUBLIC_KEY_LEN]),
+            ]
+        };
+
+        // Handle the graph request:
+        match await!(graph_requests_receiver.next()).unwrap() {
+            GraphRequest::GetRoutes(
+                _,
+                _,
+                capacity,
+                _,
+                disjointness,
+                allow_partial,
+                response_sender,
+            ) => {
+                assert_eq!(capacity, 100);
+                assert_eq!(disjointness, RouteDisjointness::NodeDisjoint(2));
+                assert!(allow_partial);
+                response_sender
+                    .send(vec![(route(10), 60), (route(11), 50)])
+                    .unwrap();
+            }
+            _ => unreachable!(),
+        }
+
+        match await!(client_receiver.next()).unwrap() {
+            IndexServerToClient::ResponseRoutes(response_routes) => {
+                assert_eq!(response_routes.request_id, request_id);
+                let routes = response_routes.routes;
+                assert_eq!(routes.len(), 2);
+                assert_eq!(routes[0].route.public_keys, route(10));
+                assert_eq!(routes[0].capacity, 60);
+                assert!(!routes[0].meets_request);
+                assert_eq!(routes[1].route.public_keys, route(11));
+                assert_eq!(routes[1].capacity, 50);
+                assert!(!routes[1].meets_request);
+            }
+            _ => unreachable!(),
+        };
+
         // Server should periodically send time hashes to the client:
         await!(tick_sender.send(())).unwrap();
 
@@ -803,6 +968,224 @@ mod tests {
         thread_pool.run(task_index_server_loop_single_server(thread_pool.clone()));
     }
 
+    #[test]
+    fn test_is_counter_gap() {
+        let session_id = Uid::from(&[1; UID_LEN]);
+        let node_counter = NodeCounter {
+            session_id,
+            counter: 5,
+            opt_server_public_key: None,
+        };
+
+        assert!(!is_counter_gap(Some(&node_counter), &session_id, 6));
+        assert!(is_counter_gap(Some(&node_counter), &session_id, 7));
+
+        // A new session:
+        let new_session_id = Uid::from(&[2; UID_LEN]);
+        assert!(!is_counter_gap(Some(&node_counter), &new_session_id, 0));
+        assert!(is_counter_gap(Some(&node_counter), &new_session_id, 1));
+
+        // A node we have never heard about:
+        assert!(!is_counter_gap(None, &session_id, 0));
+        assert!(is_counter_gap(None, &session_id, 3));
+    }
+
+    /// Create a signed MutationsUpdate that removes the friend [11; PUBLIC_KEY_LEN]
+    async fn create_mutations_update(
+        identity_client: IdentityClient,
+        time_hash: HashResult,
+        counter: u64,
+    ) -> MutationsUpdate {
+        let mut mutations_update = MutationsUpdate {
+            node_public_key: await!(identity_client.request_public_key()).unwrap(),
+            index_mutations: vec![IndexMutation::RemoveFriend(PublicKey::from(
+                &[11; PUBLIC_KEY_LEN],
+            ))],
+            time_hash,
+            session_id: Uid::from(&[0; UID_LEN]),
+            counter,
+            rand_nonce: RandValue::from(&[0; RAND_VALUE_LEN]),
+            signature: Signature::from(&[0; SIGNATURE_LEN]),
+        };
+        mutations_update.signature =
+            await!(identity_client.request_signature(mutations_update.signature_buff())).unwrap();
+        mutations_update
+    }
+
+    /// Handle the graph requests caused by a MutationsUpdate created by
+    /// `create_mutations_update()`
+    async fn expect_remove_friend(
+        graph_requests_receiver: &mut mpsc::Receiver<GraphRequest<PublicKey, u128>>,
+        client_public_key: PublicKey,
+    ) {
+        match await!(graph_requests_receiver.next()).unwrap() {
+            GraphRequest::Tick(node, response_sender) => {
+                assert_eq!(node, client_public_key);
+                response_sender.send(()).unwrap();
+            }
+            _ => unreachable!(),
+        }
+        match await!(graph_requests_receiver.next()).unwrap() {
+            GraphRequest::RemoveEdge(src, dest, response_sender) => {
+                assert_eq!(src, client_public_key);
+                assert_eq!(dest, PublicKey::from(&[11; PUBLIC_KEY_LEN]));
+                response_sender.send(None).unwrap();
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    async fn task_index_server_loop_counter_gap<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let local_public_key = PublicKey::from(&[0; PUBLIC_KEY_LEN]);
+        let trusted_servers: HashMap<PublicKey, u8> = HashMap::new();
+
+        let (_server_connections_sender, incoming_server_connections) = mpsc::channel(0);
+        let (mut client_connections_sender, incoming_client_connections) = mpsc::channel(0);
+
+        let (conn_request_sender, _conn_request_receiver) = mpsc::channel(0);
+        let server_connector = DummyConnector::new(conn_request_sender);
+
+        let (mut tick_sender, timer_stream) = mpsc::channel::<()>(0);
+
+        let (graph_requests_sender, mut graph_requests_receiver) = mpsc::channel(0);
+        let graph_client = GraphClient::new(graph_requests_sender);
+
+        let compare_public_key = |pk_a: &PublicKey, pk_b: &PublicKey| pk_a.cmp(pk_b);
+
+        let rng = DummyRandom::new(&[0u8]);
+        let verifier = SimpleVerifier::new(8, rng);
+
+        let server_loop_fut = server_loop(
+            local_public_key,
+            trusted_servers,
+            incoming_server_connections,
+            incoming_client_connections,
+            server_connector,
+            graph_client,
+            compare_public_key,
+            verifier,
+            timer_stream,
+            spawner.clone(),
+            None,
+        )
+        .map_err(|e| error!("Error in server_loop(): {:?}", e))
+        .map(|_| ());
+
+        spawner.spawn(server_loop_fut).unwrap();
+
+        let identity_client = create_identity_client(spawner.clone(), &[1, 1]);
+        let client_public_key = await!(identity_client.request_public_key()).unwrap();
+
+        let (mut client_sender, server_receiver) = mpsc::channel(CHANNEL_SIZE);
+        let (server_sender, mut client_receiver) = mpsc::channel(CHANNEL_SIZE);
+        await!(client_connections_sender
+            .send((client_public_key.clone(), (server_sender, server_receiver))))
+        .unwrap();
+
+        // Make sure that the client is registered before the server ticks:
+        let request_routes = RequestRoutes {
+            request_id: Uid::from(&[0; UID_LEN]),
+            capacity: 100,
+            source: PublicKey::from(&[8; PUBLIC_KEY_LEN]),
+            destination: PublicKey::from(&[9; PUBLIC_KEY_LEN]),
+            opt_exclude: None,
+            disjointness: RouteDisjointness::None,
+            allow_partial: false,
+        };
+        await!(client_sender.send(IndexClientToServer::RequestRoutes(request_routes))).unwrap();
+        match await!(graph_requests_receiver.next()).unwrap() {
+            GraphRequest::GetRoutes(_, _, _, _, _, _, response_sender) => {
+                response_sender.send(Vec::new()).unwrap();
+            }
+            _ => unreachable!(),
+        }
+        match await!(client_receiver.next()).unwrap() {
+            IndexServerToClient::ResponseRoutes(_) => {}
+            _ => unreachable!(),
+        };
+
+        await!(tick_sender.send(())).unwrap();
+        let time_hash = match await!(client_receiver.next()).unwrap() {
+            IndexServerToClient::TimeHash(time_hash) => time_hash,
+            _ => unreachable!(),
+        };
+
+        // Consecutive counters are applied without any request:
+        for &counter in &[0u64, 1] {
+            let mutations_update = await!(create_mutations_update(
+                identity_client.clone(),
+                time_hash.clone(),
+                counter
+            ));
+            await!(client_sender.send(IndexClientToServer::MutationsUpdate(mutations_update)))
+                .unwrap();
+            await!(expect_remove_friend(
+                &mut graph_requests_receiver,
+                client_public_key.clone()
+            ));
+        }
+
+        // The mutations update with counter 2 was lost.
+        // The mutations are still applied, but the client is asked for its full state:
+        let mutations_update = await!(create_mutations_update(
+            identity_client.clone(),
+            time_hash.clone(),
+            3
+        ));
+        await!(client_sender.send(IndexClientToServer::MutationsUpdate(mutations_update))).unwrap();
+        await!(expect_remove_friend(
+            &mut graph_requests_receiver,
+            client_public_key.clone()
+        ));
+        match await!(client_receiver.next()).unwrap() {
+            IndexServerToClient::RequestFullState => {}
+            _ => unreachable!(),
+        };
+
+        // Another gap during the same tick does not cause another request:
+        let mutations_update = await!(create_mutations_update(
+            identity_client.clone(),
+            time_hash.clone(),
+            5
+        ));
+        await!(client_sender.send(IndexClientToServer::MutationsUpdate(mutations_update))).unwrap();
+        await!(expect_remove_friend(
+            &mut graph_requests_receiver,
+            client_public_key.clone()
+        ));
+
+        await!(tick_sender.send(())).unwrap();
+        match await!(client_receiver.next()).unwrap() {
+            IndexServerToClient::TimeHash(_) => {}
+            _ => unreachable!(),
+        };
+
+        // A gap after the tick causes a new request:
+        let mutations_update = await!(create_mutations_update(
+            identity_client.clone(),
+            time_hash.clone(),
+            7
+        ));
+        await!(client_sender.send(IndexClientToServer::MutationsUpdate(mutations_update))).unwrap();
+        await!(expect_remove_friend(
+            &mut graph_requests_receiver,
+            client_public_key.clone()
+        ));
+        match await!(client_receiver.next()).unwrap() {
+            IndexServerToClient::RequestFullState => {}
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_index_server_loop_counter_gap() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_index_server_loop_counter_gap(thread_pool.clone()));
+    }
+
     // ###########################################################
     // ###########################################################
 
@@ -989,16 +1372,28 @@ mod tests {
             source: PublicKey::from(&[8; PUBLIC_KEY_LEN]),
             destination: PublicKey::from(&[9; PUBLIC_KEY_LEN]),
             opt_exclude: None,
+            disjointness: RouteDisjointness::None,
+            allow_partial: false,
         };
         await!(client_sender.send(IndexClientToServer::RequestRoutes(request_routes))).unwrap();
 
         // Handle the graph request:
         match await!(test_servers[0].graph_requests_receiver.next()).unwrap() {
-            GraphRequest::GetRoutes(src, dest, capacity, opt_exclude, response_sender) => {
+            GraphRequest::GetRoutes(
+                src,
+                dest,
+                capacity,
+                opt_exclude,
+                disjointness,
+                allow_partial,
+                response_sender,
+            ) => {
                 assert_eq!(src, PublicKey::from(&[8; PUBLIC_KEY_LEN]));
                 assert_eq!(dest, PublicKey::from(&[9; PUBLIC_KEY_LEN]));
                 assert_eq!(capacity, 100);
                 assert_eq!(opt_exclude, None);
+                assert_eq!(disjointness, RouteDisjointness::None);
+                assert!(!allow_partial);
                 response_sender.send(Vec::new()).unwrap();
             }
             _ => unreachable!(),
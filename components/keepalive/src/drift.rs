@@ -0,0 +1,116 @@
+use std::collections::VecDeque;
+
+/// A measurement of the tick rate of the remote side, relative to our local tick rate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TickDriftReport {
+    /// Amount of local ticks that passed during the measurement
+    pub local_ticks: u64,
+    /// Amount of remote ticks that passed during the measurement
+    pub remote_ticks: u64,
+    /// remote_ticks / local_ticks, measured in permille.
+    pub ratio_permille: u64,
+}
+
+/// Tracks the tick counters sent by the remote side (inside keepalive messages), and compares
+/// the remote tick progress to the local tick progress over a sliding window.
+pub struct TickDriftDetector {
+    window_ticks: u64,
+    min_permille: u64,
+    max_permille: u64,
+    /// Received samples of (local_ticks, remote_ticks), oldest first.
+    samples: VecDeque<(u64, u64)>,
+    /// Was the last measured ratio outside of the allowed band?
+    is_drifting: bool,
+}
+
+impl TickDriftDetector {
+    pub fn new(window_ticks: u64, min_permille: u64, max_permille: u64) -> Self {
+        TickDriftDetector {
+            window_ticks,
+            min_permille,
+            max_permille,
+            samples: VecDeque::new(),
+            is_drifting: false,
+        }
+    }
+
+    /// Add a remote tick counter, received when our local tick counter was `local_ticks`.
+    /// Returns a report if the measured ratio has just left the allowed band.
+    pub fn add_sample(&mut self, local_ticks: u64, remote_ticks: u64) -> Option<TickDriftReport> {
+        if let Some(&(_, last_remote_ticks)) = self.samples.back() {
+            if remote_ticks < last_remote_ticks {
+                // Remote counter went backwards. We start measuring from scratch:
+                self.samples.clear();
+                self.is_drifting = false;
+            }
+        }
+        self.samples.push_back((local_ticks, remote_ticks));
+
+        // Remove old samples, keeping the newest sample that still covers the full window:
+        while self.samples.len() > 2
+            && local_ticks.saturating_sub(self.samples[1].0) >= self.window_ticks
+        {
+            self.samples.pop_front();
+        }
+
+        let &(first_local_ticks, first_remote_ticks) = self.samples.front()?;
+        let local_progress = local_ticks.saturating_sub(first_local_ticks);
+        if local_progress < self.window_ticks || local_progress == 0 {
+            // Not enough information yet:
+            return None;
+        }
+        let remote_progress = remote_ticks.saturating_sub(first_remote_ticks);
+        let ratio_permille = remote_progress.saturating_mul(1000) / local_progress;
+
+        let was_drifting = self.is_drifting;
+        self.is_drifting = ratio_permille < self.min_permille || ratio_permille > self.max_permille;
+        if !self.is_drifting || was_drifting {
+            return None;
+        }
+
+        Some(TickDriftReport {
+            local_ticks: local_progress,
+            remote_ticks: remote_progress,
+            ratio_permille,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tick_drift_detector_same_rate() {
+        let mut detector = TickDriftDetector::new(16, 750, 1333);
+        for i in 0..32u64 {
+            assert_eq!(detector.add_sample(i * 4, 100 + i * 4), None);
+        }
+    }
+
+    #[test]
+    fn test_tick_drift_detector_half_rate() {
+        let mut detector = TickDriftDetector::new(16, 750, 1333);
+
+        let mut reports = Vec::new();
+        for i in 0..32u64 {
+            if let Some(report) = detector.add_sample(i * 4, i * 2) {
+                reports.push(report);
+            }
+        }
+        // We expect to get only one report:
+        assert_eq!(
+            reports,
+            vec![TickDriftReport {
+                local_ticks: 16,
+                remote_ticks: 8,
+                ratio_permille: 500,
+            }]
+        );
+
+        // Remote counter went backwards (For example, the remote side restarted):
+        assert_eq!(detector.add_sample(128, 0), None);
+        assert_eq!(detector.add_sample(136, 8), None);
+        assert_eq!(detector.add_sample(144, 16), None);
+    }
+}
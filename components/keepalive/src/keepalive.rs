@@ -1,15 +1,20 @@
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
 use futures::{future, stream, FutureExt, Sink, SinkExt, Stream, StreamExt, TryFutureExt};
+use std::convert::TryFrom;
 use std::marker::Unpin;
 use timer::{TimerClient, TimerTick};
 
 use common::conn::{BoxFuture, ConnPair, FutTransform};
+use common::int_convert::usize_to_u64;
 use common::select_streams::{select_streams, BoxStream};
 
+use proto::consts::{TICK_DRIFT_MAX_PERMILLE, TICK_DRIFT_MIN_PERMILLE, TICK_DRIFT_WINDOW_TICKS};
 use proto::keepalive::messages::KaMessage;
 use proto::keepalive::serialize::{deserialize_ka_message, serialize_ka_message};
 
+use crate::drift::{TickDriftDetector, TickDriftReport};
+
 #[derive(Debug)]
 pub enum KeepAliveError {
     // TimerClosed,
@@ -19,7 +24,8 @@ pub enum KeepAliveError {
 
 #[derive(Debug, Clone)]
 enum KeepAliveEvent {
-    TimerTick,
+    /// Amount of time ticks that have elapsed
+    TimerTick(u64),
     TimerClosed,
     RemoteChannelClosed,
     UserChannelClosed,
@@ -56,6 +62,8 @@ async fn inner_keepalive_loop<TR, FR, TU, FU, TS>(
     from_user: FU,
     timer_stream: TS,
     keepalive_ticks: usize,
+    mut drift_detector: TickDriftDetector,
+    mut opt_drift_sender: Option<mpsc::Sender<TickDriftReport>>,
     mut opt_event_sender: Option<mpsc::Sender<KeepAliveEvent>>,
 ) -> Result<(), KeepAliveError>
 where
@@ -66,7 +74,7 @@ where
     TS: Stream<Item = TimerTick> + Unpin + Send,
 {
     let timer_stream = timer_stream
-        .map(|_| KeepAliveEvent::TimerTick)
+        .map(|timer_tick| KeepAliveEvent::TimerTick(timer_tick.ticks_elapsed))
         .chain(stream::once(future::ready(KeepAliveEvent::TimerClosed)));
 
     let from_remote = from_remote
@@ -88,6 +96,9 @@ where
     // Amount of ticks remaining until we need to send a new keepalive (To make sure remote side
     // knows we are alive).
     let mut ticks_to_send_keepalive = keepalive_ticks / 2;
+    // Amount of ticks passed since the beginning of this connection.
+    // Sent to the remote side inside keepalive messages:
+    let mut local_ticks: u64 = 0;
 
     while let Some(event) = await!(events.next()) {
         if let Some(ref mut event_sender) = opt_event_sender {
@@ -98,10 +109,25 @@ where
                 let ka_message = deserialize_ka_message(&ser_ka_message)
                     .map_err(|_| KeepAliveError::DeserializeError)?;
                 ticks_to_close = keepalive_ticks;
-                if let KaMessage::Message(message) = ka_message {
-                    if await!(to_user.send(message)).is_err() {
-                        warn!("keepalive_loop(): Can not send to local side");
-                        break;
+                match ka_message {
+                    KaMessage::KeepAlive(remote_ticks) => {
+                        if let Some(report) = drift_detector.add_sample(local_ticks, remote_ticks)
+                        {
+                            warn!(
+                                "keepalive_loop(): Remote tick rate: {} permille of local rate",
+                                report.ratio_permille
+                            );
+                            if let Some(ref mut drift_sender) = opt_drift_sender {
+                                // We don't want to block the keepalive loop here:
+                                let _ = drift_sender.try_send(report);
+                            }
+                        }
+                    }
+                    KaMessage::Message(message) => {
+                        if await!(to_user.send(message)).is_err() {
+                            warn!("keepalive_loop(): Can not send to local side");
+                            break;
+                        }
                     }
                 }
             }
@@ -114,14 +140,18 @@ where
                 }
                 ticks_to_send_keepalive = keepalive_ticks / 2;
             }
-            KeepAliveEvent::TimerTick => {
-                ticks_to_close = ticks_to_close.saturating_sub(1);
-                ticks_to_send_keepalive = ticks_to_send_keepalive.saturating_sub(1);
+            KeepAliveEvent::TimerTick(ticks_elapsed) => {
+                // If many ticks have elapsed at once, at most one keepalive is sent.
+                // After a long enough pause we assume that the remote side is gone.
+                local_ticks = local_ticks.wrapping_add(ticks_elapsed);
+                let ticks_elapsed = usize::try_from(ticks_elapsed).unwrap_or(usize::max_value());
+                ticks_to_close = ticks_to_close.saturating_sub(ticks_elapsed);
+                ticks_to_send_keepalive = ticks_to_send_keepalive.saturating_sub(ticks_elapsed);
                 if ticks_to_close == 0 {
                     return Err(KeepAliveError::RemoteTimeout);
                 }
                 if ticks_to_send_keepalive == 0 {
-                    let ka_message = KaMessage::KeepAlive;
+                    let ka_message = KaMessage::KeepAlive(local_ticks);
                     let ser_ka_message = serialize_ka_message(&ka_message);
                     if await!(to_remote.send(ser_ka_message)).is_err() {
                         warn!("Keepalive_loop(): Can not send to remote side");
@@ -142,6 +172,7 @@ where
 pub struct KeepAliveChannel<S> {
     timer_client: TimerClient,
     keepalive_ticks: usize,
+    opt_drift_sender: Option<mpsc::Sender<TickDriftReport>>,
     spawner: S,
 }
 
@@ -157,10 +188,18 @@ where
         KeepAliveChannel {
             timer_client,
             keepalive_ticks,
+            opt_drift_sender: None,
             spawner,
         }
     }
 
+    /// Report every connection whose remote side ticks at a rate outside of the allowed band
+    /// through `drift_sender`. This usually means that one of the sides has a broken timer.
+    pub fn with_drift_sender(mut self, drift_sender: mpsc::Sender<TickDriftReport>) -> Self {
+        self.opt_drift_sender = Some(drift_sender);
+        self
+    }
+
     /// Transform a usual `Vec<u8>` connection end into a connection end that performs
     /// keepalives automatically. The output `conn_pair` looks exactly like the input pair, however
     /// it also maintains keepalives.
@@ -183,6 +222,12 @@ where
                         from_user,
                         timer_stream,
                         self.keepalive_ticks,
+                        TickDriftDetector::new(
+                            usize_to_u64(TICK_DRIFT_WINDOW_TICKS).unwrap(),
+                            TICK_DRIFT_MIN_PERMILLE,
+                            TICK_DRIFT_MAX_PERMILLE,
+                        ),
+                        self.opt_drift_sender.clone(),
                         None,
                     )
                     .map_err(|e| {
@@ -251,6 +296,12 @@ mod tests {
             from_user,
             timer_stream,
             keepalive_ticks,
+            TickDriftDetector::new(
+                usize_to_u64(TICK_DRIFT_WINDOW_TICKS).unwrap(),
+                TICK_DRIFT_MIN_PERMILLE,
+                TICK_DRIFT_MAX_PERMILLE,
+            ),
+            None,
             None,
         )
         .map_err(|e| error!("[KeepAlive] inner_keepalive_loop() error: {:?}", e))
@@ -283,6 +334,12 @@ mod tests {
             from_user,
             timer_stream,
             keepalive_ticks,
+            TickDriftDetector::new(
+                usize_to_u64(TICK_DRIFT_WINDOW_TICKS).unwrap(),
+                TICK_DRIFT_MIN_PERMILLE,
+                TICK_DRIFT_MAX_PERMILLE,
+            ),
+            None,
             Some(event_sender),
         )
         // .map_err(|e| println!("client_tunnel error: {:?}", e))
@@ -300,7 +357,7 @@ mod tests {
         );
 
         // User can not see Keepalive messages sent from remote:
-        let vec = serialize_ka_message(&KaMessage::KeepAlive);
+        let vec = serialize_ka_message(&KaMessage::KeepAlive(0));
         await!(remote_sender.send(vec)).unwrap();
         await!(event_receiver.next()).unwrap();
 
@@ -319,10 +376,10 @@ mod tests {
 
         // We expect to see a keepalive being sent:
         let vec = await!(remote_receiver.next()).unwrap();
-        assert_eq!(vec, serialize_ka_message(&KaMessage::KeepAlive));
+        assert_eq!(vec, serialize_ka_message(&KaMessage::KeepAlive(8)));
 
         // Remote sends a keepalive:
-        let vec = serialize_ka_message(&KaMessage::KeepAlive);
+        let vec = serialize_ka_message(&KaMessage::KeepAlive(8));
         await!(remote_sender.send(vec)).unwrap();
         await!(event_receiver.next()).unwrap();
 
@@ -344,6 +401,115 @@ mod tests {
         thread_pool.run(task_keepalive_loop_basic(thread_pool.clone()));
     }
 
+    async fn task_keepalive_loop_tick_drift(mut spawner: impl Spawn + Clone) {
+        // Create a mock time service:
+        let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let mut timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (event_sender, mut event_receiver) = mpsc::channel(0);
+        let (drift_sender, mut drift_receiver) = mpsc::channel(1);
+
+        // Large enough, so that we don't have to read keepalives sent to the remote side:
+        let (to_remote, _remote_receiver) = mpsc::channel::<Vec<u8>>(0x100);
+        let (mut remote_sender, from_remote) = mpsc::channel::<Vec<u8>>(0);
+
+        let (to_user, _user_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (_user_sender, from_user) = mpsc::channel::<Vec<u8>>(0);
+
+        let timer_stream = await!(timer_client.request_timer_stream()).unwrap();
+        let keepalive_ticks = 16;
+        let fut_keepalive_loop = inner_keepalive_loop(
+            to_remote,
+            from_remote,
+            to_user,
+            from_user,
+            timer_stream,
+            keepalive_ticks,
+            TickDriftDetector::new(32, 750, 1333),
+            Some(drift_sender),
+            Some(event_sender),
+        )
+        .map(|_| ());
+
+        spawner.spawn(fut_keepalive_loop).unwrap();
+
+        // The remote side's tick feed runs at half speed.
+        // It sends a keepalive every 4 remote ticks:
+        for local_ticks in 1..=64u64 {
+            await!(tick_sender.send(())).unwrap();
+            await!(event_receiver.next()).unwrap();
+
+            if local_ticks % 8 == 0 {
+                let remote_ticks = local_ticks / 2;
+                let vec = serialize_ka_message(&KaMessage::KeepAlive(remote_ticks));
+                await!(remote_sender.send(vec)).unwrap();
+                await!(event_receiver.next()).unwrap();
+            }
+        }
+
+        let report = await!(drift_receiver.next()).unwrap();
+        assert!(report.ratio_permille >= 450 && report.ratio_permille <= 550);
+
+        // Only one report is expected:
+        drop(remote_sender);
+        assert!(await!(drift_receiver.next()).is_none());
+    }
+
+    #[test]
+    fn test_keepalive_loop_tick_drift() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_keepalive_loop_tick_drift(thread_pool.clone()));
+    }
+
+    async fn task_keepalive_loop_ticks_jump(mut spawner: impl Spawn + Clone) {
+        let (mut tick_sender, timer_stream) = mpsc::channel::<TimerTick>(0);
+
+        let (event_sender, mut event_receiver) = mpsc::channel(0);
+
+        let (to_remote, mut remote_receiver) = mpsc::channel::<Vec<u8>>(0x100);
+        let (_remote_sender, from_remote) = mpsc::channel::<Vec<u8>>(0);
+
+        let (to_user, mut user_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (_user_sender, from_user) = mpsc::channel::<Vec<u8>>(0);
+
+        let keepalive_ticks = 16;
+        let fut_keepalive_loop = inner_keepalive_loop(
+            to_remote,
+            from_remote,
+            to_user,
+            from_user,
+            timer_stream,
+            keepalive_ticks,
+            TickDriftDetector::new(
+                usize_to_u64(TICK_DRIFT_WINDOW_TICKS).unwrap(),
+                TICK_DRIFT_MIN_PERMILLE,
+                TICK_DRIFT_MAX_PERMILLE,
+            ),
+            None,
+            Some(event_sender),
+        )
+        .map(|_| ());
+
+        spawner.spawn(fut_keepalive_loop).unwrap();
+
+        // Many ticks elapse at once (For example, the host was suspended):
+        await!(tick_sender.send(TimerTick {
+            ticks_elapsed: 10_000
+        }))
+        .unwrap();
+        await!(event_receiver.next()).unwrap();
+
+        // The remote side timed out. No keepalives were sent:
+        assert!(await!(remote_receiver.next()).is_none());
+        assert!(await!(user_receiver.next()).is_none());
+    }
+
+    #[test]
+    fn test_keepalive_loop_ticks_jump() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_keepalive_loop_ticks_jump(thread_pool.clone()));
+    }
+
     async fn task_keepalive_channel_basic(spawner: impl Spawn + Clone) {
         // Create a mock time service:
         let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
@@ -19,6 +19,8 @@ extern crate log;
 #[macro_use]
 extern crate common;
 
+mod drift;
 mod keepalive;
 
+pub use self::drift::TickDriftReport;
 pub use self::keepalive::KeepAliveChannel;
@@ -16,7 +16,11 @@
 #[macro_use]
 extern crate log;
 
+#[macro_use]
+extern crate common;
+
 mod net_connector;
+mod race_connector;
 mod resolver;
 mod tcp_connector;
 mod tcp_listener;
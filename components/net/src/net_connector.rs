@@ -3,27 +3,47 @@ use futures::task::Spawn;
 
 use proto::net::messages::NetAddress;
 
+use timer::TimerClient;
+
+use crate::race_connector::RaceConnector;
 use crate::resolver::Resolver;
 use crate::tcp_connector::TcpConnector;
 
 #[derive(Clone)]
 pub struct NetConnector<S, RS> {
     resolver: Resolver<RS>,
-    tcp_connector: TcpConnector<S>,
+    race_connector: RaceConnector<TcpConnector<S>, S>,
 }
 
-impl<S, RS> NetConnector<S, RS> {
-    pub fn new(max_frame_length: usize, resolve_spawner: RS, spawner: S) -> Self {
+impl<S, RS> NetConnector<S, RS>
+where
+    S: Spawn + Clone + Send + 'static,
+{
+    /// `connect_stagger_ticks` is the amount of ticks we wait for a connection attempt to one of
+    /// the resolved addresses before starting an attempt to the next address.
+    pub fn new(
+        max_frame_length: usize,
+        timer_client: TimerClient,
+        connect_stagger_ticks: usize,
+        resolve_spawner: RS,
+        spawner: S,
+    ) -> Self {
+        let tcp_connector = TcpConnector::new(max_frame_length, spawner.clone());
         NetConnector {
             resolver: Resolver::new(resolve_spawner),
-            tcp_connector: TcpConnector::new(max_frame_length, spawner),
+            race_connector: RaceConnector::new(
+                tcp_connector,
+                timer_client,
+                connect_stagger_ticks,
+                spawner,
+            ),
         }
     }
 }
 
 impl<S, RS> FutTransform for NetConnector<S, RS>
 where
-    S: Spawn + Send,
+    S: Spawn + Clone + Send + 'static,
     RS: Spawn + Send,
 {
     type Input = NetAddress;
@@ -34,10 +54,8 @@ where
         Box::pin(
             async move {
                 let socket_addr_vec = await!(self.resolver.transform(net_address));
-                // A trivial implementation: We try to connect to the first address on the list.
-                // TODO: Maybe choose a random address in the future?
-                let socket_addr = socket_addr_vec.get(0)?;
-                await!(self.tcp_connector.transform(*socket_addr))
+                // Race connection attempts to all the resolved addresses:
+                await!(self.race_connector.transform(socket_addr_vec))
             },
         )
     }
@@ -0,0 +1,395 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures::channel::mpsc;
+use futures::future::RemoteHandle;
+use futures::task::{Spawn, SpawnExt};
+use futures::{SinkExt, StreamExt};
+
+use common::conn::{BoxFuture, ConnPairVec, FutTransform};
+use common::int_convert::usize_to_u64;
+use common::select_streams::{select_streams, BoxStream};
+
+use timer::{TimerClient, TimerTick};
+
+/// Maximum absolute score of an address family. Bounding the score allows a family that used to
+/// work to lose its preference after a few failures.
+const MAX_FAMILY_SCORE: i8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AddrFamily {
+    V4,
+    V6,
+}
+
+impl AddrFamily {
+    fn of(socket_addr: &SocketAddr) -> Self {
+        if socket_addr.is_ipv4() {
+            AddrFamily::V4
+        } else {
+            AddrFamily::V6
+        }
+    }
+}
+
+/// Connection outcomes of every address family, used to decide which family we try first.
+#[derive(Debug, Default)]
+struct FamilyScores {
+    v4: i8,
+    v6: i8,
+}
+
+impl FamilyScores {
+    /// IPv6 is preferred, unless IPv4 has been working better recently.
+    fn preferred(&self) -> AddrFamily {
+        if self.v4 > self.v6 {
+            AddrFamily::V4
+        } else {
+            AddrFamily::V6
+        }
+    }
+
+    fn update(&mut self, family: AddrFamily, success: bool) {
+        let score = match family {
+            AddrFamily::V4 => &mut self.v4,
+            AddrFamily::V6 => &mut self.v6,
+        };
+        *score = if success {
+            score.saturating_add(1).min(MAX_FAMILY_SCORE)
+        } else {
+            score.saturating_sub(1).max(-MAX_FAMILY_SCORE)
+        };
+    }
+}
+
+/// Order addresses for connection attempts: Families are interleaved, starting with the
+/// preferred family. The order of addresses within a family is kept.
+fn order_addrs(socket_addrs: Vec<SocketAddr>, preferred: AddrFamily) -> Vec<SocketAddr> {
+    let (first, second): (Vec<_>, Vec<_>) = socket_addrs
+        .into_iter()
+        .partition(|socket_addr| AddrFamily::of(socket_addr) == preferred);
+
+    let mut ordered = Vec::with_capacity(first.len() + second.len());
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+            (opt_a, opt_b) => {
+                ordered.extend(opt_a);
+                ordered.extend(opt_b);
+            }
+        }
+    }
+    ordered
+}
+
+#[derive(Debug)]
+enum RaceEvent {
+    /// A connection attempt was finished
+    Attempt((SocketAddr, Option<ConnPairVec>)),
+    TimerTick(TimerTick),
+}
+
+/// Connects to one of multiple addresses, Happy Eyeballs style:
+/// Connection attempts are started one by one, every `stagger_ticks` ticks (Or immediately
+/// when the previous attempt failed). The first successful connection is returned, and all the
+/// other attempts are aborted.
+#[derive(Clone)]
+pub struct RaceConnector<C, S> {
+    connector: C,
+    timer_client: TimerClient,
+    stagger_ticks: usize,
+    /// Shared between all the clones of this connector
+    family_scores: Arc<Mutex<FamilyScores>>,
+    spawner: S,
+}
+
+impl<C, S> RaceConnector<C, S>
+where
+    C: FutTransform<Input = SocketAddr, Output = Option<ConnPairVec>> + Clone + Send + 'static,
+    S: Spawn,
+{
+    pub fn new(connector: C, timer_client: TimerClient, stagger_ticks: usize, spawner: S) -> Self {
+        RaceConnector {
+            connector,
+            timer_client,
+            stagger_ticks,
+            family_scores: Arc::new(Mutex::new(FamilyScores::default())),
+            spawner,
+        }
+    }
+
+    /// Start a connection attempt. The attempt is aborted when the returned handle is dropped.
+    fn start_attempt(
+        &mut self,
+        socket_addr: SocketAddr,
+        mut attempt_sender: mpsc::Sender<(SocketAddr, Option<ConnPairVec>)>,
+    ) -> Option<RemoteHandle<()>> {
+        let mut connector = self.connector.clone();
+        let attempt_fut = async move {
+            let opt_conn_pair = await!(connector.transform(socket_addr));
+            let _ = await!(attempt_sender.send((socket_addr, opt_conn_pair)));
+        };
+        self.spawner.spawn_with_handle(attempt_fut).ok()
+    }
+}
+
+impl<C, S> FutTransform for RaceConnector<C, S>
+where
+    C: FutTransform<Input = SocketAddr, Output = Option<ConnPairVec>> + Clone + Send + 'static,
+    S: Spawn + Send,
+{
+    type Input = Vec<SocketAddr>;
+    type Output = Option<ConnPairVec>;
+
+    fn transform(&mut self, socket_addrs: Self::Input) -> BoxFuture<'_, Self::Output> {
+        Box::pin(
+            async move {
+                let preferred = self.family_scores.lock().unwrap().preferred();
+                let mut pending_addrs = order_addrs(socket_addrs, preferred).into_iter();
+
+                let timer_stream = await!(self.timer_client.request_timer_stream()).ok()?;
+                let timer_stream = timer_stream.map(RaceEvent::TimerTick);
+
+                let (attempt_sender, attempt_receiver) = mpsc::channel(0);
+                let attempt_receiver = attempt_receiver.map(RaceEvent::Attempt);
+                let mut incoming_events = select_streams![attempt_receiver, timer_stream];
+
+                // Dropping the handles aborts the attempts that are still running:
+                let mut attempt_handles = Vec::new();
+                let stagger_ticks = usize_to_u64(self.stagger_ticks).unwrap();
+                let mut ticks_since_start = 0u64;
+
+                loop {
+                    // Start the next attempt if the previous attempts had enough time, or if
+                    // no attempt is running:
+                    let num_running = attempt_handles.len();
+                    if num_running == 0 || ticks_since_start >= stagger_ticks {
+                        match pending_addrs.next() {
+                            Some(socket_addr) => {
+                                let handle =
+                                    self.start_attempt(socket_addr, attempt_sender.clone())?;
+                                attempt_handles.push((socket_addr, handle));
+                                ticks_since_start = 0;
+                                continue;
+                            }
+                            None if num_running == 0 => return None,
+                            None => {}
+                        }
+                    }
+
+                    match await!(incoming_events.next())? {
+                        RaceEvent::Attempt((socket_addr, opt_conn_pair)) => {
+                            let family = AddrFamily::of(&socket_addr);
+                            self.family_scores
+                                .lock()
+                                .unwrap()
+                                .update(family, opt_conn_pair.is_some());
+                            if opt_conn_pair.is_some() {
+                                debug!("Connected to {:?}", socket_addr);
+                                return opt_conn_pair;
+                            }
+                            attempt_handles.retain(|(addr, _)| addr != &socket_addr);
+                        }
+                        RaceEvent::TimerTick(timer_tick) => {
+                            ticks_since_start =
+                                ticks_since_start.saturating_add(timer_tick.ticks_elapsed);
+                        }
+                    }
+                }
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use futures::channel::oneshot;
+    use futures::executor::ThreadPool;
+
+    use timer::create_timer_incoming;
+
+    fn addr_v4(last: u8) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last)), 1337)
+    }
+
+    fn addr_v6(last: u16) -> SocketAddr {
+        SocketAddr::new(
+            IpAddr::V6(Ipv6Addr::new(0xfd, 0, 0, 0, 0, 0, 0, last)),
+            1337,
+        )
+    }
+
+    #[test]
+    fn test_order_addrs() {
+        let socket_addrs = vec![addr_v6(1), addr_v6(2), addr_v6(3), addr_v4(1), addr_v4(2)];
+        assert_eq!(
+            order_addrs(socket_addrs.clone(), AddrFamily::V6),
+            vec![addr_v6(1), addr_v4(1), addr_v6(2), addr_v4(2), addr_v6(3)]
+        );
+        assert_eq!(
+            order_addrs(socket_addrs, AddrFamily::V4),
+            vec![addr_v4(1), addr_v6(1), addr_v4(2), addr_v6(2), addr_v6(3)]
+        );
+        assert_eq!(
+            order_addrs(vec![addr_v4(1), addr_v4(2)], AddrFamily::V6),
+            vec![addr_v4(1), addr_v4(2)]
+        );
+    }
+
+    #[test]
+    fn test_family_scores() {
+        let mut family_scores = FamilyScores::default();
+        assert_eq!(family_scores.preferred(), AddrFamily::V6);
+
+        family_scores.update(AddrFamily::V4, true);
+        assert_eq!(family_scores.preferred(), AddrFamily::V4);
+
+        // A bounded score is lost after a bounded amount of failures:
+        for _ in 0..0x100 {
+            family_scores.update(AddrFamily::V4, true);
+        }
+        for _ in 0..MAX_FAMILY_SCORE {
+            family_scores.update(AddrFamily::V4, false);
+        }
+        assert_eq!(family_scores.preferred(), AddrFamily::V6);
+    }
+
+    /// A connection attempt, as seen by the test
+    struct Attempt {
+        socket_addr: SocketAddr,
+        /// Keeps a blackholed attempt hanging
+        _blackhole_sender: oneshot::Sender<()>,
+        /// Canceled when the attempt is dropped
+        abort_receiver: oneshot::Receiver<()>,
+    }
+
+    /// Connects successfully to all the addresses, except for the blackholed addresses, that
+    /// never answer.
+    #[derive(Clone)]
+    struct ScriptedConnector {
+        blackholed: Vec<SocketAddr>,
+        attempts_sender: mpsc::UnboundedSender<Attempt>,
+    }
+
+    impl FutTransform for ScriptedConnector {
+        type Input = SocketAddr;
+        type Output = Option<ConnPairVec>;
+
+        fn transform(&mut self, socket_addr: Self::Input) -> BoxFuture<'_, Self::Output> {
+            let (blackhole_sender, blackhole_receiver) = oneshot::channel::<()>();
+            let (abort_sender, abort_receiver) = oneshot::channel::<()>();
+            let attempt = Attempt {
+                socket_addr,
+                _blackhole_sender: blackhole_sender,
+                abort_receiver,
+            };
+            self.attempts_sender.unbounded_send(attempt).unwrap();
+            let blackholed = self.blackholed.contains(&socket_addr);
+
+            Box::pin(
+                async move {
+                    let _abort_sender = abort_sender;
+                    if blackholed {
+                        await!(blackhole_receiver).ok()?;
+                    }
+                    let (sender, _) = mpsc::channel(0);
+                    let (_, receiver) = mpsc::channel(0);
+                    Some((sender, receiver))
+                },
+            )
+        }
+    }
+
+    async fn task_race_connector_blackholed_family<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        // Create a mock time service:
+        let (mut tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        // IPv6 is broken:
+        let (attempts_sender, mut attempts_receiver) = mpsc::unbounded();
+        let scripted_connector = ScriptedConnector {
+            blackholed: vec![addr_v6(1)],
+            attempts_sender,
+        };
+        let race_connector =
+            RaceConnector::new(scripted_connector, timer_client, 1, spawner.clone());
+        let socket_addrs = vec![addr_v6(1), addr_v4(1)];
+
+        let mut c_race_connector = race_connector.clone();
+        let c_socket_addrs = socket_addrs.clone();
+        let connect_handle = spawner
+            .clone()
+            .spawn_with_handle(async move { await!(c_race_connector.transform(c_socket_addrs)) })
+            .unwrap();
+
+        // IPv6 is preferred at first:
+        let attempt_v6 = await!(attempts_receiver.next()).unwrap();
+        assert_eq!(attempt_v6.socket_addr, addr_v6(1));
+
+        // The IPv4 attempt starts after one stagger tick, instead of after a full timeout:
+        assert!(attempts_receiver.try_next().is_err());
+        await!(tick_sender.send(())).unwrap();
+        let attempt_v4 = await!(attempts_receiver.next()).unwrap();
+        assert_eq!(attempt_v4.socket_addr, addr_v4(1));
+        assert!(await!(connect_handle).is_some());
+
+        // The blackholed attempt was aborted:
+        assert!(await!(attempt_v6.abort_receiver).is_err());
+
+        // IPv4 is preferred on the next attempt, and connects right away:
+        let mut race_connector = race_connector;
+        assert!(await!(race_connector.transform(socket_addrs)).is_some());
+        let attempt = await!(attempts_receiver.next()).unwrap();
+        assert_eq!(attempt.socket_addr, addr_v4(1));
+        assert!(attempts_receiver.try_next().is_err());
+    }
+
+    #[test]
+    fn test_race_connector_blackholed_family() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_race_connector_blackholed_family(thread_pool.clone()));
+    }
+
+    async fn task_race_connector_all_failed<S>(spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (attempts_sender, mut attempts_receiver) = mpsc::unbounded();
+        let scripted_connector = ScriptedConnector {
+            blackholed: vec![addr_v6(1), addr_v4(1)],
+            attempts_sender,
+        };
+        let mut race_connector =
+            RaceConnector::new(scripted_connector, timer_client, 1, spawner.clone());
+        let connect_fut = race_connector.transform(vec![addr_v6(1), addr_v4(1)]);
+
+        // Failed attempts are followed by the next attempt right away, without waiting for a
+        // timer tick:
+        let fut_fail_attempts = async move {
+            for _ in 0..2 {
+                // Dropping the attempt fails it:
+                let _ = await!(attempts_receiver.next()).unwrap();
+            }
+        };
+        spawner.clone().spawn(fut_fail_attempts).unwrap();
+        assert!(await!(connect_fut).is_none());
+    }
+
+    #[test]
+    fn test_race_connector_all_failed() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_race_connector_all_failed(thread_pool.clone()));
+    }
+}
@@ -3,6 +3,7 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use env_logger;
 
+use futures::channel::mpsc;
 use futures::executor::ThreadPool;
 use futures::task::Spawn;
 use futures::{SinkExt, StreamExt};
@@ -10,6 +11,8 @@ use futures::{SinkExt, StreamExt};
 use common::conn::{FutTransform, Listener};
 use proto::net::messages::NetAddress;
 
+use timer::{create_timer_incoming, TimerClient};
+
 use crate::net_connector::NetConnector;
 use crate::tcp_connector::TcpConnector;
 use crate::tcp_listener::TcpListener;
@@ -28,6 +31,17 @@ fn get_available_port_v4() -> u16 {
 }
 
 const TEST_MAX_FRAME_LEN: usize = 0x100;
+const TEST_CONNECT_STAGGER_TICKS: usize = 1;
+
+/// A timer that never ticks
+fn dummy_timer_client<S>(spawner: S) -> (mpsc::Sender<()>, TimerClient)
+where
+    S: Spawn,
+{
+    let (tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+    let timer_client = create_timer_incoming(tick_receiver, spawner).unwrap();
+    (tick_sender, timer_client)
+}
 
 async fn task_tcp_client_server_v4<S>(spawner: S)
 where
@@ -82,7 +96,14 @@ where
     let socket_addr = SocketAddr::new(IpAddr::V4(loopback), available_port);
 
     let tcp_listener = TcpListener::new(TEST_MAX_FRAME_LEN, spawner.clone());
-    let mut net_connector = NetConnector::new(TEST_MAX_FRAME_LEN, spawner.clone(), spawner.clone());
+    let (_tick_sender, timer_client) = dummy_timer_client(spawner.clone());
+    let mut net_connector = NetConnector::new(
+        TEST_MAX_FRAME_LEN,
+        timer_client,
+        TEST_CONNECT_STAGGER_TICKS,
+        spawner.clone(),
+        spawner.clone(),
+    );
 
     let (_config_sender, mut incoming_connections) = tcp_listener.listen(socket_addr.clone());
 
@@ -116,7 +137,14 @@ where
     let socket_addr = SocketAddr::new(IpAddr::V4(loopback), available_port);
 
     let tcp_listener = TcpListener::new(TEST_MAX_FRAME_LEN, spawner.clone());
-    let mut net_connector = NetConnector::new(TEST_MAX_FRAME_LEN, spawner.clone(), spawner.clone());
+    let (_tick_sender, timer_client) = dummy_timer_client(spawner.clone());
+    let mut net_connector = NetConnector::new(
+        TEST_MAX_FRAME_LEN,
+        timer_client,
+        TEST_CONNECT_STAGGER_TICKS,
+        spawner.clone(),
+        spawner.clone(),
+    );
 
     let (_config_sender, mut incoming_connections) = tcp_listener.listen(socket_addr.clone());
 
@@ -1,12 +1,16 @@
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
-use futures::{SinkExt, StreamExt};
+use futures::{future, stream, SinkExt, Stream, StreamExt};
 
 use common::conn::{ConnPair, ConnPairVec, FutTransform};
 
-use proto::app_server::messages::{AppPermissions, AppServerToApp, AppToAppServer, NodeReport};
+use proto::app_server::chunk::{ChunkAssembler, ChunkError};
+use proto::app_server::messages::{
+    AppHello, AppPermissions, AppServerToApp, AppToAppServer, AppToAppServerFrame, NodeReport,
+};
 use proto::app_server::serialize::{
-    deserialize_app_permissions, deserialize_app_server_to_app, serialize_app_to_app_server,
+    deserialize_app_permissions, deserialize_app_server_to_app_frame, serialize_app_hello,
+    serialize_app_to_app_server_frame,
 };
 use proto::consts::{KEEPALIVE_TICKS, PROTOCOL_VERSION, TICKS_TO_REKEY};
 use proto::net::messages::NetAddress;
@@ -15,6 +19,7 @@ use timer::TimerClient;
 
 use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::PublicKey;
+use crypto::uid::Uid;
 use identity::IdentityClient;
 
 pub use super::node_connection::NodeConnection;
@@ -34,18 +39,58 @@ pub enum SetupConnectionError {
     EncryptSetupError,
     RecvAppPermissionsError,
     DeserializeAppPermissionsError,
+    SendAppHelloError,
     ClosedBeforeNodeReport,
     DeserializeNodeReportError,
+    ChunkedNodeReportError(ChunkError),
     FirstMessageNotNodeReport,
 }
 
-/// Connect to an offst-node
+#[derive(Debug)]
+enum RecvMessageError {
+    DeserializeError,
+    ChunkError(ChunkError),
+}
+
+/// Events handled by the task that sends data to the node
+enum SendEvent {
+    Frame(AppToAppServerFrame),
+    UserClosed,
+}
+
+/// Receive the next complete message from the node.
+/// Large messages are received in chunks, and are reassembled here.
+/// Returns None if the connection was closed.
+async fn recv_message<'a, M>(
+    receiver: &'a mut M,
+    assembler: &'a mut ChunkAssembler,
+) -> Result<Option<AppServerToApp>, RecvMessageError>
+where
+    M: Stream<Item = Vec<u8>> + Unpin,
+{
+    while let Some(data) = await!(receiver.next()) {
+        let frame = deserialize_app_server_to_app_frame(&data)
+            .map_err(|_| RecvMessageError::DeserializeError)?;
+        if let Some(message) = assembler
+            .push_frame(frame)
+            .map_err(RecvMessageError::ChunkError)?
+        {
+            return Ok(Some(message));
+        }
+    }
+    Ok(None)
+}
+
+/// Connect to an offst-node.
+/// `session_id` identifies the session of the app. Connecting again with the same session id
+/// resumes the session.
 pub async fn setup_connection<R, S>(
     conn_pair: ConnPairVec,
     timer_client: TimerClient,
     rng: R,
     node_public_key: PublicKey,
     app_identity_client: IdentityClient,
+    session_id: Uid,
     mut spawner: S,
 ) -> Result<NodeConnectionTuple, SetupConnectionError>
 where
@@ -59,6 +104,9 @@ where
         rng.clone(),
         timer_client.clone(),
         TICKS_TO_REKEY,
+        None, // max_messages_before_rekey
+        0,    // incoming_queue_len
+        None, // opt_resume_ticks
         spawner.clone(),
     );
 
@@ -83,10 +131,26 @@ where
     let app_permissions = deserialize_app_permissions(&app_permissions_data)
         .map_err(|_| SetupConnectionError::DeserializeAppPermissionsError)?;
 
-    // Wait for the first NodeReport.
-    let data = await!(receiver.next()).ok_or(SetupConnectionError::ClosedBeforeNodeReport)?;
-    let message = deserialize_app_server_to_app(&data)
-        .map_err(|_| SetupConnectionError::DeserializeNodeReportError)?;
+    // Tell the node which session this connection belongs to:
+    await!(sender.send(serialize_app_hello(&AppHello { session_id })))
+        .map_err(|_| SetupConnectionError::SendAppHelloError)?;
+
+    // Wait for the first NodeReport. It might be sent in chunks.
+    let mut assembler = ChunkAssembler::new();
+    let message = match await!(recv_message(&mut receiver, &mut assembler)) {
+        Ok(Some(message)) => message,
+        Ok(None) => return Err(SetupConnectionError::ClosedBeforeNodeReport),
+        Err(RecvMessageError::DeserializeError) => {
+            return Err(SetupConnectionError::DeserializeNodeReportError)
+        }
+        Err(RecvMessageError::ChunkError(chunk_error)) => {
+            if let Some(transfer_id) = assembler.abort() {
+                let abort_frame = AppToAppServerFrame::AbortTransfer(transfer_id);
+                let _ = await!(sender.send(serialize_app_to_app_server_frame(&abort_frame)));
+            }
+            return Err(SetupConnectionError::ChunkedNodeReportError(chunk_error));
+        }
+    };
 
     let node_report = if let AppServerToApp::Report(node_report) = message {
         node_report
@@ -98,13 +162,23 @@ where
     let (user_sender, mut from_user_sender) = mpsc::channel(0);
     let (mut to_user_receiver, user_receiver) = mpsc::channel(0);
 
+    // Transfers we want the node to abort:
+    let (mut abort_sender, abort_receiver) = mpsc::channel(0);
+
     // Deserialize data received from node:
     let _ = spawner.spawn(
         async move {
-            while let Some(data) = await!(receiver.next()) {
-                let message = match deserialize_app_server_to_app(&data) {
-                    Ok(message) => message,
-                    Err(_) => return,
+            loop {
+                let message = match await!(recv_message(&mut receiver, &mut assembler)) {
+                    Ok(Some(message)) => message,
+                    Ok(None) => return,
+                    Err(e) => {
+                        error!("recv_message() error: {:?}", e);
+                        if let Some(transfer_id) = assembler.abort() {
+                            let _ = await!(abort_sender.send(transfer_id));
+                        }
+                        return;
+                    }
                 };
                 if await!(to_user_receiver.send(message)).is_err() {
                     return;
@@ -114,10 +188,20 @@ where
     );
 
     // Serialize data sent to node:
+    let from_user_sender = from_user_sender
+        .map(|message| SendEvent::Frame(AppToAppServerFrame::Message(message)))
+        .chain(stream::once(future::ready(SendEvent::UserClosed)));
+    let abort_receiver = abort_receiver
+        .map(|transfer_id| SendEvent::Frame(AppToAppServerFrame::AbortTransfer(transfer_id)));
+    let mut send_events = from_user_sender.select(abort_receiver);
     let _ = spawner.spawn(
         async move {
-            while let Some(message) = await!(from_user_sender.next()) {
-                let data = serialize_app_to_app_server(&message);
+            while let Some(send_event) = await!(send_events.next()) {
+                let frame = match send_event {
+                    SendEvent::Frame(frame) => frame,
+                    SendEvent::UserClosed => return,
+                };
+                let data = serialize_app_to_app_server_frame(&frame);
                 if await!(sender.send(data)).is_err() {
                     return;
                 }
@@ -136,13 +220,45 @@ pub enum NodeConnectError {
     CreateNodeConnectionError,
 }
 
-/// Connect to an offst node
+/// Connect to an offst node, opening a new session
 pub async fn node_connect<C, R, S>(
+    net_connector: C,
+    node_public_key: PublicKey,
+    node_net_address: NetAddress,
+    timer_client: TimerClient,
+    app_identity_client: IdentityClient,
+    rng: R,
+    spawner: S,
+) -> Result<NodeConnection<R>, NodeConnectError>
+where
+    C: FutTransform<Input = NetAddress, Output = Option<ConnPairVec>>,
+    R: CryptoRandom + Clone + 'static,
+    S: Spawn + Send + Sync + Clone + 'static,
+{
+    let session_id = Uid::new(&rng);
+    await!(node_connect_session(
+        net_connector,
+        node_public_key,
+        node_net_address,
+        timer_client,
+        app_identity_client,
+        session_id,
+        rng,
+        spawner
+    ))
+}
+
+/// Connect to an offst node, using the given session id.
+/// Responses to requests sent over a previous connection of the same session are received over
+/// the new connection, if the node still remembers the session.
+/// Other sessions of the same app are not affected.
+pub async fn node_connect_session<C, R, S>(
     mut net_connector: C,
     node_public_key: PublicKey,
     node_net_address: NetAddress,
     timer_client: TimerClient,
     app_identity_client: IdentityClient,
+    session_id: Uid,
     rng: R,
     mut spawner: S,
 ) -> Result<NodeConnection<R>, NodeConnectError>
@@ -156,14 +272,15 @@ where
 
     let conn_tuple = await!(setup_connection(
         conn_pair,
-        timer_client,
+        timer_client.clone(),
         rng.clone(),
         node_public_key,
         app_identity_client,
+        session_id,
         spawner.clone()
     ))
     .map_err(NodeConnectError::SetupConnectionError)?;
 
-    NodeConnection::new(conn_tuple, rng, &mut spawner)
+    NodeConnection::new(conn_tuple, timer_client, rng, &mut spawner)
         .map_err(|_| NodeConnectError::CreateNodeConnectionError)
 }
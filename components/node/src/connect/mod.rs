@@ -1,8 +1,24 @@
 mod connect;
 mod node_connection;
 
-pub use self::connect::{node_connect, NodeConnection};
+pub use self::connect::{node_connect, node_connect_session, NodeConnection};
 
 pub use self::node_connection::{
-    config::AppConfig, report::AppReport, routes::AppRoutes, send_funds::AppSendFunds,
+    config::{AddFriendError, AppConfig, ExistingFriend, SetFriendRelaysError},
+    estimate::{estimate_payment, EstimateConfidence, EstimateParams, PaymentEstimate},
+    incoming_payments::{AppIncomingPayments, IncomingPayments},
+    mirror::NodeStateMirror,
+    rebalance::{AppRebalance, BalanceRange, RebalanceAction, RebalanceConfig, RebalanceError},
+    report::{AppReport, WaitForError},
+    routes::AppRoutes,
+    self_test::{AppSelfTest, AppSelfTestError},
+    send_funds::{
+        AckSubmissionError, AppSendFunds, CancelSendFundsError, LabeledPaymentsError,
+        PendingSubmissionsError, PrewarmError, SendFundsError,
+    },
+};
+
+pub use self::node_connection::route_select::{
+    select_route, select_route_by_policy, CheapestFee, HighestCapacity, RandomWeighted,
+    ReliabilityWeighted, RoutePolicy, RouteSelector, Shortest,
 };
@@ -2,24 +2,65 @@ use futures::channel::mpsc;
 use futures::{SinkExt, StreamExt};
 
 use common::multi_consumer::MultiConsumerClient;
+use common::mutable_state::BatchMutable;
+use common::state_service::StateClient;
 
 use crypto::crypto_rand::{CryptoRandom, OffstSystemRandom};
 use crypto::identity::{PublicKey, Signature};
 use crypto::uid::Uid;
 
-use proto::app_server::messages::{AppRequest, AppToAppServer, NamedRelayAddress, RelayAddress};
+use proto::app_server::messages::{
+    AppRequest, AppToAppServer, NamedRelayAddress, NodeReport, NodeReportMutation, RelayAddress,
+    RequestDebugBundle, ResponseDebugBundle,
+};
+use proto::directory::messages::DirectorySubscription;
 use proto::funder::messages::{
-    AddFriend, ResetFriendChannel, SetFriendRelays, SetFriendRemoteMaxDebt,
+    AddFriend, DustThresholds, FriendPair, Goodbye, PairFreezeLimit, PaymentConsumer,
+    PaymentNotifier, PaymentNotifyFilter, RemoteMaxDebtExpiry, RequestPairFreezeUsage,
+    ResetFriendChannel, ResponsePairFreezeUsage, SetFriendIndexPrivate, SetFriendRelays,
+    SetFriendRemoteMaxDebt, SetFriendResponseDeadline, SetFriendVerificationPhrase,
+    SetPairFreezeLimit,
 };
 use proto::index_server::messages::NamedIndexServerAddress;
+use proto::invite::messages::{
+    FriendInvite, ImportFriendInvite, ImportFriendInviteResult, ResponseImportFriendInvite,
+};
+use proto::net::messages::NetAddress;
+use proto::report::messages::{FriendReport, FriendStatusReport};
 
 #[derive(Debug)]
 pub struct AppConfigError;
 
+/// A friend that already exists, as currently known to the node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExistingFriend {
+    pub name: String,
+    pub relays: Vec<RelayAddress>,
+    pub status: FriendStatusReport,
+}
+
+#[derive(Debug)]
+pub enum AddFriendError {
+    /// A friend with the same public key already exists. Nothing was changed.
+    /// The address of an existing friend is updated using `set_friend_relays`.
+    AlreadyExists(ExistingFriend),
+    AppConfigError,
+}
+
+#[derive(Debug)]
+pub enum SetFriendRelaysError {
+    FriendDoesNotExist,
+    AppConfigError,
+}
+
 #[derive(Clone)]
 pub struct AppConfig<R = OffstSystemRandom> {
     sender: mpsc::Sender<AppToAppServer>,
     done_app_requests_mc: MultiConsumerClient<Uid>,
+    debug_bundle_mc: MultiConsumerClient<ResponseDebugBundle>,
+    import_invite_mc: MultiConsumerClient<ResponseImportFriendInvite>,
+    pair_freeze_usage_mc: MultiConsumerClient<ResponsePairFreezeUsage>,
+    report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
     rng: R,
 }
 
@@ -30,15 +71,38 @@ where
     pub(super) fn new(
         sender: mpsc::Sender<AppToAppServer>,
         done_app_requests_mc: MultiConsumerClient<Uid>,
+        debug_bundle_mc: MultiConsumerClient<ResponseDebugBundle>,
+        import_invite_mc: MultiConsumerClient<ResponseImportFriendInvite>,
+        pair_freeze_usage_mc: MultiConsumerClient<ResponsePairFreezeUsage>,
+        report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
         rng: R,
     ) -> Self {
         AppConfig {
             sender,
             done_app_requests_mc,
+            debug_bundle_mc,
+            import_invite_mc,
+            pair_freeze_usage_mc,
+            report_client,
             rng,
         }
     }
 
+    /// Obtain the current report of a friend, if the friend exists.
+    async fn friend_report(
+        &mut self,
+        friend_public_key: PublicKey,
+    ) -> Result<Option<FriendReport>, AppConfigError> {
+        let (batch_mutable, _incoming_mutations) =
+            await!(self.report_client.request_state()).map_err(|_| AppConfigError)?;
+        let node_report = batch_mutable.0;
+        Ok(node_report
+            .funder_report
+            .friends
+            .get(&friend_public_key)
+            .cloned())
+    }
+
     async fn send_request(&mut self, app_request: AppRequest) -> Result<(), AppConfigError> {
         // Randomly generate a new app_request_id:
         let app_request_id = Uid::new(&self.rng);
@@ -74,13 +138,24 @@ where
         await!(self.send_request(AppRequest::RemoveRelay(relay_public_key)))
     }
 
+    /// Add a new friend. Fails if a friend with the same public key already exists.
     pub async fn add_friend(
         &mut self,
         friend_public_key: PublicKey,
         relays: Vec<RelayAddress>,
         name: String,
         balance: i128,
-    ) -> Result<(), AppConfigError> {
+    ) -> Result<(), AddFriendError> {
+        let opt_friend_report = await!(self.friend_report(friend_public_key.clone()))
+            .map_err(|_| AddFriendError::AppConfigError)?;
+        if let Some(friend_report) = opt_friend_report {
+            return Err(AddFriendError::AlreadyExists(ExistingFriend {
+                name: friend_report.name,
+                relays: friend_report.remote_relays,
+                status: friend_report.status,
+            }));
+        }
+
         let add_friend = AddFriend {
             friend_public_key,
             relays,
@@ -88,18 +163,28 @@ where
             balance,
         };
         await!(self.send_request(AppRequest::AddFriend(add_friend)))
+            .map_err(|_| AddFriendError::AppConfigError)
     }
 
+    /// Update the address of an existing friend.
+    /// The node reconnects to the friend using the new address. The channel is kept.
     pub async fn set_friend_relays(
         &mut self,
         friend_public_key: PublicKey,
         relays: Vec<RelayAddress>,
-    ) -> Result<(), AppConfigError> {
+    ) -> Result<(), SetFriendRelaysError> {
+        let opt_friend_report = await!(self.friend_report(friend_public_key.clone()))
+            .map_err(|_| SetFriendRelaysError::AppConfigError)?;
+        if opt_friend_report.is_none() {
+            return Err(SetFriendRelaysError::FriendDoesNotExist);
+        }
+
         let set_friend_relays = SetFriendRelays {
             friend_public_key,
             relays,
         };
         await!(self.send_request(AppRequest::SetFriendRelays(set_friend_relays)))
+            .map_err(|_| SetFriendRelaysError::AppConfigError)
     }
 
     pub async fn remove_friend(
@@ -109,6 +194,8 @@ where
         await!(self.send_request(AppRequest::RemoveFriend(friend_public_key)))
     }
 
+    /// Enabling a friend that is already enabled makes the node reconnect to the friend
+    /// immediately, even if the friend said goodbye with an expected downtime.
     pub async fn enable_friend(
         &mut self,
         friend_public_key: PublicKey,
@@ -145,12 +232,139 @@ where
         let set_friend_remote_max_debt = SetFriendRemoteMaxDebt {
             friend_public_key,
             remote_max_debt,
+            opt_expiry: None,
         };
         await!(self.send_request(AppRequest::SetFriendRemoteMaxDebt(
             set_friend_remote_max_debt
         )))
     }
 
+    /// Set a remote max debt that lasts for `expires_after_ticks` ticks only.
+    /// Afterwards the remote max debt is reduced to `post_expiry_max_debt`, but never below the
+    /// debt the friend already owes us.
+    pub async fn set_friend_expiring_remote_max_debt(
+        &mut self,
+        friend_public_key: PublicKey,
+        remote_max_debt: u128,
+        expires_after_ticks: u64,
+        post_expiry_max_debt: u128,
+    ) -> Result<(), AppConfigError> {
+        let set_friend_remote_max_debt = SetFriendRemoteMaxDebt {
+            friend_public_key,
+            remote_max_debt,
+            opt_expiry: Some(RemoteMaxDebtExpiry {
+                expires_after_ticks,
+                post_expiry_max_debt,
+            }),
+        };
+        await!(self.send_request(AppRequest::SetFriendRemoteMaxDebt(
+            set_friend_remote_max_debt
+        )))
+    }
+
+    /// Fail requests we forward to a friend if the friend does not answer them within
+    /// `opt_deadline_ticks` ticks. `None` means that we wait for as long as it takes.
+    pub async fn set_friend_response_deadline(
+        &mut self,
+        friend_public_key: PublicKey,
+        opt_deadline_ticks: Option<u64>,
+    ) -> Result<(), AppConfigError> {
+        let set_friend_response_deadline = SetFriendResponseDeadline {
+            friend_public_key,
+            opt_deadline_ticks,
+        };
+        await!(self.send_request(AppRequest::SetFriendResponseDeadline(
+            set_friend_response_deadline
+        )))
+    }
+
+    /// Set the minimum payments the node is willing to send, forward and receive.
+    /// Smaller payments are rejected. A zero threshold accepts any payment.
+    pub async fn set_dust_thresholds(
+        &mut self,
+        dust_thresholds: DustThresholds,
+    ) -> Result<(), AppConfigError> {
+        await!(self.send_request(AppRequest::SetDustThresholds(dust_thresholds)))
+    }
+
+    /// Limit the credits frozen against the friend `pair.opt_to` for requests that arrive from
+    /// the friend `pair.opt_from` and are forwarded by us. An empty side of the pair matches any
+    /// friend. `opt_limit` of None removes the rule of the pair.
+    ///
+    /// If more than one rule matches, the most specific rule applies (See `FriendPair`).
+    pub async fn set_pair_freeze_limit(
+        &mut self,
+        pair: FriendPair,
+        opt_limit: Option<u128>,
+    ) -> Result<(), AppConfigError> {
+        let set_pair_freeze_limit = SetPairFreezeLimit { pair, opt_limit };
+        await!(self.send_request(AppRequest::SetPairFreezeLimit(set_pair_freeze_limit)))
+    }
+
+    /// Get the rule that applies to requests forwarded from `from_public_key` to
+    /// `to_public_key`, and the credits currently frozen for such requests.
+    pub async fn pair_freeze_usage(
+        &mut self,
+        from_public_key: PublicKey,
+        to_public_key: PublicKey,
+    ) -> Result<(Option<PairFreezeLimit>, u128), AppConfigError> {
+        let request_id = Uid::new(&self.rng);
+        let request_usage = RequestPairFreezeUsage {
+            request_id,
+            from_public_key,
+            to_public_key,
+        };
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server = AppToAppServer::new(
+            app_request_id,
+            AppRequest::RequestPairFreezeUsage(request_usage),
+        );
+
+        let mut incoming_usage_responses =
+            await!(self.pair_freeze_usage_mc.request_stream()).map_err(|_| AppConfigError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| AppConfigError)?;
+
+        while let Some(response_usage) = await!(incoming_usage_responses.next()) {
+            if response_usage.request_id == request_id {
+                return Ok((response_usage.opt_limit, response_usage.frozen_credits));
+            }
+        }
+        Err(AppConfigError)
+    }
+
+    /// Verify a friend using a phrase that was shared with the friend out of band.
+    /// The same phrase should be set on both sides. The result of the verification shows up in
+    /// the friend's report. Verification never affects the channel with the friend.
+    pub async fn set_friend_verification_phrase(
+        &mut self,
+        friend_public_key: PublicKey,
+        phrase: String,
+    ) -> Result<(), AppConfigError> {
+        let set_friend_verification_phrase = SetFriendVerificationPhrase {
+            friend_public_key,
+            phrase,
+        };
+        await!(self.send_request(AppRequest::SetFriendVerificationPhrase(
+            set_friend_verification_phrase
+        )))
+    }
+
+    /// Stop (Or resume) advertising the capacities with a friend to index servers.
+    /// Routes through a private friend are only found by nodes that know about it out of band.
+    /// Payments we send through the friend are not affected.
+    pub async fn set_friend_index_private(
+        &mut self,
+        friend_public_key: PublicKey,
+        index_private: bool,
+    ) -> Result<(), AppConfigError> {
+        let set_friend_index_private = SetFriendIndexPrivate {
+            friend_public_key,
+            index_private,
+        };
+        await!(self.send_request(AppRequest::SetFriendIndexPrivate(set_friend_index_private)))
+    }
+
     pub async fn reset_friend_channel(
         &mut self,
         friend_public_key: PublicKey,
@@ -180,4 +394,253 @@ where
     ) -> Result<(), AppConfigError> {
         await!(self.send_request(AppRequest::RemoveIndexServer(index_public_key)))
     }
+
+    /// Deliver notifications about incoming payments to a consumer listening on `address`.
+    /// The consumer must acknowledge every notification it receives.
+    pub async fn set_payment_notifier(
+        &mut self,
+        address: NetAddress,
+        filter: PaymentNotifyFilter,
+    ) -> Result<(), AppConfigError> {
+        let payment_notifier = PaymentNotifier {
+            consumer: PaymentConsumer::Address(address),
+            filter,
+        };
+        await!(self.send_request(AppRequest::SetPaymentNotifier(payment_notifier)))
+    }
+
+    /// Deliver incoming payments to the node's apps, through incoming payments subscriptions.
+    /// See `AppIncomingPayments`.
+    pub async fn set_apps_payment_notifier(
+        &mut self,
+        filter: PaymentNotifyFilter,
+    ) -> Result<(), AppConfigError> {
+        let payment_notifier = PaymentNotifier {
+            consumer: PaymentConsumer::Apps,
+            filter,
+        };
+        await!(self.send_request(AppRequest::SetPaymentNotifier(payment_notifier)))
+    }
+
+    pub async fn clear_payment_notifier(&mut self) -> Result<(), AppConfigError> {
+        await!(self.send_request(AppRequest::ClearPaymentNotifier))
+    }
+
+    /// Subscribe to a directory of relays and index servers, served at `address`.
+    /// The node periodically fetches the document published by the directory, and adds or
+    /// removes relays and index servers accordingly. Documents are verified using
+    /// `signing_public_key`.
+    pub async fn set_directory(
+        &mut self,
+        directory_public_key: PublicKey,
+        address: NetAddress,
+        signing_public_key: PublicKey,
+    ) -> Result<(), AppConfigError> {
+        let subscription = DirectorySubscription {
+            public_key: directory_public_key,
+            address,
+            signing_public_key,
+        };
+        await!(self.send_request(AppRequest::SetDirectory(subscription)))
+    }
+
+    /// Stop following the directory.
+    /// Relays and index servers that were added by the directory are kept.
+    pub async fn clear_directory(&mut self) -> Result<(), AppConfigError> {
+        await!(self.send_request(AppRequest::ClearDirectory))
+    }
+
+    /// Make sure the directory never removes the relay or index server with the given public key.
+    pub async fn pin_directory_entry(
+        &mut self,
+        public_key: PublicKey,
+    ) -> Result<(), AppConfigError> {
+        await!(self.send_request(AppRequest::PinDirectoryEntry(public_key)))
+    }
+
+    pub async fn unpin_directory_entry(
+        &mut self,
+        public_key: PublicKey,
+    ) -> Result<(), AppConfigError> {
+        await!(self.send_request(AppRequest::UnpinDirectoryEntry(public_key)))
+    }
+
+    /// Tell all the online friends that the node is about to shut down on purpose, and is
+    /// expected to be back after `opt_downtime_ticks` ticks (`None` if unknown).
+    /// Should be called right before the node is stopped.
+    pub async fn announce_shutdown(
+        &mut self,
+        opt_downtime_ticks: Option<u64>,
+    ) -> Result<(), AppConfigError> {
+        let goodbye = Goodbye { opt_downtime_ticks };
+        await!(self.send_request(AppRequest::AnnounceShutdown(goodbye)))
+    }
+
+    /// Add the inviting node of a friend invite as a friend, in one step.
+    /// If `accept_suggested_max_debt` is set, the new friend may owe us up to the max debt
+    /// suggested by the invite. The app should ask the user before accepting it.
+    pub async fn import_friend_invite(
+        &mut self,
+        invite: FriendInvite,
+        name: String,
+        accept_suggested_max_debt: bool,
+    ) -> Result<ImportFriendInviteResult, AppConfigError> {
+        let request_id = Uid::new(&self.rng);
+        let import_friend_invite = ImportFriendInvite {
+            request_id,
+            invite,
+            name,
+            accept_suggested_max_debt,
+        };
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server = AppToAppServer::new(
+            app_request_id,
+            AppRequest::ImportFriendInvite(import_friend_invite),
+        );
+
+        let mut incoming_import_responses =
+            await!(self.import_invite_mc.request_stream()).map_err(|_| AppConfigError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| AppConfigError)?;
+
+        while let Some(response_import) = await!(incoming_import_responses.next()) {
+            if response_import.request_id == request_id {
+                return Ok(response_import.result);
+            }
+        }
+        Err(AppConfigError)
+    }
+
+    /// Get a snapshot of the state of the node, to be attached to a bug report.
+    /// Returns a serialized bundle (See `proto::app_server::debug_bundle`).
+    ///
+    /// If `full` is set, the bundle also contains the tokens of the channels with all the
+    /// friends. Such a bundle should never be shared with anyone who is not fully trusted.
+    pub async fn request_debug_bundle(&mut self, full: bool) -> Result<Vec<u8>, AppConfigError> {
+        let request_id = Uid::new(&self.rng);
+        let request_debug_bundle = RequestDebugBundle { request_id, full };
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server = AppToAppServer::new(
+            app_request_id,
+            AppRequest::RequestDebugBundle(request_debug_bundle),
+        );
+
+        let mut incoming_debug_bundles =
+            await!(self.debug_bundle_mc.request_stream()).map_err(|_| AppConfigError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| AppConfigError)?;
+
+        while let Some(response_debug_bundle) = await!(incoming_debug_bundles.next()) {
+            if response_debug_bundle.request_id == request_id {
+                return Ok(response_debug_bundle.bundle);
+            }
+        }
+        Err(AppConfigError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::ThreadPool;
+    use futures::task::{Spawn, SpawnExt};
+    use futures::FutureExt;
+
+    use common::multi_consumer::multi_consumer_service;
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::test_utils::DummyRandom;
+
+    fn create_app_config(
+        sender: mpsc::Sender<AppToAppServer>,
+        done_app_requests_mc: MultiConsumerClient<Uid>,
+        seed: u8,
+    ) -> AppConfig<DummyRandom> {
+        let (debug_bundle_requests_sender, _) = mpsc::channel(0);
+        let (import_invite_requests_sender, _) = mpsc::channel(0);
+        let (pair_freeze_usage_requests_sender, _) = mpsc::channel(0);
+        let (report_requests_sender, _) = mpsc::channel(0);
+        AppConfig::new(
+            sender,
+            done_app_requests_mc,
+            MultiConsumerClient::new(debug_bundle_requests_sender),
+            MultiConsumerClient::new(import_invite_requests_sender),
+            MultiConsumerClient::new(pair_freeze_usage_requests_sender),
+            StateClient::new(report_requests_sender),
+            DummyRandom::new(&[seed]),
+        )
+    }
+
+    async fn task_app_config_concurrent_requests<S>(mut spawner: S, reverse: bool)
+    where
+        S: Spawn,
+    {
+        let (sender, mut app_server_receiver) = mpsc::channel(0);
+        let (mut done_sender, incoming_done) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let done_app_requests_mc = MultiConsumerClient::new(requests_sender);
+        spawner
+            .spawn(multi_consumer_service(incoming_done, incoming_requests).map(|_| ()))
+            .unwrap();
+
+        // Two apps set the remote max debt of the same friend concurrently:
+        let friend_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let (completions_sender, mut completions) = mpsc::unbounded();
+        for &(seed, remote_max_debt) in &[(1u8, 10u128), (2u8, 20u128)] {
+            let mut app_config =
+                create_app_config(sender.clone(), done_app_requests_mc.clone(), seed);
+            let c_friend_public_key = friend_public_key.clone();
+            let c_completions_sender = completions_sender.clone();
+            spawner
+                .spawn(async move {
+                    await!(
+                        app_config.set_friend_remote_max_debt(c_friend_public_key, remote_max_debt)
+                    )
+                    .unwrap();
+                    c_completions_sender
+                        .unbounded_send(remote_max_debt)
+                        .unwrap();
+                })
+                .unwrap();
+        }
+
+        // Both requests are in flight:
+        let mut requests = Vec::new();
+        for _ in 0..2 {
+            let to_app_server = await!(app_server_receiver.next()).unwrap();
+            let remote_max_debt = match to_app_server.app_request {
+                AppRequest::SetFriendRemoteMaxDebt(set_friend_remote_max_debt) => {
+                    assert_eq!(
+                        set_friend_remote_max_debt.friend_public_key,
+                        friend_public_key
+                    );
+                    set_friend_remote_max_debt.remote_max_debt
+                }
+                _ => unreachable!(),
+            };
+            requests.push((to_app_server.app_request_id, remote_max_debt));
+        }
+        requests.sort_by_key(|(_, remote_max_debt)| *remote_max_debt);
+        if reverse {
+            requests.reverse();
+        }
+
+        // Every request is resolved by its own ack, regardless of the completion order:
+        for (app_request_id, remote_max_debt) in requests {
+            assert!(completions.try_next().is_err());
+            await!(done_sender.send(app_request_id)).unwrap();
+            assert_eq!(await!(completions.next()).unwrap(), remote_max_debt);
+        }
+    }
+
+    #[test]
+    fn test_app_config_concurrent_requests() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        for &reverse in &[false, true] {
+            thread_pool.run(task_app_config_concurrent_requests(
+                thread_pool.clone(),
+                reverse,
+            ));
+        }
+    }
 }
@@ -0,0 +1,249 @@
+use common::int_convert::usize_to_u64;
+
+use proto::app_server::messages::NodeReport;
+use proto::consts::{MAX_OPERATIONS_IN_BATCH, TICK_MS};
+use proto::funder::messages::FriendsRoute;
+use proto::report::messages::ReliabilityReport;
+
+/// Default amount of ticks a hop we have no measurements about adds to a payment.
+const DEFAULT_HOP_TICKS: usize = 1000 / TICK_MS; // 1 second
+
+/// Minimal amount of samples (Recorded payment outcomes) for a medium confidence estimate.
+const MEDIUM_CONFIDENCE_SAMPLES: u64 = 2;
+
+/// Minimal amount of samples (Recorded payment outcomes) for a high confidence estimate.
+const HIGH_CONFIDENCE_SAMPLES: u64 = 8;
+
+/// How much an estimate of the time a payment will take can be trusted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EstimateConfidence {
+    Low,
+    Medium,
+    High,
+}
+
+impl EstimateConfidence {
+    fn from_samples(samples: u64) -> Self {
+        if samples >= HIGH_CONFIDENCE_SAMPLES {
+            EstimateConfidence::High
+        } else if samples >= MEDIUM_CONFIDENCE_SAMPLES {
+            EstimateConfidence::Medium
+        } else {
+            EstimateConfidence::Low
+        }
+    }
+
+    fn lower(self) -> Self {
+        match self {
+            EstimateConfidence::High => EstimateConfidence::Medium,
+            EstimateConfidence::Medium | EstimateConfidence::Low => EstimateConfidence::Low,
+        }
+    }
+}
+
+/// Estimated amount of ticks until a payment is answered.
+/// Purely informational: Payments are not limited by their estimates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentEstimate {
+    pub min_ticks: u64,
+    pub max_ticks: u64,
+    pub confidence: EstimateConfidence,
+}
+
+/// Parameters of a payment estimate that are not part of the node report.
+#[derive(Debug, Clone)]
+pub struct EstimateParams {
+    /// Assumed amount of ticks a hop we have no measurements about adds to a payment.
+    pub default_hop_ticks: u64,
+    /// Amount of operations sent to a friend in one move token.
+    /// Requests queued beyond this amount wait for another exchange of the token.
+    pub max_operations_in_batch: u64,
+    /// The payment is failed if it is not answered within this amount of ticks (For example,
+    /// the response deadline set for the first hop). Bounds the estimate from above.
+    pub opt_timeout_ticks: Option<u64>,
+}
+
+impl Default for EstimateParams {
+    fn default() -> Self {
+        EstimateParams {
+            default_hop_ticks: usize_to_u64(DEFAULT_HOP_TICKS).unwrap(),
+            max_operations_in_batch: usize_to_u64(MAX_OPERATIONS_IN_BATCH).unwrap(),
+            opt_timeout_ticks: None,
+        }
+    }
+}
+
+/// Estimate the time a payment along a route of `num_hops` hops will take.
+///
+/// `opt_first_hop` is the measured reliability of the first hop, and `queue_len` is the amount of
+/// requests already waiting to be sent to the first hop.
+///
+/// The measured latency of the first hop covers all the hops of earlier payments through it,
+/// which are not known. Therefore every hop after the first one only widens the estimate
+/// upwards, and lowers its confidence.
+fn estimate_route(
+    opt_first_hop: Option<&ReliabilityReport>,
+    queue_len: u64,
+    num_hops: u64,
+    params: &EstimateParams,
+) -> PaymentEstimate {
+    let opt_measured = opt_first_hop.and_then(|reliability| {
+        let latency_ticks = reliability.opt_latency_ticks?;
+        Some((latency_ticks, reliability.samples))
+    });
+
+    let (first_hop_min, first_hop_max, mut confidence, num_tail_hops) = match opt_measured {
+        // A measured average might be off by a factor of 2 in both directions. A measurement of
+        // 0 ticks might be up to one tick long:
+        Some((latency_ticks, samples)) => (
+            latency_ticks / 2,
+            latency_ticks.saturating_mul(2).saturating_add(1),
+            EstimateConfidence::from_samples(samples),
+            num_hops.saturating_sub(1),
+        ),
+        None => (0, 0, EstimateConfidence::Low, num_hops),
+    };
+
+    // Every full batch queued before our request delays it by another exchange of the token:
+    let hop_ticks = opt_measured
+        .map(|(latency_ticks, _samples)| latency_ticks)
+        .unwrap_or(params.default_hop_ticks);
+    let queue_ticks = (queue_len / params.max_operations_in_batch.max(1)).saturating_mul(hop_ticks);
+
+    // A hop we know nothing about might already be covered by the measurement of the first hop,
+    // or it might be slow:
+    let tail_ticks = num_tail_hops.saturating_mul(params.default_hop_ticks.saturating_mul(2));
+    for _ in 0..num_tail_hops {
+        confidence = confidence.lower();
+    }
+
+    let mut max_ticks = first_hop_max
+        .saturating_add(queue_ticks)
+        .saturating_add(tail_ticks);
+    if let Some(timeout_ticks) = params.opt_timeout_ticks {
+        max_ticks = max_ticks.min(timeout_ticks);
+    }
+    let min_ticks = first_hop_min.saturating_add(queue_ticks).min(max_ticks);
+
+    PaymentEstimate {
+        min_ticks,
+        max_ticks,
+        confidence,
+    }
+}
+
+/// Estimate the time a payment along `route` will take, using the measurements in a node report.
+/// Returns None if the route does not begin at the local node, or if its first hop is not a
+/// friend.
+pub fn estimate_payment(
+    node_report: &NodeReport,
+    route: &FriendsRoute,
+    params: &EstimateParams,
+) -> Option<PaymentEstimate> {
+    let funder_report = &node_report.funder_report;
+    if route.public_keys.first()? != &funder_report.local_public_key {
+        return None;
+    }
+    let first_hop = route.public_keys.get(1)?;
+    let friend_report = funder_report.friends.get(first_hop)?;
+    let queue_len = friend_report
+        .num_pending_user_requests
+        .saturating_add(friend_report.num_pending_requests);
+    let num_hops = usize_to_u64(route.public_keys.len() - 1)?;
+
+    Some(estimate_route(
+        funder_report.reliability.get(first_hop),
+        queue_len,
+        num_hops,
+        params,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reliability_report(opt_latency_ticks: Option<u64>, samples: u64) -> ReliabilityReport {
+        ReliabilityReport {
+            success_ppm: 900_000,
+            opt_latency_ticks,
+            samples,
+        }
+    }
+
+    fn params(opt_timeout_ticks: Option<u64>) -> EstimateParams {
+        EstimateParams {
+            default_hop_ticks: 4,
+            max_operations_in_batch: 8,
+            opt_timeout_ticks,
+        }
+    }
+
+    #[test]
+    fn test_estimate_route_measured() {
+        let reliability = reliability_report(Some(10), 16);
+        let estimate = estimate_route(Some(&reliability), 0, 1, &params(None));
+        assert_eq!(
+            estimate,
+            PaymentEstimate {
+                min_ticks: 5,
+                max_ticks: 21,
+                confidence: EstimateConfidence::High,
+            }
+        );
+
+        // Few samples:
+        let reliability = reliability_report(Some(10), 2);
+        let estimate = estimate_route(Some(&reliability), 0, 1, &params(None));
+        assert_eq!(estimate.confidence, EstimateConfidence::Medium);
+    }
+
+    #[test]
+    fn test_estimate_route_tail_and_queue() {
+        let reliability = reliability_report(Some(10), 16);
+        let direct = estimate_route(Some(&reliability), 0, 1, &params(None));
+
+        // Every unknown hop widens the range and lowers the confidence:
+        let two_hops = estimate_route(Some(&reliability), 0, 2, &params(None));
+        assert_eq!(two_hops.min_ticks, direct.min_ticks);
+        assert_eq!(two_hops.max_ticks, direct.max_ticks + 8);
+        assert_eq!(two_hops.confidence, EstimateConfidence::Medium);
+        let three_hops = estimate_route(Some(&reliability), 0, 3, &params(None));
+        assert_eq!(three_hops.max_ticks, direct.max_ticks + 16);
+        assert_eq!(three_hops.confidence, EstimateConfidence::Low);
+
+        // Two full batches are queued before our request:
+        let queued = estimate_route(Some(&reliability), 17, 1, &params(None));
+        assert_eq!(queued.min_ticks, direct.min_ticks + 20);
+        assert_eq!(queued.max_ticks, direct.max_ticks + 20);
+    }
+
+    #[test]
+    fn test_estimate_route_unmeasured() {
+        // Outcomes were recorded, but no latency was measured:
+        let reliability = reliability_report(None, 16);
+        for opt_reliability in &[None, Some(&reliability)] {
+            let estimate = estimate_route(*opt_reliability, 0, 2, &params(None));
+            assert_eq!(
+                estimate,
+                PaymentEstimate {
+                    min_ticks: 0,
+                    max_ticks: 16,
+                    confidence: EstimateConfidence::Low,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_estimate_route_timeout() {
+        let reliability = reliability_report(Some(10), 16);
+        let estimate = estimate_route(Some(&reliability), 0, 3, &params(Some(12)));
+        assert_eq!(estimate.min_ticks, 5);
+        assert_eq!(estimate.max_ticks, 12);
+
+        let estimate = estimate_route(Some(&reliability), 0, 3, &params(Some(3)));
+        assert_eq!(estimate.min_ticks, 3);
+        assert_eq!(estimate.max_ticks, 3);
+    }
+}
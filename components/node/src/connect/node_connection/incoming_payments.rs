@@ -0,0 +1,97 @@
+use std::pin::Pin;
+
+use futures::channel::mpsc;
+use futures::{future, SinkExt, Stream, StreamExt};
+
+use common::multi_consumer::MultiConsumerClient;
+
+use crypto::crypto_rand::{CryptoRandom, OffstSystemRandom};
+use crypto::uid::Uid;
+
+use proto::app_server::messages::{AppRequest, AppToAppServer};
+use proto::funder::messages::IncomingPayment;
+
+#[derive(Debug)]
+pub struct AppIncomingPaymentsError;
+
+/// A stream of incoming payments, ordered by notification_id.
+pub type IncomingPayments = Pin<Box<dyn Stream<Item = IncomingPayment> + Send>>;
+
+/// Receive the node's incoming payments.
+/// Payments are only delivered to apps if the node's payment notifier is set to the apps.
+/// (See `AppConfig::set_apps_payment_notifier()`).
+#[derive(Clone)]
+pub struct AppIncomingPayments<R = OffstSystemRandom> {
+    sender: mpsc::Sender<AppToAppServer>,
+    incoming_payments_mc: MultiConsumerClient<IncomingPayment>,
+    done_app_requests_mc: MultiConsumerClient<Uid>,
+    rng: R,
+}
+
+impl<R> AppIncomingPayments<R>
+where
+    R: CryptoRandom,
+{
+    pub(super) fn new(
+        sender: mpsc::Sender<AppToAppServer>,
+        incoming_payments_mc: MultiConsumerClient<IncomingPayment>,
+        done_app_requests_mc: MultiConsumerClient<Uid>,
+        rng: R,
+    ) -> Self {
+        AppIncomingPayments {
+            sender,
+            incoming_payments_mc,
+            done_app_requests_mc,
+            rng,
+        }
+    }
+
+    /// Subscribe to incoming payments.
+    /// All the payments that were not yet acknowledged are received first, followed by new
+    /// payments. A payment is received again on every new subscription until it is acknowledged,
+    /// so a payment should be handled before it is acknowledged.
+    pub async fn subscribe(&mut self) -> Result<IncomingPayments, AppIncomingPaymentsError> {
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server =
+            AppToAppServer::new(app_request_id, AppRequest::SubscribeIncomingPayments);
+
+        // Start listening for incoming payments:
+        let incoming_payments = await!(self.incoming_payments_mc.request_stream())
+            .map_err(|_| AppIncomingPaymentsError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| AppIncomingPaymentsError)?;
+
+        // Payments are sent again when another subscription is opened through this connection.
+        // We make sure every payment shows up only once in this stream:
+        let mut next_notification_id = 0;
+        let incoming_payments = incoming_payments.filter(move |incoming_payment| {
+            let is_new = incoming_payment.notification_id >= next_notification_id;
+            if is_new {
+                next_notification_id = incoming_payment.notification_id.wrapping_add(1);
+            }
+            future::ready(is_new)
+        });
+        Ok(Box::pin(incoming_payments))
+    }
+
+    /// Acknowledge a payment, so that it will not be received again.
+    pub async fn ack(&mut self, notification_id: u64) -> Result<(), AppIncomingPaymentsError> {
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server =
+            AppToAppServer::new(app_request_id, AppRequest::AckIncomingPayment(notification_id));
+
+        // Start listening to done requests:
+        let mut incoming_done_requests = await!(self.done_app_requests_mc.request_stream())
+            .map_err(|_| AppIncomingPaymentsError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| AppIncomingPaymentsError)?;
+
+        // Wait for a sign that our request was received:
+        while let Some(done_request_id) = await!(incoming_done_requests.next()) {
+            if app_request_id == done_request_id {
+                return Ok(());
+            }
+        }
+        Err(AppIncomingPaymentsError)
+    }
+}
@@ -0,0 +1,366 @@
+use crypto::identity::PublicKey;
+use crypto::uid::Uid;
+
+use proto::app_server::messages::{NodeReport, NodeReportMutateError, NodeReportMutation};
+use proto::funder::messages::FriendsRoute;
+use proto::report::convert::calc_friend_capacities;
+use proto::report::messages::{ChannelStatusReport, FriendReport, ReliabilityReport};
+
+use super::estimate::{estimate_payment, EstimateParams, PaymentEstimate};
+
+/// A local mirror of the state of a node.
+/// Built from a NodeReport, and kept up to date by applying the report mutations sent by the node.
+///
+/// The underlying report is made of persistent data structures, so cloning a mirror (For example,
+/// to evaluate a predicate over a snapshot) is cheap.
+#[derive(Debug, Clone)]
+pub struct NodeStateMirror {
+    node_report: NodeReport,
+}
+
+impl NodeStateMirror {
+    pub fn new(node_report: NodeReport) -> Self {
+        NodeStateMirror { node_report }
+    }
+
+    /// Apply a batch of report mutations, as received from the node.
+    pub fn apply(&mut self, mutations: &[NodeReportMutation]) -> Result<(), NodeReportMutateError> {
+        for mutation in mutations {
+            self.node_report.mutate(mutation)?;
+        }
+        Ok(())
+    }
+
+    pub fn node_report(&self) -> &NodeReport {
+        &self.node_report
+    }
+
+    pub fn local_public_key(&self) -> &PublicKey {
+        &self.node_report.funder_report.local_public_key
+    }
+
+    pub fn friend_report(&self, friend_public_key: &PublicKey) -> Option<&FriendReport> {
+        self.node_report
+            .funder_report
+            .friends
+            .get(friend_public_key)
+    }
+
+    /// Is the friend currently online?
+    pub fn is_friend_online(&self, friend_public_key: &PublicKey) -> bool {
+        self.friend_report(friend_public_key)
+            .map(|friend_report| friend_report.liveness.is_online())
+            .unwrap_or(false)
+    }
+
+    /// Is the token channel with the friend consistent?
+    pub fn is_channel_consistent(&self, friend_public_key: &PublicKey) -> bool {
+        match self.friend_report(friend_public_key) {
+            Some(friend_report) => match friend_report.channel_status {
+                ChannelStatusReport::Consistent(_) => true,
+                ChannelStatusReport::Inconsistent(_) => false,
+            },
+            None => false,
+        }
+    }
+
+    /// Balance we have against the friend.
+    /// None if the friend does not exist or the channel is inconsistent.
+    pub fn balance(&self, friend_public_key: &PublicKey) -> Option<i128> {
+        match &self.friend_report(friend_public_key)?.channel_status {
+            ChannelStatusReport::Consistent(tc_report) => Some(tc_report.balance.balance),
+            ChannelStatusReport::Inconsistent(_) => None,
+        }
+    }
+
+    /// Amount of credits we can currently send to the friend.
+    /// This is zero if the friend is offline, disabled or the channel is inconsistent.
+    pub fn send_capacity(&self, friend_public_key: &PublicKey) -> u128 {
+        self.friend_report(friend_public_key)
+            .map(|friend_report| calc_friend_capacities(friend_report).0)
+            .unwrap_or(0)
+    }
+
+    /// Can we currently send at least `capacity` credits to the friend?
+    pub fn has_send_capacity(&self, friend_public_key: &PublicKey, capacity: u128) -> bool {
+        self.send_capacity(friend_public_key) >= capacity
+    }
+
+    /// Is the payment with the given `request_id` no longer in progress?
+    /// Note that a payment that was never requested is also considered completed.
+    pub fn is_payment_completed(&self, request_id: &Uid) -> bool {
+        !self
+            .node_report
+            .funder_report
+            .friends
+            .values()
+            .flat_map(|friend_report| friend_report.pending_payments.iter())
+            .any(|pending_payment| &pending_payment.request_id == request_id)
+    }
+
+    /// Is the relay with the given public key configured?
+    pub fn has_relay(&self, relay_public_key: &PublicKey) -> bool {
+        self.node_report
+            .funder_report
+            .relays
+            .iter()
+            .any(|named_relay_address| &named_relay_address.public_key == relay_public_key)
+    }
+
+    /// Is the index server with the given public key configured?
+    pub fn has_index_server(&self, index_public_key: &PublicKey) -> bool {
+        self.node_report
+            .index_client_report
+            .index_servers
+            .iter()
+            .any(|named_index_server| &named_index_server.public_key == index_public_key)
+    }
+
+    /// Are we connected to the index server with the given public key?
+    pub fn is_index_server_connected(&self, index_public_key: &PublicKey) -> bool {
+        self.node_report.index_client_report.opt_connected_server.as_ref() == Some(index_public_key)
+    }
+
+    /// Measured reliability of a remote node we have sent payments through.
+    /// None if no payment outcomes were recorded for the node, or if they have decayed.
+    pub fn reliability(&self, public_key: &PublicKey) -> Option<&ReliabilityReport> {
+        self.node_report.funder_report.reliability.get(public_key)
+    }
+
+    /// Estimate the time a payment along `route` will take, from the current measurements.
+    /// None if the route does not begin at the local node, or if its first hop is not a friend.
+    pub fn estimate_payment(
+        &self,
+        route: &FriendsRoute,
+        params: &EstimateParams,
+    ) -> Option<PaymentEstimate> {
+        estimate_payment(&self.node_report, route, params)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::uid::UID_LEN;
+
+    use proto::app_server::messages::{NamedRelayAddress, RelayAddress};
+    use proto::index_client::messages::{IndexClientReport, IndexClientReportMutation};
+    use proto::index_server::messages::NamedIndexServerAddress;
+    use proto::net::messages::NetAddress;
+    use proto::report::messages::{
+        AddFriendReport, ChannelInconsistentReport, DirectionReport, FriendLivenessReport,
+        FriendReportMutation, FriendStatusReport, FunderReport, FunderReportMutation,
+        McBalanceReport, McRequestsStatusReport, PendingPaymentReport, PendingPaymentStageReport,
+        RequestsStatusReport, TcReport,
+    };
+
+    fn net_address(address: &str) -> NetAddress {
+        NetAddress::try_from(address.to_owned()).unwrap()
+    }
+
+    fn empty_node_report(local_public_key: PublicKey) -> NodeReport {
+        NodeReport {
+            funder_report: FunderReport {
+                local_public_key,
+                relays: Default::default(),
+                friends: Default::default(),
+                num_ready_receipts: 0,
+                quarantined_friends: Default::default(),
+                dust_thresholds: Default::default(),
+                directory: Default::default(),
+                reliability: Default::default(),
+                read_only: false,
+                labeled_payments: Default::default(),
+            },
+            index_client_report: IndexClientReport {
+                index_servers: Vec::new(),
+                opt_connected_server: None,
+                num_corrections: 0,
+            },
+        }
+    }
+
+    fn consistent_channel(balance: i128, local_max_debt: u128) -> ChannelStatusReport {
+        ChannelStatusReport::Consistent(TcReport {
+            direction: DirectionReport::Incoming,
+            move_token_counter: 0,
+            inconsistency_counter: 0,
+            balance: McBalanceReport {
+                balance,
+                local_max_debt,
+                remote_max_debt: 0,
+                local_pending_debt: 0,
+                remote_pending_debt: 0,
+            },
+            requests_status: McRequestsStatusReport {
+                local: RequestsStatusReport::Open,
+                remote: RequestsStatusReport::Open,
+            },
+            num_local_pending_requests: 0,
+            num_remote_pending_requests: 0,
+        })
+    }
+
+    fn friend_mutation(
+        friend_public_key: &PublicKey,
+        friend_report_mutation: FriendReportMutation,
+    ) -> NodeReportMutation {
+        NodeReportMutation::Funder(FunderReportMutation::FriendReportMutation((
+            friend_public_key.clone(),
+            friend_report_mutation,
+        )))
+    }
+
+    #[test]
+    fn test_node_state_mirror_recorded_mutations() {
+        let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let friend_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let relay_public_key = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+        let index_public_key = PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]);
+        let request_id = Uid::from(&[1; UID_LEN]);
+
+        let mut mirror = NodeStateMirror::new(empty_node_report(local_public_key.clone()));
+        assert!(!mirror.is_friend_online(&friend_public_key));
+        assert!(!mirror.is_channel_consistent(&friend_public_key));
+        assert!(!mirror.has_relay(&relay_public_key));
+        assert!(!mirror.has_index_server(&index_public_key));
+
+        // A recorded stream of mutation batches, as sent by a node:
+        let add_friend_report = AddFriendReport {
+            friend_public_key: friend_public_key.clone(),
+            name: "friend".to_owned(),
+            relays: vec![RelayAddress {
+                public_key: relay_public_key.clone(),
+                address: net_address("relay_address"),
+            }],
+            balance: 0,
+            opt_last_incoming_move_token: None,
+            channel_status: consistent_channel(0, 0),
+        };
+        let batches = vec![
+            vec![
+                NodeReportMutation::Funder(FunderReportMutation::AddRelay(NamedRelayAddress {
+                    public_key: relay_public_key.clone(),
+                    address: net_address("relay_address"),
+                    name: "relay".to_owned(),
+                })),
+                NodeReportMutation::IndexClient(IndexClientReportMutation::AddIndexServer(
+                    NamedIndexServerAddress {
+                        public_key: index_public_key.clone(),
+                        address: net_address("index_address"),
+                        name: "index".to_owned(),
+                    },
+                )),
+            ],
+            vec![NodeReportMutation::Funder(FunderReportMutation::AddFriend(
+                add_friend_report,
+            ))],
+            vec![
+                friend_mutation(
+                    &friend_public_key,
+                    FriendReportMutation::SetStatus(FriendStatusReport::Enabled),
+                ),
+                friend_mutation(
+                    &friend_public_key,
+                    FriendReportMutation::SetLiveness(FriendLivenessReport::Online),
+                ),
+            ],
+            vec![friend_mutation(
+                &friend_public_key,
+                FriendReportMutation::SetChannelStatus(consistent_channel(0, 100)),
+            )],
+            vec![friend_mutation(
+                &friend_public_key,
+                FriendReportMutation::SetPendingPayments(vec![PendingPaymentReport {
+                    request_id: request_id.clone(),
+                    route: FriendsRoute {
+                        public_keys: vec![local_public_key.clone(), friend_public_key.clone()],
+                    },
+                    dest_payment: 10,
+                    frozen_credits: 10,
+                    stage: PendingPaymentStageReport::Sent,
+                    opt_queued_tick: Some(0),
+                    opt_signed_tick: Some(1),
+                }]),
+            )],
+            vec![
+                friend_mutation(
+                    &friend_public_key,
+                    FriendReportMutation::SetPendingPayments(Vec::new()),
+                ),
+                friend_mutation(
+                    &friend_public_key,
+                    FriendReportMutation::SetChannelStatus(consistent_channel(-10, 100)),
+                ),
+            ],
+            vec![friend_mutation(
+                &friend_public_key,
+                FriendReportMutation::SetChannelStatus(ChannelStatusReport::Inconsistent(
+                    ChannelInconsistentReport {
+                        local_reset_terms_balance: -10,
+                        opt_remote_reset_terms: None,
+                    },
+                )),
+            )],
+            vec![NodeReportMutation::IndexClient(
+                IndexClientReportMutation::SetConnectedServer(Some(index_public_key.clone())),
+            )],
+        ];
+
+        let mut batches = batches.into_iter();
+
+        mirror.apply(&batches.next().unwrap()).unwrap();
+        assert!(mirror.has_relay(&relay_public_key));
+        assert!(mirror.has_index_server(&index_public_key));
+        assert!(!mirror.is_index_server_connected(&index_public_key));
+
+        mirror.apply(&batches.next().unwrap()).unwrap();
+        assert!(mirror.friend_report(&friend_public_key).is_some());
+        assert!(!mirror.is_friend_online(&friend_public_key));
+        assert!(mirror.is_channel_consistent(&friend_public_key));
+        assert_eq!(mirror.send_capacity(&friend_public_key), 0);
+
+        mirror.apply(&batches.next().unwrap()).unwrap();
+        assert!(mirror.is_friend_online(&friend_public_key));
+        assert_eq!(mirror.send_capacity(&friend_public_key), 0);
+
+        mirror.apply(&batches.next().unwrap()).unwrap();
+        assert!(mirror.has_send_capacity(&friend_public_key, 100));
+        assert!(!mirror.has_send_capacity(&friend_public_key, 101));
+
+        // A snapshot is not affected by later mutations:
+        let snapshot = mirror.clone();
+
+        mirror.apply(&batches.next().unwrap()).unwrap();
+        assert!(!mirror.is_payment_completed(&request_id));
+
+        mirror.apply(&batches.next().unwrap()).unwrap();
+        assert!(mirror.is_payment_completed(&request_id));
+        assert_eq!(mirror.send_capacity(&friend_public_key), 90);
+        assert_eq!(mirror.balance(&friend_public_key), Some(-10));
+
+        mirror.apply(&batches.next().unwrap()).unwrap();
+        assert!(!mirror.is_channel_consistent(&friend_public_key));
+        assert_eq!(mirror.send_capacity(&friend_public_key), 0);
+        assert_eq!(mirror.balance(&friend_public_key), None);
+
+        mirror.apply(&batches.next().unwrap()).unwrap();
+        assert!(mirror.is_index_server_connected(&index_public_key));
+
+        assert!(snapshot.is_channel_consistent(&friend_public_key));
+        assert_eq!(snapshot.send_capacity(&friend_public_key), 100);
+
+        // Mutating a friend that does not exist fails:
+        let unknown_public_key = PublicKey::from(&[0xee; PUBLIC_KEY_LEN]);
+        assert!(mirror
+            .apply(&[friend_mutation(
+                &unknown_public_key,
+                FriendReportMutation::SetName("unknown".to_owned()),
+            )])
+            .is_err());
+    }
+}
@@ -1,8 +1,15 @@
 pub mod config;
+pub mod estimate;
+pub mod incoming_payments;
+pub mod mirror;
+pub mod rebalance;
 pub mod report;
+pub mod route_select;
 pub mod routes;
+pub mod self_test;
 pub mod send_funds;
 
 mod node_connection;
+mod sequencer;
 
 pub use self::node_connection::{NodeConnection, NodeConnectionError};
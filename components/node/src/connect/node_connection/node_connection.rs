@@ -10,10 +10,16 @@ use common::multi_consumer::{multi_consumer_service, MultiConsumerClient};
 use common::mutable_state::BatchMutable;
 use common::state_service::{state_service, StateClient};
 
+use timer::TimerClient;
+
 use super::config::AppConfig;
+use super::incoming_payments::AppIncomingPayments;
+use super::rebalance::{AppRebalance, RebalanceConfig};
 use super::report::AppReport;
 use super::routes::AppRoutes;
+use super::self_test::AppSelfTest;
 use super::send_funds::AppSendFunds;
+use super::sequencer::ReportSequencer;
 
 use crate::connect::connect::NodeConnectionTuple;
 
@@ -28,8 +34,10 @@ pub enum NodeConnectionError {
 pub struct NodeConnection<R = OffstSystemRandom> {
     report: AppReport,
     opt_config: Option<AppConfig<R>>,
+    opt_incoming_payments: Option<AppIncomingPayments<R>>,
     opt_routes: Option<AppRoutes<R>>,
     opt_send_funds: Option<AppSendFunds<R>>,
+    self_test: AppSelfTest<R>,
     rng: R,
 }
 
@@ -39,6 +47,7 @@ where
 {
     pub fn new<S>(
         conn_tuple: NodeConnectionTuple,
+        timer_client: TimerClient,
         rng: R,
         spawner: &mut S,
     ) -> Result<Self, NodeConnectionError>
@@ -81,6 +90,98 @@ where
             .spawn(send_funds_fut)
             .map_err(|_| NodeConnectionError::SpawnError)?;
 
+        let (mut incoming_prewarm_sender, incoming_prewarm) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let prewarm_mc = MultiConsumerClient::new(requests_sender);
+        let prewarm_fut = multi_consumer_service(incoming_prewarm, incoming_requests)
+            .map_err(|e| error!("Prewarm multi_consumer_service() error: {:?}", e))
+            .map(|_| ());
+        spawner
+            .spawn(prewarm_fut)
+            .map_err(|_| NodeConnectionError::SpawnError)?;
+
+        let (mut incoming_cancel_sender, incoming_cancel) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let cancel_mc = MultiConsumerClient::new(requests_sender);
+        let cancel_fut = multi_consumer_service(incoming_cancel, incoming_requests)
+            .map_err(|e| error!("CancelUserRequest multi_consumer_service() error: {:?}", e))
+            .map(|_| ());
+        spawner
+            .spawn(cancel_fut)
+            .map_err(|_| NodeConnectionError::SpawnError)?;
+
+        let (mut incoming_import_invite_sender, incoming_import_invite) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let import_invite_mc = MultiConsumerClient::new(requests_sender);
+        let import_invite_fut = multi_consumer_service(incoming_import_invite, incoming_requests)
+            .map_err(|e| error!("ImportFriendInvite multi_consumer_service() error: {:?}", e))
+            .map(|_| ());
+        spawner
+            .spawn(import_invite_fut)
+            .map_err(|_| NodeConnectionError::SpawnError)?;
+
+        let (mut incoming_pair_freeze_usage_sender, incoming_pair_freeze_usage) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let pair_freeze_usage_mc = MultiConsumerClient::new(requests_sender);
+        let pair_freeze_usage_fut =
+            multi_consumer_service(incoming_pair_freeze_usage, incoming_requests)
+                .map_err(|e| error!("PairFreezeUsage multi_consumer_service() error: {:?}", e))
+                .map(|_| ());
+        spawner
+            .spawn(pair_freeze_usage_fut)
+            .map_err(|_| NodeConnectionError::SpawnError)?;
+
+        let (mut incoming_labeled_payments_sender, incoming_labeled_payments) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let labeled_payments_mc = MultiConsumerClient::new(requests_sender);
+        let labeled_payments_fut =
+            multi_consumer_service(incoming_labeled_payments, incoming_requests)
+                .map_err(|e| error!("LabeledPayments multi_consumer_service() error: {:?}", e))
+                .map(|_| ());
+        spawner
+            .spawn(labeled_payments_fut)
+            .map_err(|_| NodeConnectionError::SpawnError)?;
+
+        let (mut incoming_submissions_sender, incoming_submissions) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let submissions_mc = MultiConsumerClient::new(requests_sender);
+        let submissions_fut = multi_consumer_service(incoming_submissions, incoming_requests)
+            .map_err(|e| error!("PendingSubmissions multi_consumer_service() error: {:?}", e))
+            .map(|_| ());
+        spawner
+            .spawn(submissions_fut)
+            .map_err(|_| NodeConnectionError::SpawnError)?;
+
+        let (mut incoming_debug_bundle_sender, incoming_debug_bundle) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let debug_bundle_mc = MultiConsumerClient::new(requests_sender);
+        let debug_bundle_fut = multi_consumer_service(incoming_debug_bundle, incoming_requests)
+            .map_err(|e| error!("DebugBundle multi_consumer_service() error: {:?}", e))
+            .map(|_| ());
+        spawner
+            .spawn(debug_bundle_fut)
+            .map_err(|_| NodeConnectionError::SpawnError)?;
+
+        let (mut incoming_self_test_sender, incoming_self_test) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let self_test_mc = MultiConsumerClient::new(requests_sender);
+        let self_test_fut = multi_consumer_service(incoming_self_test, incoming_requests)
+            .map_err(|e| error!("SelfTest multi_consumer_service() error: {:?}", e))
+            .map(|_| ());
+        spawner
+            .spawn(self_test_fut)
+            .map_err(|_| NodeConnectionError::SpawnError)?;
+
+        let (mut incoming_payments_sender, incoming_payments) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let incoming_payments_mc = MultiConsumerClient::new(requests_sender);
+        let incoming_payments_fut = multi_consumer_service(incoming_payments, incoming_requests)
+            .map_err(|e| error!("IncomingPayments multi_consumer_service() error: {:?}", e))
+            .map(|_| ());
+        spawner
+            .spawn(incoming_payments_fut)
+            .map_err(|_| NodeConnectionError::SpawnError)?;
+
         let (mut incoming_done_app_requests_sender, incoming_done_app_requests) = mpsc::channel(0);
         let (requests_sender, incoming_requests) = mpsc::channel(0);
         let done_app_requests_mc = MultiConsumerClient::new(requests_sender);
@@ -95,11 +196,22 @@ where
         spawner
             .spawn(
                 async move {
+                    // Report mutations of different scopes may arrive out of order:
+                    let mut sequencer = ReportSequencer::new();
                     while let Some(message) = await!(receiver.next()) {
                         match message {
                             AppServerToApp::ResponseReceived(response_received) => {
                                 let _ = await!(incoming_send_funds_sender.send(response_received));
                             }
+                            AppServerToApp::ResponsePrewarm(response_prewarm) => {
+                                let _ = await!(incoming_prewarm_sender.send(response_prewarm));
+                            }
+                            AppServerToApp::ResponseCancelUserRequest(response_cancel) => {
+                                let _ = await!(incoming_cancel_sender.send(response_cancel));
+                            }
+                            AppServerToApp::ResponseImportFriendInvite(response_import) => {
+                                let _ = await!(incoming_import_invite_sender.send(response_import));
+                            }
                             AppServerToApp::Report(_node_report) => {
                                 // TODO: Maybe somehow redesign the type AppServerToApp
                                 // so that we don't have this edge case?
@@ -108,21 +220,51 @@ where
                                 );
                                 return;
                             }
-                            AppServerToApp::ReportMutations(node_report_mutations) => {
-                                let _ =
-                                    await!(incoming_mutations_sender
+                            AppServerToApp::ReportMutations(report_mutations) => {
+                                let ready = match sequencer.push(report_mutations) {
+                                    Ok(ready) => ready,
+                                    Err(e) => {
+                                        error!("ReportSequencer error: {:?}. Aborting.", e);
+                                        return;
+                                    }
+                                };
+                                for node_report_mutations in ready {
+                                    let _ = await!(incoming_mutations_sender
                                         .send(node_report_mutations.mutations));
-                                if let Some(app_request_id) =
-                                    node_report_mutations.opt_app_request_id
-                                {
-                                    let _ = await!(
-                                        incoming_done_app_requests_sender.send(app_request_id)
-                                    );
+                                    if let Some(app_request_id) =
+                                        node_report_mutations.opt_app_request_id
+                                    {
+                                        let _ = await!(
+                                            incoming_done_app_requests_sender.send(app_request_id)
+                                        );
+                                    }
                                 }
                             }
                             AppServerToApp::ResponseRoutes(client_response_routes) => {
                                 let _ = await!(incoming_routes_sender.send(client_response_routes));
                             }
+                            AppServerToApp::ResponseDebugBundle(response_debug_bundle) => {
+                                let _ = await!(
+                                    incoming_debug_bundle_sender.send(response_debug_bundle)
+                                );
+                            }
+                            AppServerToApp::ResponseSelfTest(response_self_test) => {
+                                let _ = await!(incoming_self_test_sender.send(response_self_test));
+                            }
+                            AppServerToApp::ResponseLabeledPayments(response_labeled_payments) => {
+                                let _ = await!(incoming_labeled_payments_sender
+                                    .send(response_labeled_payments));
+                            }
+                            AppServerToApp::ResponsePendingSubmissions(response_pending) => {
+                                let _ = await!(incoming_submissions_sender.send(response_pending));
+                            }
+                            AppServerToApp::ResponsePairFreezeUsage(response_usage) => {
+                                let _ =
+                                    await!(incoming_pair_freeze_usage_sender.send(response_usage));
+                            }
+                            AppServerToApp::IncomingPayment(incoming_payment) => {
+                                let _ = await!(incoming_payments_sender.send(incoming_payment));
+                            }
                         }
                     }
                 },
@@ -133,6 +275,21 @@ where
             Some(AppConfig::new(
                 sender.clone(),
                 done_app_requests_mc.clone(),
+                debug_bundle_mc.clone(),
+                import_invite_mc.clone(),
+                pair_freeze_usage_mc.clone(),
+                report_client.clone(),
+                rng.clone(),
+            ))
+        } else {
+            None
+        };
+
+        let opt_incoming_payments = if app_permissions.config {
+            Some(AppIncomingPayments::new(
+                sender.clone(),
+                incoming_payments_mc.clone(),
+                done_app_requests_mc.clone(),
                 rng.clone(),
             ))
         } else {
@@ -153,7 +310,12 @@ where
             Some(AppSendFunds::new(
                 sender.clone(),
                 send_funds_mc.clone(),
+                prewarm_mc.clone(),
+                cancel_mc.clone(),
+                labeled_payments_mc.clone(),
+                submissions_mc.clone(),
                 done_app_requests_mc.clone(),
+                report_client.clone(),
                 rng.clone(),
             ))
         } else {
@@ -161,10 +323,12 @@ where
         };
 
         Ok(NodeConnection {
-            report: AppReport::new(report_client.clone()),
+            report: AppReport::new(report_client.clone(), timer_client),
             opt_config,
+            opt_incoming_payments,
             opt_routes,
             opt_send_funds,
+            self_test: AppSelfTest::new(sender, self_test_mc, rng.clone()),
             rng,
         })
     }
@@ -177,6 +341,11 @@ where
         self.opt_config.as_mut()
     }
 
+    /// Receive incoming payments. Requires the config permission.
+    pub fn incoming_payments(&mut self) -> Option<&mut AppIncomingPayments<R>> {
+        self.opt_incoming_payments.as_mut()
+    }
+
     pub fn routes(&mut self) -> Option<&mut AppRoutes<R>> {
         self.opt_routes.as_mut()
     }
@@ -184,4 +353,20 @@ where
     pub fn send_funds(&mut self) -> Option<&mut AppSendFunds<R>> {
         self.opt_send_funds.as_mut()
     }
+
+    /// Self tests are allowed with any permissions.
+    pub fn self_test(&mut self) -> &mut AppSelfTest<R> {
+        &mut self.self_test
+    }
+
+    /// Create a rebalancer for this node.
+    /// Requires both the routes and the send funds permissions.
+    pub fn rebalance(&self, config: RebalanceConfig) -> Option<AppRebalance<R>> {
+        Some(AppRebalance::new(
+            self.opt_routes.clone()?,
+            self.opt_send_funds.clone()?,
+            config,
+            self.rng.clone(),
+        ))
+    }
 }
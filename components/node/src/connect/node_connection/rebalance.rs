@@ -0,0 +1,439 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crypto::crypto_rand::{CryptoRandom, OffstSystemRandom};
+use crypto::identity::PublicKey;
+use crypto::invoice_id::InvoiceId;
+use crypto::uid::Uid;
+
+use proto::funder::messages::{FriendsRoute, Receipt};
+use proto::index_server::messages::RouteWithCapacity;
+use proto::report::convert::calc_friend_capacities;
+
+use super::mirror::NodeStateMirror;
+use super::route_select::{route_fee, select_route, CheapestFee};
+use super::routes::AppRoutes;
+use super::send_funds::{AppSendFunds, SendFundsError};
+
+/// Allowed range for the balance we have against a friend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceRange {
+    pub min: i128,
+    pub max: i128,
+}
+
+#[derive(Debug, Clone)]
+pub struct RebalanceConfig {
+    /// Wanted balance range for every friend. Friends without a range are never rebalanced,
+    /// but may still be used as the other side of a rebalance.
+    pub ranges: HashMap<PublicKey, BalanceRange>,
+    /// Maximum amount of credits paid as fees for rebalancing during one day.
+    pub daily_fee_budget: u128,
+}
+
+/// A request to move `amount` credits of balance from `friend_over` to `friend_under`.
+/// Carried out as a payment to ourselves along a cycle:
+///
+/// ```text
+/// local -- friend_over -- ... -- friend_under -- local
+/// ```
+///
+/// Our balance against `friend_over` decreases by `amount` plus fees, and our balance against
+/// `friend_under` increases by `amount`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebalanceRequest {
+    pub friend_over: PublicKey,
+    pub friend_under: PublicKey,
+    pub amount: u128,
+}
+
+/// A rebalance that was carried out successfully.
+#[derive(Debug, Clone)]
+pub struct RebalanceAction {
+    pub rebalance_request: RebalanceRequest,
+    pub route: FriendsRoute,
+    /// Credits paid to the intermediate nodes of the route.
+    pub fee: u128,
+    pub receipt: Receipt,
+}
+
+#[derive(Debug)]
+pub enum RebalanceError {
+    /// All friends are inside their balance ranges.
+    Balanced,
+    /// A drifting friend was found, but no other friend can take the balance.
+    NoPartner,
+    RequestRoutesError,
+    /// No cycle was found through the two friends.
+    NoRoute,
+    /// The chosen route is not a valid rebalance cycle.
+    InvalidRoute,
+    /// Paying the amount and fees would exceed the capacity to the first friend.
+    InsufficientCapacity,
+    /// The fee does not fit in what is left of the daily fee budget.
+    FeeBudgetExceeded,
+    SendFundsError(SendFundsError),
+    ReceiptAckError,
+}
+
+/// Amount of credits paid as rebalance fees during the current day.
+#[derive(Debug, Clone)]
+pub struct FeeBudget {
+    daily_fee_budget: u128,
+    day: u64,
+    spent: u128,
+}
+
+impl FeeBudget {
+    pub fn new(daily_fee_budget: u128) -> Self {
+        FeeBudget {
+            daily_fee_budget,
+            day: 0,
+            spent: 0,
+        }
+    }
+
+    /// Amount of credits we may still pay as fees during `day`.
+    pub fn remaining(&self, day: u64) -> u128 {
+        if day != self.day {
+            self.daily_fee_budget
+        } else {
+            self.daily_fee_budget.saturating_sub(self.spent)
+        }
+    }
+
+    pub fn spend(&mut self, day: u64, fee: u128) {
+        if day != self.day {
+            self.day = day;
+            self.spent = 0;
+        }
+        self.spent = self.spent.saturating_add(fee);
+    }
+}
+
+/// Amount by which `a` is larger than `b`, or 0 if `a <= b`.
+fn excess(a: i128, b: i128) -> u128 {
+    if a <= b {
+        return 0;
+    }
+    a.checked_sub(b)
+        .and_then(|diff| u128::try_from(diff).ok())
+        .unwrap_or(u128::max_value())
+}
+
+/// Balance we have against a friend, if we can currently send or receive through the friend.
+fn ready_balance(mirror: &NodeStateMirror, friend_public_key: &PublicKey) -> Option<i128> {
+    let friend_report = mirror.friend_report(friend_public_key)?;
+    let (send_capacity, recv_capacity) = calc_friend_capacities(friend_report);
+    if send_capacity == 0 && recv_capacity == 0 {
+        return None;
+    }
+    mirror.balance(friend_public_key)
+}
+
+/// Find the friend that drifted furthest out of its balance range, and a partner friend that has
+/// room to take the drift.
+pub fn plan_rebalance(
+    config: &RebalanceConfig,
+    mirror: &NodeStateMirror,
+) -> Result<RebalanceRequest, RebalanceError> {
+    let mut friends = config
+        .ranges
+        .iter()
+        .filter_map(|(public_key, range)| {
+            Some((public_key, range, ready_balance(mirror, public_key)?))
+        })
+        .collect::<Vec<_>>();
+    // Make the plan independent of the iteration order of the map:
+    friends.sort_by(|a, b| a.0.cmp(b.0));
+
+    // Find the friend with the largest drift. `over` is true if the balance is above the range:
+    let (drifting_public_key, drift, over) = friends
+        .iter()
+        .map(|(public_key, range, balance)| {
+            let above = excess(*balance, range.max);
+            let below = excess(range.min, *balance);
+            (*public_key, above.max(below), above > 0)
+        })
+        .filter(|(_, drift, _)| *drift > 0)
+        .max_by_key(|(_, drift, _)| *drift)
+        .ok_or(RebalanceError::Balanced)?;
+
+    // Find the partner with the most room, without pushing it out of its own range:
+    let (partner_public_key, room) = friends
+        .iter()
+        .filter(|(public_key, _, _)| *public_key != drifting_public_key)
+        .map(|(public_key, range, balance)| {
+            let room = if over {
+                excess(range.max, *balance)
+            } else {
+                excess(*balance, range.min)
+            };
+            (*public_key, room)
+        })
+        .filter(|(_, room)| *room > 0)
+        .max_by_key(|(_, room)| *room)
+        .ok_or(RebalanceError::NoPartner)?;
+
+    let (friend_over, friend_under) = if over {
+        (drifting_public_key.clone(), partner_public_key.clone())
+    } else {
+        (partner_public_key.clone(), drifting_public_key.clone())
+    };
+
+    // We pay the first friend, so we can never move more than our send capacity to it:
+    let amount = drift.min(room).min(mirror.send_capacity(&friend_over));
+    if amount == 0 {
+        return Err(RebalanceError::InsufficientCapacity);
+    }
+
+    Ok(RebalanceRequest {
+        friend_over,
+        friend_under,
+        amount,
+    })
+}
+
+/// Check that `route` is a cycle from the local node back to itself, going out through
+/// `friend_over` and coming back through `friend_under`.
+pub fn is_rebalance_route(
+    local_public_key: &PublicKey,
+    rebalance_request: &RebalanceRequest,
+    route: &FriendsRoute,
+) -> bool {
+    let pks = &route.public_keys;
+    route.is_valid()
+        && pks.len() >= 4
+        && rebalance_request.friend_over != rebalance_request.friend_under
+        && &pks[0] == local_public_key
+        && &pks[pks.len() - 1] == local_public_key
+        && pks[1] == rebalance_request.friend_over
+        && pks[pks.len() - 2] == rebalance_request.friend_under
+}
+
+/// Keeps the balances against friends inside their configured ranges, by paying ourselves along
+/// cycles that go out through a friend with too high balance and come back through a friend with
+/// too low balance.
+#[derive(Clone)]
+pub struct AppRebalance<R = OffstSystemRandom> {
+    app_routes: AppRoutes<R>,
+    app_send_funds: AppSendFunds<R>,
+    config: RebalanceConfig,
+    fee_budget: FeeBudget,
+    rng: R,
+}
+
+impl<R> AppRebalance<R>
+where
+    R: CryptoRandom,
+{
+    pub(super) fn new(
+        app_routes: AppRoutes<R>,
+        app_send_funds: AppSendFunds<R>,
+        config: RebalanceConfig,
+        rng: R,
+    ) -> Self {
+        let fee_budget = FeeBudget::new(config.daily_fee_budget);
+        AppRebalance {
+            app_routes,
+            app_send_funds,
+            config,
+            fee_budget,
+            rng,
+        }
+    }
+
+    pub fn config(&self) -> &RebalanceConfig {
+        &self.config
+    }
+
+    /// Amount of credits we may still pay as rebalance fees during `day`.
+    pub fn remaining_fee_budget(&self, day: u64) -> u128 {
+        self.fee_budget.remaining(day)
+    }
+
+    /// Find cycles through the friends of `rebalance_request`, using the index servers.
+    async fn find_cycles<'a>(
+        &'a mut self,
+        mirror: &'a NodeStateMirror,
+        rebalance_request: &'a RebalanceRequest,
+    ) -> Result<Vec<RouteWithCapacity>, RebalanceError> {
+        let local_public_key = mirror.local_public_key().clone();
+        let friend_over = rebalance_request.friend_over.clone();
+        let friend_under = rebalance_request.friend_under.clone();
+
+        let routes_with_capacity = await!(self.app_routes.request_routes(
+            rebalance_request.amount,
+            friend_over.clone(),
+            friend_under.clone(),
+            None
+        ))
+        .map_err(|_| RebalanceError::RequestRoutesError)?;
+
+        // Capacity of the first and last edges of the cycle, as we see them:
+        let send_capacity = mirror.send_capacity(&friend_over);
+        let recv_capacity = mirror
+            .friend_report(&friend_under)
+            .map(|friend_report| calc_friend_capacities(friend_report).1)
+            .unwrap_or(0);
+
+        Ok(routes_with_capacity
+            .into_iter()
+            // A route through ourselves can not be closed into a single cycle:
+            .filter(|route_with_capacity| {
+                !route_with_capacity
+                    .route
+                    .public_keys
+                    .contains(&local_public_key)
+            })
+            .map(|route_with_capacity| {
+                let mut public_keys = vec![local_public_key.clone()];
+                public_keys.extend(route_with_capacity.route.public_keys);
+                public_keys.push(local_public_key.clone());
+                let capacity = route_with_capacity
+                    .capacity
+                    .min(send_capacity)
+                    .min(recv_capacity);
+                RouteWithCapacity {
+                    route: FriendsRoute { public_keys },
+                    capacity,
+                    meets_request: capacity >= rebalance_request.amount,
+                }
+            })
+            .collect())
+    }
+
+    /// Rebalance the friend that drifted furthest out of its balance range.
+    /// `day` is the index of the current day, used for the daily fee budget.
+    ///
+    /// At most one payment is made on every call. Returns the action taken, together with the
+    /// receipt for the payment.
+    pub async fn rebalance<'a>(
+        &'a mut self,
+        mirror: &'a NodeStateMirror,
+        day: u64,
+    ) -> Result<RebalanceAction, RebalanceError> {
+        let rebalance_request = plan_rebalance(&self.config, mirror)?;
+        let amount = rebalance_request.amount;
+
+        let cycles = await!(self.find_cycles(mirror, &rebalance_request))?;
+        let route = select_route(cycles, amount, &CheapestFee)
+            .ok_or(RebalanceError::NoRoute)?;
+
+        if !is_rebalance_route(mirror.local_public_key(), &rebalance_request, &route) {
+            return Err(RebalanceError::InvalidRoute);
+        }
+
+        let fee = route_fee(&route, amount).ok_or(RebalanceError::InvalidRoute)?;
+        let total_payment = amount
+            .checked_add(fee)
+            .ok_or(RebalanceError::InsufficientCapacity)?;
+        if total_payment > mirror.send_capacity(&rebalance_request.friend_over) {
+            return Err(RebalanceError::InsufficientCapacity);
+        }
+        if fee > self.fee_budget.remaining(day) {
+            return Err(RebalanceError::FeeBudgetExceeded);
+        }
+
+        let request_id = Uid::new(&self.rng);
+        let receipt = await!(self.app_send_funds.request_send_funds(
+            request_id.clone(),
+            route.clone(),
+            InvoiceId::new(&self.rng),
+            amount
+        ))
+        .map_err(RebalanceError::SendFundsError)?;
+        // Fees are only paid for successful payments:
+        self.fee_budget.spend(day, fee);
+
+        await!(self
+            .app_send_funds
+            .receipt_ack(request_id, receipt.clone()))
+        .map_err(|_| RebalanceError::ReceiptAckError)?;
+
+        Ok(RebalanceAction {
+            rebalance_request,
+            route,
+            fee,
+            receipt,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    #[test]
+    fn test_fee_budget() {
+        let mut fee_budget = FeeBudget::new(10);
+        assert_eq!(fee_budget.remaining(1), 10);
+
+        fee_budget.spend(1, 4);
+        fee_budget.spend(1, 4);
+        assert_eq!(fee_budget.remaining(1), 2);
+
+        fee_budget.spend(1, 4);
+        assert_eq!(fee_budget.remaining(1), 0);
+
+        // A new day starts with the full budget:
+        assert_eq!(fee_budget.remaining(2), 10);
+        fee_budget.spend(2, 3);
+        assert_eq!(fee_budget.remaining(2), 7);
+    }
+
+    #[test]
+    fn test_is_rebalance_route() {
+        let pk_local = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_over = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let pk_mid = PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]);
+        let pk_under = PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]);
+
+        let rebalance_request = RebalanceRequest {
+            friend_over: pk_over.clone(),
+            friend_under: pk_under.clone(),
+            amount: 10,
+        };
+        let route = |pks: &[&PublicKey]| FriendsRoute {
+            public_keys: pks.iter().map(|&pk| pk.clone()).collect(),
+        };
+
+        assert!(is_rebalance_route(
+            &pk_local,
+            &rebalance_request,
+            &route(&[&pk_local, &pk_over, &pk_under, &pk_local])
+        ));
+        assert!(is_rebalance_route(
+            &pk_local,
+            &rebalance_request,
+            &route(&[&pk_local, &pk_over, &pk_mid, &pk_under, &pk_local])
+        ));
+
+        // Wrong direction:
+        assert!(!is_rebalance_route(
+            &pk_local,
+            &rebalance_request,
+            &route(&[&pk_local, &pk_under, &pk_over, &pk_local])
+        ));
+        // Not a cycle:
+        assert!(!is_rebalance_route(
+            &pk_local,
+            &rebalance_request,
+            &route(&[&pk_local, &pk_over, &pk_under])
+        ));
+        // Back and forth through a single friend:
+        assert!(!is_rebalance_route(
+            &pk_local,
+            &rebalance_request,
+            &route(&[&pk_local, &pk_over, &pk_local])
+        ));
+        // Passes through the local node twice:
+        assert!(!is_rebalance_route(
+            &pk_local,
+            &rebalance_request,
+            &route(&[&pk_local, &pk_over, &pk_local, &pk_under, &pk_local])
+        ));
+    }
+}
@@ -1,23 +1,50 @@
 use futures::channel::mpsc;
+use futures::{future, stream, StreamExt};
 
 use common::mutable_state::BatchMutable;
+use common::select_streams::{select_streams, BoxStream};
 use common::state_service::StateClient;
 use proto::app_server::messages::{NodeReport, NodeReportMutation};
 
+use timer::TimerClient;
+
+use super::mirror::NodeStateMirror;
+
 #[derive(Debug)]
 pub struct AppReportError;
 
+#[derive(Debug)]
+pub enum WaitForError {
+    RequestReportError,
+    RequestTimerStreamError,
+    MutateError,
+    ReportClosed,
+    /// The predicate did not become true in the given amount of ticks.
+    Timeout,
+}
+
+enum WaitForEvent {
+    Mutations(Vec<NodeReportMutation>),
+    MutationsClosed,
+    TimerTick,
+}
+
 #[derive(Clone)]
 pub struct AppReport {
     report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
+    timer_client: TimerClient,
 }
 
 impl AppReport {
     // TODO; Should this be private?
     pub(super) fn new(
         report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
+        timer_client: TimerClient,
     ) -> Self {
-        AppReport { report_client }
+        AppReport {
+            report_client,
+            timer_client,
+        }
     }
 
     pub async fn incoming_reports(
@@ -28,4 +55,207 @@ impl AppReport {
 
         Ok((batch_mutable.0, incoming_mutations))
     }
+
+    /// Obtain a mirror of the current state of the node.
+    pub async fn mirror(&mut self) -> Result<NodeStateMirror, AppReportError> {
+        let (node_report, _incoming_mutations) = await!(self.incoming_reports())?;
+        Ok(NodeStateMirror::new(node_report))
+    }
+
+    /// Wait until `predicate` over the state of the node becomes true.
+    /// Returns the mirrored state for which the predicate was true,
+    /// or an error if `timeout_ticks` ticks have passed first.
+    ///
+    /// Example: `wait_for(|mirror| mirror.is_friend_online(&friend_public_key), 100)`
+    pub async fn wait_for<P>(
+        &mut self,
+        predicate: P,
+        timeout_ticks: usize,
+    ) -> Result<NodeStateMirror, WaitForError>
+    where
+        P: Fn(&NodeStateMirror) -> bool,
+    {
+        let (node_report, incoming_mutations) =
+            await!(self.incoming_reports()).map_err(|_| WaitForError::RequestReportError)?;
+        let mut mirror = NodeStateMirror::new(node_report);
+        if predicate(&mirror) {
+            return Ok(mirror);
+        }
+
+        let timer_stream = await!(self.timer_client.request_timer_stream())
+            .map_err(|_| WaitForError::RequestTimerStreamError)?;
+
+        let incoming_mutations = incoming_mutations
+            .map(WaitForEvent::Mutations)
+            .chain(stream::once(future::ready(WaitForEvent::MutationsClosed)));
+        let timer_stream = timer_stream.map(|_| WaitForEvent::TimerTick);
+        let mut incoming_events = select_streams![incoming_mutations, timer_stream];
+
+        let mut ticks_left = timeout_ticks;
+        while let Some(event) = await!(incoming_events.next()) {
+            match event {
+                WaitForEvent::Mutations(mutations) => {
+                    mirror
+                        .apply(&mutations)
+                        .map_err(|_| WaitForError::MutateError)?;
+                    if predicate(&mirror) {
+                        return Ok(mirror);
+                    }
+                }
+                WaitForEvent::MutationsClosed => return Err(WaitForError::ReportClosed),
+                WaitForEvent::TimerTick => {
+                    ticks_left = ticks_left.saturating_sub(1);
+                    if ticks_left == 0 {
+                        return Err(WaitForError::Timeout);
+                    }
+                }
+            }
+        }
+        Err(WaitForError::ReportClosed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use futures::channel::oneshot;
+    use futures::task::{Spawn, SpawnExt};
+    use futures::{FutureExt, SinkExt, TryFutureExt};
+
+    use common::state_service::state_service;
+    use common::test_executor::TestExecutor;
+
+    use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+
+    use proto::app_server::messages::NamedRelayAddress;
+    use proto::index_client::messages::IndexClientReport;
+    use proto::net::messages::NetAddress;
+    use proto::report::messages::{FunderReport, FunderReportMutation};
+
+    use timer::create_timer_incoming;
+
+    fn create_report_client<S>(
+        mut spawner: S,
+    ) -> (
+        mpsc::Sender<Vec<NodeReportMutation>>,
+        StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
+    )
+    where
+        S: Spawn,
+    {
+        let node_report = NodeReport {
+            funder_report: FunderReport {
+                local_public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                relays: Default::default(),
+                friends: Default::default(),
+                num_ready_receipts: 0,
+                quarantined_friends: Default::default(),
+                dust_thresholds: Default::default(),
+                directory: Default::default(),
+                reliability: Default::default(),
+                read_only: false,
+                labeled_payments: Default::default(),
+            },
+            index_client_report: IndexClientReport {
+                index_servers: Vec::new(),
+                opt_connected_server: None,
+                num_corrections: 0,
+            },
+        };
+
+        let (mutations_sender, incoming_mutations) = mpsc::channel(0);
+        let (requests_sender, incoming_requests) = mpsc::channel(0);
+        let state_service_fut = state_service(
+            incoming_requests,
+            BatchMutable(node_report),
+            incoming_mutations,
+        )
+        .map_err(|e| error!("state_service() error: {:?}", e))
+        .map(|_| ());
+        spawner.spawn(state_service_fut).unwrap();
+
+        (mutations_sender, StateClient::new(requests_sender))
+    }
+
+    fn add_relay_mutation(relay_public_key: &PublicKey) -> NodeReportMutation {
+        NodeReportMutation::Funder(FunderReportMutation::AddRelay(NamedRelayAddress {
+            public_key: relay_public_key.clone(),
+            address: NetAddress::try_from("relay_address".to_owned()).unwrap(),
+            name: "relay".to_owned(),
+        }))
+    }
+
+    async fn task_app_report_wait_for(mut test_executor: TestExecutor) {
+        let (mut tick_sender, tick_receiver) = mpsc::channel(0);
+        let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+        let (mut mutations_sender, report_client) = create_report_client(test_executor.clone());
+        let app_report = AppReport::new(report_client, timer_client);
+
+        let relay_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        // Wait for a relay that is never added:
+        let (result_sender, mut result_receiver) = oneshot::channel();
+        let mut c_app_report = app_report.clone();
+        let c_relay_public_key = relay_public_key.clone();
+        test_executor
+            .spawn(async move {
+                let predicate = |mirror: &NodeStateMirror| mirror.has_relay(&c_relay_public_key);
+                let res = await!(c_app_report.wait_for(predicate, 8));
+                let _ = result_sender.send(res);
+            })
+            .unwrap();
+
+        await!(test_executor.wait());
+        for _ in 0..7 {
+            await!(tick_sender.send(())).unwrap();
+            await!(test_executor.wait());
+        }
+        assert!(result_receiver.try_recv().unwrap().is_none());
+
+        await!(tick_sender.send(())).unwrap();
+        await!(test_executor.wait());
+        match result_receiver.try_recv().unwrap() {
+            Some(Err(WaitForError::Timeout)) => {}
+            _ => unreachable!(),
+        };
+
+        // Wait for a relay that is added while waiting:
+        let (result_sender, mut result_receiver) = oneshot::channel();
+        let mut c_app_report = app_report.clone();
+        let c_relay_public_key = relay_public_key.clone();
+        test_executor
+            .spawn(async move {
+                let predicate = |mirror: &NodeStateMirror| mirror.has_relay(&c_relay_public_key);
+                let res = await!(c_app_report.wait_for(predicate, 8));
+                let _ = result_sender.send(res);
+            })
+            .unwrap();
+
+        await!(test_executor.wait());
+        await!(tick_sender.send(())).unwrap();
+        await!(test_executor.wait());
+        assert!(result_receiver.try_recv().unwrap().is_none());
+
+        await!(mutations_sender.send(vec![add_relay_mutation(&relay_public_key)])).unwrap();
+        await!(test_executor.wait());
+        let mirror = result_receiver.try_recv().unwrap().unwrap().unwrap();
+        assert!(mirror.has_relay(&relay_public_key));
+
+        // The predicate already holds, so we don't wait at all:
+        let mut c_app_report = app_report.clone();
+        let mirror =
+            await!(c_app_report.wait_for(|mirror| mirror.has_relay(&relay_public_key), 0)).unwrap();
+        assert!(mirror.has_relay(&relay_public_key));
+    }
+
+    #[test]
+    fn test_app_report_wait_for() {
+        let test_executor = TestExecutor::new();
+        let res = test_executor.run(task_app_report_wait_for(test_executor.clone()));
+        assert!(res.is_output());
+    }
 }
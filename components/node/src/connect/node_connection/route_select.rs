@@ -0,0 +1,387 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use crypto::crypto_rand::CryptoRandom;
+use crypto::identity::PublicKey;
+
+use funder::CreditCalculator;
+
+use proto::consts::NEUTRAL_SUCCESS_PPM;
+use proto::funder::messages::FriendsRoute;
+use proto::index_server::messages::RouteWithCapacity;
+use proto::report::messages::ReliabilityReport;
+
+/// A policy for choosing one route out of multiple candidate routes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoutePolicy {
+    /// Route with the lowest fee.
+    CheapestFee,
+    /// Route with the least amount of nodes.
+    Shortest,
+    /// Route with the highest capacity.
+    HighestCapacity,
+    /// A random route, weighted by capacity. Useful for privacy.
+    RandomWeighted,
+    /// Route with the lowest fee, weighted by the reliability of its nodes.
+    ReliabilityWeighted,
+}
+
+/// Choose one route out of a few candidate routes.
+/// All the candidate routes given to a selector are able to carry the payment.
+pub trait RouteSelector {
+    /// Returns the index of the chosen route inside `candidates`,
+    /// or None if `candidates` is empty.
+    fn select(&self, candidates: &[RouteWithCapacity], dest_payment: u128) -> Option<usize>;
+}
+
+/// Total amount of credits the source node has to send along the route,
+/// in order to pay `dest_payment` to the destination (Including fees).
+fn route_total_payment(route: &FriendsRoute, dest_payment: u128) -> Option<u128> {
+    CreditCalculator::new(route.len(), dest_payment)
+        .ok()?
+        .credits_to_freeze(1)
+}
+
+/// Amount of credits paid to the intermediate nodes of the route.
+pub(super) fn route_fee(route: &FriendsRoute, dest_payment: u128) -> Option<u128> {
+    route_total_payment(route, dest_payment)?.checked_sub(dest_payment)
+}
+
+pub struct CheapestFee;
+
+impl RouteSelector for CheapestFee {
+    fn select(&self, candidates: &[RouteWithCapacity], dest_payment: u128) -> Option<usize> {
+        candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(index, candidate)| {
+                Some((index, route_fee(&candidate.route, dest_payment)?))
+            })
+            .min_by_key(|&(_index, fee)| fee)
+            .map(|(index, _fee)| index)
+    }
+}
+
+pub struct Shortest;
+
+impl RouteSelector for Shortest {
+    fn select(&self, candidates: &[RouteWithCapacity], _dest_payment: u128) -> Option<usize> {
+        candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|(_index, candidate)| candidate.route.len())
+            .map(|(index, _candidate)| index)
+    }
+}
+
+pub struct HighestCapacity;
+
+impl RouteSelector for HighestCapacity {
+    fn select(&self, candidates: &[RouteWithCapacity], _dest_payment: u128) -> Option<usize> {
+        candidates
+            .iter()
+            .enumerate()
+            .min_by_key(|(_index, candidate)| Reverse(candidate.capacity))
+            .map(|(index, _candidate)| index)
+    }
+}
+
+pub struct RandomWeighted<'a, R> {
+    rng: &'a R,
+}
+
+impl<'a, R> RandomWeighted<'a, R> {
+    pub fn new(rng: &'a R) -> Self {
+        RandomWeighted { rng }
+    }
+}
+
+impl<'a, R> RouteSelector for RandomWeighted<'a, R>
+where
+    R: CryptoRandom,
+{
+    fn select(&self, candidates: &[RouteWithCapacity], _dest_payment: u128) -> Option<usize> {
+        if candidates.is_empty() {
+            return None;
+        }
+        // Every route gets a weight of at least 1, so that we never divide by zero:
+        let weights = candidates
+            .iter()
+            .map(|candidate| candidate.capacity.max(1))
+            .collect::<Vec<_>>();
+        let total_weight = weights
+            .iter()
+            .fold(0u128, |total, &weight| total.saturating_add(weight));
+
+        let mut rand_bytes = [0u8; 16];
+        self.rng.fill(&mut rand_bytes).ok()?;
+        let mut point = u128::from_le_bytes(rand_bytes) % total_weight;
+
+        for (index, &weight) in weights.iter().enumerate() {
+            if point < weight {
+                return Some(index);
+            }
+            point -= weight;
+        }
+        // Might happen if total_weight was saturated:
+        Some(candidates.len() - 1)
+    }
+}
+
+pub struct ReliabilityWeighted<'a> {
+    reliability: &'a HashMap<PublicKey, ReliabilityReport>,
+}
+
+impl<'a> ReliabilityWeighted<'a> {
+    pub fn new(reliability: &'a HashMap<PublicKey, ReliabilityReport>) -> Self {
+        ReliabilityWeighted { reliability }
+    }
+
+    /// Estimated probability (In parts per million) that a payment along the route succeeds.
+    /// Nodes we know nothing about are considered neutral.
+    fn route_success_ppm(&self, route: &FriendsRoute) -> u128 {
+        // The first node on the route is the sender:
+        route
+            .public_keys
+            .iter()
+            .skip(1)
+            .fold(1_000_000u128, |route_success_ppm, public_key| {
+                let success_ppm = self
+                    .reliability
+                    .get(public_key)
+                    .map(|reliability_report| reliability_report.success_ppm)
+                    .unwrap_or(NEUTRAL_SUCCESS_PPM);
+                route_success_ppm * u128::from(success_ppm) / 1_000_000
+            })
+    }
+}
+
+impl<'a> RouteSelector for ReliabilityWeighted<'a> {
+    fn select(&self, candidates: &[RouteWithCapacity], dest_payment: u128) -> Option<usize> {
+        candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(index, candidate)| {
+                let total_payment = route_total_payment(&candidate.route, dest_payment)?;
+                let route_success_ppm = self.route_success_ppm(&candidate.route).max(1);
+                // Expected amount of credits we have to send until the payment succeeds:
+                let cost = total_payment.saturating_mul(1_000_000) / route_success_ppm;
+                Some((index, cost))
+            })
+            .min_by_key(|&(_index, cost)| cost)
+            .map(|(index, _cost)| index)
+    }
+}
+
+/// Choose a route for sending `dest_payment` credits to the destination.
+/// Routes that can not carry the payment (including fees) are discarded before
+/// the selector is invoked.
+pub fn select_route(
+    routes_with_capacity: Vec<RouteWithCapacity>,
+    dest_payment: u128,
+    route_selector: &impl RouteSelector,
+) -> Option<FriendsRoute> {
+    let mut candidates = routes_with_capacity
+        .into_iter()
+        .filter(|route_with_capacity| {
+            if route_with_capacity.route.len() < 2 {
+                // This is an invalid route
+                return false;
+            }
+            match route_total_payment(&route_with_capacity.route, dest_payment) {
+                Some(total_payment) => total_payment <= route_with_capacity.capacity,
+                None => false,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let index = route_selector.select(&candidates, dest_payment)?;
+    if index >= candidates.len() {
+        return None;
+    }
+    Some(candidates.swap_remove(index).route)
+}
+
+/// Choose a route for sending `dest_payment` credits to the destination, according to `policy`.
+/// `reliability` contains the measured reliability of remote nodes, as reported by the funder.
+pub fn select_route_by_policy<R>(
+    routes_with_capacity: Vec<RouteWithCapacity>,
+    dest_payment: u128,
+    policy: RoutePolicy,
+    reliability: &HashMap<PublicKey, ReliabilityReport>,
+    rng: &R,
+) -> Option<FriendsRoute>
+where
+    R: CryptoRandom,
+{
+    match policy {
+        RoutePolicy::CheapestFee => select_route(routes_with_capacity, dest_payment, &CheapestFee),
+        RoutePolicy::Shortest => select_route(routes_with_capacity, dest_payment, &Shortest),
+        RoutePolicy::HighestCapacity => {
+            select_route(routes_with_capacity, dest_payment, &HighestCapacity)
+        }
+        RoutePolicy::RandomWeighted => select_route(
+            routes_with_capacity,
+            dest_payment,
+            &RandomWeighted::new(rng),
+        ),
+        RoutePolicy::ReliabilityWeighted => select_route(
+            routes_with_capacity,
+            dest_payment,
+            &ReliabilityWeighted::new(reliability),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+    use crypto::test_utils::DummyRandom;
+
+    /// Create a route of length `route_len`.
+    /// `route_id` is used to make sure the intermediate nodes of different routes are distinct.
+    fn route_with_capacity(route_id: u8, route_len: u8, capacity: u128) -> RouteWithCapacity {
+        let mut public_keys = vec![PublicKey::from(&[0xaa; PUBLIC_KEY_LEN])];
+        for i in 0..route_len - 2 {
+            let mut public_key_bytes = [i; PUBLIC_KEY_LEN];
+            public_key_bytes[0] = route_id;
+            public_keys.push(PublicKey::from(&public_key_bytes));
+        }
+        public_keys.push(PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]));
+
+        RouteWithCapacity {
+            route: FriendsRoute { public_keys },
+            capacity,
+            meets_request: true,
+        }
+    }
+
+    /// A fixed set of candidate routes. We pay 100 credits:
+    fn candidates() -> Vec<RouteWithCapacity> {
+        vec![
+            // Too little capacity to carry the payment with fees (Needs 103):
+            route_with_capacity(0, 5, 102),
+            // Fee: 2
+            route_with_capacity(1, 4, 150),
+            // Fee: 4
+            route_with_capacity(2, 6, 300),
+            // Fee: 1
+            route_with_capacity(3, 3, 120),
+            // Fee: 3
+            route_with_capacity(4, 5, 200),
+        ]
+    }
+
+    #[test]
+    fn test_select_route_policies() {
+        let rng = DummyRandom::new(&[1u8]);
+        let reliability = HashMap::new();
+        let candidates = candidates();
+        let select = |dest_payment, policy| {
+            select_route_by_policy(candidates.clone(), dest_payment, policy, &reliability, &rng)
+        };
+
+        assert_eq!(
+            select(100, RoutePolicy::CheapestFee),
+            Some(candidates[3].route.clone())
+        );
+        assert_eq!(
+            select(100, RoutePolicy::Shortest),
+            Some(candidates[3].route.clone())
+        );
+        assert_eq!(
+            select(100, RoutePolicy::HighestCapacity),
+            Some(candidates[2].route.clone())
+        );
+        // Without any measurements, all nodes are neutral:
+        assert_eq!(
+            select(100, RoutePolicy::ReliabilityWeighted),
+            Some(candidates[3].route.clone())
+        );
+        // No route can carry such a payment:
+        assert_eq!(select(1000, RoutePolicy::Shortest), None);
+    }
+
+    fn reliability_report(success_ppm: u32) -> ReliabilityReport {
+        ReliabilityReport {
+            success_ppm,
+            opt_latency_ticks: None,
+            samples: 16,
+        }
+    }
+
+    #[test]
+    fn test_select_route_reliability_weighted() {
+        let candidates = candidates();
+        // The intermediate node of the cheapest route fails half of the payments:
+        let unreliable_public_key = candidates[3].route.public_keys[1].clone();
+        let mut reliability = HashMap::new();
+        reliability.insert(unreliable_public_key.clone(), reliability_report(500_000));
+
+        // A slightly more expensive route is preferred:
+        assert_eq!(
+            select_route(candidates.clone(), 100, &ReliabilityWeighted::new(&reliability)),
+            Some(candidates[1].route.clone())
+        );
+        assert_eq!(
+            select_route(candidates.clone(), 100, &CheapestFee),
+            Some(candidates[3].route.clone())
+        );
+
+        // A slightly unreliable node is still preferred, if it is much cheaper:
+        reliability.insert(unreliable_public_key, reliability_report(995_000));
+        assert_eq!(
+            select_route(candidates.clone(), 100, &ReliabilityWeighted::new(&reliability)),
+            Some(candidates[3].route.clone())
+        );
+    }
+
+    #[test]
+    fn test_select_route_policies_disagree() {
+        // A long route with high capacity, and a short (cheap) route with low capacity:
+        let candidates = vec![
+            route_with_capacity(0, 6, 1000),
+            route_with_capacity(1, 3, 101),
+        ];
+        assert_eq!(
+            select_route(candidates.clone(), 100, &CheapestFee),
+            Some(candidates[1].route.clone())
+        );
+        assert_eq!(
+            select_route(candidates.clone(), 100, &HighestCapacity),
+            Some(candidates[0].route.clone())
+        );
+    }
+
+    #[test]
+    fn test_select_route_random_weighted_deterministic() {
+        let candidates = candidates();
+
+        let reliability = HashMap::new();
+        let choices = |seed: u8| {
+            let rng = DummyRandom::new(&[seed]);
+            (0..16)
+                .map(|_| {
+                    select_route_by_policy(
+                        candidates.clone(),
+                        100,
+                        RoutePolicy::RandomWeighted,
+                        &reliability,
+                        &rng,
+                    )
+                    .unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // The same seed always gives the same choices:
+        assert_eq!(choices(3), choices(3));
+
+        // The route that can not carry the payment is never chosen:
+        for route in choices(4) {
+            assert_ne!(route, candidates[0].route);
+        }
+    }
+}
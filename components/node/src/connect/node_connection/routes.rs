@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use futures::channel::mpsc;
 use futures::{SinkExt, StreamExt};
 
@@ -8,8 +10,12 @@ use crypto::identity::PublicKey;
 use crypto::uid::Uid;
 
 use proto::app_server::messages::{AppRequest, AppToAppServer};
+use proto::funder::messages::FriendsRoute;
 use proto::index_client::messages::{ClientResponseRoutes, ResponseRoutesResult};
-use proto::index_server::messages::{RequestRoutes, RouteWithCapacity};
+use proto::index_server::messages::{RequestRoutes, RouteDisjointness, RouteWithCapacity};
+use proto::report::messages::ReliabilityReport;
+
+use super::route_select::{select_route_by_policy, RoutePolicy};
 
 #[derive(Debug)]
 pub struct AppRoutesError;
@@ -18,6 +24,10 @@ pub struct AppRoutesError;
 pub struct AppRoutes<R = OffstSystemRandom> {
     sender: mpsc::Sender<AppToAppServer>,
     routes_mc: MultiConsumerClient<ClientResponseRoutes>,
+    /// Default policy for choosing between multiple candidate routes
+    route_policy: RoutePolicy,
+    /// Measured reliability of remote nodes, used by the reliability weighted route policy
+    reliability: HashMap<PublicKey, ReliabilityReport>,
     rng: R,
 }
 
@@ -60,16 +70,106 @@ where
         AppRoutes {
             sender,
             routes_mc,
+            route_policy: RoutePolicy::CheapestFee,
+            reliability: HashMap::new(),
             rng,
         }
     }
 
+    /// Set the default policy for choosing between multiple candidate routes.
+    pub fn set_route_policy(&mut self, route_policy: RoutePolicy) {
+        self.route_policy = route_policy;
+    }
+
+    /// Set the measured reliability of remote nodes, usually taken from the funder report.
+    pub fn set_reliability(&mut self, reliability: HashMap<PublicKey, ReliabilityReport>) {
+        self.reliability = reliability;
+    }
+
+    /// Choose a route for sending `dest_payment` credits, out of the given candidate routes.
+    /// `opt_route_policy` overrides the default route policy for this payment.
+    pub fn select_route(
+        &self,
+        routes_with_capacity: Vec<RouteWithCapacity>,
+        dest_payment: u128,
+        opt_route_policy: Option<RoutePolicy>,
+    ) -> Option<FriendsRoute> {
+        let route_policy = opt_route_policy.unwrap_or(self.route_policy);
+        select_route_by_policy(
+            routes_with_capacity,
+            dest_payment,
+            route_policy,
+            &self.reliability,
+            &self.rng,
+        )
+    }
+
     pub async fn request_routes(
         &mut self,
         capacity: u128,
         source: PublicKey,
         destination: PublicKey,
         opt_exclude: Option<(PublicKey, PublicKey)>,
+    ) -> Result<Vec<RouteWithCapacity>, AppRoutesError> {
+        await!(self.request_disjoint_routes(
+            capacity,
+            source,
+            destination,
+            opt_exclude,
+            RouteDisjointness::None
+        ))
+    }
+
+    /// Request multiple routes that do not share intermediate nodes (or edges), according to
+    /// `disjointness`. Index servers that do not support disjointness will return a single route.
+    pub async fn request_disjoint_routes(
+        &mut self,
+        capacity: u128,
+        source: PublicKey,
+        destination: PublicKey,
+        opt_exclude: Option<(PublicKey, PublicKey)>,
+        disjointness: RouteDisjointness,
+    ) -> Result<Vec<RouteWithCapacity>, AppRoutesError> {
+        await!(self.request_routes_inner(
+            capacity,
+            source,
+            destination,
+            opt_exclude,
+            disjointness,
+            false
+        ))
+    }
+
+    /// Like `request_disjoint_routes`, but if no route has the wanted capacity, the routes with
+    /// the largest capacities are returned instead, with `meets_request` set to false.
+    /// The payment could then be split between those routes.
+    /// Index servers that do not support partial routes will return no routes in this case.
+    pub async fn request_partial_routes(
+        &mut self,
+        capacity: u128,
+        source: PublicKey,
+        destination: PublicKey,
+        opt_exclude: Option<(PublicKey, PublicKey)>,
+        disjointness: RouteDisjointness,
+    ) -> Result<Vec<RouteWithCapacity>, AppRoutesError> {
+        await!(self.request_routes_inner(
+            capacity,
+            source,
+            destination,
+            opt_exclude,
+            disjointness,
+            true
+        ))
+    }
+
+    async fn request_routes_inner(
+        &mut self,
+        capacity: u128,
+        source: PublicKey,
+        destination: PublicKey,
+        opt_exclude: Option<(PublicKey, PublicKey)>,
+        disjointness: RouteDisjointness,
+        allow_partial: bool,
     ) -> Result<Vec<RouteWithCapacity>, AppRoutesError> {
         let request_routes_id = Uid::new(&self.rng);
         let request_routes = RequestRoutes {
@@ -78,6 +178,8 @@ where
             source,
             destination,
             opt_exclude,
+            disjointness,
+            allow_partial,
         };
 
         let app_request = AppRequest::RequestRoutes(request_routes);
@@ -105,3 +207,48 @@ where
         Err(AppRoutesError)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use crypto::test_utils::DummyRandom;
+
+    fn route_with_capacity(route_len: u8, capacity: u128) -> RouteWithCapacity {
+        RouteWithCapacity {
+            route: FriendsRoute {
+                public_keys: (0..route_len)
+                    .map(|i| PublicKey::from(&[i; PUBLIC_KEY_LEN]))
+                    .collect(),
+            },
+            capacity,
+            meets_request: true,
+        }
+    }
+
+    #[test]
+    fn test_app_routes_select_route_override() {
+        let (sender, _receiver) = mpsc::channel(0);
+        let (mc_sender, _mc_receiver) = mpsc::channel(0);
+        let mut app_routes = AppRoutes::new(
+            sender,
+            MultiConsumerClient::new(mc_sender),
+            DummyRandom::new(&[1u8]),
+        );
+        app_routes.set_route_policy(RoutePolicy::HighestCapacity);
+
+        let candidates = vec![route_with_capacity(3, 200), route_with_capacity(6, 1000)];
+
+        // Node default policy:
+        assert_eq!(
+            app_routes.select_route(candidates.clone(), 100, None),
+            Some(candidates[1].route.clone())
+        );
+        // Per payment override:
+        assert_eq!(
+            app_routes.select_route(candidates.clone(), 100, Some(RoutePolicy::Shortest)),
+            Some(candidates[0].route.clone())
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+
+use common::multi_consumer::MultiConsumerClient;
+
+use crypto::crypto_rand::{CryptoRandom, OffstSystemRandom};
+use crypto::uid::Uid;
+
+use proto::app_server::messages::{
+    AppRequest, AppToAppServer, ResponseSelfTest, SelfTestStageReport,
+};
+
+#[derive(Debug)]
+pub struct AppSelfTestError;
+
+/// Run self tests of the node. Available to apps with any permissions.
+#[derive(Clone)]
+pub struct AppSelfTest<R = OffstSystemRandom> {
+    sender: mpsc::Sender<AppToAppServer>,
+    self_test_mc: MultiConsumerClient<ResponseSelfTest>,
+    rng: R,
+}
+
+impl<R> AppSelfTest<R>
+where
+    R: CryptoRandom,
+{
+    pub(super) fn new(
+        sender: mpsc::Sender<AppToAppServer>,
+        self_test_mc: MultiConsumerClient<ResponseSelfTest>,
+        rng: R,
+    ) -> Self {
+        AppSelfTest {
+            sender,
+            self_test_mc,
+            rng,
+        }
+    }
+
+    /// Let the node pay a throwaway in-memory peer, and be paid by it.
+    /// Returns a report for every stage that was run. The self test stops at the first failed
+    /// stage.
+    pub async fn run(&mut self) -> Result<Vec<SelfTestStageReport>, AppSelfTestError> {
+        let request_id = Uid::new(&self.rng);
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server =
+            AppToAppServer::new(app_request_id, AppRequest::RequestSelfTest(request_id));
+
+        let mut incoming_self_tests =
+            await!(self.self_test_mc.request_stream()).map_err(|_| AppSelfTestError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| AppSelfTestError)?;
+
+        while let Some(response_self_test) = await!(incoming_self_tests.next()) {
+            if response_self_test.request_id == request_id {
+                return Ok(response_self_test.stages);
+            }
+        }
+        Err(AppSelfTestError)
+    }
+}
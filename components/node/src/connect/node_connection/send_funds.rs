@@ -1,4 +1,6 @@
 use common::multi_consumer::MultiConsumerClient;
+use common::mutable_state::BatchMutable;
+use common::state_service::StateClient;
 use futures::channel::mpsc;
 use futures::{SinkExt, StreamExt};
 
@@ -7,11 +9,17 @@ use crypto::identity::PublicKey;
 use crypto::invoice_id::InvoiceId;
 use crypto::uid::Uid;
 
-use proto::app_server::messages::{AppRequest, AppToAppServer};
+use proto::app_server::messages::{
+    AppRequest, AppToAppServer, LabelFilter, NodeReport, NodeReportMutation,
+    RequestLabeledPayments, ResponseLabeledPayments,
+};
 use proto::funder::messages::{
-    FriendsRoute, Receipt, ReceiptAck, ResponseReceived, ResponseSendFundsResult,
-    UserRequestSendFunds,
+    CancelUserRequestResult, FailureReason, FriendsRoute, LabeledPayment, PaymentSubmission,
+    PrewarmFailure, PrewarmFriend, PrewarmResult, Receipt, ReceiptAck, ResponseCancelUserRequest,
+    ResponsePendingSubmissions, ResponsePrewarm, ResponseReceived, ResponseSendFundsResult,
+    UserRequestSendFunds, UserRequestSweepFunds,
 };
+use proto::index_server::messages::RouteWithCapacity;
 
 // TODO; Different in naming convention from AppConfigError and AppRoutesError:
 #[derive(Debug)]
@@ -22,19 +30,103 @@ pub enum SendFundsError {
     /// A remote error occurred when trying to send funds.
     /// (Not enough credits, Some node cancelled along the route)
     RemoteError(PublicKey),
+    /// The payment is below the minimum payment the node is willing to send.
+    /// Contains the minimum payment.
+    BelowMinPayment(u128),
+    /// A node along the route refused to handle a payment this small.
+    /// Contains the public key of the refusing node.
+    PricingRejected(PublicKey),
+    /// The maximum amount we can sweep is below the minimum payment the node is willing to send.
+    NothingToSend,
+    /// The node can not currently send this amount to the first hop friend, for example because
+    /// of other payments in progress. Contains the largest payment that may be sent instead
+    /// along the same route.
+    InsufficientLocalCapacity(u128),
+    /// The node can not write to its database (For example: the disk is full).
+    /// The request may be sent again after the node recovers.
+    StorageUnavailable,
+    /// The given route does not lead from us to the destination.
+    InvalidRoute,
+    /// The app has too many open requests. The request may be sent again after some of the open
+    /// requests are done.
+    RateLimited,
+    /// The request was cancelled (Using `cancel_send_funds()`) before it was sent.
+    Cancelled,
     /// The request was issued, but no response was received.
     /// The request should be saved (By the caller) and resent at another time.
     NoResponse,
 }
 
+fn send_funds_error(public_key: PublicKey, reason: FailureReason) -> SendFundsError {
+    match reason {
+        FailureReason::PricingRejected => SendFundsError::PricingRejected(public_key),
+        FailureReason::NothingToSend => SendFundsError::NothingToSend,
+        FailureReason::RateLimited => SendFundsError::RateLimited,
+        FailureReason::InsufficientLocalCapacity(max_payment) => {
+            SendFundsError::InsufficientLocalCapacity(max_payment)
+        }
+        FailureReason::StorageUnavailable => SendFundsError::StorageUnavailable,
+        FailureReason::Cancelled => SendFundsError::Cancelled,
+        _ => SendFundsError::RemoteError(public_key),
+    }
+}
+
 #[derive(Debug)]
 pub struct ReceiptAckError;
 
+#[derive(Debug)]
+pub enum PrewarmError {
+    /// A local error occurred when trying to pre-warm.
+    /// (Connectivity error)
+    LocalError,
+    /// The given route does not have a first hop friend.
+    InvalidRoute,
+    /// The node could not pre-warm the channel with the friend.
+    Failure(PrewarmFailure),
+    /// The request was issued, but no response was received.
+    NoResponse,
+}
+
+#[derive(Debug)]
+pub enum CancelSendFundsError {
+    /// A local error occurred when trying to cancel.
+    /// (Connectivity error)
+    LocalError,
+    /// The request was issued, but no response was received.
+    NoResponse,
+}
+
+#[derive(Debug)]
+pub enum LabeledPaymentsError {
+    /// A local error occurred when trying to find the payments.
+    /// (Connectivity error)
+    LocalError,
+    /// The request was issued, but no response was received.
+    NoResponse,
+}
+
+#[derive(Debug)]
+pub enum PendingSubmissionsError {
+    /// A local error occurred when trying to list the submissions.
+    /// (Connectivity error)
+    LocalError,
+    /// The request was issued, but no response was received.
+    NoResponse,
+}
+
+#[derive(Debug)]
+pub struct AckSubmissionError;
+
 #[derive(Clone)]
 pub struct AppSendFunds<R = OffstSystemRandom> {
     sender: mpsc::Sender<AppToAppServer>,
     send_funds_mc: MultiConsumerClient<ResponseReceived>,
+    prewarm_mc: MultiConsumerClient<ResponsePrewarm>,
+    cancel_mc: MultiConsumerClient<ResponseCancelUserRequest>,
+    labeled_payments_mc: MultiConsumerClient<ResponseLabeledPayments>,
+    submissions_mc: MultiConsumerClient<ResponsePendingSubmissions>,
     done_app_requests_mc: MultiConsumerClient<Uid>,
+    report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
     rng: R,
 }
 
@@ -45,13 +137,23 @@ where
     pub(super) fn new(
         sender: mpsc::Sender<AppToAppServer>,
         send_funds_mc: MultiConsumerClient<ResponseReceived>,
+        prewarm_mc: MultiConsumerClient<ResponsePrewarm>,
+        cancel_mc: MultiConsumerClient<ResponseCancelUserRequest>,
+        labeled_payments_mc: MultiConsumerClient<ResponseLabeledPayments>,
+        submissions_mc: MultiConsumerClient<ResponsePendingSubmissions>,
         done_app_requests_mc: MultiConsumerClient<Uid>,
+        report_client: StateClient<BatchMutable<NodeReport>, Vec<NodeReportMutation>>,
         rng: R,
     ) -> Self {
         AppSendFunds {
             sender,
             send_funds_mc,
+            prewarm_mc,
+            cancel_mc,
+            labeled_payments_mc,
+            submissions_mc,
             done_app_requests_mc,
+            report_client,
             rng,
         }
     }
@@ -63,11 +165,38 @@ where
         invoice_id: InvoiceId,
         dest_payment: u128,
     ) -> Result<Receipt, SendFundsError> {
+        await!(self.request_send_labeled_funds(request_id, route, invoice_id, dest_payment, None))
+    }
+
+    /// Send funds, and tag the payment with a label of at most `MAX_PAYMENT_LABEL_LEN` bytes.
+    /// The label is kept by the node and is never sent to other nodes. Payments may be found
+    /// later by their labels using `labeled_payments()`.
+    pub async fn request_send_labeled_funds(
+        &mut self,
+        request_id: Uid,
+        route: FriendsRoute,
+        invoice_id: InvoiceId,
+        dest_payment: u128,
+        opt_label: Option<Vec<u8>>,
+    ) -> Result<Receipt, SendFundsError> {
+        // Dust payments are rejected before they are sent to the node:
+        let batch_mutable =
+            await!(self.report_client.request_state()).map_err(|_| SendFundsError::LocalError)?;
+        let min_send_payment = batch_mutable
+            .0
+            .funder_report
+            .dust_thresholds
+            .min_send_payment;
+        if dest_payment < min_send_payment {
+            return Err(SendFundsError::BelowMinPayment(min_send_payment));
+        }
+
         let user_request_send_funds = UserRequestSendFunds {
             request_id,
             route,
             invoice_id,
             dest_payment,
+            opt_label,
         };
         let app_request_id = Uid::new(&self.rng);
         let to_app_server = AppToAppServer::new(
@@ -87,8 +216,8 @@ where
             }
             match response_received.result {
                 ResponseSendFundsResult::Success(receipt) => return Ok(receipt),
-                ResponseSendFundsResult::Failure(public_key) => {
-                    return Err(SendFundsError::RemoteError(public_key))
+                ResponseSendFundsResult::Failure((public_key, reason)) => {
+                    return Err(send_funds_error(public_key, reason));
                 }
             }
         }
@@ -97,6 +226,194 @@ where
         Err(SendFundsError::NoResponse)
     }
 
+    /// Send everything we can to `dest_public_key`: The node computes the maximum amount it can
+    /// send along the route, given our capacity to send to the first hop friend and the capacity
+    /// of the route, and pays exactly that amount.
+    /// If no route is given, the destination must be a friend, and the payment is sent directly.
+    /// Returns the amount received by the destination, and the receipt.
+    pub async fn request_sweep_funds(
+        &mut self,
+        request_id: Uid,
+        dest_public_key: PublicKey,
+        opt_route: Option<RouteWithCapacity>,
+        invoice_id: InvoiceId,
+    ) -> Result<(u128, Receipt), SendFundsError> {
+        let batch_mutable =
+            await!(self.report_client.request_state()).map_err(|_| SendFundsError::LocalError)?;
+        let local_public_key = &batch_mutable.0.funder_report.local_public_key;
+
+        let (route, opt_route_capacity) = match opt_route {
+            Some(route_with_capacity) => (
+                route_with_capacity.route,
+                Some(route_with_capacity.capacity),
+            ),
+            None => (
+                FriendsRoute {
+                    public_keys: vec![local_public_key.clone(), dest_public_key.clone()],
+                },
+                None,
+            ),
+        };
+        if route.public_keys.first() != Some(local_public_key)
+            || route.public_keys.last() != Some(&dest_public_key)
+        {
+            return Err(SendFundsError::InvalidRoute);
+        }
+
+        let user_request_sweep_funds = UserRequestSweepFunds {
+            request_id,
+            route,
+            invoice_id,
+            opt_route_capacity,
+        };
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server = AppToAppServer::new(
+            app_request_id,
+            AppRequest::RequestSweepFunds(user_request_sweep_funds),
+        );
+
+        let mut incoming_send_funds =
+            await!(self.send_funds_mc.request_stream()).map_err(|_| SendFundsError::LocalError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| SendFundsError::LocalError)?;
+
+        while let Some(response_received) = await!(incoming_send_funds.next()) {
+            if response_received.request_id != request_id {
+                // This is not our request
+                continue;
+            }
+            match response_received.result {
+                ResponseSendFundsResult::Success(receipt) => {
+                    return Ok((receipt.dest_payment, receipt))
+                }
+                ResponseSendFundsResult::Failure((public_key, reason)) => {
+                    return Err(send_funds_error(public_key, reason));
+                }
+            }
+        }
+
+        Err(SendFundsError::NoResponse)
+    }
+
+    /// Cancel a request to send funds (Issued by this app) that is still waiting to be sent to
+    /// the first hop friend. A cancelled request fails with `SendFundsError::Cancelled`.
+    /// A request that was already sent can not be cancelled, and is answered as usual.
+    pub async fn cancel_send_funds(
+        &mut self,
+        request_id: Uid,
+    ) -> Result<CancelUserRequestResult, CancelSendFundsError> {
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server =
+            AppToAppServer::new(app_request_id, AppRequest::CancelUserRequest(request_id));
+
+        let mut incoming_cancel = await!(self.cancel_mc.request_stream())
+            .map_err(|_| CancelSendFundsError::LocalError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| CancelSendFundsError::LocalError)?;
+
+        while let Some(response_cancel) = await!(incoming_cancel.next()) {
+            if response_cancel.request_id != request_id {
+                // This is not our request
+                continue;
+            }
+            return Ok(response_cancel.result);
+        }
+
+        Err(CancelSendFundsError::NoResponse)
+    }
+
+    /// Find payments we have sent by their labels, oldest first.
+    /// Skips the first `offset` matching payments, and returns at most `max_results` payments.
+    /// Also returns whether more matching payments exist.
+    pub async fn labeled_payments(
+        &mut self,
+        filter: LabelFilter,
+        offset: u64,
+        max_results: u64,
+    ) -> Result<(Vec<LabeledPayment>, bool), LabeledPaymentsError> {
+        let request_id = Uid::new(&self.rng);
+        let request_labeled_payments = RequestLabeledPayments {
+            request_id,
+            filter,
+            offset,
+            max_results,
+        };
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server = AppToAppServer::new(
+            app_request_id,
+            AppRequest::RequestLabeledPayments(request_labeled_payments),
+        );
+
+        let mut incoming_labeled_payments = await!(self.labeled_payments_mc.request_stream())
+            .map_err(|_| LabeledPaymentsError::LocalError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| LabeledPaymentsError::LocalError)?;
+
+        while let Some(response_labeled_payments) = await!(incoming_labeled_payments.next()) {
+            if response_labeled_payments.request_id != request_id {
+                // This is not our request
+                continue;
+            }
+            return Ok((
+                response_labeled_payments.labeled_payments,
+                response_labeled_payments.more,
+            ));
+        }
+
+        Err(LabeledPaymentsError::NoResponse)
+    }
+
+    /// List the payments submitted by this app whose outcomes were not yet acknowledged
+    /// (Using `ack_submission()`), oldest first. Useful after the connection to the node was lost
+    /// while submitting payments.
+    pub async fn pending_submissions(
+        &mut self,
+    ) -> Result<Vec<PaymentSubmission>, PendingSubmissionsError> {
+        let request_id = Uid::new(&self.rng);
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server = AppToAppServer::new(
+            app_request_id,
+            AppRequest::ListPendingSubmissions(request_id),
+        );
+
+        let mut incoming_submissions = await!(self.submissions_mc.request_stream())
+            .map_err(|_| PendingSubmissionsError::LocalError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| PendingSubmissionsError::LocalError)?;
+
+        while let Some(response_pending) = await!(incoming_submissions.next()) {
+            if response_pending.request_id != request_id {
+                // This is not our request
+                continue;
+            }
+            return Ok(response_pending.submissions);
+        }
+
+        Err(PendingSubmissionsError::NoResponse)
+    }
+
+    /// Acknowledge the outcome of a submitted payment, so that the node forgets about it.
+    /// Only submissions that have an outcome can be acknowledged.
+    pub async fn ack_submission(&mut self, request_id: Uid) -> Result<(), AckSubmissionError> {
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server =
+            AppToAppServer::new(app_request_id, AppRequest::AckSubmission(request_id));
+
+        // Start listening to done requests:
+        let mut incoming_done_requests =
+            await!(self.done_app_requests_mc.request_stream()).map_err(|_| AckSubmissionError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| AckSubmissionError)?;
+
+        // Wait for a sign that our request was received:
+        while let Some(done_request_id) = await!(incoming_done_requests.next()) {
+            if app_request_id == done_request_id {
+                return Ok(());
+            }
+        }
+        Err(AckSubmissionError)
+    }
+
     pub async fn receipt_ack(
         &mut self,
         request_id: Uid,
@@ -126,4 +443,55 @@ where
         }
         Err(ReceiptAckError)
     }
+
+    /// Prepare the channel with a friend for an upcoming payment: Make sure that the friend is
+    /// online, and get the token of the channel in advance. No credits are sent.
+    /// Returns once the channel is ready.
+    pub async fn prewarm_friend(
+        &mut self,
+        friend_public_key: PublicKey,
+    ) -> Result<(), PrewarmError> {
+        let request_id = Uid::new(&self.rng);
+        let prewarm_friend = PrewarmFriend {
+            request_id,
+            friend_public_key,
+        };
+        let app_request_id = Uid::new(&self.rng);
+        let to_app_server =
+            AppToAppServer::new(app_request_id, AppRequest::PrewarmFriend(prewarm_friend));
+
+        let mut incoming_prewarm =
+            await!(self.prewarm_mc.request_stream()).map_err(|_| PrewarmError::LocalError)?;
+
+        await!(self.sender.send(to_app_server)).map_err(|_| PrewarmError::LocalError)?;
+
+        while let Some(response_prewarm) = await!(incoming_prewarm.next()) {
+            if response_prewarm.request_id != request_id {
+                // This is not our request
+                continue;
+            }
+            match response_prewarm.result {
+                PrewarmResult::Success => return Ok(()),
+                PrewarmResult::Failure(prewarm_failure) => {
+                    return Err(PrewarmError::Failure(prewarm_failure))
+                }
+            }
+        }
+
+        Err(PrewarmError::NoResponse)
+    }
+
+    /// Pre-warm the channel with the first hop friend of a route.
+    /// See `prewarm_friend()`.
+    pub async fn prewarm_route<'a>(
+        &'a mut self,
+        route: &'a FriendsRoute,
+    ) -> Result<(), PrewarmError> {
+        let friend_public_key = route
+            .public_keys
+            .get(1)
+            .cloned()
+            .ok_or(PrewarmError::InvalidRoute)?;
+        await!(self.prewarm_friend(friend_public_key))
+    }
 }
@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+
+use proto::app_server::messages::{ReportMutations, ReportScope};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReportSequencerError {
+    /// A message with this (scope, seq) was already received.
+    DuplicateSeq((ReportScope, u64)),
+}
+
+/// Orders report mutations messages received from the node.
+///
+/// Messages of different scopes may arrive out of order. A message is released only after all
+/// previous messages of its scope, and all the messages it depends on, were released.
+/// This allows applying changes of one friend without waiting behind a backlog of changes of
+/// another friend, while keeping the mirror consistent.
+pub struct ReportSequencer {
+    /// Sequence number of the next message to release, for every scope
+    next_seqs: HashMap<ReportScope, u64>,
+    /// Messages that can not be released yet
+    pending: Vec<ReportMutations>,
+}
+
+impl ReportSequencer {
+    pub fn new() -> Self {
+        ReportSequencer {
+            next_seqs: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn next_seq(&self, scope: &ReportScope) -> u64 {
+        self.next_seqs.get(scope).cloned().unwrap_or(0)
+    }
+
+    fn is_ready(&self, report_mutations: &ReportMutations) -> bool {
+        report_mutations.seq == self.next_seq(&report_mutations.scope)
+            && report_mutations
+                .dependencies
+                .iter()
+                .all(|(scope, seq)| *seq < self.next_seq(scope))
+    }
+
+    /// Handle a message received from the node.
+    /// Returns all the messages that can be applied now, in the order they should be applied.
+    pub fn push(
+        &mut self,
+        report_mutations: ReportMutations,
+    ) -> Result<Vec<ReportMutations>, ReportSequencerError> {
+        let is_duplicate = report_mutations.seq < self.next_seq(&report_mutations.scope)
+            || self.pending.iter().any(|pending| {
+                pending.scope == report_mutations.scope && pending.seq == report_mutations.seq
+            });
+        if is_duplicate {
+            return Err(ReportSequencerError::DuplicateSeq((
+                report_mutations.scope,
+                report_mutations.seq,
+            )));
+        }
+        self.pending.push(report_mutations);
+
+        let mut ready = Vec::new();
+        while let Some(index) = self
+            .pending
+            .iter()
+            .position(|pending| self.is_ready(pending))
+        {
+            let report_mutations = self.pending.remove(index);
+            self.next_seqs.insert(
+                report_mutations.scope.clone(),
+                report_mutations.seq.wrapping_add(1),
+            );
+            ready.push(report_mutations);
+        }
+        Ok(ready)
+    }
+
+    /// Amount of messages waiting for earlier messages
+    pub fn num_pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+    use crypto::uid::{Uid, UID_LEN};
+
+    use proto::app_server::messages::NodeReportMutation;
+    use proto::report::messages::FunderReportMutation;
+
+    fn friend_scope(index: u8) -> ReportScope {
+        ReportScope::Friend(PublicKey::from(&[index; PUBLIC_KEY_LEN]))
+    }
+
+    fn report_mutations(
+        scope: ReportScope,
+        seq: u64,
+        dependencies: Vec<(ReportScope, u64)>,
+    ) -> ReportMutations {
+        ReportMutations {
+            opt_app_request_id: None,
+            scope,
+            seq,
+            dependencies,
+            mutations: vec![NodeReportMutation::Funder(
+                FunderReportMutation::SetNumReadyReceipts(seq),
+            )],
+        }
+    }
+
+    fn scope_seqs(ready: Vec<ReportMutations>) -> Vec<(ReportScope, u64)> {
+        ready
+            .into_iter()
+            .map(|report_mutations| (report_mutations.scope, report_mutations.seq))
+            .collect()
+    }
+
+    #[test]
+    fn test_report_sequencer_scopes_independent() {
+        let mut sequencer = ReportSequencer::new();
+
+        // A backlog of friend 0 was only partially received,
+        // but the change of friend 1 is released immediately:
+        for seq in 0..4 {
+            let ready = sequencer
+                .push(report_mutations(friend_scope(0), seq, Vec::new()))
+                .unwrap();
+            assert_eq!(scope_seqs(ready), vec![(friend_scope(0), seq)]);
+        }
+        let ready = sequencer
+            .push(report_mutations(friend_scope(1), 0, Vec::new()))
+            .unwrap();
+        assert_eq!(scope_seqs(ready), vec![(friend_scope(1), 0)]);
+
+        // Inside a scope, messages are released in order:
+        let ready = sequencer
+            .push(report_mutations(friend_scope(0), 5, Vec::new()))
+            .unwrap();
+        assert!(ready.is_empty());
+        let ready = sequencer
+            .push(report_mutations(friend_scope(0), 4, Vec::new()))
+            .unwrap();
+        assert_eq!(
+            scope_seqs(ready),
+            vec![(friend_scope(0), 4), (friend_scope(0), 5)]
+        );
+        assert_eq!(sequencer.num_pending(), 0);
+
+        assert_eq!(
+            sequencer.push(report_mutations(friend_scope(1), 0, Vec::new())),
+            Err(ReportSequencerError::DuplicateSeq((friend_scope(1), 0)))
+        );
+    }
+
+    #[test]
+    fn test_report_sequencer_dependencies() {
+        let mut sequencer = ReportSequencer::new();
+
+        // A node scope message (Carrying an app request id) that depends on changes of two
+        // friends arrives first:
+        let mut node_report_mutations = report_mutations(
+            ReportScope::Node,
+            0,
+            vec![(friend_scope(0), 1), (friend_scope(1), 0)],
+        );
+        node_report_mutations.opt_app_request_id = Some(Uid::from(&[1; UID_LEN]));
+        assert!(sequencer.push(node_report_mutations).unwrap().is_empty());
+
+        let ready = sequencer
+            .push(report_mutations(friend_scope(0), 0, Vec::new()))
+            .unwrap();
+        assert_eq!(scope_seqs(ready), vec![(friend_scope(0), 0)]);
+
+        let ready = sequencer
+            .push(report_mutations(friend_scope(1), 0, Vec::new()))
+            .unwrap();
+        assert_eq!(scope_seqs(ready), vec![(friend_scope(1), 0)]);
+
+        // The last dependency releases the node scope message:
+        let ready = sequencer
+            .push(report_mutations(friend_scope(0), 1, Vec::new()))
+            .unwrap();
+        assert_eq!(
+            scope_seqs(ready),
+            vec![(friend_scope(0), 1), (ReportScope::Node, 0)]
+        );
+        assert_eq!(sequencer.num_pending(), 0);
+    }
+}
@@ -0,0 +1,303 @@
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, stream, SinkExt, StreamExt};
+
+use common::conn::{ConnPairVec, FutTransform};
+use common::select_streams::{select_streams, BoxStream};
+
+use crypto::crypto_rand::CryptoRandom;
+use crypto::uid::Uid;
+
+use proto::app_server::messages::RelayAddress;
+use proto::directory::messages::{DirectoryDocument, DirectoryListing, DirectoryState};
+use proto::directory::serialize::deserialize_directory_document;
+use proto::directory::signature_buff::verify_directory_document;
+use proto::funder::messages::{FunderControl, FunderIncomingControl};
+use proto::index_client::messages::{AppServerToIndexClient, IndexClientRequest};
+use proto::net::messages::NetAddress;
+
+use timer::TimerClient;
+
+#[derive(Debug)]
+pub enum DirectoryError {
+    RequestTimerStreamError,
+    SpawnError,
+}
+
+enum DirectoryEvent {
+    /// The directory state of the funder has changed.
+    Funder(DirectoryState<NetAddress>),
+    FunderClosed,
+    TimerTick,
+    /// A fetch of the directory document is done. (fetch_id, opt_data)
+    Fetched((u64, Option<Vec<u8>>)),
+}
+
+struct Directory<C, R, S> {
+    directory: DirectoryState<NetAddress>,
+    /// Amount of ticks left until the next fetch
+    ticks_to_fetch: usize,
+    fetch_ticks: usize,
+    /// Identifies the current fetch. Results of older fetches are ignored.
+    fetch_id: u64,
+    connector: C,
+    to_funder: mpsc::Sender<FunderIncomingControl<NetAddress>>,
+    to_index_client: mpsc::Sender<AppServerToIndexClient<NetAddress>>,
+    event_sender: mpsc::Sender<DirectoryEvent>,
+    rng: R,
+    spawner: S,
+}
+
+/// Obtain the first message sent by the directory server: The current directory document.
+async fn fetch_document<C>(mut connector: C, relay_address: RelayAddress) -> Option<Vec<u8>>
+where
+    C: FutTransform<Input = RelayAddress, Output = Option<ConnPairVec>>,
+{
+    let (_sender, mut receiver) = await!(connector.transform(relay_address))?;
+    await!(receiver.next())
+}
+
+impl<C, R, S> Directory<C, R, S>
+where
+    C: FutTransform<Input = RelayAddress, Output = Option<ConnPairVec>> + Clone + Send + 'static,
+    R: CryptoRandom,
+    S: Spawn,
+{
+    fn handle_funder(&mut self, directory: DirectoryState<NetAddress>) {
+        if directory.opt_subscription != self.directory.opt_subscription {
+            // Ignore the results of fetches from the old directory,
+            // and fetch from the new directory at the next tick:
+            self.fetch_id = self.fetch_id.wrapping_add(1);
+            self.ticks_to_fetch = 0;
+        }
+        self.directory = directory;
+    }
+
+    fn handle_timer_tick(&mut self) -> Result<(), DirectoryError> {
+        let subscription = match &self.directory.opt_subscription {
+            Some(subscription) => subscription,
+            None => return Ok(()),
+        };
+
+        self.ticks_to_fetch = self.ticks_to_fetch.saturating_sub(1);
+        if self.ticks_to_fetch > 0 {
+            return Ok(());
+        }
+        self.ticks_to_fetch = self.fetch_ticks;
+
+        // A fetch that did not complete until now is ignored:
+        self.fetch_id = self.fetch_id.wrapping_add(1);
+
+        // Directory servers are reached like relays:
+        // An encrypted connection with a server of a known public key.
+        let relay_address = RelayAddress {
+            public_key: subscription.public_key.clone(),
+            address: subscription.address.clone(),
+        };
+        let fetch_id = self.fetch_id;
+        let c_connector = self.connector.clone();
+        let mut c_event_sender = self.event_sender.clone();
+        let fetch_fut = async move {
+            let opt_data = await!(fetch_document(c_connector, relay_address));
+            let _ = await!(c_event_sender.send(DirectoryEvent::Fetched((fetch_id, opt_data))));
+        };
+        self.spawner
+            .spawn(fetch_fut)
+            .map_err(|_| DirectoryError::SpawnError)
+    }
+
+    fn handle_fetched(
+        &mut self,
+        fetch_id: u64,
+        opt_data: Option<Vec<u8>>,
+    ) -> Result<(), DirectoryError> {
+        if fetch_id != self.fetch_id {
+            return Ok(());
+        }
+
+        let signing_public_key = match &self.directory.opt_subscription {
+            Some(subscription) => subscription.signing_public_key.clone(),
+            None => return Ok(()),
+        };
+
+        let data = match opt_data {
+            Some(data) => data,
+            None => {
+                warn!("Directory: Failed fetching the directory document");
+                return Ok(());
+            }
+        };
+
+        let document = match deserialize_directory_document(&data) {
+            Ok(document) => document,
+            Err(e) => {
+                warn!("Directory: Invalid directory document: {:?}", e);
+                return Ok(());
+            }
+        };
+
+        if !verify_directory_document(&document, &signing_public_key) {
+            warn!("Directory: Invalid signature for directory document");
+            return Ok(());
+        }
+
+        if document.version <= self.directory.listing.version {
+            // We never go back to an older document:
+            if document.version < self.directory.listing.version {
+                warn!(
+                    "Directory: Ignoring directory document with old version {}",
+                    document.version
+                );
+            }
+            return Ok(());
+        }
+
+        self.apply_document(document)
+    }
+
+    /// Add the relays and index servers listed in the document, and remove those that were
+    /// added by an earlier document but are no longer listed. Pinned entries are never removed.
+    /// Changes are made through the same requests used by the node's apps.
+    fn apply_document(
+        &mut self,
+        document: DirectoryDocument<NetAddress>,
+    ) -> Result<(), DirectoryError> {
+        let old_listing = &self.directory.listing;
+        let pinned = &self.directory.pinned;
+
+        let new_listing = DirectoryListing {
+            version: document.version,
+            relays: document
+                .relays
+                .iter()
+                .map(|named_relay_address| named_relay_address.public_key.clone())
+                .collect(),
+            index_servers: document
+                .index_servers
+                .iter()
+                .map(|named_index_server_address| named_index_server_address.public_key.clone())
+                .collect(),
+        };
+
+        let mut funder_controls = Vec::new();
+        for named_relay_address in document.relays {
+            if !old_listing.relays.contains(&named_relay_address.public_key) {
+                funder_controls.push(FunderControl::AddRelay(named_relay_address));
+            }
+        }
+        for public_key in &old_listing.relays {
+            if !new_listing.relays.contains(public_key) && !pinned.contains(public_key) {
+                funder_controls.push(FunderControl::RemoveRelay(public_key.clone()));
+            }
+        }
+        funder_controls.push(FunderControl::SetDirectoryListing(new_listing.clone()));
+
+        let mut index_client_requests = Vec::new();
+        for named_index_server_address in document.index_servers {
+            if !old_listing
+                .index_servers
+                .contains(&named_index_server_address.public_key)
+            {
+                let request = IndexClientRequest::AddIndexServer(named_index_server_address);
+                index_client_requests.push(request);
+            }
+        }
+        for public_key in &old_listing.index_servers {
+            if !new_listing.index_servers.contains(public_key) && !pinned.contains(public_key) {
+                let request = IndexClientRequest::RemoveIndexServer(public_key.clone());
+                index_client_requests.push(request);
+            }
+        }
+
+        let funder_messages = funder_controls
+            .into_iter()
+            .map(|funder_control| FunderIncomingControl::new(Uid::new(&self.rng), funder_control))
+            .collect::<Vec<_>>();
+        let index_client_messages = index_client_requests
+            .into_iter()
+            .map(|request| AppServerToIndexClient::AppRequest((Uid::new(&self.rng), request)))
+            .collect::<Vec<_>>();
+
+        self.directory.listing = new_listing;
+
+        // Sending is done outside of the main loop, so that the directory never blocks the
+        // funder, which might be waiting to send us a report:
+        let mut c_to_funder = self.to_funder.clone();
+        let mut c_to_index_client = self.to_index_client.clone();
+        let send_fut = async move {
+            for funder_message in funder_messages {
+                if await!(c_to_funder.send(funder_message)).is_err() {
+                    return;
+                }
+            }
+            for index_client_message in index_client_messages {
+                if await!(c_to_index_client.send(index_client_message)).is_err() {
+                    return;
+                }
+            }
+        };
+        self.spawner
+            .spawn(send_fut)
+            .map_err(|_| DirectoryError::SpawnError)
+    }
+}
+
+/// Periodically fetch the signed document published by the directory the node is subscribed
+/// to, and apply it to the relays and index servers of the node.
+///
+/// A document is only applied if its signature is valid and its version is newer than the
+/// version of the last applied document. If the directory can not be reached, the node keeps
+/// its current relays and index servers.
+pub async fn directory_loop<C, R, S>(
+    directory: DirectoryState<NetAddress>,
+    from_funder: mpsc::Receiver<DirectoryState<NetAddress>>,
+    to_funder: mpsc::Sender<FunderIncomingControl<NetAddress>>,
+    to_index_client: mpsc::Sender<AppServerToIndexClient<NetAddress>>,
+    mut timer_client: TimerClient,
+    connector: C,
+    fetch_ticks: usize,
+    rng: R,
+    spawner: S,
+) -> Result<(), DirectoryError>
+where
+    C: FutTransform<Input = RelayAddress, Output = Option<ConnPairVec>> + Clone + Send + 'static,
+    R: CryptoRandom,
+    S: Spawn,
+{
+    let timer_stream = await!(timer_client.request_timer_stream())
+        .map_err(|_| DirectoryError::RequestTimerStreamError)?;
+
+    let (event_sender, event_receiver) = mpsc::channel(0);
+
+    let mut directory = Directory {
+        directory,
+        ticks_to_fetch: 0,
+        fetch_ticks,
+        fetch_id: 0,
+        connector,
+        to_funder,
+        to_index_client,
+        event_sender,
+        rng,
+        spawner,
+    };
+
+    let from_funder = from_funder
+        .map(DirectoryEvent::Funder)
+        .chain(stream::once(future::ready(DirectoryEvent::FunderClosed)));
+    let timer_stream = timer_stream.map(|_| DirectoryEvent::TimerTick);
+
+    let mut incoming_events = select_streams![from_funder, timer_stream, event_receiver];
+
+    while let Some(event) = await!(incoming_events.next()) {
+        match event {
+            DirectoryEvent::Funder(directory_state) => directory.handle_funder(directory_state),
+            DirectoryEvent::FunderClosed => break,
+            DirectoryEvent::TimerTick => directory.handle_timer_tick()?,
+            DirectoryEvent::Fetched((fetch_id, opt_data)) => {
+                directory.handle_fetched(fetch_id, opt_data)?
+            }
+        }
+    }
+    Ok(())
+}
@@ -15,15 +15,21 @@
 #[macro_use]
 extern crate log;
 
+#[macro_use]
+extern crate common;
+
 #[macro_use]
 extern crate serde_derive;
 
 mod adapters;
 pub mod connect;
+mod directory;
 mod net_node;
 mod node;
+pub mod notifier;
+mod self_test;
 mod types;
 
 pub use self::net_node::{net_node, NetNodeError};
 pub use self::types::{NodeConfig, NodeState};
-pub use app_server::IncomingAppConnection;
+pub use app_server::{AppSession, IncomingAppConnection};
@@ -11,18 +11,20 @@ use common::transform_pool::transform_pool_loop;
 use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::PublicKey;
 
-use proto::app_server::messages::AppPermissions;
+use proto::app_server::chunk::app_server_to_app_frames;
+use proto::app_server::messages::{AppPermissions, AppServerToAppFrame, AppToAppServerFrame};
 use proto::app_server::serialize::{
-    deserialize_app_to_app_server, serialize_app_permissions, serialize_app_server_to_app,
+    deserialize_app_hello, deserialize_app_to_app_server_frame, serialize_app_permissions,
+    serialize_app_server_to_app_frame,
 };
 use proto::consts::{KEEPALIVE_TICKS, PROTOCOL_VERSION, TICKS_TO_REKEY};
 use proto::net::messages::NetAddress;
 
-use database::{database_loop, AtomicDb, DatabaseClient};
+use database::{compact_loop, database_loop, AtomicDb, CompactClient, DatabaseClient};
 use identity::IdentityClient;
 use timer::TimerClient;
 
-use app_server::IncomingAppConnection;
+use app_server::{AppSession, IncomingAppConnection};
 use keepalive::KeepAliveChannel;
 use secure_channel::SecureChannel;
 use version::VersionPrefix;
@@ -34,6 +36,7 @@ use crate::types::{NodeConfig, NodeMutation, NodeState};
 pub enum NetNodeError {
     CreateThreadPoolError,
     RequestPublicKeyError,
+    RequestTimerStreamError,
     SpawnError,
     DatabaseIdentityMismatch,
     NodeError(NodeError),
@@ -117,16 +120,32 @@ where
                 // Tell app about its permissions: (TODO: Is this required?)
                 await!(sender.send(serialize_app_permissions(&app_permissions))).ok()?;
 
+                // The app tells us which of its sessions this connection belongs to:
+                let app_hello = deserialize_app_hello(&await!(receiver.next())?).ok()?;
+                let app_session = AppSession {
+                    app_public_key: public_key,
+                    session_id: app_hello.session_id,
+                };
+
                 // serialization:
                 let (user_sender, mut from_user_sender) = mpsc::channel(0);
                 let (mut to_user_receiver, user_receiver) = mpsc::channel(0);
 
+                // Transfers the app asked to abort.
+                // An abort that does not fit is dropped. It is only an optimization: The app
+                // closes the connection after aborting a transfer.
+                let (mut abort_sender, mut abort_receiver) = mpsc::channel(0);
+
                 // Deserialize received data
                 let _ = self.spawner.spawn(
                     async move {
                         while let Some(data) = await!(receiver.next()) {
-                            let message = match deserialize_app_to_app_server(&data) {
-                                Ok(message) => message,
+                            let message = match deserialize_app_to_app_server_frame(&data) {
+                                Ok(AppToAppServerFrame::Message(message)) => message,
+                                Ok(AppToAppServerFrame::AbortTransfer(transfer_id)) => {
+                                    let _ = abort_sender.try_send(transfer_id);
+                                    continue;
+                                }
                                 Err(_) => return,
                             };
                             if await!(to_user_receiver.send(message)).is_err() {
@@ -136,26 +155,101 @@ where
                     },
                 );
 
-                // Serialize sent data:
+                // Serialize sent data. Large messages are sent in chunks:
                 let _ = self.spawner.spawn(
                     async move {
+                        let mut transfer_id: u64 = 0;
                         while let Some(message) = await!(from_user_sender.next()) {
-                            let data = serialize_app_server_to_app(&message);
-                            if await!(sender.send(data)).is_err() {
-                                return;
+                            // Aborts of previous transfers are not relevant anymore:
+                            while let Ok(Some(_)) = abort_receiver.try_next() {}
+
+                            for data in app_server_to_app_frames(&message, transfer_id) {
+                                if let Ok(Some(aborted_id)) = abort_receiver.try_next() {
+                                    if aborted_id == transfer_id {
+                                        let abort_frame =
+                                            AppServerToAppFrame::AbortTransfer(transfer_id);
+                                        let data = serialize_app_server_to_app_frame(&abort_frame);
+                                        if await!(sender.send(data)).is_err() {
+                                            return;
+                                        }
+                                        break;
+                                    }
+                                }
+                                if await!(sender.send(data)).is_err() {
+                                    return;
+                                }
                             }
+                            transfer_id = transfer_id.wrapping_add(1);
                         }
                     },
                 );
 
-                Some((app_permissions.clone(), (user_sender, user_receiver)))
+                Some((
+                    app_session,
+                    app_permissions.clone(),
+                    (user_sender, user_receiver),
+                ))
             },
         )
     }
 }
 
-pub async fn net_node<IAC, C, R, GT, AD, DS, TS, S>(
+/// Reload the trusted apps every `reload_ticks` ticks.
+/// Apps that are no longer trusted, or whose permissions have changed, are sent over
+/// `app_revocations_sender`. The app server then closes all of their sessions.
+async fn trusted_apps_loop<GT, TT, TS>(
+    get_trusted_apps: GT,
+    mut timer_stream: TT,
+    reload_ticks: usize,
+    mut app_revocations_sender: mpsc::Sender<PublicKey>,
+    mut trusted_apps_spawner: TS,
+) -> Result<(), NetNodeError>
+where
+    GT: Fn() -> Option<HashMap<PublicKey, AppPermissions>> + Clone + Send + 'static,
+    TT: Stream + Unpin,
+    TS: Spawn,
+{
+    let mut opt_trusted_apps: Option<HashMap<PublicKey, AppPermissions>> = None;
+    let mut ticks_to_reload = 0;
+    loop {
+        if ticks_to_reload == 0 {
+            ticks_to_reload = reload_ticks;
+
+            let c_get_trusted_apps = get_trusted_apps.clone();
+            let trusted_apps_fut = trusted_apps_spawner
+                .spawn_with_handle(future::lazy(move |_| (c_get_trusted_apps)()))
+                .map_err(|_| NetNodeError::SpawnError)?;
+
+            // If the trusted apps could not be read, we keep the previous ones and try again
+            // later:
+            if let Some(trusted_apps) = await!(trusted_apps_fut) {
+                if let Some(prev_trusted_apps) = &opt_trusted_apps {
+                    for (public_key, app_permissions) in prev_trusted_apps {
+                        if trusted_apps.get(public_key) == Some(app_permissions) {
+                            continue;
+                        }
+                        if await!(app_revocations_sender.send(public_key.clone())).is_err() {
+                            return Ok(());
+                        }
+                    }
+                }
+                opt_trusted_apps = Some(trusted_apps);
+            }
+        }
+
+        if await!(timer_stream.next()).is_none() {
+            return Ok(());
+        }
+        ticks_to_reload = ticks_to_reload.saturating_sub(1);
+    }
+}
+
+/// `incoming_direct_raw_conns` are connections from remote nodes that connect to this node
+/// directly, without going through a relay. Use an empty stream if this node does not listen for
+/// direct connections.
+pub async fn net_node<IAC, IDC, C, R, GT, AD, DS, TS, S>(
     incoming_app_raw_conns: IAC,
+    incoming_direct_raw_conns: IDC,
     net_connector: C,
     timer_client: TimerClient,
     identity_client: IdentityClient,
@@ -169,6 +263,7 @@ pub async fn net_node<IAC, C, R, GT, AD, DS, TS, S>(
 ) -> Result<(), NetNodeError>
 where
     IAC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
+    IDC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
     C: FutTransform<Input = NetAddress, Output = Option<ConnPairVec>>
         + Clone
         + Send
@@ -184,6 +279,9 @@ where
     TS: Spawn + Clone + Send + Sync + 'static,
     S: Spawn + Clone + Send + Sync + 'static,
 {
+    // Notifications about incoming payments are sent directly over the net connector:
+    let notify_connector = net_connector.clone();
+
     // Wrap net connector with a version prefix:
     let version_transform = VersionPrefix::new(PROTOCOL_VERSION, spawner.clone());
     let c_version_transform = version_transform.clone();
@@ -198,6 +296,11 @@ where
         )
     });
 
+    // Version prefix for incoming direct connections:
+    let mut c_version_transform = version_transform.clone();
+    let incoming_direct_conns = incoming_direct_raw_conns
+        .map(move |conn_pair| c_version_transform.spawn_prefix(conn_pair));
+
     let local_public_key = await!(identity_client.request_public_key())
         .map_err(|_| NetNodeError::RequestPublicKeyError)?;
 
@@ -212,9 +315,15 @@ where
 
     // Spawn database service:
     let (db_request_sender, incoming_db_requests) = mpsc::channel(0);
-    let loop_fut = database_loop(atomic_db, incoming_db_requests, database_spawner)
-        .map_err(|e| error!("database_loop() error: {:?}", e))
-        .map(|_| ());
+    let (compact_request_sender, incoming_compact_requests) = mpsc::channel(0);
+    let loop_fut = database_loop(
+        atomic_db,
+        incoming_db_requests,
+        incoming_compact_requests,
+        database_spawner,
+    )
+    .map_err(|e| error!("database_loop() error: {:?}", e))
+    .map(|_| ());
     spawner
         .spawn(loop_fut)
         .map_err(|_| NetNodeError::SpawnError)?;
@@ -222,17 +331,53 @@ where
     // Obtain a client to the database service:
     let database_client = DatabaseClient::new(db_request_sender);
 
+    // Periodically compact the database and verify its integrity:
+    let mut c_timer_client = timer_client.clone();
+    let compact_timer_stream = await!(c_timer_client.request_timer_stream())
+        .map_err(|_| NetNodeError::RequestTimerStreamError)?;
+    let compact_fut = compact_loop(
+        CompactClient::new(compact_request_sender),
+        compact_timer_stream,
+        node_config.database_compact_ticks,
+    )
+    .map_err(|e| error!("compact_loop() error: {:?}", e))
+    .map(|_| ());
+    spawner
+        .spawn(compact_fut)
+        .map_err(|_| NetNodeError::SpawnError)?;
+
     let encrypt_transform = SecureChannel::new(
         identity_client.clone(),
         rng.clone(),
         timer_client.clone(),
         TICKS_TO_REKEY,
+        None, // max_messages_before_rekey
+        0,    // incoming_queue_len
+        None, // opt_resume_ticks
         spawner.clone(),
     );
 
     let keepalive_transform =
         KeepAliveChannel::new(timer_client.clone(), KEEPALIVE_TICKS, spawner.clone());
 
+    // Periodically reload the trusted apps, and disconnect apps that are no longer trusted:
+    let trusted_apps_timer_stream = await!(c_timer_client.request_timer_stream())
+        .map_err(|_| NetNodeError::RequestTimerStreamError)?;
+    let (app_revocations_sender, app_revocations) = mpsc::channel(0);
+    let trusted_apps_fut = trusted_apps_loop(
+        get_trusted_apps.clone(),
+        trusted_apps_timer_stream,
+        node_config.trusted_apps_reload_ticks,
+        app_revocations_sender,
+        trusted_apps_spawner.clone(),
+    )
+    .map_err(|e| error!("trusted_apps_loop() error: {:?}", e))
+    .map(|_| ());
+    // Dropped when this async function ends:
+    let _trusted_apps_handle = spawner
+        .spawn_with_handle(trusted_apps_fut)
+        .map_err(|_| NetNodeError::SpawnError)?;
+
     let app_conn_transform = AppConnTransform::new(
         version_transform,
         encrypt_transform,
@@ -268,7 +413,10 @@ where
         node_state,
         database_client,
         version_connector,
+        notify_connector,
         incoming_apps,
+        app_revocations,
+        incoming_direct_conns,
         rng,
         spawner.clone()
     ))
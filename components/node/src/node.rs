@@ -8,7 +8,7 @@ use common::conn::{ConnPairVec, FutTransform};
 use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::PublicKey;
 
-use database::DatabaseClient;
+use database::{DatabaseClient, DatabaseClientError};
 use identity::IdentityClient;
 use timer::TimerClient;
 
@@ -17,13 +17,14 @@ use channeler::{spawn_channeler, ChannelerError};
 use funder::types::{
     ChannelerConfig, FunderIncomingComm, FunderOutgoingComm, IncomingLivenessMessage,
 };
-use funder::{funder_loop, FunderError, FunderState};
+use funder::{funder_loop, EphemeralLimits, FunderError, FunderEventStats, FunderState};
 use keepalive::KeepAliveChannel;
-use secure_channel::SecureChannel;
+use secure_channel::{SecureChannel, SecureChannelMigrate};
 
 use index_client::{spawn_index_client, IndexClientError};
 
 use proto::app_server::messages::RelayAddress;
+use proto::directory::messages::DirectoryState;
 use proto::funder::messages::{
     ChannelerToFunder, FunderIncomingControl, FunderOutgoingControl, FunderToChanneler,
 };
@@ -31,8 +32,12 @@ use proto::funder::serialize::{deserialize_friend_message, serialize_friend_mess
 use proto::index_client::messages::{AppServerToIndexClient, IndexClientToAppServer};
 use proto::net::messages::NetAddress;
 use proto::report::convert::funder_report_to_index_client_state;
+use proto::report::messages::FunderReportMutation;
 
 use crate::adapters::{EncKeepaliveConnector, EncRelayConnector};
+use crate::directory::{directory_loop, DirectoryError};
+use crate::notifier::{notifier_loop, FunderToNotifier, NotifierError};
+use crate::self_test::SelfTester;
 use crate::types::{create_node_report, NodeConfig, NodeMutation, NodeState};
 
 #[derive(Debug, From)]
@@ -43,14 +48,17 @@ pub enum NodeError {
     FunderError(FunderError),
     IndexClientError(IndexClientError),
     AppServerError(AppServerError),
+    NotifierError(NotifierError),
+    DirectoryError(DirectoryError),
 }
 
-fn node_spawn_channeler<C, R, S>(
+pub(crate) fn node_spawn_channeler<C, IDC, R, S>(
     node_config: &NodeConfig,
     local_public_key: PublicKey,
     identity_client: IdentityClient,
     timer_client: TimerClient,
     version_connector: C,
+    incoming_direct_conns: IDC,
     rng: R,
     from_funder: mpsc::Receiver<FunderToChanneler<RelayAddress>>,
     to_funder: mpsc::Sender<ChannelerToFunder>,
@@ -62,6 +70,7 @@ where
         + Send
         + Sync
         + 'static,
+    IDC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
     R: CryptoRandom + Clone + 'static,
     S: Spawn + Clone + Send + Sync + 'static,
 {
@@ -70,16 +79,24 @@ where
         rng.clone(),
         timer_client.clone(),
         node_config.ticks_to_rekey,
+        node_config.max_messages_before_rekey,
+        node_config.friend_incoming_queue_len,
+        Some(node_config.session_resume_ticks),
         spawner.clone(),
     );
 
+    // Friend channels are migrated (Instead of reconnected) when the relay they go through is
+    // removed:
+    let migrate_transform = SecureChannelMigrate::new(encrypt_transform.clone());
+
     let keepalive_transform = KeepAliveChannel::new(
         timer_client.clone(),
         node_config.keepalive_ticks,
         spawner.clone(),
     );
 
-    let enc_relay_connector = EncRelayConnector::new(encrypt_transform.clone(), version_connector);
+    let enc_relay_connector =
+        EncRelayConnector::new(encrypt_transform.clone(), version_connector.clone());
 
     spawner
         .spawn_with_handle(spawn_channeler(
@@ -88,8 +105,12 @@ where
             node_config.backoff_ticks,
             node_config.conn_timeout_ticks,
             node_config.max_concurrent_encrypt,
+            node_config.relay_mux,
             enc_relay_connector,
+            version_connector,
+            incoming_direct_conns,
             encrypt_transform,
+            migrate_transform,
             keepalive_transform,
             from_funder,
             to_funder,
@@ -98,9 +119,10 @@ where
         .map_err(|_| NodeError::SpawnError)
 }
 
-fn node_spawn_funder<R, S>(
+pub(crate) fn node_spawn_funder<R, S>(
     node_config: &NodeConfig,
     identity_client: IdentityClient,
+    timer_client: TimerClient,
     funder_state: FunderState<NetAddress>,
     mut database_client: DatabaseClient<NodeMutation<NetAddress>>,
     mut from_channeler: mpsc::Receiver<ChannelerToFunder>,
@@ -126,11 +148,16 @@ where
                 .map(NodeMutation::Funder)
                 .collect::<Vec<_>>();
 
-            if let Err(e) = await!(database_client.mutate(mutations)) {
-                error!("error in funder database adapter: {:?}", e);
-                return;
-            }
-            if let Err(e) = request.response_sender.send(()) {
+            let opt_done = match await!(database_client.mutate(mutations)) {
+                Ok(()) => Some(()),
+                // Nothing was applied. Let the funder decide how to proceed:
+                Err(DatabaseClientError::StorageUnavailable) => None,
+                Err(e) => {
+                    error!("error in funder database adapter: {:?}", e);
+                    return;
+                }
+            };
+            if let Err(e) = request.response_sender.send(opt_done) {
                 error!("error in funder database adapter: {:?}", e);
                 return;
             }
@@ -188,6 +215,9 @@ where
                     ChannelerConfig::RemoveFriend(friend_public_key) => {
                         FunderToChanneler::RemoveFriend(friend_public_key)
                     }
+                    ChannelerConfig::SuspendFriend(friend_public_key) => {
+                        FunderToChanneler::SuspendFriend(friend_public_key)
+                    }
                 },
                 FunderOutgoingComm::FriendMessage((public_key, friend_message)) => {
                     let data = serialize_friend_message(&friend_message);
@@ -207,13 +237,25 @@ where
     let funder_fut = funder_loop(
         identity_client.clone(),
         rng.clone(),
+        timer_client,
         from_app_server,
         incoming_comm,
         to_app_server,
         outgoing_comm_sender,
-        node_config.max_node_relays,
+        node_config.min_operations_in_batch,
         node_config.max_operations_in_batch,
+        node_config.max_node_relays,
         node_config.max_pending_user_requests,
+        node_config.friend_relays_damping_ticks,
+        node_config.friend_prewarm_ticks,
+        node_config.reliability_decay_ticks,
+        node_config.deadlines_granularity_ticks,
+        EphemeralLimits {
+            completed: node_config.completed_cache_limits,
+            payment_timings: node_config.payment_timings_cache_limits,
+        },
+        node_config.slow_funder_event_limits.clone(),
+        FunderEventStats::default(),
         funder_state,
         funder_db_client,
     );
@@ -259,11 +301,16 @@ where
                 .map(NodeMutation::IndexClient)
                 .collect::<Vec<_>>();
 
-            if let Err(e) = await!(database_client.mutate(mutations)) {
-                error!("error in index_client database adapter: {:?}", e);
-                return;
-            }
-            if let Err(e) = request.response_sender.send(()) {
+            let opt_done = match await!(database_client.mutate(mutations)) {
+                Ok(()) => Some(()),
+                // Nothing was applied. Let the index_client decide how to proceed:
+                Err(DatabaseClientError::StorageUnavailable) => None,
+                Err(e) => {
+                    error!("error in index_client database adapter: {:?}", e);
+                    return;
+                }
+            };
+            if let Err(e) = request.response_sender.send(opt_done) {
                 error!("error in index_client database adapter: {:?}", e);
                 return;
             }
@@ -281,6 +328,9 @@ where
         rng.clone(),
         timer_client.clone(),
         node_config.ticks_to_rekey,
+        None, // max_messages_before_rekey
+        0,    // incoming_queue_len
+        None, // opt_resume_ticks
         spawner.clone(),
     );
 
@@ -309,6 +359,8 @@ where
         node_config.max_open_index_client_requests,
         node_config.keepalive_ticks,
         node_config.backoff_ticks,
+        node_config.index_reconcile_ticks,
+        node_config.index_route_cache_limits,
         enc_keepalive_connector,
         rng,
         spawner.clone()
@@ -316,14 +368,152 @@ where
     .map_err(|_| NodeError::SpawnError)
 }
 
-pub async fn node<C, IA, R, S>(
+fn node_spawn_notifier<C, R, S>(
+    node_config: &NodeConfig,
+    timer_client: TimerClient,
+    node_state: &NodeState<NetAddress>,
+    mut from_funder: mpsc::Receiver<FunderOutgoingControl<NetAddress>>,
+    mut to_app_server: mpsc::Sender<FunderOutgoingControl<NetAddress>>,
+    mut to_directory: mpsc::Sender<DirectoryState<NetAddress>>,
+    to_funder: mpsc::Sender<FunderIncomingControl<NetAddress>>,
+    notify_connector: C,
+    rng: R,
+    mut spawner: S,
+) -> Result<impl Future<Output = Result<(), NotifierError>>, NodeError>
+where
+    C: FutTransform<Input = NetAddress, Output = Option<ConnPairVec>>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    R: CryptoRandom + Clone + 'static,
+    S: Spawn + Clone + Send + Sync + 'static,
+{
+    let (mut to_notifier, from_funder_notifier) = mpsc::channel(node_config.channel_len);
+
+    // Funder to AppServer adapter.
+    // Incoming payment notifications are also sent to the notifier. Depending on the configured
+    // consumer, they are delivered either by the notifier or by the app server.
+    // Changes to the directory state are also sent to the directory:
+    let funder_to_app_server_adapter = async move {
+        while let Some(funder_message) = await!(from_funder.next()) {
+            let opt_to_notifier = match &funder_message {
+                FunderOutgoingControl::IncomingPayment(incoming_payment) => {
+                    Some(FunderToNotifier::IncomingPayment(incoming_payment.clone()))
+                }
+                FunderOutgoingControl::PaymentNotifierChanged(opt_payment_notifier) => {
+                    Some(FunderToNotifier::PaymentNotifierChanged(opt_payment_notifier.clone()))
+                }
+                _ => None,
+            };
+            if let Some(to_notifier_message) = opt_to_notifier {
+                if await!(to_notifier.send(to_notifier_message)).is_err() {
+                    return;
+                }
+            }
+            if let FunderOutgoingControl::ReportMutations(report_mutations) = &funder_message {
+                for mutation in &report_mutations.mutations {
+                    if let FunderReportMutation::SetDirectory(directory) = mutation {
+                        if await!(to_directory.send(directory.clone())).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+            if await!(to_app_server.send(funder_message)).is_err() {
+                return;
+            }
+        }
+    };
+
+    spawner
+        .spawn(funder_to_app_server_adapter)
+        .map_err(|_| NodeError::SpawnError)?;
+
+    let notifier_fut = notifier_loop(
+        node_state.funder_state.opt_payment_notifier.clone(),
+        node_state
+            .funder_state
+            .incoming_payments
+            .values()
+            .cloned()
+            .collect(),
+        from_funder_notifier,
+        to_funder,
+        timer_client,
+        notify_connector,
+        node_config.backoff_ticks,
+        rng,
+        spawner.clone(),
+    );
+
+    spawner
+        .spawn_with_handle(notifier_fut)
+        .map_err(|_| NodeError::SpawnError)
+}
+
+fn node_spawn_directory<C, R, S>(
+    node_config: &NodeConfig,
+    identity_client: IdentityClient,
+    timer_client: TimerClient,
+    node_state: &NodeState<NetAddress>,
+    from_funder: mpsc::Receiver<DirectoryState<NetAddress>>,
+    to_funder: mpsc::Sender<FunderIncomingControl<NetAddress>>,
+    to_index_client: mpsc::Sender<AppServerToIndexClient<NetAddress>>,
+    version_connector: C,
+    rng: R,
+    mut spawner: S,
+) -> Result<impl Future<Output = Result<(), DirectoryError>>, NodeError>
+where
+    C: FutTransform<Input = NetAddress, Output = Option<ConnPairVec>>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    R: CryptoRandom + Clone + 'static,
+    S: Spawn + Clone + Send + Sync + 'static,
+{
+    let encrypt_transform = SecureChannel::new(
+        identity_client,
+        rng.clone(),
+        timer_client.clone(),
+        node_config.ticks_to_rekey,
+        None, // max_messages_before_rekey
+        0,    // incoming_queue_len
+        None, // opt_resume_ticks
+        spawner.clone(),
+    );
+
+    let enc_directory_connector = EncRelayConnector::new(encrypt_transform, version_connector);
+
+    let directory_fut = directory_loop(
+        node_state.funder_state.directory.clone(),
+        from_funder,
+        to_funder,
+        to_index_client,
+        timer_client,
+        enc_directory_connector,
+        node_config.directory_fetch_ticks,
+        rng,
+        spawner.clone(),
+    );
+
+    spawner
+        .spawn_with_handle(directory_fut)
+        .map_err(|_| NodeError::SpawnError)
+}
+
+pub async fn node<C, NC, IA, AR, IDC, R, S>(
     node_config: NodeConfig,
     identity_client: IdentityClient,
     timer_client: TimerClient,
     node_state: NodeState<NetAddress>,
     database_client: DatabaseClient<NodeMutation<NetAddress>>,
     version_connector: C,
+    notify_connector: NC,
     incoming_apps: IA,
+    app_revocations: AR,
+    incoming_direct_conns: IDC,
     rng: R,
     mut spawner: S,
 ) -> Result<(), NodeError>
@@ -333,7 +523,14 @@ where
         + Send
         + Sync
         + 'static,
+    NC: FutTransform<Input = NetAddress, Output = Option<ConnPairVec>>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
     IA: Stream<Item = IncomingAppConnection<NetAddress>> + Unpin + Send + 'static,
+    AR: Stream<Item = PublicKey> + Unpin + Send + 'static,
+    IDC: Stream<Item = ConnPairVec> + Unpin + Send + 'static,
     R: CryptoRandom + Clone + 'static,
     S: Spawn + Clone + Send + Sync + 'static,
 {
@@ -355,6 +552,7 @@ where
         identity_client.clone(),
         timer_client.clone(),
         version_connector.clone(),
+        incoming_direct_conns,
         rng.clone(),
         funder_to_channeler_receiver,
         channeler_to_funder_sender,
@@ -366,16 +564,34 @@ where
         mpsc::channel(node_config.channel_len);
     let (funder_to_app_server_sender, funder_to_app_server_receiver) =
         mpsc::channel(node_config.channel_len);
+    // Funder --> (AppServer, Notifier, Directory)
+    let (funder_control_sender, funder_control_receiver) = mpsc::channel(node_config.channel_len);
+    let (funder_to_directory_sender, funder_to_directory_receiver) =
+        mpsc::channel(node_config.channel_len);
 
     let funder_handle = node_spawn_funder(
         &node_config,
         identity_client.clone(),
+        timer_client.clone(),
         node_state.funder_state.clone(),
         database_client.clone(),
         channeler_to_funder_receiver,
         funder_to_channeler_sender,
         app_server_to_funder_receiver,
+        funder_control_sender,
+        rng.clone(),
+        spawner.clone(),
+    )?;
+
+    let notifier_handle = node_spawn_notifier(
+        &node_config,
+        timer_client.clone(),
+        &node_state,
+        funder_control_receiver,
         funder_to_app_server_sender,
+        funder_to_directory_sender,
+        app_server_to_funder_sender.clone(),
+        notify_connector,
         rng.clone(),
         spawner.clone(),
     )?;
@@ -386,13 +602,44 @@ where
     let (index_client_to_app_server_sender, index_client_to_app_server_receiver) =
         mpsc::channel(node_config.channel_len);
 
+    let directory_handle = node_spawn_directory(
+        &node_config,
+        identity_client.clone(),
+        timer_client.clone(),
+        &node_state,
+        funder_to_directory_receiver,
+        app_server_to_funder_sender.clone(),
+        app_server_to_index_client_sender.clone(),
+        version_connector.clone(),
+        rng.clone(),
+        spawner.clone(),
+    )?;
+
+    let self_tester = SelfTester::new(
+        node_config.clone(),
+        identity_client.clone(),
+        timer_client.clone(),
+        rng.clone(),
+        spawner.clone(),
+    );
+
     let app_server_fut = app_server_loop(
         funder_to_app_server_receiver,
         app_server_to_funder_sender,
         index_client_to_app_server_receiver,
         app_server_to_index_client_sender,
         incoming_apps,
+        app_revocations,
         initial_node_report.clone(),
+        node_state.funder_state.opt_payment_notifier.clone(),
+        node_state
+            .funder_state
+            .incoming_payments
+            .values()
+            .cloned()
+            .collect(),
+        identity_client.clone(),
+        self_tester,
         spawner.clone(),
     );
 
@@ -420,6 +667,8 @@ where
         res = funder_handle.fuse() => res?,
         res = app_server_handle.fuse() => res?,
         res = index_client_handle.fuse() => res?,
+        res = notifier_handle.fuse() => res?,
+        res = directory_handle.fuse() => res?,
     }
     Ok(())
 }
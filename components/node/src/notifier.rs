@@ -0,0 +1,372 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, stream, SinkExt, StreamExt};
+
+use common::conn::{ConnPairVec, FutTransform};
+use common::select_streams::{select_streams, BoxStream};
+
+use crypto::crypto_rand::CryptoRandom;
+use crypto::uid::Uid;
+
+use proto::consts::MAX_INCOMING_PAYMENTS;
+use proto::funder::messages::{
+    FunderControl, FunderIncomingControl, IncomingPayment, PaymentNotifier,
+};
+
+use timer::TimerClient;
+
+/// Maximum exponent used for the exponential backoff between delivery attempts.
+/// The longest wait between two attempts is `backoff_ticks * 2^MAX_BACKOFF_EXPONENT`.
+const MAX_BACKOFF_EXPONENT: usize = 6;
+
+/// Serialize an incoming payment notification, sent from the node to the consumer.
+pub fn serialize_incoming_payment(incoming_payment: &IncomingPayment) -> Vec<u8> {
+    bincode::serialize(incoming_payment).unwrap()
+}
+
+pub fn deserialize_incoming_payment(data: &[u8]) -> Option<IncomingPayment> {
+    bincode::deserialize(data).ok()
+}
+
+/// Serialize an acknowledgement of a notification, sent from the consumer to the node.
+/// The consumer should only acknowledge a notification after it was handled.
+pub fn serialize_notification_ack(notification_id: u64) -> Vec<u8> {
+    bincode::serialize(&notification_id).unwrap()
+}
+
+pub fn deserialize_notification_ack(data: &[u8]) -> Option<u64> {
+    bincode::deserialize(data).ok()
+}
+
+/// Add a notification to an outbox of at most `MAX_INCOMING_PAYMENTS` notifications.
+/// Like the funder, we drop the oldest notification to make room.
+fn insert_bounded(outbox: &mut BTreeMap<u64, IncomingPayment>, incoming_payment: IncomingPayment) {
+    outbox.insert(incoming_payment.notification_id, incoming_payment);
+    while outbox.len() > MAX_INCOMING_PAYMENTS {
+        let oldest_id = *outbox.keys().next().unwrap();
+        outbox.remove(&oldest_id);
+    }
+}
+
+#[derive(Debug)]
+pub enum NotifierError {
+    RequestTimerStreamError,
+    SpawnError,
+    SendToFunderError,
+}
+
+/// Messages sent from the funder to the notifier
+#[derive(Debug)]
+pub enum FunderToNotifier<B: Clone> {
+    IncomingPayment(IncomingPayment),
+    PaymentNotifierChanged(Option<PaymentNotifier<B>>),
+}
+
+enum NotifierEvent<B: Clone> {
+    Funder(FunderToNotifier<B>),
+    FunderClosed,
+    TimerTick,
+    /// A connection attempt to the consumer was done. (conn_id, opt_conn_pair)
+    Connected((u64, Option<ConnPairVec>)),
+    /// A message was received from the consumer. (conn_id, data)
+    Consumer((u64, Vec<u8>)),
+    /// Connection to the consumer was closed. (conn_id)
+    ConsumerClosed(u64),
+}
+
+enum ConnStatus {
+    /// Waiting the given amount of ticks before the next connection attempt
+    Waiting(usize),
+    Connecting,
+    Connected(mpsc::Sender<Vec<u8>>),
+}
+
+struct Notifier<B: Clone, C, R, S> {
+    opt_payment_notifier: Option<PaymentNotifier<B>>,
+    /// Notifications that were not yet acknowledged by the consumer, ordered by notification_id
+    outbox: BTreeMap<u64, IncomingPayment>,
+    conn_status: ConnStatus,
+    /// Identifies the current connection attempt. Events of older connections are ignored.
+    conn_id: u64,
+    /// Notifications with a smaller id were already sent through the current connection.
+    next_send_id: u64,
+    /// Amount of consecutive failed delivery attempts
+    num_failures: usize,
+    backoff_ticks: usize,
+    connector: C,
+    to_funder: mpsc::Sender<FunderIncomingControl<B>>,
+    event_sender: mpsc::Sender<NotifierEvent<B>>,
+    rng: R,
+    spawner: S,
+}
+
+impl<B, C, R, S> Notifier<B, C, R, S>
+where
+    B: Clone + PartialEq + Eq + Debug + Send + 'static,
+    C: FutTransform<Input = B, Output = Option<ConnPairVec>> + Clone + Send + 'static,
+    R: CryptoRandom,
+    S: Spawn,
+{
+    /// Close the current connection (if any), and wait before the next connection attempt.
+    fn disconnect(&mut self) {
+        self.conn_id = self.conn_id.wrapping_add(1);
+        let exponent = self.num_failures.min(MAX_BACKOFF_EXPONENT);
+        self.conn_status = ConnStatus::Waiting(self.backoff_ticks.saturating_mul(1 << exponent));
+        self.num_failures = self.num_failures.saturating_add(1);
+    }
+
+    /// Address of the consumer, if notifications should be delivered by the notifier.
+    /// (Notifications for the node's apps are delivered by the app server).
+    fn opt_address(&self) -> Option<B> {
+        self.opt_payment_notifier
+            .as_ref()
+            .and_then(|payment_notifier| payment_notifier.opt_address().cloned())
+    }
+
+    fn connect(&mut self) -> Result<(), NotifierError> {
+        let address = match self.opt_address() {
+            Some(address) => address,
+            None => return Ok(()),
+        };
+
+        let conn_id = self.conn_id;
+        let mut c_connector = self.connector.clone();
+        let mut c_event_sender = self.event_sender.clone();
+        let connect_fut = async move {
+            let opt_conn_pair = await!(c_connector.transform(address));
+            let connected_event = NotifierEvent::Connected((conn_id, opt_conn_pair));
+            let _ = await!(c_event_sender.send(connected_event));
+        };
+        self.spawner
+            .spawn(connect_fut)
+            .map_err(|_| NotifierError::SpawnError)?;
+
+        self.conn_status = ConnStatus::Connecting;
+        Ok(())
+    }
+
+    /// Send all the notifications that were not yet sent through the current connection.
+    async fn send_pending(&mut self) {
+        let sender = match &mut self.conn_status {
+            ConnStatus::Connected(sender) => sender,
+            _ => return,
+        };
+
+        let mut send_failed = false;
+        for (notification_id, incoming_payment) in self.outbox.range(self.next_send_id..) {
+            if await!(sender.send(serialize_incoming_payment(incoming_payment))).is_err() {
+                send_failed = true;
+                break;
+            }
+            self.next_send_id = notification_id.wrapping_add(1);
+        }
+
+        if send_failed {
+            warn!("Notifier: Failed sending notification to the consumer");
+            self.disconnect();
+        }
+    }
+
+    async fn handle_funder(
+        &mut self,
+        funder_to_notifier: FunderToNotifier<B>,
+    ) -> Result<(), NotifierError> {
+        match funder_to_notifier {
+            FunderToNotifier::IncomingPayment(incoming_payment) => {
+                if self.opt_address().is_none() {
+                    return Ok(());
+                }
+                insert_bounded(&mut self.outbox, incoming_payment);
+                await!(self.send_pending());
+            }
+            FunderToNotifier::PaymentNotifierChanged(opt_payment_notifier) => {
+                let old_address = self.opt_address();
+                self.opt_payment_notifier = opt_payment_notifier;
+                let new_address = self.opt_address();
+
+                if new_address.is_none() {
+                    // The funder sends the notifications again when a new consumer is set:
+                    self.outbox.clear();
+                }
+
+                if old_address != new_address {
+                    // Drop the connection to the old consumer,
+                    // and connect to the new consumer at the next tick:
+                    self.num_failures = 0;
+                    self.conn_id = self.conn_id.wrapping_add(1);
+                    self.conn_status = ConnStatus::Waiting(0);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_timer_tick(&mut self) -> Result<(), NotifierError> {
+        if let ConnStatus::Waiting(ticks_left) = &mut self.conn_status {
+            *ticks_left = ticks_left.saturating_sub(1);
+            // We only connect if there is something to deliver:
+            if *ticks_left == 0 && !self.outbox.is_empty() {
+                self.connect()?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_connected(
+        &mut self,
+        conn_id: u64,
+        opt_conn_pair: Option<ConnPairVec>,
+    ) -> Result<(), NotifierError> {
+        if conn_id != self.conn_id {
+            // An old connection attempt. Dropping the connection:
+            return Ok(());
+        }
+
+        let (sender, mut receiver) = match opt_conn_pair {
+            Some(conn_pair) => conn_pair,
+            None => {
+                warn!("Notifier: Failed connecting to the consumer");
+                self.disconnect();
+                return Ok(());
+            }
+        };
+
+        // Forward messages from the consumer:
+        let mut c_event_sender = self.event_sender.clone();
+        let receiver_fut = async move {
+            while let Some(data) = await!(receiver.next()) {
+                let consumer_event = NotifierEvent::Consumer((conn_id, data));
+                if await!(c_event_sender.send(consumer_event)).is_err() {
+                    return;
+                }
+            }
+            let _ = await!(c_event_sender.send(NotifierEvent::ConsumerClosed(conn_id)));
+        };
+        self.spawner
+            .spawn(receiver_fut)
+            .map_err(|_| NotifierError::SpawnError)?;
+
+        self.conn_status = ConnStatus::Connected(sender);
+        self.next_send_id = 0;
+        await!(self.send_pending());
+        Ok(())
+    }
+
+    async fn handle_consumer(&mut self, conn_id: u64, data: Vec<u8>) -> Result<(), NotifierError> {
+        if conn_id != self.conn_id {
+            return Ok(());
+        }
+
+        let notification_id = match deserialize_notification_ack(&data) {
+            Some(notification_id) => notification_id,
+            None => {
+                warn!("Notifier: Received invalid message from the consumer");
+                self.disconnect();
+                return Ok(());
+            }
+        };
+
+        if self.outbox.remove(&notification_id).is_none() {
+            // Duplicate acknowledgement. Nothing to do:
+            return Ok(());
+        }
+        self.num_failures = 0;
+
+        // Let the funder know that this notification was delivered,
+        // so that it will not be delivered again:
+        let funder_control = FunderControl::AckIncomingPayment(notification_id);
+        let funder_incoming_control =
+            FunderIncomingControl::new(Uid::new(&self.rng), funder_control);
+        await!(self.to_funder.send(funder_incoming_control))
+            .map_err(|_| NotifierError::SendToFunderError)
+    }
+
+    fn handle_consumer_closed(&mut self, conn_id: u64) {
+        if conn_id == self.conn_id {
+            self.disconnect();
+        }
+    }
+}
+
+/// Deliver notifications about incoming payments to the configured consumer,
+/// if the consumer is reached over the network.
+///
+/// Notifications are kept by the funder until they are acknowledged by the consumer,
+/// so delivery is at least once, also across node restarts.
+/// Notifications are sent in the order of their notification_id.
+pub async fn notifier_loop<B, C, R, S>(
+    opt_payment_notifier: Option<PaymentNotifier<B>>,
+    incoming_payments: Vec<IncomingPayment>,
+    from_funder: mpsc::Receiver<FunderToNotifier<B>>,
+    to_funder: mpsc::Sender<FunderIncomingControl<B>>,
+    mut timer_client: TimerClient,
+    connector: C,
+    backoff_ticks: usize,
+    rng: R,
+    spawner: S,
+) -> Result<(), NotifierError>
+where
+    B: Clone + PartialEq + Eq + Debug + Send + 'static,
+    C: FutTransform<Input = B, Output = Option<ConnPairVec>> + Clone + Send + 'static,
+    R: CryptoRandom,
+    S: Spawn,
+{
+    let timer_stream = await!(timer_client.request_timer_stream())
+        .map_err(|_| NotifierError::RequestTimerStreamError)?;
+
+    let (event_sender, event_receiver) = mpsc::channel(0);
+
+    let mut outbox = BTreeMap::new();
+    let has_address = opt_payment_notifier
+        .as_ref()
+        .and_then(PaymentNotifier::opt_address)
+        .is_some();
+    if has_address {
+        for incoming_payment in incoming_payments {
+            insert_bounded(&mut outbox, incoming_payment);
+        }
+    }
+
+    let mut notifier = Notifier {
+        opt_payment_notifier,
+        outbox,
+        conn_status: ConnStatus::Waiting(0),
+        conn_id: 0,
+        next_send_id: 0,
+        num_failures: 0,
+        backoff_ticks,
+        connector,
+        to_funder,
+        event_sender,
+        rng,
+        spawner,
+    };
+
+    let from_funder = from_funder
+        .map(NotifierEvent::Funder)
+        .chain(stream::once(future::ready(NotifierEvent::FunderClosed)));
+    let timer_stream = timer_stream.map(|_| NotifierEvent::TimerTick);
+
+    let mut incoming_events = select_streams![from_funder, timer_stream, event_receiver];
+
+    while let Some(event) = await!(incoming_events.next()) {
+        match event {
+            NotifierEvent::Funder(funder_to_notifier) => {
+                await!(notifier.handle_funder(funder_to_notifier))?
+            }
+            NotifierEvent::FunderClosed => break,
+            NotifierEvent::TimerTick => notifier.handle_timer_tick()?,
+            NotifierEvent::Connected((conn_id, opt_conn_pair)) => {
+                await!(notifier.handle_connected(conn_id, opt_conn_pair))?
+            }
+            NotifierEvent::Consumer((conn_id, data)) => {
+                await!(notifier.handle_consumer(conn_id, data))?
+            }
+            NotifierEvent::ConsumerClosed(conn_id) => notifier.handle_consumer_closed(conn_id),
+        }
+    }
+    Ok(())
+}
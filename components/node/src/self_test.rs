@@ -0,0 +1,778 @@
+use std::convert::TryFrom;
+use std::marker::Unpin;
+
+use futures::channel::mpsc;
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, select, Future, FutureExt, SinkExt, StreamExt};
+
+use common::conn::{BoxFuture, ConnPairVec, FutTransform};
+use common::int_convert::usize_to_u64;
+use common::mutable_state::MutableState;
+use common::select_streams::{select_streams, BoxStream, SelectStreams};
+
+use crypto::crypto_rand::{CryptoRandom, RandValue};
+use crypto::identity::{
+    generate_pkcs8_key_pair, verify_signature, PublicKey, SoftwareEd25519Identity,
+};
+use crypto::invoice_id::InvoiceId;
+use crypto::uid::Uid;
+
+use database::{DatabaseClient, DatabaseRequest};
+use identity::{create_identity, IdentityClient};
+use timer::TimerClient;
+
+use funder::report::create_initial_report;
+use funder::FunderState;
+
+use proto::app_server::messages::{RelayAddress, SelfTestStage, SelfTestStageReport};
+use proto::funder::messages::{
+    AddFriend, FriendStatus, FriendsRoute, FunderControl, FunderIncomingControl,
+    FunderOutgoingControl, RequestsStatus, ResponseReceived, ResponseSendFundsResult,
+    SetFriendRemoteMaxDebt, SetFriendStatus, SetRequestsStatus, UserRequestSendFunds,
+};
+use proto::funder::signature_buff::verify_receipt;
+use proto::net::messages::NetAddress;
+use proto::report::messages::{ChannelStatusReport, FunderReport, RequestsStatusReport};
+
+use crate::node::{node_spawn_channeler, node_spawn_funder};
+use crate::types::{NodeConfig, NodeMutation};
+
+/// The credit limit the local node and the peer extend to each other during the self test.
+const SELF_TEST_MAX_DEBT: u128 = 0x10;
+
+/// Amount of credits paid in every direction during the self test.
+const SELF_TEST_PAYMENT: u128 = 1;
+
+#[derive(Debug)]
+enum SelfTestError {
+    RequestPublicKeyError,
+    RequestSignatureError,
+    InvalidSignature,
+    CreateIdentityError,
+    SpawnError,
+    SendToFunderError,
+    FunderClosed,
+    ReportMutateError,
+    PaymentFailed,
+    InvalidReceipt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Local,
+    Peer,
+}
+
+/// A node taking part in the self test: A funder and a channeler that keep their state in
+/// memory.
+struct SelfTestNode {
+    public_key: PublicKey,
+    to_funder: mpsc::Sender<FunderIncomingControl<NetAddress>>,
+    funder_report: FunderReport<NetAddress>,
+}
+
+/// The local node and the throwaway peer, connected to each other directly in memory.
+struct SelfTestRun<R> {
+    local: SelfTestNode,
+    peer: SelfTestNode,
+    /// Messages from the funders of both nodes
+    from_funders: SelectStreams<'static, (Side, FunderOutgoingControl<NetAddress>)>,
+    rng: R,
+    /// Dropping the handles stops the spawned components
+    _handles: Vec<BoxFuture<'static, ()>>,
+}
+
+/// A database client that acknowledges all mutations without storing them.
+fn spawn_memory_db<S>(
+    spawner: &mut S,
+) -> Result<DatabaseClient<NodeMutation<NetAddress>>, SelfTestError>
+where
+    S: Spawn,
+{
+    let (request_sender, mut request_receiver) =
+        mpsc::channel::<DatabaseRequest<NodeMutation<NetAddress>>>(0);
+    let memory_db_fut = async move {
+        while let Some(request) = await!(request_receiver.next()) {
+            let _ = request.response_sender.send(Some(()));
+        }
+    };
+    spawner
+        .spawn(memory_db_fut)
+        .map_err(|_| SelfTestError::SpawnError)?;
+    Ok(DatabaseClient::new(request_sender))
+}
+
+#[derive(Clone)]
+/// Connects directly to a node, by handing the other end of a new in memory connection to
+/// the node's incoming direct connections.
+struct MemoryConnector {
+    conns_sender: mpsc::Sender<ConnPairVec>,
+}
+
+impl FutTransform for MemoryConnector {
+    type Input = NetAddress;
+    type Output = Option<ConnPairVec>;
+
+    fn transform(&mut self, _net_address: Self::Input) -> BoxFuture<'_, Self::Output> {
+        Box::pin(
+            async move {
+                let (local_sender, remote_receiver) = mpsc::channel(0);
+                let (remote_sender, local_receiver) = mpsc::channel(0);
+                await!(self.conns_sender.send((remote_sender, remote_receiver))).ok()?;
+                Some((local_sender, local_receiver))
+            },
+        )
+    }
+}
+
+/// A relay address that carries the node's own public key is a direct address of the node.
+/// The network address is never used, as connections are created in memory.
+fn direct_relay_address(public_key: &PublicKey) -> RelayAddress<NetAddress> {
+    RelayAddress {
+        public_key: public_key.clone(),
+        address: NetAddress::try_from("self-test".to_owned()).unwrap(),
+    }
+}
+
+fn spawn_self_test_node<C, R, S>(
+    node_config: &NodeConfig,
+    public_key: PublicKey,
+    identity_client: IdentityClient,
+    timer_client: TimerClient,
+    connector: C,
+    incoming_conns: mpsc::Receiver<ConnPairVec>,
+    rng: R,
+    mut spawner: S,
+    handles: &mut Vec<BoxFuture<'static, ()>>,
+) -> Result<(SelfTestNode, mpsc::Receiver<FunderOutgoingControl<NetAddress>>), SelfTestError>
+where
+    C: FutTransform<Input = NetAddress, Output = Option<ConnPairVec>>
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+    R: CryptoRandom + Clone + 'static,
+    S: Spawn + Clone + Send + Sync + 'static,
+{
+    let funder_state = FunderState::new(public_key.clone(), Vec::new());
+    let funder_report = create_initial_report(&funder_state);
+
+    // Channeler <--> Funder
+    let (channeler_to_funder_sender, channeler_to_funder_receiver) =
+        mpsc::channel(node_config.channel_len);
+    let (funder_to_channeler_sender, funder_to_channeler_receiver) =
+        mpsc::channel(node_config.channel_len);
+
+    let channeler_handle = node_spawn_channeler(
+        node_config,
+        public_key.clone(),
+        identity_client.clone(),
+        timer_client.clone(),
+        connector,
+        incoming_conns,
+        rng.clone(),
+        funder_to_channeler_receiver,
+        channeler_to_funder_sender,
+        spawner.clone(),
+    )
+    .map_err(|_| SelfTestError::SpawnError)?;
+    handles.push(Box::pin(channeler_handle.map(|_| ())));
+
+    // SelfTest <--> Funder
+    let (to_funder, from_self_test) = mpsc::channel(node_config.channel_len);
+    let (to_self_test, from_funder) = mpsc::channel(node_config.channel_len);
+
+    let funder_handle = node_spawn_funder(
+        node_config,
+        identity_client,
+        timer_client,
+        funder_state,
+        spawn_memory_db(&mut spawner)?,
+        channeler_to_funder_receiver,
+        funder_to_channeler_sender,
+        from_self_test,
+        to_self_test,
+        rng,
+        spawner,
+    )
+    .map_err(|_| SelfTestError::SpawnError)?;
+    handles.push(Box::pin(funder_handle.map(|_| ())));
+
+    let self_test_node = SelfTestNode {
+        public_key,
+        to_funder,
+        funder_report,
+    };
+    Ok((self_test_node, from_funder))
+}
+
+/// Is the channel with the friend consistent, while the friend is online?
+fn is_channel_ready(
+    funder_report: &FunderReport<NetAddress>,
+    friend_public_key: &PublicKey,
+) -> bool {
+    match funder_report.friends.get(friend_public_key) {
+        Some(friend_report) => match &friend_report.channel_status {
+            ChannelStatusReport::Consistent(_) => friend_report.liveness.is_online(),
+            ChannelStatusReport::Inconsistent(_) => false,
+        },
+        None => false,
+    }
+}
+
+/// Did both sides of the channel extend credit to each other, and open for requests?
+fn is_credit_ready(
+    funder_report: &FunderReport<NetAddress>,
+    friend_public_key: &PublicKey,
+) -> bool {
+    let friend_report = match funder_report.friends.get(friend_public_key) {
+        Some(friend_report) => friend_report,
+        None => return false,
+    };
+    match &friend_report.channel_status {
+        ChannelStatusReport::Consistent(tc_report) => {
+            tc_report.balance.local_max_debt >= SELF_TEST_MAX_DEBT
+                && tc_report.balance.remote_max_debt >= SELF_TEST_MAX_DEBT
+                && tc_report.requests_status.local == RequestsStatusReport::Open
+                && tc_report.requests_status.remote == RequestsStatusReport::Open
+        }
+        ChannelStatusReport::Inconsistent(_) => false,
+    }
+}
+
+impl<R> SelfTestRun<R>
+where
+    R: CryptoRandom,
+{
+    fn node_mut(&mut self, side: Side) -> &mut SelfTestNode {
+        match side {
+            Side::Local => &mut self.local,
+            Side::Peer => &mut self.peer,
+        }
+    }
+
+    async fn send_control(
+        &mut self,
+        side: Side,
+        funder_control: FunderControl<NetAddress>,
+    ) -> Result<(), SelfTestError> {
+        let funder_incoming_control =
+            FunderIncomingControl::new(Uid::new(&self.rng), funder_control);
+        await!(self.node_mut(side).to_funder.send(funder_incoming_control))
+            .map_err(|_| SelfTestError::SendToFunderError)
+    }
+
+    /// Send the same kind of control message to both sides. `create_control` receives the
+    /// public key of the remote side.
+    async fn send_control_both<F>(&mut self, create_control: F) -> Result<(), SelfTestError>
+    where
+        F: Fn(&PublicKey) -> FunderControl<NetAddress>,
+    {
+        let local_control = create_control(&self.peer.public_key);
+        await!(self.send_control(Side::Local, local_control))?;
+        let peer_control = create_control(&self.local.public_key);
+        await!(self.send_control(Side::Peer, peer_control))
+    }
+
+    /// Handle the next message from one of the funders.
+    /// Returns responses to payments, together with the side that sent the payment.
+    async fn next_response(&mut self) -> Result<Option<(Side, ResponseReceived)>, SelfTestError> {
+        let (side, funder_message) =
+            await!(self.from_funders.next()).ok_or(SelfTestError::FunderClosed)?;
+        match funder_message {
+            FunderOutgoingControl::ReportMutations(report_mutations) => {
+                let funder_report = &mut self.node_mut(side).funder_report;
+                for mutation in &report_mutations.mutations {
+                    funder_report
+                        .mutate(mutation)
+                        .map_err(|_| SelfTestError::ReportMutateError)?;
+                }
+                Ok(None)
+            }
+            FunderOutgoingControl::ResponseReceived(response_received) => {
+                Ok(Some((side, response_received)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Wait until the reports of both sides satisfy `pred`.
+    /// `pred` receives the report of a side and the public key of the remote side.
+    async fn wait_reports<P>(&mut self, pred: P) -> Result<(), SelfTestError>
+    where
+        P: Fn(&FunderReport<NetAddress>, &PublicKey) -> bool,
+    {
+        while !pred(&self.local.funder_report, &self.peer.public_key)
+            || !pred(&self.peer.funder_report, &self.local.public_key)
+        {
+            let _ = await!(self.next_response())?;
+        }
+        Ok(())
+    }
+
+    /// Add the other side as a friend on both sides, and wait for the token channel.
+    async fn open_channel(&mut self) -> Result<(), SelfTestError> {
+        await!(self.send_control_both(|friend_public_key| {
+            FunderControl::AddFriend(AddFriend {
+                friend_public_key: friend_public_key.clone(),
+                relays: vec![direct_relay_address(friend_public_key)],
+                name: "self-test".to_owned(),
+                balance: 0,
+            })
+        }))?;
+        await!(self.send_control_both(|friend_public_key| {
+            FunderControl::SetFriendStatus(SetFriendStatus {
+                friend_public_key: friend_public_key.clone(),
+                status: FriendStatus::Enabled,
+            })
+        }))?;
+        await!(self.wait_reports(is_channel_ready))
+    }
+
+    /// Extend credit to the other side on both sides, and open for requests.
+    async fn grant_credit(&mut self) -> Result<(), SelfTestError> {
+        await!(self.send_control_both(|friend_public_key| {
+            FunderControl::SetFriendRemoteMaxDebt(SetFriendRemoteMaxDebt {
+                friend_public_key: friend_public_key.clone(),
+                remote_max_debt: SELF_TEST_MAX_DEBT,
+                opt_expiry: None,
+            })
+        }))?;
+        await!(self.send_control_both(|friend_public_key| {
+            FunderControl::SetRequestsStatus(SetRequestsStatus {
+                friend_public_key: friend_public_key.clone(),
+                status: RequestsStatus::Open,
+            })
+        }))?;
+        await!(self.wait_reports(is_credit_ready))
+    }
+
+    /// Send a payment from one side to the other, and verify the receipt.
+    async fn pay(&mut self, payer: Side) -> Result<(), SelfTestError> {
+        let (payer_public_key, payee_public_key) = match payer {
+            Side::Local => (self.local.public_key.clone(), self.peer.public_key.clone()),
+            Side::Peer => (self.peer.public_key.clone(), self.local.public_key.clone()),
+        };
+
+        let request_id = Uid::new(&self.rng);
+        let user_request_send_funds = UserRequestSendFunds {
+            request_id,
+            route: FriendsRoute {
+                public_keys: vec![payer_public_key, payee_public_key.clone()],
+            },
+            invoice_id: InvoiceId::new(&self.rng),
+            dest_payment: SELF_TEST_PAYMENT,
+            opt_label: None,
+        };
+        let funder_control = FunderControl::RequestSendFunds(user_request_send_funds);
+        await!(self.send_control(payer, funder_control))?;
+
+        let response_received = loop {
+            if let Some((side, response_received)) = await!(self.next_response())? {
+                if side == payer && response_received.request_id == request_id {
+                    break response_received;
+                }
+            }
+        };
+
+        match response_received.result {
+            ResponseSendFundsResult::Success(receipt) => {
+                if receipt.dest_payment != SELF_TEST_PAYMENT
+                    || !verify_receipt(&receipt, &payee_public_key)
+                {
+                    return Err(SelfTestError::InvalidReceipt);
+                }
+                Ok(())
+            }
+            ResponseSendFundsResult::Failure(_) => Err(SelfTestError::PaymentFailed),
+        }
+    }
+}
+
+/// Obtain the local public key, and make sure that the identity produces valid signatures.
+async fn check_identity<R>(
+    identity_client: IdentityClient,
+    rng: R,
+) -> Result<PublicKey, SelfTestError>
+where
+    R: CryptoRandom,
+{
+    let local_public_key = await!(identity_client.request_public_key())
+        .map_err(|_| SelfTestError::RequestPublicKeyError)?;
+    let message = RandValue::new(&rng).to_vec();
+    let signature = await!(identity_client.request_signature(message.clone()))
+        .map_err(|_| SelfTestError::RequestSignatureError)?;
+    if !verify_signature(&message, &local_public_key, &signature) {
+        return Err(SelfTestError::InvalidSignature);
+    }
+    Ok(local_public_key)
+}
+
+/// Spawn the local node and a throwaway peer, and open a token channel between them.
+async fn create_run<'a, R, S>(
+    node_config: &'a NodeConfig,
+    local_public_key: PublicKey,
+    identity_client: IdentityClient,
+    timer_client: TimerClient,
+    rng: R,
+    mut spawner: S,
+) -> Result<SelfTestRun<R>, SelfTestError>
+where
+    R: CryptoRandom + Clone + 'static,
+    S: Spawn + Clone + Send + Sync + 'static,
+{
+    let mut handles: Vec<BoxFuture<'static, ()>> = Vec::new();
+
+    // A throwaway identity for the peer:
+    let pkcs8 = generate_pkcs8_key_pair(&rng);
+    let peer_identity = SoftwareEd25519Identity::from_pkcs8(&pkcs8)
+        .map_err(|_| SelfTestError::CreateIdentityError)?;
+    let (requests_sender, peer_identity_loop) = create_identity(peer_identity);
+    let peer_identity_handle = spawner
+        .spawn_with_handle(peer_identity_loop)
+        .map_err(|_| SelfTestError::SpawnError)?;
+    handles.push(Box::pin(peer_identity_handle));
+    let peer_identity_client = IdentityClient::new(requests_sender);
+    let peer_public_key = await!(peer_identity_client.request_public_key())
+        .map_err(|_| SelfTestError::RequestPublicKeyError)?;
+
+    // Every node connects directly to the incoming connections of the other node:
+    let (local_conns_sender, local_incoming_conns) = mpsc::channel(0);
+    let (peer_conns_sender, peer_incoming_conns) = mpsc::channel(0);
+
+    let (local, local_from_funder) = spawn_self_test_node(
+        node_config,
+        local_public_key,
+        identity_client,
+        timer_client.clone(),
+        MemoryConnector {
+            conns_sender: peer_conns_sender,
+        },
+        local_incoming_conns,
+        rng.clone(),
+        spawner.clone(),
+        &mut handles,
+    )?;
+
+    let (peer, peer_from_funder) = spawn_self_test_node(
+        node_config,
+        peer_public_key,
+        peer_identity_client,
+        timer_client,
+        MemoryConnector {
+            conns_sender: local_conns_sender,
+        },
+        peer_incoming_conns,
+        rng.clone(),
+        spawner,
+        &mut handles,
+    )?;
+
+    let local_from_funder = local_from_funder.map(|message| (Side::Local, message));
+    let peer_from_funder = peer_from_funder.map(|message| (Side::Peer, message));
+
+    let mut run = SelfTestRun {
+        local,
+        peer,
+        from_funders: select_streams![local_from_funder, peer_from_funder],
+        rng,
+        _handles: handles,
+    };
+    await!(run.open_channel())?;
+    Ok(run)
+}
+
+/// Run a stage of the self test, and count the ticks that passed until it was done.
+/// The stage fails if it is not done within `stage_ticks`.
+async fn run_stage<'a, T, F>(
+    stage: SelfTestStage,
+    fut: F,
+    timer_client: &'a mut TimerClient,
+    stage_ticks: usize,
+) -> (Option<T>, SelfTestStageReport)
+where
+    F: Future<Output = Result<T, SelfTestError>> + Unpin,
+{
+    let mut ticks = 0;
+    let opt_output = match await!(timer_client.request_timer_stream()) {
+        Ok(timer_stream) => {
+            let mut fut_time = timer_stream
+                .take(usize_to_u64(stage_ticks).unwrap())
+                .for_each(|_| {
+                    ticks += 1;
+                    future::ready(())
+                })
+                .map(|_| None);
+
+            select! {
+                res = fut.fuse() => match res {
+                    Ok(output) => Some(output),
+                    Err(e) => {
+                        warn!("self test stage {:?} failed: {:?}", stage, e);
+                        None
+                    }
+                },
+                fut_time = fut_time => fut_time,
+            }
+        }
+        Err(_) => None,
+    };
+
+    let stage_report = SelfTestStageReport {
+        stage,
+        success: opt_output.is_some(),
+        ticks,
+    };
+    (opt_output, stage_report)
+}
+
+/// Run a self test of the node.
+///
+/// A throwaway peer is created in memory, and connected directly to a second instance of the
+/// local node's funder and channeler, using the node's identity. Both sides keep their state in
+/// memory only: Nothing is written to the database, and the index client is not involved.
+/// The two sides open a token channel, extend credit to each other, and pay each other.
+///
+/// Returns a report for every stage that was run. The self test stops at the first failed stage.
+pub async fn self_test<R, S>(
+    node_config: NodeConfig,
+    identity_client: IdentityClient,
+    mut timer_client: TimerClient,
+    rng: R,
+    spawner: S,
+) -> Vec<SelfTestStageReport>
+where
+    R: CryptoRandom + Clone + 'static,
+    S: Spawn + Clone + Send + Sync + 'static,
+{
+    let stage_ticks = node_config.self_test_stage_ticks;
+    let mut stage_reports = Vec::new();
+
+    let identity_fut = Box::pin(check_identity(identity_client.clone(), rng.clone()));
+    let (opt_local_public_key, stage_report) = await!(run_stage(
+        SelfTestStage::Identity,
+        identity_fut,
+        &mut timer_client,
+        stage_ticks
+    ));
+    stage_reports.push(stage_report);
+    let local_public_key = match opt_local_public_key {
+        Some(local_public_key) => local_public_key,
+        None => return stage_reports,
+    };
+
+    let channel_fut = Box::pin(create_run(
+        &node_config,
+        local_public_key,
+        identity_client,
+        timer_client.clone(),
+        rng,
+        spawner,
+    ));
+    let (opt_run, stage_report) = await!(run_stage(
+        SelfTestStage::Channel,
+        channel_fut,
+        &mut timer_client,
+        stage_ticks
+    ));
+    stage_reports.push(stage_report);
+    let mut run = match opt_run {
+        Some(run) => run,
+        None => return stage_reports,
+    };
+
+    let credit_fut = Box::pin(run.grant_credit());
+    let (opt_done, stage_report) = await!(run_stage(
+        SelfTestStage::Credit,
+        credit_fut,
+        &mut timer_client,
+        stage_ticks
+    ));
+    stage_reports.push(stage_report);
+    if opt_done.is_none() {
+        return stage_reports;
+    }
+
+    let payment_fut = Box::pin(run.pay(Side::Local));
+    let (opt_done, stage_report) = await!(run_stage(
+        SelfTestStage::PaymentToPeer,
+        payment_fut,
+        &mut timer_client,
+        stage_ticks
+    ));
+    stage_reports.push(stage_report);
+    if opt_done.is_none() {
+        return stage_reports;
+    }
+
+    let payment_fut = Box::pin(run.pay(Side::Peer));
+    let (_opt_done, stage_report) = await!(run_stage(
+        SelfTestStage::PaymentFromPeer,
+        payment_fut,
+        &mut timer_client,
+        stage_ticks
+    ));
+    stage_reports.push(stage_report);
+
+    // Tear down the throwaway nodes:
+    drop(run);
+    stage_reports
+}
+
+/// Runs self tests of the node. See `self_test()`.
+#[derive(Clone)]
+pub struct SelfTester<R, S> {
+    node_config: NodeConfig,
+    identity_client: IdentityClient,
+    timer_client: TimerClient,
+    rng: R,
+    spawner: S,
+}
+
+impl<R, S> SelfTester<R, S> {
+    pub fn new(
+        node_config: NodeConfig,
+        identity_client: IdentityClient,
+        timer_client: TimerClient,
+        rng: R,
+        spawner: S,
+    ) -> Self {
+        SelfTester {
+            node_config,
+            identity_client,
+            timer_client,
+            rng,
+            spawner,
+        }
+    }
+}
+
+impl<R, S> FutTransform for SelfTester<R, S>
+where
+    R: CryptoRandom + Clone + 'static,
+    S: Spawn + Clone + Send + Sync + 'static,
+{
+    type Input = ();
+    type Output = Vec<SelfTestStageReport>;
+
+    fn transform(&mut self, _input: ()) -> BoxFuture<'_, Self::Output> {
+        Box::pin(self_test(
+            self.node_config.clone(),
+            self.identity_client.clone(),
+            self.timer_client.clone(),
+            self.rng.clone(),
+            self.spawner.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use common::bounded_cache::CacheLimits;
+    use common::test_executor::TestExecutor;
+
+    use crypto::identity::{Identity, Signature};
+    use crypto::test_utils::{fixture_software_identity, DummyRandom};
+
+    use funder::SlowEventLimits;
+
+    use timer::create_timer_incoming;
+
+    /// An identity that produces invalid signatures.
+    struct BrokenSignerIdentity {
+        identity: SoftwareEd25519Identity,
+    }
+
+    impl Identity for BrokenSignerIdentity {
+        fn sign(&self, _message: &[u8]) -> Signature {
+            Signature::zero()
+        }
+
+        fn get_public_key(&self) -> PublicKey {
+            self.identity.get_public_key()
+        }
+    }
+
+    fn dummy_node_config() -> NodeConfig {
+        NodeConfig {
+            channel_len: 0x10,
+            backoff_ticks: 0x8,
+            keepalive_ticks: 0x10,
+            ticks_to_rekey: 0x100,
+            max_messages_before_rekey: Some(0x100),
+            session_resume_ticks: 0x20,
+            max_concurrent_encrypt: 0x8,
+            conn_timeout_ticks: 0x8,
+            relay_mux: false,
+            min_operations_in_batch: 0x4,
+            max_operations_in_batch: 0x10,
+            max_pending_user_requests: 0x10,
+            max_open_index_client_requests: 0x8,
+            max_node_relays: 0x10,
+            friend_relays_damping_ticks: 0x4,
+            friend_prewarm_ticks: 0x4,
+            max_concurrent_incoming_apps: 0x8,
+            database_compact_ticks: 0x100,
+            self_test_stage_ticks: 0x10,
+            friend_incoming_queue_len: 0x4,
+            directory_fetch_ticks: 0x40,
+            reliability_decay_ticks: 0x100,
+            deadlines_granularity_ticks: 0x4,
+            completed_cache_limits: CacheLimits {
+                max_entries: 0x40,
+                max_age_ticks: 0x100,
+            },
+            payment_timings_cache_limits: CacheLimits {
+                max_entries: 0x40,
+                max_age_ticks: 0x100,
+            },
+            trusted_apps_reload_ticks: 0x40,
+            index_reconcile_ticks: 0x40,
+            index_route_cache_limits: CacheLimits {
+                max_entries: 0x40,
+                max_age_ticks: 0x10,
+            },
+            slow_funder_event_limits: SlowEventLimits::default(),
+        }
+    }
+
+    async fn task_self_test_broken_signer(mut test_executor: TestExecutor) {
+        // A timer that never ticks:
+        let (_tick_sender, tick_receiver) = mpsc::channel(0);
+        let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+        let identity = BrokenSignerIdentity {
+            identity: fixture_software_identity(1),
+        };
+        let (requests_sender, identity_server) = create_identity(identity);
+        test_executor.spawn(identity_server).unwrap();
+        let identity_client = IdentityClient::new(requests_sender);
+
+        let stage_reports = await!(self_test(
+            dummy_node_config(),
+            identity_client,
+            timer_client,
+            DummyRandom::new(&[2u8]),
+            test_executor.clone()
+        ));
+
+        // The self test stops right after the identity stage:
+        assert_eq!(
+            stage_reports,
+            vec![SelfTestStageReport {
+                stage: SelfTestStage::Identity,
+                success: false,
+                ticks: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_self_test_broken_signer() {
+        let test_executor = TestExecutor::new();
+        let res = test_executor.run(task_self_test_broken_signer(test_executor.clone()));
+        assert!(res.is_output());
+    }
+}
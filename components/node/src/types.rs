@@ -1,9 +1,13 @@
+use serde::de::DeserializeOwned;
+
+use common::bounded_cache::CacheLimits;
 use common::canonical_serialize::CanonicalSerialize;
 use common::mutable_state::MutableState;
 
 use crypto::identity::PublicKey;
+use funder::quarantine::StoredFunderState;
 use funder::report::create_initial_report;
-use funder::{FunderMutation, FunderState};
+use funder::{FunderMutation, FunderState, SlowEventLimits};
 use index_client::{IndexClientConfig, IndexClientConfigMutation};
 
 use proto::app_server::messages::NodeReport;
@@ -16,11 +20,20 @@ pub enum NodeMutation<B: Clone> {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(bound(deserialize = "B: Clone + serde::de::DeserializeOwned"))]
 pub struct NodeState<B: Clone> {
     pub funder_state: FunderState<B>,
     pub index_client_config: IndexClientConfig<B>,
 }
 
+/// The stored representation of a `NodeState`, used for loading with quarantine.
+/// Must have the same fields (In the same order) as `NodeState`.
+#[derive(Deserialize)]
+struct StoredNodeState<B: Clone> {
+    funder_state: StoredFunderState<B>,
+    index_client_config: IndexClientConfig<B>,
+}
+
 impl<B> NodeState<B>
 where
     B: Clone + CanonicalSerialize,
@@ -33,6 +46,21 @@ where
     }
 }
 
+impl<B> NodeState<B>
+where
+    B: Clone + CanonicalSerialize + DeserializeOwned,
+{
+    /// Deserialize a stored NodeState. Friends with a corrupted state are moved into quarantine,
+    /// instead of failing the whole deserialization.
+    pub fn deserialize_quarantine(serialized: &[u8]) -> Result<Self, bincode::Error> {
+        let stored_node_state: StoredNodeState<B> = bincode::deserialize(serialized)?;
+        Ok(NodeState {
+            funder_state: stored_node_state.funder_state.quarantine_corrupt(),
+            index_client_config: stored_node_state.index_client_config,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct NodeMutateError;
 
@@ -66,6 +94,7 @@ where
         index_servers: index_client_config.index_servers.clone(),
         // Initially we are not connected to a server:
         opt_connected_server: None,
+        num_corrections: 0,
     }
 }
 
@@ -90,11 +119,23 @@ pub struct NodeConfig {
     pub keepalive_ticks: usize,
     /// Amount of ticks to wait until the next rekeying (Channel encryption)
     pub ticks_to_rekey: usize,
+    /// Amount of messages sent over a relay or friend secure channel before rekeying.
+    /// None means rekeying happens only every `ticks_to_rekey` ticks.
+    pub max_messages_before_rekey: Option<u64>,
+    /// Amount of ticks a closed secure channel with a friend may be resumed using a ticket,
+    /// without a full exchange.
+    pub session_resume_ticks: usize,
     /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
     /// time from external communications (Channeler side)
     pub max_concurrent_encrypt: usize,
     /// The amount of ticks we are willing to wait until a connection is established.
     pub conn_timeout_ticks: usize,
+    /// Connect to friends through the same relay over a single multiplexed connection to the
+    /// relay. All the relays of our friends must support multiplexed connections.
+    pub relay_mux: bool,
+    /// Minimum amount of operations in one move token message, used for friends with a short
+    /// token round trip time
+    pub min_operations_in_batch: usize,
     /// Maximum amount of operations in one move token message
     pub max_operations_in_batch: usize,
     /// The size we allocate for the user send funds requests queue.
@@ -103,7 +144,46 @@ pub struct NodeConfig {
     pub max_open_index_client_requests: usize,
     /// Maximum amount of relays a node may use.
     pub max_node_relays: usize,
+    /// Minimum amount of ticks between two applied changes of a friend's relays
+    pub friend_relays_damping_ticks: usize,
+    /// Minimum amount of ticks between two requests for the token of a friend, issued because
+    /// of pre-warm requests
+    pub friend_prewarm_ticks: usize,
     /// Maximum amount of encryption set ups we allow to occur at the same time
     /// for incoming app connections
     pub max_concurrent_incoming_apps: usize,
+    /// Amount of ticks between two periodic compactions of the database
+    pub database_compact_ticks: usize,
+    /// Maximum amount of ticks a single stage of a self test may take
+    pub self_test_stage_ticks: usize,
+    /// Amount of incoming messages from a friend that may wait for processing.
+    /// Further messages are not read from the friend's connection until processing catches up.
+    pub friend_incoming_queue_len: usize,
+    /// Amount of ticks between two fetches of the directory document
+    pub directory_fetch_ticks: usize,
+    /// Amount of ticks after which recorded payment outcomes of remote nodes lose half of their
+    /// weight
+    pub reliability_decay_ticks: usize,
+    /// Reported deadlines of friends are only updated when they change by at least this amount
+    /// of ticks
+    pub deadlines_granularity_ticks: usize,
+    /// Limits for the recently completed requests remembered for every friend.
+    /// An early eviction might cause an unnecessary channel reset, if the friend retransmits a
+    /// response for a completed request after a reset.
+    pub completed_cache_limits: CacheLimits,
+    /// Limits for the start ticks of pending payments we originate.
+    /// An early eviction only loses the latency measurement of a payment.
+    pub payment_timings_cache_limits: CacheLimits,
+    /// Amount of ticks between two reloads of the trusted apps.
+    /// Connected apps that are no longer trusted are disconnected.
+    pub trusted_apps_reload_ticks: usize,
+    /// Amount of ticks between two comparisons of the capacities sent to the index server
+    /// against the current capacities of our friends
+    pub index_reconcile_ticks: usize,
+    /// Limits for the recent route responses of index servers remembered by the index client.
+    /// A `max_age_ticks` of 0 disables the cache.
+    pub index_route_cache_limits: CacheLimits,
+    /// Incoming events the funder takes longer than a threshold to handle are logged, together
+    /// with the time spent in every phase of the handling.
+    pub slow_funder_event_limits: SlowEventLimits,
 }
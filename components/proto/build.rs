@@ -17,6 +17,7 @@ fn main() {
         "src/schema/keepalive.capnp",
         "src/schema/app_server.capnp",
         "src/schema/report.capnp",
-        "src/schema/index.capnp"
+        "src/schema/index.capnp",
+        "src/schema/directory.capnp"
     }
 }
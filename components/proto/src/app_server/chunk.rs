@@ -0,0 +1,378 @@
+use common::int_convert::usize_to_u32;
+
+use crate::app_server::messages::{AppServerToApp, AppServerToAppFrame, TransferChunk};
+use crate::app_server::serialize::{
+    deserialize_app_server_to_app, serialize_app_server_to_app, serialize_app_server_to_app_frame,
+};
+use crate::consts::{APP_CHUNK_SIZE, APP_CHUNK_THRESHOLD, MAX_APP_TRANSFER_CHUNKS};
+use crate::serialize::SerializeError;
+
+/// Serialize a message sent from the app server to an app into frames.
+///
+/// A message of at most APP_CHUNK_THRESHOLD bytes is sent as a single frame, with exactly the
+/// bytes returned by `serialize_app_server_to_app()`. A larger message is split into chunks of
+/// APP_CHUNK_SIZE bytes, all carrying `transfer_id`.
+pub fn app_server_to_app_frames(
+    app_server_to_app: &AppServerToApp,
+    transfer_id: u64,
+) -> Vec<Vec<u8>> {
+    let data = serialize_app_server_to_app(app_server_to_app);
+    if data.len() <= APP_CHUNK_THRESHOLD {
+        return vec![data];
+    }
+
+    let chunks_data = data.chunks(APP_CHUNK_SIZE).collect::<Vec<_>>();
+    let total = usize_to_u32(chunks_data.len()).unwrap();
+    chunks_data
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk_data)| {
+            serialize_app_server_to_app_frame(&AppServerToAppFrame::Chunk(TransferChunk {
+                transfer_id,
+                index: usize_to_u32(index).unwrap(),
+                total,
+                data: chunk_data.to_vec(),
+            }))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum ChunkError {
+    /// A chunk arrived out of order: Some chunks are missing or were reordered.
+    OutOfOrder,
+    /// A chunk does not belong to the transfer in progress.
+    TransferIdMismatch,
+    /// The total amount of chunks changed in the middle of a transfer.
+    TotalMismatch,
+    /// A transfer without chunks, or with more than MAX_APP_TRANSFER_CHUNKS chunks.
+    InvalidTotal,
+    /// A chunk that carries more than APP_CHUNK_SIZE bytes.
+    ChunkTooLarge,
+    /// A complete message arrived in the middle of a transfer.
+    MissingChunks,
+    /// The sender aborted the transfer.
+    Aborted(u64),
+    /// The reassembled message could not be deserialized.
+    DeserializeError(SerializeError),
+}
+
+struct PendingTransfer {
+    transfer_id: u64,
+    total: u32,
+    next_index: u32,
+    data: Vec<u8>,
+}
+
+/// Reassembles messages sent from the app server from received frames.
+/// Only complete messages are ever returned.
+pub struct ChunkAssembler {
+    opt_pending: Option<PendingTransfer>,
+}
+
+impl ChunkAssembler {
+    pub fn new() -> Self {
+        ChunkAssembler { opt_pending: None }
+    }
+
+    /// Process a received frame.
+    /// Returns a message if the frame completed one.
+    ///
+    /// After an error the transfer in progress is left in place, so that the caller may `abort()`
+    /// it.
+    pub fn push_frame(
+        &mut self,
+        frame: AppServerToAppFrame,
+    ) -> Result<Option<AppServerToApp>, ChunkError> {
+        match frame {
+            AppServerToAppFrame::Message(app_server_to_app) => {
+                if self.opt_pending.is_some() {
+                    return Err(ChunkError::MissingChunks);
+                }
+                Ok(Some(app_server_to_app))
+            }
+            AppServerToAppFrame::Chunk(transfer_chunk) => self.push_chunk(transfer_chunk),
+            AppServerToAppFrame::AbortTransfer(transfer_id) => match &self.opt_pending {
+                Some(pending) if pending.transfer_id == transfer_id => {
+                    self.opt_pending = None;
+                    Err(ChunkError::Aborted(transfer_id))
+                }
+                // The transfer was already completed (or aborted by us):
+                _ => Ok(None),
+            },
+        }
+    }
+
+    fn push_chunk(
+        &mut self,
+        transfer_chunk: TransferChunk,
+    ) -> Result<Option<AppServerToApp>, ChunkError> {
+        if self.opt_pending.is_none() {
+            if transfer_chunk.index != 0 {
+                return Err(ChunkError::OutOfOrder);
+            }
+            // Note that we don't preallocate according to total. We don't trust the sender.
+            self.opt_pending = Some(PendingTransfer {
+                transfer_id: transfer_chunk.transfer_id,
+                total: transfer_chunk.total,
+                next_index: 0,
+                data: Vec::new(),
+            });
+            if transfer_chunk.total == 0 || transfer_chunk.total > MAX_APP_TRANSFER_CHUNKS {
+                return Err(ChunkError::InvalidTotal);
+            }
+        }
+
+        let pending = match &mut self.opt_pending {
+            Some(pending) => pending,
+            None => unreachable!(),
+        };
+
+        if transfer_chunk.transfer_id != pending.transfer_id {
+            return Err(ChunkError::TransferIdMismatch);
+        }
+        if transfer_chunk.total != pending.total {
+            return Err(ChunkError::TotalMismatch);
+        }
+        if transfer_chunk.index != pending.next_index {
+            return Err(ChunkError::OutOfOrder);
+        }
+        if transfer_chunk.data.len() > APP_CHUNK_SIZE {
+            return Err(ChunkError::ChunkTooLarge);
+        }
+
+        pending.data.extend_from_slice(&transfer_chunk.data);
+        pending.next_index += 1;
+        if pending.next_index < pending.total {
+            return Ok(None);
+        }
+
+        // The transfer is complete:
+        let data = match self.opt_pending.take() {
+            Some(pending) => pending.data,
+            None => unreachable!(),
+        };
+        deserialize_app_server_to_app(&data)
+            .map(Some)
+            .map_err(ChunkError::DeserializeError)
+    }
+
+    /// Discard the transfer in progress.
+    /// Returns the id of the discarded transfer, if there was one.
+    pub fn abort(&mut self) -> Option<u64> {
+        self.opt_pending.take().map(|pending| pending.transfer_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryInto;
+
+    use im::hashmap::HashMap as ImHashMap;
+
+    use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+
+    use crate::app_server::messages::{NodeReport, RelayAddress};
+    use crate::app_server::serialize::deserialize_app_server_to_app_frame;
+    use crate::consts::MAX_FRAME_LENGTH;
+    use crate::index_client::messages::IndexClientReport;
+    use crate::report::messages::{
+        ChannelStatusReport, DirectionReport, FriendDeadlinesReport, FriendLivenessReport,
+        FriendReport, FriendStatusReport, FunderReport, McBalanceReport, McRequestsStatusReport,
+        RequestsStatusReport, SentLocalRelaysReport, TcReport, VerificationStatusReport,
+    };
+
+    /// A public key that does not compress well
+    fn public_key(index: u16) -> PublicKey {
+        let mut public_key = [0xab; PUBLIC_KEY_LEN];
+        public_key[0..2].copy_from_slice(&index.to_le_bytes());
+        PublicKey::from(&public_key)
+    }
+
+    fn friend_report(index: u16) -> FriendReport {
+        FriendReport {
+            name: format!("friend{}", index),
+            remote_relays: vec![RelayAddress {
+                public_key: public_key(index.wrapping_add(1)),
+                address: format!("relay{}:1337", index).try_into().unwrap(),
+            }],
+            sent_local_relays: SentLocalRelaysReport::NeverSent,
+            opt_last_incoming_move_token: None,
+            liveness: FriendLivenessReport::Online,
+            channel_status: ChannelStatusReport::Consistent(TcReport {
+                direction: DirectionReport::Incoming,
+                move_token_counter: 0,
+                inconsistency_counter: 0,
+                balance: McBalanceReport {
+                    balance: -i128::from(index),
+                    local_max_debt: 100,
+                    remote_max_debt: u128::from(index),
+                    local_pending_debt: 0,
+                    remote_pending_debt: 0,
+                },
+                requests_status: McRequestsStatusReport {
+                    local: RequestsStatusReport::Open,
+                    remote: RequestsStatusReport::Closed,
+                },
+                num_local_pending_requests: 0,
+                num_remote_pending_requests: 0,
+            }),
+            wanted_remote_max_debt: u128::from(index),
+            wanted_local_requests_status: RequestsStatusReport::Open,
+            num_pending_requests: 0,
+            num_pending_responses: 0,
+            status: FriendStatusReport::Enabled,
+            num_pending_user_requests: 0,
+            pending_payments: Vec::new(),
+            opt_protocol_violation: None,
+            verification_status: VerificationStatusReport::Unverified,
+            deadlines: FriendDeadlinesReport::default(),
+            index_private: false,
+        }
+    }
+
+    fn node_report(num_friends: u16) -> NodeReport {
+        let friends = (0..num_friends)
+            .map(|index| (public_key(index), friend_report(index)))
+            .collect::<ImHashMap<_, _>>();
+
+        NodeReport {
+            funder_report: FunderReport {
+                local_public_key: public_key(0xffff),
+                relays: Default::default(),
+                friends,
+                num_ready_receipts: 0,
+                quarantined_friends: Default::default(),
+                dust_thresholds: Default::default(),
+                directory: Default::default(),
+                reliability: Default::default(),
+                read_only: false,
+                labeled_payments: Default::default(),
+            },
+            index_client_report: IndexClientReport {
+                index_servers: Vec::new(),
+                opt_connected_server: None,
+                num_corrections: 0,
+            },
+        }
+    }
+
+    /// Deserialize and push all frames. Returns the last result.
+    fn push_frames(
+        assembler: &mut ChunkAssembler,
+        frames: &[Vec<u8>],
+    ) -> Result<Option<AppServerToApp>, ChunkError> {
+        let mut res = Ok(None);
+        for frame in frames {
+            let frame = deserialize_app_server_to_app_frame(frame).unwrap();
+            res = assembler.push_frame(frame);
+            if res.is_err() {
+                break;
+            }
+        }
+        res
+    }
+
+    #[test]
+    fn test_small_message_not_chunked() {
+        let app_server_to_app = AppServerToApp::Report(node_report(4));
+        let frames = app_server_to_app_frames(&app_server_to_app, 0);
+
+        // The bytes on the wire are the same as without chunking:
+        assert_eq!(
+            frames,
+            vec![serialize_app_server_to_app(&app_server_to_app)]
+        );
+
+        let mut assembler = ChunkAssembler::new();
+        let res = push_frames(&mut assembler, &frames).unwrap().unwrap();
+        assert_eq!(res, app_server_to_app);
+    }
+
+    #[test]
+    fn test_large_report_chunked() {
+        let app_server_to_app = AppServerToApp::Report(node_report(10_000));
+        assert!(serialize_app_server_to_app(&app_server_to_app).len() > APP_CHUNK_THRESHOLD);
+
+        let frames = app_server_to_app_frames(&app_server_to_app, 7);
+        assert!(frames.len() > 1);
+        for frame in &frames {
+            assert!(frame.len() < MAX_FRAME_LENGTH);
+        }
+
+        let mut assembler = ChunkAssembler::new();
+        // Only the last frame completes the message:
+        let (last_frame, first_frames) = frames.split_last().unwrap();
+        assert!(push_frames(&mut assembler, first_frames).unwrap().is_none());
+        let res = push_frames(&mut assembler, &[last_frame.clone()])
+            .unwrap()
+            .unwrap();
+        assert_eq!(res, app_server_to_app);
+
+        // The assembler can be used for the next message:
+        let res = push_frames(&mut assembler, &frames).unwrap().unwrap();
+        assert_eq!(res, app_server_to_app);
+    }
+
+    #[test]
+    fn test_missing_chunks() {
+        let app_server_to_app = AppServerToApp::Report(node_report(10_000));
+        let frames = app_server_to_app_frames(&app_server_to_app, 3);
+        assert!(frames.len() > 2);
+
+        // Reordered chunks:
+        let mut reordered_frames = frames.clone();
+        reordered_frames.swap(0, 1);
+        let mut assembler = ChunkAssembler::new();
+        match push_frames(&mut assembler, &reordered_frames) {
+            Err(ChunkError::OutOfOrder) => {}
+            _ => unreachable!(),
+        };
+
+        // A missing chunk in the middle:
+        let mut missing_frames = frames.clone();
+        missing_frames.remove(1);
+        let mut assembler = ChunkAssembler::new();
+        match push_frames(&mut assembler, &missing_frames) {
+            Err(ChunkError::OutOfOrder) => {}
+            _ => unreachable!(),
+        };
+        assert_eq!(assembler.abort(), Some(3));
+
+        // A complete message arrives before the last chunk:
+        let small_frames = app_server_to_app_frames(&AppServerToApp::Report(node_report(1)), 4);
+        let mut truncated_frames = frames[..frames.len() - 1].to_vec();
+        truncated_frames.extend(small_frames);
+        let mut assembler = ChunkAssembler::new();
+        match push_frames(&mut assembler, &truncated_frames) {
+            Err(ChunkError::MissingChunks) => {}
+            _ => unreachable!(),
+        };
+        assert_eq!(assembler.abort(), Some(3));
+        assert_eq!(assembler.abort(), None);
+    }
+
+    #[test]
+    fn test_aborted_transfer() {
+        let app_server_to_app = AppServerToApp::Report(node_report(10_000));
+        let mut frames = app_server_to_app_frames(&app_server_to_app, 5);
+        frames.truncate(2);
+        frames.push(serialize_app_server_to_app_frame(
+            &AppServerToAppFrame::AbortTransfer(5),
+        ));
+
+        let mut assembler = ChunkAssembler::new();
+        match push_frames(&mut assembler, &frames) {
+            Err(ChunkError::Aborted(5)) => {}
+            _ => unreachable!(),
+        };
+        // Nothing is left of the aborted transfer:
+        assert_eq!(assembler.abort(), None);
+
+        // An abort of a transfer that is not in progress is ignored:
+        let frame = AppServerToAppFrame::AbortTransfer(6);
+        assert!(assembler.push_frame(frame).unwrap().is_none());
+    }
+}
@@ -0,0 +1,390 @@
+use std::io::{self, Read};
+
+use byteorder::{BigEndian, ReadBytesExt};
+
+use bincode;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crypto::identity::{PublicKey, Signature};
+use crypto::uid::Uid;
+
+use crate::app_server::messages::NodeReport;
+use crate::net::messages::NetAddress;
+use crate::report::messages::{ChannelStatusReport, FriendReport};
+use crate::signed_export::{create_export_body, verify_export, SignedExportError, VerifiedExport};
+
+/// Every serialized debug bundle begins with these bytes.
+pub const DEBUG_BUNDLE_MAGIC: &[u8; 8] = b"OFSTDBG\0";
+
+/// Version of the debug bundle format.
+/// Should be incremented whenever the contents of `DebugBundle` change.
+pub const DEBUG_BUNDLE_VERSION: u32 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DebugBundleInfo {
+    /// Version of the node software that created the bundle
+    pub node_version: String,
+    pub protocol_version: u32,
+    /// Were tokens and payment labels removed from the bundle?
+    pub redacted: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AppSessionEventKind {
+    /// A new session was opened
+    Opened,
+    /// A disconnected session was resumed by a new connection
+    Resumed,
+    /// The connection of a session was replaced by a newer connection of the same session
+    Replaced,
+    /// The connection of a session was closed. The session may be resumed later.
+    Detached,
+    /// A disconnected session was dropped to make room for other disconnected sessions
+    Expired,
+    /// The app is no longer trusted, and the session was closed
+    Revoked,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AppSessionEvent {
+    pub app_public_key: PublicKey,
+    pub session_id: Uid,
+    pub kind: AppSessionEventKind,
+}
+
+/// A snapshot of the state of a node, sent to the maintainers when reporting a bug.
+/// All the contents of a bundle are collected at the same point in time.
+///
+/// A bundle never contains private keys. Tokens (Signatures that prove a balance or allow to
+/// reset a channel) and payment labels are redacted unless explicitly requested.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DebugBundle<B = NetAddress>
+where
+    B: Clone,
+{
+    pub info: DebugBundleInfo,
+    pub node_report: NodeReport<B>,
+    /// Recent app session events, oldest first
+    pub app_session_events: Vec<AppSessionEvent>,
+}
+
+#[derive(Debug)]
+pub enum DebugBundleError {
+    /// The data does not begin with DEBUG_BUNDLE_MAGIC
+    InvalidMagic,
+    /// The bundle was created using an unknown version of the format
+    UnsupportedVersion(u32),
+    /// Length of the contents does not match the length written in the header
+    InvalidLength,
+    /// The bundle was modified after it was signed by the node
+    InvalidSignature,
+    IoError(io::Error),
+    BincodeError(bincode::Error),
+}
+
+impl From<io::Error> for DebugBundleError {
+    fn from(e: io::Error) -> Self {
+        DebugBundleError::IoError(e)
+    }
+}
+
+impl From<SignedExportError> for DebugBundleError {
+    fn from(e: SignedExportError) -> Self {
+        match e {
+            SignedExportError::InvalidLength => DebugBundleError::InvalidLength,
+            SignedExportError::InvalidSignature => DebugBundleError::InvalidSignature,
+        }
+    }
+}
+
+impl From<bincode::Error> for DebugBundleError {
+    fn from(e: bincode::Error) -> Self {
+        DebugBundleError::BincodeError(e)
+    }
+}
+
+fn redact_friend_report<B>(friend_report: &mut FriendReport<B>)
+where
+    B: Clone,
+{
+    if let Some(move_token_hashed) = &mut friend_report.opt_last_incoming_move_token {
+        move_token_hashed.new_token = Signature::zero();
+    }
+    if let ChannelStatusReport::Inconsistent(channel_inconsistent) =
+        &mut friend_report.channel_status
+    {
+        if let Some(reset_terms) = &mut channel_inconsistent.opt_remote_reset_terms {
+            reset_terms.reset_token = Signature::zero();
+        }
+    }
+}
+
+/// Replace all the tokens inside a node report with zero signatures, and remove all the payment
+/// labels.
+pub fn redact_node_report<B>(node_report: &mut NodeReport<B>)
+where
+    B: Clone,
+{
+    node_report.funder_report.friends = node_report
+        .funder_report
+        .friends
+        .iter()
+        .map(|(friend_public_key, friend_report)| {
+            let mut friend_report = friend_report.clone();
+            redact_friend_report(&mut friend_report);
+            (friend_public_key.clone(), friend_report)
+        })
+        .collect();
+
+    for labeled_payment in node_report.funder_report.labeled_payments.iter_mut() {
+        labeled_payment.label = Vec::new();
+    }
+}
+
+/// Serialize a debug bundle into the body of a signed export (See `signed_export`).
+/// The body must be signed by the node before the bundle can be deserialized.
+///
+/// Format: DEBUG_BUNDLE_MAGIC, followed by the version of the format (u32), the length of the
+/// contents (u64) and the contents themselves.
+pub fn serialize_debug_bundle<B>(debug_bundle: &DebugBundle<B>) -> Vec<u8>
+where
+    B: Clone + Serialize,
+{
+    // Serializing a bundle into memory should never fail:
+    let contents = bincode::serialize(debug_bundle).unwrap();
+    create_export_body(DEBUG_BUNDLE_MAGIC, DEBUG_BUNDLE_VERSION, &contents)
+}
+
+/// Check the header of a signed debug bundle, and verify its signature.
+fn verify_debug_bundle_export(data: &[u8]) -> Result<VerifiedExport, DebugBundleError> {
+    let mut cursor = io::Cursor::new(data);
+
+    let mut magic = [0u8; 8];
+    cursor
+        .read_exact(&mut magic)
+        .map_err(|_| DebugBundleError::InvalidMagic)?;
+    if magic != *DEBUG_BUNDLE_MAGIC {
+        return Err(DebugBundleError::InvalidMagic);
+    }
+
+    let version = cursor.read_u32::<BigEndian>()?;
+    if version != DEBUG_BUNDLE_VERSION {
+        return Err(DebugBundleError::UnsupportedVersion(version));
+    }
+
+    Ok(verify_export(data)?)
+}
+
+/// Verify that a signed debug bundle was not modified or truncated.
+/// Returns the public key of the node that created the bundle.
+pub fn verify_debug_bundle(data: &[u8]) -> Result<PublicKey, DebugBundleError> {
+    Ok(verify_debug_bundle_export(data)?.public_key)
+}
+
+/// Deserialize a signed debug bundle. Fails if the signature of the bundle is invalid.
+pub fn deserialize_debug_bundle<B>(data: &[u8]) -> Result<DebugBundle<B>, DebugBundleError>
+where
+    B: Clone + DeserializeOwned,
+{
+    let verified_export = verify_debug_bundle_export(data)?;
+    Ok(bincode::deserialize(verified_export.contents)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use im::hashmap::HashMap as ImHashMap;
+    use im::vector::Vector as ImVec;
+
+    use crypto::crypto_rand::{RandValue, RAND_VALUE_LEN};
+    use crypto::hash::{HashResult, HASH_RESULT_LEN};
+    use crypto::identity::{Identity, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+    use crypto::test_utils::fixture_software_identity;
+    use crypto::uid::UID_LEN;
+
+    use crate::funder::messages::LabeledPayment;
+    use crate::index_client::messages::IndexClientReport;
+    use crate::report::messages::{
+        ChannelInconsistentReport, FriendDeadlinesReport, FriendLivenessReport, FriendStatusReport,
+        FunderReport, MoveTokenHashedReport, RequestsStatusReport, ResetTermsReport,
+        SentLocalRelaysReport, VerificationStatusReport,
+    };
+    use crate::signed_export::{sign_export, EXPORT_FOOTER_LEN, EXPORT_HEADER_LEN};
+
+    fn create_node_report() -> NodeReport<u32> {
+        let local_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let friend_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        let friend_report = FriendReport {
+            name: "friend".to_owned(),
+            remote_relays: Vec::new(),
+            sent_local_relays: SentLocalRelaysReport::NeverSent,
+            opt_last_incoming_move_token: Some(MoveTokenHashedReport {
+                prefix_hash: HashResult::from(&[1; HASH_RESULT_LEN]),
+                local_public_key: local_public_key.clone(),
+                remote_public_key: friend_public_key.clone(),
+                inconsistency_counter: 1,
+                move_token_counter: 2,
+                balance: -5,
+                local_pending_debt: 0,
+                remote_pending_debt: 0,
+                rand_nonce: RandValue::from(&[2; RAND_VALUE_LEN]),
+                new_token: Signature::from(&[3; SIGNATURE_LEN]),
+            }),
+            liveness: FriendLivenessReport::Online,
+            channel_status: ChannelStatusReport::Inconsistent(ChannelInconsistentReport {
+                local_reset_terms_balance: -5,
+                opt_remote_reset_terms: Some(ResetTermsReport {
+                    reset_token: Signature::from(&[4; SIGNATURE_LEN]),
+                    balance_for_reset: 5,
+                }),
+            }),
+            wanted_remote_max_debt: 100,
+            wanted_local_requests_status: RequestsStatusReport::Open,
+            num_pending_requests: 0,
+            num_pending_responses: 0,
+            status: FriendStatusReport::Enabled,
+            num_pending_user_requests: 0,
+            pending_payments: Vec::new(),
+            opt_protocol_violation: None,
+            verification_status: VerificationStatusReport::Unverified,
+            deadlines: FriendDeadlinesReport {
+                opt_reconnect_ticks: Some(12),
+                opt_response_timeout_ticks: None,
+                opt_remote_max_debt_expiry_ticks: Some(30),
+            },
+            index_private: true,
+        };
+
+        let mut friends = ImHashMap::new();
+        friends.insert(friend_public_key, friend_report);
+
+        let mut labeled_payments = ImVec::new();
+        labeled_payments.push_back(LabeledPayment {
+            request_id: Uid::from(&[5; UID_LEN]),
+            label: b"order-5".to_vec(),
+            opt_result: None,
+        });
+
+        NodeReport {
+            funder_report: FunderReport {
+                local_public_key,
+                relays: ImVec::new(),
+                friends,
+                num_ready_receipts: 0,
+                quarantined_friends: Default::default(),
+                dust_thresholds: Default::default(),
+                directory: Default::default(),
+                reliability: Default::default(),
+                read_only: false,
+                labeled_payments,
+            },
+            index_client_report: IndexClientReport {
+                index_servers: Vec::new(),
+                opt_connected_server: None,
+                num_corrections: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_serialize_debug_bundle() {
+        let debug_bundle = DebugBundle {
+            info: DebugBundleInfo {
+                node_version: "0.1.0".to_owned(),
+                protocol_version: 0,
+                redacted: false,
+            },
+            node_report: create_node_report(),
+            app_session_events: vec![AppSessionEvent {
+                app_public_key: PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]),
+                session_id: Uid::from(&[1; UID_LEN]),
+                kind: AppSessionEventKind::Opened,
+            }],
+        };
+
+        let identity = fixture_software_identity(1);
+        let data = sign_export(serialize_debug_bundle(&debug_bundle), &identity);
+        let debug_bundle2 = deserialize_debug_bundle::<u32>(&data).unwrap();
+        assert_eq!(debug_bundle, debug_bundle2);
+        assert_eq!(
+            verify_debug_bundle(&data).unwrap(),
+            identity.get_public_key()
+        );
+
+        // A bundle that was not signed:
+        assert!(deserialize_debug_bundle::<u32>(&serialize_debug_bundle(&debug_bundle)).is_err());
+
+        // Flipping any byte of the contents breaks the signature:
+        for i in EXPORT_HEADER_LEN..data.len() - EXPORT_FOOTER_LEN {
+            let mut data2 = data.clone();
+            data2[i] ^= 0x01;
+            match deserialize_debug_bundle::<u32>(&data2) {
+                Err(DebugBundleError::InvalidSignature) => {}
+                res => panic!("Unexpected result: {:?}", res),
+            };
+        }
+
+        // A redacted bundle is signed in the same way:
+        let mut redacted_debug_bundle = debug_bundle.clone();
+        redacted_debug_bundle.info.redacted = true;
+        redact_node_report(&mut redacted_debug_bundle.node_report);
+        let redacted_data = sign_export(serialize_debug_bundle(&redacted_debug_bundle), &identity);
+        assert_eq!(
+            deserialize_debug_bundle::<u32>(&redacted_data).unwrap(),
+            redacted_debug_bundle
+        );
+        assert_eq!(
+            verify_debug_bundle(&redacted_data).unwrap(),
+            identity.get_public_key()
+        );
+
+        // Truncated bundle:
+        match deserialize_debug_bundle::<u32>(&data[..data.len() - 1]) {
+            Err(DebugBundleError::InvalidLength) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        };
+
+        // Unknown format version:
+        let mut data2 = data.clone();
+        data2[DEBUG_BUNDLE_MAGIC.len() + 3] = 0xff;
+        match deserialize_debug_bundle::<u32>(&data2) {
+            Err(DebugBundleError::UnsupportedVersion(0xff)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        };
+
+        // Not a debug bundle:
+        match deserialize_debug_bundle::<u32>(&data[1..]) {
+            Err(DebugBundleError::InvalidMagic) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        };
+    }
+
+    #[test]
+    fn test_redact_node_report() {
+        let mut node_report = create_node_report();
+        redact_node_report(&mut node_report);
+
+        let friend_report = node_report.funder_report.friends.values().next().unwrap();
+        let move_token_hashed = friend_report.opt_last_incoming_move_token.as_ref().unwrap();
+        assert_eq!(move_token_hashed.new_token, Signature::zero());
+        assert_eq!(move_token_hashed.balance, -5);
+        match &friend_report.channel_status {
+            ChannelStatusReport::Inconsistent(channel_inconsistent) => {
+                let reset_terms = channel_inconsistent
+                    .opt_remote_reset_terms
+                    .as_ref()
+                    .unwrap();
+                assert_eq!(reset_terms.reset_token, Signature::zero());
+                assert_eq!(reset_terms.balance_for_reset, 5);
+            }
+            ChannelStatusReport::Consistent(_) => unreachable!(),
+        };
+
+        let labeled_payment = node_report.funder_report.labeled_payments.front().unwrap();
+        assert_eq!(labeled_payment.request_id, Uid::from(&[5; UID_LEN]));
+        assert!(labeled_payment.label.is_empty());
+    }
+}
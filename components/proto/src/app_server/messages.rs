@@ -1,16 +1,25 @@
+use std::collections::HashSet;
+
 use common::canonical_serialize::CanonicalSerialize;
 use common::mutable_state::MutableState;
 use crypto::identity::PublicKey;
 use crypto::uid::Uid;
 
+use crate::consts::MAX_NET_ADDRESS_LENGTH;
+use crate::directory::messages::DirectorySubscription;
 use crate::funder::messages::{
-    AddFriend, ReceiptAck, ResetFriendChannel, ResponseReceived, SetFriendName, SetFriendRelays,
-    SetFriendRemoteMaxDebt, UserRequestSendFunds,
+    AddFriend, DustThresholds, Goodbye, IncomingPayment, LabeledPayment, PaymentNotifier,
+    PrewarmFriend, ReceiptAck, RequestPairFreezeUsage, ResetFriendChannel,
+    ResponseCancelUserRequest, ResponsePairFreezeUsage, ResponsePendingSubmissions,
+    ResponsePrewarm, ResponseReceived, SetFriendIndexPrivate, SetFriendName, SetFriendRelays,
+    SetFriendRemoteMaxDebt, SetFriendResponseDeadline, SetFriendVerificationPhrase,
+    SetPairFreezeLimit, UserRequestSendFunds, UserRequestSweepFunds,
 };
 use crate::index_client::messages::{
     ClientResponseRoutes, IndexClientReport, IndexClientReportMutation,
 };
 use crate::index_server::messages::{NamedIndexServerAddress, RequestRoutes};
+use crate::invite::messages::{ImportFriendInvite, ResponseImportFriendInvite};
 use crate::net::messages::NetAddress;
 use crate::report::messages::{FunderReport, FunderReportMutation};
 
@@ -22,6 +31,9 @@ pub struct NamedRelayAddress<B = NetAddress> {
     pub name: String,
 }
 
+/// Address of a relay that a node listens on.
+/// A relay address that carries the node's own public key is a direct address: The node can be
+/// reached at `address` directly, without going through a relay.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct RelayAddress<B = NetAddress> {
     pub public_key: PublicKey,
@@ -49,7 +61,36 @@ where
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl<B> RelayAddress<B>
+where
+    B: CanonicalSerialize,
+{
+    /// Check if the relay address is sane.
+    /// The address must not be empty, and must not be longer than MAX_NET_ADDRESS_LENGTH.
+    pub fn is_valid(&self) -> bool {
+        let address_len = self.address.canonical_serialize().len();
+        address_len > 0 && address_len <= MAX_NET_ADDRESS_LENGTH
+    }
+}
+
+/// Check if a list of relays (Usually received from a remote node) is valid.
+/// A valid list contains at most `max_relays` relays, every relay is valid
+/// and no relay public key appears twice.
+pub fn is_valid_relays<B>(relays: &[RelayAddress<B>], max_relays: usize) -> bool
+where
+    B: CanonicalSerialize,
+{
+    if relays.len() > max_relays {
+        return false;
+    }
+
+    let mut seen = HashSet::new();
+    relays
+        .iter()
+        .all(|relay| relay.is_valid() && seen.insert(relay.public_key.clone()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NodeReport<B = NetAddress>
 where
     B: Clone,
@@ -67,15 +108,162 @@ where
     IndexClient(IndexClientReportMutation<B>),
 }
 
+/// The part of the node report that a report mutation changes.
+/// Mutations of different scopes may be delivered to an app out of order.
+/// Mutations of the same scope are always delivered in order.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ReportScope {
+    /// Node wide state: Relays, index servers and ready receipts.
+    Node,
+    /// The state of a single friend.
+    Friend(PublicKey),
+}
+
+impl<B> NodeReportMutation<B>
+where
+    B: Clone,
+{
+    pub fn scope(&self) -> ReportScope {
+        match self {
+            NodeReportMutation::Funder(funder_report_mutation) => match funder_report_mutation {
+                FunderReportMutation::AddFriend(add_friend_report) => {
+                    ReportScope::Friend(add_friend_report.friend_public_key.clone())
+                }
+                FunderReportMutation::RemoveFriend(friend_public_key)
+                | FunderReportMutation::FriendReportMutation((friend_public_key, _)) => {
+                    ReportScope::Friend(friend_public_key.clone())
+                }
+                FunderReportMutation::AddRelay(_)
+                | FunderReportMutation::RemoveRelay(_)
+                | FunderReportMutation::SetNumReadyReceipts(_)
+                | FunderReportMutation::SetDustThresholds(_)
+                | FunderReportMutation::SetDirectory(_)
+                | FunderReportMutation::SetReliability(_)
+                | FunderReportMutation::RemoveReliability(_)
+                | FunderReportMutation::SetReadOnly(_)
+                | FunderReportMutation::AddLabeledPayment(_)
+                | FunderReportMutation::SetLabeledPaymentResult(_) => ReportScope::Node,
+            },
+            NodeReportMutation::IndexClient(_) => ReportScope::Node,
+        }
+    }
+}
+
+/// Split a list of mutations by scope, keeping the order of mutations inside every scope.
+/// Scopes are ordered by their first appearance.
+pub fn split_by_scope<B>(
+    mutations: Vec<NodeReportMutation<B>>,
+) -> Vec<(ReportScope, Vec<NodeReportMutation<B>>)>
+where
+    B: Clone,
+{
+    let mut scoped: Vec<(ReportScope, Vec<NodeReportMutation<B>>)> = Vec::new();
+    for mutation in mutations {
+        let scope = mutation.scope();
+        match scoped.iter_mut().find(|(cur_scope, _)| cur_scope == &scope) {
+            Some((_, scope_mutations)) => scope_mutations.push(mutation),
+            None => scoped.push((scope, vec![mutation])),
+        }
+    }
+    scoped
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ReportMutations<B = NetAddress>
 where
     B: Clone,
 {
     pub opt_app_request_id: Option<Uid>,
+    /// All the mutations belong to this scope
+    pub scope: ReportScope,
+    /// Sequence number of this message inside its scope, starting from 0 for every connection.
+    pub seq: u64,
+    /// Messages of other scopes that must be applied before this one: (scope, seq).
+    pub dependencies: Vec<(ReportScope, u64)>,
     pub mutations: Vec<NodeReportMutation<B>>,
 }
 
+/// Request a debug bundle: A snapshot of the state of the node, useful for reporting bugs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestDebugBundle {
+    pub request_id: Uid,
+    /// Include tokens and payment labels in the bundle. They are redacted by default.
+    pub full: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseDebugBundle {
+    pub request_id: Uid,
+    /// A serialized debug bundle. See `debug_bundle::deserialize_debug_bundle()`.
+    pub bundle: Vec<u8>,
+}
+
+/// Labels matched by a query of labeled payments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LabelFilter {
+    Exact(Vec<u8>),
+    /// An empty prefix matches all the labels.
+    Prefix(Vec<u8>),
+}
+
+impl LabelFilter {
+    pub fn is_match(&self, label: &[u8]) -> bool {
+        match self {
+            LabelFilter::Exact(exact) => label == &exact[..],
+            LabelFilter::Prefix(prefix) => label.starts_with(prefix),
+        }
+    }
+}
+
+/// Request a page of the payments we have sent with a matching label, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestLabeledPayments {
+    pub request_id: Uid,
+    pub filter: LabelFilter,
+    /// Amount of matching payments to skip
+    pub offset: u64,
+    /// Maximum amount of matching payments to return
+    pub max_results: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseLabeledPayments {
+    pub request_id: Uid,
+    pub labeled_payments: Vec<LabeledPayment>,
+    /// More matching payments exist after the returned payments.
+    pub more: bool,
+}
+
+/// A stage of the node self test. Stages run in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTestStage {
+    /// Obtain the local public key, and check a signature of the identity
+    Identity,
+    /// Establish a token channel with a throwaway in-memory peer
+    Channel,
+    /// Extend credit to the peer, and receive credit from the peer
+    Credit,
+    /// Pay the peer, and verify the receipt
+    PaymentToPeer,
+    /// Receive a payment from the peer, and verify the receipt
+    PaymentFromPeer,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestStageReport {
+    pub stage: SelfTestStage,
+    pub success: bool,
+    /// Amount of time ticks that passed while the stage was running
+    pub ticks: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseSelfTest {
+    pub request_id: Uid,
+    /// Reports of the stages that were run. The self test stops at the first failed stage.
+    pub stages: Vec<SelfTestStageReport>,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum AppServerToApp<B = NetAddress>
 where
@@ -83,10 +271,47 @@ where
 {
     /// Funds:
     ResponseReceived(ResponseReceived),
+    ResponsePrewarm(ResponsePrewarm),
+    ResponseCancelUserRequest(ResponseCancelUserRequest),
+    ResponsePendingSubmissions(ResponsePendingSubmissions),
+    /// Friend management:
+    ResponseImportFriendInvite(ResponseImportFriendInvite),
     /// Reports about current state:
     Report(NodeReport<B>),
     ReportMutations(ReportMutations<B>),
     ResponseRoutes(ClientResponseRoutes),
+    /// Debugging:
+    ResponseDebugBundle(ResponseDebugBundle),
+    ResponseSelfTest(ResponseSelfTest),
+    ResponseLabeledPayments(ResponseLabeledPayments),
+    ResponsePairFreezeUsage(ResponsePairFreezeUsage),
+    /// An incoming payment, sent to apps that subscribed to incoming payments.
+    /// Sent again on every new subscription, until acknowledged.
+    IncomingPayment(IncomingPayment),
+}
+
+/// A chunk of a large serialized AppServerToApp message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferChunk {
+    pub transfer_id: u64,
+    /// Index of this chunk inside the transfer
+    pub index: u32,
+    /// Total amount of chunks in the transfer
+    pub total: u32,
+    pub data: Vec<u8>,
+}
+
+/// A single frame sent from the app server to an app.
+/// Messages that are too large to be sent in one frame are split into chunks.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AppServerToAppFrame<B = NetAddress>
+where
+    B: Clone,
+{
+    Message(AppServerToApp<B>),
+    Chunk(TransferChunk),
+    /// The app server stopped sending the chunks of a transfer
+    AbortTransfer(u64),
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -102,7 +327,12 @@ pub enum AppRequest<B = NetAddress> {
     RemoveRelay(PublicKey),
     /// Sending funds:
     RequestSendFunds(UserRequestSendFunds),
+    /// Send the maximum possible amount along a route:
+    RequestSweepFunds(UserRequestSweepFunds),
+    /// Withdraw a request to send funds (By its request id) that was not yet sent:
+    CancelUserRequest(Uid),
     ReceiptAck(ReceiptAck),
+    PrewarmFriend(PrewarmFriend),
     /// Friend management:
     AddFriend(AddFriend<B>),
     SetFriendRelays(SetFriendRelays<B>),
@@ -113,12 +343,48 @@ pub enum AppRequest<B = NetAddress> {
     OpenFriend(PublicKey),
     CloseFriend(PublicKey),
     SetFriendRemoteMaxDebt(SetFriendRemoteMaxDebt),
+    SetFriendResponseDeadline(SetFriendResponseDeadline),
+    SetFriendVerificationPhrase(SetFriendVerificationPhrase),
+    /// Do not advertise the capacities with a friend to index servers:
+    SetFriendIndexPrivate(SetFriendIndexPrivate),
     ResetFriendChannel(ResetFriendChannel),
+    /// Add the inviting node of a friend invite as a friend:
+    ImportFriendInvite(ImportFriendInvite<B>),
     /// Request routes from one node to another:
     RequestRoutes(RequestRoutes),
     /// Manage index servers:
     AddIndexServer(NamedIndexServerAddress<B>),
     RemoveIndexServer(PublicKey),
+    /// Manage notifications about incoming payments:
+    SetPaymentNotifier(PaymentNotifier<B>),
+    ClearPaymentNotifier,
+    /// Receive incoming payments, if the payment consumer is the node's apps:
+    SubscribeIncomingPayments,
+    AckIncomingPayment(u64), // notification_id
+    /// Debugging:
+    RequestDebugBundle(RequestDebugBundle),
+    /// Run a self test against a throwaway peer. Contains a request id.
+    RequestSelfTest(Uid),
+    /// Set the minimum payments the node is willing to handle:
+    SetDustThresholds(DustThresholds),
+    /// Subscribe to a directory of relays and index servers:
+    SetDirectory(DirectorySubscription<B>),
+    ClearDirectory,
+    /// Relays and index servers (By public key) the directory never removes:
+    PinDirectoryEntry(PublicKey),
+    UnpinDirectoryEntry(PublicKey),
+    /// Tell all the online friends that the node is about to shut down:
+    AnnounceShutdown(Goodbye),
+    /// Find payments we have sent by their labels:
+    RequestLabeledPayments(RequestLabeledPayments),
+    /// List the payments submitted by this app whose outcomes were not yet acknowledged.
+    /// Contains a request id.
+    ListPendingSubmissions(Uid),
+    /// Acknowledge the outcome of a submitted payment (By its request id):
+    AckSubmission(Uid),
+    /// Limit the credits frozen for requests forwarded between a pair of friends:
+    SetPairFreezeLimit(SetPairFreezeLimit),
+    RequestPairFreezeUsage(RequestPairFreezeUsage),
 }
 #[derive(Debug, PartialEq, Eq)]
 pub struct AppToAppServer<B = NetAddress> {
@@ -135,6 +401,14 @@ impl<B> AppToAppServer<B> {
     }
 }
 
+/// A single frame sent from an app to the app server.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AppToAppServerFrame<B = NetAddress> {
+    Message(AppToAppServer<B>),
+    /// Ask the app server to stop sending the chunks of a transfer
+    AbortTransfer(u64),
+}
+
 #[derive(Debug)]
 pub struct NodeReportMutateError;
 
@@ -178,3 +452,55 @@ pub struct AppPermissions {
     /// Can configure friends
     pub config: bool,
 }
+
+/// Sent by an app right after it receives its permissions.
+/// Connections of the same app with the same session id belong to the same session: A new
+/// connection resumes the session, receiving the responses that arrived while the session was
+/// disconnected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AppHello {
+    /// Chosen by the app. Should be random.
+    pub session_id: Uid,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    fn relay_address(i: u8, address: &str) -> RelayAddress<String> {
+        RelayAddress {
+            public_key: PublicKey::from(&[i; PUBLIC_KEY_LEN]),
+            address: address.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_relay_address_is_valid() {
+        assert!(relay_address(0, "127.0.0.1:1337").is_valid());
+        assert!(relay_address(0, &"a".repeat(MAX_NET_ADDRESS_LENGTH)).is_valid());
+        assert!(!relay_address(0, "").is_valid());
+        assert!(!relay_address(0, &"a".repeat(MAX_NET_ADDRESS_LENGTH + 1)).is_valid());
+    }
+
+    #[test]
+    fn test_is_valid_relays() {
+        let relays = vec![relay_address(0, "addr0"), relay_address(1, "addr1")];
+        assert!(is_valid_relays(&relays, 2));
+        assert!(is_valid_relays::<String>(&[], 2));
+
+        // Too many relays:
+        assert!(!is_valid_relays(&relays, 1));
+
+        // Oversized address:
+        let relays = vec![
+            relay_address(0, "addr0"),
+            relay_address(1, &"a".repeat(MAX_NET_ADDRESS_LENGTH + 1)),
+        ];
+        assert!(!is_valid_relays(&relays, 2));
+
+        // Duplicate relay public key:
+        let relays = vec![relay_address(0, "addr0"), relay_address(0, "addr1")];
+        assert!(!is_valid_relays(&relays, 2));
+    }
+}
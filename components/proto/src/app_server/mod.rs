@@ -1,2 +1,4 @@
+pub mod chunk;
+pub mod debug_bundle;
 pub mod messages;
 pub mod serialize;
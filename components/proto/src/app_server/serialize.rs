@@ -1,15 +1,18 @@
 use std::io;
 
 use crate::capnp_common::{
-    read_custom_int128, read_custom_u_int128, read_invoice_id, read_named_index_server_address,
-    read_named_relay_address, read_public_key, read_receipt, read_relay_address, read_signature,
-    read_uid, write_custom_int128, write_custom_u_int128, write_invoice_id,
-    write_named_index_server_address, write_named_relay_address, write_public_key, write_receipt,
+    read_custom_int128, read_custom_u_int128, read_directory_subscription, read_dust_thresholds,
+    read_friend_invite, read_invoice_id, read_named_index_server_address, read_named_relay_address,
+    read_net_address, read_public_key, read_receipt, read_relay_address, read_signature, read_uid,
+    write_custom_int128, write_custom_u_int128, write_directory_subscription,
+    write_dust_thresholds, write_friend_invite, write_invoice_id, write_named_index_server_address,
+    write_named_relay_address, write_net_address, write_public_key, write_receipt,
     write_relay_address, write_signature, write_uid,
 };
 use capnp;
 use capnp::serialize_packed;
 use common::int_convert::usize_to_u32;
+use crypto::uid::{Uid, UID_LEN};
 
 use crate::serialize::SerializeError;
 use app_server_capnp;
@@ -17,20 +20,35 @@ use app_server_capnp;
 use crate::index_client::messages::{ClientResponseRoutes, ResponseRoutesResult};
 
 use crate::report::serialize::{
-    deser_node_report, deser_node_report_mutation, ser_node_report, ser_node_report_mutation,
+    deser_labeled_payment, deser_node_report, deser_node_report_mutation, deser_payment_result,
+    ser_labeled_payment, ser_node_report, ser_node_report_mutation, ser_payment_result,
 };
 use index_server::serialize::{
     deser_request_routes, deser_route_with_capacity, ser_request_routes, ser_route_with_capacity,
 };
 
 use crate::funder::messages::{
-    AddFriend, ReceiptAck, ResetFriendChannel, ResponseReceived, ResponseSendFundsResult,
-    SetFriendName, SetFriendRelays, SetFriendRemoteMaxDebt, UserRequestSendFunds,
+    AddFriend, CancelUserRequestResult, FailureReason, FriendPair, IncomingPayment,
+    PairFreezeLimit, PaymentConsumer, PaymentNotifier, PaymentNotifyFilter, PaymentSubmission,
+    PaymentTiming, PrewarmFailure, PrewarmFriend, PrewarmResult, ReceiptAck, RemoteMaxDebtExpiry,
+    RequestPairFreezeUsage, ResetFriendChannel, ResponseCancelUserRequest, ResponsePairFreezeUsage,
+    ResponsePendingSubmissions, ResponsePrewarm, ResponseReceived, ResponseSendFundsResult,
+    SetFriendIndexPrivate, SetFriendName, SetFriendRelays, SetFriendRemoteMaxDebt,
+    SetFriendResponseDeadline, SetFriendVerificationPhrase, SetPairFreezeLimit,
+    UserRequestSendFunds, UserRequestSweepFunds,
+};
+use crate::funder::serialize::{
+    deser_friends_route, deser_goodbye, ser_friends_route, ser_goodbye,
+};
+use crate::invite::messages::{
+    ImportFriendInvite, ImportFriendInviteResult, ResponseImportFriendInvite,
 };
-use crate::funder::serialize::{deser_friends_route, ser_friends_route};
 
 use crate::app_server::messages::{
-    AppPermissions, AppRequest, AppServerToApp, AppToAppServer, ReportMutations,
+    AppHello, AppPermissions, AppRequest, AppServerToApp, AppServerToAppFrame, AppToAppServer,
+    AppToAppServerFrame, LabelFilter, ReportMutations, ReportScope, RequestDebugBundle,
+    RequestLabeledPayments, ResponseDebugBundle, ResponseLabeledPayments, ResponseSelfTest,
+    SelfTestStage, SelfTestStageReport, TransferChunk,
 };
 
 fn ser_user_request_send_funds(
@@ -56,6 +74,12 @@ fn ser_user_request_send_funds(
         &user_request_send_funds.invoice_id,
         &mut user_request_send_funds_builder.reborrow().init_invoice_id(),
     );
+
+    let mut opt_label_builder = user_request_send_funds_builder.reborrow().init_opt_label();
+    match &user_request_send_funds.opt_label {
+        Some(label) => opt_label_builder.set_label(label),
+        None => opt_label_builder.set_empty(()),
+    };
 }
 
 fn deser_user_request_send_funds(
@@ -66,6 +90,99 @@ fn deser_user_request_send_funds(
         route: deser_friends_route(&user_request_send_funds_reader.get_route()?)?,
         dest_payment: read_custom_u_int128(&user_request_send_funds_reader.get_dest_payment()?)?,
         invoice_id: read_invoice_id(&user_request_send_funds_reader.get_invoice_id()?)?,
+        opt_label: match user_request_send_funds_reader.get_opt_label().which()? {
+            app_server_capnp::user_request_send_funds::opt_label::Label(label) => {
+                Some(label?.to_vec())
+            }
+            app_server_capnp::user_request_send_funds::opt_label::Empty(()) => None,
+        },
+    })
+}
+
+fn ser_user_request_sweep_funds(
+    user_request_sweep_funds: &UserRequestSweepFunds,
+    user_request_sweep_funds_builder: &mut app_server_capnp::user_request_sweep_funds::Builder,
+) {
+    write_uid(
+        &user_request_sweep_funds.request_id,
+        &mut user_request_sweep_funds_builder
+            .reborrow()
+            .init_request_id(),
+    );
+
+    let mut route_builder = user_request_sweep_funds_builder.reborrow().init_route();
+    ser_friends_route(&user_request_sweep_funds.route, &mut route_builder);
+
+    write_invoice_id(
+        &user_request_sweep_funds.invoice_id,
+        &mut user_request_sweep_funds_builder
+            .reborrow()
+            .init_invoice_id(),
+    );
+
+    let mut opt_route_capacity_builder = user_request_sweep_funds_builder
+        .reborrow()
+        .init_opt_route_capacity();
+    match user_request_sweep_funds.opt_route_capacity {
+        Some(route_capacity) => write_custom_u_int128(
+            route_capacity,
+            &mut opt_route_capacity_builder.init_route_capacity(),
+        ),
+        None => opt_route_capacity_builder.set_empty(()),
+    };
+}
+
+fn deser_user_request_sweep_funds(
+    user_request_sweep_funds_reader: &app_server_capnp::user_request_sweep_funds::Reader,
+) -> Result<UserRequestSweepFunds, SerializeError> {
+    let opt_route_capacity = match user_request_sweep_funds_reader
+        .get_opt_route_capacity()
+        .which()?
+    {
+        app_server_capnp::user_request_sweep_funds::opt_route_capacity::RouteCapacity(
+            route_capacity_reader,
+        ) => Some(read_custom_u_int128(&route_capacity_reader?)?),
+        app_server_capnp::user_request_sweep_funds::opt_route_capacity::Empty(()) => None,
+    };
+
+    Ok(UserRequestSweepFunds {
+        request_id: read_uid(&user_request_sweep_funds_reader.get_request_id()?)?,
+        route: deser_friends_route(&user_request_sweep_funds_reader.get_route()?)?,
+        invoice_id: read_invoice_id(&user_request_sweep_funds_reader.get_invoice_id()?)?,
+        opt_route_capacity,
+    })
+}
+
+fn ser_payment_timing(
+    payment_timing: &PaymentTiming,
+    payment_timing_builder: &mut app_server_capnp::payment_timing::Builder,
+) {
+    let mut ticks_to_sent_builder = payment_timing_builder.reborrow().init_ticks_to_sent();
+    match payment_timing.opt_ticks_to_sent {
+        Some(ticks_to_sent) => ticks_to_sent_builder.set_ticks(ticks_to_sent),
+        None => ticks_to_sent_builder.set_empty(()),
+    };
+    payment_timing_builder.set_ticks_to_response(payment_timing.ticks_to_response);
+    ser_friends_route(
+        &payment_timing.route,
+        &mut payment_timing_builder.reborrow().init_route(),
+    );
+}
+
+fn deser_payment_timing(
+    payment_timing_reader: &app_server_capnp::payment_timing::Reader,
+) -> Result<PaymentTiming, SerializeError> {
+    let opt_ticks_to_sent = match payment_timing_reader.get_ticks_to_sent().which()? {
+        app_server_capnp::payment_timing::ticks_to_sent::Ticks(ticks_to_sent) => {
+            Some(ticks_to_sent)
+        }
+        app_server_capnp::payment_timing::ticks_to_sent::Empty(()) => None,
+    };
+
+    Ok(PaymentTiming {
+        opt_ticks_to_sent,
+        ticks_to_response: payment_timing_reader.get_ticks_to_response(),
+        route: deser_friends_route(&payment_timing_reader.get_route()?)?,
     })
 }
 
@@ -84,10 +201,33 @@ fn ser_response_received(
             let mut success_builder = result_builder.init_success();
             write_receipt(receipt, &mut success_builder);
         }
-        ResponseSendFundsResult::Failure(public_key) => {
+        ResponseSendFundsResult::Failure((public_key, reason)) => {
             let mut failure_builder = result_builder.init_failure();
             write_public_key(public_key, &mut failure_builder);
+            response_received_builder.set_failure_reason(reason.to_u16());
+            if let FailureReason::InsufficientLocalCapacity(remaining_capacity) = reason {
+                write_custom_u_int128(
+                    *remaining_capacity,
+                    &mut response_received_builder
+                        .reborrow()
+                        .init_remaining_capacity(),
+                );
+            }
+        }
+    };
+
+    let mut opt_timing_builder = response_received_builder.reborrow().init_opt_timing();
+    match &response_received.opt_timing {
+        Some(payment_timing) => {
+            ser_payment_timing(payment_timing, &mut opt_timing_builder.init_timing())
         }
+        None => opt_timing_builder.set_empty(()),
+    };
+
+    let mut opt_label_builder = response_received_builder.reborrow().init_opt_label();
+    match &response_received.opt_label {
+        Some(label) => opt_label_builder.set_label(label),
+        None => opt_label_builder.set_empty(()),
     };
 }
 
@@ -101,13 +241,366 @@ fn deser_response_received(
         }
         app_server_capnp::response_received::result::Failure(public_key_reader) => {
             let public_key_reader = public_key_reader?;
-            ResponseSendFundsResult::Failure(read_public_key(&public_key_reader)?)
+            let code = response_received_reader.get_failure_reason();
+            let reason = match FailureReason::from_u16(code) {
+                FailureReason::InsufficientLocalCapacity(_) => {
+                    FailureReason::InsufficientLocalCapacity(read_custom_u_int128(
+                        &response_received_reader.get_remaining_capacity()?,
+                    )?)
+                }
+                reason => reason,
+            };
+            ResponseSendFundsResult::Failure((read_public_key(&public_key_reader)?, reason))
+        }
+    };
+
+    let opt_timing = match response_received_reader.get_opt_timing().which()? {
+        app_server_capnp::response_received::opt_timing::Timing(payment_timing_reader) => {
+            Some(deser_payment_timing(&payment_timing_reader?)?)
         }
+        app_server_capnp::response_received::opt_timing::Empty(()) => None,
+    };
+
+    let opt_label = match response_received_reader.get_opt_label().which()? {
+        app_server_capnp::response_received::opt_label::Label(label) => Some(label?.to_vec()),
+        app_server_capnp::response_received::opt_label::Empty(()) => None,
     };
 
     Ok(ResponseReceived {
         request_id: read_uid(&response_received_reader.get_request_id()?)?,
         result,
+        opt_timing,
+        opt_label,
+    })
+}
+
+fn ser_request_labeled_payments(
+    request_labeled_payments: &RequestLabeledPayments,
+    request_labeled_payments_builder: &mut app_server_capnp::request_labeled_payments::Builder,
+) {
+    write_uid(
+        &request_labeled_payments.request_id,
+        &mut request_labeled_payments_builder
+            .reborrow()
+            .init_request_id(),
+    );
+
+    let mut filter_builder = request_labeled_payments_builder.reborrow().init_filter();
+    match &request_labeled_payments.filter {
+        LabelFilter::Exact(label) => filter_builder.set_exact(label),
+        LabelFilter::Prefix(prefix) => filter_builder.set_prefix(prefix),
+    };
+
+    request_labeled_payments_builder.set_offset(request_labeled_payments.offset);
+    request_labeled_payments_builder.set_max_results(request_labeled_payments.max_results);
+}
+
+fn deser_request_labeled_payments(
+    request_labeled_payments_reader: &app_server_capnp::request_labeled_payments::Reader,
+) -> Result<RequestLabeledPayments, SerializeError> {
+    let filter = match request_labeled_payments_reader.get_filter().which()? {
+        app_server_capnp::request_labeled_payments::filter::Exact(label) => {
+            LabelFilter::Exact(label?.to_vec())
+        }
+        app_server_capnp::request_labeled_payments::filter::Prefix(prefix) => {
+            LabelFilter::Prefix(prefix?.to_vec())
+        }
+    };
+
+    Ok(RequestLabeledPayments {
+        request_id: read_uid(&request_labeled_payments_reader.get_request_id()?)?,
+        filter,
+        offset: request_labeled_payments_reader.get_offset(),
+        max_results: request_labeled_payments_reader.get_max_results(),
+    })
+}
+
+fn ser_response_labeled_payments(
+    response_labeled_payments: &ResponseLabeledPayments,
+    response_labeled_payments_builder: &mut app_server_capnp::response_labeled_payments::Builder,
+) {
+    write_uid(
+        &response_labeled_payments.request_id,
+        &mut response_labeled_payments_builder
+            .reborrow()
+            .init_request_id(),
+    );
+
+    let labeled_payments_len =
+        usize_to_u32(response_labeled_payments.labeled_payments.len()).unwrap();
+    let mut labeled_payments_builder = response_labeled_payments_builder
+        .reborrow()
+        .init_labeled_payments(labeled_payments_len);
+    for (index, labeled_payment) in response_labeled_payments
+        .labeled_payments
+        .iter()
+        .enumerate()
+    {
+        let mut labeled_payment_builder = labeled_payments_builder
+            .reborrow()
+            .get(usize_to_u32(index).unwrap());
+        ser_labeled_payment(labeled_payment, &mut labeled_payment_builder);
+    }
+
+    response_labeled_payments_builder.set_more(response_labeled_payments.more);
+}
+
+fn deser_response_labeled_payments(
+    response_labeled_payments_reader: &app_server_capnp::response_labeled_payments::Reader,
+) -> Result<ResponseLabeledPayments, SerializeError> {
+    let mut labeled_payments = Vec::new();
+    for labeled_payment_reader in response_labeled_payments_reader.get_labeled_payments()? {
+        labeled_payments.push(deser_labeled_payment(&labeled_payment_reader)?);
+    }
+
+    Ok(ResponseLabeledPayments {
+        request_id: read_uid(&response_labeled_payments_reader.get_request_id()?)?,
+        labeled_payments,
+        more: response_labeled_payments_reader.get_more(),
+    })
+}
+
+fn ser_payment_submission(
+    payment_submission: &PaymentSubmission,
+    payment_submission_builder: &mut app_server_capnp::payment_submission::Builder,
+) {
+    write_uid(
+        &payment_submission.request_id,
+        &mut payment_submission_builder.reborrow().init_request_id(),
+    );
+    write_public_key(
+        &payment_submission.app_public_key,
+        &mut payment_submission_builder.reborrow().init_app_public_key(),
+    );
+    ser_friends_route(
+        &payment_submission.route,
+        &mut payment_submission_builder.reborrow().init_route(),
+    );
+    write_invoice_id(
+        &payment_submission.invoice_id,
+        &mut payment_submission_builder.reborrow().init_invoice_id(),
+    );
+    write_custom_u_int128(
+        payment_submission.dest_payment,
+        &mut payment_submission_builder.reborrow().init_dest_payment(),
+    );
+
+    let mut opt_result_builder = payment_submission_builder.reborrow().init_opt_result();
+    match &payment_submission.opt_result {
+        Some(result) => ser_payment_result(result, &mut opt_result_builder.init_payment_result()),
+        None => opt_result_builder.set_empty(()),
+    };
+}
+
+fn deser_payment_submission(
+    payment_submission_reader: &app_server_capnp::payment_submission::Reader,
+) -> Result<PaymentSubmission, SerializeError> {
+    Ok(PaymentSubmission {
+        request_id: read_uid(&payment_submission_reader.get_request_id()?)?,
+        app_public_key: read_public_key(&payment_submission_reader.get_app_public_key()?)?,
+        route: deser_friends_route(&payment_submission_reader.get_route()?)?,
+        invoice_id: read_invoice_id(&payment_submission_reader.get_invoice_id()?)?,
+        dest_payment: read_custom_u_int128(&payment_submission_reader.get_dest_payment()?)?,
+        opt_result: match payment_submission_reader.get_opt_result().which()? {
+            app_server_capnp::payment_submission::opt_result::PaymentResult(result_reader) => {
+                Some(deser_payment_result(&result_reader?)?)
+            }
+            app_server_capnp::payment_submission::opt_result::Empty(()) => None,
+        },
+    })
+}
+
+fn ser_response_pending_submissions(
+    response_pending_submissions: &ResponsePendingSubmissions,
+    response_pending_builder: &mut app_server_capnp::response_pending_submissions::Builder,
+) {
+    write_uid(
+        &response_pending_submissions.request_id,
+        &mut response_pending_builder.reborrow().init_request_id(),
+    );
+
+    let submissions_len = usize_to_u32(response_pending_submissions.submissions.len()).unwrap();
+    let mut submissions_builder = response_pending_builder
+        .reborrow()
+        .init_submissions(submissions_len);
+    for (index, payment_submission) in response_pending_submissions.submissions.iter().enumerate() {
+        let mut payment_submission_builder = submissions_builder
+            .reborrow()
+            .get(usize_to_u32(index).unwrap());
+        ser_payment_submission(payment_submission, &mut payment_submission_builder);
+    }
+}
+
+fn deser_response_pending_submissions(
+    response_pending_submissions_reader: &app_server_capnp::response_pending_submissions::Reader,
+) -> Result<ResponsePendingSubmissions, SerializeError> {
+    let mut submissions = Vec::new();
+    for payment_submission_reader in response_pending_submissions_reader.get_submissions()? {
+        submissions.push(deser_payment_submission(&payment_submission_reader)?);
+    }
+
+    Ok(ResponsePendingSubmissions {
+        request_id: read_uid(&response_pending_submissions_reader.get_request_id()?)?,
+        submissions,
+    })
+}
+
+fn ser_friend_pair(
+    friend_pair: &FriendPair,
+    friend_pair_builder: &mut app_server_capnp::friend_pair::Builder,
+) {
+    let mut opt_from_builder = friend_pair_builder.reborrow().init_opt_from();
+    match &friend_pair.opt_from {
+        Some(from_public_key) => {
+            write_public_key(from_public_key, &mut opt_from_builder.init_public_key())
+        }
+        None => opt_from_builder.set_empty(()),
+    };
+
+    let mut opt_to_builder = friend_pair_builder.reborrow().init_opt_to();
+    match &friend_pair.opt_to {
+        Some(to_public_key) => {
+            write_public_key(to_public_key, &mut opt_to_builder.init_public_key())
+        }
+        None => opt_to_builder.set_empty(()),
+    };
+}
+
+fn deser_friend_pair(
+    friend_pair_reader: &app_server_capnp::friend_pair::Reader,
+) -> Result<FriendPair, SerializeError> {
+    Ok(FriendPair {
+        opt_from: match friend_pair_reader.get_opt_from().which()? {
+            app_server_capnp::friend_pair::opt_from::PublicKey(public_key_reader) => {
+                Some(read_public_key(&public_key_reader?)?)
+            }
+            app_server_capnp::friend_pair::opt_from::Empty(()) => None,
+        },
+        opt_to: match friend_pair_reader.get_opt_to().which()? {
+            app_server_capnp::friend_pair::opt_to::PublicKey(public_key_reader) => {
+                Some(read_public_key(&public_key_reader?)?)
+            }
+            app_server_capnp::friend_pair::opt_to::Empty(()) => None,
+        },
+    })
+}
+
+fn ser_pair_freeze_limit(
+    pair_freeze_limit: &PairFreezeLimit,
+    pair_freeze_limit_builder: &mut app_server_capnp::pair_freeze_limit::Builder,
+) {
+    ser_friend_pair(
+        &pair_freeze_limit.pair,
+        &mut pair_freeze_limit_builder.reborrow().init_pair(),
+    );
+    write_custom_u_int128(
+        pair_freeze_limit.limit,
+        &mut pair_freeze_limit_builder.reborrow().init_limit(),
+    );
+}
+
+fn deser_pair_freeze_limit(
+    pair_freeze_limit_reader: &app_server_capnp::pair_freeze_limit::Reader,
+) -> Result<PairFreezeLimit, SerializeError> {
+    Ok(PairFreezeLimit {
+        pair: deser_friend_pair(&pair_freeze_limit_reader.get_pair()?)?,
+        limit: read_custom_u_int128(&pair_freeze_limit_reader.get_limit()?)?,
+    })
+}
+
+fn ser_set_pair_freeze_limit(
+    set_pair_freeze_limit: &SetPairFreezeLimit,
+    set_pair_freeze_limit_builder: &mut app_server_capnp::set_pair_freeze_limit::Builder,
+) {
+    ser_friend_pair(
+        &set_pair_freeze_limit.pair,
+        &mut set_pair_freeze_limit_builder.reborrow().init_pair(),
+    );
+
+    let mut opt_limit_builder = set_pair_freeze_limit_builder.reborrow().init_opt_limit();
+    match set_pair_freeze_limit.opt_limit {
+        Some(limit) => write_custom_u_int128(limit, &mut opt_limit_builder.init_limit()),
+        None => opt_limit_builder.set_empty(()),
+    };
+}
+
+fn deser_set_pair_freeze_limit(
+    set_pair_freeze_limit_reader: &app_server_capnp::set_pair_freeze_limit::Reader,
+) -> Result<SetPairFreezeLimit, SerializeError> {
+    Ok(SetPairFreezeLimit {
+        pair: deser_friend_pair(&set_pair_freeze_limit_reader.get_pair()?)?,
+        opt_limit: match set_pair_freeze_limit_reader.get_opt_limit().which()? {
+            app_server_capnp::set_pair_freeze_limit::opt_limit::Limit(limit_reader) => {
+                Some(read_custom_u_int128(&limit_reader?)?)
+            }
+            app_server_capnp::set_pair_freeze_limit::opt_limit::Empty(()) => None,
+        },
+    })
+}
+
+fn ser_request_pair_freeze_usage(
+    request_usage: &RequestPairFreezeUsage,
+    request_usage_builder: &mut app_server_capnp::request_pair_freeze_usage::Builder,
+) {
+    write_uid(
+        &request_usage.request_id,
+        &mut request_usage_builder.reborrow().init_request_id(),
+    );
+    write_public_key(
+        &request_usage.from_public_key,
+        &mut request_usage_builder.reborrow().init_from_public_key(),
+    );
+    write_public_key(
+        &request_usage.to_public_key,
+        &mut request_usage_builder.reborrow().init_to_public_key(),
+    );
+}
+
+fn deser_request_pair_freeze_usage(
+    request_usage_reader: &app_server_capnp::request_pair_freeze_usage::Reader,
+) -> Result<RequestPairFreezeUsage, SerializeError> {
+    Ok(RequestPairFreezeUsage {
+        request_id: read_uid(&request_usage_reader.get_request_id()?)?,
+        from_public_key: read_public_key(&request_usage_reader.get_from_public_key()?)?,
+        to_public_key: read_public_key(&request_usage_reader.get_to_public_key()?)?,
+    })
+}
+
+fn ser_response_pair_freeze_usage(
+    response_usage: &ResponsePairFreezeUsage,
+    response_usage_builder: &mut app_server_capnp::response_pair_freeze_usage::Builder,
+) {
+    write_uid(
+        &response_usage.request_id,
+        &mut response_usage_builder.reborrow().init_request_id(),
+    );
+
+    let mut opt_limit_builder = response_usage_builder.reborrow().init_opt_limit();
+    match &response_usage.opt_limit {
+        Some(pair_freeze_limit) => {
+            ser_pair_freeze_limit(pair_freeze_limit, &mut opt_limit_builder.init_limit())
+        }
+        None => opt_limit_builder.set_empty(()),
+    };
+
+    write_custom_u_int128(
+        response_usage.frozen_credits,
+        &mut response_usage_builder.reborrow().init_frozen_credits(),
+    );
+}
+
+fn deser_response_pair_freeze_usage(
+    response_usage_reader: &app_server_capnp::response_pair_freeze_usage::Reader,
+) -> Result<ResponsePairFreezeUsage, SerializeError> {
+    Ok(ResponsePairFreezeUsage {
+        request_id: read_uid(&response_usage_reader.get_request_id()?)?,
+        opt_limit: match response_usage_reader.get_opt_limit().which()? {
+            app_server_capnp::response_pair_freeze_usage::opt_limit::Limit(limit_reader) => {
+                Some(deser_pair_freeze_limit(&limit_reader?)?)
+            }
+            app_server_capnp::response_pair_freeze_usage::opt_limit::Empty(()) => None,
+        },
+        frozen_credits: read_custom_u_int128(&response_usage_reader.get_frozen_credits()?)?,
     })
 }
 
@@ -134,6 +627,238 @@ fn deser_receipt_ack(
     })
 }
 
+fn ser_prewarm_friend(
+    prewarm_friend: &PrewarmFriend,
+    prewarm_friend_builder: &mut app_server_capnp::prewarm_friend::Builder,
+) {
+    write_uid(
+        &prewarm_friend.request_id,
+        &mut prewarm_friend_builder.reborrow().init_request_id(),
+    );
+    write_public_key(
+        &prewarm_friend.friend_public_key,
+        &mut prewarm_friend_builder.reborrow().init_friend_public_key(),
+    );
+}
+
+fn deser_prewarm_friend(
+    prewarm_friend_reader: &app_server_capnp::prewarm_friend::Reader,
+) -> Result<PrewarmFriend, SerializeError> {
+    Ok(PrewarmFriend {
+        request_id: read_uid(&prewarm_friend_reader.get_request_id()?)?,
+        friend_public_key: read_public_key(&prewarm_friend_reader.get_friend_public_key()?)?,
+    })
+}
+
+fn ser_prewarm_failure(
+    prewarm_failure: &PrewarmFailure,
+    prewarm_failure_builder: &mut app_server_capnp::prewarm_failure::Builder,
+) {
+    match prewarm_failure {
+        PrewarmFailure::FriendDoesNotExist => prewarm_failure_builder.set_friend_does_not_exist(()),
+        PrewarmFailure::FriendDisabled => prewarm_failure_builder.set_friend_disabled(()),
+        PrewarmFailure::FriendOffline => prewarm_failure_builder.set_friend_offline(()),
+        PrewarmFailure::ChannelInconsistent => prewarm_failure_builder.set_channel_inconsistent(()),
+        PrewarmFailure::RateLimited => prewarm_failure_builder.set_rate_limited(()),
+    }
+}
+
+fn deser_prewarm_failure(
+    prewarm_failure_reader: &app_server_capnp::prewarm_failure::Reader,
+) -> Result<PrewarmFailure, SerializeError> {
+    Ok(match prewarm_failure_reader.which()? {
+        app_server_capnp::prewarm_failure::FriendDoesNotExist(()) => {
+            PrewarmFailure::FriendDoesNotExist
+        }
+        app_server_capnp::prewarm_failure::FriendDisabled(()) => PrewarmFailure::FriendDisabled,
+        app_server_capnp::prewarm_failure::FriendOffline(()) => PrewarmFailure::FriendOffline,
+        app_server_capnp::prewarm_failure::ChannelInconsistent(()) => {
+            PrewarmFailure::ChannelInconsistent
+        }
+        app_server_capnp::prewarm_failure::RateLimited(()) => PrewarmFailure::RateLimited,
+    })
+}
+
+fn ser_response_prewarm(
+    response_prewarm: &ResponsePrewarm,
+    response_prewarm_builder: &mut app_server_capnp::response_prewarm::Builder,
+) {
+    write_uid(
+        &response_prewarm.request_id,
+        &mut response_prewarm_builder.reborrow().init_request_id(),
+    );
+
+    let mut result_builder = response_prewarm_builder.reborrow().init_result();
+    match &response_prewarm.result {
+        PrewarmResult::Success => result_builder.set_success(()),
+        PrewarmResult::Failure(prewarm_failure) => {
+            let mut failure_builder = result_builder.init_failure();
+            ser_prewarm_failure(prewarm_failure, &mut failure_builder);
+        }
+    };
+}
+
+fn deser_response_prewarm(
+    response_prewarm_reader: &app_server_capnp::response_prewarm::Reader,
+) -> Result<ResponsePrewarm, SerializeError> {
+    let result = match response_prewarm_reader.get_result().which()? {
+        app_server_capnp::response_prewarm::result::Success(()) => PrewarmResult::Success,
+        app_server_capnp::response_prewarm::result::Failure(prewarm_failure_reader) => {
+            PrewarmResult::Failure(deser_prewarm_failure(&prewarm_failure_reader?)?)
+        }
+    };
+
+    Ok(ResponsePrewarm {
+        request_id: read_uid(&response_prewarm_reader.get_request_id()?)?,
+        result,
+    })
+}
+
+fn ser_response_cancel_user_request(
+    response_cancel: &ResponseCancelUserRequest,
+    response_cancel_builder: &mut app_server_capnp::response_cancel_user_request::Builder,
+) {
+    write_uid(
+        &response_cancel.request_id,
+        &mut response_cancel_builder.reborrow().init_request_id(),
+    );
+
+    let mut result_builder = response_cancel_builder.reborrow().init_result();
+    match response_cancel.result {
+        CancelUserRequestResult::Cancelled => result_builder.set_cancelled(()),
+        CancelUserRequestResult::InFlight => result_builder.set_in_flight(()),
+        CancelUserRequestResult::NotFound => result_builder.set_not_found(()),
+    };
+}
+
+fn deser_response_cancel_user_request(
+    response_cancel_reader: &app_server_capnp::response_cancel_user_request::Reader,
+) -> Result<ResponseCancelUserRequest, SerializeError> {
+    let result = match response_cancel_reader.get_result().which()? {
+        app_server_capnp::response_cancel_user_request::result::Cancelled(()) => {
+            CancelUserRequestResult::Cancelled
+        }
+        app_server_capnp::response_cancel_user_request::result::InFlight(()) => {
+            CancelUserRequestResult::InFlight
+        }
+        app_server_capnp::response_cancel_user_request::result::NotFound(()) => {
+            CancelUserRequestResult::NotFound
+        }
+    };
+
+    Ok(ResponseCancelUserRequest {
+        request_id: read_uid(&response_cancel_reader.get_request_id()?)?,
+        result,
+    })
+}
+
+fn ser_request_debug_bundle(
+    request_debug_bundle: &RequestDebugBundle,
+    request_debug_bundle_builder: &mut app_server_capnp::request_debug_bundle::Builder,
+) {
+    write_uid(
+        &request_debug_bundle.request_id,
+        &mut request_debug_bundle_builder.reborrow().init_request_id(),
+    );
+    request_debug_bundle_builder.set_full(request_debug_bundle.full);
+}
+
+fn deser_request_debug_bundle(
+    request_debug_bundle_reader: &app_server_capnp::request_debug_bundle::Reader,
+) -> Result<RequestDebugBundle, SerializeError> {
+    Ok(RequestDebugBundle {
+        request_id: read_uid(&request_debug_bundle_reader.get_request_id()?)?,
+        full: request_debug_bundle_reader.get_full(),
+    })
+}
+
+fn ser_response_debug_bundle(
+    response_debug_bundle: &ResponseDebugBundle,
+    response_debug_bundle_builder: &mut app_server_capnp::response_debug_bundle::Builder,
+) {
+    write_uid(
+        &response_debug_bundle.request_id,
+        &mut response_debug_bundle_builder.reborrow().init_request_id(),
+    );
+    response_debug_bundle_builder.set_bundle(&response_debug_bundle.bundle);
+}
+
+fn deser_response_debug_bundle(
+    response_debug_bundle_reader: &app_server_capnp::response_debug_bundle::Reader,
+) -> Result<ResponseDebugBundle, SerializeError> {
+    Ok(ResponseDebugBundle {
+        request_id: read_uid(&response_debug_bundle_reader.get_request_id()?)?,
+        bundle: response_debug_bundle_reader.get_bundle()?.to_vec(),
+    })
+}
+
+fn ser_self_test_stage(
+    self_test_stage: &SelfTestStage,
+    self_test_stage_builder: &mut app_server_capnp::self_test_stage::Builder,
+) {
+    match self_test_stage {
+        SelfTestStage::Identity => self_test_stage_builder.set_identity(()),
+        SelfTestStage::Channel => self_test_stage_builder.set_channel(()),
+        SelfTestStage::Credit => self_test_stage_builder.set_credit(()),
+        SelfTestStage::PaymentToPeer => self_test_stage_builder.set_payment_to_peer(()),
+        SelfTestStage::PaymentFromPeer => self_test_stage_builder.set_payment_from_peer(()),
+    }
+}
+
+fn deser_self_test_stage(
+    self_test_stage_reader: &app_server_capnp::self_test_stage::Reader,
+) -> Result<SelfTestStage, SerializeError> {
+    Ok(match self_test_stage_reader.which()? {
+        app_server_capnp::self_test_stage::Identity(()) => SelfTestStage::Identity,
+        app_server_capnp::self_test_stage::Channel(()) => SelfTestStage::Channel,
+        app_server_capnp::self_test_stage::Credit(()) => SelfTestStage::Credit,
+        app_server_capnp::self_test_stage::PaymentToPeer(()) => SelfTestStage::PaymentToPeer,
+        app_server_capnp::self_test_stage::PaymentFromPeer(()) => SelfTestStage::PaymentFromPeer,
+    })
+}
+
+fn ser_response_self_test(
+    response_self_test: &ResponseSelfTest,
+    response_self_test_builder: &mut app_server_capnp::response_self_test::Builder,
+) {
+    write_uid(
+        &response_self_test.request_id,
+        &mut response_self_test_builder.reborrow().init_request_id(),
+    );
+
+    let stages_len = usize_to_u32(response_self_test.stages.len()).unwrap();
+    let mut stages_builder = response_self_test_builder
+        .reborrow()
+        .init_stages(stages_len);
+    for (index, stage_report) in response_self_test.stages.iter().enumerate() {
+        let mut stage_report_builder = stages_builder.reborrow().get(usize_to_u32(index).unwrap());
+        ser_self_test_stage(
+            &stage_report.stage,
+            &mut stage_report_builder.reborrow().init_stage(),
+        );
+        stage_report_builder.set_success(stage_report.success);
+        stage_report_builder.set_ticks(stage_report.ticks);
+    }
+}
+
+fn deser_response_self_test(
+    response_self_test_reader: &app_server_capnp::response_self_test::Reader,
+) -> Result<ResponseSelfTest, SerializeError> {
+    let mut stages = Vec::new();
+    for stage_report_reader in response_self_test_reader.get_stages()? {
+        stages.push(SelfTestStageReport {
+            stage: deser_self_test_stage(&stage_report_reader.get_stage()?)?,
+            success: stage_report_reader.get_success(),
+            ticks: stage_report_reader.get_ticks(),
+        });
+    }
+
+    Ok(ResponseSelfTest {
+        request_id: read_uid(&response_self_test_reader.get_request_id()?)?,
+        stages,
+    })
+}
+
 fn ser_add_friend(
     add_friend: &AddFriend,
     add_friend_builder: &mut app_server_capnp::add_friend::Builder,
@@ -228,6 +953,105 @@ fn deser_set_friend_relays(
     })
 }
 
+fn ser_payment_notifier(
+    payment_notifier: &PaymentNotifier,
+    payment_notifier_builder: &mut app_server_capnp::payment_notifier::Builder,
+) {
+    let mut consumer_builder = payment_notifier_builder.reborrow().init_consumer();
+    match &payment_notifier.consumer {
+        PaymentConsumer::Address(address) => {
+            write_net_address(address, &mut consumer_builder.init_address())
+        }
+        PaymentConsumer::Apps => consumer_builder.set_apps(()),
+    }
+
+    let mut filter_builder = payment_notifier_builder.reborrow().init_filter();
+    match &payment_notifier.filter {
+        PaymentNotifyFilter::All => filter_builder.set_all(()),
+        PaymentNotifyFilter::Invoices(invoice_ids) => {
+            let invoice_ids_len = usize_to_u32(invoice_ids.len()).unwrap();
+            let mut invoice_ids_builder = filter_builder.init_invoices(invoice_ids_len);
+            for (index, invoice_id) in invoice_ids.iter().enumerate() {
+                let mut invoice_id_builder = invoice_ids_builder
+                    .reborrow()
+                    .get(usize_to_u32(index).unwrap());
+                write_invoice_id(invoice_id, &mut invoice_id_builder);
+            }
+        }
+    }
+}
+
+fn deser_payment_notifier(
+    payment_notifier_reader: &app_server_capnp::payment_notifier::Reader,
+) -> Result<PaymentNotifier, SerializeError> {
+    let consumer = match payment_notifier_reader.get_consumer().which()? {
+        app_server_capnp::payment_notifier::consumer::Address(address_reader) => {
+            PaymentConsumer::Address(read_net_address(&address_reader?)?)
+        }
+        app_server_capnp::payment_notifier::consumer::Apps(()) => PaymentConsumer::Apps,
+    };
+
+    let filter = match payment_notifier_reader.get_filter().which()? {
+        app_server_capnp::payment_notifier::filter::All(()) => PaymentNotifyFilter::All,
+        app_server_capnp::payment_notifier::filter::Invoices(invoice_ids_reader) => {
+            let mut invoice_ids = Vec::new();
+            for invoice_id in invoice_ids_reader? {
+                invoice_ids.push(read_invoice_id(&invoice_id)?);
+            }
+            PaymentNotifyFilter::Invoices(invoice_ids)
+        }
+    };
+
+    Ok(PaymentNotifier { consumer, filter })
+}
+
+fn ser_incoming_payment(
+    incoming_payment: &IncomingPayment,
+    incoming_payment_builder: &mut app_server_capnp::incoming_payment::Builder,
+) {
+    incoming_payment_builder.set_notification_id(incoming_payment.notification_id);
+    incoming_payment_builder.set_route_len(incoming_payment.route_len);
+    write_receipt(
+        &incoming_payment.receipt,
+        &mut incoming_payment_builder.reborrow().init_receipt(),
+    );
+}
+
+fn deser_incoming_payment(
+    incoming_payment_reader: &app_server_capnp::incoming_payment::Reader,
+) -> Result<IncomingPayment, SerializeError> {
+    Ok(IncomingPayment {
+        notification_id: incoming_payment_reader.get_notification_id(),
+        route_len: incoming_payment_reader.get_route_len(),
+        receipt: read_receipt(&incoming_payment_reader.get_receipt()?)?,
+    })
+}
+
+fn ser_remote_max_debt_expiry(
+    remote_max_debt_expiry: &RemoteMaxDebtExpiry,
+    remote_max_debt_expiry_builder: &mut app_server_capnp::remote_max_debt_expiry::Builder,
+) {
+    remote_max_debt_expiry_builder
+        .set_expires_after_ticks(remote_max_debt_expiry.expires_after_ticks);
+    write_custom_u_int128(
+        remote_max_debt_expiry.post_expiry_max_debt,
+        &mut remote_max_debt_expiry_builder
+            .reborrow()
+            .init_post_expiry_max_debt(),
+    );
+}
+
+fn deser_remote_max_debt_expiry(
+    remote_max_debt_expiry_reader: &app_server_capnp::remote_max_debt_expiry::Reader,
+) -> Result<RemoteMaxDebtExpiry, SerializeError> {
+    Ok(RemoteMaxDebtExpiry {
+        expires_after_ticks: remote_max_debt_expiry_reader.get_expires_after_ticks(),
+        post_expiry_max_debt: read_custom_u_int128(
+            &remote_max_debt_expiry_reader.get_post_expiry_max_debt()?,
+        )?,
+    })
+}
+
 fn ser_set_friend_remote_max_debt(
     set_friend_remote_max_debt: &SetFriendRemoteMaxDebt,
     set_friend_remote_max_debt_builder: &mut app_server_capnp::set_friend_remote_max_debt::Builder,
@@ -245,11 +1069,26 @@ fn ser_set_friend_remote_max_debt(
             .reborrow()
             .init_remote_max_debt(),
     );
+
+    let mut opt_expiry_builder = set_friend_remote_max_debt_builder
+        .reborrow()
+        .init_opt_expiry();
+    match &set_friend_remote_max_debt.opt_expiry {
+        Some(expiry) => ser_remote_max_debt_expiry(expiry, &mut opt_expiry_builder.init_expiry()),
+        None => opt_expiry_builder.set_empty(()),
+    };
 }
 
 fn deser_set_friend_remote_max_debt(
     set_friend_remote_max_debt_reader: &app_server_capnp::set_friend_remote_max_debt::Reader,
 ) -> Result<SetFriendRemoteMaxDebt, SerializeError> {
+    let opt_expiry = match set_friend_remote_max_debt_reader.get_opt_expiry().which()? {
+        app_server_capnp::set_friend_remote_max_debt::opt_expiry::Expiry(expiry_reader) => {
+            Some(deser_remote_max_debt_expiry(&expiry_reader?)?)
+        }
+        app_server_capnp::set_friend_remote_max_debt::opt_expiry::Empty(()) => None,
+    };
+
     Ok(SetFriendRemoteMaxDebt {
         friend_public_key: read_public_key(
             &set_friend_remote_max_debt_reader.get_friend_public_key()?,
@@ -257,6 +1096,92 @@ fn deser_set_friend_remote_max_debt(
         remote_max_debt: read_custom_u_int128(
             &set_friend_remote_max_debt_reader.get_remote_max_debt()?,
         )?,
+        opt_expiry,
+    })
+}
+
+fn ser_set_friend_response_deadline(
+    set_friend_response_deadline: &SetFriendResponseDeadline,
+    set_response_deadline_builder: &mut app_server_capnp::set_friend_response_deadline::Builder,
+) {
+    write_public_key(
+        &set_friend_response_deadline.friend_public_key,
+        &mut set_response_deadline_builder
+            .reborrow()
+            .init_friend_public_key(),
+    );
+
+    let mut opt_deadline_ticks_builder = set_response_deadline_builder
+        .reborrow()
+        .init_opt_deadline_ticks();
+    match set_friend_response_deadline.opt_deadline_ticks {
+        Some(deadline_ticks) => opt_deadline_ticks_builder.set_deadline_ticks(deadline_ticks),
+        None => opt_deadline_ticks_builder.set_empty(()),
+    };
+}
+
+fn deser_set_friend_response_deadline(
+    set_friend_response_deadline_reader: &app_server_capnp::set_friend_response_deadline::Reader,
+) -> Result<SetFriendResponseDeadline, SerializeError> {
+    let opt_deadline_ticks = match set_friend_response_deadline_reader
+        .get_opt_deadline_ticks()
+        .which()?
+    {
+        app_server_capnp::set_friend_response_deadline::opt_deadline_ticks::DeadlineTicks(
+            deadline_ticks,
+        ) => Some(deadline_ticks),
+        app_server_capnp::set_friend_response_deadline::opt_deadline_ticks::Empty(()) => None,
+    };
+
+    Ok(SetFriendResponseDeadline {
+        friend_public_key: read_public_key(
+            &set_friend_response_deadline_reader.get_friend_public_key()?,
+        )?,
+        opt_deadline_ticks,
+    })
+}
+
+fn ser_set_friend_verification_phrase(
+    set_friend_verification_phrase: &SetFriendVerificationPhrase,
+    set_phrase_builder: &mut app_server_capnp::set_friend_verification_phrase::Builder,
+) {
+    write_public_key(
+        &set_friend_verification_phrase.friend_public_key,
+        &mut set_phrase_builder.reborrow().init_friend_public_key(),
+    );
+
+    set_phrase_builder.set_phrase(&set_friend_verification_phrase.phrase);
+}
+
+fn deser_set_friend_verification_phrase(
+    set_phrase_reader: &app_server_capnp::set_friend_verification_phrase::Reader,
+) -> Result<SetFriendVerificationPhrase, SerializeError> {
+    Ok(SetFriendVerificationPhrase {
+        friend_public_key: read_public_key(&set_phrase_reader.get_friend_public_key()?)?,
+        phrase: set_phrase_reader.get_phrase()?.to_owned(),
+    })
+}
+
+fn ser_set_friend_index_private(
+    set_friend_index_private: &SetFriendIndexPrivate,
+    set_index_private_builder: &mut app_server_capnp::set_friend_index_private::Builder,
+) {
+    write_public_key(
+        &set_friend_index_private.friend_public_key,
+        &mut set_index_private_builder
+            .reborrow()
+            .init_friend_public_key(),
+    );
+
+    set_index_private_builder.set_index_private(set_friend_index_private.index_private);
+}
+
+fn deser_set_friend_index_private(
+    set_index_private_reader: &app_server_capnp::set_friend_index_private::Reader,
+) -> Result<SetFriendIndexPrivate, SerializeError> {
+    Ok(SetFriendIndexPrivate {
+        friend_public_key: read_public_key(&set_index_private_reader.get_friend_public_key()?)?,
+        index_private: set_index_private_reader.get_index_private(),
     })
 }
 
@@ -286,6 +1211,78 @@ fn deser_reset_friend_channel(
     })
 }
 
+fn ser_import_friend_invite(
+    import_friend_invite: &ImportFriendInvite,
+    import_friend_invite_builder: &mut app_server_capnp::import_friend_invite::Builder,
+) {
+    write_uid(
+        &import_friend_invite.request_id,
+        &mut import_friend_invite_builder.reborrow().init_request_id(),
+    );
+    write_friend_invite(
+        &import_friend_invite.invite,
+        &mut import_friend_invite_builder.reborrow().init_invite(),
+    );
+    import_friend_invite_builder.set_name(&import_friend_invite.name);
+    import_friend_invite_builder
+        .set_accept_suggested_max_debt(import_friend_invite.accept_suggested_max_debt);
+}
+
+fn deser_import_friend_invite(
+    import_friend_invite_reader: &app_server_capnp::import_friend_invite::Reader,
+) -> Result<ImportFriendInvite, SerializeError> {
+    Ok(ImportFriendInvite {
+        request_id: read_uid(&import_friend_invite_reader.get_request_id()?)?,
+        invite: read_friend_invite(&import_friend_invite_reader.get_invite()?)?,
+        name: import_friend_invite_reader.get_name()?.to_owned(),
+        accept_suggested_max_debt: import_friend_invite_reader.get_accept_suggested_max_debt(),
+    })
+}
+
+fn ser_response_import_friend_invite(
+    response_import: &ResponseImportFriendInvite,
+    response_import_builder: &mut app_server_capnp::response_import_friend_invite::Builder,
+) {
+    write_uid(
+        &response_import.request_id,
+        &mut response_import_builder.reborrow().init_request_id(),
+    );
+
+    let mut result_builder = response_import_builder.reborrow().init_result();
+    match response_import.result {
+        ImportFriendInviteResult::Success => result_builder.set_success(()),
+        ImportFriendInviteResult::InvalidSignature => result_builder.set_invalid_signature(()),
+        ImportFriendInviteResult::Expired => result_builder.set_expired(()),
+        ImportFriendInviteResult::FriendAlreadyExists => {
+            result_builder.set_friend_already_exists(())
+        }
+    };
+}
+
+fn deser_response_import_friend_invite(
+    response_import_reader: &app_server_capnp::response_import_friend_invite::Reader,
+) -> Result<ResponseImportFriendInvite, SerializeError> {
+    let result = match response_import_reader.get_result().which()? {
+        app_server_capnp::response_import_friend_invite::result::Success(()) => {
+            ImportFriendInviteResult::Success
+        }
+        app_server_capnp::response_import_friend_invite::result::InvalidSignature(()) => {
+            ImportFriendInviteResult::InvalidSignature
+        }
+        app_server_capnp::response_import_friend_invite::result::Expired(()) => {
+            ImportFriendInviteResult::Expired
+        }
+        app_server_capnp::response_import_friend_invite::result::FriendAlreadyExists(()) => {
+            ImportFriendInviteResult::FriendAlreadyExists
+        }
+    };
+
+    Ok(ResponseImportFriendInvite {
+        request_id: read_uid(&response_import_reader.get_request_id()?)?,
+        result,
+    })
+}
+
 // TODO: Add serialization code for ResponseRoutesResult, ClientResponseRoutes
 fn ser_response_routes_result(
     response_routes_result: &ResponseRoutesResult,
@@ -391,6 +1388,50 @@ fn deser_app_permissions(
     })
 }
 
+fn ser_app_hello(
+    app_hello: &AppHello,
+    app_hello_builder: &mut app_server_capnp::app_hello::Builder,
+) {
+    write_uid(
+        &app_hello.session_id,
+        &mut app_hello_builder.reborrow().init_session_id(),
+    );
+}
+
+fn deser_app_hello(
+    app_hello_reader: &app_server_capnp::app_hello::Reader,
+) -> Result<AppHello, SerializeError> {
+    Ok(AppHello {
+        session_id: read_uid(&app_hello_reader.get_session_id()?)?,
+    })
+}
+
+fn ser_report_scope(
+    report_scope: &ReportScope,
+    report_scope_builder: &mut app_server_capnp::report_scope::Builder,
+) {
+    match report_scope {
+        ReportScope::Node => report_scope_builder.set_node(()),
+        ReportScope::Friend(friend_public_key) => {
+            write_public_key(
+                friend_public_key,
+                &mut report_scope_builder.reborrow().init_friend(),
+            );
+        }
+    }
+}
+
+fn deser_report_scope(
+    report_scope_reader: &app_server_capnp::report_scope::Reader,
+) -> Result<ReportScope, SerializeError> {
+    Ok(match report_scope_reader.which()? {
+        app_server_capnp::report_scope::Node(()) => ReportScope::Node,
+        app_server_capnp::report_scope::Friend(public_key_reader) => {
+            ReportScope::Friend(read_public_key(&public_key_reader?)?)
+        }
+    })
+}
+
 fn ser_report_mutations(
     report_mutations: &ReportMutations,
     report_mutations_builder: &mut app_server_capnp::report_mutations::Builder,
@@ -418,6 +1459,24 @@ fn ser_report_mutations(
             .get(usize_to_u32(index).unwrap());
         ser_node_report_mutation(node_report_mutation, &mut node_report_mutation_builder);
     }
+
+    ser_report_scope(
+        &report_mutations.scope,
+        &mut report_mutations_builder.reborrow().init_scope(),
+    );
+    report_mutations_builder.set_seq(report_mutations.seq);
+
+    let dependencies_len = usize_to_u32(report_mutations.dependencies.len()).unwrap();
+    let mut dependencies_builder = report_mutations_builder
+        .reborrow()
+        .init_dependencies(dependencies_len);
+    for (index, (scope, seq)) in report_mutations.dependencies.iter().enumerate() {
+        let mut scope_seq_builder = dependencies_builder
+            .reborrow()
+            .get(usize_to_u32(index).unwrap());
+        ser_report_scope(scope, &mut scope_seq_builder.reborrow().init_scope());
+        scope_seq_builder.set_seq(*seq);
+    }
 }
 
 fn deser_report_mutations(
@@ -435,8 +1494,17 @@ fn deser_report_mutations(
         mutations.push(deser_node_report_mutation(&node_report_mutation)?);
     }
 
+    let mut dependencies = Vec::new();
+    for scope_seq_reader in report_mutations_reader.get_dependencies()? {
+        let scope = deser_report_scope(&scope_seq_reader.get_scope()?)?;
+        dependencies.push((scope, scope_seq_reader.get_seq()));
+    }
+
     Ok(ReportMutations {
         opt_app_request_id,
+        scope: deser_report_scope(&report_mutations_reader.get_scope()?)?,
+        seq: report_mutations_reader.get_seq(),
+        dependencies,
         mutations,
     })
 }
@@ -464,6 +1532,64 @@ fn ser_app_server_to_app(
             response_routes,
             &mut app_server_to_app_builder.reborrow().init_response_routes(),
         ),
+        AppServerToApp::ResponsePrewarm(response_prewarm) => ser_response_prewarm(
+            response_prewarm,
+            &mut app_server_to_app_builder.reborrow().init_response_prewarm(),
+        ),
+        AppServerToApp::ResponseCancelUserRequest(response_cancel) => {
+            ser_response_cancel_user_request(
+                response_cancel,
+                &mut app_server_to_app_builder
+                    .reborrow()
+                    .init_response_cancel_user_request(),
+            )
+        }
+        AppServerToApp::ResponseImportFriendInvite(response_import) => {
+            ser_response_import_friend_invite(
+                response_import,
+                &mut app_server_to_app_builder
+                    .reborrow()
+                    .init_response_import_friend_invite(),
+            )
+        }
+        AppServerToApp::ResponsePendingSubmissions(response_pending) => {
+            ser_response_pending_submissions(
+                response_pending,
+                &mut app_server_to_app_builder
+                    .reborrow()
+                    .init_response_pending_submissions(),
+            )
+        }
+        AppServerToApp::ResponsePairFreezeUsage(response_usage) => ser_response_pair_freeze_usage(
+            response_usage,
+            &mut app_server_to_app_builder
+                .reborrow()
+                .init_response_pair_freeze_usage(),
+        ),
+        AppServerToApp::ResponseDebugBundle(response_debug_bundle) => ser_response_debug_bundle(
+            response_debug_bundle,
+            &mut app_server_to_app_builder
+                .reborrow()
+                .init_response_debug_bundle(),
+        ),
+        AppServerToApp::ResponseSelfTest(response_self_test) => ser_response_self_test(
+            response_self_test,
+            &mut app_server_to_app_builder
+                .reborrow()
+                .init_response_self_test(),
+        ),
+        AppServerToApp::ResponseLabeledPayments(response_labeled_payments) => {
+            ser_response_labeled_payments(
+                response_labeled_payments,
+                &mut app_server_to_app_builder
+                    .reborrow()
+                    .init_response_labeled_payments(),
+            )
+        }
+        AppServerToApp::IncomingPayment(incoming_payment) => ser_incoming_payment(
+            incoming_payment,
+            &mut app_server_to_app_builder.reborrow().init_incoming_payment(),
+        ),
     }
 }
 
@@ -485,6 +1611,100 @@ fn deser_app_server_to_app(
                 &client_response_routes_reader?,
             )?)
         }
+        app_server_capnp::app_server_to_app::ResponsePrewarm(response_prewarm_reader) => {
+            AppServerToApp::ResponsePrewarm(deser_response_prewarm(&response_prewarm_reader?)?)
+        }
+        app_server_capnp::app_server_to_app::ResponseCancelUserRequest(response_cancel_reader) => {
+            AppServerToApp::ResponseCancelUserRequest(deser_response_cancel_user_request(
+                &response_cancel_reader?,
+            )?)
+        }
+        app_server_capnp::app_server_to_app::ResponseImportFriendInvite(response_import_reader) => {
+            AppServerToApp::ResponseImportFriendInvite(deser_response_import_friend_invite(
+                &response_import_reader?,
+            )?)
+        }
+        app_server_capnp::app_server_to_app::ResponsePendingSubmissions(pending_reader) => {
+            AppServerToApp::ResponsePendingSubmissions(deser_response_pending_submissions(
+                &pending_reader?,
+            )?)
+        }
+        app_server_capnp::app_server_to_app::ResponsePairFreezeUsage(response_usage_reader) => {
+            AppServerToApp::ResponsePairFreezeUsage(deser_response_pair_freeze_usage(
+                &response_usage_reader?,
+            )?)
+        }
+        app_server_capnp::app_server_to_app::ResponseDebugBundle(bundle_reader) => {
+            AppServerToApp::ResponseDebugBundle(deser_response_debug_bundle(&bundle_reader?)?)
+        }
+        app_server_capnp::app_server_to_app::ResponseSelfTest(response_self_test_reader) => {
+            AppServerToApp::ResponseSelfTest(deser_response_self_test(&response_self_test_reader?)?)
+        }
+        app_server_capnp::app_server_to_app::ResponseLabeledPayments(labeled_payments_reader) => {
+            AppServerToApp::ResponseLabeledPayments(deser_response_labeled_payments(
+                &labeled_payments_reader?,
+            )?)
+        }
+        app_server_capnp::app_server_to_app::IncomingPayment(incoming_payment_reader) => {
+            AppServerToApp::IncomingPayment(deser_incoming_payment(&incoming_payment_reader?)?)
+        }
+        app_server_capnp::app_server_to_app::TransferChunk(_)
+        | app_server_capnp::app_server_to_app::AbortTransfer(_) => {
+            return Err(SerializeError::TransferFrame)
+        }
+    })
+}
+
+fn ser_transfer_chunk(
+    transfer_chunk: &TransferChunk,
+    transfer_chunk_builder: &mut app_server_capnp::transfer_chunk::Builder,
+) {
+    transfer_chunk_builder.set_transfer_id(transfer_chunk.transfer_id);
+    transfer_chunk_builder.set_index(transfer_chunk.index);
+    transfer_chunk_builder.set_total(transfer_chunk.total);
+    transfer_chunk_builder.set_data(&transfer_chunk.data);
+}
+
+fn deser_transfer_chunk(
+    transfer_chunk_reader: &app_server_capnp::transfer_chunk::Reader,
+) -> Result<TransferChunk, SerializeError> {
+    Ok(TransferChunk {
+        transfer_id: transfer_chunk_reader.get_transfer_id(),
+        index: transfer_chunk_reader.get_index(),
+        total: transfer_chunk_reader.get_total(),
+        data: transfer_chunk_reader.get_data()?.to_vec(),
+    })
+}
+
+fn ser_app_server_to_app_frame(
+    app_server_to_app_frame: &AppServerToAppFrame,
+    app_server_to_app_builder: &mut app_server_capnp::app_server_to_app::Builder,
+) {
+    match app_server_to_app_frame {
+        AppServerToAppFrame::Message(app_server_to_app) => {
+            ser_app_server_to_app(app_server_to_app, app_server_to_app_builder)
+        }
+        AppServerToAppFrame::Chunk(transfer_chunk) => ser_transfer_chunk(
+            transfer_chunk,
+            &mut app_server_to_app_builder.reborrow().init_transfer_chunk(),
+        ),
+        AppServerToAppFrame::AbortTransfer(transfer_id) => {
+            app_server_to_app_builder.set_abort_transfer(*transfer_id)
+        }
+    }
+}
+
+fn deser_app_server_to_app_frame(
+    app_server_to_app_reader: &app_server_capnp::app_server_to_app::Reader,
+) -> Result<AppServerToAppFrame, SerializeError> {
+    Ok(match app_server_to_app_reader.which()? {
+        app_server_capnp::app_server_to_app::TransferChunk(transfer_chunk_reader) => {
+            AppServerToAppFrame::Chunk(deser_transfer_chunk(&transfer_chunk_reader?)?)
+        }
+        app_server_capnp::app_server_to_app::AbortTransfer(transfer_id) => {
+            AppServerToAppFrame::AbortTransfer(transfer_id)
+        }
+        _ => AppServerToAppFrame::Message(deser_app_server_to_app(app_server_to_app_reader)?),
     })
 }
 
@@ -505,10 +1725,22 @@ fn ser_app_request(
             user_request_send_funds,
             &mut app_request_builder.reborrow().init_request_send_funds(),
         ),
+        AppRequest::RequestSweepFunds(user_request_sweep_funds) => ser_user_request_sweep_funds(
+            user_request_sweep_funds,
+            &mut app_request_builder.reborrow().init_request_sweep_funds(),
+        ),
+        AppRequest::CancelUserRequest(request_id) => write_uid(
+            request_id,
+            &mut app_request_builder.reborrow().init_cancel_user_request(),
+        ),
         AppRequest::ReceiptAck(receipt_ack) => ser_receipt_ack(
             receipt_ack,
             &mut app_request_builder.reborrow().init_receipt_ack(),
         ),
+        AppRequest::PrewarmFriend(prewarm_friend) => ser_prewarm_friend(
+            prewarm_friend,
+            &mut app_request_builder.reborrow().init_prewarm_friend(),
+        ),
         AppRequest::AddFriend(add_friend) => ser_add_friend(
             add_friend,
             &mut app_request_builder.reborrow().init_add_friend(),
@@ -549,10 +1781,58 @@ fn ser_app_request(
                     .init_set_friend_remote_max_debt(),
             )
         }
+        AppRequest::SetFriendResponseDeadline(set_friend_response_deadline) => {
+            ser_set_friend_response_deadline(
+                set_friend_response_deadline,
+                &mut app_request_builder
+                    .reborrow()
+                    .init_set_friend_response_deadline(),
+            )
+        }
+        AppRequest::SetFriendVerificationPhrase(set_friend_verification_phrase) => {
+            ser_set_friend_verification_phrase(
+                set_friend_verification_phrase,
+                &mut app_request_builder
+                    .reborrow()
+                    .init_set_friend_verification_phrase(),
+            )
+        }
+        AppRequest::SetFriendIndexPrivate(set_friend_index_private) => {
+            ser_set_friend_index_private(
+                set_friend_index_private,
+                &mut app_request_builder
+                    .reborrow()
+                    .init_set_friend_index_private(),
+            )
+        }
         AppRequest::ResetFriendChannel(reset_friend_channel) => ser_reset_friend_channel(
             reset_friend_channel,
             &mut app_request_builder.reborrow().init_reset_friend_channel(),
         ),
+        AppRequest::ImportFriendInvite(import_friend_invite) => ser_import_friend_invite(
+            import_friend_invite,
+            &mut app_request_builder.reborrow().init_import_friend_invite(),
+        ),
+        AppRequest::ListPendingSubmissions(request_id) => write_uid(
+            request_id,
+            &mut app_request_builder
+                .reborrow()
+                .init_list_pending_submissions(),
+        ),
+        AppRequest::AckSubmission(request_id) => write_uid(
+            request_id,
+            &mut app_request_builder.reborrow().init_ack_submission(),
+        ),
+        AppRequest::SetPairFreezeLimit(set_pair_freeze_limit) => ser_set_pair_freeze_limit(
+            set_pair_freeze_limit,
+            &mut app_request_builder.reborrow().init_set_pair_freeze_limit(),
+        ),
+        AppRequest::RequestPairFreezeUsage(request_usage) => ser_request_pair_freeze_usage(
+            request_usage,
+            &mut app_request_builder
+                .reborrow()
+                .init_request_pair_freeze_usage(),
+        ),
         AppRequest::RequestRoutes(request_routes) => ser_request_routes(
             request_routes,
             &mut app_request_builder.reborrow().init_request_routes(),
@@ -567,6 +1847,54 @@ fn ser_app_request(
             public_key,
             &mut app_request_builder.reborrow().init_remove_index_server(),
         ),
+        AppRequest::SetPaymentNotifier(payment_notifier) => ser_payment_notifier(
+            payment_notifier,
+            &mut app_request_builder.reborrow().init_set_payment_notifier(),
+        ),
+        AppRequest::ClearPaymentNotifier => app_request_builder.set_clear_payment_notifier(()),
+        AppRequest::SubscribeIncomingPayments => {
+            app_request_builder.set_subscribe_incoming_payments(())
+        }
+        AppRequest::AckIncomingPayment(notification_id) => {
+            app_request_builder.set_ack_incoming_payment(*notification_id)
+        }
+        AppRequest::RequestDebugBundle(request_debug_bundle) => ser_request_debug_bundle(
+            request_debug_bundle,
+            &mut app_request_builder.reborrow().init_request_debug_bundle(),
+        ),
+        AppRequest::RequestSelfTest(request_id) => write_uid(
+            request_id,
+            &mut app_request_builder.reborrow().init_request_self_test(),
+        ),
+        AppRequest::SetDustThresholds(dust_thresholds) => write_dust_thresholds(
+            dust_thresholds,
+            &mut app_request_builder.reborrow().init_set_dust_thresholds(),
+        ),
+        AppRequest::SetDirectory(subscription) => write_directory_subscription(
+            subscription,
+            &mut app_request_builder.reborrow().init_set_directory(),
+        ),
+        AppRequest::ClearDirectory => app_request_builder.set_clear_directory(()),
+        AppRequest::PinDirectoryEntry(public_key) => write_public_key(
+            public_key,
+            &mut app_request_builder.reborrow().init_pin_directory_entry(),
+        ),
+        AppRequest::UnpinDirectoryEntry(public_key) => write_public_key(
+            public_key,
+            &mut app_request_builder.reborrow().init_unpin_directory_entry(),
+        ),
+        AppRequest::AnnounceShutdown(goodbye) => ser_goodbye(
+            goodbye,
+            &mut app_request_builder.reborrow().init_announce_shutdown(),
+        ),
+        AppRequest::RequestLabeledPayments(request_labeled_payments) => {
+            ser_request_labeled_payments(
+                request_labeled_payments,
+                &mut app_request_builder
+                    .reborrow()
+                    .init_request_labeled_payments(),
+            )
+        }
     }
 }
 
@@ -585,9 +1913,20 @@ fn deser_app_request(
                 &request_send_funds_reader?,
             )?)
         }
+        app_server_capnp::app_request::RequestSweepFunds(request_sweep_funds_reader) => {
+            AppRequest::RequestSweepFunds(deser_user_request_sweep_funds(
+                &request_sweep_funds_reader?,
+            )?)
+        }
+        app_server_capnp::app_request::CancelUserRequest(request_id_reader) => {
+            AppRequest::CancelUserRequest(read_uid(&request_id_reader?)?)
+        }
         app_server_capnp::app_request::ReceiptAck(receipt_ack_reader) => {
             AppRequest::ReceiptAck(deser_receipt_ack(&receipt_ack_reader?)?)
         }
+        app_server_capnp::app_request::PrewarmFriend(prewarm_friend_reader) => {
+            AppRequest::PrewarmFriend(deser_prewarm_friend(&prewarm_friend_reader?)?)
+        }
         app_server_capnp::app_request::AddFriend(add_friend_reader) => {
             AppRequest::AddFriend(deser_add_friend(&add_friend_reader?)?)
         }
@@ -617,11 +1956,47 @@ fn deser_app_request(
         ) => AppRequest::SetFriendRemoteMaxDebt(deser_set_friend_remote_max_debt(
             &set_friend_remote_max_debt_reader?,
         )?),
+        app_server_capnp::app_request::SetFriendResponseDeadline(
+            set_friend_response_deadline_reader,
+        ) => AppRequest::SetFriendResponseDeadline(deser_set_friend_response_deadline(
+            &set_friend_response_deadline_reader?,
+        )?),
+        app_server_capnp::app_request::SetFriendVerificationPhrase(
+            set_friend_verification_phrase_reader,
+        ) => AppRequest::SetFriendVerificationPhrase(deser_set_friend_verification_phrase(
+            &set_friend_verification_phrase_reader?,
+        )?),
+        app_server_capnp::app_request::SetFriendIndexPrivate(set_friend_index_private_reader) => {
+            AppRequest::SetFriendIndexPrivate(deser_set_friend_index_private(
+                &set_friend_index_private_reader?,
+            )?)
+        }
         app_server_capnp::app_request::ResetFriendChannel(reset_friend_channel_reader) => {
             AppRequest::ResetFriendChannel(deser_reset_friend_channel(
                 &reset_friend_channel_reader?,
             )?)
         }
+        app_server_capnp::app_request::ImportFriendInvite(import_friend_invite_reader) => {
+            AppRequest::ImportFriendInvite(deser_import_friend_invite(
+                &import_friend_invite_reader?,
+            )?)
+        }
+        app_server_capnp::app_request::ListPendingSubmissions(request_id_reader) => {
+            AppRequest::ListPendingSubmissions(read_uid(&request_id_reader?)?)
+        }
+        app_server_capnp::app_request::AckSubmission(request_id_reader) => {
+            AppRequest::AckSubmission(read_uid(&request_id_reader?)?)
+        }
+        app_server_capnp::app_request::SetPairFreezeLimit(set_pair_freeze_limit_reader) => {
+            AppRequest::SetPairFreezeLimit(deser_set_pair_freeze_limit(
+                &set_pair_freeze_limit_reader?,
+            )?)
+        }
+        app_server_capnp::app_request::RequestPairFreezeUsage(request_usage_reader) => {
+            AppRequest::RequestPairFreezeUsage(deser_request_pair_freeze_usage(
+                &request_usage_reader?,
+            )?)
+        }
         app_server_capnp::app_request::RequestRoutes(request_routes_reader) => {
             AppRequest::RequestRoutes(deser_request_routes(&request_routes_reader?)?)
         }
@@ -631,6 +2006,46 @@ fn deser_app_request(
         app_server_capnp::app_request::RemoveIndexServer(public_key_reader) => {
             AppRequest::RemoveIndexServer(read_public_key(&public_key_reader?)?)
         }
+        app_server_capnp::app_request::SetPaymentNotifier(payment_notifier_reader) => {
+            AppRequest::SetPaymentNotifier(deser_payment_notifier(&payment_notifier_reader?)?)
+        }
+        app_server_capnp::app_request::ClearPaymentNotifier(()) => AppRequest::ClearPaymentNotifier,
+        app_server_capnp::app_request::SubscribeIncomingPayments(()) => {
+            AppRequest::SubscribeIncomingPayments
+        }
+        app_server_capnp::app_request::AckIncomingPayment(notification_id) => {
+            AppRequest::AckIncomingPayment(notification_id)
+        }
+        app_server_capnp::app_request::RequestDebugBundle(request_bundle_reader) => {
+            AppRequest::RequestDebugBundle(deser_request_debug_bundle(&request_bundle_reader?)?)
+        }
+        app_server_capnp::app_request::RequestSelfTest(request_id_reader) => {
+            AppRequest::RequestSelfTest(read_uid(&request_id_reader?)?)
+        }
+        app_server_capnp::app_request::SetDustThresholds(dust_thresholds_reader) => {
+            AppRequest::SetDustThresholds(read_dust_thresholds(&dust_thresholds_reader?)?)
+        }
+        app_server_capnp::app_request::SetDirectory(subscription_reader) => {
+            AppRequest::SetDirectory(read_directory_subscription(&subscription_reader?)?)
+        }
+        app_server_capnp::app_request::ClearDirectory(()) => AppRequest::ClearDirectory,
+        app_server_capnp::app_request::PinDirectoryEntry(public_key_reader) => {
+            AppRequest::PinDirectoryEntry(read_public_key(&public_key_reader?)?)
+        }
+        app_server_capnp::app_request::UnpinDirectoryEntry(public_key_reader) => {
+            AppRequest::UnpinDirectoryEntry(read_public_key(&public_key_reader?)?)
+        }
+        app_server_capnp::app_request::AnnounceShutdown(goodbye_reader) => {
+            AppRequest::AnnounceShutdown(deser_goodbye(&goodbye_reader?)?)
+        }
+        app_server_capnp::app_request::RequestLabeledPayments(request_labeled_payments_reader) => {
+            AppRequest::RequestLabeledPayments(deser_request_labeled_payments(
+                &request_labeled_payments_reader?,
+            )?)
+        }
+        app_server_capnp::app_request::AbortTransfer(_) => {
+            return Err(SerializeError::TransferFrame)
+        }
     })
 }
 
@@ -658,6 +2073,40 @@ fn deser_app_to_app_server(
     })
 }
 
+fn ser_app_to_app_server_frame(
+    app_to_app_server_frame: &AppToAppServerFrame,
+    app_to_app_server_builder: &mut app_server_capnp::app_to_app_server::Builder,
+) {
+    match app_to_app_server_frame {
+        AppToAppServerFrame::Message(app_to_app_server) => {
+            ser_app_to_app_server(app_to_app_server, app_to_app_server_builder)
+        }
+        AppToAppServerFrame::AbortTransfer(transfer_id) => {
+            // An abort is not a request, so it has no meaningful request id:
+            write_uid(
+                &Uid::from(&[0; UID_LEN]),
+                &mut app_to_app_server_builder.reborrow().init_app_request_id(),
+            );
+            app_to_app_server_builder
+                .reborrow()
+                .init_app_request()
+                .set_abort_transfer(*transfer_id);
+        }
+    }
+}
+
+fn deser_app_to_app_server_frame(
+    app_to_app_server_reader: &app_server_capnp::app_to_app_server::Reader,
+) -> Result<AppToAppServerFrame, SerializeError> {
+    let app_request_reader = app_to_app_server_reader.get_app_request()?;
+    Ok(match app_request_reader.which()? {
+        app_server_capnp::app_request::AbortTransfer(transfer_id) => {
+            AppToAppServerFrame::AbortTransfer(transfer_id)
+        }
+        _ => AppToAppServerFrame::Message(deser_app_to_app_server(app_to_app_server_reader)?),
+    })
+}
+
 // ---------------------------------------------------
 // ---------------------------------------------------
 pub fn serialize_app_permissions(app_permissions: &AppPermissions) -> Vec<u8> {
@@ -680,6 +2129,25 @@ pub fn deserialize_app_permissions(data: &[u8]) -> Result<AppPermissions, Serial
     deser_app_permissions(&app_permissions_reader)
 }
 
+pub fn serialize_app_hello(app_hello: &AppHello) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut app_hello_builder = builder.init_root::<app_server_capnp::app_hello::Builder>();
+    ser_app_hello(app_hello, &mut app_hello_builder);
+
+    let mut ser_buff = Vec::new();
+    serialize_packed::write_message(&mut ser_buff, &builder).unwrap();
+    ser_buff
+}
+
+pub fn deserialize_app_hello(data: &[u8]) -> Result<AppHello, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let app_hello_reader = reader.get_root::<app_server_capnp::app_hello::Reader>()?;
+
+    deser_app_hello(&app_hello_reader)
+}
+
 pub fn serialize_app_server_to_app(app_server_to_app: &AppServerToApp) -> Vec<u8> {
     let mut builder = capnp::message::Builder::new_default();
     let mut app_server_to_app_builder =
@@ -701,6 +2169,29 @@ pub fn deserialize_app_server_to_app(data: &[u8]) -> Result<AppServerToApp, Seri
     deser_app_server_to_app(&app_server_to_app_reader)
 }
 
+pub fn serialize_app_server_to_app_frame(app_server_to_app_frame: &AppServerToAppFrame) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut app_server_to_app_builder =
+        builder.init_root::<app_server_capnp::app_server_to_app::Builder>();
+    ser_app_server_to_app_frame(app_server_to_app_frame, &mut app_server_to_app_builder);
+
+    let mut ser_buff = Vec::new();
+    serialize_packed::write_message(&mut ser_buff, &builder).unwrap();
+    ser_buff
+}
+
+pub fn deserialize_app_server_to_app_frame(
+    data: &[u8],
+) -> Result<AppServerToAppFrame, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let app_server_to_app_reader =
+        reader.get_root::<app_server_capnp::app_server_to_app::Reader>()?;
+
+    deser_app_server_to_app_frame(&app_server_to_app_reader)
+}
+
 pub fn serialize_app_to_app_server(app_server_to_app: &AppToAppServer) -> Vec<u8> {
     let mut builder = capnp::message::Builder::new_default();
     let mut app_to_app_server = builder.init_root::<app_server_capnp::app_to_app_server::Builder>();
@@ -720,13 +2211,39 @@ pub fn deserialize_app_to_app_server(data: &[u8]) -> Result<AppToAppServer, Seri
     deser_app_to_app_server(&app_to_app_server)
 }
 
+pub fn serialize_app_to_app_server_frame(app_to_app_server_frame: &AppToAppServerFrame) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut app_to_app_server = builder.init_root::<app_server_capnp::app_to_app_server::Builder>();
+    ser_app_to_app_server_frame(app_to_app_server_frame, &mut app_to_app_server);
+
+    let mut ser_buff = Vec::new();
+    serialize_packed::write_message(&mut ser_buff, &builder).unwrap();
+    ser_buff
+}
+
+pub fn deserialize_app_to_app_server_frame(
+    data: &[u8],
+) -> Result<AppToAppServerFrame, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let app_to_app_server = reader.get_root::<app_server_capnp::app_to_app_server::Reader>()?;
+
+    deser_app_to_app_server_frame(&app_to_app_server)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::app_server::messages::{NodeReportMutation, RelayAddress};
+    use crate::directory::messages::DirectorySubscription;
+    use crate::funder::messages::{DustThresholds, FriendsRoute, Goodbye, LabeledPayment, Receipt};
     use crate::index_client::messages::IndexClientReportMutation;
+    use crate::invite::messages::FriendInvite;
     use crate::report::messages::FunderReportMutation;
-    use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
+    use crypto::hash::{HashResult, HASH_RESULT_LEN};
+    use crypto::identity::{PublicKey, Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+    use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
     use crypto::uid::{Uid, UID_LEN};
     use std::convert::TryInto;
 
@@ -743,6 +2260,17 @@ mod tests {
         assert_eq!(app_permissions, app_permissions2);
     }
 
+    #[test]
+    fn test_serialize_app_hello() {
+        let app_hello = AppHello {
+            session_id: Uid::from(&[0x22; UID_LEN]),
+        };
+
+        let data = serialize_app_hello(&app_hello);
+        let app_hello2 = deserialize_app_hello(&data).unwrap();
+        assert_eq!(app_hello, app_hello2);
+    }
+
     #[test]
     fn test_serialize_app_server_to_app() {
         let mut mutations = Vec::new();
@@ -760,6 +2288,15 @@ mod tests {
         ));
         let report_mutations = ReportMutations {
             opt_app_request_id: Some(Uid::from(&[0; UID_LEN])),
+            scope: ReportScope::Node,
+            seq: 3,
+            dependencies: vec![
+                (
+                    ReportScope::Friend(PublicKey::from(&[0xaa; PUBLIC_KEY_LEN])),
+                    7,
+                ),
+                (ReportScope::Node, 2),
+            ],
             mutations,
         };
         let app_server_to_app = AppServerToApp::ReportMutations(report_mutations);
@@ -797,5 +2334,642 @@ mod tests {
         assert_eq!(app_to_app_server, app_to_app_server2);
     }
 
+    #[test]
+    fn test_serialize_app_to_app_server_payment_notifier() {
+        let invoice_ids = vec![
+            InvoiceId::from(&[0x11; INVOICE_ID_LEN]),
+            InvoiceId::from(&[0x22; INVOICE_ID_LEN]),
+        ];
+        let payment_notifiers = vec![
+            PaymentNotifier {
+                consumer: PaymentConsumer::Address("consumer:1340".to_owned().try_into().unwrap()),
+                filter: PaymentNotifyFilter::All,
+            },
+            PaymentNotifier {
+                consumer: PaymentConsumer::Apps,
+                filter: PaymentNotifyFilter::Invoices(invoice_ids),
+            },
+        ];
+
+        for payment_notifier in payment_notifiers {
+            let app_to_app_server = AppToAppServer {
+                app_request_id: Uid::from(&[2; UID_LEN]),
+                app_request: AppRequest::SetPaymentNotifier(payment_notifier),
+            };
+            let data = serialize_app_to_app_server(&app_to_app_server);
+            let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+            assert_eq!(app_to_app_server, app_to_app_server2);
+        }
+
+        let app_to_app_server = AppToAppServer {
+            app_request_id: Uid::from(&[3; UID_LEN]),
+            app_request: AppRequest::ClearPaymentNotifier,
+        };
+        let data = serialize_app_to_app_server(&app_to_app_server);
+        let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+        assert_eq!(app_to_app_server, app_to_app_server2);
+    }
+
+    #[test]
+    fn test_serialize_incoming_payments() {
+        for app_request in vec![
+            AppRequest::SubscribeIncomingPayments,
+            AppRequest::AckIncomingPayment(0x1234),
+        ] {
+            let app_to_app_server = AppToAppServer {
+                app_request_id: Uid::from(&[7; UID_LEN]),
+                app_request,
+            };
+            let data = serialize_app_to_app_server(&app_to_app_server);
+            let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+            assert_eq!(app_to_app_server, app_to_app_server2);
+        }
+
+        let app_server_to_app = AppServerToApp::IncomingPayment(IncomingPayment {
+            notification_id: 0x1234,
+            route_len: 3,
+            receipt: Receipt {
+                response_hash: HashResult::from(&[0x11; HASH_RESULT_LEN]),
+                invoice_id: InvoiceId::from(&[0x22; INVOICE_ID_LEN]),
+                dest_payment: 10,
+                signature: Signature::from(&[0x33; SIGNATURE_LEN]),
+            },
+        });
+        let data = serialize_app_server_to_app(&app_server_to_app);
+        let app_server_to_app2 = deserialize_app_server_to_app(&data).unwrap();
+        assert_eq!(app_server_to_app, app_server_to_app2);
+    }
+
+    #[test]
+    fn test_serialize_prewarm() {
+        let prewarm_friend = PrewarmFriend {
+            request_id: Uid::from(&[4; UID_LEN]),
+            friend_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+        };
+        let app_to_app_server = AppToAppServer {
+            app_request_id: Uid::from(&[5; UID_LEN]),
+            app_request: AppRequest::PrewarmFriend(prewarm_friend),
+        };
+        let data = serialize_app_to_app_server(&app_to_app_server);
+        let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+        assert_eq!(app_to_app_server, app_to_app_server2);
+
+        let results = vec![
+            PrewarmResult::Success,
+            PrewarmResult::Failure(PrewarmFailure::FriendDoesNotExist),
+            PrewarmResult::Failure(PrewarmFailure::FriendDisabled),
+            PrewarmResult::Failure(PrewarmFailure::FriendOffline),
+            PrewarmResult::Failure(PrewarmFailure::ChannelInconsistent),
+            PrewarmResult::Failure(PrewarmFailure::RateLimited),
+        ];
+        for result in results {
+            let app_server_to_app = AppServerToApp::ResponsePrewarm(ResponsePrewarm {
+                request_id: Uid::from(&[4; UID_LEN]),
+                result,
+            });
+            let data = serialize_app_server_to_app(&app_server_to_app);
+            let app_server_to_app2 = deserialize_app_server_to_app(&data).unwrap();
+            assert_eq!(app_server_to_app, app_server_to_app2);
+        }
+    }
+
+    #[test]
+    fn test_serialize_cancel_user_request() {
+        let app_to_app_server = AppToAppServer {
+            app_request_id: Uid::from(&[5; UID_LEN]),
+            app_request: AppRequest::CancelUserRequest(Uid::from(&[4; UID_LEN])),
+        };
+        let data = serialize_app_to_app_server(&app_to_app_server);
+        let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+        assert_eq!(app_to_app_server, app_to_app_server2);
+
+        let results = vec![
+            CancelUserRequestResult::Cancelled,
+            CancelUserRequestResult::InFlight,
+            CancelUserRequestResult::NotFound,
+        ];
+        for result in results {
+            let app_server_to_app =
+                AppServerToApp::ResponseCancelUserRequest(ResponseCancelUserRequest {
+                    request_id: Uid::from(&[4; UID_LEN]),
+                    result,
+                });
+            let data = serialize_app_server_to_app(&app_server_to_app);
+            let app_server_to_app2 = deserialize_app_server_to_app(&data).unwrap();
+            assert_eq!(app_server_to_app, app_server_to_app2);
+        }
+    }
+
+    #[test]
+    fn test_serialize_import_friend_invite() {
+        let invite = FriendInvite {
+            public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            relays: vec![RelayAddress {
+                public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                address: "MyAddress:1337".to_owned().try_into().unwrap(),
+            }],
+            opt_suggested_max_debt: Some(100),
+            expiry_time: 1_000_000,
+            signature: Signature::from(&[0xcc; SIGNATURE_LEN]),
+        };
+        let app_to_app_server = AppToAppServer {
+            app_request_id: Uid::from(&[5; UID_LEN]),
+            app_request: AppRequest::ImportFriendInvite(ImportFriendInvite {
+                request_id: Uid::from(&[4; UID_LEN]),
+                invite,
+                name: "friend".to_owned(),
+                accept_suggested_max_debt: true,
+            }),
+        };
+        let data = serialize_app_to_app_server(&app_to_app_server);
+        let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+        assert_eq!(app_to_app_server, app_to_app_server2);
+
+        let results = vec![
+            ImportFriendInviteResult::Success,
+            ImportFriendInviteResult::InvalidSignature,
+            ImportFriendInviteResult::Expired,
+            ImportFriendInviteResult::FriendAlreadyExists,
+        ];
+        for result in results {
+            let app_server_to_app =
+                AppServerToApp::ResponseImportFriendInvite(ResponseImportFriendInvite {
+                    request_id: Uid::from(&[4; UID_LEN]),
+                    result,
+                });
+            let data = serialize_app_server_to_app(&app_server_to_app);
+            let app_server_to_app2 = deserialize_app_server_to_app(&data).unwrap();
+            assert_eq!(app_server_to_app, app_server_to_app2);
+        }
+    }
+
+    #[test]
+    fn test_serialize_debug_bundle() {
+        let request_debug_bundle = RequestDebugBundle {
+            request_id: Uid::from(&[5; UID_LEN]),
+            full: true,
+        };
+        let app_to_app_server = AppToAppServer {
+            app_request_id: Uid::from(&[6; UID_LEN]),
+            app_request: AppRequest::RequestDebugBundle(request_debug_bundle),
+        };
+        let data = serialize_app_to_app_server(&app_to_app_server);
+        let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+        assert_eq!(app_to_app_server, app_to_app_server2);
+
+        let app_server_to_app = AppServerToApp::ResponseDebugBundle(ResponseDebugBundle {
+            request_id: Uid::from(&[5; UID_LEN]),
+            bundle: vec![1, 2, 3, 4],
+        });
+        let data = serialize_app_server_to_app(&app_server_to_app);
+        let app_server_to_app2 = deserialize_app_server_to_app(&data).unwrap();
+        assert_eq!(app_server_to_app, app_server_to_app2);
+    }
+
+    #[test]
+    fn test_serialize_self_test() {
+        let app_to_app_server = AppToAppServer {
+            app_request_id: Uid::from(&[6; UID_LEN]),
+            app_request: AppRequest::RequestSelfTest(Uid::from(&[5; UID_LEN])),
+        };
+        let data = serialize_app_to_app_server(&app_to_app_server);
+        let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+        assert_eq!(app_to_app_server, app_to_app_server2);
+
+        let stages = vec![
+            SelfTestStageReport {
+                stage: SelfTestStage::Identity,
+                success: true,
+                ticks: 0,
+            },
+            SelfTestStageReport {
+                stage: SelfTestStage::Channel,
+                success: true,
+                ticks: 3,
+            },
+            SelfTestStageReport {
+                stage: SelfTestStage::Credit,
+                success: false,
+                ticks: 0x20,
+            },
+        ];
+        let app_server_to_app = AppServerToApp::ResponseSelfTest(ResponseSelfTest {
+            request_id: Uid::from(&[5; UID_LEN]),
+            stages,
+        });
+        let data = serialize_app_server_to_app(&app_server_to_app);
+        let app_server_to_app2 = deserialize_app_server_to_app(&data).unwrap();
+        assert_eq!(app_server_to_app, app_server_to_app2);
+    }
+
+    #[test]
+    fn test_serialize_set_friend_response_deadline() {
+        for &opt_deadline_ticks in &[Some(0x20), None] {
+            let set_friend_response_deadline = SetFriendResponseDeadline {
+                friend_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                opt_deadline_ticks,
+            };
+            let app_to_app_server = AppToAppServer {
+                app_request_id: Uid::from(&[6; UID_LEN]),
+                app_request: AppRequest::SetFriendResponseDeadline(set_friend_response_deadline),
+            };
+            let data = serialize_app_to_app_server(&app_to_app_server);
+            let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+            assert_eq!(app_to_app_server, app_to_app_server2);
+        }
+    }
+
+    #[test]
+    fn test_serialize_set_friend_remote_max_debt() {
+        let opt_expiries = vec![
+            Some(RemoteMaxDebtExpiry {
+                expires_after_ticks: 0x40,
+                post_expiry_max_debt: 5,
+            }),
+            None,
+        ];
+        for opt_expiry in opt_expiries {
+            let set_friend_remote_max_debt = SetFriendRemoteMaxDebt {
+                friend_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                remote_max_debt: 100,
+                opt_expiry,
+            };
+            let app_to_app_server = AppToAppServer {
+                app_request_id: Uid::from(&[6; UID_LEN]),
+                app_request: AppRequest::SetFriendRemoteMaxDebt(set_friend_remote_max_debt),
+            };
+            let data = serialize_app_to_app_server(&app_to_app_server);
+            let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+            assert_eq!(app_to_app_server, app_to_app_server2);
+        }
+    }
+
+    #[test]
+    fn test_serialize_set_friend_verification_phrase() {
+        let set_friend_verification_phrase = SetFriendVerificationPhrase {
+            friend_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            phrase: "purple elephant".to_owned(),
+        };
+        let app_to_app_server = AppToAppServer {
+            app_request_id: Uid::from(&[7; UID_LEN]),
+            app_request: AppRequest::SetFriendVerificationPhrase(set_friend_verification_phrase),
+        };
+        let data = serialize_app_to_app_server(&app_to_app_server);
+        let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+        assert_eq!(app_to_app_server, app_to_app_server2);
+    }
+
+    #[test]
+    fn test_serialize_set_friend_index_private() {
+        for &index_private in &[false, true] {
+            let set_friend_index_private = SetFriendIndexPrivate {
+                friend_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                index_private,
+            };
+            let app_to_app_server = AppToAppServer {
+                app_request_id: Uid::from(&[7; UID_LEN]),
+                app_request: AppRequest::SetFriendIndexPrivate(set_friend_index_private),
+            };
+            let data = serialize_app_to_app_server(&app_to_app_server);
+            let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+            assert_eq!(app_to_app_server, app_to_app_server2);
+        }
+    }
+
+    #[test]
+    fn test_serialize_dust_thresholds() {
+        let dust_thresholds = DustThresholds {
+            min_send_payment: 10,
+            min_forward_payment: 20,
+            min_receive_payment: 0,
+        };
+        let app_to_app_server = AppToAppServer {
+            app_request_id: Uid::from(&[8; UID_LEN]),
+            app_request: AppRequest::SetDustThresholds(dust_thresholds),
+        };
+        let data = serialize_app_to_app_server(&app_to_app_server);
+        let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+        assert_eq!(app_to_app_server, app_to_app_server2);
+
+        // The failure reason of a response is kept:
+        let response_received = ResponseReceived {
+            request_id: Uid::from(&[9; UID_LEN]),
+            result: ResponseSendFundsResult::Failure((
+                PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                FailureReason::PricingRejected,
+            )),
+            opt_timing: None,
+            opt_label: None,
+        };
+        let app_server_to_app = AppServerToApp::ResponseReceived(response_received);
+        let data = serialize_app_server_to_app(&app_server_to_app);
+        let app_server_to_app2 = deserialize_app_server_to_app(&data).unwrap();
+        assert_eq!(app_server_to_app, app_server_to_app2);
+
+        // So is the remaining capacity of an insufficient local capacity failure:
+        let response_received = ResponseReceived {
+            request_id: Uid::from(&[9; UID_LEN]),
+            result: ResponseSendFundsResult::Failure((
+                PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                FailureReason::InsufficientLocalCapacity(0x1234_5678_9abc_def0_1234),
+            )),
+            opt_timing: None,
+            opt_label: None,
+        };
+        let app_server_to_app = AppServerToApp::ResponseReceived(response_received);
+        let data = serialize_app_server_to_app(&app_server_to_app);
+        let app_server_to_app2 = deserialize_app_server_to_app(&data).unwrap();
+        assert_eq!(app_server_to_app, app_server_to_app2);
+    }
+
+    #[test]
+    fn test_serialize_response_received_timing() {
+        let route = FriendsRoute {
+            public_keys: vec![
+                PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            ],
+        };
+        let opt_ticks_to_sents = vec![Some(2), None];
+        for opt_ticks_to_sent in opt_ticks_to_sents {
+            let response_received = ResponseReceived {
+                request_id: Uid::from(&[9; UID_LEN]),
+                result: ResponseSendFundsResult::Success(Receipt {
+                    response_hash: HashResult::from(&[0x11; HASH_RESULT_LEN]),
+                    invoice_id: InvoiceId::from(&[0x22; INVOICE_ID_LEN]),
+                    dest_payment: 10,
+                    signature: Signature::from(&[0x33; SIGNATURE_LEN]),
+                }),
+                opt_timing: Some(PaymentTiming {
+                    opt_ticks_to_sent,
+                    ticks_to_response: 7,
+                    route: route.clone(),
+                }),
+                opt_label: Some(b"order-7".to_vec()),
+            };
+            let app_server_to_app = AppServerToApp::ResponseReceived(response_received);
+            let data = serialize_app_server_to_app(&app_server_to_app);
+            let app_server_to_app2 = deserialize_app_server_to_app(&data).unwrap();
+            assert_eq!(app_server_to_app, app_server_to_app2);
+        }
+    }
+
+    #[test]
+    fn test_serialize_directory_requests() {
+        let subscription = DirectorySubscription {
+            public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            address: "directory.example.com:1337".to_owned().try_into().unwrap(),
+            signing_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+        };
+        let app_requests = vec![
+            AppRequest::SetDirectory(subscription),
+            AppRequest::ClearDirectory,
+            AppRequest::PinDirectoryEntry(PublicKey::from(&[0xcc; PUBLIC_KEY_LEN])),
+            AppRequest::UnpinDirectoryEntry(PublicKey::from(&[0xdd; PUBLIC_KEY_LEN])),
+        ];
+        for app_request in app_requests {
+            let app_to_app_server = AppToAppServer {
+                app_request_id: Uid::from(&[10; UID_LEN]),
+                app_request,
+            };
+            let data = serialize_app_to_app_server(&app_to_app_server);
+            let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+            assert_eq!(app_to_app_server, app_to_app_server2);
+        }
+    }
+
+    #[test]
+    fn test_serialize_announce_shutdown() {
+        for &opt_downtime_ticks in &[Some(0x100), None] {
+            let app_to_app_server = AppToAppServer {
+                app_request_id: Uid::from(&[11; UID_LEN]),
+                app_request: AppRequest::AnnounceShutdown(Goodbye { opt_downtime_ticks }),
+            };
+            let data = serialize_app_to_app_server(&app_to_app_server);
+            let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+            assert_eq!(app_to_app_server, app_to_app_server2);
+        }
+    }
+
+    #[test]
+    fn test_serialize_request_sweep_funds() {
+        for &opt_route_capacity in &[Some(100), None] {
+            let user_request_sweep_funds = UserRequestSweepFunds {
+                request_id: Uid::from(&[12; UID_LEN]),
+                route: FriendsRoute {
+                    public_keys: vec![
+                        PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                        PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                    ],
+                },
+                invoice_id: InvoiceId::from(&[0xcc; INVOICE_ID_LEN]),
+                opt_route_capacity,
+            };
+            let app_to_app_server = AppToAppServer {
+                app_request_id: Uid::from(&[13; UID_LEN]),
+                app_request: AppRequest::RequestSweepFunds(user_request_sweep_funds),
+            };
+            let data = serialize_app_to_app_server(&app_to_app_server);
+            let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+            assert_eq!(app_to_app_server, app_to_app_server2);
+        }
+    }
+
+    #[test]
+    fn test_serialize_labeled_payments() {
+        for opt_label in vec![Some(b"order-14".to_vec()), Some(Vec::new()), None] {
+            let user_request_send_funds = UserRequestSendFunds {
+                request_id: Uid::from(&[14; UID_LEN]),
+                route: FriendsRoute {
+                    public_keys: vec![
+                        PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                        PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                    ],
+                },
+                invoice_id: InvoiceId::from(&[0xcc; INVOICE_ID_LEN]),
+                dest_payment: 20,
+                opt_label,
+            };
+            let app_to_app_server = AppToAppServer {
+                app_request_id: Uid::from(&[15; UID_LEN]),
+                app_request: AppRequest::RequestSendFunds(user_request_send_funds),
+            };
+            let data = serialize_app_to_app_server(&app_to_app_server);
+            let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+            assert_eq!(app_to_app_server, app_to_app_server2);
+        }
+
+        let filters = vec![
+            LabelFilter::Exact(b"order-14".to_vec()),
+            LabelFilter::Prefix(b"order-".to_vec()),
+        ];
+        for filter in filters {
+            let request_labeled_payments = RequestLabeledPayments {
+                request_id: Uid::from(&[16; UID_LEN]),
+                filter,
+                offset: 3,
+                max_results: 10,
+            };
+            let app_to_app_server = AppToAppServer {
+                app_request_id: Uid::from(&[17; UID_LEN]),
+                app_request: AppRequest::RequestLabeledPayments(request_labeled_payments),
+            };
+            let data = serialize_app_to_app_server(&app_to_app_server);
+            let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+            assert_eq!(app_to_app_server, app_to_app_server2);
+        }
+
+        let labeled_payments = vec![
+            LabeledPayment {
+                request_id: Uid::from(&[14; UID_LEN]),
+                label: b"order-14".to_vec(),
+                opt_result: Some(ResponseSendFundsResult::Failure((
+                    PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                    FailureReason::InsufficientLocalCapacity(7),
+                ))),
+            },
+            LabeledPayment {
+                request_id: Uid::from(&[18; UID_LEN]),
+                label: b"order-18".to_vec(),
+                opt_result: None,
+            },
+        ];
+        let response_labeled_payments = ResponseLabeledPayments {
+            request_id: Uid::from(&[16; UID_LEN]),
+            labeled_payments,
+            more: true,
+        };
+        let app_server_to_app = AppServerToApp::ResponseLabeledPayments(response_labeled_payments);
+        let data = serialize_app_server_to_app(&app_server_to_app);
+        let app_server_to_app2 = deserialize_app_server_to_app(&data).unwrap();
+        assert_eq!(app_server_to_app, app_server_to_app2);
+    }
+
+    #[test]
+    fn test_serialize_pending_submissions() {
+        let app_requests = vec![
+            AppRequest::ListPendingSubmissions(Uid::from(&[19; UID_LEN])),
+            AppRequest::AckSubmission(Uid::from(&[14; UID_LEN])),
+        ];
+        for app_request in app_requests {
+            let app_to_app_server = AppToAppServer {
+                app_request_id: Uid::from(&[20; UID_LEN]),
+                app_request,
+            };
+            let data = serialize_app_to_app_server(&app_to_app_server);
+            let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+            assert_eq!(app_to_app_server, app_to_app_server2);
+        }
+
+        let opt_results = vec![
+            Some(ResponseSendFundsResult::Success(Receipt {
+                response_hash: HashResult::from(&[0x11; HASH_RESULT_LEN]),
+                invoice_id: InvoiceId::from(&[0x22; INVOICE_ID_LEN]),
+                dest_payment: 10,
+                signature: Signature::from(&[0x33; SIGNATURE_LEN]),
+            })),
+            Some(ResponseSendFundsResult::Failure((
+                PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                FailureReason::Unspecified,
+            ))),
+            None,
+        ];
+        let submissions = opt_results
+            .into_iter()
+            .enumerate()
+            .map(|(i, opt_result)| PaymentSubmission {
+                request_id: Uid::from(&[i as u8; UID_LEN]),
+                app_public_key: PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]),
+                route: FriendsRoute {
+                    public_keys: vec![
+                        PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                        PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                    ],
+                },
+                invoice_id: InvoiceId::from(&[0xcc; INVOICE_ID_LEN]),
+                dest_payment: 20,
+                opt_result,
+            })
+            .collect();
+        let app_server_to_app =
+            AppServerToApp::ResponsePendingSubmissions(ResponsePendingSubmissions {
+                request_id: Uid::from(&[19; UID_LEN]),
+                submissions,
+            });
+        let data = serialize_app_server_to_app(&app_server_to_app);
+        let app_server_to_app2 = deserialize_app_server_to_app(&data).unwrap();
+        assert_eq!(app_server_to_app, app_server_to_app2);
+    }
+
+    #[test]
+    fn test_serialize_pair_freeze_limits() {
+        let pairs = vec![
+            FriendPair {
+                opt_from: Some(PublicKey::from(&[0xaa; PUBLIC_KEY_LEN])),
+                opt_to: Some(PublicKey::from(&[0xbb; PUBLIC_KEY_LEN])),
+            },
+            FriendPair {
+                opt_from: None,
+                opt_to: Some(PublicKey::from(&[0xbb; PUBLIC_KEY_LEN])),
+            },
+            FriendPair {
+                opt_from: Some(PublicKey::from(&[0xaa; PUBLIC_KEY_LEN])),
+                opt_to: None,
+            },
+            FriendPair {
+                opt_from: None,
+                opt_to: None,
+            },
+        ];
+        for pair in pairs {
+            for &opt_limit in &[Some(100), None] {
+                let app_to_app_server = AppToAppServer {
+                    app_request_id: Uid::from(&[20; UID_LEN]),
+                    app_request: AppRequest::SetPairFreezeLimit(SetPairFreezeLimit {
+                        pair: pair.clone(),
+                        opt_limit,
+                    }),
+                };
+                let data = serialize_app_to_app_server(&app_to_app_server);
+                let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+                assert_eq!(app_to_app_server, app_to_app_server2);
+            }
+        }
+
+        let app_to_app_server = AppToAppServer {
+            app_request_id: Uid::from(&[21; UID_LEN]),
+            app_request: AppRequest::RequestPairFreezeUsage(RequestPairFreezeUsage {
+                request_id: Uid::from(&[22; UID_LEN]),
+                from_public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                to_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            }),
+        };
+        let data = serialize_app_to_app_server(&app_to_app_server);
+        let app_to_app_server2 = deserialize_app_to_app_server(&data).unwrap();
+        assert_eq!(app_to_app_server, app_to_app_server2);
+
+        let opt_limits = vec![
+            Some(PairFreezeLimit {
+                pair: FriendPair {
+                    opt_from: None,
+                    opt_to: Some(PublicKey::from(&[0xbb; PUBLIC_KEY_LEN])),
+                },
+                limit: 100,
+            }),
+            None,
+        ];
+        for opt_limit in opt_limits {
+            let app_server_to_app =
+                AppServerToApp::ResponsePairFreezeUsage(ResponsePairFreezeUsage {
+                    request_id: Uid::from(&[22; UID_LEN]),
+                    opt_limit,
+                    frozen_credits: 30,
+                });
+            let data = serialize_app_server_to_app(&app_server_to_app);
+            let app_server_to_app2 = deserialize_app_server_to_app(&data).unwrap();
+            assert_eq!(app_server_to_app, app_server_to_app2);
+        }
+    }
+
     // TODO: More tests are required here
 }
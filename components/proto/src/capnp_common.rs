@@ -3,14 +3,17 @@ use std::convert::{TryFrom, TryInto};
 use std::io;
 
 use common_capnp::{
-    buffer128, buffer256, buffer512, custom_int128, custom_u_int128, dh_public_key, hash,
-    invoice_id, named_index_server_address, named_relay_address, net_address, public_key,
+    buffer128, buffer256, buffer512, custom_int128, custom_u_int128, dh_public_key,
+    directory_listing, directory_state, directory_subscription, dust_thresholds, friend_invite,
+    hash, invoice_id, named_index_server_address, named_relay_address, net_address, public_key,
     rand_nonce, receipt, relay_address, salt, signature, uid,
 };
 
 use crate::app_server::messages::{NamedRelayAddress, RelayAddress};
-use crate::funder::messages::Receipt;
+use crate::directory::messages::{DirectoryListing, DirectoryState, DirectorySubscription};
+use crate::funder::messages::{DustThresholds, Receipt};
 use crate::index_server::messages::NamedIndexServerAddress;
+use crate::invite::messages::FriendInvite;
 use crate::net::messages::NetAddress;
 use crate::serialize::SerializeError;
 
@@ -21,6 +24,8 @@ use crypto::identity::{PublicKey, Signature};
 use crypto::invoice_id::InvoiceId;
 use crypto::uid::Uid;
 
+use common::int_convert::usize_to_u32;
+
 /// Read the underlying bytes from given `CustomUInt128` reader.
 fn read_buffer128(from: &buffer128::Reader) -> Vec<u8> {
     let mut vec = Vec::new();
@@ -284,3 +289,183 @@ pub fn write_receipt(from: &Receipt, to: &mut receipt::Builder) {
     write_custom_u_int128(from.dest_payment, &mut to.reborrow().init_dest_payment());
     write_signature(&from.signature, &mut to.reborrow().init_signature());
 }
+
+pub fn read_dust_thresholds(
+    from: &dust_thresholds::Reader,
+) -> Result<DustThresholds, SerializeError> {
+    Ok(DustThresholds {
+        min_send_payment: read_custom_u_int128(&from.get_min_send_payment()?)?,
+        min_forward_payment: read_custom_u_int128(&from.get_min_forward_payment()?)?,
+        min_receive_payment: read_custom_u_int128(&from.get_min_receive_payment()?)?,
+    })
+}
+
+pub fn write_dust_thresholds(from: &DustThresholds, to: &mut dust_thresholds::Builder) {
+    write_custom_u_int128(
+        from.min_send_payment,
+        &mut to.reborrow().init_min_send_payment(),
+    );
+    write_custom_u_int128(
+        from.min_forward_payment,
+        &mut to.reborrow().init_min_forward_payment(),
+    );
+    write_custom_u_int128(
+        from.min_receive_payment,
+        &mut to.reborrow().init_min_receive_payment(),
+    );
+}
+
+pub fn read_directory_subscription(
+    from: &directory_subscription::Reader,
+) -> Result<DirectorySubscription<NetAddress>, SerializeError> {
+    Ok(DirectorySubscription {
+        public_key: read_public_key(&from.get_public_key()?)?,
+        address: read_net_address(&from.get_address()?)?,
+        signing_public_key: read_public_key(&from.get_signing_public_key()?)?,
+    })
+}
+
+pub fn write_directory_subscription(
+    from: &DirectorySubscription<NetAddress>,
+    to: &mut directory_subscription::Builder,
+) {
+    write_public_key(&from.public_key, &mut to.reborrow().init_public_key());
+    write_net_address(&from.address, &mut to.reborrow().init_address());
+    write_public_key(
+        &from.signing_public_key,
+        &mut to.reborrow().init_signing_public_key(),
+    );
+}
+
+pub fn read_directory_listing(
+    from: &directory_listing::Reader,
+) -> Result<DirectoryListing, SerializeError> {
+    let mut relays = Vec::new();
+    for public_key in from.get_relays()? {
+        relays.push(read_public_key(&public_key)?);
+    }
+
+    let mut index_servers = Vec::new();
+    for public_key in from.get_index_servers()? {
+        index_servers.push(read_public_key(&public_key)?);
+    }
+
+    Ok(DirectoryListing {
+        version: from.get_version(),
+        relays,
+        index_servers,
+    })
+}
+
+pub fn write_directory_listing(from: &DirectoryListing, to: &mut directory_listing::Builder) {
+    to.set_version(from.version);
+
+    let relays_len = usize_to_u32(from.relays.len()).unwrap();
+    let mut relays_builder = to.reborrow().init_relays(relays_len);
+    for (index, public_key) in from.relays.iter().enumerate() {
+        let mut public_key_builder = relays_builder.reborrow().get(usize_to_u32(index).unwrap());
+        write_public_key(public_key, &mut public_key_builder);
+    }
+
+    let index_servers_len = usize_to_u32(from.index_servers.len()).unwrap();
+    let mut index_servers_builder = to.reborrow().init_index_servers(index_servers_len);
+    for (index, public_key) in from.index_servers.iter().enumerate() {
+        let mut public_key_builder = index_servers_builder
+            .reborrow()
+            .get(usize_to_u32(index).unwrap());
+        write_public_key(public_key, &mut public_key_builder);
+    }
+}
+
+pub fn read_directory_state(
+    from: &directory_state::Reader,
+) -> Result<DirectoryState<NetAddress>, SerializeError> {
+    let opt_subscription = match from.get_opt_subscription().which()? {
+        directory_state::opt_subscription::Subscription(subscription_reader) => {
+            Some(read_directory_subscription(&subscription_reader?)?)
+        }
+        directory_state::opt_subscription::Empty(()) => None,
+    };
+
+    let mut pinned = Vec::new();
+    for public_key in from.get_pinned()? {
+        pinned.push(read_public_key(&public_key)?);
+    }
+
+    Ok(DirectoryState {
+        opt_subscription,
+        listing: read_directory_listing(&from.get_listing()?)?,
+        pinned,
+    })
+}
+
+pub fn write_directory_state(from: &DirectoryState<NetAddress>, to: &mut directory_state::Builder) {
+    let mut opt_subscription_builder = to.reborrow().init_opt_subscription();
+    match &from.opt_subscription {
+        Some(subscription) => {
+            write_directory_subscription(
+                subscription,
+                &mut opt_subscription_builder.init_subscription(),
+            );
+        }
+        None => {
+            opt_subscription_builder.set_empty(());
+        }
+    }
+
+    write_directory_listing(&from.listing, &mut to.reborrow().init_listing());
+
+    let pinned_len = usize_to_u32(from.pinned.len()).unwrap();
+    let mut pinned_builder = to.reborrow().init_pinned(pinned_len);
+    for (index, public_key) in from.pinned.iter().enumerate() {
+        let mut public_key_builder = pinned_builder.reborrow().get(usize_to_u32(index).unwrap());
+        write_public_key(public_key, &mut public_key_builder);
+    }
+}
+
+pub fn read_friend_invite(
+    from: &friend_invite::Reader,
+) -> Result<FriendInvite<NetAddress>, SerializeError> {
+    let mut relays = Vec::new();
+    for relay_address in from.get_relays()? {
+        relays.push(read_relay_address(&relay_address)?);
+    }
+
+    let opt_suggested_max_debt = match from.get_opt_suggested_max_debt().which()? {
+        friend_invite::opt_suggested_max_debt::SuggestedMaxDebt(max_debt_reader) => {
+            Some(read_custom_u_int128(&max_debt_reader?)?)
+        }
+        friend_invite::opt_suggested_max_debt::Empty(()) => None,
+    };
+
+    Ok(FriendInvite {
+        public_key: read_public_key(&from.get_public_key()?)?,
+        relays,
+        opt_suggested_max_debt,
+        expiry_time: from.get_expiry_time(),
+        signature: read_signature(&from.get_signature()?)?,
+    })
+}
+
+pub fn write_friend_invite(from: &FriendInvite<NetAddress>, to: &mut friend_invite::Builder) {
+    write_public_key(&from.public_key, &mut to.reborrow().init_public_key());
+
+    let relays_len = usize_to_u32(from.relays.len()).unwrap();
+    let mut relays_builder = to.reborrow().init_relays(relays_len);
+    for (index, relay_address) in from.relays.iter().enumerate() {
+        let mut relay_address_builder = relays_builder.reborrow().get(usize_to_u32(index).unwrap());
+        write_relay_address(relay_address, &mut relay_address_builder);
+    }
+
+    let mut opt_suggested_max_debt_builder = to.reborrow().init_opt_suggested_max_debt();
+    match from.opt_suggested_max_debt {
+        Some(suggested_max_debt) => write_custom_u_int128(
+            suggested_max_debt,
+            &mut opt_suggested_max_debt_builder.init_suggested_max_debt(),
+        ),
+        None => opt_suggested_max_debt_builder.set_empty(()),
+    }
+
+    to.set_expiry_time(from.expiry_time);
+    write_signature(&from.signature, &mut to.reborrow().init_signature());
+}
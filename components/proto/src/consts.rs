@@ -4,6 +4,11 @@ pub const PROTOCOL_VERSION: u32 = 0;
 /// Maximum amount of friend operations sent in one move token message.
 pub const MAX_OPERATIONS_IN_BATCH: usize = 16;
 
+/// Minimum amount of friend operations sent in one move token message.
+/// The amount of operations adapts to the token round trip time of every friend,
+/// between MIN_OPERATIONS_IN_BATCH and MAX_OPERATIONS_IN_BATCH.
+pub const MIN_OPERATIONS_IN_BATCH: usize = 4;
+
 /// Maximum length of route used to pass credit.
 pub const MAX_ROUTE_LEN: usize = 32;
 
@@ -13,6 +18,14 @@ pub const TICK_MS: usize = 1000;
 /// Amount of ticks to wait before rekeying a secure channel.
 pub const TICKS_TO_REKEY: usize = 60 * 60 * (1000 / TICK_MS); // 1 hour
 
+/// Amount of messages sent over a secure channel before rekeying, even if `TICKS_TO_REKEY` ticks
+/// did not pass yet.
+pub const MAX_MESSAGES_BEFORE_REKEY: u64 = 0x10_0000;
+
+/// Amount of ticks a closed secure channel with a friend may be resumed, without a full
+/// exchange.
+pub const SESSION_RESUME_TICKS: usize = 60 * (1000 / TICK_MS); // 1 minute
+
 /// If no message was sent for this amount of ticks, the connection will be closed
 pub const KEEPALIVE_TICKS: usize = 0x20;
 
@@ -20,14 +33,55 @@ pub const KEEPALIVE_TICKS: usize = 0x20;
 /// sends identification of which type of connection it is.
 pub const CONN_TIMEOUT_TICKS: usize = 4;
 
+/// Maximum amount of channels open at the same time over a single multiplexed relay connection.
+/// Further channels are refused.
+pub const MAX_MUX_CHANNELS: usize = 0x100;
+
+/// Amount of incoming messages buffered for a single channel of a multiplexed relay connection.
+/// A channel whose user does not keep up is closed, instead of blocking the other channels.
+pub const MUX_CHANNEL_QUEUE_LEN: usize = 0x20;
+
+/// When connecting to a name that resolves to multiple addresses, the amount of ticks to wait for
+/// a connection attempt before racing it with an attempt to the next address.
+pub const CONNECT_STAGGER_TICKS: usize = 1;
+
 /// The stream TCP connection is split into prefix length frames. This is the maximum allowed
 /// length for such frame, measured in bytes.
 pub const MAX_FRAME_LENGTH: usize = 1 << 20; // 1[MB]
 
+/// App protocol: Serialized messages larger than this amount of bytes are split into chunks.
+/// Smaller than MAX_FRAME_LENGTH, to leave room for the overhead of the layers below.
+pub const APP_CHUNK_THRESHOLD: usize = MAX_FRAME_LENGTH / 2;
+
+/// App protocol: Maximum amount of message bytes carried by a single chunk.
+pub const APP_CHUNK_SIZE: usize = MAX_FRAME_LENGTH / 4;
+
+/// App protocol: Maximum amount of chunks in a single transfer.
+/// A receiver aborts transfers with more chunks.
+pub const MAX_APP_TRANSFER_CHUNKS: u32 = 0x400;
+
 /// Index server: The amount of ticks it takes for an idle node to be removed from the
 /// index server database.
 pub const INDEX_NODE_TIMEOUT_TICKS: usize = 60 * (1000 / TICK_MS); // 1 minute
 
+/// Maximum amount of unacknowledged incoming payment notifications kept by a node.
+/// When a new payment arrives and there is no room for it, the oldest unacknowledged
+/// notification is dropped. A consumer that stays away for long might therefore miss
+/// notifications, but a consumer can never make the node's state grow without bound.
+pub const MAX_INCOMING_PAYMENTS: usize = 0x400;
+
+/// Maximum length (in bytes) of the local label an app may attach to a payment it sends.
+pub const MAX_PAYMENT_LABEL_LEN: usize = 0x40;
+
+/// Maximum amount of labeled payments remembered by a node. When a new labeled payment is sent
+/// and there is no room for it, the oldest labeled payment is forgotten.
+pub const MAX_LABELED_PAYMENTS: usize = 0x400;
+
+/// Maximum amount of payment submissions (Whose outcome was not yet acknowledged by the
+/// submitting app) remembered by a node. When a new payment is submitted and there is no room for
+/// it, the oldest submission is forgotten.
+pub const MAX_PAYMENT_SUBMISSIONS: usize = 0x400;
+
 /// Maximum length for an address string used in NetAddress
 pub const MAX_NET_ADDRESS_LENGTH: usize = 256;
 
@@ -35,3 +89,93 @@ pub const MAX_NET_ADDRESS_LENGTH: usize = 256;
 /// We limit this number because sending many relays in a single move token message
 /// might exceed frame length
 pub const MAX_NODE_RELAYS: usize = 16;
+
+/// Minimum amount of ticks between two applied changes of a friend's relays.
+/// Changes that arrive faster are kept pending until this amount of ticks has passed.
+pub const FRIEND_RELAYS_DAMPING_TICKS: usize = 0x10;
+
+/// Minimum amount of ticks between two pre-warms of the same friend that require requesting the
+/// token from the friend.
+pub const FRIEND_PREWARM_TICKS: usize = 0x8;
+
+/// Amount of local ticks over which the tick rate of a remote side is measured (Using the tick
+/// counters carried in keepalive messages).
+pub const TICK_DRIFT_WINDOW_TICKS: usize = 0x80;
+
+/// Allowed band for the ratio between remote tick progress and local tick progress, measured in
+/// permille. A ratio outside this band probably means that one of the sides has a broken timer.
+pub const TICK_DRIFT_MIN_PERMILLE: u64 = 750;
+pub const TICK_DRIFT_MAX_PERMILLE: u64 = 1333;
+
+/// Amount of ticks between two periodic compactions (and integrity verifications) of the node's
+/// database.
+pub const DATABASE_COMPACT_TICKS: usize = 60 * 60 * (1000 / TICK_MS); // 1 hour
+
+/// Maximum amount of ticks a single stage of a node self test may take.
+pub const SELF_TEST_STAGE_TICKS: usize = 60 * (1000 / TICK_MS); // 1 minute
+
+/// Amount of ticks after which recorded payment outcomes of remote nodes lose half of their
+/// weight. This allows a node that was unreliable for a while to recover its score.
+pub const RELIABILITY_DECAY_TICKS: usize = 6 * 60 * 60 * (1000 / TICK_MS); // 6 hours
+
+/// Reported deadlines of friends are only updated when they change by at least this amount of
+/// ticks. This avoids a report mutation every tick.
+pub const DEADLINES_GRANULARITY_TICKS: usize = 1000 / TICK_MS; // 1 second
+
+/// Success probability (In parts per million) assumed for a node without recorded payment
+/// outcomes. Scores of nodes with few recorded outcomes stay close to this value.
+pub const NEUTRAL_SUCCESS_PPM: u32 = 1_000_000;
+
+/// Maximum amount of recently completed requests remembered for every friend.
+/// Those are used to ignore responses retransmitted by a friend after a channel reset.
+pub const COMPLETED_CACHE_MAX_ENTRIES: usize = 0x400;
+
+/// Amount of ticks a completed request is remembered.
+pub const COMPLETED_CACHE_MAX_AGE_TICKS: usize = 24 * 60 * 60 * (1000 / TICK_MS); // 24 hours
+
+/// Maximum amount of pending payments whose latency is measured at the same time.
+pub const PAYMENT_TIMINGS_CACHE_MAX_ENTRIES: usize = 0x400;
+
+/// Payments that take longer than this amount of ticks are not measured.
+pub const PAYMENT_TIMINGS_CACHE_MAX_AGE_TICKS: usize = 60 * 60 * (1000 / TICK_MS); // 1 hour
+
+/// Amount of recent successful payments whose latencies are summarized in the metrics.
+pub const PAYMENT_LATENCY_SAMPLES: usize = 0x100;
+
+/// Incoming events that take the funder longer than this amount of milliseconds to handle are
+/// logged, together with the time spent in every phase of the handling.
+pub const SLOW_FUNDER_EVENT_MS: usize = 500;
+
+/// At most one slow funder event is logged during this amount of milliseconds. Further slow events
+/// are only counted.
+pub const SLOW_FUNDER_EVENT_LOG_INTERVAL_MS: usize = 60 * 1000; // 1 minute
+
+/// Maximum amount of open requests (payments, routes and pre-warms) of a single app identity,
+/// counted across all of its sessions. Further requests fail immediately.
+pub const MAX_OPEN_APP_REQUESTS: usize = 0x100;
+
+/// Maximum amount of disconnected app sessions that are kept, waiting to be resumed.
+/// When exceeded, the session that was disconnected first is dropped.
+pub const MAX_DETACHED_APP_SESSIONS: usize = 0x40;
+
+/// Amount of recent app session events (connections, resumptions, revocations) kept for
+/// diagnostics.
+pub const MAX_APP_SESSION_EVENTS: usize = 0x80;
+
+/// Amount of ticks between two reloads of the trusted apps. Connected apps that are no longer
+/// trusted are disconnected.
+pub const TRUSTED_APPS_RELOAD_TICKS: usize = 60 * (1000 / TICK_MS); // 1 minute
+
+/// Amount of ticks between two comparisons of the capacities sent to the index server against
+/// the current capacities of our friends. Outdated capacities are sent again.
+pub const INDEX_RECONCILE_TICKS: usize = 60 * (1000 / TICK_MS); // 1 minute
+
+/// Maximum amount of recent route responses of index servers remembered by the index client.
+pub const INDEX_ROUTE_CACHE_MAX_ENTRIES: usize = 0x100;
+
+/// Amount of ticks a route response of an index server is remembered.
+pub const INDEX_ROUTE_CACHE_MAX_AGE_TICKS: usize = 5 * (1000 / TICK_MS); // 5 seconds
+
+/// Maximum amount of partial routes (Routes with less than the requested capacity) an index
+/// server returns for a single route request.
+pub const MAX_PARTIAL_ROUTES: usize = 4;
@@ -0,0 +1,56 @@
+use crypto::identity::{PublicKey, Signature};
+
+use crate::app_server::messages::NamedRelayAddress;
+use crate::index_server::messages::NamedIndexServerAddress;
+use crate::net::messages::NetAddress;
+
+/// A signed list of recommended relays and index servers, published by a directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryDocument<B = NetAddress> {
+    /// Increases with every published document.
+    /// A node never applies a document older than the last document it applied.
+    pub version: u64,
+    pub relays: Vec<NamedRelayAddress<B>>,
+    pub index_servers: Vec<NamedIndexServerAddress<B>>,
+    /// Signature over the document, using the signing key of the directory.
+    pub signature: Signature,
+}
+
+/// A directory a node is subscribed to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectorySubscription<B = NetAddress> {
+    /// Public key of the directory server. Used to set up an encrypted connection.
+    pub public_key: PublicKey,
+    /// Address of the directory server.
+    pub address: B,
+    /// Public key used to verify the signature of documents published by the directory.
+    pub signing_public_key: PublicKey,
+}
+
+/// Relays and index servers a node added because the directory listed them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryListing {
+    /// Version of the last applied document.
+    pub version: u64,
+    pub relays: Vec<PublicKey>,
+    pub index_servers: Vec<PublicKey>,
+}
+
+/// The state of a node's directory subscription.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryState<B = NetAddress> {
+    pub opt_subscription: Option<DirectorySubscription<B>>,
+    pub listing: DirectoryListing,
+    /// Relays and index servers (By public key) the directory never removes.
+    pub pinned: Vec<PublicKey>,
+}
+
+impl<B> Default for DirectoryState<B> {
+    fn default() -> Self {
+        DirectoryState {
+            opt_subscription: None,
+            listing: DirectoryListing::default(),
+            pinned: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub mod messages;
+pub mod serialize;
+pub mod signature_buff;
@@ -0,0 +1,109 @@
+use std::io;
+
+use capnp;
+use capnp::serialize_packed;
+use common::int_convert::usize_to_u32;
+use directory_capnp;
+
+use crate::capnp_common::{
+    read_named_index_server_address, read_named_relay_address, read_signature,
+    write_named_index_server_address, write_named_relay_address, write_signature,
+};
+use crate::net::messages::NetAddress;
+use crate::serialize::SerializeError;
+
+use super::messages::DirectoryDocument;
+
+pub fn serialize_directory_document(document: &DirectoryDocument<NetAddress>) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut msg = builder.init_root::<directory_capnp::directory_document::Builder>();
+
+    msg.set_version(document.version);
+
+    let relays_len = usize_to_u32(document.relays.len()).unwrap();
+    let mut relays_builder = msg.reborrow().init_relays(relays_len);
+    for (index, named_relay_address) in document.relays.iter().enumerate() {
+        let mut named_relay_address_builder =
+            relays_builder.reborrow().get(usize_to_u32(index).unwrap());
+        write_named_relay_address(named_relay_address, &mut named_relay_address_builder);
+    }
+
+    let index_servers_len = usize_to_u32(document.index_servers.len()).unwrap();
+    let mut index_servers_builder = msg.reborrow().init_index_servers(index_servers_len);
+    for (index, named_index_server_address) in document.index_servers.iter().enumerate() {
+        let mut named_index_server_address_builder = index_servers_builder
+            .reborrow()
+            .get(usize_to_u32(index).unwrap());
+        write_named_index_server_address(
+            named_index_server_address,
+            &mut named_index_server_address_builder,
+        );
+    }
+
+    write_signature(&document.signature, &mut msg.reborrow().init_signature());
+
+    let mut serialized_msg = Vec::new();
+    serialize_packed::write_message(&mut serialized_msg, &builder).unwrap();
+    serialized_msg
+}
+
+pub fn deserialize_directory_document(
+    data: &[u8],
+) -> Result<DirectoryDocument<NetAddress>, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let msg = reader.get_root::<directory_capnp::directory_document::Reader>()?;
+
+    let mut relays = Vec::new();
+    for named_relay_address in msg.get_relays()? {
+        relays.push(read_named_relay_address(&named_relay_address)?);
+    }
+
+    let mut index_servers = Vec::new();
+    for named_index_server_address in msg.get_index_servers()? {
+        index_servers.push(read_named_index_server_address(
+            &named_index_server_address,
+        )?);
+    }
+
+    Ok(DirectoryDocument {
+        version: msg.get_version(),
+        relays,
+        index_servers,
+        signature: read_signature(&msg.get_signature()?)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryInto;
+
+    use crypto::identity::{PublicKey, Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+
+    use crate::app_server::messages::NamedRelayAddress;
+    use crate::index_server::messages::NamedIndexServerAddress;
+
+    #[test]
+    fn test_serialize_directory_document() {
+        let document = DirectoryDocument {
+            version: 7,
+            relays: vec![NamedRelayAddress {
+                public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                address: "relay.example.com:1337".to_owned().try_into().unwrap(),
+                name: "relay".to_owned(),
+            }],
+            index_servers: vec![NamedIndexServerAddress {
+                public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                address: "index.example.com:1338".to_owned().try_into().unwrap(),
+                name: "index".to_owned(),
+            }],
+            signature: Signature::from(&[0xcc; SIGNATURE_LEN]),
+        };
+        let data = serialize_directory_document(&document);
+        let document2 = deserialize_directory_document(&data).unwrap();
+        assert_eq!(document, document2);
+    }
+}
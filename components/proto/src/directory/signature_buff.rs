@@ -0,0 +1,112 @@
+use byteorder::{BigEndian, WriteBytesExt};
+
+use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::usize_to_u64;
+use crypto::hash;
+use crypto::identity::{verify_signature, PublicKey};
+
+use super::messages::DirectoryDocument;
+
+pub const DIRECTORY_DOCUMENT_PREFIX: &[u8] = b"DIRECTORY_DOCUMENT";
+
+/// Append `data` to `sbuffer`, prefixed by its length.
+/// Variable length fields are prefixed so that different documents never produce the same buffer.
+fn extend_with_len(sbuffer: &mut Vec<u8>, data: &[u8]) {
+    sbuffer
+        .write_u64::<BigEndian>(usize_to_u64(data.len()).unwrap())
+        .unwrap();
+    sbuffer.extend_from_slice(data);
+}
+
+/// Create the buffer the directory signs over when publishing a document.
+pub fn create_directory_signature_buffer<B>(document: &DirectoryDocument<B>) -> Vec<u8>
+where
+    B: CanonicalSerialize,
+{
+    let mut sbuffer = Vec::new();
+    sbuffer.extend_from_slice(&hash::sha_512_256(DIRECTORY_DOCUMENT_PREFIX));
+    sbuffer.write_u64::<BigEndian>(document.version).unwrap();
+
+    sbuffer
+        .write_u64::<BigEndian>(usize_to_u64(document.relays.len()).unwrap())
+        .unwrap();
+    for named_relay_address in &document.relays {
+        sbuffer.extend_from_slice(&named_relay_address.public_key);
+        extend_with_len(
+            &mut sbuffer,
+            &named_relay_address.address.canonical_serialize(),
+        );
+        extend_with_len(&mut sbuffer, named_relay_address.name.as_bytes());
+    }
+
+    sbuffer
+        .write_u64::<BigEndian>(usize_to_u64(document.index_servers.len()).unwrap())
+        .unwrap();
+    for named_index_server_address in &document.index_servers {
+        sbuffer.extend_from_slice(&named_index_server_address.public_key);
+        extend_with_len(
+            &mut sbuffer,
+            &named_index_server_address.address.canonical_serialize(),
+        );
+        extend_with_len(&mut sbuffer, named_index_server_address.name.as_bytes());
+    }
+
+    sbuffer
+}
+
+/// Verify that a document was signed by the directory with the given signing public key.
+pub fn verify_directory_document<B>(
+    document: &DirectoryDocument<B>,
+    signing_public_key: &PublicKey,
+) -> bool
+where
+    B: CanonicalSerialize,
+{
+    let signature_buffer = create_directory_signature_buffer(document);
+    verify_signature(&signature_buffer, signing_public_key, &document.signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryInto;
+
+    use crypto::identity::{
+        generate_pkcs8_key_pair, Identity, PublicKey, Signature, SoftwareEd25519Identity,
+        PUBLIC_KEY_LEN, SIGNATURE_LEN,
+    };
+    use crypto::test_utils::DummyRandom;
+
+    use crate::app_server::messages::NamedRelayAddress;
+    use crate::net::messages::NetAddress;
+
+    #[test]
+    fn test_verify_directory_document() {
+        let rng = DummyRandom::new(&[1u8]);
+        let pkcs8 = generate_pkcs8_key_pair(&rng);
+        let identity = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
+
+        let mut document: DirectoryDocument<NetAddress> = DirectoryDocument {
+            version: 3,
+            relays: vec![NamedRelayAddress {
+                public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                address: "relay.example.com:1337".to_owned().try_into().unwrap(),
+                name: "relay".to_owned(),
+            }],
+            index_servers: Vec::new(),
+            signature: Signature::from(&[0; SIGNATURE_LEN]),
+        };
+        document.signature = identity.sign(&create_directory_signature_buffer(&document));
+        let signing_public_key = identity.get_public_key();
+        assert!(verify_directory_document(&document, &signing_public_key));
+
+        // Signed by someone else:
+        let other_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        assert!(!verify_directory_document(&document, &other_public_key));
+
+        // A modified document:
+        document.version = 2;
+        assert!(!verify_directory_document(&document, &signing_public_key));
+    }
+}
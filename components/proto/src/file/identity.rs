@@ -1,10 +1,16 @@
+use std::convert::TryFrom;
 use std::fs::{self, File};
 use std::io::{self, Write};
 use std::path::Path;
 
+use base64::{self, URL_SAFE_NO_PAD};
 use toml;
+use zeroize::Zeroize;
 
+use crypto::crypto_rand::CryptoRandom;
 use crypto::identity::{Identity, SoftwareEd25519Identity};
+use crypto::passphrase::{derive_passphrase_key, PassphraseSalt};
+use crypto::sym_encrypt::{Decryptor, Encryptor};
 
 use crate::file::ser_string::{private_key_to_string, string_to_private_key, SerStringError};
 use crate::net::messages::NetAddressError;
@@ -19,14 +25,55 @@ pub enum IdentityFileError {
     InvalidPublicKey,
     NetAddressError(NetAddressError),
     Pkcs8ParseError,
+    /// The file is encrypted, and no passphrase was given
+    PassphraseRequired,
+    /// The file is not encrypted
+    NotEncrypted,
+    UnsupportedVersion(u32),
+    /// The passphrase is wrong, or the file was modified
+    WrongPassphrase,
+    InvalidEncryptedKey,
+    CryptoError,
 }
 
+/// Version of the encrypted identity file format
+pub const ENCRYPTED_IDENTITY_VERSION: u32 = 1;
+
+/// Default amount of iterations used to derive the encryption key of an identity file from a
+/// passphrase.
+pub const IDENTITY_KDF_ITERATIONS: u32 = 200_000;
+
 /// A helper structure for serialize and deserializing IdentityAddress.
 #[derive(Serialize, Deserialize)]
 pub struct IdentityFile {
     pub private_key: String,
 }
 
+/// An identity file where the private key is encrypted using a key derived from a passphrase.
+#[derive(Serialize, Deserialize)]
+pub struct EncryptedIdentityFile {
+    /// Version of the file format
+    pub version: u32,
+    /// Amount of PBKDF2-HMAC-SHA512 iterations used to derive the key from the passphrase
+    pub kdf_iterations: u32,
+    pub kdf_salt: String,
+    /// The private key (PKCS#8), encrypted and authenticated using ChaCha20-Poly1305
+    pub encrypted_private_key: String,
+}
+
+/// An identity file of any kind
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyIdentityFile {
+    Encrypted(EncryptedIdentityFile),
+    Plain(IdentityFile),
+}
+
+fn load_any_identity_file(path: &Path) -> Result<AnyIdentityFile, IdentityFileError> {
+    let data = fs::read_to_string(&path)?;
+    Ok(toml::from_str(&data)?)
+}
+
 impl From<SerStringError> for IdentityFileError {
     fn from(_e: SerStringError) -> Self {
         IdentityFileError::SerStringError
@@ -35,14 +82,72 @@ impl From<SerStringError> for IdentityFileError {
 
 /// Load Identity from a file
 pub fn load_raw_identity_from_file(path: &Path) -> Result<[u8; 85], IdentityFileError> {
-    let data = fs::read_to_string(&path)?;
-    let identity_file: IdentityFile = toml::from_str(&data)?;
+    let identity_file = match load_any_identity_file(path)? {
+        AnyIdentityFile::Plain(identity_file) => identity_file,
+        AnyIdentityFile::Encrypted(_) => return Err(IdentityFileError::PassphraseRequired),
+    };
 
     // Decode public key:
     let private_key = string_to_private_key(&identity_file.private_key)?;
     Ok(private_key)
 }
 
+/// Does the identity file require a passphrase?
+pub fn is_encrypted_identity_file(path: &Path) -> Result<bool, IdentityFileError> {
+    Ok(match load_any_identity_file(path)? {
+        AnyIdentityFile::Plain(_) => false,
+        AnyIdentityFile::Encrypted(_) => true,
+    })
+}
+
+/// Load Identity from an encrypted file
+pub fn decrypt_raw_identity_from_file(
+    path: &Path,
+    passphrase: &str,
+) -> Result<[u8; 85], IdentityFileError> {
+    let encrypted_identity_file = match load_any_identity_file(path)? {
+        AnyIdentityFile::Plain(_) => return Err(IdentityFileError::NotEncrypted),
+        AnyIdentityFile::Encrypted(encrypted_identity_file) => encrypted_identity_file,
+    };
+    if encrypted_identity_file.version != ENCRYPTED_IDENTITY_VERSION {
+        return Err(IdentityFileError::UnsupportedVersion(
+            encrypted_identity_file.version,
+        ));
+    }
+
+    let salt_vec = base64::decode_config(&encrypted_identity_file.kdf_salt, URL_SAFE_NO_PAD)
+        .map_err(|_| IdentityFileError::SerStringError)?;
+    let salt =
+        PassphraseSalt::try_from(&salt_vec[..]).map_err(|_| IdentityFileError::SerStringError)?;
+    let encrypted_private_key = base64::decode_config(
+        &encrypted_identity_file.encrypted_private_key,
+        URL_SAFE_NO_PAD,
+    )
+    .map_err(|_| IdentityFileError::SerStringError)?;
+
+    let mut symmetric_key = derive_passphrase_key(
+        passphrase.as_bytes(),
+        &salt,
+        encrypted_identity_file.kdf_iterations,
+    )
+    .map_err(|_| IdentityFileError::CryptoError)?;
+    let res_decrypt = Decryptor::new(&symmetric_key)
+        .map_err(|_| IdentityFileError::CryptoError)
+        .map(|mut decryptor| decryptor.decrypt(&encrypted_private_key));
+    symmetric_key.zeroize();
+    // Decryption fails if the authentication tag does not match:
+    let mut private_key_vec = res_decrypt?.map_err(|_| IdentityFileError::WrongPassphrase)?;
+
+    if private_key_vec.len() != 85 {
+        private_key_vec.zeroize();
+        return Err(IdentityFileError::InvalidEncryptedKey);
+    }
+    let mut private_key = [0u8; 85];
+    private_key.copy_from_slice(&private_key_vec);
+    private_key_vec.zeroize();
+    Ok(private_key)
+}
+
 /// Store Identity to file
 pub fn store_raw_identity_to_file(
     identity: &[u8; 85],
@@ -60,12 +165,95 @@ pub fn store_raw_identity_to_file(
     Ok(())
 }
 
+/// Store Identity to a file, encrypted using a key derived from `passphrase`.
+pub fn store_raw_identity_to_encrypted_file<R: CryptoRandom>(
+    identity: &[u8; 85],
+    passphrase: &str,
+    kdf_iterations: u32,
+    rng: &R,
+    path: &Path,
+) -> Result<(), IdentityFileError> {
+    let salt = PassphraseSalt::new(rng).map_err(|_| IdentityFileError::CryptoError)?;
+    let mut symmetric_key = derive_passphrase_key(passphrase.as_bytes(), &salt, kdf_iterations)
+        .map_err(|_| IdentityFileError::CryptoError)?;
+    // The key is derived using a new random salt, so the nonce of the Encryptor is never
+    // reused with the same key:
+    let res_encrypt = Encryptor::new(&symmetric_key)
+        .and_then(|mut encryptor| encryptor.encrypt(&identity[0..85]))
+        .map_err(|_| IdentityFileError::CryptoError);
+    symmetric_key.zeroize();
+
+    let encrypted_identity_file = EncryptedIdentityFile {
+        version: ENCRYPTED_IDENTITY_VERSION,
+        kdf_iterations,
+        kdf_salt: base64::encode_config(&salt, URL_SAFE_NO_PAD),
+        encrypted_private_key: base64::encode_config(&res_encrypt?, URL_SAFE_NO_PAD),
+    };
+
+    let data = toml::to_string(&encrypted_identity_file)?;
+
+    let mut file = File::create(path)?;
+    file.write_all(&data.as_bytes())?;
+
+    Ok(())
+}
+
+/// Encrypt an existing (plain) identity file. The identity is not changed.
+pub fn encrypt_identity_file<R: CryptoRandom>(
+    input: &Path,
+    output: &Path,
+    passphrase: &str,
+    kdf_iterations: u32,
+    rng: &R,
+) -> Result<(), IdentityFileError> {
+    let mut raw_identity = load_raw_identity_from_file(input)?;
+    let res = store_raw_identity_to_encrypted_file(
+        &raw_identity,
+        passphrase,
+        kdf_iterations,
+        rng,
+        output,
+    );
+    raw_identity[..].zeroize();
+    res
+}
+
+/// Create an identity from a private key (PKCS#8), and erase the private key.
+fn identity_from_raw(
+    raw_identity: &mut [u8; 85],
+) -> Result<SoftwareEd25519Identity, IdentityFileError> {
+    let res = SoftwareEd25519Identity::from_pkcs8(&raw_identity[..])
+        .map_err(|_| IdentityFileError::Pkcs8ParseError);
+    raw_identity[..].zeroize();
+    res
+}
+
 /// Load an identity from a file
 /// The file stores the private key according to PKCS#8.
 pub fn load_identity_from_file(path: &Path) -> Result<impl Identity, IdentityFileError> {
-    let raw_identity = load_raw_identity_from_file(path)?;
-    SoftwareEd25519Identity::from_pkcs8(&raw_identity)
-        .map_err(|_| IdentityFileError::Pkcs8ParseError)
+    let mut raw_identity = load_raw_identity_from_file(path)?;
+    identity_from_raw(&mut raw_identity)
+}
+
+/// Load an identity from a file that may be encrypted.
+/// `get_passphrase` is called only if the file is encrypted. It may return `None` if no
+/// passphrase is available.
+pub fn load_identity_from_file_with_passphrase<F>(
+    path: &Path,
+    get_passphrase: F,
+) -> Result<impl Identity, IdentityFileError>
+where
+    F: FnOnce() -> Option<String>,
+{
+    let mut raw_identity = if is_encrypted_identity_file(path)? {
+        let mut passphrase = get_passphrase().ok_or(IdentityFileError::PassphraseRequired)?;
+        let res_raw_identity = decrypt_raw_identity_from_file(path, &passphrase);
+        passphrase.zeroize();
+        res_raw_identity?
+    } else {
+        load_raw_identity_from_file(path)?
+    };
+    identity_from_raw(&mut raw_identity)
 }
 
 #[cfg(test)]
@@ -73,6 +261,12 @@ mod tests {
     use super::*;
     use tempfile::tempdir;
 
+    use crypto::identity::generate_pkcs8_key_pair;
+    use crypto::test_utils::DummyRandom;
+
+    /// A low amount of iterations, to keep the tests fast
+    const TEST_KDF_ITERATIONS: u32 = 16;
+
     #[test]
     fn test_identity_file_basic() {
         let identity_file: IdentityFile = toml::from_str(
@@ -99,4 +293,122 @@ mod tests {
         // We convert to vec here because [u8; 85] doesn't implement PartialEq
         assert_eq!(identity.to_vec(), identity2.to_vec());
     }
+
+    #[test]
+    fn test_store_load_encrypted_identity() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("identity_file");
+
+        let rng = DummyRandom::new(&[1u8]);
+        let pkcs8 = generate_pkcs8_key_pair(&rng);
+        let public_key = SoftwareEd25519Identity::from_pkcs8(&pkcs8[..])
+            .unwrap()
+            .get_public_key();
+
+        store_raw_identity_to_encrypted_file(
+            &pkcs8,
+            "passphrase",
+            TEST_KDF_ITERATIONS,
+            &rng,
+            &file_path,
+        )
+        .unwrap();
+        assert!(is_encrypted_identity_file(&file_path).unwrap());
+
+        // The private key is not stored in plain:
+        let data = fs::read_to_string(&file_path).unwrap();
+        assert!(!data.contains(&private_key_to_string(&pkcs8)));
+
+        let pkcs8_2 = decrypt_raw_identity_from_file(&file_path, "passphrase").unwrap();
+        assert_eq!(pkcs8.to_vec(), pkcs8_2.to_vec());
+
+        let identity =
+            load_identity_from_file_with_passphrase(&file_path, || Some("passphrase".to_owned()))
+                .unwrap();
+        assert_eq!(identity.get_public_key(), public_key);
+
+        // A passphrase is required:
+        match load_raw_identity_from_file(&file_path) {
+            Err(IdentityFileError::PassphraseRequired) => {}
+            _ => unreachable!(),
+        };
+        match load_identity_from_file_with_passphrase(&file_path, || None) {
+            Err(IdentityFileError::PassphraseRequired) => {}
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_load_encrypted_identity_wrong_passphrase() {
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("identity_file");
+
+        let rng = DummyRandom::new(&[2u8]);
+        let pkcs8 = generate_pkcs8_key_pair(&rng);
+        store_raw_identity_to_encrypted_file(
+            &pkcs8,
+            "passphrase",
+            TEST_KDF_ITERATIONS,
+            &rng,
+            &file_path,
+        )
+        .unwrap();
+
+        match decrypt_raw_identity_from_file(&file_path, "passphrasf") {
+            Err(IdentityFileError::WrongPassphrase) => {}
+            _ => unreachable!(),
+        };
+        match load_identity_from_file_with_passphrase(&file_path, || Some(String::new())) {
+            Err(IdentityFileError::WrongPassphrase) => {}
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_encrypt_identity_file() {
+        let dir = tempdir().unwrap();
+        let plain_path = dir.path().join("plain_identity_file");
+        let encrypted_path = dir.path().join("encrypted_identity_file");
+
+        let rng = DummyRandom::new(&[3u8]);
+        let pkcs8 = generate_pkcs8_key_pair(&rng);
+        store_raw_identity_to_file(&pkcs8, &plain_path).unwrap();
+        assert!(!is_encrypted_identity_file(&plain_path).unwrap());
+
+        encrypt_identity_file(
+            &plain_path,
+            &encrypted_path,
+            "passphrase",
+            TEST_KDF_ITERATIONS,
+            &rng,
+        )
+        .unwrap();
+
+        // A passphrase is not needed for a plain file:
+        let plain_identity = load_identity_from_file_with_passphrase(&plain_path, || {
+            unreachable!();
+        })
+        .unwrap();
+        let encrypted_identity = load_identity_from_file_with_passphrase(&encrypted_path, || {
+            Some("passphrase".to_owned())
+        })
+        .unwrap();
+        assert_eq!(
+            plain_identity.get_public_key(),
+            encrypted_identity.get_public_key()
+        );
+
+        // An encrypted file can not be encrypted again:
+        let encrypted_path2 = dir.path().join("encrypted_identity_file2");
+        match encrypt_identity_file(
+            &encrypted_path,
+            &encrypted_path2,
+            "passphrase",
+            TEST_KDF_ITERATIONS,
+            &rng,
+        ) {
+            Err(IdentityFileError::PassphraseRequired) => {}
+            _ => unreachable!(),
+        };
+    }
 }
@@ -5,9 +5,11 @@ use std::path::{Path, PathBuf};
 
 use toml;
 
+use crate::file::relay::RelayFile;
 use crate::file::ser_string::{public_key_to_string, string_to_public_key, SerStringError};
 
-use crate::index_server::messages::IndexServerAddress;
+use crate::app_server::messages::RelayAddress;
+use crate::index_server::messages::{FederationAddress, IndexServerAddress};
 use crate::net::messages::{NetAddress, NetAddressError};
 
 #[derive(Debug, From)]
@@ -19,6 +21,8 @@ pub enum IndexServerFileError {
     ParseSocketAddrError,
     InvalidPublicKey,
     NetAddressError(NetAddressError),
+    /// A trusted server must have exactly one of address and relay.
+    InvalidFederationAddress,
 }
 
 /// A helper structure for serialize and deserializing IndexServer.
@@ -28,6 +32,16 @@ struct IndexServerFile {
     address: String,
 }
 
+/// A helper structure for serialize and deserializing a trusted index server.
+/// A trusted index server is reachable either directly at `address`, or through `relay`.
+/// Files created using `store_index_server_to_file()` are valid trusted index server files.
+#[derive(Serialize, Deserialize)]
+struct TrustedServerFile {
+    public_key: String,
+    address: Option<String>,
+    relay: Option<RelayFile>,
+}
+
 impl From<SerStringError> for IndexServerFileError {
     fn from(_e: SerStringError) -> Self {
         IndexServerFileError::SerStringError
@@ -72,6 +86,65 @@ pub fn store_index_server_to_file(
     Ok(())
 }
 
+/// Load a trusted index server from a file
+pub fn load_trusted_server_from_file(
+    path: &Path,
+) -> Result<IndexServerAddress<FederationAddress>, IndexServerFileError> {
+    let data = fs::read_to_string(&path)?;
+    let trusted_server_file: TrustedServerFile = toml::from_str(&data)?;
+
+    let public_key = string_to_public_key(&trusted_server_file.public_key)?;
+
+    let address = match (trusted_server_file.address, trusted_server_file.relay) {
+        (Some(address), None) => FederationAddress::Direct(address.try_into()?),
+        (None, Some(relay_file)) => FederationAddress::Relay(RelayAddress {
+            public_key: string_to_public_key(&relay_file.public_key)?,
+            address: relay_file.address.try_into()?,
+        }),
+        _ => return Err(IndexServerFileError::InvalidFederationAddress),
+    };
+
+    Ok(IndexServerAddress {
+        public_key,
+        address,
+    })
+}
+
+/// Store a trusted index server to file
+pub fn store_trusted_server_to_file(
+    trusted_server: &IndexServerAddress<FederationAddress>,
+    path: &Path,
+) -> Result<(), IndexServerFileError> {
+    let IndexServerAddress {
+        ref public_key,
+        ref address,
+    } = trusted_server;
+
+    let (opt_address, opt_relay) = match address {
+        FederationAddress::Direct(address) => (Some(address.as_str().to_string()), None),
+        FederationAddress::Relay(relay_address) => {
+            let relay_file = RelayFile {
+                public_key: public_key_to_string(&relay_address.public_key),
+                address: relay_address.address.as_str().to_string(),
+            };
+            (None, Some(relay_file))
+        }
+    };
+
+    let trusted_server_file = TrustedServerFile {
+        public_key: public_key_to_string(&public_key),
+        address: opt_address,
+        relay: opt_relay,
+    };
+
+    let data = toml::to_string(&trusted_server_file)?;
+
+    let mut file = File::create(path)?;
+    file.write_all(&data.as_bytes())?;
+
+    Ok(())
+}
+
 #[derive(Debug)]
 pub enum IndexServerDirectoryError {
     IoError(io::Error),
@@ -85,11 +158,11 @@ impl From<io::Error> for IndexServerDirectoryError {
     }
 }
 
-/// Load a directory of index server address files, and return a map representing
+/// Load a directory of trusted index server files, and return a map representing
 /// the information from all files
 pub fn load_trusted_servers(
     dir_path: &Path,
-) -> Result<Vec<IndexServerAddress<NetAddress>>, IndexServerDirectoryError> {
+) -> Result<Vec<IndexServerAddress<FederationAddress>>, IndexServerDirectoryError> {
     let mut res_trusted = Vec::new();
     for entry in fs::read_dir(dir_path).map_err(IndexServerDirectoryError::InvalidDirectory)? {
         let entry = entry?;
@@ -98,7 +171,7 @@ pub fn load_trusted_servers(
             continue;
         }
         res_trusted.push(
-            load_index_server_from_file(&path)
+            load_trusted_server_from_file(&path)
                 .map_err(|e| IndexServerDirectoryError::InvalidFile(path, e))?,
         );
     }
@@ -108,6 +181,7 @@ pub fn load_trusted_servers(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
     use tempfile::tempdir;
 
     use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
@@ -143,6 +217,73 @@ mod tests {
         assert_eq!(index_server_address, index_server_address2);
     }
 
+    #[test]
+    fn test_trusted_server_file_relay() {
+        let trusted_server_file: TrustedServerFile = toml::from_str(
+            r#"
+            public_key = 'public_key_string'
+
+            [relay]
+            public_key = 'relay_public_key_string'
+            address = 'localhost:1337'
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(trusted_server_file.public_key, "public_key_string");
+        assert!(trusted_server_file.address.is_none());
+        let relay_file = trusted_server_file.relay.unwrap();
+        assert_eq!(relay_file.public_key, "relay_public_key_string");
+        assert_eq!(relay_file.address, "localhost:1337");
+    }
+
+    #[test]
+    fn test_store_load_trusted_server() {
+        // Create a temporary directory:
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("trusted_server_file");
+
+        let trusted_server = IndexServerAddress {
+            public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            address: FederationAddress::Relay(RelayAddress {
+                public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                address: "127.0.0.1:1337".to_owned().try_into().unwrap(),
+            }),
+        };
+
+        store_trusted_server_to_file(&trusted_server, &file_path).unwrap();
+        let trusted_server2 = load_trusted_server_from_file(&file_path).unwrap();
+
+        assert_eq!(trusted_server, trusted_server2);
+    }
+
+    #[test]
+    fn test_load_trusted_server_invalid() {
+        // Create a temporary directory:
+        let dir = tempdir().unwrap();
+        let file_path = dir.path().join("trusted_server_file");
+
+        // Both a direct address and a relay:
+        let data = format!(
+            r#"
+            public_key = '{}'
+            address = '127.0.0.1:1000'
+
+            [relay]
+            public_key = '{}'
+            address = '127.0.0.1:1001'
+        "#,
+            public_key_to_string(&PublicKey::from(&[0xaa; PUBLIC_KEY_LEN])),
+            public_key_to_string(&PublicKey::from(&[0xbb; PUBLIC_KEY_LEN])),
+        );
+        fs::write(&file_path, data).unwrap();
+
+        match load_trusted_server_from_file(&file_path) {
+            Err(IndexServerFileError::InvalidFederationAddress) => {}
+            _ => unreachable!(),
+        };
+    }
+
     #[test]
     fn test_load_trusted_index_servers() {
         // Create a temporary directory:
@@ -162,29 +303,37 @@ mod tests {
         };
         store_index_server_to_file(&index_server_address, &file_path).unwrap();
 
+        // A trusted server reachable only through a relay:
         let file_path = dir.path().join("index_server_address_file_c");
-        let index_server_address = IndexServerAddress {
-            public_key: PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]),
+        let relay_address = RelayAddress {
+            public_key: PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]),
             address: "127.0.0.1:1002".to_owned().try_into().unwrap(),
         };
-        store_index_server_to_file(&index_server_address, &file_path).unwrap();
+        let trusted_server = IndexServerAddress {
+            public_key: PublicKey::from(&[0xcc; PUBLIC_KEY_LEN]),
+            address: FederationAddress::Relay(relay_address.clone()),
+        };
+        store_trusted_server_to_file(&trusted_server, &file_path).unwrap();
 
         let trusted_servers = load_trusted_servers(&dir.path()).unwrap();
         assert_eq!(trusted_servers.len(), 3);
 
-        let mut addresses = trusted_servers
-            .iter()
-            .map(|server| server.address.clone())
-            .collect::<Vec<_>>();
-        addresses.sort();
+        let addresses = trusted_servers
+            .into_iter()
+            .map(|server| (server.public_key, server.address))
+            .collect::<HashMap<_, _>>();
 
         assert_eq!(
-            addresses,
-            vec![
-                "127.0.0.1:1000".to_owned().try_into().unwrap(),
-                "127.0.0.1:1001".to_owned().try_into().unwrap(),
-                "127.0.0.1:1002".to_owned().try_into().unwrap()
-            ]
+            addresses[&PublicKey::from(&[0xaa; PUBLIC_KEY_LEN])],
+            FederationAddress::Direct("127.0.0.1:1000".to_owned().try_into().unwrap())
+        );
+        assert_eq!(
+            addresses[&PublicKey::from(&[0xbb; PUBLIC_KEY_LEN])],
+            FederationAddress::Direct("127.0.0.1:1001".to_owned().try_into().unwrap())
+        );
+        assert_eq!(
+            addresses[&PublicKey::from(&[0xcc; PUBLIC_KEY_LEN])],
+            FederationAddress::Relay(relay_address)
         );
     }
 }
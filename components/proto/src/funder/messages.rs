@@ -1,6 +1,8 @@
 use byteorder::{BigEndian, WriteBytesExt};
 use std::collections::HashSet;
 
+use im::vector::Vector as ImVec;
+
 use crypto::crypto_rand::RandValue;
 use crypto::hash::{self, HashResult};
 use crypto::identity::{PublicKey, Signature};
@@ -8,7 +10,9 @@ use crypto::invoice_id::InvoiceId;
 use crypto::uid::Uid;
 
 use crate::app_server::messages::{NamedRelayAddress, RelayAddress};
-use crate::consts::MAX_ROUTE_LEN;
+use crate::consts::{MAX_LABELED_PAYMENTS, MAX_ROUTE_LEN};
+use crate::directory::messages::{DirectoryListing, DirectorySubscription};
+use crate::invite::messages::ResponseImportFriendInvite;
 use crate::net::messages::NetAddress;
 use crate::report::messages::FunderReportMutations;
 use common::canonical_serialize::CanonicalSerialize;
@@ -33,6 +37,9 @@ pub enum FunderToChanneler<RA> {
     UpdateFriend(ChannelerUpdateFriend<RA>),
     /// Request to remove a friend
     RemoveFriend(PublicKey), // friend_public_key
+    /// Stop reconnecting to a friend that went offline intentionally.
+    /// Reconnection resumes at the next `UpdateFriend` for this friend.
+    SuspendFriend(PublicKey), // friend_public_key
 }
 
 #[derive(Debug)]
@@ -59,6 +66,20 @@ pub struct FriendsRoute {
     pub public_keys: Vec<PublicKey>,
 }
 
+/// Identifies a route, for use as a map key. Obtained using `FriendsRoute::id()`.
+///
+/// The id is a hash over the canonical serialization of the ordered public keys of the route,
+/// and nothing else. Two routes have equal ids exactly when they have the same public keys in
+/// the same order (A collision of the hash function is considered impossible).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RouteId(HashResult);
+
+impl RouteId {
+    pub fn as_hash_result(&self) -> &HashResult {
+        &self.0
+    }
+}
+
 #[derive(Eq, PartialEq, Debug, Clone, Serialize, Deserialize)]
 pub struct RequestSendFunds {
     pub request_id: Uid,
@@ -78,6 +99,7 @@ pub struct ResponseSendFunds<S = Signature> {
 pub struct FailureSendFunds<S = Signature> {
     pub request_id: Uid,
     pub reporting_public_key: PublicKey,
+    pub reason: FailureReason,
     pub rand_nonce: RandValue,
     pub signature: S,
 }
@@ -122,11 +144,180 @@ pub struct MoveTokenRequest<B = NetAddress> {
     pub token_wanted: bool,
 }
 
+/// Machine readable codes for the reasons an incoming operation could be rejected.
+/// A code is never reused for a different reason, so that implementations of different
+/// versions can understand each other's reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OperationErrorCode {
+    RemoteMaxDebtTooLarge,
+    PkPairNotInRoute,
+    InvalidRoute,
+    RequestsAlreadyDisabled,
+    RouteTooLong,
+    InsufficientTrust,
+    RequestAlreadyExists,
+    RequestDoesNotExist,
+    InvalidResponseSignature,
+    ReportingNodeNonexistent,
+    InvalidReportingNode,
+    InvalidFailureSignature,
+    LocalRequestsClosed,
+    InsufficientFrozenCredits,
+    BalanceOverflow,
+    /// Two operations of the same move token refer to the same request id.
+    DuplicateRequestId,
+    /// A response or a failure refers to a request of the same move token.
+    ResolvesRequestOfSameBatch,
+    /// A response for a request whose response deadline has passed.
+    LateResponse,
+    /// A code we do not know. Possibly sent by a newer implementation.
+    Unknown(u16),
+}
+
+impl OperationErrorCode {
+    pub fn to_u16(self) -> u16 {
+        match self {
+            OperationErrorCode::RemoteMaxDebtTooLarge => 1,
+            OperationErrorCode::PkPairNotInRoute => 2,
+            OperationErrorCode::InvalidRoute => 3,
+            OperationErrorCode::RequestsAlreadyDisabled => 4,
+            OperationErrorCode::RouteTooLong => 5,
+            OperationErrorCode::InsufficientTrust => 6,
+            OperationErrorCode::RequestAlreadyExists => 7,
+            OperationErrorCode::RequestDoesNotExist => 8,
+            OperationErrorCode::InvalidResponseSignature => 9,
+            OperationErrorCode::ReportingNodeNonexistent => 10,
+            OperationErrorCode::InvalidReportingNode => 11,
+            OperationErrorCode::InvalidFailureSignature => 12,
+            OperationErrorCode::LocalRequestsClosed => 13,
+            OperationErrorCode::InsufficientFrozenCredits => 14,
+            OperationErrorCode::BalanceOverflow => 15,
+            OperationErrorCode::DuplicateRequestId => 16,
+            OperationErrorCode::ResolvesRequestOfSameBatch => 17,
+            OperationErrorCode::LateResponse => 18,
+            OperationErrorCode::Unknown(code) => code,
+        }
+    }
+
+    pub fn from_u16(code: u16) -> OperationErrorCode {
+        match code {
+            1 => OperationErrorCode::RemoteMaxDebtTooLarge,
+            2 => OperationErrorCode::PkPairNotInRoute,
+            3 => OperationErrorCode::InvalidRoute,
+            4 => OperationErrorCode::RequestsAlreadyDisabled,
+            5 => OperationErrorCode::RouteTooLong,
+            6 => OperationErrorCode::InsufficientTrust,
+            7 => OperationErrorCode::RequestAlreadyExists,
+            8 => OperationErrorCode::RequestDoesNotExist,
+            9 => OperationErrorCode::InvalidResponseSignature,
+            10 => OperationErrorCode::ReportingNodeNonexistent,
+            11 => OperationErrorCode::InvalidReportingNode,
+            12 => OperationErrorCode::InvalidFailureSignature,
+            13 => OperationErrorCode::LocalRequestsClosed,
+            14 => OperationErrorCode::InsufficientFrozenCredits,
+            15 => OperationErrorCode::BalanceOverflow,
+            16 => OperationErrorCode::DuplicateRequestId,
+            17 => OperationErrorCode::ResolvesRequestOfSameBatch,
+            18 => OperationErrorCode::LateResponse,
+            code => OperationErrorCode::Unknown(code),
+        }
+    }
+}
+
+/// Machine readable reasons for the failure of a request, reported by the failing node.
+/// Like `OperationErrorCode`, a code is never reused for a different reason.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FailureReason {
+    /// The reporting node did not specify a reason.
+    Unspecified,
+    /// The payment is below the minimum amount the reporting node is willing to handle.
+    PricingRejected,
+    /// The maximum amount we can send along the route is below our minimum payment.
+    /// Only reported locally, in response to a sweep request.
+    NothingToSend,
+    /// The app that issued the request has too many open requests.
+    /// Only reported locally, by the app server.
+    RateLimited,
+    /// We can not freeze enough credits against the first hop friend to send the request.
+    /// Contains the largest payment along the same route we could send instead.
+    /// Only reported locally, when the request is submitted.
+    InsufficientLocalCapacity(u128),
+    /// The node can not write to its database (For example: the disk is full).
+    /// Only reported locally, when the request is submitted.
+    StorageUnavailable,
+    /// The user cancelled the request before it was sent to the first hop friend.
+    /// Only reported locally.
+    Cancelled,
+    /// A reason we do not know. Possibly sent by a newer implementation.
+    Unknown(u16),
+}
+
+impl FailureReason {
+    pub fn to_u16(self) -> u16 {
+        match self {
+            FailureReason::Unspecified => 0,
+            FailureReason::PricingRejected => 1,
+            FailureReason::NothingToSend => 2,
+            FailureReason::RateLimited => 3,
+            FailureReason::InsufficientLocalCapacity(_) => 4,
+            FailureReason::StorageUnavailable => 5,
+            FailureReason::Cancelled => 6,
+            FailureReason::Unknown(code) => code,
+        }
+    }
+
+    /// The data of a reason is not a part of its code. It is zeroed, and should be filled in
+    /// by the caller, if it is available.
+    pub fn from_u16(code: u16) -> FailureReason {
+        match code {
+            0 => FailureReason::Unspecified,
+            1 => FailureReason::PricingRejected,
+            2 => FailureReason::NothingToSend,
+            3 => FailureReason::RateLimited,
+            4 => FailureReason::InsufficientLocalCapacity(0),
+            5 => FailureReason::StorageUnavailable,
+            6 => FailureReason::Cancelled,
+            code => FailureReason::Unknown(code),
+        }
+    }
+}
+
+/// Sent to a friend whose MoveToken we rejected because one of its operations was invalid.
+/// This report is only a debugging aid: it is not signed, and the receiver should not act on
+/// it, except for logging and reporting it to the user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProtocolViolationReport {
+    /// Index of the rejected operation inside the MoveToken
+    pub operation_index: u64,
+    pub error_code: OperationErrorCode,
+    /// The new_token of the rejected MoveToken, allowing the sender to correlate.
+    pub new_token: Signature,
+}
+
+/// Proof that the sender knows the verification phrase the user has entered for this friend.
+/// The signature is over a buffer derived from both public keys and the phrase.
+/// (See `create_verification_proof_buffer()`).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerificationProof {
+    pub signature: Signature,
+}
+
+/// Sent to a friend right before an intentional disconnect (For example: A shutdown of the node),
+/// so that the friend can tell it apart from a failure. The message is advisory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Goodbye {
+    /// Expected amount of ticks until we are back online, if known.
+    pub opt_downtime_ticks: Option<u64>,
+}
+
 #[allow(clippy::large_enum_variant)]
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum FriendMessage<B = NetAddress> {
     MoveTokenRequest(MoveTokenRequest<B>),
     InconsistencyError(ResetTerms),
+    ProtocolViolation(ProtocolViolationReport),
+    VerificationProof(VerificationProof),
+    Goodbye(Goodbye),
 }
 
 /// A `Receipt` is received if a `RequestSendFunds` is successful.
@@ -184,6 +375,9 @@ impl CanonicalSerialize for FailureSendFunds {
         let mut res_bytes = Vec::new();
         res_bytes.extend_from_slice(&self.request_id);
         res_bytes.extend_from_slice(&self.reporting_public_key);
+        res_bytes
+            .write_u16::<BigEndian>(self.reason.to_u16())
+            .unwrap();
         res_bytes.extend_from_slice(&self.signature);
         res_bytes
     }
@@ -233,6 +427,17 @@ impl CanonicalSerialize for FriendsRoute {
     }
 }
 
+/// The reason a route is not valid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RouteValidationError {
+    /// The route has less than 2 nodes
+    TooShort,
+    /// The route has more than MAX_ROUTE_LEN nodes
+    TooLong,
+    /// A public key appears twice in the route (Other than a cycle, where first == last)
+    RepeatedPublicKey(PublicKey),
+}
+
 impl FriendsRoute {
     pub fn len(&self) -> usize {
         self.public_keys.len()
@@ -246,25 +451,31 @@ impl FriendsRoute {
     /// A valid route must have at least 2 nodes, and is in one of the following forms:
     /// A -- B -- C -- D -- E -- F -- A   (Single cycle, first == last)
     /// A -- B -- C -- D -- E -- F        (A route with no repetitions)
-    pub fn is_valid(&self) -> bool {
-        if (self.public_keys.len() < 2) || (self.public_keys.len() > MAX_ROUTE_LEN) {
-            return false;
+    pub fn validate(&self) -> Result<(), RouteValidationError> {
+        if self.public_keys.len() < 2 {
+            return Err(RouteValidationError::TooShort);
+        }
+        if self.public_keys.len() > MAX_ROUTE_LEN {
+            return Err(RouteValidationError::TooLong);
         }
 
         let mut seen = HashSet::new();
         for public_key in &self.public_keys[..self.public_keys.len() - 1] {
             if !seen.insert(public_key.clone()) {
-                return false;
+                return Err(RouteValidationError::RepeatedPublicKey(public_key.clone()));
             }
         }
         let last_pk = &self.public_keys[self.public_keys.len() - 1];
-        if last_pk == &self.public_keys[0] {
-            // The last public key closes a cycle
-            true
-        } else {
-            // A route with no repetitions
-            seen.insert(last_pk.clone())
+        // The last public key may only close a cycle:
+        if last_pk != &self.public_keys[0] && !seen.insert(last_pk.clone()) {
+            return Err(RouteValidationError::RepeatedPublicKey(last_pk.clone()));
         }
+        Ok(())
+    }
+
+    /// Check if the route is valid. See `validate()`.
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_ok()
     }
 
     /// Find two consecutive public keys (pk1, pk2) inside a friends route.
@@ -283,6 +494,11 @@ impl FriendsRoute {
         hash::sha_512_256(&self.canonical_serialize())
     }
 
+    /// The id of the route. Computed on every call, as the public keys may be changed.
+    pub fn id(&self) -> RouteId {
+        RouteId(self.hash())
+    }
+
     /// Find the index of a public key inside the route.
     /// source is considered to be index 0.
     /// dest is considered to be the last index.
@@ -347,6 +563,15 @@ pub struct AddFriend<B = NetAddress> {
     pub balance: i128, // Initial balance
 }
 
+/// Add a friend from a verified friend invite, together with the max debt we let the friend owe
+/// us, in one step.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddFriendFromInvite<B = NetAddress> {
+    pub request_id: Uid,
+    pub add_friend: AddFriend<B>,
+    pub opt_remote_max_debt: Option<u128>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RemoveFriend {
     pub friend_public_key: PublicKey,
@@ -364,10 +589,30 @@ pub struct SetFriendStatus {
     pub status: FriendStatus,
 }
 
+/// An expiry for a remote max debt.
+/// After the given amount of ticks, the remote max debt is reduced to `post_expiry_max_debt`,
+/// but never below the debt the friend already owes us.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteMaxDebtExpiry {
+    pub expires_after_ticks: u64,
+    pub post_expiry_max_debt: u128,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SetFriendRemoteMaxDebt {
     pub friend_public_key: PublicKey,
     pub remote_max_debt: u128,
+    /// `None` means that the remote max debt does not expire.
+    pub opt_expiry: Option<RemoteMaxDebtExpiry>,
+}
+
+/// Set the amount of ticks we wait for a friend to answer a request we have forwarded to him.
+/// When the deadline passes, we fail the request towards its origin.
+/// `None` means that we wait for as long as it takes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetFriendResponseDeadline {
+    pub friend_public_key: PublicKey,
+    pub opt_deadline_ticks: Option<u64>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -376,6 +621,35 @@ pub struct SetFriendName {
     pub name: String,
 }
 
+/// Set a phrase the user has shared with the friend out of band.
+/// The phrase is used to verify that the friend is really the node the user meant to add.
+/// Verification is optional, and only informs the user. It never blocks the channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetFriendVerificationPhrase {
+    pub friend_public_key: PublicKey,
+    pub phrase: String,
+}
+
+/// Hide (Or stop hiding) the capacities with a friend from index servers.
+/// Routes through a private friend can only be found by nodes that know about it out of band.
+/// Local payments through the friend are not affected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetFriendIndexPrivate {
+    pub friend_public_key: PublicKey,
+    pub index_private: bool,
+}
+
+/// The result of verifying a friend using a shared phrase
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VerificationStatus {
+    /// We have no verification phrase, or the friend has not sent us a proof yet.
+    Unverified,
+    /// The friend has proved knowledge of our verification phrase.
+    PhraseVerified,
+    /// The friend has sent a proof that does not match our verification phrase.
+    Failed,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SetFriendRelays<B = NetAddress> {
     pub friend_public_key: PublicKey,
@@ -388,6 +662,19 @@ pub struct ResetFriendChannel {
     pub reset_token: Signature,
 }
 
+/// Minimum payments (`dest_payment`, not including fees) this node is willing to handle.
+/// Small payments take the same resources as large ones, so a flood of them could
+/// degrade the throughput of the node. A threshold of zero accepts any payment.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DustThresholds {
+    /// Minimum payment for requests originating from this node.
+    pub min_send_payment: u128,
+    /// Minimum payment for requests we forward as an intermediary.
+    pub min_forward_payment: u128,
+    /// Minimum payment for requests of which we are the destination.
+    pub min_receive_payment: u128,
+}
+
 /// A request to send funds that originates from the user
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UserRequestSendFunds {
@@ -395,6 +682,23 @@ pub struct UserRequestSendFunds {
     pub route: FriendsRoute,
     pub invoice_id: InvoiceId,
     pub dest_payment: u128,
+    /// A label chosen by the app (For example: an order number), of at most
+    /// `MAX_PAYMENT_LABEL_LEN` bytes. The label is kept locally, and is never sent to other
+    /// nodes.
+    pub opt_label: Option<Vec<u8>>,
+}
+
+/// A request to send the maximum possible amount along a route, originating from the user.
+/// The node computes the `dest_payment` of the request itself. If there is nothing to send, the
+/// request fails with `FailureReason::NothingToSend`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserRequestSweepFunds {
+    pub request_id: Uid,
+    pub route: FriendsRoute,
+    pub invoice_id: InvoiceId,
+    /// The maximum total payment (Including fees) the route can carry, as reported by the index
+    /// servers. None if unknown.
+    pub opt_route_capacity: Option<u128>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -403,26 +707,246 @@ pub struct ReceiptAck {
     pub receipt_signature: Signature,
 }
 
+/// Which incoming payments should be reported to the payment notifier
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaymentNotifyFilter {
+    /// Report all incoming payments
+    All,
+    /// Report only payments for one of the given invoices
+    Invoices(Vec<InvoiceId>),
+}
+
+impl PaymentNotifyFilter {
+    pub fn matches(&self, invoice_id: &InvoiceId) -> bool {
+        match self {
+            PaymentNotifyFilter::All => true,
+            PaymentNotifyFilter::Invoices(invoice_ids) => invoice_ids.contains(invoice_id),
+        }
+    }
+}
+
+/// Who consumes the notifications about incoming payments
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PaymentConsumer<B = NetAddress> {
+    /// A consumer listening on the given address.
+    Address(B),
+    /// Apps of the node that subscribe to incoming payments.
+    Apps,
+}
+
+/// A consumer of notifications about incoming payments.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentNotifier<B = NetAddress> {
+    pub consumer: PaymentConsumer<B>,
+    pub filter: PaymentNotifyFilter,
+}
+
+impl<B> PaymentNotifier<B> {
+    /// Address of the consumer, if the consumer is not one of the node's apps.
+    pub fn opt_address(&self) -> Option<&B> {
+        match &self.consumer {
+            PaymentConsumer::Address(address) => Some(address),
+            PaymentConsumer::Apps => None,
+        }
+    }
+}
+
+/// A notification about a completed incoming payment (We are the destination of the payment).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IncomingPayment {
+    /// Increasing identifier. Does not change if the notification is delivered more than once.
+    pub notification_id: u64,
+    /// Length of the route the payment went through, including the source and us.
+    /// The route itself is not kept, for the privacy of the payer.
+    pub route_len: u32,
+    pub receipt: Receipt,
+}
+
+/// A payment we have sent with a label.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LabeledPayment {
+    pub request_id: Uid,
+    pub label: Vec<u8>,
+    /// None until the payment is answered.
+    pub opt_result: Option<ResponseSendFundsResult>,
+}
+
+/// A payment submitted by an app. Recorded together with the admission of the payment, and kept
+/// until the submitting app acknowledges its outcome. An app that lost its connection while
+/// submitting payments can find out what happened to them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaymentSubmission {
+    pub request_id: Uid,
+    /// The app that submitted the payment
+    pub app_public_key: PublicKey,
+    pub route: FriendsRoute,
+    pub invoice_id: InvoiceId,
+    /// For sweep requests, this is the amount computed by the node (0 if nothing could be sent).
+    pub dest_payment: u128,
+    /// None while the payment is pending.
+    pub opt_result: Option<ResponseSendFundsResult>,
+}
+
+/// The submissions of an app whose outcomes were not yet acknowledged, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponsePendingSubmissions {
+    pub request_id: Uid,
+    pub submissions: Vec<PaymentSubmission>,
+}
+
+/// A directed pair of friends: Requests that arrive from the friend `opt_from` and are forwarded
+/// to the friend `opt_to`. None matches any friend.
+///
+/// If more than one rule matches a pair of friends, the most specific rule applies:
+///
+/// 1. A rule for the exact pair: (A, B)
+/// 2. A rule for requests forwarded to the friend: (Anyone, B)
+/// 3. A rule for requests that arrive from the friend: (A, Anyone)
+/// 4. A rule for all the pairs: (Anyone, Anyone)
+///
+/// If no rule matches, the credits frozen for a pair are limited only by the channel with the
+/// friend the requests are forwarded to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FriendPair {
+    pub opt_from: Option<PublicKey>,
+    pub opt_to: Option<PublicKey>,
+}
+
+impl FriendPair {
+    /// Check if requests from `from_public_key` forwarded to `to_public_key` match this pair.
+    pub fn matches(&self, from_public_key: &PublicKey, to_public_key: &PublicKey) -> bool {
+        self.opt_from
+            .as_ref()
+            .map_or(true, |from| from == from_public_key)
+            && self.opt_to.as_ref().map_or(true, |to| to == to_public_key)
+    }
+}
+
+/// Maximum amount of credits we freeze against the friend `pair.opt_to` for requests that
+/// arrived from the friend `pair.opt_from`. A rule with a wildcard applies separately to every
+/// pair it matches: The matching pairs do not share the limit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PairFreezeLimit {
+    pub pair: FriendPair,
+    pub limit: u128,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetPairFreezeLimit {
+    pub pair: FriendPair,
+    /// None removes the rule of the pair.
+    pub opt_limit: Option<u128>,
+}
+
+/// Ask for the limit and the current usage of the credits frozen for requests that arrive from
+/// one friend and are forwarded to another friend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestPairFreezeUsage {
+    pub request_id: Uid,
+    pub from_public_key: PublicKey,
+    pub to_public_key: PublicKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponsePairFreezeUsage {
+    pub request_id: Uid,
+    /// The rule that applies to the pair, or None if no rule matches.
+    pub opt_limit: Option<PairFreezeLimit>,
+    /// Credits currently frozen (Or queued to be frozen) against the second friend, for requests
+    /// that arrived from the first friend.
+    pub frozen_credits: u128,
+}
+
+/// Remember a new labeled payment (Oldest first). If `MAX_LABELED_PAYMENTS` labeled payments
+/// are already remembered, the oldest one is forgotten to make room.
+/// A payment that is already remembered is not added again.
+pub fn add_labeled_payment(
+    labeled_payments: &mut ImVec<LabeledPayment>,
+    labeled_payment: &LabeledPayment,
+) {
+    if labeled_payments
+        .iter()
+        .any(|cur| cur.request_id == labeled_payment.request_id)
+    {
+        return;
+    }
+    if labeled_payments.len() >= MAX_LABELED_PAYMENTS {
+        let _ = labeled_payments.pop_front();
+    }
+    labeled_payments.push_back(labeled_payment.clone());
+}
+
+/// Record the result of a remembered labeled payment.
+pub fn set_labeled_payment_result(
+    labeled_payments: &mut ImVec<LabeledPayment>,
+    request_id: &Uid,
+    result: &ResponseSendFundsResult,
+) {
+    for labeled_payment in labeled_payments.iter_mut() {
+        if &labeled_payment.request_id == request_id {
+            labeled_payment.opt_result = Some(result.clone());
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum FunderControl<B> {
     AddRelay(NamedRelayAddress<B>),
     RemoveRelay(PublicKey),
     AddFriend(AddFriend<B>),
+    /// Answered with `FunderOutgoingControl::ResponseImportFriendInvite`.
+    AddFriendFromInvite(AddFriendFromInvite<B>),
     RemoveFriend(RemoveFriend),
     SetRequestsStatus(SetRequestsStatus),
     SetFriendStatus(SetFriendStatus),
     SetFriendRemoteMaxDebt(SetFriendRemoteMaxDebt),
+    SetFriendResponseDeadline(SetFriendResponseDeadline),
     SetFriendRelays(SetFriendRelays<B>),
     SetFriendName(SetFriendName),
+    SetFriendVerificationPhrase(SetFriendVerificationPhrase),
+    SetFriendIndexPrivate(SetFriendIndexPrivate),
     ResetFriendChannel(ResetFriendChannel),
     RequestSendFunds(UserRequestSendFunds),
+    /// Send the maximum possible amount along a route.
+    RequestSweepFunds(UserRequestSweepFunds),
+    /// Withdraw a payment request (By its request id) that was not yet sent to the first hop
+    /// friend.
+    CancelUserRequest(Uid),
     ReceiptAck(ReceiptAck),
+    SetPaymentNotifier(PaymentNotifier<B>),
+    ClearPaymentNotifier,
+    /// The notification with the given id was delivered to the consumer.
+    AckIncomingPayment(u64),
+    PrewarmFriend(PrewarmFriend),
+    SetDustThresholds(DustThresholds),
+    SetDirectory(DirectorySubscription<B>),
+    ClearDirectory,
+    /// A newer document of the directory was applied, resulting in the given listing.
+    SetDirectoryListing(DirectoryListing),
+    PinDirectoryEntry(PublicKey),
+    UnpinDirectoryEntry(PublicKey),
+    /// Send a goodbye to all the online friends, before an intentional shutdown of the node.
+    AnnounceShutdown(Goodbye),
+    /// List the payment submissions of the app that sent this control (By its request id).
+    /// Answered with `FunderOutgoingControl::ResponsePendingSubmissions`.
+    ListPendingSubmissions(Uid),
+    /// The app that sent this control has learned the outcome of the submitted payment with the
+    /// given request id. The submission is forgotten. Pending submissions can not be
+    /// acknowledged.
+    AckSubmission(Uid),
+    /// Limit the credits frozen for requests forwarded between a pair of friends.
+    SetPairFreezeLimit(SetPairFreezeLimit),
+    /// Answered with `FunderOutgoingControl::ResponsePairFreezeUsage`.
+    RequestPairFreezeUsage(RequestPairFreezeUsage),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FunderIncomingControl<B> {
     pub app_request_id: Uid,
     pub funder_control: FunderControl<B>,
+    /// The app that sent this control, if any.
+    /// Payments are recorded as submissions only if they were sent by an app.
+    pub opt_app_public_key: Option<PublicKey>,
 }
 
 impl<B> FunderIncomingControl<B> {
@@ -430,6 +954,20 @@ impl<B> FunderIncomingControl<B> {
         FunderIncomingControl {
             app_request_id,
             funder_control,
+            opt_app_public_key: None,
+        }
+    }
+
+    /// A control sent by the app with the given public key
+    pub fn new_from_app(
+        app_public_key: PublicKey,
+        app_request_id: Uid,
+        funder_control: FunderControl<B>,
+    ) -> Self {
+        FunderIncomingControl {
+            app_request_id,
+            funder_control,
+            opt_app_public_key: Some(app_public_key),
         }
     }
 }
@@ -454,20 +992,210 @@ impl UserRequestSendFunds {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+impl UserRequestSweepFunds {
+    /// A request to send exactly `dest_payment` along the route of the sweep.
+    pub fn into_user_request_send_funds(self, dest_payment: u128) -> UserRequestSendFunds {
+        UserRequestSendFunds {
+            request_id: self.request_id,
+            route: self.route,
+            invoice_id: self.invoice_id,
+            dest_payment,
+            opt_label: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ResponseSendFundsResult {
     Success(Receipt),
-    Failure(PublicKey), // Reporting public key.
+    Failure((PublicKey, FailureReason)), // (Reporting public key, reason)
+}
+
+/// End to end timing of a payment we have originated, in time ticks since the payment was
+/// requested. Measured locally, and not a part of the signed receipt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaymentTiming {
+    /// Until the move token carrying the request was sent to the first hop. A request is placed
+    /// inside a move token in the same tick the move token is sent.
+    /// None if the request was never sent (Or it was not measured).
+    pub opt_ticks_to_sent: Option<u64>,
+    /// Until the response (or failure) was received
+    pub ticks_to_response: u64,
+    /// The route used for the payment
+    pub route: FriendsRoute,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ResponseReceived {
     pub request_id: Uid,
     pub result: ResponseSendFundsResult,
+    /// Available if the response arrived from the route, and the payment was measured.
+    pub opt_timing: Option<PaymentTiming>,
+    /// The label the payment was sent with, if any.
+    pub opt_label: Option<Vec<u8>>,
+}
+
+/// Prepare the channel with a friend for an upcoming payment:
+/// Make sure the friend is online and that we hold the token, without sending any credit
+/// operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrewarmFriend {
+    pub request_id: Uid,
+    pub friend_public_key: PublicKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrewarmFailure {
+    FriendDoesNotExist,
+    FriendDisabled,
+    FriendOffline,
+    ChannelInconsistent,
+    /// A pre-warm of this friend was performed recently, or the app that issued the request has
+    /// too many open requests.
+    RateLimited,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PrewarmResult {
+    Success,
+    Failure(PrewarmFailure),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponsePrewarm {
+    pub request_id: Uid,
+    pub result: PrewarmResult,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelUserRequestResult {
+    /// The request was withdrawn before it was sent. The request is answered with a `Cancelled`
+    /// failure.
+    Cancelled,
+    /// The request was already sent to the first hop friend. It will be answered as usual.
+    InFlight,
+    /// There is no pending request with this request id (It might have already been answered).
+    NotFound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseCancelUserRequest {
+    pub request_id: Uid,
+    pub result: CancelUserRequestResult,
 }
 
 #[derive(Debug)]
 pub enum FunderOutgoingControl<B: Clone> {
     ResponseReceived(ResponseReceived),
+    ResponsePrewarm(ResponsePrewarm),
+    ResponseCancelUserRequest(ResponseCancelUserRequest),
+    ResponseImportFriendInvite(ResponseImportFriendInvite),
+    ResponsePendingSubmissions(ResponsePendingSubmissions),
+    ResponsePairFreezeUsage(ResponsePairFreezeUsage),
     ReportMutations(FunderReportMutations<B>),
+    /// A notification was added to the incoming payments outbox
+    IncomingPayment(IncomingPayment),
+    /// The payment notifier was set or cleared
+    PaymentNotifierChanged(Option<PaymentNotifier<B>>),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    /// Create a route of `route_len` distinct nodes
+    fn friends_route(route_len: usize) -> FriendsRoute {
+        FriendsRoute {
+            public_keys: (0..route_len)
+                .map(|i| PublicKey::from(&[i as u8; PUBLIC_KEY_LEN]))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_friends_route_is_valid() {
+        assert!(!friends_route(0).is_valid());
+        assert!(!friends_route(1).is_valid());
+        assert!(friends_route(2).is_valid());
+        assert!(friends_route(MAX_ROUTE_LEN).is_valid());
+        assert!(!friends_route(MAX_ROUTE_LEN + 1).is_valid());
+
+        // A cycle:
+        let mut route = friends_route(3);
+        route.public_keys.push(route.public_keys[0].clone());
+        assert!(route.is_valid());
+
+        // Repetition in the middle of the route:
+        let mut route = friends_route(3);
+        route.public_keys.push(route.public_keys[1].clone());
+        assert!(!route.is_valid());
+    }
+
+    #[test]
+    fn test_friends_route_validate() {
+        assert_eq!(
+            friends_route(0).validate(),
+            Err(RouteValidationError::TooShort)
+        );
+        assert_eq!(
+            friends_route(1).validate(),
+            Err(RouteValidationError::TooShort)
+        );
+        assert_eq!(
+            friends_route(MAX_ROUTE_LEN + 1).validate(),
+            Err(RouteValidationError::TooLong)
+        );
+        // A valid long route:
+        assert_eq!(friends_route(MAX_ROUTE_LEN).validate(), Ok(()));
+
+        // A repetition inside the route:
+        let mut route = friends_route(4);
+        route.public_keys[2] = route.public_keys[1].clone();
+        assert_eq!(
+            route.validate(),
+            Err(RouteValidationError::RepeatedPublicKey(
+                route.public_keys[1].clone()
+            ))
+        );
+
+        // The last node repeats a node other than the first:
+        let mut route = friends_route(4);
+        route.public_keys.push(route.public_keys[2].clone());
+        assert_eq!(
+            route.validate(),
+            Err(RouteValidationError::RepeatedPublicKey(
+                route.public_keys[2].clone()
+            ))
+        );
+
+        // A cycle of two nodes:
+        let mut route = friends_route(2);
+        route.public_keys.push(route.public_keys[0].clone());
+        assert_eq!(route.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_friends_route_id() {
+        let route = friends_route(4);
+        assert_eq!(route.id(), route.clone().id());
+        assert_eq!(route.id().as_hash_result(), &route.hash());
+
+        // Changing any public key changes the id:
+        for index in 0..route.len() {
+            let mut other_route = route.clone();
+            other_route.public_keys[index] = PublicKey::from(&[0xff; PUBLIC_KEY_LEN]);
+            assert_ne!(route.id(), other_route.id());
+        }
+
+        // The order of the public keys matters:
+        let mut other_route = route.clone();
+        other_route.public_keys.swap(1, 2);
+        assert_ne!(route.id(), other_route.id());
+
+        // A route is not identified with its prefix:
+        let mut other_route = route.clone();
+        other_route.public_keys.pop();
+        assert_ne!(route.id(), other_route.id());
+    }
 }
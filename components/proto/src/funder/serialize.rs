@@ -12,10 +12,12 @@ use std::io;
 use funder_capnp;
 
 use super::messages::{
-    FailureSendFunds, FriendMessage, FriendTcOp, FriendsRoute, MoveToken, MoveTokenRequest,
-    RequestSendFunds, ResetTerms, ResponseSendFunds,
+    FailureReason, FailureSendFunds, FriendMessage, FriendTcOp, FriendsRoute, Goodbye, MoveToken,
+    MoveTokenRequest, OperationErrorCode, ProtocolViolationReport, RequestSendFunds, ResetTerms,
+    ResponseSendFunds, VerificationProof,
 };
 
+use crate::consts::MAX_ROUTE_LEN;
 use crate::serialize::SerializeError;
 
 pub fn ser_friends_route(
@@ -90,6 +92,7 @@ fn ser_failure_send_funds_op(
             .reborrow()
             .init_reporting_public_key(),
     );
+    failure_send_funds_op_builder.set_reason(failure_send_funds.reason.to_u16());
     write_rand_nonce(
         &failure_send_funds.rand_nonce,
         &mut failure_send_funds_op_builder.reborrow().init_rand_nonce(),
@@ -231,6 +234,36 @@ fn ser_inconsistency_error(
     write_custom_int128(reset_terms.balance_for_reset, &mut balance_for_reset);
 }
 
+pub fn ser_protocol_violation_report(
+    protocol_violation_report: &ProtocolViolationReport,
+    protocol_violation_report_builder: &mut funder_capnp::protocol_violation_report::Builder,
+) {
+    protocol_violation_report_builder
+        .set_operation_index(protocol_violation_report.operation_index);
+    protocol_violation_report_builder.set_error_code(protocol_violation_report.error_code.to_u16());
+
+    let mut new_token = protocol_violation_report_builder
+        .reborrow()
+        .init_new_token();
+    write_signature(&protocol_violation_report.new_token, &mut new_token);
+}
+
+fn ser_verification_proof(
+    verification_proof: &VerificationProof,
+    verification_proof_builder: &mut funder_capnp::verification_proof::Builder,
+) {
+    let mut signature = verification_proof_builder.reborrow().init_signature();
+    write_signature(&verification_proof.signature, &mut signature);
+}
+
+pub fn ser_goodbye(goodbye: &Goodbye, goodbye_builder: &mut funder_capnp::goodbye::Builder) {
+    let mut opt_downtime_ticks_builder = goodbye_builder.reborrow().init_opt_downtime_ticks();
+    match goodbye.opt_downtime_ticks {
+        Some(downtime_ticks) => opt_downtime_ticks_builder.set_downtime_ticks(downtime_ticks),
+        None => opt_downtime_ticks_builder.set_empty(()),
+    }
+}
+
 fn ser_friend_message(
     friend_message: &FriendMessage,
     friend_message_builder: &mut funder_capnp::friend_message::Builder,
@@ -246,6 +279,23 @@ fn ser_friend_message(
                 friend_message_builder.reborrow().init_inconsistency_error();
             ser_inconsistency_error(inconsistency_error, &mut inconsistency_error_builder);
         }
+        FriendMessage::ProtocolViolation(protocol_violation_report) => {
+            let mut protocol_violation_report_builder =
+                friend_message_builder.reborrow().init_protocol_violation();
+            ser_protocol_violation_report(
+                protocol_violation_report,
+                &mut protocol_violation_report_builder,
+            );
+        }
+        FriendMessage::VerificationProof(verification_proof) => {
+            let mut verification_proof_builder =
+                friend_message_builder.reborrow().init_verification_proof();
+            ser_verification_proof(verification_proof, &mut verification_proof_builder);
+        }
+        FriendMessage::Goodbye(goodbye) => {
+            let mut goodbye_builder = friend_message_builder.reborrow().init_goodbye();
+            ser_goodbye(goodbye, &mut goodbye_builder);
+        }
     };
 }
 
@@ -267,8 +317,14 @@ pub fn serialize_friend_message(friend_message: &FriendMessage) -> Vec<u8> {
 pub fn deser_friends_route(
     friends_route_reader: &funder_capnp::friends_route::Reader,
 ) -> Result<FriendsRoute, SerializeError> {
+    let public_keys_reader = friends_route_reader.get_public_keys()?;
+    // Reject long routes before reading them:
+    if public_keys_reader.len() as usize > MAX_ROUTE_LEN {
+        return Err(SerializeError::RouteTooLong);
+    }
+
     let mut public_keys = Vec::new();
-    for public_key_reader in friends_route_reader.get_public_keys()? {
+    for public_key_reader in public_keys_reader {
         public_keys.push(read_public_key(&public_key_reader)?);
     }
 
@@ -304,6 +360,7 @@ fn deser_failure_send_funds_op(
         reporting_public_key: read_public_key(
             &failure_send_funds_op_reader.get_reporting_public_key()?,
         )?,
+        reason: FailureReason::from_u16(failure_send_funds_op_reader.get_reason()),
         rand_nonce: read_rand_nonce(&failure_send_funds_op_reader.get_rand_nonce()?)?,
         signature: read_signature(&failure_send_funds_op_reader.get_signature()?)?,
     })
@@ -392,6 +449,36 @@ fn deser_inconsistency_error(
     })
 }
 
+pub fn deser_protocol_violation_report(
+    protocol_violation_report_reader: &funder_capnp::protocol_violation_report::Reader,
+) -> Result<ProtocolViolationReport, SerializeError> {
+    Ok(ProtocolViolationReport {
+        operation_index: protocol_violation_report_reader.get_operation_index(),
+        error_code: OperationErrorCode::from_u16(protocol_violation_report_reader.get_error_code()),
+        new_token: read_signature(&protocol_violation_report_reader.get_new_token()?)?,
+    })
+}
+
+fn deser_verification_proof(
+    verification_proof_reader: &funder_capnp::verification_proof::Reader,
+) -> Result<VerificationProof, SerializeError> {
+    Ok(VerificationProof {
+        signature: read_signature(&verification_proof_reader.get_signature()?)?,
+    })
+}
+
+pub fn deser_goodbye(
+    goodbye_reader: &funder_capnp::goodbye::Reader,
+) -> Result<Goodbye, SerializeError> {
+    let opt_downtime_ticks = match goodbye_reader.get_opt_downtime_ticks().which()? {
+        funder_capnp::goodbye::opt_downtime_ticks::DowntimeTicks(downtime_ticks) => {
+            Some(downtime_ticks)
+        }
+        funder_capnp::goodbye::opt_downtime_ticks::Empty(()) => None,
+    };
+    Ok(Goodbye { opt_downtime_ticks })
+}
+
 fn deser_friend_message(
     friend_message_reader: &funder_capnp::friend_message::Reader,
 ) -> Result<FriendMessage, SerializeError> {
@@ -404,6 +491,17 @@ fn deser_friend_message(
                 &inconsistency_error_reader?,
             )?)
         }
+        funder_capnp::friend_message::ProtocolViolation(protocol_violation_report_reader) => {
+            FriendMessage::ProtocolViolation(deser_protocol_violation_report(
+                &protocol_violation_report_reader?,
+            )?)
+        }
+        funder_capnp::friend_message::VerificationProof(verification_proof_reader) => {
+            FriendMessage::VerificationProof(deser_verification_proof(&verification_proof_reader?)?)
+        }
+        funder_capnp::friend_message::Goodbye(goodbye_reader) => {
+            FriendMessage::Goodbye(deser_goodbye(&goodbye_reader?)?)
+        }
     })
 }
 
@@ -453,6 +551,7 @@ mod tests {
         let failure_send_funds = FailureSendFunds {
             request_id: Uid::from(&[10; UID_LEN]),
             reporting_public_key: PublicKey::from(&[0x11; PUBLIC_KEY_LEN]),
+            reason: FailureReason::PricingRejected,
             rand_nonce: RandValue::from(&[0xbb; RAND_VALUE_LEN]),
             signature: Signature::from(&[3; SIGNATURE_LEN]),
         };
@@ -498,6 +597,24 @@ mod tests {
         FriendMessage::MoveTokenRequest(move_token_request)
     }
 
+    /// Create an example FriendMessage::MoveTokenRequest, containing a request with a route of
+    /// `route_len` nodes.
+    fn create_move_token_request_with_route_len(route_len: usize) -> FriendMessage {
+        let mut friend_message = create_move_token_request();
+        if let FriendMessage::MoveTokenRequest(move_token_request) = &mut friend_message {
+            for operation in &mut move_token_request.friend_move_token.operations {
+                if let FriendTcOp::RequestSendFunds(request_send_funds) = operation {
+                    request_send_funds.route = FriendsRoute {
+                        public_keys: (0..route_len)
+                            .map(|i| PublicKey::from(&[i as u8; PUBLIC_KEY_LEN]))
+                            .collect(),
+                    };
+                }
+            }
+        }
+        friend_message
+    }
+
     /// Create an example FriendMessage::InconsistencyError
     fn create_inconsistency_error() -> FriendMessage {
         let reset_terms = ResetTerms {
@@ -523,4 +640,78 @@ mod tests {
         let friend_message2 = deserialize_friend_message(&ser_buff).unwrap();
         assert_eq!(friend_message, friend_message2);
     }
+
+    #[test]
+    fn test_serialize_friend_message_protocol_violation() {
+        let protocol_violation_report = ProtocolViolationReport {
+            operation_index: 2,
+            error_code: OperationErrorCode::RequestsAlreadyDisabled,
+            new_token: Signature::from(&[4; SIGNATURE_LEN]),
+        };
+        let friend_message = FriendMessage::ProtocolViolation(protocol_violation_report);
+        let ser_buff = serialize_friend_message(&friend_message);
+        let friend_message2 = deserialize_friend_message(&ser_buff).unwrap();
+        assert_eq!(friend_message, friend_message2);
+    }
+
+    #[test]
+    fn test_serialize_friend_message_verification_proof() {
+        let verification_proof = VerificationProof {
+            signature: Signature::from(&[5; SIGNATURE_LEN]),
+        };
+        let friend_message = FriendMessage::VerificationProof(verification_proof);
+        let ser_buff = serialize_friend_message(&friend_message);
+        let friend_message2 = deserialize_friend_message(&ser_buff).unwrap();
+        assert_eq!(friend_message, friend_message2);
+    }
+
+    #[test]
+    fn test_serialize_friend_message_goodbye() {
+        for &opt_downtime_ticks in &[Some(0x100), None] {
+            let friend_message = FriendMessage::Goodbye(Goodbye { opt_downtime_ticks });
+            let ser_buff = serialize_friend_message(&friend_message);
+            let friend_message2 = deserialize_friend_message(&ser_buff).unwrap();
+            assert_eq!(friend_message, friend_message2);
+        }
+    }
+
+    #[test]
+    fn test_operation_error_code_u16() {
+        for code in 0..0x20u16 {
+            assert_eq!(OperationErrorCode::from_u16(code).to_u16(), code);
+        }
+        // Codes we do not know are kept as they are:
+        assert_eq!(
+            OperationErrorCode::from_u16(0x1234),
+            OperationErrorCode::Unknown(0x1234)
+        );
+    }
+
+    #[test]
+    fn test_failure_reason_u16() {
+        for code in 0..0x20u16 {
+            assert_eq!(FailureReason::from_u16(code).to_u16(), code);
+        }
+        assert_eq!(FailureReason::from_u16(0), FailureReason::Unspecified);
+        assert_eq!(
+            FailureReason::from_u16(0x1234),
+            FailureReason::Unknown(0x1234)
+        );
+    }
+
+    #[test]
+    fn test_deserialize_friend_message_max_route_len() {
+        let friend_message = create_move_token_request_with_route_len(MAX_ROUTE_LEN);
+        let ser_buff = serialize_friend_message(&friend_message);
+        let friend_message2 = deserialize_friend_message(&ser_buff).unwrap();
+        assert_eq!(friend_message, friend_message2);
+
+        // A route longer than MAX_ROUTE_LEN is rejected:
+        let friend_message = create_move_token_request_with_route_len(MAX_ROUTE_LEN + 1);
+        let ser_buff = serialize_friend_message(&friend_message);
+        match deserialize_friend_message(&ser_buff) {
+            Err(SerializeError::RouteTooLong) => {}
+            _ => unreachable!(),
+        };
+    }
 }
@@ -5,10 +5,13 @@ use crypto::identity::{verify_signature, PublicKey};
 use common::canonical_serialize::CanonicalSerialize;
 use common::int_convert::usize_to_u64;
 
-use super::messages::{FailureSendFunds, MoveToken, PendingRequest, Receipt, ResponseSendFunds};
+use super::messages::{
+    FailureSendFunds, MoveToken, PendingRequest, Receipt, ResponseSendFunds, VerificationProof,
+};
 
 pub const FUND_SUCCESS_PREFIX: &[u8] = b"FUND_SUCCESS";
 pub const FUND_FAILURE_PREFIX: &[u8] = b"FUND_FAILURE";
+pub const FRIEND_VERIFY_PREFIX: &[u8] = b"FRIEND_VERIFY";
 
 /// Create the buffer we sign over at the Response funds.
 /// Note that the signature is not just over the Response funds bytes. The signed buffer also
@@ -35,6 +38,26 @@ pub fn create_response_signature_buffer<S>(
     sbuffer
 }
 
+/// Verify a response signature.
+/// A response is signed by the destination node of the original request.
+pub fn verify_response_signature(
+    response_send_funds: &ResponseSendFunds,
+    pending_request: &PendingRequest,
+) -> bool {
+    let response_signature_buffer =
+        create_response_signature_buffer(response_send_funds, pending_request);
+    let dest_public_key = match pending_request.route.public_keys.last() {
+        Some(dest_public_key) => dest_public_key,
+        None => return false,
+    };
+
+    verify_signature(
+        &response_signature_buffer,
+        dest_public_key,
+        &response_send_funds.signature,
+    )
+}
+
 // TODO: How to keep in sync with verify_receipt and prepare receipt?
 // TODO: Add tests for synchronization between those functions? Possibly share code?
 /// Create the buffer we sign over at the Failure funds.
@@ -55,6 +78,9 @@ pub fn create_failure_signature_buffer<S>(
         .unwrap();
     sbuffer.extend_from_slice(&pending_request.invoice_id);
     sbuffer.extend_from_slice(&failure_send_funds.reporting_public_key);
+    sbuffer
+        .write_u16::<BigEndian>(failure_send_funds.reason.to_u16())
+        .unwrap();
     sbuffer.extend_from_slice(&failure_send_funds.rand_nonce);
 
     sbuffer
@@ -68,8 +94,8 @@ pub fn verify_failure_signature(
     let failure_signature_buffer =
         create_failure_signature_buffer(&failure_send_funds, &pending_request);
     let reporting_public_key = &failure_send_funds.reporting_public_key;
-    // Make sure that the reporting_public_key is on the route:
-    // TODO: Should we check that it is after us? Is it checked somewhere else?
+    // Make sure that the reporting_public_key is on the route.
+    // The caller should also check that the reporting node appears after us on the route.
     let _ = pending_request.route.pk_to_index(&reporting_public_key)?;
 
     if !verify_signature(
@@ -165,21 +191,11 @@ where
     sig_buffer.extend_from_slice(&prefix_hash(move_token));
     sig_buffer.extend_from_slice(&move_token.local_public_key);
     sig_buffer.extend_from_slice(&move_token.remote_public_key);
-    sig_buffer
-        .write_u64::<BigEndian>(move_token.inconsistency_counter)
-        .unwrap();
-    sig_buffer
-        .write_u128::<BigEndian>(move_token.move_token_counter)
-        .unwrap();
-    sig_buffer
-        .write_i128::<BigEndian>(move_token.balance)
-        .unwrap();
-    sig_buffer
-        .write_u128::<BigEndian>(move_token.local_pending_debt)
-        .unwrap();
-    sig_buffer
-        .write_u128::<BigEndian>(move_token.remote_pending_debt)
-        .unwrap();
+    sig_buffer.extend_from_slice(&move_token.inconsistency_counter.canonical_serialize());
+    sig_buffer.extend_from_slice(&move_token.move_token_counter.canonical_serialize());
+    sig_buffer.extend_from_slice(&move_token.balance.canonical_serialize());
+    sig_buffer.extend_from_slice(&move_token.local_pending_debt.canonical_serialize());
+    sig_buffer.extend_from_slice(&move_token.remote_pending_debt.canonical_serialize());
     sig_buffer.extend_from_slice(&move_token.rand_nonce);
 
     sig_buffer
@@ -194,4 +210,33 @@ where
     verify_signature(&sig_buffer, public_key, &move_token.new_token)
 }
 
+/// Create the buffer a node signs over to prove to a friend that it knows the verification phrase.
+/// The public keys are ordered (signer first), so that a proof can not be reflected back to its
+/// signer.
+pub fn create_verification_proof_buffer(
+    signer_public_key: &PublicKey,
+    verifier_public_key: &PublicKey,
+    phrase: &str,
+) -> Vec<u8> {
+    let mut sbuffer = Vec::new();
+
+    sbuffer.extend_from_slice(&hash::sha_512_256(FRIEND_VERIFY_PREFIX));
+    sbuffer.extend_from_slice(signer_public_key);
+    sbuffer.extend_from_slice(verifier_public_key);
+    sbuffer.extend_from_slice(&hash::sha_512_256(phrase.as_bytes()));
+
+    sbuffer
+}
+
+/// Verify a proof sent by a friend against our own verification phrase.
+pub fn verify_verification_proof(
+    verification_proof: &VerificationProof,
+    signer_public_key: &PublicKey,
+    verifier_public_key: &PublicKey,
+    phrase: &str,
+) -> bool {
+    let sbuffer = create_verification_proof_buffer(signer_public_key, verifier_public_key, phrase);
+    verify_signature(&sbuffer, signer_public_key, &verification_proof.signature)
+}
+
 // TODO: How to test this?
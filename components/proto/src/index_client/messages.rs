@@ -3,7 +3,9 @@ use std::collections::HashMap;
 use crypto::identity::PublicKey;
 use crypto::uid::Uid;
 
-pub use crate::index_server::messages::{IndexMutation, RequestRoutes, UpdateFriend};
+pub use crate::index_server::messages::{
+    IndexMutation, RequestRoutes, RouteDisjointness, UpdateFriend,
+};
 use crate::index_server::messages::{NamedIndexServerAddress, RouteWithCapacity};
 
 #[derive(Debug, Clone)]
@@ -15,13 +17,16 @@ pub struct IndexClientState {
 // IndexClient <--> AppServer communication
 // ---------------------------------------------------
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 /// ISA stands for Index Server Address
 pub struct IndexClientReport<ISA> {
     /// A list of trusted index servers.
     pub index_servers: Vec<NamedIndexServerAddress<ISA>>,
     /// The server we are currently connected to (None if not connected).
     pub opt_connected_server: Option<PublicKey>,
+    /// Amount of friend capacities corrected after an index server was found to hold outdated
+    /// values. A growing value indicates that updates are lost.
+    pub num_corrections: u64,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -36,6 +41,7 @@ pub enum IndexClientReportMutation<ISA> {
     AddIndexServer(NamedIndexServerAddress<ISA>),
     RemoveIndexServer(PublicKey),
     SetConnectedServer(Option<PublicKey>),
+    SetNumCorrections(u64),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -98,6 +104,9 @@ where
             IndexClientReportMutation::SetConnectedServer(opt_public_key) => {
                 self.opt_connected_server = opt_public_key.clone();
             }
+            IndexClientReportMutation::SetNumCorrections(num_corrections) => {
+                self.num_corrections = *num_corrections;
+            }
         }
     }
 }
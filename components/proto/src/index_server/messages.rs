@@ -3,9 +3,21 @@ use crypto::hash::HashResult;
 use crypto::identity::{PublicKey, Signature};
 use crypto::uid::Uid;
 
+use crate::app_server::messages::RelayAddress;
 use crate::funder::messages::FriendsRoute;
 use crate::net::messages::NetAddress;
 
+/// Disjointness requirement between multiple returned routes.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum RouteDisjointness {
+    /// Only the best route is returned.
+    None,
+    /// Up to the given amount of routes, not sharing any intermediate node.
+    NodeDisjoint(u16),
+    /// Up to the given amount of routes, not sharing any directed edge.
+    EdgeDisjoint(u16),
+}
+
 /// IndexClient -> IndexServer
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct RequestRoutes {
@@ -18,12 +30,22 @@ pub struct RequestRoutes {
     /// This directed edge must not show up in the route.
     /// Useful for finding non trivial directed loops.
     pub opt_exclude: Option<(PublicKey, PublicKey)>,
+    /// Request multiple disjoint routes, for resilience.
+    /// Note that index servers that are not aware of this field will treat it as `None`.
+    pub disjointness: RouteDisjointness,
+    /// If no route has the wanted capacity, return the best available routes instead (Disjoint
+    /// according to `disjointness`), so that the payment could be split between them.
+    /// Note that index servers that are not aware of this field will treat it as `false`.
+    pub allow_partial: bool,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct RouteWithCapacity {
     pub route: FriendsRoute,
     pub capacity: u128,
+    /// Does the route have the requested capacity?
+    /// Only false for partial routes, returned if `allow_partial` was requested.
+    pub meets_request: bool,
 }
 
 /// IndexServer -> IndexClient
@@ -98,6 +120,9 @@ pub struct ForwardMutationsUpdate {
 pub enum IndexServerToClient {
     TimeHash(HashResult),
     ResponseRoutes(ResponseRoutes),
+    /// Some of the client's mutations were lost on their way to one of the servers.
+    /// The client should send the full state of its friends.
+    RequestFullState,
 }
 
 #[derive(Debug)]
@@ -110,6 +135,10 @@ pub enum IndexClientToServer {
 pub enum IndexServerToServer {
     TimeHash(HashResult),
     ForwardMutationsUpdate(ForwardMutationsUpdate),
+    /// Request the full state of the friends of a node, after a gap in the counters of the node's
+    /// mutations was detected. Forwarded back along the path the mutations of the node arrived
+    /// from, until it reaches the server the node is connected to.
+    RequestFullState(PublicKey),
 }
 
 // ----------------------------------------------
@@ -128,6 +157,15 @@ pub struct IndexServerAddress<ISA = NetAddress> {
     pub address: ISA,
 }
 
+/// The address used by an index server to connect to a trusted index server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FederationAddress<B = NetAddress> {
+    /// The trusted index server listens on this address.
+    Direct(B),
+    /// The trusted index server listens through this relay.
+    Relay(RelayAddress<B>),
+}
+
 impl<ISA> From<NamedIndexServerAddress<ISA>> for IndexServerAddress<ISA> {
     fn from(from: NamedIndexServerAddress<ISA>) -> Self {
         IndexServerAddress {
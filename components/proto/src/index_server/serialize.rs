@@ -11,8 +11,8 @@ use index_capnp;
 
 use super::messages::{
     ForwardMutationsUpdate, IndexClientToServer, IndexMutation, IndexServerToClient,
-    IndexServerToServer, MutationsUpdate, RequestRoutes, ResponseRoutes, RouteWithCapacity,
-    TimeProofLink, UpdateFriend,
+    IndexServerToServer, MutationsUpdate, RequestRoutes, ResponseRoutes, RouteDisjointness,
+    RouteWithCapacity, TimeProofLink, UpdateFriend,
 };
 
 use crate::funder::serialize::{deser_friends_route, ser_friends_route};
@@ -57,6 +57,18 @@ pub fn ser_request_routes(
             opt_exclude_builder.set_empty(());
         }
     }
+
+    let mut disjointness_builder = request_routes_builder.reborrow().init_disjointness();
+    match &request_routes.disjointness {
+        RouteDisjointness::None => disjointness_builder.set_none(()),
+        RouteDisjointness::NodeDisjoint(max_routes) => {
+            disjointness_builder.set_node_disjoint(*max_routes)
+        }
+        RouteDisjointness::EdgeDisjoint(max_routes) => {
+            disjointness_builder.set_edge_disjoint(*max_routes)
+        }
+    }
+    request_routes_builder.set_allow_partial(request_routes.allow_partial);
 }
 
 pub fn deser_request_routes(
@@ -72,12 +84,24 @@ pub fn deser_request_routes(
         index_capnp::request_routes::opt_exclude::Empty(()) => None,
     };
 
+    let disjointness = match request_routes_reader.get_disjointness().which()? {
+        index_capnp::request_routes::disjointness::None(()) => RouteDisjointness::None,
+        index_capnp::request_routes::disjointness::NodeDisjoint(max_routes) => {
+            RouteDisjointness::NodeDisjoint(max_routes)
+        }
+        index_capnp::request_routes::disjointness::EdgeDisjoint(max_routes) => {
+            RouteDisjointness::EdgeDisjoint(max_routes)
+        }
+    };
+
     Ok(RequestRoutes {
         request_id: read_uid(&request_routes_reader.get_request_id()?)?,
         capacity: read_custom_u_int128(&request_routes_reader.get_capacity()?)?,
         source: read_public_key(&request_routes_reader.get_source()?)?,
         destination: read_public_key(&request_routes_reader.get_destination()?)?,
         opt_exclude,
+        disjointness,
+        allow_partial: request_routes_reader.get_allow_partial(),
     })
 }
 
@@ -93,6 +117,7 @@ pub fn ser_route_with_capacity(
         route_with_capacity.capacity,
         &mut route_with_capacity_builder.reborrow().init_capacity(),
     );
+    route_with_capacity_builder.set_meets_request(route_with_capacity.meets_request);
 }
 
 pub fn deser_route_with_capacity(
@@ -101,6 +126,7 @@ pub fn deser_route_with_capacity(
     Ok(RouteWithCapacity {
         route: deser_friends_route(&route_with_capacity_reader.get_route()?)?,
         capacity: read_custom_u_int128(&route_with_capacity_reader.get_capacity()?)?,
+        meets_request: route_with_capacity_reader.get_meets_request(),
     })
 }
 
@@ -338,6 +364,9 @@ fn ser_index_server_to_client(
                 .init_response_routes();
             ser_response_routes(response_routes, &mut response_routes_builder);
         }
+        IndexServerToClient::RequestFullState => {
+            index_server_to_client_builder.set_request_full_state(());
+        }
     }
 }
 
@@ -351,6 +380,9 @@ fn deser_index_server_to_client(
         index_capnp::index_server_to_client::ResponseRoutes(response_routes_reader) => {
             IndexServerToClient::ResponseRoutes(deser_response_routes(&response_routes_reader?)?)
         }
+        index_capnp::index_server_to_client::RequestFullState(()) => {
+            IndexServerToClient::RequestFullState
+        }
     })
 }
 
@@ -405,6 +437,12 @@ fn ser_index_server_to_server(
                 &mut forward_mutations_update_builder,
             );
         }
+        IndexServerToServer::RequestFullState(node_public_key) => {
+            let mut node_public_key_builder = index_server_to_server_builder
+                .reborrow()
+                .init_request_full_state();
+            write_public_key(node_public_key, &mut node_public_key_builder);
+        }
     }
 }
 
@@ -420,6 +458,9 @@ fn deser_index_server_to_server(
         ) => IndexServerToServer::ForwardMutationsUpdate(deser_forward_mutations_update(
             &forward_mutations_update_reader?,
         )?),
+        index_capnp::index_server_to_server::RequestFullState(node_public_key_reader) => {
+            IndexServerToServer::RequestFullState(read_public_key(&node_public_key_reader?)?)
+        }
     })
 }
 
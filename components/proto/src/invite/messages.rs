@@ -0,0 +1,52 @@
+use crypto::identity::{PublicKey, Signature};
+use crypto::uid::Uid;
+
+use crate::app_server::messages::RelayAddress;
+use crate::net::messages::NetAddress;
+
+/// An invitation to become friends with a node, signed by the node.
+/// Shared out of band as a single string (See `friend_invite_to_string()`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FriendInvite<B = NetAddress> {
+    /// Public key of the inviting node
+    pub public_key: PublicKey,
+    /// Relays the inviting node can be reached through
+    pub relays: Vec<RelayAddress<B>>,
+    /// The max debt the inviting node suggests to let it owe
+    pub opt_suggested_max_debt: Option<u128>,
+    /// The invite can not be imported after this time (Seconds since the UNIX epoch)
+    pub expiry_time: u64,
+    /// Signature over the invite, using the identity of the inviting node
+    pub signature: Signature,
+}
+
+/// Add the inviting node of an invite as a friend.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportFriendInvite<B = NetAddress> {
+    pub request_id: Uid,
+    pub invite: FriendInvite<B>,
+    /// Name for the new friend
+    pub name: String,
+    /// Let the new friend owe us up to the max debt suggested by the invite.
+    /// Should be set only after the user confirmed the suggested max debt.
+    pub accept_suggested_max_debt: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFriendInviteResult {
+    /// The friend was added, together with the suggested max debt if it was accepted.
+    Success,
+    /// The invite was not signed by the inviting node. Nothing was changed.
+    InvalidSignature,
+    /// The invite has expired. Nothing was changed.
+    Expired,
+    /// A friend with the same public key already exists (Or is quarantined).
+    /// Nothing was changed.
+    FriendAlreadyExists,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResponseImportFriendInvite {
+    pub request_id: Uid,
+    pub result: ImportFriendInviteResult,
+}
@@ -0,0 +1,89 @@
+use std::io;
+
+use base64::{self, URL_SAFE_NO_PAD};
+
+use capnp;
+use capnp::serialize_packed;
+use common_capnp;
+
+use crate::capnp_common::{read_friend_invite, write_friend_invite};
+use crate::net::messages::NetAddress;
+use crate::serialize::SerializeError;
+
+use super::messages::FriendInvite;
+
+#[derive(Debug)]
+pub enum FriendInviteStringError {
+    /// The string is not valid base64
+    InvalidBase64,
+    SerializeError(SerializeError),
+}
+
+pub fn serialize_friend_invite(invite: &FriendInvite<NetAddress>) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut msg = builder.init_root::<common_capnp::friend_invite::Builder>();
+    write_friend_invite(invite, &mut msg);
+
+    let mut serialized_msg = Vec::new();
+    serialize_packed::write_message(&mut serialized_msg, &builder).unwrap();
+    serialized_msg
+}
+
+pub fn deserialize_friend_invite(data: &[u8]) -> Result<FriendInvite<NetAddress>, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let msg = reader.get_root::<common_capnp::friend_invite::Reader>()?;
+    read_friend_invite(&msg)
+}
+
+/// Encode an invite as a single string, that can be shared out of band.
+pub fn friend_invite_to_string(invite: &FriendInvite<NetAddress>) -> String {
+    base64::encode_config(&serialize_friend_invite(invite), URL_SAFE_NO_PAD)
+}
+
+/// Decode an invite that was encoded using `friend_invite_to_string()`.
+/// The signature of the invite is not verified.
+pub fn string_to_friend_invite(
+    invite_str: &str,
+) -> Result<FriendInvite<NetAddress>, FriendInviteStringError> {
+    let data = base64::decode_config(invite_str.trim(), URL_SAFE_NO_PAD)
+        .map_err(|_| FriendInviteStringError::InvalidBase64)?;
+    deserialize_friend_invite(&data).map_err(FriendInviteStringError::SerializeError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryInto;
+
+    use crypto::identity::{PublicKey, Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN};
+
+    use crate::app_server::messages::RelayAddress;
+
+    #[test]
+    fn test_friend_invite_string() {
+        let mut invite = FriendInvite {
+            public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+            relays: vec![RelayAddress {
+                public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+                address: "relay.example.com:1337".to_owned().try_into().unwrap(),
+            }],
+            opt_suggested_max_debt: Some(100),
+            expiry_time: 1_000_000,
+            signature: Signature::from(&[0xcc; SIGNATURE_LEN]),
+        };
+        let invite_str = friend_invite_to_string(&invite);
+        assert_eq!(string_to_friend_invite(&invite_str).unwrap(), invite);
+
+        invite.opt_suggested_max_debt = None;
+        let invite_str = friend_invite_to_string(&invite);
+        assert_eq!(string_to_friend_invite(&invite_str).unwrap(), invite);
+
+        match string_to_friend_invite("not an invite!") {
+            Err(FriendInviteStringError::InvalidBase64) => {}
+            _ => unreachable!(),
+        };
+    }
+}
@@ -0,0 +1,103 @@
+use byteorder::{BigEndian, WriteBytesExt};
+
+use common::canonical_serialize::CanonicalSerialize;
+use common::int_convert::usize_to_u64;
+use crypto::hash;
+use crypto::identity::verify_signature;
+
+use super::messages::FriendInvite;
+
+pub const FRIEND_INVITE_PREFIX: &[u8] = b"FRIEND_INVITE";
+
+/// Append `data` to `sbuffer`, prefixed by its length.
+fn extend_with_len(sbuffer: &mut Vec<u8>, data: &[u8]) {
+    sbuffer
+        .write_u64::<BigEndian>(usize_to_u64(data.len()).unwrap())
+        .unwrap();
+    sbuffer.extend_from_slice(data);
+}
+
+/// Create the buffer the inviting node signs over when creating an invite.
+pub fn create_friend_invite_signature_buffer<B>(invite: &FriendInvite<B>) -> Vec<u8>
+where
+    B: CanonicalSerialize,
+{
+    let mut sbuffer = Vec::new();
+    sbuffer.extend_from_slice(&hash::sha_512_256(FRIEND_INVITE_PREFIX));
+    sbuffer.extend_from_slice(&invite.public_key);
+
+    sbuffer
+        .write_u64::<BigEndian>(usize_to_u64(invite.relays.len()).unwrap())
+        .unwrap();
+    for relay_address in &invite.relays {
+        sbuffer.extend_from_slice(&relay_address.public_key);
+        // Addresses are not length prefixed by their canonical serialization:
+        extend_with_len(&mut sbuffer, &relay_address.address.canonical_serialize());
+    }
+
+    sbuffer.extend_from_slice(&invite.opt_suggested_max_debt.canonical_serialize());
+    sbuffer.write_u64::<BigEndian>(invite.expiry_time).unwrap();
+    sbuffer
+}
+
+/// Verify that an invite was signed by the inviting node.
+pub fn verify_friend_invite<B>(invite: &FriendInvite<B>) -> bool
+where
+    B: CanonicalSerialize,
+{
+    let signature_buffer = create_friend_invite_signature_buffer(invite);
+    verify_signature(&signature_buffer, &invite.public_key, &invite.signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryInto;
+
+    use crypto::identity::{
+        generate_pkcs8_key_pair, Identity, PublicKey, Signature, SoftwareEd25519Identity,
+        PUBLIC_KEY_LEN, SIGNATURE_LEN,
+    };
+    use crypto::test_utils::DummyRandom;
+
+    use crate::app_server::messages::RelayAddress;
+    use crate::net::messages::NetAddress;
+
+    #[test]
+    fn test_verify_friend_invite() {
+        let rng = DummyRandom::new(&[1u8]);
+        let pkcs8 = generate_pkcs8_key_pair(&rng);
+        let identity = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
+
+        let mut invite: FriendInvite<NetAddress> = FriendInvite {
+            public_key: identity.get_public_key(),
+            relays: vec![RelayAddress {
+                public_key: PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]),
+                address: "relay.example.com:1337".to_owned().try_into().unwrap(),
+            }],
+            opt_suggested_max_debt: Some(100),
+            expiry_time: 1_000_000,
+            signature: Signature::from(&[0; SIGNATURE_LEN]),
+        };
+        invite.signature = identity.sign(&create_friend_invite_signature_buffer(&invite));
+        assert!(verify_friend_invite(&invite));
+
+        // Every part of the invite is signed:
+        let mut tampered = invite.clone();
+        tampered.public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        assert!(!verify_friend_invite(&tampered));
+
+        let mut tampered = invite.clone();
+        tampered.relays.clear();
+        assert!(!verify_friend_invite(&tampered));
+
+        let mut tampered = invite.clone();
+        tampered.opt_suggested_max_debt = None;
+        assert!(!verify_friend_invite(&tampered));
+
+        let mut tampered = invite.clone();
+        tampered.expiry_time += 1;
+        assert!(!verify_friend_invite(&tampered));
+    }
+}
@@ -1,5 +1,6 @@
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum KaMessage {
-    KeepAlive,
+    /// A keepalive, carrying the tick counter of the sender.
+    KeepAlive(u64),
     Message(Vec<u8>),
 }
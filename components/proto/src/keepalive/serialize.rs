@@ -11,7 +11,7 @@ pub fn serialize_ka_message(ka_message: &KaMessage) -> Vec<u8> {
     let mut msg = builder.init_root::<keepalive_capnp::ka_message::Builder>();
 
     match ka_message {
-        KaMessage::KeepAlive => msg.set_keep_alive(()),
+        KaMessage::KeepAlive(ticks) => msg.set_keep_alive(*ticks),
         KaMessage::Message(message) => msg.set_message(message),
     };
 
@@ -27,7 +27,7 @@ pub fn deserialize_ka_message(data: &[u8]) -> Result<KaMessage, SerializeError>
     let msg = reader.get_root::<keepalive_capnp::ka_message::Reader>()?;
 
     match msg.which() {
-        Ok(keepalive_capnp::ka_message::KeepAlive(())) => Ok(KaMessage::KeepAlive),
+        Ok(keepalive_capnp::ka_message::KeepAlive(ticks)) => Ok(KaMessage::KeepAlive(ticks)),
         Ok(keepalive_capnp::ka_message::Message(opt_message_reader)) => {
             Ok(KaMessage::Message(Vec::from(opt_message_reader?)))
         }
@@ -49,7 +49,7 @@ mod tests {
 
     #[test]
     fn test_basic_serialize_ka_message_keepalive() {
-        let ka_message = KaMessage::KeepAlive;
+        let ka_message = KaMessage::KeepAlive(0x1234);
         let ser_data = serialize_ka_message(&ka_message);
         let ka_message2 = deserialize_ka_message(&ser_data).unwrap();
         assert_eq!(ka_message, ka_message2);
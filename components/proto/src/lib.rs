@@ -18,11 +18,13 @@ extern crate crypto;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate bincode;
 extern crate bytes;
 
 extern crate base64;
 extern crate im;
 extern crate toml;
+extern crate zeroize;
 
 #[cfg(test)]
 extern crate tempfile;
@@ -35,10 +37,12 @@ pub mod macros;
 pub mod app_server;
 pub mod capnp_common;
 pub mod consts;
+pub mod directory;
 pub mod file;
 pub mod funder;
 pub mod index_client;
 pub mod index_server;
+pub mod invite;
 pub mod keepalive;
 pub mod net;
 pub mod node;
@@ -46,6 +50,7 @@ pub mod relay;
 pub mod report;
 pub mod secure_channel;
 pub mod serialize;
+pub mod signed_export;
 
 include_schema!(report_capnp, "report_capnp");
 include_schema!(app_server_capnp, "app_server_capnp");
@@ -55,3 +60,4 @@ include_schema!(relay_capnp, "relay_capnp");
 include_schema!(funder_capnp, "funder_capnp");
 include_schema!(keepalive_capnp, "keepalive_capnp");
 include_schema!(index_capnp, "index_capnp");
+include_schema!(directory_capnp, "directory_capnp");
@@ -7,6 +7,23 @@ pub enum InitConnection {
     Accept(PublicKey),
     // remote side wants to connect to public_key
     Connect(PublicKey),
+    // remote side wants to open multiple connections, multiplexed over this connection
+    ConnectMux,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum MuxOp {
+    /// Open a new channel to the given public key (Sent by the client only)
+    Open(PublicKey),
+    Data(Vec<u8>),
+    Close,
+}
+
+/// A message sent over a multiplexed connection
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MuxMessage {
+    pub channel_id: u32,
+    pub op: MuxOp,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -5,7 +5,7 @@ use std::io;
 
 use relay_capnp;
 
-use super::messages::{IncomingConnection, InitConnection, RejectConnection};
+use super::messages::{IncomingConnection, InitConnection, MuxMessage, MuxOp, RejectConnection};
 
 use crate::serialize::SerializeError;
 
@@ -23,6 +23,7 @@ pub fn serialize_init_connection(init_connection: &InitConnection) -> Vec<u8> {
             let mut connect = msg.init_connect();
             write_public_key(&public_key, &mut connect);
         }
+        InitConnection::ConnectMux => msg.set_connect_mux(()),
     }
 
     let mut serialized_msg = Vec::new();
@@ -46,6 +47,7 @@ pub fn deserialize_init_connection(data: &[u8]) -> Result<InitConnection, Serial
             let public_key = read_public_key(&(public_key?))?;
             Ok(InitConnection::Connect(public_key))
         }
+        Ok(relay_capnp::init_connection::ConnectMux(())) => Ok(InitConnection::ConnectMux),
         Err(e) => Err(SerializeError::NotInSchema(e)),
     }
 }
@@ -92,6 +94,46 @@ pub fn deserialize_incoming_connection(data: &[u8]) -> Result<IncomingConnection
     Ok(IncomingConnection { public_key })
 }
 
+pub fn serialize_mux_message(mux_message: &MuxMessage) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut msg = builder.init_root::<relay_capnp::mux_message::Builder>();
+
+    msg.set_channel_id(mux_message.channel_id);
+    match &mux_message.op {
+        MuxOp::Open(public_key) => {
+            let mut open = msg.init_open();
+            write_public_key(&public_key, &mut open);
+        }
+        MuxOp::Data(data) => msg.set_data(data),
+        MuxOp::Close => msg.set_close(()),
+    }
+
+    let mut serialized_msg = Vec::new();
+    serialize_packed::write_message(&mut serialized_msg, &builder).unwrap();
+    serialized_msg
+}
+
+pub fn deserialize_mux_message(data: &[u8]) -> Result<MuxMessage, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let msg = reader.get_root::<relay_capnp::mux_message::Reader>()?;
+
+    let op = match msg.which() {
+        Ok(relay_capnp::mux_message::Open(public_key)) => {
+            MuxOp::Open(read_public_key(&(public_key?))?)
+        }
+        Ok(relay_capnp::mux_message::Data(data)) => MuxOp::Data(data?.to_vec()),
+        Ok(relay_capnp::mux_message::Close(())) => MuxOp::Close,
+        Err(e) => return Err(SerializeError::NotInSchema(e)),
+    };
+
+    Ok(MuxMessage {
+        channel_id: msg.get_channel_id(),
+        op,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -117,6 +159,30 @@ mod tests {
         let serialized = serialize_init_connection(&msg);
         let msg2 = deserialize_init_connection(&serialized[..]).unwrap();
         assert_eq!(msg, msg2);
+
+        let msg = InitConnection::ConnectMux;
+        let serialized = serialize_init_connection(&msg);
+        let msg2 = deserialize_init_connection(&serialized[..]).unwrap();
+        assert_eq!(msg, msg2);
+    }
+
+    #[test]
+    fn test_serialize_mux_message() {
+        let public_key = PublicKey::try_from(&[0x33u8; PUBLIC_KEY_LEN][..]).unwrap();
+        let ops = vec![
+            MuxOp::Open(public_key),
+            MuxOp::Data(vec![1, 2, 3]),
+            MuxOp::Close,
+        ];
+        for (i, op) in ops.into_iter().enumerate() {
+            let msg = MuxMessage {
+                channel_id: 0x1000 + i as u32,
+                op,
+            };
+            let serialized = serialize_mux_message(&msg);
+            let msg2 = deserialize_mux_message(&serialized[..]).unwrap();
+            assert_eq!(msg, msg2);
+        }
     }
 
     #[test]
@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::fmt::Debug;
 
 use common::mutable_state::MutableState;
-use common::safe_arithmetic::{SafeSignedArithmetic, SafeUnsignedArithmetic};
+use common::safe_arithmetic::SafeUnsignedArithmetic;
 
 use crypto::identity::PublicKey;
 
@@ -10,7 +10,7 @@ use crate::index_client::messages::IndexClientState;
 use crate::index_server::messages::{IndexMutation, UpdateFriend};
 
 use crate::report::messages::{
-    ChannelStatusReport, FriendLivenessReport, FriendReport, FriendStatusReport, FunderReport,
+    ChannelStatusReport, FriendReport, FriendReportMutation, FriendStatusReport, FunderReport,
     FunderReportMutation, RequestsStatusReport,
 };
 
@@ -22,15 +22,16 @@ use crate::report::messages::{
 // crate.
 
 // TODO: Maybe this logic shouldn't be here? Where should we move it to?
-// TODO: Add tests (Mostly for arithmetic stuff here)
 
 /// Calculate send and receive capacities for a given `friend_report`.
-fn calc_friend_capacities<B>(friend_report: &FriendReport<B>) -> (u128, u128)
+/// The capacities of an index private friend are never advertised, so they are zero.
+pub fn calc_friend_capacities<B>(friend_report: &FriendReport<B>) -> (u128, u128)
 where
     B: Clone,
 {
     if friend_report.status == FriendStatusReport::Disabled
-        || friend_report.liveness == FriendLivenessReport::Offline
+        || !friend_report.liveness.is_online()
+        || friend_report.index_private
     {
         return (0, 0);
     }
@@ -46,23 +47,20 @@ where
         0
     } else {
         // local_max_debt + balance - local_pending_debt
-        balance.local_max_debt.saturating_add_signed(
-            balance
-                .balance
-                .checked_sub_unsigned(balance.local_pending_debt)
-                .unwrap(),
-        )
+        balance
+            .local_max_debt
+            .saturating_add_signed(balance.balance)
+            .saturating_sub(balance.local_pending_debt)
     };
 
     let recv_capacity = if tc_report.requests_status.local == RequestsStatusReport::Closed {
         0
     } else {
-        balance.remote_max_debt.saturating_sub_signed(
-            balance
-                .balance
-                .checked_add_unsigned(balance.remote_pending_debt)
-                .unwrap(),
-        )
+        // remote_max_debt - balance - remote_pending_debt
+        balance
+            .remote_max_debt
+            .saturating_sub_signed(balance.balance)
+            .saturating_sub(balance.remote_pending_debt)
     };
 
     (send_capacity, recv_capacity)
@@ -124,13 +122,26 @@ where
     match funder_report_mutation {
         FunderReportMutation::AddRelay(_)
         | FunderReportMutation::RemoveRelay(_)
-        | FunderReportMutation::SetNumReadyReceipts(_) => None,
+        | FunderReportMutation::SetNumReadyReceipts(_)
+        | FunderReportMutation::SetDustThresholds(_)
+        | FunderReportMutation::SetDirectory(_)
+        | FunderReportMutation::SetReliability(_)
+        | FunderReportMutation::RemoveReliability(_)
+        | FunderReportMutation::SetReadOnly(_)
+        | FunderReportMutation::AddLabeledPayment(_)
+        | FunderReportMutation::SetLabeledPaymentResult(_) => None,
         FunderReportMutation::AddFriend(add_friend_report) => {
             create_update_friend(&add_friend_report.friend_public_key)
         }
         FunderReportMutation::RemoveFriend(public_key) => {
             Some(IndexMutation::RemoveFriend(public_key.clone()))
         }
+        // Stop advertising the friend right away.
+        // When the friend stops being private, its capacities are sent again as an UpdateFriend.
+        FunderReportMutation::FriendReportMutation((
+            public_key,
+            FriendReportMutation::SetIndexPrivate(true),
+        )) => Some(IndexMutation::RemoveFriend(public_key.clone())),
         FunderReportMutation::FriendReportMutation((public_key, _friend_report_mutation)) => {
             create_update_friend(&public_key)
         }
@@ -138,3 +149,170 @@ where
 }
 
 // TODO: Add tests.
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use im::hashmap::HashMap as ImHashMap;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    use crate::report::messages::{
+        DirectionReport, FriendDeadlinesReport, FriendLivenessReport, McBalanceReport,
+        McRequestsStatusReport, SentLocalRelaysReport, TcReport, VerificationStatusReport,
+    };
+
+    /// The maximum possible funder debt (See MAX_FUNDER_DEBT in the funder crate).
+    const MAX_DEBT: u128 = (1 << 127) - 1;
+
+    fn create_friend_report(balance: McBalanceReport) -> FriendReport<u32> {
+        let tc_report = TcReport {
+            direction: DirectionReport::Incoming,
+            move_token_counter: 0,
+            inconsistency_counter: 0,
+            balance,
+            requests_status: McRequestsStatusReport {
+                local: RequestsStatusReport::Open,
+                remote: RequestsStatusReport::Open,
+            },
+            num_local_pending_requests: 0,
+            num_remote_pending_requests: 0,
+        };
+
+        FriendReport {
+            name: "friend".to_owned(),
+            remote_relays: Vec::new(),
+            sent_local_relays: SentLocalRelaysReport::NeverSent,
+            opt_last_incoming_move_token: None,
+            liveness: FriendLivenessReport::Online,
+            channel_status: ChannelStatusReport::Consistent(tc_report),
+            wanted_remote_max_debt: 0,
+            wanted_local_requests_status: RequestsStatusReport::Open,
+            num_pending_requests: 0,
+            num_pending_responses: 0,
+            status: FriendStatusReport::Enabled,
+            num_pending_user_requests: 0,
+            pending_payments: Vec::new(),
+            opt_protocol_violation: None,
+            verification_status: VerificationStatusReport::Unverified,
+            deadlines: FriendDeadlinesReport::default(),
+            index_private: false,
+        }
+    }
+
+    #[test]
+    fn test_calc_friend_capacities_basic() {
+        let friend_report = create_friend_report(McBalanceReport {
+            balance: 5,
+            local_max_debt: 100,
+            remote_max_debt: 50,
+            local_pending_debt: 10,
+            remote_pending_debt: 20,
+        });
+        // send: 100 + 5 - 10, recv: 50 - 5 - 20
+        assert_eq!(calc_friend_capacities(&friend_report), (95, 25));
+    }
+
+    #[test]
+    fn test_calc_friend_capacities_extreme() {
+        // The remote side owes us the maximum possible debt:
+        let friend_report = create_friend_report(McBalanceReport {
+            balance: MAX_DEBT as i128,
+            local_max_debt: MAX_DEBT,
+            remote_max_debt: MAX_DEBT,
+            local_pending_debt: 0,
+            remote_pending_debt: 0,
+        });
+        assert_eq!(calc_friend_capacities(&friend_report), (2 * MAX_DEBT, 0));
+
+        // All the remote trust is frozen:
+        let friend_report = create_friend_report(McBalanceReport {
+            balance: 0,
+            local_max_debt: 0,
+            remote_max_debt: MAX_DEBT,
+            local_pending_debt: 0,
+            remote_pending_debt: MAX_DEBT,
+        });
+        assert_eq!(calc_friend_capacities(&friend_report), (0, 0));
+
+        // We owe the maximum possible debt, and even more credits are frozen.
+        // This used to overflow when calculating the balance minus the pending debt:
+        let friend_report = create_friend_report(McBalanceReport {
+            balance: -(MAX_DEBT as i128),
+            local_max_debt: MAX_DEBT,
+            remote_max_debt: MAX_DEBT,
+            local_pending_debt: MAX_DEBT,
+            remote_pending_debt: MAX_DEBT,
+        });
+        assert_eq!(calc_friend_capacities(&friend_report), (0, MAX_DEBT));
+    }
+
+    #[test]
+    fn test_index_private_friend() {
+        let friend_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let mut friend_report = create_friend_report(McBalanceReport {
+            balance: 5,
+            local_max_debt: 100,
+            remote_max_debt: 50,
+            local_pending_debt: 10,
+            remote_pending_debt: 20,
+        });
+        friend_report.index_private = true;
+        assert_eq!(calc_friend_capacities(&friend_report), (0, 0));
+
+        let mut friends = ImHashMap::new();
+        friends.insert(friend_public_key.clone(), friend_report);
+        let mut funder_report = FunderReport {
+            local_public_key: PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]),
+            relays: Default::default(),
+            friends,
+            num_ready_receipts: 0,
+            quarantined_friends: Default::default(),
+            dust_thresholds: Default::default(),
+            directory: Default::default(),
+            reliability: Default::default(),
+            read_only: false,
+            labeled_payments: Default::default(),
+        };
+
+        // A private friend is not a part of the state sent to index servers:
+        let index_client_state = funder_report_to_index_client_state(&funder_report);
+        assert!(index_client_state.friends.is_empty());
+
+        // Changes to a private friend are not sent to index servers:
+        let mutation = FunderReportMutation::FriendReportMutation((
+            friend_public_key.clone(),
+            FriendReportMutation::SetNumPendingRequests(3),
+        ));
+        assert_eq!(
+            funder_report_mutation_to_index_mutation(&funder_report, &mutation),
+            None
+        );
+
+        // The friend stops being private. Its capacities are advertised again:
+        let mutation = FunderReportMutation::FriendReportMutation((
+            friend_public_key.clone(),
+            FriendReportMutation::SetIndexPrivate(false),
+        ));
+        assert_eq!(
+            funder_report_mutation_to_index_mutation(&funder_report, &mutation),
+            Some(IndexMutation::UpdateFriend(UpdateFriend {
+                public_key: friend_public_key.clone(),
+                send_capacity: 95,
+                recv_capacity: 25,
+            }))
+        );
+        funder_report.mutate(&mutation).unwrap();
+
+        // The friend becomes private again. It is removed from index servers right away:
+        let mutation = FunderReportMutation::FriendReportMutation((
+            friend_public_key.clone(),
+            FriendReportMutation::SetIndexPrivate(true),
+        ));
+        assert_eq!(
+            funder_report_mutation_to_index_mutation(&funder_report, &mutation),
+            Some(IndexMutation::RemoveFriend(friend_public_key))
+        );
+    }
+}
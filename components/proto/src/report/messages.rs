@@ -9,10 +9,15 @@ use crypto::identity::{PublicKey, Signature};
 use crypto::uid::Uid;
 
 use crate::app_server::messages::{NamedRelayAddress, RelayAddress};
-use crate::funder::messages::{FriendStatus, RequestsStatus};
+use crate::directory::messages::DirectoryState;
+use crate::funder::messages::{
+    add_labeled_payment, set_labeled_payment_result, DustThresholds, FriendStatus, FriendsRoute,
+    Goodbye, LabeledPayment, ProtocolViolationReport, RequestsStatus, ResponseSendFundsResult,
+    VerificationStatus,
+};
 use crate::net::messages::NetAddress;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MoveTokenHashedReport {
     pub prefix_hash: HashResult,
     pub local_public_key: PublicKey,
@@ -42,6 +47,13 @@ pub enum FriendStatusReport {
     Disabled,
 }
 
+#[derive(Clone, Serialize, Deserialize, Debug, Eq, PartialEq)]
+pub enum VerificationStatusReport {
+    Unverified,
+    PhraseVerified,
+    Failed,
+}
+
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
 pub enum RequestsStatusReport {
     Open,
@@ -71,10 +83,13 @@ pub struct McBalanceReport {
     pub remote_pending_debt: u128,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DirectionReport {
     Incoming,
-    Outgoing,
+    /// token_wanted: Did we ask the remote side to send the token back?
+    Outgoing {
+        token_wanted: bool,
+    },
 }
 
 impl DirectionReport {
@@ -87,7 +102,7 @@ impl DirectionReport {
     }
 
     pub fn is_outgoing(&self) -> bool {
-        if let DirectionReport::Outgoing = self {
+        if let DirectionReport::Outgoing { .. } = self {
             true
         } else {
             false
@@ -95,10 +110,12 @@ impl DirectionReport {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum FriendLivenessReport {
     Online,
     Offline,
+    /// The friend told us it was going offline on purpose, before disconnecting.
+    IntentionallyOffline(Goodbye),
 }
 
 impl FriendLivenessReport {
@@ -111,34 +128,74 @@ impl FriendLivenessReport {
     }
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PendingPaymentStageReport {
+    /// Waiting to be sent to the friend. No credits are frozen yet.
+    Queued,
+    /// Sent to the friend inside a MoveToken. Credits are frozen.
+    Sent,
+}
+
+/// A payment originated by the local node, that was not yet completed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingPaymentReport {
+    pub request_id: Uid,
+    pub route: FriendsRoute,
+    pub dest_payment: u128,
+    /// Amount of credits frozen (Or to be frozen, if the payment is still queued)
+    /// against the first hop of the route.
+    pub frozen_credits: u128,
+    pub stage: PendingPaymentStageReport,
+    /// The tick in which the user queued the payment, counted since the funder has started.
+    /// None if the payment was queued before the funder has started, or is no longer measured.
+    pub opt_queued_tick: Option<u64>,
+    /// The tick in which the payment was first signed into a MoveToken to the first hop.
+    pub opt_signed_tick: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TcReport {
     pub direction: DirectionReport,
+    pub move_token_counter: u128,
+    pub inconsistency_counter: u64,
     pub balance: McBalanceReport,
     pub requests_status: McRequestsStatusReport,
     pub num_local_pending_requests: u64,
     pub num_remote_pending_requests: u64,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ResetTermsReport {
     pub reset_token: Signature,
     pub balance_for_reset: i128,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ChannelInconsistentReport {
     pub local_reset_terms_balance: i128,
     pub opt_remote_reset_terms: Option<ResetTermsReport>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ChannelStatusReport {
     Inconsistent(ChannelInconsistentReport),
     Consistent(TcReport),
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Amounts of ticks until the next scheduled local events related to a friend.
+/// A reported value may differ from the real value by less than the granularity the node was
+/// configured with.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FriendDeadlinesReport {
+    /// Ticks until we try to reconnect to the friend, after it went offline on purpose
+    pub opt_reconnect_ticks: Option<u64>,
+    /// Ticks until the soonest response deadline of a request we have forwarded to the friend
+    pub opt_response_timeout_ticks: Option<u64>,
+    /// Ticks until the expiring remote max debt of the friend expires
+    pub opt_remote_max_debt_expiry_ticks: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FriendReport<B = NetAddress>
 where
     B: Clone,
@@ -159,14 +216,37 @@ where
     pub num_pending_responses: u64,
     // Pending operations to be sent to the token channel.
     pub status: FriendStatusReport,
-    pub num_pending_user_requests: u64,
     // Request that the user has sent to this neighbor,
     // but have not been processed yet. Bounded in size.
+    pub num_pending_user_requests: u64,
+    /// Payments originated locally through this friend that were not completed yet.
+    pub pending_payments: Vec<PendingPaymentReport>,
+    /// The last report the friend has sent us about a move token it rejected.
+    pub opt_protocol_violation: Option<ProtocolViolationReport>,
+    /// Result of verifying the friend using a phrase shared out of band.
+    pub verification_status: VerificationStatusReport,
+    /// Amounts of ticks until the next scheduled local events related to the friend.
+    pub deadlines: FriendDeadlinesReport,
+    /// Are the capacities with the friend hidden from index servers?
+    pub index_private: bool,
+}
+
+/// Empirical reliability of a remote node, measured from the outcomes of payments we have sent
+/// through it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReliabilityReport {
+    /// Estimated probability (In parts per million) that a payment through the node succeeds.
+    pub success_ppm: u32,
+    /// Average amount of ticks until a response arrives, for payments whose first hop is this
+    /// node. None if no such payment has succeeded.
+    pub opt_latency_ticks: Option<u64>,
+    /// Decayed amount of payment outcomes the score is based on.
+    pub samples: u64,
 }
 
 /// A FunderReport is a summary of a FunderState.
 /// It contains the information the Funder exposes to the user apps of the Offst node.
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 // TODO: Removed A: Clone here and ImHashMap. Should this struct be cloneable for some reason?
 pub struct FunderReport<B = NetAddress>
 where
@@ -176,6 +256,21 @@ where
     pub relays: ImVec<NamedRelayAddress<B>>,
     pub friends: ImHashMap<PublicKey, FriendReport<B>>,
     pub num_ready_receipts: u64,
+    /// Friends whose stored state was found corrupted when the node started.
+    /// A quarantined friend has no channel activity.
+    pub quarantined_friends: ImVec<PublicKey>,
+    /// Minimum payments the node is willing to handle.
+    pub dust_thresholds: DustThresholds,
+    /// Directory of relays and index servers the node is subscribed to.
+    /// Relays and index servers in `directory.listing` were added by the directory.
+    pub directory: DirectoryState<B>,
+    /// Reliability of remote nodes (Friends or not) we have sent payments through.
+    pub reliability: ImHashMap<PublicKey, ReliabilityReport>,
+    /// The database can not be written (For example: the disk is full).
+    /// No payments are handled until writing is possible again.
+    pub read_only: bool,
+    /// Recent payments we have sent with a label, oldest first.
+    pub labeled_payments: ImVec<LabeledPayment>,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -196,6 +291,14 @@ where
     SetNumPendingUserRequests(u64),
     SetOptLastIncomingMoveToken(Option<MoveTokenHashedReport>),
     SetLiveness(FriendLivenessReport),
+    SetPendingPayments(Vec<PendingPaymentReport>),
+    SetOptProtocolViolation(Option<ProtocolViolationReport>),
+    SetVerificationStatus(VerificationStatusReport),
+    SetDeadlines(FriendDeadlinesReport),
+    SetIndexPrivate(bool),
+    /// An expiring remote max debt has expired, and the wanted remote max debt was reduced to
+    /// the given value.
+    RemoteMaxDebtExpired(u128),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -220,6 +323,13 @@ where
     RemoveFriend(PublicKey),
     FriendReportMutation((PublicKey, FriendReportMutation<B>)),
     SetNumReadyReceipts(u64),
+    SetDustThresholds(DustThresholds),
+    SetDirectory(DirectoryState<B>),
+    SetReliability((PublicKey, ReliabilityReport)),
+    RemoveReliability(PublicKey),
+    SetReadOnly(bool),
+    AddLabeledPayment(LabeledPayment),
+    SetLabeledPaymentResult((Uid, ResponseSendFundsResult)),
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -237,6 +347,16 @@ impl From<&FriendStatus> for FriendStatusReport {
     }
 }
 
+impl From<&VerificationStatus> for VerificationStatusReport {
+    fn from(verification_status: &VerificationStatus) -> VerificationStatusReport {
+        match verification_status {
+            VerificationStatus::Unverified => VerificationStatusReport::Unverified,
+            VerificationStatus::PhraseVerified => VerificationStatusReport::PhraseVerified,
+            VerificationStatus::Failed => VerificationStatusReport::Failed,
+        }
+    }
+}
+
 impl From<&RequestsStatus> for RequestsStatusReport {
     fn from(requests_status: &RequestsStatus) -> RequestsStatusReport {
         match requests_status {
@@ -297,6 +417,24 @@ where
             FriendReportMutation::SetLiveness(friend_liveness_report) => {
                 self.liveness = friend_liveness_report.clone();
             }
+            FriendReportMutation::SetPendingPayments(pending_payments) => {
+                self.pending_payments = pending_payments.clone();
+            }
+            FriendReportMutation::SetOptProtocolViolation(opt_protocol_violation) => {
+                self.opt_protocol_violation = opt_protocol_violation.clone();
+            }
+            FriendReportMutation::SetVerificationStatus(verification_status) => {
+                self.verification_status = verification_status.clone();
+            }
+            FriendReportMutation::SetDeadlines(deadlines) => {
+                self.deadlines = deadlines.clone();
+            }
+            FriendReportMutation::SetIndexPrivate(index_private) => {
+                self.index_private = *index_private;
+            }
+            FriendReportMutation::RemoteMaxDebtExpired(wanted_remote_max_debt) => {
+                self.wanted_remote_max_debt = *wanted_remote_max_debt;
+            }
         };
         Ok(())
     }
@@ -344,6 +482,11 @@ where
                     num_pending_requests: 0,
                     status: FriendStatusReport::from(&FriendStatus::Disabled),
                     num_pending_user_requests: 0,
+                    pending_payments: Vec::new(),
+                    opt_protocol_violation: None,
+                    verification_status: VerificationStatusReport::Unverified,
+                    deadlines: FriendDeadlinesReport::default(),
+                    index_private: false,
                 };
                 if self
                     .friends
@@ -377,6 +520,35 @@ where
                 self.num_ready_receipts = *num_ready_receipts;
                 Ok(())
             }
+            FunderReportMutation::SetDustThresholds(dust_thresholds) => {
+                self.dust_thresholds = dust_thresholds.clone();
+                Ok(())
+            }
+            FunderReportMutation::SetDirectory(directory) => {
+                self.directory = directory.clone();
+                Ok(())
+            }
+            FunderReportMutation::SetReliability((public_key, reliability_report)) => {
+                self.reliability
+                    .insert(public_key.clone(), reliability_report.clone());
+                Ok(())
+            }
+            FunderReportMutation::RemoveReliability(public_key) => {
+                let _ = self.reliability.remove(public_key);
+                Ok(())
+            }
+            FunderReportMutation::SetReadOnly(read_only) => {
+                self.read_only = *read_only;
+                Ok(())
+            }
+            FunderReportMutation::AddLabeledPayment(labeled_payment) => {
+                add_labeled_payment(&mut self.labeled_payments, labeled_payment);
+                Ok(())
+            }
+            FunderReportMutation::SetLabeledPaymentResult((request_id, result)) => {
+                set_labeled_payment_result(&mut self.labeled_payments, request_id, result);
+                Ok(())
+            }
         }
     }
 }
@@ -1,21 +1,35 @@
+use std::io;
+
 use im::hashmap::HashMap as ImHashMap;
 use im::vector::Vector as ImVec;
 
 use crate::capnp_common::{
-    read_custom_int128, read_custom_u_int128, read_hash, read_named_index_server_address,
-    read_named_relay_address, read_public_key, read_rand_nonce, read_relay_address, read_signature,
-    write_custom_int128, write_custom_u_int128, write_hash, write_named_index_server_address,
-    write_named_relay_address, write_public_key, write_rand_nonce, write_relay_address,
-    write_signature,
+    read_custom_int128, read_custom_u_int128, read_directory_state, read_dust_thresholds,
+    read_hash, read_named_index_server_address, read_named_relay_address, read_public_key,
+    read_rand_nonce, read_receipt, read_relay_address, read_signature, read_uid,
+    write_custom_int128, write_custom_u_int128, write_directory_state, write_dust_thresholds,
+    write_hash, write_named_index_server_address, write_named_relay_address, write_public_key,
+    write_rand_nonce, write_receipt, write_relay_address, write_signature, write_uid,
 };
+use capnp::serialize_packed;
 use common::int_convert::usize_to_u32;
 use crypto::identity::PublicKey;
+use crypto::uid::Uid;
 
+use crate::funder::messages::{
+    FailureReason, LabeledPayment, ProtocolViolationReport, ResponseSendFundsResult,
+};
+use crate::funder::serialize::{
+    deser_friends_route, deser_goodbye, deser_protocol_violation_report, ser_friends_route,
+    ser_goodbye, ser_protocol_violation_report,
+};
 use crate::report::messages::{
     AddFriendReport, ChannelInconsistentReport, ChannelStatusReport, DirectionReport,
-    FriendLivenessReport, FriendReport, FriendReportMutation, FriendStatusReport, FunderReport,
-    FunderReportMutation, McBalanceReport, McRequestsStatusReport, MoveTokenHashedReport,
-    RequestsStatusReport, ResetTermsReport, SentLocalRelaysReport, TcReport,
+    FriendDeadlinesReport, FriendLivenessReport, FriendReport, FriendReportMutation,
+    FriendStatusReport, FunderReport, FunderReportMutation, McBalanceReport,
+    McRequestsStatusReport, MoveTokenHashedReport, PendingPaymentReport, PendingPaymentStageReport,
+    ReliabilityReport, RequestsStatusReport, ResetTermsReport, SentLocalRelaysReport, TcReport,
+    VerificationStatusReport,
 };
 use crate::serialize::SerializeError;
 use report_capnp;
@@ -139,6 +153,35 @@ fn deser_friend_status_report(
     })
 }
 
+fn ser_verification_status_report(
+    verification_status_report: &VerificationStatusReport,
+    verification_status_report_builder: &mut report_capnp::verification_status_report::Builder,
+) {
+    match verification_status_report {
+        VerificationStatusReport::Unverified => {
+            verification_status_report_builder.set_unverified(())
+        }
+        VerificationStatusReport::PhraseVerified => {
+            verification_status_report_builder.set_phrase_verified(())
+        }
+        VerificationStatusReport::Failed => verification_status_report_builder.set_failed(()),
+    }
+}
+
+fn deser_verification_status_report(
+    verification_status_report_reader: &report_capnp::verification_status_report::Reader,
+) -> Result<VerificationStatusReport, SerializeError> {
+    Ok(match verification_status_report_reader.which()? {
+        report_capnp::verification_status_report::Unverified(()) => {
+            VerificationStatusReport::Unverified
+        }
+        report_capnp::verification_status_report::PhraseVerified(()) => {
+            VerificationStatusReport::PhraseVerified
+        }
+        report_capnp::verification_status_report::Failed(()) => VerificationStatusReport::Failed,
+    })
+}
+
 fn ser_requests_status_report(
     requests_status_report: &RequestsStatusReport,
     requests_status_report_builder: &mut report_capnp::requests_status_report::Builder,
@@ -165,6 +208,12 @@ fn ser_friend_liveness_report(
     match friend_liveness_report {
         FriendLivenessReport::Offline => friend_liveness_report_builder.set_offline(()),
         FriendLivenessReport::Online => friend_liveness_report_builder.set_online(()),
+        FriendLivenessReport::IntentionallyOffline(goodbye) => {
+            let mut goodbye_builder = friend_liveness_report_builder
+                .reborrow()
+                .init_intentionally_offline();
+            ser_goodbye(goodbye, &mut goodbye_builder);
+        }
     }
 }
 
@@ -174,6 +223,104 @@ fn deser_friend_liveness_report(
     Ok(match friend_liveness_report_reader.which()? {
         report_capnp::friend_liveness_report::Offline(()) => FriendLivenessReport::Offline,
         report_capnp::friend_liveness_report::Online(()) => FriendLivenessReport::Online,
+        report_capnp::friend_liveness_report::IntentionallyOffline(goodbye_reader) => {
+            FriendLivenessReport::IntentionallyOffline(deser_goodbye(&goodbye_reader?)?)
+        }
+    })
+}
+
+fn ser_pending_payment_stage_report(
+    pending_payment_stage_report: &PendingPaymentStageReport,
+    pending_payment_stage_report_builder: &mut report_capnp::pending_payment_stage_report::Builder,
+) {
+    match pending_payment_stage_report {
+        PendingPaymentStageReport::Queued => pending_payment_stage_report_builder.set_queued(()),
+        PendingPaymentStageReport::Sent => pending_payment_stage_report_builder.set_sent(()),
+    }
+}
+
+fn deser_pending_payment_stage_report(
+    pending_payment_stage_report_reader: &report_capnp::pending_payment_stage_report::Reader,
+) -> Result<PendingPaymentStageReport, SerializeError> {
+    Ok(match pending_payment_stage_report_reader.which()? {
+        report_capnp::pending_payment_stage_report::Queued(()) => PendingPaymentStageReport::Queued,
+        report_capnp::pending_payment_stage_report::Sent(()) => PendingPaymentStageReport::Sent,
+    })
+}
+
+fn ser_pending_payment_report(
+    pending_payment_report: &PendingPaymentReport,
+    pending_payment_report_builder: &mut report_capnp::pending_payment_report::Builder,
+) {
+    write_uid(
+        &pending_payment_report.request_id,
+        &mut pending_payment_report_builder.reborrow().init_request_id(),
+    );
+    ser_friends_route(
+        &pending_payment_report.route,
+        &mut pending_payment_report_builder.reborrow().init_route(),
+    );
+    write_custom_u_int128(
+        pending_payment_report.dest_payment,
+        &mut pending_payment_report_builder
+            .reborrow()
+            .init_dest_payment(),
+    );
+    write_custom_u_int128(
+        pending_payment_report.frozen_credits,
+        &mut pending_payment_report_builder
+            .reborrow()
+            .init_frozen_credits(),
+    );
+    ser_pending_payment_stage_report(
+        &pending_payment_report.stage,
+        &mut pending_payment_report_builder.reborrow().init_stage(),
+    );
+
+    let mut opt_queued_tick_builder = pending_payment_report_builder
+        .reborrow()
+        .init_opt_queued_tick();
+    match pending_payment_report.opt_queued_tick {
+        Some(tick) => opt_queued_tick_builder.set_tick(tick),
+        None => opt_queued_tick_builder.set_empty(()),
+    };
+
+    let mut opt_signed_tick_builder = pending_payment_report_builder
+        .reborrow()
+        .init_opt_signed_tick();
+    match pending_payment_report.opt_signed_tick {
+        Some(tick) => opt_signed_tick_builder.set_tick(tick),
+        None => opt_signed_tick_builder.set_empty(()),
+    };
+}
+
+fn deser_pending_payment_report(
+    pending_payment_report_reader: &report_capnp::pending_payment_report::Reader,
+) -> Result<PendingPaymentReport, SerializeError> {
+    let opt_queued_tick = match pending_payment_report_reader
+        .get_opt_queued_tick()
+        .which()?
+    {
+        report_capnp::pending_payment_report::opt_queued_tick::Tick(tick) => Some(tick),
+        report_capnp::pending_payment_report::opt_queued_tick::Empty(()) => None,
+    };
+
+    let opt_signed_tick = match pending_payment_report_reader
+        .get_opt_signed_tick()
+        .which()?
+    {
+        report_capnp::pending_payment_report::opt_signed_tick::Tick(tick) => Some(tick),
+        report_capnp::pending_payment_report::opt_signed_tick::Empty(()) => None,
+    };
+
+    Ok(PendingPaymentReport {
+        request_id: read_uid(&pending_payment_report_reader.get_request_id()?)?,
+        route: deser_friends_route(&pending_payment_report_reader.get_route()?)?,
+        dest_payment: read_custom_u_int128(&pending_payment_report_reader.get_dest_payment()?)?,
+        frozen_credits: read_custom_u_int128(&pending_payment_report_reader.get_frozen_credits()?)?,
+        stage: deser_pending_payment_stage_report(&pending_payment_report_reader.get_stage()?)?,
+        opt_queued_tick,
+        opt_signed_tick,
     })
 }
 
@@ -183,7 +330,10 @@ fn ser_direction_report(
 ) {
     match direction_report {
         DirectionReport::Incoming => direction_report_builder.set_incoming(()),
-        DirectionReport::Outgoing => direction_report_builder.set_outgoing(()),
+        DirectionReport::Outgoing { token_wanted } => {
+            direction_report_builder.set_outgoing(());
+            direction_report_builder.set_token_wanted(*token_wanted);
+        }
     }
 }
 
@@ -192,7 +342,9 @@ fn deser_direction_report(
 ) -> Result<DirectionReport, SerializeError> {
     Ok(match direction_report_reader.which()? {
         report_capnp::direction_report::Incoming(()) => DirectionReport::Incoming,
-        report_capnp::direction_report::Outgoing(()) => DirectionReport::Outgoing,
+        report_capnp::direction_report::Outgoing(()) => DirectionReport::Outgoing {
+            token_wanted: direction_report_reader.get_token_wanted(),
+        },
     })
 }
 
@@ -292,6 +444,14 @@ fn ser_tc_report(tc_report: &TcReport, tc_report_builder: &mut report_capnp::tc_
     tc_report_builder
         .reborrow()
         .set_num_remote_pending_requests(tc_report.num_remote_pending_requests);
+
+    write_custom_u_int128(
+        tc_report.move_token_counter,
+        &mut tc_report_builder.reborrow().init_move_token_counter(),
+    );
+    tc_report_builder
+        .reborrow()
+        .set_inconsistency_counter(tc_report.inconsistency_counter);
 }
 
 fn deser_tc_report(
@@ -303,6 +463,8 @@ fn deser_tc_report(
         requests_status: deser_mc_requests_status_report(&tc_report_reader.get_requests_status()?)?,
         num_local_pending_requests: tc_report_reader.get_num_local_pending_requests(),
         num_remote_pending_requests: tc_report_reader.get_num_remote_pending_requests(),
+        move_token_counter: read_custom_u_int128(&tc_report_reader.get_move_token_counter()?)?,
+        inconsistency_counter: tc_report_reader.get_inconsistency_counter(),
     })
 }
 
@@ -441,6 +603,37 @@ fn deser_opt_last_incoming_move_token(
     })
 }
 
+fn ser_opt_protocol_violation(
+    opt_protocol_violation: &Option<ProtocolViolationReport>,
+    opt_protocol_violation_builder: &mut report_capnp::opt_protocol_violation::Builder,
+) {
+    match opt_protocol_violation {
+        Some(protocol_violation_report) => {
+            let mut protocol_violation_report_builder = opt_protocol_violation_builder
+                .reborrow()
+                .init_protocol_violation();
+            ser_protocol_violation_report(
+                protocol_violation_report,
+                &mut protocol_violation_report_builder,
+            );
+        }
+        None => {
+            opt_protocol_violation_builder.set_empty(());
+        }
+    };
+}
+
+fn deser_opt_protocol_violation(
+    opt_protocol_violation_reader: &report_capnp::opt_protocol_violation::Reader,
+) -> Result<Option<ProtocolViolationReport>, SerializeError> {
+    Ok(match opt_protocol_violation_reader.which()? {
+        report_capnp::opt_protocol_violation::ProtocolViolation(protocol_violation_reader) => Some(
+            deser_protocol_violation_report(&protocol_violation_reader?)?,
+        ),
+        report_capnp::opt_protocol_violation::Empty(()) => None,
+    })
+}
+
 fn ser_relays_transition(
     relays_transition: &(
         ImVec<NamedRelayAddress<NetAddress>>,
@@ -540,6 +733,73 @@ fn deser_sent_local_relays_report(
     })
 }
 
+fn ser_friend_deadlines_report(
+    friend_deadlines_report: &FriendDeadlinesReport,
+    friend_deadlines_report_builder: &mut report_capnp::friend_deadlines_report::Builder,
+) {
+    let mut opt_reconnect_ticks_builder = friend_deadlines_report_builder
+        .reborrow()
+        .init_opt_reconnect_ticks();
+    match friend_deadlines_report.opt_reconnect_ticks {
+        Some(ticks) => opt_reconnect_ticks_builder.set_ticks(ticks),
+        None => opt_reconnect_ticks_builder.set_empty(()),
+    };
+
+    let mut opt_response_timeout_ticks_builder = friend_deadlines_report_builder
+        .reborrow()
+        .init_opt_response_timeout_ticks();
+    match friend_deadlines_report.opt_response_timeout_ticks {
+        Some(ticks) => opt_response_timeout_ticks_builder.set_ticks(ticks),
+        None => opt_response_timeout_ticks_builder.set_empty(()),
+    };
+
+    let mut opt_remote_max_debt_expiry_ticks_builder = friend_deadlines_report_builder
+        .reborrow()
+        .init_opt_remote_max_debt_expiry_ticks();
+    match friend_deadlines_report.opt_remote_max_debt_expiry_ticks {
+        Some(ticks) => opt_remote_max_debt_expiry_ticks_builder.set_ticks(ticks),
+        None => opt_remote_max_debt_expiry_ticks_builder.set_empty(()),
+    };
+}
+
+fn deser_friend_deadlines_report(
+    friend_deadlines_report_reader: &report_capnp::friend_deadlines_report::Reader,
+) -> Result<FriendDeadlinesReport, SerializeError> {
+    let opt_reconnect_ticks = match friend_deadlines_report_reader
+        .get_opt_reconnect_ticks()
+        .which()?
+    {
+        report_capnp::friend_deadlines_report::opt_reconnect_ticks::Ticks(ticks) => Some(ticks),
+        report_capnp::friend_deadlines_report::opt_reconnect_ticks::Empty(()) => None,
+    };
+
+    let opt_response_timeout_ticks = match friend_deadlines_report_reader
+        .get_opt_response_timeout_ticks()
+        .which()?
+    {
+        report_capnp::friend_deadlines_report::opt_response_timeout_ticks::Ticks(ticks) => {
+            Some(ticks)
+        }
+        report_capnp::friend_deadlines_report::opt_response_timeout_ticks::Empty(()) => None,
+    };
+
+    let opt_remote_max_debt_expiry_ticks = match friend_deadlines_report_reader
+        .get_opt_remote_max_debt_expiry_ticks()
+        .which()?
+    {
+        report_capnp::friend_deadlines_report::opt_remote_max_debt_expiry_ticks::Ticks(ticks) => {
+            Some(ticks)
+        }
+        report_capnp::friend_deadlines_report::opt_remote_max_debt_expiry_ticks::Empty(()) => None,
+    };
+
+    Ok(FriendDeadlinesReport {
+        opt_reconnect_ticks,
+        opt_response_timeout_ticks,
+        opt_remote_max_debt_expiry_ticks,
+    })
+}
+
 fn ser_friend_report(
     friend_report: &FriendReport,
     friend_report_builder: &mut report_capnp::friend_report::Builder,
@@ -603,6 +863,36 @@ fn ser_friend_report(
     );
 
     friend_report_builder.set_num_pending_user_requests(friend_report.num_pending_user_requests);
+
+    let pending_payments_len = usize_to_u32(friend_report.pending_payments.len()).unwrap();
+    let mut pending_payments_builder = friend_report_builder
+        .reborrow()
+        .init_pending_payments(pending_payments_len);
+    for (index, pending_payment_report) in friend_report.pending_payments.iter().enumerate() {
+        let mut pending_payment_report_builder = pending_payments_builder
+            .reborrow()
+            .get(usize_to_u32(index).unwrap());
+        ser_pending_payment_report(pending_payment_report, &mut pending_payment_report_builder);
+    }
+
+    ser_opt_protocol_violation(
+        &friend_report.opt_protocol_violation,
+        &mut friend_report_builder
+            .reborrow()
+            .init_opt_protocol_violation(),
+    );
+
+    ser_verification_status_report(
+        &friend_report.verification_status,
+        &mut friend_report_builder.reborrow().init_verification_status(),
+    );
+
+    ser_friend_deadlines_report(
+        &friend_report.deadlines,
+        &mut friend_report_builder.reborrow().init_deadlines(),
+    );
+
+    friend_report_builder.set_index_private(friend_report.index_private);
 }
 
 fn deser_friend_report(
@@ -613,6 +903,11 @@ fn deser_friend_report(
         remote_relays.push(read_relay_address(&relay_address)?);
     }
 
+    let mut pending_payments = Vec::new();
+    for pending_payment_report in friend_report_reader.get_pending_payments()? {
+        pending_payments.push(deser_pending_payment_report(&pending_payment_report)?);
+    }
+
     Ok(FriendReport {
         name: friend_report_reader.get_name()?.to_owned(),
         remote_relays,
@@ -634,6 +929,15 @@ fn deser_friend_report(
         num_pending_responses: friend_report_reader.get_num_pending_responses(),
         status: deser_friend_status_report(&friend_report_reader.get_status()?)?,
         num_pending_user_requests: friend_report_reader.get_num_pending_user_requests(),
+        pending_payments,
+        opt_protocol_violation: deser_opt_protocol_violation(
+            &friend_report_reader.get_opt_protocol_violation()?,
+        )?,
+        verification_status: deser_verification_status_report(
+            &friend_report_reader.get_verification_status()?,
+        )?,
+        deadlines: deser_friend_deadlines_report(&friend_report_reader.get_deadlines()?)?,
+        index_private: friend_report_reader.get_index_private(),
     })
 }
 
@@ -661,6 +965,173 @@ fn deser_pk_friend_report(
     Ok((friend_public_key, friend_report))
 }
 
+fn ser_reliability_report(
+    reliability_report: &ReliabilityReport,
+    reliability_report_builder: &mut report_capnp::reliability_report::Builder,
+) {
+    reliability_report_builder.set_success_ppm(reliability_report.success_ppm);
+
+    let mut opt_latency_ticks_builder = reliability_report_builder
+        .reborrow()
+        .init_opt_latency_ticks();
+    match reliability_report.opt_latency_ticks {
+        Some(latency_ticks) => opt_latency_ticks_builder.set_latency_ticks(latency_ticks),
+        None => opt_latency_ticks_builder.set_empty(()),
+    };
+
+    reliability_report_builder.set_samples(reliability_report.samples);
+}
+
+fn deser_reliability_report(
+    reliability_report_reader: &report_capnp::reliability_report::Reader,
+) -> Result<ReliabilityReport, SerializeError> {
+    let opt_latency_ticks = match reliability_report_reader.get_opt_latency_ticks().which()? {
+        report_capnp::reliability_report::opt_latency_ticks::LatencyTicks(latency_ticks) => {
+            Some(latency_ticks)
+        }
+        report_capnp::reliability_report::opt_latency_ticks::Empty(()) => None,
+    };
+
+    Ok(ReliabilityReport {
+        success_ppm: reliability_report_reader.get_success_ppm(),
+        opt_latency_ticks,
+        samples: reliability_report_reader.get_samples(),
+    })
+}
+
+fn ser_pk_reliability_report(
+    pk_reliability_report: (&PublicKey, &ReliabilityReport),
+    pk_reliability_report_builder: &mut report_capnp::pk_reliability_report::Builder,
+) {
+    let (public_key, reliability_report) = pk_reliability_report;
+    write_public_key(
+        public_key,
+        &mut pk_reliability_report_builder.reborrow().init_public_key(),
+    );
+    ser_reliability_report(
+        reliability_report,
+        &mut pk_reliability_report_builder
+            .reborrow()
+            .init_reliability_report(),
+    );
+}
+
+fn deser_pk_reliability_report(
+    pk_reliability_report_reader: &report_capnp::pk_reliability_report::Reader,
+) -> Result<(PublicKey, ReliabilityReport), SerializeError> {
+    let public_key = read_public_key(&pk_reliability_report_reader.get_public_key()?)?;
+    let reliability_report =
+        deser_reliability_report(&pk_reliability_report_reader.get_reliability_report()?)?;
+
+    Ok((public_key, reliability_report))
+}
+
+pub fn ser_payment_result(
+    result: &ResponseSendFundsResult,
+    payment_result_builder: &mut report_capnp::payment_result::Builder,
+) {
+    match result {
+        ResponseSendFundsResult::Success(receipt) => {
+            write_receipt(
+                receipt,
+                &mut payment_result_builder.reborrow().init_success(),
+            );
+        }
+        ResponseSendFundsResult::Failure((public_key, reason)) => {
+            write_public_key(
+                public_key,
+                &mut payment_result_builder.reborrow().init_failure(),
+            );
+            payment_result_builder.set_failure_reason(reason.to_u16());
+            if let FailureReason::InsufficientLocalCapacity(remaining_capacity) = reason {
+                write_custom_u_int128(
+                    *remaining_capacity,
+                    &mut payment_result_builder.reborrow().init_remaining_capacity(),
+                );
+            }
+        }
+    }
+}
+
+pub fn deser_payment_result(
+    payment_result_reader: &report_capnp::payment_result::Reader,
+) -> Result<ResponseSendFundsResult, SerializeError> {
+    Ok(match payment_result_reader.which()? {
+        report_capnp::payment_result::Success(receipt_reader) => {
+            ResponseSendFundsResult::Success(read_receipt(&receipt_reader?)?)
+        }
+        report_capnp::payment_result::Failure(public_key_reader) => {
+            let code = payment_result_reader.get_failure_reason();
+            let reason = match FailureReason::from_u16(code) {
+                FailureReason::InsufficientLocalCapacity(_) => {
+                    FailureReason::InsufficientLocalCapacity(read_custom_u_int128(
+                        &payment_result_reader.get_remaining_capacity()?,
+                    )?)
+                }
+                reason => reason,
+            };
+            ResponseSendFundsResult::Failure((read_public_key(&public_key_reader?)?, reason))
+        }
+    })
+}
+
+pub fn ser_labeled_payment(
+    labeled_payment: &LabeledPayment,
+    labeled_payment_builder: &mut report_capnp::labeled_payment::Builder,
+) {
+    write_uid(
+        &labeled_payment.request_id,
+        &mut labeled_payment_builder.reborrow().init_request_id(),
+    );
+    labeled_payment_builder.set_label(&labeled_payment.label);
+
+    let mut opt_result_builder = labeled_payment_builder.reborrow().init_opt_result();
+    match &labeled_payment.opt_result {
+        Some(result) => ser_payment_result(result, &mut opt_result_builder.init_payment_result()),
+        None => opt_result_builder.set_empty(()),
+    };
+}
+
+pub fn deser_labeled_payment(
+    labeled_payment_reader: &report_capnp::labeled_payment::Reader,
+) -> Result<LabeledPayment, SerializeError> {
+    let opt_result = match labeled_payment_reader.get_opt_result().which()? {
+        report_capnp::labeled_payment::opt_result::PaymentResult(payment_result_reader) => {
+            Some(deser_payment_result(&payment_result_reader?)?)
+        }
+        report_capnp::labeled_payment::opt_result::Empty(()) => None,
+    };
+
+    Ok(LabeledPayment {
+        request_id: read_uid(&labeled_payment_reader.get_request_id()?)?,
+        label: labeled_payment_reader.get_label()?.to_vec(),
+        opt_result,
+    })
+}
+
+fn ser_request_payment_result(
+    request_payment_result: (&Uid, &ResponseSendFundsResult),
+    request_payment_result_builder: &mut report_capnp::request_payment_result::Builder,
+) {
+    let (request_id, result) = request_payment_result;
+    write_uid(
+        request_id,
+        &mut request_payment_result_builder.reborrow().init_request_id(),
+    );
+    ser_payment_result(
+        result,
+        &mut request_payment_result_builder.reborrow().init_result(),
+    );
+}
+
+fn deser_request_payment_result(
+    request_payment_result_reader: &report_capnp::request_payment_result::Reader,
+) -> Result<(Uid, ResponseSendFundsResult), SerializeError> {
+    let request_id = read_uid(&request_payment_result_reader.get_request_id()?)?;
+    let result = deser_payment_result(&request_payment_result_reader.get_result()?)?;
+    Ok((request_id, result))
+}
+
 fn ser_funder_report(
     funder_report: &FunderReport,
     funder_report_builder: &mut report_capnp::funder_report::Builder,
@@ -686,6 +1157,54 @@ fn ser_funder_report(
     }
 
     funder_report_builder.set_num_ready_receipts(funder_report.num_ready_receipts);
+
+    let quarantined_friends_len = usize_to_u32(funder_report.quarantined_friends.len()).unwrap();
+    let mut quarantined_friends_builder = funder_report_builder
+        .reborrow()
+        .init_quarantined_friends(quarantined_friends_len);
+    for (index, friend_public_key) in funder_report.quarantined_friends.iter().enumerate() {
+        let mut public_key_builder = quarantined_friends_builder
+            .reborrow()
+            .get(usize_to_u32(index).unwrap());
+        write_public_key(friend_public_key, &mut public_key_builder);
+    }
+
+    write_dust_thresholds(
+        &funder_report.dust_thresholds,
+        &mut funder_report_builder.reborrow().init_dust_thresholds(),
+    );
+
+    write_directory_state(
+        &funder_report.directory,
+        &mut funder_report_builder.reborrow().init_directory(),
+    );
+
+    let reliability_len = usize_to_u32(funder_report.reliability.len()).unwrap();
+    let mut reliability_builder = funder_report_builder
+        .reborrow()
+        .init_reliability(reliability_len);
+    for (index, (public_key, reliability_report)) in funder_report.reliability.iter().enumerate() {
+        let mut pk_reliability_report_builder = reliability_builder
+            .reborrow()
+            .get(usize_to_u32(index).unwrap());
+        ser_pk_reliability_report(
+            (public_key, reliability_report),
+            &mut pk_reliability_report_builder,
+        );
+    }
+
+    funder_report_builder.set_read_only(funder_report.read_only);
+
+    let labeled_payments_len = usize_to_u32(funder_report.labeled_payments.len()).unwrap();
+    let mut labeled_payments_builder = funder_report_builder
+        .reborrow()
+        .init_labeled_payments(labeled_payments_len);
+    for (index, labeled_payment) in funder_report.labeled_payments.iter().enumerate() {
+        let mut labeled_payment_builder = labeled_payments_builder
+            .reborrow()
+            .get(usize_to_u32(index).unwrap());
+        ser_labeled_payment(labeled_payment, &mut labeled_payment_builder);
+    }
 }
 
 fn deser_funder_report(
@@ -702,11 +1221,33 @@ fn deser_funder_report(
         friends.insert(friend_public_key, friend_report);
     }
 
+    let mut quarantined_friends = ImVec::new();
+    for friend_public_key in funder_report_reader.get_quarantined_friends()? {
+        quarantined_friends.push_back(read_public_key(&friend_public_key)?);
+    }
+
+    let mut reliability = ImHashMap::new();
+    for pk_reliability_report in funder_report_reader.get_reliability()? {
+        let (public_key, reliability_report) = deser_pk_reliability_report(&pk_reliability_report)?;
+        reliability.insert(public_key, reliability_report);
+    }
+
+    let mut labeled_payments = ImVec::new();
+    for labeled_payment in funder_report_reader.get_labeled_payments()? {
+        labeled_payments.push_back(deser_labeled_payment(&labeled_payment)?);
+    }
+
     Ok(FunderReport {
         local_public_key: read_public_key(&funder_report_reader.get_local_public_key()?)?,
         relays: named_relays.into_iter().collect(),
         friends,
         num_ready_receipts: funder_report_reader.get_num_ready_receipts(),
+        quarantined_friends,
+        dust_thresholds: read_dust_thresholds(&funder_report_reader.get_dust_thresholds()?)?,
+        directory: read_directory_state(&funder_report_reader.get_directory()?)?,
+        reliability,
+        read_only: funder_report_reader.get_read_only(),
+        labeled_payments,
     })
 }
 
@@ -852,6 +1393,54 @@ fn ser_friend_report_mutation(
                 .reborrow()
                 .init_set_liveness(),
         ),
+        FriendReportMutation::SetPendingPayments(pending_payments) => {
+            let pending_payments_len = usize_to_u32(pending_payments.len()).unwrap();
+            let mut pending_payments_builder = friend_report_mutation_builder
+                .reborrow()
+                .init_set_pending_payments(pending_payments_len);
+            for (index, pending_payment_report) in pending_payments.iter().enumerate() {
+                let mut pending_payment_report_builder = pending_payments_builder
+                    .reborrow()
+                    .get(usize_to_u32(index).unwrap());
+                ser_pending_payment_report(
+                    pending_payment_report,
+                    &mut pending_payment_report_builder,
+                );
+            }
+        }
+        FriendReportMutation::SetOptProtocolViolation(opt_protocol_violation) => {
+            ser_opt_protocol_violation(
+                opt_protocol_violation,
+                &mut friend_report_mutation_builder
+                    .reborrow()
+                    .init_set_opt_protocol_violation(),
+            )
+        }
+        FriendReportMutation::SetVerificationStatus(verification_status_report) => {
+            ser_verification_status_report(
+                verification_status_report,
+                &mut friend_report_mutation_builder
+                    .reborrow()
+                    .init_set_verification_status(),
+            )
+        }
+        FriendReportMutation::RemoteMaxDebtExpired(wanted_remote_max_debt) => {
+            write_custom_u_int128(
+                *wanted_remote_max_debt,
+                &mut friend_report_mutation_builder
+                    .reborrow()
+                    .init_remote_max_debt_expired(),
+            )
+        }
+        FriendReportMutation::SetDeadlines(friend_deadlines_report) => ser_friend_deadlines_report(
+            friend_deadlines_report,
+            &mut friend_report_mutation_builder
+                .reborrow()
+                .init_set_deadlines(),
+        ),
+        FriendReportMutation::SetIndexPrivate(index_private) => friend_report_mutation_builder
+            .reborrow()
+            .set_set_index_private(*index_private),
     };
 }
 
@@ -913,6 +1502,36 @@ fn deser_friend_report_mutation(
                 &friend_liveness_report_reader?,
             )?)
         }
+        report_capnp::friend_report_mutation::SetPendingPayments(pending_payments_reader) => {
+            let mut pending_payments = Vec::new();
+            for pending_payment_report in pending_payments_reader? {
+                pending_payments.push(deser_pending_payment_report(&pending_payment_report)?);
+            }
+            FriendReportMutation::SetPendingPayments(pending_payments)
+        }
+        report_capnp::friend_report_mutation::SetOptProtocolViolation(
+            opt_protocol_violation_reader,
+        ) => FriendReportMutation::SetOptProtocolViolation(deser_opt_protocol_violation(
+            &opt_protocol_violation_reader?,
+        )?),
+        report_capnp::friend_report_mutation::SetVerificationStatus(
+            verification_status_report_reader,
+        ) => FriendReportMutation::SetVerificationStatus(deser_verification_status_report(
+            &verification_status_report_reader?,
+        )?),
+        report_capnp::friend_report_mutation::RemoteMaxDebtExpired(
+            wanted_remote_max_debt_reader,
+        ) => FriendReportMutation::RemoteMaxDebtExpired(read_custom_u_int128(
+            &wanted_remote_max_debt_reader?,
+        )?),
+        report_capnp::friend_report_mutation::SetDeadlines(friend_deadlines_report_reader) => {
+            FriendReportMutation::SetDeadlines(deser_friend_deadlines_report(
+                &friend_deadlines_report_reader?,
+            )?)
+        }
+        report_capnp::friend_report_mutation::SetIndexPrivate(index_private) => {
+            FriendReportMutation::SetIndexPrivate(index_private)
+        }
     })
 }
 
@@ -993,6 +1612,59 @@ fn ser_funder_report_mutation(
                 .reborrow()
                 .set_set_num_ready_receipts(*num_ready_receipts);
         }
+        FunderReportMutation::SetDustThresholds(dust_thresholds) => {
+            write_dust_thresholds(
+                dust_thresholds,
+                &mut funder_report_mutation_builder
+                    .reborrow()
+                    .init_set_dust_thresholds(),
+            );
+        }
+        FunderReportMutation::SetDirectory(directory) => {
+            write_directory_state(
+                directory,
+                &mut funder_report_mutation_builder
+                    .reborrow()
+                    .init_set_directory(),
+            );
+        }
+        FunderReportMutation::SetReliability((public_key, reliability_report)) => {
+            ser_pk_reliability_report(
+                (public_key, reliability_report),
+                &mut funder_report_mutation_builder
+                    .reborrow()
+                    .init_set_reliability(),
+            );
+        }
+        FunderReportMutation::RemoveReliability(public_key) => {
+            write_public_key(
+                public_key,
+                &mut funder_report_mutation_builder
+                    .reborrow()
+                    .init_remove_reliability(),
+            );
+        }
+        FunderReportMutation::SetReadOnly(read_only) => {
+            funder_report_mutation_builder
+                .reborrow()
+                .set_set_read_only(*read_only);
+        }
+        FunderReportMutation::AddLabeledPayment(labeled_payment) => {
+            ser_labeled_payment(
+                labeled_payment,
+                &mut funder_report_mutation_builder
+                    .reborrow()
+                    .init_add_labeled_payment(),
+            );
+        }
+        FunderReportMutation::SetLabeledPaymentResult((request_id, result)) => {
+            ser_request_payment_result(
+                (request_id, result),
+                &mut funder_report_mutation_builder
+                    .reborrow()
+                    .init_set_labeled_payment_result(),
+            );
+        }
     }
 }
 
@@ -1020,6 +1692,33 @@ fn deser_funder_report_mutation(
         report_capnp::funder_report_mutation::SetNumReadyReceipts(num_ready_receipts) => {
             FunderReportMutation::SetNumReadyReceipts(num_ready_receipts)
         }
+        report_capnp::funder_report_mutation::SetDustThresholds(dust_thresholds_reader) => {
+            FunderReportMutation::SetDustThresholds(read_dust_thresholds(&dust_thresholds_reader?)?)
+        }
+        report_capnp::funder_report_mutation::SetDirectory(directory_state_reader) => {
+            FunderReportMutation::SetDirectory(read_directory_state(&directory_state_reader?)?)
+        }
+        report_capnp::funder_report_mutation::SetReliability(pk_reliability_report_reader) => {
+            FunderReportMutation::SetReliability(deser_pk_reliability_report(
+                &pk_reliability_report_reader?,
+            )?)
+        }
+        report_capnp::funder_report_mutation::RemoveReliability(public_key_reader) => {
+            FunderReportMutation::RemoveReliability(read_public_key(&public_key_reader?)?)
+        }
+        report_capnp::funder_report_mutation::SetReadOnly(read_only) => {
+            FunderReportMutation::SetReadOnly(read_only)
+        }
+        report_capnp::funder_report_mutation::AddLabeledPayment(labeled_payment_reader) => {
+            FunderReportMutation::AddLabeledPayment(deser_labeled_payment(
+                &labeled_payment_reader?,
+            )?)
+        }
+        report_capnp::funder_report_mutation::SetLabeledPaymentResult(
+            request_payment_result_reader,
+        ) => FunderReportMutation::SetLabeledPaymentResult(deser_request_payment_result(
+            &request_payment_result_reader?,
+        )?),
     })
 }
 
@@ -1052,6 +1751,8 @@ fn ser_index_client_report(
             opt_connected_server_builder.set_empty(());
         }
     }
+
+    index_client_report_builder.set_num_corrections(index_client_report.num_corrections);
 }
 
 fn deser_index_client_report(
@@ -1075,6 +1776,7 @@ fn deser_index_client_report(
     Ok(IndexClientReport {
         index_servers,
         opt_connected_server,
+        num_corrections: index_client_report_reader.get_num_corrections(),
     })
 }
 
@@ -1109,6 +1811,9 @@ fn ser_index_client_report_mutation(
                 None => set_connected_server_builder.set_empty(()),
             }
         }
+        IndexClientReportMutation::SetNumCorrections(num_corrections) => {
+            index_client_report_mutation_builder.set_set_num_corrections(*num_corrections)
+        }
     }
 }
 
@@ -1136,6 +1841,9 @@ fn deser_index_client_report_mutation(
                 IndexClientReportMutation::SetConnectedServer(None)
             }
         },
+        report_capnp::index_client_report_mutation::SetNumCorrections(num_corrections) => {
+            IndexClientReportMutation::SetNumCorrections(num_corrections)
+        }
     })
 }
 
@@ -1198,3 +1906,24 @@ pub fn deser_node_report_mutation(
         }
     })
 }
+
+pub fn serialize_node_report_mutation(node_report_mutation: &NodeReportMutation) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut node_report_mutation_builder =
+        builder.init_root::<report_capnp::node_report_mutation::Builder>();
+    ser_node_report_mutation(node_report_mutation, &mut node_report_mutation_builder);
+
+    let mut ser_buff = Vec::new();
+    serialize_packed::write_message(&mut ser_buff, &builder).unwrap();
+    ser_buff
+}
+
+pub fn deserialize_node_report_mutation(data: &[u8]) -> Result<NodeReportMutation, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let node_report_mutation_reader =
+        reader.get_root::<report_capnp::node_report_mutation::Reader>()?;
+
+    deser_node_report_mutation(&node_report_mutation_reader)
+}
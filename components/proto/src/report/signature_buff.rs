@@ -1,5 +1,4 @@
-use byteorder::{BigEndian, WriteBytesExt};
-
+use common::canonical_serialize::CanonicalSerialize;
 use crypto::hash::sha_512_256;
 use crypto::identity::{verify_signature, PublicKey};
 
@@ -16,21 +15,27 @@ fn move_token_hashed_report_signature_buff(
     sig_buffer.extend_from_slice(&move_token_hashed_report.prefix_hash);
     sig_buffer.extend_from_slice(&move_token_hashed_report.local_public_key);
     sig_buffer.extend_from_slice(&move_token_hashed_report.remote_public_key);
-    sig_buffer
-        .write_u64::<BigEndian>(move_token_hashed_report.inconsistency_counter)
-        .unwrap();
-    sig_buffer
-        .write_u128::<BigEndian>(move_token_hashed_report.move_token_counter)
-        .unwrap();
-    sig_buffer
-        .write_i128::<BigEndian>(move_token_hashed_report.balance)
-        .unwrap();
-    sig_buffer
-        .write_u128::<BigEndian>(move_token_hashed_report.local_pending_debt)
-        .unwrap();
-    sig_buffer
-        .write_u128::<BigEndian>(move_token_hashed_report.remote_pending_debt)
-        .unwrap();
+    sig_buffer.extend_from_slice(
+        &move_token_hashed_report
+            .inconsistency_counter
+            .canonical_serialize(),
+    );
+    sig_buffer.extend_from_slice(
+        &move_token_hashed_report
+            .move_token_counter
+            .canonical_serialize(),
+    );
+    sig_buffer.extend_from_slice(&move_token_hashed_report.balance.canonical_serialize());
+    sig_buffer.extend_from_slice(
+        &move_token_hashed_report
+            .local_pending_debt
+            .canonical_serialize(),
+    );
+    sig_buffer.extend_from_slice(
+        &move_token_hashed_report
+            .remote_pending_debt
+            .canonical_serialize(),
+    );
     sig_buffer.extend_from_slice(&move_token_hashed_report.rand_nonce);
 
     sig_buffer
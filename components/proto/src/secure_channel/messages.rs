@@ -1,5 +1,6 @@
 use crypto::crypto_rand::RandValue;
 use crypto::dh::{DhPublicKey, Salt};
+use crypto::hash::HashResult;
 use crypto::identity::{PublicKey, Signature};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -33,6 +34,49 @@ impl ExchangeDh {
     }
 }
 
+/// Sent by the side that wants to move an existing session to a new transport.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ResumeRequest {
+    pub public_key: PublicKey,
+    pub rand_nonce: RandValue,
+}
+
+/// Sent by the remote side in response to a ResumeRequest.
+/// Proves knowledge of the session secret.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ResumeChallenge {
+    pub rand_nonce: RandValue,
+    pub proof: HashResult,
+}
+
+/// Final resumption message, proving knowledge of the session secret by the initiator.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ResumeProof {
+    pub proof: HashResult,
+}
+
+/// Sent by a side that holds a ticket of a previous session with the remote side, offering to
+/// derive fresh keys from the ticket instead of performing a full Diffie-Hellman exchange.
+/// If the remote side does not offer the same ticket, the full exchange continues using
+/// `exchange_rand_nonce`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ResumeSession {
+    pub exchange_rand_nonce: ExchangeRandNonce,
+    pub ticket_id: HashResult,
+    /// Proves knowledge of the ticket secret.
+    pub proof: HashResult,
+}
+
+/// First message sent over a new transport:
+/// Either a full Diffie-Hellman exchange, a resumption of an existing session over the new
+/// transport, or a new session derived from a ticket of a previous session.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChannelOpen {
+    ExchangeRandNonce(ExchangeRandNonce),
+    ResumeRequest(ResumeRequest),
+    ResumeSession(ResumeSession),
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct Rekey {
     pub dh_public_key: DhPublicKey,
@@ -4,14 +4,16 @@ use dh_capnp;
 use std::io;
 
 use crate::capnp_common::{
-    read_dh_public_key, read_public_key, read_rand_nonce, read_salt, read_signature,
-    write_dh_public_key, write_public_key, write_rand_nonce, write_salt, write_signature,
+    read_dh_public_key, read_hash, read_public_key, read_rand_nonce, read_salt, read_signature,
+    write_dh_public_key, write_hash, write_public_key, write_rand_nonce, write_salt,
+    write_signature,
 };
 
 use crate::serialize::SerializeError;
 
 use super::messages::{
-    ChannelContent, ChannelMessage, ExchangeDh, ExchangeRandNonce, PlainData, Rekey,
+    ChannelContent, ChannelMessage, ChannelOpen, ExchangeDh, ExchangeRandNonce, PlainData, Rekey,
+    ResumeChallenge, ResumeProof, ResumeRequest, ResumeSession,
 };
 
 pub fn serialize_exchange_rand_nonce(exchange_rand_nonce: &ExchangeRandNonce) -> Vec<u8> {
@@ -47,6 +49,141 @@ pub fn deserialize_exchange_rand_nonce(data: &[u8]) -> Result<ExchangeRandNonce,
     })
 }
 
+/// The first message over a new transport is an `ExchangeRandNonce`, also when it asks to resume
+/// a session. Peers that do not know about session resumption read every first message as a
+/// plain `ExchangeRandNonce`, and their messages are read as `ChannelOpen::ExchangeRandNonce`.
+pub fn serialize_channel_open(channel_open: &ChannelOpen) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut msg = builder.init_root::<dh_capnp::exchange_rand_nonce::Builder>();
+
+    let (rand_nonce, public_key) = match channel_open {
+        ChannelOpen::ExchangeRandNonce(exchange_rand_nonce) => {
+            msg.reborrow().get_open().set_exchange(());
+            (
+                &exchange_rand_nonce.rand_nonce,
+                &exchange_rand_nonce.public_key,
+            )
+        }
+        ChannelOpen::ResumeRequest(resume_request) => {
+            msg.reborrow().get_open().set_resume_request(());
+            (&resume_request.rand_nonce, &resume_request.public_key)
+        }
+        ChannelOpen::ResumeSession(resume_session) => {
+            let mut resume_session_msg = msg.reborrow().get_open().init_resume_session();
+            write_hash(
+                &resume_session.ticket_id,
+                &mut resume_session_msg.reborrow().get_ticket_id().unwrap(),
+            );
+            write_hash(
+                &resume_session.proof,
+                &mut resume_session_msg.reborrow().get_proof().unwrap(),
+            );
+            (
+                &resume_session.exchange_rand_nonce.rand_nonce,
+                &resume_session.exchange_rand_nonce.public_key,
+            )
+        }
+    };
+    write_rand_nonce(rand_nonce, &mut msg.reborrow().get_rand_nonce().unwrap());
+    write_public_key(public_key, &mut msg.reborrow().get_public_key().unwrap());
+
+    let mut serialized_msg = Vec::new();
+    serialize_packed::write_message(&mut serialized_msg, &builder).unwrap();
+    serialized_msg
+}
+
+pub fn deserialize_channel_open(data: &[u8]) -> Result<ChannelOpen, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let msg = reader.get_root::<dh_capnp::exchange_rand_nonce::Reader>()?;
+
+    let rand_nonce = read_rand_nonce(&msg.get_rand_nonce()?)?;
+    let public_key = read_public_key(&msg.get_public_key()?)?;
+
+    Ok(match msg.get_open().which() {
+        Ok(dh_capnp::exchange_rand_nonce::open::Exchange(())) => {
+            ChannelOpen::ExchangeRandNonce(ExchangeRandNonce {
+                rand_nonce,
+                public_key,
+            })
+        }
+        Ok(dh_capnp::exchange_rand_nonce::open::ResumeRequest(())) => {
+            ChannelOpen::ResumeRequest(ResumeRequest {
+                public_key,
+                rand_nonce,
+            })
+        }
+        Ok(dh_capnp::exchange_rand_nonce::open::ResumeSession(resume_session)) => {
+            let resume_session = resume_session?;
+            ChannelOpen::ResumeSession(ResumeSession {
+                exchange_rand_nonce: ExchangeRandNonce {
+                    rand_nonce,
+                    public_key,
+                },
+                ticket_id: read_hash(&resume_session.get_ticket_id()?)?,
+                proof: read_hash(&resume_session.get_proof()?)?,
+            })
+        }
+        Err(e) => return Err(SerializeError::NotInSchema(e)),
+    })
+}
+
+pub fn serialize_resume_challenge(resume_challenge: &ResumeChallenge) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut msg = builder.init_root::<dh_capnp::resume_challenge::Builder>();
+
+    write_rand_nonce(
+        &resume_challenge.rand_nonce,
+        &mut msg.reborrow().get_rand_nonce().unwrap(),
+    );
+    write_hash(
+        &resume_challenge.proof,
+        &mut msg.reborrow().get_proof().unwrap(),
+    );
+
+    let mut serialized_msg = Vec::new();
+    serialize_packed::write_message(&mut serialized_msg, &builder).unwrap();
+    serialized_msg
+}
+
+pub fn deserialize_resume_challenge(data: &[u8]) -> Result<ResumeChallenge, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let msg = reader.get_root::<dh_capnp::resume_challenge::Reader>()?;
+
+    Ok(ResumeChallenge {
+        rand_nonce: read_rand_nonce(&msg.get_rand_nonce()?)?,
+        proof: read_hash(&msg.get_proof()?)?,
+    })
+}
+
+pub fn serialize_resume_proof(resume_proof: &ResumeProof) -> Vec<u8> {
+    let mut builder = capnp::message::Builder::new_default();
+    let mut msg = builder.init_root::<dh_capnp::resume_proof::Builder>();
+
+    write_hash(
+        &resume_proof.proof,
+        &mut msg.reborrow().get_proof().unwrap(),
+    );
+
+    let mut serialized_msg = Vec::new();
+    serialize_packed::write_message(&mut serialized_msg, &builder).unwrap();
+    serialized_msg
+}
+
+pub fn deserialize_resume_proof(data: &[u8]) -> Result<ResumeProof, SerializeError> {
+    let mut cursor = io::Cursor::new(data);
+    let reader =
+        serialize_packed::read_message(&mut cursor, ::capnp::message::ReaderOptions::new())?;
+    let msg = reader.get_root::<dh_capnp::resume_proof::Reader>()?;
+
+    Ok(ResumeProof {
+        proof: read_hash(&msg.get_proof()?)?,
+    })
+}
+
 pub fn serialize_exchange_dh(exchange_dh: &ExchangeDh) -> Vec<u8> {
     let mut builder = capnp::message::Builder::new_default();
     let mut msg = builder.init_root::<dh_capnp::exchange_dh::Builder>();
@@ -158,6 +295,7 @@ mod tests {
     use crypto::crypto_rand::RAND_VALUE_LEN;
     use crypto::dh::{DhPublicKey, Salt};
     use crypto::dh::{DH_PUBLIC_KEY_LEN, SALT_LEN};
+    use crypto::hash::{HashResult, HASH_RESULT_LEN};
     use crypto::identity::{PublicKey, Signature};
     use crypto::identity::{PUBLIC_KEY_LEN, SIGNATURE_LEN};
     use std::convert::TryFrom;
@@ -186,6 +324,87 @@ mod tests {
         assert_eq!(msg, msg2);
     }
 
+    #[test]
+    fn test_serialize_channel_open() {
+        let msg = ChannelOpen::ExchangeRandNonce(ExchangeRandNonce {
+            rand_nonce: RandValue::try_from(&[0x01u8; RAND_VALUE_LEN][..]).unwrap(),
+            public_key: PublicKey::try_from(&[0x02u8; PUBLIC_KEY_LEN][..]).unwrap(),
+        });
+        let serialized = serialize_channel_open(&msg);
+        let msg2 = deserialize_channel_open(&serialized[..]).unwrap();
+        assert_eq!(msg, msg2);
+
+        let msg = ChannelOpen::ResumeRequest(ResumeRequest {
+            public_key: PublicKey::try_from(&[0x02u8; PUBLIC_KEY_LEN][..]).unwrap(),
+            rand_nonce: RandValue::try_from(&[0x01u8; RAND_VALUE_LEN][..]).unwrap(),
+        });
+        let serialized = serialize_channel_open(&msg);
+        let msg2 = deserialize_channel_open(&serialized[..]).unwrap();
+        assert_eq!(msg, msg2);
+
+        let msg = ChannelOpen::ResumeSession(ResumeSession {
+            exchange_rand_nonce: ExchangeRandNonce {
+                rand_nonce: RandValue::try_from(&[0x01u8; RAND_VALUE_LEN][..]).unwrap(),
+                public_key: PublicKey::try_from(&[0x02u8; PUBLIC_KEY_LEN][..]).unwrap(),
+            },
+            ticket_id: HashResult::try_from(&[0x03u8; HASH_RESULT_LEN][..]).unwrap(),
+            proof: HashResult::try_from(&[0x04u8; HASH_RESULT_LEN][..]).unwrap(),
+        });
+        let serialized = serialize_channel_open(&msg);
+        let msg2 = deserialize_channel_open(&serialized[..]).unwrap();
+        assert_eq!(msg, msg2);
+    }
+
+    #[test]
+    fn test_channel_open_compatible() {
+        let exchange_rand_nonce = ExchangeRandNonce {
+            rand_nonce: RandValue::try_from(&[0x01u8; RAND_VALUE_LEN][..]).unwrap(),
+            public_key: PublicKey::try_from(&[0x02u8; PUBLIC_KEY_LEN][..]).unwrap(),
+        };
+
+        // The first message of a peer that does not know about session resumption:
+        let serialized = serialize_exchange_rand_nonce(&exchange_rand_nonce);
+        let msg = deserialize_channel_open(&serialized[..]).unwrap();
+        assert_eq!(
+            msg,
+            ChannelOpen::ExchangeRandNonce(ExchangeRandNonce {
+                rand_nonce: exchange_rand_nonce.rand_nonce.clone(),
+                public_key: exchange_rand_nonce.public_key.clone(),
+            })
+        );
+
+        // A peer that does not know about session resumption sees a plain exchange:
+        let msg = ChannelOpen::ResumeSession(ResumeSession {
+            exchange_rand_nonce: ExchangeRandNonce {
+                rand_nonce: exchange_rand_nonce.rand_nonce.clone(),
+                public_key: exchange_rand_nonce.public_key.clone(),
+            },
+            ticket_id: HashResult::try_from(&[0x03u8; HASH_RESULT_LEN][..]).unwrap(),
+            proof: HashResult::try_from(&[0x04u8; HASH_RESULT_LEN][..]).unwrap(),
+        });
+        let serialized = serialize_channel_open(&msg);
+        let msg2 = deserialize_exchange_rand_nonce(&serialized[..]).unwrap();
+        assert_eq!(msg2, exchange_rand_nonce);
+    }
+
+    #[test]
+    fn test_serialize_resume_challenge_proof() {
+        let msg = ResumeChallenge {
+            rand_nonce: RandValue::try_from(&[0x01u8; RAND_VALUE_LEN][..]).unwrap(),
+            proof: HashResult::try_from(&[0x02u8; HASH_RESULT_LEN][..]).unwrap(),
+        };
+        let serialized = serialize_resume_challenge(&msg);
+        let msg2 = deserialize_resume_challenge(&serialized[..]).unwrap();
+        assert_eq!(msg, msg2);
+
+        let msg = ResumeProof {
+            proof: HashResult::try_from(&[0x03u8; HASH_RESULT_LEN][..]).unwrap(),
+        };
+        let serialized = serialize_resume_proof(&msg);
+        let msg2 = deserialize_resume_proof(&serialized[..]).unwrap();
+        assert_eq!(msg, msg2);
+    }
+
     #[test]
     fn test_serialize_channel_message_rekey() {
         let rekey = Rekey {
@@ -8,4 +8,8 @@ pub enum SerializeError {
     NotInSchema(capnp::NotInSchema),
     IoError(io::Error),
     NetAddressError(NetAddressError),
+    /// A route longer than MAX_ROUTE_LEN
+    RouteTooLong,
+    /// A chunked transfer frame, where a complete message was expected
+    TransferFrame,
 }
@@ -0,0 +1,198 @@
+//! A container for data a node exports to be inspected by others, like debug bundles and state
+//! exports. The container is signed by the identity of the exporting node, allowing the receiver
+//! to verify that the data was not modified or truncated, and which node created it.
+//!
+//! Layout:
+//! ```text
+//! magic (8 bytes) | version (u32) | contents length (u64) | contents | public key | signature
+//! ```
+//! The part from the magic up to the end of the contents is the body of the export. The body is
+//! signed exactly as it was assembled. The footer (The public key and the signature of the
+//! exporting node) is appended after the body is signed, and is not part of the signed data.
+
+use std::convert::TryFrom;
+
+use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+
+use common::int_convert::usize_to_u64;
+use crypto::hash;
+use crypto::identity::{
+    verify_signature, Identity, PublicKey, Signature, PUBLIC_KEY_LEN, SIGNATURE_LEN,
+};
+
+pub const SIGNED_EXPORT_PREFIX: &[u8] = b"SIGNED_EXPORT";
+
+/// Magic (8 bytes), version (u32) and contents length (u64)
+pub const EXPORT_HEADER_LEN: usize = 8 + 4 + 8;
+
+/// Public key and signature of the exporting node
+pub const EXPORT_FOOTER_LEN: usize = PUBLIC_KEY_LEN + SIGNATURE_LEN;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignedExportError {
+    /// The data is too short, too long, or does not match the length written in the header
+    InvalidLength,
+    /// The body was not signed by the public key in the footer
+    InvalidSignature,
+}
+
+/// An export with a valid signature
+#[derive(Debug, PartialEq, Eq)]
+pub struct VerifiedExport<'a> {
+    pub magic: [u8; 8],
+    pub version: u32,
+    pub contents: &'a [u8],
+    /// Public key of the node that signed the export
+    pub public_key: PublicKey,
+}
+
+/// Assemble the body of an export: The magic, the version of the format, the length of the
+/// contents and the contents themselves.
+pub fn create_export_body(magic: &[u8; 8], version: u32, contents: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(magic);
+    body.write_u32::<BigEndian>(version).unwrap();
+    body.write_u64::<BigEndian>(usize_to_u64(contents.len()).unwrap())
+        .unwrap();
+    body.extend_from_slice(contents);
+    body
+}
+
+/// Create the buffer the exporting node signs over.
+/// The public key of the exporting node is included, so that the footer can not be replaced by a
+/// different public key.
+pub fn create_export_signature_buffer(body: &[u8], public_key: &PublicKey) -> Vec<u8> {
+    let mut sbuffer = Vec::new();
+    sbuffer.extend_from_slice(&hash::sha_512_256(SIGNED_EXPORT_PREFIX));
+    sbuffer.extend_from_slice(public_key);
+    sbuffer.extend_from_slice(&hash::sha_512_256(body));
+    sbuffer
+}
+
+/// Append the footer to a signed body, creating a complete export.
+pub fn append_export_footer(
+    mut body: Vec<u8>,
+    public_key: &PublicKey,
+    signature: &Signature,
+) -> Vec<u8> {
+    body.extend_from_slice(public_key);
+    body.extend_from_slice(signature);
+    body
+}
+
+/// Sign the body of an export using a local identity, creating a complete export.
+pub fn sign_export<I>(body: Vec<u8>, identity: &I) -> Vec<u8>
+where
+    I: Identity,
+{
+    let public_key = identity.get_public_key();
+    let signature = identity.sign(&create_export_signature_buffer(&body, &public_key));
+    append_export_footer(body, &public_key, &signature)
+}
+
+/// Verify the integrity of an export of any kind.
+/// Returns the contents of the export together with the public key of the node that signed it.
+/// Checking the magic and the version is left to the caller.
+pub fn verify_export(data: &[u8]) -> Result<VerifiedExport, SignedExportError> {
+    if data.len() < EXPORT_HEADER_LEN + EXPORT_FOOTER_LEN {
+        return Err(SignedExportError::InvalidLength);
+    }
+
+    let mut magic = [0u8; 8];
+    magic.copy_from_slice(&data[..8]);
+    let version = BigEndian::read_u32(&data[8..12]);
+    let contents_len = BigEndian::read_u64(&data[12..EXPORT_HEADER_LEN]);
+
+    let expected_len = usize_to_u64(EXPORT_HEADER_LEN + EXPORT_FOOTER_LEN)
+        .and_then(|len| len.checked_add(contents_len));
+    if usize_to_u64(data.len()) != expected_len {
+        return Err(SignedExportError::InvalidLength);
+    }
+
+    let body_len = data.len() - EXPORT_FOOTER_LEN;
+    let (body, footer) = data.split_at(body_len);
+    let (public_key_bytes, signature_bytes) = footer.split_at(PUBLIC_KEY_LEN);
+    let public_key = PublicKey::try_from(public_key_bytes).unwrap();
+    let signature = Signature::try_from(signature_bytes).unwrap();
+
+    let signature_buffer = create_export_signature_buffer(body, &public_key);
+    if !verify_signature(&signature_buffer, &public_key, &signature) {
+        return Err(SignedExportError::InvalidSignature);
+    }
+
+    Ok(VerifiedExport {
+        magic,
+        version,
+        contents: &body[EXPORT_HEADER_LEN..],
+        public_key,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crypto::test_utils::fixture_software_identity;
+
+    #[test]
+    fn test_verify_export() {
+        let identity = fixture_software_identity(1);
+        let body = create_export_body(b"OFSTTST\0", 7, b"contents of the export");
+        let data = sign_export(body.clone(), &identity);
+        assert_eq!(data.len(), body.len() + EXPORT_FOOTER_LEN);
+
+        let verified_export = verify_export(&data).unwrap();
+        assert_eq!(&verified_export.magic, b"OFSTTST\0");
+        assert_eq!(verified_export.version, 7);
+        assert_eq!(verified_export.contents, b"contents of the export");
+        assert_eq!(verified_export.public_key, identity.get_public_key());
+
+        // Flipping any byte breaks the export:
+        for i in 0..data.len() {
+            let mut data2 = data.clone();
+            data2[i] ^= 0x01;
+            assert!(verify_export(&data2).is_err());
+        }
+
+        // Truncated or extended exports:
+        assert_eq!(
+            verify_export(&data[..data.len() - 1]),
+            Err(SignedExportError::InvalidLength)
+        );
+        let mut data2 = data.clone();
+        data2.push(0);
+        assert_eq!(verify_export(&data2), Err(SignedExportError::InvalidLength));
+
+        // Signed by a different node, with the public key of the original node in the footer:
+        let other_identity = fixture_software_identity(2);
+        let other_signature = other_identity.sign(&create_export_signature_buffer(
+            &body,
+            &identity.get_public_key(),
+        ));
+        let data2 =
+            append_export_footer(body.clone(), &identity.get_public_key(), &other_signature);
+        assert_eq!(
+            verify_export(&data2),
+            Err(SignedExportError::InvalidSignature)
+        );
+
+        // The other node can only sign the export using its own public key:
+        let data2 = sign_export(body, &other_identity);
+        assert_eq!(
+            verify_export(&data2).unwrap().public_key,
+            other_identity.get_public_key()
+        );
+    }
+
+    #[test]
+    fn test_verify_export_empty_contents() {
+        let identity = fixture_software_identity(3);
+        let data = sign_export(create_export_body(b"OFSTTST\0", 0, &[]), &identity);
+        assert_eq!(data.len(), EXPORT_HEADER_LEN + EXPORT_FOOTER_LEN);
+        assert!(verify_export(&data).unwrap().contents.is_empty());
+        assert_eq!(
+            verify_export(&data[..EXPORT_HEADER_LEN]),
+            Err(SignedExportError::InvalidLength)
+        );
+    }
+}
@@ -419,7 +419,7 @@ mod tests {
         assert_eq!(req.address, ());
 
         for _ in 0..8usize {
-            await!(timer_sender.send(TimerTick)).unwrap();
+            await!(timer_sender.send(TimerTick { ticks_elapsed: 1 })).unwrap();
         }
 
         assert!(await!(res_receiver).unwrap().is_none());
@@ -1,2 +1,3 @@
 pub mod client_connector;
 pub mod client_listener;
+pub mod mux_client_connector;
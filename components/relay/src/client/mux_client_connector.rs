@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use futures::channel::{mpsc, oneshot};
+use futures::task::{Spawn, SpawnExt};
+use futures::{FutureExt, SinkExt};
+
+use common::conn::{BoxFuture, ConnPairVec, FutTransform};
+use crypto::identity::PublicKey;
+
+use proto::consts::{MAX_MUX_CHANNELS, MUX_CHANNEL_QUEUE_LEN};
+use proto::relay::messages::InitConnection;
+use proto::relay::serialize::serialize_init_connection;
+
+use crate::mux::{mux_loop, OpenChannel};
+
+#[derive(Debug)]
+pub enum MuxClientConnectorError {
+    InnerConnectorError,
+    SendInitConnectionError,
+    SpawnError,
+    RelayConnectionClosed,
+}
+
+/// A live connection to a relay, shared by all the connections through this relay.
+struct RelayConn {
+    id: u64,
+    open_sender: mpsc::Sender<OpenChannel>,
+}
+
+struct RelayConns<A> {
+    next_id: u64,
+    conns: HashMap<A, RelayConn>,
+}
+
+/// MuxClientConnector is an end-to-end connector to a remote node, like ClientConnector.
+/// All the connections through the same relay share a single connection to the relay, and are
+/// closed together if the connection to the relay is closed.
+#[derive(Clone)]
+pub struct MuxClientConnector<A, C, FT, S> {
+    connector: C,
+    keepalive_transform: FT,
+    relay_conns: Arc<Mutex<RelayConns<A>>>,
+    spawner: S,
+}
+
+impl<A, C, FT, S> MuxClientConnector<A, C, FT, S>
+where
+    A: Hash + Eq + Clone + Send + 'static,
+    C: FutTransform<Input = A, Output = Option<ConnPairVec>>,
+    FT: FutTransform<Input = ConnPairVec, Output = ConnPairVec>,
+    S: Spawn + Clone + Send + 'static,
+{
+    pub fn new(connector: C, keepalive_transform: FT, spawner: S) -> Self {
+        MuxClientConnector {
+            connector,
+            keepalive_transform,
+            relay_conns: Arc::new(Mutex::new(RelayConns {
+                next_id: 0,
+                conns: HashMap::new(),
+            })),
+            spawner,
+        }
+    }
+
+    /// Open a new connection to a relay, to be shared by all the connections through this relay.
+    async fn connect_relay(
+        &mut self,
+        relay_address: A,
+    ) -> Result<mpsc::Sender<OpenChannel>, MuxClientConnectorError> {
+        let (mut sender, receiver) = await!(self.connector.transform(relay_address.clone()))
+            .ok_or(MuxClientConnectorError::InnerConnectorError)?;
+
+        let ser_init_connection = serialize_init_connection(&InitConnection::ConnectMux);
+        await!(sender.send(ser_init_connection))
+            .map_err(|_| MuxClientConnectorError::SendInitConnectionError)?;
+
+        // A single keepalive for all the connections through this relay:
+        let conn_pair = await!(self.keepalive_transform.transform((sender, receiver)));
+
+        let (open_sender, open_receiver) = mpsc::channel(0);
+        let id = {
+            let mut relay_conns = self.relay_conns.lock().unwrap();
+            let id = relay_conns.next_id;
+            relay_conns.next_id = relay_conns.next_id.wrapping_add(1);
+            let relay_conn = RelayConn {
+                id,
+                open_sender: open_sender.clone(),
+            };
+            relay_conns.conns.insert(relay_address.clone(), relay_conn);
+            id
+        };
+
+        let c_relay_conns = self.relay_conns.clone();
+        let mux_fut = mux_loop(
+            conn_pair,
+            open_receiver,
+            None::<mpsc::Sender<(PublicKey, ConnPairVec)>>,
+            true,
+            MAX_MUX_CHANNELS,
+            MUX_CHANNEL_QUEUE_LEN,
+            self.spawner.clone(),
+        )
+        .map(move |res| {
+            if let Err(e) = res {
+                warn!("MuxClientConnector: mux_loop() error: {:?}", e);
+            }
+            // Make sure that the closed connection is not used for new connections.
+            // A newer connection to the same relay might already exist:
+            let mut relay_conns = c_relay_conns.lock().unwrap();
+            if relay_conns
+                .conns
+                .get(&relay_address)
+                .map(|relay_conn| relay_conn.id)
+                == Some(id)
+            {
+                relay_conns.conns.remove(&relay_address);
+            }
+        });
+        self.spawner
+            .spawn(mux_fut)
+            .map_err(|_| MuxClientConnectorError::SpawnError)?;
+
+        Ok(open_sender)
+    }
+
+    async fn relay_connect(
+        &mut self,
+        relay_address: A,
+        remote_public_key: PublicKey,
+    ) -> Result<ConnPairVec, MuxClientConnectorError> {
+        let opt_open_sender = {
+            let relay_conns = self.relay_conns.lock().unwrap();
+            relay_conns
+                .conns
+                .get(&relay_address)
+                .map(|relay_conn| relay_conn.open_sender.clone())
+        };
+
+        let mut open_sender = match opt_open_sender {
+            Some(open_sender) => open_sender,
+            None => await!(self.connect_relay(relay_address))?,
+        };
+
+        // If the connection to the relay is closed, the remote side is treated as unreachable.
+        // The connection to the relay will be opened again on the next attempt.
+        let (response_sender, response_receiver) = oneshot::channel();
+        let open_channel = OpenChannel {
+            remote_public_key,
+            response_sender,
+        };
+        await!(open_sender.send(open_channel))
+            .map_err(|_| MuxClientConnectorError::RelayConnectionClosed)?;
+        await!(response_receiver).map_err(|_| MuxClientConnectorError::RelayConnectionClosed)
+    }
+}
+
+impl<A, C, FT, S> FutTransform for MuxClientConnector<A, C, FT, S>
+where
+    A: Hash + Eq + Clone + Sync + Send + 'static,
+    C: FutTransform<Input = A, Output = Option<ConnPairVec>> + Send + Sync,
+    FT: FutTransform<Input = ConnPairVec, Output = ConnPairVec> + Send,
+    S: Spawn + Clone + Send + Sync + 'static,
+{
+    type Input = (A, PublicKey);
+    type Output = Option<ConnPairVec>;
+
+    fn transform(&mut self, input: (A, PublicKey)) -> BoxFuture<'_, Self::Output> {
+        let (relay_address, remote_public_key) = input;
+        let relay_connect = self
+            .relay_connect(relay_address, remote_public_key)
+            .map(Result::ok);
+        Box::pin(relay_connect)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::ThreadPool;
+    use futures::{future, StreamExt};
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+    use proto::relay::messages::{MuxMessage, MuxOp};
+    use proto::relay::serialize::{deserialize_init_connection, deserialize_mux_message};
+
+    use common::conn::FuncFutTransform;
+    use common::dummy_connector::DummyConnector;
+
+    async fn task_mux_client_connector_shared<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + Sync + 'static,
+    {
+        let (req_sender, mut req_receiver) = mpsc::channel(0);
+        let connector = DummyConnector::new(req_sender);
+
+        // keepalive_transform does nothing:
+        let keepalive_transform = FuncFutTransform::new(|x| Box::pin(future::ready(x)));
+
+        let mux_client_connector =
+            MuxClientConnector::new(connector, keepalive_transform, spawner.clone());
+
+        let address: u32 = 15;
+        let mut relay_receiver = None;
+        // Closing all the connections would also close the connection to the relay:
+        let mut conn_pairs = Vec::new();
+        for i in 0..3u8 {
+            let mut c_mux_client_connector = mux_client_connector.clone();
+            let public_key = PublicKey::from(&[i; PUBLIC_KEY_LEN]);
+            let c_public_key = public_key.clone();
+            let fut_conn_pair = spawner
+                .spawn_with_handle(
+                    async move {
+                        await!(c_mux_client_connector.transform((address, c_public_key))).unwrap()
+                    },
+                )
+                .unwrap();
+
+            if relay_receiver.is_none() {
+                // Only the first connection attempt connects to the relay:
+                let (local_sender, mut receiver) = mpsc::channel::<Vec<u8>>(0);
+                let (relay_sender, local_receiver) = mpsc::channel::<Vec<u8>>(0);
+                let req = await!(req_receiver.next()).unwrap();
+                req.reply(Some((local_sender, local_receiver)));
+
+                let vec = await!(receiver.next()).unwrap();
+                let init_connection = deserialize_init_connection(&vec).unwrap();
+                assert_eq!(init_connection, InitConnection::ConnectMux);
+                relay_receiver = Some((relay_sender, receiver));
+            }
+
+            let (_relay_sender, receiver) = relay_receiver.as_mut().unwrap();
+            let vec = await!(receiver.next()).unwrap();
+            let mux_message = deserialize_mux_message(&vec).unwrap();
+            assert_eq!(
+                mux_message,
+                MuxMessage {
+                    channel_id: u32::from(i),
+                    op: MuxOp::Open(public_key),
+                }
+            );
+            conn_pairs.push(await!(fut_conn_pair));
+        }
+    }
+
+    #[test]
+    fn test_mux_client_connector_shared() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_mux_client_connector_shared(thread_pool.clone()));
+    }
+}
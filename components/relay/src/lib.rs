@@ -20,8 +20,12 @@ extern crate log;
 extern crate common;
 
 mod client;
+mod mux;
+#[cfg(feature = "server")]
 mod server;
 
 pub use self::client::client_connector::ClientConnector;
 pub use self::client::client_listener::ClientListener;
+pub use self::client::mux_client_connector::MuxClientConnector;
+#[cfg(feature = "server")]
 pub use self::server::net_server::{net_relay_server, NetRelayServerError};
@@ -0,0 +1,586 @@
+use std::collections::HashMap;
+use std::marker::Unpin;
+
+use futures::channel::{mpsc, oneshot};
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, stream, Sink, SinkExt, Stream, StreamExt};
+
+use common::conn::ConnPairVec;
+use common::select_streams::{select_streams, BoxStream};
+use crypto::identity::PublicKey;
+
+use proto::relay::messages::{MuxMessage, MuxOp};
+use proto::relay::serialize::{deserialize_mux_message, serialize_mux_message};
+
+/// A request to open a new channel over a multiplexed connection.
+pub struct OpenChannel {
+    pub remote_public_key: PublicKey,
+    pub response_sender: oneshot::Sender<ConnPairVec>,
+}
+
+#[derive(Debug)]
+pub enum MuxError {
+    SpawnError,
+    SendError,
+    DeserializeError,
+    UnexpectedOpen,
+    ChannelIdInUse,
+    RemoteOpensSenderError,
+}
+
+enum MuxEvent {
+    OpenChannel(OpenChannel),
+    OpenChannelsClosed,
+    Incoming(Vec<u8>),
+    IncomingClosed,
+    /// Data sent by the user of a channel. None means that the user closed the channel.
+    FromUser((u32, Option<Vec<u8>>)),
+}
+
+struct Mux<S> {
+    sender: mpsc::Sender<Vec<u8>>,
+    /// Senders to the users of the open channels
+    channels: HashMap<u32, mpsc::Sender<Vec<u8>>>,
+    next_channel_id: u32,
+    /// Maximum amount of channels open at the same time
+    max_channels: usize,
+    /// Amount of messages buffered for the user of a channel
+    channel_queue_len: usize,
+    event_sender: mpsc::Sender<MuxEvent>,
+    spawner: S,
+}
+
+impl<S> Mux<S>
+where
+    S: Spawn,
+{
+    async fn send_message(&mut self, channel_id: u32, op: MuxOp) -> Result<(), MuxError> {
+        let mux_message = MuxMessage { channel_id, op };
+        await!(self.sender.send(serialize_mux_message(&mux_message)))
+            .map_err(|_| MuxError::SendError)
+    }
+
+    /// Create a new channel. Returns the pair used by the user of the channel.
+    fn create_channel(&mut self, channel_id: u32) -> Result<ConnPairVec, MuxError> {
+        let (user_sender, from_user) = mpsc::channel(0);
+        let (to_user, user_receiver) = mpsc::channel(self.channel_queue_len);
+        self.channels.insert(channel_id, to_user);
+
+        let mut from_user = from_user
+            .map(move |data| MuxEvent::FromUser((channel_id, Some(data))))
+            .chain(stream::once(future::ready(MuxEvent::FromUser((
+                channel_id, None,
+            )))));
+        let mut event_sender = self.event_sender.clone();
+        self.spawner
+            .spawn(
+                async move {
+                    let _ = await!(event_sender.send_all(&mut from_user));
+                },
+            )
+            .map_err(|_| MuxError::SpawnError)?;
+
+        Ok((user_sender, user_receiver))
+    }
+
+    /// Pick an id for a channel opened by the local side.
+    /// Ids are not reused quickly, so that late messages of a closed channel are never mistaken
+    /// for messages of a new channel.
+    fn new_channel_id(&mut self) -> u32 {
+        while self.channels.contains_key(&self.next_channel_id) {
+            self.next_channel_id = self.next_channel_id.wrapping_add(1);
+        }
+        let channel_id = self.next_channel_id;
+        self.next_channel_id = self.next_channel_id.wrapping_add(1);
+        channel_id
+    }
+
+    async fn handle_open_channel(&mut self, open_channel: OpenChannel) -> Result<(), MuxError> {
+        let OpenChannel {
+            remote_public_key,
+            response_sender,
+        } = open_channel;
+        if self.channels.len() >= self.max_channels {
+            // Dropping response_sender notifies the requester that the channel was not opened:
+            warn!("Mux::handle_open_channel(): Too many open channels");
+            return Ok(());
+        }
+        let channel_id = self.new_channel_id();
+        let conn_pair = self.create_channel(channel_id)?;
+        await!(self.send_message(channel_id, MuxOp::Open(remote_public_key)))?;
+        if response_sender.send(conn_pair).is_err() {
+            // The requester is gone. The channel will be closed when the user pair is dropped.
+            warn!("Mux::handle_open_channel(): Requester is gone");
+        }
+        Ok(())
+    }
+
+    async fn handle_incoming<RO>(
+        &mut self,
+        data: Vec<u8>,
+        opt_remote_opens_sender: &mut Option<RO>,
+    ) -> Result<(), MuxError>
+    where
+        RO: Sink<SinkItem = (PublicKey, ConnPairVec)> + Unpin,
+    {
+        let MuxMessage { channel_id, op } =
+            deserialize_mux_message(&data).map_err(|_| MuxError::DeserializeError)?;
+        match op {
+            MuxOp::Open(remote_public_key) => {
+                let remote_opens_sender = match opt_remote_opens_sender {
+                    Some(remote_opens_sender) => remote_opens_sender,
+                    None => return Err(MuxError::UnexpectedOpen),
+                };
+                if self.channels.contains_key(&channel_id) {
+                    return Err(MuxError::ChannelIdInUse);
+                }
+                if self.channels.len() >= self.max_channels {
+                    warn!("Mux::handle_incoming(): Too many open channels");
+                    return await!(self.send_message(channel_id, MuxOp::Close));
+                }
+                let conn_pair = self.create_channel(channel_id)?;
+                await!(remote_opens_sender.send((remote_public_key, conn_pair)))
+                    .map_err(|_| MuxError::RemoteOpensSenderError)?;
+            }
+            MuxOp::Data(data) => {
+                // Data might still arrive for a channel we have already closed. We discard it.
+                let to_user = match self.channels.get_mut(&channel_id) {
+                    Some(to_user) => to_user,
+                    None => return Ok(()),
+                };
+                // We never wait for the user of a single channel, as this would block all the
+                // other channels:
+                if let Err(e) = to_user.try_send(data) {
+                    if e.is_full() {
+                        warn!("Mux::handle_incoming(): Channel queue is full. Closing channel");
+                    }
+                    // The user is not interested in this channel anymore, or can not keep up:
+                    self.channels.remove(&channel_id);
+                    await!(self.send_message(channel_id, MuxOp::Close))?;
+                }
+            }
+            MuxOp::Close => {
+                // Dropping the sender closes the channel for the user:
+                self.channels.remove(&channel_id);
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_from_user(
+        &mut self,
+        channel_id: u32,
+        opt_data: Option<Vec<u8>>,
+    ) -> Result<(), MuxError> {
+        match opt_data {
+            Some(data) => {
+                if self.channels.contains_key(&channel_id) {
+                    await!(self.send_message(channel_id, MuxOp::Data(data)))?;
+                }
+            }
+            None => {
+                if self.channels.remove(&channel_id).is_some() {
+                    await!(self.send_message(channel_id, MuxOp::Close))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run one side of a multiplexed connection: Many channels over a single connection.
+///
+/// Channels are opened by the local side through `local_opens`, or by the remote side, in which
+/// case they are sent to `opt_remote_opens_sender` together with the public key the remote side
+/// wants to connect to. If `opt_remote_opens_sender` is None, the remote side may not open
+/// channels.
+///
+/// At most `max_channels` channels may be open at the same time. Further channels are refused.
+/// Up to `channel_queue_len` incoming messages are buffered for the user of every channel. A
+/// channel whose user does not keep up is closed, so that it can not block the other channels.
+///
+/// All the channels are closed when the connection is closed.
+/// If `close_when_idle` is set, the connection is closed after its last channel is closed.
+pub async fn mux_loop<LO, RO, S>(
+    conn_pair: ConnPairVec,
+    local_opens: LO,
+    mut opt_remote_opens_sender: Option<RO>,
+    close_when_idle: bool,
+    max_channels: usize,
+    channel_queue_len: usize,
+    spawner: S,
+) -> Result<(), MuxError>
+where
+    LO: Stream<Item = OpenChannel> + Send + Unpin,
+    RO: Sink<SinkItem = (PublicKey, ConnPairVec)> + Unpin,
+    S: Spawn,
+{
+    let (sender, receiver) = conn_pair;
+    let (event_sender, event_receiver) = mpsc::channel(0);
+
+    let local_opens = local_opens
+        .map(MuxEvent::OpenChannel)
+        .chain(stream::once(future::ready(MuxEvent::OpenChannelsClosed)));
+    let receiver = receiver
+        .map(MuxEvent::Incoming)
+        .chain(stream::once(future::ready(MuxEvent::IncomingClosed)));
+    let mut mux_events = select_streams![local_opens, receiver, event_receiver];
+
+    let mut mux = Mux {
+        sender,
+        channels: HashMap::new(),
+        next_channel_id: 0,
+        max_channels,
+        channel_queue_len,
+        event_sender,
+        spawner,
+    };
+
+    while let Some(mux_event) = await!(mux_events.next()) {
+        match mux_event {
+            MuxEvent::OpenChannel(open_channel) => await!(mux.handle_open_channel(open_channel))?,
+            MuxEvent::OpenChannelsClosed => {
+                if close_when_idle && mux.channels.is_empty() {
+                    break;
+                }
+            }
+            MuxEvent::Incoming(data) => {
+                await!(mux.handle_incoming(data, &mut opt_remote_opens_sender))?;
+                if close_when_idle && mux.channels.is_empty() {
+                    break;
+                }
+            }
+            MuxEvent::IncomingClosed => break,
+            MuxEvent::FromUser((channel_id, opt_data)) => {
+                await!(mux.handle_from_user(channel_id, opt_data))?;
+                if close_when_idle && mux.channels.is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::ThreadPool;
+    use futures::task::{Spawn, SpawnExt};
+    use futures::FutureExt;
+
+    use common::int_convert::usize_to_u32;
+
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    const MAX_CHANNELS: usize = 8;
+    const CHANNEL_QUEUE_LEN: usize = 2;
+
+    async fn task_mux_loop_basic<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (client_sender, server_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (server_sender, client_receiver) = mpsc::channel::<Vec<u8>>(0);
+
+        let (mut open_sender, local_opens) = mpsc::channel(0);
+        let client_fut = mux_loop(
+            (client_sender, client_receiver),
+            local_opens,
+            None::<mpsc::Sender<(PublicKey, ConnPairVec)>>,
+            true,
+            MAX_CHANNELS,
+            CHANNEL_QUEUE_LEN,
+            spawner.clone(),
+        );
+        let client_handle = spawner.spawn_with_handle(client_fut).unwrap();
+
+        let (remote_opens_sender, mut remote_opens) = mpsc::channel(0);
+        let server_fut = mux_loop(
+            (server_sender, server_receiver),
+            stream::empty(),
+            Some(remote_opens_sender),
+            false,
+            MAX_CHANNELS,
+            CHANNEL_QUEUE_LEN,
+            spawner.clone(),
+        );
+        let server_handle = spawner.spawn_with_handle(server_fut).unwrap();
+
+        // Open two channels:
+        let mut client_pairs = Vec::new();
+        let mut server_pairs = Vec::new();
+        for i in 0..2u8 {
+            let remote_public_key = PublicKey::from(&[i; PUBLIC_KEY_LEN]);
+            let (response_sender, response_receiver) = oneshot::channel();
+            let open_channel = OpenChannel {
+                remote_public_key: remote_public_key.clone(),
+                response_sender,
+            };
+            await!(open_sender.send(open_channel)).unwrap();
+            client_pairs.push(await!(response_receiver).unwrap());
+
+            let (public_key, server_pair) = await!(remote_opens.next()).unwrap();
+            assert_eq!(public_key, remote_public_key);
+            server_pairs.push(server_pair);
+        }
+
+        // Every channel carries its own data:
+        for i in 0..2 {
+            await!(client_pairs[i].0.send(vec![i as u8; 3])).unwrap();
+            assert_eq!(await!(server_pairs[i].1.next()).unwrap(), vec![i as u8; 3]);
+
+            await!(server_pairs[i].0.send(vec![0x10 + i as u8])).unwrap();
+            assert_eq!(
+                await!(client_pairs[i].1.next()).unwrap(),
+                vec![0x10 + i as u8]
+            );
+        }
+
+        // Closing a channel on the server side closes it on the client side:
+        let (server_sender0, server_receiver0) = server_pairs.remove(0);
+        drop(server_sender0);
+        drop(server_receiver0);
+        assert!(await!(client_pairs[0].1.next()).is_none());
+
+        // The other channel is still open:
+        await!(client_pairs[1].0.send(vec![5])).unwrap();
+        assert_eq!(await!(server_pairs[0].1.next()).unwrap(), vec![5]);
+
+        // The client closes the connection after its last channel is closed:
+        drop(client_pairs);
+        await!(client_handle).unwrap();
+        assert!(await!(server_pairs[0].1.next()).is_none());
+        await!(server_handle).unwrap();
+    }
+
+    #[test]
+    fn test_mux_loop_basic() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_mux_loop_basic(thread_pool.clone()));
+    }
+
+    async fn task_mux_loop_conn_closed<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (client_sender, mut server_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (server_sender, client_receiver) = mpsc::channel::<Vec<u8>>(0);
+
+        let (mut open_sender, local_opens) = mpsc::channel(0);
+        let client_fut = mux_loop(
+            (client_sender, client_receiver),
+            local_opens,
+            None::<mpsc::Sender<(PublicKey, ConnPairVec)>>,
+            true,
+            MAX_CHANNELS,
+            CHANNEL_QUEUE_LEN,
+            spawner.clone(),
+        );
+        let client_handle = spawner.spawn_with_handle(client_fut).unwrap();
+
+        let mut client_pairs = Vec::new();
+        for i in 0..3u8 {
+            let (response_sender, response_receiver) = oneshot::channel();
+            let open_channel = OpenChannel {
+                remote_public_key: PublicKey::from(&[i; PUBLIC_KEY_LEN]),
+                response_sender,
+            };
+            await!(open_sender.send(open_channel)).unwrap();
+            client_pairs.push(await!(response_receiver).unwrap());
+
+            let mux_message = deserialize_mux_message(&await!(server_receiver.next()).unwrap());
+            assert_eq!(
+                mux_message.unwrap().op,
+                MuxOp::Open(PublicKey::from(&[i; PUBLIC_KEY_LEN]))
+            );
+        }
+
+        // The underlying connection dies:
+        drop(server_receiver);
+        drop(server_sender);
+
+        // All the channels are closed:
+        for (_sender, receiver) in &mut client_pairs {
+            assert!(await!(receiver.next()).is_none());
+        }
+        await!(client_handle).unwrap();
+    }
+
+    #[test]
+    fn test_mux_loop_conn_closed() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_mux_loop_conn_closed(thread_pool.clone()));
+    }
+
+    async fn task_mux_loop_slow_channel<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let (client_sender, mut server_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (mut server_sender, client_receiver) = mpsc::channel::<Vec<u8>>(0);
+
+        let (mut open_sender, local_opens) = mpsc::channel(0);
+        let client_fut = mux_loop(
+            (client_sender, client_receiver),
+            local_opens,
+            None::<mpsc::Sender<(PublicKey, ConnPairVec)>>,
+            true,
+            MAX_CHANNELS,
+            CHANNEL_QUEUE_LEN,
+            spawner.clone(),
+        );
+        let client_handle = spawner.spawn_with_handle(client_fut).unwrap();
+
+        let mut client_pairs = Vec::new();
+        for i in 0..2u8 {
+            let (response_sender, response_receiver) = oneshot::channel();
+            let open_channel = OpenChannel {
+                remote_public_key: PublicKey::from(&[i; PUBLIC_KEY_LEN]),
+                response_sender,
+            };
+            await!(open_sender.send(open_channel)).unwrap();
+            client_pairs.push(await!(response_receiver).unwrap());
+            let _ = await!(server_receiver.next()).unwrap();
+        }
+
+        // The user of channel 0 does not read. Its channel is closed once its queue is full:
+        let num_messages = CHANNEL_QUEUE_LEN + 2;
+        for i in 0..num_messages {
+            let mux_message = MuxMessage {
+                channel_id: 0,
+                op: MuxOp::Data(vec![i as u8]),
+            };
+            await!(server_sender.send(serialize_mux_message(&mux_message))).unwrap();
+        }
+        let mux_message = deserialize_mux_message(&await!(server_receiver.next()).unwrap());
+        assert_eq!(
+            mux_message.unwrap(),
+            MuxMessage {
+                channel_id: 0,
+                op: MuxOp::Close,
+            }
+        );
+
+        // Channel 1 was not blocked:
+        let mux_message = MuxMessage {
+            channel_id: 1,
+            op: MuxOp::Data(vec![0xaa]),
+        };
+        await!(server_sender.send(serialize_mux_message(&mux_message))).unwrap();
+        assert_eq!(await!(client_pairs[1].1.next()).unwrap(), vec![0xaa]);
+
+        // The user of channel 0 gets the queued messages, and then the channel is closed:
+        let (_sender0, receiver0) = client_pairs.remove(0);
+        let received = await!(receiver0.collect::<Vec<_>>());
+        assert!(!received.is_empty());
+        assert!(received.len() < num_messages);
+        for (i, data) in received.iter().enumerate() {
+            assert_eq!(data, &vec![i as u8]);
+        }
+
+        drop(client_pairs);
+        drop(open_sender);
+        let mux_message = deserialize_mux_message(&await!(server_receiver.next()).unwrap());
+        assert_eq!(mux_message.unwrap().op, MuxOp::Close);
+        drop(server_sender);
+        await!(client_handle).unwrap();
+    }
+
+    #[test]
+    fn test_mux_loop_slow_channel() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_mux_loop_slow_channel(thread_pool.clone()));
+    }
+
+    async fn task_mux_loop_max_channels<S>(mut spawner: S)
+    where
+        S: Spawn + Clone + Send + 'static,
+    {
+        let max_channels = 2;
+
+        // Channels opened by the local side:
+        let (client_sender, mut server_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (_server_sender, client_receiver) = mpsc::channel::<Vec<u8>>(0);
+
+        let (mut open_sender, local_opens) = mpsc::channel(0);
+        let client_fut = mux_loop(
+            (client_sender, client_receiver),
+            local_opens,
+            None::<mpsc::Sender<(PublicKey, ConnPairVec)>>,
+            true,
+            max_channels,
+            CHANNEL_QUEUE_LEN,
+            spawner.clone(),
+        );
+        spawner.spawn(client_fut.map(|_| ())).unwrap();
+
+        let mut client_pairs = Vec::new();
+        for i in 0..3u8 {
+            let (response_sender, response_receiver) = oneshot::channel();
+            let open_channel = OpenChannel {
+                remote_public_key: PublicKey::from(&[i; PUBLIC_KEY_LEN]),
+                response_sender,
+            };
+            await!(open_sender.send(open_channel)).unwrap();
+            if usize::from(i) < max_channels {
+                client_pairs.push(await!(response_receiver).unwrap());
+                let _ = await!(server_receiver.next()).unwrap();
+            } else {
+                // The channel is refused:
+                assert!(await!(response_receiver).is_err());
+            }
+        }
+
+        // Channels opened by the remote side:
+        let (server_sender, mut client_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (mut client_sender, server_receiver) = mpsc::channel::<Vec<u8>>(0);
+
+        let (remote_opens_sender, mut remote_opens) = mpsc::channel(0);
+        let server_fut = mux_loop(
+            (server_sender, server_receiver),
+            stream::empty(),
+            Some(remote_opens_sender),
+            false,
+            max_channels,
+            CHANNEL_QUEUE_LEN,
+            spawner.clone(),
+        );
+        let server_handle = spawner.spawn_with_handle(server_fut).unwrap();
+
+        let mut server_pairs = Vec::new();
+        for i in 0..max_channels {
+            let mux_message = MuxMessage {
+                channel_id: usize_to_u32(i).unwrap(),
+                op: MuxOp::Open(PublicKey::from(&[0x11; PUBLIC_KEY_LEN])),
+            };
+            await!(client_sender.send(serialize_mux_message(&mux_message))).unwrap();
+            server_pairs.push(await!(remote_opens.next()).unwrap());
+        }
+
+        // The channel is refused:
+        let mux_message = MuxMessage {
+            channel_id: 0x100,
+            op: MuxOp::Open(PublicKey::from(&[0x11; PUBLIC_KEY_LEN])),
+        };
+        await!(client_sender.send(serialize_mux_message(&mux_message))).unwrap();
+        let mux_message = deserialize_mux_message(&await!(client_receiver.next()).unwrap());
+        assert_eq!(
+            mux_message.unwrap(),
+            MuxMessage {
+                channel_id: 0x100,
+                op: MuxOp::Close,
+            }
+        );
+
+        drop(client_sender);
+        await!(server_handle).unwrap();
+    }
+
+    #[test]
+    fn test_mux_loop_max_channels() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_mux_loop_max_channels(thread_pool.clone()));
+    }
+}
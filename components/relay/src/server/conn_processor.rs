@@ -1,7 +1,9 @@
 use std::marker::Unpin;
 
 use futures::channel::mpsc;
-use futures::{future, Sink, SinkExt, Stream, StreamExt};
+use futures::sink::SinkMapErr;
+use futures::task::{Spawn, SpawnExt};
+use futures::{future, stream, FutureExt, Sink, SinkExt, Stream, StreamExt};
 
 use common::conn::{ConnPairVec, FutTransform};
 
@@ -9,98 +11,151 @@ use crypto::identity::PublicKey;
 use timer::utils::future_timeout;
 use timer::TimerClient;
 
+use crate::mux::mux_loop;
+
 use super::types::{
     IncomingAccept, IncomingConn, IncomingConnInner, IncomingConnect, IncomingListen,
 };
+use proto::consts::{MAX_MUX_CHANNELS, MUX_CHANNEL_QUEUE_LEN};
 use proto::relay::messages::{IncomingConnection, InitConnection, RejectConnection};
 use proto::relay::serialize::{
     deserialize_init_connection, deserialize_reject_connection, serialize_incoming_connection,
 };
 
-async fn dispatch_conn<FT>(
+/// The sender of a Connect connection.
+/// Connect connections multiplexed over a single connection have the same type.
+type ConnectSender = SinkMapErr<mpsc::Sender<Vec<u8>>, fn(mpsc::SendError)>;
+
+fn connect_sender(sender: mpsc::Sender<Vec<u8>>) -> ConnectSender {
+    let map_err: fn(mpsc::SendError) = |_| ();
+    sender.sink_map_err(map_err)
+}
+
+/// A Connect connection multiplexed over a single connection:
+/// (public_key, connect_public_key, conn_pair)
+type MuxConnect = (PublicKey, PublicKey, ConnPairVec);
+
+async fn dispatch_conn<FT, S>(
     sender: mpsc::Sender<Vec<u8>>,
     receiver: mpsc::Receiver<Vec<u8>>,
     public_key: PublicKey,
     first_msg: Vec<u8>,
     mut keepalive_transform: FT,
+    mux_connects_sender: mpsc::Sender<MuxConnect>,
+    mut spawner: S,
 ) -> Option<
     IncomingConn<
         impl Stream<Item = RejectConnection> + Unpin,
         impl Sink<SinkItem = IncomingConnection, SinkError = ()> + Unpin,
         impl Stream<Item = Vec<u8>> + Unpin,
         impl Sink<SinkItem = Vec<u8>, SinkError = ()> + Unpin,
-        impl Stream<Item = Vec<u8>> + Unpin,
-        impl Sink<SinkItem = Vec<u8>, SinkError = ()> + Unpin,
+        mpsc::Receiver<Vec<u8>>,
+        ConnectSender,
     >,
 >
 where
     FT: FutTransform<Input = ConnPairVec, Output = ConnPairVec>,
+    S: Spawn + Clone + Send + 'static,
 {
     let (sender, receiver) = await!(keepalive_transform.transform((sender, receiver)));
 
-    let sender = sender.sink_map_err(|_| ());
-    let inner = match deserialize_init_connection(&first_msg).ok()? {
+    let init_connection = match deserialize_init_connection(&first_msg) {
+        Ok(init_connection) => init_connection,
+        Err(_) => {
+            warn!("dispatch_conn(): Invalid first message");
+            return None;
+        }
+    };
+
+    let inner = match init_connection {
         InitConnection::Listen => IncomingConnInner::Listen(IncomingListen {
             receiver: receiver
                 .map(|data| deserialize_reject_connection(&data))
                 .take_while(|res| future::ready(res.is_ok()))
                 .map(Result::unwrap),
-            sender: sender.with(|msg| future::ready(Ok(serialize_incoming_connection(&msg)))),
+            sender: connect_sender(sender)
+                .with(|msg| future::ready(Ok(serialize_incoming_connection(&msg)))),
         }),
         InitConnection::Accept(accept_public_key) => IncomingConnInner::Accept(IncomingAccept {
             receiver,
-            sender,
+            sender: connect_sender(sender),
             accept_public_key,
         }),
         InitConnection::Connect(connect_public_key) => {
             IncomingConnInner::Connect(IncomingConnect {
                 receiver,
-                sender,
+                sender: connect_sender(sender),
                 connect_public_key,
             })
         }
+        InitConnection::ConnectMux => {
+            // Every channel opened by the remote side is a separate Connect connection:
+            let c_public_key = public_key.clone();
+            let remote_opens_sender = mux_connects_sender.with(
+                move |(connect_public_key, conn_pair): (PublicKey, ConnPairVec)| {
+                    let mux_connect = (c_public_key.clone(), connect_public_key, conn_pair);
+                    future::ready(Ok::<_, mpsc::SendError>(mux_connect))
+                },
+            );
+            let mux_fut = mux_loop(
+                (sender, receiver),
+                stream::empty(),
+                Some(remote_opens_sender),
+                false,
+                MAX_MUX_CHANNELS,
+                MUX_CHANNEL_QUEUE_LEN,
+                spawner.clone(),
+            )
+            .map(|res| {
+                if let Err(e) = res {
+                    warn!("dispatch_conn(): mux_loop() error: {:?}", e);
+                }
+            });
+            if spawner.spawn(mux_fut).is_err() {
+                error!("dispatch_conn(): Failed to spawn mux_loop()");
+            }
+            return None;
+        }
     };
 
     Some(IncomingConn { public_key, inner })
 }
 
-async fn process_conn<FT>(
+async fn process_conn<FT, S>(
     sender: mpsc::Sender<Vec<u8>>,
     mut receiver: mpsc::Receiver<Vec<u8>>,
     public_key: PublicKey,
     keepalive_transform: FT,
     mut timer_client: TimerClient,
     conn_timeout_ticks: usize,
+    mux_connects_sender: mpsc::Sender<MuxConnect>,
+    spawner: S,
 ) -> Option<
     IncomingConn<
         impl Stream<Item = RejectConnection> + Unpin,
         impl Sink<SinkItem = IncomingConnection, SinkError = ()> + Unpin,
         impl Stream<Item = Vec<u8>> + Unpin,
         impl Sink<SinkItem = Vec<u8>, SinkError = ()> + Unpin,
-        impl Stream<Item = Vec<u8>> + Unpin,
-        impl Sink<SinkItem = Vec<u8>, SinkError = ()> + Unpin,
+        mpsc::Receiver<Vec<u8>>,
+        ConnectSender,
     >,
 >
 where
     FT: FutTransform<Input = ConnPairVec, Output = ConnPairVec>,
+    S: Spawn + Clone + Send + 'static,
 {
     let fut_receiver = Box::pin(
         async move {
-            if let Some(first_msg) = await!(receiver.next()) {
-                let dispatch_res = await!(dispatch_conn(
-                    sender,
-                    receiver,
-                    public_key,
-                    first_msg,
-                    keepalive_transform
-                ));
-                if dispatch_res.is_none() {
-                    warn!("process_conn(): dispatch_conn() failure");
-                }
-                dispatch_res
-            } else {
-                None
-            }
+            let first_msg = await!(receiver.next())?;
+            await!(dispatch_conn(
+                sender,
+                receiver,
+                public_key,
+                first_msg,
+                keepalive_transform,
+                mux_connects_sender,
+                spawner
+            ))
         },
     );
 
@@ -120,11 +175,14 @@ where
 /// For each connection obtain the first message, and prepare the correct type according to this
 /// first messages.
 /// If waiting for the first message takes too long, discard the connection.
-pub fn conn_processor<T, FT>(
+///
+/// Every channel of a multiplexed connection is processed as a separate Connect connection.
+pub fn conn_processor<T, FT, S>(
     incoming_conns: T,
     keepalive_transform: FT,
     timer_client: TimerClient,
     conn_timeout_ticks: usize,
+    spawner: S,
 ) -> impl Stream<
     Item = IncomingConn<
         impl Stream<Item = RejectConnection>,
@@ -138,8 +196,11 @@ pub fn conn_processor<T, FT>(
 where
     T: Stream<Item = (PublicKey, ConnPairVec)> + Unpin,
     FT: FutTransform<Input = ConnPairVec, Output = ConnPairVec> + Clone,
+    S: Spawn + Clone + Send + 'static,
 {
-    incoming_conns
+    let (mux_connects_sender, mux_connects) = mpsc::channel::<MuxConnect>(0);
+
+    let processed_conns = incoming_conns
         .map(move |(public_key, (sender, receiver))| {
             process_conn(
                 sender,
@@ -148,9 +209,31 @@ where
                 keepalive_transform.clone(),
                 timer_client.clone(),
                 conn_timeout_ticks,
+                mux_connects_sender.clone(),
+                spawner.clone(),
             )
         })
-        .filter_map(|opt_conn| opt_conn)
+        .filter_map(|opt_conn| opt_conn);
+
+    let mux_connects = mux_connects.map(|(public_key, connect_public_key, (sender, receiver))| {
+        Some(IncomingConn {
+            public_key,
+            inner: IncomingConnInner::Connect(IncomingConnect {
+                receiver,
+                sender: connect_sender(sender),
+                connect_public_key,
+            }),
+        })
+    });
+
+    // We stop when the incoming connections are closed, even if multiplexed connections are
+    // still open:
+    processed_conns
+        .map(Some)
+        .chain(stream::once(future::ready(None)))
+        .select(mux_connects)
+        .take_while(|opt_conn| future::ready(opt_conn.is_some()))
+        .map(Option::unwrap)
 }
 
 #[cfg(test)]
@@ -167,9 +250,10 @@ mod tests {
     use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
     use timer::create_timer_incoming;
 
-    use proto::relay::serialize::serialize_init_connection;
+    use proto::relay::messages::{MuxMessage, MuxOp};
+    use proto::relay::serialize::{serialize_init_connection, serialize_mux_message};
 
-    async fn task_dispatch_conn_basic(spawner: impl Spawn + Clone) {
+    async fn task_dispatch_conn_basic(spawner: impl Spawn + Clone + Send + 'static) {
         // Create a mock time service:
         let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
         let _timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
@@ -184,7 +268,9 @@ mod tests {
             receiver,
             public_key.clone(),
             ser_first_msg,
-            keepalive_transform
+            keepalive_transform,
+            mpsc::channel(0).0,
+            spawner.clone()
         ))
         .unwrap();
 
@@ -205,7 +291,9 @@ mod tests {
             receiver,
             public_key.clone(),
             ser_first_msg,
-            keepalive_transform
+            keepalive_transform,
+            mpsc::channel(0).0,
+            spawner.clone()
         ))
         .unwrap();
 
@@ -228,7 +316,9 @@ mod tests {
             receiver,
             public_key.clone(),
             ser_first_msg,
-            keepalive_transform
+            keepalive_transform,
+            mpsc::channel(0).0,
+            spawner.clone()
         ))
         .unwrap();
 
@@ -247,7 +337,7 @@ mod tests {
         thread_pool.run(task_dispatch_conn_basic(thread_pool.clone()));
     }
 
-    async fn task_dispatch_conn_invalid_first_msg(spawner: impl Spawn + Clone) {
+    async fn task_dispatch_conn_invalid_first_msg(spawner: impl Spawn + Clone + Send + 'static) {
         // Create a mock time service:
         let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
         let _timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
@@ -261,7 +351,9 @@ mod tests {
             receiver,
             public_key.clone(),
             ser_first_msg,
-            keepalive_transform
+            keepalive_transform,
+            mpsc::channel(0).0,
+            spawner.clone()
         ));
         assert!(res.is_none());
     }
@@ -295,6 +387,7 @@ mod tests {
             keepalive_transform,
             timer_client,
             conn_timeout_ticks,
+            thread_pool.clone(),
         );
 
         let processed_conns = Box::pin(processed_conns);
@@ -323,4 +416,67 @@ mod tests {
 
         assert!(thread_pool.run(receive(processed_conns)).is_none());
     }
+
+    async fn task_conn_processor_mux(spawner: impl Spawn + Clone + Send + 'static) {
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let public_key = PublicKey::from(&[0x77; PUBLIC_KEY_LEN]);
+        let (local_sender, _remote_receiver) = mpsc::channel::<Vec<u8>>(0);
+        let (mut remote_sender, local_receiver) = mpsc::channel::<Vec<u8>>(0);
+
+        // Multiplexed connections are only processed while incoming connections are open:
+        let (mut conns_sender, incoming_conns) = mpsc::channel(0);
+
+        let conn_timeout_ticks = 16;
+        let keepalive_transform = FuncFutTransform::new(|x| Box::pin(future::ready(x)));
+        let mut processed_conns = Box::pin(conn_processor(
+            incoming_conns,
+            keepalive_transform,
+            timer_client,
+            conn_timeout_ticks,
+            spawner.clone(),
+        ));
+
+        await!(conns_sender.send((public_key.clone(), (local_sender, local_receiver)))).unwrap();
+        let ser_first_msg = serialize_init_connection(&InitConnection::ConnectMux);
+        await!(remote_sender.send(ser_first_msg)).unwrap();
+
+        // Every opened channel is a separate Connect connection:
+        let mut incoming_connects = Vec::new();
+        for i in 0..2u8 {
+            let connect_public_key = PublicKey::from(&[i; PUBLIC_KEY_LEN]);
+            let mux_message = MuxMessage {
+                channel_id: u32::from(i),
+                op: MuxOp::Open(connect_public_key.clone()),
+            };
+            await!(remote_sender.send(serialize_mux_message(&mux_message))).unwrap();
+
+            let conn = await!(processed_conns.next()).unwrap();
+            assert_eq!(conn.public_key, public_key);
+            match conn.inner {
+                IncomingConnInner::Connect(incoming_connect) => {
+                    assert_eq!(incoming_connect.connect_public_key, connect_public_key);
+                    incoming_connects.push(incoming_connect);
+                }
+                _ => panic!("Incorrect processed conn"),
+            };
+        }
+
+        // Data sent over a channel arrives to its Connect connection:
+        let mux_message = MuxMessage {
+            channel_id: 1,
+            op: MuxOp::Data(vec![1, 2, 3]),
+        };
+        await!(remote_sender.send(serialize_mux_message(&mux_message))).unwrap();
+        let data = await!(incoming_connects[1].receiver.next()).unwrap();
+        assert_eq!(data, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_conn_processor_mux() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool.run(task_conn_processor_mux(thread_pool.clone()));
+    }
 }
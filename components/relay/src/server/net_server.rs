@@ -2,7 +2,7 @@ use std::marker::Unpin;
 
 use futures::channel::mpsc;
 use futures::task::{Spawn, SpawnExt};
-use futures::{FutureExt, Stream, StreamExt, TryFutureExt};
+use futures::{stream, FutureExt, Stream, StreamExt, TryFutureExt};
 
 use derive_more::*;
 
@@ -24,6 +24,7 @@ use version::VersionPrefix;
 use super::conn_processor::conn_processor;
 use super::server::relay_server_loop;
 pub use super::server::RelayServerError;
+use super::stats::{StatsRequest, MAX_STATS_KEYS};
 
 /// A relay server loop. Incoming connections should contain both (sender, receiver) and a
 /// public_key of the remote side (Should be obtained after authentication).
@@ -52,6 +53,7 @@ where
         keepalive_transform,
         timer_client.clone(),
         conn_timeout_ticks,
+        spawner.clone(),
     ));
 
     // TODO:
@@ -62,10 +64,15 @@ where
     assert!(half_tunnel_ticks < keepalive_ticks);
     assert!(half_tunnel_ticks > 0);
 
+    // Statistics are only written to the log for now:
+    let stats_requests = stream::empty::<StatsRequest>();
+
     await!(relay_server_loop(
         timer_client,
         processed_conns,
+        stats_requests,
         half_tunnel_ticks,
+        MAX_STATS_KEYS,
         spawner
     ))
 }
@@ -124,6 +131,9 @@ where
         rng,
         timer_client.clone(),
         TICKS_TO_REKEY,
+        None, // max_messages_before_rekey
+        0,    // incoming_queue_len
+        None, // opt_resume_ticks
         spawner.clone(),
     );
 
@@ -6,12 +6,14 @@ use std::fmt;
 use std::marker::Unpin;
 
 use common::futures_compat::send_to_sink;
+use common::int_convert::usize_to_u64;
 use common::select_streams::{select_streams, BoxStream};
 use crypto::identity::PublicKey;
 use timer::TimerClient;
 
 use proto::relay::messages::{IncomingConnection, RejectConnection};
 
+use super::stats::{RejectReason, RelayStats, StatsEvent, StatsRequest, STATS_SUMMARY_TICKS};
 use super::types::{IncomingAccept, IncomingConn, IncomingConnInner};
 
 struct ConnPair<M, K> {
@@ -51,12 +53,21 @@ struct TunnelClosed {
     listen_public_key: PublicKey,
 }
 
+/// Bytes forwarded through one direction of a tunnel, reported when this direction is closed.
+struct TunnelBytes {
+    from_public_key: PublicKey,
+    to_public_key: PublicKey,
+    num_bytes: u64,
+}
+
 enum RelayServerEvent<ML, KL, MA, KA, MC, KC> {
     IncomingConn(IncomingConn<ML, KL, MA, KA, MC, KC>),
     IncomingConnsClosed,
     TunnelClosed(TunnelClosed),
+    TunnelBytes(TunnelBytes),
     ListenerMessage((PublicKey, RejectConnection)),
     ListenerClosed(PublicKey),
+    StatsRequest(StatsRequest),
     TimerTick,
     TimerClosed,
 }
@@ -69,8 +80,10 @@ impl<ML, KL, MA, KA, MC, KC> fmt::Debug for RelayServerEvent<ML, KL, MA, KA, MC,
                 write!(f, "RelayServerEvent::IncomingConnsClosed")
             }
             RelayServerEvent::TunnelClosed(_) => write!(f, "RelayServerEvent::TunnelClosed"),
+            RelayServerEvent::TunnelBytes(_) => write!(f, "RelayServerEvent::TunnelBytes"),
             RelayServerEvent::ListenerMessage(_) => write!(f, "RelayServerEvent::ListenerMessage"),
             RelayServerEvent::ListenerClosed(_) => write!(f, "RelayServerEvent::ListenerClosed"),
+            RelayServerEvent::StatsRequest(_) => write!(f, "RelayServerEvent::StatsRequest"),
             RelayServerEvent::TimerTick => write!(f, "RelayServerEvent::TimerTick"),
             RelayServerEvent::TimerClosed => write!(f, "RelayServerEvent::TimerClosed"),
         }
@@ -95,6 +108,7 @@ fn handle_accept<MT, KT, MA, KA, TCL>(
     incoming_accept: IncomingAccept<MA, KA>,
     // TODO: This should be a oneshot:
     tunnel_closed_sender: TCL,
+    tunnel_bytes_sender: mpsc::Sender<TunnelBytes>,
     mut spawner: impl Spawn,
 ) -> Result<(), RelayServerError>
 where
@@ -109,7 +123,7 @@ where
         None => return Err(RelayServerError::ListeningNotInProgress),
     };
     let IncomingAccept {
-        mut receiver,
+        receiver,
         mut sender,
         accept_public_key,
     } = incoming_accept;
@@ -118,29 +132,50 @@ where
         None => return Err(RelayServerError::NoPendingHalfTunnel),
     };
     let c_accept_public_key = accept_public_key.clone();
+    let c_acceptor_public_key = acceptor_public_key.clone();
+    let c_tunnel_bytes_sender = tunnel_bytes_sender.clone();
 
     let ConnPair {
         sender: mut remote_sender,
-        receiver: mut remote_receiver,
+        receiver: remote_receiver,
     } = conn_pair;
 
     let send_fut1 = async move {
-        await!(remote_sender
-            .send_all(&mut receiver)
-            .map_err(|e| error!("send_fut1 error: {:?}", e))
-            .then(|_| future::ready(())))
+        let mut num_bytes = 0;
+        {
+            let mut receiver = receiver.inspect(|data| num_bytes += data.len());
+            await!(remote_sender
+                .send_all(&mut receiver)
+                .map_err(|e| error!("send_fut1 error: {:?}", e))
+                .then(|_| future::ready(())));
+        }
+        let tunnel_bytes = TunnelBytes {
+            from_public_key: c_acceptor_public_key,
+            to_public_key: c_accept_public_key,
+            num_bytes: usize_to_u64(num_bytes).unwrap(),
+        };
+        let _ = await!(send_to_sink(c_tunnel_bytes_sender, tunnel_bytes));
     };
     let send_fut2 = async move {
-        await!(sender
-            .send_all(&mut remote_receiver)
-            .map_err(|e| error!("send_fut2 error: {:?}", e))
-            .then(move |_| {
-                let tunnel_closed = TunnelClosed {
-                    init_public_key: c_accept_public_key,
-                    listen_public_key: acceptor_public_key,
-                };
-                send_to_sink(tunnel_closed_sender, tunnel_closed).then(|_| future::ready(()))
-            }))
+        let mut num_bytes = 0;
+        {
+            let mut remote_receiver = remote_receiver.inspect(|data| num_bytes += data.len());
+            await!(sender
+                .send_all(&mut remote_receiver)
+                .map_err(|e| error!("send_fut2 error: {:?}", e))
+                .then(|_| future::ready(())));
+        }
+        let tunnel_bytes = TunnelBytes {
+            from_public_key: accept_public_key.clone(),
+            to_public_key: acceptor_public_key.clone(),
+            num_bytes: usize_to_u64(num_bytes).unwrap(),
+        };
+        let _ = await!(send_to_sink(tunnel_bytes_sender, tunnel_bytes));
+        let tunnel_closed = TunnelClosed {
+            init_public_key: accept_public_key,
+            listen_public_key: acceptor_public_key,
+        };
+        let _ = await!(send_to_sink(tunnel_closed_sender, tunnel_closed));
     };
 
     spawner.spawn(send_fut1).unwrap();
@@ -149,10 +184,12 @@ where
     Ok(())
 }
 
-pub async fn relay_server_loop<ML, KL, MA, KA, MC, KC, S>(
+pub async fn relay_server_loop<ML, KL, MA, KA, MC, KC, S, SR>(
     mut timer_client: TimerClient,
     incoming_conns: S,
+    stats_requests: SR,
     half_tunnel_ticks: usize,
+    max_stats_keys: usize,
     mut spawner: impl Spawn + Clone,
 ) -> Result<(), RelayServerError>
 where
@@ -163,6 +200,7 @@ where
     MC: Stream<Item = Vec<u8>> + Unpin + Send + 'static,
     KC: Sink<SinkItem = Vec<u8>, SinkError = ()> + Unpin + Send + 'static,
     S: Stream<Item = IncomingConn<ML, KL, MA, KA, MC, KC>> + Unpin + Send,
+    SR: Stream<Item = StatsRequest> + Unpin + Send,
 {
     let timer_stream = await!(timer_client.request_timer_stream())
         .map_err(|_| RelayServerError::RequestTimerStreamError)?;
@@ -176,12 +214,25 @@ where
             RelayServerEvent::IncomingConnsClosed,
         )));
 
+    let stats_requests = stats_requests.map(RelayServerEvent::StatsRequest);
+
     let (event_sender, event_receiver) = mpsc::channel::<RelayServerEvent<_, _, _, _, _, _>>(0);
 
-    let mut relay_server_events = select_streams![timer_stream, incoming_conns, event_receiver];
+    let (tunnel_bytes_sender, tunnel_bytes_receiver) = mpsc::channel::<TunnelBytes>(0);
+    let tunnel_bytes_receiver = tunnel_bytes_receiver.map(RelayServerEvent::TunnelBytes);
+
+    let mut relay_server_events = select_streams![
+        timer_stream,
+        incoming_conns,
+        event_receiver,
+        tunnel_bytes_receiver,
+        stats_requests
+    ];
 
     let mut incoming_conns_closed = false;
     let mut listeners: HashMap<PublicKey, Listener<_, _>> = HashMap::new();
+    let mut relay_stats = RelayStats::new(max_stats_keys);
+    let mut ticks_to_summary = STATS_SUMMARY_TICKS;
 
     while let Some(relay_server_event) = await!(relay_server_events.next()) {
         let c_event_sender = event_sender.clone().sink_map_err(|_| ());
@@ -191,7 +242,10 @@ where
                 match inner {
                     IncomingConnInner::Listen(incoming_listen) => {
                         if listeners.contains_key(&public_key) {
-                            continue; // Discard Listen connection
+                            // Discard Listen connection:
+                            let reject_reason = RejectReason::AlreadyListening;
+                            relay_stats.apply(&StatsEvent::Reject((public_key, reject_reason)));
+                            continue;
                         }
 
                         let sender = incoming_listen.sender;
@@ -213,10 +267,18 @@ where
                             .unwrap();
                         let listener = Listener::new(mpsc_sender);
                         listeners.insert(public_key.clone(), listener);
+                        relay_stats.apply(&StatsEvent::ConnOpened(public_key.clone()));
                         let c_public_key = public_key.clone();
                         let mut receiver = receiver
                             .map(move |reject_connection| {
-                                RelayServerEvent::ListenerMessage((
+                                RelayServerEvent::TunnelBytes(tunnel_bytes) => {
+                relay_stats.apply(&StatsEvent::Bytes((
+                    tunnel_bytes.from_public_key,
+                    tunnel_bytes.to_public_key,
+                    tunnel_bytes.num_bytes,
+                )));
+            }
+            RelayServerEvent::ListenerMessage((
                                     c_public_key.clone(),
                                     reject_connection,
                                 ))
@@ -239,24 +301,46 @@ where
                         let tunnel_closed_sender = c_event_sender.with(|tunnel_closed| {
                             future::ready(Ok(RelayServerEvent::TunnelClosed(tunnel_closed)))
                         });
-                        let _ = handle_accept(
+                        let accept_public_key = incoming_accept.accept_public_key.clone();
+                        let res = handle_accept(
                             &mut listeners,
                             public_key.clone(),
                             incoming_accept,
                             tunnel_closed_sender,
+                            tunnel_bytes_sender.clone(),
                             spawner.clone(),
-                        )
-                        .map_err(|e| warn!("handle_accept() error: {:?}", e));
+                        );
+                        match res {
+                            Ok(()) => {
+                                relay_stats.apply(&StatsEvent::RegistrationRemoved(
+                                    accept_public_key.clone(),
+                                ));
+                                relay_stats.apply(&StatsEvent::ConnOpened(accept_public_key));
+                                relay_stats.apply(&StatsEvent::ConnOpened(public_key));
+                            }
+                            Err(e) => {
+                                warn!("handle_accept() error: {:?}", e);
+                                let reject_reason = RejectReason::NoPendingConnect;
+                                relay_stats.apply(&StatsEvent::Reject((public_key, reject_reason)));
+                            }
+                        }
                     }
                     IncomingConnInner::Connect(incoming_connect) => {
                         let listener = match listeners.get_mut(&incoming_connect.connect_public_key)
                         {
                             Some(listener) => listener,
-                            None => continue, // Discard Connect connection
+                            None => {
+                                // Discard Connect connection:
+                                let reject_reason = RejectReason::NotListening;
+                                relay_stats.apply(&StatsEvent::Reject((public_key, reject_reason)));
+                                continue;
+                            }
                         };
                         if listener.half_tunnels.contains_key(&public_key)
                             || listener.tunnels.contains(&public_key)
                         {
+                            let reject_reason = RejectReason::DuplicateConnect;
+                            relay_stats.apply(&StatsEvent::Reject((public_key, reject_reason)));
                             continue;
                         }
 
@@ -267,21 +351,30 @@ where
                             ),
                             ticks_to_close: half_tunnel_ticks,
                         };
-                        if let Some(sender) = &mut listener.opt_sender {
-                            // Try to send a message to listener about new pending connection:
-                            if let Ok(()) = sender.try_send(IncomingConnection {
-                                public_key: public_key.clone(),
-                            }) {
-                                listener
-                                    .half_tunnels
-                                    .insert(public_key.clone(), half_tunnel);
+                        let reject_reason = match &mut listener.opt_sender {
+                            Some(sender) => {
+                                // Try to send a message to listener about new pending connection:
+                                if let Ok(()) = sender.try_send(IncomingConnection {
+                                    public_key: public_key.clone(),
+                                }) {
+                                    listener
+                                        .half_tunnels
+                                        .insert(public_key.clone(), half_tunnel);
+                                    relay_stats.apply(&StatsEvent::RegistrationAdded(public_key));
+                                    continue;
+                                }
+                                RejectReason::ListenerBusy
                             }
-                        }
+                            None => RejectReason::NotListening,
+                        };
+                        relay_stats.apply(&StatsEvent::Reject((public_key, reject_reason)));
                     }
                 }
             }
             RelayServerEvent::IncomingConnsClosed => incoming_conns_closed = true,
             RelayServerEvent::TunnelClosed(tunnel_closed) => {
+                relay_stats.apply(&StatsEvent::ConnClosed(tunnel_closed.init_public_key.clone()));
+                relay_stats.apply(&StatsEvent::ConnClosed(tunnel_closed.listen_public_key.clone()));
                 let listener = match listeners.get_mut(&tunnel_closed.listen_public_key) {
                     Some(listener) => listener,
                     None => continue,
@@ -301,7 +394,13 @@ where
                     Some(listener) => listener,
                     None => continue,
                 };
-                let _ = listener.half_tunnels.remove(&rejected_public_key);
+                if listener.half_tunnels.remove(&rejected_public_key).is_some() {
+                    relay_stats.apply(&StatsEvent::RegistrationRemoved(
+                        rejected_public_key.clone(),
+                    ));
+                    let reject_reason = RejectReason::RejectedByListener;
+                    relay_stats.apply(&StatsEvent::Reject((rejected_public_key, reject_reason)));
+                }
             }
             RelayServerEvent::ListenerClosed(public_key) => {
                 let listener = match listeners.get_mut(&public_key) {
@@ -309,21 +408,49 @@ where
                     None => continue,
                 };
                 listener.opt_sender = None;
+                for init_public_key in listener.half_tunnels.keys() {
+                    relay_stats.apply(&StatsEvent::RegistrationRemoved(init_public_key.clone()));
+                }
                 listener.half_tunnels = HashMap::new();
+                relay_stats.apply(&StatsEvent::ConnClosed(public_key.clone()));
                 if listener.tunnels.is_empty() {
                     listeners.remove(&public_key);
                 }
             }
+            RelayServerEvent::StatsRequest(response_sender) => {
+                let _ = response_sender.send(relay_stats.summary());
+            }
             RelayServerEvent::TimerTick => {
                 // Remove old half tunnels:
                 for listener in listeners.values_mut() {
-                    listener
-                        .half_tunnels
-                        .retain(|_init_public_key, half_tunnel| {
-                            half_tunnel.ticks_to_close =
-                                half_tunnel.ticks_to_close.saturating_sub(1);
-                            half_tunnel.ticks_to_close > 0
-                        });
+                    listener.half_tunnels.retain(|init_public_key, half_tunnel| {
+                        half_tunnel.ticks_to_close = half_tunnel.ticks_to_close.saturating_sub(1);
+                        if half_tunnel.ticks_to_close > 0 {
+                            return true;
+                        }
+                        relay_stats.apply(&StatsEvent::RegistrationRemoved(
+                            init_public_key.clone(),
+                        ));
+                        let reject_reason = RejectReason::Expired;
+                        relay_stats.apply(&StatsEvent::Reject((
+                            init_public_key.clone(),
+                            reject_reason,
+                        )));
+                        false
+                    });
+                }
+
+                // Write a summary of the statistics to the log once in a while:
+                ticks_to_summary = ticks_to_summary.saturating_sub(1);
+                if ticks_to_summary == 0 {
+                    ticks_to_summary = STATS_SUMMARY_TICKS;
+                    let summary = relay_stats.summary();
+                    info!(
+                        "Relay stats: keys: {}, total: {:?}, rejects: {:?}",
+                        summary.keys.len(),
+                        summary.total(),
+                        summary.rejects
+                    );
                 }
             }
             RelayServerEvent::TimerClosed => break,
@@ -342,7 +469,9 @@ mod tests {
     use futures::executor::ThreadPool;
     use futures::task::{Spawn, SpawnExt};
 
+    use super::super::stats::{RelayStatsSummary, MAX_STATS_KEYS};
     use super::super::types::{IncomingAccept, IncomingConnect, IncomingListen};
+    use futures::channel::oneshot;
     use crypto::identity::{PublicKey, PUBLIC_KEY_LEN};
     use timer::create_timer_incoming;
 
@@ -360,7 +489,9 @@ mod tests {
         let fut_relay_server = relay_server_loop(
             timer_client,
             incoming_conns,
+            stream::empty::<StatsRequest>(),
             half_tunnel_ticks,
+            MAX_STATS_KEYS,
             spawner.clone(),
         );
 
@@ -477,7 +608,9 @@ mod tests {
         let fut_relay_server = relay_server_loop(
             timer_client,
             incoming_conns,
+            stream::empty::<StatsRequest>(),
             half_tunnel_ticks,
+            MAX_STATS_KEYS,
             spawner.clone(),
         );
 
@@ -582,6 +715,164 @@ mod tests {
             .unwrap();
     }
 
+    /// Maximum amount of times we request statistics until they reach an expected state
+    const MAX_STATS_ATTEMPTS: usize = 0x1000;
+
+    /// Request statistics from the relay server until they satisfy a predicate.
+    async fn wait_for_stats<F>(
+        stats_requests_sender: &mut mpsc::Sender<StatsRequest>,
+        pred: F,
+    ) -> RelayStatsSummary
+    where
+        F: Fn(&RelayStatsSummary) -> bool,
+    {
+        for _ in 0..MAX_STATS_ATTEMPTS {
+            let (response_sender, response_receiver) = oneshot::channel();
+            await!(stats_requests_sender.send(response_sender)).unwrap();
+            let summary = await!(response_receiver).unwrap();
+            if pred(&summary) {
+                return summary;
+            }
+        }
+        unreachable!();
+    }
+
+    async fn task_relay_server_stats(
+        mut spawner: impl Spawn + Clone + Send + 'static,
+    ) -> Result<(), ()> {
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, spawner.clone()).unwrap();
+
+        let (mut outgoing_conns, incoming_conns) = mpsc::channel::<_>(0);
+        let (mut stats_requests_sender, stats_requests) = mpsc::channel::<StatsRequest>(0);
+
+        let half_tunnel_ticks: usize = 16;
+
+        let fut_relay_server = relay_server_loop(
+            timer_client,
+            incoming_conns,
+            stats_requests,
+            half_tunnel_ticks,
+            MAX_STATS_KEYS,
+            spawner.clone(),
+        );
+
+        spawner
+            .spawn(fut_relay_server.map_err(|_e| ()).map(|_| ()))
+            .unwrap();
+
+        let (_a_ac, c_ac) = mpsc::channel::<RejectConnection>(0);
+        let (c_ca, mut a_ca) = mpsc::channel::<IncomingConnection>(0);
+        let (mut b_bc, c_bc) = mpsc::channel::<Vec<u8>>(0);
+        let (c_cb, mut b_cb) = mpsc::channel::<Vec<u8>>(0);
+
+        let a_public_key = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let b_public_key = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+        let d_public_key = PublicKey::from(&[0xdd; PUBLIC_KEY_LEN]);
+
+        // A listens:
+        let incoming_listen_a = IncomingListen {
+            receiver: c_ac,
+            sender: c_ca.sink_map_err(|_| ()),
+        };
+        let incoming_conn_a = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Listen(incoming_listen_a),
+        };
+        await!(outgoing_conns.send(incoming_conn_a)).unwrap();
+
+        // B connects to A:
+        let incoming_connect_b = IncomingConnect {
+            receiver: c_bc,
+            sender: c_cb.sink_map_err(|_| ()),
+            connect_public_key: a_public_key.clone(),
+        };
+        let incoming_conn_b = IncomingConn {
+            public_key: b_public_key.clone(),
+            inner: IncomingConnInner::Connect(incoming_connect_b),
+        };
+        await!(outgoing_conns.send(incoming_conn_b)).unwrap();
+        let _ = await!(a_ca.next()).unwrap();
+
+        let summary = await!(wait_for_stats(&mut stats_requests_sender, |summary| {
+            summary
+                .keys
+                .get(&b_public_key)
+                .map(|key_stats| key_stats.pending_registrations == 1)
+                .unwrap_or(false)
+        }));
+        assert_eq!(summary.keys.get(&a_public_key).unwrap().active_conns, 1);
+
+        // A accepts B's connection:
+        let (mut a_ac1, c_ac1) = mpsc::channel::<Vec<u8>>(0);
+        let (c_ca1, mut a_ca1) = mpsc::channel::<Vec<u8>>(0);
+        let incoming_accept_a = IncomingAccept {
+            receiver: c_ac1,
+            sender: c_ca1.sink_map_err(|_| ()),
+            accept_public_key: b_public_key.clone(),
+        };
+        let incoming_conn_accept_a = IncomingConn {
+            public_key: a_public_key.clone(),
+            inner: IncomingConnInner::Accept(incoming_accept_a),
+        };
+        await!(outgoing_conns.send(incoming_conn_accept_a)).unwrap();
+
+        // Push traffic in both directions:
+        await!(a_ac1.send(vec![1, 2, 3])).unwrap();
+        assert_eq!(await!(b_cb.next()).unwrap(), vec![1, 2, 3]);
+        await!(b_bc.send(vec![4, 3, 2, 1])).unwrap();
+        assert_eq!(await!(a_ca1.next()).unwrap(), vec![4, 3, 2, 1]);
+        await!(b_bc.send(vec![5; 10])).unwrap();
+        assert_eq!(await!(a_ca1.next()).unwrap(), vec![5; 10]);
+
+        // Close the tunnel. Bytes are counted when the tunnel is closed:
+        drop(a_ac1);
+        drop(b_bc);
+        let summary = await!(wait_for_stats(&mut stats_requests_sender, |summary| {
+            summary.total().bytes_in == 17 && summary.total().active_conns == 1
+        }));
+        let stats_a = summary.keys.get(&a_public_key).unwrap();
+        assert_eq!(stats_a.bytes_in, 3);
+        assert_eq!(stats_a.bytes_out, 14);
+        assert_eq!(stats_a.active_conns, 1);
+        let stats_b = summary.keys.get(&b_public_key).unwrap();
+        assert_eq!(stats_b.bytes_in, 14);
+        assert_eq!(stats_b.bytes_out, 3);
+        assert_eq!(stats_b.active_conns, 0);
+        assert_eq!(stats_b.pending_registrations, 0);
+
+        // D connects to a public key that is not listening:
+        let (_d_dc, c_dc) = mpsc::channel::<Vec<u8>>(0);
+        let (c_cd, mut d_cd) = mpsc::channel::<Vec<u8>>(0);
+        let incoming_connect_d = IncomingConnect {
+            receiver: c_dc,
+            sender: c_cd.sink_map_err(|_| ()),
+            connect_public_key: b_public_key.clone(),
+        };
+        let incoming_conn_d = IncomingConn {
+            public_key: d_public_key.clone(),
+            inner: IncomingConnInner::Connect(incoming_connect_d),
+        };
+        await!(outgoing_conns.send(incoming_conn_d)).unwrap();
+        assert!(await!(d_cd.next()).is_none());
+
+        let summary = await!(wait_for_stats(&mut stats_requests_sender, |summary| {
+            summary.rejects.get(&RejectReason::NotListening) == Some(&1)
+        }));
+        assert_eq!(summary.keys.get(&d_public_key).unwrap().rejects, 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_relay_server_stats() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+        thread_pool
+            .run(task_relay_server_stats(thread_pool.clone()))
+            .unwrap();
+    }
+
     // TODO: Add tests:
     // - Timeout of half tunnels
     //      (Do some action first, to make sure timer_stream was already obtained).
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+
+use futures::channel::oneshot;
+
+use crypto::identity::PublicKey;
+
+/// Maximum amount of public keys the relay server keeps separate statistics for.
+/// Statistics of other public keys are aggregated together.
+pub const MAX_STATS_KEYS: usize = 0x100;
+
+/// Amount of ticks between two summaries of the statistics written to the log.
+pub const STATS_SUMMARY_TICKS: usize = 0x100;
+
+/// A request for a summary of the relay server statistics
+pub type StatsRequest = oneshot::Sender<RelayStatsSummary>;
+
+/// Reasons for the relay server to reject or drop a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RejectReason {
+    /// A Listen connection from a public key that is already listening
+    AlreadyListening,
+    /// A Connect connection to a public key that is not listening
+    NotListening,
+    /// A Connect connection that is already pending or connected
+    DuplicateConnect,
+    /// The listener could not be notified about a Connect connection
+    ListenerBusy,
+    /// The listener rejected a Connect connection
+    RejectedByListener,
+    /// A Connect connection was not accepted in time
+    Expired,
+    /// An Accept connection that matches no pending Connect connection
+    NoPendingConnect,
+}
+
+/// Statistics of a single public key.
+/// Bytes are counted from the point of view of the relay server.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyStats {
+    /// Open Listen connections and tunnel ends
+    pub active_conns: u64,
+    /// Connect connections waiting to be accepted
+    pub pending_registrations: u64,
+    /// Bytes received from this public key through tunnels
+    pub bytes_in: u64,
+    /// Bytes sent to this public key through tunnels
+    pub bytes_out: u64,
+    /// Rejected or dropped connections
+    pub rejects: u64,
+}
+
+impl KeyStats {
+    fn merge(&mut self, other: &KeyStats) {
+        self.active_conns += other.active_conns;
+        self.pending_registrations += other.pending_registrations;
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.rejects += other.rejects;
+    }
+}
+
+/// A summary of the statistics of the relay server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RelayStatsSummary {
+    /// Statistics of the most recently active public keys
+    pub keys: HashMap<PublicKey, KeyStats>,
+    /// Aggregated statistics of all the other public keys
+    pub others: KeyStats,
+    /// Amount of rejected connections, by reason
+    pub rejects: HashMap<RejectReason, u64>,
+}
+
+#[derive(Debug)]
+pub enum StatsEvent {
+    ConnOpened(PublicKey),
+    ConnClosed(PublicKey),
+    RegistrationAdded(PublicKey),
+    RegistrationRemoved(PublicKey),
+    /// Bytes were forwarded through a tunnel: (from, to, amount of bytes)
+    Bytes((PublicKey, PublicKey, u64)),
+    Reject((PublicKey, RejectReason)),
+}
+
+struct KeyEntry {
+    stats: KeyStats,
+    /// The value of the usage counter when this public key was last active
+    last_used: u64,
+}
+
+/// Statistics of the relay server, per public key.
+///
+/// The amount of public keys tracked separately is bounded. When a new public key shows up
+/// and there is no room for it, the least recently active public key is evicted, and its
+/// statistics are merged into the aggregated statistics of all other public keys.
+/// This way a remote side that cycles through many public keys can not exhaust our memory.
+pub struct RelayStats {
+    max_keys: usize,
+    keys: HashMap<PublicKey, KeyEntry>,
+    others: KeyStats,
+    rejects: HashMap<RejectReason, u64>,
+    usage_counter: u64,
+}
+
+impl RelayStats {
+    pub fn new(max_keys: usize) -> Self {
+        assert!(max_keys > 0);
+        RelayStats {
+            max_keys,
+            keys: HashMap::new(),
+            others: KeyStats::default(),
+            rejects: HashMap::new(),
+            usage_counter: 0,
+        }
+    }
+
+    /// Get the statistics of a public key, making room for it if needed.
+    fn key_stats(&mut self, public_key: &PublicKey) -> &mut KeyStats {
+        self.usage_counter = self.usage_counter.wrapping_add(1);
+
+        if !self.keys.contains_key(public_key) && self.keys.len() >= self.max_keys {
+            let opt_lru_public_key = self
+                .keys
+                .iter()
+                .min_by_key(|(_public_key, key_entry)| key_entry.last_used)
+                .map(|(public_key, _key_entry)| public_key.clone());
+            if let Some(lru_public_key) = opt_lru_public_key {
+                let key_entry = self.keys.remove(&lru_public_key).unwrap();
+                self.others.merge(&key_entry.stats);
+            }
+        }
+
+        let usage_counter = self.usage_counter;
+        let key_entry = self
+            .keys
+            .entry(public_key.clone())
+            .or_insert_with(|| KeyEntry {
+                stats: KeyStats::default(),
+                last_used: usage_counter,
+            });
+        key_entry.last_used = usage_counter;
+        &mut key_entry.stats
+    }
+
+    /// Get the stats that hold a gauge of a public key we are about to decrease.
+    /// If the public key was evicted, its gauges were merged into the aggregated statistics.
+    fn gauge_stats(&mut self, public_key: &PublicKey) -> &mut KeyStats {
+        if self.keys.contains_key(public_key) {
+            self.key_stats(public_key)
+        } else {
+            &mut self.others
+        }
+    }
+
+    pub fn apply(&mut self, stats_event: &StatsEvent) {
+        match stats_event {
+            StatsEvent::ConnOpened(public_key) => {
+                self.key_stats(public_key).active_conns += 1;
+            }
+            StatsEvent::ConnClosed(public_key) => {
+                let key_stats = self.gauge_stats(public_key);
+                key_stats.active_conns = key_stats.active_conns.saturating_sub(1);
+            }
+            StatsEvent::RegistrationAdded(public_key) => {
+                self.key_stats(public_key).pending_registrations += 1;
+            }
+            StatsEvent::RegistrationRemoved(public_key) => {
+                let key_stats = self.gauge_stats(public_key);
+                key_stats.pending_registrations = key_stats.pending_registrations.saturating_sub(1);
+            }
+            StatsEvent::Bytes((from_public_key, to_public_key, num_bytes)) => {
+                self.key_stats(from_public_key).bytes_in += num_bytes;
+                self.key_stats(to_public_key).bytes_out += num_bytes;
+            }
+            StatsEvent::Reject((public_key, reject_reason)) => {
+                self.key_stats(public_key).rejects += 1;
+                *self.rejects.entry(*reject_reason).or_insert(0) += 1;
+            }
+        }
+    }
+
+    pub fn summary(&self) -> RelayStatsSummary {
+        RelayStatsSummary {
+            keys: self
+                .keys
+                .iter()
+                .map(|(public_key, key_entry)| (public_key.clone(), key_entry.stats.clone()))
+                .collect(),
+            others: self.others.clone(),
+            rejects: self.rejects.clone(),
+        }
+    }
+}
+
+impl RelayStatsSummary {
+    /// Statistics of all the public keys together
+    pub fn total(&self) -> KeyStats {
+        let mut total = self.others.clone();
+        for key_stats in self.keys.values() {
+            total.merge(key_stats);
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crypto::identity::PUBLIC_KEY_LEN;
+
+    #[test]
+    fn test_relay_stats_basic() {
+        let mut relay_stats = RelayStats::new(4);
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        let pk_b = PublicKey::from(&[0xbb; PUBLIC_KEY_LEN]);
+
+        relay_stats.apply(&StatsEvent::ConnOpened(pk_a.clone()));
+        relay_stats.apply(&StatsEvent::ConnOpened(pk_b.clone()));
+        relay_stats.apply(&StatsEvent::Bytes((pk_a.clone(), pk_b.clone(), 10)));
+        relay_stats.apply(&StatsEvent::Bytes((pk_b.clone(), pk_a.clone(), 3)));
+        relay_stats.apply(&StatsEvent::Reject((pk_b.clone(), RejectReason::NotListening)));
+        relay_stats.apply(&StatsEvent::ConnClosed(pk_b.clone()));
+
+        let summary = relay_stats.summary();
+        let stats_a = summary.keys.get(&pk_a).unwrap();
+        assert_eq!(stats_a.active_conns, 1);
+        assert_eq!(stats_a.bytes_in, 10);
+        assert_eq!(stats_a.bytes_out, 3);
+        let stats_b = summary.keys.get(&pk_b).unwrap();
+        assert_eq!(stats_b.active_conns, 0);
+        assert_eq!(stats_b.bytes_in, 3);
+        assert_eq!(stats_b.bytes_out, 10);
+        assert_eq!(stats_b.rejects, 1);
+        assert_eq!(summary.rejects.get(&RejectReason::NotListening), Some(&1));
+        assert_eq!(summary.others, KeyStats::default());
+    }
+
+    #[test]
+    fn test_relay_stats_bounded_keys() {
+        let max_keys = 8;
+        let mut relay_stats = RelayStats::new(max_keys);
+        let pk_a = PublicKey::from(&[0xaa; PUBLIC_KEY_LEN]);
+        relay_stats.apply(&StatsEvent::ConnOpened(pk_a.clone()));
+
+        // Many short lived public keys:
+        for i in 0..0x100u32 {
+            let mut public_key_bytes = [0u8; PUBLIC_KEY_LEN];
+            public_key_bytes[0] = (i & 0xff) as u8;
+            public_key_bytes[1] = 1;
+            let public_key = PublicKey::from(&public_key_bytes);
+            relay_stats.apply(&StatsEvent::ConnOpened(public_key.clone()));
+            relay_stats.apply(&StatsEvent::Bytes((public_key.clone(), pk_a.clone(), 1)));
+            relay_stats.apply(&StatsEvent::RegistrationAdded(public_key.clone()));
+        }
+
+        let summary = relay_stats.summary();
+        assert_eq!(summary.keys.len(), max_keys);
+        // pk_a is active all the time, and is never evicted:
+        let stats_a = summary.keys.get(&pk_a).unwrap();
+        assert_eq!(stats_a.active_conns, 1);
+        assert_eq!(stats_a.bytes_out, 0x100);
+        // Nothing is lost by the aggregation:
+        let total = summary.total();
+        assert_eq!(total.active_conns, 0x101);
+        assert_eq!(total.pending_registrations, 0x100);
+        assert_eq!(total.bytes_in, 0x100);
+        assert!(summary.others.bytes_in > 0);
+
+        // Gauges of evicted public keys are decreased at the aggregate:
+        let mut public_key_bytes = [0u8; PUBLIC_KEY_LEN];
+        public_key_bytes[1] = 1;
+        let evicted_public_key = PublicKey::from(&public_key_bytes);
+        assert!(!summary.keys.contains_key(&evicted_public_key));
+        relay_stats.apply(&StatsEvent::ConnClosed(evicted_public_key.clone()));
+        let summary = relay_stats.summary();
+        assert_eq!(summary.keys.len(), max_keys);
+        assert_eq!(summary.total().active_conns, 0x100);
+    }
+}
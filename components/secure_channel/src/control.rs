@@ -0,0 +1,41 @@
+use futures::channel::{mpsc, oneshot};
+use futures::SinkExt;
+
+#[derive(Debug)]
+pub enum SecureChannelControlError {
+    /// The secure channel is closed
+    SendFailure,
+    /// The secure channel was closed before the request was completed
+    ResponseCanceled,
+}
+
+pub(crate) struct RekeyRequest {
+    pub response_sender: oneshot::Sender<()>,
+}
+
+/// Controls a live secure channel. Obtained using `SecureChannel::control()`.
+#[derive(Clone)]
+pub struct SecureChannelControl {
+    rekey_sender: mpsc::Sender<RekeyRequest>,
+}
+
+impl SecureChannelControl {
+    pub(crate) fn new(rekey_sender: mpsc::Sender<RekeyRequest>) -> Self {
+        SecureChannelControl { rekey_sender }
+    }
+
+    /// Replace the symmetric keys of the secure channel now, without waiting for the rekey
+    /// timer. Resolves after the new keys are in use by both sides.
+    ///
+    /// No messages are lost or reordered: Messages sent before the remote side acknowledges the
+    /// rekey are encrypted using the old keys. If a rekey is already in progress, this waits for
+    /// it to complete.
+    pub async fn rekey(&mut self) -> Result<(), SecureChannelControlError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        let rekey_request = RekeyRequest { response_sender };
+        await!(self.rekey_sender.send(rekey_request))
+            .map_err(|_| SecureChannelControlError::SendFailure)?;
+
+        await!(response_receiver).map_err(|_| SecureChannelControlError::ResponseCanceled)
+    }
+}
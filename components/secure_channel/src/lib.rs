@@ -19,7 +19,11 @@ extern crate common;
 #[macro_use]
 extern crate log;
 
+mod control;
 mod secure_channel;
 mod state;
+mod stats;
 
-pub use self::secure_channel::SecureChannel;
+pub use self::control::{SecureChannelControl, SecureChannelControlError};
+pub use self::secure_channel::{SecureChannel, SecureChannelMigrate};
+pub use self::stats::SecureChannelStats;
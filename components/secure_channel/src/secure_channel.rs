@@ -1,22 +1,31 @@
-use futures::task::{Spawn, SpawnExt};
-use futures::{future, stream, FutureExt, Sink, SinkExt, Stream, StreamExt};
+use futures::task::{Poll, Spawn, SpawnExt, Waker};
+use futures::{future, select, stream, Future, FutureExt, Sink, SinkExt, Stream, StreamExt};
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::marker::Unpin;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 
-use futures::channel::mpsc;
+use futures::channel::{mpsc, oneshot};
 
 use common::conn::{BoxFuture, ConnPairVec, FutTransform};
 use common::select_streams::{select_streams, BoxStream};
 
 use crypto::crypto_rand::CryptoRandom;
+use crypto::hash::HashResult;
 use crypto::identity::PublicKey;
 use identity::IdentityClient;
+use timer::utils::sleep_ticks;
 use timer::TimerClient;
 
-use crate::state::{ScState, ScStateError, ScStateInitial};
-use proto::secure_channel::messages::{EncryptedData, PlainData};
+use crate::control::{RekeyRequest, SecureChannelControl};
+use crate::state::{ResumeTicket, ScState, ScStateError, ScStateInitial};
+use crate::stats::SecureChannelStats;
+use proto::secure_channel::messages::{ChannelOpen, EncryptedData, PlainData, ResumeRequest};
 use proto::secure_channel::serialize::{
-    deserialize_exchange_dh, deserialize_exchange_rand_nonce, serialize_exchange_dh,
-    serialize_exchange_rand_nonce,
+    deserialize_channel_open, deserialize_exchange_dh, deserialize_resume_challenge,
+    deserialize_resume_proof, serialize_channel_open, serialize_exchange_dh,
+    serialize_resume_challenge, serialize_resume_proof,
 };
 
 #[derive(Debug)]
@@ -32,6 +41,47 @@ enum SecureChannelError {
     RequestTimerStreamError,
     HandleIncomingError,
     SpawnError,
+    UnexpectedResumeRequest,
+    NoSessionToResume,
+    DeserializeResumeError,
+    HandleResumeError(ScStateError),
+    SessionClosed,
+    PendingSendOverflow,
+}
+
+/// Amount of ticks we wait for a migration to a new transport after the current
+/// transport was closed, before closing the secure channel.
+const MIGRATE_GRACE_TICKS: usize = 8;
+
+/// Maximum amount of outgoing messages we queue while waiting for a migration.
+const MAX_PENDING_SEND: usize = 0x40;
+
+/// A sink of the underlying (unencrypted) transport.
+type TransportSink = Pin<Box<dyn Sink<SinkItem = Vec<u8>, SinkError = ()> + Send>>;
+/// An underlying transport of a secure channel: (writer, reader)
+type Transport = (TransportSink, BoxStream<'static, Vec<u8>>);
+
+/// Allows moving a live secure channel to a new transport.
+#[derive(Clone)]
+struct SessionHandle {
+    resume_ticket: ResumeTicket,
+    migrate_sender: mpsc::Sender<Transport>,
+    stats: SecureChannelStats,
+    control: SecureChannelControl,
+}
+
+/// Live sessions, indexed by the public key of the remote side.
+type Sessions = Arc<Mutex<HashMap<PublicKey, SessionHandle>>>;
+
+/// Result of the first exchange over a new transport.
+enum InitialExchange<K, M> {
+    /// A full Diffie-Hellman exchange was completed. A new session was created.
+    NewSession((ScState, K, M)),
+    /// Both sides offered the same ticket of a previous session. A new session was created
+    /// without a full exchange.
+    ResumedSession((ScState, K, M)),
+    /// The remote side asks to resume an existing session over this transport.
+    ResumeRequest((ResumeRequest, K, M)),
 }
 
 async fn initial_exchange<EK, M: 'static, K: 'static, R: CryptoRandom + 'static>(
@@ -39,24 +89,54 @@ async fn initial_exchange<EK, M: 'static, K: 'static, R: CryptoRandom + 'static>
     mut reader: M,
     identity_client: IdentityClient,
     opt_expected_remote: Option<PublicKey>,
+    opt_resume_ticket: Option<ResumeTicket>,
     rng: R,
-) -> Result<(ScState, K, M), SecureChannelError>
+) -> Result<InitialExchange<K, M>, SecureChannelError>
 where
     R: CryptoRandom + Clone,
     M: Stream<Item = Vec<u8>> + Unpin,
     K: Sink<SinkItem = Vec<u8>, SinkError = EK> + Unpin,
 {
-    let local_public_key = await!(identity_client.request_public_key())
+    let local_public_key = await!(identity_client.cached_public_key())
         .map_err(|_| SecureChannelError::IdentityFailure)?;
 
     let (dh_state_initial, exchange_rand_nonce) = ScStateInitial::new(&local_public_key, &rng);
-    let ser_exchange_rand_nonce = serialize_exchange_rand_nonce(&exchange_rand_nonce);
-    await!(writer.send(ser_exchange_rand_nonce)).map_err(|_| SecureChannelError::WriterError)?;
+    let channel_open = match &opt_resume_ticket {
+        Some(resume_ticket) => ChannelOpen::ResumeSession(
+            dh_state_initial.create_resume_session(resume_ticket, exchange_rand_nonce),
+        ),
+        None => ChannelOpen::ExchangeRandNonce(exchange_rand_nonce),
+    };
+    let ser_channel_open = serialize_channel_open(&channel_open);
+    await!(writer.send(ser_channel_open)).map_err(|_| SecureChannelError::WriterError)?;
 
     let reader_message = await!(reader.next()).ok_or(SecureChannelError::ReaderClosed)?;
 
-    let exchange_rand_nonce = deserialize_exchange_rand_nonce(&reader_message)
-        .map_err(|_| SecureChannelError::DeserializeRandNonceError)?;
+    let exchange_rand_nonce = match deserialize_channel_open(&reader_message)
+        .map_err(|_| SecureChannelError::DeserializeRandNonceError)?
+    {
+        ChannelOpen::ExchangeRandNonce(exchange_rand_nonce) => exchange_rand_nonce,
+        ChannelOpen::ResumeSession(resume_session) => match &opt_resume_ticket {
+            Some(resume_ticket) if resume_session.ticket_id == resume_ticket.ticket_id() => {
+                let dh_state = dh_state_initial
+                    .handle_resume_session(resume_ticket, &resume_session)
+                    .map_err(SecureChannelError::HandleResumeError)?;
+                return Ok(InitialExchange::ResumedSession((dh_state, writer, reader)));
+            }
+            // We have no ticket, or the remote side offers a different ticket.
+            // Fall back to a full exchange:
+            _ => resume_session.exchange_rand_nonce,
+        },
+        ChannelOpen::ResumeRequest(resume_request) => {
+            if let Some(expected_remote) = opt_expected_remote {
+                if expected_remote != resume_request.public_key {
+                    return Err(SecureChannelError::UnexpectedRemotePublicKey);
+                }
+            }
+            return Ok(InitialExchange::ResumeRequest((resume_request, writer, reader)));
+        }
+    };
+
     let (dh_state_half, exchange_dh) = await!(dh_state_initial.handle_exchange_rand_nonce(
         exchange_rand_nonce,
         identity_client.clone(),
@@ -80,31 +160,216 @@ where
         .handle_exchange_dh(exchange_dh)
         .map_err(SecureChannelError::HandleExchangeScStateError)?;
 
-    Ok((dh_state, writer, reader))
+    Ok(InitialExchange::NewSession((dh_state, writer, reader)))
+}
+
+/// Resume an existing session over a new transport, as the initiator.
+/// The remote side proves knowledge of the session secret before we do, and
+/// both proofs are bound to fresh nonces, so that the new transport can not be
+/// hijacked by a party that does not know the session secret.
+///
+/// A remote side that does not support resumption treats the ResumeRequest as a plain
+/// ExchangeRandNonce and continues with a full exchange. The resumption then fails, and a new
+/// secure channel should be opened instead.
+async fn resume_initiator<EK, M, K, R>(
+    mut writer: K,
+    mut reader: M,
+    resume_ticket: ResumeTicket,
+    rng: R,
+) -> Result<(K, M), SecureChannelError>
+where
+    R: CryptoRandom,
+    M: Stream<Item = Vec<u8>> + Unpin,
+    K: Sink<SinkItem = Vec<u8>, SinkError = EK> + Unpin,
+{
+    let (resume_initiator, resume_request) = resume_ticket.create_resume_request(&rng);
+    let ser_channel_open = serialize_channel_open(&ChannelOpen::ResumeRequest(resume_request));
+    await!(writer.send(ser_channel_open)).map_err(|_| SecureChannelError::WriterError)?;
+
+    // The listening side always begins by sending an ExchangeRandNonce (Or a ResumeSession)
+    // message. We ignore it:
+    let reader_message = await!(reader.next()).ok_or(SecureChannelError::ReaderClosed)?;
+    match deserialize_channel_open(&reader_message)
+        .map_err(|_| SecureChannelError::DeserializeResumeError)?
+    {
+        ChannelOpen::ExchangeRandNonce(_) | ChannelOpen::ResumeSession(_) => {}
+        ChannelOpen::ResumeRequest(_) => return Err(SecureChannelError::UnexpectedResumeRequest),
+    };
+
+    let reader_message = await!(reader.next()).ok_or(SecureChannelError::ReaderClosed)?;
+    let resume_challenge = deserialize_resume_challenge(&reader_message)
+        .map_err(|_| SecureChannelError::DeserializeResumeError)?;
+    let resume_proof = resume_ticket
+        .handle_resume_challenge(resume_initiator, resume_challenge)
+        .map_err(SecureChannelError::HandleResumeError)?;
+    await!(writer.send(serialize_resume_proof(&resume_proof)))
+        .map_err(|_| SecureChannelError::WriterError)?;
+
+    Ok((writer, reader))
+}
+
+/// Resume an existing session over a new transport, as the responder.
+async fn resume_responder<EK, M, K, R>(
+    mut writer: K,
+    mut reader: M,
+    resume_ticket: ResumeTicket,
+    resume_request: ResumeRequest,
+    rng: R,
+) -> Result<(K, M), SecureChannelError>
+where
+    R: CryptoRandom,
+    M: Stream<Item = Vec<u8>> + Unpin,
+    K: Sink<SinkItem = Vec<u8>, SinkError = EK> + Unpin,
+{
+    let (resume_responder, resume_challenge) = resume_ticket
+        .handle_resume_request(resume_request, &rng)
+        .map_err(SecureChannelError::HandleResumeError)?;
+    await!(writer.send(serialize_resume_challenge(&resume_challenge)))
+        .map_err(|_| SecureChannelError::WriterError)?;
+
+    let reader_message = await!(reader.next()).ok_or(SecureChannelError::ReaderClosed)?;
+    let resume_proof = deserialize_resume_proof(&reader_message)
+        .map_err(|_| SecureChannelError::DeserializeResumeError)?;
+    resume_ticket
+        .handle_resume_proof(resume_responder, resume_proof)
+        .map_err(SecureChannelError::HandleResumeError)?;
+
+    Ok((writer, reader))
+}
+
+fn create_transport<EK, M, K>(writer: K, reader: M) -> Transport
+where
+    EK: 'static,
+    M: Stream<Item = Vec<u8>> + Unpin + Send + 'static,
+    K: Sink<SinkItem = Vec<u8>, SinkError = EK> + Unpin + Send + 'static,
+{
+    (Box::pin(writer.sink_map_err(|_| ())), Box::pin(reader))
 }
 
 enum SecureChannelEvent {
     Reader(Vec<u8>),
+    /// The reader of one of the transports was closed:
+    ReaderClosed,
     User(Vec<u8>),
-    TimerTick,
+    /// Amount of time ticks that have elapsed
+    TimerTick(usize),
+    /// Move the channel to a new (already authenticated) transport:
+    Migrate(Transport),
+    /// The user asks to rekey now:
+    Rekey(RekeyRequest),
     /// Any of the receivers was closed:
     ReceiverClosed,
 }
 
-async fn secure_channel_loop<EK, M: 'static, K: 'static, R: CryptoRandom + 'static>(
+fn transport_reader_events(
+    reader: BoxStream<'static, Vec<u8>>,
+) -> BoxStream<'static, SecureChannelEvent> {
+    Box::pin(
+        reader
+            .map(SecureChannelEvent::Reader)
+            .chain(stream::once(future::ready(SecureChannelEvent::ReaderClosed))),
+    )
+}
+
+/// Resolves to the next item of a stream. The stream is polled only after the sink is ready to
+/// receive an item, so that items are not taken from the stream faster than they can be
+/// handed to the sink.
+struct NextWhenReady<'a, St, K> {
+    stream: &'a mut St,
+    /// `None` means that the stream is polled without waiting.
+    opt_sink: Option<&'a mut K>,
+    /// Set to `true` whenever the sink is found not ready.
+    blocked: &'a mut bool,
+}
+
+impl<'a, St, K> NextWhenReady<'a, St, K> {
+    fn new(stream: &'a mut St, opt_sink: Option<&'a mut K>, blocked: &'a mut bool) -> Self {
+        NextWhenReady {
+            stream,
+            opt_sink,
+            blocked,
+        }
+    }
+}
+
+impl<'a, St, K> Future for NextWhenReady<'a, St, K>
+where
+    St: Stream + Unpin,
+    K: Sink + Unpin,
+{
+    type Output = Option<St::Item>;
+
+    fn poll(mut self: Pin<&mut Self>, lw: &Waker) -> Poll<Self::Output> {
+        let fself = &mut *self;
+        if let Some(sink) = &mut fself.opt_sink {
+            match Pin::new(&mut **sink).poll_ready(lw) {
+                Poll::Pending => {
+                    *fself.blocked = true;
+                    return Poll::Pending;
+                }
+                // An error will be reported when attempting to send through the sink:
+                Poll::Ready(_) => {}
+            }
+        }
+        *fself.blocked = false;
+        fself.stream.poll_next_unpin(lw)
+    }
+}
+
+/// Initiate a rekey, unless a rekey is already in progress.
+/// Returns the encrypted Rekey message to send to the remote side.
+fn start_rekey<R: CryptoRandom>(dh_state: &mut ScState, rng: &R) -> Option<Vec<u8>> {
+    match dh_state.create_rekey(rng) {
+        Ok(enc_data) => Some(enc_data.0),
+        Err(ScStateError::RekeyInProgress) => None,
+        Err(_) => unreachable!(),
+    }
+}
+
+/// Send data through the live transport. If there is no live transport, the data is queued
+/// until we migrate to a new transport.
+async fn send_or_queue<'a>(
+    opt_writer: &'a mut Option<TransportSink>,
+    pending_send: &'a mut Vec<Vec<u8>>,
+    send_data: Vec<u8>,
+) -> Result<(), SecureChannelError> {
+    match opt_writer {
+        Some(writer) => await!(writer.send(send_data)).map_err(|_| SecureChannelError::WriterError),
+        None => {
+            if pending_send.len() >= MAX_PENDING_SEND {
+                return Err(SecureChannelError::PendingSendOverflow);
+            }
+            pending_send.push(send_data);
+            Ok(())
+        }
+    }
+}
+
+/// Incoming frames are read (and decrypted) only when the user is ready to receive another
+/// message, and messages from the user are taken only when the transport is ready to send.
+/// This way a slow user slows down the remote side (Through the transport), instead of having
+/// messages pile up in memory. Each direction waits separately, so that a slow receiver never
+/// blocks sending.
+///
+/// A rekey is issued every `ticks_to_rekey` ticks, after `max_messages_before_rekey` messages
+/// were sent using the same keys (If specified), or when the user asks for it. Messages are sent
+/// in order: Messages sent before the remote side acknowledges a rekey are encrypted using the
+/// old keys, and the remote side keeps the old keys until it receives them.
+async fn secure_channel_loop<R: CryptoRandom + 'static>(
     mut dh_state: ScState,
-    mut writer: K,
-    reader: M,
+    transport: Transport,
     from_user: mpsc::Receiver<Vec<u8>>,
     mut to_user: mpsc::Sender<Vec<u8>>,
+    incoming_migrate: mpsc::Receiver<Transport>,
+    incoming_rekey: mpsc::Receiver<RekeyRequest>,
     rng: R,
     ticks_to_rekey: usize,
+    max_messages_before_rekey: Option<u64>,
     mut timer_client: TimerClient,
+    stats: SecureChannelStats,
 ) -> Result<(), SecureChannelError>
 where
     R: CryptoRandom,
-    M: Stream<Item = Vec<u8>> + Unpin + Send,
-    K: Sink<SinkItem = Vec<u8>, SinkError = EK> + Unpin,
 {
     // TODO: How to perform graceful shutdown of sinks?
     // Is there a way to do it?
@@ -112,64 +377,180 @@ where
     let timer_stream = await!(timer_client.request_timer_stream())
         .map_err(|_| SecureChannelError::RequestTimerStreamError)?;
     let timer_stream = timer_stream
-        .map(|_| SecureChannelEvent::TimerTick)
+        .map(|timer_tick| {
+            let ticks_elapsed =
+                usize::try_from(timer_tick.ticks_elapsed).unwrap_or(usize::max_value());
+            SecureChannelEvent::TimerTick(ticks_elapsed)
+        })
         .chain(stream::once(future::ready(
             SecureChannelEvent::ReceiverClosed,
         )));
 
-    let reader = reader
-        .map(SecureChannelEvent::Reader)
-        .chain(stream::once(future::ready(
-            SecureChannelEvent::ReceiverClosed,
-        )));
-    let from_user = from_user
+    let (writer, reader) = transport;
+    // `None` means that there is currently no live transport.
+    // Outgoing messages are queued until we migrate to a new transport.
+    let mut opt_writer = Some(writer);
+    let mut pending_send: Vec<Vec<u8>> = Vec::new();
+    // Amount of ticks left to wait for a migration, if there is no live transport:
+    let mut opt_ticks_to_close: Option<usize> = None;
+
+    // Readers of all transports are read one after the other.
+    // When migrating, messages sent by the remote side over the old transport
+    // must be processed before messages sent over the new transport, to keep the
+    // encryption nonces in order.
+    let (readers_sender, readers_receiver) =
+        mpsc::unbounded::<BoxStream<'static, SecureChannelEvent>>();
+    // Can not fail, as readers_receiver is owned by this loop:
+    readers_sender
+        .unbounded_send(transport_reader_events(reader))
+        .unwrap();
+    let mut num_readers: usize = 1;
+    let mut readers = readers_receiver.flatten();
+
+    let mut from_user = from_user
         .map(SecureChannelEvent::User)
         .chain(stream::once(future::ready(
             SecureChannelEvent::ReceiverClosed,
         )));
+    let incoming_migrate = incoming_migrate.map(SecureChannelEvent::Migrate);
+    let incoming_rekey = incoming_rekey.map(SecureChannelEvent::Rekey);
 
     let mut cur_ticks_to_rekey = ticks_to_rekey;
-    let mut events = select_streams![reader, from_user, timer_stream];
+    // Amount of messages sent using the current keys:
+    let mut messages_since_rekey: u64 = 0;
+    // Users waiting for the rekey in progress to complete:
+    let mut rekey_waiters: Vec<oneshot::Sender<()>> = Vec::new();
+    let mut events = select_streams![timer_stream, incoming_migrate, incoming_rekey];
+
+    // Were we waiting for the user (incoming) or for the transport (outgoing) the last time we
+    // checked?
+    let mut incoming_blocked = false;
+    let mut outgoing_blocked = false;
 
-    while let Some(event) = await!(events.next()) {
-        match event {
+    loop {
+        let opt_event = {
+            let mut next_incoming =
+                NextWhenReady::new(&mut readers, Some(&mut to_user), &mut incoming_blocked)
+                    .fuse();
+            // Without a live transport, outgoing messages are queued (See `pending_send`):
+            let mut next_outgoing =
+                NextWhenReady::new(&mut from_user, opt_writer.as_mut(), &mut outgoing_blocked)
+                    .fuse();
+            let mut next_event = events.next().fuse();
+            select! {
+                opt_event = next_incoming => opt_event,
+                opt_event = next_outgoing => opt_event,
+                opt_event = next_event => opt_event,
+            }
+        };
+        let event = match opt_event {
+            Some(event) => event,
+            None => break,
+        };
+
+        let opt_send_data = match event {
             SecureChannelEvent::Reader(data) => {
                 let hi_output = dh_state
                     .handle_incoming(&EncryptedData(data), &rng)
                     .map_err(|_| SecureChannelError::HandleIncomingError)?;
                 if hi_output.rekey_occurred {
                     cur_ticks_to_rekey = ticks_to_rekey;
-                }
-                if let Some(send_message) = hi_output.opt_send_message {
-                    await!(writer.send(send_message.0))
-                        .map_err(|_| SecureChannelError::WriterError)?;
+                    messages_since_rekey = 0;
+                    stats.add_rekey();
+                    for rekey_waiter in rekey_waiters.drain(..) {
+                        let _ = rekey_waiter.send(());
+                    }
                 }
                 if let Some(incoming_message) = hi_output.opt_incoming_message {
+                    // The user is known to be ready, so this does not wait:
                     await!(to_user.send(incoming_message.0))
                         .map_err(|_| SecureChannelError::WriterError)?;
+                    stats.add_incoming_message();
+                }
+                hi_output.opt_send_message.map(|send_message| send_message.0)
+            }
+            SecureChannelEvent::ReaderClosed => {
+                num_readers = num_readers.saturating_sub(1);
+                if num_readers == 0 {
+                    // No live transport. Close the old transport completely,
+                    // and wait a while for a migration to a new transport:
+                    info!("secure_channel_loop(): ReaderClosed. Waiting for migration.");
+                    opt_writer = None;
+                    opt_ticks_to_close = Some(MIGRATE_GRACE_TICKS);
                 }
+                None
             }
             SecureChannelEvent::User(data) => {
                 let enc_data = dh_state.create_outgoing(&PlainData(data), &rng);
-                await!(writer.send(enc_data.0)).map_err(|_| SecureChannelError::WriterError)?;
+                await!(send_or_queue(
+                    &mut opt_writer,
+                    &mut pending_send,
+                    enc_data.0
+                ))?;
+                messages_since_rekey = messages_since_rekey.saturating_add(1);
+                match max_messages_before_rekey {
+                    Some(max_messages) if messages_since_rekey >= max_messages => {
+                        start_rekey(&mut dh_state, &rng)
+                    }
+                    _ => None,
+                }
             }
-            SecureChannelEvent::TimerTick => {
-                if let Some(new_cur_ticks_to_rekey) = cur_ticks_to_rekey.checked_sub(1) {
+            SecureChannelEvent::TimerTick(ticks_elapsed) => {
+                if incoming_blocked {
+                    stats.add_incoming_blocked_ticks(ticks_elapsed);
+                }
+                if outgoing_blocked {
+                    stats.add_outgoing_blocked_ticks(ticks_elapsed);
+                }
+                if let Some(ticks_to_close) = opt_ticks_to_close {
+                    opt_ticks_to_close = ticks_to_close.checked_sub(ticks_elapsed);
+                    if opt_ticks_to_close.is_none() {
+                        info!("secure_channel_loop(): No migration occurred. Closing.");
+                        break;
+                    }
+                }
+                // If many ticks have elapsed at once, a single rekey is issued:
+                if let Some(new_cur_ticks_to_rekey) = cur_ticks_to_rekey.checked_sub(ticks_elapsed)
+                {
                     cur_ticks_to_rekey = new_cur_ticks_to_rekey;
                     continue;
                 }
-                let enc_data = match dh_state.create_rekey(&rng) {
-                    Ok(enc_data) => enc_data,
-                    Err(ScStateError::RekeyInProgress) => continue,
-                    Err(_) => unreachable!(),
+                let rekey_data = match start_rekey(&mut dh_state, &rng) {
+                    Some(rekey_data) => rekey_data,
+                    None => continue,
                 };
-                await!(writer.send(enc_data.0)).map_err(|_| SecureChannelError::WriterError)?;
                 cur_ticks_to_rekey = ticks_to_rekey;
+                Some(rekey_data)
+            }
+            SecureChannelEvent::Migrate((mut new_writer, new_reader)) => {
+                // Messages queued while we had no live transport are sent first:
+                for data in pending_send.drain(..) {
+                    await!(new_writer.send(data)).map_err(|_| SecureChannelError::WriterError)?;
+                }
+                // Replacing the writer drops the old one, closing the old transport.
+                // The remote side will read the rest of the old transport before moving on to
+                // the new one.
+                opt_writer = Some(new_writer);
+                opt_ticks_to_close = None;
+                readers_sender
+                    .unbounded_send(transport_reader_events(new_reader))
+                    .unwrap();
+                num_readers += 1;
+                None
+            }
+            SecureChannelEvent::Rekey(rekey_request) => {
+                // If a rekey is already in progress, the user waits for it to complete:
+                rekey_waiters.push(rekey_request.response_sender);
+                start_rekey(&mut dh_state, &rng)
             }
             SecureChannelEvent::ReceiverClosed => {
                 info!("secure_channel_loop(): ReceiverClosed");
                 break;
             }
+        };
+
+        if let Some(send_data) = opt_send_data {
+            await!(send_or_queue(&mut opt_writer, &mut pending_send, send_data))?;
         }
     }
     Ok(())
@@ -184,6 +565,25 @@ where
 ///
 /// `ticks_to_rekey` is the amount of time ticks it takes to issue a rekey, changing the symmetric
 /// key used for the encryption.
+///
+/// `max_messages_before_rekey` is the amount of messages we send before issuing a rekey,
+/// regardless of `ticks_to_rekey`. `None` means that rekeys are issued only by time (Or on
+/// demand, using the `SecureChannelControl` of the session).
+///
+/// `incoming_queue_len` is the amount of incoming messages that may wait for the user to receive
+/// them. Further incoming messages are not read from the underlying channel until the user
+/// receives.
+///
+/// If the remote side asks to resume an existing session over this channel, and resumption
+/// succeeds, the channel is handed to the existing session and `Ok(None)` is returned.
+///
+/// `opt_resume_ticket` is the ticket of a previous session with the expected remote side. If the
+/// remote side offers the same ticket, the keys of the new session are derived from the ticket,
+/// without a full exchange. Otherwise a full exchange is performed.
+///
+/// If `opt_resume_ticks` is specified, the ticket of the new session is kept in `sessions`, and
+/// expires `opt_resume_ticks` ticks after the new session is closed. Otherwise the new session is
+/// not kept.
 async fn create_secure_channel<EK, M, K, R, S>(
     writer: K,
     reader: M,
@@ -192,8 +592,13 @@ async fn create_secure_channel<EK, M, K, R, S>(
     rng: R,
     timer_client: TimerClient,
     ticks_to_rekey: usize,
+    max_messages_before_rekey: Option<u64>,
+    incoming_queue_len: usize,
+    opt_resume_ticket: Option<ResumeTicket>,
+    opt_resume_ticks: Option<usize>,
+    sessions: Sessions,
     mut spawner: S,
-) -> Result<(PublicKey, ConnPairVec), SecureChannelError>
+) -> Result<Option<(PublicKey, ConnPairVec)>, SecureChannelError>
 where
     EK: 'static,
     M: Stream<Item = Vec<u8>> + Unpin + Send + 'static,
@@ -201,40 +606,152 @@ where
     R: CryptoRandom + Clone + 'static,
     S: Spawn,
 {
-    let (dh_state, writer, reader) = await!(initial_exchange(
+    let ((dh_state, writer, reader), resumed) = match await!(initial_exchange(
         writer,
         reader,
         identity_client,
         opt_expected_remote,
+        opt_resume_ticket,
         rng.clone()
-    ))?;
+    ))? {
+        InitialExchange::NewSession(new_session) => (new_session, false),
+        InitialExchange::ResumedSession(resumed_session) => (resumed_session, true),
+        InitialExchange::ResumeRequest((resume_request, writer, reader)) => {
+            let opt_session_handle = sessions
+                .lock()
+                .unwrap()
+                .get(&resume_request.public_key)
+                .cloned();
+            let mut session_handle =
+                opt_session_handle.ok_or(SecureChannelError::NoSessionToResume)?;
+            let (writer, reader) = await!(resume_responder(
+                writer,
+                reader,
+                session_handle.resume_ticket.clone(),
+                resume_request,
+                rng.clone()
+            ))?;
+            await!(session_handle
+                .migrate_sender
+                .send(create_transport(writer, reader)))
+            .map_err(|_| SecureChannelError::SessionClosed)?;
+            return Ok(None);
+        }
+    };
 
     let remote_public_key = dh_state.get_remote_public_key().clone();
 
     let (user_sender, from_user) = mpsc::channel::<Vec<u8>>(0);
-    let (to_user, user_receiver) = mpsc::channel::<Vec<u8>>(0);
+    let (to_user, user_receiver) = mpsc::channel::<Vec<u8>>(incoming_queue_len);
+    let (migrate_sender, incoming_migrate) = mpsc::channel::<Transport>(0);
+    let (rekey_sender, incoming_rekey) = mpsc::channel::<RekeyRequest>(0);
+    let stats = SecureChannelStats::default();
+    if resumed {
+        stats.set_resumed();
+    }
+
+    // A new session with the same remote side replaces any previous session:
+    let resume_ticket = dh_state.create_resume_ticket();
+    let ticket_id = resume_ticket.ticket_id();
+    let session_handle = SessionHandle {
+        resume_ticket,
+        migrate_sender,
+        stats: stats.clone(),
+        control: SecureChannelControl::new(rekey_sender),
+    };
+    // Without resumption the session could not be resumed or migrated, so it is not kept:
+    if opt_resume_ticks.is_some() {
+        sessions
+            .lock()
+            .unwrap()
+            .insert(remote_public_key.clone(), session_handle);
+    }
 
     let sc_loop = secure_channel_loop(
         dh_state,
-        writer,
-        reader,
+        create_transport(writer, reader),
         from_user,
         to_user,
+        incoming_migrate,
+        incoming_rekey,
         rng.clone(),
         ticks_to_rekey,
-        timer_client,
+        max_messages_before_rekey,
+        timer_client.clone(),
+        stats,
     );
 
-    let sc_loop_report_error = sc_loop.map(|res| {
-        if let Err(e) = res {
+    let c_remote_public_key = remote_public_key.clone();
+    let sc_loop_report_error = async move {
+        if let Err(e) = await!(sc_loop) {
             warn!("Secure Channel error: {:?}", e);
         }
-    });
+        // The session may still be resumed for a while, using its ticket:
+        if let Some(resume_ticks) = opt_resume_ticks {
+            let _ = await!(sleep_ticks(resume_ticks, timer_client));
+            remove_session_if_current(&sessions, &c_remote_public_key, &ticket_id);
+        }
+    };
     spawner
         .spawn(sc_loop_report_error)
         .map_err(|_| SecureChannelError::SpawnError)?;
 
-    Ok((remote_public_key, (user_sender, user_receiver)))
+    Ok(Some((remote_public_key, (user_sender, user_receiver))))
+}
+
+/// Remove the session with `remote_public_key`, unless it was already replaced by a newer
+/// session.
+fn remove_session_if_current(
+    sessions: &Sessions,
+    remote_public_key: &PublicKey,
+    ticket_id: &HashResult,
+) {
+    let mut sessions = sessions.lock().unwrap();
+    let is_current = match sessions.get(remote_public_key) {
+        Some(session_handle) => session_handle.resume_ticket.ticket_id() == *ticket_id,
+        None => false,
+    };
+    if is_current {
+        sessions.remove(remote_public_key);
+    }
+}
+
+/// Move a live secure channel with `remote_public_key` to a new transport.
+/// On failure the old transport keeps being used, and the caller may fall back to
+/// creating a new secure channel using a full handshake.
+async fn migrate_secure_channel<EK, M, K, R>(
+    writer: K,
+    reader: M,
+    remote_public_key: PublicKey,
+    rng: R,
+    sessions: Sessions,
+) -> Result<(), SecureChannelError>
+where
+    EK: 'static,
+    M: Stream<Item = Vec<u8>> + Unpin + Send + 'static,
+    K: Sink<SinkItem = Vec<u8>, SinkError = EK> + Unpin + Send + 'static,
+    R: CryptoRandom,
+{
+    let opt_session_handle = sessions.lock().unwrap().get(&remote_public_key).cloned();
+    let mut session_handle = opt_session_handle.ok_or(SecureChannelError::NoSessionToResume)?;
+
+    let (writer, reader) = await!(resume_initiator(
+        writer,
+        reader,
+        session_handle.resume_ticket.clone(),
+        rng
+    ))?;
+
+    if await!(session_handle
+        .migrate_sender
+        .send(create_transport(writer, reader)))
+    .is_err()
+    {
+        // The session is not alive anymore:
+        sessions.lock().unwrap().remove(&remote_public_key);
+        return Err(SecureChannelError::SessionClosed);
+    }
+    Ok(())
 }
 
 #[derive(Clone)]
@@ -243,15 +760,24 @@ pub struct SecureChannel<R, S> {
     rng: R,
     timer_client: TimerClient,
     ticks_to_rekey: usize,
+    max_messages_before_rekey: Option<u64>,
+    incoming_queue_len: usize,
+    opt_resume_ticks: Option<usize>,
+    sessions: Sessions,
     spawner: S,
 }
 
 impl<R, S> SecureChannel<R, S> {
+    /// `opt_resume_ticks` is the amount of ticks a closed secure channel may be resumed using
+    /// its ticket, without a full exchange. `None` disables resumption.
     pub fn new(
         identity_client: IdentityClient,
         rng: R,
         timer_client: TimerClient,
         ticks_to_rekey: usize,
+        max_messages_before_rekey: Option<u64>,
+        incoming_queue_len: usize,
+        opt_resume_ticks: Option<usize>,
         spawner: S,
     ) -> SecureChannel<R, S> {
         SecureChannel {
@@ -259,9 +785,103 @@ impl<R, S> SecureChannel<R, S> {
             rng,
             timer_client,
             ticks_to_rekey,
+            max_messages_before_rekey,
+            incoming_queue_len,
+            opt_resume_ticks,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
             spawner,
         }
     }
+
+    /// Instrumentation counters of the most recent secure channel with `remote_public_key`.
+    /// Available only if resumption is enabled. The counters are dropped once the ticket of the
+    /// channel expires.
+    pub fn stats(&self, remote_public_key: &PublicKey) -> Option<SecureChannelStats> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(remote_public_key)
+            .map(|session_handle| session_handle.stats.clone())
+    }
+
+    /// A handle that controls the most recent secure channel with `remote_public_key`.
+    /// Allows, for example, forcing a rekey. Available only if resumption is enabled.
+    pub fn control(&self, remote_public_key: &PublicKey) -> Option<SecureChannelControl> {
+        self.sessions
+            .lock()
+            .unwrap()
+            .get(remote_public_key)
+            .map(|session_handle| session_handle.control.clone())
+    }
+}
+
+impl<R, S> SecureChannel<R, S>
+where
+    R: CryptoRandom + Clone + 'static,
+{
+    /// Move a live secure channel with `remote_public_key` to a new plain channel
+    /// (For example: a connection through a different relay), without performing a full
+    /// handshake. The secure channel previously returned to the user keeps working, and the old
+    /// plain channel is closed.
+    ///
+    /// Migration requires resumption to be enabled.
+    ///
+    /// Returns `false` if migration failed. In that case the old plain channel is still used,
+    /// and a new secure channel may be created using `transform()`.
+    pub fn migrate(
+        &mut self,
+        remote_public_key: PublicKey,
+        conn_pair: ConnPairVec,
+    ) -> BoxFuture<'_, bool> {
+        let (sender, receiver) = conn_pair;
+        let rng = self.rng.clone();
+        let sessions = self.sessions.clone();
+        Box::pin(
+            async move {
+                let res = await!(migrate_secure_channel(
+                    sender,
+                    receiver,
+                    remote_public_key,
+                    rng,
+                    sessions
+                ));
+                if let Err(e) = &res {
+                    warn!("SecureChannel::migrate(): Migration failed: {:?}", e);
+                }
+                res.is_ok()
+            },
+        )
+    }
+}
+
+/// Moves live secure channels to new plain channels. See `SecureChannel::migrate()`.
+#[derive(Clone)]
+pub struct SecureChannelMigrate<R, S> {
+    secure_channel: SecureChannel<R, S>,
+}
+
+impl<R, S> SecureChannelMigrate<R, S> {
+    /// Migrate secure channels created using `secure_channel`.
+    pub fn new(secure_channel: SecureChannel<R, S>) -> Self {
+        SecureChannelMigrate { secure_channel }
+    }
+}
+
+impl<R, S> FutTransform for SecureChannelMigrate<R, S>
+where
+    R: CryptoRandom + Clone + 'static,
+{
+    /// Input:
+    /// - Public key of the remote side of the live secure channel.
+    /// - (sender, receiver) of the new plain channel.
+    type Input = (PublicKey, ConnPairVec);
+    /// Output: `true` if the migration succeeded.
+    type Output = bool;
+
+    fn transform(&mut self, input: (PublicKey, ConnPairVec)) -> BoxFuture<'_, bool> {
+        let (remote_public_key, conn_pair) = input;
+        self.secure_channel.migrate(remote_public_key, conn_pair)
+    }
 }
 
 impl<R, S> FutTransform for SecureChannel<R, S>
@@ -277,6 +897,9 @@ where
     /// - Public key of remote side (Must match the expected public key of remote side if
     /// specified).
     /// - (sender, receiver) for the resulting encrypted channel.
+    ///
+    /// `None` is also returned if the plain channel was used to resume an existing
+    /// secure channel.
     type Output = Option<(PublicKey, ConnPairVec)>;
 
     fn transform(
@@ -285,6 +908,16 @@ where
     ) -> BoxFuture<'_, Option<(PublicKey, ConnPairVec)>> {
         let (opt_expected_remote, conn_pair) = input;
         let (sender, receiver) = conn_pair;
+        // Resumption is possible only if we know who the remote side is:
+        let opt_resume_ticket = match (self.opt_resume_ticks, &opt_expected_remote) {
+            (Some(_), Some(expected_remote)) => self
+                .sessions
+                .lock()
+                .unwrap()
+                .get(expected_remote)
+                .map(|session_handle| session_handle.resume_ticket.clone()),
+            _ => None,
+        };
 
         Box::pin(
             async move {
@@ -296,9 +929,14 @@ where
                     self.rng.clone(),
                     self.timer_client.clone(),
                     self.ticks_to_rekey,
+                    self.max_messages_before_rekey,
+                    self.incoming_queue_len,
+                    opt_resume_ticket,
+                    self.opt_resume_ticks,
+                    self.sessions.clone(),
                     self.spawner.clone()
                 ))
-                .ok()
+                .unwrap_or(None)
             },
         )
     }
@@ -314,16 +952,20 @@ mod tests {
     use futures::executor::ThreadPool;
     use futures::task::SpawnExt;
 
-    use crypto::identity::{generate_pkcs8_key_pair, Identity, SoftwareEd25519Identity};
+    use crypto::crypto_rand::RandValue;
+    use crypto::hash::{HashResult, HASH_RESULT_LEN};
     use crypto::test_utils::DummyRandom;
-    use identity::{create_identity, IdentityClient};
+    use identity::test_utils::spawn_fixture_identity;
+    use identity::IdentityClient;
+    use proto::secure_channel::messages::ResumeChallenge;
 
     async fn secure_channel1(
-        fut_sc: impl Future<Output = Result<(PublicKey, ConnPairVec), SecureChannelError>> + 'static,
+        fut_sc: impl Future<Output = Result<Option<(PublicKey, ConnPairVec)>, SecureChannelError>>
+            + 'static,
         mut tick_sender: mpsc::Sender<()>,
         output_sender: oneshot::Sender<bool>,
     ) {
-        let (_public_key, (mut sender, mut receiver)) = await!(fut_sc).unwrap();
+        let (_public_key, (mut sender, mut receiver)) = await!(fut_sc).unwrap().unwrap();
         await!(sender.send(vec![0, 1, 2, 3, 4, 5])).unwrap();
         let data = await!(receiver.next()).unwrap();
         assert_eq!(data, vec![5, 4, 3]);
@@ -338,11 +980,12 @@ mod tests {
     }
 
     async fn secure_channel2(
-        fut_sc: impl Future<Output = Result<(PublicKey, ConnPairVec), SecureChannelError>> + 'static,
+        fut_sc: impl Future<Output = Result<Option<(PublicKey, ConnPairVec)>, SecureChannelError>>
+            + 'static,
         _tick_sender: mpsc::Sender<()>,
         output_sender: oneshot::Sender<bool>,
     ) {
-        let (_public_key, (mut sender, mut receiver)) = await!(fut_sc).unwrap();
+        let (_public_key, (mut sender, mut receiver)) = await!(fut_sc).unwrap().unwrap();
         let data = await!(receiver.next()).unwrap();
         assert_eq!(data, vec![0, 1, 2, 3, 4, 5]);
         await!(sender.send(vec![5, 4, 3])).unwrap();
@@ -362,31 +1005,16 @@ mod tests {
         let (tick_sender, tick_receiver) = mpsc::channel::<()>(0);
         let timer_client = create_timer_incoming(tick_receiver, thread_pool.clone()).unwrap();
 
+        let (identity_client1, public_key1) = spawn_fixture_identity(1, &mut thread_pool);
+        let (identity_client2, public_key2) = spawn_fixture_identity(2, &mut thread_pool);
         let rng1 = DummyRandom::new(&[1u8]);
-        let pkcs8 = generate_pkcs8_key_pair(&rng1);
-        let identity1 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-        let public_key1 = identity1.get_public_key();
-        let (requests_sender1, identity_server1) = create_identity(identity1);
-        let identity_client1 = IdentityClient::new(requests_sender1);
-
         let rng2 = DummyRandom::new(&[2u8]);
-        let pkcs8 = generate_pkcs8_key_pair(&rng2);
-        let identity2 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-        let public_key2 = identity2.get_public_key();
-        let (requests_sender2, identity_server2) = create_identity(identity2);
-        let identity_client2 = IdentityClient::new(requests_sender2);
-
-        thread_pool
-            .spawn(identity_server1.then(|_| future::ready(())))
-            .unwrap();
-        thread_pool
-            .spawn(identity_server2.then(|_| future::ready(())))
-            .unwrap();
 
         let (sender1, receiver2) = mpsc::channel::<Vec<u8>>(0);
         let (sender2, receiver1) = mpsc::channel::<Vec<u8>>(0);
 
         let ticks_to_rekey: usize = 16;
+        let sessions1: Sessions = Arc::new(Mutex::new(HashMap::new()));
 
         let fut_sc1 = create_secure_channel(
             sender1.sink_map_err(|_| ()),
@@ -396,6 +1024,11 @@ mod tests {
             rng1.clone(),
             timer_client.clone(),
             ticks_to_rekey,
+            None,
+            0,
+            None,
+            None,
+            sessions1.clone(),
             thread_pool.clone(),
         );
 
@@ -407,6 +1040,11 @@ mod tests {
             rng2.clone(),
             timer_client.clone(),
             ticks_to_rekey,
+            None,
+            0,
+            None,
+            None,
+            Arc::new(Mutex::new(HashMap::new())),
             thread_pool.clone(),
         );
 
@@ -430,5 +1068,719 @@ mod tests {
 
         assert_eq!(true, thread_pool.run(output_receiver1).unwrap());
         assert_eq!(true, thread_pool.run(output_receiver2).unwrap());
+
+        // Resumption is disabled, so the session was not kept:
+        assert!(sessions1.lock().unwrap().is_empty());
+    }
+
+    async fn task_secure_channel_migrate(
+        identity_client1: IdentityClient,
+        public_key1: PublicKey,
+        identity_client2: IdentityClient,
+        public_key2: PublicKey,
+        timer_client: TimerClient,
+        mut spawner: ThreadPool,
+    ) {
+        let rng1 = DummyRandom::new(&[1u8]);
+        let rng2 = DummyRandom::new(&[2u8]);
+        let sessions1: Sessions = Arc::new(Mutex::new(HashMap::new()));
+        let sessions2: Sessions = Arc::new(Mutex::new(HashMap::new()));
+        let ticks_to_rekey: usize = 16;
+
+        let (sender1, receiver2) = mpsc::channel::<Vec<u8>>(0);
+        let (sender2, receiver1) = mpsc::channel::<Vec<u8>>(0);
+
+        let fut_sc1 = create_secure_channel(
+            sender1,
+            receiver1,
+            identity_client1,
+            Some(public_key2.clone()),
+            rng1.clone(),
+            timer_client.clone(),
+            ticks_to_rekey,
+            None,
+            0,
+            None,
+            Some(TEST_RESUME_TICKS),
+            sessions1.clone(),
+            spawner.clone(),
+        );
+        let fut_sc2 = create_secure_channel(
+            sender2,
+            receiver2,
+            identity_client2.clone(),
+            None,
+            rng2.clone(),
+            timer_client.clone(),
+            ticks_to_rekey,
+            None,
+            0,
+            None,
+            Some(TEST_RESUME_TICKS),
+            sessions2.clone(),
+            spawner.clone(),
+        );
+        let (res_sender, res_receiver) = oneshot::channel();
+        spawner
+            .spawn(fut_sc2.map(|res| {
+                let _ = res_sender.send(res);
+            }))
+            .unwrap();
+        let res1 = await!(fut_sc1);
+        let res2 = await!(res_receiver).unwrap();
+        let (_, (mut user_sender1, mut user_receiver1)) = res1.unwrap().unwrap();
+        let (_, (mut user_sender2, mut user_receiver2)) = res2.unwrap().unwrap();
+
+        await!(user_sender1.send(vec![1, 2, 3])).unwrap();
+        assert_eq!(await!(user_receiver2.next()).unwrap(), vec![1, 2, 3]);
+
+        // Move the secure channel to a new transport:
+        let (sender1, receiver2) = mpsc::channel::<Vec<u8>>(0);
+        let (sender2, receiver1) = mpsc::channel::<Vec<u8>>(0);
+
+        let fut_migrate1 = migrate_secure_channel(
+            sender1,
+            receiver1,
+            public_key2.clone(),
+            rng1.clone(),
+            sessions1.clone(),
+        );
+        let fut_sc2 = create_secure_channel(
+            sender2,
+            receiver2,
+            identity_client2.clone(),
+            None,
+            rng2.clone(),
+            timer_client.clone(),
+            ticks_to_rekey,
+            None,
+            0,
+            None,
+            Some(TEST_RESUME_TICKS),
+            sessions2.clone(),
+            spawner.clone(),
+        );
+        let (res_sender, res_receiver) = oneshot::channel();
+        spawner
+            .spawn(fut_sc2.map(|res| {
+                let _ = res_sender.send(res);
+            }))
+            .unwrap();
+        let res1 = await!(fut_migrate1);
+        let res2 = await!(res_receiver).unwrap();
+        res1.unwrap();
+        // No new secure channel was created:
+        assert!(res2.unwrap().is_none());
+
+        // The original secure channel keeps working:
+        await!(user_sender1.send(vec![4, 5])).unwrap();
+        assert_eq!(await!(user_receiver2.next()).unwrap(), vec![4, 5]);
+        await!(user_sender2.send(vec![6])).unwrap();
+        assert_eq!(await!(user_receiver1.next()).unwrap(), vec![6]);
+
+        // A tampered resumption is rejected:
+        let (sender1, receiver2) = mpsc::channel::<Vec<u8>>(0);
+        let (mut sender2, receiver1) = mpsc::channel::<Vec<u8>>(0);
+        let fut_migrate1 = migrate_secure_channel(
+            sender1,
+            receiver1,
+            public_key2.clone(),
+            rng1.clone(),
+            sessions1.clone(),
+        );
+        let fut_tampered = async move {
+            let mut receiver2 = receiver2;
+            // Pretend to be the listening side, without knowing the session secret:
+            let _ = await!(receiver2.next()).unwrap();
+            let (_, exchange_rand_nonce) = ScStateInitial::new(&public_key1, &rng2);
+            await!(sender2.send(serialize_channel_open(&ChannelOpen::ExchangeRandNonce(
+                exchange_rand_nonce
+            ))))
+            .unwrap();
+            let resume_challenge = ResumeChallenge {
+                rand_nonce: RandValue::new(&rng2),
+                proof: HashResult::default(),
+            };
+            await!(sender2.send(serialize_resume_challenge(&resume_challenge))).unwrap();
+        };
+        spawner.spawn(fut_tampered).unwrap();
+        match await!(fut_migrate1) {
+            Err(SecureChannelError::HandleResumeError(ScStateError::InvalidResumeProof)) => {}
+            _ => unreachable!(),
+        };
+
+        // The original secure channel still works:
+        await!(user_sender1.send(vec![7])).unwrap();
+        assert_eq!(await!(user_receiver2.next()).unwrap(), vec![7]);
+    }
+
+    /// Amount of incoming messages that may wait for the user, used in the backpressure test.
+    const TEST_INCOMING_QUEUE_LEN: usize = 2;
+
+    async fn task_secure_channel_backpressure(
+        identity_client1: IdentityClient,
+        public_key1: PublicKey,
+        identity_client2: IdentityClient,
+        public_key2: PublicKey,
+        timer_client: TimerClient,
+        mut tick_sender: mpsc::Sender<()>,
+        mut spawner: ThreadPool,
+    ) {
+        let rng1 = DummyRandom::new(&[1u8]);
+        let rng2 = DummyRandom::new(&[2u8]);
+        let sessions2: Sessions = Arc::new(Mutex::new(HashMap::new()));
+        let ticks_to_rekey: usize = 16;
+
+        let (sender1, receiver2) = mpsc::channel::<Vec<u8>>(0);
+        let (sender2, receiver1) = mpsc::channel::<Vec<u8>>(0);
+
+        let fut_sc1 = create_secure_channel(
+            sender1,
+            receiver1,
+            identity_client1,
+            Some(public_key2),
+            rng1,
+            timer_client.clone(),
+            ticks_to_rekey,
+            None,
+            TEST_INCOMING_QUEUE_LEN,
+            None,
+            None,
+            Arc::new(Mutex::new(HashMap::new())),
+            spawner.clone(),
+        );
+        let fut_sc2 = create_secure_channel(
+            sender2,
+            receiver2,
+            identity_client2,
+            Some(public_key1.clone()),
+            rng2,
+            timer_client,
+            ticks_to_rekey,
+            None,
+            TEST_INCOMING_QUEUE_LEN,
+            None,
+            Some(TEST_RESUME_TICKS),
+            sessions2.clone(),
+            spawner.clone(),
+        );
+        let (res_sender, res_receiver) = oneshot::channel();
+        spawner
+            .spawn(fut_sc2.map(|res| {
+                let _ = res_sender.send(res);
+            }))
+            .unwrap();
+        let res1 = await!(fut_sc1);
+        let res2 = await!(res_receiver).unwrap();
+        let (_, (mut user_sender1, mut user_receiver1)) = res1.unwrap().unwrap();
+        let (_, (mut user_sender2, mut user_receiver2)) = res2.unwrap().unwrap();
+        let stats2 = sessions2
+            .lock()
+            .unwrap()
+            .get(&public_key1)
+            .unwrap()
+            .stats
+            .clone();
+
+        // Side 1 sends many messages, but the user of side 2 does not receive them yet:
+        let num_messages = 32u8;
+        spawner
+            .spawn(
+                async move {
+                    for i in 0..num_messages {
+                        await!(user_sender1.send(vec![i])).unwrap();
+                    }
+                },
+            )
+            .unwrap();
+
+        // Side 2 keeps sending while its incoming direction is blocked.
+        // Wait until the incoming queue of side 2 is full:
+        let mut j = 0u8;
+        while stats2.incoming_messages() < TEST_INCOMING_QUEUE_LEN + 1 {
+            await!(user_sender2.send(vec![j])).unwrap();
+            assert_eq!(await!(user_receiver1.next()).unwrap(), vec![j]);
+            j = j.wrapping_add(1);
+        }
+
+        // Time passes. No more messages are decrypted by side 2:
+        for _ in 0..4 {
+            await!(tick_sender.send(())).unwrap();
+        }
+        while stats2.incoming_blocked_ticks() == 0 {
+            await!(user_sender2.send(vec![j])).unwrap();
+            assert_eq!(await!(user_receiver1.next()).unwrap(), vec![j]);
+            j = j.wrapping_add(1);
+        }
+        assert_eq!(stats2.incoming_messages(), TEST_INCOMING_QUEUE_LEN + 1);
+
+        // All the messages arrive in order once the user of side 2 receives:
+        for i in 0..num_messages {
+            assert_eq!(await!(user_receiver2.next()).unwrap(), vec![i]);
+        }
+        assert_eq!(stats2.incoming_messages(), usize::from(num_messages));
+    }
+
+    #[test]
+    fn test_secure_channel_backpressure() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+
+        // Create a mock time service:
+        let (tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, thread_pool.clone()).unwrap();
+
+        let (identity_client1, public_key1) = spawn_fixture_identity(1, &mut thread_pool);
+        let (identity_client2, public_key2) = spawn_fixture_identity(2, &mut thread_pool);
+
+        thread_pool.run(task_secure_channel_backpressure(
+            identity_client1,
+            public_key1,
+            identity_client2,
+            public_key2,
+            timer_client,
+            tick_sender,
+            thread_pool.clone(),
+        ));
+    }
+
+    #[test]
+    fn test_secure_channel_migrate() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, thread_pool.clone()).unwrap();
+
+        let (identity_client1, public_key1) = spawn_fixture_identity(1, &mut thread_pool);
+        let (identity_client2, public_key2) = spawn_fixture_identity(2, &mut thread_pool);
+
+        thread_pool.run(task_secure_channel_migrate(
+            identity_client1,
+            public_key1,
+            identity_client2,
+            public_key2,
+            timer_client,
+            thread_pool.clone(),
+        ));
+    }
+
+    /// Create a secure channel between two sides.
+    /// Returns the user side of both channels and their session handles.
+    async fn create_sc_pair(
+        identity_client1: IdentityClient,
+        public_key1: PublicKey,
+        identity_client2: IdentityClient,
+        public_key2: PublicKey,
+        timer_client: TimerClient,
+        max_messages_before_rekey: Option<u64>,
+        mut spawner: ThreadPool,
+    ) -> ((ConnPairVec, SessionHandle), (ConnPairVec, SessionHandle)) {
+        let rng1 = DummyRandom::new(&[1u8]);
+        let rng2 = DummyRandom::new(&[2u8]);
+        let sessions1: Sessions = Arc::new(Mutex::new(HashMap::new()));
+        let sessions2: Sessions = Arc::new(Mutex::new(HashMap::new()));
+        let ticks_to_rekey: usize = 16;
+
+        let (sender1, receiver2) = mpsc::channel::<Vec<u8>>(0);
+        let (sender2, receiver1) = mpsc::channel::<Vec<u8>>(0);
+
+        let fut_sc1 = create_secure_channel(
+            sender1,
+            receiver1,
+            identity_client1,
+            Some(public_key2.clone()),
+            rng1,
+            timer_client.clone(),
+            ticks_to_rekey,
+            max_messages_before_rekey,
+            0,
+            None,
+            Some(TEST_RESUME_TICKS),
+            sessions1.clone(),
+            spawner.clone(),
+        );
+        let fut_sc2 = create_secure_channel(
+            sender2,
+            receiver2,
+            identity_client2,
+            Some(public_key1.clone()),
+            rng2,
+            timer_client,
+            ticks_to_rekey,
+            max_messages_before_rekey,
+            0,
+            None,
+            Some(TEST_RESUME_TICKS),
+            sessions2.clone(),
+            spawner.clone(),
+        );
+        let (res_sender, res_receiver) = oneshot::channel();
+        spawner
+            .spawn(fut_sc2.map(|res| {
+                let _ = res_sender.send(res);
+            }))
+            .unwrap();
+        let res1 = await!(fut_sc1);
+        let res2 = await!(res_receiver).unwrap();
+        let (_, conn_pair1) = res1.unwrap().unwrap();
+        let (_, conn_pair2) = res2.unwrap().unwrap();
+
+        let session_handle1 = sessions1
+            .lock()
+            .unwrap()
+            .get(&public_key2)
+            .cloned()
+            .unwrap();
+        let session_handle2 = sessions2
+            .lock()
+            .unwrap()
+            .get(&public_key1)
+            .cloned()
+            .unwrap();
+        ((conn_pair1, session_handle1), (conn_pair2, session_handle2))
+    }
+
+    async fn task_secure_channel_forced_rekey(
+        identity_client1: IdentityClient,
+        public_key1: PublicKey,
+        identity_client2: IdentityClient,
+        public_key2: PublicKey,
+        timer_client: TimerClient,
+        mut spawner: ThreadPool,
+    ) {
+        let (
+            ((mut user_sender1, mut user_receiver1), session_handle1),
+            ((mut user_sender2, mut user_receiver2), session_handle2),
+        ) = await!(create_sc_pair(
+            identity_client1,
+            public_key1,
+            identity_client2,
+            public_key2,
+            timer_client,
+            None,
+            spawner.clone()
+        ));
+        let mut control1 = session_handle1.control.clone();
+
+        // Side 1 forces a rekey while messages are in flight:
+        let num_messages = 8u8;
+        for i in 0..num_messages / 2 {
+            await!(user_sender1.send(vec![i])).unwrap();
+            assert_eq!(await!(user_receiver2.next()).unwrap(), vec![i]);
+        }
+        let (rekey_done_sender, rekey_done_receiver) = oneshot::channel();
+        spawner
+            .spawn(
+                async move {
+                    await!(control1.rekey()).unwrap();
+                    rekey_done_sender.send(()).unwrap();
+                },
+            )
+            .unwrap();
+        for i in num_messages / 2..num_messages {
+            await!(user_sender1.send(vec![i])).unwrap();
+            assert_eq!(await!(user_receiver2.next()).unwrap(), vec![i]);
+        }
+
+        // No time has passed, so this is the only rekey:
+        await!(rekey_done_receiver).unwrap();
+        assert_eq!(session_handle1.stats.rekeys(), 1);
+        assert_eq!(session_handle2.stats.rekeys(), 1);
+
+        // Both sides can still communicate using the new keys:
+        await!(user_sender2.send(vec![5, 4, 3])).unwrap();
+        assert_eq!(await!(user_receiver1.next()).unwrap(), vec![5, 4, 3]);
+        await!(user_sender1.send(vec![0, 1, 2])).unwrap();
+        assert_eq!(await!(user_receiver2.next()).unwrap(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn test_secure_channel_forced_rekey() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, thread_pool.clone()).unwrap();
+
+        let (identity_client1, public_key1) = spawn_fixture_identity(1, &mut thread_pool);
+        let (identity_client2, public_key2) = spawn_fixture_identity(2, &mut thread_pool);
+
+        thread_pool.run(task_secure_channel_forced_rekey(
+            identity_client1,
+            public_key1,
+            identity_client2,
+            public_key2,
+            timer_client,
+            thread_pool.clone(),
+        ));
+    }
+
+    async fn task_secure_channel_max_messages_rekey(
+        identity_client1: IdentityClient,
+        public_key1: PublicKey,
+        identity_client2: IdentityClient,
+        public_key2: PublicKey,
+        timer_client: TimerClient,
+        spawner: ThreadPool,
+    ) {
+        let (
+            ((mut user_sender1, mut user_receiver1), session_handle1),
+            ((mut user_sender2, mut user_receiver2), session_handle2),
+        ) = await!(create_sc_pair(
+            identity_client1,
+            public_key1,
+            identity_client2,
+            public_key2,
+            timer_client,
+            Some(2),
+            spawner
+        ));
+
+        for i in 0..6u8 {
+            await!(user_sender1.send(vec![i])).unwrap();
+            assert_eq!(await!(user_receiver2.next()).unwrap(), vec![i]);
+        }
+        // Side 2 answered the rekey of side 1 before sending this message:
+        await!(user_sender2.send(vec![5, 4, 3])).unwrap();
+        assert_eq!(await!(user_receiver1.next()).unwrap(), vec![5, 4, 3]);
+
+        // No time has passed, but side 1 has sent enough messages to cause a rekey:
+        assert!(session_handle1.stats.rekeys() >= 1);
+        assert!(session_handle2.stats.rekeys() >= 1);
+    }
+
+    #[test]
+    fn test_secure_channel_max_messages_rekey() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+
+        // Create a mock time service:
+        let (_tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, thread_pool.clone()).unwrap();
+
+        let (identity_client1, public_key1) = spawn_fixture_identity(1, &mut thread_pool);
+        let (identity_client2, public_key2) = spawn_fixture_identity(2, &mut thread_pool);
+
+        thread_pool.run(task_secure_channel_max_messages_rekey(
+            identity_client1,
+            public_key1,
+            identity_client2,
+            public_key2,
+            timer_client,
+            thread_pool.clone(),
+        ));
+    }
+
+    /// Amount of ticks a closed secure channel may be resumed, used in the resumption test.
+    const TEST_RESUME_TICKS: usize = 4;
+
+    /// One side of the resumption test.
+    struct ResumeSide {
+        identity_client: IdentityClient,
+        public_key: PublicKey,
+        sessions: Sessions,
+    }
+
+    /// The ticket kept for the most recent session with `remote_public_key`, if any.
+    fn session_ticket(sessions: &Sessions, remote_public_key: &PublicKey) -> Option<ResumeTicket> {
+        sessions
+            .lock()
+            .unwrap()
+            .get(remote_public_key)
+            .map(|session_handle| session_handle.resume_ticket.clone())
+    }
+
+    /// Create a secure channel between two sides, offering the given tickets.
+    /// Returns the user side of both channels, and whether each of them was resumed.
+    async fn connect_resume_sides<'a>(
+        side1: &'a ResumeSide,
+        side2: &'a ResumeSide,
+        opt_ticket1: Option<ResumeTicket>,
+        opt_ticket2: Option<ResumeTicket>,
+        seed: u8,
+        timer_client: TimerClient,
+        mut spawner: ThreadPool,
+    ) -> ((ConnPairVec, bool), (ConnPairVec, bool)) {
+        let ticks_to_rekey: usize = 16;
+
+        let (sender1, receiver2) = mpsc::channel::<Vec<u8>>(0);
+        let (sender2, receiver1) = mpsc::channel::<Vec<u8>>(0);
+
+        let fut_sc1 = create_secure_channel(
+            sender1,
+            receiver1,
+            side1.identity_client.clone(),
+            Some(side2.public_key.clone()),
+            DummyRandom::new(&[seed, 1u8]),
+            timer_client.clone(),
+            ticks_to_rekey,
+            None,
+            0,
+            opt_ticket1,
+            Some(TEST_RESUME_TICKS),
+            side1.sessions.clone(),
+            spawner.clone(),
+        );
+        let fut_sc2 = create_secure_channel(
+            sender2,
+            receiver2,
+            side2.identity_client.clone(),
+            Some(side1.public_key.clone()),
+            DummyRandom::new(&[seed, 2u8]),
+            timer_client,
+            ticks_to_rekey,
+            None,
+            0,
+            opt_ticket2,
+            Some(TEST_RESUME_TICKS),
+            side2.sessions.clone(),
+            spawner.clone(),
+        );
+        let (res_sender, res_receiver) = oneshot::channel();
+        spawner
+            .spawn(fut_sc2.map(|res| {
+                let _ = res_sender.send(res);
+            }))
+            .unwrap();
+        let res1 = await!(fut_sc1);
+        let res2 = await!(res_receiver).unwrap();
+        let (_, conn_pair1) = res1.unwrap().unwrap();
+        let (_, conn_pair2) = res2.unwrap().unwrap();
+
+        let resumed = |side: &ResumeSide, remote_public_key: &PublicKey| {
+            side.sessions
+                .lock()
+                .unwrap()
+                .get(remote_public_key)
+                .unwrap()
+                .stats
+                .resumed()
+        };
+        let resumed1 = resumed(side1, &side2.public_key);
+        let resumed2 = resumed(side2, &side1.public_key);
+        ((conn_pair1, resumed1), (conn_pair2, resumed2))
+    }
+
+    /// Send a message in each direction, and then disconnect both sides.
+    async fn send_recv_disconnect(conn_pair1: ConnPairVec, conn_pair2: ConnPairVec) {
+        let (mut user_sender1, mut user_receiver1) = conn_pair1;
+        let (mut user_sender2, mut user_receiver2) = conn_pair2;
+        await!(user_sender1.send(vec![1, 2, 3])).unwrap();
+        assert_eq!(await!(user_receiver2.next()).unwrap(), vec![1, 2, 3]);
+        await!(user_sender2.send(vec![4, 5])).unwrap();
+        assert_eq!(await!(user_receiver1.next()).unwrap(), vec![4, 5]);
+        // The secure channels are closed when the user sides are dropped.
+    }
+
+    async fn task_secure_channel_resume(
+        identity_client1: IdentityClient,
+        public_key1: PublicKey,
+        identity_client2: IdentityClient,
+        public_key2: PublicKey,
+        timer_client: TimerClient,
+        mut tick_sender: mpsc::Sender<()>,
+        spawner: ThreadPool,
+    ) {
+        let side1 = ResumeSide {
+            identity_client: identity_client1,
+            public_key: public_key1,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        };
+        let side2 = ResumeSide {
+            identity_client: identity_client2,
+            public_key: public_key2,
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        };
+
+        // The first session requires a full exchange:
+        let ((conn_pair1, resumed1), (conn_pair2, resumed2)) = await!(connect_resume_sides(
+            &side1,
+            &side2,
+            None,
+            None,
+            1,
+            timer_client.clone(),
+            spawner.clone()
+        ));
+        assert!(!resumed1 && !resumed2);
+        await!(send_recv_disconnect(conn_pair1, conn_pair2));
+
+        // Reconnect shortly after the disconnect, using the tickets of the previous session:
+        let opt_ticket1 = session_ticket(&side1.sessions, &side2.public_key);
+        let opt_ticket2 = session_ticket(&side2.sessions, &side1.public_key);
+        assert!(opt_ticket1.is_some() && opt_ticket2.is_some());
+        let ((conn_pair1, resumed1), (conn_pair2, resumed2)) = await!(connect_resume_sides(
+            &side1,
+            &side2,
+            opt_ticket1,
+            opt_ticket2,
+            2,
+            timer_client.clone(),
+            spawner.clone()
+        ));
+        assert!(resumed1 && resumed2);
+        await!(send_recv_disconnect(conn_pair1, conn_pair2));
+
+        // Time passes, and the tickets of the resumed session expire:
+        let expired_ticket1 = session_ticket(&side1.sessions, &side2.public_key).unwrap();
+        while session_ticket(&side1.sessions, &side2.public_key).is_some()
+            || session_ticket(&side2.sessions, &side1.public_key).is_some()
+        {
+            await!(tick_sender.send(())).unwrap();
+        }
+
+        // Side 1 still offers the expired ticket. Side 2 does not recognize it, and we fall back
+        // to a full exchange:
+        let ((conn_pair1, resumed1), (conn_pair2, resumed2)) = await!(connect_resume_sides(
+            &side1,
+            &side2,
+            Some(expired_ticket1),
+            None,
+            3,
+            timer_client.clone(),
+            spawner.clone()
+        ));
+        assert!(!resumed1 && !resumed2);
+        await!(send_recv_disconnect(conn_pair1, conn_pair2));
+
+        // Side 1 offers a garbage ticket, while side 2 offers a valid ticket.
+        // We fall back to a full exchange:
+        let garbage_ticket1 = session_ticket(&side1.sessions, &side2.public_key)
+            .unwrap()
+            .with_resume_secret(HashResult::from(&[0x33; HASH_RESULT_LEN]));
+        let opt_ticket2 = session_ticket(&side2.sessions, &side1.public_key);
+        assert!(opt_ticket2.is_some());
+        let ((conn_pair1, resumed1), (conn_pair2, resumed2)) = await!(connect_resume_sides(
+            &side1,
+            &side2,
+            Some(garbage_ticket1),
+            opt_ticket2,
+            4,
+            timer_client,
+            spawner
+        ));
+        assert!(!resumed1 && !resumed2);
+        await!(send_recv_disconnect(conn_pair1, conn_pair2));
+    }
+
+    #[test]
+    fn test_secure_channel_resume() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+
+        // Create a mock time service:
+        let (tick_sender, tick_receiver) = mpsc::channel::<()>(0);
+        let timer_client = create_timer_incoming(tick_receiver, thread_pool.clone()).unwrap();
+
+        let (identity_client1, public_key1) = spawn_fixture_identity(1, &mut thread_pool);
+        let (identity_client2, public_key2) = spawn_fixture_identity(2, &mut thread_pool);
+
+        thread_pool.run(task_secure_channel_resume(
+            identity_client1,
+            public_key1,
+            identity_client2,
+            public_key2,
+            timer_client,
+            tick_sender,
+            thread_pool.clone(),
+        ));
     }
 }
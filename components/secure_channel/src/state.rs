@@ -3,11 +3,13 @@ use std::mem;
 
 use crypto::crypto_rand::{CryptoRandom, RandValue};
 use crypto::dh::{DhPrivateKey, Salt};
+use crypto::hash::{sha_512_256, HashResult};
 use crypto::identity::{verify_signature, PublicKey, Signature};
-use crypto::sym_encrypt::{Decryptor, Encryptor};
+use crypto::sym_encrypt::{Decryptor, Encryptor, SymmetricKey};
 use identity::IdentityClient;
 use proto::secure_channel::messages::{
     ChannelContent, ChannelMessage, EncryptedData, ExchangeDh, ExchangeRandNonce, PlainData, Rekey,
+    ResumeChallenge, ResumeProof, ResumeRequest, ResumeSession,
 };
 use proto::secure_channel::serialize::{deserialize_channel_message, serialize_channel_message};
 
@@ -26,6 +28,9 @@ pub enum ScStateError {
     DecryptionFailure,
     DeserializeError,
     RekeyInProgress,
+    UnexpectedResumePublicKey,
+    InvalidResumeProof,
+    UnknownResumeTicket,
 }
 
 pub struct ScStateInitial {
@@ -47,7 +52,6 @@ struct PendingRekey {
 }
 
 pub struct ScState {
-    #[allow(unused)]
     local_public_key: PublicKey,
     remote_public_key: PublicKey,
     sender: Encryptor,
@@ -57,6 +61,207 @@ pub struct ScState {
     /// messages for the new receiver.
     opt_old_receiver: Option<Decryptor>,
     opt_pending_rekey: Option<PendingRekey>,
+    /// Secret shared by both sides of the session, used to move the session
+    /// to a new transport without a full handshake.
+    resume_secret: HashResult,
+}
+
+/// Allows resuming a session over a new transport.
+/// Holds the immutable parts of a session state (ScState), so that
+/// resumption may be performed without access to the encryption state.
+#[derive(Clone)]
+pub struct ResumeTicket {
+    local_public_key: PublicKey,
+    remote_public_key: PublicKey,
+    resume_secret: HashResult,
+}
+
+/// Resumption in progress, initiated by the local side.
+pub struct ResumeInitiator {
+    local_rand_nonce: RandValue,
+}
+
+/// Resumption in progress, initiated by the remote side.
+pub struct ResumeResponder {
+    remote_rand_nonce: RandValue,
+    local_rand_nonce: RandValue,
+}
+
+/// Calculate a shared resumption secret from the session symmetric keys.
+/// Both sides obtain the same result, as the send key of one side is the receive key of
+/// the other side.
+fn calc_resume_secret(send_key: &SymmetricKey, recv_key: &SymmetricKey) -> HashResult {
+    let (first, second) = if send_key < recv_key {
+        (send_key, recv_key)
+    } else {
+        (recv_key, send_key)
+    };
+    let mut hash_buffer = Vec::new();
+    hash_buffer.extend_from_slice(b"RESUME");
+    hash_buffer.extend_from_slice(first);
+    hash_buffer.extend_from_slice(second);
+    sha_512_256(&hash_buffer)
+}
+
+/// Calculate a resumption proof. `prover_public_key` is the identity of the side
+/// creating the proof, binding the proof to its direction.
+fn calc_resume_proof(
+    resume_secret: &HashResult,
+    prover_public_key: &PublicKey,
+    first_nonce: &RandValue,
+    second_nonce: &RandValue,
+) -> HashResult {
+    let mut hash_buffer = Vec::new();
+    hash_buffer.extend_from_slice(resume_secret);
+    hash_buffer.extend_from_slice(prover_public_key);
+    hash_buffer.extend_from_slice(first_nonce);
+    hash_buffer.extend_from_slice(second_nonce);
+    sha_512_256(&hash_buffer)
+}
+
+/// Calculate the identifier of a resumption ticket. Both sides of a session obtain the same
+/// identifier, and the identifier reveals nothing about the session secret.
+fn calc_ticket_id(resume_secret: &HashResult) -> HashResult {
+    let mut hash_buffer = Vec::new();
+    hash_buffer.extend_from_slice(b"TICKET");
+    hash_buffer.extend_from_slice(resume_secret);
+    sha_512_256(&hash_buffer)
+}
+
+/// Calculate the proof sent when offering to resume a session using a ticket.
+fn calc_resume_session_proof(
+    resume_secret: &HashResult,
+    prover_public_key: &PublicKey,
+    rand_nonce: &RandValue,
+) -> HashResult {
+    let mut hash_buffer = Vec::new();
+    hash_buffer.extend_from_slice(b"SESSION");
+    hash_buffer.extend_from_slice(resume_secret);
+    hash_buffer.extend_from_slice(prover_public_key);
+    hash_buffer.extend_from_slice(rand_nonce);
+    sha_512_256(&hash_buffer)
+}
+
+/// Calculate the key used by `sender_public_key` to encrypt messages in a session resumed from a
+/// ticket. The nonces of both sides are fresh, so every resumption results in new keys.
+fn calc_resumed_key(
+    resume_secret: &HashResult,
+    sender_public_key: &PublicKey,
+    sender_rand_nonce: &RandValue,
+    receiver_rand_nonce: &RandValue,
+) -> SymmetricKey {
+    let mut hash_buffer = Vec::new();
+    hash_buffer.extend_from_slice(b"RESUME_KEY");
+    hash_buffer.extend_from_slice(resume_secret);
+    hash_buffer.extend_from_slice(sender_public_key);
+    hash_buffer.extend_from_slice(sender_rand_nonce);
+    hash_buffer.extend_from_slice(receiver_rand_nonce);
+    SymmetricKey::from(sha_512_256(&hash_buffer).as_array_ref())
+}
+
+impl ResumeTicket {
+    /// Identifies this ticket to the remote side, which holds the same ticket.
+    pub fn ticket_id(&self) -> HashResult {
+        calc_ticket_id(&self.resume_secret)
+    }
+
+    /// Get the public key of the remote side
+    pub fn get_remote_public_key(&self) -> &PublicKey {
+        &self.remote_public_key
+    }
+
+    /// A ticket between the same sides, with a different secret.
+    #[cfg(test)]
+    pub(crate) fn with_resume_secret(&self, resume_secret: HashResult) -> ResumeTicket {
+        ResumeTicket {
+            resume_secret,
+            ..self.clone()
+        }
+    }
+
+    /// Begin resumption of the session over a new transport.
+    pub fn create_resume_request<R: CryptoRandom>(
+        &self,
+        rng: &R,
+    ) -> (ResumeInitiator, ResumeRequest) {
+        let local_rand_nonce = RandValue::new(rng);
+        let resume_request = ResumeRequest {
+            public_key: self.local_public_key.clone(),
+            rand_nonce: local_rand_nonce.clone(),
+        };
+        (ResumeInitiator { local_rand_nonce }, resume_request)
+    }
+
+    /// Handle a resumption request received from the remote side over a new transport.
+    pub fn handle_resume_request<R: CryptoRandom>(
+        &self,
+        resume_request: ResumeRequest,
+        rng: &R,
+    ) -> Result<(ResumeResponder, ResumeChallenge), ScStateError> {
+        if resume_request.public_key != self.remote_public_key {
+            return Err(ScStateError::UnexpectedResumePublicKey);
+        }
+        let local_rand_nonce = RandValue::new(rng);
+        let proof = calc_resume_proof(
+            &self.resume_secret,
+            &self.local_public_key,
+            &resume_request.rand_nonce,
+            &local_rand_nonce,
+        );
+        let resume_challenge = ResumeChallenge {
+            rand_nonce: local_rand_nonce.clone(),
+            proof,
+        };
+        let resume_responder = ResumeResponder {
+            remote_rand_nonce: resume_request.rand_nonce,
+            local_rand_nonce,
+        };
+        Ok((resume_responder, resume_challenge))
+    }
+
+    /// Verify the remote side's challenge, and create a proof of our own.
+    /// On success, the new transport is bound to the session from our side.
+    pub fn handle_resume_challenge(
+        &self,
+        resume_initiator: ResumeInitiator,
+        resume_challenge: ResumeChallenge,
+    ) -> Result<ResumeProof, ScStateError> {
+        let expected_proof = calc_resume_proof(
+            &self.resume_secret,
+            &self.remote_public_key,
+            &resume_initiator.local_rand_nonce,
+            &resume_challenge.rand_nonce,
+        );
+        if expected_proof != resume_challenge.proof {
+            return Err(ScStateError::InvalidResumeProof);
+        }
+        let proof = calc_resume_proof(
+            &self.resume_secret,
+            &self.local_public_key,
+            &resume_challenge.rand_nonce,
+            &resume_initiator.local_rand_nonce,
+        );
+        Ok(ResumeProof { proof })
+    }
+
+    /// Verify the final proof of the remote side.
+    /// On success, the new transport is bound to the session from our side.
+    pub fn handle_resume_proof(
+        &self,
+        resume_responder: ResumeResponder,
+        resume_proof: ResumeProof,
+    ) -> Result<(), ScStateError> {
+        let expected_proof = calc_resume_proof(
+            &self.resume_secret,
+            &self.remote_public_key,
+            &resume_responder.local_rand_nonce,
+            &resume_responder.remote_rand_nonce,
+        );
+        if expected_proof != resume_proof.proof {
+            return Err(ScStateError::InvalidResumeProof);
+        }
+        Ok(())
+    }
 }
 
 impl ScStateInitial {
@@ -77,6 +282,71 @@ impl ScStateInitial {
         (sc_state_initial, exchange_rand_nonce)
     }
 
+    /// Offer the remote side to derive the keys of the new session from `resume_ticket`, instead
+    /// of performing a full exchange. `exchange_rand_nonce` (Created by `new()`) is used if the
+    /// remote side does not offer the same ticket.
+    pub fn create_resume_session(
+        &self,
+        resume_ticket: &ResumeTicket,
+        exchange_rand_nonce: ExchangeRandNonce,
+    ) -> ResumeSession {
+        ResumeSession {
+            exchange_rand_nonce,
+            ticket_id: resume_ticket.ticket_id(),
+            proof: calc_resume_session_proof(
+                &resume_ticket.resume_secret,
+                &self.local_public_key,
+                &self.local_rand_nonce,
+            ),
+        }
+    }
+
+    /// Both sides offered to resume using `resume_ticket`. Derive fresh keys for the new session
+    /// from the ticket and the nonces of both sides.
+    pub fn handle_resume_session(
+        self,
+        resume_ticket: &ResumeTicket,
+        resume_session: &ResumeSession,
+    ) -> Result<ScState, ScStateError> {
+        if resume_session.ticket_id != resume_ticket.ticket_id() {
+            return Err(ScStateError::UnknownResumeTicket);
+        }
+        let remote_rand_nonce = &resume_session.exchange_rand_nonce.rand_nonce;
+        let remote_public_key = &resume_session.exchange_rand_nonce.public_key;
+        if remote_public_key != &resume_ticket.remote_public_key
+            || self.local_public_key != resume_ticket.local_public_key
+        {
+            return Err(ScStateError::UnexpectedResumePublicKey);
+        }
+        let expected_proof = calc_resume_session_proof(
+            &resume_ticket.resume_secret,
+            remote_public_key,
+            remote_rand_nonce,
+        );
+        if expected_proof != resume_session.proof {
+            return Err(ScStateError::InvalidResumeProof);
+        }
+
+        let send_key = calc_resumed_key(
+            &resume_ticket.resume_secret,
+            &self.local_public_key,
+            &self.local_rand_nonce,
+            remote_rand_nonce,
+        );
+        let recv_key = calc_resumed_key(
+            &resume_ticket.resume_secret,
+            remote_public_key,
+            remote_rand_nonce,
+            &self.local_rand_nonce,
+        );
+        ScState::new(
+            self.local_public_key,
+            remote_public_key.clone(),
+            &send_key,
+            &recv_key,
+        )
+    }
+
     pub async fn handle_exchange_rand_nonce<R: CryptoRandom + 'static>(
         self,
         exchange_rand_nonce: ExchangeRandNonce,
@@ -138,15 +408,12 @@ impl ScStateHalf {
             )
             .map_err(|_| ScStateError::KeyDerivationFailure)?;
 
-        Ok(ScState {
-            local_public_key: self.local_public_key,
-            remote_public_key: self.remote_public_key,
-            sender: Encryptor::new(&send_key).map_err(|_| ScStateError::CreateEncryptorFailure)?,
-            receiver: Decryptor::new(&recv_key)
-                .map_err(|_| ScStateError::CreateDecryptorFailure)?,
-            opt_old_receiver: None,
-            opt_pending_rekey: None,
-        })
+        ScState::new(
+            self.local_public_key,
+            self.remote_public_key,
+            &send_key,
+            &recv_key,
+        )
     }
 }
 
@@ -157,6 +424,23 @@ pub struct HandleIncomingOutput {
 }
 
 impl ScState {
+    fn new(
+        local_public_key: PublicKey,
+        remote_public_key: PublicKey,
+        send_key: &SymmetricKey,
+        recv_key: &SymmetricKey,
+    ) -> Result<ScState, ScStateError> {
+        Ok(ScState {
+            local_public_key,
+            remote_public_key,
+            sender: Encryptor::new(send_key).map_err(|_| ScStateError::CreateEncryptorFailure)?,
+            receiver: Decryptor::new(recv_key).map_err(|_| ScStateError::CreateDecryptorFailure)?,
+            opt_old_receiver: None,
+            opt_pending_rekey: None,
+            resume_secret: calc_resume_secret(send_key, recv_key),
+        })
+    }
+
     fn encrypt_outgoing<R: CryptoRandom>(
         &mut self,
         channel_content: ChannelContent,
@@ -331,18 +615,25 @@ impl ScState {
     pub fn get_remote_public_key(&self) -> &PublicKey {
         &self.remote_public_key
     }
+
+    /// Create a ticket that allows resuming this session over a new transport.
+    pub fn create_resume_ticket(&self) -> ResumeTicket {
+        ResumeTicket {
+            local_public_key: self.local_public_key.clone(),
+            remote_public_key: self.remote_public_key.clone(),
+            resume_secret: self.resume_secret.clone(),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     // use tokio_core::reactor::Core;
-    use crypto::identity::{generate_pkcs8_key_pair, SoftwareEd25519Identity};
+    use crypto::hash::HASH_RESULT_LEN;
     use crypto::test_utils::DummyRandom;
     use futures::executor::ThreadPool;
-    use futures::task::SpawnExt;
-    use futures::{future, FutureExt};
-    use identity::create_identity;
+    use identity::test_utils::spawn_fixture_identity;
     use identity::IdentityClient;
 
     async fn run_basic_sc_state(
@@ -444,25 +735,12 @@ mod tests {
 
     fn prepare_dh_test() -> (ScState, ScState, DummyRandom, DummyRandom) {
         let rng1 = DummyRandom::new(&[1u8]);
-        let pkcs8 = generate_pkcs8_key_pair(&rng1);
-        let identity1 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-        let (requests_sender1, identity_server1) = create_identity(identity1);
-        let identity_client1 = IdentityClient::new(requests_sender1);
-
         let rng2 = DummyRandom::new(&[2u8]);
-        let pkcs8 = generate_pkcs8_key_pair(&rng2);
-        let identity2 = SoftwareEd25519Identity::from_pkcs8(&pkcs8).unwrap();
-        let (requests_sender2, identity_server2) = create_identity(identity2);
-        let identity_client2 = IdentityClient::new(requests_sender2);
 
         // Start the Identity service:
         let mut thread_pool = ThreadPool::new().unwrap();
-        thread_pool
-            .spawn(identity_server1.then(|_| future::ready(())))
-            .unwrap();
-        thread_pool
-            .spawn(identity_server2.then(|_| future::ready(())))
-            .unwrap();
+        let (identity_client1, _) = spawn_fixture_identity(1, &mut thread_pool);
+        let (identity_client2, _) = spawn_fixture_identity(2, &mut thread_pool);
 
         let (sc_state1, sc_state2) = thread_pool
             .run(run_basic_sc_state(identity_client1, identity_client2))
@@ -480,9 +758,171 @@ mod tests {
         rekey_simultaneous(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
         send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
     }
+    #[test]
+    fn test_sc_state_resume() {
+        let (sc_state1, sc_state2, rng1, rng2) = prepare_dh_test();
+        let ticket1 = sc_state1.create_resume_ticket();
+        let ticket2 = sc_state2.create_resume_ticket();
+
+        let (resume_initiator, resume_request) = ticket1.create_resume_request(&rng1);
+        let (resume_responder, resume_challenge) = ticket2
+            .handle_resume_request(resume_request, &rng2)
+            .unwrap();
+        let resume_proof = ticket1
+            .handle_resume_challenge(resume_initiator, resume_challenge)
+            .unwrap();
+        ticket2
+            .handle_resume_proof(resume_responder, resume_proof)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sc_state_resume_tampered() {
+        let (sc_state1, sc_state2, rng1, rng2) = prepare_dh_test();
+        let ticket1 = sc_state1.create_resume_ticket();
+        let ticket2 = sc_state2.create_resume_ticket();
+
+        // A resumption request claiming the wrong identity is rejected:
+        let (_resume_initiator, mut resume_request) = ticket1.create_resume_request(&rng1);
+        resume_request.public_key = sc_state2.local_public_key.clone();
+        match ticket2.handle_resume_request(resume_request, &rng2) {
+            Err(ScStateError::UnexpectedResumePublicKey) => {}
+            _ => unreachable!(),
+        };
+
+        // A tampered challenge is rejected by the initiator:
+        let (resume_initiator, resume_request) = ticket1.create_resume_request(&rng1);
+        let (_resume_responder, mut resume_challenge) = ticket2
+            .handle_resume_request(resume_request, &rng2)
+            .unwrap();
+        resume_challenge.rand_nonce = RandValue::new(&rng2);
+        match ticket1.handle_resume_challenge(resume_initiator, resume_challenge) {
+            Err(ScStateError::InvalidResumeProof) => {}
+            _ => unreachable!(),
+        };
+
+        // A tampered proof is rejected by the responder:
+        let (resume_initiator, resume_request) = ticket1.create_resume_request(&rng1);
+        let (resume_responder, resume_challenge) = ticket2
+            .handle_resume_request(resume_request, &rng2)
+            .unwrap();
+        let mut resume_proof = ticket1
+            .handle_resume_challenge(resume_initiator, resume_challenge)
+            .unwrap();
+        resume_proof.proof = HashResult::default();
+        match ticket2.handle_resume_proof(resume_responder, resume_proof) {
+            Err(ScStateError::InvalidResumeProof) => {}
+            _ => unreachable!(),
+        };
+    }
+
+    /// Open a new session using the tickets of a previous session.
+    fn resume_session(
+        ticket1: &ResumeTicket,
+        ticket2: &ResumeTicket,
+        rng1: &DummyRandom,
+        rng2: &DummyRandom,
+    ) -> (Result<ScState, ScStateError>, Result<ScState, ScStateError>) {
+        let (sc_state_initial1, exchange_rand_nonce1) =
+            ScStateInitial::new(&ticket1.local_public_key, rng1);
+        let (sc_state_initial2, exchange_rand_nonce2) =
+            ScStateInitial::new(&ticket2.local_public_key, rng2);
+        let resume_session1 =
+            sc_state_initial1.create_resume_session(ticket1, exchange_rand_nonce1);
+        let resume_session2 =
+            sc_state_initial2.create_resume_session(ticket2, exchange_rand_nonce2);
+        (
+            sc_state_initial1.handle_resume_session(ticket1, &resume_session2),
+            sc_state_initial2.handle_resume_session(ticket2, &resume_session1),
+        )
+    }
+
+    #[test]
+    fn test_sc_state_resume_session() {
+        let (sc_state1, sc_state2, rng1, rng2) = prepare_dh_test();
+        let ticket1 = sc_state1.create_resume_ticket();
+        let ticket2 = sc_state2.create_resume_ticket();
+        assert_eq!(ticket1.ticket_id(), ticket2.ticket_id());
+
+        let (res1, res2) = resume_session(&ticket1, &ticket2, &rng1, &rng2);
+        let mut sc_state1 = res1.unwrap();
+        let mut sc_state2 = res2.unwrap();
+        send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+        rekey_sequential(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+        send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+
+        // The resumed session has a ticket of its own:
+        let new_ticket1 = sc_state1.create_resume_ticket();
+        let new_ticket2 = sc_state2.create_resume_ticket();
+        assert_eq!(new_ticket1.ticket_id(), new_ticket2.ticket_id());
+        assert_ne!(new_ticket1.ticket_id(), ticket1.ticket_id());
+    }
+
+    #[test]
+    fn test_sc_state_resume_session_invalid() {
+        let (sc_state1, sc_state2, rng1, rng2) = prepare_dh_test();
+        let ticket1 = sc_state1.create_resume_ticket();
+        let ticket2 = sc_state2.create_resume_ticket();
+
+        // A ticket of a different session is not recognized:
+        let garbage_ticket2 = ResumeTicket {
+            resume_secret: HashResult::from(&[0x33; HASH_RESULT_LEN]),
+            ..ticket2.clone()
+        };
+        match resume_session(&ticket1, &garbage_ticket2, &rng1, &rng2) {
+            (Err(ScStateError::UnknownResumeTicket), Err(ScStateError::UnknownResumeTicket)) => {}
+            _ => unreachable!(),
+        };
+
+        // A tampered proof is rejected:
+        let (sc_state_initial1, exchange_rand_nonce1) =
+            ScStateInitial::new(&ticket1.local_public_key, &rng1);
+        let mut resume_session1 =
+            sc_state_initial1.create_resume_session(&ticket1, exchange_rand_nonce1);
+        resume_session1.proof = HashResult::default();
+        let (sc_state_initial2, _) = ScStateInitial::new(&ticket2.local_public_key, &rng2);
+        match sc_state_initial2.handle_resume_session(&ticket2, &resume_session1) {
+            Err(ScStateError::InvalidResumeProof) => {}
+            _ => unreachable!(),
+        };
+    }
+
+    #[test]
+    fn test_sc_state_rekey_in_flight() {
+        let (mut sc_state1, mut sc_state2, rng1, rng2) = prepare_dh_test();
+
+        let rekey_enc_data1 = sc_state1.create_rekey(&rng1).unwrap();
+        // Only one rekey may be in progress:
+        match sc_state1.create_rekey(&rng1) {
+            Err(ScStateError::RekeyInProgress) => {}
+            _ => unreachable!(),
+        };
+        assert!(sc_state1.opt_pending_rekey.is_some());
+
+        // Side 1 keeps sending messages using the old keys until side 2 answers the rekey:
+        let plain_data = PlainData(vec![1, 2, 3]);
+        let enc_data1 = sc_state1.create_outgoing(&plain_data, &rng1);
+
+        let incoming_output = sc_state2.handle_incoming(&rekey_enc_data1, &rng2).unwrap();
+        assert!(incoming_output.rekey_occurred);
+        let rekey_enc_data2 = incoming_output.opt_send_message.unwrap();
+
+        // Side 2 already uses the new keys, but still decrypts the in flight message:
+        let incoming_output = sc_state2.handle_incoming(&enc_data1, &rng2).unwrap();
+        assert_eq!(incoming_output.opt_incoming_message.unwrap(), plain_data);
+        assert!(sc_state2.opt_old_receiver.is_some());
+
+        let incoming_output = sc_state1.handle_incoming(&rekey_enc_data2, &rng1).unwrap();
+        assert!(incoming_output.rekey_occurred);
+        assert!(sc_state1.opt_pending_rekey.is_none());
+
+        // The old keys are dropped once a message encrypted using the new keys arrives:
+        send_recv_messages(&mut sc_state1, &mut sc_state2, &rng1, &rng2);
+        assert!(sc_state1.opt_old_receiver.is_none());
+        assert!(sc_state2.opt_old_receiver.is_none());
+    }
+
     // TODO: Add tests:
-    // - Test the usage of old receiver
     // - Test error cases
     //   - deserialize error
-    //   - create_rekey() twice
 }
@@ -0,0 +1,71 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Default)]
+struct StatsInner {
+    incoming_messages: AtomicUsize,
+    incoming_blocked_ticks: AtomicUsize,
+    outgoing_blocked_ticks: AtomicUsize,
+    rekeys: AtomicUsize,
+    resumed: AtomicBool,
+}
+
+/// Instrumentation counters of a single secure channel.
+/// Clones share the same counters.
+#[derive(Debug, Clone, Default)]
+pub struct SecureChannelStats {
+    inner: Arc<StatsInner>,
+}
+
+impl SecureChannelStats {
+    pub(crate) fn add_incoming_message(&self) {
+        self.inner.incoming_messages.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_incoming_blocked_ticks(&self, ticks: usize) {
+        self.inner
+            .incoming_blocked_ticks
+            .fetch_add(ticks, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_outgoing_blocked_ticks(&self, ticks: usize) {
+        self.inner
+            .outgoing_blocked_ticks
+            .fetch_add(ticks, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_rekey(&self) {
+        self.inner.rekeys.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_resumed(&self) {
+        self.inner.resumed.store(true, Ordering::Relaxed);
+    }
+
+    /// Amount of incoming messages that were decrypted and handed to the user.
+    pub fn incoming_messages(&self) -> usize {
+        self.inner.incoming_messages.load(Ordering::Relaxed)
+    }
+
+    /// Amount of time ticks during which reading from the remote side was paused, because the
+    /// user did not receive the previous incoming messages yet.
+    pub fn incoming_blocked_ticks(&self) -> usize {
+        self.inner.incoming_blocked_ticks.load(Ordering::Relaxed)
+    }
+
+    /// Amount of time ticks during which messages from the user were not taken, because the
+    /// underlying channel was not ready to send.
+    pub fn outgoing_blocked_ticks(&self) -> usize {
+        self.inner.outgoing_blocked_ticks.load(Ordering::Relaxed)
+    }
+
+    /// Amount of completed rekeys, initiated by either side.
+    pub fn rekeys(&self) -> usize {
+        self.inner.rekeys.load(Ordering::Relaxed)
+    }
+
+    /// Was the channel created using the ticket of a previous session, without a full exchange?
+    pub fn resumed(&self) -> bool {
+        self.inner.resumed.load(Ordering::Relaxed)
+    }
+}
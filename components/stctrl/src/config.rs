@@ -1,11 +1,13 @@
+use std::fs;
 use std::path::PathBuf;
 
 use structopt::StructOpt;
 
+use app::invite::{string_to_friend_invite, ImportFriendInviteResult};
 use app::report::{ChannelStatusReport, NodeReport};
 use app::{
-    load_friend_from_file, load_index_server_from_file, load_relay_from_file, AppConfig,
-    NamedIndexServerAddress, NamedRelayAddress, NodeConnection,
+    load_friend_from_file, load_index_server_from_file, load_relay_from_file, AddFriendError,
+    AppConfig, NamedIndexServerAddress, NamedRelayAddress, NodeConnection,
 };
 
 use crate::utils::friend_public_key_by_name;
@@ -62,6 +64,20 @@ pub struct AddFriendCmd {
     pub balance: i128,
 }
 
+/// Add a friend using a friend invite
+#[derive(Clone, Debug, StructOpt)]
+pub struct ImportInviteCmd {
+    /// Path of friend invite file
+    #[structopt(parse(from_os_str), long = "invite", short = "i")]
+    pub invite_file: PathBuf,
+    /// Assigned friend name (You can pick any name)
+    #[structopt(long = "name", short = "n")]
+    pub friend_name: String,
+    /// Let the friend owe us up to the max debt suggested by the invite
+    #[structopt(long = "accept-max-debt")]
+    pub accept_max_debt: bool,
+}
+
 /// Set friend relays
 #[derive(Clone, Debug, StructOpt)]
 pub struct SetFriendRelaysCmd {
@@ -113,6 +129,22 @@ pub struct CloseFriendCmd {
     pub friend_name: String,
 }
 
+/// Stop advertising the capacities with a friend to index servers
+#[derive(Clone, Debug, StructOpt)]
+pub struct HideFriendCmd {
+    /// Friend name to hide
+    #[structopt(long = "name", short = "n")]
+    pub friend_name: String,
+}
+
+/// Advertise the capacities with a friend to index servers again
+#[derive(Clone, Debug, StructOpt)]
+pub struct UnhideFriendCmd {
+    /// Friend name to unhide
+    #[structopt(long = "name", short = "n")]
+    pub friend_name: String,
+}
+
 /// Set friend's maximum allowed debt
 /// If you lose this friend, you can lose this amount of credits.
 #[derive(Clone, Debug, StructOpt)]
@@ -150,6 +182,9 @@ pub enum ConfigCmd {
     /// Add a new friend
     #[structopt(name = "add-friend")]
     AddFriend(AddFriendCmd),
+    /// Add a new friend using a friend invite
+    #[structopt(name = "import-invite")]
+    ImportInvite(ImportInviteCmd),
     /// Update friend's relays
     #[structopt(name = "set-friend-relays")]
     SetFriendRelays(SetFriendRelaysCmd),
@@ -168,6 +203,12 @@ pub enum ConfigCmd {
     /// Close requests from friend
     #[structopt(name = "close-friend")]
     CloseFriend(CloseFriendCmd),
+    /// Hide friend from index servers
+    #[structopt(name = "hide-friend")]
+    HideFriend(HideFriendCmd),
+    /// Stop hiding friend from index servers
+    #[structopt(name = "unhide-friend")]
+    UnhideFriend(UnhideFriendCmd),
     /// Set friend's max debt
     #[structopt(name = "set-friend-max-debt")]
     SetFriendMaxDebt(SetFriendMaxDebtCmd),
@@ -190,9 +231,16 @@ pub enum ConfigError {
     IndexFileNotFound,
     LoadIndexFromFileError,
     FriendNameAlreadyExists,
+    /// A friend with the same public key already exists
+    FriendAlreadyExists,
     ParseBalanceError,
     FriendFileNotFound,
     LoadFriendFromFileError,
+    InviteFileNotFound,
+    LoadInviteFromFileError,
+    /// The invite was not signed by the inviting node
+    InvalidInviteSignature,
+    InviteExpired,
     FriendPublicKeyMismatch,
     FriendNameNotFound,
     ParseMaxDebtError,
@@ -325,10 +373,49 @@ async fn config_add_friend(
         friend_name.to_owned(),
         balance
     ))
-    .map_err(|_| ConfigError::AppConfigError)?;
+    .map_err(|e| match e {
+        AddFriendError::AlreadyExists(_) => ConfigError::FriendAlreadyExists,
+        AddFriendError::AppConfigError => ConfigError::AppConfigError,
+    })?;
     Ok(())
 }
 
+async fn config_import_invite(
+    import_invite_cmd: ImportInviteCmd,
+    mut app_config: AppConfig,
+    node_report: NodeReport,
+) -> Result<(), ConfigError> {
+    let ImportInviteCmd {
+        invite_file,
+        friend_name,
+        accept_max_debt,
+    } = import_invite_cmd;
+
+    for (_friend_public_key, friend_report) in node_report.funder_report.friends {
+        if friend_report.name == friend_name {
+            return Err(ConfigError::FriendNameAlreadyExists);
+        }
+    }
+
+    if !invite_file.exists() {
+        return Err(ConfigError::InviteFileNotFound);
+    }
+
+    let invite_str =
+        fs::read_to_string(&invite_file).map_err(|_| ConfigError::LoadInviteFromFileError)?;
+    let invite =
+        string_to_friend_invite(&invite_str).map_err(|_| ConfigError::LoadInviteFromFileError)?;
+
+    let result = await!(app_config.import_friend_invite(invite, friend_name, accept_max_debt))
+        .map_err(|_| ConfigError::AppConfigError)?;
+    match result {
+        ImportFriendInviteResult::Success => Ok(()),
+        ImportFriendInviteResult::InvalidSignature => Err(ConfigError::InvalidInviteSignature),
+        ImportFriendInviteResult::Expired => Err(ConfigError::InviteExpired),
+        ImportFriendInviteResult::FriendAlreadyExists => Err(ConfigError::FriendAlreadyExists),
+    }
+}
+
 async fn config_set_friend_relays(
     set_friend_relays_cmd: SetFriendRelaysCmd,
     mut app_config: AppConfig,
@@ -423,6 +510,32 @@ async fn config_close_friend(
     await!(app_config.close_friend(friend_public_key)).map_err(|_| ConfigError::AppConfigError)
 }
 
+async fn config_hide_friend(
+    hide_friend_cmd: HideFriendCmd,
+    mut app_config: AppConfig,
+    node_report: NodeReport,
+) -> Result<(), ConfigError> {
+    let friend_public_key = friend_public_key_by_name(&node_report, &hide_friend_cmd.friend_name)
+        .ok_or(ConfigError::FriendNameNotFound)?
+        .clone();
+
+    await!(app_config.set_friend_index_private(friend_public_key, true))
+        .map_err(|_| ConfigError::AppConfigError)
+}
+
+async fn config_unhide_friend(
+    unhide_friend_cmd: UnhideFriendCmd,
+    mut app_config: AppConfig,
+    node_report: NodeReport,
+) -> Result<(), ConfigError> {
+    let friend_public_key = friend_public_key_by_name(&node_report, &unhide_friend_cmd.friend_name)
+        .ok_or(ConfigError::FriendNameNotFound)?
+        .clone();
+
+    await!(app_config.set_friend_index_private(friend_public_key, false))
+        .map_err(|_| ConfigError::AppConfigError)
+}
+
 async fn config_set_friend_max_debt(
     set_friend_max_debt_cmd: SetFriendMaxDebtCmd,
     mut app_config: AppConfig,
@@ -511,6 +624,11 @@ pub async fn config(
         ConfigCmd::AddFriend(add_friend_cmd) => {
             await!(config_add_friend(add_friend_cmd, app_config, node_report))?
         }
+        ConfigCmd::ImportInvite(import_invite_cmd) => await!(config_import_invite(
+            import_invite_cmd,
+            app_config,
+            node_report
+        ))?,
         ConfigCmd::SetFriendRelays(set_friend_relays_cmd) => await!(config_set_friend_relays(
             set_friend_relays_cmd,
             app_config,
@@ -539,6 +657,14 @@ pub async fn config(
             app_config,
             node_report
         ))?,
+        ConfigCmd::HideFriend(hide_friend_cmd) => {
+            await!(config_hide_friend(hide_friend_cmd, app_config, node_report))?
+        }
+        ConfigCmd::UnhideFriend(unhide_friend_cmd) => await!(config_unhide_friend(
+            unhide_friend_cmd,
+            app_config,
+            node_report
+        ))?,
         ConfigCmd::SetFriendMaxDebt(set_friend_max_debt_cmd) => await!(
             config_set_friend_max_debt(set_friend_max_debt_cmd, app_config, node_report)
         )?,
@@ -8,7 +8,7 @@ use structopt::StructOpt;
 
 use app::gen::gen_uid;
 use app::invoice::{InvoiceId, INVOICE_ID_LEN};
-use app::route::{FriendsRoute, RouteWithCapacity};
+use app::route::RoutePolicy;
 
 use crate::file::invoice::load_invoice_from_file;
 use crate::file::receipt::store_receipt_to_file;
@@ -25,6 +25,9 @@ pub struct SendFundsCmd {
     /// Output receipt file
     #[structopt(parse(from_os_str), short = "r", long = "receipt")]
     pub opt_receipt_file: Option<PathBuf>,
+    /// Route selection policy (cheapest, shortest, capacity, random, reliable)
+    #[structopt(parse(try_from_str = "parse_route_policy"), short = "p", long = "policy")]
+    pub opt_route_policy: Option<RoutePolicy>,
 }
 
 /// Pay an invoice
@@ -36,6 +39,9 @@ pub struct PayInvoiceCmd {
     /// Output receipt file
     #[structopt(parse(from_os_str), short = "r", long = "receipt")]
     pub receipt_file: PathBuf,
+    /// Route selection policy (cheapest, shortest, capacity, random, reliable)
+    #[structopt(parse(try_from_str = "parse_route_policy"), short = "p", long = "policy")]
+    pub opt_route_policy: Option<RoutePolicy>,
 }
 
 /// Funds sending related commands
@@ -66,43 +72,20 @@ pub enum FundsError {
     WriteError,
 }
 
-/// Choose a route for pushing `amount` credits
-fn choose_route(
-    routes_with_capacity: Vec<RouteWithCapacity>,
-    amount: u128,
-) -> Result<FriendsRoute, FundsError> {
-    // We naively select the first route we find suitable:
-    // TODO: Possibly improve this later:
-    for route_with_capacity in routes_with_capacity {
-        // TODO: Is this dangerous? How can we do this safely?
-        let length = route_with_capacity.route.len() as u128;
-
-        // For route of length 2 we pay 0. (source and destination are included)
-        // For route of length 3 we pay 1.
-        // ...
-        let extra: u128 = if let Some(extra) = length.checked_sub(2) {
-            extra
-        } else {
-            // This is an invalid route
-            warn!(
-                "Received invalid route of length: {}. Skipping route",
-                route_with_capacity.route.len()
-            );
-            continue;
-        };
-
-        let total: u128 = if let Some(total) = extra.checked_add(amount) {
-            total
-        } else {
-            warn!("Overflow when calculating total payment. Skipping route");
-            continue;
-        };
-
-        if total <= route_with_capacity.capacity {
-            return Ok(route_with_capacity.route);
-        }
+/// Parse a route selection policy given on the command line
+fn parse_route_policy(policy_str: &str) -> Result<RoutePolicy, String> {
+    match policy_str {
+        "cheapest" => Ok(RoutePolicy::CheapestFee),
+        "shortest" => Ok(RoutePolicy::Shortest),
+        "capacity" => Ok(RoutePolicy::HighestCapacity),
+        "random" => Ok(RoutePolicy::RandomWeighted),
+        "reliable" => Ok(RoutePolicy::ReliabilityWeighted),
+        _ => Err(format!(
+            "Invalid route policy: {}. Expected one of: \
+             cheapest, shortest, capacity, random, reliable",
+            policy_str
+        )),
     }
-    Err(FundsError::NoSuitableRoute)
 }
 
 /// Send funds to a remote destination without using an invoice.
@@ -117,6 +100,7 @@ async fn funds_send_funds(
         destination_str,
         dest_payment,
         opt_receipt_file,
+        opt_route_policy,
     } = send_raw_cmd;
 
     // In case the user wants a receipt, make sure that we will be able to write the receipt
@@ -144,7 +128,9 @@ async fn funds_send_funds(
     )) // No exclusion of edges
     .map_err(|_| FundsError::AppRoutesError)?;
 
-    let route = choose_route(routes_with_capacity, dest_payment)?;
+    let route = app_routes
+        .select_route(routes_with_capacity, dest_payment, opt_route_policy)
+        .ok_or(FundsError::NoSuitableRoute)?;
     let fees = route.len().checked_sub(2).unwrap();
 
     // A trivial invoice:
@@ -180,6 +166,7 @@ async fn funds_pay_invoice(
     let PayInvoiceCmd {
         invoice_file,
         receipt_file,
+        opt_route_policy,
     } = pay_invoice_cmd;
 
     // Make sure that we will be able to write the receipt
@@ -204,7 +191,9 @@ async fn funds_pay_invoice(
     )) // No exclusion of edges
     .map_err(|_| FundsError::AppRoutesError)?;
 
-    let route = choose_route(routes_with_capacity, invoice.dest_payment)?;
+    let route = app_routes
+        .select_route(routes_with_capacity, invoice.dest_payment, opt_route_policy)
+        .ok_or(FundsError::NoSuitableRoute)?;
     let fees = route.len().checked_sub(2).unwrap();
 
     // Randomly generate a request id:
@@ -246,10 +235,12 @@ pub async fn funds(
         .ok_or(FundsError::NoFundsPermissions)?
         .clone();
 
-    let app_routes = node_connection
+    let mut app_routes = node_connection
         .routes()
         .ok_or(FundsError::NoRoutesPermissions)?
         .clone();
+    // Used by the reliability weighted route policy:
+    app_routes.set_reliability(node_report.funder_report.reliability.into_iter().collect());
 
     match funds_cmd {
         FundsCmd::SendFunds(send_raw_cmd) => await!(funds_send_funds(
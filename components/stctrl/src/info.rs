@@ -5,7 +5,8 @@ use prettytable::Table;
 use structopt::StructOpt;
 
 use app::report::{
-    ChannelStatusReport, FriendReport, FriendStatusReport, NodeReport, RequestsStatusReport,
+    ChannelStatusReport, FriendLivenessReport, FriendReport, FriendStatusReport, NodeReport,
+    RequestsStatusReport,
 };
 use app::ser_string::public_key_to_string;
 use app::{store_friend_to_file, AppReport, FriendAddress, NodeConnection, RelayAddress};
@@ -236,6 +237,15 @@ pub async fn info_friends(
 ) -> Result<(), InfoError> {
     let report = await!(get_report(&mut app_report))?;
 
+    if report.funder_report.read_only {
+        writeln!(
+            writer,
+            "Warning: The node can not write to its database (Is the disk full?). \
+             Payments are rejected until writing is possible again."
+        )
+        .map_err(|_| InfoError::WriteError)?;
+    }
+
     let mut table = Table::new();
     // Add titlek:
     table.set_titles(row!["st", "name", "balance"]);
@@ -248,15 +258,20 @@ pub async fn info_friends(
             "D"
         };
 
-        let liveness_str = if friend_report.liveness.is_online() {
-            "+"
-        } else {
-            "-"
+        // "~" means the friend went offline on purpose:
+        let liveness_str = match friend_report.liveness {
+            FriendLivenessReport::Online => "+",
+            FriendLivenessReport::IntentionallyOffline(_) => "~",
+            FriendLivenessReport::Offline => "-",
         };
 
         let mut status_string = String::new();
         status_string += status_str;
         status_string += liveness_str;
+        // "h" means the friend is hidden from index servers:
+        if friend_report.index_private {
+            status_string += "h";
+        }
 
         table.add_row(row![
             status_string,
@@ -270,6 +285,12 @@ pub async fn info_friends(
     } else {
         writeln!(writer, "No configured friends.").map_err(|_| InfoError::WriteError)?;
     }
+
+    // Friends with a corrupted stored state:
+    for friend_public_key in &report.funder_report.quarantined_friends {
+        let pk_string = public_key_to_string(friend_public_key);
+        writeln!(writer, "Quarantined friend: {}", pk_string).map_err(|_| InfoError::WriteError)?;
+    }
     Ok(())
 }
 
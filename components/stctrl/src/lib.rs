@@ -14,8 +14,6 @@
 #[macro_use]
 extern crate prettytable;
 #[macro_use]
-extern crate log;
-#[macro_use]
 extern crate serde_derive;
 
 pub mod config;
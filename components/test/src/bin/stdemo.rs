@@ -0,0 +1,31 @@
+#![feature(futures_api, async_await, await_macro, arbitrary_self_types)]
+#![feature(nll)]
+#![feature(generators)]
+#![feature(never_type)]
+#![deny(trivial_numeric_casts, warnings)]
+#![allow(intra_doc_link_resolution_failure)]
+#![allow(
+    clippy::too_many_arguments,
+    clippy::implicit_hasher,
+    clippy::module_inception,
+    clippy::new_without_default
+)]
+
+#[macro_use]
+extern crate log;
+
+use structopt::StructOpt;
+
+use offst_test::demo::{stdemo, DemoBinError, StDemoCmd};
+
+fn run() -> Result<(), DemoBinError> {
+    env_logger::init();
+    let st_demo_cmd = StDemoCmd::from_args();
+    stdemo(st_demo_cmd)
+}
+
+fn main() {
+    if let Err(e) = run() {
+        error!("run() error: {:?}", e);
+    }
+}
@@ -89,8 +89,11 @@ fn spawn_entities(stctrl_setup: &StCtrlSetup) {
     let st_node_cmd = StNodeCmd {
         idfile: stctrl_setup.temp_dir_path.join("node0").join("node0.ident"),
         laddr: stctrl_setup.node0_addr.clone().parse().unwrap(),
+        opt_direct_laddr: None,
         database: stctrl_setup.temp_dir_path.join("node0").join("node0.db"),
         trusted: stctrl_setup.temp_dir_path.join("node0").join("trusted"),
+        quarantine_corrupt: false,
+        relay_mux: false,
     };
     // TODO: How can we close this thread?
     thread::spawn(move || {
@@ -102,8 +105,11 @@ fn spawn_entities(stctrl_setup: &StCtrlSetup) {
     let st_node_cmd = StNodeCmd {
         idfile: stctrl_setup.temp_dir_path.join("node1").join("node1.ident"),
         laddr: stctrl_setup.node1_addr.clone().parse().unwrap(),
+        opt_direct_laddr: None,
         database: stctrl_setup.temp_dir_path.join("node1").join("node1.db"),
         trusted: stctrl_setup.temp_dir_path.join("node1").join("trusted"),
+        quarantine_corrupt: false,
+        relay_mux: false,
     };
     // TODO: How can we close this thread?
     thread::spawn(move || {
@@ -455,6 +461,7 @@ fn send_funds(stctrl_setup: &StCtrlSetup) {
                 .join("app1")
                 .join("receipt_50.receipt"),
         ),
+        opt_route_policy: None,
     };
     let funds_cmd = FundsCmd::SendFunds(send_funds_cmd);
     let subcommand = StCtrlSubcommand::Funds(funds_cmd);
@@ -532,6 +539,7 @@ fn pay_invoice(stctrl_setup: &StCtrlSetup) {
             .temp_dir_path
             .join("node1")
             .join("receipt_40.receipt"),
+        opt_route_policy: None,
     };
     let funds_cmd = FundsCmd::PayInvoice(pay_invoice_cmd);
     let subcommand = StCtrlSubcommand::Funds(funds_cmd);
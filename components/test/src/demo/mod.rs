@@ -0,0 +1,10 @@
+mod network;
+mod scenario;
+mod stdemo;
+
+pub use self::network::{create_demo_network, DemoError, DemoNetwork, ScenarioError};
+pub use self::scenario::{
+    parse_command, parse_scenario, DemoCommand, ParseCommandError, ParseScenarioError,
+    COMMANDS_HELP,
+};
+pub use self::stdemo::{stdemo, DemoBinError, StDemoCmd};
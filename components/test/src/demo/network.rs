@@ -0,0 +1,334 @@
+use std::collections::HashMap;
+
+use futures::task::Spawn;
+
+use common::conn::FutTransform;
+
+use crypto::crypto_rand::CryptoRandom;
+use crypto::invoice_id::InvoiceId;
+use crypto::test_utils::DummyRandom;
+use crypto::uid::Uid;
+
+use proto::app_server::messages::AppPermissions;
+
+use node::connect::{AppConfig, NodeConnection};
+use timer::TimerClient;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    create_app, create_exposed_node, create_index_server, create_node, create_relay,
+    named_index_server_address, named_relay_address, node_public_key, relay_address, ExternalApps,
+    SimDb,
+};
+
+use super::scenario::DemoCommand;
+
+#[derive(Debug)]
+pub enum DemoError {
+    /// The demo network has no node with this index
+    InvalidNode(u8),
+    /// The given node index is invalid, or the node is not exposed
+    InvalidExposedNode(u8),
+    CreateAppError(u8),
+    ConfigError(u8),
+    RequestRoutesError(u8),
+    /// (source, destination)
+    NoRoute((u8, u8)),
+    SendFundsError(u8),
+    ReportError(u8),
+    /// The channel of a node with a friend is inconsistent, or they are not friends.
+    /// (node, friend)
+    NoBalance((u8, u8)),
+    /// (node, friend, expected balance, balance)
+    UnexpectedBalance((u8, u8, i128, i128)),
+}
+
+/// A command of a scenario that failed.
+#[derive(Debug)]
+pub struct ScenarioError {
+    /// Index of the command in the scenario
+    pub index: usize,
+    pub error: DemoError,
+}
+
+/// A complete Offst network running inside one process: Nodes (each with a connected app), a
+/// relay and an index server, connected through a simulated network.
+///
+/// Identities and all randomness are derived from fixed seeds (See `crate::utils`), so running
+/// the same scenario always creates the same network.
+///
+/// The network does not pass time on its own. `waiter` lets time pass whenever a `Wait`
+/// command is executed.
+pub struct DemoNetwork<R, W> {
+    apps: Vec<NodeConnection<R>>,
+    waiter: W,
+    /// Used to create request ids and invoice ids for payments
+    rng: DummyRandom,
+}
+
+/// Create a demo network of `num_nodes` nodes, using fresh databases in `sim_db`.
+///
+/// Every node uses relay 0 and index server 0. If `opt_exposed` is given, the node with the
+/// given index also accepts applications from outside of the demo network.
+pub async fn create_demo_network<W, S>(
+    num_nodes: u8,
+    sim_db: SimDb,
+    timer_client: TimerClient,
+    mut opt_exposed: Option<(u8, ExternalApps)>,
+    waiter: W,
+    mut spawner: S,
+) -> Result<DemoNetwork<impl CryptoRandom + Clone, W>, DemoError>
+where
+    S: Spawn + Send + Sync + Clone + 'static,
+{
+    if let Some((exposed_index, _)) = &opt_exposed {
+        if *exposed_index >= num_nodes {
+            return Err(DemoError::InvalidExposedNode(*exposed_index));
+        }
+    }
+
+    let sim_net_client = create_sim_network(&mut spawner);
+
+    await!(create_relay(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        spawner.clone()
+    ));
+
+    await!(create_index_server(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        vec![],
+        spawner.clone()
+    ));
+
+    let mut apps = Vec::new();
+    for index in 0..num_nodes {
+        sim_db.init_db(index);
+
+        // Every node trusts the app with the same index:
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            index,
+            AppPermissions {
+                routes: true,
+                send_funds: true,
+                config: true,
+            },
+        );
+
+        let is_exposed = match &opt_exposed {
+            Some((exposed_index, _)) => *exposed_index == index,
+            None => false,
+        };
+        let node_handle = if is_exposed {
+            let (_, external_apps) = opt_exposed.take().unwrap();
+            await!(create_exposed_node(
+                index,
+                sim_db.clone(),
+                timer_client.clone(),
+                sim_net_client.clone(),
+                trusted_apps,
+                external_apps,
+                spawner.clone()
+            ))
+        } else {
+            await!(create_node(
+                index,
+                sim_db.clone(),
+                timer_client.clone(),
+                sim_net_client.clone(),
+                trusted_apps,
+                spawner.clone()
+            ))
+        };
+        node_handle.forget();
+
+        let mut app = await!(create_app(
+            index,
+            sim_net_client.clone(),
+            timer_client.clone(),
+            index,
+            spawner.clone()
+        ))
+        .ok_or(DemoError::CreateAppError(index))?;
+
+        let config = app.config().ok_or(DemoError::ConfigError(index))?;
+        await!(config.add_relay(named_relay_address(0)))
+            .map_err(|_| DemoError::ConfigError(index))?;
+        await!(config.add_index_server(named_index_server_address(0)))
+            .map_err(|_| DemoError::ConfigError(index))?;
+
+        apps.push(app);
+    }
+
+    Ok(DemoNetwork {
+        apps,
+        waiter,
+        rng: DummyRandom::new(&[0x13, 0x40]),
+    })
+}
+
+impl<R, W> DemoNetwork<R, W>
+where
+    R: CryptoRandom + Clone,
+    W: FutTransform<Input = usize, Output = ()>,
+{
+    pub fn num_nodes(&self) -> usize {
+        self.apps.len()
+    }
+
+    fn app(&mut self, index: u8) -> Result<&mut NodeConnection<R>, DemoError> {
+        self.apps
+            .get_mut(usize::from(index))
+            .ok_or(DemoError::InvalidNode(index))
+    }
+
+    fn check_node(&self, index: u8) -> Result<(), DemoError> {
+        if usize::from(index) < self.apps.len() {
+            Ok(())
+        } else {
+            Err(DemoError::InvalidNode(index))
+        }
+    }
+
+    fn config(&mut self, index: u8) -> Result<&mut AppConfig<R>, DemoError> {
+        self.app(index)?
+            .config()
+            .ok_or(DemoError::ConfigError(index))
+    }
+
+    async fn set_max_debt(
+        &mut self,
+        index: u8,
+        friend: u8,
+        max_debt: u128,
+    ) -> Result<(), DemoError> {
+        self.check_node(friend)?;
+        let config = self.config(index)?;
+        await!(config.set_friend_remote_max_debt(node_public_key(friend), max_debt))
+            .map_err(|_| DemoError::ConfigError(index))
+    }
+
+    async fn add_friends(&mut self, a: u8, b: u8, max_debt: u128) -> Result<(), DemoError> {
+        self.check_node(a)?;
+        self.check_node(b)?;
+        for &(index, friend) in &[(a, b), (b, a)] {
+            let config = self.config(index)?;
+            await!(config.add_friend(
+                node_public_key(friend),
+                vec![relay_address(0)],
+                format!("node{}", friend),
+                0
+            ))
+            .map_err(|_| DemoError::ConfigError(index))?;
+            await!(config.enable_friend(node_public_key(friend)))
+                .map_err(|_| DemoError::ConfigError(index))?;
+            await!(config.open_friend(node_public_key(friend)))
+                .map_err(|_| DemoError::ConfigError(index))?;
+            await!(self.set_max_debt(index, friend, max_debt))?;
+        }
+        Ok(())
+    }
+
+    async fn pay(
+        &mut self,
+        source: u8,
+        destination: u8,
+        dest_payment: u128,
+    ) -> Result<(), DemoError> {
+        self.check_node(destination)?;
+        let request_id = Uid::new(&self.rng);
+        let invoice_id = InvoiceId::new(&self.rng);
+        let app = self.app(source)?;
+
+        let routes = app.routes().ok_or(DemoError::RequestRoutesError(source))?;
+        let routes_with_capacity = await!(routes.request_routes(
+            dest_payment,
+            node_public_key(source),
+            node_public_key(destination),
+            None
+        ))
+        .map_err(|_| DemoError::RequestRoutesError(source))?;
+        let route = routes
+            .select_route(routes_with_capacity, dest_payment, None)
+            .ok_or(DemoError::NoRoute((source, destination)))?;
+
+        let send_funds = app.send_funds().ok_or(DemoError::SendFundsError(source))?;
+        let receipt =
+            await!(send_funds.request_send_funds(request_id, route, invoice_id, dest_payment))
+                .map_err(|_| DemoError::SendFundsError(source))?;
+        await!(send_funds.receipt_ack(request_id, receipt))
+            .map_err(|_| DemoError::SendFundsError(source))
+    }
+
+    /// Balance of a node against one of its friends.
+    pub async fn balance(&mut self, index: u8, friend: u8) -> Result<i128, DemoError> {
+        self.check_node(friend)?;
+        let mirror = await!(self.app(index)?.report().mirror())
+            .map_err(|_| DemoError::ReportError(index))?;
+        mirror
+            .balance(&node_public_key(friend))
+            .ok_or(DemoError::NoBalance((index, friend)))
+    }
+
+    /// Execute a command.
+    /// Returns a line of output for commands that show something.
+    pub async fn run_command(
+        &mut self,
+        command: &DemoCommand,
+    ) -> Result<Option<String>, DemoError> {
+        match *command {
+            DemoCommand::Friends((a, b, max_debt)) => await!(self.add_friends(a, b, max_debt))?,
+            DemoCommand::SetMaxDebt((index, friend, max_debt)) => {
+                await!(self.set_max_debt(index, friend, max_debt))?
+            }
+            DemoCommand::Pay((source, destination, dest_payment)) => {
+                await!(self.pay(source, destination, dest_payment))?;
+                return Ok(Some(format!(
+                    "node{} paid {} credits to node{}",
+                    source, dest_payment, destination
+                )));
+            }
+            DemoCommand::Wait(ticks) => await!(self.waiter.transform(ticks)),
+            DemoCommand::Balance((index, friend)) => {
+                let balance = await!(self.balance(index, friend))?;
+                return Ok(Some(format!(
+                    "node{} balance against node{}: {}",
+                    index, friend, balance
+                )));
+            }
+            DemoCommand::ExpectBalance((index, friend, expected)) => {
+                let balance = await!(self.balance(index, friend))?;
+                if balance != expected {
+                    return Err(DemoError::UnexpectedBalance((
+                        index, friend, expected, balance,
+                    )));
+                }
+            }
+        };
+        Ok(None)
+    }
+
+    /// Execute the commands of a scenario in order, handing every line of output to
+    /// `handle_output`. Stops at the first command that fails.
+    pub async fn run_scenario<'a, F>(
+        &'a mut self,
+        commands: &'a [DemoCommand],
+        mut handle_output: F,
+    ) -> Result<(), ScenarioError>
+    where
+        F: FnMut(String),
+    {
+        for (index, command) in commands.iter().enumerate() {
+            let opt_output = await!(self.run_command(command))
+                .map_err(|error| ScenarioError { index, error })?;
+            if let Some(output) = opt_output {
+                handle_output(output);
+            }
+        }
+        Ok(())
+    }
+}
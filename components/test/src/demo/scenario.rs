@@ -0,0 +1,196 @@
+use std::str::FromStr;
+
+/// A command for a demo network.
+/// Commands are read from scenario files, or typed into the console of the demo network.
+///
+/// Nodes are referred to by their index in the demo network.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DemoCommand {
+    /// Make two nodes friends. Each of the nodes lets the other node owe it up to the given
+    /// amount of credits.
+    /// (node, node, max_debt)
+    Friends((u8, u8, u128)),
+    /// Set the amount of credits a friend may owe a node.
+    /// (node, friend, max_debt)
+    SetMaxDebt((u8, u8, u128)),
+    /// Send credits along a route obtained from the index server.
+    /// (source, destination, dest_payment)
+    Pay((u8, u8, u128)),
+    /// Let the given amount of ticks pass.
+    Wait(usize),
+    /// Show the balance of a node against one of its friends.
+    /// (node, friend)
+    Balance((u8, u8)),
+    /// Fail if the balance of a node against one of its friends is not the given balance.
+    /// (node, friend, balance)
+    ExpectBalance((u8, u8, i128)),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseCommandError {
+    UnknownCommand(String),
+    /// (command, expected amount of arguments)
+    WrongArgsCount((String, usize)),
+    InvalidArgument(String),
+}
+
+/// A line of a scenario file that could not be parsed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseScenarioError {
+    /// Line number, starting from 1
+    pub line: usize,
+    pub error: ParseCommandError,
+}
+
+/// Usage of the commands, as shown by the console.
+pub const COMMANDS_HELP: &str = "\
+friends <node> <node> <max_debt>         Make two nodes friends
+limit <node> <friend> <max_debt>         Set the amount of credits a friend may owe a node
+pay <source> <destination> <amount>      Send credits to a node
+wait <ticks>                             Let time pass
+balance <node> <friend>                  Show the balance of a node against a friend
+expect-balance <node> <friend> <balance> Fail unless a node has the given balance";
+
+fn parse_arg<T: FromStr>(arg: &str) -> Result<T, ParseCommandError> {
+    arg.parse()
+        .map_err(|_| ParseCommandError::InvalidArgument(arg.to_owned()))
+}
+
+/// Parse one line of a scenario file (Or of the console).
+/// Returns None for an empty line or a comment (A line that begins with `#`).
+pub fn parse_command(line: &str) -> Result<Option<DemoCommand>, ParseCommandError> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    let (name, args) = (words[0], &words[1..]);
+    let expected_args = match name {
+        "friends" | "limit" | "pay" | "expect-balance" => 3,
+        "balance" => 2,
+        "wait" => 1,
+        _ => return Err(ParseCommandError::UnknownCommand(name.to_owned())),
+    };
+    if args.len() != expected_args {
+        return Err(ParseCommandError::WrongArgsCount((
+            name.to_owned(),
+            expected_args,
+        )));
+    }
+
+    let command = match name {
+        "friends" => DemoCommand::Friends((
+            parse_arg(args[0])?,
+            parse_arg(args[1])?,
+            parse_arg(args[2])?,
+        )),
+        "limit" => DemoCommand::SetMaxDebt((
+            parse_arg(args[0])?,
+            parse_arg(args[1])?,
+            parse_arg(args[2])?,
+        )),
+        "pay" => DemoCommand::Pay((
+            parse_arg(args[0])?,
+            parse_arg(args[1])?,
+            parse_arg(args[2])?,
+        )),
+        "expect-balance" => DemoCommand::ExpectBalance((
+            parse_arg(args[0])?,
+            parse_arg(args[1])?,
+            parse_arg(args[2])?,
+        )),
+        "balance" => DemoCommand::Balance((parse_arg(args[0])?, parse_arg(args[1])?)),
+        "wait" => DemoCommand::Wait(parse_arg(args[0])?),
+        _ => unreachable!(),
+    };
+    Ok(Some(command))
+}
+
+/// Parse a scenario file: A list of commands, one command per line.
+pub fn parse_scenario(text: &str) -> Result<Vec<DemoCommand>, ParseScenarioError> {
+    let mut commands = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let opt_command = parse_command(line).map_err(|error| ParseScenarioError {
+            line: index + 1,
+            error,
+        })?;
+        commands.extend(opt_command);
+    }
+    Ok(commands)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            parse_command("friends 0 1 100"),
+            Ok(Some(DemoCommand::Friends((0, 1, 100))))
+        );
+        assert_eq!(
+            parse_command("  limit 1  0 50 "),
+            Ok(Some(DemoCommand::SetMaxDebt((1, 0, 50))))
+        );
+        assert_eq!(
+            parse_command("pay 0 2 10"),
+            Ok(Some(DemoCommand::Pay((0, 2, 10))))
+        );
+        assert_eq!(parse_command("wait 40"), Ok(Some(DemoCommand::Wait(40))));
+        assert_eq!(
+            parse_command("balance 2 1"),
+            Ok(Some(DemoCommand::Balance((2, 1))))
+        );
+        assert_eq!(
+            parse_command("expect-balance 0 1 -21"),
+            Ok(Some(DemoCommand::ExpectBalance((0, 1, -21))))
+        );
+        assert_eq!(parse_command(""), Ok(None));
+        assert_eq!(parse_command("# A comment"), Ok(None));
+    }
+
+    #[test]
+    fn test_parse_command_invalid() {
+        assert_eq!(
+            parse_command("send 0 1 10"),
+            Err(ParseCommandError::UnknownCommand("send".to_owned()))
+        );
+        assert_eq!(
+            parse_command("pay 0 1"),
+            Err(ParseCommandError::WrongArgsCount(("pay".to_owned(), 3)))
+        );
+        // Nodes are indexed using u8, and a max debt can not be negative:
+        assert_eq!(
+            parse_command("friends 0 256 100"),
+            Err(ParseCommandError::InvalidArgument("256".to_owned()))
+        );
+        assert_eq!(
+            parse_command("limit 0 1 -5"),
+            Err(ParseCommandError::InvalidArgument("-5".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_parse_scenario() {
+        let text = "# Two friends\nfriends 0 1 100\n\nwait 40\npay 0 1 10\n";
+        assert_eq!(
+            parse_scenario(text),
+            Ok(vec![
+                DemoCommand::Friends((0, 1, 100)),
+                DemoCommand::Wait(40),
+                DemoCommand::Pay((0, 1, 10)),
+            ])
+        );
+
+        let text = "friends 0 1 100\nwait\n";
+        assert_eq!(
+            parse_scenario(text),
+            Err(ParseScenarioError {
+                line: 2,
+                error: ParseCommandError::WrongArgsCount(("wait".to_owned(), 1)),
+            })
+        );
+    }
+}
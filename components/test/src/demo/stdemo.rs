@@ -0,0 +1,235 @@
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, BufRead};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use futures::channel::mpsc;
+use futures::executor::{block_on, ThreadPool};
+use futures::{SinkExt, StreamExt};
+
+use structopt::StructOpt;
+
+use tempfile::tempdir;
+
+use common::conn::{BoxFuture, FutTransform, Listener};
+use common::int_convert::usize_to_u64;
+
+use crypto::crypto_rand::CryptoRandom;
+
+use proto::consts::{MAX_FRAME_LENGTH, TICK_MS};
+use proto::file::node::store_node_to_file;
+use proto::node::types::NodeAddress;
+
+use net::TcpListener;
+use timer::utils::sleep_ticks;
+use timer::{create_timer, TimerClient};
+
+use crate::utils::{node_public_key, ExternalApps, SimDb};
+
+use super::network::{create_demo_network, DemoError, DemoNetwork, ScenarioError};
+use super::scenario::{parse_command, parse_scenario, ParseScenarioError, COMMANDS_HELP};
+
+#[derive(Debug)]
+pub enum DemoBinError {
+    CreateThreadPoolError,
+    CreateTimerError,
+    CreateTempDirError,
+    LoadScenarioError,
+    ParseScenarioError(ParseScenarioError),
+    /// --laddr and --trusted are required for an exposed node
+    MissingExposeArgs,
+    InvalidListenAddress,
+    StoreNodeTicketError,
+    CreateDemoNetworkError(DemoError),
+    ScenarioError(ScenarioError),
+}
+
+/// stdemo: Offst demo network
+/// Runs a complete local network inside one process: Nodes, a relay and an index server.
+/// Commands can be given using a scenario file, and using the console.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "stdemo")]
+pub struct StDemoCmd {
+    /// Amount of nodes in the network
+    #[structopt(short = "n", long = "nodes", default_value = "3")]
+    pub num_nodes: u8,
+    /// Scenario file path: Commands that are executed on startup, one command per line
+    #[structopt(parse(from_os_str), short = "s", long = "scenario")]
+    pub opt_scenario: Option<PathBuf>,
+    /// Exit after executing the scenario, instead of starting the console
+    #[structopt(long = "no-console")]
+    pub no_console: bool,
+    /// Index of a node that accepts applications from outside of the demo network
+    #[structopt(long = "expose")]
+    pub opt_expose: Option<u8>,
+    /// Listening address for applications of the exposed node
+    #[structopt(short = "l", long = "laddr")]
+    pub opt_laddr: Option<SocketAddr>,
+    /// Directory path of trusted applications of the exposed node
+    #[structopt(parse(from_os_str), short = "t", long = "trusted")]
+    pub opt_trusted: Option<PathBuf>,
+    /// Output path for a node ticket of the exposed node (Used by applications to connect)
+    #[structopt(parse(from_os_str), long = "node-ticket")]
+    pub opt_node_ticket: Option<PathBuf>,
+}
+
+/// Lets time pass in real time, using the timer of the demo network.
+struct TimerWaiter {
+    timer_client: TimerClient,
+}
+
+impl FutTransform for TimerWaiter {
+    type Input = usize;
+    type Output = ();
+
+    fn transform(&mut self, ticks: Self::Input) -> BoxFuture<'_, Self::Output> {
+        let timer_client = self.timer_client.clone();
+        Box::pin(
+            async move {
+                let _ = await!(sleep_ticks(ticks, timer_client));
+            },
+        )
+    }
+}
+
+/// Read lines from stdin in a separate thread, as reading from stdin blocks.
+fn stdin_lines() -> mpsc::Receiver<String> {
+    let (mut sender, receiver) = mpsc::channel(0);
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => return,
+            };
+            if block_on(sender.send(line)).is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+async fn run_console<R>(mut demo_network: DemoNetwork<R, TimerWaiter>)
+where
+    R: CryptoRandom + Clone,
+{
+    println!(
+        "Demo network of {} nodes is ready. Type \"help\" for the list of commands.",
+        demo_network.num_nodes()
+    );
+    let mut lines = stdin_lines();
+    while let Some(line) = await!(lines.next()) {
+        match line.trim() {
+            "quit" | "exit" => return,
+            "help" => {
+                println!("{}", COMMANDS_HELP);
+                continue;
+            }
+            _ => {}
+        };
+        let command = match parse_command(&line) {
+            Ok(Some(command)) => command,
+            Ok(None) => continue,
+            Err(e) => {
+                println!("Invalid command: {:?}", e);
+                continue;
+            }
+        };
+        match await!(demo_network.run_command(&command)) {
+            Ok(Some(output)) => println!("{}", output),
+            Ok(None) => {}
+            Err(e) => println!("Command failed: {:?}", e),
+        }
+    }
+}
+
+pub fn stdemo(st_demo_cmd: StDemoCmd) -> Result<(), DemoBinError> {
+    let StDemoCmd {
+        num_nodes,
+        opt_scenario,
+        no_console,
+        opt_expose,
+        opt_laddr,
+        opt_trusted,
+        opt_node_ticket,
+    } = st_demo_cmd;
+
+    let commands = match opt_scenario {
+        Some(scenario) => {
+            let text =
+                fs::read_to_string(&scenario).map_err(|_| DemoBinError::LoadScenarioError)?;
+            parse_scenario(&text).map_err(DemoBinError::ParseScenarioError)?
+        }
+        None => Vec::new(),
+    };
+
+    let mut thread_pool = ThreadPool::new().map_err(|_| DemoBinError::CreateThreadPoolError)?;
+
+    // Get a timer client:
+    let dur = Duration::from_millis(usize_to_u64(TICK_MS).unwrap());
+    let timer_client =
+        create_timer(dur, thread_pool.clone()).map_err(|_| DemoBinError::CreateTimerError)?;
+
+    // The databases of the nodes are deleted when the demo network exits:
+    let temp_dir = tempdir().map_err(|_| DemoBinError::CreateTempDirError)?;
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    let opt_exposed = match opt_expose {
+        Some(index) => {
+            let (laddr, trusted_dir) = match (opt_laddr, opt_trusted) {
+                (Some(laddr), Some(trusted_dir)) => (laddr, trusted_dir),
+                _ => return Err(DemoBinError::MissingExposeArgs),
+            };
+            if let Some(node_ticket) = opt_node_ticket {
+                let node_address = NodeAddress {
+                    public_key: node_public_key(index),
+                    address: laddr
+                        .to_string()
+                        .try_into()
+                        .map_err(|_| DemoBinError::InvalidListenAddress)?,
+                };
+                store_node_to_file(&node_address, &node_ticket)
+                    .map_err(|_| DemoBinError::StoreNodeTicketError)?;
+            }
+
+            let tcp_listener = TcpListener::new(MAX_FRAME_LENGTH, thread_pool.clone());
+            let (_config_sender, incoming_raw_conns) = tcp_listener.listen(laddr);
+            let external_apps = ExternalApps {
+                incoming_raw_conns: Box::pin(incoming_raw_conns),
+                trusted_dir,
+            };
+            Some((index, external_apps))
+        }
+        None => None,
+    };
+
+    let waiter = TimerWaiter {
+        timer_client: timer_client.clone(),
+    };
+    let c_thread_pool = thread_pool.clone();
+    thread_pool.run(
+        async move {
+            let mut demo_network = await!(create_demo_network(
+                num_nodes,
+                sim_db,
+                timer_client,
+                opt_exposed,
+                waiter,
+                c_thread_pool
+            ))
+            .map_err(DemoBinError::CreateDemoNetworkError)?;
+
+            await!(demo_network.run_scenario(&commands, |output| println!("{}", output)))
+                .map_err(DemoBinError::ScenarioError)?;
+
+            if !no_console {
+                await!(run_console(demo_network));
+            }
+            Ok(())
+        },
+    )
+}
@@ -14,15 +14,15 @@
     clippy::new_without_default
 )]
 
-#[cfg(test)]
 #[macro_use]
 extern crate log;
 
-#[cfg(test)]
-mod sim_network;
+#[macro_use]
+extern crate common;
 
-#[cfg(test)]
-mod utils;
+pub mod demo;
+pub mod sim_network;
+pub mod utils;
 
 #[cfg(test)]
 mod tests;
@@ -1,9 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::convert::TryFrom;
 
 use futures::channel::{mpsc, oneshot};
+use futures::future::RemoteHandle;
 use futures::task::{Spawn, SpawnExt};
-use futures::{SinkExt, StreamExt};
+use futures::{future, stream, SinkExt, StreamExt};
 
 use common::conn::{BoxFuture, ConnPairVec, FutTransform};
 use proto::net::messages::NetAddress;
@@ -21,12 +22,156 @@ pub fn net_address(from: &str) -> NetAddress {
 pub enum SimNetworkRequest {
     Listen((NetAddress, oneshot::Sender<mpsc::Receiver<ConnPairVec>>)),
     Connect((NetAddress, oneshot::Sender<ConnPairVec>)),
+    /// Get the amount of open connections to an address
+    NumConns((NetAddress, oneshot::Sender<usize>)),
+    /// Close all the open connections to an address, as if the network failed.
+    /// Messages that were not yet delivered are lost.
+    CloseConns((NetAddress, oneshot::Sender<()>)),
+    /// Hold (Or release) the messages of all the connections to an address, as if the network
+    /// was slow. The connections stay open, and held messages are delivered once released.
+    HoldConns((NetAddress, bool, oneshot::Sender<()>)),
+    /// Tamper with (Or stop tampering with) the messages of all the connections to an address, as
+    /// if an attacker was on the path. Every message is corrupted before it is delivered.
+    TamperConns((NetAddress, bool, oneshot::Sender<()>)),
 }
 
-pub async fn sim_network_loop(mut incoming_requests: mpsc::Receiver<SimNetworkRequest>) {
+/// Identifies a connection: (listen address, connection id)
+type ConnId = (NetAddress, u64);
+
+enum SimNetworkEvent {
+    Request(SimNetworkRequest),
+    RequestsClosed,
+    ConnClosed(ConnId),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PumpControl {
+    Hold(bool),
+    Tamper(bool),
+}
+
+enum PumpEvent {
+    Message(Vec<u8>),
+    ReceiverClosed,
+    Control(PumpControl),
+}
+
+/// An open connection. Dropping the handles of the pumps closes the connection.
+struct SimConn {
+    pump_handles: Vec<RemoteHandle<()>>,
+    control_senders: Vec<mpsc::Sender<PumpControl>>,
+}
+
+/// Forward messages of one direction of a connection.
+/// While the connection is held, messages are kept until it is released.
+/// While the connection is tampered with, every forwarded message is corrupted.
+/// The connection is closed when the forwarding stops, at any direction.
+async fn pump_conn(
+    receiver: mpsc::Receiver<Vec<u8>>,
+    mut sender: mpsc::Sender<Vec<u8>>,
+    control_receiver: mpsc::Receiver<PumpControl>,
+    mut is_held: bool,
+    mut is_tampered: bool,
+    conn_id: ConnId,
+    mut conn_closed_sender: mpsc::Sender<ConnId>,
+) {
+    let receiver = receiver
+        .map(PumpEvent::Message)
+        .chain(stream::once(future::ready(PumpEvent::ReceiverClosed)));
+    let mut events = receiver.select(control_receiver.map(PumpEvent::Control));
+    let mut held_messages = VecDeque::new();
+
+    while let Some(event) = await!(events.next()) {
+        match event {
+            PumpEvent::Message(mut message) => {
+                if is_tampered {
+                    if let Some(last_byte) = message.last_mut() {
+                        *last_byte ^= 1;
+                    }
+                }
+                if is_held {
+                    held_messages.push_back(message);
+                } else if await!(sender.send(message)).is_err() {
+                    break;
+                }
+            }
+            PumpEvent::ReceiverClosed => break,
+            PumpEvent::Control(PumpControl::Tamper(new_is_tampered)) => {
+                is_tampered = new_is_tampered;
+            }
+            PumpEvent::Control(PumpControl::Hold(new_is_held)) => {
+                is_held = new_is_held;
+                if is_held {
+                    continue;
+                }
+                while let Some(message) = held_messages.pop_front() {
+                    if await!(sender.send(message)).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    let _ = await!(conn_closed_sender.send(conn_id));
+}
+
+/// Send a control message to the pumps of all the open connections to an address.
+fn send_pump_control(
+    open_conns: &mut HashMap<NetAddress, HashMap<u64, SimConn>>,
+    address: &NetAddress,
+    pump_control: PumpControl,
+) {
+    let sim_conns = open_conns
+        .get_mut(address)
+        .into_iter()
+        .flat_map(|conns| conns.values_mut());
+    for sim_conn in sim_conns {
+        for control_sender in &mut sim_conn.control_senders {
+            // A pump that has stopped no longer needs to be controlled:
+            let _ = control_sender.try_send(pump_control);
+        }
+    }
+}
+
+pub async fn sim_network_loop<S>(
+    incoming_requests: mpsc::Receiver<SimNetworkRequest>,
+    mut spawner: S,
+) where
+    S: Spawn,
+{
     let mut listeners: HashMap<NetAddress, mpsc::Sender<ConnPairVec>> = HashMap::new();
+    // Open connections, by listen address:
+    let mut open_conns: HashMap<NetAddress, HashMap<u64, SimConn>> = HashMap::new();
+    // Listen addresses whose connections are held:
+    let mut held_addresses: HashSet<NetAddress> = HashSet::new();
+    // Listen addresses whose connections are tampered with:
+    let mut tampered_addresses: HashSet<NetAddress> = HashSet::new();
+    let mut next_conn_id: u64 = 0;
 
-    while let Some(request) = await!(incoming_requests.next()) {
+    let (conn_closed_sender, conn_closed_receiver) = mpsc::channel(CHANNEL_SIZE);
+    let incoming_requests = incoming_requests
+        .map(SimNetworkEvent::Request)
+        .chain(stream::once(future::ready(SimNetworkEvent::RequestsClosed)));
+    let mut events =
+        incoming_requests.select(conn_closed_receiver.map(SimNetworkEvent::ConnClosed));
+
+    while let Some(event) = await!(events.next()) {
+        let request = match event {
+            SimNetworkEvent::Request(request) => request,
+            SimNetworkEvent::RequestsClosed => break,
+            SimNetworkEvent::ConnClosed((address, conn_id)) => {
+                // The pump of the other direction keeps forwarding the remaining messages:
+                let opt_sim_conn = open_conns
+                    .get_mut(&address)
+                    .and_then(|conns| conns.remove(&conn_id));
+                if let Some(sim_conn) = opt_sim_conn {
+                    for pump_handle in sim_conn.pump_handles {
+                        pump_handle.forget();
+                    }
+                }
+                continue;
+            }
+        };
         match request {
             SimNetworkRequest::Listen((listen_address, receiver_sender)) => {
                 info!("SimNetworkRequest::Listen({:?})", listen_address);
@@ -52,8 +197,10 @@ pub async fn sim_network_loop(mut incoming_requests: mpsc::Receiver<SimNetworkRe
             SimNetworkRequest::Connect((connect_address, oneshot_sender)) => {
                 info!("SimNetworkRequest::Connect({:?})", connect_address);
                 if let Some(mut conn_sender) = listeners.remove(&connect_address) {
-                    let (connect_sender, listen_receiver) = mpsc::channel(CHANNEL_SIZE);
-                    let (listen_sender, connect_receiver) = mpsc::channel(CHANNEL_SIZE);
+                    let (connect_sender, pump_receiver) = mpsc::channel(CHANNEL_SIZE);
+                    let (pump_sender, listen_receiver) = mpsc::channel(CHANNEL_SIZE);
+                    let (listen_sender, c_pump_receiver) = mpsc::channel(CHANNEL_SIZE);
+                    let (c_pump_sender, connect_receiver) = mpsc::channel(CHANNEL_SIZE);
 
                     if let Err(_) = await!(conn_sender.send((listen_sender, listen_receiver))) {
                         // Note that we dropped the listener's sender.
@@ -61,6 +208,43 @@ pub async fn sim_network_loop(mut incoming_requests: mpsc::Receiver<SimNetworkRe
                         continue;
                     }
 
+                    // Messages are forwarded through pumps, so that we know when the connection
+                    // is closed:
+                    let conn_id = (connect_address.clone(), next_conn_id);
+                    next_conn_id = next_conn_id.wrapping_add(1);
+                    let is_held = held_addresses.contains(&connect_address);
+                    let is_tampered = tampered_addresses.contains(&connect_address);
+                    let (control_sender, control_receiver) = mpsc::channel(CHANNEL_SIZE);
+                    let pump_fut = pump_conn(
+                        pump_receiver,
+                        pump_sender,
+                        control_receiver,
+                        is_held,
+                        is_tampered,
+                        conn_id.clone(),
+                        conn_closed_sender.clone(),
+                    );
+                    let pump_handle = spawner.spawn_with_handle(pump_fut).unwrap();
+                    let (c_control_sender, c_control_receiver) = mpsc::channel(CHANNEL_SIZE);
+                    let pump_fut = pump_conn(
+                        c_pump_receiver,
+                        c_pump_sender,
+                        c_control_receiver,
+                        is_held,
+                        is_tampered,
+                        conn_id.clone(),
+                        conn_closed_sender.clone(),
+                    );
+                    let c_pump_handle = spawner.spawn_with_handle(pump_fut).unwrap();
+                    let sim_conn = SimConn {
+                        pump_handles: vec![pump_handle, c_pump_handle],
+                        control_senders: vec![control_sender, c_control_sender],
+                    };
+                    open_conns
+                        .entry(connect_address.clone())
+                        .or_insert_with(HashMap::new)
+                        .insert(conn_id.1, sim_conn);
+
                     // Put the listener sender back in to the map:
                     listeners.insert(connect_address, conn_sender);
                     if let Err(_) = oneshot_sender.send((connect_sender, connect_receiver)) {
@@ -70,6 +254,39 @@ pub async fn sim_network_loop(mut incoming_requests: mpsc::Receiver<SimNetworkRe
                     warn!("Connection failed: No listeners at: {:?}", connect_address);
                 }
             }
+            SimNetworkRequest::NumConns((address, response_sender)) => {
+                let num_conns = open_conns.get(&address).map_or(0, HashMap::len);
+                let _ = response_sender.send(num_conns);
+            }
+            SimNetworkRequest::CloseConns((address, response_sender)) => {
+                info!("SimNetworkRequest::CloseConns({:?})", address);
+                // Dropping the handles stops the pumps:
+                open_conns.remove(&address);
+                let _ = response_sender.send(());
+            }
+            SimNetworkRequest::HoldConns((address, is_held, response_sender)) => {
+                info!("SimNetworkRequest::HoldConns({:?}, {:?})", address, is_held);
+                if is_held {
+                    held_addresses.insert(address.clone());
+                } else {
+                    held_addresses.remove(&address);
+                }
+                send_pump_control(&mut open_conns, &address, PumpControl::Hold(is_held));
+                let _ = response_sender.send(());
+            }
+            SimNetworkRequest::TamperConns((address, is_tampered, response_sender)) => {
+                info!(
+                    "SimNetworkRequest::TamperConns({:?}, {:?})",
+                    address, is_tampered
+                );
+                if is_tampered {
+                    tampered_addresses.insert(address.clone());
+                } else {
+                    tampered_addresses.remove(&address);
+                }
+                send_pump_control(&mut open_conns, &address, PumpControl::Tamper(is_tampered));
+                let _ = response_sender.send(());
+            }
         }
     }
     info!("sim_network_loop() closed");
@@ -102,6 +319,68 @@ impl SimNetworkClient {
         .map_err(|_| SimNetworkClientError::SendRequestError)?;
         await!(response_receiver).map_err(|_| SimNetworkClientError::ReceiveResponseError)
     }
+
+    /// Amount of open connections to `net_address`.
+    /// A connection is closed once any of its sides is closed.
+    pub async fn num_conns(
+        &mut self,
+        net_address: NetAddress,
+    ) -> Result<usize, SimNetworkClientError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        await!(self
+            .sender
+            .send(SimNetworkRequest::NumConns((net_address, response_sender))))
+        .map_err(|_| SimNetworkClientError::SendRequestError)?;
+        await!(response_receiver).map_err(|_| SimNetworkClientError::ReceiveResponseError)
+    }
+
+    /// Close all the open connections to `net_address`. New connections may still be opened.
+    pub async fn close_conns(
+        &mut self,
+        net_address: NetAddress,
+    ) -> Result<(), SimNetworkClientError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        await!(self.sender.send(SimNetworkRequest::CloseConns((
+            net_address,
+            response_sender
+        ))))
+        .map_err(|_| SimNetworkClientError::SendRequestError)?;
+        await!(response_receiver).map_err(|_| SimNetworkClientError::ReceiveResponseError)
+    }
+
+    /// Hold the messages of all the connections to `net_address`, including connections opened
+    /// later, until they are released. The connections stay open.
+    pub async fn hold_conns(
+        &mut self,
+        net_address: NetAddress,
+        is_held: bool,
+    ) -> Result<(), SimNetworkClientError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        await!(self.sender.send(SimNetworkRequest::HoldConns((
+            net_address,
+            is_held,
+            response_sender
+        ))))
+        .map_err(|_| SimNetworkClientError::SendRequestError)?;
+        await!(response_receiver).map_err(|_| SimNetworkClientError::ReceiveResponseError)
+    }
+
+    /// Corrupt every message of all the connections to `net_address`, including connections
+    /// opened later, until tampering is stopped. The connections stay open.
+    pub async fn tamper_conns(
+        &mut self,
+        net_address: NetAddress,
+        is_tampered: bool,
+    ) -> Result<(), SimNetworkClientError> {
+        let (response_sender, response_receiver) = oneshot::channel();
+        await!(self.sender.send(SimNetworkRequest::TamperConns((
+            net_address,
+            is_tampered,
+            response_sender
+        ))))
+        .map_err(|_| SimNetworkClientError::SendRequestError)?;
+        await!(response_receiver).map_err(|_| SimNetworkClientError::ReceiveResponseError)
+    }
 }
 
 impl FutTransform for SimNetworkClient {
@@ -129,10 +408,12 @@ impl FutTransform for SimNetworkClient {
 /// No two listeners can listen on the same address.
 pub fn create_sim_network<S>(spawner: &mut S) -> SimNetworkClient
 where
-    S: Spawn,
+    S: Spawn + Clone + Send + 'static,
 {
     let (request_sender, incoming_requests) = mpsc::channel(CHANNEL_SIZE);
-    spawner.spawn(sim_network_loop(incoming_requests)).unwrap();
+    spawner
+        .spawn(sim_network_loop(incoming_requests, spawner.clone()))
+        .unwrap();
 
     SimNetworkClient::new(request_sender)
 }
@@ -144,7 +425,7 @@ mod tests {
 
     async fn task_sim_network_basic<S>(mut spawner: S)
     where
-        S: Spawn,
+        S: Spawn + Clone + Send + 'static,
     {
         let mut net_client1 = create_sim_network(&mut spawner);
         let mut net_client2 = net_client1.clone();
@@ -180,6 +461,31 @@ mod tests {
         drop(incoming2);
 
         assert!(await!(net_client3.transform(net_address("net_client2"))).is_none());
+
+        // Closing the connections to an address closes both of their sides:
+        let (_sender3, mut receiver3) =
+            await!(net_client3.transform(net_address("net_client1"))).unwrap();
+        let (_sender1, mut receiver1) = await!(incoming1.next()).unwrap();
+        await!(net_client3.close_conns(net_address("net_client1"))).unwrap();
+        assert_eq!(await!(receiver1.next()), None);
+        assert_eq!(await!(receiver3.next()), None);
+        assert_eq!(
+            await!(net_client3.num_conns(net_address("net_client1"))).unwrap(),
+            0
+        );
+
+        // Held messages are delivered once the connections are released:
+        let (_sender3, mut receiver3) =
+            await!(net_client3.transform(net_address("net_client1"))).unwrap();
+        let (mut sender1, _receiver1) = await!(incoming1.next()).unwrap();
+        await!(net_client3.hold_conns(net_address("net_client1"), true)).unwrap();
+        await!(sender1.send(vec![4, 5, 6])).unwrap();
+        await!(net_client3.hold_conns(net_address("net_client1"), false)).unwrap();
+        assert_eq!(await!(receiver3.next()), Some(vec![4, 5, 6]));
+        assert_eq!(
+            await!(net_client3.num_conns(net_address("net_client1"))).unwrap(),
+            1
+        );
     }
 
     #[test]
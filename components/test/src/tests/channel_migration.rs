@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use futures::channel::mpsc;
+use futures::task::SpawnExt;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use crypto::identity::compare_public_key;
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::FriendsRoute;
+use proto::net::messages::NetAddress;
+use timer::create_timer_incoming;
+
+use crate::sim_network::{create_sim_network, SimNetworkClient};
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
+    relay_address, relay_public_key, SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+/// The amount the destination receives
+const DEST_PAYMENT: u128 = 10;
+
+fn all_permissions(index: u8) -> HashMap<u8, AppPermissions> {
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        index,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    trusted_apps
+}
+
+/// Wait until the amount of open connections to `address` is `num_conns`.
+async fn wait_conns<'a>(
+    sim_net_client: &'a mut SimNetworkClient,
+    address: NetAddress,
+    num_conns: usize,
+    tick_sender: &'a mut mpsc::Sender<()>,
+    test_executor: &'a TestExecutor,
+) {
+    for _ in 0..WAIT_TICKS {
+        if await!(sim_net_client.num_conns(address.clone())).unwrap() == num_conns {
+            return;
+        }
+        await!(advance_time(1, tick_sender, test_executor));
+    }
+    let cur_num_conns = await!(sim_net_client.num_conns(address)).unwrap();
+    assert_eq!(cur_num_conns, num_conns);
+}
+
+async fn task_channel_migration_during_payment(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let mut sim_net_client = create_sim_network(&mut test_executor);
+
+    // The friend with the bigger public key is the one that initiates the connection.
+    // source connects to the relay of mid, and mid connects to the relay of dest:
+    let mut indices = vec![0u8, 1, 2];
+    indices.sort_by(|a, b| compare_public_key(&node_public_key(*a), &node_public_key(*b)));
+    let (dest, mid, source) = (indices[0], indices[1], indices[2]);
+
+    let mut apps = HashMap::new();
+    for &index in &indices {
+        sim_db.init_db(index);
+        await!(create_node(
+            index,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            all_permissions(index),
+            test_executor.clone()
+        ))
+        .forget();
+
+        let app = await!(create_app(
+            index,
+            sim_net_client.clone(),
+            timer_client.clone(),
+            index,
+            test_executor.clone()
+        ))
+        .unwrap();
+        apps.insert(index, app);
+
+        await!(create_relay(
+            index,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+    }
+
+    // The relay mid moves to:
+    await!(create_relay(
+        3,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    for &index in &indices {
+        let mut config = apps[&index].config().unwrap().clone();
+        await!(config.add_relay(named_relay_address(index))).unwrap();
+    }
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // source --> mid --> dest. Every node may send 100 credits to the next node on the route:
+    for &(payer, payee) in &[(source, mid), (mid, dest)] {
+        let mut payer_config = apps[&payer].config().unwrap().clone();
+        let mut payee_config = apps[&payee].config().unwrap().clone();
+        await!(payer_config.add_friend(
+            node_public_key(payee),
+            vec![relay_address(payee)],
+            format!("node{}", payee),
+            100
+        ))
+        .unwrap();
+        await!(payee_config.add_friend(
+            node_public_key(payer),
+            vec![relay_address(payer)],
+            format!("node{}", payer),
+            -100
+        ))
+        .unwrap();
+        await!(payer_config.enable_friend(node_public_key(payee))).unwrap();
+        await!(payee_config.enable_friend(node_public_key(payer))).unwrap();
+    }
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    for &(index, friend_index) in &[(source, mid), (mid, source), (mid, dest), (dest, mid)] {
+        let mut report = apps[&index].report().clone();
+        await!(report.wait_for(
+            |mirror| mirror.is_friend_online(&node_public_key(friend_index)),
+            WAIT_TICKS
+        ))
+        .unwrap();
+        let mut config = apps[&index].config().unwrap().clone();
+        await!(config.open_friend(node_public_key(friend_index))).unwrap();
+    }
+
+    await!(advance_time(10, &mut tick_sender, &test_executor));
+
+    // The last hop of the route is slow, so that the payment stays in flight:
+    await!(sim_net_client.hold_conns(relay_address(dest).address, true)).unwrap();
+
+    let route = FriendsRoute {
+        public_keys: vec![
+            node_public_key(source),
+            node_public_key(mid),
+            node_public_key(dest),
+        ],
+    };
+    let request_id = Uid::from(&[1; UID_LEN]);
+    let mut send_funds = apps[&source].send_funds().unwrap().clone();
+    let mut c_send_funds = send_funds.clone();
+    let fut_receipt = test_executor
+        .spawn_with_handle(
+            async move {
+                await!(c_send_funds.request_send_funds(
+                    request_id,
+                    route,
+                    InvoiceId::from(&[1; INVOICE_ID_LEN]),
+                    DEST_PAYMENT
+                ))
+            },
+        )
+        .unwrap();
+
+    let mut source_report = apps[&source].report().clone();
+    await!(source_report.wait_for(
+        |mirror| !mirror.is_payment_completed(&request_id),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // Watch mid from the point of view of source, until the payment completes:
+    let mut c_source_report = source_report.clone();
+    let mid_public_key = node_public_key(mid);
+    let fut_went_offline = test_executor
+        .spawn_with_handle(
+            async move {
+                let went_offline = AtomicBool::new(false);
+                await!(c_source_report.wait_for(
+                    |mirror| {
+                        if !mirror.is_friend_online(&mid_public_key) {
+                            went_offline.store(true, Ordering::SeqCst);
+                        }
+                        mirror.is_payment_completed(&request_id)
+                    },
+                    4 * WAIT_TICKS
+                ))
+                .unwrap();
+                went_offline.load(Ordering::SeqCst)
+            },
+        )
+        .unwrap();
+
+    // mid moves to a new relay, while the payment is in flight:
+    let mut mid_config = apps[&mid].config().unwrap().clone();
+    await!(mid_config.add_relay(named_relay_address(3))).unwrap();
+    await!(mid_config.remove_relay(relay_public_key(mid))).unwrap();
+
+    // The channel between source and mid migrates to the new relay. Nothing is left on the old
+    // relay:
+    await!(wait_conns(
+        &mut sim_net_client,
+        relay_address(mid).address,
+        0,
+        &mut tick_sender,
+        &test_executor
+    ));
+
+    // The last hop recovers, and the payment completes:
+    await!(sim_net_client.hold_conns(relay_address(dest).address, false)).unwrap();
+    let receipt = await!(fut_receipt).unwrap();
+    await!(send_funds.receipt_ack(request_id, receipt)).unwrap();
+
+    // mid was never seen offline by source:
+    assert!(!await!(fut_went_offline));
+}
+
+#[test]
+fn test_channel_migration_during_payment() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_channel_migration_during_payment(test_executor.clone()));
+    assert!(res.is_output());
+}
+
+async fn task_channel_migration_fallback(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let mut sim_net_client = create_sim_network(&mut test_executor);
+
+    // The friend with the bigger public key is the one that initiates the connection.
+    // connector connects to the relay of listener:
+    let mut indices = vec![0u8, 1];
+    indices.sort_by(|a, b| compare_public_key(&node_public_key(*a), &node_public_key(*b)));
+    let (listener, connector) = (indices[0], indices[1]);
+
+    let mut apps = HashMap::new();
+    for &index in &indices {
+        sim_db.init_db(index);
+        await!(create_node(
+            index,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            all_permissions(index),
+            test_executor.clone()
+        ))
+        .forget();
+
+        let app = await!(create_app(
+            index,
+            sim_net_client.clone(),
+            timer_client.clone(),
+            index,
+            test_executor.clone()
+        ))
+        .unwrap();
+        apps.insert(index, app);
+
+        await!(create_relay(
+            index,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+    }
+
+    // The relay listener moves to:
+    await!(create_relay(
+        2,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    for &index in &indices {
+        let mut config = apps[&index].config().unwrap().clone();
+        await!(config.add_relay(named_relay_address(index))).unwrap();
+    }
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    let mut connector_config = apps[&connector].config().unwrap().clone();
+    let mut listener_config = apps[&listener].config().unwrap().clone();
+    await!(connector_config.add_friend(
+        node_public_key(listener),
+        vec![relay_address(listener)],
+        format!("node{}", listener),
+        100
+    ))
+    .unwrap();
+    await!(listener_config.add_friend(
+        node_public_key(connector),
+        vec![relay_address(connector)],
+        format!("node{}", connector),
+        -100
+    ))
+    .unwrap();
+    await!(connector_config.enable_friend(node_public_key(listener))).unwrap();
+    await!(listener_config.enable_friend(node_public_key(connector))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    let mut connector_report = apps[&connector].report().clone();
+    let mut listener_report = apps[&listener].report().clone();
+    await!(connector_report.wait_for(
+        |mirror| mirror.is_friend_online(&node_public_key(listener)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+    await!(listener_report.wait_for(
+        |mirror| mirror.is_friend_online(&node_public_key(connector)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // Everything that goes through the new relay is tampered with, so resuming the channel
+    // through it fails:
+    await!(sim_net_client.tamper_conns(relay_address(2).address, true)).unwrap();
+
+    // listener moves to the new relay:
+    await!(listener_config.add_relay(named_relay_address(2))).unwrap();
+    await!(listener_config.remove_relay(relay_public_key(listener))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // The channel through the old relay is still alive, but the migration failed. connector
+    // falls back to a full reconnect, closing the channel:
+    await!(connector_report.wait_for(
+        |mirror| !mirror.is_friend_online(&node_public_key(listener)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // Once the new relay is reachable again, the friends reconnect through it:
+    await!(sim_net_client.tamper_conns(relay_address(2).address, false)).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    await!(connector_report.wait_for(
+        |mirror| mirror.is_friend_online(&node_public_key(listener)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+    await!(listener_report.wait_for(
+        |mirror| mirror.is_friend_online(&node_public_key(connector)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+}
+
+#[test]
+fn test_channel_migration_fallback() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_channel_migration_fallback(test_executor.clone()));
+    assert!(res.is_output());
+}
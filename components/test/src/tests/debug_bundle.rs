@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::debug_bundle::{deserialize_debug_bundle, verify_debug_bundle, DebugBundle};
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::FriendsRoute;
+use proto::report::messages::{ChannelStatusReport, FriendReport};
+use timer::create_timer_incoming;
+
+use crypto::identity::Signature;
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
+    relay_address, SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+fn bundle_balance(friend_report: &FriendReport) -> i128 {
+    match &friend_report.channel_status {
+        ChannelStatusReport::Consistent(tc_report) => tc_report.balance.balance,
+        ChannelStatusReport::Inconsistent(_) => unreachable!(),
+    }
+}
+
+async fn task_debug_bundle(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    await!(create_relay(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    let mut apps = Vec::new();
+    for i in 0..2 {
+        sim_db.init_db(i);
+
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            i,
+            AppPermissions {
+                routes: true,
+                send_funds: true,
+                config: true,
+            },
+        );
+
+        await!(create_node(
+            i,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            trusted_apps,
+            test_executor.clone()
+        ))
+        .forget();
+
+        let mut app = await!(create_app(
+            i,
+            sim_net_client.clone(),
+            timer_client.clone(),
+            i,
+            test_executor.clone()
+        ))
+        .unwrap();
+        await!(app.config().unwrap().add_relay(named_relay_address(0))).unwrap();
+        apps.push(app);
+    }
+
+    for &(i, j) in &[(0, 1), (1, 0)] {
+        let app = &mut apps[i as usize];
+        await!(app.config().unwrap().add_friend(
+            node_public_key(j),
+            vec![relay_address(0)],
+            format!("node{}", j),
+            0
+        ))
+        .unwrap();
+        await!(app.config().unwrap().enable_friend(node_public_key(j))).unwrap();
+        await!(app.config().unwrap().open_friend(node_public_key(j))).unwrap();
+        await!(app
+            .config()
+            .unwrap()
+            .set_friend_remote_max_debt(node_public_key(j), 100))
+        .unwrap();
+    }
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node1 pays node0:
+    let request_id = Uid::from(&[0; UID_LEN]);
+    let route = FriendsRoute {
+        public_keys: vec![node_public_key(1), node_public_key(0)],
+    };
+    let receipt = await!(apps[1].send_funds().unwrap().request_send_funds(
+        request_id,
+        route,
+        InvoiceId::from(&[0; INVOICE_ID_LEN]),
+        10
+    ))
+    .unwrap();
+    await!(apps[1].send_funds().unwrap().receipt_ack(request_id, receipt)).unwrap();
+
+    let mirror = await!(apps[0].report().wait_for(
+        |mirror| mirror.balance(&node_public_key(1)) == Some(10),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // The bundle agrees with the report:
+    let data = await!(apps[0].config().unwrap().request_debug_bundle(false)).unwrap();
+    assert_eq!(verify_debug_bundle(&data).unwrap(), node_public_key(0));
+    let debug_bundle: DebugBundle = deserialize_debug_bundle(&data).unwrap();
+    assert!(debug_bundle.info.redacted);
+    let funder_report = &debug_bundle.node_report.funder_report;
+    assert_eq!(&funder_report.local_public_key, mirror.local_public_key());
+    let friend_report = funder_report.friends.get(&node_public_key(1)).unwrap();
+    assert_eq!(Some(bundle_balance(friend_report)), mirror.balance(&node_public_key(1)));
+
+    // Tokens were redacted:
+    let move_token_hashed = friend_report.opt_last_incoming_move_token.as_ref().unwrap();
+    assert_eq!(move_token_hashed.new_token, Signature::zero());
+
+    // A full bundle contains the tokens:
+    let data = await!(apps[0].config().unwrap().request_debug_bundle(true)).unwrap();
+    assert_eq!(verify_debug_bundle(&data).unwrap(), node_public_key(0));
+    let debug_bundle: DebugBundle = deserialize_debug_bundle(&data).unwrap();
+    assert!(!debug_bundle.info.redacted);
+    let friend_report = debug_bundle
+        .node_report
+        .funder_report
+        .friends
+        .get(&node_public_key(1))
+        .unwrap();
+    assert_eq!(bundle_balance(friend_report), 10);
+    let move_token_hashed = friend_report.opt_last_incoming_move_token.as_ref().unwrap();
+    assert_ne!(move_token_hashed.new_token, Signature::zero());
+}
+
+#[test]
+fn test_debug_bundle() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_debug_bundle(test_executor.clone()));
+    assert!(res.is_output());
+}
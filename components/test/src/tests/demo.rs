@@ -0,0 +1,102 @@
+use std::fs;
+use std::path::Path;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::conn::{BoxFuture, FutTransform};
+use common::test_executor::TestExecutor;
+
+use timer::create_timer_incoming;
+
+use crate::demo::{create_demo_network, parse_scenario};
+use crate::utils::{advance_time, SimDb};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+/// Lets time pass in the simulation.
+struct TestWaiter {
+    tick_sender: mpsc::Sender<()>,
+    test_executor: TestExecutor,
+}
+
+impl FutTransform for TestWaiter {
+    type Input = usize;
+    type Output = ();
+
+    fn transform(&mut self, ticks: Self::Input) -> BoxFuture<'_, Self::Output> {
+        Box::pin(
+            async move {
+                await!(advance_time(
+                    ticks,
+                    &mut self.tick_sender,
+                    &self.test_executor
+                ))
+            },
+        )
+    }
+}
+
+async fn task_demo_scenario(test_executor: TestExecutor) {
+    let scenario_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/three_nodes.txt");
+    let commands = parse_scenario(&fs::read_to_string(scenario_path).unwrap()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // Create timer_client:
+    let (tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    let waiter = TestWaiter {
+        tick_sender,
+        test_executor: test_executor.clone(),
+    };
+    let mut demo_network = await!(create_demo_network(
+        3,
+        sim_db,
+        timer_client,
+        None,
+        waiter,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    let mut outputs = Vec::new();
+    await!(demo_network.run_scenario(&commands, |output| outputs.push(output))).unwrap();
+    assert_eq!(
+        outputs,
+        vec![
+            "node0 paid 10 credits to node1".to_owned(),
+            "node0 paid 10 credits to node2".to_owned(),
+        ]
+    );
+
+    // The scenario checks the final balances too:
+    assert_eq!(await!(demo_network.balance(0, 1)).unwrap(), -21);
+    assert_eq!(await!(demo_network.balance(1, 0)).unwrap(), 21);
+    assert_eq!(await!(demo_network.balance(1, 2)).unwrap(), -10);
+    assert_eq!(await!(demo_network.balance(2, 1)).unwrap(), 10);
+
+    // Node0 and node2 are not friends:
+    assert!(await!(demo_network.balance(0, 2)).is_err());
+    // There is no node3:
+    assert!(await!(demo_network.balance(0, 3)).is_err());
+
+    // A max debt of 100 can not cover a payment of 100 through node1:
+    let mut outputs = Vec::new();
+    let commands = parse_scenario("pay 0 2 100\n").unwrap();
+    let res = await!(demo_network.run_scenario(&commands, |output| outputs.push(output)));
+    assert_eq!(res.unwrap_err().index, 0);
+    assert!(outputs.is_empty());
+}
+
+#[test]
+fn test_demo_scenario() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_demo_scenario(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::FriendsRoute;
+use timer::create_timer_incoming;
+
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_direct_node, create_node, create_relay, direct_address,
+    named_direct_address, named_relay_address, node_public_key, relay_address, SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+fn all_permissions(index: u8) -> HashMap<u8, AppPermissions> {
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        index,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    trusted_apps
+}
+
+async fn task_direct_connections(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    // Node0 and node1 listen for direct connections, and do not use any relay.
+    // Node2 uses a relay, and can not be reached directly.
+    for index in 0..3 {
+        sim_db.init_db(index);
+    }
+
+    await!(create_direct_node(
+        0,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        all_permissions(0),
+        test_executor.clone()
+    ))
+    .forget();
+
+    await!(create_direct_node(
+        1,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        all_permissions(1),
+        test_executor.clone()
+    ))
+    .forget();
+
+    await!(create_node(
+        2,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        all_permissions(2),
+        test_executor.clone()
+    ))
+    .forget();
+
+    let mut app0 = await!(create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    let mut app1 = await!(create_app(
+        1,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        1,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    let mut app2 = await!(create_app(
+        2,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        2,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    // Create a relay, used only by node2:
+    await!(create_relay(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    let mut config0 = app0.config().unwrap().clone();
+    let mut config1 = app1.config().unwrap().clone();
+    let mut config2 = app2.config().unwrap().clone();
+
+    let mut send_funds0 = app0.send_funds().unwrap().clone();
+    let mut send_funds1 = app1.send_funds().unwrap().clone();
+
+    let mut report0 = app0.report().clone();
+    let mut report1 = app1.report().clone();
+    let mut report2 = app2.report().clone();
+
+    // Node0 and node1 advertise their direct addresses instead of relays:
+    await!(config0.add_relay(named_direct_address(0))).unwrap();
+    await!(config1.add_relay(named_direct_address(1))).unwrap();
+    await!(config2.add_relay(named_relay_address(0))).unwrap();
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node0 <--> Node1, using direct connections only:
+    await!(config0.add_friend(
+        node_public_key(1),
+        vec![direct_address(1)],
+        String::from("node1"),
+        100
+    ))
+    .unwrap();
+
+    await!(config1.add_friend(
+        node_public_key(0),
+        vec![direct_address(0)],
+        String::from("node0"),
+        -100
+    ))
+    .unwrap();
+
+    // Node1 <--> Node2: Node2 is reachable through the relay,
+    // node1 is reachable directly:
+    await!(config1.add_friend(
+        node_public_key(2),
+        vec![relay_address(0)],
+        String::from("node2"),
+        50
+    ))
+    .unwrap();
+
+    await!(config2.add_friend(
+        node_public_key(1),
+        vec![direct_address(1)],
+        String::from("node1"),
+        -50
+    ))
+    .unwrap();
+
+    await!(config0.enable_friend(node_public_key(1))).unwrap();
+    await!(config1.enable_friend(node_public_key(0))).unwrap();
+    await!(config1.enable_friend(node_public_key(2))).unwrap();
+    await!(config2.enable_friend(node_public_key(1))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    await!(report0.wait_for(|mirror| mirror.is_friend_online(&node_public_key(1)), WAIT_TICKS))
+        .unwrap();
+    await!(report1.wait_for(|mirror| mirror.is_friend_online(&node_public_key(0)), WAIT_TICKS))
+        .unwrap();
+    await!(report1.wait_for(|mirror| mirror.is_friend_online(&node_public_key(2)), WAIT_TICKS))
+        .unwrap();
+    await!(report2.wait_for(|mirror| mirror.is_friend_online(&node_public_key(1)), WAIT_TICKS))
+        .unwrap();
+
+    await!(config0.open_friend(node_public_key(1))).unwrap();
+    await!(config1.open_friend(node_public_key(0))).unwrap();
+    await!(config1.open_friend(node_public_key(2))).unwrap();
+    await!(config2.open_friend(node_public_key(1))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node0: Send 10 credits to node1, over a direct connection:
+    let route = FriendsRoute {
+        public_keys: vec![node_public_key(0), node_public_key(1)],
+    };
+    let request_id = Uid::from(&[0x0; UID_LEN]);
+    let invoice_id = InvoiceId::from(&[0; INVOICE_ID_LEN]);
+    let receipt = await!(send_funds0.request_send_funds(
+        request_id.clone(),
+        route,
+        invoice_id,
+        10
+    ))
+    .unwrap();
+    await!(send_funds0.receipt_ack(request_id, receipt)).unwrap();
+
+    // Node1: Send 20 credits to node2, which uses a relay:
+    let route = FriendsRoute {
+        public_keys: vec![node_public_key(1), node_public_key(2)],
+    };
+    let request_id = Uid::from(&[0x1; UID_LEN]);
+    let invoice_id = InvoiceId::from(&[1; INVOICE_ID_LEN]);
+    let receipt = await!(send_funds1.request_send_funds(
+        request_id.clone(),
+        route,
+        invoice_id,
+        20
+    ))
+    .unwrap();
+    await!(send_funds1.receipt_ack(request_id, receipt)).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    let mirror0 = await!(report0.mirror()).unwrap();
+    assert_eq!(mirror0.send_capacity(&node_public_key(1)), 90);
+    let mirror1 = await!(report1.mirror()).unwrap();
+    assert_eq!(mirror1.send_capacity(&node_public_key(2)), 30);
+}
+
+#[test]
+fn test_direct_connections() {
+    // let _ = env_logger::init();
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_direct_connections(test_executor.clone()));
+    assert!(res.is_output());
+}
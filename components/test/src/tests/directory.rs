@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::AppPermissions;
+use timer::create_timer_incoming;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_directory, create_node, create_relay, directory_address,
+    directory_public_key, directory_signing_public_key, index_server_public_key,
+    named_index_server_address, named_relay_address, relay_public_key, signed_directory_document,
+    SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+async fn task_directory(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    // Create a node:
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        0,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    sim_db.init_db(0);
+    await!(create_node(
+        0,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        trusted_apps,
+        test_executor.clone()
+    ))
+    .forget();
+
+    let app = await!(create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    for index in 0..4 {
+        await!(create_relay(
+            index,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+    }
+
+    let mut config = app.config().unwrap().clone();
+    let mut report = app.report().clone();
+
+    // A manually configured relay:
+    await!(config.add_relay(named_relay_address(0))).unwrap();
+
+    // Subscribe to a directory that can not be reached yet:
+    await!(config.set_directory(
+        directory_public_key(0),
+        directory_address(0),
+        directory_signing_public_key(0)
+    ))
+    .unwrap();
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Nothing has changed:
+    let mirror = await!(report.mirror()).unwrap();
+    assert!(mirror.has_relay(&relay_public_key(0)));
+    assert_eq!(mirror.node_report().funder_report.relays.len(), 1);
+    assert!(mirror.node_report().index_client_report.index_servers.is_empty());
+
+    // The directory comes up:
+    let sim_directory = await!(create_directory(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+    let relays = vec![
+        named_relay_address(1),
+        named_relay_address(2),
+        named_relay_address(3),
+    ];
+    let document = signed_directory_document(0, 1, relays, vec![named_index_server_address(0)]);
+    sim_directory.publish(document);
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // The listed relays and index servers are added:
+    let mirror = await!(report.wait_for(
+        |mirror| {
+            (1..4).all(|index| mirror.has_relay(&relay_public_key(index)))
+                && mirror.has_index_server(&index_server_public_key(0))
+        },
+        WAIT_TICKS
+    ))
+    .unwrap();
+    assert!(mirror.has_relay(&relay_public_key(0)));
+    let directory = &mirror.node_report().funder_report.directory;
+    assert_eq!(directory.listing.version, 1);
+    assert_eq!(directory.listing.relays.len(), 3);
+
+    // Relay 2 should never be removed:
+    await!(config.pin_directory_entry(relay_public_key(2))).unwrap();
+
+    // Relay 2, relay 3 and the index server are delisted:
+    let document = signed_directory_document(0, 2, vec![named_relay_address(1)], Vec::new());
+    sim_directory.publish(document);
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Delisted entries are removed, except for the pinned relay.
+    // The manually configured relay is kept:
+    let mirror = await!(report.wait_for(
+        |mirror| {
+            !mirror.has_relay(&relay_public_key(3))
+                && !mirror.has_index_server(&index_server_public_key(0))
+        },
+        WAIT_TICKS
+    ))
+    .unwrap();
+    assert!(mirror.has_relay(&relay_public_key(0)));
+    assert!(mirror.has_relay(&relay_public_key(1)));
+    assert!(mirror.has_relay(&relay_public_key(2)));
+    assert_eq!(mirror.node_report().funder_report.directory.listing.version, 2);
+
+    // A rollback to an older document is ignored:
+    let document = signed_directory_document(0, 1, vec![named_relay_address(3)], Vec::new());
+    sim_directory.publish(document);
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+    let mirror = await!(report.mirror()).unwrap();
+    assert!(!mirror.has_relay(&relay_public_key(3)));
+    assert_eq!(mirror.node_report().funder_report.directory.listing.version, 2);
+
+    // A document with an invalid signature is ignored.
+    // (Signed using the signing key of another directory):
+    let document = signed_directory_document(1, 3, vec![named_relay_address(3)], Vec::new());
+    sim_directory.publish(document);
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+    let mirror = await!(report.mirror()).unwrap();
+    assert!(!mirror.has_relay(&relay_public_key(3)));
+    assert!(mirror.has_relay(&relay_public_key(1)));
+    assert_eq!(mirror.node_report().funder_report.directory.listing.version, 2);
+}
+
+#[test]
+fn test_directory() {
+    // let _ = env_logger::init();
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_directory(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use node::connect::{AddFriendError, SetFriendRelaysError};
+use proto::app_server::messages::AppPermissions;
+use proto::report::messages::FriendStatusReport;
+use timer::create_timer_incoming;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
+    relay_address, SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+fn all_permissions(index: u8) -> HashMap<u8, AppPermissions> {
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        index,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    trusted_apps
+}
+
+async fn task_duplicate_friend(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    let mut apps = Vec::new();
+    for index in 0..2 {
+        sim_db.init_db(index);
+        await!(create_node(
+            index,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            all_permissions(index),
+            test_executor.clone()
+        ))
+        .forget();
+
+        let app = await!(create_app(
+            index,
+            sim_net_client.clone(),
+            timer_client.clone(),
+            index,
+            test_executor.clone()
+        ))
+        .unwrap();
+        apps.push(app);
+    }
+
+    for index in 0..3 {
+        await!(create_relay(
+            index,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+    }
+
+    let mut config0 = apps[0].config().unwrap().clone();
+    let mut config1 = apps[1].config().unwrap().clone();
+
+    let mut report0 = apps[0].report().clone();
+    let mut report1 = apps[1].report().clone();
+
+    // Configure relays:
+    await!(config0.add_relay(named_relay_address(0))).unwrap();
+    await!(config1.add_relay(named_relay_address(1))).unwrap();
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node0 <--> Node1:
+    await!(config0.add_friend(
+        node_public_key(1),
+        vec![relay_address(1)],
+        String::from("node1"),
+        100
+    ))
+    .unwrap();
+
+    await!(config1.add_friend(
+        node_public_key(0),
+        vec![relay_address(0)],
+        String::from("node0"),
+        -100
+    ))
+    .unwrap();
+
+    await!(config0.enable_friend(node_public_key(1))).unwrap();
+    await!(config1.enable_friend(node_public_key(0))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    await!(report0.wait_for(|mirror| mirror.is_friend_online(&node_public_key(1)), WAIT_TICKS))
+        .unwrap();
+    await!(report1.wait_for(|mirror| mirror.is_friend_online(&node_public_key(0)), WAIT_TICKS))
+        .unwrap();
+
+    // Node0 adds node1 again, from a different invite:
+    let res = await!(config0.add_friend(
+        node_public_key(1),
+        vec![relay_address(2)],
+        String::from("node1_again"),
+        5
+    ));
+    match res {
+        Err(AddFriendError::AlreadyExists(existing_friend)) => {
+            assert_eq!(existing_friend.name, "node1");
+            assert_eq!(existing_friend.relays, vec![relay_address(1)]);
+            assert_eq!(existing_friend.status, FriendStatusReport::Enabled);
+        }
+        _ => unreachable!(),
+    };
+
+    // Nothing has changed:
+    let mirror0 = await!(report0.mirror()).unwrap();
+    let friend_report = mirror0.friend_report(&node_public_key(1)).unwrap();
+    assert_eq!(friend_report.name, "node1");
+    assert_eq!(friend_report.remote_relays, vec![relay_address(1)]);
+    assert_eq!(mirror0.balance(&node_public_key(1)), Some(100));
+    assert!(mirror0.is_friend_online(&node_public_key(1)));
+
+    // Node1 moves to relay 2:
+    await!(config1.add_relay(named_relay_address(2))).unwrap();
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node0 updates the address of node1:
+    await!(config0.set_friend_relays(node_public_key(1), vec![relay_address(2)])).unwrap();
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node0 reconnects to node1 using the new address. The balance is kept:
+    let mirror0 = await!(report0.wait_for(
+        |mirror| {
+            mirror.is_friend_online(&node_public_key(1))
+                && mirror
+                    .friend_report(&node_public_key(1))
+                    .map(|friend_report| friend_report.remote_relays == vec![relay_address(2)])
+                    .unwrap_or(false)
+        },
+        WAIT_TICKS
+    ))
+    .unwrap();
+    assert_eq!(mirror0.balance(&node_public_key(1)), Some(100));
+    await!(report1.wait_for(|mirror| mirror.is_friend_online(&node_public_key(0)), WAIT_TICKS))
+        .unwrap();
+
+    // Updating the address of a nonexistent friend fails:
+    let res = await!(config0.set_friend_relays(node_public_key(2), vec![relay_address(2)]));
+    match res {
+        Err(SetFriendRelaysError::FriendDoesNotExist) => {}
+        _ => unreachable!(),
+    };
+    let mirror0 = await!(report0.mirror()).unwrap();
+    assert!(mirror0.friend_report(&node_public_key(2)).is_none());
+}
+
+#[test]
+fn test_duplicate_friend() {
+    // let _ = env_logger::init();
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_duplicate_friend(test_executor.clone()));
+    assert!(res.is_output());
+}
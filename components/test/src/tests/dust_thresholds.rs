@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use node::connect::SendFundsError;
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::{DustThresholds, FriendsRoute};
+use timer::create_timer_incoming;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
+    relay_address, SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+fn all_permissions(index: u8) -> HashMap<u8, AppPermissions> {
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        index,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    trusted_apps
+}
+
+async fn task_dust_thresholds(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    let mut apps = Vec::new();
+    for index in 0..2 {
+        sim_db.init_db(index);
+        await!(create_node(
+            index,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            all_permissions(index),
+            test_executor.clone()
+        ))
+        .forget();
+
+        let app = await!(create_app(
+            index,
+            sim_net_client.clone(),
+            timer_client.clone(),
+            index,
+            test_executor.clone()
+        ))
+        .unwrap();
+        apps.push(app);
+
+        await!(create_relay(
+            index,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+    }
+
+    let mut config0 = apps[0].config().unwrap().clone();
+    let mut config1 = apps[1].config().unwrap().clone();
+
+    let mut send_funds0 = apps[0].send_funds().unwrap().clone();
+
+    let mut report0 = apps[0].report().clone();
+    let mut report1 = apps[1].report().clone();
+
+    // Configure relays:
+    await!(config0.add_relay(named_relay_address(0))).unwrap();
+    await!(config1.add_relay(named_relay_address(1))).unwrap();
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node0 <--> Node1:
+    await!(config0.add_friend(
+        node_public_key(1),
+        vec![relay_address(1)],
+        String::from("node1"),
+        100
+    ))
+    .unwrap();
+
+    await!(config1.add_friend(
+        node_public_key(0),
+        vec![relay_address(0)],
+        String::from("node0"),
+        -100
+    ))
+    .unwrap();
+
+    await!(config0.enable_friend(node_public_key(1))).unwrap();
+    await!(config1.enable_friend(node_public_key(0))).unwrap();
+    await!(config1.open_friend(node_public_key(0))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    await!(report0.wait_for(
+        |mirror| mirror.has_send_capacity(&node_public_key(1), 100),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // Node0 does not send less than 10 credits. Node1 does not receive less than 20 credits:
+    let dust_thresholds0 = DustThresholds {
+        min_send_payment: 10,
+        ..DustThresholds::default()
+    };
+    let dust_thresholds1 = DustThresholds {
+        min_receive_payment: 20,
+        ..DustThresholds::default()
+    };
+    await!(config0.set_dust_thresholds(dust_thresholds0.clone())).unwrap();
+    await!(config1.set_dust_thresholds(dust_thresholds1.clone())).unwrap();
+
+    // The thresholds are visible in the reports:
+    await!(report0.wait_for(
+        |mirror| mirror.node_report().funder_report.dust_thresholds == dust_thresholds0,
+        WAIT_TICKS
+    ))
+    .unwrap();
+    await!(report1.wait_for(
+        |mirror| mirror.node_report().funder_report.dust_thresholds == dust_thresholds1,
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    let route = FriendsRoute {
+        public_keys: vec![node_public_key(0), node_public_key(1)],
+    };
+    let invoice_id = InvoiceId::from(&[0; INVOICE_ID_LEN]);
+
+    // Rejected by node0, before it is sent:
+    let res = await!(send_funds0.request_send_funds(
+        Uid::from(&[0; UID_LEN]),
+        route.clone(),
+        invoice_id.clone(),
+        9
+    ));
+    match res {
+        Err(SendFundsError::BelowMinPayment(min_send_payment)) => {
+            assert_eq!(min_send_payment, 10);
+        }
+        _ => unreachable!(),
+    };
+
+    // Rejected by node1:
+    let res = await!(send_funds0.request_send_funds(
+        Uid::from(&[1; UID_LEN]),
+        route.clone(),
+        invoice_id.clone(),
+        19
+    ));
+    match res {
+        Err(SendFundsError::PricingRejected(public_key)) => {
+            assert_eq!(public_key, node_public_key(1));
+        }
+        _ => unreachable!(),
+    };
+
+    // Exactly at the thresholds:
+    let request_id = Uid::from(&[2; UID_LEN]);
+    let receipt =
+        await!(send_funds0.request_send_funds(request_id, route, invoice_id, 20)).unwrap();
+    assert_eq!(receipt.dest_payment, 20);
+    await!(send_funds0.receipt_ack(request_id, receipt)).unwrap();
+
+    // Only the last payment moved credits:
+    let mirror0 = await!(report0.mirror()).unwrap();
+    assert_eq!(mirror0.balance(&node_public_key(1)), Some(80));
+}
+
+#[test]
+fn test_dust_thresholds() {
+    // let _ = env_logger::init();
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_dust_thresholds(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -0,0 +1,210 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use crypto::identity::compare_public_key;
+
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::Goodbye;
+use proto::report::messages::FriendLivenessReport;
+use timer::create_timer_incoming;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
+    relay_address, SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+/// The downtime the shutting down node announces to its friend
+const DOWNTIME_TICKS: u64 = 100;
+
+fn all_permissions(index: u8) -> HashMap<u8, AppPermissions> {
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        index,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    trusted_apps
+}
+
+async fn task_goodbye(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    // The friend with the bigger public key is the one that initiates the connection.
+    // The node that shuts down is the one with the smaller public key, so that its friend is
+    // the one that has to decide when to reconnect:
+    let (quitter, peer) = match compare_public_key(&node_public_key(0), &node_public_key(1)) {
+        Ordering::Less => (0u8, 1u8),
+        _ => (1u8, 0u8),
+    };
+
+    sim_db.init_db(quitter);
+    let quitter_handle = await!(create_node(
+        quitter,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        all_permissions(quitter),
+        test_executor.clone()
+    ));
+
+    sim_db.init_db(peer);
+    await!(create_node(
+        peer,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        all_permissions(peer),
+        test_executor.clone()
+    ))
+    .forget();
+
+    let quitter_app = await!(create_app(
+        quitter,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        quitter,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    let peer_app = await!(create_app(
+        peer,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        peer,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    for index in 0..2 {
+        await!(create_relay(
+            index,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+    }
+
+    let mut quitter_config = quitter_app.config().unwrap().clone();
+    let mut peer_config = peer_app.config().unwrap().clone();
+
+    let mut quitter_report = quitter_app.report().clone();
+    let mut peer_report = peer_app.report().clone();
+
+    // Configure relays:
+    await!(quitter_config.add_relay(named_relay_address(quitter))).unwrap();
+    await!(peer_config.add_relay(named_relay_address(peer))).unwrap();
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // quitter <--> peer:
+    await!(quitter_config.add_friend(
+        node_public_key(peer),
+        vec![relay_address(peer)],
+        String::from("peer"),
+        0
+    ))
+    .unwrap();
+
+    await!(peer_config.add_friend(
+        node_public_key(quitter),
+        vec![relay_address(quitter)],
+        String::from("quitter"),
+        0
+    ))
+    .unwrap();
+
+    await!(quitter_config.enable_friend(node_public_key(peer))).unwrap();
+    await!(peer_config.enable_friend(node_public_key(quitter))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    await!(quitter_report.wait_for(
+        |mirror| mirror.is_friend_online(&node_public_key(peer)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+    await!(peer_report.wait_for(
+        |mirror| mirror.is_friend_online(&node_public_key(quitter)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // The quitter announces that it is going to shut down, and then shuts down:
+    await!(quitter_config.announce_shutdown(Some(DOWNTIME_TICKS))).unwrap();
+    await!(advance_time(5, &mut tick_sender, &test_executor));
+
+    drop(quitter_handle);
+    drop(quitter_config);
+    drop(quitter_report);
+    drop(quitter_app);
+
+    await!(advance_time(20, &mut tick_sender, &test_executor));
+
+    // The peer knows that the quitter went offline on purpose:
+    let expected_liveness = FriendLivenessReport::IntentionallyOffline(Goodbye {
+        opt_downtime_ticks: Some(DOWNTIME_TICKS),
+    });
+    let mirror = await!(peer_report.mirror()).unwrap();
+    let friend_report = mirror.friend_report(&node_public_key(quitter)).unwrap();
+    assert_eq!(friend_report.liveness, expected_liveness);
+
+    // The quitter is back early:
+    let _quitter_handle = await!(create_node(
+        quitter,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        all_permissions(quitter),
+        test_executor.clone()
+    ));
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // The peer does not try to reconnect before the announced downtime has passed:
+    let mirror = await!(peer_report.mirror()).unwrap();
+    let friend_report = mirror.friend_report(&node_public_key(quitter)).unwrap();
+    assert_eq!(friend_report.liveness, expected_liveness);
+
+    await!(advance_time(60, &mut tick_sender, &test_executor));
+
+    // The peer reconnects after the announced downtime:
+    await!(peer_report.wait_for(
+        |mirror| mirror.is_friend_online(&node_public_key(quitter)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+}
+
+#[test]
+fn test_goodbye() {
+    // let _ = env_logger::init();
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_goodbye(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+use futures::StreamExt;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::{FriendsRoute, PaymentNotifyFilter, Receipt};
+use timer::create_timer_incoming;
+
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use node::connect::{AppSendFunds, IncomingPayments};
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
+    relay_address, SimDb,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+/// Send a payment from node0 to node1, and return the receipt.
+async fn pay_node1(send_funds0: &mut AppSendFunds, i: u8) -> Receipt {
+    let route = FriendsRoute {
+        public_keys: vec![node_public_key(0), node_public_key(1)],
+    };
+    let request_id = Uid::from(&[i; UID_LEN]);
+    let invoice_id = InvoiceId::from(&[i; INVOICE_ID_LEN]);
+    let receipt = await!(send_funds0.request_send_funds(
+        request_id.clone(),
+        route,
+        invoice_id,
+        10
+    ))
+    .unwrap();
+    await!(send_funds0.receipt_ack(request_id, receipt.clone())).unwrap();
+    receipt
+}
+
+/// Receive the next incoming payment, and check that it matches the given receipt.
+async fn recv_payment(incoming_payments: &mut IncomingPayments, receipt: &Receipt) -> u64 {
+    let incoming_payment = await!(incoming_payments.next()).unwrap();
+    assert_eq!(&incoming_payment.receipt, receipt);
+    assert_eq!(incoming_payment.route_len, 2);
+    incoming_payment.notification_id
+}
+
+async fn task_incoming_payments(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    let app_permissions = AppPermissions {
+        routes: true,
+        send_funds: true,
+        config: true,
+    };
+
+    // Create initial database for node 0:
+    sim_db.init_db(0);
+
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(0, app_permissions.clone());
+    await!(create_node(
+        0,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        trusted_apps,
+        test_executor.clone()
+    ))
+    .forget();
+
+    let mut app0 = await!(create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    // Create initial database for node 1:
+    sim_db.init_db(1);
+
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(1, app_permissions);
+    await!(create_node(
+        1,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        trusted_apps,
+        test_executor.clone()
+    ))
+    .forget();
+
+    let mut app1 = await!(create_app(
+        1,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        1,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    // Create relays:
+    await!(create_relay(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    await!(create_relay(
+        1,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    let mut config0 = app0.config().unwrap().clone();
+    let mut config1 = app1.config().unwrap().clone();
+
+    let mut send_funds0 = app0.send_funds().unwrap().clone();
+
+    // Configure relays:
+    await!(config0.add_relay(named_relay_address(0))).unwrap();
+    await!(config1.add_relay(named_relay_address(1))).unwrap();
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node0: Add node1 as a friend:
+    await!(config0.add_friend(
+        node_public_key(1),
+        vec![relay_address(1)],
+        String::from("node1"),
+        100
+    ))
+    .unwrap();
+
+    // Node1: Add node0 as a friend:
+    await!(config1.add_friend(
+        node_public_key(0),
+        vec![relay_address(0)],
+        String::from("node0"),
+        -100
+    ))
+    .unwrap();
+
+    await!(config0.enable_friend(node_public_key(1))).unwrap();
+    await!(config1.enable_friend(node_public_key(0))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    await!(config0.open_friend(node_public_key(1))).unwrap();
+    await!(config1.open_friend(node_public_key(0))).unwrap();
+
+    // Node1: Incoming payments are delivered to the apps:
+    await!(config1.set_apps_payment_notifier(PaymentNotifyFilter::All)).unwrap();
+
+    await!(advance_time(10, &mut tick_sender, &test_executor));
+
+    // App1 subscribes, and receives a payment, but does not acknowledge it:
+    let mut incoming_payments1 = app1.incoming_payments().unwrap().clone();
+    let mut payments_stream = await!(incoming_payments1.subscribe()).unwrap();
+    let mut receipts = Vec::new();
+    receipts.push(await!(pay_node1(&mut send_funds0, 0)));
+    assert_eq!(await!(recv_payment(&mut payments_stream, &receipts[0])), 0);
+
+    // App1 disconnects:
+    drop(payments_stream);
+    drop(incoming_payments1);
+    drop(config1);
+    drop(app1);
+
+    await!(advance_time(10, &mut tick_sender, &test_executor));
+
+    // Payments are not held back while nobody is subscribed:
+    for i in 1..3 {
+        receipts.push(await!(pay_node1(&mut send_funds0, i)));
+    }
+
+    // App1 reconnects and subscribes again.
+    // The unacknowledged payment is delivered again, followed by the new payments:
+    let mut app1 = await!(create_app(
+        1,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        1,
+        test_executor.clone()
+    ))
+    .unwrap();
+    let mut config1 = app1.config().unwrap().clone();
+    let mut incoming_payments1 = app1.incoming_payments().unwrap().clone();
+    let mut payments_stream = await!(incoming_payments1.subscribe()).unwrap();
+    for (i, receipt) in receipts.iter().enumerate() {
+        assert_eq!(await!(recv_payment(&mut payments_stream, receipt)), i as u64);
+    }
+
+    // Setting the notifier again makes the node send all unacknowledged payments again.
+    // Every payment shows up only once in the stream:
+    await!(config1.set_apps_payment_notifier(PaymentNotifyFilter::All)).unwrap();
+    receipts.push(await!(pay_node1(&mut send_funds0, 3)));
+    assert_eq!(await!(recv_payment(&mut payments_stream, &receipts[3])), 3);
+
+    for notification_id in 0..4 {
+        await!(incoming_payments1.ack(notification_id)).unwrap();
+    }
+
+    // A new subscription, that is not being read while new payments arrive:
+    let mut incoming_payments1b = app1.incoming_payments().unwrap().clone();
+    let mut payments_stream_b = await!(incoming_payments1b.subscribe()).unwrap();
+    drop(payments_stream);
+
+    for i in 4..6 {
+        receipts.push(await!(pay_node1(&mut send_funds0, i)));
+    }
+
+    // Acknowledged payments are not delivered again:
+    assert_eq!(await!(recv_payment(&mut payments_stream_b, &receipts[4])), 4);
+    assert_eq!(await!(recv_payment(&mut payments_stream_b, &receipts[5])), 5);
+}
+
+#[test]
+fn test_incoming_payments() {
+    // let _ = env_logger::init();
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_incoming_payments(test_executor.clone()));
+    assert!(res.is_output());
+}
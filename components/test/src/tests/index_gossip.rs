@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::AppPermissions;
+use timer::create_timer_incoming;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_index_server, create_node, create_relay,
+    named_index_server_address, named_relay_address, node_public_key, relay_address, SimDb,
+    WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+/// Friendships between the nodes: 0 -- 1 -- 2
+const FRIENDS: [(u8, u8); 4] = [(0, 1), (1, 0), (1, 2), (2, 1)];
+
+fn all_permissions(index: u8) -> HashMap<u8, AppPermissions> {
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        index,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    trusted_apps
+}
+
+async fn task_index_gossip(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    let mut apps = Vec::new();
+    for i in 0..3 {
+        sim_db.init_db(i);
+        await!(create_node(
+            i,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            all_permissions(i),
+            test_executor.clone()
+        ))
+        .forget();
+
+        await!(create_relay(
+            i,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+
+        apps.push(
+            await!(create_app(
+                i,
+                sim_net_client.clone(),
+                timer_client.clone(),
+                i,
+                test_executor.clone()
+            ))
+            .unwrap(),
+        );
+    }
+
+    // Create three index servers:
+    // 0 -- 2 -- 1
+    // Mutations of nodes connected to the index servers 0 and 1 are forwarded by the middle
+    // server.
+    await!(create_index_server(
+        2,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        vec![0, 1],
+        test_executor.clone()
+    ));
+    for &i in &[0, 1] {
+        await!(create_index_server(
+            i,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            vec![2],
+            test_executor.clone()
+        ));
+    }
+
+    // Every node uses its own relay, and is connected to a different index server:
+    for i in 0..3u8 {
+        let app = &mut apps[usize::from(i)];
+        await!(app.config().unwrap().add_relay(named_relay_address(i))).unwrap();
+        await!(app
+            .config()
+            .unwrap()
+            .add_index_server(named_index_server_address(i)))
+        .unwrap();
+    }
+
+    for &(a, b) in &FRIENDS {
+        let mut config = apps[usize::from(a)].config().unwrap().clone();
+        await!(config.add_friend(
+            node_public_key(b),
+            vec![relay_address(b)],
+            format!("node{}", b),
+            0
+        ))
+        .unwrap();
+        await!(config.enable_friend(node_public_key(b))).unwrap();
+        await!(config.open_friend(node_public_key(b))).unwrap();
+        await!(config.set_friend_remote_max_debt(node_public_key(b), 100)).unwrap();
+    }
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Make sure that node1 sees its friends as online:
+    for &b in &[0, 2] {
+        await!(apps[1].report().wait_for(
+            |mirror| mirror.is_friend_online(&node_public_key(b)),
+            WAIT_TICKS
+        ))
+        .unwrap();
+    }
+
+    // A burst of capacity updates for every friend.
+    // Every update is sent to the index servers as a separate mutation:
+    for round in 1..=8u128 {
+        for &(a, b) in &FRIENDS {
+            let remote_max_debt = 100 + round * 10 + u128::from(a);
+            await!(apps[usize::from(a)]
+                .config()
+                .unwrap()
+                .set_friend_remote_max_debt(node_public_key(b), remote_max_debt))
+            .unwrap();
+        }
+    }
+
+    // Let the mutations propagate between the index servers:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Every node asks its own index server for the same routes.
+    // All the index servers should have the same view of the network:
+    let mut all_routes = Vec::new();
+    for app in &mut apps {
+        let routes = await!(app.routes().unwrap().request_routes(
+            20,
+            node_public_key(0),
+            node_public_key(2),
+            None
+        ))
+        .unwrap();
+        all_routes.push(routes);
+    }
+
+    assert_eq!(all_routes[0].len(), 1);
+    assert_eq!(
+        all_routes[0][0].route.public_keys,
+        vec![node_public_key(0), node_public_key(1), node_public_key(2)]
+    );
+    assert_eq!(all_routes[1], all_routes[0]);
+    assert_eq!(all_routes[2], all_routes[0]);
+}
+
+#[test]
+fn test_index_gossip() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_index_gossip(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::FriendsRoute;
+use timer::create_timer_incoming;
+
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_index_server, create_node, create_relay,
+    named_index_server_address, named_relay_address, node_public_key, relay_address, SimDb,
+    WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+fn all_permissions(index: u8) -> HashMap<u8, AppPermissions> {
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        index,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    trusted_apps
+}
+
+async fn task_index_private(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    for i in 0..2 {
+        sim_db.init_db(i);
+        await!(create_node(
+            i,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            all_permissions(i),
+            test_executor.clone()
+        ))
+        .forget();
+
+        await!(create_relay(
+            i,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+    }
+
+    let mut app0 = await!(create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone()
+    ))
+    .unwrap();
+    let mut app1 = await!(create_app(
+        1,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        1,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    // Both nodes use the same index server:
+    await!(create_index_server(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        vec![],
+        test_executor.clone()
+    ));
+
+    let mut config0 = app0.config().unwrap().clone();
+    let mut config1 = app1.config().unwrap().clone();
+
+    let mut routes0 = app0.routes().unwrap().clone();
+    let mut routes1 = app1.routes().unwrap().clone();
+
+    let mut send_funds0 = app0.send_funds().unwrap().clone();
+
+    let mut report0 = app0.report().clone();
+    let mut report1 = app1.report().clone();
+
+    await!(config0.add_relay(named_relay_address(0))).unwrap();
+    await!(config1.add_relay(named_relay_address(1))).unwrap();
+
+    await!(config0.add_index_server(named_index_server_address(0))).unwrap();
+    await!(config1.add_index_server(named_index_server_address(0))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    await!(config0.add_friend(
+        node_public_key(1),
+        vec![relay_address(1)],
+        String::from("node1"),
+        100
+    ))
+    .unwrap();
+    await!(config1.add_friend(
+        node_public_key(0),
+        vec![relay_address(0)],
+        String::from("node0"),
+        -100
+    ))
+    .unwrap();
+
+    await!(config0.enable_friend(node_public_key(1))).unwrap();
+    await!(config1.enable_friend(node_public_key(0))).unwrap();
+
+    await!(report0.wait_for(
+        |mirror| mirror.is_friend_online(&node_public_key(1)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+    await!(report1.wait_for(
+        |mirror| mirror.is_friend_online(&node_public_key(0)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    await!(config0.open_friend(node_public_key(1))).unwrap();
+    await!(config1.open_friend(node_public_key(0))).unwrap();
+
+    // Let the index server learn about the channel:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    let routes_0_1 =
+        await!(routes1.request_routes(20, node_public_key(0), node_public_key(1), None)).unwrap();
+    assert_eq!(routes_0_1.len(), 1);
+
+    // Node0 hides node1 from index servers:
+    await!(config0.set_friend_index_private(node_public_key(1), true)).unwrap();
+    await!(report0.wait_for(
+        |mirror| mirror
+            .friend_report(&node_public_key(1))
+            .map(|friend_report| friend_report.index_private)
+            .unwrap_or(false),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // The index server does not know about the channel anymore:
+    let routes_0_1 =
+        await!(routes1.request_routes(20, node_public_key(0), node_public_key(1), None)).unwrap();
+    assert!(routes_0_1.is_empty());
+    let routes_0_1 =
+        await!(routes0.request_routes(20, node_public_key(0), node_public_key(1), None)).unwrap();
+    assert!(routes_0_1.is_empty());
+
+    // Node0 can still pay node1 directly:
+    let route = FriendsRoute {
+        public_keys: vec![node_public_key(0), node_public_key(1)],
+    };
+    let request_id = Uid::from(&[0x0; UID_LEN]);
+    let invoice_id = InvoiceId::from(&[0; INVOICE_ID_LEN]);
+    let receipt =
+        await!(send_funds0.request_send_funds(request_id.clone(), route, invoice_id, 10)).unwrap();
+    await!(send_funds0.receipt_ack(request_id, receipt)).unwrap();
+
+    // Node0 advertises node1 again:
+    await!(config0.set_friend_index_private(node_public_key(1), false)).unwrap();
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    let mut routes_0_1 =
+        await!(routes1.request_routes(20, node_public_key(0), node_public_key(1), None)).unwrap();
+    assert_eq!(routes_0_1.len(), 1);
+    assert_eq!(routes_0_1.pop().unwrap().capacity, 90);
+}
+
+#[test]
+fn test_index_private() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_index_private(test_executor.clone()));
+    assert!(res.is_output());
+}
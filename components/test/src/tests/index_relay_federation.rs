@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::AppPermissions;
+use timer::create_timer_incoming;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, create_relay_index_server,
+    named_index_server_address, named_relay_address, node_public_key, relay_address, SimDb,
+    WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+async fn task_index_relay_federation(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    // Create relays:
+    for i in 0..2 {
+        await!(create_relay(
+            i,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+    }
+
+    // Create three index servers:
+    // 0 -- 2 -- 1
+    // The index servers do not listen for other index servers directly. They can only reach each
+    // other through relay 0, and the only way for information to flow between the two edge
+    // servers is by having the middle server forward it.
+    await!(create_relay_index_server(
+        2,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        vec![0, 1],
+        0,
+        test_executor.clone()
+    ));
+
+    for i in 0..2 {
+        await!(create_relay_index_server(
+            i,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            vec![2],
+            0,
+            test_executor.clone()
+        ));
+    }
+
+    let mut apps = Vec::new();
+    for i in 0..2 {
+        sim_db.init_db(i);
+
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            i,
+            AppPermissions {
+                routes: true,
+                send_funds: true,
+                config: true,
+            },
+        );
+
+        await!(create_node(
+            i,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            trusted_apps,
+            test_executor.clone()
+        ))
+        .forget();
+
+        let mut app = await!(create_app(
+            i,
+            sim_net_client.clone(),
+            timer_client.clone(),
+            i,
+            test_executor.clone()
+        ))
+        .unwrap();
+
+        // Every node uses its own relay and its own index server:
+        let mut config = app.config().unwrap().clone();
+        await!(config.add_relay(named_relay_address(i))).unwrap();
+        await!(config.add_index_server(named_index_server_address(i))).unwrap();
+        apps.push(app);
+    }
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    for &(i, j, balance) in &[(0, 1, 100), (1, 0, -100)] {
+        let mut config = apps[i as usize].config().unwrap().clone();
+        await!(config.add_friend(
+            node_public_key(j),
+            vec![relay_address(j)],
+            format!("node{}", j),
+            balance
+        ))
+        .unwrap();
+        await!(config.enable_friend(node_public_key(j))).unwrap();
+    }
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    for &(i, j) in &[(0, 1), (1, 0)] {
+        let app = &mut apps[i as usize];
+        await!(app
+            .report()
+            .wait_for(|mirror| mirror.is_friend_online(&node_public_key(j)), WAIT_TICKS))
+        .unwrap();
+        await!(app.config().unwrap().open_friend(node_public_key(j))).unwrap();
+    }
+
+    // Wait some time, to let the index servers exchange information:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Both edge index servers know about the capacity between node0 and node1.
+    // Each of them can only learn about one side of the friendship through the middle server.
+    for app in &mut apps {
+        let mut routes_0_1 = await!(app.routes().unwrap().request_routes(
+            20,
+            node_public_key(0),
+            node_public_key(1),
+            None
+        ))
+        .unwrap();
+        assert_eq!(routes_0_1.len(), 1);
+        let route_with_capacity = routes_0_1.pop().unwrap();
+        assert_eq!(route_with_capacity.capacity, 100);
+        assert_eq!(
+            route_with_capacity.route.public_keys,
+            vec![node_public_key(0), node_public_key(1)]
+        );
+    }
+}
+
+#[test]
+fn test_index_relay_federation() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_index_relay_federation(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -1,4 +1,27 @@
+mod channel_migration;
+mod debug_bundle;
+mod demo;
+mod direct_connections;
+mod directory;
+mod dust_thresholds;
+mod duplicate_friend;
+mod goodbye;
+mod incoming_payments;
+mod index_gossip;
+mod index_private;
+mod index_relay_federation;
 mod nodes_chain;
+mod payment_estimate;
+mod payment_notifications;
+mod payment_submissions;
+mod pending_payments;
+mod prewarm;
+mod quarantine;
+mod rebalance;
+mod reliability;
 mod relay_migration;
+mod relay_mux;
 mod resolve_inconsistency;
+mod self_test;
+mod sweep;
 mod two_nodes_payment;
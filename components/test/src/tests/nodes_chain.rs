@@ -16,6 +16,7 @@ use crate::sim_network::create_sim_network;
 use crate::utils::{
     advance_time, create_app, create_index_server, create_node, create_relay,
     named_index_server_address, named_relay_address, node_public_key, relay_address, SimDb,
+    WAIT_TICKS,
 };
 
 const TIMER_CHANNEL_LEN: usize = 0;
@@ -357,13 +358,10 @@ async fn task_nodes_chain(mut test_executor: TestExecutor) {
     await!(advance_time(40, &mut tick_sender, &test_executor));
 
     // Make sure that node1 sees node2 as online:
-    let (node_report, mutations_receiver) = await!(apps[1].report().incoming_reports()).unwrap();
-    drop(mutations_receiver);
-    let friend_report = match node_report.funder_report.friends.get(&node_public_key(2)) {
-        None => unreachable!(),
-        Some(friend_report) => friend_report,
-    };
-    assert!(friend_report.liveness.is_online());
+    await!(apps[1]
+        .report()
+        .wait_for(|mirror| mirror.is_friend_online(&node_public_key(2)), WAIT_TICKS))
+    .unwrap();
 
     // Node0: Request routes:
     let mut routes_0_4 = await!(apps[0].routes().unwrap().request_routes(
@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use node::connect::{EstimateConfidence, EstimateParams};
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::FriendsRoute;
+use timer::create_timer_incoming;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
+    relay_address, SimDb,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+/// Amount of payments sent to warm up the measurements of node1
+const NUM_WARM_UP_PAYMENTS: u8 = 8;
+
+async fn task_payment_estimate(mut test_executor: TestExecutor) {
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    let mut apps = Vec::new();
+
+    // Create 3 nodes with apps:
+    for i in 0..3 {
+        sim_db.init_db(i);
+
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            i,
+            AppPermissions {
+                routes: true,
+                send_funds: true,
+                config: true,
+            },
+        );
+
+        await!(create_node(
+            i,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            trusted_apps,
+            test_executor.clone()
+        ))
+        .forget();
+
+        apps.push(
+            await!(create_app(
+                i,
+                sim_net_client.clone(),
+                timer_client.clone(),
+                i,
+                test_executor.clone()
+            ))
+            .unwrap(),
+        );
+    }
+
+    await!(create_relay(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    for app in &mut apps {
+        await!(app.config().unwrap().add_relay(named_relay_address(0))).unwrap();
+    }
+
+    // A chain of nodes: 0 -- 1 -- 2
+    let friend_pairs = [(0, 1), (1, 2)];
+    for &(i, j) in &friend_pairs {
+        for &(a, b) in &[(i, j), (j, i)] {
+            let app = &mut apps[a as usize];
+            await!(app.config().unwrap().add_friend(
+                node_public_key(b),
+                vec![relay_address(0)],
+                format!("node{}", b),
+                0
+            ))
+            .unwrap();
+            await!(app.config().unwrap().enable_friend(node_public_key(b))).unwrap();
+            await!(app.config().unwrap().open_friend(node_public_key(b))).unwrap();
+            await!(app
+                .config()
+                .unwrap()
+                .set_friend_remote_max_debt(node_public_key(b), 100))
+            .unwrap();
+        }
+    }
+
+    // Let the nodes connect to each other:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    let route_direct = FriendsRoute {
+        public_keys: vec![node_public_key(0), node_public_key(1)],
+    };
+    let route_two_hops = FriendsRoute {
+        public_keys: vec![node_public_key(0), node_public_key(1), node_public_key(2)],
+    };
+    let params = EstimateParams::default();
+
+    // Nothing was measured yet:
+    let mirror = await!(apps[0].report().mirror()).unwrap();
+    let cold_estimate = mirror.estimate_payment(&route_direct, &params).unwrap();
+    assert_eq!(cold_estimate.confidence, EstimateConfidence::Low);
+
+    // Warm up the measurements of node1:
+    for i in 0..NUM_WARM_UP_PAYMENTS {
+        let request_id = Uid::from(&[i; UID_LEN]);
+        let receipt = await!(apps[0].send_funds().unwrap().request_send_funds(
+            request_id,
+            route_direct.clone(),
+            InvoiceId::from(&[i; INVOICE_ID_LEN]),
+            10
+        ))
+        .unwrap();
+        await!(apps[0].send_funds().unwrap().receipt_ack(request_id, receipt)).unwrap();
+    }
+
+    let mirror = await!(apps[0].report().mirror()).unwrap();
+    let direct_estimate = mirror.estimate_payment(&route_direct, &params).unwrap();
+    assert_eq!(direct_estimate.confidence, EstimateConfidence::High);
+
+    // Time passes in the simulation only when we advance it, so payments are answered within the
+    // tick they were sent in. The estimate brackets the measured payment time:
+    let measured_ticks = mirror
+        .reliability(&node_public_key(1))
+        .unwrap()
+        .opt_latency_ticks
+        .unwrap();
+    assert_eq!(measured_ticks, 0);
+    assert!(direct_estimate.min_ticks <= measured_ticks);
+    assert!(measured_ticks < direct_estimate.max_ticks);
+
+    let request_id = Uid::from(&[NUM_WARM_UP_PAYMENTS; UID_LEN]);
+    let receipt = await!(apps[0].send_funds().unwrap().request_send_funds(
+        request_id,
+        route_two_hops.clone(),
+        InvoiceId::from(&[NUM_WARM_UP_PAYMENTS; INVOICE_ID_LEN]),
+        10
+    ))
+    .unwrap();
+    await!(apps[0].send_funds().unwrap().receipt_ack(request_id, receipt)).unwrap();
+
+    // Node2 is beyond our measurements. It widens the range and lowers the confidence:
+    let mirror = await!(apps[0].report().mirror()).unwrap();
+    let direct_estimate = mirror.estimate_payment(&route_direct, &params).unwrap();
+    let two_hops_estimate = mirror.estimate_payment(&route_two_hops, &params).unwrap();
+    assert_eq!(two_hops_estimate.min_ticks, direct_estimate.min_ticks);
+    assert!(two_hops_estimate.max_ticks > direct_estimate.max_ticks);
+    assert!(two_hops_estimate.confidence < direct_estimate.confidence);
+
+    // A configured timeout bounds the estimate:
+    let params_timeout = EstimateParams {
+        opt_timeout_ticks: Some(1),
+        ..EstimateParams::default()
+    };
+    let timeout_estimate = mirror
+        .estimate_payment(&route_two_hops, &params_timeout)
+        .unwrap();
+    assert_eq!(timeout_estimate.max_ticks, 1);
+
+    // Routes that do not begin at node0 can not be estimated:
+    let route_other = FriendsRoute {
+        public_keys: vec![node_public_key(1), node_public_key(2)],
+    };
+    assert!(mirror.estimate_payment(&route_other, &params).is_none());
+}
+
+#[test]
+fn test_payment_estimate() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_payment_estimate(test_executor.clone()));
+    assert!(res.is_output());
+}
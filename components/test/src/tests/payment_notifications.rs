@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt};
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::{FriendsRoute, PaymentNotifyFilter};
+use timer::create_timer_incoming;
+
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use node::notifier::{deserialize_incoming_payment, serialize_notification_ack};
+
+use crate::sim_network::{create_sim_network, net_address};
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
+    relay_address, SimDb,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+/// Amount of payments node0 sends to node1
+const NUM_PAYMENTS: u8 = 3;
+
+async fn task_payment_notifications(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let mut sim_net_client = create_sim_network(&mut test_executor);
+
+    // Create initial database for node 0:
+    sim_db.init_db(0);
+
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        0,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+
+    await!(create_node(
+        0,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        trusted_apps,
+        test_executor.clone()
+    ))
+    .forget();
+
+    let mut app0 = await!(create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    // Create initial database for node 1:
+    sim_db.init_db(1);
+
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        1,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    let node1_handle = await!(create_node(
+        1,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        trusted_apps,
+        test_executor.clone()
+    ));
+
+    let mut app1 = await!(create_app(
+        1,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        1,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    // Create relays:
+    await!(create_relay(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    await!(create_relay(
+        1,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    let mut config0 = app0.config().unwrap().clone();
+    let mut config1 = app1.config().unwrap().clone();
+
+    let mut send_funds0 = app0.send_funds().unwrap().clone();
+
+    // Configure relays:
+    await!(config0.add_relay(named_relay_address(0))).unwrap();
+    await!(config1.add_relay(named_relay_address(1))).unwrap();
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node0: Add node1 as a friend:
+    await!(config0.add_friend(
+        node_public_key(1),
+        vec![relay_address(1)],
+        String::from("node1"),
+        100
+    ))
+    .unwrap();
+
+    // Node1: Add node0 as a friend:
+    await!(config1.add_friend(
+        node_public_key(0),
+        vec![relay_address(0)],
+        String::from("node0"),
+        -100
+    ))
+    .unwrap();
+
+    await!(config0.enable_friend(node_public_key(1))).unwrap();
+    await!(config1.enable_friend(node_public_key(0))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    await!(config0.open_friend(node_public_key(1))).unwrap();
+    await!(config1.open_friend(node_public_key(0))).unwrap();
+
+    // Node1: Notify a consumer about every incoming payment.
+    // Nobody is listening on this address yet:
+    await!(config1.set_payment_notifier(net_address("notify_consumer"), PaymentNotifyFilter::All))
+        .unwrap();
+
+    await!(advance_time(10, &mut tick_sender, &test_executor));
+
+    // Node0: Send a few payments to node1 while the consumer is down:
+    let mut receipts = Vec::new();
+    for i in 0..NUM_PAYMENTS {
+        let route = FriendsRoute {
+            public_keys: vec![node_public_key(0), node_public_key(1)],
+        };
+        let request_id = Uid::from(&[i; UID_LEN]);
+        let invoice_id = InvoiceId::from(&[i; INVOICE_ID_LEN]);
+        let receipt = await!(send_funds0.request_send_funds(
+            request_id.clone(),
+            route,
+            invoice_id,
+            10
+        ))
+        .unwrap();
+        await!(send_funds0.receipt_ack(request_id, receipt.clone())).unwrap();
+        receipts.push(receipt);
+    }
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Close node1:
+    drop(node1_handle);
+    drop(config1);
+    drop(app1);
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Reopen node1:
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        1,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    let _node1_handle = await!(create_node(
+        1,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        trusted_apps,
+        test_executor.clone()
+    ));
+
+    await!(advance_time(10, &mut tick_sender, &test_executor));
+
+    // Bring the consumer up:
+    let mut incoming_conns = await!(sim_net_client.listen(net_address("notify_consumer"))).unwrap();
+
+    await!(advance_time(100, &mut tick_sender, &test_executor));
+
+    let (mut sender, mut receiver) = await!(incoming_conns.next()).unwrap();
+
+    // Every payment should be delivered exactly once, in order:
+    for (i, receipt) in receipts.iter().enumerate() {
+        let data = await!(receiver.next()).unwrap();
+        let incoming_payment = deserialize_incoming_payment(&data).unwrap();
+        assert_eq!(incoming_payment.notification_id, i as u64);
+        assert_eq!(&incoming_payment.receipt, receipt);
+        await!(sender.send(serialize_notification_ack(incoming_payment.notification_id)))
+            .unwrap();
+    }
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Acknowledged notifications are not sent again:
+    assert!(receiver.try_next().is_err());
+}
+
+#[test]
+fn test_payment_notifications() {
+    // let _ = env_logger::init();
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_payment_notifications(test_executor.clone()));
+    assert!(res.is_output());
+}
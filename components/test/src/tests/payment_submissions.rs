@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+use futures::task::SpawnExt;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::{FriendsRoute, PaymentSubmission, ResponseSendFundsResult};
+use timer::create_timer_incoming;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, disconnect_apps, named_relay_address,
+    node_public_key, relay_address, SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+fn all_permissions(index: u8) -> HashMap<u8, AppPermissions> {
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        index,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    trusted_apps
+}
+
+fn route_0_1() -> FriendsRoute {
+    FriendsRoute {
+        public_keys: vec![node_public_key(0), node_public_key(1)],
+    }
+}
+
+/// The submissions with the given request id
+fn find_submissions(submissions: &[PaymentSubmission], request_id: Uid) -> Vec<PaymentSubmission> {
+    submissions
+        .iter()
+        .filter(|submission| submission.request_id == request_id)
+        .cloned()
+        .collect()
+}
+
+fn is_success(submission: &PaymentSubmission) -> bool {
+    match submission.opt_result {
+        Some(ResponseSendFundsResult::Success(_)) => true,
+        _ => false,
+    }
+}
+
+fn is_failure(submission: &PaymentSubmission) -> bool {
+    match submission.opt_result {
+        Some(ResponseSendFundsResult::Failure(_)) => true,
+        _ => false,
+    }
+}
+
+async fn task_payment_submissions(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    for index in 0..2 {
+        sim_db.init_db(index);
+        await!(create_node(
+            index,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            all_permissions(index),
+            test_executor.clone()
+        ))
+        .forget();
+
+        await!(create_relay(
+            index,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+    }
+
+    let app0 = await!(create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone()
+    ))
+    .unwrap();
+    let app1 = await!(create_app(
+        1,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        1,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    let mut config0 = app0.config().unwrap().clone();
+    let mut config1 = app1.config().unwrap().clone();
+    let mut report1 = app1.report().clone();
+    let mut send_funds0 = app0.send_funds().unwrap().clone();
+
+    await!(config0.add_relay(named_relay_address(0))).unwrap();
+    await!(config1.add_relay(named_relay_address(1))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    await!(config0.add_friend(
+        node_public_key(1),
+        vec![relay_address(1)],
+        String::from("node1"),
+        100
+    ))
+    .unwrap();
+    await!(config1.add_friend(
+        node_public_key(0),
+        vec![relay_address(0)],
+        String::from("node0"),
+        -100
+    ))
+    .unwrap();
+
+    await!(config0.enable_friend(node_public_key(1))).unwrap();
+    await!(config1.enable_friend(node_public_key(0))).unwrap();
+
+    await!(report1.wait_for(|mirror| mirror.is_friend_online(&node_public_key(0)), WAIT_TICKS))
+        .unwrap();
+
+    await!(config0.open_friend(node_public_key(1))).unwrap();
+    await!(config1.open_friend(node_public_key(0))).unwrap();
+
+    await!(advance_time(10, &mut tick_sender, &test_executor));
+
+    // Stage 1: The connection is closed before the submission reaches the node.
+    // The payment is never admitted:
+    await!(disconnect_apps(sim_net_client.clone(), 0));
+    let request_id1 = Uid::from(&[1; UID_LEN]);
+    let res = await!(send_funds0.request_send_funds(
+        request_id1,
+        route_0_1(),
+        InvoiceId::from(&[1; INVOICE_ID_LEN]),
+        10
+    ));
+    assert!(res.is_err());
+    drop(send_funds0);
+    drop(app0);
+
+    let app0 = await!(create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone()
+    ))
+    .unwrap();
+    let mut send_funds0 = app0.send_funds().unwrap().clone();
+    let submissions = await!(send_funds0.pending_submissions()).unwrap();
+    assert!(find_submissions(&submissions, request_id1).is_empty());
+
+    // Stage 2: The connection is closed while the submission is in flight.
+    // Whether the payment was admitted or not, it is either listed exactly once together with its
+    // outcome, or it was never sent:
+    let balance_before = await!(report1.mirror())
+        .unwrap()
+        .balance(&node_public_key(0))
+        .unwrap();
+    let request_id2 = Uid::from(&[2; UID_LEN]);
+    let mut c_send_funds0 = send_funds0.clone();
+    let fut_res = test_executor
+        .spawn_with_handle(
+            async move {
+                await!(c_send_funds0.request_send_funds(
+                    request_id2,
+                    route_0_1(),
+                    InvoiceId::from(&[2; INVOICE_ID_LEN]),
+                    10
+                ))
+            },
+        )
+        .unwrap();
+    await!(disconnect_apps(sim_net_client.clone(), 0));
+    let res = await!(fut_res);
+    drop(send_funds0);
+    drop(app0);
+
+    await!(advance_time(10, &mut tick_sender, &test_executor));
+
+    let app0 = await!(create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone()
+    ))
+    .unwrap();
+    let mut send_funds0 = app0.send_funds().unwrap().clone();
+    let submissions = await!(send_funds0.pending_submissions()).unwrap();
+    let found = find_submissions(&submissions, request_id2);
+    assert!(found.len() <= 1);
+    let balance_after = await!(report1.mirror())
+        .unwrap()
+        .balance(&node_public_key(0))
+        .unwrap();
+    if res.is_ok() {
+        assert_eq!(found.len(), 1);
+        assert!(is_success(&found[0]));
+    }
+    if found.iter().any(is_success) {
+        assert_ne!(balance_after, balance_before);
+    } else {
+        assert_eq!(balance_after, balance_before);
+    }
+    for submission in &found {
+        await!(send_funds0.ack_submission(submission.request_id)).unwrap();
+    }
+
+    // Stage 3: The connection is closed after the outcomes were received, but before they were
+    // acknowledged. Both a successful and a failed payment are listed:
+    let request_id3 = Uid::from(&[3; UID_LEN]);
+    await!(send_funds0.request_send_funds(
+        request_id3,
+        route_0_1(),
+        InvoiceId::from(&[3; INVOICE_ID_LEN]),
+        10
+    ))
+    .unwrap();
+    // More than node0 may send to node1:
+    let request_id4 = Uid::from(&[4; UID_LEN]);
+    let res = await!(send_funds0.request_send_funds(
+        request_id4,
+        route_0_1(),
+        InvoiceId::from(&[4; INVOICE_ID_LEN]),
+        1_000
+    ));
+    assert!(res.is_err());
+
+    await!(disconnect_apps(sim_net_client.clone(), 0));
+    drop(send_funds0);
+    drop(app0);
+
+    let app0 = await!(create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone()
+    ))
+    .unwrap();
+    let mut send_funds0 = app0.send_funds().unwrap().clone();
+    let submissions = await!(send_funds0.pending_submissions()).unwrap();
+    assert!(find_submissions(&submissions, request_id2).is_empty());
+    let found = find_submissions(&submissions, request_id3);
+    assert_eq!(found.len(), 1);
+    assert!(is_success(&found[0]));
+    let found = find_submissions(&submissions, request_id4);
+    assert_eq!(found.len(), 1);
+    assert!(is_failure(&found[0]));
+
+    // Acknowledged submissions are not listed again:
+    await!(send_funds0.ack_submission(request_id3)).unwrap();
+    let submissions = await!(send_funds0.pending_submissions()).unwrap();
+    assert!(find_submissions(&submissions, request_id3).is_empty());
+    assert_eq!(find_submissions(&submissions, request_id4).len(), 1);
+
+    await!(send_funds0.ack_submission(request_id4)).unwrap();
+    let submissions = await!(send_funds0.pending_submissions()).unwrap();
+    assert!(submissions.is_empty());
+}
+
+#[test]
+fn test_payment_submissions() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_payment_submissions(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+use futures::task::SpawnExt;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use crypto::identity::compare_public_key;
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::FriendsRoute;
+use proto::report::messages::{PendingPaymentReport, PendingPaymentStageReport};
+use timer::create_timer_incoming;
+
+use funder::CreditCalculator;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
+    relay_address, SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+/// The amount the destination receives
+const DEST_PAYMENT: u128 = 10;
+
+fn all_permissions(index: u8) -> HashMap<u8, AppPermissions> {
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        index,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    trusted_apps
+}
+
+async fn task_pending_payments(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let mut sim_net_client = create_sim_network(&mut test_executor);
+
+    // The friend with the bigger public key is the one that initiates the connection.
+    // The destination has the smallest public key, so that its friend on the route connects to
+    // the destination's relay, and only the last hop of the route goes through that relay:
+    let mut indices = vec![0u8, 1, 2];
+    indices.sort_by(|a, b| compare_public_key(&node_public_key(*a), &node_public_key(*b)));
+    let (dest, mid, source) = (indices[0], indices[1], indices[2]);
+
+    let mut apps = HashMap::new();
+    for &index in &indices {
+        sim_db.init_db(index);
+        await!(create_node(
+            index,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            all_permissions(index),
+            test_executor.clone()
+        ))
+        .forget();
+
+        let app = await!(create_app(
+            index,
+            sim_net_client.clone(),
+            timer_client.clone(),
+            index,
+            test_executor.clone()
+        ))
+        .unwrap();
+        apps.insert(index, app);
+
+        await!(create_relay(
+            index,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+    }
+
+    for &index in &indices {
+        let mut config = apps[&index].config().unwrap().clone();
+        await!(config.add_relay(named_relay_address(index))).unwrap();
+    }
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // source --> mid --> dest. Every node may send 100 credits to the next node on the route:
+    for &(payer, payee) in &[(source, mid), (mid, dest)] {
+        let mut payer_config = apps[&payer].config().unwrap().clone();
+        let mut payee_config = apps[&payee].config().unwrap().clone();
+        await!(payer_config.add_friend(
+            node_public_key(payee),
+            vec![relay_address(payee)],
+            format!("node{}", payee),
+            100
+        ))
+        .unwrap();
+        await!(payee_config.add_friend(
+            node_public_key(payer),
+            vec![relay_address(payer)],
+            format!("node{}", payer),
+            -100
+        ))
+        .unwrap();
+        await!(payer_config.enable_friend(node_public_key(payee))).unwrap();
+        await!(payee_config.enable_friend(node_public_key(payer))).unwrap();
+    }
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    for &(index, friend_index) in &[(source, mid), (mid, source), (mid, dest), (dest, mid)] {
+        let mut report = apps[&index].report().clone();
+        await!(report.wait_for(
+            |mirror| mirror.is_friend_online(&node_public_key(friend_index)),
+            WAIT_TICKS
+        ))
+        .unwrap();
+        let mut config = apps[&index].config().unwrap().clone();
+        await!(config.open_friend(node_public_key(friend_index))).unwrap();
+    }
+
+    await!(advance_time(10, &mut tick_sender, &test_executor));
+
+    // The last hop of the route becomes slow. The connections stay open, so the destination is
+    // still considered online:
+    await!(sim_net_client.hold_conns(relay_address(dest).address, true)).unwrap();
+
+    let route = FriendsRoute {
+        public_keys: vec![
+            node_public_key(source),
+            node_public_key(mid),
+            node_public_key(dest),
+        ],
+    };
+    let request_id = Uid::from(&[1; UID_LEN]);
+    let mut send_funds = apps[&source].send_funds().unwrap().clone();
+    let mut c_send_funds = send_funds.clone();
+    let c_route = route.clone();
+    let fut_receipt = test_executor
+        .spawn_with_handle(
+            async move {
+                await!(c_send_funds.request_send_funds(
+                    request_id,
+                    c_route,
+                    InvoiceId::from(&[1; INVOICE_ID_LEN]),
+                    DEST_PAYMENT
+                ))
+            },
+        )
+        .unwrap();
+
+    // The payment is in flight. The report of the source shows the credits it has frozen against
+    // the first hop of the route:
+    let mut source_report = apps[&source].report().clone();
+    let mirror = await!(source_report.wait_for(
+        |mirror| {
+            let pending_payments: Vec<PendingPaymentReport> = mirror
+                .friend_report(&node_public_key(mid))
+                .map(|friend_report| friend_report.pending_payments.clone())
+                .unwrap_or_default();
+            match pending_payments.as_slice() {
+                [pending_payment] => pending_payment.stage == PendingPaymentStageReport::Sent,
+                _ => false,
+            }
+        },
+        WAIT_TICKS
+    ))
+    .unwrap();
+    let friend_report = mirror.friend_report(&node_public_key(mid)).unwrap();
+    let pending_payment = &friend_report.pending_payments[0];
+    assert_eq!(pending_payment.request_id, request_id);
+    assert_eq!(pending_payment.route, route);
+    assert_eq!(pending_payment.dest_payment, DEST_PAYMENT);
+    let credits_to_freeze = CreditCalculator::new(route.len(), DEST_PAYMENT)
+        .unwrap()
+        .credits_to_freeze(1)
+        .unwrap();
+    assert_eq!(pending_payment.frozen_credits, credits_to_freeze);
+    let queued_tick = pending_payment.opt_queued_tick.unwrap();
+    let signed_tick = pending_payment.opt_signed_tick.unwrap();
+    assert!(signed_tick >= queued_tick);
+    assert!(!mirror.is_payment_completed(&request_id));
+
+    // The last hop recovers, and the payment completes:
+    await!(sim_net_client.hold_conns(relay_address(dest).address, false)).unwrap();
+    let receipt = await!(fut_receipt).unwrap();
+    await!(send_funds.receipt_ack(request_id, receipt)).unwrap();
+
+    // The payment disappears from the report:
+    await!(source_report.wait_for(
+        |mirror| mirror.is_payment_completed(&request_id),
+        WAIT_TICKS
+    ))
+    .unwrap();
+}
+
+#[test]
+fn test_pending_payments() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_pending_payments(test_executor.clone()));
+    assert!(res.is_output());
+}
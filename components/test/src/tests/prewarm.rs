@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::{FriendsRoute, PrewarmFailure};
+use timer::create_timer_incoming;
+
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use node::connect::PrewarmError;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
+    relay_address, SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+async fn task_prewarm_friend(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    await!(create_relay(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    let mut apps = Vec::new();
+    for i in 0..2 {
+        sim_db.init_db(i);
+
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            i,
+            AppPermissions {
+                routes: true,
+                send_funds: true,
+                config: true,
+            },
+        );
+
+        await!(create_node(
+            i,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            trusted_apps,
+            test_executor.clone()
+        ))
+        .forget();
+
+        let mut app = await!(create_app(
+            i,
+            sim_net_client.clone(),
+            timer_client.clone(),
+            i,
+            test_executor.clone()
+        ))
+        .unwrap();
+        await!(app.config().unwrap().add_relay(named_relay_address(0))).unwrap();
+        apps.push(app);
+    }
+
+    // Node0: Add node1 as a friend. Node1 does not know node0 yet, so node1 stays offline:
+    await!(apps[0].config().unwrap().add_friend(
+        node_public_key(1),
+        vec![relay_address(0)],
+        String::from("node1"),
+        0
+    ))
+    .unwrap();
+    await!(apps[0].config().unwrap().enable_friend(node_public_key(1))).unwrap();
+    await!(advance_time(10, &mut tick_sender, &test_executor));
+
+    match await!(apps[0].send_funds().unwrap().prewarm_friend(node_public_key(1))) {
+        Err(PrewarmError::Failure(PrewarmFailure::FriendOffline)) => {}
+        res => panic!("Unexpected prewarm result: {:?}", res),
+    };
+
+    // Pre-warming a node that is not a friend:
+    match await!(apps[0].send_funds().unwrap().prewarm_friend(node_public_key(2))) {
+        Err(PrewarmError::Failure(PrewarmFailure::FriendDoesNotExist)) => {}
+        res => panic!("Unexpected prewarm result: {:?}", res),
+    };
+
+    // Node1: Add node0 as a friend, and let node0 send funds:
+    await!(apps[1].config().unwrap().add_friend(
+        node_public_key(0),
+        vec![relay_address(0)],
+        String::from("node0"),
+        0
+    ))
+    .unwrap();
+    await!(apps[1].config().unwrap().enable_friend(node_public_key(0))).unwrap();
+    await!(apps[1].config().unwrap().open_friend(node_public_key(0))).unwrap();
+    await!(apps[1]
+        .config()
+        .unwrap()
+        .set_friend_remote_max_debt(node_public_key(0), 100))
+    .unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+    await!(apps[0].report().wait_for(
+        |mirror| mirror.is_friend_online(&node_public_key(1)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // Pre-warm the first hop of the route, and then pay:
+    let route = FriendsRoute {
+        public_keys: vec![node_public_key(0), node_public_key(1)],
+    };
+    await!(apps[0].send_funds().unwrap().prewarm_route(&route)).unwrap();
+
+    let request_id = Uid::from(&[0; UID_LEN]);
+    let receipt = await!(apps[0].send_funds().unwrap().request_send_funds(
+        request_id,
+        route,
+        InvoiceId::from(&[0; INVOICE_ID_LEN]),
+        10
+    ))
+    .unwrap();
+    await!(apps[0].send_funds().unwrap().receipt_ack(request_id, receipt)).unwrap();
+
+    // A route without a first hop can not be pre-warmed:
+    let route = FriendsRoute {
+        public_keys: vec![node_public_key(0)],
+    };
+    match await!(apps[0].send_funds().unwrap().prewarm_route(&route)) {
+        Err(PrewarmError::InvalidRoute) => {}
+        res => panic!("Unexpected prewarm result: {:?}", res),
+    };
+}
+
+#[test]
+fn test_prewarm_friend() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_prewarm_friend(test_executor.clone()));
+    assert!(res.is_output());
+}
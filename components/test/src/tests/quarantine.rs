@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::fs;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::AppPermissions;
+use proto::file::ser_string::public_key_to_string;
+use proto::funder::messages::{AddFriend, FriendsRoute};
+use proto::net::messages::NetAddress;
+use timer::create_timer_incoming;
+
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use bin::stmgrlib::{stmgr, ExportQuarantinedCmd, StMgrCmd};
+use database::file_db::FileDb;
+use funder::FunderMutation;
+use node::NodeState;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_quarantine_node, create_relay,
+    named_relay_address, node_public_key, relay_address, SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+fn all_permissions(index: u8) -> HashMap<u8, AppPermissions> {
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        index,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    trusted_apps
+}
+
+/// Create a database for node0, with node1 and node2 as friends.
+/// The stored state of node2 is then overwritten with garbage.
+/// Returns the stored bytes of node2.
+fn create_corrupted_db(sim_db: &SimDb) -> Vec<u8> {
+    let mut node_state = NodeState::<NetAddress>::new(node_public_key(0));
+    for &index in &[1, 2] {
+        node_state
+            .funder_state
+            .mutate(&FunderMutation::AddFriend(AddFriend {
+                friend_public_key: node_public_key(index),
+                relays: vec![relay_address(0)],
+                name: format!("node{}", index),
+                balance: 0,
+            }));
+    }
+    let friend2 = node_state
+        .funder_state
+        .friends
+        .get(&node_public_key(2))
+        .unwrap();
+    let data = bincode::serialize(friend2).unwrap();
+    FileDb::create(sim_db.db_path(0), node_state).unwrap();
+
+    // Overwrite the stored state of node2:
+    let mut serialized = fs::read(sim_db.db_path(0)).unwrap();
+    let pos = serialized
+        .windows(data.len())
+        .position(|window| window == &data[..])
+        .unwrap();
+    for byte in &mut serialized[pos..pos + data.len()] {
+        *byte = 0xff;
+    }
+    fs::write(sim_db.db_path(0), &serialized).unwrap();
+
+    vec![0xff; data.len()]
+}
+
+async fn task_quarantine(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    let corrupted_data = create_corrupted_db(&sim_db);
+    // The database can not be loaded as a whole:
+    assert!(FileDb::<NodeState<NetAddress>>::load(sim_db.db_path(0)).is_err());
+
+    sim_db.init_db(1);
+
+    await!(create_quarantine_node(
+        0,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        all_permissions(0),
+        test_executor.clone()
+    ))
+    .forget();
+
+    await!(create_node(
+        1,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        all_permissions(1),
+        test_executor.clone()
+    ))
+    .forget();
+
+    let mut app0 = await!(create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    let mut app1 = await!(create_app(
+        1,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        1,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    await!(create_relay(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    let mut config0 = app0.config().unwrap().clone();
+    let mut config1 = app1.config().unwrap().clone();
+
+    let mut send_funds0 = app0.send_funds().unwrap().clone();
+
+    let mut report0 = app0.report().clone();
+    let mut report1 = app1.report().clone();
+
+    await!(config0.add_relay(named_relay_address(0))).unwrap();
+    await!(config1.add_relay(named_relay_address(0))).unwrap();
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node0 already has node1 as a friend, loaded from the database:
+    await!(config1.add_friend(
+        node_public_key(0),
+        vec![relay_address(0)],
+        String::from("node0"),
+        0
+    ))
+    .unwrap();
+
+    await!(config0.enable_friend(node_public_key(1))).unwrap();
+    await!(config1.enable_friend(node_public_key(0))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    await!(report0.wait_for(|mirror| mirror.is_friend_online(&node_public_key(1)), WAIT_TICKS))
+        .unwrap();
+    await!(report1.wait_for(|mirror| mirror.is_friend_online(&node_public_key(0)), WAIT_TICKS))
+        .unwrap();
+
+    await!(config0.open_friend(node_public_key(1))).unwrap();
+    await!(config1.open_friend(node_public_key(0))).unwrap();
+    await!(config1.set_friend_remote_max_debt(node_public_key(0), 100)).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node2 is reported as quarantined, and not as a friend:
+    let mirror0 = await!(report0.mirror()).unwrap();
+    let quarantined_friends = mirror0
+        .node_report()
+        .funder_report
+        .quarantined_friends
+        .iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(quarantined_friends, vec![node_public_key(2)]);
+    assert!(mirror0.friend_report(&node_public_key(2)).is_none());
+
+    // Node0: Send 10 credits to node1:
+    let route = FriendsRoute {
+        public_keys: vec![node_public_key(0), node_public_key(1)],
+    };
+    let request_id = Uid::from(&[0x0; UID_LEN]);
+    let invoice_id = InvoiceId::from(&[0; INVOICE_ID_LEN]);
+    let receipt = await!(send_funds0.request_send_funds(
+        request_id.clone(),
+        route,
+        invoice_id,
+        10
+    ))
+    .unwrap();
+    await!(send_funds0.receipt_ack(request_id, receipt)).unwrap();
+
+    // Payments through the quarantined node2 fail:
+    let route = FriendsRoute {
+        public_keys: vec![node_public_key(0), node_public_key(2)],
+    };
+    let request_id = Uid::from(&[0x1; UID_LEN]);
+    let invoice_id = InvoiceId::from(&[1; INVOICE_ID_LEN]);
+    assert!(await!(send_funds0.request_send_funds(request_id, route, invoice_id, 10)).is_err());
+
+    // The original stored state of node2 can be exported:
+    let output = temp_dir.path().join("node2_quarantined");
+    let export_quarantined_cmd = ExportQuarantinedCmd {
+        database: sim_db.db_path(0),
+        friend_public_key: public_key_to_string(&node_public_key(2)),
+        output: output.clone(),
+    };
+    stmgr(StMgrCmd::ExportQuarantined(export_quarantined_cmd)).unwrap();
+    assert_eq!(fs::read(&output).unwrap(), corrupted_data);
+}
+
+#[test]
+fn test_quarantine() {
+    // let _ = env_logger::init();
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_quarantine(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::FriendsRoute;
+use timer::create_timer_incoming;
+
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use node::connect::{BalanceRange, RebalanceConfig, RebalanceError};
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_index_server, create_node, create_relay,
+    named_index_server_address, named_relay_address, node_public_key, relay_address, SimDb,
+    WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+async fn task_rebalance_triangle(mut test_executor: TestExecutor) {
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    let mut apps = Vec::new();
+
+    // Create 3 nodes with apps:
+    for i in 0..3 {
+        sim_db.init_db(i);
+
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            i,
+            AppPermissions {
+                routes: true,
+                send_funds: true,
+                config: true,
+            },
+        );
+
+        await!(create_node(
+            i,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            trusted_apps,
+            test_executor.clone()
+        ))
+        .forget();
+
+        apps.push(
+            await!(create_app(
+                i,
+                sim_net_client.clone(),
+                timer_client.clone(),
+                i,
+                test_executor.clone()
+            ))
+            .unwrap(),
+        );
+    }
+
+    await!(create_relay(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    await!(create_index_server(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        vec![],
+        test_executor.clone()
+    ));
+
+    for app in &mut apps {
+        await!(app.config().unwrap().add_relay(named_relay_address(0))).unwrap();
+        await!(app
+            .config()
+            .unwrap()
+            .add_index_server(named_index_server_address(0)))
+        .unwrap();
+    }
+
+    /*
+     A triangle of friends:
+               0
+              / \
+             1 - 2
+    */
+    for &(i, j) in &[(0, 1), (1, 0), (0, 2), (2, 0), (1, 2), (2, 1)] {
+        let app = &mut apps[i as usize];
+        await!(app.config().unwrap().add_friend(
+            node_public_key(j),
+            vec![relay_address(0)],
+            format!("node{}", j),
+            0
+        ))
+        .unwrap();
+        await!(app.config().unwrap().enable_friend(node_public_key(j))).unwrap();
+        await!(app.config().unwrap().open_friend(node_public_key(j))).unwrap();
+        await!(app
+            .config()
+            .unwrap()
+            .set_friend_remote_max_debt(node_public_key(j), 100))
+        .unwrap();
+    }
+
+    // Wait some time, to let the index server learn about the capacities:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node0 wants to keep its balance against each of its friends between -20 and 20.
+    // The fee budget is enough for a single rebalance along 0 -- 1 -- 2 -- 0 every day:
+    let mut ranges = HashMap::new();
+    ranges.insert(node_public_key(1), BalanceRange { min: -20, max: 20 });
+    ranges.insert(node_public_key(2), BalanceRange { min: -20, max: 20 });
+    let config = RebalanceConfig {
+        ranges,
+        daily_fee_budget: 2,
+    };
+    let mut app_rebalance = apps[0].rebalance(config).unwrap();
+
+    // Node1 pays 30 credits directly to Node0.
+    // Node0's balance against Node1 drifts above the wanted range:
+    let request_id = Uid::from(&[0; UID_LEN]);
+    let route = FriendsRoute {
+        public_keys: vec![node_public_key(1), node_public_key(0)],
+    };
+    let receipt = await!(apps[1].send_funds().unwrap().request_send_funds(
+        request_id.clone(),
+        route.clone(),
+        InvoiceId::from(&[0; INVOICE_ID_LEN]),
+        30
+    ))
+    .unwrap();
+    await!(apps[1].send_funds().unwrap().receipt_ack(request_id, receipt)).unwrap();
+
+    let mirror = await!(apps[0].report().wait_for(
+        |mirror| mirror.balance(&node_public_key(1)) == Some(30),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // Node0 pays itself 10 credits along the cycle, paying one credit to each of Node1 and Node2:
+    let action = await!(app_rebalance.rebalance(&mirror, 0)).unwrap();
+    assert_eq!(
+        action.route.public_keys,
+        vec![
+            node_public_key(0),
+            node_public_key(1),
+            node_public_key(2),
+            node_public_key(0)
+        ]
+    );
+    assert_eq!(action.rebalance_request.amount, 10);
+    assert_eq!(action.fee, 2);
+    assert_eq!(app_rebalance.remaining_fee_budget(0), 0);
+
+    // Both balances are back inside the wanted range:
+    await!(apps[0].report().wait_for(
+        |mirror| {
+            mirror.balance(&node_public_key(1)) == Some(18)
+                && mirror.balance(&node_public_key(2)) == Some(10)
+        },
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // Node1 pays Node0 another 30 credits:
+    let request_id = Uid::from(&[1; UID_LEN]);
+    let receipt = await!(apps[1].send_funds().unwrap().request_send_funds(
+        request_id.clone(),
+        route,
+        InvoiceId::from(&[1; INVOICE_ID_LEN]),
+        30
+    ))
+    .unwrap();
+    await!(apps[1].send_funds().unwrap().receipt_ack(request_id, receipt)).unwrap();
+
+    let mirror = await!(apps[0].report().wait_for(
+        |mirror| mirror.balance(&node_public_key(1)) == Some(48),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // The balance is out of range again, but the fee budget of the day is exhausted:
+    match await!(app_rebalance.rebalance(&mirror, 0)) {
+        Err(RebalanceError::FeeBudgetExceeded) => {}
+        res => panic!("Unexpected rebalance result: {:?}", res),
+    };
+
+    // No payment was made:
+    await!(advance_time(5, &mut tick_sender, &test_executor));
+    let mirror = await!(apps[0].report().mirror()).unwrap();
+    assert_eq!(mirror.balance(&node_public_key(1)), Some(48));
+    assert_eq!(mirror.balance(&node_public_key(2)), Some(10));
+
+    // A new day starts with the full budget.
+    // Node2 only has room for 10 more credits:
+    let action = await!(app_rebalance.rebalance(&mirror, 1)).unwrap();
+    assert_eq!(action.rebalance_request.amount, 10);
+    assert_eq!(action.fee, 2);
+
+    await!(apps[0].report().wait_for(
+        |mirror| {
+            mirror.balance(&node_public_key(1)) == Some(36)
+                && mirror.balance(&node_public_key(2)) == Some(20)
+        },
+        WAIT_TICKS
+    ))
+    .unwrap();
+}
+
+#[test]
+fn test_rebalance_triangle() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_rebalance_triangle(test_executor.clone()));
+    assert!(res.is_output());
+}
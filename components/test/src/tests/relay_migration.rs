@@ -11,32 +11,13 @@ use timer::create_timer_incoming;
 
 use crate::utils::{
     advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
-    relay_address, relay_public_key, SimDb,
+    relay_address, relay_public_key, SimDb, WAIT_TICKS,
 };
 
-use node::connect::AppReport;
-
 use crate::sim_network::create_sim_network;
 
 const TIMER_CHANNEL_LEN: usize = 0;
 
-/// Checks if a friend is online
-/// panics if the friend does not exist.
-async fn is_friend_online(report: &mut AppReport, index: u8) -> bool {
-    let (node_report, mutations_receiver) = await!(report.incoming_reports()).unwrap();
-    drop(mutations_receiver);
-
-    let friend_report = match node_report
-        .funder_report
-        .friends
-        .get(&node_public_key(index))
-    {
-        None => unreachable!(),
-        Some(friend_report) => friend_report,
-    };
-    friend_report.liveness.is_online()
-}
-
 async fn task_relay_migration(mut test_executor: TestExecutor) {
     // Create timer_client:
     let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
@@ -185,8 +166,10 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
 
     await!(advance_time(40, &mut tick_sender, &test_executor));
 
-    assert!(await!(is_friend_online(&mut report0, 1)));
-    assert!(await!(is_friend_online(&mut report1, 0)));
+    await!(report0.wait_for(|mirror| mirror.is_friend_online(&node_public_key(1)), WAIT_TICKS))
+        .unwrap();
+    await!(report1.wait_for(|mirror| mirror.is_friend_online(&node_public_key(0)), WAIT_TICKS))
+        .unwrap();
 
     // Change relays for node0:
     await!(config0.add_relay(named_relay_address(2))).unwrap();
@@ -200,7 +183,8 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
     await!(advance_time(40, &mut tick_sender, &test_executor));
 
     // Node0 should see Node1 as offline:
-    assert!(!await!(is_friend_online(&mut report0, 1)));
+    let mirror0 = await!(report0.mirror()).unwrap();
+    assert!(!mirror0.is_friend_online(&node_public_key(1)));
     // App can not communicate with node1:
     assert!(await!(config1.add_relay(named_relay_address(2))).is_err());
     drop(app1);
@@ -247,8 +231,10 @@ async fn task_relay_migration(mut test_executor: TestExecutor) {
     await!(advance_time(40, &mut tick_sender, &test_executor));
 
     // Node1 should be able to achieve connectivity:
-    assert!(await!(is_friend_online(&mut report0, 1)));
-    assert!(await!(is_friend_online(&mut report1, 0)));
+    await!(report0.wait_for(|mirror| mirror.is_friend_online(&node_public_key(1)), WAIT_TICKS))
+        .unwrap();
+    await!(report1.wait_for(|mirror| mirror.is_friend_online(&node_public_key(0)), WAIT_TICKS))
+        .unwrap();
 }
 
 #[test]
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use crypto::identity::compare_public_key;
+
+use proto::app_server::messages::AppPermissions;
+use timer::create_timer_incoming;
+
+use crate::sim_network::{create_sim_network, SimNetworkClient};
+use crate::utils::{
+    advance_time, create_app, create_mux_node, create_relay, named_relay_address, node_public_key,
+    relay_address, SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+fn all_permissions(index: u8) -> HashMap<u8, AppPermissions> {
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        index,
+        AppPermissions {
+            routes: true,
+            send_funds: true,
+            config: true,
+        },
+    );
+    trusted_apps
+}
+
+/// Wait until the amount of open connections to relay0 is `num_conns`.
+async fn wait_relay_conns<'a>(
+    sim_net_client: &'a mut SimNetworkClient,
+    num_conns: usize,
+    tick_sender: &'a mut mpsc::Sender<()>,
+    test_executor: &'a TestExecutor,
+) {
+    for _ in 0..WAIT_TICKS {
+        if await!(sim_net_client.num_conns(relay_address(0).address)).unwrap() == num_conns {
+            return;
+        }
+        await!(advance_time(1, tick_sender, test_executor));
+    }
+    let cur_num_conns = await!(sim_net_client.num_conns(relay_address(0).address)).unwrap();
+    assert_eq!(cur_num_conns, num_conns);
+}
+
+async fn task_relay_mux(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let mut sim_net_client = create_sim_network(&mut test_executor);
+
+    // The friend with the bigger public key is the one that initiates the connection.
+    // The hub has the biggest public key, so it connects to both of its friends:
+    let mut indices = vec![0u8, 1, 2];
+    indices.sort_by(|a, b| compare_public_key(&node_public_key(*a), &node_public_key(*b)));
+    let hub = indices.pop().unwrap();
+    let spokes = indices;
+
+    let mut configs = HashMap::new();
+    let mut reports = HashMap::new();
+    for &index in spokes.iter().chain(&[hub]) {
+        sim_db.init_db(index);
+        await!(create_mux_node(
+            index,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            all_permissions(index),
+            test_executor.clone()
+        ))
+        .forget();
+
+        let app = await!(create_app(
+            index,
+            sim_net_client.clone(),
+            timer_client.clone(),
+            index,
+            test_executor.clone()
+        ))
+        .unwrap();
+        configs.insert(index, app.config().unwrap().clone());
+        reports.insert(index, app.report().clone());
+    }
+
+    for index in 0..2 {
+        await!(create_relay(
+            index,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+    }
+
+    // Both spokes listen on relay0. The hub listens on relay1:
+    for &spoke in &spokes {
+        await!(configs
+            .get_mut(&spoke)
+            .unwrap()
+            .add_relay(named_relay_address(0)))
+        .unwrap();
+    }
+    await!(configs
+        .get_mut(&hub)
+        .unwrap()
+        .add_relay(named_relay_address(1)))
+    .unwrap();
+
+    // Wait some time:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // spoke <--> hub <--> spoke:
+    for &spoke in &spokes {
+        let hub_config = configs.get_mut(&hub).unwrap();
+        await!(hub_config.add_friend(
+            node_public_key(spoke),
+            vec![relay_address(0)],
+            format!("spoke{}", spoke),
+            0
+        ))
+        .unwrap();
+        await!(hub_config.enable_friend(node_public_key(spoke))).unwrap();
+
+        let spoke_config = configs.get_mut(&spoke).unwrap();
+        await!(spoke_config.add_friend(
+            node_public_key(hub),
+            vec![relay_address(1)],
+            String::from("hub"),
+            0
+        ))
+        .unwrap();
+        await!(spoke_config.enable_friend(node_public_key(hub))).unwrap();
+    }
+
+    // Wait until all the friends are online:
+    for &spoke in &spokes {
+        let hub_report = reports.get_mut(&hub).unwrap();
+        await!(hub_report.wait_for(
+            |mirror| mirror.is_friend_online(&node_public_key(spoke)),
+            WAIT_TICKS
+        ))
+        .unwrap();
+
+        let spoke_report = reports.get_mut(&spoke).unwrap();
+        await!(spoke_report.wait_for(
+            |mirror| mirror.is_friend_online(&node_public_key(hub)),
+            WAIT_TICKS
+        ))
+        .unwrap();
+    }
+
+    // Connections to relay0: A listen connection and an accept connection for every spoke, and
+    // a single connection of the hub, shared by the connections to both spokes:
+    await!(wait_relay_conns(
+        &mut sim_net_client,
+        2 + 2 + 1,
+        &mut tick_sender,
+        &test_executor
+    ));
+
+    // Disconnecting from one spoke keeps the shared connection open:
+    await!(configs
+        .get_mut(&hub)
+        .unwrap()
+        .disable_friend(node_public_key(spokes[0])))
+    .unwrap();
+    await!(wait_relay_conns(
+        &mut sim_net_client,
+        2 + 1 + 1,
+        &mut tick_sender,
+        &test_executor
+    ));
+    let hub_mirror = await!(reports.get_mut(&hub).unwrap().mirror()).unwrap();
+    assert!(hub_mirror.is_friend_online(&node_public_key(spokes[1])));
+
+    // The shared connection is closed together with the last connection that uses it:
+    await!(configs
+        .get_mut(&hub)
+        .unwrap()
+        .disable_friend(node_public_key(spokes[1])))
+    .unwrap();
+    await!(wait_relay_conns(
+        &mut sim_net_client,
+        2,
+        &mut tick_sender,
+        &test_executor
+    ));
+
+    // Connecting again opens a new shared connection:
+    for &spoke in &spokes {
+        await!(configs
+            .get_mut(&hub)
+            .unwrap()
+            .enable_friend(node_public_key(spoke)))
+        .unwrap();
+    }
+    await!(wait_relay_conns(
+        &mut sim_net_client,
+        2 + 2 + 1,
+        &mut tick_sender,
+        &test_executor
+    ));
+}
+
+#[test]
+fn test_relay_mux() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_relay_mux(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use node::connect::RoutePolicy;
+use proto::app_server::messages::AppPermissions;
+use proto::consts::NEUTRAL_SUCCESS_PPM;
+use proto::funder::messages::FriendsRoute;
+use proto::index_server::messages::RouteWithCapacity;
+use timer::create_timer_incoming;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
+    relay_address, SimDb, RELIABILITY_DECAY_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+/// Amount of ticks to wait for a change of friend status to reach the friend
+const SETTLE_TICKS: usize = 10;
+
+/// Amount of warm up payments sent through the unreliable node
+const NUM_WARM_UP_PAYMENTS: u8 = 6;
+
+async fn task_reliability_weighted_route(mut test_executor: TestExecutor) {
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    let mut apps = Vec::new();
+
+    // Create 5 nodes with apps:
+    for i in 0..5 {
+        sim_db.init_db(i);
+
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            i,
+            AppPermissions {
+                routes: true,
+                send_funds: true,
+                config: true,
+            },
+        );
+
+        await!(create_node(
+            i,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            trusted_apps,
+            test_executor.clone()
+        ))
+        .forget();
+
+        apps.push(
+            await!(create_app(
+                i,
+                sim_net_client.clone(),
+                timer_client.clone(),
+                i,
+                test_executor.clone()
+            ))
+            .unwrap(),
+        );
+    }
+
+    await!(create_relay(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    for app in &mut apps {
+        await!(app.config().unwrap().add_relay(named_relay_address(0))).unwrap();
+    }
+
+    /*
+     Two paths from node0 to node3.
+     The path through node1 is cheaper, but node1 fails half of the payments:
+             1
+           /   \
+          0     3
+           \   /
+            2-4
+    */
+    let friend_pairs = [(0, 1), (1, 3), (0, 2), (2, 4), (4, 3)];
+    for &(i, j) in &friend_pairs {
+        for &(a, b) in &[(i, j), (j, i)] {
+            let app = &mut apps[a as usize];
+            await!(app.config().unwrap().add_friend(
+                node_public_key(b),
+                vec![relay_address(0)],
+                format!("node{}", b),
+                0
+            ))
+            .unwrap();
+            await!(app.config().unwrap().enable_friend(node_public_key(b))).unwrap();
+            await!(app.config().unwrap().open_friend(node_public_key(b))).unwrap();
+            await!(app
+                .config()
+                .unwrap()
+                .set_friend_remote_max_debt(node_public_key(b), 100))
+            .unwrap();
+        }
+    }
+
+    // Let the nodes connect to each other:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    let route_unreliable = FriendsRoute {
+        public_keys: vec![node_public_key(0), node_public_key(1), node_public_key(3)],
+    };
+    let route_reliable = FriendsRoute {
+        public_keys: vec![
+            node_public_key(0),
+            node_public_key(2),
+            node_public_key(4),
+            node_public_key(3),
+        ],
+    };
+
+    // Warm up: Node0 pays Node3 through Node1.
+    // Node3 refuses every second payment arriving from Node1:
+    for i in 0..NUM_WARM_UP_PAYMENTS {
+        let should_fail = i % 2 == 0;
+        if should_fail {
+            await!(apps[3].config().unwrap().close_friend(node_public_key(1))).unwrap();
+        } else {
+            await!(apps[3].config().unwrap().open_friend(node_public_key(1))).unwrap();
+        }
+        await!(advance_time(SETTLE_TICKS, &mut tick_sender, &test_executor));
+
+        let request_id = Uid::from(&[i; UID_LEN]);
+        let res = await!(apps[0].send_funds().unwrap().request_send_funds(
+            request_id,
+            route_unreliable.clone(),
+            InvoiceId::from(&[i; INVOICE_ID_LEN]),
+            10
+        ));
+        if should_fail {
+            assert!(res.is_err());
+        } else {
+            let receipt = res.unwrap();
+            await!(apps[0].send_funds().unwrap().receipt_ack(request_id, receipt)).unwrap();
+        }
+    }
+
+    let mirror = await!(apps[0].report().mirror()).unwrap();
+    let reliability_report = mirror.reliability(&node_public_key(1)).unwrap().clone();
+    assert!(reliability_report.success_ppm < NEUTRAL_SUCCESS_PPM);
+    assert!(reliability_report.samples > 0);
+    assert!(reliability_report.opt_latency_ticks.is_some());
+    // We never sent payments through the other path:
+    assert!(mirror.reliability(&node_public_key(2)).is_none());
+
+    // Both routes can carry the payment. The route through node1 has a lower fee:
+    let candidates = vec![
+        RouteWithCapacity {
+            route: route_unreliable.clone(),
+            capacity: 100,
+            meets_request: true,
+        },
+        RouteWithCapacity {
+            route: route_reliable.clone(),
+            capacity: 100,
+            meets_request: true,
+        },
+    ];
+
+    let app_routes = apps[0].routes().unwrap();
+    app_routes.set_reliability(
+        mirror
+            .node_report()
+            .funder_report
+            .reliability
+            .clone()
+            .into_iter()
+            .collect(),
+    );
+    assert_eq!(
+        app_routes.select_route(candidates.clone(), 10, Some(RoutePolicy::CheapestFee)),
+        Some(route_unreliable.clone())
+    );
+    // The clean path is preferred, although it is more expensive:
+    let route = app_routes
+        .select_route(
+            candidates.clone(),
+            10,
+            Some(RoutePolicy::ReliabilityWeighted),
+        )
+        .unwrap();
+    assert_eq!(route, route_reliable);
+
+    let request_id = Uid::from(&[NUM_WARM_UP_PAYMENTS; UID_LEN]);
+    let receipt = await!(apps[0].send_funds().unwrap().request_send_funds(
+        request_id,
+        route,
+        InvoiceId::from(&[NUM_WARM_UP_PAYMENTS; INVOICE_ID_LEN]),
+        10
+    ))
+    .unwrap();
+    await!(apps[0].send_funds().unwrap().receipt_ack(request_id, receipt)).unwrap();
+
+    // While idle, the score of node1 recovers towards neutral:
+    await!(advance_time(RELIABILITY_DECAY_TICKS, &mut tick_sender, &test_executor));
+    let mirror = await!(apps[0].report().mirror()).unwrap();
+    let decayed_report = mirror.reliability(&node_public_key(1)).unwrap();
+    assert!(decayed_report.success_ppm > reliability_report.success_ppm);
+    assert!(decayed_report.samples < reliability_report.samples);
+
+    // Eventually all the recorded outcomes are forgotten:
+    await!(advance_time(
+        RELIABILITY_DECAY_TICKS * 8,
+        &mut tick_sender,
+        &test_executor
+    ));
+    let mirror = await!(apps[0].report().mirror()).unwrap();
+    assert!(mirror.node_report().funder_report.reliability.is_empty());
+
+    // Without any measurements, the cheapest route is chosen again:
+    let app_routes = apps[0].routes().unwrap();
+    app_routes.set_reliability(
+        mirror
+            .node_report()
+            .funder_report
+            .reliability
+            .clone()
+            .into_iter()
+            .collect(),
+    );
+    assert_eq!(
+        app_routes.select_route(candidates, 10, Some(RoutePolicy::ReliabilityWeighted)),
+        Some(route_unreliable)
+    );
+}
+
+#[test]
+fn test_reliability_weighted_route() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_reliability_weighted_route(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -12,7 +12,7 @@ use timer::create_timer_incoming;
 
 use crate::utils::{
     advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
-    relay_address, SimDb,
+    relay_address, SimDb, WAIT_TICKS,
 };
 
 use crate::sim_network::create_sim_network;
@@ -219,30 +219,18 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
     await!(advance_time(40, &mut tick_sender, &test_executor));
 
     // Node0: Channel should be consistent now:
-    let (node_report, _) = await!(report0.incoming_reports()).unwrap();
-    let friend_report = node_report
-        .funder_report
-        .friends
-        .get(&node_public_key(1))
-        .unwrap();
-
-    match &friend_report.channel_status {
-        ChannelStatusReport::Consistent(_) => {}
-        ChannelStatusReport::Inconsistent(_) => unreachable!(),
-    };
+    await!(report0.wait_for(
+        |mirror| mirror.is_channel_consistent(&node_public_key(1)),
+        WAIT_TICKS
+    ))
+    .unwrap();
 
     // Node1: Channel should be consistent now:
-    let (node_report, _) = await!(report1.incoming_reports()).unwrap();
-    let friend_report = node_report
-        .funder_report
-        .friends
-        .get(&node_public_key(0))
-        .unwrap();
-
-    match &friend_report.channel_status {
-        ChannelStatusReport::Consistent(_) => {}
-        ChannelStatusReport::Inconsistent(_) => unreachable!(),
-    };
+    await!(report1.wait_for(
+        |mirror| mirror.is_channel_consistent(&node_public_key(0)),
+        WAIT_TICKS
+    ))
+    .unwrap();
 
     // Let both sides open the channel:
     await!(config0.open_friend(node_public_key(1))).unwrap();
@@ -252,30 +240,217 @@ async fn task_resolve_inconsistency(mut test_executor: TestExecutor) {
 
     // Make sure again that the channel stays consistent:
     // Node0: Channel should be consistent now:
-    let (node_report, _) = await!(report0.incoming_reports()).unwrap();
-    let friend_report = node_report
-        .funder_report
-        .friends
-        .get(&node_public_key(1))
-        .unwrap();
+    await!(report0.wait_for(
+        |mirror| mirror.is_channel_consistent(&node_public_key(1)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // Node1: Channel should be consistent now:
+    await!(report1.wait_for(
+        |mirror| mirror.is_channel_consistent(&node_public_key(0)),
+        WAIT_TICKS
+    ))
+    .unwrap();
+}
+
+async fn task_resolve_inconsistency_restart(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    // Create initial databases:
+    sim_db.init_db(0);
+    sim_db.init_db(1);
+
+    let mut node_handles = Vec::new();
+    let mut apps = Vec::new();
+    for index in 0..2u8 {
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            index,
+            AppPermissions {
+                routes: true,
+                send_funds: true,
+                config: true,
+            },
+        );
+        node_handles.push(await!(create_node(
+            index,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            trusted_apps,
+            test_executor.clone()
+        )));
+
+        apps.push(
+            await!(create_app(
+                index,
+                sim_net_client.clone(),
+                timer_client.clone(),
+                index,
+                test_executor.clone()
+            ))
+            .unwrap(),
+        );
+
+        await!(create_relay(
+            index,
+            timer_client.clone(),
+            sim_net_client.clone(),
+            test_executor.clone()
+        ));
+    }
+    let mut app1 = apps.pop().unwrap();
+    let mut app0 = apps.pop().unwrap();
+    let node1_handle = node_handles.pop().unwrap();
+    let node0_handle = node_handles.pop().unwrap();
+
+    let mut config0 = app0.config().unwrap().clone();
+    let mut config1 = app1.config().unwrap().clone();
+
+    let mut report0 = app0.report().clone();
+
+    // Configure relays:
+    await!(config0.add_relay(named_relay_address(0))).unwrap();
+    await!(config1.add_relay(named_relay_address(1))).unwrap();
 
-    match &friend_report.channel_status {
-        ChannelStatusReport::Consistent(_) => {}
-        ChannelStatusReport::Inconsistent(_) => unreachable!(),
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node0 thinks its balance is 50, while node1 thinks its balance is -100:
+    await!(config0.add_friend(
+        node_public_key(1),
+        vec![relay_address(1)],
+        String::from("node1"),
+        50
+    ))
+    .unwrap();
+    await!(config1.add_friend(
+        node_public_key(0),
+        vec![relay_address(0)],
+        String::from("node0"),
+        -100
+    ))
+    .unwrap();
+
+    await!(config0.enable_friend(node_public_key(1))).unwrap();
+    await!(config1.enable_friend(node_public_key(0))).unwrap();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Node0 should now have the reset terms of node1:
+    let mirror0 = await!(report0.wait_for(
+        |mirror| match mirror.friend_report(&node_public_key(1)) {
+            Some(friend_report) => match &friend_report.channel_status {
+                ChannelStatusReport::Inconsistent(channel_inconsistent_report) => {
+                    channel_inconsistent_report.opt_remote_reset_terms.is_some()
+                }
+                ChannelStatusReport::Consistent(_) => false,
+            },
+            None => false,
+        },
+        WAIT_TICKS
+    ))
+    .unwrap();
+    let friend_report = mirror0.friend_report(&node_public_key(1)).unwrap();
+    let remote_reset_terms = match &friend_report.channel_status {
+        ChannelStatusReport::Inconsistent(channel_inconsistent_report) => {
+            channel_inconsistent_report
+                .opt_remote_reset_terms
+                .clone()
+                .unwrap()
+        }
+        ChannelStatusReport::Consistent(_) => unreachable!(),
     };
+    assert_eq!(remote_reset_terms.balance_for_reset, -100);
 
-    // Node1: Channel should be consistent now:
-    let (node_report, _) = await!(report1.incoming_reports()).unwrap();
-    let friend_report = node_report
-        .funder_report
-        .friends
-        .get(&node_public_key(0))
+    // Close node1, so that the reset move token of node0 can not be sent:
+    drop(node1_handle);
+    drop(config1);
+    drop(app1);
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    let mirror0 = await!(report0.mirror()).unwrap();
+    assert!(!mirror0.is_friend_online(&node_public_key(1)));
+
+    // Node0 agrees to the conditions of node1. The acceptance is persisted, but not sent:
+    await!(config0.reset_friend_channel(node_public_key(1), remote_reset_terms.reset_token))
         .unwrap();
 
-    match &friend_report.channel_status {
-        ChannelStatusReport::Consistent(_) => {}
-        ChannelStatusReport::Inconsistent(_) => unreachable!(),
-    };
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    let mirror0 = await!(report0.mirror()).unwrap();
+    assert!(!mirror0.is_channel_consistent(&node_public_key(1)));
+
+    // Close node0 before it had a chance to send the reset move token:
+    drop(node0_handle);
+    drop(config0);
+    drop(report0);
+    drop(app0);
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // Reopen both nodes, loading their databases:
+    let mut node_handles = Vec::new();
+    let mut apps = Vec::new();
+    for index in 0..2u8 {
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            index,
+            AppPermissions {
+                routes: true,
+                send_funds: true,
+                config: true,
+            },
+        );
+        node_handles.push(await!(create_node(
+            index,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            trusted_apps,
+            test_executor.clone()
+        )));
+
+        apps.push(
+            await!(create_app(
+                index,
+                sim_net_client.clone(),
+                timer_client.clone(),
+                index,
+                test_executor.clone()
+            ))
+            .unwrap(),
+        );
+    }
+    let mut report0 = apps[0].report().clone();
+    let mut report1 = apps[1].report().clone();
+
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    // The channel converges, and the balance for reset is applied exactly once:
+    await!(report0.wait_for(
+        |mirror| mirror.balance(&node_public_key(1)) == Some(100),
+        WAIT_TICKS
+    ))
+    .unwrap();
+    await!(report1.wait_for(
+        |mirror| mirror.balance(&node_public_key(0)) == Some(-100),
+        WAIT_TICKS
+    ))
+    .unwrap();
 }
 
 #[test]
@@ -285,3 +460,11 @@ fn test_resolve_inconsistency() {
     let res = test_executor.run(task_resolve_inconsistency(test_executor.clone()));
     assert!(res.is_output());
 }
+
+#[test]
+fn test_resolve_inconsistency_restart() {
+    // let _ = env_logger::init();
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_resolve_inconsistency_restart(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+use futures::task::SpawnExt;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use proto::app_server::messages::{AppPermissions, SelfTestStage};
+use timer::create_timer_incoming;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{advance_time, create_app, create_node, SimDb};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+async fn task_self_test(mut test_executor: TestExecutor) {
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    sim_db.init_db(0);
+
+    // The app has no permissions at all:
+    let mut trusted_apps = HashMap::new();
+    trusted_apps.insert(
+        0,
+        AppPermissions {
+            routes: false,
+            send_funds: false,
+            config: false,
+        },
+    );
+
+    await!(create_node(
+        0,
+        sim_db.clone(),
+        timer_client.clone(),
+        sim_net_client.clone(),
+        trusted_apps,
+        test_executor.clone()
+    ))
+    .forget();
+
+    let mut app0 = await!(create_app(
+        0,
+        sim_net_client.clone(),
+        timer_client.clone(),
+        0,
+        test_executor.clone()
+    ))
+    .unwrap();
+
+    let mut self_test0 = app0.self_test().clone();
+    let self_test_handle = test_executor
+        .spawn_with_handle(async move { await!(self_test0.run()).unwrap() })
+        .unwrap();
+
+    // Let the node connect to the throwaway peer:
+    await!(advance_time(20, &mut tick_sender, &test_executor));
+
+    let stage_reports = await!(self_test_handle);
+    let stages = stage_reports
+        .iter()
+        .map(|stage_report| stage_report.stage)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        stages,
+        vec![
+            SelfTestStage::Identity,
+            SelfTestStage::Channel,
+            SelfTestStage::Credit,
+            SelfTestStage::PaymentToPeer,
+            SelfTestStage::PaymentFromPeer,
+        ]
+    );
+    assert!(stage_reports.iter().all(|report| report.success));
+
+    // The node's own report is not affected by the self test:
+    let mut report0 = app0.report().clone();
+    let (node_report, _) = await!(report0.incoming_reports()).unwrap();
+    assert!(node_report.funder_report.friends.is_empty());
+}
+
+#[test]
+fn test_self_test() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_self_test(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+
+use tempfile::tempdir;
+
+use common::test_executor::TestExecutor;
+
+use crypto::invoice_id::{InvoiceId, INVOICE_ID_LEN};
+use crypto::uid::{Uid, UID_LEN};
+
+use node::connect::SendFundsError;
+use proto::app_server::messages::AppPermissions;
+use proto::funder::messages::{DustThresholds, FriendsRoute};
+use proto::index_server::messages::RouteWithCapacity;
+use timer::create_timer_incoming;
+
+use crate::sim_network::create_sim_network;
+use crate::utils::{
+    advance_time, create_app, create_node, create_relay, named_relay_address, node_public_key,
+    relay_address, SimDb, WAIT_TICKS,
+};
+
+const TIMER_CHANNEL_LEN: usize = 0;
+
+async fn task_sweep(mut test_executor: TestExecutor) {
+    // Create a temporary directory.
+    // Should be deleted when gets out of scope:
+    let temp_dir = tempdir().unwrap();
+
+    // Create a database manager at the temporary directory:
+    let sim_db = SimDb::new(temp_dir.path().to_path_buf());
+
+    // A network simulator:
+    let sim_net_client = create_sim_network(&mut test_executor);
+
+    // Create timer_client:
+    let (mut tick_sender, tick_receiver) = mpsc::channel(TIMER_CHANNEL_LEN);
+    let timer_client = create_timer_incoming(tick_receiver, test_executor.clone()).unwrap();
+
+    let mut apps = Vec::new();
+
+    // Create 5 nodes with apps:
+    for i in 0..5 {
+        sim_db.init_db(i);
+
+        let mut trusted_apps = HashMap::new();
+        trusted_apps.insert(
+            i,
+            AppPermissions {
+                routes: true,
+                send_funds: true,
+                config: true,
+            },
+        );
+
+        await!(create_node(
+            i,
+            sim_db.clone(),
+            timer_client.clone(),
+            sim_net_client.clone(),
+            trusted_apps,
+            test_executor.clone()
+        ))
+        .forget();
+
+        apps.push(
+            await!(create_app(
+                i,
+                sim_net_client.clone(),
+                timer_client.clone(),
+                i,
+                test_executor.clone()
+            ))
+            .unwrap(),
+        );
+    }
+
+    await!(create_relay(
+        0,
+        timer_client.clone(),
+        sim_net_client.clone(),
+        test_executor.clone()
+    ));
+
+    for app in &mut apps {
+        await!(app.config().unwrap().add_relay(named_relay_address(0))).unwrap();
+    }
+
+    /*
+          1
+         /
+        0
+         \
+          2 -- 3 -- 4
+
+     Node1 lets node0 owe it 100 credits. Node2 lets node0 owe it 50 credits.
+    */
+    let friend_pairs = [(0, 1), (0, 2), (2, 3), (3, 4)];
+    for &(i, j) in &friend_pairs {
+        for &(a, b) in &[(i, j), (j, i)] {
+            let remote_max_debt = match (a, b) {
+                (2, 0) => 50,
+                _ => 100,
+            };
+            let app = &mut apps[a as usize];
+            await!(app.config().unwrap().add_friend(
+                node_public_key(b),
+                vec![relay_address(0)],
+                format!("node{}", b),
+                0
+            ))
+            .unwrap();
+            await!(app.config().unwrap().enable_friend(node_public_key(b))).unwrap();
+            await!(app.config().unwrap().open_friend(node_public_key(b))).unwrap();
+            await!(app
+                .config()
+                .unwrap()
+                .set_friend_remote_max_debt(node_public_key(b), remote_max_debt))
+            .unwrap();
+        }
+    }
+
+    // Let the nodes connect to each other:
+    await!(advance_time(40, &mut tick_sender, &test_executor));
+
+    let mut config0 = apps[0].config().unwrap().clone();
+    let mut send_funds0 = apps[0].send_funds().unwrap().clone();
+    let mut report0 = apps[0].report().clone();
+
+    await!(report0.wait_for(
+        |mirror| {
+            mirror.has_send_capacity(&node_public_key(1), 100)
+                && mirror.has_send_capacity(&node_public_key(2), 50)
+        },
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // A direct payment to a friend has no fees. Everything is sent:
+    let request_id = Uid::from(&[0; UID_LEN]);
+    let (dest_payment, receipt) = await!(send_funds0.request_sweep_funds(
+        request_id,
+        node_public_key(1),
+        None,
+        InvoiceId::from(&[0; INVOICE_ID_LEN])
+    ))
+    .unwrap();
+    assert_eq!(dest_payment, 100);
+    assert_eq!(receipt.dest_payment, 100);
+    await!(send_funds0.receipt_ack(request_id, receipt)).unwrap();
+
+    let mirror0 = await!(report0.mirror()).unwrap();
+    assert_eq!(mirror0.balance(&node_public_key(1)), Some(-100));
+    assert_eq!(mirror0.send_capacity(&node_public_key(1)), 0);
+
+    // Nothing is left to send to node1:
+    let res = await!(send_funds0.request_sweep_funds(
+        Uid::from(&[1; UID_LEN]),
+        node_public_key(1),
+        None,
+        InvoiceId::from(&[1; INVOICE_ID_LEN])
+    ));
+    match res {
+        Err(SendFundsError::NothingToSend) => {}
+        _ => unreachable!(),
+    };
+
+    let route = FriendsRoute {
+        public_keys: vec![
+            node_public_key(0),
+            node_public_key(2),
+            node_public_key(3),
+            node_public_key(4),
+        ],
+    };
+
+    // The route does not lead to the destination:
+    let res = await!(send_funds0.request_sweep_funds(
+        Uid::from(&[2; UID_LEN]),
+        node_public_key(3),
+        Some(RouteWithCapacity {
+            route: route.clone(),
+            capacity: 100,
+            meets_request: true,
+        }),
+        InvoiceId::from(&[2; INVOICE_ID_LEN])
+    ));
+    match res {
+        Err(SendFundsError::InvalidRoute) => {}
+        _ => unreachable!(),
+    };
+
+    // The route can carry a total payment of 30 credits.
+    // Node2 and node3 take one credit each, so node4 gets 28 credits:
+    let request_id = Uid::from(&[3; UID_LEN]);
+    let (dest_payment, receipt) = await!(send_funds0.request_sweep_funds(
+        request_id,
+        node_public_key(4),
+        Some(RouteWithCapacity {
+            route: route.clone(),
+            capacity: 30,
+            meets_request: true,
+        }),
+        InvoiceId::from(&[3; INVOICE_ID_LEN])
+    ))
+    .unwrap();
+    assert_eq!(dest_payment, 28);
+    await!(send_funds0.receipt_ack(request_id, receipt)).unwrap();
+
+    let mirror0 = await!(report0.mirror()).unwrap();
+    assert_eq!(mirror0.balance(&node_public_key(2)), Some(-30));
+
+    // We can now send 20 more credits to node2, so node4 may get 18 credits.
+    // This is below our minimum payment:
+    let dust_thresholds = DustThresholds {
+        min_send_payment: 19,
+        ..DustThresholds::default()
+    };
+    await!(config0.set_dust_thresholds(dust_thresholds.clone())).unwrap();
+    await!(report0.wait_for(
+        |mirror| mirror.node_report().funder_report.dust_thresholds == dust_thresholds,
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    let res = await!(send_funds0.request_sweep_funds(
+        Uid::from(&[4; UID_LEN]),
+        node_public_key(4),
+        Some(RouteWithCapacity {
+            route: route.clone(),
+            capacity: 100,
+            meets_request: true,
+        }),
+        InvoiceId::from(&[4; INVOICE_ID_LEN])
+    ));
+    match res {
+        Err(SendFundsError::NothingToSend) => {}
+        _ => unreachable!(),
+    };
+
+    await!(config0.set_dust_thresholds(DustThresholds::default())).unwrap();
+    await!(report0.wait_for(
+        |mirror| mirror.node_report().funder_report.dust_thresholds == DustThresholds::default(),
+        WAIT_TICKS
+    ))
+    .unwrap();
+
+    // Our capacity to send to node2 is now the binding constraint:
+    let request_id = Uid::from(&[5; UID_LEN]);
+    let (dest_payment, receipt) = await!(send_funds0.request_sweep_funds(
+        request_id,
+        node_public_key(4),
+        Some(RouteWithCapacity {
+            route,
+            capacity: 100,
+            meets_request: true,
+        }),
+        InvoiceId::from(&[5; INVOICE_ID_LEN])
+    ))
+    .unwrap();
+    assert_eq!(dest_payment, 18);
+    await!(send_funds0.receipt_ack(request_id, receipt)).unwrap();
+
+    // We are at the limit of node2:
+    let mirror0 = await!(report0.mirror()).unwrap();
+    assert_eq!(mirror0.balance(&node_public_key(2)), Some(-50));
+    assert_eq!(mirror0.send_capacity(&node_public_key(2)), 0);
+}
+
+#[test]
+fn test_sweep() {
+    let test_executor = TestExecutor::new();
+    let res = test_executor.run(task_sweep(test_executor.clone()));
+    assert!(res.is_output());
+}
@@ -1,7 +1,6 @@
 use std::collections::HashMap;
 
 use futures::channel::mpsc;
-use futures::StreamExt;
 
 use tempfile::tempdir;
 
@@ -17,6 +16,7 @@ use crate::sim_network::create_sim_network;
 use crate::utils::{
     advance_time, create_app, create_index_server, create_node, create_relay,
     named_index_server_address, named_relay_address, node_public_key, relay_address, SimDb,
+    WAIT_TICKS,
 };
 
 const TIMER_CHANNEL_LEN: usize = 0;
@@ -207,42 +207,12 @@ async fn task_two_nodes_payment(mut test_executor: TestExecutor) {
     await!(advance_time(40, &mut tick_sender, &test_executor));
 
     // Node0: Wait until node1 is online:
-    let (mut node_report, mut mutations_receiver) = await!(report0.incoming_reports()).unwrap();
-    loop {
-        let friend_report = match node_report.funder_report.friends.get(&node_public_key(1)) {
-            None => continue,
-            Some(friend_report) => friend_report,
-        };
-        if friend_report.liveness.is_online() {
-            break;
-        }
-
-        // Apply mutations:
-        let mutations = await!(mutations_receiver.next()).unwrap();
-        for mutation in mutations {
-            node_report.mutate(&mutation).unwrap();
-        }
-    }
-    drop(mutations_receiver);
+    await!(report0.wait_for(|mirror| mirror.is_friend_online(&node_public_key(1)), WAIT_TICKS))
+        .unwrap();
 
     // Node1: Wait until node0 is online:
-    let (mut node_report, mut mutations_receiver) = await!(report1.incoming_reports()).unwrap();
-    loop {
-        let friend_report = match node_report.funder_report.friends.get(&node_public_key(0)) {
-            None => continue,
-            Some(friend_report) => friend_report,
-        };
-        if friend_report.liveness.is_online() {
-            break;
-        }
-
-        // Apply mutations:
-        let mutations = await!(mutations_receiver.next()).unwrap();
-        for mutation in mutations {
-            node_report.mutate(&mutation).unwrap();
-        }
-    }
-    drop(mutations_receiver);
+    await!(report1.wait_for(|mirror| mirror.is_friend_online(&node_public_key(0)), WAIT_TICKS))
+        .unwrap();
 
     await!(config0.open_friend(node_public_key(1))).unwrap();
     await!(config1.open_friend(node_public_key(0))).unwrap();
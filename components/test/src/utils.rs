@@ -1,32 +1,52 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 use futures::channel::mpsc;
 use futures::future::RemoteHandle;
 use futures::task::{Spawn, SpawnExt};
-use futures::{future, FutureExt, SinkExt, TryFutureExt};
+use futures::{future, stream, FutureExt, SinkExt, StreamExt, TryFutureExt};
 
-use crypto::identity::{generate_pkcs8_key_pair, Identity, PublicKey, SoftwareEd25519Identity};
+use crypto::identity::{
+    generate_pkcs8_key_pair, Identity, PublicKey, Signature, SoftwareEd25519Identity,
+};
 
 use crypto::crypto_rand::CryptoRandom;
 use crypto::test_utils::DummyRandom;
 
+use common::bounded_cache::CacheLimits;
+use common::conn::{ConnPairVec, FutTransform};
+use common::select_streams::{select_streams, BoxStream};
 use common::test_executor::TestExecutor;
 
 use proto::app_server::messages::{AppPermissions, NamedRelayAddress, RelayAddress};
-use proto::consts::{KEEPALIVE_TICKS, MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, TICKS_TO_REKEY};
-use proto::index_server::messages::NamedIndexServerAddress;
+use proto::consts::{
+    COMPLETED_CACHE_MAX_AGE_TICKS, COMPLETED_CACHE_MAX_ENTRIES, DATABASE_COMPACT_TICKS,
+    FRIEND_PREWARM_TICKS, FRIEND_RELAYS_DAMPING_TICKS, KEEPALIVE_TICKS, MAX_MESSAGES_BEFORE_REKEY,
+    MAX_NODE_RELAYS, MAX_OPERATIONS_IN_BATCH, MIN_OPERATIONS_IN_BATCH,
+    PAYMENT_TIMINGS_CACHE_MAX_AGE_TICKS, PAYMENT_TIMINGS_CACHE_MAX_ENTRIES, PROTOCOL_VERSION,
+    SELF_TEST_STAGE_TICKS, SESSION_RESUME_TICKS, TICKS_TO_REKEY,
+};
+use proto::directory::messages::DirectoryDocument;
+use proto::directory::serialize::serialize_directory_document;
+use proto::directory::signature_buff::create_directory_signature_buffer;
+use proto::file::app::load_trusted_apps;
+use proto::index_server::messages::{FederationAddress, NamedIndexServerAddress};
 use proto::net::messages::NetAddress;
 
 use identity::{create_identity, IdentityClient};
 
+use funder::SlowEventLimits;
 use node::connect::{node_connect, NodeConnection};
 use node::{net_node, NodeConfig, NodeState};
 
 use database::file_db::FileDb;
+use database::AtomicDb;
 
 use index_server::net_index_server;
 use relay::net_relay_server;
+use secure_channel::SecureChannel;
+use version::VersionPrefix;
 
 use timer::TimerClient;
 
@@ -49,6 +69,22 @@ const CONN_TIMEOUT_TICKS: usize = 0x8;
 /// Maximum amount of concurrent applications
 /// going through the incoming connection transform at the same time
 const MAX_CONCURRENT_INCOMING_APPS: usize = 0x8;
+/// Amount of incoming messages from a friend that may wait for processing
+const FRIEND_INCOMING_QUEUE_LEN: usize = 0x4;
+/// Amount of ticks between two fetches of the directory document
+const DIRECTORY_FETCH_TICKS: usize = 0x10;
+/// Amount of ticks after which recorded payment outcomes of remote nodes lose half of their weight
+pub const RELIABILITY_DECAY_TICKS: usize = 0x40;
+/// Amount of ticks between two reloads of the trusted apps
+const TRUSTED_APPS_RELOAD_TICKS: usize = 0x10;
+/// Amount of ticks between two comparisons of the capacities sent to the index server against
+/// the current capacities of our friends
+const INDEX_RECONCILE_TICKS: usize = 0x20;
+/// Reported deadlines of friends are only updated when they change by at least this amount of
+/// ticks
+const DEADLINES_GRANULARITY_TICKS: usize = 0x4;
+/// Maximum amount of ticks to wait for a node to reach an expected state
+pub const WAIT_TICKS: usize = 100;
 
 /*
 // Based on:
@@ -118,6 +154,17 @@ fn get_relay_identity(index: u8) -> impl Identity {
     gen_identity(&rng)
 }
 
+fn get_directory_identity(index: u8) -> impl Identity {
+    let rng = DummyRandom::new(&[0x13, 0x3a, index]);
+    gen_identity(&rng)
+}
+
+/// Documents of a directory are signed using a separate key.
+fn get_directory_signing_identity(index: u8) -> impl Identity {
+    let rng = DummyRandom::new(&[0x13, 0x3b, index]);
+    gen_identity(&rng)
+}
+
 fn default_node_config() -> NodeConfig {
     NodeConfig {
         /// Memory allocated to a channel in memory (Used to connect two components)
@@ -128,12 +175,22 @@ fn default_node_config() -> NodeConfig {
         keepalive_ticks: KEEPALIVE_TICKS,
         /// Amount of ticks to wait until the next rekeying (Channel encryption)
         ticks_to_rekey: TICKS_TO_REKEY,
+        /// Amount of messages sent over a relay or friend secure channel before rekeying
+        max_messages_before_rekey: Some(MAX_MESSAGES_BEFORE_REKEY),
+        /// Amount of ticks a closed secure channel with a friend may be resumed, without a full
+        /// exchange
+        session_resume_ticks: SESSION_RESUME_TICKS,
         /// Maximum amount of encryption set ups (diffie hellman) that we allow to occur at the same
         /// time.
         max_concurrent_encrypt: MAX_CONCURRENT_ENCRYPT,
         /// The amount of ticks we are willing to wait until a connection is established (Through
         /// the relay)
         conn_timeout_ticks: CONN_TIMEOUT_TICKS,
+        /// Every connection to a friend uses a separate connection to the relay. See
+        /// `create_mux_node()`
+        relay_mux: false,
+        /// Minimum amount of operations in one move token message
+        min_operations_in_batch: MIN_OPERATIONS_IN_BATCH,
         /// Maximum amount of operations in one move token message
         max_operations_in_batch: MAX_OPERATIONS_IN_BATCH,
         /// The size we allocate for the user send funds requests queue.
@@ -142,11 +199,59 @@ fn default_node_config() -> NodeConfig {
         max_open_index_client_requests: MAX_OPEN_INDEX_CLIENT_REQUESTS,
         /// Maximum amount of relays a node may use.
         max_node_relays: MAX_NODE_RELAYS,
+        /// Minimum amount of ticks between two applied changes of a friend's relays
+        friend_relays_damping_ticks: FRIEND_RELAYS_DAMPING_TICKS,
+        /// Minimum amount of ticks between two token requests of pre-warms
+        friend_prewarm_ticks: FRIEND_PREWARM_TICKS,
         /// Maximum amount of incoming app connections we set up at the same time
         max_concurrent_incoming_apps: MAX_CONCURRENT_INCOMING_APPS,
+        /// Amount of ticks between two periodic compactions of the database
+        database_compact_ticks: DATABASE_COMPACT_TICKS,
+        /// Maximum amount of ticks a single stage of a self test may take
+        self_test_stage_ticks: SELF_TEST_STAGE_TICKS,
+        /// Amount of incoming messages from a friend that may wait for processing
+        friend_incoming_queue_len: FRIEND_INCOMING_QUEUE_LEN,
+        /// Amount of ticks between two fetches of the directory document
+        directory_fetch_ticks: DIRECTORY_FETCH_TICKS,
+        /// Amount of ticks after which recorded payment outcomes of remote nodes lose half of
+        /// their weight
+        reliability_decay_ticks: RELIABILITY_DECAY_TICKS,
+        /// Reported deadlines of friends are only updated when they change by at least this
+        /// amount of ticks
+        deadlines_granularity_ticks: DEADLINES_GRANULARITY_TICKS,
+        /// Limits for the recently completed requests remembered for every friend
+        completed_cache_limits: CacheLimits {
+            max_entries: COMPLETED_CACHE_MAX_ENTRIES,
+            max_age_ticks: COMPLETED_CACHE_MAX_AGE_TICKS,
+        },
+        /// Limits for the start ticks of pending payments we originate
+        payment_timings_cache_limits: CacheLimits {
+            max_entries: PAYMENT_TIMINGS_CACHE_MAX_ENTRIES,
+            max_age_ticks: PAYMENT_TIMINGS_CACHE_MAX_AGE_TICKS,
+        },
+        /// Amount of ticks between two reloads of the trusted apps
+        trusted_apps_reload_ticks: TRUSTED_APPS_RELOAD_TICKS,
+        /// Amount of ticks between two comparisons of the capacities sent to the index server
+        /// against the current capacities of our friends
+        index_reconcile_ticks: INDEX_RECONCILE_TICKS,
+        /// Route requests always reach the index server, so that tests observe capacity changes
+        /// of remote nodes immediately
+        index_route_cache_limits: CacheLimits {
+            max_entries: 0,
+            max_age_ticks: 0,
+        },
+        slow_funder_event_limits: SlowEventLimits::default(),
     }
 }
 
+/// Applications that connect to a node from outside of the simulated network.
+pub struct ExternalApps {
+    /// Incoming connections of the applications (For example, from a TCP listener)
+    pub incoming_raw_conns: BoxStream<'static, ConnPairVec>,
+    /// Directory of trusted applications files. Reloaded periodically, like in stnode
+    pub trusted_dir: PathBuf,
+}
+
 #[derive(Clone)]
 pub struct SimDb {
     temp_dir_path: PathBuf,
@@ -157,24 +262,35 @@ impl SimDb {
         SimDb { temp_dir_path }
     }
 
+    /// Path of the database file of a node
+    pub fn db_path(&self, index: u8) -> PathBuf {
+        self.temp_dir_path.join(format!("db_{}", index))
+    }
+
     /// Create an empty node database
     pub fn init_db(&self, index: u8) -> FileDb<NodeState<NetAddress>> {
         let identity = get_node_identity(index);
         let local_public_key = identity.get_public_key();
 
         // Create a new database file:
-        let db_path_buf = self.temp_dir_path.join(format!("db_{}", index));
         let initial_state = NodeState::<NetAddress>::new(local_public_key);
-        FileDb::create(db_path_buf, initial_state).unwrap()
+        FileDb::create(self.db_path(index), initial_state).unwrap()
     }
 
     /// Load a database. The database should already exist,
     /// otherwise a panic happens.
     pub fn load_db(&self, index: u8) -> FileDb<NodeState<NetAddress>> {
-        let db_path_buf = self.temp_dir_path.join(format!("db_{}", index));
-
         // Load database from file:
-        FileDb::<NodeState<NetAddress>>::load(db_path_buf).unwrap()
+        FileDb::<NodeState<NetAddress>>::load(self.db_path(index)).unwrap()
+    }
+
+    /// Load a database, moving corrupted friends into quarantine.
+    /// The quarantine is stored back into the database right away.
+    pub fn load_db_quarantine(&self, index: u8) -> FileDb<NodeState<NetAddress>> {
+        let deserialize = NodeState::<NetAddress>::deserialize_quarantine;
+        let mut atomic_db = FileDb::load_with(self.db_path(index), deserialize).unwrap();
+        atomic_db.mutate_db(&[]).unwrap();
+        atomic_db
     }
 }
 
@@ -182,6 +298,10 @@ fn listen_node_address(index: u8) -> NetAddress {
     net_address(&format!("node_{}", index))
 }
 
+fn listen_node_direct_address(index: u8) -> NetAddress {
+    net_address(&format!("node_direct_{}", index))
+}
+
 fn listen_index_server_client_address(index: u8) -> NetAddress {
     net_address(&format!("index_server_client_{}", index))
 }
@@ -194,6 +314,10 @@ fn listen_relay_address(index: u8) -> NetAddress {
     net_address(&format!("relay_{}", index))
 }
 
+pub fn directory_address(index: u8) -> NetAddress {
+    net_address(&format!("directory_{}", index))
+}
+
 pub fn named_relay_address(index: u8) -> NamedRelayAddress {
     NamedRelayAddress {
         public_key: get_relay_identity(index).get_public_key(),
@@ -209,6 +333,23 @@ pub fn relay_address(index: u8) -> RelayAddress {
     }
 }
 
+/// The direct address of a node, advertised as a relay that carries the node's own public key.
+/// Only reachable for nodes created using `create_direct_node()`.
+pub fn named_direct_address(index: u8) -> NamedRelayAddress {
+    NamedRelayAddress {
+        public_key: node_public_key(index),
+        address: listen_node_direct_address(index),
+        name: format!("named_direct_{}", index),
+    }
+}
+
+pub fn direct_address(index: u8) -> RelayAddress {
+    RelayAddress {
+        public_key: node_public_key(index),
+        address: listen_node_direct_address(index),
+    }
+}
+
 pub fn named_index_server_address(index: u8) -> NamedIndexServerAddress {
     NamedIndexServerAddress {
         public_key: get_index_server_identity(index).get_public_key(),
@@ -225,6 +366,36 @@ pub fn relay_public_key(index: u8) -> PublicKey {
     get_relay_identity(index).get_public_key()
 }
 
+pub fn index_server_public_key(index: u8) -> PublicKey {
+    get_index_server_identity(index).get_public_key()
+}
+
+pub fn directory_public_key(index: u8) -> PublicKey {
+    get_directory_identity(index).get_public_key()
+}
+
+pub fn directory_signing_public_key(index: u8) -> PublicKey {
+    get_directory_signing_identity(index).get_public_key()
+}
+
+/// Create a directory document, signed using the signing key of the directory `index`.
+pub fn signed_directory_document(
+    index: u8,
+    version: u64,
+    relays: Vec<NamedRelayAddress>,
+    index_servers: Vec<NamedIndexServerAddress>,
+) -> DirectoryDocument {
+    let mut document = DirectoryDocument {
+        version,
+        relays,
+        index_servers,
+        signature: Signature::zero(),
+    };
+    let signature_buff = create_directory_signature_buffer(&document);
+    document.signature = get_directory_signing_identity(index).sign(&signature_buff);
+    document
+}
+
 pub async fn create_app<S>(
     index: u8,
     sim_network_client: SimNetworkClient,
@@ -253,12 +424,148 @@ where
     .ok()
 }
 
+/// Close the connections of all the apps connected to a node, as if the network failed.
+pub async fn disconnect_apps(mut sim_network_client: SimNetworkClient, node_index: u8) {
+    await!(sim_network_client.close_conns(listen_node_address(node_index))).unwrap();
+}
+
 pub async fn create_node<S>(
     index: u8,
     sim_db: SimDb,
     timer_client: TimerClient,
+    sim_network_client: SimNetworkClient,
+    trusted_apps: HashMap<u8, AppPermissions>,
+    spawner: S,
+) -> RemoteHandle<()>
+where
+    S: Spawn + Send + Sync + Clone + 'static,
+{
+    await!(spawn_node(
+        index,
+        sim_db.load_db(index),
+        timer_client,
+        sim_network_client,
+        trusted_apps,
+        false,
+        false,
+        None,
+        spawner
+    ))
+}
+
+/// Create a node, moving friends that are found corrupted in its database into quarantine.
+/// See `SimDb::load_db_quarantine()`.
+pub async fn create_quarantine_node<S>(
+    index: u8,
+    sim_db: SimDb,
+    timer_client: TimerClient,
+    sim_network_client: SimNetworkClient,
+    trusted_apps: HashMap<u8, AppPermissions>,
+    spawner: S,
+) -> RemoteHandle<()>
+where
+    S: Spawn + Send + Sync + Clone + 'static,
+{
+    await!(spawn_node(
+        index,
+        sim_db.load_db_quarantine(index),
+        timer_client,
+        sim_network_client,
+        trusted_apps,
+        false,
+        false,
+        None,
+        spawner
+    ))
+}
+
+/// Create a node that also listens for direct connections from friends.
+/// See `named_direct_address()`.
+pub async fn create_direct_node<S>(
+    index: u8,
+    sim_db: SimDb,
+    timer_client: TimerClient,
+    sim_network_client: SimNetworkClient,
+    trusted_apps: HashMap<u8, AppPermissions>,
+    spawner: S,
+) -> RemoteHandle<()>
+where
+    S: Spawn + Send + Sync + Clone + 'static,
+{
+    await!(spawn_node(
+        index,
+        sim_db.load_db(index),
+        timer_client,
+        sim_network_client,
+        trusted_apps,
+        true,
+        false,
+        None,
+        spawner
+    ))
+}
+
+/// Create a node that connects to friends through the same relay over a single multiplexed
+/// connection to the relay.
+pub async fn create_mux_node<S>(
+    index: u8,
+    sim_db: SimDb,
+    timer_client: TimerClient,
+    sim_network_client: SimNetworkClient,
+    trusted_apps: HashMap<u8, AppPermissions>,
+    spawner: S,
+) -> RemoteHandle<()>
+where
+    S: Spawn + Send + Sync + Clone + 'static,
+{
+    await!(spawn_node(
+        index,
+        sim_db.load_db(index),
+        timer_client,
+        sim_network_client,
+        trusted_apps,
+        false,
+        true,
+        None,
+        spawner
+    ))
+}
+
+/// Create a node that also accepts applications from outside of the simulated network.
+pub async fn create_exposed_node<S>(
+    index: u8,
+    sim_db: SimDb,
+    timer_client: TimerClient,
+    sim_network_client: SimNetworkClient,
+    trusted_apps: HashMap<u8, AppPermissions>,
+    external_apps: ExternalApps,
+    spawner: S,
+) -> RemoteHandle<()>
+where
+    S: Spawn + Send + Sync + Clone + 'static,
+{
+    await!(spawn_node(
+        index,
+        sim_db.load_db(index),
+        timer_client,
+        sim_network_client,
+        trusted_apps,
+        false,
+        false,
+        Some(external_apps),
+        spawner
+    ))
+}
+
+async fn spawn_node<S>(
+    index: u8,
+    atomic_db: FileDb<NodeState<NetAddress>>,
+    timer_client: TimerClient,
     mut sim_network_client: SimNetworkClient,
     trusted_apps: HashMap<u8, AppPermissions>,
+    listen_direct: bool,
+    relay_mux: bool,
+    opt_external_apps: Option<ExternalApps>,
     mut spawner: S,
 ) -> RemoteHandle<()>
 where
@@ -268,26 +575,52 @@ where
     let identity_client = create_identity_client(identity, spawner.clone());
     let listen_address = listen_node_address(index);
     let incoming_app_raw_conns = await!(sim_network_client.listen(listen_address)).unwrap();
+    let (incoming_app_raw_conns, opt_trusted_dir) = match opt_external_apps {
+        Some(external_apps) => (
+            select_streams![incoming_app_raw_conns, external_apps.incoming_raw_conns],
+            Some(external_apps.trusted_dir),
+        ),
+        None => (select_streams![incoming_app_raw_conns], None),
+    };
+
+    let incoming_direct_raw_conns: BoxStream<'static, ConnPairVec> = if listen_direct {
+        let listen_direct_address = listen_node_direct_address(index);
+        Box::pin(await!(sim_network_client.listen(listen_direct_address)).unwrap())
+    } else {
+        Box::pin(stream::empty())
+    };
 
     // Translate application index to application public key:
     let trusted_apps = trusted_apps
         .into_iter()
         .map(|(index, app_permissions)| (get_app_identity(index).get_public_key(), app_permissions))
         .collect::<HashMap<_, _>>();
-    let get_trusted_apps = move || Some(trusted_apps.clone());
+    let get_trusted_apps = move || {
+        let mut trusted_apps = trusted_apps.clone();
+        if let Some(trusted_dir) = &opt_trusted_dir {
+            for trusted_app in load_trusted_apps(trusted_dir).ok()? {
+                trusted_apps.insert(trusted_app.public_key, trusted_app.permissions);
+            }
+        }
+        Some(trusted_apps)
+    };
+
+    let mut node_config = default_node_config();
+    node_config.relay_mux = relay_mux;
 
     let rng = DummyRandom::new(&[0xff, 0x13, 0x37, index]);
     // Note: we use the same spawner for testing purposes.
     // Simulating the passage of time becomes more difficult if our code uses a few different executors.
     let net_node_fut = net_node(
         incoming_app_raw_conns,
+        incoming_direct_raw_conns,
         sim_network_client,
         timer_client,
         identity_client,
         rng,
-        default_node_config(),
+        node_config,
         get_trusted_apps,
-        sim_db.load_db(index),
+        atomic_db,
         spawner.clone(), // trusted_apps_spawner
         spawner.clone(), // database_spawner
         spawner.clone(),
@@ -303,31 +636,88 @@ pub async fn create_index_server<S>(
     timer_client: TimerClient,
     mut sim_network_client: SimNetworkClient,
     trusted_servers: Vec<u8>,
-    mut spawner: S,
+    spawner: S,
 ) where
     S: Spawn + Send + Sync + Clone + 'static,
 {
-    let identity = get_index_server_identity(index);
-    let identity_client = create_identity_client(identity, spawner.clone());
-    let client_listen_address = listen_index_server_client_address(index);
     let server_listen_address = listen_index_server_server_address(index);
+    let incoming_server_raw_conns: BoxStream<'static, ConnPairVec> =
+        Box::pin(await!(sim_network_client.listen(server_listen_address)).unwrap());
 
-    let incoming_client_raw_conns =
-        await!(sim_network_client.listen(client_listen_address)).unwrap();
-    let incoming_server_raw_conns =
-        await!(sim_network_client.listen(server_listen_address)).unwrap();
+    // Translate index server index into a map of public_key -> FederationAddress
+    let trusted_servers = trusted_servers
+        .into_iter()
+        .map(|index| {
+            (
+                get_index_server_identity(index).get_public_key(),
+                FederationAddress::Direct(listen_index_server_server_address(index)),
+            )
+        })
+        .collect::<HashMap<_, _>>();
 
-    // Translate index server index into a map of public_key -> NetAddress
+    await!(spawn_index_server(
+        index,
+        timer_client,
+        sim_network_client,
+        incoming_server_raw_conns,
+        trusted_servers,
+        Vec::new(),
+        spawner
+    ));
+}
+
+/// Create an index server that communicates with its trusted servers only through a relay.
+/// Clients still connect to the index server directly.
+pub async fn create_relay_index_server<S>(
+    index: u8,
+    timer_client: TimerClient,
+    sim_network_client: SimNetworkClient,
+    trusted_servers: Vec<u8>,
+    relay_index: u8,
+    spawner: S,
+) where
+    S: Spawn + Send + Sync + Clone + 'static,
+{
+    // Translate index server index into a map of public_key -> FederationAddress
     let trusted_servers = trusted_servers
         .into_iter()
         .map(|index| {
             (
                 get_index_server_identity(index).get_public_key(),
-                listen_index_server_server_address(index),
+                FederationAddress::Relay(relay_address(relay_index)),
             )
         })
         .collect::<HashMap<_, _>>();
 
+    await!(spawn_index_server(
+        index,
+        timer_client,
+        sim_network_client,
+        Box::pin(stream::empty()),
+        trusted_servers,
+        vec![relay_address(relay_index)],
+        spawner
+    ));
+}
+
+async fn spawn_index_server<S>(
+    index: u8,
+    timer_client: TimerClient,
+    mut sim_network_client: SimNetworkClient,
+    incoming_server_raw_conns: BoxStream<'static, ConnPairVec>,
+    trusted_servers: HashMap<PublicKey, FederationAddress>,
+    listen_relays: Vec<RelayAddress>,
+    mut spawner: S,
+) where
+    S: Spawn + Send + Sync + Clone + 'static,
+{
+    let identity = get_index_server_identity(index);
+    let identity_client = create_identity_client(identity, spawner.clone());
+    let client_listen_address = listen_index_server_client_address(index);
+
+    let incoming_client_raw_conns =
+        await!(sim_network_client.listen(client_listen_address)).unwrap();
+
     let rng = DummyRandom::new(&[0xff, 0x13, 0x38, index]);
     // We use the same spawner for both required spawners.
     // We do this to make it easier to simulate the passage of time in tests.
@@ -339,8 +729,10 @@ pub async fn create_index_server<S>(
         timer_client,
         rng,
         trusted_servers,
+        listen_relays,
         MAX_CONCURRENT_ENCRYPT,
         BACKOFF_TICKS,
+        CONN_TIMEOUT_TICKS,
         spawner.clone(),
         spawner.clone(),
     )
@@ -390,3 +782,82 @@ pub async fn advance_time<'a>(
         await!(test_executor.wait());
     }
 }
+
+/// A simulated directory server.
+/// Sends the currently published document to every incoming connection.
+#[derive(Clone)]
+pub struct SimDirectory {
+    opt_document: Arc<Mutex<Option<DirectoryDocument>>>,
+}
+
+impl SimDirectory {
+    pub fn publish(&self, document: DirectoryDocument) {
+        *self.opt_document.lock().unwrap() = Some(document);
+    }
+}
+
+pub async fn create_directory<S>(
+    index: u8,
+    timer_client: TimerClient,
+    mut sim_network_client: SimNetworkClient,
+    mut spawner: S,
+) -> SimDirectory
+where
+    S: Spawn + Send + Sync + Clone + 'static,
+{
+    let identity = get_directory_identity(index);
+    let identity_client = create_identity_client(identity, spawner.clone());
+
+    let mut incoming_raw_conns =
+        await!(sim_network_client.listen(directory_address(index))).unwrap();
+
+    let rng = DummyRandom::new(&[0xff, 0x13, 0x3a, index]);
+    let mut version_transform = VersionPrefix::new(PROTOCOL_VERSION, spawner.clone());
+    let encrypt_transform = SecureChannel::new(
+        identity_client,
+        rng,
+        timer_client,
+        TICKS_TO_REKEY,
+        None, // max_messages_before_rekey
+        0,    // incoming_queue_len
+        None, // opt_resume_ticks
+        spawner.clone(),
+    );
+
+    let sim_directory = SimDirectory {
+        opt_document: Arc::new(Mutex::new(None)),
+    };
+
+    let c_sim_directory = sim_directory.clone();
+    let mut c_spawner = spawner.clone();
+    let directory_fut = async move {
+        while let Some(raw_conn) = await!(incoming_raw_conns.next()) {
+            let conn_pair = version_transform.spawn_prefix(raw_conn);
+            let opt_data = c_sim_directory
+                .opt_document
+                .lock()
+                .unwrap()
+                .as_ref()
+                .map(serialize_directory_document);
+            let mut c_encrypt_transform = encrypt_transform.clone();
+            let serve_fut = async move {
+                let (_public_key, (mut sender, mut receiver)) =
+                    match await!(c_encrypt_transform.transform((None, conn_pair))) {
+                        Some(enc_conn) => enc_conn,
+                        None => return,
+                    };
+                if let Some(data) = opt_data {
+                    if await!(sender.send(data)).is_err() {
+                        return;
+                    }
+                }
+                // Keep the connection open until the node closes it:
+                while await!(receiver.next()).is_some() {}
+            };
+            c_spawner.spawn(serve_fut).unwrap();
+        }
+    };
+
+    spawner.spawn(directory_fut).unwrap();
+    sim_directory
+}
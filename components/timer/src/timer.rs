@@ -5,6 +5,17 @@
 //! The timer is based on broadcast model. It sends time tick to all clients
 //! periodically.
 //!
+//! ## Bursts
+//!
+//! After the host is suspended and resumed (Or when the timer is delayed for any other reason),
+//! many ticks may pass at once. Every tick event carries the amount of ticks that have elapsed
+//! since the previous event. Ticks that are ready at the same time are coalesced into a single
+//! event, so that clients never have to handle a burst of events.
+//!
+//! Elapsed ticks are measured from the time the timer was created. After a stall, the interval
+//! emits all the missed ticks back to back. Those catch-up ticks were already measured, and are
+//! dropped.
+//!
 //! ## The Timer Message Format
 //!
 //! ## Details
@@ -30,11 +41,18 @@ use common::select_streams::{select_streams, BoxStream};
 use futures::channel::{mpsc, oneshot};
 use futures::prelude::*;
 use futures::stream;
-use futures::task::{Spawn, SpawnExt};
-use std::time::Duration;
-
-#[derive(Debug, Eq, PartialEq)]
-pub struct TimerTick;
+use futures::task::{Spawn, SpawnExt, Waker};
+use futures::Poll;
+use std::cmp;
+use std::convert::TryFrom;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct TimerTick {
+    /// Amount of ticks that have elapsed since the previous tick event. At least 1.
+    pub ticks_elapsed: u64,
+}
 
 #[derive(Debug)]
 pub enum TimerError {
@@ -84,9 +102,92 @@ impl TimerClient {
     }
 }
 
+/// Coalesces all the ticks counts that are ready at the same time into a single count.
+struct CoalesceTicks<M> {
+    incoming: M,
+    done: bool,
+}
+
+impl<M> CoalesceTicks<M> {
+    fn new(incoming: M) -> Self {
+        CoalesceTicks {
+            incoming,
+            done: false,
+        }
+    }
+}
+
+impl<M> Stream for CoalesceTicks<M>
+where
+    M: Stream<Item = u64> + std::marker::Unpin,
+{
+    type Item = u64;
+
+    fn poll_next(mut self: Pin<&mut Self>, waker: &Waker) -> Poll<Option<u64>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        let mut opt_ticks: Option<u64> = None;
+        loop {
+            match self.incoming.poll_next_unpin(waker) {
+                Poll::Ready(Some(ticks)) => {
+                    opt_ticks = Some(opt_ticks.unwrap_or(0).saturating_add(ticks));
+                }
+                Poll::Ready(None) => {
+                    self.done = true;
+                    return Poll::Ready(opt_ticks);
+                }
+                Poll::Pending => {
+                    return match opt_ticks {
+                        Some(ticks) => Poll::Ready(Some(ticks)),
+                        None => Poll::Pending,
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Amount of ticks of length `dur` in `elapsed`, rounded to the nearest tick.
+fn elapsed_ticks(elapsed: Duration, dur: Duration) -> u64 {
+    let dur_nanos = cmp::max(dur.as_nanos(), 1);
+    let ticks = elapsed.as_nanos().saturating_add(dur_nanos / 2) / dur_nanos;
+    u64::try_from(ticks).unwrap_or(u64::max_value())
+}
+
+/// Counts the ticks that have elapsed since a fixed start time.
+struct TickCounter {
+    start: Instant,
+    dur: Duration,
+    /// Total amount of ticks counted so far
+    ticks_counted: u64,
+}
+
+impl TickCounter {
+    fn new(start: Instant, dur: Duration) -> Self {
+        TickCounter {
+            start,
+            dur,
+            ticks_counted: 0,
+        }
+    }
+
+    /// Amount of ticks that have elapsed at `now` since the last counted tick.
+    /// Returns None if no new tick has elapsed.
+    fn count(&mut self, now: Instant) -> Option<u64> {
+        let total_ticks = elapsed_ticks(now.duration_since(self.start), self.dur);
+        let new_ticks = total_ticks.saturating_sub(self.ticks_counted);
+        if new_ticks == 0 {
+            return None;
+        }
+        self.ticks_counted = total_ticks;
+        Some(new_ticks)
+    }
+}
+
 #[derive(Debug)]
 enum TimerEvent {
-    Incoming,
+    Incoming(u64),
     IncomingDone,
     Request(TimerRequest),
     RequestsDone,
@@ -97,10 +198,10 @@ async fn timer_loop<M>(
     from_client: mpsc::Receiver<TimerRequest>,
 ) -> Result<(), TimerError>
 where
-    M: Stream<Item = ()> + std::marker::Unpin + Send,
+    M: Stream<Item = u64> + std::marker::Unpin + Send,
 {
-    let incoming = incoming
-        .map(|_| TimerEvent::Incoming)
+    let incoming = CoalesceTicks::new(incoming)
+        .map(TimerEvent::Incoming)
         .chain(stream::once(future::ready(TimerEvent::IncomingDone)));
     let from_client = from_client
         .map(TimerEvent::Request)
@@ -113,11 +214,12 @@ where
 
     while let Some(event) = await!(events.next()) {
         match event {
-            TimerEvent::Incoming => {
+            TimerEvent::Incoming(ticks_elapsed) => {
+                let timer_tick = TimerTick { ticks_elapsed };
                 let mut temp_tick_senders = Vec::new();
                 temp_tick_senders.append(&mut tick_senders);
                 for mut tick_sender in temp_tick_senders {
-                    if let Ok(()) = await!(tick_sender.send(TimerTick)) {
+                    if let Ok(()) = await!(tick_sender.send(timer_tick)) {
                         tick_senders.push(tick_sender);
                     }
                 }
@@ -141,14 +243,10 @@ where
     Ok(())
 }
 
-/// Create a timer service that broadcasts everything from the incoming Stream.
-/// Useful for testing, as this function allows full control on the rate of incoming signals.
-pub fn create_timer_incoming<M>(
-    incoming: M,
-    mut spawner: impl Spawn,
-) -> Result<TimerClient, TimerError>
+/// Spawn a timer service. Every item of `incoming` is an amount of elapsed ticks.
+fn spawn_timer<M>(incoming: M, mut spawner: impl Spawn) -> Result<TimerClient, TimerError>
 where
-    M: Stream<Item = ()> + std::marker::Unpin + Send + 'static,
+    M: Stream<Item = u64> + std::marker::Unpin + Send + 'static,
 {
     let (sender, receiver) = mpsc::channel::<TimerRequest>(0);
     let timer_loop_future = timer_loop(incoming, receiver);
@@ -159,6 +257,16 @@ where
     Ok(TimerClient::new(sender))
 }
 
+/// Create a timer service that broadcasts everything from the incoming Stream.
+/// Every incoming item is one tick.
+/// Useful for testing, as this function allows full control on the rate of incoming signals.
+pub fn create_timer_incoming<M>(incoming: M, spawner: impl Spawn) -> Result<TimerClient, TimerError>
+where
+    M: Stream<Item = ()> + std::marker::Unpin + Send + 'static,
+{
+    spawn_timer(incoming.map(|_| 1), spawner)
+}
+
 /// A test util function. Every time a timer_client.request_timer_stream() is called,
 /// a new mpsc::Sender<TimerTick> will be received through the receiver.
 /// This provides greater control over the sent timer ticks.
@@ -184,9 +292,13 @@ pub fn dummy_timer_multi_sender(
 }
 
 /// Create a timer service that ticks every `dur`.
+/// If more time has passed between two ticks of the interval (For example, because the host was
+/// suspended), the amount of elapsed ticks is measured.
 pub fn create_timer(dur: Duration, spawner: impl Spawn) -> Result<TimerClient, TimerError> {
-    let interval = create_interval(dur);
-    create_timer_incoming(interval, spawner)
+    let mut tick_counter = TickCounter::new(Instant::now(), dur);
+    let interval = create_interval(dur)
+        .filter_map(move |()| future::ready(tick_counter.count(Instant::now())));
+    spawn_timer(interval, spawner)
 }
 
 #[cfg(test)]
@@ -364,7 +476,10 @@ mod tests {
         let timer_stream_fut = async {
             let mut timer_stream = await!(timer_client.request_timer_stream()).unwrap();
             for _ in 0..16usize {
-                assert_eq!(await!(timer_stream.next()), Some(TimerTick));
+                assert_eq!(
+                    await!(timer_stream.next()),
+                    Some(TimerTick { ticks_elapsed: 1 })
+                );
             }
             assert_eq!(await!(timer_stream.next()), None);
         };
@@ -372,7 +487,7 @@ mod tests {
             let mut tick_sender = await!(tick_sender_receiver.next()).unwrap();
 
             for _ in 0..16usize {
-                await!(tick_sender.send(TimerTick)).unwrap();
+                await!(tick_sender.send(TimerTick { ticks_elapsed: 1 })).unwrap();
             }
         };
         let _ = await!(timer_stream_fut.join(tick_sender_fut));
@@ -383,4 +498,57 @@ mod tests {
         let mut thread_pool = ThreadPool::new().unwrap();
         thread_pool.run(task_dummy_timer_multi_sender(thread_pool.clone()));
     }
+
+    #[test]
+    fn test_coalesce_ticks() {
+        let mut thread_pool = ThreadPool::new().unwrap();
+
+        // A burst of 10,000 ticks is delivered as a single count:
+        let burst = stream::iter(vec![1u64; 10_000]);
+        let coalesced = thread_pool.run(CoalesceTicks::new(burst).collect::<Vec<u64>>());
+        assert_eq!(coalesced, vec![10_000]);
+
+        // Ticks that are not ready at the same time are not coalesced:
+        let (mut tick_sender, tick_receiver) = mpsc::unbounded::<u64>();
+        let mut coalesced = CoalesceTicks::new(tick_receiver);
+        tick_sender.unbounded_send(1).unwrap();
+        tick_sender.unbounded_send(3).unwrap();
+        assert_eq!(thread_pool.run(coalesced.next()), Some(4));
+        tick_sender.unbounded_send(1).unwrap();
+        assert_eq!(thread_pool.run(coalesced.next()), Some(1));
+        drop(tick_sender);
+        assert_eq!(thread_pool.run(coalesced.next()), None);
+    }
+
+    #[test]
+    fn test_elapsed_ticks() {
+        let dur = Duration::from_millis(10);
+        assert_eq!(elapsed_ticks(Duration::from_millis(10), dur), 1);
+        // Jitter:
+        assert_eq!(elapsed_ticks(Duration::from_millis(4), dur), 0);
+        assert_eq!(elapsed_ticks(Duration::from_millis(13), dur), 1);
+        // A long pause:
+        assert_eq!(elapsed_ticks(dur * 10_000, dur), 10_000);
+    }
+
+    #[test]
+    fn test_tick_counter_stall() {
+        let dur = Duration::from_millis(10);
+        let start = Instant::now();
+        let mut tick_counter = TickCounter::new(start, dur);
+
+        assert_eq!(tick_counter.count(start + dur), Some(1));
+        assert_eq!(tick_counter.count(start + dur * 2), Some(1));
+
+        // A stall: The interval emits the 8 missed ticks back to back, at the same time.
+        // Only the first of them is counted, and it carries all the missed ticks:
+        let now = start + dur * 10;
+        assert_eq!(tick_counter.count(now), Some(8));
+        for _ in 0..7 {
+            assert_eq!(tick_counter.count(now), None);
+        }
+
+        // Ticking continues as usual:
+        assert_eq!(tick_counter.count(start + dur * 11), Some(1));
+    }
 }
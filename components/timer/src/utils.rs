@@ -2,7 +2,7 @@ use std::marker::Unpin;
 
 use crate::timer::{TimerClient, TimerTick};
 use common::int_convert::usize_to_u64;
-use futures::{future, select, Future, FutureExt, Stream, StreamExt};
+use futures::{future, select, stream, Future, FutureExt, Stream, StreamExt};
 
 #[derive(Debug)]
 pub enum SleepTicksError {
@@ -14,11 +14,15 @@ pub async fn sleep_ticks(
     ticks: usize,
     mut timer_client: TimerClient,
 ) -> Result<(), SleepTicksError> {
-    let timer_stream = await!(timer_client.request_timer_stream())
+    let mut timer_stream = await!(timer_client.request_timer_stream())
         .map_err(|_| SleepTicksError::RequestTimerStreamError)?;
-    let ticks_u64 = usize_to_u64(ticks).unwrap();
-    let fut = timer_stream.take(ticks_u64).for_each(|_| future::ready(()));
-    await!(fut);
+    let mut ticks_left = usize_to_u64(ticks).unwrap();
+    while ticks_left > 0 {
+        match await!(timer_stream.next()) {
+            Some(timer_tick) => ticks_left = ticks_left.saturating_sub(timer_tick.ticks_elapsed),
+            None => break,
+        }
+    }
     Ok(())
 }
 
@@ -30,9 +34,15 @@ where
     TS: Stream<Item = TimerTick> + Unpin + Send + 'static,
     F: Future<Output = T> + Unpin,
 {
-    let time_ticks_u64 = usize_to_u64(time_ticks).unwrap();
-    let mut fut_time = timer_stream
-        .take(time_ticks_u64)
+    let mut ticks_left = usize_to_u64(time_ticks).unwrap();
+    // Resolves once `time_ticks` ticks have elapsed (Immediately if `time_ticks` is 0):
+    let mut fut_time = stream::once(future::ready(0))
+        .chain(timer_stream.map(|timer_tick| timer_tick.ticks_elapsed))
+        .filter(move |ticks_elapsed| {
+            ticks_left = ticks_left.saturating_sub(*ticks_elapsed);
+            future::ready(ticks_left == 0)
+        })
+        .take(1)
         .for_each(|_| future::ready(()))
         .map(|_| None);
 
@@ -83,7 +93,7 @@ mod tests {
         let (sender, receiver) = oneshot::channel::<()>();
 
         let (mut tick_sender, timer_stream) = mpsc::channel(0);
-        let timer_stream = timer_stream.map(|_| TimerTick);
+        let timer_stream = timer_stream.map(|_| TimerTick { ticks_elapsed: 1 });
         let receiver = receiver.map(|res| res.unwrap());
         let timeout_fut = spawner
             .spawn_with_handle(future_timeout(receiver, timer_stream, 8))
@@ -0,0 +1,13 @@
+#!/usr/bin/env bash
+
+# Build offst-bin with every combination of the server features.
+# Building without any features results in a client-only node (stnode, stmgr),
+# without the relay server and index server implementations.
+
+set -ex
+
+cargo build -p offst-relay --no-default-features
+cargo build -p offst-bin --no-default-features
+cargo build -p offst-bin --no-default-features --features relay-server
+cargo build -p offst-bin --no-default-features --features index-server
+cargo build -p offst-bin --no-default-features --features "relay-server index-server"